@@ -0,0 +1,230 @@
+use lnvps_api_common::ApiError;
+#[cfg(feature = "nostr-domain")]
+use lnvps_db::NostrDomain;
+use lnvps_db::{DbError, LNVpsDb, SubscriptionPayment, Vm};
+
+/// Load a VM, checking it belongs to `uid`.
+///
+/// Deleted VMs are returned too, use [get_owned_vm] for anything which acts on
+/// the VM.
+pub(crate) async fn authorize_vm_owner(
+    db: &dyn LNVpsDb,
+    uid: u64,
+    vm_id: u64,
+) -> Result<Vm, ApiError> {
+    let vm = db.get_vm(vm_id).await?;
+    if vm.user_id != uid {
+        return Err(ApiError::forbidden("VM does not belong to you"));
+    }
+    Ok(vm)
+}
+
+/// Load a VM, checking it belongs to `uid` and is not deleted
+pub(crate) async fn get_owned_vm(db: &dyn LNVpsDb, uid: u64, vm_id: u64) -> Result<Vm, ApiError> {
+    let vm = authorize_vm_owner(db, uid, vm_id).await?;
+    if vm.deleted {
+        return Err(ApiError::not_found("VM not found"));
+    }
+    Ok(vm)
+}
+
+/// Load a subscription payment, checking its subscription belongs to `uid`.
+///
+/// Works for payments of any subscription (VMs, IP ranges, ASNs, DNS, apps).
+pub(crate) async fn authorize_subscription_payment_owner(
+    db: &dyn LNVpsDb,
+    uid: u64,
+    payment_id: &[u8],
+) -> Result<SubscriptionPayment, ApiError> {
+    let payment = db
+        .get_subscription_payment(&payment_id.to_vec())
+        .await
+        .map_err(|e| not_found_or(e, "Payment not found"))?;
+    let sub = db
+        .get_subscription(payment.subscription_id)
+        .await
+        .map_err(|e| not_found_or(e, "Subscription not found"))?;
+    if sub.user_id != uid {
+        return Err(ApiError::forbidden("Payment does not belong to you"));
+    }
+    Ok(payment)
+}
+
+/// Load a subscription payment and the VM it pays for, checking the VM
+/// belongs to `uid`.
+///
+/// Payments of deleted VMs are returned too, so invoices stay available.
+pub(crate) async fn authorize_payment_owner(
+    db: &dyn LNVpsDb,
+    uid: u64,
+    payment_id: &[u8],
+) -> Result<(SubscriptionPayment, Vm), ApiError> {
+    let payment = db
+        .get_subscription_payment(&payment_id.to_vec())
+        .await
+        .map_err(|e| not_found_or(e, "Payment not found"))?;
+    let vm = db
+        .get_vm_by_subscription(payment.subscription_id)
+        .await
+        .map_err(|e| not_found_or(e, "VM not found"))?;
+    if vm.user_id != uid {
+        return Err(ApiError::forbidden("Payment does not belong to you"));
+    }
+    Ok((payment, vm))
+}
+
+/// A missing row is a 404 with `message`, any other error is internal
+fn not_found_or(e: DbError, message: &str) -> ApiError {
+    if e.is_row_not_found() {
+        ApiError::not_found(message)
+    } else {
+        e.into()
+    }
+}
+
+/// Load a nostr domain, checking it belongs to `uid`
+#[cfg(feature = "nostr-domain")]
+pub(crate) async fn authorize_domain_owner(
+    db: &dyn LNVpsDb,
+    uid: u64,
+    domain_id: u64,
+) -> Result<NostrDomain, ApiError> {
+    let domain = db.get_domain(domain_id).await?;
+    if domain.owner_id != uid {
+        return Err(ApiError::forbidden("Access denied"));
+    }
+    Ok(domain)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::StatusCode;
+    use chrono::Utc;
+    use lnvps_api_common::MockDb;
+    use lnvps_db::{LNVpsDbBase, PaymentMethod, SubscriptionPaymentType};
+
+    #[tokio::test]
+    async fn test_vm_non_owner_denied() -> anyhow::Result<()> {
+        let db = MockDb::default();
+        let owner = db.upsert_user(&[1; 32]).await?;
+        let other = db.upsert_user(&[2; 32]).await?;
+        db.vms.lock().await.insert(
+            1,
+            Vm {
+                user_id: owner,
+                ..MockDb::mock_vm()
+            },
+        );
+
+        assert_eq!(authorize_vm_owner(&db, owner, 1).await?.id, 1);
+        let err = authorize_vm_owner(&db, other, 1).await.unwrap_err();
+        assert_eq!(err.code, StatusCode::FORBIDDEN);
+        let err = get_owned_vm(&db, other, 1).await.unwrap_err();
+        assert_eq!(err.code, StatusCode::FORBIDDEN);
+
+        // deleted VMs are only returned by authorize_vm_owner
+        db.vms.lock().await.get_mut(&1).unwrap().deleted = true;
+        assert!(authorize_vm_owner(&db, owner, 1).await.is_ok());
+        let err = get_owned_vm(&db, owner, 1).await.unwrap_err();
+        assert_eq!(err.code, StatusCode::NOT_FOUND);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_payment_non_owner_denied() -> anyhow::Result<()> {
+        let db = MockDb::default();
+        let owner = db.upsert_user(&[1; 32]).await?;
+        let other = db.upsert_user(&[2; 32]).await?;
+        db.vms.lock().await.insert(
+            1,
+            Vm {
+                user_id: owner,
+                ..MockDb::mock_vm()
+            },
+        );
+        db.subscription_payments
+            .lock()
+            .await
+            .push(SubscriptionPayment {
+                id: vec![1; 32],
+                subscription_id: 1,
+                user_id: owner,
+                created: Utc::now(),
+                expires: Utc::now(),
+                amount: 1000,
+                currency: "BTC".to_string(),
+                payment_method: PaymentMethod::Lightning,
+                payment_type: SubscriptionPaymentType::Renewal,
+                external_data: "".to_string().into(),
+                external_id: None,
+                is_paid: true,
+                rate: 1.0,
+                time_value: None,
+                metadata: None,
+                tax: 0,
+                processing_fee: 0,
+                paid_at: None,
+                tax_rate: None,
+                tax_country_code: None,
+                tax_treatment: None,
+                tax_evidence: None,
+                tax_breakdown: None,
+                invoice_number: None,
+            });
+
+        let (payment, vm) = authorize_payment_owner(&db, owner, &[1; 32]).await?;
+        assert_eq!(payment.id, vec![1; 32]);
+        assert_eq!(vm.id, 1);
+        let err = authorize_payment_owner(&db, other, &[1; 32])
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, StatusCode::FORBIDDEN);
+        let err = authorize_payment_owner(&db, owner, &[2; 32])
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, StatusCode::NOT_FOUND);
+
+        // a payment of a subscription without a VM is checked by the
+        // subscription owner
+        db.subscriptions.lock().await.get_mut(&1).unwrap().user_id = owner;
+        db.vms.lock().await.clear();
+        let err = authorize_payment_owner(&db, owner, &[1; 32])
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, StatusCode::NOT_FOUND);
+        let payment = authorize_subscription_payment_owner(&db, owner, &[1; 32]).await?;
+        assert_eq!(payment.id, vec![1; 32]);
+        let err = authorize_subscription_payment_owner(&db, other, &[1; 32])
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, StatusCode::FORBIDDEN);
+        let err = authorize_subscription_payment_owner(&db, owner, &[2; 32])
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, StatusCode::NOT_FOUND);
+        Ok(())
+    }
+
+    #[cfg(feature = "nostr-domain")]
+    #[tokio::test]
+    async fn test_domain_non_owner_denied() -> anyhow::Result<()> {
+        use lnvps_db::nostr::LNVPSNostrDb;
+
+        let db = MockDb::default();
+        let owner = db.upsert_user(&[1; 32]).await?;
+        let other = db.upsert_user(&[2; 32]).await?;
+        let id = db
+            .insert_domain(&NostrDomain {
+                owner_id: owner,
+                name: "example.com".to_string(),
+                ..Default::default()
+            })
+            .await?;
+
+        assert_eq!(authorize_domain_owner(&db, owner, id).await?.id, id);
+        let err = authorize_domain_owner(&db, other, id).await.unwrap_err();
+        assert_eq!(err.code, StatusCode::FORBIDDEN);
+        Ok(())
+    }
+}
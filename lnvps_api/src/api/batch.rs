@@ -0,0 +1,233 @@
+use crate::api::{RouterState, api_routes};
+use axum::body::{Body, to_bytes};
+use axum::extract::State;
+use axum::http::{HeaderMap, Method, Request, header};
+use axum::routing::post;
+use axum::{Json, Router};
+use lnvps_api_common::{ApiData, ApiError, ApiResult, Nip98Auth};
+use serde::{Deserialize, Serialize};
+use tower::ServiceExt;
+
+/// Max number of sub-requests in a single batch
+pub const MAX_BATCH_SIZE: usize = 20;
+
+/// Max size of a single sub-request response body
+const MAX_RESPONSE_BYTES: usize = 4 * 1024 * 1024;
+
+const BATCH_PATH: &str = "/api/v1/batch";
+
+/// Headers copied from the batch request to every sub-request
+const FORWARDED_HEADERS: [&str; 2] = ["x-forwarded-for", "x-real-ip"];
+
+#[derive(Deserialize)]
+struct BatchRequest {
+    /// HTTP method, e.g. `GET`
+    method: String,
+    /// Path including any query string, e.g. `/api/v1/vm/1`
+    path: String,
+    /// JSON body, sent as `application/json`
+    body: Option<serde_json::Value>,
+}
+
+#[derive(Serialize)]
+struct BatchResponse {
+    /// HTTP status code of the sub-request
+    status: u16,
+    /// Response body, JSON if the handler returned JSON otherwise a string
+    body: serde_json::Value,
+}
+
+pub fn router() -> Router<RouterState> {
+    Router::new().route(BATCH_PATH, post(v1_batch))
+}
+
+/// Execute a list of API requests in order
+///
+/// The batch is authenticated once, every sub-request runs as the same user.
+/// A failing sub-request does not stop the batch, its status and error are
+/// returned in its slot.
+async fn v1_batch(
+    auth: Nip98Auth,
+    State(this): State<RouterState>,
+    headers: HeaderMap,
+    Json(req): Json<Vec<BatchRequest>>,
+) -> ApiResult<Vec<BatchResponse>> {
+    if req.is_empty() {
+        return ApiData::err("Batch is empty");
+    }
+    if req.len() > MAX_BATCH_SIZE {
+        return Err(ApiError::new(format!(
+            "Batch too large, max {} requests",
+            MAX_BATCH_SIZE
+        )));
+    }
+
+    let routes = api_routes().with_state(this);
+    let mut ret = Vec::with_capacity(req.len());
+    for r in req {
+        let sub = match make_request(&auth, &headers, r) {
+            Ok(sub) => sub,
+            Err(e) => {
+                ret.push(BatchResponse {
+                    status: e.code.as_u16(),
+                    body: serde_json::to_value(&e).unwrap_or_default(),
+                });
+                continue;
+            }
+        };
+        let Ok(rsp) = routes.clone().oneshot(sub).await;
+        let status = rsp.status().as_u16();
+        let body = match to_bytes(rsp.into_body(), MAX_RESPONSE_BYTES).await {
+            Ok(b) => serde_json::from_slice(&b)
+                .unwrap_or_else(|_| String::from_utf8_lossy(&b).into_owned().into()),
+            Err(_) => serde_json::json!({ "error": "Response too large" }),
+        };
+        ret.push(BatchResponse { status, body });
+    }
+    ApiData::ok(ret)
+}
+
+/// Build the internal request for a batch item, carrying the batch auth and
+/// the client IP headers of the batch request
+fn make_request(
+    auth: &Nip98Auth,
+    headers: &HeaderMap,
+    r: BatchRequest,
+) -> Result<Request<Body>, ApiError> {
+    let method = Method::from_bytes(r.method.to_uppercase().as_bytes())
+        .map_err(|_| ApiError::new(format!("Invalid method {}", r.method)))?;
+    if !r.path.starts_with("/api/") {
+        return Err(ApiError::new(format!("Invalid path {}", r.path)));
+    }
+    if r.path.split('?').next() == Some(BATCH_PATH) {
+        return Err(ApiError::new("Nested batches are not allowed"));
+    }
+    let body = match r.body {
+        Some(b) => Body::from(b.to_string()),
+        None => Body::empty(),
+    };
+    let mut sub = Request::builder()
+        .method(method)
+        .uri(&r.path)
+        .header(header::CONTENT_TYPE, "application/json");
+    for name in FORWARDED_HEADERS {
+        if let Some(v) = headers.get(name) {
+            sub = sub.header(name, v);
+        }
+    }
+    let mut sub = sub
+        .body(body)
+        .map_err(|e| ApiError::new(format!("Invalid request: {}", e)))?;
+    sub.extensions_mut().insert(auth.clone());
+    Ok(sub)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mocks::{MockNode, MockOnChainProvider};
+    use crate::settings::mock_settings;
+    use crate::subscription::SubscriptionHandler;
+    use axum::http::StatusCode;
+    use base64::Engine;
+    use base64::prelude::BASE64_STANDARD;
+    use lnvps_api_common::{
+        ChannelWorkCommander, FeatureFlags, MockDb, MockExchangeRate, VmHistoryLogger,
+        VmStateCache, WorkCommander,
+    };
+    use lnvps_db::{LNVpsDbBase, UserSshKey};
+    use nostr::{EventBuilder, JsonUtil, Keys, Kind, Tag};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_batch_list_and_get_vm() -> anyhow::Result<()> {
+        let settings = mock_settings();
+        let db = Arc::new(MockDb::default());
+        let rates = Arc::new(MockExchangeRate::new());
+        let wrk: Arc<dyn WorkCommander> = Arc::new(ChannelWorkCommander::new());
+        let cache = VmStateCache::new();
+        let sub_handler = SubscriptionHandler::new(
+            settings.clone(),
+            db.clone(),
+            Arc::new(MockNode::default()),
+            Arc::new(MockOnChainProvider::default()),
+            None,
+            rates.clone(),
+            lnvps_api_common::VatClient::new(),
+            wrk.clone(),
+            cache.clone(),
+        )?;
+
+        let keys = Keys::generate();
+        let uid = db.upsert_user(&keys.public_key().to_bytes()).await?;
+        let key_id = db
+            .insert_user_ssh_key(&UserSshKey {
+                id: 0,
+                name: "test".to_string(),
+                user_id: uid,
+                created: Default::default(),
+                key_data: "ssh-rsa AAA==".into(),
+            })
+            .await?;
+        let vm = sub_handler
+            .vm_provisioner()
+            .provision(uid, 1, 1, key_id, None, false)
+            .await?;
+
+        let app = router().with_state(RouterState {
+            db: db.clone(),
+            state: cache,
+            sub_handler,
+            history: VmHistoryLogger::new(db.clone()),
+            settings,
+            rates,
+            work_sender: wrk,
+            feedback: None,
+            geoip: None,
+            readiness: Default::default(),
+            features: FeatureFlags::new(db.clone()),
+            domain_verifier: crate::nostr_domain::NostrDomainVerifier::local(None),
+        });
+
+        let event = EventBuilder::new(Kind::HttpAuth, "")
+            .tags([
+                Tag::parse(["u", "http://localhost/api/v1/batch"])?,
+                Tag::parse(["method", "POST"])?,
+            ])
+            .sign_with_keys(&keys)?;
+        let batch = serde_json::json!([
+            { "method": "GET", "path": "/api/v1/vm" },
+            { "method": "GET", "path": format!("/api/v1/vm/{}", vm.id) },
+            { "method": "POST", "path": "/api/v1/batch", "body": [] },
+        ]);
+        let req = Request::builder()
+            .method(Method::POST)
+            .uri(BATCH_PATH)
+            .header(
+                header::AUTHORIZATION,
+                format!("Nostr {}", BASE64_STANDARD.encode(event.as_json())),
+            )
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(batch.to_string()))?;
+
+        let rsp = app.oneshot(req).await?;
+        assert_eq!(rsp.status(), StatusCode::OK);
+        let body: serde_json::Value =
+            serde_json::from_slice(&to_bytes(rsp.into_body(), MAX_RESPONSE_BYTES).await?)?;
+        let items = body["data"].as_array().expect("batch responses");
+        assert_eq!(items.len(), 3);
+
+        assert_eq!(items[0]["status"], 200);
+        let vms = items[0]["body"]["data"].as_array().expect("vm list");
+        assert_eq!(vms.len(), 1);
+        assert_eq!(vms[0]["id"], vm.id);
+
+        assert_eq!(items[1]["status"], 200);
+        assert_eq!(items[1]["body"]["data"]["id"], vm.id);
+
+        // nesting is rejected per item
+        assert_eq!(items[2]["status"], 400);
+        assert_eq!(items[2]["body"]["code"], "bad_request");
+        Ok(())
+    }
+}
@@ -0,0 +1,286 @@
+use crate::api::RouterState;
+use anyhow::{Context, Result, anyhow, bail};
+use async_trait::async_trait;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::{Json, Router};
+use futures::future::join_all;
+use lnvps_api_common::WorkCommander;
+use lnvps_db::{LNVpsDb, PaymentMethodConfig, ProviderConfig};
+use log::warn;
+use nostr_sdk::Url;
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Max time a single readiness check may take, so the probe never hangs
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// A worker which hasn't read the work queue for this long is considered dead.
+/// Generous since the worker doesn't read while it runs a long job.
+const MAX_WORKER_IDLE: Duration = Duration::from_secs(600);
+
+pub fn router() -> Router<RouterState> {
+    Router::new()
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+}
+
+/// A dependency which must be up before the API can serve traffic
+#[async_trait]
+pub trait ReadinessCheck: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    async fn check(&self) -> Result<()>;
+}
+
+/// Database connectivity
+pub struct DbCheck(pub Arc<dyn LNVpsDb>);
+
+#[async_trait]
+impl ReadinessCheck for DbCheck {
+    fn name(&self) -> &'static str {
+        "database"
+    }
+
+    async fn check(&self) -> Result<()> {
+        Ok(self.0.ping().await?)
+    }
+}
+
+/// The work queue is reachable and a worker is reading from it
+pub struct WorkQueueCheck(pub Arc<dyn WorkCommander>);
+
+#[async_trait]
+impl ReadinessCheck for WorkQueueCheck {
+    fn name(&self) -> &'static str {
+        "work_queue"
+    }
+
+    async fn check(&self) -> Result<()> {
+        match self.0.worker_idle().await? {
+            None => bail!("No worker has read the work queue"),
+            Some(idle) if idle > MAX_WORKER_IDLE => {
+                bail!("No worker has read the work queue for {}s", idle.as_secs())
+            }
+            Some(_) => Ok(()),
+        }
+    }
+}
+
+/// The Lightning node accepts connections
+pub struct LightningCheck {
+    url: String,
+}
+
+impl LightningCheck {
+    /// Check for a Lightning payment method config, `None` for providers
+    /// without a node of our own (e.g. Bitvora).
+    ///
+    /// The node url is only parsed when the check runs, so a bad url reports
+    /// the API as not ready instead of stopping it from starting.
+    pub fn from_config(config: &PaymentMethodConfig) -> Option<Self> {
+        match config.get_provider_config() {
+            Some(ProviderConfig::Lnd(lnd)) => Some(Self { url: lnd.url }),
+            _ => None,
+        }
+    }
+
+    fn endpoint(&self) -> Result<(String, u16)> {
+        let url = Url::parse(&self.url).context("Invalid Lightning node url")?;
+        Ok((
+            url.host_str()
+                .ok_or(anyhow!("Lightning node url has no host"))?
+                .to_string(),
+            url.port_or_known_default()
+                .ok_or(anyhow!("Lightning node url has no port"))?,
+        ))
+    }
+}
+
+#[async_trait]
+impl ReadinessCheck for LightningCheck {
+    fn name(&self) -> &'static str {
+        "lightning"
+    }
+
+    async fn check(&self) -> Result<()> {
+        let (host, port) = self.endpoint()?;
+        tokio::net::TcpStream::connect((host.as_str(), port)).await?;
+        Ok(())
+    }
+}
+
+/// Checks run by `/readyz`
+#[derive(Clone, Default)]
+pub struct Readiness {
+    checks: Vec<Arc<dyn ReadinessCheck>>,
+}
+
+impl Readiness {
+    pub fn with_check(mut self, check: impl ReadinessCheck + 'static) -> Self {
+        self.checks.push(Arc::new(check));
+        self
+    }
+
+    /// Run all checks concurrently, each bounded by [CHECK_TIMEOUT].
+    ///
+    /// The probe is unauthenticated, so failure details (hosts, driver errors)
+    /// only go to the log and the response carries a generic reason.
+    pub async fn run(&self) -> ApiReadiness {
+        let checks = join_all(self.checks.iter().map(|c| async move {
+            let start = Instant::now();
+            let error = match tokio::time::timeout(CHECK_TIMEOUT, c.check()).await {
+                Ok(Ok(())) => None,
+                Ok(Err(e)) => {
+                    warn!("Readiness check {} failed: {:#}", c.name(), e);
+                    Some("unavailable")
+                }
+                Err(_) => {
+                    warn!(
+                        "Readiness check {} timed out after {}s",
+                        c.name(),
+                        CHECK_TIMEOUT.as_secs()
+                    );
+                    Some("timeout")
+                }
+            };
+            ApiReadinessCheck {
+                name: c.name(),
+                ok: error.is_none(),
+                error,
+                took_ms: start.elapsed().as_millis() as u64,
+            }
+        }))
+        .await;
+        ApiReadiness {
+            ready: checks.iter().all(|c| c.ok),
+            checks,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct ApiReadinessCheck {
+    pub name: &'static str,
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<&'static str>,
+    pub took_ms: u64,
+}
+
+#[derive(Serialize)]
+pub struct ApiReadiness {
+    pub ready: bool,
+    pub checks: Vec<ApiReadinessCheck>,
+}
+
+/// Liveness, the process is up and serving requests
+async fn healthz() -> &'static str {
+    "ok"
+}
+
+/// Readiness, `200` only when every dependency is up, `503` otherwise
+async fn readyz(State(this): State<RouterState>) -> (StatusCode, Json<ApiReadiness>) {
+    readiness_response(&this.readiness).await
+}
+
+async fn readiness_response(readiness: &Readiness) -> (StatusCode, Json<ApiReadiness>) {
+    let rsp = readiness.run().await;
+    let code = if rsp.ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (code, Json(rsp))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lnvps_api_common::{ChannelWorkCommander, MockDb};
+
+    struct HangingCheck;
+
+    #[async_trait]
+    impl ReadinessCheck for HangingCheck {
+        fn name(&self) -> &'static str {
+            "hanging"
+        }
+
+        async fn check(&self) -> Result<()> {
+            std::future::pending().await
+        }
+    }
+
+    fn readiness(db: &MockDb) -> Readiness {
+        Readiness::default()
+            .with_check(DbCheck(Arc::new(db.clone())))
+            .with_check(WorkQueueCheck(Arc::new(ChannelWorkCommander::new())))
+    }
+
+    #[tokio::test]
+    async fn test_ready_when_dependencies_up() {
+        let db = MockDb::default();
+        let (code, Json(rsp)) = readiness_response(&readiness(&db)).await;
+        assert_eq!(code, StatusCode::OK);
+        assert!(rsp.ready);
+        assert_eq!(rsp.checks.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_db_down_not_ready() {
+        let db = MockDb::default();
+        *db.offline.lock().await = true;
+        let (code, Json(rsp)) = readiness_response(&readiness(&db)).await;
+        assert_eq!(code, StatusCode::SERVICE_UNAVAILABLE);
+        assert!(!rsp.ready);
+        let check = rsp.checks.iter().find(|c| c.name == "database").unwrap();
+        assert!(!check.ok);
+        // driver errors aren't leaked to the unauthenticated probe
+        assert_eq!(check.error, Some("unavailable"));
+        // the other checks still report their own state
+        assert!(
+            rsp.checks
+                .iter()
+                .find(|c| c.name == "work_queue")
+                .unwrap()
+                .ok
+        );
+    }
+
+    #[tokio::test]
+    async fn test_hanging_check_times_out() {
+        let readiness = Readiness::default().with_check(HangingCheck);
+        let (code, Json(rsp)) = readiness_response(&readiness).await;
+        assert_eq!(code, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(rsp.checks[0].error, Some("timeout"));
+    }
+
+    fn lightning(url: &str) -> LightningCheck {
+        LightningCheck {
+            url: url.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_lightning_check_endpoint() {
+        let (host, port) = lightning("https://lnd.example.com:8080")
+            .endpoint()
+            .unwrap();
+        assert_eq!((host.as_str(), port), ("lnd.example.com", 8080));
+        let (_, port) = lightning("https://lnd.example.com").endpoint().unwrap();
+        assert_eq!(port, 443);
+    }
+
+    /// A bad node url makes the API not ready rather than failing startup
+    #[tokio::test]
+    async fn test_lightning_bad_url_not_ready() {
+        let readiness = Readiness::default().with_check(lightning("not a url"));
+        let (code, Json(rsp)) = readiness_response(&readiness).await;
+        assert_eq!(code, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(rsp.checks[0].name, "lightning");
+        assert_eq!(rsp.checks[0].error, Some("unavailable"));
+    }
+}
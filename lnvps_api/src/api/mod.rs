@@ -1,33 +1,45 @@
 mod apps;
+mod authorize;
+mod batch;
 mod contact;
 mod docs;
+mod health;
 mod ip_space;
 mod legal;
 mod model;
 #[cfg(feature = "nostr-domain")]
 mod nostr_domain;
 mod oauth;
+#[cfg(feature = "openapi")]
+mod openapi;
 mod referral;
 mod routes;
 mod subscriptions;
 mod webauthn;
 mod webhook;
 
+use crate::nostr_domain::NostrDomainVerifier;
 use crate::settings::Settings;
 use crate::subscription::SubscriptionHandler;
 pub use apps::router as apps_router;
+use axum::Router;
+pub use batch::router as batch_router;
 pub use contact::router as contacts_router;
 pub use docs::router as docs_router;
+pub use health::router as health_router;
+pub use health::{DbCheck, LightningCheck, Readiness, ReadinessCheck, WorkQueueCheck};
 pub use ip_space::router as ip_space_router;
 pub use legal::router as legal_router;
 use lnvps_api_common::{
-    CountryResolver, ExchangeRateService, VmHistoryLogger, VmStateCache, WorkCommander,
-    WorkFeedback,
+    CountryResolver, ExchangeRateService, FeatureFlags, VmHistoryLogger, VmStateCache,
+    WorkCommander, WorkFeedback,
 };
 use lnvps_db::LNVpsDb;
 #[cfg(feature = "nostr-domain")]
 pub use nostr_domain::router as nostr_domain_router;
 pub use oauth::router as oauth_router;
+#[cfg(feature = "openapi")]
+pub use openapi::router as openapi_router;
 pub use referral::router as referral_router;
 pub use routes::routes as main_router;
 use serde::Deserialize;
@@ -60,6 +72,35 @@ pub(crate) struct AuthQuery {
     pub auth: String,
 }
 
+/// Every public API route, except `POST /api/v1/batch` which dispatches
+/// sub-requests to these
+pub fn api_routes() -> Router<RouterState> {
+    #[allow(unused_mut)]
+    let mut router = Router::new()
+        .merge(docs_router())
+        .merge(health_router())
+        .merge(main_router())
+        .merge(contacts_router())
+        .merge(webhook_router())
+        .merge(subscriptions_router())
+        .merge(ip_space_router())
+        .merge(referral_router())
+        .merge(apps_router())
+        .merge(legal_router())
+        .merge(oauth_router())
+        .merge(webauthn_router());
+
+    #[cfg(feature = "openapi")]
+    {
+        router = router.merge(openapi_router());
+    }
+    #[cfg(feature = "nostr-domain")]
+    {
+        router = router.merge(nostr_domain_router());
+    }
+    router
+}
+
 #[derive(Clone)]
 pub struct RouterState {
     pub db: Arc<dyn LNVpsDb>,
@@ -76,6 +117,12 @@ pub struct RouterState {
     /// Resolves client IPs to a country for VAT place-of-supply evidence.
     /// `None` when no geolocation database is configured.
     pub geoip: Option<Arc<dyn CountryResolver>>,
+    /// Dependency checks served on `/readyz`
+    pub readiness: Readiness,
+    /// Feature flags gating optional behaviour per region
+    pub features: FeatureFlags,
+    /// Checks user nostr domains on demand
+    pub domain_verifier: NostrDomainVerifier,
 }
 
 /// Resolve a payment-method query into a concrete `(PaymentMethod, RenewMode)`.
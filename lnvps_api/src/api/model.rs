@@ -5,7 +5,7 @@ use crate::exchange::{ExchangeRateService, alt_prices};
 use anyhow::Result;
 use chrono::{DateTime, Utc};
 use humantime::format_duration;
-use lnvps_api_common::{ApiDiskInterface, ApiDiskType};
+use lnvps_api_common::{ApiDiskInterface, ApiDiskType, ApiPlacementPolicy};
 use lnvps_db::{PaymentMethod, VmCustomTemplate};
 
 use payments_rs::currency::{Currency, CurrencyAmount};
@@ -21,6 +21,12 @@ pub struct ApiCustomVmOrder {
     pub image_id: u64,
     pub ssh_key_id: u64,
     pub ref_code: Option<String>,
+    /// Client supplied id, repeating a create with the same id returns the same VM
+    #[serde(default)]
+    pub client_request_id: Option<String>,
+    /// Isolate the VM at L2 from other VMs on its host
+    #[serde(default)]
+    pub private_network: bool,
 }
 
 #[derive(Serialize)]
@@ -58,6 +64,9 @@ pub struct ApiCustomVmPrice {
     pub amount: u64,
     /// The same price converted to other supported currencies.
     pub other_price: Vec<ApiPrice>,
+    /// Whether the seller shows prices including or excluding tax, `amount`
+    /// is always net
+    pub tax_display: ApiTaxDisplay,
 }
 
 impl ApiCustomVmPrice {
@@ -79,6 +88,7 @@ impl ApiCustomVmPrice {
             currency: amount.currency().into(),
             amount: amount.value(),
             other_price,
+            tax_display: Default::default(),
         })
     }
 }
@@ -96,6 +106,29 @@ pub struct VMPatchRequest {
     pub auto_renewal_enabled: Option<bool>,
 }
 
+#[derive(Serialize, Deserialize, Default)]
+pub struct VmPasswordResetRequest {
+    /// New password for the VM's default user, a random one is generated when
+    /// omitted
+    #[serde(default)]
+    pub password: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ApiVmPasswordReset {
+    /// The generated password, omitted when the caller supplied one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+}
+
+/// An IP assigned to one of the user's VMs
+#[derive(Serialize)]
+pub struct ApiUserIpAssignment {
+    pub vm_id: u64,
+    #[serde(flatten)]
+    pub ip: ApiVmIpAssignment,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct AccountPatchRequest {
     #[serde(
@@ -176,10 +209,20 @@ pub struct AccountPatchRequest {
         deserialize_with = "lnvps_api_common::deserialize_nullable_option"
     )]
     pub tax_id: Option<Option<String>>,
+    /// Whether `tax_id` was confirmed by VIES (read-only, ignored on PATCH)
+    #[serde(skip_deserializing, skip_serializing_if = "Option::is_none")]
+    pub tax_id_validated: Option<bool>,
+    /// Business name registered in VIES for `tax_id` (read-only, ignored on PATCH)
+    #[serde(skip_deserializing, skip_serializing_if = "Option::is_none")]
+    pub tax_id_name: Option<String>,
     /// Tax (VAT) rate that will be applied to this user's payments, per seller
     /// company (read-only, ignored on PATCH)
     #[serde(skip_deserializing, skip_serializing_if = "Option::is_none")]
     pub tax: Option<Vec<AccountTaxInfo>>,
+    /// Whether new VMs are spread over hosts apart from the user's other VMs
+    /// or packed onto the same hosts, unchanged on PATCH when omitted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub placement_policy: Option<ApiPlacementPolicy>,
 }
 
 /// Result of a successful account PATCH.
@@ -228,6 +271,39 @@ impl AccountTaxInfo {
     }
 }
 
+/// Dashboard overview of an account, assembled server-side in one response
+#[derive(Serialize)]
+pub struct ApiAccountSummary {
+    /// Number of VMs in each running state
+    pub vms: ApiVmStateCounts,
+    /// Recurring cost of all active subscriptions, normalised to one month, per currency
+    pub monthly_spend: Vec<ApiAccountAmount>,
+    /// Earliest upcoming VM expiry, `None` when no VM has been paid for
+    pub next_expiry: Option<DateTime<Utc>>,
+    /// Account credit balance per currency (zero balances omitted)
+    pub credit: Vec<ApiAccountAmount>,
+    /// Unpaid, unexpired invoices across all VMs
+    pub unpaid_invoices: Vec<ApiVmPayment>,
+}
+
+#[derive(Serialize, Default, Debug, PartialEq)]
+pub struct ApiVmStateCounts {
+    pub total: u64,
+    pub running: u64,
+    pub stopped: u64,
+    pub creating: u64,
+    pub suspended: u64,
+    pub migrating: u64,
+    pub unknown: u64,
+}
+
+/// Amount in the smallest currency unit (cents for fiat, millisats for BTC)
+#[derive(Serialize, Debug, PartialEq)]
+pub struct ApiAccountAmount {
+    pub currency: String,
+    pub amount: u64,
+}
+
 /// A saved payment method for automatic renewals. Never exposes the underlying
 /// provider tokens / NWC connection string.
 #[derive(Serialize, Deserialize)]
@@ -312,7 +388,10 @@ impl From<lnvps_db::User> for AccountPatchRequest {
             city: Some(user.billing_city),
             postcode: Some(user.billing_postcode),
             tax_id: Some(user.billing_tax_id),
+            tax_id_validated: Some(user.billing_tax_id_validated),
+            tax_id_name: user.billing_tax_id_name,
             tax: None,
+            placement_policy: Some(user.placement_policy.into()),
         }
     }
 }
@@ -323,6 +402,12 @@ pub struct CreateVmRequest {
     pub image_id: u64,
     pub ssh_key_id: u64,
     pub ref_code: Option<String>,
+    /// Client supplied id, repeating a create with the same id returns the same VM
+    #[serde(default)]
+    pub client_request_id: Option<String>,
+    /// Isolate the VM at L2 from other VMs on its host
+    #[serde(default)]
+    pub private_network: bool,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -344,11 +429,18 @@ pub struct ApiVmPayment {
     pub is_paid: bool,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub paid_at: Option<DateTime<Utc>>,
+    /// Sequential invoice number, set once paid
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub invoice_number: Option<String>,
     pub data: ApiPaymentData,
     pub time: u64,
     pub is_upgrade: bool,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub upgrade_params: Option<String>,
+    /// Whether the seller shows prices including (`amount + tax`) or excluding
+    /// (`amount`) tax
+    #[serde(default)]
+    pub tax_display: ApiTaxDisplay,
 }
 
 #[derive(Serialize)]
@@ -473,12 +565,26 @@ impl ApiVmPayment {
             currency: value.currency,
             is_paid: value.is_paid,
             paid_at: value.paid_at,
+            invoice_number: value.invoice_number,
             time: value.time_value.unwrap_or(0),
             is_upgrade,
             upgrade_params,
             data,
+            tax_display: Default::default(),
         })
     }
+
+    /// Convert a payment for a VM, shown per the tax display of the VM's seller
+    pub async fn for_vm(
+        db: &Arc<dyn lnvps_db::LNVpsDb>,
+        value: lnvps_db::SubscriptionPayment,
+        vm_id: u64,
+    ) -> anyhow::Result<Self> {
+        let company = db.get_company(db.get_vm_company_id(vm_id).await?).await?;
+        let mut payment = Self::from_subscription_payment(value, vm_id)?;
+        payment.tax_display = company.tax_display.into();
+        Ok(payment)
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -736,6 +842,12 @@ pub struct ApiVmUpgradeQuote {
     pub tax: ApiPrice,
     /// Payment processing fee added on top (zero for Lightning).
     pub processing_fee: ApiPrice,
+    /// Upgrade cost before tax (same as `cost_difference`).
+    pub net_amount: ApiPrice,
+    /// Upgrade cost including tax (`net_amount + tax`), without the processing fee.
+    pub gross_amount: ApiPrice,
+    /// Which price to show: `gross_amount` when `inclusive`, `net_amount` when `exclusive`.
+    pub tax_display: ApiTaxDisplay,
 }
 
 // ============================================================================
@@ -1003,6 +1115,174 @@ pub fn validate_firewall_ports(
     }
 }
 
+/// VM metric watched by a usage alert
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ApiUsageMetric {
+    Cpu,
+    Memory,
+    NetIn,
+    NetOut,
+    DiskRead,
+    DiskWrite,
+    DiskUsage,
+}
+
+impl From<lnvps_db::VmUsageMetric> for ApiUsageMetric {
+    fn from(v: lnvps_db::VmUsageMetric) -> Self {
+        match v {
+            lnvps_db::VmUsageMetric::Cpu => ApiUsageMetric::Cpu,
+            lnvps_db::VmUsageMetric::Memory => ApiUsageMetric::Memory,
+            lnvps_db::VmUsageMetric::NetIn => ApiUsageMetric::NetIn,
+            lnvps_db::VmUsageMetric::NetOut => ApiUsageMetric::NetOut,
+            lnvps_db::VmUsageMetric::DiskRead => ApiUsageMetric::DiskRead,
+            lnvps_db::VmUsageMetric::DiskWrite => ApiUsageMetric::DiskWrite,
+            lnvps_db::VmUsageMetric::DiskUsage => ApiUsageMetric::DiskUsage,
+        }
+    }
+}
+
+impl From<ApiUsageMetric> for lnvps_db::VmUsageMetric {
+    fn from(v: ApiUsageMetric) -> Self {
+        match v {
+            ApiUsageMetric::Cpu => lnvps_db::VmUsageMetric::Cpu,
+            ApiUsageMetric::Memory => lnvps_db::VmUsageMetric::Memory,
+            ApiUsageMetric::NetIn => lnvps_db::VmUsageMetric::NetIn,
+            ApiUsageMetric::NetOut => lnvps_db::VmUsageMetric::NetOut,
+            ApiUsageMetric::DiskRead => lnvps_db::VmUsageMetric::DiskRead,
+            ApiUsageMetric::DiskWrite => lnvps_db::VmUsageMetric::DiskWrite,
+            ApiUsageMetric::DiskUsage => lnvps_db::VmUsageMetric::DiskUsage,
+        }
+    }
+}
+
+/// A user-configurable resource usage alert on a VM
+#[derive(Serialize, Deserialize)]
+pub struct ApiVmUsageAlert {
+    pub id: u64,
+    pub metric: ApiUsageMetric,
+    /// Percent for `cpu`/`memory`, bytes per second for network and disk metrics
+    pub threshold: f64,
+    /// Averaging window in seconds
+    pub window: u32,
+    /// When the alert last sent a notification
+    pub last_triggered: Option<DateTime<Utc>>,
+}
+
+impl From<lnvps_db::VmUsageAlert> for ApiVmUsageAlert {
+    fn from(a: lnvps_db::VmUsageAlert) -> Self {
+        ApiVmUsageAlert {
+            id: a.id,
+            metric: a.metric.into(),
+            threshold: a.threshold,
+            window: a.window_seconds,
+            last_triggered: a.last_triggered,
+        }
+    }
+}
+
+/// Request body to create a usage alert
+#[derive(Serialize, Deserialize)]
+pub struct CreateVmUsageAlert {
+    pub metric: ApiUsageMetric,
+    pub threshold: f64,
+    /// Averaging window in seconds (defaults to 300)
+    pub window: Option<u32>,
+}
+
+/// Request body to update a usage alert (all fields optional)
+#[derive(Serialize, Deserialize)]
+pub struct PatchVmUsageAlert {
+    pub metric: Option<ApiUsageMetric>,
+    pub threshold: Option<f64>,
+    pub window: Option<u32>,
+}
+
+/// Validate a usage alert threshold and window.
+///
+/// The window is bounded by the hourly time-series the worker evaluates.
+pub fn validate_usage_alert(
+    metric: ApiUsageMetric,
+    threshold: f64,
+    window: u32,
+) -> Result<(), String> {
+    if !threshold.is_finite() || threshold <= 0.0 {
+        return Err("threshold must be greater than 0".to_string());
+    }
+    if matches!(
+        metric,
+        ApiUsageMetric::Cpu | ApiUsageMetric::Memory | ApiUsageMetric::DiskUsage
+    ) && threshold > 100.0
+    {
+        return Err("threshold for cpu/memory/disk_usage is a percentage (0-100]".to_string());
+    }
+    if !(60..=3600).contains(&window) {
+        return Err("window must be between 60 and 3600 seconds".to_string());
+    }
+    Ok(())
+}
+
+/// Backup schedule of a VM
+#[derive(Serialize, Deserialize)]
+pub struct ApiVmBackupSchedule {
+    /// Hours between backups
+    pub interval_hours: u16,
+    pub enabled: bool,
+    /// When the schedule last ran
+    pub last_run: Option<DateTime<Utc>>,
+    /// When the next backup is due, none when disabled
+    pub next_run: Option<DateTime<Utc>>,
+    /// Number of backups kept, the oldest is removed when a new one is taken
+    pub retention: u16,
+}
+
+impl ApiVmBackupSchedule {
+    pub fn new(s: lnvps_db::VmBackupSchedule, retention: u16) -> Self {
+        ApiVmBackupSchedule {
+            interval_hours: s.interval_hours,
+            enabled: s.enabled,
+            last_run: s.last_run,
+            next_run: s.next_run(),
+            retention,
+        }
+    }
+}
+
+/// Request body to set the backup schedule of a VM
+#[derive(Serialize, Deserialize)]
+pub struct PutVmBackupSchedule {
+    pub interval_hours: u16,
+    /// Defaults to true
+    pub enabled: Option<bool>,
+}
+
+/// A backup of a VM
+#[derive(Serialize, Deserialize)]
+pub struct ApiVmBackup {
+    pub id: u64,
+    /// Size in bytes
+    pub size: u64,
+    pub created: DateTime<Utc>,
+}
+
+impl From<lnvps_db::VmBackup> for ApiVmBackup {
+    fn from(b: lnvps_db::VmBackup) -> Self {
+        ApiVmBackup {
+            id: b.id,
+            size: b.size,
+            created: b.created,
+        }
+    }
+}
+
+/// Validate the interval of a backup schedule
+pub fn validate_backup_interval(interval_hours: u16) -> Result<(), String> {
+    if !(6..=720).contains(&interval_hours) {
+        return Err("interval_hours must be between 6 and 720".to_string());
+    }
+    Ok(())
+}
+
 // ============================================================================
 // Subscription Models
 // ============================================================================
@@ -1100,6 +1380,9 @@ pub struct ApiSubscriptionPayment {
     pub is_paid: bool,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub paid_at: Option<DateTime<Utc>>,
+    /// Sequential invoice number, set once paid
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub invoice_number: Option<String>,
     pub tax: ApiPrice,
     pub processing_fee: ApiPrice,
     /// Payment-method-specific data needed to complete the payment
@@ -1179,6 +1462,7 @@ impl From<lnvps_db::SubscriptionPayment> for ApiSubscriptionPayment {
             payment_type: ApiSubscriptionPaymentType::from(payment.payment_type),
             is_paid: payment.is_paid,
             paid_at: payment.paid_at,
+            invoice_number: payment.invoice_number,
             tax: tax.into(),
             processing_fee: processing_fee.into(),
             data,
@@ -1439,6 +1723,7 @@ mod tests {
             tax_treatment: None,
             tax_evidence: None,
             tax_breakdown: None,
+            invoice_number: None,
         };
 
         // No deposit seen yet -> waiting for payment, no outpoint.
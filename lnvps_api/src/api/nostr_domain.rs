@@ -1,5 +1,6 @@
 use axum::extract::{Path, State};
-use axum::routing::{delete, get};
+use axum::http::StatusCode;
+use axum::routing::{get, patch, post};
 use axum::{Json, Router};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
@@ -9,6 +10,8 @@ use lnvps_db::{NostrDomain, NostrDomainHandle};
 
 use crate::Nip98Auth;
 use crate::api::RouterState;
+use crate::api::authorize::authorize_domain_owner;
+use crate::nostr_domain::is_public_domain_name;
 
 pub fn router() -> Router<RouterState> {
     Router::new()
@@ -17,12 +20,16 @@ pub fn router() -> Router<RouterState> {
             get(v1_nostr_domains).post(v1_create_nostr_domain),
         )
         .route(
-            "/api/v1/nostr/domain/<dom>/handle",
+            "/api/v1/nostr/domain/{dom}/verify",
+            post(v1_verify_nostr_domain),
+        )
+        .route(
+            "/api/v1/nostr/domain/{dom}/handle",
             get(v1_list_nostr_domain_handles).post(v1_create_nostr_domain_handle),
         )
         .route(
-            "/api/v1/nostr/domain/<dom>/handle/<handle>",
-            delete(v1_delete_nostr_domain_handle),
+            "/api/v1/nostr/domain/{dom}/handle/{handle}",
+            patch(v1_update_nostr_domain_handle).delete(v1_delete_nostr_domain_handle),
         )
 }
 
@@ -45,6 +52,11 @@ async fn v1_create_nostr_domain(
     State(this): State<RouterState>,
     Json(data): Json<NameRequest>,
 ) -> ApiResult<ApiNostrDomain> {
+    if !is_public_domain_name(&data.name) {
+        return Err(ApiError::validation(
+            "Domain must be a public DNS name, without a port",
+        ));
+    }
     let pubkey = auth.pubkey();
     let uid = this.db.upsert_user(&pubkey).await?;
 
@@ -60,6 +72,40 @@ async fn v1_create_nostr_domain(
     ApiData::ok(dom.into())
 }
 
+/// Check the domain's DNS / activation path now instead of waiting for the
+/// periodic check, enabling it if either is set up
+async fn v1_verify_nostr_domain(
+    auth: Nip98Auth,
+    State(this): State<RouterState>,
+    Path(dom): Path<u64>,
+) -> ApiResult<ApiNostrDomainVerification> {
+    let pubkey = auth.pubkey();
+    let uid = this.db.upsert_user(&pubkey).await?;
+
+    let domain = authorize_domain_owner(this.db.as_ref(), uid, dom).await?;
+
+    if !this.domain_verifier.try_verify(domain.id) {
+        return Err(ApiError::with_status(
+            StatusCode::TOO_MANY_REQUESTS,
+            "This domain was checked recently, try again in a minute",
+        ));
+    }
+    let cname = this.settings.nostr_address_host.clone().unwrap_or_default();
+    let (domain, check) = this.domain_verifier.verify(&this.db, &domain).await?;
+
+    ApiData::ok(ApiNostrDomainVerification {
+        records: vec![ApiDnsRecord {
+            kind: "CNAME".to_string(),
+            name: domain.name.clone(),
+            value: cname,
+        }],
+        activation_url: this.domain_verifier.activation_url(&domain),
+        dns_verified: check.dns,
+        path_verified: check.path,
+        domain: domain.into(),
+    })
+}
+
 async fn v1_list_nostr_domain_handles(
     auth: Nip98Auth,
     State(this): State<RouterState>,
@@ -68,10 +114,7 @@ async fn v1_list_nostr_domain_handles(
     let pubkey = auth.pubkey();
     let uid = this.db.upsert_user(&pubkey).await?;
 
-    let domain = this.db.get_domain(dom).await?;
-    if domain.owner_id != uid {
-        return Err(ApiError::forbidden("Access denied"));
-    }
+    let domain = authorize_domain_owner(this.db.as_ref(), uid, dom).await?;
 
     let handles = this.db.list_handles(domain.id).await?;
     ApiData::ok(handles.into_iter().map(|h| h.into()).collect())
@@ -86,21 +129,12 @@ async fn v1_create_nostr_domain_handle(
     let pubkey = auth.pubkey();
     let uid = this.db.upsert_user(&pubkey).await?;
 
-    let domain = this.db.get_domain(dom).await?;
-    if domain.owner_id != uid {
-        return Err(ApiError::forbidden("Access denied"));
-    }
-
-    let h_pubkey =
-        hex::decode(&data.pubkey).map_err(|_| ApiError::new("Invalid public key hex encoding"))?;
-    if h_pubkey.len() != 32 {
-        return ApiData::err("Invalid public key");
-    }
+    let domain = authorize_domain_owner(this.db.as_ref(), uid, dom).await?;
 
     let mut handle = NostrDomainHandle {
         domain_id: domain.id,
         handle: data.name.clone(),
-        pubkey: h_pubkey,
+        pubkey: parse_pubkey(&data.pubkey)?,
         ..Default::default()
     };
     let id = this.db.insert_handle(&handle).await?;
@@ -109,20 +143,46 @@ async fn v1_create_nostr_domain_handle(
     ApiData::ok(handle.into())
 }
 
+async fn v1_update_nostr_domain_handle(
+    auth: Nip98Auth,
+    State(this): State<RouterState>,
+    Path((dom, handle)): Path<(u64, u64)>,
+    data: Json<PatchHandleRequest>,
+) -> ApiResult<ApiNostrDomainHandle> {
+    let pubkey = auth.pubkey();
+    let uid = this.db.upsert_user(&pubkey).await?;
+
+    let domain = authorize_domain_owner(this.db.as_ref(), uid, dom).await?;
+    let mut handle = this.db.get_handle(handle).await?;
+    if handle.domain_id != domain.id {
+        return Err(ApiError::not_found("Handle not found"));
+    }
+
+    if let Some(pubkey) = &data.pubkey {
+        handle.pubkey = parse_pubkey(pubkey)?;
+    }
+    if let Some(relays) = &data.relays {
+        handle.relays = join_relays(relays)?;
+    }
+    this.db.update_handle(&handle).await?;
+
+    ApiData::ok(handle.into())
+}
+
 async fn v1_delete_nostr_domain_handle(
     auth: Nip98Auth,
     State(this): State<RouterState>,
-    Path(dom): Path<u64>,
-    Path(handle): Path<u64>,
+    Path((dom, handle)): Path<(u64, u64)>,
 ) -> ApiResult<()> {
     let pubkey = auth.pubkey();
     let uid = this.db.upsert_user(&pubkey).await?;
 
-    let domain = this.db.get_domain(dom).await?;
-    if domain.owner_id != uid {
-        return Err(ApiError::forbidden("Access denied"));
+    let domain = authorize_domain_owner(this.db.as_ref(), uid, dom).await?;
+    let handle = this.db.get_handle(handle).await?;
+    if handle.domain_id != domain.id {
+        return Err(ApiError::not_found("Handle not found"));
     }
-    this.db.delete_handle(handle).await?;
+    this.db.delete_handle(handle.id).await?;
     ApiData::ok(())
 }
 
@@ -137,6 +197,45 @@ struct HandleRequest {
     pub name: String,
 }
 
+#[derive(Deserialize)]
+struct PatchHandleRequest {
+    pub pubkey: Option<String>,
+    pub relays: Option<Vec<String>>,
+}
+
+/// Parse a hex encoded 32-byte nostr public key
+fn parse_pubkey(pubkey: &str) -> Result<Vec<u8>, ApiError> {
+    let pubkey =
+        hex::decode(pubkey).map_err(|_| ApiError::validation("Invalid public key hex encoding"))?;
+    if pubkey.len() != 32 {
+        return Err(ApiError::validation("Invalid public key"));
+    }
+    Ok(pubkey)
+}
+
+/// Check each relay is a websocket URL and join them for storage,
+/// `None` when the list is empty
+fn join_relays(relays: &[String]) -> Result<Option<String>, ApiError> {
+    for relay in relays {
+        let valid = match reqwest::Url::parse(relay) {
+            // relays are stored comma separated
+            Ok(u) => matches!(u.scheme(), "ws" | "wss") && u.has_host() && !relay.contains(','),
+            Err(_) => false,
+        };
+        if !valid {
+            return Err(ApiError::validation(format!(
+                "Invalid relay URL: {}",
+                relay
+            )));
+        }
+    }
+    Ok(if relays.is_empty() {
+        None
+    } else {
+        Some(relays.join(","))
+    })
+}
+
 #[derive(Serialize)]
 struct ApiNostrDomain {
     pub id: u64,
@@ -172,11 +271,14 @@ struct ApiNostrDomainHandle {
     pub created: DateTime<Utc>,
     pub pubkey: String,
     pub relays: Vec<String>,
+    /// The catch-all `_` handle, names without a handle resolve to it
+    pub is_default: bool,
 }
 
 impl From<NostrDomainHandle> for ApiNostrDomainHandle {
     fn from(value: NostrDomainHandle) -> Self {
         Self {
+            is_default: value.is_default(),
             id: value.id,
             domain_id: value.domain_id,
             created: value.created,
@@ -196,3 +298,240 @@ struct ApiDomainsResponse {
     pub domains: Vec<ApiNostrDomain>,
     pub cname: String,
 }
+
+#[derive(Serialize)]
+struct ApiDnsRecord {
+    pub kind: String,
+    pub name: String,
+    pub value: String,
+}
+
+#[derive(Serialize)]
+struct ApiNostrDomainVerification {
+    /// The domain after the check
+    pub domain: ApiNostrDomain,
+    /// DNS records which enable the domain with HTTPS
+    pub records: Vec<ApiDnsRecord>,
+    /// URL which must return the activation response to enable the domain
+    /// over HTTP only
+    pub activation_url: Option<String>,
+    pub dns_verified: bool,
+    pub path_verified: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mocks::{MockNode, MockOnChainProvider};
+    use crate::nostr_domain::NostrDomainVerifier;
+    use crate::settings::mock_settings;
+    use crate::subscription::SubscriptionHandler;
+    use axum::body::{Body, to_bytes};
+    use axum::http::{Method, Request, StatusCode, header};
+    use base64::Engine;
+    use base64::prelude::BASE64_STANDARD;
+    use lnvps_api_common::{
+        ChannelWorkCommander, FeatureFlags, MockDb, MockExchangeRate, VmHistoryLogger,
+        VmStateCache, WorkCommander,
+    };
+    use lnvps_db::LNVpsDbBase;
+    use lnvps_db::nostr::LNVPSNostrDb;
+    use nostr::{EventBuilder, JsonUtil, Keys, Kind, Tag};
+    use std::sync::Arc;
+    use tower::ServiceExt;
+
+    fn test_app(db: Arc<MockDb>) -> anyhow::Result<Router> {
+        let settings = mock_settings();
+        let rates = Arc::new(MockExchangeRate::new());
+        let wrk: Arc<dyn WorkCommander> = Arc::new(ChannelWorkCommander::new());
+        let cache = VmStateCache::new();
+        let sub_handler = SubscriptionHandler::new(
+            settings.clone(),
+            db.clone(),
+            Arc::new(MockNode::default()),
+            Arc::new(MockOnChainProvider::default()),
+            None,
+            rates.clone(),
+            lnvps_api_common::VatClient::new(),
+            wrk.clone(),
+            cache.clone(),
+        )?;
+        Ok(router().with_state(RouterState {
+            db: db.clone(),
+            state: cache,
+            sub_handler,
+            history: VmHistoryLogger::new(db.clone()),
+            settings,
+            rates,
+            work_sender: wrk,
+            feedback: None,
+            geoip: None,
+            readiness: Default::default(),
+            features: FeatureFlags::new(db.clone()),
+            domain_verifier: NostrDomainVerifier::local(None),
+        }))
+    }
+
+    /// Send a NIP-98 signed JSON request, returning the status and body
+    async fn send(
+        app: Router,
+        keys: &Keys,
+        method: Method,
+        path: &str,
+        body: serde_json::Value,
+    ) -> anyhow::Result<(StatusCode, serde_json::Value)> {
+        let event = EventBuilder::new(Kind::HttpAuth, "")
+            .tags([
+                Tag::parse(["u", &format!("http://localhost{}", path)])?,
+                Tag::parse(["method", method.as_str()])?,
+            ])
+            .sign_with_keys(keys)?;
+        let req = Request::builder()
+            .method(method)
+            .uri(path)
+            .header(
+                header::AUTHORIZATION,
+                format!("Nostr {}", BASE64_STANDARD.encode(event.as_json())),
+            )
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))?;
+        let rsp = app.oneshot(req).await?;
+        let status = rsp.status();
+        let body = to_bytes(rsp.into_body(), usize::MAX).await?;
+        Ok((status, serde_json::from_slice(&body).unwrap_or_default()))
+    }
+
+    /// A domain owned by `keys` with a single handle, returns (domain id, handle id)
+    async fn setup_handle(db: &MockDb, keys: &Keys) -> anyhow::Result<(u64, u64)> {
+        let uid = db.upsert_user(&keys.public_key().to_bytes()).await?;
+        let domain_id = db
+            .insert_domain(&NostrDomain {
+                owner_id: uid,
+                name: "example.com".to_string(),
+                ..Default::default()
+            })
+            .await?;
+        let handle_id = db
+            .insert_handle(&NostrDomainHandle {
+                domain_id,
+                handle: "alice".to_string(),
+                pubkey: vec![1; 32],
+                ..Default::default()
+            })
+            .await?;
+        Ok((domain_id, handle_id))
+    }
+
+    #[tokio::test]
+    async fn test_update_handle() -> anyhow::Result<()> {
+        let db = Arc::new(MockDb::default());
+        let keys = Keys::generate();
+        let (domain_id, handle_id) = setup_handle(&db, &keys).await?;
+
+        let (status, body) = send(
+            test_app(db.clone())?,
+            &keys,
+            Method::PATCH,
+            &format!("/api/v1/nostr/domain/{}/handle/{}", domain_id, handle_id),
+            serde_json::json!({
+                "pubkey": hex::encode([2; 32]),
+                "relays": ["wss://relay.example.com", "ws://10.0.0.1:7777"]
+            }),
+        )
+        .await?;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["data"]["relays"].as_array().map(|r| r.len()), Some(2));
+
+        let handle = db.get_handle(handle_id).await?;
+        assert_eq!(handle.pubkey, vec![2; 32]);
+        assert_eq!(
+            handle.relays.as_deref(),
+            Some("wss://relay.example.com,ws://10.0.0.1:7777")
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_update_handle_rejects_invalid_relay() -> anyhow::Result<()> {
+        let db = Arc::new(MockDb::default());
+        let keys = Keys::generate();
+        let (domain_id, handle_id) = setup_handle(&db, &keys).await?;
+
+        for relay in [
+            "https://relay.example.com",
+            "not a url",
+            "wss://a.com,wss://b.com",
+        ] {
+            let (status, _) = send(
+                test_app(db.clone())?,
+                &keys,
+                Method::PATCH,
+                &format!("/api/v1/nostr/domain/{}/handle/{}", domain_id, handle_id),
+                serde_json::json!({ "relays": [relay] }),
+            )
+            .await?;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{}", relay);
+        }
+        assert!(db.get_handle(handle_id).await?.relays.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_delete_handle_by_id() -> anyhow::Result<()> {
+        let db = Arc::new(MockDb::default());
+        let keys = Keys::generate();
+        let (domain_id, alice) = setup_handle(&db, &keys).await?;
+        let bob = db
+            .insert_handle(&NostrDomainHandle {
+                domain_id,
+                handle: "bob".to_string(),
+                pubkey: vec![2; 32],
+                ..Default::default()
+            })
+            .await?;
+
+        let (status, _) = send(
+            test_app(db.clone())?,
+            &keys,
+            Method::DELETE,
+            &format!("/api/v1/nostr/domain/{}/handle/{}", domain_id, bob),
+            serde_json::Value::Null,
+        )
+        .await?;
+        assert_eq!(status, StatusCode::OK);
+
+        let handles = db.list_handles(domain_id).await?;
+        assert_eq!(handles.len(), 1);
+        assert_eq!(handles[0].id, alice);
+        assert_eq!(handles[0].handle, "alice");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_delete_handle_of_other_domain() -> anyhow::Result<()> {
+        let db = Arc::new(MockDb::default());
+        let keys = Keys::generate();
+        let (_, alice) = setup_handle(&db, &keys).await?;
+        let uid = db.upsert_user(&keys.public_key().to_bytes()).await?;
+        let other = db
+            .insert_domain(&NostrDomain {
+                owner_id: uid,
+                name: "example.net".to_string(),
+                ..Default::default()
+            })
+            .await?;
+
+        // the caller owns both domains, but alice isn't a handle of the other one
+        let (status, _) = send(
+            test_app(db.clone())?,
+            &keys,
+            Method::DELETE,
+            &format!("/api/v1/nostr/domain/{}/handle/{}", other, alice),
+            serde_json::Value::Null,
+        )
+        .await?;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert!(db.get_handle(alice).await.is_ok());
+        Ok(())
+    }
+}
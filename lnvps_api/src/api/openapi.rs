@@ -0,0 +1,56 @@
+use crate::api::RouterState;
+use axum::Router;
+use axum::http::header;
+use axum::response::{Html, IntoResponse};
+use axum::routing::get;
+
+mod spec {
+    include!(concat!(env!("OUT_DIR"), "/openapi.rs"));
+}
+
+/// OpenAPI document generated from the route handlers at build time, with
+/// Swagger UI served at `/docs` (and the older `/swagger`)
+pub fn router() -> Router<RouterState> {
+    Router::new()
+        .route("/openapi.json", get(openapi_json))
+        .route("/docs", get(swagger_ui))
+        .route("/swagger", get(swagger_ui))
+}
+
+async fn openapi_json() -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "application/json")],
+        spec::OPENAPI_JSON,
+    )
+}
+
+async fn swagger_ui() -> Html<&'static str> {
+    Html(include_str!("swagger.html"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_openapi_json_is_openapi_3() -> anyhow::Result<()> {
+        let doc: serde_json::Value = serde_json::from_str(spec::OPENAPI_JSON)?;
+        let version = doc["openapi"].as_str().unwrap_or_default();
+        assert!(version.starts_with("3."), "not OpenAPI 3: {}", version);
+        assert!(doc["info"]["title"].is_string());
+
+        let paths = doc["paths"].as_object().expect("paths object");
+        let mut expected = vec!["/api/v1/vm", "/api/v1/vm/{id}", "/api/v1/payment/{id}"];
+        if cfg!(feature = "nostr-domain") {
+            expected.extend([
+                "/api/v1/nostr/domain",
+                "/api/v1/nostr/domain/{dom}/handle",
+                "/api/v1/nostr/domain/{dom}/handle/{handle}",
+            ]);
+        }
+        for path in expected {
+            assert!(paths.contains_key(path), "missing path {}", path);
+        }
+        Ok(())
+    }
+}
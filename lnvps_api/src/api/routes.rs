@@ -1,47 +1,67 @@
 use anyhow::Result;
 use axum::extract::ws::{Message, WebSocket};
 use axum::extract::{Path, Query, State, WebSocketUpgrade};
+use axum::http::{HeaderMap, StatusCode, header};
 use axum::response::{Html, IntoResponse};
 use axum::routing::{any, delete, get, patch, post};
 use axum::{Json, Router};
-use chrono::{DateTime, Datelike, Utc};
+use chrono::{DateTime, Datelike, TimeDelta, Utc};
 use futures::StreamExt;
 use futures::future::join_all;
 use isocountry::CountryCode;
 use lnurl::pay::PayResponse;
 use lnurl::{LnUrlResponse, Tag};
-use log::{error, info};
+use log::{error, info, warn};
 use nostr_sdk::{ToBech32, Url};
 use payments_rs::currency::CurrencyAmount;
 use serde::Serialize;
 use ssh_key::PublicKey;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::io::Cursor;
 use std::str::FromStr;
+use std::sync::Arc;
 use std::time::Duration;
 
+use lnvps_api_common::retry::OpResult;
 use lnvps_api_common::{
-    ApiCurrency, ApiData, ApiError, ApiResult, ApiUserSshKey, ApiVmOsImage, ApiVmTemplate,
-    ClientIp, JobFeedback, JobFeedbackStatus, Nip98Auth, PageQuery, TraderDetails, UpgradeConfig,
-    VatClient, WorkJob,
+    ApiCurrency, ApiCursorData, ApiCursorResult, ApiData, ApiError, ApiResult, ApiSystemNotice,
+    ApiTaxDisplay, ApiUserSshKey, ApiVmBandwidthUsage, ApiVmIpAssignment, ApiVmOsImage,
+    ApiVmTemplate, ClientIp, FEATURE_BACKUPS, FeatureFlags, GuestInfo, Invoice, JobFeedback,
+    JobFeedbackStatus, Nip98Auth, PageCursor, PageQuery, TraderDetails, UpgradeConfig,
+    VmHistoryLogger, VmRunningState, VmRunningStates, VmStateCache, WorkJob, op_fatal,
 };
 use lnvps_db::{
-    CpuArch, LNVpsDb, PaymentMethod, Region, Vm, VmCustomPricing, VmCustomPricingDisk,
-    VmCustomTemplate, VmHost,
+    CpuArch, IntervalType, LNVpsDb, OsImageKind, PaymentMethod, Region, Vm, VmCustomPricing,
+    VmCustomPricingDisk, VmCustomTemplate, VmHost, VmStateKind,
 };
 
+use crate::api::authorize::{
+    authorize_payment_owner, authorize_subscription_payment_owner, authorize_vm_owner, get_owned_vm,
+};
 use crate::api::model::{
     AccountPatchRequest, AccountPatchResult, AccountTaxInfo, AddNwcPaymentMethodRequest,
-    ApiCompany, ApiCustomTemplateParams, ApiCustomVmOrder, ApiCustomVmPrice, ApiCustomVmRequest,
-    ApiInvoiceItem, ApiPaymentInfo, ApiPaymentMethod, ApiTemplatesResponse, ApiVmFirewallPolicy,
-    ApiVmFirewallRule, ApiVmHistory, ApiVmPayment, ApiVmStatus, ApiVmUpgradeQuote,
-    ApiVmUpgradeRequest, CreateSshKey, CreateVmFirewallRule, CreateVmRequest,
-    PatchPaymentMethodRequest, PatchVmFirewallPolicy, PatchVmFirewallRule, PaymentMethodResponse,
-    VMPatchRequest, validate_firewall_cidr, validate_firewall_ports, vm_to_status,
+    ApiAccountAmount, ApiAccountSummary, ApiCompany, ApiCustomTemplateParams, ApiCustomVmOrder,
+    ApiCustomVmPrice, ApiCustomVmRequest, ApiInvoiceItem, ApiPaymentInfo, ApiPaymentMethod,
+    ApiTemplatesResponse, ApiUserIpAssignment, ApiVmBackup, ApiVmBackupSchedule,
+    ApiVmFirewallPolicy, ApiVmFirewallRule, ApiVmHistory, ApiVmPasswordReset, ApiVmPayment,
+    ApiVmStateCounts, ApiVmStatus, ApiVmUpgradeQuote, ApiVmUpgradeRequest, ApiVmUsageAlert,
+    CreateSshKey, CreateVmFirewallRule, CreateVmRequest, CreateVmUsageAlert,
+    PatchPaymentMethodRequest, PatchVmFirewallPolicy, PatchVmFirewallRule, PatchVmUsageAlert,
+    PaymentMethodResponse, PutVmBackupSchedule, VMPatchRequest, VmPasswordResetRequest,
+    validate_backup_interval, validate_firewall_cidr, validate_firewall_ports,
+    validate_usage_alert, vm_to_status,
 };
 use crate::api::{AmountQuery, AuthQuery, PaymentMethodQuery, RouterState};
-use crate::host::{FullVmInfo, TimeSeries, TimeSeriesData, get_host_client};
-use crate::provisioner::{HostCapacityService, PricingEngine};
+use crate::host::{
+    FullVmInfo, PasswordResetMethod, TimeSeries, TimeSeriesData, VmHostClient, get_host_client,
+};
+use crate::metrics::ApiMetrics;
+use crate::provisioner::{
+    HostCapacityService, PricingEngine, ensure_image_allowed_in_region, generate_vm_password,
+    validate_vm_password,
+};
+use crate::settings::SuspendedBilling;
+use crate::subscription::end_vm_suspension;
 
 pub fn routes() -> Router<RouterState> {
     Router::new()
@@ -50,6 +70,7 @@ pub fn routes() -> Router<RouterState> {
             get(v1_get_account).patch(v1_patch_account),
         )
         .route("/api/v1/account/verify-email", get(v1_verify_email))
+        .route("/api/v1/account/summary", get(v1_get_account_summary))
         .route(
             "/api/v1/payment-methods",
             get(v1_list_payment_methods).post(v1_add_nwc_payment_method),
@@ -75,6 +96,7 @@ pub fn routes() -> Router<RouterState> {
             get(v1_notification_channels),
         )
         .route("/api/v1/vm", get(v1_list_vms))
+        .route("/api/v1/ip", get(v1_list_ip_assignments))
         .route("/api/v1/vm/{id}", get(v1_get_vm).patch(v1_patch_vm))
         .route("/api/v1/image", get(v1_list_vm_images))
         .route("/api/v1/vm/templates", get(v1_list_vm_templates))
@@ -98,8 +120,17 @@ pub fn routes() -> Router<RouterState> {
         .route("/api/v1/vm/{id}/start", patch(v1_start_vm))
         .route("/api/v1/vm/{id}/stop", patch(v1_stop_vm))
         .route("/api/v1/vm/{id}/restart", patch(v1_restart_vm))
+        .route("/api/v1/vm/{id}/reboot", patch(v1_reboot_vm))
+        .route("/api/v1/vm/{id}/suspend", patch(v1_suspend_vm))
+        .route("/api/v1/vm/{id}/resume", patch(v1_resume_vm))
         .route("/api/v1/vm/{id}/re-install", patch(v1_reinstall_vm))
+        .route("/api/v1/vm/{id}/password", patch(v1_reset_vm_password))
+        .route("/api/v1/vm/{id}/transfer", post(v1_transfer_vm))
         .route("/api/v1/vm/{id}/time-series", get(v1_time_series))
+        .route(
+            "/api/v1/vm/{id}/console/screenshot",
+            get(v1_console_screenshot),
+        )
         .route(
             "/api/v1/vm/{id}/console",
             any(
@@ -116,8 +147,13 @@ pub fn routes() -> Router<RouterState> {
             ),
         )
         .route("/api/v1/payment/methods", get(v1_get_payment_methods))
+        .route("/api/v1/notices", get(v1_list_notices))
         .route("/api/v1/payment/{id}", get(v1_get_payment))
         .route("/api/v1/payment/{id}/invoice", get(v1_get_payment_invoice))
+        .route(
+            "/api/v1/payment/{id}/invoice/pdf",
+            get(v1_get_payment_invoice_pdf),
+        )
         .route("/api/v1/vm/{id}/payments", get(v1_payment_history))
         .route("/api/v1/vm/{id}/history", get(v1_get_vm_history))
         .route("/api/v1/vm/{id}/upgrade/quote", post(v1_vm_upgrade_quote))
@@ -134,6 +170,25 @@ pub fn routes() -> Router<RouterState> {
             "/api/v1/vm/{id}/firewall/{rule_id}",
             patch(v1_patch_firewall_rule).delete(v1_delete_firewall_rule),
         )
+        .route(
+            "/api/v1/vm/{id}/alerts",
+            get(v1_list_usage_alerts).post(v1_create_usage_alert),
+        )
+        .route(
+            "/api/v1/vm/{id}/alerts/{alert_id}",
+            patch(v1_patch_usage_alert).delete(v1_delete_usage_alert),
+        )
+        .route(
+            "/api/v1/vm/{id}/backup-schedule",
+            get(v1_get_backup_schedule)
+                .put(v1_put_backup_schedule)
+                .delete(v1_delete_backup_schedule),
+        )
+        .route("/api/v1/vm/{id}/backups", get(v1_list_backups))
+        .route(
+            "/api/v1/vm/{id}/backups/{backup_id}/restore",
+            post(v1_restore_backup),
+        )
 }
 
 /// Capture IP-derived geolocation for a user as an independent place-of-supply
@@ -168,6 +223,10 @@ async fn v1_patch_account(
 
     capture_client_geo(&this, uid, client_ip).await;
 
+    // previous VAT registration inputs, the stored validation stays valid while these are unchanged
+    let prev_tax_id = user.billing_tax_id.clone();
+    let prev_country_code = user.country_code.clone();
+
     // validate and handle email change
     let mut pending_verification: Option<String> = None;
     if let Some(new_email_opt) = &req.email {
@@ -225,6 +284,9 @@ async fn v1_patch_account(
     user.contact_email = req.contact_email;
     user.contact_telegram = req.contact_telegram;
     user.contact_whatsapp = req.contact_whatsapp;
+    if let Some(policy) = req.placement_policy {
+        user.placement_policy = policy.into();
+    }
     if let Some(country_code) = &req.country_code {
         user.country_code = country_code
             .as_ref()
@@ -253,16 +315,29 @@ async fn v1_patch_account(
         user.billing_tax_id = tax_id.clone();
     }
 
-    // Validate the tax ID (VAT number) against VIES when one is set, and ask
-    // VIES to match the customer's billing name/address against the registered
-    // values. An invalid VAT number is a hard error; name/address mismatches are
-    // surfaced as non-fatal warnings so the account is still saved.
+    // Validate the tax ID (VAT number) against VIES when one is set for an EU
+    // country, and ask VIES to match the customer's billing name/address against
+    // the registered values. An invalid VAT number is a hard error; name/address
+    // mismatches are surfaced as non-fatal warnings so the account is still saved.
+    // VIES is only called again when the tax ID or country changes, so editing
+    // other account settings doesn't depend on VIES being up.
     let mut warnings: Vec<String> = Vec::new();
+    let tax_id_changed =
+        prev_tax_id != user.billing_tax_id || prev_country_code != user.country_code;
+    if tax_id_changed {
+        user.billing_tax_id_validated = false;
+        user.billing_tax_id_name = None;
+    }
+    let eu_customer = user
+        .country_code
+        .as_deref()
+        .is_some_and(lnvps_api_common::is_eu_vat_country);
     if let Some(tax_id) = user
         .billing_tax_id
         .as_deref()
         .map(str::trim)
-        .filter(|s| !s.is_empty())
+        .filter(|s| tax_id_changed && eu_customer && !s.is_empty())
+        .map(str::to_string)
     {
         let trader = TraderDetails {
             name: user.billing_name.clone(),
@@ -276,13 +351,18 @@ async fn v1_patch_account(
             city: user.billing_city.clone(),
             company_type: None,
         };
-        let result = VatClient::new()
-            .validate_vat_number_with_trader(tax_id, None, Some(&trader))
+        let result = this
+            .sub_handler
+            .pricing_engine()
+            .vat_client()
+            .validate_vat_number_with_trader(&tax_id, None, Some(&trader))
             .await
             .map_err(|e| ApiError::bad_request(format!("Failed to validate tax ID: {}", e)))?;
         if !result.valid {
-            return ApiData::err("Invalid tax ID");
+            return ApiData::err("Invalid tax ID, it is not registered in the EU VIES database");
         }
+        user.billing_tax_id_validated = true;
+        user.billing_tax_id_name = result.name.clone();
         let mismatches = result.mismatched_fields();
         if !mismatches.is_empty() {
             warnings.push(format!(
@@ -413,6 +493,98 @@ async fn build_account_tax_info(
     out
 }
 
+/// Get a dashboard summary of the account (VM states, spend, expiry, credit, unpaid invoices)
+async fn v1_get_account_summary(
+    auth: Nip98Auth,
+    State(this): State<RouterState>,
+) -> ApiResult<ApiAccountSummary> {
+    let pubkey = auth.pubkey();
+    let uid = this.db.upsert_user(&pubkey).await?;
+    ApiData::ok(build_account_summary(&this.db, &this.state, uid).await?)
+}
+
+/// Assemble the account summary from the same data served by the VM list,
+/// VM payments and subscription endpoints.
+async fn build_account_summary(
+    db: &Arc<dyn LNVpsDb>,
+    state: &VmStateCache,
+    uid: u64,
+) -> Result<ApiAccountSummary> {
+    let mut vms = ApiVmStateCounts::default();
+    let mut next_expiry: Option<DateTime<Utc>> = None;
+    let mut unpaid_invoices = vec![];
+    for vm in db.list_user_vms(uid).await? {
+        vms.total += 1;
+        match state.get_state(vm.id).await.map(|s| s.state) {
+            Some(VmRunningStates::Running) => vms.running += 1,
+            Some(VmRunningStates::Stopped) => vms.stopped += 1,
+            Some(VmRunningStates::Creating) => vms.creating += 1,
+            Some(VmRunningStates::Suspended) => vms.suspended += 1,
+            Some(VmRunningStates::Migrating) => vms.migrating += 1,
+            Some(VmRunningStates::Unknown) | None => vms.unknown += 1,
+        }
+        if let Ok(sub) = db
+            .get_subscription_by_line_item_id(vm.subscription_line_item_id)
+            .await
+            && let Some(expires) = sub.expires
+        {
+            next_expiry = Some(next_expiry.map_or(expires, |e| e.min(expires)));
+        }
+        for p in db.list_pending_vm_subscription_payments(vm.id).await? {
+            unpaid_invoices.push(ApiVmPayment::for_vm(db, p, vm.id).await?);
+        }
+    }
+    unpaid_invoices.sort_by(|a, b| b.created.cmp(&a.created));
+
+    let mut spend: BTreeMap<String, f64> = BTreeMap::new();
+    for sub in db.list_subscriptions_active(uid).await? {
+        let per_month = match (sub.interval_type, sub.interval_amount as f64) {
+            (_, 0.0) => 0.0,
+            // average days per month
+            (IntervalType::Day, n) => 30.436875 / n,
+            (IntervalType::Month, n) => 1.0 / n,
+            (IntervalType::Year, n) => 1.0 / (n * 12.0),
+        };
+        let amount: u64 = db
+            .list_subscription_line_items(sub.id)
+            .await?
+            .iter()
+            .map(|i| i.amount)
+            .sum();
+        *spend.entry(sub.currency).or_default() += amount as f64 * per_month;
+    }
+    let monthly_spend = spend
+        .into_iter()
+        .map(|(currency, amount)| ApiAccountAmount {
+            currency,
+            amount: amount.round() as u64,
+        })
+        .collect();
+
+    let mut currencies: BTreeSet<String> = db
+        .list_subscriptions_by_user(uid)
+        .await?
+        .into_iter()
+        .map(|s| s.currency)
+        .collect();
+    currencies.insert("BTC".to_string());
+    let mut credit = vec![];
+    for currency in currencies {
+        let amount = db.get_user_credit(uid, &currency).await?;
+        if amount > 0 {
+            credit.push(ApiAccountAmount { currency, amount });
+        }
+    }
+
+    Ok(ApiAccountSummary {
+        vms,
+        monthly_spend,
+        next_expiry,
+        credit,
+        unpaid_invoices,
+    })
+}
+
 /// List the user's saved payment methods for automatic renewals.
 async fn v1_list_payment_methods(
     auth: Nip98Auth,
@@ -714,6 +886,12 @@ async fn v1_list_vms(
         .into_iter()
         .map(|h| (h.id, h))
         .collect();
+    let mut states = resolve_vm_states(&this.state, &this.db, &vms, |vm| {
+        hosts
+            .get(&vm.host_id)
+            .and_then(|h| get_host_client(h, &this.settings.provisioner).ok())
+    })
+    .await;
     let mut ret = vec![];
     for vm in vms {
         let vm_id = vm.id;
@@ -723,7 +901,7 @@ async fn v1_list_vms(
                 &this.db,
                 vm,
                 host,
-                this.state.get_state(vm_id).await,
+                states.remove(&vm_id),
                 this.settings.delete_after,
                 this.settings.max_prepay_days,
             )
@@ -734,6 +912,28 @@ async fn v1_list_vms(
     ApiData::ok(ret)
 }
 
+/// List the IPs of all VMs belonging to user
+async fn v1_list_ip_assignments(
+    auth: Nip98Auth,
+    State(this): State<RouterState>,
+) -> ApiResult<Vec<ApiUserIpAssignment>> {
+    let pubkey = auth.pubkey();
+    let uid = this.db.upsert_user(&pubkey).await?;
+    ApiData::ok(user_ip_assignments(&this.db, uid).await?)
+}
+
+async fn user_ip_assignments(db: &Arc<dyn LNVpsDb>, uid: u64) -> Result<Vec<ApiUserIpAssignment>> {
+    Ok(db
+        .list_user_ip_assignments(uid)
+        .await?
+        .into_iter()
+        .map(|(vm, ip, range)| ApiUserIpAssignment {
+            vm_id: vm.id,
+            ip: ApiVmIpAssignment::from(&ip, &range),
+        })
+        .collect())
+}
+
 /// Get status of a VM
 async fn v1_get_vm(
     auth: Nip98Auth,
@@ -742,17 +942,140 @@ async fn v1_get_vm(
 ) -> ApiResult<ApiVmStatus> {
     let (_uid, vm) = get_user_vm(&auth, &this, id).await?;
     let host = this.db.get_host(vm.host_id).await.ok();
-    ApiData::ok(
-        vm_to_status(
-            &this.db,
-            vm,
-            host,
-            this.state.get_state(id).await,
-            this.settings.delete_after,
-            this.settings.max_prepay_days,
-        )
-        .await?,
+    let client = host
+        .as_ref()
+        .and_then(|h| get_host_client(h, &this.settings.provisioner).ok());
+    let mut states = resolve_vm_states(&this.state, &this.db, std::slice::from_ref(&vm), |_| {
+        client.clone()
+    })
+    .await;
+    let mut status = vm_to_status(
+        &this.db,
+        vm.clone(),
+        host,
+        states.remove(&id),
+        this.settings.delete_after,
+        this.settings.max_prepay_days,
     )
+    .await?;
+    if status.status.state == VmRunningStates::Running {
+        if let Some(client) = client {
+            status.guest_info = fetch_guest_info(client.as_ref(), &vm).await;
+        }
+    }
+    // the usage is extra detail, a failed lookup doesn't fail the VM
+    status.bandwidth_usage = match ApiVmBandwidthUsage::load(&this.db, &vm, Utc::now()).await {
+        Ok(u) => Some(u),
+        Err(e) => {
+            warn!("Failed to load bandwidth usage of VM {}: {}", vm.id, e);
+            None
+        }
+    };
+    ApiData::ok(status)
+}
+
+/// States older than this are re-read from the host, the worker checks every 30s
+const VM_STATE_MAX_AGE: TimeDelta = TimeDelta::minutes(2);
+
+/// How long a listing waits for a host to report a stale VM's state
+const VM_STATE_LIVE_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Load the running state of VMs, from the worker's state cache or the stored
+/// `vm_state` rows. The stored row is only rewritten when the state changes,
+/// otherwise only its `last_seen` is refreshed.
+///
+/// States older than [VM_STATE_MAX_AGE] (or missing) are read live from the
+/// host, keeping the stale state when the host doesn't answer in time. The
+/// `timestamp` of each state is when it was read from the host.
+async fn resolve_vm_states(
+    cache: &VmStateCache,
+    db: &Arc<dyn LNVpsDb>,
+    vms: &[Vm],
+    client_for: impl Fn(&Vm) -> Option<Arc<dyn VmHostClient>>,
+) -> HashMap<u64, VmRunningState> {
+    let max_age = VM_STATE_MAX_AGE.num_seconds() as u64;
+    let now = Utc::now().timestamp() as u64;
+    let is_fresh = |s: &VmRunningState| now.saturating_sub(s.timestamp) <= max_age;
+
+    let mut ret = HashMap::new();
+    let mut missing = Vec::new();
+    for vm in vms {
+        match cache.get_state(vm.id).await {
+            Some(s) if is_fresh(&s) => {
+                ret.insert(vm.id, s);
+            }
+            _ => missing.push(vm),
+        }
+    }
+    if missing.is_empty() {
+        return ret;
+    }
+
+    let ids: Vec<u64> = missing.iter().map(|v| v.id).collect();
+    let stored = match db.list_vm_states(&ids).await {
+        Ok(s) => s,
+        Err(e) => {
+            warn!("Failed to load stored VM states: {}", e);
+            vec![]
+        }
+    };
+    let stored_kinds: HashMap<u64, VmStateKind> =
+        stored.iter().map(|s| (s.vm_id, s.state)).collect();
+    for s in stored {
+        ret.insert(
+            s.vm_id,
+            VmRunningState {
+                timestamp: s.last_seen.timestamp() as u64,
+                state: s.state.into(),
+                ..Default::default()
+            },
+        );
+    }
+
+    let live = missing
+        .into_iter()
+        .filter(|vm| !ret.get(&vm.id).is_some_and(is_fresh))
+        .filter_map(|vm| client_for(vm).map(|c| (vm, c)))
+        .map(|(vm, c)| async move {
+            let state = tokio::time::timeout(VM_STATE_LIVE_TIMEOUT, c.get_vm_state(vm)).await;
+            (vm.id, state)
+        });
+    let mut unchanged = Vec::new();
+    for (vm_id, state) in join_all(live).await {
+        match state {
+            Ok(Ok(s)) => {
+                let kind = VmStateKind::from(&s.state);
+                if stored_kinds.get(&vm_id) == Some(&kind) {
+                    unchanged.push(vm_id);
+                } else if let Err(e) = db.upsert_vm_state(vm_id, kind).await {
+                    warn!("Failed to save VM {} state: {}", vm_id, e);
+                }
+                ret.insert(vm_id, s);
+            }
+            Ok(Err(e)) => info!("Failed to read state of VM {}: {}", vm_id, e),
+            Err(_) => info!("Timeout reading state of VM {}", vm_id),
+        }
+    }
+    if let Err(e) = db.touch_vm_states(&unchanged).await {
+        warn!("Failed to refresh stored VM states: {}", e);
+    }
+    ret
+}
+
+/// Query the guest agent for interface/uptime info, a missing agent or a slow
+/// host never fails the VM info request
+async fn fetch_guest_info(client: &dyn VmHostClient, vm: &Vm) -> Option<GuestInfo> {
+    match tokio::time::timeout(Duration::from_secs(5), client.get_guest_info(vm)).await {
+        Ok(Ok(info)) => Some(info),
+        Ok(Err(e)) => {
+            info!("No guest info for VM {}: {}", vm.id, e);
+            None
+        }
+        Err(_) => {
+            info!("Timeout loading guest info for VM {}", vm.id);
+            None
+        }
+    }
 }
 
 /// Update a VM config
@@ -827,6 +1150,9 @@ struct ImageListQuery {
     /// `aarch64`). When set, only images matching that architecture (plus
     /// architecture-agnostic images) are returned.
     arch: Option<String>,
+    /// Optional region the VM will be created in, only images offered in that
+    /// region are returned.
+    region: Option<u64>,
 }
 
 /// Whether an image with `image_arch` should be included given an optional
@@ -855,18 +1181,35 @@ async fn v1_list_vm_images(
         None => None,
     };
 
-    let images = this.db.list_os_image().await?;
+    ApiData::ok(list_vm_images(&this.db, arch_filter, q.region).await?)
+}
+
+/// Enabled cloud-init images with their popularity, optionally limited to an
+/// architecture and to the images offered in a region
+async fn list_vm_images(
+    db: &Arc<dyn LNVpsDb>,
+    arch_filter: Option<CpuArch>,
+    region_id: Option<u64>,
+) -> anyhow::Result<Vec<ApiVmOsImage>> {
+    // Only cloud-init images can be used for automated provisioning
+    let images = db.list_os_image_by_kind(OsImageKind::CloudInit).await?;
+    // An empty allowlist means the region offers every image
+    let allowed = match region_id {
+        Some(r) => db.list_region_os_images(r).await?,
+        None => vec![],
+    };
 
     // Compute popularity as the fraction of active VMs using each image
-    let counts: HashMap<u64, u64> = this.db.count_vms_by_os_image().await?.into_iter().collect();
+    let counts: HashMap<u64, u64> = db.count_vms_by_os_image().await?.into_iter().collect();
     let total: u64 = counts.values().sum();
 
-    let ret = images
+    Ok(images
         .into_iter()
         .filter(|i| i.enabled)
         // Architecture filter: keep images matching the requested arch, plus
         // architecture-agnostic images (`Unknown` = any).
         .filter(|i| image_matches_arch(i.cpu_arch, arch_filter))
+        .filter(|i| allowed.is_empty() || allowed.contains(&i.id))
         .map(|i| {
             let count = counts.get(&i.id).copied().unwrap_or(0);
             let mut image: ApiVmOsImage = i.into();
@@ -877,16 +1220,12 @@ async fn v1_list_vm_images(
             };
             image
         })
-        .collect();
-    ApiData::ok(ret)
+        .collect())
 }
 
 /// List available VM templates (Offers)
 async fn v1_list_vm_templates(State(this): State<RouterState>) -> ApiResult<ApiTemplatesResponse> {
     let hc = HostCapacityService::new(this.db.clone());
-    let templates = hc.list_available_vm_templates().await?;
-
-    let cost_plans: HashSet<u64> = templates.iter().map(|t| t.cost_plan_id).collect();
     let regions: HashMap<u64, Region> = this
         .db
         .list_host_region()
@@ -894,6 +1233,23 @@ async fn v1_list_vm_templates(State(this): State<RouterState>) -> ApiResult<ApiT
         .into_iter()
         .map(|h| (h.id, h))
         .collect();
+    // prices are shown per the tax display of each region's seller
+    let company_ids: HashSet<u64> = regions.values().map(|r| r.company_id).collect();
+    let mut tax_display = HashMap::new();
+    for id in company_ids {
+        tax_display.insert(id, this.db.get_company(id).await?.tax_display);
+    }
+
+    // only offer templates which can currently be placed in their region
+    let templates: Vec<_> = hc
+        .list_available_templates(None)
+        .await?
+        .into_iter()
+        .filter(|t| t.available)
+        .map(|t| t.template)
+        .collect();
+
+    let cost_plans: HashSet<u64> = templates.iter().map(|t| t.cost_plan_id).collect();
 
     let cost_plans: Vec<_> = cost_plans
         .into_iter()
@@ -914,7 +1270,8 @@ async fn v1_list_vm_templates(State(this): State<RouterState>) -> ApiResult<ApiT
         .filter_map(|i| {
             let cp = cost_plans.get(&i.cost_plan_id)?;
             let hr = regions.get(&i.region_id)?;
-            ApiVmTemplate::from_standard_data(i, cp, hr).ok()
+            let td = *tax_display.get(&hr.company_id)?;
+            ApiVmTemplate::from_standard_data(i, cp, hr, td).ok()
         })
         .collect();
     let custom_templates: Vec<VmCustomPricing> =
@@ -950,6 +1307,7 @@ async fn v1_list_vm_templates(State(this): State<RouterState>) -> ApiResult<ApiT
                         &t,
                         &custom_template_disks,
                         region,
+                        *tax_display.get(&region.company_id)?,
                     ))
                 })
                 .collect();
@@ -970,13 +1328,19 @@ async fn v1_custom_template_calc(
     let template: VmCustomTemplate = req.into();
 
     // Reject out-of-range specs so the order form surfaces the error early.
-    PricingEngine::validate_custom_vm_spec(&this.db, &template).await?;
+    PricingEngine::validate_custom_vm_spec(&this.db, &template, &this.settings.custom_vm_floors)
+        .await?;
 
     let price = PricingEngine::get_custom_vm_cost_amount(&this.db, 0, &template).await?;
     let amount = CurrencyAmount::from_u64(price.currency, price.total());
+    let pricing = this.db.get_custom_pricing(template.pricing_id).await?;
+    let region = this.db.get_host_region(pricing.region_id).await?;
+    let company = this.db.get_company(region.company_id).await?;
     // Include conversions to the other supported currencies, like the template
     // listing's `other_price`.
-    ApiData::ok(ApiCustomVmPrice::from_amount(amount, &this.rates).await?)
+    let mut rsp = ApiCustomVmPrice::from_amount(amount, &this.rates).await?;
+    rsp.tax_display = company.tax_display.into();
+    ApiData::ok(rsp)
 }
 
 /// Create a new VM order
@@ -988,9 +1352,11 @@ async fn v1_custom_template_calc(
 async fn v1_create_custom_vm_order(
     auth: Nip98Auth,
     client_ip: ClientIp,
+    headers: HeaderMap,
     State(this): State<RouterState>,
     Json(req): Json<ApiCustomVmOrder>,
 ) -> ApiResult<ApiVmStatus> {
+    let request_id = client_request_id(&headers, req.client_request_id.as_deref())?;
     let pubkey = auth.pubkey();
     let uid = this.db.upsert_user(&pubkey).await?;
 
@@ -1010,17 +1376,31 @@ async fn v1_create_custom_vm_order(
     // create a fake template from the request to generate the order
     let template = req.spec.clone().into();
 
-    let rsp = this
-        .sub_handler
-        .vm_provisioner()
-        .provision_custom(uid, template, req.image_id, req.ssh_key_id, req.ref_code)
-        .await?;
+    let provisioner = this.sub_handler.vm_provisioner();
+    let res = provisioner
+        .provision_once(
+            uid,
+            request_id.as_deref(),
+            provisioner.provision_custom(
+                uid,
+                template,
+                req.image_id,
+                req.ssh_key_id,
+                req.ref_code,
+                req.private_network,
+            ),
+        )
+        .await;
+    ApiMetrics::global().record_vm_operation("create", res.is_ok());
+    let (rsp, created) = res?;
 
     // Log VM creation
-    this.history
-        .log_vm_created(&rsp, Some(uid), None)
-        .await
-        .ok();
+    if created {
+        this.history
+            .log_vm_created(&rsp, Some(uid), None)
+            .await
+            .ok();
+    }
 
     let host = this.db.get_host(rsp.host_id).await.ok();
     ApiData::ok(
@@ -1127,9 +1507,11 @@ async fn v1_delete_ssh_key(
 async fn v1_create_vm_order(
     auth: Nip98Auth,
     client_ip: ClientIp,
+    headers: HeaderMap,
     State(this): State<RouterState>,
     Json(req): Json<CreateVmRequest>,
 ) -> ApiResult<ApiVmStatus> {
+    let request_id = client_request_id(&headers, req.client_request_id.as_deref())?;
     let pubkey = auth.pubkey();
     let uid = this.db.upsert_user(&pubkey).await?;
 
@@ -1145,23 +1527,31 @@ async fn v1_create_vm_order(
         ));
     }
 
-    let rsp = this
-        .sub_handler
-        .vm_provisioner()
-        .provision(
+    let provisioner = this.sub_handler.vm_provisioner();
+    let res = provisioner
+        .provision_once(
             uid,
-            req.template_id,
-            req.image_id,
-            req.ssh_key_id,
-            req.ref_code,
+            request_id.as_deref(),
+            provisioner.provision(
+                uid,
+                req.template_id,
+                req.image_id,
+                req.ssh_key_id,
+                req.ref_code,
+                req.private_network,
+            ),
         )
-        .await?;
+        .await;
+    ApiMetrics::global().record_vm_operation("create", res.is_ok());
+    let (rsp, created) = res?;
 
     // Log VM creation
-    this.history
-        .log_vm_created(&rsp, Some(uid), None)
-        .await
-        .ok();
+    if created {
+        this.history
+            .log_vm_created(&rsp, Some(uid), None)
+            .await
+            .ok();
+    }
 
     let host = this.db.get_host(rsp.host_id).await.ok();
     ApiData::ok(
@@ -1177,6 +1567,25 @@ async fn v1_create_vm_order(
     )
 }
 
+/// Client request id of a VM create, from the request body or the
+/// `X-Client-Request-Id` header. The body field takes precedence.
+fn client_request_id(headers: &HeaderMap, field: Option<&str>) -> Result<Option<String>, ApiError> {
+    let id = match field {
+        Some(id) => Some(id),
+        None => headers
+            .get("x-client-request-id")
+            .map(|v| v.to_str())
+            .transpose()
+            .map_err(|_| ApiError::bad_request("Invalid X-Client-Request-Id header"))?,
+    };
+    match id.map(str::trim) {
+        Some(id) if id.is_empty() || id.len() > 64 => Err(ApiError::bad_request(
+            "client_request_id must be between 1 and 64 characters",
+        )),
+        id => Ok(id.map(str::to_string)),
+    }
+}
+
 /// Renew(Extend) a VM
 async fn v1_renew_vm(
     auth: Nip98Auth,
@@ -1197,7 +1606,7 @@ async fn v1_renew_vm(
         .renew_subscription_with_mode(vm_line.subscription_id, method, intervals, mode)
         .await?;
 
-    ApiData::ok(ApiVmPayment::from_subscription_payment(payment, id)?)
+    ApiData::ok(ApiVmPayment::for_vm(&this.db, payment, id).await?)
 }
 
 /// LNURL-pay callback response (LUD-06).
@@ -1275,11 +1684,13 @@ async fn v1_lnurlp(
 
     let meta = vec![vec!["text/plain".to_string(), format!("Extend VM {}", id)]];
     let rsp = PayResponse {
-        callback: Url::parse(&this.settings.public_url)
-            .map_err(|_| "Invalid public url")?
-            .join(&format!("/api/v1/vm/{}/renew-lnurlp", id))
-            .map_err(|_| "Could not get callback url")?
-            .to_string(),
+        callback: Url::parse(&format!(
+            "{}/api/v1/vm/{}/renew-lnurlp",
+            this.settings.public_url.trim_end_matches('/'),
+            id
+        ))
+        .map_err(|_| "Invalid public url")?
+        .to_string(),
         max_sendable: 1_000_000_000,
         min_sendable: 100_000, // TODO: calc min by using 1s extend time
         tag: Tag::PayRequest,
@@ -1291,19 +1702,135 @@ async fn v1_lnurlp(
     Ok(Json(rsp))
 }
 
-/// Start a VM
-async fn v1_start_vm(
-    auth: Nip98Auth,
-    State(this): State<RouterState>,
-    Path(id): Path<u64>,
+/// Power actions a user can run against their VM
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum VmPowerAction {
+    Start,
+    Stop,
+    /// Hard reset
+    Restart,
+    /// Graceful (ACPI) reboot, the host falls back to a reset on timeout
+    Reboot,
+    /// Pause the VM keeping its memory
+    Suspend,
+    Resume,
+}
+
+impl VmPowerAction {
+    fn as_str(&self) -> &'static str {
+        match self {
+            VmPowerAction::Start => "start",
+            VmPowerAction::Stop => "stop",
+            VmPowerAction::Restart => "restart",
+            VmPowerAction::Reboot => "reboot",
+            VmPowerAction::Suspend => "suspend",
+            VmPowerAction::Resume => "resume",
+        }
+    }
+}
+
+/// Run a power action on the host and record it in the VM history
+async fn run_vm_power_action(
+    client: &dyn VmHostClient,
+    history: &VmHistoryLogger,
+    vm: &Vm,
+    uid: u64,
+    action: VmPowerAction,
+) -> OpResult<()> {
+    match action {
+        VmPowerAction::Start => {
+            client.start_vm(vm).await?;
+            history.log_vm_started(vm.id, Some(uid), None).await.ok();
+        }
+        VmPowerAction::Stop => {
+            client.stop_vm(vm).await?;
+            history.log_vm_stopped(vm.id, Some(uid), None).await.ok();
+        }
+        VmPowerAction::Restart => {
+            // Hard reset (restart) the VM — previously this only issued a stop,
+            // leaving the VM powered off.
+            client.reset_vm(vm).await?;
+            history.log_vm_restarted(vm.id, Some(uid), None).await.ok();
+        }
+        VmPowerAction::Reboot => {
+            // reboots are queued on the worker by [vm_power_action]
+            op_fatal!("VM reboots run as a RebootVm job")
+        }
+        VmPowerAction::Suspend => {
+            client.suspend_vm(vm).await?;
+            history.log_vm_suspended(vm.id, Some(uid), None).await.ok();
+        }
+        VmPowerAction::Resume => {
+            client.resume_vm(vm).await?;
+            history.log_vm_resumed(vm.id, Some(uid), None).await.ok();
+        }
+    }
+    Ok(())
+}
+
+/// Track the suspension of a VM after a power action. Suspending stores the
+/// time, any other action ends the suspension (see [end_vm_suspension]).
+/// Returns the time credited.
+async fn track_suspension(
+    db: &Arc<dyn LNVpsDb>,
+    vm: &Vm,
+    action: VmPowerAction,
+    billing: SuspendedBilling,
+    max_suspended: TimeDelta,
+) -> Result<Option<TimeDelta>> {
+    if action == VmPowerAction::Suspend {
+        db.set_vm_suspended_at(vm.id, Utc::now()).await?;
+        return Ok(None);
+    }
+    end_vm_suspension(db, vm, billing, max_suspended).await
+}
+
+async fn vm_power_action(
+    auth: &Nip98Auth,
+    this: &RouterState,
+    id: u64,
+    action: VmPowerAction,
 ) -> ApiResult<()> {
-    let (uid, vm) = get_user_vm(&auth, &this, id).await?;
+    let (uid, vm) = get_user_vm(auth, this, id).await?;
+    if action == VmPowerAction::Reboot {
+        // a graceful reboot waits for the guest to shut down (up to the host's
+        // reboot timeout), so run it on the worker instead of holding the request
+        if let Some(s) = this.state.get_state(vm.id).await
+            && s.state != VmRunningStates::Running
+        {
+            return Err(ApiError::bad_request("VM is not running"));
+        }
+        this.work_sender
+            .send(WorkJob::RebootVm {
+                vm_id: vm.id,
+                user_id: uid,
+            })
+            .await?;
+        return ApiData::ok(());
+    }
     let host = this.db.get_host(vm.host_id).await?;
     let client = get_host_client(&host, &this.settings.provisioner)?;
-    client.start_vm(&vm).await?;
-
-    // Log VM start
-    this.history.log_vm_started(id, Some(uid), None).await.ok();
+    let res = run_vm_power_action(client.as_ref(), &this.history, &vm, uid, action).await;
+    ApiMetrics::global().record_vm_operation(action.as_str(), res.is_ok());
+    res?;
+
+    match track_suspension(
+        &this.db,
+        &vm,
+        action,
+        this.settings.suspended_billing,
+        TimeDelta::days(this.settings.max_suspended_days as i64),
+    )
+    .await
+    {
+        Ok(Some(t)) => info!(
+            "Extended VM {} expiry by {}s spent suspended",
+            vm.id,
+            t.num_seconds()
+        ),
+        Ok(None) => {}
+        Err(e) => warn!("Failed to track suspension of VM {}: {}", vm.id, e),
+    }
 
     this.work_sender
         .send(WorkJob::CheckVm { vm_id: id })
@@ -1311,24 +1838,22 @@ async fn v1_start_vm(
     ApiData::ok(())
 }
 
+/// Start a VM
+async fn v1_start_vm(
+    auth: Nip98Auth,
+    State(this): State<RouterState>,
+    Path(id): Path<u64>,
+) -> ApiResult<()> {
+    vm_power_action(&auth, &this, id, VmPowerAction::Start).await
+}
+
 /// Stop a VM
 async fn v1_stop_vm(
     auth: Nip98Auth,
     State(this): State<RouterState>,
     Path(id): Path<u64>,
 ) -> ApiResult<()> {
-    let (uid, vm) = get_user_vm(&auth, &this, id).await?;
-    let host = this.db.get_host(vm.host_id).await?;
-    let client = get_host_client(&host, &this.settings.provisioner)?;
-    client.stop_vm(&vm).await?;
-
-    // Log VM stop
-    this.history.log_vm_stopped(id, Some(uid), None).await.ok();
-
-    this.work_sender
-        .send(WorkJob::CheckVm { vm_id: id })
-        .await?;
-    ApiData::ok(())
+    vm_power_action(&auth, &this, id, VmPowerAction::Stop).await
 }
 
 /// Restart a VM
@@ -1337,53 +1862,190 @@ async fn v1_restart_vm(
     State(this): State<RouterState>,
     Path(id): Path<u64>,
 ) -> ApiResult<()> {
-    let (uid, vm) = get_user_vm(&auth, &this, id).await?;
-    let host = this.db.get_host(vm.host_id).await?;
-    let client = get_host_client(&host, &this.settings.provisioner)?;
-    // Hard reset (restart) the VM — previously this only issued a stop, leaving
-    // the VM powered off.
-    client.reset_vm(&vm).await?;
-
-    // Log VM restart
-    this.history
-        .log_vm_restarted(id, Some(uid), None)
-        .await
-        .ok();
+    vm_power_action(&auth, &this, id, VmPowerAction::Restart).await
+}
 
-    this.work_sender
-        .send(WorkJob::CheckVm { vm_id: id })
-        .await?;
-    ApiData::ok(())
+/// Gracefully reboot a VM
+async fn v1_reboot_vm(
+    auth: Nip98Auth,
+    State(this): State<RouterState>,
+    Path(id): Path<u64>,
+) -> ApiResult<()> {
+    vm_power_action(&auth, &this, id, VmPowerAction::Reboot).await
 }
 
-#[derive(serde::Deserialize, Default)]
-#[serde(default)]
-struct ReinstallRequest {
-    /// Optionally switch to a different OS image during the reinstall
-    image_id: Option<u64>,
+/// Suspend a VM, keeping its memory
+async fn v1_suspend_vm(
+    auth: Nip98Auth,
+    State(this): State<RouterState>,
+    Path(id): Path<u64>,
+) -> ApiResult<()> {
+    vm_power_action(&auth, &this, id, VmPowerAction::Suspend).await
 }
 
-/// Re-install a VM
-async fn v1_reinstall_vm(
+/// Resume a suspended VM
+async fn v1_resume_vm(
     auth: Nip98Auth,
     State(this): State<RouterState>,
     Path(id): Path<u64>,
-    body: Option<Json<ReinstallRequest>>,
 ) -> ApiResult<()> {
-    let (uid, mut vm) = get_user_vm(&auth, &this, id).await?;
+    vm_power_action(&auth, &this, id, VmPowerAction::Resume).await
+}
+
+/// Reset the login password of a VM's default user
+async fn v1_reset_vm_password(
+    auth: Nip98Auth,
+    State(this): State<RouterState>,
+    Path(id): Path<u64>,
+    body: Option<Json<VmPasswordResetRequest>>,
+) -> ApiResult<ApiVmPasswordReset> {
+    let (uid, vm) = get_user_vm(&auth, &this, id).await?;
     let req = body.map(|Json(b)| b).unwrap_or_default();
+    let (password, generated) = match req.password {
+        Some(p) => {
+            validate_vm_password(&p).map_err(ApiError::bad_request)?;
+            (p, false)
+        }
+        None => (generate_vm_password(), true),
+    };
 
-    // Reject re-install only on a *genuinely* expired VM (a concrete expiry in
-    // the past). Such a VM may already be stopped/removed on the host, so
-    // running the reinstall pipeline would fail with a 500. A subscription with
-    // no expiry yet (e.g. a freshly provisioned VM whose payment expiry hasn't
-    // propagated) is treated as active — matching the client, which shows such
-    // a VM as new — rather than blocked with a misleading "renew first" error.
-    // A missing/failed subscription lookup surfaces as an error, not "expired".
-    let subscription = this
-        .db
-        .get_subscription_by_line_item_id(vm.subscription_line_item_id)
-        .await?;
+    let method = this
+        .sub_handler
+        .vm_provisioner()
+        .reset_vm_password(vm.id, &password)
+        .await?;
+
+    // applying the password may have started or rebooted the VM
+    let meta = Some(serde_json::json!({ "reason": "password_reset" }));
+    match method {
+        PasswordResetMethod::Started => {
+            this.history
+                .log_vm_started(vm.id, Some(uid), meta)
+                .await
+                .ok();
+        }
+        PasswordResetMethod::Rebooted => {
+            this.history
+                .log_vm_rebooted(vm.id, Some(uid), meta)
+                .await
+                .ok();
+        }
+        PasswordResetMethod::GuestAgent => {}
+    }
+    this.work_sender
+        .send(WorkJob::CheckVm { vm_id: id })
+        .await?;
+    ApiData::ok(ApiVmPasswordReset {
+        password: generated.then_some(password),
+    })
+}
+
+#[derive(serde::Deserialize)]
+struct TransferVmRequest {
+    /// Pubkey of the new owner, hex or npub
+    pubkey: String,
+    /// Must be true, the transfer can only be undone by the new owner
+    #[serde(default)]
+    confirm: bool,
+    /// OpenSSH public key of the new owner, replaces the old owner's key on
+    /// the VM
+    #[serde(default)]
+    ssh_key: Option<String>,
+}
+
+/// Transfer a VM to another user
+async fn v1_transfer_vm(
+    auth: Nip98Auth,
+    State(this): State<RouterState>,
+    Path(id): Path<u64>,
+    Json(req): Json<TransferVmRequest>,
+) -> ApiResult<()> {
+    let (uid, vm) = get_user_vm(&auth, &this, id).await?;
+    if !req.confirm {
+        return ApiData::err("Transfer must be confirmed");
+    }
+    let new_owner = nostr_sdk::PublicKey::parse(&req.pubkey)
+        .map_err(|_| ApiError::bad_request("Invalid pubkey"))?;
+
+    let new_uid = this
+        .sub_handler
+        .vm_provisioner()
+        .transfer_vm(vm.id, &new_owner.to_bytes(), req.ssh_key.as_deref())
+        .await?;
+
+    // drop the old owner's key from the host config
+    if req.ssh_key.is_some()
+        && let Err(e) = this
+            .work_sender
+            .send(WorkJob::ConfigureVm {
+                vm_id: vm.id,
+                admin_user_id: None,
+            })
+            .await
+    {
+        warn!("Failed to queue re-configure of VM {}: {}", vm.id, e);
+    }
+
+    let title = Some(format!("[VM{}] Transferred", vm.id));
+    for (user_id, message) in [
+        (
+            uid,
+            format!(
+                "Your VM #{} has been transferred to another account.",
+                vm.id
+            ),
+        ),
+        (
+            new_uid,
+            format!("VM #{} has been transferred to your account.", vm.id),
+        ),
+    ] {
+        if let Err(e) = this
+            .work_sender
+            .send(WorkJob::SendNotification {
+                user_id,
+                message,
+                title: title.clone(),
+            })
+            .await
+        {
+            warn!(
+                "Failed to queue transfer notification for VM {}: {}",
+                vm.id, e
+            );
+        }
+    }
+    ApiData::ok(())
+}
+
+#[derive(serde::Deserialize, Default)]
+#[serde(default)]
+struct ReinstallRequest {
+    /// Optionally switch to a different OS image during the reinstall
+    image_id: Option<u64>,
+}
+
+/// Re-install a VM
+async fn v1_reinstall_vm(
+    auth: Nip98Auth,
+    State(this): State<RouterState>,
+    Path(id): Path<u64>,
+    body: Option<Json<ReinstallRequest>>,
+) -> ApiResult<()> {
+    let (uid, vm) = get_user_vm(&auth, &this, id).await?;
+    let req = body.map(|Json(b)| b).unwrap_or_default();
+
+    // Reject re-install only on a *genuinely* expired VM (a concrete expiry in
+    // the past). Such a VM may already be stopped/removed on the host, so
+    // running the reinstall pipeline would fail with a 500. A subscription with
+    // no expiry yet (e.g. a freshly provisioned VM whose payment expiry hasn't
+    // propagated) is treated as active — matching the client, which shows such
+    // a VM as new — rather than blocked with a misleading "renew first" error.
+    // A missing/failed subscription lookup surfaces as an error, not "expired".
+    let subscription = this
+        .db
+        .get_subscription_by_line_item_id(vm.subscription_line_item_id)
+        .await?;
     if is_vm_expired(subscription.expires, Utc::now()) {
         return Err(ApiError::payment_required(
             "Cannot re-install an expired VM, please renew it first",
@@ -1392,19 +2054,34 @@ async fn v1_reinstall_vm(
 
     let old_image_id = vm.image_id;
 
-    // Optionally switch to a different OS image. Persist the change before
-    // loading FullVmInfo so the reinstall pipeline provisions the new image.
+    // Optionally switch to a different OS image. The reinstall pipeline
+    // persists the change once the new disk is imported, so a failed
+    // reinstall leaves the VM on its old image.
     if let Some(new_image_id) = req.image_id
         && new_image_id != old_image_id
     {
         let image = this.db.get_os_image(new_image_id).await?;
-        if !image.enabled {
+        // container templates are swapped in place, the provisioner checks
+        // the image kind matches the VM
+        if !image.enabled
+            || !matches!(
+                image.image_kind,
+                OsImageKind::CloudInit | OsImageKind::ContainerTemplate
+            )
+        {
             return Err(ApiError::forbidden("OS image is not available"));
         }
-        vm.image_id = new_image_id;
-        this.db.update_vm(&vm).await?;
+        let host = this.db.get_host(vm.host_id).await?;
+        if ensure_image_allowed_in_region(&this.db, host.region_id, image.id)
+            .await
+            .is_err()
+        {
+            return Err(ApiError::forbidden(
+                "OS image is not available in this region",
+            ));
+        }
     }
-    let new_image_id = vm.image_id;
+    let new_image_id = req.image_id.unwrap_or(old_image_id);
 
     // Without a real job-feedback service (dev/tests without Redis) run the
     // reinstall pipeline inline so behaviour is unchanged. In production the
@@ -1413,7 +2090,7 @@ async fn v1_reinstall_vm(
     let Some(feedback) = this.feedback.clone() else {
         this.sub_handler
             .vm_provisioner()
-            .reinstall_vm(vm.id)
+            .reinstall_vm(vm.id, new_image_id)
             .await?;
         this.history
             .log_vm_reinstalled(id, Some(uid), old_image_id, new_image_id, None)
@@ -1482,6 +2159,19 @@ async fn v1_time_series(
     ApiData::ok(client.get_time_series_data(&vm, TimeSeries::Hourly).await?)
 }
 
+/// PNG screenshot of the VM display
+async fn v1_console_screenshot(
+    auth: Nip98Auth,
+    State(this): State<RouterState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, ApiError> {
+    let (_, vm) = get_user_vm(&auth, &this, id).await?;
+    let host = this.db.get_host(vm.host_id).await?;
+    let client = get_host_client(&host, &this.settings.provisioner)?;
+    let png = client.get_console_screenshot(&vm).await?;
+    Ok(([(header::CONTENT_TYPE, "image/png")], png))
+}
+
 #[allow(unused)]
 async fn v1_terminal_proxy(
     id: u64,
@@ -1502,10 +2192,13 @@ async fn v1_terminal_proxy(
         .upsert_user(&pubkey)
         .await
         .map_err(|_| "Insert failed")?;
-    let vm = this.db.get_vm(id).await.map_err(|_| "VM not found")?;
-    if uid != vm.user_id {
-        return Err("VM does not belong to you");
-    }
+    let vm = get_owned_vm(this.db.as_ref(), uid, id).await.map_err(|e| {
+        if e.code == StatusCode::FORBIDDEN {
+            "VM does not belong to you"
+        } else {
+            "VM not found"
+        }
+    })?;
 
     let host = this
         .db
@@ -1561,6 +2254,34 @@ async fn v1_terminal_proxy(
     Ok(())
 }
 
+#[derive(serde::Deserialize)]
+struct NoticeQuery {
+    /// Region the customer is interested in, region scoped notices are only
+    /// returned for their region
+    region: Option<u64>,
+}
+
+/// Notices (e.g. planned maintenance) to show as a banner right now
+async fn v1_list_notices(
+    State(this): State<RouterState>,
+    Query(q): Query<NoticeQuery>,
+) -> ApiResult<Vec<ApiSystemNotice>> {
+    ApiData::ok(active_notices(this.db.as_ref(), q.region).await?)
+}
+
+async fn active_notices(
+    db: &dyn LNVpsDb,
+    region_id: Option<u64>,
+) -> anyhow::Result<Vec<ApiSystemNotice>> {
+    Ok(db
+        .list_active_system_notices()
+        .await?
+        .into_iter()
+        .filter(|n| n.applies_to(region_id))
+        .map(ApiSystemNotice::from)
+        .collect())
+}
+
 async fn v1_get_payment_methods(State(this): State<RouterState>) -> ApiResult<Vec<ApiPaymentInfo>> {
     let configs = this.db.list_payment_method_configs().await?;
     ApiData::ok(build_payment_methods_response(configs))
@@ -1658,16 +2379,9 @@ async fn v1_get_payment(
         return ApiData::err("Invalid payment id");
     };
 
-    let payment = this.db.get_subscription_payment(&id).await?;
-    let vm = this
-        .db
-        .get_vm_by_subscription(payment.subscription_id)
-        .await?;
-    if vm.user_id != uid {
-        return Err(ApiError::forbidden("VM does not belong to you"));
-    }
+    let (payment, vm) = authorize_payment_owner(this.db.as_ref(), uid, &id).await?;
 
-    ApiData::ok(ApiVmPayment::from_subscription_payment(payment, vm.id)?)
+    ApiData::ok(ApiVmPayment::for_vm(&this.db, payment, vm.id).await?)
 }
 
 /// Map a payment's stored tax fields to invoice display fields:
@@ -1742,19 +2456,15 @@ async fn v1_get_payment_invoice(
         return Err("Invalid payment id");
     };
 
-    let payment = this
-        .db
-        .get_subscription_payment(&id)
-        .await
-        .map_err(|_| "Payment not found")?;
-    let vm = this
-        .db
-        .get_vm_by_subscription(payment.subscription_id)
+    let (payment, vm) = authorize_payment_owner(this.db.as_ref(), uid, &id)
         .await
-        .map_err(|_| "VM not found")?;
-    if vm.user_id != uid {
-        return Err("VM does not belong to you");
-    }
+        .map_err(|e| {
+            if e.code == StatusCode::FORBIDDEN {
+                "Payment does not belong to you"
+            } else {
+                "Payment not found"
+            }
+        })?;
     let vm_id_for_payment = vm.id;
 
     if !payment.is_paid {
@@ -1904,59 +2614,136 @@ async fn v1_get_payment_invoice(
     Ok(Html(String::from_utf8(html.into_inner()).unwrap()))
 }
 
+/// Download the invoice for a paid payment as a PDF
+///
+/// Uses the same `?auth=` NIP-98 query param as the HTML invoice so it can be
+/// opened directly from a link.
+async fn v1_get_payment_invoice_pdf(
+    State(this): State<RouterState>,
+    Path(id): Path<String>,
+    Query(q): Query<AuthQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    let auth = Nip98Auth::from_base64(&q.auth)
+        .map_err(|_| ApiError::unauthorized("Missing or invalid auth param"))?;
+    auth.check(&format!("/api/v1/payment/{id}/invoice/pdf"), "GET")
+        .map_err(|_| ApiError::unauthorized("Invalid auth event"))?;
+    let uid = this.db.upsert_user(&auth.pubkey()).await?;
+    let payment_id = hex::decode(&id).map_err(|_| ApiError::bad_request("Invalid payment id"))?;
+    let payment = authorize_subscription_payment_owner(this.db.as_ref(), uid, &payment_id).await?;
+    if !payment.is_paid {
+        return Err(ApiError::bad_request(
+            "Payment is not paid, can't generate invoice",
+        ));
+    }
+    let invoice = Invoice::for_payment(this.db.as_ref(), &payment).await?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/pdf".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("inline; filename=\"invoice-{}.pdf\"", id),
+            ),
+        ],
+        invoice.to_pdf(),
+    ))
+}
+
 /// List payment history of a VM
+///
+/// Pages by `limit`/`offset`, or by the `after` cursor returned as `next`
 async fn v1_payment_history(
     auth: Nip98Auth,
     State(this): State<RouterState>,
     Path(id): Path<u64>,
     Query(q): Query<PageQuery>,
-) -> ApiResult<Vec<ApiVmPayment>> {
+) -> ApiCursorResult<ApiVmPayment> {
     let pubkey = auth.pubkey();
     let uid = this.db.upsert_user(&pubkey).await?;
-    let vm = this.db.get_vm(id).await?;
-    if vm.user_id != uid {
-        return Err(ApiError::forbidden("VM does not belong to you"));
-    }
-
-    let payments = {
-        let limit = q.limit.unwrap_or(50);
-        let offset = q.offset.unwrap_or(0);
-        this.db
-            .list_vm_subscription_payments_paginated(id, limit, offset)
-            .await?
+    let vm = authorize_vm_owner(this.db.as_ref(), uid, id).await?;
+
+    let limit = q.limit.unwrap_or(50);
+    let payments = match (q.offset, &q.after) {
+        (Some(offset), None) => {
+            this.db
+                .list_vm_subscription_payments_paginated(id, limit, offset)
+                .await?
+        }
+        (_, after) => {
+            let after = after
+                .as_deref()
+                .map(PageCursor::decode)
+                .transpose()
+                .map_err(|_| ApiError::bad_request("Invalid cursor"))?
+                .map(|c| (c.timestamp, c.id));
+            this.db
+                .list_vm_subscription_payments_after(id, after, limit)
+                .await?
+        }
     };
-    ApiData::ok(
+    let next = if payments.len() as u64 == limit {
+        payments
+            .last()
+            .map(|p| PageCursor::new(p.created, p.id.clone()).encode())
+    } else {
+        None
+    };
+    let tax_display: ApiTaxDisplay = this
+        .db
+        .get_company(this.db.get_vm_company_id(id).await?)
+        .await?
+        .tax_display
+        .into();
+    ApiCursorData::ok(
         payments
             .into_iter()
-            .map(|p| ApiVmPayment::from_subscription_payment(p, id))
+            .map(|p| {
+                let mut p = ApiVmPayment::from_subscription_payment(p, id)?;
+                p.tax_display = tax_display;
+                Ok(p)
+            })
             .collect::<anyhow::Result<Vec<_>>>()?,
+        next,
     )
 }
 
 /// List action history of a VM
+///
+/// Returns the full history unless paged by `limit`/`offset`, or by `limit`
+/// and the `after` cursor returned as `next`
 async fn v1_get_vm_history(
     auth: Nip98Auth,
     State(this): State<RouterState>,
     Path(id): Path<u64>,
     Query(q): Query<PageQuery>,
-) -> ApiResult<Vec<ApiVmHistory>> {
+) -> ApiCursorResult<ApiVmHistory> {
     let pubkey = auth.pubkey();
     let uid = this.db.upsert_user(&pubkey).await?;
-    let vm = this.db.get_vm(id).await?;
-    if vm.user_id != uid {
-        return Err(ApiError::forbidden("VM does not belong to you"));
-    }
+    let vm = authorize_vm_owner(this.db.as_ref(), uid, id).await?;
 
-    let history = match (q.limit, q.offset) {
-        (Some(limit), Some(offset)) => this.db.list_vm_history_paginated(id, limit, offset).await?,
-        _ => this.db.list_vm_history(id).await?,
+    let (history, next) = match (q.limit, q.offset, &q.after) {
+        (Some(limit), Some(offset), None) => (
+            this.db.list_vm_history_paginated(id, limit, offset).await?,
+            None,
+        ),
+        (None, None, None) => (this.db.list_vm_history(id).await?, None),
+        (limit, _, after) => {
+            let after = after
+                .as_deref()
+                .map(PageCursor::decode)
+                .transpose()
+                .map_err(|_| ApiError::bad_request("Invalid cursor"))?;
+            VmHistoryLogger::new(this.db.clone())
+                .get_vm_history_after(id, after.as_ref(), limit.unwrap_or(50))
+                .await?
+        }
     };
 
-    ApiData::ok(
+    ApiCursorData::ok(
         history
             .into_iter()
             .map(|h| ApiVmHistory::from_with_owner(h, vm.user_id))
             .collect(),
+        next.map(|c| c.encode()),
     )
 }
 
@@ -1970,10 +2757,7 @@ async fn v1_vm_upgrade_quote(
 ) -> ApiResult<ApiVmUpgradeQuote> {
     let pubkey = auth.pubkey();
     let uid = this.db.upsert_user(&pubkey).await?;
-    let vm = this.db.get_vm(id).await?;
-    if vm.user_id != uid {
-        return Err(ApiError::forbidden("VM does not belong to you"));
-    }
+    let vm = get_owned_vm(this.db.as_ref(), uid, id).await?;
 
     // Create UpgradeConfig from request
     let cfg = UpgradeConfig {
@@ -1997,12 +2781,16 @@ async fn v1_vm_upgrade_quote(
     {
         Ok(quote) => {
             let currency = quote.upgrade.amount.currency();
+            let price = quote.price();
             ApiData::ok(ApiVmUpgradeQuote {
                 cost_difference: quote.upgrade.amount.into(),
                 new_renewal_cost: quote.renewal.amount.into(),
                 discount: quote.discount.amount.into(),
                 tax: CurrencyAmount::from_u64(currency, quote.tax.amount).into(),
                 processing_fee: CurrencyAmount::from_u64(currency, quote.processing_fee).into(),
+                net_amount: CurrencyAmount::from_u64(currency, price.net).into(),
+                gross_amount: CurrencyAmount::from_u64(currency, price.gross).into(),
+                tax_display: price.tax_display.into(),
             })
         }
         Err(e) => ApiData::err(e.to_string().as_str()),
@@ -2019,10 +2807,7 @@ async fn v1_vm_upgrade(
 ) -> ApiResult<ApiVmPayment> {
     let pubkey = auth.pubkey();
     let uid = this.db.upsert_user(&pubkey).await?;
-    let vm = this.db.get_vm(id).await?;
-    if vm.user_id != uid {
-        return Err(ApiError::forbidden("VM does not belong to you"));
-    }
+    let vm = get_owned_vm(this.db.as_ref(), uid, id).await?;
 
     // Create UpgradeConfig from request
     let cfg = UpgradeConfig {
@@ -2040,7 +2825,7 @@ async fn v1_vm_upgrade(
         .await?;
 
     // Note: The actual upgrade happens after payment is confirmed
-    ApiData::ok(ApiVmPayment::from_subscription_payment(payment, id)?)
+    ApiData::ok(ApiVmPayment::for_vm(&this.db, payment, id).await?)
 }
 
 /// Default maximum number of user firewall rules per VM when no template limit is set.
@@ -2169,14 +2954,8 @@ async fn v1_patch_firewall_rule(
     Path((id, rule_id)): Path<(u64, u64)>,
     Json(req): Json<PatchVmFirewallRule>,
 ) -> ApiResult<ApiVmFirewallRule> {
-    let (_uid, vm) = get_user_vm(&auth, &this, id).await?;
-
-    let mut rule = this.db.get_vm_firewall_rule(rule_id).await?;
-    if rule.vm_id != vm.id {
-        return Err(ApiError::not_found(
-            "Firewall rule does not belong to this VM",
-        ));
-    }
+    let uid = this.db.upsert_user(&auth.pubkey()).await?;
+    let (vm, mut rule) = get_owned_firewall_rule(this.db.as_ref(), uid, id, rule_id).await?;
 
     if let Some(p) = req.priority {
         rule.priority = p;
@@ -2228,17 +3007,215 @@ async fn v1_delete_firewall_rule(
     State(this): State<RouterState>,
     Path((id, rule_id)): Path<(u64, u64)>,
 ) -> ApiResult<()> {
+    let uid = this.db.upsert_user(&auth.pubkey()).await?;
+    let (vm, _rule) = get_owned_firewall_rule(this.db.as_ref(), uid, id, rule_id).await?;
+
+    this.db.delete_vm_firewall_rule(rule_id).await?;
+    apply_firewall(&this, vm.id).await?;
+    ApiData::ok(())
+}
+
+/// Maximum number of usage alerts per VM
+const MAX_USAGE_ALERTS_PER_VM: usize = 10;
+
+/// List usage alerts for a VM
+async fn v1_list_usage_alerts(
+    auth: Nip98Auth,
+    State(this): State<RouterState>,
+    Path(id): Path<u64>,
+) -> ApiResult<Vec<ApiVmUsageAlert>> {
     let (_uid, vm) = get_user_vm(&auth, &this, id).await?;
+    let alerts = this.db.list_vm_usage_alerts(vm.id).await?;
+    ApiData::ok(alerts.into_iter().map(ApiVmUsageAlert::from).collect())
+}
 
-    let rule = this.db.get_vm_firewall_rule(rule_id).await?;
-    if rule.vm_id != vm.id {
-        return Err(ApiError::not_found(
-            "Firewall rule does not belong to this VM",
+/// Create a usage alert for a VM
+async fn v1_create_usage_alert(
+    auth: Nip98Auth,
+    State(this): State<RouterState>,
+    Path(id): Path<u64>,
+    Json(req): Json<CreateVmUsageAlert>,
+) -> ApiResult<ApiVmUsageAlert> {
+    let (_uid, vm) = get_user_vm(&auth, &this, id).await?;
+
+    let existing = this.db.list_vm_usage_alerts(vm.id).await?;
+    if existing.len() >= MAX_USAGE_ALERTS_PER_VM {
+        return ApiData::err(&format!(
+            "Usage alert limit reached ({})",
+            MAX_USAGE_ALERTS_PER_VM
         ));
     }
+    let window = req.window.unwrap_or(300);
+    if let Err(e) = validate_usage_alert(req.metric, req.threshold, window) {
+        return ApiData::err(&e);
+    }
 
-    this.db.delete_vm_firewall_rule(rule_id).await?;
-    apply_firewall(&this, vm.id).await?;
+    let alert = lnvps_db::VmUsageAlert {
+        id: 0,
+        vm_id: vm.id,
+        metric: req.metric.into(),
+        threshold: req.threshold,
+        window_seconds: window,
+        last_triggered: None,
+        created: Utc::now(),
+    };
+    let alert_id = this.db.insert_vm_usage_alert(&alert).await?;
+    let created = this.db.get_vm_usage_alert(alert_id).await?;
+    ApiData::ok(ApiVmUsageAlert::from(created))
+}
+
+/// Update a usage alert
+async fn v1_patch_usage_alert(
+    auth: Nip98Auth,
+    State(this): State<RouterState>,
+    Path((id, alert_id)): Path<(u64, u64)>,
+    Json(req): Json<PatchVmUsageAlert>,
+) -> ApiResult<ApiVmUsageAlert> {
+    let uid = this.db.upsert_user(&auth.pubkey()).await?;
+    let mut alert = get_owned_usage_alert(this.db.as_ref(), uid, id, alert_id).await?;
+
+    if let Some(m) = req.metric {
+        alert.metric = m.into();
+    }
+    if let Some(t) = req.threshold {
+        alert.threshold = t;
+    }
+    if let Some(w) = req.window {
+        alert.window_seconds = w;
+    }
+    if let Err(e) = validate_usage_alert(alert.metric.into(), alert.threshold, alert.window_seconds)
+    {
+        return ApiData::err(&e);
+    }
+
+    this.db.update_vm_usage_alert(&alert).await?;
+    let updated = this.db.get_vm_usage_alert(alert_id).await?;
+    ApiData::ok(ApiVmUsageAlert::from(updated))
+}
+
+/// Delete a usage alert
+async fn v1_delete_usage_alert(
+    auth: Nip98Auth,
+    State(this): State<RouterState>,
+    Path((id, alert_id)): Path<(u64, u64)>,
+) -> ApiResult<()> {
+    let uid = this.db.upsert_user(&auth.pubkey()).await?;
+    get_owned_usage_alert(this.db.as_ref(), uid, id, alert_id).await?;
+    this.db.delete_vm_usage_alert(alert_id).await?;
+    ApiData::ok(())
+}
+
+/// Backup retention of a VM's plan, backups are unavailable when 0
+async fn vm_backup_retention(this: &RouterState, vm: &Vm) -> Result<u16, ApiError> {
+    Ok(this
+        .sub_handler
+        .vm_provisioner()
+        .backup_retention(vm)
+        .await?)
+}
+
+/// Backups can be switched off per region with the `backups` feature flag
+async fn ensure_backups_available(
+    db: &Arc<dyn LNVpsDb>,
+    features: &FeatureFlags,
+    vm: &Vm,
+) -> Result<(), ApiError> {
+    let host = db.get_host(vm.host_id).await?;
+    features
+        .require(FEATURE_BACKUPS, Some(host.region_id))
+        .await?;
+    Ok(())
+}
+
+/// Get the backup schedule of a VM
+async fn v1_get_backup_schedule(
+    auth: Nip98Auth,
+    State(this): State<RouterState>,
+    Path(id): Path<u64>,
+) -> ApiResult<Option<ApiVmBackupSchedule>> {
+    let (_uid, vm) = get_user_vm(&auth, &this, id).await?;
+    let retention = vm_backup_retention(&this, &vm).await?;
+    let schedule = this.db.get_vm_backup_schedule(vm.id).await?;
+    ApiData::ok(schedule.map(|s| ApiVmBackupSchedule::new(s, retention)))
+}
+
+/// Create or replace the backup schedule of a VM
+async fn v1_put_backup_schedule(
+    auth: Nip98Auth,
+    State(this): State<RouterState>,
+    Path(id): Path<u64>,
+    Json(req): Json<PutVmBackupSchedule>,
+) -> ApiResult<ApiVmBackupSchedule> {
+    let (_uid, vm) = get_user_vm(&auth, &this, id).await?;
+    ensure_backups_available(&this.db, &this.features, &vm).await?;
+    let retention = vm_backup_retention(&this, &vm).await?;
+    if retention == 0 {
+        return ApiData::err("Backups are not included in this VM's plan");
+    }
+    if let Err(e) = validate_backup_interval(req.interval_hours) {
+        return ApiData::err(&e);
+    }
+
+    let existing = this.db.get_vm_backup_schedule(vm.id).await?;
+    let schedule = lnvps_db::VmBackupSchedule {
+        id: existing.as_ref().map(|s| s.id).unwrap_or_default(),
+        vm_id: vm.id,
+        interval_hours: req.interval_hours,
+        enabled: req.enabled.unwrap_or(true),
+        last_run: existing.as_ref().and_then(|s| s.last_run),
+        created: existing.map(|s| s.created).unwrap_or_else(Utc::now),
+    };
+    this.db.upsert_vm_backup_schedule(&schedule).await?;
+    let Some(saved) = this.db.get_vm_backup_schedule(vm.id).await? else {
+        return Err(ApiError::internal("Backup schedule was not saved"));
+    };
+    ApiData::ok(ApiVmBackupSchedule::new(saved, retention))
+}
+
+/// Remove the backup schedule of a VM, existing backups are kept
+async fn v1_delete_backup_schedule(
+    auth: Nip98Auth,
+    State(this): State<RouterState>,
+    Path(id): Path<u64>,
+) -> ApiResult<()> {
+    let (_uid, vm) = get_user_vm(&auth, &this, id).await?;
+    this.db.delete_vm_backup_schedule(vm.id).await?;
+    ApiData::ok(())
+}
+
+/// List backups of a VM, newest first
+async fn v1_list_backups(
+    auth: Nip98Auth,
+    State(this): State<RouterState>,
+    Path(id): Path<u64>,
+) -> ApiResult<Vec<ApiVmBackup>> {
+    let (_uid, vm) = get_user_vm(&auth, &this, id).await?;
+    let backups = this.db.list_vm_backups(vm.id).await?;
+    ApiData::ok(backups.into_iter().map(ApiVmBackup::from).collect())
+}
+
+/// Queue a restore of a VM from one of its backups
+///
+/// The VM is stopped and its disks replaced, the owner is notified once the
+/// restore finishes.
+async fn v1_restore_backup(
+    auth: Nip98Auth,
+    State(this): State<RouterState>,
+    Path((id, backup_id)): Path<(u64, u64)>,
+) -> ApiResult<()> {
+    let (_uid, vm) = get_user_vm(&auth, &this, id).await?;
+    ensure_backups_available(&this.db, &this.features, &vm).await?;
+    let backup = this.db.get_vm_backup(backup_id).await?;
+    if backup.vm_id != vm.id {
+        return Err(ApiError::not_found("Backup does not belong to this VM"));
+    }
+    this.work_sender
+        .send(WorkJob::RestoreVmBackup {
+            vm_id: vm.id,
+            backup_id,
+        })
+        .await
+        .map_err(|e| ApiError::internal(format!("Failed to queue restore: {}", e)))?;
     ApiData::ok(())
 }
 
@@ -2254,14 +3231,42 @@ async fn apply_firewall(this: &RouterState, vm_id: u64) -> Result<(), ApiError>
 async fn get_user_vm(auth: &Nip98Auth, this: &RouterState, id: u64) -> Result<(u64, Vm), ApiError> {
     let pubkey = auth.pubkey();
     let uid = this.db.upsert_user(&pubkey).await?;
-    let vm = this.db.get_vm(id).await?;
-    if uid != vm.user_id {
-        return Err(ApiError::forbidden("VM does not belong to you"));
+    let vm = get_owned_vm(this.db.as_ref(), uid, id).await?;
+    Ok((uid, vm))
+}
+
+/// Load a firewall rule of a VM owned by `uid`
+async fn get_owned_firewall_rule(
+    db: &dyn LNVpsDb,
+    uid: u64,
+    vm_id: u64,
+    rule_id: u64,
+) -> Result<(Vm, lnvps_db::VmFirewallRule), ApiError> {
+    let vm = get_owned_vm(db, uid, vm_id).await?;
+    let rule = db.get_vm_firewall_rule(rule_id).await?;
+    if rule.vm_id != vm.id {
+        return Err(ApiError::not_found(
+            "Firewall rule does not belong to this VM",
+        ));
     }
-    if vm.deleted {
-        return Err(ApiError::not_found("VM not found"));
+    Ok((vm, rule))
+}
+
+/// Load a usage alert of a VM owned by `uid`
+async fn get_owned_usage_alert(
+    db: &dyn LNVpsDb,
+    uid: u64,
+    vm_id: u64,
+    alert_id: u64,
+) -> Result<lnvps_db::VmUsageAlert, ApiError> {
+    let vm = get_owned_vm(db, uid, vm_id).await?;
+    let alert = db.get_vm_usage_alert(alert_id).await?;
+    if alert.vm_id != vm.id {
+        return Err(ApiError::not_found(
+            "Usage alert does not belong to this VM",
+        ));
     }
-    Ok((uid, vm))
+    Ok(alert)
 }
 
 /// Determine whether a VM is expired based on its subscription expiry.
@@ -2278,6 +3283,8 @@ fn is_vm_expired(expires: Option<DateTime<Utc>>, now: DateTime<Utc>) -> bool {
 mod tests {
     use super::*;
     use chrono::Utc;
+    use lnvps_api_common::retry::RetryPolicy;
+    use lnvps_api_common::{CustomVmFloors, VatClient};
     use lnvps_db::PaymentMethodConfig;
 
     fn make_config(
@@ -2350,6 +3357,7 @@ mod tests {
             db.clone(),
             std::sync::Arc::new(lnvps_api_common::MockExchangeRate::new()),
             vat,
+            CustomVmFloors::default(),
         );
         let info = build_account_tax_info(db.as_ref(), &pricing, uid).await;
         assert_eq!(info.len(), 1);
@@ -2370,6 +3378,7 @@ mod tests {
             db.clone(),
             std::sync::Arc::new(lnvps_api_common::MockExchangeRate::new()),
             VatClient::default(),
+            CustomVmFloors::default(),
         );
         let info = build_account_tax_info(db.as_ref(), &pricing, uid).await;
         assert_eq!(info.len(), 1);
@@ -2377,6 +3386,167 @@ mod tests {
         assert_eq!(info[0].treatment, "out_of_scope");
     }
 
+    fn make_payment(id: u8, subscription_id: u64, is_paid: bool) -> lnvps_db::SubscriptionPayment {
+        lnvps_db::SubscriptionPayment {
+            id: vec![id; 32],
+            subscription_id,
+            user_id: 1,
+            created: Utc::now() - chrono::Duration::minutes(id as i64),
+            expires: Utc::now() + chrono::Duration::hours(1),
+            amount: 1000,
+            currency: "BTC".to_string(),
+            payment_method: PaymentMethod::Lightning,
+            payment_type: lnvps_db::SubscriptionPaymentType::Renewal,
+            external_data: "".to_string().into(),
+            external_id: None,
+            is_paid,
+            rate: 1.0,
+            time_value: Some(86400),
+            metadata: None,
+            tax: 0,
+            processing_fee: 0,
+            paid_at: None,
+            tax_rate: None,
+            tax_country_code: None,
+            tax_treatment: None,
+            tax_evidence: None,
+            tax_breakdown: None,
+            invoice_number: None,
+        }
+    }
+
+    /// The account summary must agree with what the VM list and VM payment
+    /// endpoints report for the same account.
+    #[tokio::test]
+    async fn test_account_summary_matches_individual_endpoints() -> Result<()> {
+        use lnvps_api_common::{MockDb, VmRunningState, vm_to_status};
+        use lnvps_db::{LNVpsDbBase, UserSshKey};
+
+        let mock = MockDb::default();
+        let uid = mock.upsert_user(&[1; 32]).await?;
+        mock.insert_user_ssh_key(&UserSshKey {
+            id: 0,
+            name: "k".to_string(),
+            user_id: uid,
+            ..Default::default()
+        })
+        .await?;
+
+        // VM 1: monthly BTC subscription expiring in 3 days
+        // VM 2: yearly EUR subscription expiring in 10 days
+        let soonest = Utc::now() + chrono::Duration::days(3);
+        {
+            let mut subs = mock.subscriptions.lock().await;
+            let sub1 = subs.get_mut(&1).unwrap();
+            sub1.is_active = true;
+            sub1.expires = Some(soonest);
+            let mut sub2 = sub1.clone();
+            sub2.id = 2;
+            sub2.currency = "EUR".to_string();
+            sub2.interval_type = IntervalType::Year;
+            sub2.expires = Some(Utc::now() + chrono::Duration::days(10));
+            subs.insert(2, sub2);
+
+            let mut items = mock.subscription_line_items.lock().await;
+            let mut item2 = items.get(&1).unwrap().clone();
+            item2.id = 2;
+            item2.subscription_id = 2;
+            item2.amount = 1200;
+            items.insert(2, item2);
+
+            let mut vms = mock.vms.lock().await;
+            vms.insert(1, MockDb::mock_vm());
+            let mut vm2 = MockDb::mock_vm();
+            vm2.id = 2;
+            vm2.subscription_line_item_id = 2;
+            vms.insert(2, vm2);
+        }
+        mock.insert_subscription_payment(&make_payment(1, 1, false))
+            .await?;
+        mock.insert_subscription_payment(&make_payment(2, 1, true))
+            .await?;
+        mock.insert_subscription_payment(&make_payment(3, 2, false))
+            .await?;
+        mock.add_user_credit(uid, "BTC", 500).await?;
+
+        let state = VmStateCache::new();
+        state
+            .set_state(
+                1,
+                VmRunningState {
+                    state: VmRunningStates::Running,
+                    ..Default::default()
+                },
+            )
+            .await?;
+
+        let db: Arc<dyn LNVpsDb> = Arc::new(mock);
+        let summary = build_account_summary(&db, &state, uid).await?;
+
+        // GET /api/v1/vm
+        let mut statuses = vec![];
+        for vm in db.list_user_vms(uid).await? {
+            let host = db.get_host(vm.host_id).await.ok();
+            let vm_state = state.get_state(vm.id).await;
+            statuses.push(vm_to_status(&db, vm, host, vm_state, 0, 365).await?);
+        }
+        assert_eq!(summary.vms.total, statuses.len() as u64);
+        assert_eq!(
+            summary.vms.running,
+            statuses
+                .iter()
+                .filter(|s| s.status.state == VmRunningStates::Running)
+                .count() as u64
+        );
+        assert_eq!(summary.vms.unknown, 1);
+        assert_eq!(
+            summary.next_expiry,
+            statuses.iter().filter_map(|s| s.expires).min()
+        );
+        assert_eq!(summary.next_expiry, Some(soonest));
+
+        // GET /api/v1/vm/{id}/payments, unpaid only
+        let mut unpaid = vec![];
+        for s in &statuses {
+            for p in db.list_vm_subscription_payments(s.id).await? {
+                if !p.is_paid {
+                    unpaid.push(hex::encode(&p.id));
+                }
+            }
+        }
+        let mut summary_unpaid: Vec<String> = summary
+            .unpaid_invoices
+            .iter()
+            .map(|p| p.id.clone())
+            .collect();
+        unpaid.sort();
+        summary_unpaid.sort();
+        assert_eq!(summary_unpaid, unpaid);
+        assert_eq!(summary_unpaid.len(), 2);
+
+        assert_eq!(
+            summary.monthly_spend,
+            vec![
+                ApiAccountAmount {
+                    currency: "BTC".to_string(),
+                    amount: 1000
+                },
+                ApiAccountAmount {
+                    currency: "EUR".to_string(),
+                    amount: 100
+                },
+            ]
+        );
+        assert_eq!(
+            summary.credit,
+            vec![ApiAccountAmount {
+                currency: "BTC".to_string(),
+                amount: 500
+            }]
+        );
+        Ok(())
+    }
+
     #[test]
     fn test_payment_methods_empty_configs() {
         let result = build_payment_methods_response(vec![]);
@@ -2577,6 +3747,27 @@ mod tests {
         assert!(!is_vm_expired(Some(now + chrono::Duration::hours(1)), now));
     }
 
+    #[test]
+    fn test_client_request_id() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(client_request_id(&headers, None).unwrap(), None);
+
+        headers.insert("x-client-request-id", "from-header".parse().unwrap());
+        assert_eq!(
+            client_request_id(&headers, None).unwrap().as_deref(),
+            Some("from-header")
+        );
+        // body field takes precedence
+        assert_eq!(
+            client_request_id(&headers, Some("from-body"))
+                .unwrap()
+                .as_deref(),
+            Some("from-body")
+        );
+        assert!(client_request_id(&headers, Some(" ")).is_err());
+        assert!(client_request_id(&headers, Some(&"a".repeat(65))).is_err());
+    }
+
     #[test]
     fn test_expired_vm_reinstall_error_is_payment_required() {
         let err = ApiError::payment_required("Cannot re-install an expired VM");
@@ -2657,6 +3848,258 @@ mod tests {
         assert!(!image_matches_arch(CpuArch::X86_64, Some(CpuArch::ARM64)));
     }
 
+    #[tokio::test]
+    async fn test_vm_power_action_routing() -> Result<()> {
+        use crate::host::dummy_host::DummyVmHost;
+        use crate::host::tests::mock_full_vm;
+        use lnvps_api_common::MockDb;
+        use lnvps_db::LNVpsDbBase;
+
+        let cfg = mock_full_vm();
+        let vm = cfg.vm.clone();
+        let host = DummyVmHost::new();
+        host.create_vm(&cfg, RetryPolicy::default()).await?;
+        let db: Arc<dyn LNVpsDb> = Arc::new(MockDb::default());
+        let history = VmHistoryLogger::new(db.clone());
+        let state = async || anyhow::Ok(host.get_vm_state(&vm).await?.state);
+
+        // reboots only run on the worker
+        assert!(
+            run_vm_power_action(&host, &history, &vm, 1, VmPowerAction::Reboot)
+                .await
+                .is_err()
+        );
+        run_vm_power_action(&host, &history, &vm, 1, VmPowerAction::Restart).await?;
+        assert_eq!(state().await?, VmRunningStates::Running);
+        run_vm_power_action(&host, &history, &vm, 1, VmPowerAction::Stop).await?;
+        assert_eq!(state().await?, VmRunningStates::Stopped);
+        run_vm_power_action(&host, &history, &vm, 1, VmPowerAction::Start).await?;
+        assert_eq!(state().await?, VmRunningStates::Running);
+
+        // each successful action is logged under its own type, the refused
+        // reboot is not
+        let mut actions: Vec<String> = db
+            .list_vm_history(vm.id)
+            .await?
+            .into_iter()
+            .map(|h| h.action_type.to_string())
+            .collect();
+        actions.sort();
+        assert_eq!(actions, vec!["restarted", "started", "stopped"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_vm_suspend_resume() -> Result<()> {
+        use crate::host::dummy_host::DummyVmHost;
+        use crate::host::tests::mock_full_vm;
+        use lnvps_api_common::MockDb;
+        use lnvps_db::LNVpsDbBase;
+        const MAX: TimeDelta = TimeDelta::days(30);
+
+        let mut cfg = mock_full_vm();
+        cfg.vm.subscription_line_item_id = 1;
+        let vm = cfg.vm.clone();
+        let host = DummyVmHost::new();
+        host.create_vm(&cfg, RetryPolicy::default()).await?;
+        host.start_vm(&vm).await?;
+        let mock = MockDb::default();
+        let expires = Utc::now() + TimeDelta::days(10);
+        mock.subscriptions.lock().await.get_mut(&1).unwrap().expires = Some(expires);
+        let db: Arc<dyn LNVpsDb> = Arc::new(mock.clone());
+        let history = VmHistoryLogger::new(db.clone());
+        let state = async || anyhow::Ok(host.get_vm_state(&vm).await?.state);
+
+        // only a suspended VM can be resumed
+        assert!(
+            run_vm_power_action(&host, &history, &vm, 1, VmPowerAction::Resume)
+                .await
+                .is_err()
+        );
+        run_vm_power_action(&host, &history, &vm, 1, VmPowerAction::Suspend).await?;
+        assert_eq!(state().await?, VmRunningStates::Suspended);
+        assert_eq!(
+            VmStateKind::from(&VmRunningStates::Suspended),
+            VmStateKind::Suspended
+        );
+        assert!(
+            run_vm_power_action(&host, &history, &vm, 1, VmPowerAction::Suspend)
+                .await
+                .is_err()
+        );
+
+        mock.vms.lock().await.insert(vm.id, vm.clone());
+        track_suspension(
+            &db,
+            &vm,
+            VmPowerAction::Suspend,
+            SuspendedBilling::Paused,
+            MAX,
+        )
+        .await?;
+        assert!(db.get_vm(vm.id).await?.suspended_at.is_some());
+
+        // the stored time is what counts, not the history: suspended two hours
+        // ago, with unrelated history in between
+        mock.vms.lock().await.get_mut(&vm.id).unwrap().suspended_at =
+            Some(Utc::now() - TimeDelta::hours(2));
+        let suspended = db.get_vm(vm.id).await?;
+        history.log_vm_rebooted(vm.id, None, None).await?;
+        run_vm_power_action(&host, &history, &vm, 1, VmPowerAction::Resume).await?;
+        assert_eq!(state().await?, VmRunningStates::Running);
+
+        let credited = track_suspension(
+            &db,
+            &suspended,
+            VmPowerAction::Resume,
+            SuspendedBilling::Paused,
+            MAX,
+        )
+        .await?
+        .unwrap();
+        assert!((credited - TimeDelta::hours(2)).num_seconds().abs() < 5);
+        let sub = db.get_subscription_by_line_item_id(1).await?;
+        assert_eq!(sub.expires, Some(expires + credited));
+        assert!(db.get_vm(vm.id).await?.suspended_at.is_none());
+
+        // the same suspension is not credited twice, even from a stale copy
+        run_vm_power_action(&host, &history, &vm, 1, VmPowerAction::Stop).await?;
+        assert!(
+            track_suspension(
+                &db,
+                &suspended,
+                VmPowerAction::Stop,
+                SuspendedBilling::Paused,
+                MAX
+            )
+            .await?
+            .is_none()
+        );
+
+        // with charged billing the suspension ends without credit
+        track_suspension(
+            &db,
+            &vm,
+            VmPowerAction::Suspend,
+            SuspendedBilling::Charged,
+            MAX,
+        )
+        .await?;
+        let suspended = db.get_vm(vm.id).await?;
+        assert!(
+            track_suspension(
+                &db,
+                &suspended,
+                VmPowerAction::Start,
+                SuspendedBilling::Charged,
+                MAX
+            )
+            .await?
+            .is_none()
+        );
+        assert!(db.get_vm(vm.id).await?.suspended_at.is_none());
+        assert_eq!(
+            db.get_subscription_by_line_item_id(1).await?.expires,
+            Some(expires + credited)
+        );
+
+        // a suspension longer than the cap is only credited up to the cap
+        track_suspension(
+            &db,
+            &vm,
+            VmPowerAction::Suspend,
+            SuspendedBilling::Paused,
+            MAX,
+        )
+        .await?;
+        mock.vms.lock().await.get_mut(&vm.id).unwrap().suspended_at =
+            Some(Utc::now() - TimeDelta::days(45));
+        let suspended = db.get_vm(vm.id).await?;
+        let capped = track_suspension(
+            &db,
+            &suspended,
+            VmPowerAction::Resume,
+            SuspendedBilling::Paused,
+            MAX,
+        )
+        .await?;
+        assert_eq!(capped, Some(MAX));
+        assert_eq!(
+            db.get_subscription_by_line_item_id(1).await?.expires,
+            Some(expires + credited + MAX)
+        );
+
+        let mut actions: Vec<String> = db
+            .list_vm_history(vm.id)
+            .await?
+            .into_iter()
+            .map(|h| h.action_type.to_string())
+            .collect();
+        actions.sort();
+        assert_eq!(actions, vec!["rebooted", "resumed", "stopped", "suspended"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_fetch_guest_info() -> Result<()> {
+        use crate::host::dummy_host::DummyVmHost;
+        use crate::host::tests::mock_full_vm;
+
+        let cfg = mock_full_vm();
+        let host = DummyVmHost::new();
+        host.create_vm(&cfg, RetryPolicy::default()).await?;
+
+        // agent is not running while the VM is stopped
+        assert!(fetch_guest_info(&host, &cfg.vm).await.is_none());
+
+        host.start_vm(&cfg.vm).await?;
+        let info = fetch_guest_info(&host, &cfg.vm)
+            .await
+            .expect("guest info when running");
+        let eth0 = info
+            .interfaces
+            .iter()
+            .find(|i| i.name == "eth0")
+            .expect("eth0");
+        assert_eq!(eth0.mac_address.as_ref(), Some(&cfg.vm.mac_address));
+        assert!(!eth0.addresses.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_list_vm_images_region_allowlist() -> Result<()> {
+        use lnvps_api_common::MockDb;
+        use lnvps_db::LNVpsDbBase;
+
+        let mock = MockDb::default();
+        {
+            let mut images = mock.os_images.lock().await;
+            let mut other = images.get(&1).unwrap().clone();
+            other.id = 2;
+            other.version = "13".to_string();
+            images.insert(2, other);
+        }
+        mock.set_region_os_images(1, &[2]).await?;
+        let db: Arc<dyn LNVpsDb> = Arc::new(mock);
+
+        let ids = |v: Vec<ApiVmOsImage>| {
+            let mut ids: Vec<u64> = v.into_iter().map(|i| i.id).collect();
+            ids.sort();
+            ids
+        };
+        // no region, every image
+        assert_eq!(ids(list_vm_images(&db, None, None).await?), vec![1, 2]);
+        // region with an allowlist only shows the allowed images
+        assert_eq!(ids(list_vm_images(&db, None, Some(1)).await?), vec![2]);
+        // region without an allowlist offers everything
+        assert_eq!(ids(list_vm_images(&db, None, Some(2)).await?), vec![1, 2]);
+
+        // clearing the allowlist lifts the restriction
+        db.set_region_os_images(1, &[]).await?;
+        assert_eq!(ids(list_vm_images(&db, None, Some(1)).await?), vec![1, 2]);
+        Ok(())
+    }
+
     #[test]
     fn lnurlp_callback_response_omits_hodl_invoice() {
         // Regression for #197: the LNURL-pay callback must not emit a
@@ -2668,4 +4111,211 @@ mod tests {
         // LUD-06 routes field present and empty
         assert_eq!(json["routes"], serde_json::json!([]));
     }
+
+    #[tokio::test]
+    async fn firewall_rule_requires_vm_owner() {
+        use lnvps_db::{
+            LNVpsDbBase, VmFirewallDirection, VmFirewallProtocol, VmFirewallRuleAction,
+        };
+        let mock = lnvps_api_common::MockDb::default();
+        mock.vms
+            .lock()
+            .await
+            .insert(1, lnvps_api_common::MockDb::mock_vm());
+        let mut other = lnvps_api_common::MockDb::mock_vm();
+        other.id = 2;
+        mock.vms.lock().await.insert(2, other);
+        let owner = mock.upsert_user(&[1; 32]).await.unwrap();
+        let stranger = mock.upsert_user(&[2; 32]).await.unwrap();
+        assert_eq!(owner, 1);
+
+        let mk = |vm_id| lnvps_db::VmFirewallRule {
+            id: 0,
+            vm_id,
+            priority: 0,
+            direction: VmFirewallDirection::Inbound,
+            protocol: VmFirewallProtocol::Tcp,
+            action: VmFirewallRuleAction::Accept,
+            src_cidr: None,
+            dst_port_start: Some(22),
+            dst_port_end: None,
+            enabled: true,
+            created: Utc::now(),
+            updated: Utc::now(),
+        };
+        let rule_id = mock.insert_vm_firewall_rule(&mk(1)).await.unwrap();
+        let other_rule_id = mock.insert_vm_firewall_rule(&mk(2)).await.unwrap();
+
+        let (vm, rule) = get_owned_firewall_rule(&mock, owner, 1, rule_id)
+            .await
+            .unwrap();
+        assert_eq!(vm.id, 1);
+        assert_eq!(rule.id, rule_id);
+
+        // another user can't reach the rule through the VM
+        let err = get_owned_firewall_rule(&mock, stranger, 1, rule_id)
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, axum::http::StatusCode::FORBIDDEN);
+
+        // nor can the owner address another VM's rule through their own VM
+        let err = get_owned_firewall_rule(&mock, owner, 1, other_rule_id)
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, axum::http::StatusCode::NOT_FOUND);
+    }
+
+    /// Listings read the stored `vm_state` when the cache has nothing, and keep a
+    /// stale state (with its old timestamp) when the host can't be asked.
+    #[tokio::test]
+    async fn test_resolve_vm_states_reads_stored_state() -> Result<()> {
+        use lnvps_api_common::MockDb;
+        use lnvps_db::{LNVpsDbBase, VmStateKind};
+
+        let mock = Arc::new(MockDb::default());
+        let mut vm2 = MockDb::mock_vm();
+        vm2.id = 2;
+        let mut vm3 = MockDb::mock_vm();
+        vm3.id = 3;
+        let vms = vec![MockDb::mock_vm(), vm2, vm3];
+        mock.upsert_vm_state(1, VmStateKind::Stopped).await?;
+        mock.upsert_vm_state(2, VmStateKind::Running).await?;
+        let stale_seen = Utc::now() - chrono::Duration::minutes(30);
+        mock.vm_states.lock().await.get_mut(&2).unwrap().last_seen = stale_seen;
+
+        let cache = VmStateCache::new();
+        let db: Arc<dyn LNVpsDb> = mock.clone();
+        let states = resolve_vm_states(&cache, &db, &vms, |_| None).await;
+
+        let s1 = states.get(&1).expect("stored state");
+        assert_eq!(s1.state, VmRunningStates::Stopped);
+        assert!(Utc::now().timestamp() as u64 - s1.timestamp < 60);
+
+        let s2 = states.get(&2).expect("stale stored state");
+        assert_eq!(s2.state, VmRunningStates::Running);
+        assert_eq!(s2.timestamp, stale_seen.timestamp() as u64);
+
+        // never seen and no host to ask
+        assert!(!states.contains_key(&3));
+
+        // a fresh cached state wins over the stored one
+        cache
+            .set_state(
+                1,
+                VmRunningState {
+                    timestamp: Utc::now().timestamp() as u64,
+                    state: VmRunningStates::Running,
+                    ..Default::default()
+                },
+            )
+            .await?;
+        let states = resolve_vm_states(&cache, &db, &vms[..1], |_| None).await;
+        assert_eq!(states[&1].state, VmRunningStates::Running);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_active_notices_window_and_region() -> anyhow::Result<()> {
+        use chrono::TimeDelta;
+        use lnvps_api_common::MockDb;
+        use lnvps_db::{NoticeSeverity, SystemNotice};
+
+        let db = MockDb::default();
+        let now = Utc::now();
+        let hour = TimeDelta::hours(1);
+        let notice = |id: u64, starts, ends, regions: Vec<u64>| SystemNotice {
+            id,
+            created: now,
+            message: format!("notice {}", id),
+            severity: NoticeSeverity::Info,
+            starts,
+            ends,
+            regions: regions.into(),
+        };
+        {
+            let mut n = db.system_notices.lock().await;
+            n.insert(1, notice(1, now - hour, Some(now + hour), vec![]));
+            n.insert(2, notice(2, now - hour, None, vec![2]));
+            // future and past notices are never returned
+            n.insert(3, notice(3, now + hour, Some(now + hour * 2), vec![]));
+            n.insert(4, notice(4, now - hour * 2, Some(now - hour), vec![2]));
+        }
+
+        let ids = |v: Vec<ApiSystemNotice>| {
+            let mut ids: Vec<u64> = v.iter().map(|n| n.id).collect();
+            ids.sort();
+            ids
+        };
+        assert_eq!(ids(active_notices(&db, None).await?), vec![1]);
+        assert_eq!(ids(active_notices(&db, Some(2)).await?), vec![1, 2]);
+        assert_eq!(ids(active_notices(&db, Some(1)).await?), vec![1]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_user_ip_assignments_across_vms() -> Result<()> {
+        use lnvps_api_common::MockDb;
+        use lnvps_db::{LNVpsDbBase, VmIpAssignment};
+
+        let db = MockDb::default();
+        let uid = db.upsert_user(&[1; 32]).await?;
+        let other = db.upsert_user(&[2; 32]).await?;
+        for (vm_id, user_id) in [(1, uid), (2, uid), (3, other)] {
+            db.vms.lock().await.insert(
+                vm_id,
+                Vm {
+                    id: vm_id,
+                    user_id,
+                    ..MockDb::mock_vm()
+                },
+            );
+        }
+        for (vm_id, range, ip) in [(1, 1, "10.0.0.2"), (2, 2, "fd00::2"), (3, 1, "10.0.0.3")] {
+            db.insert_vm_ip_assignment(&VmIpAssignment {
+                vm_id,
+                ip_range_id: range,
+                ip: ip.to_string(),
+                ..Default::default()
+            })
+            .await?;
+        }
+
+        let db: Arc<dyn LNVpsDb> = Arc::new(db);
+        let ips = user_ip_assignments(&db, uid).await?;
+        let ips: Vec<(u64, &str)> = ips.iter().map(|i| (i.vm_id, i.ip.ip.as_str())).collect();
+        assert_eq!(ips, vec![(1, "10.0.0.2/24"), (2, "fd00::2/64")]);
+        assert!(user_ip_assignments(&db, 99).await?.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_backups_gated_by_feature_flag() -> Result<()> {
+        use lnvps_api_common::{ApiErrorCode, MockDb};
+        use lnvps_db::FeatureFlag;
+
+        let mock = Arc::new(MockDb::default());
+        let db: Arc<dyn LNVpsDb> = mock.clone();
+        let features = FeatureFlags::new(db.clone());
+        let vm = MockDb::mock_vm();
+        assert!(ensure_backups_available(&db, &features, &vm).await.is_ok());
+
+        // switched off for the VM's region
+        mock.feature_flags.lock().await.insert(
+            1,
+            FeatureFlag {
+                id: 1,
+                key: FEATURE_BACKUPS.to_string(),
+                region_id: Some(1),
+                enabled: false,
+                ..Default::default()
+            },
+        );
+        features.invalidate().await;
+        let err = ensure_backups_available(&db, &features, &vm)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind, ApiErrorCode::FeatureUnavailable);
+        assert_eq!(err.error, "The backups feature is not available");
+        Ok(())
+    }
 }
@@ -1,24 +1,24 @@
 use anyhow::Error;
+use axum::Extension;
 use clap::{Parser, ValueEnum};
-use config::{Config, File};
 use lnvps_api::data_migration::run_data_migrations;
 use lnvps_api::dvm::start_dvms;
+use lnvps_api::nostr_domain::NostrDomainVerifier;
 use lnvps_api::payment_factory::PaymentMethodFactory;
 use lnvps_api::payments::listen_all_payments;
 use lnvps_api::settings::Settings;
 use lnvps_api::worker::Worker;
 use lnvps_api_common::{
-    ChannelWorkCommander, CountryResolver, MaxmindCountryResolver, RedisWorkCommander,
-    VmHistoryLogger, WorkCommander,
+    ChannelWorkCommander, CountryResolver, DbWorkCommander, FeatureFlags, MaxmindCountryResolver,
+    RedisWorkCommander, TrustedProxies, VmHistoryLogger, WORKER_GROUP, WorkCommander,
 };
-use lnvps_api_common::{VatClient, VmStateCache, WorkJob, make_exchange_service};
+use lnvps_api_common::{VatClient, VmStateCache, WorkJob, make_exchange_service, with_base_path};
 use std::fmt::{Display, Formatter};
 
-use lnvps_db::{EncryptionContext, LNVpsDb, LNVpsDbBase, LNVpsDbMysql};
+use lnvps_db::{EncryptionContext, LNVpsDb, LNVpsDbBase, LNVpsDbMysql, PaymentMethod};
 use log::{error, info, warn};
 use nostr_sdk::{Client, Keys};
 
-use axum::Router;
 use lnvps_api::api::*;
 use lnvps_api::subscription::SubscriptionHandler;
 use payments_rs::lightning::setup_crypto_provider;
@@ -27,27 +27,6 @@ use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::net::{TcpListener, TcpSocket};
-use tower_http::cors::{AllowHeaders, Any, CorsLayer};
-
-/// CORS layer for the public API.
-///
-/// Auth is carried in the `Authorization` header (NIP-98 / JWT), never cookies,
-/// so credentials are NOT needed. We therefore return `Access-Control-Allow-
-/// Origin: *` rather than reflecting the request origin. Reflecting the origin
-/// (as `very_permissive` does) breaks Tor/Brave, which send `Origin: null` on
-/// cross-site requests: a `null` allow-origin combined with allow-credentials
-/// is rejected by browsers. A wildcard origin with no credentials works for
-/// clearnet, `.onion`, and `null` origins alike.
-///
-/// Headers are MIRRORED rather than wildcarded because a literal `*` in
-/// `Access-Control-Allow-Headers` does not cover `Authorization`.
-fn cors_layer() -> CorsLayer {
-    CorsLayer::new()
-        .allow_origin(Any)
-        .allow_methods(Any)
-        .allow_headers(AllowHeaders::mirror_request())
-        .expose_headers(Any)
-}
 
 #[derive(Parser)]
 #[clap(about, version, author)]
@@ -92,18 +71,12 @@ async fn main() -> Result<(), Error> {
     let args = Args::parse();
     let mut tasks = Vec::new();
 
-    let settings: Settings = {
-        let mut builder = Config::builder();
-        if args.config.is_empty() {
-            builder = builder.add_source(File::from(PathBuf::from("config.yaml")));
-        } else {
-            // Explicit files are layered in order; later files override earlier.
-            for path in &args.config {
-                builder = builder.add_source(File::from(path.clone()));
-            }
-        }
-        builder.build()?.try_deserialize()?
+    let config_paths = if args.config.is_empty() {
+        vec![PathBuf::from("config.yaml")]
+    } else {
+        args.config.clone()
     };
+    let settings = Settings::load(&config_paths)?;
 
     // Email verification gates VM ordering, but it can only be delivered over
     // SMTP. When SMTP is unconfigured the verification requirement is skipped so
@@ -128,7 +101,10 @@ async fn main() -> Result<(), Error> {
     }
 
     // Connect database and migrate
-    let db = LNVpsDbMysql::new(&settings.db).await?;
+    let db = match &settings.db_replica {
+        Some(replica) => LNVpsDbMysql::new_with_replica(&settings.db, replica).await?,
+        None => LNVpsDbMysql::new(&settings.db).await?,
+    };
     db.migrate().await?;
     #[cfg(debug_assertions)]
     if std::env::var("LNVPS_NO_DEV_SETUP").is_err() {
@@ -136,6 +112,9 @@ async fn main() -> Result<(), Error> {
         db.execute(setup_script).await?;
         info!("Executed dev_setup.sql");
     }
+    // the worker and payment handling read back what they just wrote, which a
+    // lagging replica may not have yet
+    let primary: Arc<dyn LNVpsDb> = Arc::new(db.primary());
     let db: Arc<dyn LNVpsDb> = Arc::new(db);
 
     // Enable session (Bearer JWT) auth, shared by OAuth and WebAuthn logins.
@@ -244,7 +223,16 @@ async fn main() -> Result<(), Error> {
     let vm_history = VmHistoryLogger::new(db.clone());
 
     let work_commander: Arc<dyn WorkCommander> = if let Some(redis_config) = &settings.redis {
-        Arc::new(RedisWorkCommander::new(&redis_config.url, "workers", "api-worker").await?)
+        Arc::new(RedisWorkCommander::new(&redis_config.url, WORKER_GROUP, "api-worker").await?)
+    } else if settings.db_work_queue {
+        let mut work = DbWorkCommander::new(primary.clone(), "api-worker");
+        if let Some(secs) = settings.db_work_queue_lease_secs {
+            work = work.with_lease(Duration::from_secs(secs));
+        }
+        if let Some(max) = settings.db_work_queue_max_attempts {
+            work = work.with_max_attempts(max);
+        }
+        Arc::new(work)
     } else {
         Arc::new(ChannelWorkCommander::new())
     };
@@ -278,7 +266,7 @@ async fn main() -> Result<(), Error> {
 
     let sub_handler = SubscriptionHandler::new(
         settings.clone(),
-        db.clone(),
+        primary.clone(),
         node.clone(),
         onchain.clone(),
         revolut.clone(),
@@ -298,7 +286,7 @@ async fn main() -> Result<(), Error> {
     let worker_onchain: Option<Arc<dyn payments_rs::onchain::OnChainProvider>> = None;
 
     let worker = Worker::new(
-        db.clone(),
+        primary.clone(),
         work_commander.clone(),
         sub_handler.clone(),
         node.clone(),
@@ -312,10 +300,25 @@ async fn main() -> Result<(), Error> {
 
     if mode.contains(&ExecMode::Worker) {
         // Data migrations touch hosts, ARP tables, DNS, etc. — worker concerns only.
-        run_data_migrations(db.clone(), sub_handler.vm_provisioner(), &settings).await?;
+        run_data_migrations(
+            primary.clone(),
+            sub_handler.vm_provisioner(),
+            &settings,
+            work_commander.clone(),
+        )
+        .await?;
 
         tasks.push(worker.spawn_job_interval(WorkJob::CheckVms, Duration::from_secs(30)));
         tasks.push(worker.spawn_job_interval(WorkJob::CheckSubscriptions, Duration::from_secs(30)));
+        tasks.push(
+            worker.spawn_job_interval(WorkJob::CancelExpiredPayments, Duration::from_secs(60)),
+        );
+        tasks.push(worker.spawn_job_interval(WorkJob::CheckUsageAlerts, Duration::from_secs(300)));
+        tasks.push(worker.spawn_job_interval(WorkJob::CheckBackups, Duration::from_secs(600)));
+        // the hourly time-series covers a bit more than an hour, count it twice an hour
+        tasks.push(
+            worker.spawn_job_interval(WorkJob::CheckBandwidthUsage, Duration::from_secs(1800)),
+        );
         // Refresh cached router tunnel/BGP session/route state + traffic every 60s
         tasks.push(worker.spawn_job_interval(WorkJob::SyncRouterState, Duration::from_secs(60)));
         // Automated referral payouts are opt-in (config-gated); run hourly.
@@ -325,6 +328,17 @@ async fn main() -> Result<(), Error> {
                     .spawn_job_interval(WorkJob::ProcessReferralPayouts, Duration::from_secs(3600)),
             );
         }
+        // Reclaiming never-paid VMs is opt-in (config-gated); run hourly.
+        if let Some(unpaid) = &settings.unpaid_vms {
+            tasks.push(worker.spawn_job_interval(
+                WorkJob::ReclaimUnpaidVms {
+                    grace_hours: unpaid.grace_hours,
+                    action: unpaid.action,
+                    admin_user_id: None,
+                },
+                Duration::from_secs(3600),
+            ));
+        }
         tasks.push(worker.spawn_handler_loop());
 
         // check all nostr domains every 10 minutes for CNAME entries (enable/disable as needed)
@@ -335,6 +349,35 @@ async fn main() -> Result<(), Error> {
             );
         }
 
+        // Re-read the config files on SIGHUP and apply what can change live
+        #[cfg(unix)]
+        {
+            let worker = worker.clone();
+            let paths = config_paths.clone();
+            let mut applied = settings.clone();
+            tasks.push(tokio::spawn(async move {
+                use tokio::signal::unix::{SignalKind, signal};
+                let mut hup = match signal(SignalKind::hangup()) {
+                    Ok(s) => s,
+                    Err(e) => {
+                        error!("Failed to listen for SIGHUP: {}", e);
+                        return;
+                    }
+                };
+                while hup.recv().await.is_some() {
+                    info!("SIGHUP received, reloading settings");
+                    match Settings::load(&paths) {
+                        Ok(new) => {
+                            if let Err(e) = worker.reload_settings(&mut applied, &new) {
+                                error!("Failed to reload settings: {}", e);
+                            }
+                        }
+                        Err(e) => error!("Failed to read settings: {}", e),
+                    }
+                }
+            }));
+        }
+
         // check vms now to get current state
         worker.send(WorkJob::CheckVms).await?;
     }
@@ -442,6 +485,17 @@ async fn main() -> Result<(), Error> {
         }));
     }
 
+    if let Some(metrics_listen) = &settings.metrics_listen {
+        let ip: SocketAddr = metrics_listen.parse()?;
+        let listener = bind_address(ip).await?;
+        info!("Metrics listening on {}", ip);
+        tasks.push(tokio::spawn(async move {
+            if let Err(e) = axum::serve(listener, lnvps_api::metrics::router()).await {
+                error!("Error while running metrics server: {}", e);
+            }
+        }));
+    }
+
     if mode.contains(&ExecMode::Api) {
         let ip: SocketAddr = match &settings.listen {
             Some(i) => i.parse()?,
@@ -453,60 +507,47 @@ async fn main() -> Result<(), Error> {
         // service is configured. Without Redis the feedback is a blackhole, so the
         // reinstall endpoint falls back to running its pipeline inline.
         let api_feedback = settings.redis.as_ref().map(|_| worker.feedback());
-        let mut router = Router::new()
-            .merge(docs_router())
-            .merge(main_router())
-            .merge(contacts_router())
-            .merge(webhook_router())
-            .merge(subscriptions_router())
-            .merge(ip_space_router())
-            .merge(referral_router())
-            .merge(apps_router())
-            .merge(legal_router())
-            .merge(oauth_router())
-            .merge(webauthn_router());
-
-        #[cfg(feature = "openapi")]
-        {
-            mod openapi {
-                include!(concat!(env!("OUT_DIR"), "/openapi.rs"));
-            }
-
-            router = router
-                .route(
-                    "/openapi.json",
-                    axum::routing::get(async || {
-                        (
-                            [(axum::http::header::CONTENT_TYPE, "application/json")],
-                            openapi::OPENAPI_JSON,
-                        )
-                    }),
-                )
-                .route(
-                    "/swagger",
-                    axum::routing::get(async move || {
-                        axum::response::Html(include_str!("../api/swagger.html"))
-                    }),
-                );
-        }
-        #[cfg(feature = "nostr-domain")]
+        let features = FeatureFlags::new(db.clone());
+        let mut readiness = Readiness::default()
+            .with_check(DbCheck(db.clone()))
+            .with_check(WorkQueueCheck(worker.commander()));
+        if let Ok(cfg) = db
+            .get_payment_method_config_for_company(default_company_id, PaymentMethod::Lightning)
+            .await
+            && let Some(check) = LightningCheck::from_config(&cfg)
         {
-            router = router.merge(nostr_domain_router());
+            readiness = readiness.with_check(check);
         }
+        let router = api_routes()
+            .merge(batch_router())
+            .route_layer(axum::middleware::from_fn(lnvps_api::metrics::track_metrics));
+        let router = with_base_path(router, settings.base_path.as_deref());
+        let router = match &settings.trusted_proxies {
+            Some(p) => router.layer(Extension(TrustedProxies::parse(p)?)),
+            None => router,
+        };
+        let cors = settings.cors.layer()?;
+        let domain_verifier = NostrDomainVerifier::new(settings.nostr_address_host.clone())?;
         tasks.push(tokio::spawn(async move {
             if let Err(e) = axum::serve(
                 listener,
-                router.layer(cors_layer()).with_state(RouterState {
-                    db,
-                    state: status,
-                    sub_handler,
-                    history: vm_history,
-                    settings,
-                    rates: exchange,
-                    work_sender: worker.commander(),
-                    feedback: api_feedback,
-                    geoip: geoip.clone(),
-                }),
+                router
+                    .layer(cors)
+                    .with_state(RouterState {
+                        db,
+                        state: status,
+                        sub_handler,
+                        history: vm_history,
+                        settings,
+                        rates: exchange,
+                        work_sender: worker.commander(),
+                        feedback: api_feedback,
+                        geoip: geoip.clone(),
+                        readiness,
+                        features,
+                        domain_verifier,
+                    })
+                    .into_make_service_with_connect_info::<SocketAddr>(),
             )
             .await
             {
@@ -1,7 +1,7 @@
 use crate::data_migration::DataMigration;
 use crate::settings::Settings;
 use anyhow::Result;
-use lnvps_api_common::{BasicRecord, DnsRef, get_dns_server};
+use lnvps_api_common::{BasicRecord, DnsRef, DnsServerRegistry};
 use lnvps_db::{DnsServer, DnsServerKind, LNVpsDb, RouterKind};
 use log::warn;
 use std::future::Future;
@@ -182,6 +182,8 @@ impl DnsDataMigration {
     /// Best-effort backfill/refresh of forward/reverse records for existing IPs.
     /// Failures are logged and skipped so a DNS/permission problem never aborts startup.
     async fn backfill_records(db: &Arc<dyn LNVpsDb>) -> Result<()> {
+        // one client per dns server for the whole run, OVH syncs its clock on connect
+        let servers = DnsServerRegistry::new(db.clone());
         let vms = db.list_vms().await?;
         for vm in vms {
             let mut ips = db.list_vm_ip_assignments(vm.id).await?;
@@ -192,9 +194,16 @@ impl DnsDataMigration {
                 if ip.dns_forward.is_none()
                     && let Some(fwd_id) = range.forward_dns_server_id
                 {
-                    let rec =
-                        BasicRecord::forward(ip, DnsRef::from_opt(range.forward_zone_id.clone()))?;
-                    match get_dns_server(db, fwd_id).await {
+                    let suffix = db
+                        .get_host_region(range.region_id)
+                        .await?
+                        .forward_dns_suffix;
+                    let rec = BasicRecord::forward(
+                        ip,
+                        DnsRef::from_opt(range.forward_zone_id.clone()),
+                        suffix.as_deref(),
+                    )?;
+                    match servers.get(fwd_id).await {
                         Ok(dns) => match dns.add_record(&rec).await {
                             Ok(r) => {
                                 ip.dns_forward = Some(r.name.clone());
@@ -224,7 +233,7 @@ impl DnsDataMigration {
                             ip,
                             DnsRef::from_opt(range.reverse_zone_id.clone()),
                         )?;
-                        match get_dns_server(db, rev_id).await {
+                        match servers.get(rev_id).await {
                             Ok(dns) => match dns.add_record(&rec).await {
                                 Ok(r) => {
                                     ip.dns_reverse = Some(r.value.clone());
@@ -0,0 +1,168 @@
+use crate::data_migration::DataMigration;
+use anyhow::Result;
+use lnvps_api_common::{WorkCommander, WorkJob};
+use lnvps_db::LNVpsDb;
+use log::warn;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+
+/// Detects IPs assigned to more than one live VM.
+///
+/// `insert_vm_ip_assignment` historically had no uniqueness guard, so the same
+/// IP may have been handed out to two non-deleted VMs. Picking a "winner" needs
+/// a human (the wrong choice takes a customer offline), so this migration does
+/// NOT modify any assignment: it logs every conflict and sends a single admin
+/// notification listing them. The reported conflict set is recorded in
+/// `data_migration_state`, so the notification is only re-sent on a later boot
+/// when the set of conflicts changes.
+pub struct DuplicateIpAssignmentsMigration {
+    db: Arc<dyn LNVpsDb>,
+    tx: Arc<dyn WorkCommander>,
+}
+
+/// Key under which the last reported conflict set is recorded
+const STATE_KEY: &str = "duplicate_ip_assignments";
+
+impl DuplicateIpAssignmentsMigration {
+    pub fn new(db: Arc<dyn LNVpsDb>, tx: Arc<dyn WorkCommander>) -> Self {
+        Self { db, tx }
+    }
+
+    /// Build the admin notification body for a set of conflicts.
+    fn format_conflicts(conflicts: &[(String, Vec<u64>)]) -> String {
+        let mut msg = format!(
+            "Found {} IP address(es) assigned to more than one live VM. \
+             No changes were made, please resolve manually:\n",
+            conflicts.len()
+        );
+        for (ip, vm_ids) in conflicts {
+            let vms: Vec<String> = vm_ids.iter().map(|id| id.to_string()).collect();
+            msg.push_str(&format!("- {}: VMs {}\n", ip, vms.join(", ")));
+        }
+        msg
+    }
+}
+
+impl DataMigration for DuplicateIpAssignmentsMigration {
+    fn name(&self) -> &'static str {
+        "duplicate IP assignment detection"
+    }
+
+    fn migrate(&self) -> Pin<Box<dyn Future<Output = Result<String>> + Send>> {
+        let db = self.db.clone();
+        let tx = self.tx.clone();
+        Box::pin(async move {
+            let conflicts = db.find_duplicate_live_ips().await?;
+            let fingerprint = Self::format_conflicts(&conflicts);
+            let last = db.get_data_migration_state(STATE_KEY).await?;
+            if conflicts.is_empty() {
+                if last.is_some_and(|l| !l.is_empty()) {
+                    db.set_data_migration_state(STATE_KEY, "").await?;
+                }
+                return Ok("no duplicate IP assignments".to_string());
+            }
+            for (ip, vm_ids) in &conflicts {
+                warn!("IP {} is assigned to multiple live VMs: {:?}", ip, vm_ids);
+            }
+            if last.as_deref() == Some(fingerprint.as_str()) {
+                return Ok(format!(
+                    "{} duplicate IP assignment(s) already reported",
+                    conflicts.len()
+                ));
+            }
+            tx.send(WorkJob::SendAdminNotification {
+                message: fingerprint.clone(),
+                title: Some("Duplicate IP assignments detected".to_string()),
+            })
+            .await?;
+            db.set_data_migration_state(STATE_KEY, &fingerprint).await?;
+            Ok(format!(
+                "flagged {} duplicate IP assignment(s)",
+                conflicts.len()
+            ))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lnvps_api_common::{ChannelWorkCommander, MockDb};
+    use lnvps_db::{LNVpsDbBase, Vm, VmIpAssignment};
+
+    #[tokio::test]
+    async fn test_reports_duplicate_live_ips() -> Result<()> {
+        let db = MockDb::default();
+        {
+            let mut vms = db.vms.lock().await;
+            for id in 1..=3 {
+                vms.insert(
+                    id,
+                    Vm {
+                        id,
+                        ..MockDb::mock_vm()
+                    },
+                );
+            }
+            // VM 3 is deleted; its stale assignment must not count as a conflict
+            vms.get_mut(&3).unwrap().deleted = true;
+        }
+        for (vm_id, ip) in [
+            (1, "10.0.0.5"),
+            (2, "10.0.0.5"),
+            (3, "10.0.0.6"),
+            (1, "10.0.0.6"),
+        ] {
+            db.insert_vm_ip_assignment(&VmIpAssignment {
+                vm_id,
+                ip_range_id: 1,
+                ip: ip.to_string(),
+                ..Default::default()
+            })
+            .await?;
+        }
+
+        let dupes = db.find_duplicate_live_ips().await?;
+        assert_eq!(dupes, vec![("10.0.0.5".to_string(), vec![1, 2])]);
+
+        let db: Arc<dyn LNVpsDb> = Arc::new(db);
+        let tx = Arc::new(ChannelWorkCommander::new());
+        let migration = DuplicateIpAssignmentsMigration::new(db.clone(), tx.clone());
+        let summary = migration.migrate().await?;
+        assert!(summary.contains("flagged 1"));
+
+        let jobs = tx.recv().await?;
+        assert_eq!(jobs.len(), 1);
+        match &jobs[0].job {
+            WorkJob::SendAdminNotification { message, title } => {
+                assert!(title.as_deref().unwrap_or("").contains("Duplicate IP"));
+                assert!(message.contains("10.0.0.5: VMs 1, 2"));
+                assert!(!message.contains("10.0.0.6"));
+            }
+            other => panic!("expected SendAdminNotification, got {:?}", other),
+        }
+
+        // Nothing was auto-resolved
+        assert_eq!(db.list_vm_ip_assignments(1).await?.len(), 2);
+        assert_eq!(db.list_vm_ip_assignments(2).await?.len(), 1);
+
+        // A second boot with the same conflicts does not notify again
+        let summary = migration.migrate().await?;
+        assert!(summary.contains("already reported"));
+        let no_job = tokio::time::timeout(std::time::Duration::from_millis(50), tx.recv()).await;
+        assert!(no_job.is_err(), "no repeat notification expected");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_no_duplicates_is_noop() -> Result<()> {
+        let db: Arc<dyn LNVpsDb> = Arc::new(MockDb::default());
+        let tx = Arc::new(ChannelWorkCommander::new());
+        let migration = DuplicateIpAssignmentsMigration::new(db, tx.clone());
+        assert_eq!(migration.migrate().await?, "no duplicate IP assignments");
+        let no_job = tokio::time::timeout(std::time::Duration::from_millis(50), tx.recv()).await;
+        assert!(no_job.is_err(), "no notification expected");
+        Ok(())
+    }
+}
@@ -1,5 +1,6 @@
 use crate::data_migration::arp_ref_fixer::ArpRefFixerDataMigration;
 use crate::data_migration::dns::DnsDataMigration;
+use crate::data_migration::duplicate_ip_assignments::DuplicateIpAssignmentsMigration;
 use crate::data_migration::email_hash_backfill::EmailHashBackfillMigration;
 use crate::data_migration::encryption_migration::EncryptionDataMigration;
 use crate::data_migration::ip6_init::Ip6InitDataMigration;
@@ -9,6 +10,7 @@ use crate::data_migration::ssh_key_migration::SshKeyMigration;
 use crate::provisioner::VmProvisioner;
 use crate::settings::Settings;
 use anyhow::Result;
+use lnvps_api_common::WorkCommander;
 use lnvps_db::LNVpsDb;
 use log::{error, info};
 use std::future::Future;
@@ -17,6 +19,7 @@ use std::sync::Arc;
 
 mod arp_ref_fixer;
 mod dns;
+mod duplicate_ip_assignments;
 mod email_hash_backfill;
 mod encryption_migration;
 mod ip6_init;
@@ -38,6 +41,7 @@ pub async fn run_data_migrations(
     db: Arc<dyn LNVpsDb>,
     lnvps: VmProvisioner,
     settings: &Settings,
+    tx: Arc<dyn WorkCommander>,
 ) -> Result<()> {
     let mut migrations: Vec<Box<dyn DataMigration>> = vec![];
 
@@ -55,6 +59,12 @@ pub async fn run_data_migrations(
 
     migrations.push(Box::new(ArpRefFixerDataMigration::new(db.clone())));
 
+    // Report IPs assigned to more than one live VM (flag only, never auto-resolved)
+    migrations.push(Box::new(DuplicateIpAssignmentsMigration::new(
+        db.clone(),
+        tx.clone(),
+    )));
+
     // Migrate SSH key from proxmox config to database
     migrations.push(Box::new(SshKeyMigration::new(db.clone(), settings.clone())));
 
@@ -4,7 +4,8 @@ use crate::subscription::SubscriptionHandler;
 use anyhow::Context;
 use lnvps_api_common::VmStateCache;
 use lnvps_db::{
-    DiskInterface, DiskType, OsDistribution, PaymentMethod, UserSshKey, VmCustomTemplate,
+    DiskInterface, DiskType, OsDistribution, OsImageKind, PaymentMethod, UserSshKey,
+    VmCustomTemplate,
 };
 use nostr_sdk::prelude::DataVendingMachineStatus;
 use nostr_sdk::{Client, Tag};
@@ -125,7 +126,7 @@ impl DVMHandler for LnvpsDvm {
 
             let image = OsDistribution::from_str(os_image)?;
             let image = db
-                .list_os_image()
+                .list_os_image_by_kind(OsImageKind::CloudInit)
                 .await?
                 .into_iter()
                 .find(|i| i.distribution == image && i.version == *os_version)
@@ -133,7 +134,7 @@ impl DVMHandler for LnvpsDvm {
 
             let vm = sub_handler
                 .vm_provisioner()
-                .provision_custom(uid, template, image.id, ssh_key_id, None)
+                .provision_custom(uid, template, image.id, ssh_key_id, None, false)
                 .await?;
             let line_item = db
                 .get_subscription_line_item(vm.subscription_line_item_id)
@@ -0,0 +1,473 @@
+use crate::host::{
+    DataDisk, FullVmInfo, HostBackup, PasswordResetMethod, TerminalStream, TimeSeries,
+    TimeSeriesData, VmHostClient, VmHostInfo,
+};
+use anyhow::anyhow;
+use async_trait::async_trait;
+use lnvps_api_common::retry::{OpError, OpResult, RetryPolicy};
+use lnvps_api_common::{GuestInfo, HostVmSpec, VmRunningState};
+use lnvps_db::{Vm, VmHost, VmHostDisk, VmOsImage};
+use log::{info, warn};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+/// Consecutive transient failures which open the breaker
+const FAILURE_THRESHOLD: u32 = 5;
+/// Failures must happen within this window to count as consecutive
+const FAILURE_WINDOW: Duration = Duration::from_secs(60);
+/// How long calls fail fast before a probe call is allowed
+const OPEN_COOLDOWN: Duration = Duration::from_secs(30);
+
+/// One breaker per host id, shared by every client created for that host
+static HOST_BREAKERS: LazyLock<Mutex<HashMap<u64, Arc<CircuitBreaker>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Get the breaker for a host
+pub fn host_breaker(host_id: u64) -> Arc<CircuitBreaker> {
+    let mut breakers = HOST_BREAKERS.lock().unwrap();
+    breakers
+        .entry(host_id)
+        .or_insert_with(|| Arc::new(CircuitBreaker::default()))
+        .clone()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BreakerState {
+    /// Calls pass through, transient failures are counted
+    Closed,
+    /// Calls fail fast until the cooldown has passed
+    Open,
+    /// Cooldown passed, a single probe call is let through
+    HalfOpen,
+}
+
+struct BreakerInner {
+    state: BreakerState,
+    failures: u32,
+    first_failure: Option<Instant>,
+    opened: Option<Instant>,
+    probe_started: Option<Instant>,
+}
+
+/// Circuit breaker counting transient host failures.
+///
+/// Fatal errors mean the host answered (e.g. a VM doesn't exist) so they count
+/// as a success, only [OpError::Transient] failures (timeouts, connection
+/// errors, 5xx) trip the breaker.
+pub struct CircuitBreaker {
+    threshold: u32,
+    window: Duration,
+    cooldown: Duration,
+    inner: Mutex<BreakerInner>,
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        Self::new(FAILURE_THRESHOLD, FAILURE_WINDOW, OPEN_COOLDOWN)
+    }
+}
+
+impl CircuitBreaker {
+    pub fn new(threshold: u32, window: Duration, cooldown: Duration) -> Self {
+        Self {
+            threshold,
+            window,
+            cooldown,
+            inner: Mutex::new(BreakerInner {
+                state: BreakerState::Closed,
+                failures: 0,
+                first_failure: None,
+                opened: None,
+                probe_started: None,
+            }),
+        }
+    }
+
+    pub fn state(&self) -> BreakerState {
+        self.inner.lock().unwrap().state
+    }
+
+    /// Check if a call may be made now
+    pub fn try_acquire(&self) -> bool {
+        self.try_acquire_at(Instant::now())
+    }
+
+    fn try_acquire_at(&self, now: Instant) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        match inner.state {
+            BreakerState::Closed => true,
+            BreakerState::Open => {
+                if inner
+                    .opened
+                    .is_some_and(|t| now.duration_since(t) >= self.cooldown)
+                {
+                    inner.state = BreakerState::HalfOpen;
+                    inner.probe_started = Some(now);
+                    true
+                } else {
+                    false
+                }
+            }
+            BreakerState::HalfOpen => {
+                // a probe which never reported back (e.g. cancelled by a timeout)
+                // doesn't block the host forever
+                if inner
+                    .probe_started
+                    .is_some_and(|t| now.duration_since(t) < self.cooldown)
+                {
+                    false
+                } else {
+                    inner.probe_started = Some(now);
+                    true
+                }
+            }
+        }
+    }
+
+    /// Record the outcome of a call made after [CircuitBreaker::try_acquire]
+    pub fn record<T>(&self, result: &OpResult<T>) {
+        self.record_at(Instant::now(), matches!(result, Err(OpError::Transient(_))))
+    }
+
+    fn record_at(&self, now: Instant, transient_failure: bool) {
+        let mut inner = self.inner.lock().unwrap();
+        if !transient_failure {
+            inner.state = BreakerState::Closed;
+            inner.failures = 0;
+            inner.first_failure = None;
+            inner.opened = None;
+            inner.probe_started = None;
+            return;
+        }
+
+        match inner.state {
+            BreakerState::HalfOpen | BreakerState::Open => {
+                inner.state = BreakerState::Open;
+                inner.opened = Some(now);
+                inner.probe_started = None;
+            }
+            BreakerState::Closed => {
+                if inner
+                    .first_failure
+                    .is_none_or(|t| now.duration_since(t) > self.window)
+                {
+                    inner.failures = 0;
+                    inner.first_failure = Some(now);
+                }
+                inner.failures += 1;
+                if inner.failures >= self.threshold {
+                    inner.state = BreakerState::Open;
+                    inner.opened = Some(now);
+                }
+            }
+        }
+    }
+}
+
+/// [VmHostClient] wrapper which fails fast while the host's breaker is open
+pub struct BreakerHostClient {
+    host: String,
+    breaker: Arc<CircuitBreaker>,
+    inner: Arc<dyn VmHostClient>,
+}
+
+impl BreakerHostClient {
+    pub fn new(host: &VmHost, inner: Arc<dyn VmHostClient>) -> Self {
+        Self {
+            host: host.name.clone(),
+            breaker: host_breaker(host.id),
+            inner,
+        }
+    }
+
+    async fn call<T>(&self, f: impl Future<Output = OpResult<T>>) -> OpResult<T> {
+        if !self.breaker.try_acquire() {
+            return Err(OpError::Fatal(anyhow!(
+                "Host {} is unavailable, try again later",
+                self.host
+            )));
+        }
+        let was = self.breaker.state();
+        let ret = f.await;
+        self.breaker.record(&ret);
+        match (was, self.breaker.state()) {
+            (BreakerState::Closed, BreakerState::Open) => {
+                warn!("Host {} is failing, pausing calls", self.host)
+            }
+            (BreakerState::HalfOpen, BreakerState::Closed) => {
+                info!("Host {} recovered", self.host)
+            }
+            _ => {}
+        }
+        ret
+    }
+}
+
+#[async_trait]
+impl VmHostClient for BreakerHostClient {
+    async fn get_info(&self) -> OpResult<VmHostInfo> {
+        self.call(self.inner.get_info()).await
+    }
+
+    async fn list_host_vms(&self) -> OpResult<Vec<HostVmSpec>> {
+        self.call(self.inner.list_host_vms()).await
+    }
+
+    async fn download_os_image(&self, image: &VmOsImage, retry: RetryPolicy) -> OpResult<()> {
+        self.call(self.inner.download_os_image(image, retry)).await
+    }
+
+    async fn generate_mac(&self, vm: &Vm) -> OpResult<String> {
+        self.call(self.inner.generate_mac(vm)).await
+    }
+
+    async fn start_vm(&self, vm: &Vm) -> OpResult<()> {
+        self.call(self.inner.start_vm(vm)).await
+    }
+
+    async fn stop_vm(&self, vm: &Vm) -> OpResult<()> {
+        self.call(self.inner.stop_vm(vm)).await
+    }
+
+    async fn reset_vm(&self, vm: &Vm) -> OpResult<()> {
+        self.call(self.inner.reset_vm(vm)).await
+    }
+
+    async fn reboot_vm(&self, vm: &Vm) -> OpResult<()> {
+        self.call(self.inner.reboot_vm(vm)).await
+    }
+
+    async fn suspend_vm(&self, vm: &Vm) -> OpResult<()> {
+        self.call(self.inner.suspend_vm(vm)).await
+    }
+
+    async fn resume_vm(&self, vm: &Vm) -> OpResult<()> {
+        self.call(self.inner.resume_vm(vm)).await
+    }
+
+    async fn create_vm(&self, cfg: &FullVmInfo, retry: RetryPolicy) -> OpResult<()> {
+        self.call(self.inner.create_vm(cfg, retry)).await
+    }
+
+    async fn delete_vm(&self, vm: &Vm) -> OpResult<()> {
+        self.call(self.inner.delete_vm(vm)).await
+    }
+
+    async fn unlink_primary_disk(&self, vm: &Vm) -> OpResult<()> {
+        self.call(self.inner.unlink_primary_disk(vm)).await
+    }
+
+    async fn delete_unused_disks(&self, vm: &Vm) -> OpResult<()> {
+        self.call(self.inner.delete_unused_disks(vm)).await
+    }
+
+    async fn import_template_disk(&self, cfg: &FullVmInfo) -> OpResult<()> {
+        self.call(self.inner.import_template_disk(cfg)).await
+    }
+
+    async fn is_container(&self, vm: &Vm) -> OpResult<bool> {
+        self.call(self.inner.is_container(vm)).await
+    }
+
+    async fn recreate_container(&self, cfg: &FullVmInfo) -> OpResult<()> {
+        self.call(self.inner.recreate_container(cfg)).await
+    }
+
+    async fn resize_disk(&self, cfg: &FullVmInfo) -> OpResult<()> {
+        self.call(self.inner.resize_disk(cfg)).await
+    }
+
+    async fn attach_data_disk(&self, vm: &Vm, disk: &DataDisk) -> OpResult<()> {
+        self.call(self.inner.attach_data_disk(vm, disk)).await
+    }
+
+    async fn detach_data_disk(&self, vm: &Vm, disk: &DataDisk) -> OpResult<()> {
+        self.call(self.inner.detach_data_disk(vm, disk)).await
+    }
+
+    async fn backup_vm(&self, vm: &Vm) -> OpResult<HostBackup> {
+        self.call(self.inner.backup_vm(vm)).await
+    }
+
+    async fn delete_backup(&self, volume_id: &str) -> OpResult<()> {
+        self.call(self.inner.delete_backup(volume_id)).await
+    }
+
+    async fn restore_backup(&self, cfg: &FullVmInfo, volume_id: &str) -> OpResult<()> {
+        self.call(self.inner.restore_backup(cfg, volume_id)).await
+    }
+
+    async fn start_migrate_vm(
+        &self,
+        vm: &Vm,
+        target: &VmHost,
+        target_disk: &VmHostDisk,
+    ) -> OpResult<String> {
+        self.call(self.inner.start_migrate_vm(vm, target, target_disk))
+            .await
+    }
+
+    async fn migration_finished(&self, task: &str) -> OpResult<bool> {
+        self.call(self.inner.migration_finished(task)).await
+    }
+
+    async fn hotplug_resize(&self, vm: &Vm, cpu: u16, memory: u64) -> OpResult<bool> {
+        self.call(self.inner.hotplug_resize(vm, cpu, memory)).await
+    }
+
+    async fn get_vm_state(&self, vm: &Vm) -> OpResult<VmRunningState> {
+        self.call(self.inner.get_vm_state(vm)).await
+    }
+
+    async fn get_all_vm_states(&self) -> OpResult<Vec<(u64, VmRunningState)>> {
+        self.call(self.inner.get_all_vm_states()).await
+    }
+
+    async fn get_guest_info(&self, vm: &Vm) -> OpResult<GuestInfo> {
+        self.call(self.inner.get_guest_info(vm)).await
+    }
+
+    async fn reset_password(
+        &self,
+        cfg: &FullVmInfo,
+        password: &str,
+    ) -> OpResult<PasswordResetMethod> {
+        self.call(self.inner.reset_password(cfg, password)).await
+    }
+
+    async fn configure_vm(&self, cfg: &FullVmInfo) -> OpResult<()> {
+        self.call(self.inner.configure_vm(cfg)).await
+    }
+
+    async fn patch_firewall(&self, cfg: &FullVmInfo) -> OpResult<()> {
+        self.call(self.inner.patch_firewall(cfg)).await
+    }
+
+    async fn get_time_series_data(
+        &self,
+        vm: &Vm,
+        series: TimeSeries,
+    ) -> OpResult<Vec<TimeSeriesData>> {
+        self.call(self.inner.get_time_series_data(vm, series)).await
+    }
+
+    async fn connect_terminal(&self, vm: &Vm) -> OpResult<TerminalStream> {
+        self.call(self.inner.connect_terminal(vm)).await
+    }
+
+    async fn get_console_screenshot(&self, vm: &Vm) -> OpResult<Vec<u8>> {
+        self.call(self.inner.get_console_screenshot(vm)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::host::dummy_host::DummyVmHost;
+    use lnvps_db::VmHostKind;
+
+    const WINDOW: Duration = Duration::from_secs(60);
+    const COOLDOWN: Duration = Duration::from_secs(30);
+
+    #[test]
+    fn test_breaker_opens_after_threshold() {
+        let b = CircuitBreaker::new(3, WINDOW, COOLDOWN);
+        let now = Instant::now();
+        for _ in 0..2 {
+            assert!(b.try_acquire_at(now));
+            b.record_at(now, true);
+        }
+        assert_eq!(b.state(), BreakerState::Closed);
+        b.record_at(now, true);
+        assert_eq!(b.state(), BreakerState::Open);
+        assert!(!b.try_acquire_at(now + Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn test_breaker_failures_outside_window_reset() {
+        let b = CircuitBreaker::new(3, WINDOW, COOLDOWN);
+        let now = Instant::now();
+        b.record_at(now, true);
+        b.record_at(now, true);
+        // third failure comes after the window, counting starts again
+        b.record_at(now + WINDOW + Duration::from_secs(1), true);
+        assert_eq!(b.state(), BreakerState::Closed);
+
+        // a success between failures also resets the count
+        b.record_at(now + WINDOW + Duration::from_secs(2), false);
+        b.record_at(now + WINDOW + Duration::from_secs(3), true);
+        b.record_at(now + WINDOW + Duration::from_secs(4), true);
+        assert_eq!(b.state(), BreakerState::Closed);
+    }
+
+    #[test]
+    fn test_breaker_half_open_probe() {
+        let b = CircuitBreaker::new(1, WINDOW, COOLDOWN);
+        let now = Instant::now();
+        b.record_at(now, true);
+        assert_eq!(b.state(), BreakerState::Open);
+
+        // one probe after the cooldown, other calls keep failing fast
+        let later = now + COOLDOWN;
+        assert!(b.try_acquire_at(later));
+        assert_eq!(b.state(), BreakerState::HalfOpen);
+        assert!(!b.try_acquire_at(later));
+
+        // failed probe opens again for a new cooldown
+        b.record_at(later, true);
+        assert_eq!(b.state(), BreakerState::Open);
+        assert!(!b.try_acquire_at(later + Duration::from_secs(1)));
+
+        // successful probe closes it
+        let probe = later + COOLDOWN;
+        assert!(b.try_acquire_at(probe));
+        b.record_at(probe, false);
+        assert_eq!(b.state(), BreakerState::Closed);
+        assert!(b.try_acquire_at(probe));
+    }
+
+    #[test]
+    fn test_breaker_lost_probe_expires() {
+        let b = CircuitBreaker::new(1, WINDOW, COOLDOWN);
+        let now = Instant::now();
+        b.record_at(now, true);
+        assert!(b.try_acquire_at(now + COOLDOWN));
+        // the probe never reported back
+        assert!(!b.try_acquire_at(now + COOLDOWN + Duration::from_secs(1)));
+        assert!(b.try_acquire_at(now + COOLDOWN * 2));
+    }
+
+    #[test]
+    fn test_breaker_fatal_counts_as_success() {
+        let b = CircuitBreaker::new(2, WINDOW, COOLDOWN);
+        b.record::<()>(&Err(OpError::Transient(anyhow!("timeout"))));
+        b.record::<()>(&Err(OpError::Fatal(anyhow!("not found"))));
+        b.record::<()>(&Err(OpError::Transient(anyhow!("timeout"))));
+        assert_eq!(b.state(), BreakerState::Closed);
+        b.record::<()>(&Err(OpError::Transient(anyhow!("timeout"))));
+        assert_eq!(b.state(), BreakerState::Open);
+    }
+
+    #[tokio::test]
+    async fn test_client_fails_fast_when_open() {
+        let host = VmHost {
+            id: 9_001,
+            kind: VmHostKind::Proxmox,
+            name: "breaker-test".to_string(),
+            ..Default::default()
+        };
+        let client = BreakerHostClient::new(&host, Arc::new(DummyVmHost::new()));
+        assert!(client.get_info().await.is_ok());
+
+        let breaker = host_breaker(host.id);
+        for _ in 0..FAILURE_THRESHOLD {
+            breaker.record::<()>(&Err(OpError::Transient(anyhow!("timeout"))));
+        }
+        match client.get_info().await {
+            Err(OpError::Fatal(e)) => assert!(e.to_string().contains("breaker-test")),
+            _ => panic!("expected fast failure"),
+        }
+    }
+}
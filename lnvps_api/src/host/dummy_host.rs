@@ -1,17 +1,30 @@
 use crate::host::{
-    FullVmInfo, TerminalStream, TimeSeries, TimeSeriesData, VmHostClient, VmHostDiskInfo,
-    VmHostInfo,
+    DataDisk, FullVmInfo, HostBackup, PasswordResetMethod, TerminalStream, TimeSeries,
+    TimeSeriesData, VmHostClient, VmHostDiskInfo, VmHostInfo,
 };
 use async_trait::async_trait;
 use chrono::Utc;
-use lnvps_api_common::retry::OpResult;
-use lnvps_api_common::{GB, HostVmSpec, PB, TB, VmRunningState, VmRunningStates, op_fatal};
-use lnvps_db::{Vm, VmOsImage};
+use lnvps_api_common::retry::{OpError, OpResult, RetryPolicy};
+use lnvps_api_common::{
+    GB, GuestFilesystem, GuestInfo, GuestInterface, HostVmSpec, PB, TB, VmRunningState,
+    VmRunningStates, op_fatal,
+};
+use lnvps_db::{Vm, VmHost, VmHostDisk, VmOsImage};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::{Arc, LazyLock};
 use tokio::sync::Mutex;
 
+/// Fixed 1x1 PNG returned by [DummyVmHost] as the console screenshot.
+pub const DUMMY_SCREENSHOT_PNG: &[u8] = &[
+    0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0x00, 0x00, 0x0d, 0x49, 0x48, 0x44, 0x52,
+    0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x08, 0x06, 0x00, 0x00, 0x00, 0x1f, 0x15, 0xc4,
+    0x89, 0x00, 0x00, 0x00, 0x0a, 0x49, 0x44, 0x41, 0x54, 0x78, 0x9c, 0x63, 0x00, 0x01, 0x00, 0x00,
+    0x05, 0x00, 0x01, 0x0d, 0x0a, 0x2d, 0xb4, 0x00, 0x00, 0x00, 0x00, 0x49, 0x45, 0x4e, 0x44, 0xae,
+    0x42, 0x60, 0x82,
+];
+
 /// Per-VM state tracked by the mock host.
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 struct MockVm {
@@ -28,6 +41,21 @@ struct MockVm {
     disk_write: u64,
     /// Unix timestamp of the last `tick` call (used to advance counters).
     last_tick: u64,
+    /// Slots of the attached extra data disks.
+    #[serde(default)]
+    data_disks: Vec<u16>,
+    /// OS image the primary disk was imported from, `None` once unlinked.
+    #[serde(default)]
+    image_id: Option<u64>,
+    /// Primary NIC MAC address the VM was created with.
+    #[serde(default)]
+    mac_address: Option<String>,
+    /// CPU cores the VM was created with.
+    #[serde(default)]
+    cpu: u16,
+    /// Memory in bytes the VM was created with.
+    #[serde(default)]
+    memory: u64,
 }
 
 impl MockVm {
@@ -101,6 +129,13 @@ pub struct DummyVmHost {
     vms: Arc<Mutex<HashMap<u64, MockVm>>>,
     /// When `true`, mutations are flushed to [`STATE_FILE`].
     persist: bool,
+    /// Number of upcoming `create_vm` calls which create the VM and then
+    /// fail transiently, like a request timing out after the host acted on it
+    create_timeouts: Arc<AtomicU32>,
+    /// Number of `create_vm` calls made
+    create_calls: Arc<AtomicU32>,
+    /// Acts as a container host, see [`DummyVmHost::with_containers`]
+    containers: bool,
 }
 
 impl Default for DummyVmHost {
@@ -119,6 +154,9 @@ impl DummyVmHost {
         Self {
             vms: Arc::new(Mutex::new(HashMap::new())),
             persist: false,
+            create_timeouts: Default::default(),
+            create_calls: Default::default(),
+            containers: false,
         }
     }
 
@@ -133,6 +171,9 @@ impl DummyVmHost {
         Self {
             vms: LAZY_VMS.clone(),
             persist: true,
+            create_timeouts: Default::default(),
+            create_calls: Default::default(),
+            containers: false,
         }
     }
 
@@ -161,6 +202,40 @@ impl DummyVmHost {
     pub async fn set_host_vms(vms: Vec<HostVmSpec>) {
         *DUMMY_HOST_VMS.lock().await = vms;
     }
+
+    /// Make the next `n` `create_vm` calls time out after creating the VM
+    pub fn with_create_timeouts(self, n: u32) -> Self {
+        self.create_timeouts.store(n, Ordering::SeqCst);
+        self
+    }
+
+    /// Run containers instead of VMs, OS changes then go through
+    /// `recreate_container` and disk imports are rejected
+    pub fn with_containers(mut self) -> Self {
+        self.containers = true;
+        self
+    }
+
+    /// Number of `create_vm` calls made on this host
+    pub fn create_calls(&self) -> u32 {
+        self.create_calls.load(Ordering::SeqCst)
+    }
+
+    /// Slots of the data disks attached to a VM
+    pub async fn data_disk_slots(&self, vm_id: u64) -> Vec<u16> {
+        self.vms
+            .lock()
+            .await
+            .get(&vm_id)
+            .map(|m| m.data_disks.clone())
+            .unwrap_or_default()
+    }
+
+    /// OS image the VM's primary disk was imported from, `None` when the VM is
+    /// unknown or its disk is unlinked.
+    pub async fn primary_disk_image(&self, vm_id: u64) -> Option<u64> {
+        self.vms.lock().await.get(&vm_id).and_then(|m| m.image_id)
+    }
 }
 
 /// Process-wide registry of "host" VMs reported by [`DummyVmHost::list_host_vms`].
@@ -169,8 +244,23 @@ static DUMMY_HOST_VMS: LazyLock<Arc<Mutex<Vec<HostVmSpec>>>> =
 
 #[async_trait]
 impl VmHostClient for DummyVmHost {
+    /// The seeded foreign VMs followed by the VMs created on this host
     async fn list_host_vms(&self) -> OpResult<Vec<HostVmSpec>> {
-        Ok(DUMMY_HOST_VMS.lock().await.clone())
+        let mut out = DUMMY_HOST_VMS.lock().await.clone();
+        let vms = self.vms.lock().await;
+        out.extend(vms.iter().map(|(id, m)| HostVmSpec {
+            host_vm_id: *id as i64 + 100,
+            mapped_vm_id: Some(*id),
+            name: None,
+            cpu: m.cpu,
+            memory: m.memory,
+            disk_size: 0,
+            disk_storage: None,
+            mac_address: m.mac_address.clone(),
+            ip_addresses: vec![],
+            running: m.state == VmRunningStates::Running,
+        }));
+        Ok(out)
     }
 
     async fn get_info(&self) -> OpResult<VmHostInfo> {
@@ -185,7 +275,7 @@ impl VmHostClient for DummyVmHost {
         })
     }
 
-    async fn download_os_image(&self, _image: &VmOsImage) -> OpResult<()> {
+    async fn download_os_image(&self, _image: &VmOsImage, _retry: RetryPolicy) -> OpResult<()> {
         Ok(())
     }
 
@@ -201,8 +291,10 @@ impl VmHostClient for DummyVmHost {
     /// Register the VM under its DB id in the `Creating` state, then
     /// transition it to `Stopped` after a real async delay of 10–60 seconds,
     /// simulating provisioning time on a real hypervisor.
-    async fn create_vm(&self, cfg: &FullVmInfo) -> OpResult<()> {
+    async fn create_vm(&self, cfg: &FullVmInfo, _retry: RetryPolicy) -> OpResult<()> {
         let vm_id = cfg.vm.id;
+        let res = cfg.resources()?;
+        self.create_calls.fetch_add(1, Ordering::SeqCst);
 
         // when using dummy host in real dev env, add a small delete in create_vm
         #[cfg(not(test))]
@@ -213,12 +305,24 @@ impl VmHostClient for DummyVmHost {
                 vm_id,
                 MockVm {
                     state: VmRunningStates::Stopped,
+                    data_disks: cfg.data_disks.iter().map(|d| d.disk.slot).collect(),
+                    image_id: Some(cfg.image.id),
+                    mac_address: Some(cfg.vm.mac_address.clone()),
+                    cpu: res.cpu,
+                    memory: res.memory,
                     ..MockVm::default()
                 },
             );
         }
         self.save().await;
 
+        if self
+            .create_timeouts
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+            .is_ok()
+        {
+            return Err(OpError::Transient(anyhow::anyhow!("Request timed out")));
+        }
         Ok(())
     }
 
@@ -269,11 +373,75 @@ impl VmHostClient for DummyVmHost {
         Ok(())
     }
 
-    async fn unlink_primary_disk(&self, _vm: &Vm) -> OpResult<()> {
+    async fn reboot_vm(&self, vm: &Vm) -> OpResult<()> {
+        // guests always shut down cleanly here, but like a real host a
+        // stopped VM can't be rebooted
+        {
+            let mut vms = self.vms.lock().await;
+            if let Some(m) = vms.get_mut(&vm.id) {
+                if m.state != VmRunningStates::Running {
+                    op_fatal!("VM {} is not running", vm.id);
+                }
+                m.tick();
+                m.uptime_secs = 0;
+            }
+        }
+        self.save().await;
+        Ok(())
+    }
+
+    async fn suspend_vm(&self, vm: &Vm) -> OpResult<()> {
+        {
+            let mut vms = self.vms.lock().await;
+            if let Some(m) = vms.get_mut(&vm.id) {
+                if m.state != VmRunningStates::Running {
+                    op_fatal!("VM {} is not running", vm.id);
+                }
+                m.tick();
+                m.state = VmRunningStates::Suspended;
+            }
+        }
+        self.save().await;
+        Ok(())
+    }
+
+    async fn resume_vm(&self, vm: &Vm) -> OpResult<()> {
+        {
+            let mut vms = self.vms.lock().await;
+            if let Some(m) = vms.get_mut(&vm.id) {
+                if m.state != VmRunningStates::Suspended {
+                    op_fatal!("VM {} is not suspended", vm.id);
+                }
+                m.tick();
+                m.state = VmRunningStates::Running;
+            }
+        }
+        self.save().await;
+        Ok(())
+    }
+
+    async fn unlink_primary_disk(&self, vm: &Vm) -> OpResult<()> {
+        {
+            let mut vms = self.vms.lock().await;
+            if let Some(m) = vms.get_mut(&vm.id) {
+                m.image_id = None;
+            }
+        }
+        self.save().await;
         Ok(())
     }
 
-    async fn import_template_disk(&self, _cfg: &FullVmInfo) -> OpResult<()> {
+    async fn import_template_disk(&self, cfg: &FullVmInfo) -> OpResult<()> {
+        if self.containers {
+            op_fatal!("Container hosts can't import disk images");
+        }
+        {
+            let mut vms = self.vms.lock().await;
+            if let Some(m) = vms.get_mut(&cfg.vm.id) {
+                m.image_id = Some(cfg.image.id);
+            }
+        }
+        self.save().await;
         Ok(())
     }
 
@@ -281,7 +449,64 @@ impl VmHostClient for DummyVmHost {
         Ok(())
     }
 
-    async fn configure_vm(&self, _vm: &FullVmInfo) -> OpResult<()> {
+    async fn is_container(&self, _vm: &Vm) -> OpResult<bool> {
+        Ok(self.containers)
+    }
+
+    /// Swap the root filesystem's image, data disks stay attached
+    async fn recreate_container(&self, cfg: &FullVmInfo) -> OpResult<()> {
+        if !self.containers {
+            op_fatal!("Containers are not supported on this host type");
+        }
+        {
+            let mut vms = self.vms.lock().await;
+            let Some(m) = vms.get_mut(&cfg.vm.id) else {
+                op_fatal!("VM {} does not exist on this host", cfg.vm.id);
+            };
+            m.image_id = Some(cfg.image.id);
+            m.tick();
+            m.state = VmRunningStates::Running;
+        }
+        self.save().await;
+        Ok(())
+    }
+
+    /// Attach a data disk to a registered VM. Like a real hypervisor, this
+    /// fails if the VM does not exist on the host.
+    async fn attach_data_disk(&self, vm: &Vm, disk: &DataDisk) -> OpResult<()> {
+        {
+            let mut vms = self.vms.lock().await;
+            let Some(m) = vms.get_mut(&vm.id) else {
+                op_fatal!("VM {} not found on host", vm.id);
+            };
+            if !m.data_disks.contains(&disk.disk.slot) {
+                m.data_disks.push(disk.disk.slot);
+            }
+        }
+        self.save().await;
+        Ok(())
+    }
+
+    async fn detach_data_disk(&self, vm: &Vm, disk: &DataDisk) -> OpResult<()> {
+        {
+            let mut vms = self.vms.lock().await;
+            if let Some(m) = vms.get_mut(&vm.id) {
+                m.data_disks.retain(|s| *s != disk.disk.slot);
+            }
+        }
+        self.save().await;
+        Ok(())
+    }
+
+    /// Reconcile the attached data disks with the VM config.
+    async fn configure_vm(&self, cfg: &FullVmInfo) -> OpResult<()> {
+        {
+            let mut vms = self.vms.lock().await;
+            if let Some(m) = vms.get_mut(&cfg.vm.id) {
+                m.data_disks = cfg.data_disks.iter().map(|d| d.disk.slot).collect();
+            }
+        }
+        self.save().await;
         Ok(())
     }
 
@@ -289,6 +514,106 @@ impl VmHostClient for DummyVmHost {
         Ok(())
     }
 
+    /// Fixed interface list, the agent only "runs" while the VM is running
+    async fn get_guest_info(&self, vm: &Vm) -> OpResult<GuestInfo> {
+        let mut vms = self.vms.lock().await;
+        let Some(m) = vms.get_mut(&vm.id) else {
+            op_fatal!("Vm not found")
+        };
+        if m.state != VmRunningStates::Running {
+            op_fatal!("Guest agent is not running");
+        }
+        m.tick();
+        Ok(GuestInfo {
+            interfaces: vec![
+                GuestInterface {
+                    name: "lo".to_string(),
+                    mac_address: None,
+                    addresses: vec!["127.0.0.1/8".to_string(), "::1/128".to_string()],
+                },
+                GuestInterface {
+                    name: "eth0".to_string(),
+                    mac_address: Some(vm.mac_address.clone()),
+                    addresses: vec!["10.0.0.2/24".to_string(), "fe80::2/64".to_string()],
+                },
+            ],
+            uptime: Some(m.uptime_secs),
+            filesystems: vec![GuestFilesystem {
+                mountpoint: "/".to_string(),
+                used_bytes: 4 * GB,
+                total_bytes: 20 * GB,
+            }],
+        })
+    }
+
+    async fn reset_password(
+        &self,
+        cfg: &FullVmInfo,
+        _password: &str,
+    ) -> OpResult<PasswordResetMethod> {
+        // nothing to store, a stopped VM is booted like a real host does so
+        // cloud-init can apply the new password
+        let method = {
+            let mut vms = self.vms.lock().await;
+            let Some(m) = vms.get_mut(&cfg.vm.id) else {
+                op_fatal!("Vm not found")
+            };
+            m.tick();
+            let method = if m.state == VmRunningStates::Running {
+                PasswordResetMethod::GuestAgent
+            } else {
+                PasswordResetMethod::Started
+            };
+            m.state = VmRunningStates::Running;
+            method
+        };
+        self.save().await;
+        Ok(method)
+    }
+
+    async fn backup_vm(&self, vm: &Vm) -> OpResult<HostBackup> {
+        Ok(HostBackup {
+            volume_id: format!(
+                "backup:backup/vzdump-qemu-{}-{}.vma.zst",
+                vm.id,
+                Utc::now().format("%Y_%m_%d-%H_%M_%S%.6f")
+            ),
+            size: GB,
+        })
+    }
+
+    async fn delete_backup(&self, _volume_id: &str) -> OpResult<()> {
+        Ok(())
+    }
+
+    async fn restore_backup(&self, _cfg: &FullVmInfo, _volume_id: &str) -> OpResult<()> {
+        Ok(())
+    }
+
+    /// Migrations finish at once, the VM stays in this host's state
+    async fn start_migrate_vm(
+        &self,
+        vm: &Vm,
+        _target: &VmHost,
+        _target_disk: &VmHostDisk,
+    ) -> OpResult<String> {
+        if !self.vms.lock().await.contains_key(&vm.id) {
+            op_fatal!("VM {} not found", vm.id);
+        }
+        Ok(format!("migrate-{}", vm.id))
+    }
+
+    async fn migration_finished(&self, _task: &str) -> OpResult<bool> {
+        Ok(true)
+    }
+
+    async fn hotplug_resize(&self, vm: &Vm, _cpu: u16, _memory: u64) -> OpResult<bool> {
+        let vms = self.vms.lock().await;
+        Ok(vms
+            .get(&vm.id)
+            .is_some_and(|m| m.state == VmRunningStates::Running))
+    }
+
     /// Return the current state of a single VM.
     ///
     /// If the VM is not registered (e.g. it was deleted or never created),
@@ -385,4 +710,8 @@ impl VmHostClient for DummyVmHost {
             tx: server_tx,
         })
     }
+
+    async fn get_console_screenshot(&self, _vm: &Vm) -> OpResult<Vec<u8>> {
+        Ok(DUMMY_SCREENSHOT_PNG.to_vec())
+    }
 }
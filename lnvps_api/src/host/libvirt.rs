@@ -1,27 +1,75 @@
 use crate::KB;
 use crate::host::{
-    FullVmInfo, TerminalStream, TimeSeries, TimeSeriesData, VmHostClient, VmHostDiskInfo,
-    VmHostInfo,
+    FullVmInfo, PasswordResetMethod, TerminalStream, TimeSeries, TimeSeriesData, VmHostClient,
+    VmHostDiskInfo, VmHostInfo,
 };
-use crate::settings::QemuConfig;
+use crate::settings::{FirewallPolicy, QemuConfig};
 use anyhow::{Context, Result, bail, ensure};
 use chrono::Utc;
 use lnvps_api_common::VmRunningStates;
-use lnvps_api_common::retry::{OpError, OpResult};
-use lnvps_api_common::{VmRunningState, op_fatal};
-use lnvps_db::{LNVpsDb, Vm, VmOsImage};
-use log::info;
+use lnvps_api_common::retry::{OpError, OpResult, RetryPolicy};
+use lnvps_api_common::{GuestInfo, GuestInterface, VmRunningState, op_fatal};
+use lnvps_db::{
+    LNVpsDb, Vm, VmFirewallDirection, VmFirewallPolicy, VmFirewallProtocol, VmFirewallRuleAction,
+    VmOsImage,
+};
+use log::{info, warn};
 use rand::random;
 use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
 use std::str::FromStr;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::time::sleep;
 use uuid::Uuid;
 use virt::connect::Connect;
 use virt::domain::Domain;
+use virt::nwfilter::NWFilter;
+use virt::storage_pool::StoragePool;
+use virt::storage_vol::StorageVol;
 use virt::sys::{
-    VIR_CONNECT_LIST_STORAGE_POOLS_ACTIVE, VIR_DOMAIN_START_VALIDATE, virDomainCreate,
+    VIR_CONNECT_LIST_STORAGE_POOLS_ACTIVE, VIR_DOMAIN_BLOCKED, VIR_DOMAIN_CRASHED,
+    VIR_DOMAIN_INTERFACE_ADDRESSES_SRC_AGENT, VIR_DOMAIN_PAUSED, VIR_DOMAIN_PMSUSPENDED,
+    VIR_DOMAIN_RUNNING, VIR_DOMAIN_SHUTDOWN, VIR_DOMAIN_SHUTOFF, VIR_DOMAIN_START_VALIDATE,
+    VIR_DOMAIN_UNDEFINE_NVRAM, virDomainCreate, virDomainState,
 };
 
+/// Wrap a libvirt error as a fatal operation error
+fn virt_err(e: virt::error::Error) -> OpError {
+    OpError::Fatal(anyhow::anyhow!(e))
+}
+
+/// Libvirt domain name for a VM
+fn domain_name(vm_id: u64) -> String {
+    format!("VM{}", vm_id)
+}
+
+/// Parse the VM id back out of a domain name, domains not managed by us return None
+fn vm_id_from_domain_name(name: &str) -> Option<u64> {
+    name.strip_prefix("VM")?.parse().ok()
+}
+
+/// Storage volume name of the primary disk of a VM
+fn primary_volume_name(vm_id: u64) -> String {
+    format!("vm-{}-disk0", vm_id)
+}
+
+/// Network filter name holding the firewall rules of a VM
+fn nwfilter_name(vm_id: u64) -> String {
+    format!("lnvps-vm-{}", vm_id)
+}
+
+fn map_domain_state(state: virDomainState) -> VmRunningStates {
+    match state {
+        VIR_DOMAIN_RUNNING | VIR_DOMAIN_BLOCKED | VIR_DOMAIN_SHUTDOWN => VmRunningStates::Running,
+        VIR_DOMAIN_PAUSED => VmRunningStates::Suspended,
+        VIR_DOMAIN_SHUTOFF | VIR_DOMAIN_CRASHED | VIR_DOMAIN_PMSUSPENDED => {
+            VmRunningStates::Stopped
+        }
+        _ => VmRunningStates::Unknown,
+    }
+}
+
 #[derive(Debug)]
 pub struct LibVirtHost {
     connection: Connect,
@@ -36,13 +84,253 @@ impl LibVirtHost {
         })
     }
 
-    pub fn import_disk_image(&self, vm: &Vm, image: &VmOsImage) -> Result<()> {
-        // https://libvirt.org/html/libvirt-libvirt-storage.html#virStorageVolUpload
-        // https://libvirt.org/html/libvirt-libvirt-storage.html#virStorageVolResize
-        Ok(())
+    /// Find the domain for a VM, None if it is not defined on this host
+    fn find_domain(&self, vm_id: u64) -> OpResult<Option<Domain>> {
+        let name = domain_name(vm_id);
+        let domains = self.connection.list_all_domains(0).map_err(virt_err)?;
+        Ok(domains
+            .into_iter()
+            .find(|d| d.get_name().map(|n| n == name).unwrap_or(false)))
+    }
+
+    fn get_domain(&self, vm_id: u64) -> OpResult<Domain> {
+        match self.find_domain(vm_id)? {
+            Some(d) => Ok(d),
+            None => op_fatal!("Domain {} not found on host", domain_name(vm_id)),
+        }
+    }
+
+    fn domain_state(&self, domain: &Domain) -> OpResult<VmRunningState> {
+        let info = domain.get_info().map_err(virt_err)?;
+        Ok(VmRunningState {
+            timestamp: Utc::now().timestamp() as u64,
+            state: map_domain_state(info.state),
+            cpu_usage: 0.0,
+            mem_usage: 0.0,
+            uptime: 0,
+            net_in: 0,
+            net_out: 0,
+            disk_write: 0,
+            disk_read: 0,
+        })
+    }
+
+    fn get_pool(&self, name: &str) -> OpResult<StoragePool> {
+        StoragePool::lookup_by_name(&self.connection, name).map_err(virt_err)
+    }
+
+    fn find_volume(pool: &StoragePool, name: &str) -> OpResult<Option<StorageVol>> {
+        let volumes = pool.list_all_volumes(0).map_err(virt_err)?;
+        Ok(volumes
+            .into_iter()
+            .find(|v| v.get_name().map(|n| n == name).unwrap_or(false)))
+    }
+
+    /// Build the network filter for a VM from the host firewall config and
+    /// the VM's own rules, None when the firewall is disabled on this host
+    fn create_nwfilter_xml(&self, cfg: &FullVmInfo) -> Result<Option<NwFilterXML>> {
+        let Some(fw_cfg) = self
+            .qemu
+            .firewall_config
+            .as_ref()
+            .filter(|c| c.enable.unwrap_or(false))
+        else {
+            return Ok(None);
+        };
+
+        let mut contents = vec![];
+        if fw_cfg.ip_filter.unwrap_or(false) {
+            // clean-traffic includes MAC/ARP spoofing protection
+            contents.push(NwFilterEntry::FilterRef(NwFilterRef {
+                filter: "clean-traffic".to_string(),
+                params: cfg
+                    .ips
+                    .iter()
+                    .filter(|i| !i.deleted)
+                    .filter(|i| matches!(i.ip.parse::<IpAddr>(), Ok(IpAddr::V4(_))))
+                    .map(|i| NwFilterParam {
+                        name: "IP".to_string(),
+                        value: i.ip.clone(),
+                    })
+                    .collect(),
+            }));
+        } else if fw_cfg.mac_filter.unwrap_or(false) {
+            contents.push(NwFilterEntry::FilterRef(NwFilterRef {
+                filter: "no-mac-spoofing".to_string(),
+                params: vec![],
+            }));
+        }
+
+        // replies to established connections are always allowed
+        for direction in [NwFilterDirection::In, NwFilterDirection::Out] {
+            for proto in [NwFilterMatch::All, NwFilterMatch::AllIpv6] {
+                contents.push(NwFilterEntry::Rule(NwFilterRule {
+                    action: NwFilterAction::Accept,
+                    direction: direction.clone(),
+                    priority: -500,
+                    proto: proto(NwFilterProto {
+                        state: Some("ESTABLISHED,RELATED".to_string()),
+                        ..Default::default()
+                    }),
+                }));
+            }
+        }
+        if fw_cfg.dhcp.unwrap_or(false) {
+            contents.push(NwFilterEntry::Rule(NwFilterRule {
+                action: NwFilterAction::Accept,
+                direction: NwFilterDirection::Out,
+                priority: -450,
+                proto: NwFilterMatch::Udp(NwFilterProto {
+                    dst_port_start: Some(67),
+                    dst_port_end: Some(67),
+                    ..Default::default()
+                }),
+            }));
+            contents.push(NwFilterEntry::Rule(NwFilterRule {
+                action: NwFilterAction::Accept,
+                direction: NwFilterDirection::In,
+                priority: -450,
+                proto: NwFilterMatch::Udp(NwFilterProto {
+                    dst_port_start: Some(68),
+                    dst_port_end: Some(68),
+                    ..Default::default()
+                }),
+            }));
+        }
+        if fw_cfg.ndp.unwrap_or(false) {
+            for direction in [NwFilterDirection::In, NwFilterDirection::Out] {
+                contents.push(NwFilterEntry::Rule(NwFilterRule {
+                    action: NwFilterAction::Accept,
+                    direction,
+                    priority: -450,
+                    proto: NwFilterMatch::IcmpV6(NwFilterProto::default()),
+                }));
+            }
+        }
+
+        for (i, rule) in cfg.firewall_rules.iter().filter(|r| r.enabled).enumerate() {
+            let priority = (-400 + i as i32).min(899) as i16;
+            for proto in Self::to_nwfilter_matches(rule)? {
+                contents.push(NwFilterEntry::Rule(NwFilterRule {
+                    action: match rule.action {
+                        VmFirewallRuleAction::Accept => NwFilterAction::Accept,
+                        VmFirewallRuleAction::Drop => NwFilterAction::Drop,
+                        VmFirewallRuleAction::Reject => NwFilterAction::Reject,
+                    },
+                    direction: match rule.direction {
+                        VmFirewallDirection::Inbound => NwFilterDirection::In,
+                        VmFirewallDirection::Outbound => NwFilterDirection::Out,
+                    },
+                    priority,
+                    proto,
+                }));
+            }
+        }
+
+        // default policies go last, the VM's own policy overrides the host policy
+        let policy_in = cfg
+            .vm
+            .fw_policy_in
+            .map(NwFilterAction::from)
+            .or_else(|| fw_cfg.policy_in.as_ref().map(NwFilterAction::from));
+        let policy_out = cfg
+            .vm
+            .fw_policy_out
+            .map(NwFilterAction::from)
+            .or_else(|| fw_cfg.policy_out.as_ref().map(NwFilterAction::from));
+        for (direction, policy) in [
+            (NwFilterDirection::In, policy_in),
+            (NwFilterDirection::Out, policy_out),
+        ] {
+            let Some(action) = policy.filter(|p| !matches!(p, NwFilterAction::Accept)) else {
+                continue;
+            };
+            for proto in [NwFilterMatch::All, NwFilterMatch::AllIpv6] {
+                contents.push(NwFilterEntry::Rule(NwFilterRule {
+                    action: action.clone(),
+                    direction: direction.clone(),
+                    priority: 1000,
+                    proto: proto(NwFilterProto::default()),
+                }));
+            }
+        }
+
+        Ok(Some(NwFilterXML {
+            name: nwfilter_name(cfg.vm.id),
+            chain: "root".to_string(),
+            contents,
+        }))
+    }
+
+    /// Convert a user firewall rule into nwfilter protocol matches.
+    ///
+    /// nwfilter uses separate elements for IPv4 and IPv6, a rule without a
+    /// source address is expanded to both, and "any" protocol with a port is
+    /// expanded to tcp + udp since ports only exist on those.
+    fn to_nwfilter_matches(rule: &lnvps_db::VmFirewallRule) -> Result<Vec<NwFilterMatch>> {
+        let (src_addr, src_mask, families) = match &rule.src_cidr {
+            Some(cidr) => {
+                let (addr, mask) = match cidr.split_once('/') {
+                    Some((a, m)) => (a, Some(m.parse::<u8>()?)),
+                    None => (cidr.as_str(), None),
+                };
+                let addr: IpAddr = addr.parse()?;
+                let is_v6 = addr.is_ipv6();
+                let mask = mask.unwrap_or(if is_v6 { 128 } else { 32 });
+                (Some(addr.to_string()), Some(mask), vec![is_v6])
+            }
+            None => (None, None, vec![false, true]),
+        };
+        let has_port = rule.dst_port_start.is_some();
+        let proto = NwFilterProto {
+            src_ip_addr: src_addr,
+            src_ip_mask: src_mask,
+            dst_port_start: rule.dst_port_start,
+            dst_port_end: rule.dst_port_end.or(rule.dst_port_start),
+            state: None,
+        };
+        let no_ports = NwFilterProto {
+            dst_port_start: None,
+            dst_port_end: None,
+            ..proto.clone()
+        };
+
+        let mut ret = vec![];
+        for is_v6 in families {
+            match (rule.protocol, is_v6) {
+                (VmFirewallProtocol::Any, false) if has_port => {
+                    ret.push(NwFilterMatch::Tcp(proto.clone()));
+                    ret.push(NwFilterMatch::Udp(proto.clone()));
+                }
+                (VmFirewallProtocol::Any, true) if has_port => {
+                    ret.push(NwFilterMatch::TcpIpv6(proto.clone()));
+                    ret.push(NwFilterMatch::UdpIpv6(proto.clone()));
+                }
+                (VmFirewallProtocol::Any, false) => ret.push(NwFilterMatch::All(no_ports.clone())),
+                (VmFirewallProtocol::Any, true) => {
+                    ret.push(NwFilterMatch::AllIpv6(no_ports.clone()))
+                }
+                (VmFirewallProtocol::Tcp, false) => ret.push(NwFilterMatch::Tcp(proto.clone())),
+                (VmFirewallProtocol::Tcp, true) => ret.push(NwFilterMatch::TcpIpv6(proto.clone())),
+                (VmFirewallProtocol::Udp, false) => ret.push(NwFilterMatch::Udp(proto.clone())),
+                (VmFirewallProtocol::Udp, true) => ret.push(NwFilterMatch::UdpIpv6(proto.clone())),
+                (VmFirewallProtocol::Icmp, false) => {
+                    ret.push(NwFilterMatch::Icmp(no_ports.clone()))
+                }
+                (VmFirewallProtocol::Icmp, true) => {
+                    ret.push(NwFilterMatch::IcmpV6(no_ports.clone()))
+                }
+            }
+        }
+        Ok(ret)
     }
 
     pub fn create_domain_xml(&self, cfg: &FullVmInfo) -> Result<DomainXML> {
+        // never silently put a private network VM on the shared bridge
+        ensure!(
+            !cfg.vm.private_network,
+            "Private networks are not supported on LibVirt hosts"
+        );
         let storage = self
             .connection
             .list_all_storage_pools(VIR_CONNECT_LIST_STORAGE_POOLS_ACTIVE)?;
@@ -92,6 +380,15 @@ impl LibVirtHost {
             vlan: cfg.host.vlan_id.map(|v| NetworkVlan {
                 tags: vec![NetworkVlanTag { id: v as u32 }],
             }),
+            filterref: self
+                .qemu
+                .firewall_config
+                .as_ref()
+                .and_then(|c| c.enable)
+                .unwrap_or(false)
+                .then(|| NetworkFilterRef {
+                    filter: nwfilter_name(cfg.vm.id),
+                }),
         }));
         Ok(DomainXML {
             kind: DomainType::KVM,
@@ -153,7 +450,7 @@ impl VmHostClient for LibVirtHost {
         })
     }
 
-    async fn download_os_image(&self, image: &VmOsImage) -> OpResult<()> {
+    async fn download_os_image(&self, image: &VmOsImage, _retry: RetryPolicy) -> OpResult<()> {
         // TODO: download ISO images to host (somehow, ssh?)
         Ok(())
     }
@@ -168,18 +465,66 @@ impl VmHostClient for LibVirtHost {
     }
 
     async fn start_vm(&self, vm: &Vm) -> OpResult<()> {
+        let domain = self.get_domain(vm.id)?;
+        if !domain.is_active().map_err(virt_err)? {
+            domain.create().map_err(virt_err)?;
+        }
         Ok(())
     }
 
     async fn stop_vm(&self, vm: &Vm) -> OpResult<()> {
+        let domain = self.get_domain(vm.id)?;
+        if domain.is_active().map_err(virt_err)? {
+            domain.destroy().map_err(virt_err)?;
+        }
         Ok(())
     }
 
     async fn reset_vm(&self, vm: &Vm) -> OpResult<()> {
+        let domain = self.get_domain(vm.id)?;
+        domain.reset().map_err(virt_err)?;
+        Ok(())
+    }
+
+    async fn reboot_vm(&self, vm: &Vm) -> OpResult<()> {
+        // virDomainReboot returns as soon as the guest is signalled, with no way
+        // to tell if it ignored the request, so shut down and start instead
+        let domain = self.get_domain(vm.id)?;
+        if !domain.is_active().map_err(virt_err)? {
+            op_fatal!("VM {} is not running", vm.id);
+        }
+        domain.shutdown().map_err(virt_err)?;
+        let timeout = Duration::from_secs(self.qemu.reboot_timeout());
+        let start = Instant::now();
+        while domain.is_active().map_err(virt_err)? {
+            if start.elapsed() > timeout {
+                warn!(
+                    "VM {} did not shut down within {}s, resetting",
+                    vm.id,
+                    timeout.as_secs()
+                );
+                domain.destroy().map_err(virt_err)?;
+                break;
+            }
+            sleep(Duration::from_secs(1)).await;
+        }
+        domain.create().map_err(virt_err)?;
+        Ok(())
+    }
+
+    async fn suspend_vm(&self, vm: &Vm) -> OpResult<()> {
+        let domain = self.get_domain(vm.id)?;
+        domain.suspend().map_err(virt_err)?;
+        Ok(())
+    }
+
+    async fn resume_vm(&self, vm: &Vm) -> OpResult<()> {
+        let domain = self.get_domain(vm.id)?;
+        domain.resume().map_err(virt_err)?;
         Ok(())
     }
 
-    async fn create_vm(&self, cfg: &FullVmInfo) -> OpResult<()> {
+    async fn create_vm(&self, cfg: &FullVmInfo, _retry: RetryPolicy) -> OpResult<()> {
         let domain = self.create_domain_xml(cfg).map_err(OpError::Transient)?;
         let xml = quick_xml::se::to_string(&domain).map_err(|e| OpError::Fatal(e.into()))?;
         op_fatal!("Not implemented");
@@ -188,39 +533,154 @@ impl VmHostClient for LibVirtHost {
     }
 
     async fn delete_vm(&self, vm: &Vm) -> OpResult<()> {
-        todo!()
+        if let Some(domain) = self.find_domain(vm.id)? {
+            if domain.is_active().map_err(virt_err)? {
+                domain.destroy().map_err(virt_err)?;
+            }
+            domain
+                .undefine_flags(VIR_DOMAIN_UNDEFINE_NVRAM)
+                .map_err(virt_err)?;
+        }
+        self.unlink_primary_disk(vm).await
     }
 
     async fn unlink_primary_disk(&self, vm: &Vm) -> OpResult<()> {
-        todo!()
+        // the Vm alone doesn't tell us which pool the disk is in, check all of them
+        let name = primary_volume_name(vm.id);
+        let pools = self
+            .connection
+            .list_all_storage_pools(VIR_CONNECT_LIST_STORAGE_POOLS_ACTIVE)
+            .map_err(virt_err)?;
+        for pool in pools {
+            if let Some(vol) = Self::find_volume(&pool, &name)? {
+                info!("Deleting volume {} of VM {}", name, vm.id);
+                vol.delete(0).map_err(virt_err)?;
+            }
+        }
+        Ok(())
     }
 
     async fn import_template_disk(&self, cfg: &FullVmInfo) -> OpResult<()> {
-        todo!()
+        // OS images are expected to be uploaded to the pool as volumes named
+        // after the image file, the primary disk is cloned from that volume
+        let pool = self.get_pool(&cfg.disk.name)?;
+        let image_name = cfg.image.filename().map_err(OpError::Fatal)?;
+        let Some(image) = Self::find_volume(&pool, &image_name)? else {
+            op_fatal!(
+                "OS image volume {} not found in pool {}",
+                image_name,
+                cfg.disk.name
+            );
+        };
+        let name = primary_volume_name(cfg.vm.id);
+        if Self::find_volume(&pool, &name)?.is_some() {
+            op_fatal!("Volume {} already exists in pool {}", name, cfg.disk.name);
+        }
+
+        let size = cfg.resources().map_err(OpError::Fatal)?.disk_size;
+        let xml = format!(
+            "<volume><name>{}</name><capacity unit=\"bytes\">{}</capacity><target><format type=\"qcow2\"/></target></volume>",
+            name, size
+        );
+        info!(
+            "Importing {} as {} in pool {}",
+            image_name, name, cfg.disk.name
+        );
+        StorageVol::create_xml_from(&pool, &xml, &image, 0).map_err(virt_err)?;
+        self.resize_disk(cfg).await
     }
 
     async fn resize_disk(&self, cfg: &FullVmInfo) -> OpResult<()> {
-        todo!()
+        let pool = self.get_pool(&cfg.disk.name)?;
+        let name = primary_volume_name(cfg.vm.id);
+        let Some(vol) = Self::find_volume(&pool, &name)? else {
+            op_fatal!("Volume {} not found in pool {}", name, cfg.disk.name);
+        };
+        let size = cfg.resources().map_err(OpError::Fatal)?.disk_size;
+        let current = vol.get_info().map_err(virt_err)?.capacity;
+        if size < current {
+            op_fatal!(
+                "Cannot shrink disk of VM {} from {} to {} bytes",
+                cfg.vm.id,
+                current,
+                size
+            );
+        }
+        if size > current {
+            vol.resize(size, 0).map_err(virt_err)?;
+        }
+        Ok(())
     }
 
     async fn get_vm_state(&self, vm: &Vm) -> OpResult<VmRunningState> {
-        Ok(VmRunningState {
-            timestamp: Utc::now().timestamp() as u64,
-            state: VmRunningStates::Stopped,
-            cpu_usage: 0.0,
-            mem_usage: 0.0,
-            uptime: 0,
-            net_in: 0,
-            net_out: 0,
-            disk_write: 0,
-            disk_read: 0,
-        })
+        let domain = self.get_domain(vm.id)?;
+        self.domain_state(&domain)
     }
 
     async fn get_all_vm_states(&self) -> OpResult<Vec<(u64, VmRunningState)>> {
-        // For libvirt, this is a stub implementation
-        // In a real implementation, this would list all VMs and get their states
-        Ok(Vec::new())
+        let domains = self.connection.list_all_domains(0).map_err(virt_err)?;
+        let mut ret = vec![];
+        for domain in domains {
+            let Some(id) = domain
+                .get_name()
+                .ok()
+                .and_then(|n| vm_id_from_domain_name(&n))
+            else {
+                continue;
+            };
+            ret.push((id, self.domain_state(&domain)?));
+        }
+        Ok(ret)
+    }
+
+    async fn get_guest_info(&self, vm: &Vm) -> OpResult<GuestInfo> {
+        let domain = self.get_domain(vm.id)?;
+        if !domain.is_active().map_err(virt_err)? {
+            op_fatal!("Guest agent is not running, VM is stopped");
+        }
+        let interfaces =
+            match domain.interface_addresses(VIR_DOMAIN_INTERFACE_ADDRESSES_SRC_AGENT, 0) {
+                Ok(i) => i,
+                Err(e) => op_fatal!("Guest agent is not running: {}", e),
+            };
+        Ok(GuestInfo {
+            interfaces: interfaces
+                .into_iter()
+                .map(|i| GuestInterface {
+                    name: i.name,
+                    mac_address: if i.hwaddr.is_empty() {
+                        None
+                    } else {
+                        Some(i.hwaddr)
+                    },
+                    addresses: i
+                        .addrs
+                        .into_iter()
+                        .map(|a| format!("{}/{}", a.addr, a.prefix))
+                        .collect(),
+                })
+                .collect(),
+            uptime: None,
+            filesystems: vec![],
+        })
+    }
+
+    async fn reset_password(
+        &self,
+        cfg: &FullVmInfo,
+        password: &str,
+    ) -> OpResult<PasswordResetMethod> {
+        // no cloud-init drive on libvirt hosts, the guest agent is the only
+        // way to apply it
+        let domain = self.get_domain(cfg.vm.id)?;
+        if !domain.is_active().map_err(virt_err)? {
+            op_fatal!("VM {} must be running to reset the password", cfg.vm.id);
+        }
+        let user = cfg.image.default_username.as_deref().unwrap_or("root");
+        if let Err(e) = domain.set_user_password(user, password, 0) {
+            op_fatal!("Guest agent is not running: {}", e);
+        }
+        Ok(PasswordResetMethod::GuestAgent)
     }
 
     async fn configure_vm(&self, vm: &FullVmInfo) -> OpResult<()> {
@@ -228,8 +688,29 @@ impl VmHostClient for LibVirtHost {
     }
 
     async fn patch_firewall(&self, cfg: &FullVmInfo) -> OpResult<()> {
-        // LibVirt doesn't have native firewall/IPset support like Proxmox
-        // This would typically be handled by the host's iptables/firewalld
+        let filter = self.create_nwfilter_xml(cfg).map_err(OpError::Fatal)?;
+        let name = nwfilter_name(cfg.vm.id);
+        let filter = match filter {
+            Some(f) => f,
+            None => {
+                // firewall disabled, clear out any filter left from before
+                let existing = self.connection.list_all_nw_filters(0).map_err(virt_err)?;
+                if !existing
+                    .iter()
+                    .any(|f| f.get_name().map(|n| n == name).unwrap_or(false))
+                {
+                    return Ok(());
+                }
+                NwFilterXML {
+                    name,
+                    chain: "root".to_string(),
+                    contents: vec![],
+                }
+            }
+        };
+        // redefining a filter re-applies it to running domains which reference it
+        let xml = quick_xml::se::to_string(&filter).map_err(|e| OpError::Fatal(e.into()))?;
+        NWFilter::define_xml(&self.connection, &xml).map_err(virt_err)?;
         Ok(())
     }
 
@@ -456,6 +937,15 @@ struct NetworkInterface {
     pub target: Option<NetworkTarget>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub vlan: Option<NetworkVlan>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filterref: Option<NetworkFilterRef>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+#[serde(rename = "filterref")]
+struct NetworkFilterRef {
+    #[serde(rename = "@filter")]
+    pub filter: String,
 }
 
 #[derive(Debug, Serialize, Deserialize, Default, Clone)]
@@ -575,10 +1065,138 @@ enum DiskBus {
     SATA,
 }
 
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+#[serde(rename = "filter")]
+struct NwFilterXML {
+    #[serde(rename = "@name")]
+    pub name: String,
+    #[serde(rename = "@chain")]
+    pub chain: String,
+    #[serde(rename = "$value")]
+    pub contents: Vec<NwFilterEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+enum NwFilterEntry {
+    #[serde(rename = "filterref")]
+    FilterRef(NwFilterRef),
+    #[serde(rename = "rule")]
+    Rule(NwFilterRule),
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+#[serde(rename = "filterref")]
+struct NwFilterRef {
+    #[serde(rename = "@filter")]
+    pub filter: String,
+    #[serde(rename = "parameter")]
+    pub params: Vec<NwFilterParam>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+#[serde(rename = "parameter")]
+struct NwFilterParam {
+    #[serde(rename = "@name")]
+    pub name: String,
+    #[serde(rename = "@value")]
+    pub value: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename = "rule")]
+struct NwFilterRule {
+    #[serde(rename = "@action")]
+    pub action: NwFilterAction,
+    #[serde(rename = "@direction")]
+    pub direction: NwFilterDirection,
+    /// -1000 to 1000, lower priority is evaluated first
+    #[serde(rename = "@priority")]
+    pub priority: i16,
+    #[serde(rename = "$value")]
+    pub proto: NwFilterMatch,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "lowercase")]
+enum NwFilterAction {
+    Accept,
+    Drop,
+    Reject,
+}
+
+impl From<VmFirewallPolicy> for NwFilterAction {
+    fn from(value: VmFirewallPolicy) -> Self {
+        match value {
+            VmFirewallPolicy::Accept => NwFilterAction::Accept,
+            VmFirewallPolicy::Drop => NwFilterAction::Drop,
+            VmFirewallPolicy::Reject => NwFilterAction::Reject,
+        }
+    }
+}
+
+impl From<&FirewallPolicy> for NwFilterAction {
+    fn from(value: &FirewallPolicy) -> Self {
+        match value {
+            FirewallPolicy::Accept => NwFilterAction::Accept,
+            FirewallPolicy::Drop => NwFilterAction::Drop,
+            FirewallPolicy::Reject => NwFilterAction::Reject,
+        }
+    }
+}
+
+/// Traffic direction as seen from the VM
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "lowercase")]
+enum NwFilterDirection {
+    In,
+    Out,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+enum NwFilterMatch {
+    #[serde(rename = "all")]
+    All(NwFilterProto),
+    #[serde(rename = "all-ipv6")]
+    AllIpv6(NwFilterProto),
+    #[serde(rename = "tcp")]
+    Tcp(NwFilterProto),
+    #[serde(rename = "tcp-ipv6")]
+    TcpIpv6(NwFilterProto),
+    #[serde(rename = "udp")]
+    Udp(NwFilterProto),
+    #[serde(rename = "udp-ipv6")]
+    UdpIpv6(NwFilterProto),
+    #[serde(rename = "icmp")]
+    Icmp(NwFilterProto),
+    #[serde(rename = "icmpv6")]
+    IcmpV6(NwFilterProto),
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+struct NwFilterProto {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "@srcipaddr")]
+    pub src_ip_addr: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "@srcipmask")]
+    pub src_ip_mask: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "@dstportstart")]
+    pub dst_port_start: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "@dstportend")]
+    pub dst_port_end: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "@state")]
+    pub state: Option<String>,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::GB;
     use crate::host::tests::mock_full_vm;
+    use crate::settings::FirewallConfig;
 
     fn cfg() -> FullVmInfo {
         let mut cfg = mock_full_vm();
@@ -645,6 +1263,7 @@ mod tests {
             arch: "x86_64".to_string(),
             balloon_min_pct: None,
             firewall_config: None,
+            reboot_timeout: None,
         };
         let host = LibVirtHost::new("test:///default", q_cfg)?;
         let xml = host.create_domain_xml(&cfg)?;
@@ -677,11 +1296,172 @@ mod tests {
             arch: "x86_64".to_string(),
             balloon_min_pct: None,
             firewall_config: None,
+            reboot_timeout: None,
         };
         let host = LibVirtHost::new("test:///default", q_cfg)?;
         println!("{:?}", host.get_info().await?);
-        host.create_vm(&cfg).await?;
+        host.create_vm(&cfg, RetryPolicy::default()).await?;
+
+        Ok(())
+    }
+
+    fn test_host(firewall_config: Option<FirewallConfig>) -> Result<LibVirtHost> {
+        let q_cfg = QemuConfig {
+            machine: "q35".to_string(),
+            os_type: "l26".to_string(),
+            bridge: "vmbr0".to_string(),
+            cpu: "kvm64".to_string(),
+            kvm: true,
+            arch: "x86_64".to_string(),
+            balloon_min_pct: None,
+            firewall_config,
+            reboot_timeout: None,
+        };
+        LibVirtHost::new("test:///default", q_cfg)
+    }
+
+    #[test]
+    fn test_domain_names() {
+        assert_eq!(vm_id_from_domain_name(&domain_name(42)), Some(42));
+        assert_eq!(vm_id_from_domain_name("test"), None);
+        assert_eq!(vm_id_from_domain_name("VMabc"), None);
+        assert_eq!(
+            map_domain_state(VIR_DOMAIN_RUNNING),
+            VmRunningStates::Running
+        );
+        assert_eq!(
+            map_domain_state(VIR_DOMAIN_SHUTOFF),
+            VmRunningStates::Stopped
+        );
+    }
+
+    #[tokio::test]
+    async fn test_vm_power_and_states() -> Result<()> {
+        let host = test_host(None)?;
+        let mut vm = cfg().vm;
+        vm.id = 901;
+        // the test driver state is shared within the process, use our own domain
+        Domain::define_xml(
+            &host.connection,
+            "<domain type=\"test\"><name>VM901</name><memory>1024</memory><os><type>hvm</type></os></domain>",
+        )?;
+
+        host.start_vm(&vm).await?;
+        let states = host.get_all_vm_states().await?;
+        let (_, state) = states
+            .iter()
+            .find(|(id, _)| *id == vm.id)
+            .expect("VM901 missing from host states");
+        assert_eq!(state.state, VmRunningStates::Running);
+        // the driver's own "test" domain is not one of ours
+        assert!(states.iter().all(|(id, _)| *id != 0));
+
+        host.reset_vm(&vm).await?;
+        // the test driver shuts down instantly so this never falls back to reset
+        host.reboot_vm(&vm).await?;
+        assert_eq!(
+            host.get_vm_state(&vm).await?.state,
+            VmRunningStates::Running
+        );
+        host.suspend_vm(&vm).await?;
+        assert_eq!(
+            host.get_vm_state(&vm).await?.state,
+            VmRunningStates::Suspended
+        );
+        host.resume_vm(&vm).await?;
+        assert_eq!(
+            host.get_vm_state(&vm).await?.state,
+            VmRunningStates::Running
+        );
+        host.stop_vm(&vm).await?;
+        assert_eq!(
+            host.get_vm_state(&vm).await?.state,
+            VmRunningStates::Stopped
+        );
+
+        host.delete_vm(&vm).await?;
+        assert!(host.get_vm_state(&vm).await.is_err());
+        assert!(
+            host.get_all_vm_states()
+                .await?
+                .iter()
+                .all(|(id, _)| *id != vm.id)
+        );
+        // deleting again is a no-op
+        host.delete_vm(&vm).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_disk_import_and_unlink() -> Result<()> {
+        let host = test_host(None)?;
+        let mut cfg = cfg();
+        cfg.vm.id = 902;
+        cfg.template.as_mut().unwrap().disk_size = 2 * GB;
+        cfg.image.url = "http://localhost.com/lnvps_test_902.qcow2".to_string();
+
+        let pool = host.get_pool(&cfg.disk.name)?;
+        let image_name = cfg.image.filename()?;
+        StorageVol::create_xml(
+            &pool,
+            &format!(
+                "<volume><name>{}</name><capacity unit=\"bytes\">{}</capacity></volume>",
+                image_name, GB
+            ),
+            0,
+        )?;
+
+        host.import_template_disk(&cfg).await?;
+        let vol = LibVirtHost::find_volume(&pool, &primary_volume_name(cfg.vm.id))?
+            .expect("primary disk was not created");
+        assert!(vol.get_info()?.capacity >= 2 * GB);
+        // importing over an existing disk is refused
+        assert!(host.import_template_disk(&cfg).await.is_err());
+
+        host.unlink_primary_disk(&cfg.vm).await?;
+        assert!(LibVirtHost::find_volume(&pool, &primary_volume_name(cfg.vm.id))?.is_none());
+        Ok(())
+    }
 
+    #[test]
+    fn test_nwfilter_xml() -> Result<()> {
+        let mut cfg = cfg();
+        assert!(test_host(None)?.create_nwfilter_xml(&cfg)?.is_none());
+
+        let host = test_host(Some(FirewallConfig {
+            dhcp: Some(true),
+            enable: Some(true),
+            ip_filter: Some(true),
+            mac_filter: Some(true),
+            ndp: Some(true),
+            policy_in: Some(FirewallPolicy::Drop),
+            policy_out: None,
+        }))?;
+        cfg.firewall_rules = vec![lnvps_db::VmFirewallRule {
+            vm_id: cfg.vm.id,
+            direction: VmFirewallDirection::Inbound,
+            protocol: VmFirewallProtocol::Tcp,
+            action: VmFirewallRuleAction::Accept,
+            src_cidr: Some("10.0.0.0/8".to_string()),
+            dst_port_start: Some(22),
+            enabled: true,
+            ..Default::default()
+        }];
+
+        let filter = host.create_nwfilter_xml(&cfg)?.expect("firewall enabled");
+        let xml = quick_xml::se::to_string(&filter)?;
+        assert!(xml.starts_with("<filter name=\"lnvps-vm-1\" chain=\"root\">"));
+        // only IPv4 addresses can be passed to clean-traffic
+        assert!(xml.contains("<filterref filter=\"clean-traffic\"><parameter name=\"IP\" value=\"192.168.1.2\"/><parameter name=\"IP\" value=\"192.168.2.2\"/></filterref>"));
+        assert!(xml.contains("<rule action=\"accept\" direction=\"in\" priority=\"-400\"><tcp srcipaddr=\"10.0.0.0\" srcipmask=\"8\" dstportstart=\"22\" dstportend=\"22\"/></rule>"));
+        assert!(
+            xml.contains("<rule action=\"drop\" direction=\"in\" priority=\"1000\"><all/></rule>")
+        );
+        assert!(!xml.contains("direction=\"out\" priority=\"1000\""));
+
+        // the interface references the filter when the firewall is enabled
+        let domain = quick_xml::se::to_string(&host.create_domain_xml(&cfg)?)?;
+        assert!(domain.contains("<filterref filter=\"lnvps-vm-1\"/>"));
         Ok(())
     }
 }
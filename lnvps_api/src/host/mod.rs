@@ -3,12 +3,14 @@ use anyhow::{Result, anyhow, bail};
 use async_trait::async_trait;
 use futures::future::join_all;
 use lnvps_api_common::HostVmSpec;
-use lnvps_api_common::VmRunningState;
-use lnvps_api_common::retry::OpResult;
+use lnvps_api_common::op_fatal;
+use lnvps_api_common::retry::{OpResult, RetryPolicy};
+use lnvps_api_common::{GuestInfo, VmRunningState};
 use lnvps_db::{
-    IpRange, LNVpsDb, UserSshKey, Vm, VmCustomTemplate, VmFirewallRule, VmHost, VmHostDisk,
-    VmHostKind, VmIpAssignment, VmOsImage, VmTemplate,
+    IpRange, LNVpsDb, UserSshKey, Vm, VmCustomTemplate, VmExtraDisk, VmFirewallRule, VmHost,
+    VmHostDisk, VmHostKind, VmIpAssignment, VmOsImage, VmTemplate,
 };
+use log::{info, warn};
 use serde::{Deserialize, Serialize};
 use std::collections::HashSet;
 use std::sync::Arc;
@@ -19,8 +21,11 @@ mod libvirt;
 #[cfg(feature = "proxmox")]
 mod proxmox;
 
+mod breaker;
 pub(crate) mod dummy_host;
 
+pub use breaker::{BreakerState, CircuitBreaker, host_breaker};
+
 pub struct TerminalStream {
     pub rx: Receiver<Vec<u8>>,
     pub tx: Sender<Vec<u8>>,
@@ -43,8 +48,8 @@ pub trait VmHostClient: Send + Sync {
         )))
     }
 
-    /// Download OS image to the host
-    async fn download_os_image(&self, image: &VmOsImage) -> OpResult<()>;
+    /// Download OS image to the host, retrying interrupted downloads with `retry`
+    async fn download_os_image(&self, image: &VmOsImage, retry: RetryPolicy) -> OpResult<()>;
 
     /// Create a random MAC address for the NIC
     async fn generate_mac(&self, vm: &Vm) -> OpResult<String>;
@@ -58,8 +63,28 @@ pub trait VmHostClient: Send + Sync {
     /// Reset VM (Hard)
     async fn reset_vm(&self, vm: &Vm) -> OpResult<()>;
 
-    /// Spawn a VM
-    async fn create_vm(&self, cfg: &FullVmInfo) -> OpResult<()>;
+    /// Reboot VM (ACPI), falls back to [VmHostClient::reset_vm] when the guest
+    /// doesn't shut down within the configured reboot timeout
+    async fn reboot_vm(&self, vm: &Vm) -> OpResult<()>;
+
+    /// Suspend a running VM, its memory stays on the host until resumed
+    async fn suspend_vm(&self, _vm: &Vm) -> OpResult<()> {
+        use lnvps_api_common::retry::OpError;
+        Err(OpError::Fatal(anyhow!(
+            "Suspending VMs is not supported on this host type"
+        )))
+    }
+
+    /// Resume a VM suspended by [VmHostClient::suspend_vm]
+    async fn resume_vm(&self, _vm: &Vm) -> OpResult<()> {
+        use lnvps_api_common::retry::OpError;
+        Err(OpError::Fatal(anyhow!(
+            "Suspending VMs is not supported on this host type"
+        )))
+    }
+
+    /// Spawn a VM, retrying each step of the create with `retry`
+    async fn create_vm(&self, cfg: &FullVmInfo, retry: RetryPolicy) -> OpResult<()>;
 
     /// Delete a VM
     async fn delete_vm(&self, vm: &Vm) -> OpResult<()>;
@@ -83,12 +108,124 @@ pub trait VmHostClient: Send + Sync {
     /// Resize the primary disk of a VM
     async fn resize_disk(&self, cfg: &FullVmInfo) -> OpResult<()>;
 
+    /// The VM runs as a container (LXC) instead of a full VM, so it takes
+    /// [lnvps_db::OsImageKind::ContainerTemplate] images and no disk images
+    async fn is_container(&self, _vm: &Vm) -> OpResult<bool> {
+        Ok(false)
+    }
+
+    /// Rebuild a container's root filesystem from `cfg.image`, keeping the
+    /// mounted data volumes. The container is stopped for the rebuild and
+    /// started again afterwards. Containers use this instead of
+    /// [VmHostClient::import_template_disk] to change their OS.
+    async fn recreate_container(&self, _cfg: &FullVmInfo) -> OpResult<()> {
+        use lnvps_api_common::retry::OpError;
+        Err(OpError::Fatal(anyhow!(
+            "Containers are not supported on this host type"
+        )))
+    }
+
+    /// Allocate a new data volume and attach it to an existing VM.
+    ///
+    /// Data disks listed in [FullVmInfo::data_disks] are attached by
+    /// [VmHostClient::create_vm] and re-attached by [VmHostClient::configure_vm]
+    /// if missing; this is only needed to add one to a VM that already exists.
+    async fn attach_data_disk(&self, _vm: &Vm, _disk: &DataDisk) -> OpResult<()> {
+        use lnvps_api_common::retry::OpError;
+        Err(OpError::Fatal(anyhow!(
+            "Data disks are not supported on this host type"
+        )))
+    }
+
+    /// Detach a data volume from a VM and destroy it.
+    ///
+    /// Detaching a disk that is not attached is a no-op.
+    async fn detach_data_disk(&self, _vm: &Vm, _disk: &DataDisk) -> OpResult<()> {
+        use lnvps_api_common::retry::OpError;
+        Err(OpError::Fatal(anyhow!(
+            "Data disks are not supported on this host type"
+        )))
+    }
+
+    /// Back up a VM to the host's backup storage
+    async fn backup_vm(&self, _vm: &Vm) -> OpResult<HostBackup> {
+        use lnvps_api_common::retry::OpError;
+        Err(OpError::Fatal(anyhow!(
+            "Backups are not supported on this host type"
+        )))
+    }
+
+    /// Delete a backup archive from the host's backup storage.
+    ///
+    /// Deleting a backup which no longer exists is a no-op.
+    async fn delete_backup(&self, _volume_id: &str) -> OpResult<()> {
+        use lnvps_api_common::retry::OpError;
+        Err(OpError::Fatal(anyhow!(
+            "Backups are not supported on this host type"
+        )))
+    }
+
+    /// Replace the disks and config of a stopped VM with a backup archive
+    async fn restore_backup(&self, _cfg: &FullVmInfo, _volume_id: &str) -> OpResult<()> {
+        use lnvps_api_common::retry::OpError;
+        Err(OpError::Fatal(anyhow!(
+            "Backups are not supported on this host type"
+        )))
+    }
+
+    /// Start live-migrating a running VM to `target`, moving its disks onto
+    /// `target_disk`. Returns a task id to poll with
+    /// [Self::migration_finished]
+    async fn start_migrate_vm(
+        &self,
+        _vm: &Vm,
+        _target: &VmHost,
+        _target_disk: &VmHostDisk,
+    ) -> OpResult<String> {
+        use lnvps_api_common::retry::OpError;
+        Err(OpError::Fatal(anyhow!(
+            "Live migration is not supported on this host type"
+        )))
+    }
+
+    /// If the migration `task` has completed, a failed migration is an error
+    async fn migration_finished(&self, _task: &str) -> OpResult<bool> {
+        use lnvps_api_common::retry::OpError;
+        Err(OpError::Fatal(anyhow!(
+            "Live migration is not supported on this host type"
+        )))
+    }
+
+    /// Apply a new CPU count and memory size (bytes) to a running VM without
+    /// restarting it
+    ///
+    /// Returns `false` when the change can't be made live, either because the
+    /// VM is not running or its config doesn't allow hot-plug, in which case
+    /// nothing was changed and the VM must be restarted to apply it.
+    async fn hotplug_resize(&self, _vm: &Vm, _cpu: u16, _memory: u64) -> OpResult<bool> {
+        Ok(false)
+    }
+
     /// Get the running status of a VM
     async fn get_vm_state(&self, vm: &Vm) -> OpResult<VmRunningState>;
 
     /// Get the running status of all VMs on this host
     async fn get_all_vm_states(&self) -> OpResult<Vec<(u64, VmRunningState)>>;
 
+    /// Get the network interfaces and uptime reported by the QEMU guest agent,
+    /// fails when the agent isn't running inside the VM
+    async fn get_guest_info(&self, vm: &Vm) -> OpResult<GuestInfo>;
+
+    /// Set a new password for the image's default login user and apply it
+    /// inside the guest, only returns once the password is in effect.
+    ///
+    /// The password must never be logged.
+    async fn reset_password(
+        &self,
+        cfg: &FullVmInfo,
+        password: &str,
+    ) -> OpResult<PasswordResetMethod>;
+
     /// Apply vm configuration (patch)
     async fn configure_vm(&self, cfg: &FullVmInfo) -> OpResult<()>;
 
@@ -104,6 +241,17 @@ pub trait VmHostClient: Send + Sync {
 
     /// Connect to terminal serial port
     async fn connect_terminal(&self, vm: &Vm) -> OpResult<TerminalStream>;
+
+    /// Capture the VM's display as a PNG image.
+    ///
+    /// Lets support staff see what an unreachable VM is showing (boot loop,
+    /// kernel panic, rescue prompt). Defaults to unsupported.
+    async fn get_console_screenshot(&self, _vm: &Vm) -> OpResult<Vec<u8>> {
+        use lnvps_api_common::retry::OpError;
+        Err(OpError::Fatal(anyhow!(
+            "Console screenshots are not supported on this host type"
+        )))
+    }
 }
 
 pub async fn get_vm_host_client(
@@ -117,19 +265,25 @@ pub async fn get_vm_host_client(
     Ok(client)
 }
 
+/// Create a client for the host, calls to real hosts go through the host's
+/// [CircuitBreaker] so a host which is down fails fast instead of stalling
+/// every job
 pub fn get_host_client(host: &VmHost, cfg: &ProvisionerConfig) -> Result<Arc<dyn VmHostClient>> {
-    Ok(match host.kind.clone() {
+    let client: Arc<dyn VmHostClient> = match host.kind.clone() {
         #[cfg(feature = "proxmox")]
         VmHostKind::Proxmox if cfg.proxmox.is_some() => {
             let cfg = cfg.proxmox.clone().unwrap();
-            Arc::new(proxmox::ProxmoxClient::new(
-                host.ip.parse()?,
-                &host.name,
-                host.api_token.as_str(),
-                cfg.mac_prefix,
-                cfg.qemu,
-                cfg.ssh,
-            ))
+            Arc::new(
+                proxmox::ProxmoxClient::new(
+                    host.ip.parse()?,
+                    &host.name,
+                    host.api_token.as_str(),
+                    cfg.mac_prefix,
+                    cfg.qemu,
+                    cfg.ssh,
+                )
+                .with_backup_storage(cfg.backup_storage),
+            )
         }
         #[cfg(feature = "libvirt")]
         VmHostKind::LibVirt if cfg.libvirt.is_some() => {
@@ -144,10 +298,103 @@ pub fn get_host_client(host: &VmHost, cfg: &ProvisionerConfig) -> Result<Arc<dyn
             }
         }
         _ => bail!("Unknown host config: {}", host.kind),
-    })
+    };
+    if host.kind == VmHostKind::Dummy {
+        Ok(client)
+    } else {
+        Ok(Arc::new(breaker::BreakerHostClient::new(host, client)))
+    }
+}
+
+/// If VMs on `source` can be live-migrated to `target`
+///
+/// Only Proxmox hosts support it, and only between nodes of the same cluster
+/// which can't be checked here, so a migration may still fail.
+pub fn can_migrate(source: &VmHost, target: &VmHost) -> bool {
+    source.id != target.id
+        && source.region_id == target.region_id
+        && source.kind == VmHostKind::Proxmox
+        && target.kind == VmHostKind::Proxmox
+}
+
+/// Create a VM on the host, safe to call again after a transient failure.
+///
+/// A create which failed transiently (e.g. timed out) may still have completed
+/// on the host, so when `retrying` the host is asked for the VM first. An
+/// existing VM is only treated as created when it matches this create request
+/// (MAC address, CPU and memory), a different VM under the same id fails the
+/// create instead of being adopted.
+pub async fn create_vm_idempotent(
+    client: &dyn VmHostClient,
+    cfg: &FullVmInfo,
+    retrying: bool,
+    retry: RetryPolicy,
+) -> OpResult<()> {
+    if retrying && client.get_vm_state(&cfg.vm).await.is_ok() {
+        match client.list_host_vms().await {
+            Ok(vms) => {
+                if let Some(spec) = vms.iter().find(|v| v.mapped_vm_id == Some(cfg.vm.id))
+                    && !matches_create_request(spec, cfg)?
+                {
+                    op_fatal!(
+                        "VM {} on the host doesn't match the create request (mac {:?}, {} cpu, {} memory)",
+                        cfg.vm.id,
+                        spec.mac_address,
+                        spec.cpu,
+                        spec.memory
+                    );
+                }
+            }
+            // hosts without discovery can only be asked whether the VM exists
+            Err(e) => warn!(
+                "Can't compare VM {} on the host with the create request: {}",
+                cfg.vm.id, e
+            ),
+        }
+        info!(
+            "VM {} already exists on the host after a failed create, not creating again",
+            cfg.vm.id
+        );
+        return Ok(());
+    }
+    client.create_vm(cfg, retry).await
+}
+
+/// Whether a VM found on the host is the one `cfg` asked for. Values the host
+/// doesn't report are not compared.
+fn matches_create_request(spec: &HostVmSpec, cfg: &FullVmInfo) -> Result<bool> {
+    let res = cfg.resources()?;
+    let mac = spec
+        .mac_address
+        .as_ref()
+        .is_none_or(|m| m.eq_ignore_ascii_case(&cfg.vm.mac_address));
+    let cpu = spec.cpu == 0 || spec.cpu == res.cpu;
+    let memory = spec.memory == 0 || spec.memory == res.memory;
+    Ok(mac && cpu && memory)
+}
+
+/// A backup archive created by [VmHostClient::backup_vm]
+#[derive(Clone, Debug)]
+pub struct HostBackup {
+    /// Host volume id of the archive
+    pub volume_id: String,
+    /// Size of the archive in bytes
+    pub size: u64,
+}
+
+/// How [VmHostClient::reset_password] got the new password into the guest
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PasswordResetMethod {
+    /// Set inside the running guest by the guest agent
+    GuestAgent,
+    /// The stopped VM was started to apply it
+    Started,
+    /// The VM was rebooted to apply it
+    Rebooted,
 }
 
 /// All VM info necessary to provision a VM and its associated resources
+#[derive(Clone)]
 pub struct FullVmInfo {
     /// Instance to create
     pub vm: Vm,
@@ -169,6 +416,22 @@ pub struct FullVmInfo {
     pub ssh_key: UserSshKey,
     /// User-configured firewall rules for this VM (ordered by priority)
     pub firewall_rules: Vec<VmFirewallRule>,
+    /// Extra data disks attached to this VM (ordered by slot)
+    pub data_disks: Vec<DataDisk>,
+}
+
+/// An extra data disk along with the host storage it lives on
+#[derive(Clone, Debug)]
+pub struct DataDisk {
+    pub disk: VmExtraDisk,
+    pub storage: VmHostDisk,
+}
+
+impl DataDisk {
+    pub async fn load(disk: VmExtraDisk, db: &Arc<dyn LNVpsDb>) -> Result<Self> {
+        let storage = db.get_host_disk(disk.disk_id).await?;
+        Ok(Self { disk, storage })
+    }
 }
 
 impl FullVmInfo {
@@ -202,6 +465,10 @@ impl FullVmInfo {
             None
         };
         let firewall_rules = db.list_vm_firewall_rules(vm_id).await?;
+        let mut data_disks = Vec::new();
+        for d in db.list_vm_extra_disks(vm_id).await? {
+            data_disks.push(DataDisk::load(d, &db).await?);
+        }
         // create VM
         Ok(FullVmInfo {
             vm,
@@ -214,6 +481,7 @@ impl FullVmInfo {
             ranges,
             ssh_key,
             firewall_rules,
+            data_disks,
         })
     }
 
@@ -344,14 +612,17 @@ pub struct VmHostDiskInfo {
 }
 
 #[cfg(test)]
-mod tests {
-    use crate::host::FullVmInfo;
+pub(crate) mod tests {
+    use crate::host::dummy_host::{DUMMY_SCREENSHOT_PNG, DummyVmHost};
+    use crate::host::{FullVmInfo, VmHostClient, create_vm_idempotent};
     use crate::{GB, TB};
     use chrono::Utc;
+    use lnvps_api_common::retry::{RetryPolicy, retry_async};
     use lnvps_db::{
         CpuArch, DiskInterface, DiskType, IpRange, IpRangeAllocationMode, OsDistribution,
         UserSshKey, Vm, VmHost, VmHostDisk, VmIpAssignment, VmOsImage, VmTemplate,
     };
+    use std::time::Duration;
 
     pub fn mock_full_vm() -> FullVmInfo {
         let template = VmTemplate {
@@ -390,6 +661,8 @@ mod tests {
                 fw_policy_in: None,
                 fw_policy_out: None,
                 admin_notes: None,
+                private_network: false,
+                suspended_at: None,
             },
             host: VmHost {
                 id: 1,
@@ -409,9 +682,12 @@ mod tests {
                 load_disk: 1.0,
                 vlan_id: Some(100),
                 mtu: None,
+                private_bridge: None,
                 ssh_user: None,
                 ssh_key: None,
                 sunset_date: None,
+                maintenance: false,
+                weight: 1,
             },
             disk: VmHostDisk {
                 id: 1,
@@ -436,6 +712,7 @@ mod tests {
                 default_username: None,
                 sha2: None,
                 sha2_url: None,
+                image_kind: lnvps_db::OsImageKind::CloudInit,
             },
             ips: vec![
                 VmIpAssignment {
@@ -449,6 +726,8 @@ mod tests {
                     dns_forward_ref: None,
                     dns_reverse: None,
                     dns_reverse_ref: None,
+                    prefix_length: None,
+                    route_ref: None,
                 },
                 VmIpAssignment {
                     id: 2,
@@ -461,6 +740,8 @@ mod tests {
                     dns_forward_ref: None,
                     dns_reverse: None,
                     dns_reverse_ref: None,
+                    prefix_length: None,
+                    route_ref: None,
                 },
                 VmIpAssignment {
                     id: 3,
@@ -473,6 +754,8 @@ mod tests {
                     dns_forward_ref: None,
                     dns_reverse: None,
                     dns_reverse_ref: None,
+                    prefix_length: None,
+                    route_ref: None,
                 },
             ],
             ranges: vec![
@@ -510,6 +793,68 @@ mod tests {
                 key_data: "ssh-ed25519 AAA=".into(),
             },
             firewall_rules: vec![],
+            data_disks: vec![],
         }
     }
+
+    #[tokio::test]
+    async fn test_dummy_console_screenshot_is_png() -> anyhow::Result<()> {
+        let client = DummyVmHost::new();
+        let png = client.get_console_screenshot(&mock_full_vm().vm).await?;
+        assert_eq!(png, DUMMY_SCREENSHOT_PNG);
+        assert!(png.starts_with(b"\x89PNG\r\n\x1a\n"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_create_vm_retry_does_not_duplicate() -> anyhow::Result<()> {
+        // the first create completes on the host but the caller sees a timeout
+        let client = DummyVmHost::new().with_create_timeouts(1);
+        let cfg = mock_full_vm();
+
+        let mut retrying = false;
+        retry_async(
+            RetryPolicy::default().with_min_delay(Duration::from_millis(1)),
+            || {
+                let r = retrying;
+                retrying = true;
+                create_vm_idempotent(&client, &cfg, r, RetryPolicy::default())
+            },
+        )
+        .await?;
+
+        assert_eq!(client.create_calls(), 1);
+        assert!(client.get_vm_state(&cfg.vm).await.is_ok());
+        Ok(())
+    }
+
+    /// A different VM holding the id on the host isn't adopted by a retry
+    #[tokio::test]
+    async fn test_create_vm_retry_rejects_other_vm() -> anyhow::Result<()> {
+        let client = DummyVmHost::new();
+        let mut other = mock_full_vm();
+        other.vm.mac_address = "ff:ff:ff:00:00:01".to_string();
+        client.create_vm(&other, RetryPolicy::default()).await?;
+
+        let cfg = mock_full_vm();
+        assert!(
+            create_vm_idempotent(&client, &cfg, true, RetryPolicy::default())
+                .await
+                .is_err()
+        );
+        assert_eq!(client.create_calls(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_create_vm_retry_creates_missing_vm() -> anyhow::Result<()> {
+        let client = DummyVmHost::new();
+        let cfg = mock_full_vm();
+
+        // a retry where the first attempt never reached the host still creates
+        create_vm_idempotent(&client, &cfg, true, RetryPolicy::default()).await?;
+        assert_eq!(client.create_calls(), 1);
+        assert!(client.get_vm_state(&cfg.vm).await.is_ok());
+        Ok(())
+    }
 }
@@ -1,25 +1,30 @@
 use crate::host::{
-    FullVmInfo, TerminalStream, TimeSeries, TimeSeriesData, VmHostClient, VmHostDiskInfo,
-    VmHostInfo,
+    DataDisk, FullVmInfo, HostBackup, PasswordResetMethod, TerminalStream, TimeSeries,
+    TimeSeriesData, VmHostClient, VmHostDiskInfo, VmHostInfo,
 };
 use crate::settings::{QemuConfig, SshConfig};
 use crate::ssh_client::SshClient;
 use anyhow::Result;
 use async_trait::async_trait;
+use base64::Engine;
+use base64::prelude::BASE64_STANDARD;
 use chrono::Utc;
 use ipnetwork::IpNetwork;
-use lnvps_api_common::HostVmSpec;
 use lnvps_api_common::JsonApi;
-use lnvps_api_common::retry::{OpError, OpResult, Pipeline, RetryPolicy};
-use lnvps_api_common::{VmRunningState, VmRunningStates, op_fatal, parse_gateway};
-use lnvps_db::{DiskType, IpRangeAllocationMode, Vm, VmOsImage};
+use lnvps_api_common::retry::{OpError, OpResult, Pipeline, RetryPolicy, retry_async};
+use lnvps_api_common::{GB, HostVmSpec};
+use lnvps_api_common::{
+    GuestFilesystem, GuestInfo, GuestInterface, NetworkProvisioner, VmRunningState,
+    VmRunningStates, op_fatal, parse_gateway,
+};
+use lnvps_db::{DiskInterface, DiskType, IpRangeAllocationMode, Vm, VmHost, VmHostDisk, VmOsImage};
 use log::{info, warn};
 use rand::random;
-use reqwest::{Method, Url};
+use reqwest::{Method, StatusCode, Url};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt::{Debug, Display, Formatter};
-use std::net::IpAddr;
+use std::net::{IpAddr, Ipv6Addr};
 use std::str::FromStr;
 use std::time::Duration;
 use tokio::time::sleep;
@@ -28,6 +33,17 @@ use tokio::time::sleep;
 /// they can be identified and re-synced without disturbing system rules.
 const USER_FW_MARKER: &str = "lnvps-fw";
 
+/// Comments tagging the `ipfilter-net0` entries we manage, per address family
+const IPSET_V4_COMMENT: &str = "VM IPv4 address";
+const IPSET_V6_COMMENT: &str = "VM IPv6 address";
+
+/// Number of cloud-init `ipconfigN` slots tracked in a VM config
+const MAX_IP_CONFIGS: usize = 4;
+
+/// Network devices created on a VM (`net0`), cloud-init only applies
+/// `ipconfigN` when the matching `netN` exists
+const VM_NICS: usize = 1;
+
 #[derive(Clone)]
 pub struct ProxmoxClient {
     api: JsonApi,
@@ -35,6 +51,7 @@ pub struct ProxmoxClient {
     ssh: Option<SshConfig>,
     mac_prefix: String,
     node: String,
+    backup_storage: Option<String>,
 }
 
 impl ProxmoxClient {
@@ -52,9 +69,16 @@ impl ProxmoxClient {
             ssh,
             node: node.to_string(),
             mac_prefix: mac_prefix.unwrap_or("bc:24:11".to_string()),
+            backup_storage: None,
         }
     }
 
+    /// Storage where VM backups are written
+    pub fn with_backup_storage(mut self, storage: Option<String>) -> Self {
+        self.backup_storage = storage;
+        self
+    }
+
     /// Get version info
     pub async fn version(&self) -> OpResult<VersionResponse> {
         let rsp: ResponseBase<VersionResponse> = self.api.get("/api2/json/version").await?;
@@ -99,10 +123,126 @@ impl ProxmoxClient {
         Ok(rsp.map(|r| r.data))
     }
 
+    /// List the guest's network interfaces via the QEMU guest agent
+    ///
+    /// https://pve.proxmox.com/pve-docs/api-viewer/?ref=public_apis#/nodes/{node}/qemu/{vmid}/agent/network-get-interfaces
+    pub async fn get_guest_interfaces(
+        &self,
+        node: &str,
+        vm_id: ProxmoxVmId,
+    ) -> OpResult<Vec<AgentNetworkInterface>> {
+        let rsp: ResponseBase<AgentResult<Vec<AgentNetworkInterface>>> = self
+            .api
+            .get(&format!(
+                "/api2/json/nodes/{}/qemu/{}/agent/network-get-interfaces",
+                node, vm_id
+            ))
+            .await?;
+        Ok(rsp.data.result)
+    }
+
+    /// List the guest's mounted filesystems via the QEMU guest agent
+    ///
+    /// https://pve.proxmox.com/pve-docs/api-viewer/?ref=public_apis#/nodes/{node}/qemu/{vmid}/agent/get-fsinfo
+    pub async fn get_guest_fsinfo(
+        &self,
+        node: &str,
+        vm_id: ProxmoxVmId,
+    ) -> OpResult<Vec<AgentFsInfo>> {
+        let rsp: ResponseBase<AgentResult<Vec<AgentFsInfo>>> = self
+            .api
+            .get(&format!(
+                "/api2/json/nodes/{}/qemu/{}/agent/get-fsinfo",
+                node, vm_id
+            ))
+            .await?;
+        Ok(rsp.data.result)
+    }
+
+    /// Set the cloud-init password (`cipassword`) of a VM
+    ///
+    /// https://pve.proxmox.com/pve-docs/api-viewer/?ref=public_apis#/nodes/{node}/qemu/{vmid}/config
+    pub async fn set_cloud_init_password(
+        &self,
+        node: &str,
+        vm_id: ProxmoxVmId,
+        password: &str,
+    ) -> OpResult<Option<TaskId>> {
+        let mut body = HashMap::new();
+        body.insert("cipassword", password);
+        let rsp: ResponseBase<Option<String>> = self
+            .api
+            .post_sensitive(
+                &format!("/api2/json/nodes/{}/qemu/{}/config", node, vm_id),
+                &body,
+            )
+            .await?;
+        Ok(rsp.data.map(|id| TaskId {
+            id,
+            node: node.to_string(),
+        }))
+    }
+
+    /// Set a user's password inside the guest via the QEMU guest agent
+    ///
+    /// https://pve.proxmox.com/pve-docs/api-viewer/?ref=public_apis#/nodes/{node}/qemu/{vmid}/agent/set-user-password
+    pub async fn agent_set_user_password(
+        &self,
+        node: &str,
+        vm_id: ProxmoxVmId,
+        username: &str,
+        password: &str,
+    ) -> OpResult<()> {
+        let mut body = HashMap::new();
+        body.insert("username", username);
+        body.insert("password", password);
+        let _: ResponseBase<Option<serde_json::Value>> = self
+            .api
+            .post_sensitive(
+                &format!(
+                    "/api2/json/nodes/{}/qemu/{}/agent/set-user-password",
+                    node, vm_id
+                ),
+                &body,
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Set a user's password via the guest agent of a VM which may still be
+    /// booting, retrying until the agent answers or `timeout` has passed
+    async fn agent_set_user_password_after_boot(
+        &self,
+        vm_id: ProxmoxVmId,
+        username: &str,
+        password: &str,
+        timeout: Duration,
+    ) -> OpResult<()> {
+        let start = std::time::Instant::now();
+        loop {
+            match self
+                .agent_set_user_password(&self.node, vm_id, username, password)
+                .await
+            {
+                Ok(()) => return Ok(()),
+                Err(e) if start.elapsed() >= timeout => {
+                    return Err(OpError::Transient(anyhow::anyhow!(
+                        "Guest agent of VM {} did not answer within {}s: {}",
+                        vm_id.inner(),
+                        timeout.as_secs(),
+                        e
+                    )));
+                }
+                Err(_) => sleep(Duration::from_secs(5)).await,
+            }
+        }
+    }
+
     pub async fn list_vms(&self, node: &str) -> OpResult<Vec<VmInfo>> {
         let rsp: ResponseBase<Vec<VmInfo>> = self
             .api
-            .get(&format!("/api2/json/nodes/{node}/qemu"))
+            // full so running VMs include qmpstatus, needed to tell suspended VMs apart
+            .get(&format!("/api2/json/nodes/{node}/qemu?full=1"))
             .await?;
         Ok(rsp.data)
     }
@@ -321,6 +461,40 @@ impl ProxmoxClient {
         }
     }
 
+    /// Make sure the container template `image` is on a template storage of
+    /// the node, returning its volume id (e.g. `local:vztmpl/debian-12.tar.zst`)
+    async fn ensure_container_template(&self, image: &VmOsImage) -> OpResult<String> {
+        let storages = self.list_storage(&self.node).await?;
+        let Some(storage) = storages
+            .iter()
+            .find(|s| s.contents().contains(&StorageContent::VZTmpL))
+        else {
+            op_fatal!("No container template storage found");
+        };
+        let filename = image.url_filename()?;
+        let vol_id = format!("{}:vztmpl/{}", storage.storage, filename);
+        let files = self
+            .list_storage_files(&self.node, &storage.storage)
+            .await?;
+        if !files.iter().any(|f| f.vol_id == vol_id) {
+            info!(
+                "Downloading container template {} on {}",
+                image.url, &self.node
+            );
+            let task = self
+                .download_url(DownloadUrlRequest {
+                    node: self.node.clone(),
+                    storage: storage.storage.clone(),
+                    content: StorageContent::VZTmpL,
+                    filename,
+                    url: image.url.clone(),
+                })
+                .await?;
+            self.wait_for_task(&task).await?;
+        }
+        Ok(vol_id)
+    }
+
     async fn get_iso_storage(&self, node: &str) -> OpResult<String> {
         let storages = self.list_storage(node).await?;
         if let Some(s) = storages
@@ -553,6 +727,22 @@ impl ProxmoxClient {
         })
     }
 
+    /// Migrate a VM to another node of the cluster
+    pub async fn migrate_vm(&self, req: MigrateVm) -> OpResult<TaskId> {
+        let rsp: ResponseBase<String> = self
+            .api
+            .post(
+                &format!("/api2/json/nodes/{}/qemu/{}/migrate", req.node, req.vm_id),
+                &req,
+            )
+            .await?;
+
+        Ok(TaskId {
+            id: rsp.data,
+            node: req.node,
+        })
+    }
+
     /// Stop a VM
     pub async fn shutdown_vm(&self, node: &str, vm: ProxmoxVmId) -> OpResult<TaskId> {
         let api = &self.api;
@@ -571,6 +761,64 @@ impl ProxmoxClient {
         })
     }
 
+    /// Gracefully reboot a VM (ACPI shutdown, then start again)
+    ///
+    /// The task fails if the guest hasn't shut down after `timeout` seconds
+    pub async fn reboot_vm(&self, node: &str, vm: ProxmoxVmId, timeout: u64) -> OpResult<TaskId> {
+        let api = &self.api;
+        let node_str = node.to_string();
+
+        let mut body = HashMap::new();
+        body.insert("timeout", timeout);
+        let rsp: ResponseBase<String> = api
+            .post(
+                &format!("/api2/json/nodes/{}/qemu/{}/status/reboot", node_str, vm),
+                &body,
+            )
+            .await?;
+
+        Ok(TaskId {
+            id: rsp.data,
+            node: node_str,
+        })
+    }
+
+    /// Suspend a VM to RAM (pause), the QEMU process keeps running
+    pub async fn suspend_vm(&self, node: &str, vm: ProxmoxVmId) -> OpResult<TaskId> {
+        let api = &self.api;
+        let node_str = node.to_string();
+
+        let rsp: ResponseBase<String> = api
+            .post(
+                &format!("/api2/json/nodes/{}/qemu/{}/status/suspend", node_str, vm),
+                (),
+            )
+            .await?;
+
+        Ok(TaskId {
+            id: rsp.data,
+            node: node_str,
+        })
+    }
+
+    /// Resume a suspended VM
+    pub async fn resume_vm(&self, node: &str, vm: ProxmoxVmId) -> OpResult<TaskId> {
+        let api = &self.api;
+        let node_str = node.to_string();
+
+        let rsp: ResponseBase<String> = api
+            .post(
+                &format!("/api2/json/nodes/{}/qemu/{}/status/resume", node_str, vm),
+                (),
+            )
+            .await?;
+
+        Ok(TaskId {
+            id: rsp.data,
+            node: node_str,
+        })
+    }
+
     /// Stop a VM
     pub async fn reset_vm(&self, node: &str, vm: ProxmoxVmId) -> OpResult<TaskId> {
         let api = &self.api;
@@ -613,6 +861,90 @@ impl ProxmoxClient {
         Ok(())
     }
 
+    /// Allocate a new volume and attach it to a VM under `slot` (e.g. `scsi2`).
+    ///
+    /// `volume` uses the Proxmox `{storage}:{size_gb}[,opts]` syntax, which
+    /// allocates a fresh volume of that size on the storage.
+    ///
+    /// https://pve.proxmox.com/pve-docs/api-viewer/?ref=public_apis#/nodes/{node}/qemu/{vmid}/config
+    pub async fn attach_disk(
+        &self,
+        node: &str,
+        vm: ProxmoxVmId,
+        slot: &str,
+        volume: &str,
+    ) -> OpResult<()> {
+        let mut body = HashMap::new();
+        body.insert(slot.to_string(), volume.to_string());
+        let rsp: ResponseBase<Option<String>> = self
+            .api
+            .post(
+                &format!("/api2/json/nodes/{}/qemu/{}/config", node, vm),
+                &body,
+            )
+            .await?;
+        if let Some(id) = rsp.data {
+            self.wait_for_task(&TaskId {
+                id,
+                node: node.to_string(),
+            })
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// Run a QEMU human monitor (HMP) command on a VM and return its output.
+    ///
+    /// https://pve.proxmox.com/pve-docs/api-viewer/?ref=public_apis#/nodes/{node}/qemu/{vmid}/monitor
+    pub async fn monitor_command(
+        &self,
+        node: &str,
+        vm: ProxmoxVmId,
+        command: &str,
+    ) -> OpResult<String> {
+        let mut body = HashMap::new();
+        body.insert("command", command);
+        let rsp: ResponseBase<String> = self
+            .api
+            .post(
+                &format!("/api2/json/nodes/{}/qemu/{}/monitor", node, vm),
+                &body,
+            )
+            .await?;
+        Ok(rsp.data)
+    }
+
+    /// Capture a PNG of a VM's display.
+    ///
+    /// Proxmox has no API that returns the framebuffer, so QEMU is asked to
+    /// `screendump` to a temp file on the node (via the monitor endpoint),
+    /// which is then read back and removed over SSH.
+    pub async fn screendump_vm(&self, node: &str, vm: ProxmoxVmId) -> OpResult<Vec<u8>> {
+        if self.ssh.is_none() {
+            op_fatal!("SSH config required for console screenshots");
+        }
+        let path = format!("/tmp/lnvps-screendump-{}.png", vm);
+        let out = self
+            .monitor_command(node, vm, &format!("screendump {} -f png", path))
+            .await?;
+        if !out.trim().is_empty() {
+            op_fatal!("screendump failed: {}", out.trim());
+        }
+
+        let (code, data) = self
+            .ssh_run(format!(
+                "base64 -w0 '{path}'; rc=$?; rm -f '{path}'; exit $rc"
+            ))
+            .await
+            .map_err(OpError::Transient)?;
+        if code != 0 {
+            op_fatal!("Failed to read screendump (exit {}): {}", code, data.trim());
+        }
+        BASE64_STANDARD
+            .decode(data.trim())
+            .map_err(|e| OpError::Fatal(e.into()))
+    }
+
     /// Fetch the raw VM config as a key/value map.
     ///
     /// Unlike [`get_vm_config`], this preserves dynamically-named keys such as
@@ -629,6 +961,83 @@ impl ProxmoxClient {
         Ok(rsp.data)
     }
 
+    /// List the containers (LXC) on a node
+    ///
+    /// https://pve.proxmox.com/pve-docs/api-viewer/?ref=public_apis#/nodes/{node}/lxc
+    pub async fn list_containers(&self, node: &str) -> OpResult<Vec<VmInfo>> {
+        let rsp: ResponseBase<Vec<VmInfo>> = self
+            .api
+            .get(&format!("/api2/json/nodes/{node}/lxc"))
+            .await?;
+        Ok(rsp.data)
+    }
+
+    /// Fetch the raw container config as a key/value map
+    ///
+    /// https://pve.proxmox.com/pve-docs/api-viewer/?ref=public_apis#/nodes/{node}/lxc/{vmid}/config
+    pub async fn get_container_config_raw(
+        &self,
+        node: &str,
+        vm: ProxmoxVmId,
+    ) -> OpResult<HashMap<String, serde_json::Value>> {
+        let rsp: ResponseBase<HashMap<String, serde_json::Value>> = self
+            .api
+            .get(&format!("/api2/json/nodes/{}/lxc/{}/config", node, vm))
+            .await?;
+        Ok(rsp.data)
+    }
+
+    /// Create a container, replacing an existing container of the same id
+    /// when `force` is set
+    ///
+    /// https://pve.proxmox.com/pve-docs/api-viewer/?ref=public_apis#/nodes/{node}/lxc
+    pub async fn create_container(&self, req: CreateContainer) -> OpResult<TaskId> {
+        let rsp: ResponseBase<Option<String>> = self
+            .api
+            .post(&format!("/api2/json/nodes/{}/lxc", req.node), &req)
+            .await?;
+        if let Some(id) = rsp.data {
+            Ok(TaskId { id, node: req.node })
+        } else {
+            op_fatal!("Failed to create container")
+        }
+    }
+
+    /// Stop a container
+    pub async fn stop_container(&self, node: &str, vm: ProxmoxVmId) -> OpResult<TaskId> {
+        let rsp: ResponseBase<String> = self
+            .api
+            .post(
+                &format!("/api2/json/nodes/{}/lxc/{}/status/stop", node, vm),
+                (),
+            )
+            .await?;
+        Ok(TaskId {
+            id: rsp.data,
+            node: node.to_string(),
+        })
+    }
+
+    /// Download a file from a URL into a storage pool
+    ///
+    /// https://pve.proxmox.com/pve-docs/api-viewer/?ref=public_apis#/nodes/{node}/storage/{storage}/download-url
+    pub async fn download_url(&self, req: DownloadUrlRequest) -> OpResult<TaskId> {
+        let rsp: ResponseBase<String> = self
+            .api
+            .post(
+                &format!(
+                    "/api2/json/nodes/{}/storage/{}/download-url",
+                    req.node, req.storage
+                ),
+                &req,
+            )
+            .await?;
+        Ok(TaskId {
+            id: rsp.data,
+            node: req.node,
+        })
+    }
+
     /// Force-remove the primary disk (`scsi0`) and any orphaned `unused[n]`
     /// disks from a VM.
     ///
@@ -976,77 +1385,188 @@ impl ProxmoxClient {
             .collect()
     }
 
-    fn make_config(&self, value: &FullVmInfo, vendor_snippet: Option<&str>) -> Result<VmConfig> {
-        let ip_config = value
-            .ips
-            .iter()
-            .filter_map(|ip| {
-                if let Ok(addr) = ip.ip.parse::<IpAddr>() {
-                    Some(match addr {
-                        IpAddr::V4(_) => {
-                            let ip_range = value.ranges.iter().find(|r| r.id == ip.ip_range_id)?;
-                            let range: IpNetwork = ip_range.cidr.parse().ok()?;
-                            let range_gw: IpNetwork = parse_gateway(&ip_range.gateway).ok()?;
-                            let prefix = range.prefix().min(range_gw.prefix());
-                            format!(
-                                "ip={},gw={}",
-                                IpNetwork::new(addr, prefix).ok()?,
-                                range_gw.ip()
-                            )
-                        }
-                        IpAddr::V6(_) => {
-                            let ip_range = value.ranges.iter().find(|r| r.id == ip.ip_range_id)?;
-                            if matches!(ip_range.allocation_mode, IpRangeAllocationMode::SlaacEui64)
-                            {
-                                // just ignore what's in the db and use whatever the host wants
-                                // what's in the db is purely informational
-                                "ip6=auto".to_string()
-                            } else {
-                                let range: IpNetwork = ip_range.cidr.parse().ok()?;
-                                let range_gw: IpNetwork = parse_gateway(&ip_range.gateway).ok()?;
-                                let prefix = range.prefix().min(range_gw.prefix());
-                                format!(
-                                    "ip6={},gw6={}",
-                                    IpNetwork::new(addr, prefix).ok()?,
-                                    range_gw.ip(),
-                                )
-                            }
-                        }
-                    })
-                } else {
-                    None
-                }
-            })
-            .collect::<Vec<_>>();
-
-        let mut net = vec![
-            format!("virtio={}", value.vm.mac_address),
-            format!("bridge={}", self.config.bridge),
-            "firewall=1".to_string(), //always enable on interface
-        ];
-        if let Some(t) = value.host.vlan_id {
-            net.push(format!("tag={}", t));
-        }
-        if let Some(mtu) = value.host.mtu {
-            net.push(format!("mtu={}", mtu));
-        }
-        if value.vm.disabled {
-            net.push("link_down=1".to_string());
-        }
-        let limits = value.limits();
-        if let Some(mbps) = limits.network_mbps {
-            // Proxmox rate= is in MB/s; our field is stored in Mbit/s
-            net.push(format!("rate={}", mbps as f32 / 8.0));
-        }
-
-        let cicustom = vendor_snippet.map(|vol_ref| format!("vendor={vol_ref}"));
-
-        let vm_resources = value.resources()?;
-        Ok(VmConfig {
+    /// Build the cloud-init `ipconfigN` values for a VM's IP assignments.
+    ///
+    /// Proxmox accepts a single `ip=` and a single `ip6=` per `ipconfigN`, so
+    /// addresses are packed in order: the first IPv4 and first IPv6 share
+    /// `ipconfig0`, the next pair goes to `ipconfig1` and so on. A VM with
+    /// more addresses of one family than it has NICs is rejected rather than
+    /// provisioned with addresses missing. Addresses use the range prefix, or the gateway's
+    /// prefix when the gateway sits outside a narrower allocation range.
+    /// Gateways are always written as a bare address, and an address whose
+    /// gateway is not on-link is skipped.
+    fn make_ip_configs(value: &FullVmInfo) -> Result<Vec<String>> {
+        let mut v4 = Vec::new();
+        let mut v6 = Vec::new();
+        for ip in &value.ips {
+            let Ok(addr) = ip.ip.parse::<IpAddr>() else {
+                continue;
+            };
+            let Some(ip_range) = value.ranges.iter().find(|r| r.id == ip.ip_range_id) else {
+                continue;
+            };
+            if addr.is_ipv6()
+                && matches!(ip_range.allocation_mode, IpRangeAllocationMode::SlaacEui64)
+            {
+                // just ignore what's in the db and use whatever the host wants
+                // what's in the db is purely informational
+                v6.push("ip6=auto".to_string());
+                continue;
+            }
+            let Ok(range) = ip_range.cidr.parse::<IpNetwork>() else {
+                continue;
+            };
+            let Ok(range_gw) = parse_gateway(&ip_range.gateway) else {
+                warn!(
+                    "Invalid gateway {} on range {}, skipping {}",
+                    ip_range.gateway, ip_range.id, ip.ip
+                );
+                continue;
+            };
+            if range_gw.is_ipv4() != addr.is_ipv4() {
+                warn!(
+                    "Gateway {} on range {} does not match address family of {}",
+                    ip_range.gateway, ip_range.id, ip.ip
+                );
+                continue;
+            }
+            if let (Some(prefix_length), IpAddr::V6(net)) = (ip.prefix_length, addr) {
+                // the prefix is routed to the VM's link-local address, it takes the
+                // first address for itself and reaches the (link-local) gateway on-link
+                let first = Ipv6Addr::from(u128::from(net) + 1);
+                v6.push(format!(
+                    "ip6={}/{},gw6={}",
+                    first,
+                    prefix_length,
+                    range_gw.ip()
+                ));
+                continue;
+            }
+            // the gateway must be on-link: inside the range, or inside the wider
+            // subnet the gateway is declared with (e.g. a /26 allocation out of
+            // a /24 with the gateway at .1). IPv6 link-local gateways are always
+            // on-link.
+            let link_local = matches!(range_gw.ip(), IpAddr::V6(gw) if gw.is_unicast_link_local());
+            let prefix = if range.contains(range_gw.ip()) || link_local {
+                range.prefix()
+            } else if range_gw.prefix() < range.prefix() && range_gw.contains(addr) {
+                range_gw.prefix()
+            } else {
+                warn!(
+                    "Gateway {} on range {} is not on-link for {}, skipping",
+                    ip_range.gateway, ip_range.id, ip.ip
+                );
+                continue;
+            };
+            let Ok(net) = IpNetwork::new(addr, prefix) else {
+                continue;
+            };
+            if addr.is_ipv4() {
+                v4.push(format!("ip={},gw={}", net, range_gw.ip()));
+            } else {
+                v6.push(format!("ip6={},gw6={}", net, range_gw.ip()));
+            }
+        }
+
+        let slots = v4.len().max(v6.len());
+        if slots > VM_NICS {
+            anyhow::bail!(
+                "VM {} has {} IPv4 and {} IPv6 addresses but only {} NIC(s) to configure them on",
+                value.vm.id,
+                v4.len(),
+                v6.len(),
+                VM_NICS
+            );
+        }
+        let mut v4 = v4.into_iter();
+        let mut v6 = v6.into_iter();
+        Ok((0..slots)
+            .map(|_| {
+                v4.next()
+                    .into_iter()
+                    .chain(v6.next())
+                    .collect::<Vec<_>>()
+                    .join(",")
+            })
+            .collect())
+    }
+
+    /// `ipfilter-net0` IPset entries for every address assigned to the VM,
+    /// IPv4 and IPv6 alike
+    fn ipset_entries(cfg: &FullVmInfo) -> Vec<CreateVmIpsetEntryRequest> {
+        cfg.ips
+            .iter()
+            .filter_map(|ip| {
+                let net = NetworkProvisioner::assignment_network(ip)?;
+                Some(CreateVmIpsetEntryRequest {
+                    // single addresses are written bare, delegated prefixes as a CIDR
+                    cidr: if ip.is_prefix() {
+                        net.to_string()
+                    } else {
+                        net.ip().to_string()
+                    },
+                    comment: Some(
+                        if net.is_ipv4() {
+                            IPSET_V4_COMMENT
+                        } else {
+                            IPSET_V6_COMMENT
+                        }
+                        .to_string(),
+                    ),
+                    nomatch: None,
+                })
+            })
+            .collect()
+    }
+
+    fn make_config(&self, value: &FullVmInfo, vendor_snippet: Option<&str>) -> Result<VmConfig> {
+        let mut ip_config = Self::make_ip_configs(value)?.into_iter();
+
+        // private network VMs go on the host's bridge with isolated ports, so
+        // they can only talk to the uplink and not to other VMs at L2
+        let bridge = if value.vm.private_network {
+            match value.host.private_bridge.as_ref() {
+                Some(b) => b,
+                None => anyhow::bail!(
+                    "VM {} is on a private network but host {} has no private bridge",
+                    value.vm.id,
+                    value.host.name
+                ),
+            }
+        } else {
+            &self.config.bridge
+        };
+        let mut net = vec![
+            format!("virtio={}", value.vm.mac_address),
+            format!("bridge={}", bridge),
+            "firewall=1".to_string(), //always enable on interface
+        ];
+        if let Some(t) = value.host.vlan_id {
+            net.push(format!("tag={}", t));
+        }
+        if let Some(mtu) = value.host.mtu {
+            net.push(format!("mtu={}", mtu));
+        }
+        if value.vm.disabled {
+            net.push("link_down=1".to_string());
+        }
+        let limits = value.limits();
+        if let Some(mbps) = limits.network_mbps {
+            // Proxmox rate= is in MB/s; our field is stored in Mbit/s
+            net.push(format!("rate={}", mbps as f32 / 8.0));
+        }
+
+        let cicustom = vendor_snippet.map(|vol_ref| format!("vendor={vol_ref}"));
+
+        let vm_resources = value.resources()?;
+        Ok(VmConfig {
             name: Some(format!("VM{}", value.vm.id)), // set name to DB name
             cpu: Some(self.config.cpu.clone()),
             kvm: Some(self.config.kvm),
-            ip_config: Some(ip_config.join(",")),
+            ip_config: ip_config.next(),
+            ip_config_1: ip_config.next(),
+            ip_config_2: ip_config.next(),
+            ip_config_3: ip_config.next(),
             machine: Some(self.config.machine.clone()),
             net: Some(net.join(",")),
             os_type: Some(self.config.os_type.clone()),
@@ -1121,6 +1641,7 @@ impl ProxmoxClient {
             current: None,
             snapshot: None,
             digest: None,
+            delete: None,
             config: VmConfig {
                 scsi_0: Some(parts.join(",")),
                 ..Default::default()
@@ -1292,9 +1813,9 @@ impl VmHostClient for ProxmoxClient {
                 None
             };
 
-            // Pull the live config for MAC + backing storage; tolerate failures
-            // so a single unreadable VM doesn't abort discovery.
-            let (mac_address, disk_storage) =
+            // Pull the live config for MAC, IPs + backing storage; tolerate
+            // failures so a single unreadable VM doesn't abort discovery.
+            let (mac_address, disk_storage, ip_addresses) =
                 match self.get_vm_config(&self.node, vm.vm_id.into()).await {
                     Ok(cfg) => (
                         cfg.config.net.as_deref().and_then(parse_mac_from_net),
@@ -1302,10 +1823,16 @@ impl VmHostClient for ProxmoxClient {
                             .scsi_0
                             .as_deref()
                             .and_then(parse_storage_from_disk),
+                        cfg.config
+                            .ip_configs()
+                            .iter()
+                            .filter_map(|c| c.as_deref())
+                            .flat_map(parse_ips_from_ip_config)
+                            .collect(),
                     ),
                     Err(e) => {
                         warn!("Failed to read config for vm {}: {}", vm.vm_id, e);
-                        (None, None)
+                        (None, None, Vec::new())
                     }
                 };
 
@@ -1318,13 +1845,14 @@ impl VmHostClient for ProxmoxClient {
                 disk_size: vm.max_disk.unwrap_or(0),
                 disk_storage,
                 mac_address,
+                ip_addresses,
                 running: matches!(vm.status, VmStatus::Running),
             });
         }
         Ok(out)
     }
 
-    async fn download_os_image(&self, image: &VmOsImage) -> OpResult<()> {
+    async fn download_os_image(&self, image: &VmOsImage, retry: RetryPolicy) -> OpResult<()> {
         let iso_storage = self.get_iso_storage(&self.node).await?;
         let files = self.list_storage_files(&self.node, &iso_storage).await?;
 
@@ -1446,10 +1974,23 @@ impl VmHostClient for ProxmoxClient {
         // redirects, and its built-in checksum verification has proven broken.
         // wget/curl follow redirects natively; integrity is verified via SSH
         // below (see verify_image_checksum) and compressed images are decompressed
-        // by us afterwards.
-        self.download_image_ssh(&image.url, &download_name)
-            .await
-            .map_err(OpError::Fatal)?;
+        // by us afterwards. Interrupted downloads are resumed on the next attempt.
+        retry_async(retry, || async {
+            let size = match probe_image(&image.url).await {
+                Ok(size) => size,
+                // the probe runs from here, not on the host, which may still
+                // be able to reach the image
+                Err(OpError::Transient(e)) => {
+                    warn!("{}, downloading without a size check", e);
+                    None
+                }
+                Err(e) => return Err(e),
+            };
+            self.download_image_ssh(&image.url, &download_name, size)
+                .await
+        })
+        .await
+        .map_err(OpError::Fatal)?;
 
         // Verify the freshly-downloaded file via SSH to confirm integrity. This
         // runs against download_name (the compressed artifact for compressed
@@ -1541,7 +2082,92 @@ impl VmHostClient for ProxmoxClient {
         Ok(())
     }
 
-    async fn create_vm(&self, req: &FullVmInfo) -> OpResult<()> {
+    async fn reboot_vm(&self, vm: &Vm) -> OpResult<()> {
+        let task = self
+            .reboot_vm(&self.node, vm.id.into(), self.config.reboot_timeout())
+            .await?;
+        if let Err(e) = self.wait_for_task(&task).await {
+            warn!("Graceful reboot of VM {} failed, resetting: {}", vm.id, e);
+            let task = self.reset_vm(&self.node, vm.id.into()).await?;
+            self.wait_for_task(&task).await?;
+        }
+        Ok(())
+    }
+
+    async fn suspend_vm(&self, vm: &Vm) -> OpResult<()> {
+        let task = self.suspend_vm(&self.node, vm.id.into()).await?;
+        self.wait_for_task(&task).await?;
+        Ok(())
+    }
+
+    async fn resume_vm(&self, vm: &Vm) -> OpResult<()> {
+        let task = self.resume_vm(&self.node, vm.id.into()).await?;
+        self.wait_for_task(&task).await?;
+        Ok(())
+    }
+
+    async fn start_migrate_vm(
+        &self,
+        vm: &Vm,
+        target: &VmHost,
+        target_disk: &VmHostDisk,
+    ) -> OpResult<String> {
+        let task = self
+            .migrate_vm(MigrateVm {
+                node: self.node.clone(),
+                vm_id: vm.id.into(),
+                target: target.name.clone(),
+                online: true,
+                with_local_disks: true,
+                target_storage: Some(target_disk.name.clone()),
+            })
+            .await?;
+        Ok(task.id)
+    }
+
+    async fn migration_finished(&self, task: &str) -> OpResult<bool> {
+        let s = self
+            .get_task_status(&TaskId {
+                id: task.to_string(),
+                node: self.node.clone(),
+            })
+            .await?;
+        if !s.is_finished() {
+            return Ok(false);
+        }
+        if !s.is_success() {
+            op_fatal!(
+                "Migration finished with error: {}",
+                s.exit_status.unwrap_or("no error message".to_string())
+            );
+        }
+        Ok(true)
+    }
+
+    async fn hotplug_resize(&self, vm: &Vm, cpu: u16, memory: u64) -> OpResult<bool> {
+        let vm_id: ProxmoxVmId = vm.id.into();
+        let status = self.get_vm_status(&self.node, vm_id).await?;
+        if status.status != VmStatus::Running {
+            return Ok(false);
+        }
+        let current = self.get_vm_config(&self.node, vm_id).await?;
+        let Some(config) = current.config.hotplug_config(cpu, memory / crate::MB) else {
+            return Ok(false);
+        };
+        let task = self
+            .configure_vm(ConfigureVm {
+                node: self.node.clone(),
+                vm_id,
+                digest: Some(current.digest),
+                config,
+                ..Default::default()
+            })
+            .await?;
+        self.wait_for_task(&task).await?;
+        Ok(true)
+    }
+
+    async fn create_vm(&self, req: &FullVmInfo, retry: RetryPolicy) -> OpResult<()> {
         let vendor_snippet = self.ensure_vendor_snippet().await?;
         let config = self.make_config(req, vendor_snippet.as_deref())?;
         let vm_id: ProxmoxVmId = req.vm.id.into();
@@ -1554,11 +2180,7 @@ impl VmHostClient for ProxmoxClient {
         };
 
         Pipeline::new(ctx)
-            .with_retry_policy(
-                RetryPolicy::default()
-                    .with_min_delay(Duration::from_secs(3))
-                    .with_max_delay(Duration::from_secs(60)),
-            )
+            .with_retry_policy(retry)
             .step_with_rollback(
                 "create_vm_shell",
                 |ctx| {
@@ -1588,6 +2210,39 @@ impl VmHostClient for ProxmoxClient {
             .step("resize_disk", |ctx| {
                 Box::pin(async move { ctx.client.resize_main_disk(ctx.req).await })
             })
+            .step_with_rollback(
+                "attach_data_disks",
+                |ctx| {
+                    Box::pin(async move {
+                        for disk in &ctx.req.data_disks {
+                            ctx.client.attach_data_disk(&ctx.req.vm, disk).await?;
+                        }
+                        Ok(())
+                    })
+                },
+                |ctx| {
+                    Box::pin(async move {
+                        // detach skips slots that were never attached, so this also
+                        // covers a partial attach
+                        for disk in ctx.req.data_disks.iter().rev() {
+                            info!(
+                                "Rolling back: detaching data disk {} from VM {}",
+                                data_disk_slot(disk),
+                                ctx.vm_id
+                            );
+                            if let Err(e) = ctx.client.detach_data_disk(&ctx.req.vm, disk).await {
+                                warn!(
+                                    "Failed to detach data disk {} from VM {}: {}",
+                                    data_disk_slot(disk),
+                                    ctx.vm_id,
+                                    e
+                                );
+                            }
+                        }
+                        Ok(())
+                    })
+                },
+            )
             .step("patch_firewall", |ctx| {
                 Box::pin(async move { ctx.client.patch_firewall(ctx.req).await })
             })
@@ -1627,6 +2282,58 @@ impl VmHostClient for ProxmoxClient {
         self.import_template_disk(req).await
     }
 
+    async fn is_container(&self, vm: &Vm) -> OpResult<bool> {
+        Ok(self
+            .list_containers(&self.node)
+            .await?
+            .iter()
+            .any(|c| ProxmoxVmId::from(c.vm_id).inner() == vm.id))
+    }
+
+    /// Recreate the container from the new template with `force`, carrying
+    /// over its config. The data volumes on `mp[n]` stay in the new config so
+    /// Proxmox keeps them, only the root filesystem is replaced with a fresh
+    /// volume on the same storage.
+    async fn recreate_container(&self, cfg: &FullVmInfo) -> OpResult<()> {
+        let vm_id: ProxmoxVmId = cfg.vm.id.into();
+        let os_template = self.ensure_container_template(&cfg.image).await?;
+        let mut config = self.get_container_config_raw(&self.node, vm_id).await?;
+        let Some(storage) = config
+            .get("rootfs")
+            .and_then(|v| v.as_str())
+            .and_then(parse_storage_from_disk)
+        else {
+            op_fatal!("Container {} has no root filesystem", vm_id);
+        };
+        let rootfs = format!("{}:{}", storage, cfg.resources()?.disk_size.div_ceil(GB));
+        // state of the old container which a new one doesn't take
+        config.retain(|k, _| {
+            !matches!(k.as_str(), "digest" | "rootfs" | "ostype" | "lock")
+                && !k.starts_with("unused")
+        });
+
+        let running = self.list_containers(&self.node).await?.iter().any(|c| {
+            ProxmoxVmId::from(c.vm_id).inner() == cfg.vm.id && c.status == VmStatus::Running
+        });
+        if running {
+            let task = self.stop_container(&self.node, vm_id).await?;
+            self.wait_for_task(&task).await?;
+        }
+        let task = self
+            .create_container(CreateContainer {
+                node: self.node.clone(),
+                vm_id,
+                os_template,
+                force: true,
+                rootfs,
+                start: true,
+                config,
+            })
+            .await?;
+        self.wait_for_task(&task).await?;
+        Ok(())
+    }
+
     async fn resize_disk(&self, cfg: &FullVmInfo) -> OpResult<()> {
         let task = self
             .resize_disk(ResizeDiskRequest {
@@ -1640,6 +2347,51 @@ impl VmHostClient for ProxmoxClient {
         Ok(())
     }
 
+    async fn backup_vm(&self, vm: &Vm) -> OpResult<HostBackup> {
+        let Some(storage) = self.backup_storage.clone() else {
+            op_fatal!("No backup storage configured");
+        };
+        let vm_id: ProxmoxVmId = vm.id.into();
+        let task = self
+            .vzdump(VzdumpRequest {
+                node: self.node.clone(),
+                vm_id,
+                storage: storage.clone(),
+                mode: "snapshot".to_string(),
+                compress: "zstd".to_string(),
+            })
+            .await?;
+        self.wait_for_task(&task).await?;
+
+        // vzdump doesn't return the archive name, take the newest archive
+        let backups = self.list_vm_backups(&self.node, &storage, vm_id).await?;
+        let Some(newest) = backups.into_iter().max_by_key(|b| b.ctime.unwrap_or(0)) else {
+            op_fatal!("Backup of VM {} finished but no archive was found", vm.id);
+        };
+        Ok(HostBackup {
+            volume_id: newest.vol_id,
+            size: newest.size,
+        })
+    }
+
+    async fn delete_backup(&self, volume_id: &str) -> OpResult<()> {
+        self.delete_volume(&self.node, volume_id).await
+    }
+
+    async fn restore_backup(&self, cfg: &FullVmInfo, volume_id: &str) -> OpResult<()> {
+        let task = self
+            .restore_vm(RestoreVm {
+                node: self.node.clone(),
+                vm_id: cfg.vm.id.into(),
+                archive: volume_id.to_string(),
+                storage: cfg.disk.name.clone(),
+                force: true,
+            })
+            .await?;
+        self.wait_for_task(&task).await?;
+        Ok(())
+    }
+
     async fn get_vm_state(&self, vm: &Vm) -> OpResult<VmRunningState> {
         let s = self.get_vm_status(&self.node, vm.id.into()).await?;
         Ok(s.into())
@@ -1661,6 +2413,83 @@ impl VmHostClient for ProxmoxClient {
         Ok(states)
     }
 
+    async fn get_guest_info(&self, vm: &Vm) -> OpResult<GuestInfo> {
+        let vm_id: ProxmoxVmId = vm.id.into();
+        let status = self.get_vm_status(&self.node, vm_id).await?;
+        if status.status != VmStatus::Running {
+            op_fatal!("Guest agent is not running, VM is stopped");
+        }
+        if status.is_suspended() {
+            op_fatal!("Guest agent is not running, VM is suspended");
+        }
+        // PVE answers 500 when the agent is not running or not enabled
+        let interfaces = match self.get_guest_interfaces(&self.node, vm_id).await {
+            Ok(i) => i,
+            Err(e) => op_fatal!("Guest agent is not running: {}", e),
+        };
+        // older agents have no usage in get-fsinfo, or may not answer it at all
+        let filesystems = match self.get_guest_fsinfo(&self.node, vm_id).await {
+            Ok(f) => guest_filesystems(f),
+            Err(e) => {
+                warn!("Failed to get filesystems of VM {}: {}", vm.id, e);
+                vec![]
+            }
+        };
+        Ok(GuestInfo {
+            interfaces: interfaces.into_iter().map(Into::into).collect(),
+            // the agent has no uptime command, the QEMU process uptime matches
+            // the guest's except after an in-guest reboot
+            uptime: status.uptime,
+            filesystems,
+        })
+    }
+
+    async fn reset_password(
+        &self,
+        cfg: &FullVmInfo,
+        password: &str,
+    ) -> OpResult<PasswordResetMethod> {
+        let vm_id: ProxmoxVmId = cfg.vm.id.into();
+        // always store it in cloud-init, PVE changes the cloud-init instance-id
+        // with the config so the guest re-applies the password on next boot
+        if let Some(task) = self
+            .set_cloud_init_password(&self.node, vm_id, password)
+            .await?
+        {
+            self.wait_for_task(&task).await?;
+        }
+
+        let user = cfg.image.default_username.as_deref().unwrap_or("root");
+        let status = self.get_vm_status(&self.node, vm_id).await?;
+        if status.status != VmStatus::Running {
+            info!("Starting VM {} to apply new password", cfg.vm.id);
+            VmHostClient::start_vm(self, &cfg.vm).await?;
+            // cloud-init applies it during boot, setting it again once the
+            // guest agent answers confirms it is in effect
+            self.agent_set_user_password_after_boot(
+                vm_id,
+                user,
+                password,
+                Duration::from_secs(self.config.reboot_timeout()),
+            )
+            .await?;
+            return Ok(PasswordResetMethod::Started);
+        }
+
+        if let Err(e) = self
+            .agent_set_user_password(&self.node, vm_id, user, password)
+            .await
+        {
+            warn!(
+                "Failed to set password for VM {} via guest agent, rebooting: {}",
+                cfg.vm.id, e
+            );
+            VmHostClient::reboot_vm(self, &cfg.vm).await?;
+            return Ok(PasswordResetMethod::Rebooted);
+        }
+        Ok(PasswordResetMethod::GuestAgent)
+    }
+
     async fn configure_vm(&self, cfg: &FullVmInfo) -> OpResult<()> {
         let current_config = self.get_vm_config(&self.node, cfg.vm.id.into()).await?;
 
@@ -1678,12 +2507,14 @@ impl VmHostClient for ProxmoxClient {
             config.cicustom = None;
         }
 
+        let stale = current_config.config.stale_ip_configs(&config);
         self.configure_vm(ConfigureVm {
             node: self.node.clone(),
             vm_id: cfg.vm.id.into(),
             current: None,
             snapshot: None,
             digest: Some(current_config.digest),
+            delete: (!stale.is_empty()).then(|| stale.join(",")),
             config,
         })
         .await?;
@@ -1691,28 +2522,82 @@ impl VmHostClient for ProxmoxClient {
         // Apply disk I/O throttle limits (requires reading live scsi0 path)
         self.apply_disk_limits(cfg).await?;
 
+        // Re-attach any data disks missing from the live config
+        if !cfg.data_disks.is_empty() {
+            let live = self.get_vm_config_raw(&self.node, cfg.vm.id.into()).await?;
+            for disk in &cfg.data_disks {
+                if !live.contains_key(&data_disk_slot(disk)) {
+                    info!(
+                        "Data disk {} missing on VM {}, attaching",
+                        data_disk_slot(disk),
+                        cfg.vm.id
+                    );
+                    self.attach_data_disk(&cfg.vm, disk).await?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn attach_data_disk(&self, vm: &Vm, disk: &DataDisk) -> OpResult<()> {
+        let vm_id: ProxmoxVmId = vm.id.into();
+        if let Err(e) = self
+            .attach_disk(
+                &self.node,
+                vm_id,
+                &data_disk_slot(disk),
+                &data_disk_volume(disk),
+            )
+            .await
+        {
+            // The volume may have been allocated before the attach failed, in
+            // which case Proxmox leaves it behind as an `unused[n]` entry.
+            if let Err(ce) = self.cleanup_vm_disks(vm_id, false).await {
+                warn!("Failed to clean up unused disks on VM {}: {}", vm.id, ce);
+            }
+            return Err(e);
+        }
         Ok(())
     }
 
+    async fn detach_data_disk(&self, vm: &Vm, disk: &DataDisk) -> OpResult<()> {
+        let vm_id: ProxmoxVmId = vm.id.into();
+        let slot = data_disk_slot(disk);
+        let live = self.get_vm_config_raw(&self.node, vm_id).await?;
+        if !live.contains_key(&slot) {
+            return Ok(());
+        }
+        // force=1 destroys the volume instead of leaving it as `unused[n]`
+        self.unlink_disk(&self.node, vm_id, vec![slot], true).await
+    }
+
     async fn patch_firewall(&self, cfg: &FullVmInfo) -> OpResult<()> {
         let vm_id = cfg.vm.id.into();
 
         // Check and fix cloud-init IP config if it doesn't match expected
         let current_config = self.get_vm_config(&self.node, vm_id).await?;
         let expected_config = self.make_config(cfg, None)?;
-        if current_config.config.ip_config != expected_config.ip_config {
+        if current_config.config.ip_configs() != expected_config.ip_configs() {
             info!(
                 "IP config mismatch for VM {}: current={:?}, expected={:?}",
-                cfg.vm.id, current_config.config.ip_config, expected_config.ip_config
+                cfg.vm.id,
+                current_config.config.ip_configs(),
+                expected_config.ip_configs()
             );
+            let stale = current_config.config.stale_ip_configs(&expected_config);
             self.configure_vm(ConfigureVm {
                 node: self.node.clone(),
                 vm_id,
                 current: None,
                 snapshot: None,
                 digest: Some(current_config.digest),
+                delete: (!stale.is_empty()).then(|| stale.join(",")),
                 config: VmConfig {
                     ip_config: expected_config.ip_config,
+                    ip_config_1: expected_config.ip_config_1,
+                    ip_config_2: expected_config.ip_config_2,
+                    ip_config_3: expected_config.ip_config_3,
                     ..Default::default()
                 },
             })
@@ -1758,11 +2643,14 @@ impl VmHostClient for ProxmoxClient {
                     .map(Self::convert_firewall_policy)
             });
         // Use configured firewall options or disable firewall if no config
+        // Private network VMs always get IP/MAC filtering, so they can't
+        // answer ARP/NDP for or send as another address on the segment
+        let isolated = cfg.vm.private_network.then_some(true);
         let firewall_config = VmFirewallConfig {
             dhcp: fw_cfg.dhcp,
             enable: fw_cfg.enable,
-            ip_filter: fw_cfg.ip_filter,
-            mac_filter: fw_cfg.mac_filter,
+            ip_filter: isolated.or(fw_cfg.ip_filter),
+            mac_filter: isolated.or(fw_cfg.mac_filter),
             ndp: fw_cfg.ndp,
             policy_in,
             policy_out,
@@ -1791,52 +2679,27 @@ impl VmHostClient for ProxmoxClient {
             .await?;
         }
 
-        // Get existing entries to avoid duplicates
+        // Sync the IPset with the VM's IPv4 and IPv6 assignments alike: add
+        // missing entries and drop ones we added for addresses no longer
+        // assigned, so a released address of either family stops being allowed.
+        let expected_entries = Self::ipset_entries(cfg);
         let existing_entries = self
             .list_vm_ipset_entries(&self.node, vm_id, "ipfilter-net0")
             .await?;
-        let existing_cidrs: std::collections::HashSet<String> = existing_entries
-            .iter()
-            .map(|entry| entry.cidr.clone())
-            .collect();
-
-        // Add new IPv4 and IPv6 addresses that don't already exist
-        for ip in &cfg.ips {
-            if let Ok(addr) = ip.ip.parse::<IpAddr>() {
-                match addr {
-                    IpAddr::V4(ipv4_addr) => {
-                        let ip_str = ipv4_addr.to_string();
-                        if !existing_cidrs.contains(&ip_str) {
-                            self.add_vm_ipset_entry(
-                                &self.node,
-                                vm_id,
-                                "ipfilter-net0",
-                                CreateVmIpsetEntryRequest {
-                                    cidr: ip_str,
-                                    comment: Some("VM IPv4 address".to_string()),
-                                    nomatch: None,
-                                },
-                            )
-                            .await?;
-                        }
-                    }
-                    IpAddr::V6(ipv6_addr) => {
-                        let ip_str = ipv6_addr.to_string();
-                        if !existing_cidrs.contains(&ip_str) {
-                            self.add_vm_ipset_entry(
-                                &self.node,
-                                vm_id,
-                                "ipfilter-net0",
-                                CreateVmIpsetEntryRequest {
-                                    cidr: ip_str,
-                                    comment: Some("VM IPv6 address".to_string()),
-                                    nomatch: None,
-                                },
-                            )
-                            .await?;
-                        }
-                    }
-                }
+        for entry in &existing_entries {
+            let ours = matches!(
+                entry.comment.as_deref(),
+                Some(IPSET_V4_COMMENT) | Some(IPSET_V6_COMMENT)
+            );
+            if ours && !expected_entries.iter().any(|e| e.cidr == entry.cidr) {
+                self.remove_vm_ipset_entry(&self.node, vm_id, "ipfilter-net0", &entry.cidr)
+                    .await?;
+            }
+        }
+        for entry in expected_entries {
+            if !existing_entries.iter().any(|e| e.cidr == entry.cidr) {
+                self.add_vm_ipset_entry(&self.node, vm_id, "ipfilter-net0", entry)
+                    .await?;
             }
         }
 
@@ -1919,6 +2782,10 @@ impl VmHostClient for ProxmoxClient {
         Ok(r.into_iter().map(TimeSeriesData::from).collect())
     }
 
+    async fn get_console_screenshot(&self, vm: &Vm) -> OpResult<Vec<u8>> {
+        self.screendump_vm(&self.node, vm.id.into()).await
+    }
+
     async fn connect_terminal(&self, vm: &Vm) -> OpResult<TerminalStream> {
         let ssh = self
             .ssh
@@ -2025,33 +2892,28 @@ impl ProxmoxClient {
     /// Download a URL directly onto the host over SSH into the ISO storage dir.
     ///
     /// Fetches `url` into `filename` under `/var/lib/vz/template/iso/` using
-    /// `wget` (falling back to `curl`), both of which follow HTTP redirects
-    /// natively. The download goes to a `.part` temp file that is atomically
-    /// moved into place on success, so an interrupted download never leaves a
-    /// truncated file at the final path. Used for compressed images, whose real
-    /// filename (e.g. `foo.qcow2.xz`) Proxmox's download-url API rejects.
-    pub async fn download_image_ssh(&self, url: &str, filename: &str) -> Result<()> {
-        let dir = "/var/lib/vz/template/iso";
-        let dst = format!("{dir}/{filename}");
-        let tmp = format!("{dst}.part");
-        // Prefer wget; fall back to curl. `-fL`/redirect-following ensures CDN
-        // redirects (common for release mirrors) are handled on the host.
-        let cmd = format!(
-            "mkdir -p '{dir}' && \
-             if command -v wget >/dev/null 2>&1; then \
-                 wget -q -O '{tmp}' '{url}'; \
-             else \
-                 curl -fLsS -o '{tmp}' '{url}'; \
-             fi && mv -f '{tmp}' '{dst}'"
-        );
-
-        let (exit_code, output) = self.ssh_run(cmd).await?;
-        if exit_code != 0 {
-            // Best-effort cleanup of any partial download.
-            let _ = self.ssh_run(format!("rm -f '{tmp}'")).await;
-            anyhow::bail!("Download failed (exit {}): {}", exit_code, output.trim());
+    /// `curl` (falling back to `wget`), both of which follow HTTP redirects
+    /// natively. This is a single attempt: an interrupted download keeps its
+    /// `.part` file so the next attempt resumes it, see [`download_resumable`].
+    /// Used for compressed images, whose real filename (e.g. `foo.qcow2.xz`)
+    /// Proxmox's download-url API rejects.
+    pub async fn download_image_ssh(
+        &self,
+        url: &str,
+        filename: &str,
+        expected_size: Option<u64>,
+    ) -> OpResult<()> {
+        if self.ssh.is_none() {
+            return Err(OpError::Fatal(anyhow::anyhow!("SSH not configured")));
         }
-        Ok(())
+        download_resumable(
+            |cmd| self.ssh_run(cmd),
+            "/var/lib/vz/template/iso",
+            filename,
+            url,
+            expected_size,
+        )
+        .await
     }
 
     /// Decompress a downloaded compressed image on the host via SSH.
@@ -2099,6 +2961,73 @@ impl ProxmoxClient {
         Ok(())
     }
 
+    /// Back up a VM to a storage
+    ///
+    /// https://pve.proxmox.com/pve-docs/api-viewer/?ref=public_apis#/nodes/{node}/vzdump
+    pub async fn vzdump(&self, req: VzdumpRequest) -> OpResult<TaskId> {
+        let rsp: ResponseBase<Option<String>> = self
+            .api
+            .post(&format!("/api2/json/nodes/{}/vzdump", req.node), &req)
+            .await?;
+        if let Some(id) = rsp.data {
+            Ok(TaskId { id, node: req.node })
+        } else {
+            op_fatal!("Failed to start backup")
+        }
+    }
+
+    /// List the backup archives of a VM in a storage
+    pub async fn list_vm_backups(
+        &self,
+        node: &str,
+        storage: &str,
+        vm_id: ProxmoxVmId,
+    ) -> OpResult<Vec<StorageContentEntry>> {
+        let rsp: ResponseBase<Vec<StorageContentEntry>> = self
+            .api
+            .get(&format!(
+                "/api2/json/nodes/{node}/storage/{storage}/content?content=backup&vmid={vm_id}"
+            ))
+            .await?;
+        Ok(rsp.data)
+    }
+
+    /// Restore a VM from a backup archive, replacing the existing VM
+    ///
+    /// https://pve.proxmox.com/pve-docs/api-viewer/?ref=public_apis#/nodes/{node}/qemu
+    pub async fn restore_vm(&self, req: RestoreVm) -> OpResult<TaskId> {
+        let rsp: ResponseBase<Option<String>> = self
+            .api
+            .post(&format!("/api2/json/nodes/{}/qemu", req.node), &req)
+            .await?;
+        if let Some(id) = rsp.data {
+            Ok(TaskId { id, node: req.node })
+        } else {
+            op_fatal!("Failed to restore VM")
+        }
+    }
+
+    /// Delete a volume by its full volume id (`storage:path`)
+    pub async fn delete_volume(&self, node: &str, vol_id: &str) -> OpResult<()> {
+        let Some((storage, _)) = vol_id.split_once(':') else {
+            op_fatal!("Invalid volume id {}", vol_id);
+        };
+        let _: ResponseBase<Option<String>> = self
+            .api
+            .req::<_, ()>(
+                Method::DELETE,
+                &format!(
+                    "/api2/json/nodes/{}/storage/{}/content/{}",
+                    node,
+                    storage,
+                    urlencoding::encode(vol_id)
+                ),
+                None,
+            )
+            .await?;
+        Ok(())
+    }
+
     /// Delete a storage file on the Proxmox node
     pub async fn delete_storage_file(
         &self,
@@ -2124,6 +3053,187 @@ impl ProxmoxClient {
     }
 }
 
+/// curl exit code when the server ignored the `Range` header of a resume
+const CURL_RANGE_ERROR: i32 = 33;
+
+/// Check the image URL before (re)starting a download.
+///
+/// This runs in the API process, not on the host doing the download. Returns the image size when the server reports one. Some mirrors and CDNs
+/// reject `HEAD` outright, so a failed `HEAD` only logs a warning and the
+/// image is probed again with a single byte ranged `GET`. 404/403 on that
+/// `GET` are fatal, no amount of retrying will make the image appear, while
+/// network errors, 5xx and 429 are transient.
+pub async fn probe_image(url: &str) -> OpResult<Option<u64>> {
+    let client = reqwest::Client::new();
+    match client.head(url).send().await {
+        Ok(rsp) if rsp.status().is_success() => {
+            // read the header, the body of a HEAD response is always empty
+            if let Some(size) = content_length(&rsp) {
+                return Ok(Some(size));
+            }
+        }
+        Ok(rsp) => warn!("HEAD {} returned {}, probing with GET", url, rsp.status()),
+        Err(e) => warn!("HEAD {} failed, probing with GET: {}", url, e),
+    }
+
+    let rsp = client
+        .get(url)
+        .header(reqwest::header::RANGE, "bytes=0-0")
+        .send()
+        .await
+        .map_err(|e| OpError::Transient(anyhow::anyhow!("Failed to reach {}: {}", url, e)))?;
+    let status = rsp.status();
+    if status == StatusCode::NOT_FOUND || status == StatusCode::FORBIDDEN {
+        return Err(OpError::Fatal(anyhow::anyhow!(
+            "Image {} is not available: {}",
+            url,
+            status
+        )));
+    }
+    if status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS {
+        return Err(OpError::Transient(anyhow::anyhow!(
+            "Image {} returned {}",
+            url,
+            status
+        )));
+    }
+    // the response body is dropped unread, a server ignoring the range
+    // just gets its connection closed
+    Ok(match status {
+        StatusCode::PARTIAL_CONTENT => content_range_total(&rsp),
+        StatusCode::OK => content_length(&rsp),
+        _ => None,
+    })
+}
+
+fn content_length(rsp: &reqwest::Response) -> Option<u64> {
+    rsp.headers()
+        .get(reqwest::header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok())
+        .filter(|v| *v > 0)
+}
+
+/// Total size from a `Content-Range: bytes 0-0/<total>` header
+fn content_range_total(rsp: &reqwest::Response) -> Option<u64> {
+    rsp.headers()
+        .get(reqwest::header::CONTENT_RANGE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.rsplit_once('/'))
+        .and_then(|(_, total)| total.parse::<u64>().ok())
+        .filter(|v| *v > 0)
+}
+
+/// Shell command fetching `url` into `dir/filename` via a `.part` file,
+/// continuing from whatever a previous attempt left in the `.part` file
+fn resumable_download_command(dir: &str, filename: &str, url: &str) -> String {
+    let dst = format!("{dir}/{filename}");
+    let tmp = format!("{dst}.part");
+    format!(
+        "mkdir -p '{dir}' && \
+         if command -v curl >/dev/null 2>&1; then \
+             curl -fLsS -C - -o '{tmp}' '{url}'; \
+         else \
+             wget -q -c -O '{tmp}' '{url}'; \
+         fi && mv -f '{tmp}' '{dst}'"
+    )
+}
+
+/// Size of `path` on the machine `run` executes commands on
+async fn remote_file_size<F, Fut>(run: &F, path: &str) -> Result<Option<u64>>
+where
+    F: Fn(String) -> Fut,
+    Fut: Future<Output = Result<(i32, String)>>,
+{
+    let (exit_code, output) = run(format!("stat -c %s '{path}' 2>/dev/null")).await?;
+    if exit_code != 0 {
+        return Ok(None);
+    }
+    Ok(output.trim().parse().ok())
+}
+
+fn format_progress(have: u64, total: Option<u64>) -> String {
+    match total {
+        Some(total) if total > 0 => format!(
+            "{}/{} bytes ({:.0}%)",
+            have,
+            total,
+            have as f64 * 100.0 / total as f64
+        ),
+        _ => format!("{} bytes", have),
+    }
+}
+
+/// One attempt at downloading `url` into `dir/filename`, running the shell
+/// commands with `run`.
+///
+/// A failed attempt keeps its `.part` file and reports how far it got, so
+/// wrapping this in [`retry_async`] resumes from there with an HTTP `Range`
+/// request instead of starting over. A `.part` file already holding
+/// `expected_size` bytes is taken as finished, resuming it would only get a
+/// `416 Range Not Satisfiable`. The finished file is checked against
+/// `expected_size` when known, the checksum is verified by the caller.
+async fn download_resumable<F, Fut>(
+    run: F,
+    dir: &str,
+    filename: &str,
+    url: &str,
+    expected_size: Option<u64>,
+) -> OpResult<()>
+where
+    F: Fn(String) -> Fut,
+    Fut: Future<Output = Result<(i32, String)>>,
+{
+    let dst = format!("{dir}/{filename}");
+    let tmp = format!("{dst}.part");
+    let have = remote_file_size(&run, &tmp)
+        .await
+        .map_err(OpError::Transient)?;
+    let command = if expected_size.is_some() && have == expected_size {
+        info!("{} is already fully downloaded", filename);
+        format!("mv -f '{tmp}' '{dst}'")
+    } else {
+        resumable_download_command(dir, filename, url)
+    };
+    let (exit_code, output) = run(command).await.map_err(OpError::Transient)?;
+    if exit_code != 0 {
+        if exit_code == CURL_RANGE_ERROR {
+            // the server can't resume, the next attempt starts over
+            let _ = run(format!("rm -f '{tmp}'")).await;
+        }
+        let have = remote_file_size(&run, &tmp).await.ok().flatten();
+        return Err(OpError::Transient(anyhow::anyhow!(
+            "Download of {} interrupted at {} (exit {}): {}",
+            filename,
+            format_progress(have.unwrap_or(0), expected_size),
+            exit_code,
+            output.trim()
+        )));
+    }
+
+    let size = remote_file_size(&run, &dst)
+        .await
+        .map_err(OpError::Transient)?;
+    if let (Some(expected), Some(size)) = (expected_size, size)
+        && expected != size
+    {
+        // can't tell which part is bad, start over
+        let _ = run(format!("rm -f '{dst}'")).await;
+        return Err(OpError::Transient(anyhow::anyhow!(
+            "Downloaded {} is {} bytes, expected {}",
+            filename,
+            size,
+            expected
+        )));
+    }
+    info!(
+        "Downloaded {} ({})",
+        filename,
+        format_progress(size.unwrap_or(0), None)
+    );
+    Ok(())
+}
+
 /// Wrap a database vm id
 #[derive(Debug, Copy, Clone, Default)]
 pub struct ProxmoxVmId(u64);
@@ -2149,6 +3259,51 @@ fn parse_mac_from_net(net: &str) -> Option<String> {
     })
 }
 
+/// Extract the static IPs from a cloud-init `ipconfigN` value, e.g.
+/// `ip=10.0.0.5/24,gw=10.0.0.1,ip6=2001:db8::5/64` -> `10.0.0.5`, `2001:db8::5`.
+/// `dhcp`/`auto` addresses are skipped.
+fn parse_ips_from_ip_config(ip_config: &str) -> Vec<String> {
+    ip_config
+        .split(',')
+        .filter_map(|kv| {
+            let (k, v) = kv.split_once('=')?;
+            if k != "ip" && k != "ip6" {
+                return None;
+            }
+            let ip = v.split('/').next()?;
+            ip.parse::<IpAddr>().ok().map(|ip| ip.to_string())
+        })
+        .collect()
+}
+
+/// Proxmox config key for a data disk.
+///
+/// NVMe-backed storage is attached as virtio-blk (`virtio{n}`), everything
+/// else on the SCSI bus after the primary disk (`scsi0`) and the cloud-init
+/// drive (`scsi1`).
+fn data_disk_slot(disk: &DataDisk) -> String {
+    match disk.storage.interface {
+        DiskInterface::PCIe => format!("virtio{}", disk.disk.slot),
+        _ => format!("scsi{}", disk.disk.slot + 2),
+    }
+}
+
+/// Proxmox volume spec allocating a new data disk, e.g. `local-lvm:100,discard=on`.
+fn data_disk_volume(disk: &DataDisk) -> String {
+    let mut parts = vec![format!(
+        "{}:{}",
+        disk.storage.name,
+        disk.disk.size.div_ceil(GB)
+    )];
+    if matches!(disk.storage.kind, DiskType::SSD) {
+        parts.push("discard=on".to_string());
+        if !matches!(disk.storage.interface, DiskInterface::PCIe) {
+            parts.push("ssd=1".to_string());
+        }
+    }
+    parts.join(",")
+}
+
 /// Extract the storage pool from a Proxmox disk config string, e.g.
 /// `local-lvm:vm-1566-disk-0,size=32G` -> `local-lvm`.
 fn parse_storage_from_disk(disk: &str) -> Option<String> {
@@ -2325,6 +3480,93 @@ pub struct VmInfo {
     pub disk_write: Option<u64>,
     #[serde(rename = "diskread")]
     pub disk_read: Option<u64>,
+    /// QEMU state of a running VM (`running`, `paused`, ...), only set by the
+    /// status endpoint or a `full` listing
+    #[serde(rename = "qmpstatus")]
+    pub qmp_status: Option<String>,
+    /// Config lock held by a running operation (`migrate`, `backup`, ...)
+    pub lock: Option<String>,
+}
+
+impl VmInfo {
+    /// VM is being migrated to another node
+    pub fn is_migrating(&self) -> bool {
+        self.lock.as_deref() == Some("migrate")
+    }
+
+    /// VM is suspended, its QEMU process runs but the guest is paused
+    pub fn is_suspended(&self) -> bool {
+        self.status == VmStatus::Running
+            && matches!(self.qmp_status.as_deref(), Some("paused" | "suspended"))
+    }
+}
+
+/// Wrapper around guest agent command output
+#[derive(Debug, Deserialize)]
+pub struct AgentResult<T> {
+    pub result: T,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AgentNetworkInterface {
+    pub name: String,
+    #[serde(rename = "hardware-address")]
+    pub hardware_address: Option<String>,
+    #[serde(rename = "ip-addresses", default)]
+    pub ip_addresses: Vec<AgentIpAddress>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AgentIpAddress {
+    #[serde(rename = "ip-address")]
+    pub ip_address: String,
+    pub prefix: u8,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AgentFsInfo {
+    /// Device name, e.g. `sda1`
+    pub name: String,
+    pub mountpoint: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    #[serde(rename = "used-bytes")]
+    pub used_bytes: Option<u64>,
+    #[serde(rename = "total-bytes")]
+    pub total_bytes: Option<u64>,
+}
+
+/// Writable filesystems with usage from a `get-fsinfo` result, each device once
+/// (bind mounts repeat it). Read-only image filesystems, e.g. snap packages,
+/// are always full and skipped.
+fn guest_filesystems(fs: Vec<AgentFsInfo>) -> Vec<GuestFilesystem> {
+    const READ_ONLY: [&str; 3] = ["squashfs", "iso9660", "udf"];
+    let mut seen = HashSet::new();
+    fs.into_iter()
+        .filter(|f| !READ_ONLY.contains(&f.kind.as_str()))
+        .filter(|f| seen.insert(f.name.clone()))
+        .filter_map(|f| {
+            Some(GuestFilesystem {
+                used_bytes: f.used_bytes?,
+                total_bytes: f.total_bytes?,
+                mountpoint: f.mountpoint,
+            })
+        })
+        .collect()
+}
+
+impl From<AgentNetworkInterface> for GuestInterface {
+    fn from(value: AgentNetworkInterface) -> Self {
+        Self {
+            name: value.name,
+            mac_address: value.hardware_address,
+            addresses: value
+                .ip_addresses
+                .into_iter()
+                .map(|a| format!("{}/{}", a.ip_address, a.prefix))
+                .collect(),
+        }
+    }
 }
 
 impl From<VmInfo> for VmRunningState {
@@ -2332,6 +3574,8 @@ impl From<VmInfo> for VmRunningState {
         Self {
             timestamp: Utc::now().timestamp() as u64,
             state: match vm.status {
+                _ if vm.is_migrating() => VmRunningStates::Migrating,
+                _ if vm.is_suspended() => VmRunningStates::Suspended,
                 VmStatus::Stopped => VmRunningStates::Stopped,
                 VmStatus::Running => VmRunningStates::Running,
             },
@@ -2418,6 +3662,33 @@ pub struct StorageContentEntry {
     pub vol_id: String,
     #[serde(rename = "vmid")]
     pub vm_id: Option<u32>,
+    /// Creation time (unix seconds)
+    #[serde(default)]
+    pub ctime: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct VzdumpRequest {
+    pub node: String,
+    #[serde(rename = "vmid")]
+    pub vm_id: ProxmoxVmId,
+    pub storage: String,
+    /// `snapshot` backs up a running VM without stopping it
+    pub mode: String,
+    pub compress: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RestoreVm {
+    pub node: String,
+    #[serde(rename = "vmid")]
+    pub vm_id: ProxmoxVmId,
+    /// Backup archive volume id
+    pub archive: String,
+    /// Storage for the restored disks
+    pub storage: String,
+    /// Overwrite the existing VM
+    pub force: bool,
 }
 
 #[derive(Debug, Deserialize, Serialize, Default)]
@@ -2464,6 +3735,23 @@ pub enum VmBios {
     OVMF,
 }
 
+#[derive(Debug, Deserialize, Serialize, Default)]
+pub struct MigrateVm {
+    pub node: String,
+    #[serde(rename = "vmid")]
+    pub vm_id: ProxmoxVmId,
+    /// Node to migrate the VM to
+    pub target: String,
+    /// Migrate a running VM without stopping it
+    pub online: bool,
+    /// Copy disks on local storage to the target node
+    #[serde(rename = "with-local-disks")]
+    pub with_local_disks: bool,
+    /// Storage on the target node for the VM's disks
+    #[serde(rename = "targetstorage", skip_serializing_if = "Option::is_none")]
+    pub target_storage: Option<String>,
+}
+
 #[derive(Debug, Deserialize, Serialize, Default)]
 pub struct CreateVm {
     pub node: String,
@@ -2473,6 +3761,34 @@ pub struct CreateVm {
     pub config: VmConfig,
 }
 
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CreateContainer {
+    pub node: String,
+    #[serde(rename = "vmid")]
+    pub vm_id: ProxmoxVmId,
+    /// Volume id of the container template
+    #[serde(rename = "ostemplate")]
+    pub os_template: String,
+    /// Replace an existing container of the same id
+    pub force: bool,
+    /// Root filesystem volume, e.g. `local-lvm:8` for a new 8GB volume
+    pub rootfs: String,
+    /// Start the container once it is created
+    pub start: bool,
+    /// Other settings of the container (`hostname`, `net0`, `mp0`, ...)
+    #[serde(flatten)]
+    pub config: HashMap<String, serde_json::Value>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct DownloadUrlRequest {
+    pub node: String,
+    pub storage: String,
+    pub content: StorageContent,
+    pub filename: String,
+    pub url: String,
+}
+
 #[derive(Debug, Deserialize, Serialize, Default)]
 pub struct ConfigureVm {
     pub node: String,
@@ -2484,6 +3800,9 @@ pub struct ConfigureVm {
     pub snapshot: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub digest: Option<String>,
+    /// Comma separated list of config keys to remove
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delete: Option<String>,
     #[serde(flatten)]
     pub config: VmConfig,
 }
@@ -2517,6 +3836,15 @@ pub struct VmConfig {
     #[serde(rename = "ipconfig0")]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub ip_config: Option<String>,
+    #[serde(rename = "ipconfig1")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ip_config_1: Option<String>,
+    #[serde(rename = "ipconfig2")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ip_config_2: Option<String>,
+    #[serde(rename = "ipconfig3")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ip_config_3: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub machine: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -2562,6 +3890,81 @@ pub struct VmConfig {
     /// Custom cloud-init config files (e.g. "vendor=local:snippets/lnvps-vendor.yaml")
     #[serde(skip_serializing_if = "Option::is_none")]
     pub cicustom: Option<String>,
+    /// Comma separated list of hot-plug features (e.g. "network,disk,cpu,memory")
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hotplug: Option<String>,
+    /// NUMA emulation, required for memory hot-plug
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(
+        default,
+        deserialize_with = "lnvps_api_common::deserialize_int_to_bool"
+    )]
+    pub numa: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sockets: Option<i32>,
+    /// Number of hot-plugged vCPUs, at most `cores * sockets`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vcpus: Option<i32>,
+}
+
+impl VmConfig {
+    /// The `ipconfigN` slots in order
+    fn ip_configs(&self) -> [&Option<String>; MAX_IP_CONFIGS] {
+        [
+            &self.ip_config,
+            &self.ip_config_1,
+            &self.ip_config_2,
+            &self.ip_config_3,
+        ]
+    }
+
+    /// `ipconfigN` keys set here which are not set in `expected` and must be deleted
+    fn stale_ip_configs(&self, expected: &VmConfig) -> Vec<String> {
+        self.ip_configs()
+            .iter()
+            .zip(expected.ip_configs())
+            .enumerate()
+            .filter(|(_, (current, expected))| current.is_some() && expected.is_none())
+            .map(|(i, _)| format!("ipconfig{}", i))
+            .collect()
+    }
+
+    /// If the VM config lists `feature` in its hot-plug features
+    fn can_hotplug(&self, feature: &str) -> bool {
+        match self.hotplug.as_deref() {
+            // "1" is the proxmox default of network,disk,usb
+            None | Some("0") | Some("1") => false,
+            Some(h) => h.split(',').any(|f| f.trim() == feature),
+        }
+    }
+
+    /// The config changes which set `cpu` vCPUs and `memory_mb` of memory on
+    /// the running VM, `None` when this config doesn't allow doing it live
+    fn hotplug_config(&self, cpu: u16, memory_mb: u64) -> Option<VmConfig> {
+        let cores = self.cores.unwrap_or(1);
+        let current_cpu = self.vcpus.unwrap_or(cores * self.sockets.unwrap_or(1));
+        let current_memory: u64 = self.memory.as_ref()?.parse().ok()?;
+        // vCPUs can only be added up to the configured topology, memory can
+        // only be added
+        if cpu as i32 > cores * self.sockets.unwrap_or(1) || memory_mb < current_memory {
+            return None;
+        }
+
+        let mut config = VmConfig::default();
+        if cpu as i32 != current_cpu {
+            if !self.can_hotplug("cpu") {
+                return None;
+            }
+            config.vcpus = Some(cpu as i32);
+        }
+        if memory_mb != current_memory {
+            if !self.can_hotplug("memory") || self.numa != Some(true) {
+                return None;
+            }
+            config.memory = Some(memory_mb.to_string());
+        }
+        Some(config)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -2785,7 +4188,8 @@ mod tests {
     use super::*;
     use crate::MB;
     use crate::host::tests::mock_full_vm;
-    use lnvps_db::IpRange;
+    use lnvps_db::{IpRange, VmIpAssignment};
+    use std::sync::Arc;
     use wiremock::matchers::{method, path_regex};
     use wiremock::{Mock, MockServer, ResponseTemplate};
 
@@ -2803,6 +4207,7 @@ mod tests {
             arch: "x86_64".to_string(),
             balloon_min_pct: None,
             firewall_config: None,
+            reboot_timeout: None,
         };
 
         let p = ProxmoxClient::new(
@@ -2823,16 +4228,195 @@ mod tests {
         assert_eq!(vm.on_boot, Some(true));
         assert!(vm.net.as_ref().unwrap().contains("tag=100"));
         assert!(vm.net.as_ref().unwrap().contains("firewall=1"));
+        // one IPv4 + one IPv6 on the only NIC, address on the range prefix and
+        // gateway prefix stripped; 192.168.2.2 has an off-link gateway
         assert_eq!(
             vm.ip_config,
-            Some(
-                "ip=192.168.1.2/16,gw=192.168.1.1,ip=192.168.2.2/24,gw=10.10.10.10,ip6=auto"
-                    .to_string()
-            )
+            Some("ip=192.168.1.2/24,gw=192.168.1.1,ip6=auto".to_string())
+        );
+        assert_eq!(vm.ip_config_1, None);
+        assert_eq!(vm.ip_config_2, None);
+        assert_eq!(vm.ip_config_3, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_ip_configs_static_ipv6() -> Result<()> {
+        let mut cfg = mock_full_vm();
+        cfg.ranges[2].allocation_mode = IpRangeAllocationMode::Sequential;
+        cfg.ranges[2].gateway = "fd00::1/64".to_string();
+        cfg.ips.push(VmIpAssignment {
+            id: 4,
+            vm_id: 1,
+            ip_range_id: 3,
+            ip: "fd00::2".to_string(),
+            ..Default::default()
+        });
+
+        // the second IPv6 has no NIC to go on, so the config is refused
+        assert!(ProxmoxClient::make_ip_configs(&cfg).is_err());
+
+        cfg.ips.pop();
+        let configs = ProxmoxClient::make_ip_configs(&cfg)?;
+        assert_eq!(
+            configs,
+            vec![
+                "ip=192.168.1.2/24,gw=192.168.1.1,ip6=fd00::ff:ff:ff:ff:ff/64,gw6=fd00::1"
+                    .to_string(),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_ip_configs_delegated_prefix() -> Result<()> {
+        let mut cfg = mock_full_vm();
+        cfg.ranges[2].allocation_mode = IpRangeAllocationMode::DelegatedPrefix;
+        cfg.ranges[2].cidr = "2001:db8:100::/48".to_string();
+        cfg.ranges[2].gateway = "fe80::1".to_string();
+        cfg.ips[2].ip = "2001:db8:100:7::".to_string();
+        cfg.ips[2].prefix_length = Some(64);
+
+        let configs = ProxmoxClient::make_ip_configs(&cfg)?;
+        assert_eq!(
+            configs[0],
+            "ip=192.168.1.2/24,gw=192.168.1.1,ip6=2001:db8:100:7::1/64,gw6=fe80::1"
+        );
+        // the whole prefix passes the ip filter, not just the VM's own address
+        let entries: Vec<String> = ProxmoxClient::ipset_entries(&cfg)
+            .into_iter()
+            .map(|e| e.cidr)
+            .collect();
+        assert_eq!(entries[2], "2001:db8:100:7::/64");
+        Ok(())
+    }
+
+    #[test]
+    fn test_ip_configs_skip_bad_gateway() -> Result<()> {
+        let mut cfg = mock_full_vm();
+        // gateway with the wrong address family is never written
+        cfg.ranges[1].gateway = "fd00::1".to_string();
+        // neither is an unparseable one
+        cfg.ranges[0].gateway = "not-an-ip".to_string();
+
+        let configs = ProxmoxClient::make_ip_configs(&cfg)?;
+        assert_eq!(configs, vec!["ip6=auto".to_string()]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_ip_configs_skip_off_link_gateway() -> Result<()> {
+        let mut cfg = mock_full_vm();
+        // gateway outside the range and not declared with a covering prefix
+        cfg.ranges[0].gateway = "10.0.0.1".to_string();
+        cfg.ranges[1].gateway = "192.168.2.1".to_string();
+
+        let configs = ProxmoxClient::make_ip_configs(&cfg)?;
+        assert_eq!(
+            configs,
+            vec!["ip=192.168.2.2/24,gw=192.168.2.1,ip6=auto".to_string()]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_ip_configs_stale_slots_deleted() -> Result<()> {
+        let current = VmConfig {
+            ip_config: Some("ip=192.168.1.2/24,gw=192.168.1.1".to_string()),
+            ip_config_1: Some("ip=192.168.2.2/24,gw=192.168.2.1".to_string()),
+            ..Default::default()
+        };
+        let expected = VmConfig {
+            ip_config: Some("ip=192.168.1.2/24,gw=192.168.1.1".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            current.stale_ip_configs(&expected),
+            vec!["ipconfig1".to_string()]
         );
+        assert!(expected.stale_ip_configs(&current).is_empty());
+
+        let req = ConfigureVm {
+            node: "pve".to_string(),
+            vm_id: ProxmoxVmId::from(1u64),
+            delete: Some("ipconfig1".to_string()),
+            config: VmConfig {
+                ip_config_1: Some("ip=10.0.0.2/24,gw=10.0.0.1".to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let json = serde_json::to_value(&req)?;
+        assert_eq!(json["ipconfig1"], "ip=10.0.0.2/24,gw=10.0.0.1");
+        assert_eq!(json["delete"], "ipconfig1");
+        assert!(json.get("ipconfig0").is_none());
         Ok(())
     }
 
+    #[test]
+    fn test_hotplug_config() {
+        let current = VmConfig {
+            cores: Some(4),
+            vcpus: Some(2),
+            memory: Some("2048".to_string()),
+            hotplug: Some("network,disk,cpu,memory".to_string()),
+            numa: Some(true),
+            ..Default::default()
+        };
+        let cfg = current.hotplug_config(4, 4096).unwrap();
+        assert_eq!(cfg.vcpus, Some(4));
+        assert_eq!(cfg.memory, Some("4096".to_string()));
+        assert!(cfg.cores.is_none());
+
+        // more vCPUs than the topology allows needs a restart
+        assert!(current.hotplug_config(8, 2048).is_none());
+        // memory hot-unplug is not attempted
+        assert!(current.hotplug_config(2, 1024).is_none());
+
+        // memory hot-plug needs NUMA
+        let no_numa = VmConfig {
+            numa: None,
+            ..current
+        };
+        assert!(no_numa.hotplug_config(2, 4096).is_none());
+        assert_eq!(no_numa.hotplug_config(3, 2048).unwrap().vcpus, Some(3));
+
+        // default hot-plug features don't include cpu or memory
+        let default_hotplug = VmConfig {
+            hotplug: None,
+            numa: Some(true),
+            ..no_numa
+        };
+        assert!(default_hotplug.hotplug_config(3, 2048).is_none());
+        assert!(default_hotplug.hotplug_config(2, 4096).is_none());
+    }
+
+    #[test]
+    fn test_ipset_entries_both_families() {
+        let cfg = mock_full_vm();
+        let entries: Vec<(String, Option<String>)> = ProxmoxClient::ipset_entries(&cfg)
+            .into_iter()
+            .map(|e| (e.cidr, e.comment))
+            .collect();
+        assert_eq!(
+            entries,
+            vec![
+                (
+                    "192.168.1.2".to_string(),
+                    Some(IPSET_V4_COMMENT.to_string())
+                ),
+                (
+                    "192.168.2.2".to_string(),
+                    Some(IPSET_V4_COMMENT.to_string())
+                ),
+                (
+                    "fd00::ff:ff:ff:ff:ff".to_string(),
+                    Some(IPSET_V6_COMMENT.to_string())
+                ),
+            ]
+        );
+    }
+
     #[test]
     fn test_config_balloon_floor() -> Result<()> {
         let cfg = mock_full_vm();
@@ -2848,6 +4432,7 @@ mod tests {
             arch: "x86_64".to_string(),
             balloon_min_pct: Some(90),
             firewall_config: None,
+            reboot_timeout: None,
         };
         let p = ProxmoxClient::new("http://localhost:8006".parse()?, "", "", None, q_cfg, None);
 
@@ -2885,6 +4470,7 @@ mod tests {
             arch: "x86_64".to_string(),
             balloon_min_pct: None,
             firewall_config: None,
+            reboot_timeout: None,
         };
         let p = ProxmoxClient::new("http://localhost:8006".parse()?, "", "", None, q_cfg, None);
 
@@ -2906,7 +4492,44 @@ mod tests {
     }
 
     #[test]
-    fn test_kvm_field_deserializes_integer_to_bool() {
+    fn test_private_network_net_config() -> Result<()> {
+        let mut cfg = mock_full_vm();
+        cfg.host.vlan_id = Some(100);
+        cfg.host.private_bridge = Some("vmbr9".to_string());
+
+        let q_cfg = QemuConfig {
+            machine: "q35".to_string(),
+            os_type: "l26".to_string(),
+            bridge: "vmbr1".to_string(),
+            cpu: "kvm64".to_string(),
+            kvm: true,
+            arch: "x86_64".to_string(),
+            balloon_min_pct: None,
+            firewall_config: None,
+            reboot_timeout: None,
+        };
+        let p = ProxmoxClient::new("http://localhost:8006".parse()?, "", "", None, q_cfg, None);
+
+        // shared bridge by default
+        let net = p.make_config(&cfg, None)?.net.unwrap();
+        assert!(net.contains("bridge=vmbr1"), "{}", net);
+
+        // isolated bridge, keeping the host VLAN tag and the firewall
+        cfg.vm.private_network = true;
+        let net = p.make_config(&cfg, None)?.net.unwrap();
+        assert!(net.contains("bridge=vmbr9"), "{}", net);
+        assert!(!net.contains("bridge=vmbr1"), "{}", net);
+        assert!(net.contains("tag=100"), "{}", net);
+        assert!(net.contains("firewall=1"), "{}", net);
+
+        // never falls back to the shared bridge
+        cfg.host.private_bridge = None;
+        assert!(p.make_config(&cfg, None).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_kvm_field_deserializes_integer_to_bool() {
         // Test that KVM field can deserialize from integer (as Proxmox sends it)
         let json_with_int = r#"{"kvm":1}"#;
         let config: VmConfig =
@@ -2953,6 +4576,7 @@ mod tests {
             arch: "x86_64".to_string(),
             balloon_min_pct: None,
             firewall_config: None,
+            reboot_timeout: None,
         };
 
         let p = ProxmoxClient::new("http://localhost:8006".parse()?, "", "", None, q_cfg, None);
@@ -2982,6 +4606,7 @@ mod tests {
             arch: "x86_64".to_string(),
             balloon_min_pct: None,
             firewall_config: None,
+            reboot_timeout: None,
         };
 
         let p = ProxmoxClient::new("http://localhost:8006".parse()?, "", "", None, q_cfg, None);
@@ -3019,6 +4644,38 @@ mod tests {
         assert_eq!(pve.comment.as_deref(), Some("lnvps-fw:42"));
     }
 
+    #[test]
+    fn test_pve_firewall_rule_json() -> Result<()> {
+        let rule = lnvps_db::VmFirewallRule {
+            id: 42,
+            vm_id: 1,
+            priority: 0,
+            direction: lnvps_db::VmFirewallDirection::Inbound,
+            protocol: lnvps_db::VmFirewallProtocol::Tcp,
+            action: lnvps_db::VmFirewallRuleAction::Drop,
+            src_cidr: Some("1.2.3.0/24".to_string()),
+            dst_port_start: Some(22),
+            dst_port_end: None,
+            enabled: true,
+            created: Default::default(),
+            updated: Default::default(),
+        };
+        let pve = ProxmoxClient::to_pve_firewall_rules(&rule);
+        assert_eq!(
+            serde_json::to_value(&pve[0])?,
+            serde_json::json!({
+                "action": "DROP",
+                "comment": "lnvps-fw:42",
+                "dport": "22",
+                "enable": 1,
+                "proto": "tcp",
+                "source": "1.2.3.0/24",
+                "type": "in",
+            })
+        );
+        Ok(())
+    }
+
     #[test]
     fn test_to_pve_firewall_rule_outbound_any_single_port_expands_to_tcp_udp() {
         let rule = lnvps_db::VmFirewallRule {
@@ -3140,6 +4797,43 @@ mod tests {
         assert_eq!(parse_storage_from_disk(""), None);
     }
 
+    #[test]
+    fn test_parse_ips_from_ip_config() {
+        assert_eq!(
+            parse_ips_from_ip_config("ip=10.0.0.5/24,gw=10.0.0.1,ip6=2001:db8::5/64,gw6=fe80::1"),
+            vec!["10.0.0.5".to_string(), "2001:db8::5".to_string()]
+        );
+        assert!(parse_ips_from_ip_config("ip=dhcp,ip6=auto").is_empty());
+    }
+
+    #[test]
+    fn test_data_disk_slot_and_volume() {
+        let mut disk = DataDisk {
+            disk: lnvps_db::VmExtraDisk {
+                slot: 1,
+                size: 50 * GB,
+                ..Default::default()
+            },
+            storage: lnvps_db::VmHostDisk {
+                name: "local-lvm".to_string(),
+                kind: DiskType::SSD,
+                interface: DiskInterface::SCSI,
+                ..Default::default()
+            },
+        };
+        // SCSI slots start after the primary disk and the cloud-init drive
+        assert_eq!(data_disk_slot(&disk), "scsi3");
+        assert_eq!(data_disk_volume(&disk), "local-lvm:50,discard=on,ssd=1");
+
+        disk.storage.interface = DiskInterface::PCIe;
+        assert_eq!(data_disk_slot(&disk), "virtio1");
+        assert_eq!(data_disk_volume(&disk), "local-lvm:50,discard=on");
+
+        disk.storage.kind = DiskType::HDD;
+        disk.disk.size = 50 * GB + 1;
+        assert_eq!(data_disk_volume(&disk), "local-lvm:51");
+    }
+
     #[test]
     fn test_proxmox_vm_id_inner_maps_to_db_id() {
         // Host vmid 1566 -> db id 1466
@@ -3201,6 +4895,7 @@ mod tests {
             arch: "x86_64".to_string(),
             balloon_min_pct: None,
             firewall_config: None,
+            reboot_timeout: None,
         };
 
         let p = ProxmoxClient::new("http://localhost:8006".parse()?, "", "", None, q_cfg, None);
@@ -3244,6 +4939,7 @@ mod tests {
             arch: "x86_64".to_string(),
             balloon_min_pct: None,
             firewall_config: None,
+            reboot_timeout: None,
         };
         let p = ProxmoxClient::new("http://localhost:8006".parse()?, "", "", None, q_cfg, None);
 
@@ -3304,6 +5000,7 @@ mod tests {
             arch: "x86_64".to_string(),
             balloon_min_pct: None,
             firewall_config: None,
+            reboot_timeout: None,
         };
         let client = ProxmoxClient::new(server.uri().parse()?, "pve", "", None, q_cfg, None);
 
@@ -3320,6 +5017,304 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_get_guest_info() -> Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path_regex(r".*/status/current$"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "data": { "vmid": 100, "status": "running", "uptime": 3600 }
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path_regex(r".*/agent/network-get-interfaces$"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "data": { "result": [
+                    {
+                        "name": "lo",
+                        "hardware-address": "00:00:00:00:00:00",
+                        "ip-addresses": [
+                            { "ip-address": "127.0.0.1", "ip-address-type": "ipv4", "prefix": 8 }
+                        ]
+                    },
+                    {
+                        "name": "eth0",
+                        "hardware-address": "bc:24:11:00:00:01",
+                        "ip-addresses": [
+                            { "ip-address": "10.0.0.2", "ip-address-type": "ipv4", "prefix": 24 },
+                            { "ip-address": "fe80::1", "ip-address-type": "ipv6", "prefix": 64 }
+                        ]
+                    },
+                    { "name": "docker0" }
+                ]}
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path_regex(r".*/agent/get-fsinfo$"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "data": { "result": [
+                    { "name": "sda1", "mountpoint": "/", "type": "ext4",
+                      "used-bytes": 9_000_000_000u64, "total-bytes": 10_000_000_000u64 },
+                    // bind mount of the same device
+                    { "name": "sda1", "mountpoint": "/var/lib/docker", "type": "ext4",
+                      "used-bytes": 9_000_000_000u64, "total-bytes": 10_000_000_000u64 },
+                    // snap packages are always full
+                    { "name": "loop0", "mountpoint": "/snap/core/1", "type": "squashfs",
+                      "used-bytes": 100, "total-bytes": 100 },
+                    { "name": "sdb", "mountpoint": "/data", "type": "xfs",
+                      "used-bytes": 1_000_000_000u64, "total-bytes": 100_000_000_000u64 }
+                ]}
+            })))
+            .mount(&server)
+            .await;
+
+        let client = ProxmoxClient::new(
+            server.uri().parse()?,
+            "pve",
+            "",
+            None,
+            test_qemu_config(),
+            None,
+        );
+        let mut vm = mock_full_vm().vm;
+        vm.id = 1;
+        let info = VmHostClient::get_guest_info(&client, &vm).await?;
+        assert_eq!(info.uptime, Some(3600));
+        assert_eq!(info.interfaces.len(), 3);
+        assert_eq!(info.interfaces[1].name, "eth0");
+        assert_eq!(
+            info.interfaces[1].mac_address.as_deref(),
+            Some("bc:24:11:00:00:01")
+        );
+        assert_eq!(
+            info.interfaces[1].addresses,
+            vec!["10.0.0.2/24", "fe80::1/64"]
+        );
+        assert!(info.interfaces[2].addresses.is_empty());
+        let mounts: Vec<&str> = info
+            .filesystems
+            .iter()
+            .map(|f| f.mountpoint.as_str())
+            .collect();
+        assert_eq!(mounts, vec!["/", "/data"]);
+        assert_eq!(info.max_disk_usage(), Some(90.0));
+        Ok(())
+    }
+
+    thread_local! {
+        /// Log messages captured on this thread, `None` when not capturing
+        static CAPTURED_LOGS: std::cell::RefCell<Option<Vec<String>>> =
+            const { std::cell::RefCell::new(None) };
+    }
+
+    /// Logger which only records messages emitted on threads that called
+    /// [start_log_capture], so tests running in parallel never see each
+    /// other's output
+    struct ThreadCaptureLogger;
+
+    impl log::Log for ThreadCaptureLogger {
+        fn enabled(&self, _: &log::Metadata) -> bool {
+            CAPTURED_LOGS.with(|c| c.borrow().is_some())
+        }
+
+        fn log(&self, record: &log::Record) {
+            CAPTURED_LOGS.with(|c| {
+                if let Some(logs) = c.borrow_mut().as_mut() {
+                    logs.push(record.args().to_string());
+                }
+            });
+        }
+
+        fn flush(&self) {}
+    }
+
+    static CAPTURE_LOGGER: ThreadCaptureLogger = ThreadCaptureLogger;
+    static CAPTURE_LOGGER_INIT: std::sync::Once = std::sync::Once::new();
+
+    /// Start capturing log messages emitted on the current thread
+    fn start_log_capture() {
+        CAPTURE_LOGGER_INIT.call_once(|| {
+            if log::set_logger(&CAPTURE_LOGGER).is_ok() {
+                log::set_max_level(log::LevelFilter::Trace);
+            }
+        });
+        CAPTURED_LOGS.with(|c| *c.borrow_mut() = Some(Vec::new()));
+    }
+
+    /// Stop capturing and return the messages logged on the current thread
+    fn take_captured_logs() -> Vec<String> {
+        CAPTURED_LOGS.with(|c| c.borrow_mut().take().unwrap_or_default())
+    }
+
+    // single threaded runtime so every log line from the client is emitted
+    // on the capturing thread
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_reset_password_not_logged() -> Result<()> {
+        start_log_capture();
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path_regex(r".*/qemu/\d+/config$"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "data": null
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path_regex(r".*/status/current$"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "data": { "vmid": 100, "status": "running" }
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path_regex(r".*/agent/set-user-password$"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "data": { "result": {} }
+            })))
+            .mount(&server)
+            .await;
+
+        let client = ProxmoxClient::new(
+            server.uri().parse()?,
+            "pve",
+            "",
+            None,
+            test_qemu_config(),
+            None,
+        );
+        let mut cfg = mock_full_vm();
+        cfg.image.default_username = Some("debian".to_string());
+        let password = "Xq7-secret-Pa55word";
+        assert_eq!(
+            VmHostClient::reset_password(&client, &cfg, password).await?,
+            PasswordResetMethod::GuestAgent
+        );
+
+        // the password went to both the cloud-init config and the guest agent
+        let requests = server.received_requests().await.unwrap_or_default();
+        let bodies: Vec<String> = requests
+            .iter()
+            .filter(|r| r.method.as_str() == "POST")
+            .map(|r| String::from_utf8_lossy(&r.body).to_string())
+            .collect();
+        assert_eq!(bodies.len(), 2);
+        assert!(bodies.iter().all(|b| b.contains(password)));
+        assert!(bodies[1].contains("debian"));
+
+        let logs = take_captured_logs();
+        assert!(
+            logs.iter().any(|l| l.contains("<redacted>")),
+            "request logs were not captured"
+        );
+        assert!(
+            !logs.iter().any(|l| l.contains(password)),
+            "password leaked into the log"
+        );
+        Ok(())
+    }
+
+    /// A stopped VM is started and the password confirmed by the guest agent
+    /// once it has booted
+    #[tokio::test]
+    async fn test_reset_password_starts_stopped_vm() -> Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path_regex(r".*/qemu/\d+/config$"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "data": null
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path_regex(r".*/status/current$"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "data": { "vmid": 100, "status": "stopped" }
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path_regex(r".*/status/start$"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(serde_json::json!({"data": "start"})),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path_regex(r"/tasks/start/status$"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(serde_json::json!({ "data": {
+                    "id": "start", "node": "pve", "pid": 1, "pstart": 1, "starttime": 1,
+                    "status": "stopped", "type": "task", "upid": "start", "user": "root@pam",
+                    "exitstatus": "OK"
+                }})),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path_regex(r".*/agent/set-user-password$"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "data": { "result": {} }
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = ProxmoxClient::new(
+            server.uri().parse()?,
+            "pve",
+            "",
+            None,
+            test_qemu_config(),
+            None,
+        );
+        let cfg = mock_full_vm();
+        assert_eq!(
+            VmHostClient::reset_password(&client, &cfg, "Xq7-secret-Pa55word").await?,
+            PasswordResetMethod::Started
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_guest_info_agent_not_running() -> Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path_regex(r".*/status/current$"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "data": { "vmid": 100, "status": "running" }
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path_regex(r".*/agent/network-get-interfaces$"))
+            .respond_with(ResponseTemplate::new(500).set_body_json(serde_json::json!({
+                "data": null,
+                "message": "QEMU guest agent is not running"
+            })))
+            .mount(&server)
+            .await;
+
+        let client = ProxmoxClient::new(
+            server.uri().parse()?,
+            "pve",
+            "",
+            None,
+            test_qemu_config(),
+            None,
+        );
+        let vm = mock_full_vm().vm;
+        let err = VmHostClient::get_guest_info(&client, &vm)
+            .await
+            .err()
+            .expect("agent is not running");
+        assert!(err.to_string().contains("Guest agent is not running"));
+        Ok(())
+    }
+
     /// Regression test: `apply_disk_limits` must preserve `discard=on,ssd=1` for SSD disks.
     ///
     /// Before the fix, `apply_disk_limits` stripped all existing disk params (including
@@ -3365,6 +5360,7 @@ mod tests {
             arch: "x86_64".to_string(),
             balloon_min_pct: None,
             firewall_config: None,
+            reboot_timeout: None,
         };
         let client = ProxmoxClient::new(server.uri().parse()?, "pve", "", None, q_cfg, None);
 
@@ -3423,6 +5419,7 @@ mod tests {
             arch: "x86_64".to_string(),
             balloon_min_pct: None,
             firewall_config: None,
+            reboot_timeout: None,
         }
     }
 
@@ -3543,4 +5540,515 @@ mod tests {
 
         Ok(())
     }
+
+    /// `patch_firewall` must keep both address families in the `ipfilter-net0`
+    /// IPset: missing IPv4/IPv6 entries are added and our stale ones removed.
+    #[tokio::test]
+    async fn test_patch_firewall_syncs_ipv4_and_ipv6_ipset() -> Result<()> {
+        let server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path_regex(r".*/qemu/\d+/config$"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "data": {
+                    "digest": "d",
+                    "ipconfig0": "ip=192.168.1.2/24,gw=192.168.1.1,ip6=auto"
+                }
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("PUT"))
+            .and(path_regex(r".*/firewall/options$"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(serde_json::json!({"data": null})),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path_regex(r".*/firewall/ipset/ipfilter-net0$"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "data": [
+                    {"cidr": "192.168.1.2", "comment": IPSET_V4_COMMENT},
+                    {"cidr": "fd00::dead", "comment": IPSET_V6_COMMENT},
+                    {"cidr": "10.99.0.1", "comment": "added by hand"}
+                ]
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path_regex(r".*/firewall/ipset/ipfilter-net0$"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(serde_json::json!({"data": null})),
+            )
+            .expect(2)
+            .mount(&server)
+            .await;
+        Mock::given(method("DELETE"))
+            .and(path_regex(r".*/firewall/ipset/ipfilter-net0/.+$"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(serde_json::json!({"data": null})),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path_regex(r".*/firewall/rules$"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({"data": []})))
+            .mount(&server)
+            .await;
+
+        let mut q_cfg = test_qemu_config();
+        q_cfg.firewall_config = Some(crate::settings::FirewallConfig {
+            dhcp: None,
+            enable: Some(true),
+            ip_filter: Some(true),
+            mac_filter: None,
+            ndp: None,
+            policy_in: None,
+            policy_out: None,
+        });
+        let client = ProxmoxClient::new(server.uri().parse()?, "pve", "", None, q_cfg, None);
+        client.patch_firewall(&mock_full_vm()).await?;
+
+        let requests = server.received_requests().await.unwrap();
+        let mut added: Vec<String> = requests
+            .iter()
+            .filter(|r| r.method == wiremock::http::Method::POST)
+            .map(|r| {
+                let body: serde_json::Value = serde_json::from_slice(&r.body).unwrap();
+                body["cidr"].as_str().unwrap().to_string()
+            })
+            .collect();
+        added.sort();
+        assert_eq!(
+            added,
+            vec![
+                "192.168.2.2".to_string(),
+                "fd00::ff:ff:ff:ff:ff".to_string()
+            ]
+        );
+        let removed = requests
+            .iter()
+            .find(|r| r.method == wiremock::http::Method::DELETE)
+            .expect("expected the stale IPv6 entry to be removed");
+        assert!(
+            removed
+                .url
+                .path()
+                .ends_with(&*urlencoding::encode("fd00::dead"))
+        );
+
+        Ok(())
+    }
+
+    /// Host clients are built from the stored host on every use, so a rotated
+    /// API token must be picked up by the next client without a restart.
+    #[tokio::test]
+    async fn test_host_client_uses_rotated_api_token() -> Result<()> {
+        use crate::host::get_host_client;
+        use lnvps_api_common::MockDb;
+        use lnvps_db::{LNVpsDbBase, VmHostKind};
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path_regex(r".*/nodes$"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({"data": []})))
+            .mount(&server)
+            .await;
+
+        let db = MockDb::default();
+        {
+            let mut hosts = db.hosts.lock().await;
+            let host = hosts.get_mut(&1).unwrap();
+            host.kind = VmHostKind::Proxmox;
+            host.ip = server.uri();
+            host.api_token = "old-token".into();
+        }
+        let cfg = crate::settings::mock_settings().provisioner;
+
+        let host = db.get_host(1).await?;
+        let _ = get_host_client(&host, &cfg)?.get_info().await;
+
+        db.update_host_api_token(1, &"new-token".into()).await?;
+        let host = db.get_host(1).await?;
+        assert_eq!(host.api_token.as_str(), "new-token");
+        let _ = get_host_client(&host, &cfg)?.get_info().await;
+
+        let tokens: Vec<String> = server
+            .received_requests()
+            .await
+            .unwrap()
+            .iter()
+            .filter_map(|r| r.headers.get("authorization"))
+            .map(|v| v.to_str().unwrap().to_string())
+            .collect();
+        assert_eq!(tokens.first().unwrap(), "PVEAPIToken=old-token");
+        assert_eq!(tokens.last().unwrap(), "PVEAPIToken=new-token");
+        Ok(())
+    }
+
+    #[test]
+    fn test_vm_info_suspended_and_migrating_state() -> Result<()> {
+        let state = |json: &str| -> Result<VmRunningStates> {
+            let info: VmInfo = serde_json::from_str(json)?;
+            Ok(VmRunningState::from(info).state)
+        };
+        assert_eq!(
+            state(r#"{"status":"running","vmid":100,"qmpstatus":"paused"}"#)?,
+            VmRunningStates::Suspended
+        );
+        assert_eq!(
+            state(r#"{"status":"running","vmid":100,"qmpstatus":"running"}"#)?,
+            VmRunningStates::Running
+        );
+        // listings without full=1 have no qmpstatus
+        assert_eq!(
+            state(r#"{"status":"running","vmid":100}"#)?,
+            VmRunningStates::Running
+        );
+        assert_eq!(
+            state(r#"{"status":"stopped","vmid":100,"qmpstatus":"stopped"}"#)?,
+            VmRunningStates::Stopped
+        );
+        // migration pauses the VM at the end, the lock wins
+        assert_eq!(
+            state(r#"{"status":"running","vmid":100,"qmpstatus":"paused","lock":"migrate"}"#)?,
+            VmRunningStates::Migrating
+        );
+        assert_eq!(
+            state(r#"{"status":"running","vmid":100,"lock":"backup"}"#)?,
+            VmRunningStates::Running
+        );
+        Ok(())
+    }
+
+    /// Run a shell command locally, standing in for `ssh_run`
+    async fn run_local(cmd: String) -> Result<(i32, String)> {
+        let out = tokio::task::spawn_blocking(move || {
+            std::process::Command::new("sh").arg("-c").arg(cmd).output()
+        })
+        .await??;
+        let mut output = String::from_utf8_lossy(&out.stdout).to_string();
+        output.push_str(&String::from_utf8_lossy(&out.stderr));
+        Ok((out.status.code().unwrap_or(-1), output))
+    }
+
+    /// Serve `body` over HTTP with `Range` support. The first full GET is cut
+    /// off halfway through, returns the base url and the `Range` header of
+    /// each GET
+    async fn serve_flaky(body: Vec<u8>) -> Result<(String, Arc<std::sync::Mutex<Vec<String>>>)> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let base = format!("http://{}", listener.local_addr()?);
+        let ranges = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = ranges.clone();
+        tokio::spawn(async move {
+            let mut dropped = false;
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut req = Vec::new();
+                let mut buf = [0u8; 1024];
+                while !req.windows(4).any(|w| w == b"\r\n\r\n") {
+                    match stream.read(&mut buf).await {
+                        Ok(0) | Err(_) => break,
+                        Ok(n) => req.extend_from_slice(&buf[..n]),
+                    }
+                }
+                let req = String::from_utf8_lossy(&req).to_lowercase();
+                let len = body.len();
+                if req.starts_with("head ") {
+                    let rsp = format!(
+                        "HTTP/1.1 200 OK\r\nContent-Length: {len}\r\nAccept-Ranges: bytes\r\nConnection: close\r\n\r\n"
+                    );
+                    let _ = stream.write_all(rsp.as_bytes()).await;
+                    continue;
+                }
+                let range = req
+                    .lines()
+                    .find_map(|l| l.strip_prefix("range: bytes="))
+                    .map(|r| r.trim().to_string());
+                seen.lock().unwrap().push(range.clone().unwrap_or_default());
+                let start: usize = range
+                    .as_deref()
+                    .and_then(|r| r.trim_end_matches('-').parse().ok())
+                    .unwrap_or(0);
+                let (head, end) = if start > 0 {
+                    (
+                        format!(
+                            "HTTP/1.1 206 Partial Content\r\nContent-Length: {}\r\nContent-Range: bytes {}-{}/{}\r\nConnection: close\r\n\r\n",
+                            len - start,
+                            start,
+                            len - 1,
+                            len
+                        ),
+                        len,
+                    )
+                } else {
+                    let end = if dropped { len } else { len / 2 };
+                    dropped = true;
+                    (
+                        format!(
+                            "HTTP/1.1 200 OK\r\nContent-Length: {len}\r\nAccept-Ranges: bytes\r\nConnection: close\r\n\r\n"
+                        ),
+                        end,
+                    )
+                };
+                let _ = stream.write_all(head.as_bytes()).await;
+                let _ = stream.write_all(&body[start..end]).await;
+                let _ = stream.shutdown().await;
+            }
+        });
+        Ok((base, ranges))
+    }
+
+    #[tokio::test]
+    async fn test_download_resumes_after_dropped_connection() -> Result<()> {
+        let body: Vec<u8> = (0..256 * 1024).map(|i| (i % 251) as u8).collect();
+        let (base, ranges) = serve_flaky(body.clone()).await?;
+        let url = format!("{base}/debian.qcow2");
+        let dir = std::env::temp_dir().join(format!("lnvps-dl-{}", random::<u64>()));
+        let dir = dir.to_str().unwrap().to_string();
+
+        let attempts = std::sync::atomic::AtomicU32::new(0);
+        retry_async(
+            RetryPolicy::default()
+                .with_min_delay(Duration::from_millis(10))
+                .with_max_delay(Duration::from_millis(10))
+                .with_max_retries(3),
+            || async {
+                attempts.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                let size = probe_image(&url).await?;
+                download_resumable(run_local, &dir, "debian.qcow2", &url, size).await
+            },
+        )
+        .await?;
+
+        assert_eq!(attempts.load(std::sync::atomic::Ordering::SeqCst), 2);
+        let ranges = ranges.lock().unwrap().clone();
+        assert_eq!(ranges, vec![String::new(), format!("{}-", body.len() / 2)]);
+        assert_eq!(std::fs::read(format!("{dir}/debian.qcow2"))?, body);
+        assert!(!std::path::Path::new(&format!("{dir}/debian.qcow2.part")).exists());
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_download_size_mismatch_is_removed() -> Result<()> {
+        let body = vec![7u8; 1024];
+        let (base, _) = serve_flaky(body).await?;
+        let url = format!("{base}/debian.qcow2");
+        let dir = std::env::temp_dir().join(format!("lnvps-dl-{}", random::<u64>()));
+        let dir = dir.to_str().unwrap().to_string();
+
+        // first attempt is cut off, the resumed file is complete
+        assert!(matches!(
+            download_resumable(run_local, &dir, "debian.qcow2", &url, Some(1024)).await,
+            Err(OpError::Transient(_))
+        ));
+        assert!(matches!(
+            download_resumable(run_local, &dir, "debian.qcow2", &url, Some(2048)).await,
+            Err(OpError::Transient(_))
+        ));
+        assert!(!std::path::Path::new(&format!("{dir}/debian.qcow2")).exists());
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_download_complete_part_is_finished() -> Result<()> {
+        // a server asked to resume past the end answers 416
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(416))
+            .mount(&server)
+            .await;
+        let url = format!("{}/debian.qcow2", server.uri());
+        let dir = std::env::temp_dir().join(format!("lnvps-dl-{}", random::<u64>()));
+        let dir = dir.to_str().unwrap().to_string();
+        std::fs::create_dir_all(&dir)?;
+        std::fs::write(format!("{dir}/debian.qcow2.part"), vec![7u8; 1024])?;
+
+        download_resumable(run_local, &dir, "debian.qcow2", &url, Some(1024)).await?;
+        assert_eq!(
+            std::fs::read(format!("{dir}/debian.qcow2"))?,
+            vec![7u8; 1024]
+        );
+        assert!(!std::path::Path::new(&format!("{dir}/debian.qcow2.part")).exists());
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_probe_image() -> Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("HEAD"))
+            .and(path_regex("^/ok$"))
+            .respond_with(ResponseTemplate::new(200).insert_header("content-length", "1024"))
+            .mount(&server)
+            .await;
+        // HEAD is rejected but a ranged GET works
+        Mock::given(method("HEAD"))
+            .and(path_regex("^/nohead$"))
+            .respond_with(ResponseTemplate::new(405))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path_regex("^/nohead$"))
+            .respond_with(
+                ResponseTemplate::new(206)
+                    .insert_header("content-range", "bytes 0-0/2048")
+                    .set_body_bytes(vec![0u8]),
+            )
+            .mount(&server)
+            .await;
+        for (p, code) in [("/missing", 404), ("/private", 403), ("/busy", 503)] {
+            for m in ["HEAD", "GET"] {
+                Mock::given(method(m))
+                    .and(path_regex(format!("^{p}$")))
+                    .respond_with(ResponseTemplate::new(code))
+                    .mount(&server)
+                    .await;
+            }
+        }
+
+        let url = |p: &str| format!("{}{}", server.uri(), p);
+        assert_eq!(probe_image(&url("/ok")).await.ok(), Some(Some(1024)));
+        assert_eq!(probe_image(&url("/nohead")).await.ok(), Some(Some(2048)));
+        assert!(matches!(
+            probe_image(&url("/missing")).await,
+            Err(OpError::Fatal(_))
+        ));
+        assert!(matches!(
+            probe_image(&url("/private")).await,
+            Err(OpError::Fatal(_))
+        ));
+        assert!(matches!(
+            probe_image(&url("/busy")).await,
+            Err(OpError::Transient(_))
+        ));
+        // nothing listening
+        let closed = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await?
+            .local_addr()?;
+        assert!(matches!(
+            probe_image(&format!("http://{closed}/img")).await,
+            Err(OpError::Transient(_))
+        ));
+        Ok(())
+    }
+
+    /// A container template swap recreates the container with `force` from a
+    /// freshly downloaded template, keeping its mount points and settings
+    #[tokio::test]
+    async fn test_recreate_container_keeps_mount_points() -> Result<()> {
+        let server = MockServer::start().await;
+        let task = |upid: &str| {
+            serde_json::json!({ "data": {
+                "id": upid, "node": "pve", "pid": 1, "pstart": 1, "starttime": 1,
+                "status": "stopped", "type": "task", "upid": upid, "user": "root@pam",
+                "exitstatus": "OK"
+            }})
+        };
+        Mock::given(method("GET"))
+            .and(path_regex(r"/nodes/pve/storage$"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "data": [{ "content": "iso,vztmpl", "storage": "local", "type": "dir" }]
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path_regex(r"/storage/local/content$"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(serde_json::json!({ "data": [] })),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path_regex(r"/storage/local/download-url$"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(serde_json::json!({ "data": "dl" })),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path_regex(r"/nodes/pve/lxc$"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "data": [{ "vmid": 101, "status": "running" }]
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path_regex(r"/lxc/101/config$"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "data": {
+                    "digest": "abc",
+                    "hostname": "vm1",
+                    "ostype": "debian",
+                    "rootfs": "local-lvm:vm-101-disk-0,size=8G",
+                    "mp0": "local-lvm:vm-101-disk-1,mp=/data,size=10G",
+                    "unused0": "local-lvm:vm-101-disk-2"
+                }
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path_regex(r"/lxc/101/status/stop$"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(serde_json::json!({ "data": "stop" })),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path_regex(r"/nodes/pve/lxc$"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(serde_json::json!({ "data": "create" })),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+        for upid in ["dl", "stop", "create"] {
+            Mock::given(method("GET"))
+                .and(path_regex(format!(r"/tasks/{}/status$", upid)))
+                .respond_with(ResponseTemplate::new(200).set_body_json(task(upid)))
+                .mount(&server)
+                .await;
+        }
+
+        let client = ProxmoxClient::new(
+            server.uri().parse()?,
+            "pve",
+            "",
+            None,
+            test_qemu_config(),
+            None,
+        );
+        let mut cfg = mock_full_vm();
+        cfg.image.image_kind = lnvps_db::OsImageKind::ContainerTemplate;
+        cfg.image.url = "http://localhost.com/debian-12-standard.tar.zst".to_string();
+        assert!(VmHostClient::is_container(&client, &cfg.vm).await?);
+        VmHostClient::recreate_container(&client, &cfg).await?;
+
+        let requests = server.received_requests().await.unwrap_or_default();
+        let create = requests
+            .iter()
+            .find(|r| r.method.as_str() == "POST" && r.url.path().ends_with("/nodes/pve/lxc"))
+            .expect("container created");
+        let body: serde_json::Value = serde_json::from_slice(&create.body)?;
+        assert_eq!(body["vmid"], 101);
+        assert_eq!(
+            body["ostemplate"],
+            "local:vztmpl/debian-12-standard.tar.zst"
+        );
+        assert_eq!(body["force"], true);
+        assert_eq!(body["rootfs"], "local-lvm:100");
+        assert_eq!(body["mp0"], "local-lvm:vm-101-disk-1,mp=/data,size=10G");
+        assert_eq!(body["hostname"], "vm1");
+        for key in ["digest", "ostype", "unused0"] {
+            assert!(body.get(key).is_none(), "{} carried over", key);
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,172 @@
+use crate::settings::JobConcurrencyConfig;
+use lnvps_api_common::{WorkJob, WorkJobMessage};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, RwLock};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Limits how many worker jobs run at once, per job type.
+///
+/// Job types with a configured limit get their own allowance, so a flood of
+/// one type (e.g. `CheckVm`) can't starve another (e.g. `StartVm`). All other
+/// job types share the default allowance.
+#[derive(Clone)]
+pub struct JobLimiter {
+    /// Semaphores for the current limits, replaced on [Self::reconfigure]
+    semaphores: Arc<RwLock<JobSemaphores>>,
+    /// Ids of jobs accepted but not finished, a job still running when its
+    /// message is re-claimed from the queue must not run twice
+    running: Arc<Mutex<HashSet<String>>>,
+}
+
+struct JobSemaphores {
+    default: Arc<Semaphore>,
+    /// Semaphores keyed by job type name (the [WorkJob] `Display` value)
+    limits: HashMap<String, Arc<Semaphore>>,
+}
+
+impl JobSemaphores {
+    fn new(config: &JobConcurrencyConfig) -> Self {
+        Self {
+            default: Arc::new(Semaphore::new(config.default.max(1))),
+            limits: config
+                .limits
+                .iter()
+                .map(|(k, v)| (k.clone(), Arc::new(Semaphore::new((*v).max(1)))))
+                .collect(),
+        }
+    }
+}
+
+impl JobLimiter {
+    pub fn new(config: &JobConcurrencyConfig) -> Self {
+        Self {
+            semaphores: Arc::new(RwLock::new(JobSemaphores::new(config))),
+            running: Default::default(),
+        }
+    }
+
+    /// Apply new limits to jobs accepted from now on. Jobs already accepted
+    /// keep their old allowance, so the old and new limits may both be in use
+    /// until those jobs finish.
+    pub fn reconfigure(&self, config: &JobConcurrencyConfig) {
+        *self.semaphores.write().unwrap() = JobSemaphores::new(config);
+    }
+
+    fn semaphore(&self, job: &WorkJob) -> Arc<Semaphore> {
+        let semaphores = self.semaphores.read().unwrap();
+        semaphores
+            .limits
+            .get(&job.to_string())
+            .unwrap_or(&semaphores.default)
+            .clone()
+    }
+
+    /// Accept a job, `None` when a job with the same id is still running
+    pub fn accept(&self, msg: &WorkJobMessage) -> Option<JobSlot> {
+        let mut running = self.running.lock().unwrap();
+        if !running.insert(msg.id.clone()) {
+            return None;
+        }
+        Some(JobSlot {
+            id: msg.id.clone(),
+            semaphore: self.semaphore(&msg.job),
+            running: self.running.clone(),
+        })
+    }
+}
+
+/// An accepted job, waiting for or holding a place to run
+pub struct JobSlot {
+    id: String,
+    semaphore: Arc<Semaphore>,
+    running: Arc<Mutex<HashSet<String>>>,
+}
+
+impl JobSlot {
+    /// Wait until the job may run, the job runs while the permit is held
+    pub async fn acquire(&self) -> OwnedSemaphorePermit {
+        self.semaphore
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("job semaphore is never closed")
+    }
+}
+
+impl Drop for JobSlot {
+    fn drop(&mut self) {
+        self.running.lock().unwrap().remove(&self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn msg(id: &str, job: WorkJob) -> WorkJobMessage {
+        WorkJobMessage {
+            id: id.to_string(),
+            job,
+            is_pending: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fifth_host_sync_job_waits() {
+        let limiter = JobLimiter::new(&JobConcurrencyConfig {
+            default: 1,
+            limits: HashMap::from([("CheckVm".to_string(), 4), ("StartVm".to_string(), 1)]),
+        });
+
+        let slots: Vec<JobSlot> = (0..5)
+            .map(|i| {
+                limiter
+                    .accept(&msg(&i.to_string(), WorkJob::CheckVm { vm_id: i }))
+                    .unwrap()
+            })
+            .collect();
+        let mut running = Vec::new();
+        for slot in &slots[..4] {
+            running.push(slot.acquire().await);
+        }
+
+        // the fifth waits while four run
+        let fifth = slots[4].acquire();
+        tokio::pin!(fifth);
+        assert!(
+            tokio::time::timeout(Duration::from_millis(50), &mut fifth)
+                .await
+                .is_err()
+        );
+
+        // other job types have their own allowance
+        let start = limiter
+            .accept(&msg(
+                "start",
+                WorkJob::StartVm {
+                    vm_id: 1,
+                    admin_user_id: None,
+                },
+            ))
+            .unwrap();
+        tokio::time::timeout(Duration::from_millis(50), start.acquire())
+            .await
+            .expect("StartVm should not wait for CheckVm jobs");
+
+        // the fifth runs once one finishes
+        running.pop();
+        tokio::time::timeout(Duration::from_millis(50), &mut fifth)
+            .await
+            .expect("fifth job should run after one finishes");
+    }
+
+    #[test]
+    fn test_running_job_not_accepted_twice() {
+        let limiter = JobLimiter::new(&JobConcurrencyConfig::default());
+        let slot = limiter.accept(&msg("1", WorkJob::CheckVms)).unwrap();
+        assert!(limiter.accept(&msg("1", WorkJob::CheckVms)).is_none());
+        drop(slot);
+        assert!(limiter.accept(&msg("1", WorkJob::CheckVms)).is_some());
+    }
+}
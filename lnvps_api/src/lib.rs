@@ -2,10 +2,14 @@ pub mod api;
 pub mod data_migration;
 pub mod fee_estimate;
 pub mod host;
+pub mod job_limiter;
+pub mod metrics;
+pub mod nostr_domain;
 pub mod notifications;
 pub mod payment_factory;
 pub mod payments;
 pub mod provisioner;
+pub mod reachability;
 pub mod referral;
 pub mod router;
 pub mod settings;
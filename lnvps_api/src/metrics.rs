@@ -0,0 +1,167 @@
+use axum::Router;
+use axum::extract::{MatchedPath, Request};
+use axum::middleware::Next;
+use axum::response::Response;
+use axum::routing::get;
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, Opts, Registry, TextEncoder,
+};
+use std::sync::LazyLock;
+use std::time::Instant;
+
+static METRICS: LazyLock<ApiMetrics> = LazyLock::new(ApiMetrics::new);
+
+/// Prometheus metrics for the API and worker
+#[derive(Clone)]
+pub struct ApiMetrics {
+    registry: Registry,
+    /// Requests by (method, route, status)
+    pub http_requests: IntCounterVec,
+    /// Request latency by (method, route)
+    pub http_request_duration: HistogramVec,
+    /// VM operation outcomes by (operation, outcome)
+    pub vm_operations: IntCounterVec,
+}
+
+impl ApiMetrics {
+    pub fn new() -> Self {
+        let registry = Registry::new();
+
+        let http_requests = IntCounterVec::new(
+            Opts::new("lnvps_http_requests_total", "Number of HTTP requests"),
+            &["method", "route", "status"],
+        )
+        .expect("Failed to create http_requests");
+
+        let http_request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "lnvps_http_request_duration_seconds",
+                "HTTP request latency in seconds",
+            ),
+            &["method", "route"],
+        )
+        .expect("Failed to create http_request_duration");
+
+        let vm_operations = IntCounterVec::new(
+            Opts::new(
+                "lnvps_vm_operations_total",
+                "VM operations by outcome (success, failure)",
+            ),
+            &["operation", "outcome"],
+        )
+        .expect("Failed to create vm_operations");
+
+        registry
+            .register(Box::new(http_requests.clone()))
+            .expect("Failed to register http_requests");
+        registry
+            .register(Box::new(http_request_duration.clone()))
+            .expect("Failed to register http_request_duration");
+        registry
+            .register(Box::new(vm_operations.clone()))
+            .expect("Failed to register vm_operations");
+
+        Self {
+            registry,
+            http_requests,
+            http_request_duration,
+            vm_operations,
+        }
+    }
+
+    /// Metrics shared by everything in this process
+    pub fn global() -> &'static Self {
+        &METRICS
+    }
+
+    /// Record the outcome of a VM operation (e.g. "create", "start")
+    pub fn record_vm_operation(&self, operation: &str, success: bool) {
+        self.vm_operations
+            .with_label_values(&[operation, if success { "success" } else { "failure" }])
+            .inc();
+    }
+
+    /// Export metrics in Prometheus text format
+    pub fn export(&self) -> String {
+        let encoder = TextEncoder::new();
+        let metric_families = self.registry.gather();
+        let mut buffer = Vec::new();
+        encoder.encode(&metric_families, &mut buffer).unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+}
+
+impl Default for ApiMetrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Middleware recording request count and latency per route, add with
+/// `route_layer` so the matched route is known
+pub async fn track_metrics(req: Request, next: Next) -> Response {
+    let start = Instant::now();
+    let method = req.method().to_string();
+    // label by route template (e.g. `/api/v1/vm/{id}`) to keep cardinality bounded
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|p| p.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+
+    let rsp = next.run(req).await;
+
+    let metrics = ApiMetrics::global();
+    metrics
+        .http_requests
+        .with_label_values(&[&method, &route, rsp.status().as_str()])
+        .inc();
+    metrics
+        .http_request_duration
+        .with_label_values(&[&method, &route])
+        .observe(start.elapsed().as_secs_f64());
+    rsp
+}
+
+/// Router serving `/metrics`, run on its own listen address
+pub fn router() -> Router {
+    Router::new().route("/metrics", get(metrics_handler))
+}
+
+async fn metrics_handler() -> String {
+    ApiMetrics::global().export()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::StatusCode;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_metrics_after_request() -> anyhow::Result<()> {
+        let app = Router::new()
+            .route("/api/v1/thing/{id}", get(async || "ok"))
+            .route_layer(axum::middleware::from_fn(track_metrics));
+        let rsp = app
+            .oneshot(Request::get("/api/v1/thing/42").body(Body::empty())?)
+            .await?;
+        assert_eq!(rsp.status(), StatusCode::OK);
+        ApiMetrics::global().record_vm_operation("create", false);
+
+        let rsp = router()
+            .oneshot(Request::get("/metrics").body(Body::empty())?)
+            .await?;
+        let body = axum::body::to_bytes(rsp.into_body(), usize::MAX).await?;
+        let body = String::from_utf8(body.to_vec())?;
+        assert!(body.contains(
+            r#"lnvps_http_requests_total{method="GET",route="/api/v1/thing/{id}",status="200"}"#
+        ));
+        assert!(body.contains("lnvps_http_request_duration_seconds_bucket"));
+        assert!(
+            body.contains(r#"lnvps_vm_operations_total{operation="create",outcome="failure"}"#)
+        );
+        Ok(())
+    }
+}
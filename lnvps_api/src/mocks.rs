@@ -4,7 +4,8 @@ use crate::host::{
     FullVmInfo, TerminalStream, TimeSeries, TimeSeriesData, VmHostClient, VmHostInfo,
 };
 use crate::router::{
-    ArpEntry, BgpPeer, BgpRoute, BgpRouter, BgpSession, Router, Tunnel, TunnelRouter, TunnelTraffic,
+    ArpEntry, BgpPeer, BgpRoute, BgpRouter, BgpSession, PrefixRoute, PrefixRouter, Router, Tunnel,
+    TunnelRouter, TunnelTraffic,
 };
 pub use lnvps_api_common::MockDnsServer;
 
@@ -53,6 +54,9 @@ pub struct MockRouter {
     tunnels: Arc<Mutex<HashMap<String, Tunnel>>>,
     sessions: Arc<Mutex<HashMap<String, BgpSession>>>,
     default_route: Arc<Mutex<Option<BgpRoute>>>,
+    prefix_routes: Arc<Mutex<HashMap<String, PrefixRoute>>>,
+    /// Addresses whose ARP entries fail to be added or updated
+    arp_failures: Arc<Mutex<Vec<String>>>,
 }
 
 impl Default for MockRouter {
@@ -81,6 +85,9 @@ impl MockRouter {
                     prefix: "0.0.0.0/0".to_string(),
                     next_hop: Some("192.0.2.1".to_string()),
                 })));
+            static TL_PREFIX_ROUTES: Arc<Mutex<HashMap<String, PrefixRoute>>> =
+                Arc::new(Mutex::new(HashMap::new()));
+            static TL_ARP_FAILURES: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(Vec::new()));
         }
 
         Self {
@@ -88,10 +95,12 @@ impl MockRouter {
             tunnels: TL_TUNNELS.with(|t| t.clone()),
             sessions: TL_SESSIONS.with(|s| s.clone()),
             default_route: TL_DEFAULT_ROUTE.with(|d| d.clone()),
+            prefix_routes: TL_PREFIX_ROUTES.with(|p| p.clone()),
+            arp_failures: TL_ARP_FAILURES.with(|f| f.clone()),
         }
     }
 
-    /// Clear all ARP entries, tunnels and BGP sessions - useful for test isolation
+    /// Clear all ARP entries, tunnels, BGP sessions and prefix routes - useful for test isolation
     pub async fn clear(&self) {
         let mut arp = self.arp.lock().await;
         arp.clear();
@@ -99,6 +108,30 @@ impl MockRouter {
         tunnels.clear();
         let mut sessions = self.sessions.lock().await;
         sessions.clear();
+        let mut prefix_routes = self.prefix_routes.lock().await;
+        prefix_routes.clear();
+        self.arp_failures.lock().await.clear();
+    }
+
+    /// Installed delegated prefix routes
+    pub async fn list_prefix_routes(&self) -> Vec<PrefixRoute> {
+        let routes = self.prefix_routes.lock().await;
+        routes.values().cloned().collect()
+    }
+
+    /// Make adding or updating the ARP entry of `address` fail
+    pub async fn fail_arp(&self, address: &str) {
+        self.arp_failures.lock().await.push(address.to_string());
+    }
+
+    async fn check_arp_failure(&self, entry: &ArpEntry) -> OpResult<()> {
+        if self.arp_failures.lock().await.contains(&entry.address) {
+            return Err(OpError::Transient(anyhow::anyhow!(
+                "Injected ARP failure for {}",
+                entry.address
+            )));
+        }
+        Ok(())
     }
 
     /// Seed a BGP session for tests
@@ -142,6 +175,7 @@ impl Router for MockRouter {
     }
 
     async fn add_arp_entry(&self, entry: &ArpEntry) -> OpResult<ArpEntry> {
+        self.check_arp_failure(entry).await?;
         let mut arp = self.arp.lock().await;
         if arp.iter().any(|(k, v)| v.address == entry.address) {
             return Err(OpError::Fatal(anyhow::anyhow!(
@@ -174,6 +208,7 @@ impl Router for MockRouter {
         if entry.id.is_none() {
             return Err(OpError::Fatal(anyhow::anyhow!("id is missing")));
         }
+        self.check_arp_failure(entry).await?;
         let id_str = entry.id.as_ref().unwrap();
         let mut arp = self.arp.lock().await;
 
@@ -207,6 +242,35 @@ impl Router for MockRouter {
     fn bgp(&self) -> Option<&dyn BgpRouter> {
         Some(self)
     }
+
+    fn prefix_routes(&self) -> Option<&dyn PrefixRouter> {
+        Some(self)
+    }
+}
+
+#[async_trait]
+impl PrefixRouter for MockRouter {
+    async fn add_prefix_route(&self, route: &PrefixRoute) -> OpResult<PrefixRoute> {
+        let mut routes = self.prefix_routes.lock().await;
+        // keyed by prefix like a routing table, a different owner is a conflict
+        if let Some(existing) = routes.get(&route.prefix)
+            && existing.next_hop != route.next_hop
+        {
+            op_fatal!("Prefix is already routed {:?}", existing);
+        }
+        let r = PrefixRoute {
+            id: Some(route.prefix.clone()),
+            ..route.clone()
+        };
+        routes.insert(route.prefix.clone(), r.clone());
+        Ok(r)
+    }
+
+    async fn remove_prefix_route(&self, id: &str) -> OpResult<()> {
+        let mut routes = self.prefix_routes.lock().await;
+        routes.remove(id);
+        Ok(())
+    }
 }
 
 #[async_trait]
@@ -399,7 +463,11 @@ impl LightningNode for MockNode {
     }
 
     async fn cancel_invoice(&self, id: &[u8]) -> anyhow::Result<()> {
-        todo!()
+        let mut invoices = self.invoices.lock().await;
+        if invoices.remove(&hex::encode(id)).is_none() {
+            bail!("unable to locate invoice");
+        }
+        Ok(())
     }
 
     async fn pay_invoice(&self, req: PayInvoiceRequest) -> anyhow::Result<PayInvoiceResponse> {
@@ -483,3 +551,26 @@ impl OnChainProvider for MockOnChainProvider {
         })
     }
 }
+
+/// Mock reachability probe, answers every probe with [`MockReachabilityProbe::reachable`]
+/// and records the probed addresses.
+#[derive(Clone, Debug, Default)]
+pub struct MockReachabilityProbe {
+    pub reachable: Arc<std::sync::atomic::AtomicBool>,
+    pub probes: Arc<Mutex<Vec<std::net::IpAddr>>>,
+}
+
+impl MockReachabilityProbe {
+    pub fn set_reachable(&self, reachable: bool) {
+        self.reachable
+            .store(reachable, std::sync::atomic::Ordering::SeqCst);
+    }
+}
+
+#[async_trait]
+impl crate::reachability::ReachabilityProbe for MockReachabilityProbe {
+    async fn is_reachable(&self, ip: std::net::IpAddr) -> bool {
+        self.probes.lock().await.push(ip);
+        self.reachable.load(std::sync::atomic::Ordering::SeqCst)
+    }
+}
@@ -0,0 +1,444 @@
+//! Activation checks for user nostr domains, shared by the periodic
+//! `CheckNostrDomains` job and the owner's verify endpoint.
+
+use anyhow::Result;
+use hickory_resolver::TokioResolver;
+use lnvps_api_common::is_non_routable;
+use lnvps_db::nostr::LNVPSNostrDb;
+use lnvps_db::{LNVpsDb, NostrDomain};
+use log::{debug, error, info, warn};
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How often the owner can ask for a domain to be checked
+const VERIFY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Checks whether a nostr domain is set up to be served by us
+#[derive(Clone)]
+pub struct NostrDomainVerifier {
+    /// Hostname domains must point at (`nostr-address-host`)
+    hostname: Option<String>,
+    /// Client for the activation path, it only connects to public addresses
+    http: reqwest::Client,
+    /// Last on-demand check by domain id
+    verified: Arc<Mutex<HashMap<u64, Instant>>>,
+    /// Allow any address, only for tests against a local server
+    local: bool,
+}
+
+/// Resolves names for the activation check, refusing names which resolve to
+/// a private, loopback or otherwise non-public address so a domain can't
+/// point the check at our own network
+struct PublicResolver(Arc<TokioResolver>);
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let resolver = self.0.clone();
+        Box::pin(async move {
+            let ips = resolver.lookup_ip(name.as_str()).await?;
+            let ips: Vec<IpAddr> = ips.iter().collect();
+            if ips.is_empty() || ips.iter().any(is_non_routable) {
+                return Err(
+                    format!("{} does not resolve to a public address", name.as_str()).into(),
+                );
+            }
+            let addrs: Addrs = Box::new(ips.into_iter().map(|ip| SocketAddr::new(ip, 0)));
+            Ok(addrs)
+        })
+    }
+}
+
+/// If `name` is a public DNS name we can check, IP addresses, ports and
+/// names which only resolve inside a network are refused
+pub fn is_public_domain_name(name: &str) -> bool {
+    const LOCAL_SUFFIXES: [&str; 5] = ["localhost", "local", "internal", "lan", "arpa"];
+    let name = name.strip_suffix('.').unwrap_or(name);
+    if name.len() > 253 || name.parse::<IpAddr>().is_ok() {
+        return false;
+    }
+    let labels: Vec<&str> = name.split('.').collect();
+    let Some(tld) = labels.last() else {
+        return false;
+    };
+    labels.len() >= 2
+        && labels.iter().all(|l| {
+            !l.is_empty()
+                && l.len() <= 63
+                && !l.starts_with('-')
+                && !l.ends_with('-')
+                && l.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+        // a numeric tld is an address, e.g. `127.1`
+        && !tld.chars().all(|c| c.is_ascii_digit())
+        && !LOCAL_SUFFIXES.contains(&tld.to_ascii_lowercase().as_str())
+}
+
+/// Outcome of [NostrDomainVerifier::check]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DomainCheck {
+    /// The domain resolves to our hostname, it can be served over HTTPS
+    pub dns: bool,
+    /// The activation path is reachable over HTTP
+    pub path: bool,
+}
+
+impl NostrDomainVerifier {
+    pub fn new(hostname: Option<String>) -> Result<Self> {
+        let resolver = TokioResolver::builder_tokio()?.build()?;
+        let http = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .redirect(reqwest::redirect::Policy::none())
+            .dns_resolver(Arc::new(PublicResolver(Arc::new(resolver))))
+            .build()?;
+        Ok(Self {
+            hostname,
+            http,
+            verified: Default::default(),
+            local: false,
+        })
+    }
+
+    /// A verifier which checks any address
+    #[cfg(test)]
+    pub(crate) fn local(hostname: Option<String>) -> Self {
+        Self {
+            hostname,
+            http: Default::default(),
+            verified: Default::default(),
+            local: true,
+        }
+    }
+
+    /// The URL which must answer the activation check for `domain`
+    pub fn activation_url(&self, domain: &NostrDomain) -> Option<String> {
+        domain
+            .activation_hash
+            .as_ref()
+            .map(|h| format!("http://{}/.well-known/nostr.json?name={}", domain.name, h))
+    }
+
+    /// Count an on-demand check of `domain_id`, false when it was checked
+    /// less than [VERIFY_INTERVAL] ago
+    pub fn try_verify(&self, domain_id: u64) -> bool {
+        self.try_verify_at(domain_id, Instant::now())
+    }
+
+    fn try_verify_at(&self, domain_id: u64, now: Instant) -> bool {
+        let mut verified = self.verified.lock().unwrap();
+        verified.retain(|_, at| now.duration_since(*at) < VERIFY_INTERVAL);
+        if verified.contains_key(&domain_id) {
+            return false;
+        }
+        verified.insert(domain_id, now);
+        true
+    }
+
+    /// Run both activation checks, errors count as a failed check
+    pub async fn check(&self, domain: &NostrDomain) -> DomainCheck {
+        if !self.local && !is_public_domain_name(&domain.name) {
+            warn!(
+                "Domain {} is not a public DNS name, skipping checks",
+                domain.name
+            );
+            return DomainCheck::default();
+        }
+        DomainCheck {
+            dns: self.check_dns(&domain.name).await.unwrap_or_else(|e| {
+                error!("DNS check error for {}: {}", domain.name, e);
+                false
+            }),
+            path: self
+                .check_path_activation(domain)
+                .await
+                .unwrap_or_else(|e| {
+                    error!("Path activation check error for {}: {}", domain.name, e);
+                    false
+                }),
+        }
+    }
+
+    /// Check `domain` now and enable it if it is disabled and passes a check.
+    ///
+    /// A DNS match enables HTTPS, path activation alone enables HTTP-only.
+    /// Enabled domains are never disabled here, that is left to the periodic
+    /// job. Returns the domain as stored after the check.
+    pub async fn verify(
+        &self,
+        db: &Arc<dyn LNVpsDb>,
+        domain: &NostrDomain,
+    ) -> Result<(NostrDomain, DomainCheck)> {
+        let check = self.check(domain).await;
+        if !domain.enabled {
+            if check.dns {
+                info!(
+                    "Domain {} verified by DNS - activating with HTTPS",
+                    domain.name
+                );
+                db.enable_domain_with_https(domain.id).await?;
+            } else if check.path {
+                info!(
+                    "Domain {} verified by path - activating as HTTP-only",
+                    domain.name
+                );
+                db.enable_domain_http_only(domain.id).await?;
+            }
+        }
+        Ok((db.get_domain(domain.id).await?, check))
+    }
+
+    /// Check if a domain has a DNS record pointing to the configured nostr hostname or resolves to the same IP
+    async fn check_dns(&self, domain: &str) -> Result<bool> {
+        let Some(expected_hostname) = &self.hostname else {
+            warn!("No nostr hostname configured, skipping DNS record check");
+            return Ok(false);
+        };
+
+        // Create a resolver using system configuration
+        let resolver = TokioResolver::builder_tokio()?.build()?;
+
+        // Resolve both domain and expected hostname to IP addresses
+        // lookup_ip automatically follows DNS records to get final IPs
+        debug!(
+            "Checking IP resolution for {} vs {}",
+            domain, expected_hostname
+        );
+
+        // Resolve our expected hostname to IP addresses
+        let expected_ips = match resolver.lookup_ip(expected_hostname).await {
+            Ok(ips) => {
+                let ip_addrs: Vec<String> = ips.iter().map(|ip| ip.to_string()).collect();
+                debug!(
+                    "Expected hostname {} resolves to IPs: {:?}",
+                    expected_hostname, ip_addrs
+                );
+                ip_addrs
+            }
+            Err(e) => {
+                debug!(
+                    "Failed to resolve expected hostname {} to IP: {}",
+                    expected_hostname, e
+                );
+                return Ok(false);
+            }
+        };
+
+        // Resolve the domain to IP addresses (follows DNS records automatically)
+        match resolver.lookup_ip(domain).await {
+            Ok(domain_ips) => {
+                let domain_ip_addrs: Vec<String> =
+                    domain_ips.iter().map(|ip| ip.to_string()).collect();
+                debug!("Domain {} resolves to IPs: {:?}", domain, domain_ip_addrs);
+
+                // Check if any of the domain's IPs match any of our expected IPs
+                for domain_ip in &domain_ip_addrs {
+                    if expected_ips.contains(domain_ip) {
+                        debug!(
+                            "Domain {} IP check: {} matches expected hostname {} (matches: true)",
+                            domain, domain_ip, expected_hostname
+                        );
+                        return Ok(true);
+                    }
+                }
+
+                debug!(
+                    "Domain {} IP check: no IP overlap with expected hostname {} (matches: false)",
+                    domain, expected_hostname
+                );
+                Ok(false)
+            }
+            Err(e) => {
+                debug!("DNS IP lookup error for {}: {}", domain, e);
+                Ok(false)
+            }
+        }
+    }
+
+    /// Check if a domain can be activated via path-based activation
+    /// by fetching the activation URL and verifying the response is valid NIP-05 JSON
+    /// with an empty `names` map (indicating the hash was recognised by the server).
+    async fn check_path_activation(&self, domain: &NostrDomain) -> Result<bool> {
+        let Some(activation_url) = self.activation_url(domain) else {
+            debug!("Domain {} has no activation hash", domain.name);
+            return Ok(false);
+        };
+
+        debug!(
+            "Checking path activation for domain {} at {}",
+            domain.name, activation_url
+        );
+
+        match self.http.get(&activation_url).send().await {
+            Ok(response) => {
+                if !response.status().is_success() {
+                    debug!(
+                        "Path activation check failed for domain {} - got status {}",
+                        domain.name,
+                        response.status()
+                    );
+                    return Ok(false);
+                }
+                // Verify the body is valid NIP-05 JSON with an empty `names` map.
+                // The lnvps_nostr server returns `{"names":{},"relays":{}}` when
+                // the activation hash matches, rather than a real handle lookup.
+                match response.json::<serde_json::Value>().await {
+                    Ok(body) => {
+                        let names_empty = body
+                            .get("names")
+                            .and_then(|n| n.as_object())
+                            .map(|m| m.is_empty())
+                            .unwrap_or(false);
+                        if names_empty {
+                            debug!("Path activation check succeeded for domain {}", domain.name);
+                            Ok(true)
+                        } else {
+                            debug!(
+                                "Path activation check failed for domain {} - unexpected body",
+                                domain.name
+                            );
+                            Ok(false)
+                        }
+                    }
+                    Err(e) => {
+                        debug!(
+                            "Path activation check failed for domain {} - invalid JSON: {}",
+                            domain.name, e
+                        );
+                        Ok(false)
+                    }
+                }
+            }
+            Err(e) => {
+                debug!(
+                    "Path activation check failed for domain {} - error: {}",
+                    domain.name, e
+                );
+                Ok(false)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lnvps_api_common::MockDb;
+    use wiremock::matchers::{method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    async fn disabled_domain(db: &Arc<dyn LNVpsDb>, name: &str) -> Result<NostrDomain> {
+        let id = db
+            .insert_domain(&NostrDomain {
+                owner_id: 1,
+                name: name.to_string(),
+                activation_hash: Some("hash".to_string()),
+                http_only: true,
+                ..Default::default()
+            })
+            .await?;
+        Ok(db.get_domain(id).await?)
+    }
+
+    #[tokio::test]
+    async fn test_verify_enables_domain_with_dns() -> Result<()> {
+        let db: Arc<dyn LNVpsDb> = Arc::new(MockDb::default());
+        // an address resolves to itself without a DNS query
+        let domain = disabled_domain(&db, "127.0.0.1").await?;
+        let verifier = NostrDomainVerifier::local(Some("127.0.0.1".to_string()));
+
+        let (domain, check) = verifier.verify(&db, &domain).await?;
+        assert!(check.dns);
+        assert!(domain.enabled);
+        assert!(!domain.http_only);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_verify_enables_domain_with_path_activation() -> Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/.well-known/nostr.json"))
+            .and(query_param("name", "hash"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({"names": {}, "relays": {}})),
+            )
+            .mount(&server)
+            .await;
+
+        let db: Arc<dyn LNVpsDb> = Arc::new(MockDb::default());
+        let host = server.address().to_string();
+        let domain = disabled_domain(&db, &host).await?;
+        let verifier = NostrDomainVerifier::local(None);
+
+        let (domain, check) = verifier.verify(&db, &domain).await?;
+        assert!(!check.dns);
+        assert!(check.path);
+        assert!(domain.enabled);
+        assert!(domain.http_only);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_verify_leaves_unconfigured_domain_disabled() -> Result<()> {
+        let db: Arc<dyn LNVpsDb> = Arc::new(MockDb::default());
+        let domain = disabled_domain(&db, "127.0.0.1:1").await?;
+        let verifier = NostrDomainVerifier::local(None);
+
+        let (domain, check) = verifier.verify(&db, &domain).await?;
+        assert!(!check.dns && !check.path);
+        assert!(!domain.enabled);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_check_skips_private_addresses() -> Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({"names": {}, "relays": {}})),
+            )
+            .expect(0)
+            .mount(&server)
+            .await;
+
+        let db: Arc<dyn LNVpsDb> = Arc::new(MockDb::default());
+        let domain = disabled_domain(&db, &server.address().to_string()).await?;
+        let verifier = NostrDomainVerifier::new(Some("127.0.0.1".to_string()))?;
+
+        let (domain, check) = verifier.verify(&db, &domain).await?;
+        assert!(!check.dns && !check.path);
+        assert!(!domain.enabled);
+        Ok(())
+    }
+
+    #[test]
+    fn test_public_domain_name() {
+        assert!(is_public_domain_name("example.com"));
+        assert!(is_public_domain_name("nostr.my-domain.co.uk."));
+        assert!(!is_public_domain_name("127.0.0.1"));
+        assert!(!is_public_domain_name("127.1"));
+        assert!(!is_public_domain_name("::1"));
+        assert!(!is_public_domain_name("example.com:8080"));
+        assert!(!is_public_domain_name("localhost"));
+        assert!(!is_public_domain_name("metadata.internal"));
+        assert!(!is_public_domain_name("printer.local"));
+        assert!(!is_public_domain_name("-bad.example.com"));
+        assert!(!is_public_domain_name("example..com"));
+        assert!(!is_public_domain_name("http://example.com"));
+    }
+
+    #[test]
+    fn test_verify_interval() {
+        let verifier = NostrDomainVerifier::local(None);
+        let now = Instant::now();
+        assert!(verifier.try_verify_at(1, now));
+        assert!(!verifier.try_verify_at(1, now + Duration::from_secs(1)));
+        // other domains have their own interval
+        assert!(verifier.try_verify_at(2, now));
+        assert!(verifier.try_verify_at(1, now + VERIFY_INTERVAL));
+    }
+}
@@ -4,8 +4,9 @@
 //! be contacted on that channel ([`NotificationChannel::wants`]) and how to
 //! actually deliver a [`Notification`] ([`NotificationChannel::send`]).
 //!
-//! The worker holds a list of channels and, when dispatching a notification,
-//! iterates every channel the user has opted into. New channels (Telegram,
+//! The worker delivers notifications through a [`NotificationService`], which
+//! tries the channels the user has opted into in registration order and falls
+//! back to the next one when delivery fails. New channels (Telegram,
 //! WhatsApp, ...) can be added by implementing this trait and registering the
 //! channel in [`build_channels`].
 
@@ -23,7 +24,9 @@ use crate::worker::WorkerSettings;
 use async_trait::async_trait;
 use lnvps_api_common::retry::OpError;
 use lnvps_db::User;
+use log::{info, warn};
 use nostr_sdk::Client;
+use std::fmt::{Display, Formatter};
 use std::sync::Arc;
 
 /// A single notification to deliver to a user.
@@ -105,3 +108,253 @@ pub fn build_channels(
 
     channels
 }
+
+/// Result of delivering a [`Notification`] to a user.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeliveryStatus {
+    /// Delivered on the named channel.
+    Delivered(&'static str),
+    /// The user has no enabled channel, nothing was sent.
+    NoChannel,
+    /// Every enabled channel failed permanently.
+    Failed,
+}
+
+impl Display for DeliveryStatus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeliveryStatus::Delivered(c) => write!(f, "Delivered via {}", c),
+            DeliveryStatus::NoChannel => write!(f, "No contact channel enabled"),
+            DeliveryStatus::Failed => write!(f, "Delivery failed on all channels"),
+        }
+    }
+}
+
+/// Delivers notifications on the channels a user has enabled.
+///
+/// The first channel (in [`build_channels`] order) the user wants is tried
+/// first, on failure the next enabled channel is tried, so a user receives a
+/// notification at most once.
+#[derive(Clone)]
+pub struct NotificationService {
+    channels: Vec<Arc<dyn NotificationChannel>>,
+}
+
+impl NotificationService {
+    pub fn new(channels: Vec<Arc<dyn NotificationChannel>>) -> Self {
+        Self { channels }
+    }
+
+    /// Deliver a notification to a user.
+    ///
+    /// Returns an error only when no channel delivered and at least one failed
+    /// with a transient error, so the notification can be retried.
+    pub async fn deliver(
+        &self,
+        user: &User,
+        notification: &Notification,
+    ) -> anyhow::Result<DeliveryStatus> {
+        let mut transient = None;
+        let mut tried = false;
+        for channel in self.channels.iter().filter(|c| c.wants(user)) {
+            tried = true;
+            match channel.send(user, notification).await {
+                Ok(()) => {
+                    info!(
+                        "Notification '{}' delivered to user {} via {}",
+                        notification.subject(),
+                        user.id,
+                        channel.name()
+                    );
+                    return Ok(DeliveryStatus::Delivered(channel.name()));
+                }
+                Err(OpError::Fatal(e)) => warn!(
+                    "Permanent {} notification error for user {}, trying next channel: {}",
+                    channel.name(),
+                    user.id,
+                    e
+                ),
+                Err(OpError::Transient(e)) => {
+                    warn!(
+                        "Transient {} notification error for user {}, trying next channel: {}",
+                        channel.name(),
+                        user.id,
+                        e
+                    );
+                    transient = Some(e);
+                }
+            }
+        }
+        if !tried {
+            info!(
+                "User {} has no notification channel enabled, skipping '{}'",
+                user.id,
+                notification.subject()
+            );
+            return Ok(DeliveryStatus::NoChannel);
+        }
+        if let Some(e) = transient {
+            return Err(e);
+        }
+        warn!(
+            "Notification '{}' could not be delivered to user {}",
+            notification.subject(),
+            user.id
+        );
+        Ok(DeliveryStatus::Failed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::Mutex;
+
+    /// Channel which records deliveries and fails on demand
+    struct TestChannel {
+        name: &'static str,
+        wants: fn(&User) -> bool,
+        fail: Option<fn() -> OpError<anyhow::Error>>,
+        sent: Mutex<Vec<u64>>,
+    }
+
+    impl TestChannel {
+        fn new(name: &'static str, wants: fn(&User) -> bool) -> Arc<Self> {
+            Arc::new(Self {
+                name,
+                wants,
+                fail: None,
+                sent: Mutex::new(vec![]),
+            })
+        }
+
+        fn failing(
+            name: &'static str,
+            wants: fn(&User) -> bool,
+            fail: fn() -> OpError<anyhow::Error>,
+        ) -> Arc<Self> {
+            Arc::new(Self {
+                name,
+                wants,
+                fail: Some(fail),
+                sent: Mutex::new(vec![]),
+            })
+        }
+    }
+
+    #[async_trait]
+    impl NotificationChannel for TestChannel {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        fn wants(&self, user: &User) -> bool {
+            (self.wants)(user)
+        }
+
+        async fn send(
+            &self,
+            user: &User,
+            _notification: &Notification,
+        ) -> Result<(), OpError<anyhow::Error>> {
+            if let Some(f) = self.fail {
+                return Err(f());
+            }
+            self.sent.lock().await.push(user.id);
+            Ok(())
+        }
+    }
+
+    fn wants_email(u: &User) -> bool {
+        u.contact_email && !u.email.is_empty()
+    }
+
+    fn wants_nip17(u: &User) -> bool {
+        u.contact_nip17
+    }
+
+    fn user(nip17: bool, email: bool) -> User {
+        User {
+            id: 1,
+            contact_nip17: nip17,
+            contact_email: email,
+            email: "a@example.com".into(),
+            ..Default::default()
+        }
+    }
+
+    fn notification() -> Notification {
+        Notification::new(Some("Test".to_string()), "hello".to_string())
+    }
+
+    #[tokio::test]
+    async fn test_nip17_only_user() -> anyhow::Result<()> {
+        let email = TestChannel::new("email", wants_email);
+        let nip17 = TestChannel::new("nip17", wants_nip17);
+        let svc = NotificationService::new(vec![email.clone(), nip17.clone()]);
+
+        let status = svc.deliver(&user(true, false), &notification()).await?;
+        assert_eq!(status, DeliveryStatus::Delivered("nip17"));
+        assert!(email.sent.lock().await.is_empty());
+        assert_eq!(*nip17.sent.lock().await, vec![1]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_email_only_user() -> anyhow::Result<()> {
+        let email = TestChannel::new("email", wants_email);
+        let nip17 = TestChannel::new("nip17", wants_nip17);
+        let svc = NotificationService::new(vec![email.clone(), nip17.clone()]);
+
+        let status = svc.deliver(&user(false, true), &notification()).await?;
+        assert_eq!(status, DeliveryStatus::Delivered("email"));
+        assert_eq!(*email.sent.lock().await, vec![1]);
+        assert!(nip17.sent.lock().await.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_no_channel_enabled_skips() -> anyhow::Result<()> {
+        let email = TestChannel::new("email", wants_email);
+        let nip17 = TestChannel::new("nip17", wants_nip17);
+        let svc = NotificationService::new(vec![email.clone(), nip17.clone()]);
+
+        let status = svc.deliver(&user(false, false), &notification()).await?;
+        assert_eq!(status, DeliveryStatus::NoChannel);
+        assert!(email.sent.lock().await.is_empty());
+        assert!(nip17.sent.lock().await.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_falls_back_to_next_channel() -> anyhow::Result<()> {
+        let email = TestChannel::failing("email", wants_email, || {
+            OpError::Fatal(anyhow::anyhow!("mailbox unavailable"))
+        });
+        let nip17 = TestChannel::new("nip17", wants_nip17);
+        let svc = NotificationService::new(vec![email.clone(), nip17.clone()]);
+
+        // preferred channel only, sent once
+        let status = svc.deliver(&user(true, true), &notification()).await?;
+        assert_eq!(status, DeliveryStatus::Delivered("nip17"));
+        assert_eq!(*nip17.sent.lock().await, vec![1]);
+
+        // nothing left to fall back to
+        let status = svc.deliver(&user(false, true), &notification()).await?;
+        assert_eq!(status, DeliveryStatus::Failed);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_transient_failure_is_retried() {
+        let email = TestChannel::failing("email", wants_email, || {
+            OpError::Transient(anyhow::anyhow!("connection reset"))
+        });
+        let svc = NotificationService::new(vec![email]);
+        assert!(
+            svc.deliver(&user(false, true), &notification())
+                .await
+                .is_err()
+        );
+    }
+}
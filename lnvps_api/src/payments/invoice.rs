@@ -1,16 +1,27 @@
+use crate::settings::UnderpaymentPolicy;
 use crate::subscription::SubscriptionHandler;
 use anyhow::Result;
 use futures::StreamExt;
-use lnvps_api_common::VmStateCache;
+use lnvps_api_common::{VmHistoryLogger, VmStateCache, WorkJob, round_msat_to_sat};
 use lnvps_db::{LNVpsDb, SubscriptionPayment, SubscriptionPaymentType};
 use log::{error, info, warn};
+use payments_rs::currency::{Currency, CurrencyAmount};
 use payments_rs::lightning::{InvoiceUpdate, LightningNode};
+use serde_json::json;
+use std::cmp::Ordering;
+use std::str::FromStr;
 use std::sync::Arc;
 
+/// Payment metadata key recording the amount received when it didn't match
+/// the amount requested
+const AMOUNT_RECEIVED_KEY: &str = "amount_received";
+
 pub struct NodeInvoiceHandler {
     node: Arc<dyn LightningNode>,
     db: Arc<dyn LNVpsDb>,
     sub_handler: SubscriptionHandler,
+    vm_history: VmHistoryLogger,
+    underpayment_policy: UnderpaymentPolicy,
 }
 
 impl NodeInvoiceHandler {
@@ -22,21 +33,126 @@ impl NodeInvoiceHandler {
         Self {
             node,
             sub_handler,
+            vm_history: VmHistoryLogger::new(db.clone()),
             db,
+            underpayment_policy: UnderpaymentPolicy::default(),
         }
     }
 
-    async fn mark_paid(&self, id: &Vec<u8>) -> Result<()> {
+    pub fn with_underpayment_policy(mut self, policy: UnderpaymentPolicy) -> Self {
+        self.underpayment_policy = policy;
+        self
+    }
+
+    async fn mark_paid(&self, id: &Vec<u8>, amount_paid: Option<u64>) -> Result<()> {
         let payment = self.db.get_subscription_payment(id).await?;
-        self.complete(&payment).await
+        self.settle(&payment, amount_paid).await
     }
 
-    async fn mark_paid_ext_id(&self, external_id: &str) -> Result<()> {
+    async fn mark_paid_ext_id(&self, external_id: &str, amount_paid: Option<u64>) -> Result<()> {
         let payment = self
             .db
             .get_subscription_payment_by_ext_id(external_id)
             .await?;
-        self.complete(&payment).await
+        self.settle(&payment, amount_paid).await
+    }
+
+    /// Handle a settled invoice. `amount_paid` is the amount received in msats,
+    /// `None` when the node only reports that the invoice settled.
+    ///
+    /// The payment is only completed when at least the invoiced amount was
+    /// received, anything else is handled per [UnderpaymentPolicy].
+    async fn settle(&self, payment: &SubscriptionPayment, amount_paid: Option<u64>) -> Result<()> {
+        let expected = round_msat_to_sat(payment.amount + payment.tax);
+        let paid = amount_paid.unwrap_or(expected);
+        match paid.cmp(&expected) {
+            Ordering::Equal => self.complete(payment).await,
+            Ordering::Greater => {
+                self.complete(payment).await?;
+                self.handle_discrepancy(payment, expected, paid, paid - expected)
+                    .await
+            }
+            // never extend a subscription for less than the invoiced amount
+            Ordering::Less => self.handle_discrepancy(payment, expected, paid, paid).await,
+        }
+    }
+
+    /// Credit or refund `unapplied` (the part of `paid` not used for the
+    /// subscription) and record the discrepancy in VM history
+    async fn handle_discrepancy(
+        &self,
+        payment: &SubscriptionPayment,
+        expected: u64,
+        paid: u64,
+        unapplied: u64,
+    ) -> Result<()> {
+        // settled invoices are replayed when the listener reconnects, only
+        // handle a discrepancy once
+        let mut p = self.db.get_subscription_payment(&payment.id).await?;
+        let mut metadata = p.metadata.take().unwrap_or_else(|| json!({}));
+        if metadata.get(AMOUNT_RECEIVED_KEY).is_some() {
+            return Ok(());
+        }
+        metadata[AMOUNT_RECEIVED_KEY] = json!(paid);
+        p.metadata = Some(metadata);
+        self.db.update_subscription_payment(&p).await?;
+
+        let id = hex::encode(&payment.id);
+        let currency = Currency::from_str(&payment.currency).unwrap_or(Currency::BTC);
+        let unapplied_fmt = CurrencyAmount::from_u64(currency, unapplied);
+        warn!(
+            "Payment {} settled for {} but {} was requested",
+            id, paid, expected
+        );
+
+        let handling = match self.underpayment_policy {
+            UnderpaymentPolicy::Credit => {
+                self.db
+                    .add_user_credit(payment.user_id, &payment.currency, unapplied)
+                    .await?;
+                "credited to account"
+            }
+            UnderpaymentPolicy::Refund => {
+                self.sub_handler
+                    .work_commander()
+                    .send(WorkJob::SendAdminNotification {
+                        message: format!(
+                            "Payment {} for subscription {} (user {}) settled for {} msats but {} msats was requested, {} needs to be refunded",
+                            id, payment.subscription_id, payment.user_id, paid, expected, unapplied_fmt
+                        ),
+                        title: Some("Payment amount mismatch".to_string()),
+                    })
+                    .await?;
+                "refund pending"
+            }
+        };
+
+        if let Ok(vm) = self
+            .db
+            .get_vm_by_subscription(payment.subscription_id)
+            .await
+            && let Err(e) = self
+                .vm_history
+                .log_vm_payment_discrepancy(vm.id, expected, paid, &payment.currency, handling)
+                .await
+        {
+            warn!("Failed to log payment discrepancy for VM {}: {}", vm.id, e);
+        }
+
+        if paid < expected {
+            self.sub_handler
+                .work_commander()
+                .send(WorkJob::SendNotification {
+                    user_id: payment.user_id,
+                    message: format!(
+                        "Your payment was less than the amount requested, so it has not been applied to your subscription. {} was {}.",
+                        unapplied_fmt, handling
+                    ),
+                    title: Some("Payment incomplete".to_string()),
+                })
+                .await?;
+        }
+        Ok(())
     }
 
     async fn complete(&self, payment: &SubscriptionPayment) -> Result<()> {
@@ -70,17 +186,22 @@ impl NodeInvoiceHandler {
                 InvoiceUpdate::Settled {
                     payment_hash,
                     external_id,
+                    amt_paid_msat,
                     ..
                 } => {
+                    // providers which don't report the amount received leave
+                    // it empty, the invoiced amount is assumed for those
+                    let amount_paid: Option<u64> = amt_paid_msat.into();
+                    let amount_paid = amount_paid.filter(|a| *a > 0);
                     if !payment_hash.is_empty() {
                         let r_hash = hex::decode(&payment_hash)?;
-                        if let Err(e) = self.mark_paid(&r_hash).await {
+                        if let Err(e) = self.mark_paid(&r_hash, amount_paid).await {
                             error!("{}", e);
                         }
                         continue;
                     }
                     if let Some(e) = external_id {
-                        if let Err(e) = self.mark_paid_ext_id(&e).await {
+                        if let Err(e) = self.mark_paid_ext_id(&e, amount_paid).await {
                             error!("{}", e);
                         }
                         continue;
@@ -211,6 +332,7 @@ mod tests {
             tax_treatment: None,
             tax_evidence: None,
             tax_breakdown: None,
+            invoice_number: None,
         };
         db.insert_subscription_payment(&payment).await?;
 
@@ -367,6 +489,7 @@ mod tests {
             tax_treatment: None,
             tax_evidence: None,
             tax_breakdown: None,
+            invoice_number: None,
         };
         db.insert_subscription_payment(&payment).await?;
         let sub = SubscriptionHandler::new(
@@ -417,6 +540,102 @@ mod tests {
         Ok(())
     }
 
+    async fn is_paid(db: &MockDb, payment: &SubscriptionPayment) -> bool {
+        db.subscription_payments
+            .lock()
+            .await
+            .iter()
+            .any(|p| p.id == payment.id && p.is_paid)
+    }
+
+    async fn discrepancies(db: &MockDb, vm_id: u64) -> usize {
+        db.vm_history
+            .lock()
+            .await
+            .values()
+            .filter(|h| {
+                h.vm_id == vm_id && h.description.as_deref().unwrap_or("").contains("requested")
+            })
+            .count()
+    }
+
+    /// Exactly the invoiced amount completes the payment with nothing credited.
+    #[tokio::test]
+    async fn test_settle_exact_amount() -> Result<()> {
+        let (db, node, sub, payment, vm_id) =
+            setup_renewal(86400, SubscriptionPaymentType::Renewal).await?;
+        let handler = NodeInvoiceHandler::new(node, db.clone(), sub);
+
+        handler.settle(&payment, Some(1000)).await?;
+
+        assert!(is_paid(&db, &payment).await);
+        assert_eq!(db.get_user_credit(payment.user_id, "BTC").await?, 0);
+        assert_eq!(discrepancies(&db, vm_id).await, 0);
+        Ok(())
+    }
+
+    /// Overpaying completes the payment and credits the excess.
+    #[tokio::test]
+    async fn test_settle_overpayment_credits_excess() -> Result<()> {
+        let (db, node, sub, payment, vm_id) =
+            setup_renewal(86400, SubscriptionPaymentType::Renewal).await?;
+        let handler = NodeInvoiceHandler::new(node, db.clone(), sub);
+
+        handler.settle(&payment, Some(3000)).await?;
+
+        assert!(is_paid(&db, &payment).await);
+        assert_eq!(db.get_user_credit(payment.user_id, "BTC").await?, 2000);
+        assert_eq!(discrepancies(&db, vm_id).await, 1);
+        Ok(())
+    }
+
+    /// Underpaying never completes the payment: the amount received is
+    /// credited once, even when the settlement is replayed.
+    #[tokio::test]
+    async fn test_settle_underpayment_credits_and_keeps_open() -> Result<()> {
+        let (db, node, sub, payment, vm_id) =
+            setup_renewal(86400, SubscriptionPaymentType::Renewal).await?;
+        let handler = NodeInvoiceHandler::new(node, db.clone(), sub.clone());
+
+        handler.settle(&payment, Some(400)).await?;
+        handler.settle(&payment, Some(400)).await?;
+
+        assert!(!is_paid(&db, &payment).await);
+        assert_eq!(db.get_user_credit(payment.user_id, "BTC").await?, 400);
+        assert_eq!(discrepancies(&db, vm_id).await, 1);
+        let jobs = sub.work_commander().list_pending().await?;
+        assert!(
+            jobs.iter()
+                .any(|j| matches!(&j.job, WorkJob::SendNotification { user_id, .. } if *user_id == payment.user_id))
+        );
+        assert!(
+            !jobs
+                .iter()
+                .any(|j| matches!(&j.job, WorkJob::SpawnVm { .. } | WorkJob::CheckVm { .. }))
+        );
+        Ok(())
+    }
+
+    /// With the refund policy an underpayment is flagged to admins instead of credited.
+    #[tokio::test]
+    async fn test_settle_underpayment_refund_policy() -> Result<()> {
+        let (db, node, sub, payment, _vm_id) =
+            setup_renewal(86400, SubscriptionPaymentType::Renewal).await?;
+        let handler = NodeInvoiceHandler::new(node, db.clone(), sub.clone())
+            .with_underpayment_policy(UnderpaymentPolicy::Refund);
+
+        handler.settle(&payment, Some(400)).await?;
+
+        assert!(!is_paid(&db, &payment).await);
+        assert_eq!(db.get_user_credit(payment.user_id, "BTC").await?, 0);
+        let jobs = sub.work_commander().list_pending().await?;
+        assert!(
+            jobs.iter()
+                .any(|j| matches!(&j.job, WorkJob::SendAdminNotification { .. }))
+        );
+        Ok(())
+    }
+
     /// regression: renew_amount must credit the correct VM when VM ID != subscription ID.
     /// Before #152, the LNURL-pay callback passed vm_line.subscription_id to renew_amount(),
     /// which expects a VM ID — causing payments to land on whichever VM shared the numeric
@@ -34,7 +34,8 @@ pub async fn listen_all_payments(
     sub_handler: SubscriptionHandler,
 ) -> Result<Vec<JoinHandle<()>>> {
     let mut ret = Vec::new();
-    let mut handler = NodeInvoiceHandler::new(node.clone(), db.clone(), sub_handler.clone());
+    let mut handler = NodeInvoiceHandler::new(node.clone(), db.clone(), sub_handler.clone())
+        .with_underpayment_policy(settings.underpayment_policy);
     ret.push(tokio::spawn(async move {
         loop {
             if let Err(e) = handler.listen().await {
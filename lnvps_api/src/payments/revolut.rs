@@ -53,7 +53,11 @@ impl RevolutPaymentHandler {
     }
 
     pub async fn listen(&mut self) -> Result<()> {
-        let this_webhook = Url::parse(&self.public_url)?.join("/api/v1/webhook/revolut")?;
+        // Append rather than join so a base path in `public_url` is kept
+        let this_webhook = Url::parse(&format!(
+            "{}/api/v1/webhook/revolut",
+            self.public_url.trim_end_matches('/')
+        ))?;
 
         // First, check if we have a webhook secret stored in the database
         let mut config = self.db.get_payment_method_config(self.config_id).await?;
@@ -4,13 +4,15 @@
 
 #[cfg(test)]
 mod tests {
-    use crate::host::VmHostClient;
+    use crate::host::{FullVmInfo, VmHostClient};
     use crate::mocks::MockVmHost;
+    use crate::provisioner::VmProvisioner;
+    use crate::settings::mock_settings;
     use anyhow::Result;
     use async_trait::async_trait;
-    use lnvps_api_common::retry::{OpError, OpResult};
-    use lnvps_api_common::{MockDb, VmRunningState};
-    use lnvps_db::{LNVpsDbBase, User, UserSshKey, Vm};
+    use lnvps_api_common::retry::{OpError, OpResult, RetryPolicy};
+    use lnvps_api_common::{MockDb, VmRunningState, VmRunningStates};
+    use lnvps_db::{LNVpsDbBase, OsImageKind, User, UserSshKey, Vm, VmExtraDisk};
     use std::sync::Arc;
     use std::sync::atomic::{AtomicU32, Ordering};
 
@@ -22,6 +24,8 @@ mod tests {
         delete_vm_fail_count: Arc<AtomicU32>,
         start_vm_fail_count: Arc<AtomicU32>,
         stop_vm_fail_count: Arc<AtomicU32>,
+        /// Image whose template disk import always fails (fatal)
+        import_fail_image: Option<u64>,
     }
 
     impl FailingVmHostClient {
@@ -37,9 +41,22 @@ mod tests {
                 delete_vm_fail_count: Arc::new(AtomicU32::new(delete_fails)),
                 start_vm_fail_count: Arc::new(AtomicU32::new(start_fails)),
                 stop_vm_fail_count: Arc::new(AtomicU32::new(stop_fails)),
+                import_fail_image: None,
             }
         }
 
+        /// Act as a container host
+        pub fn with_containers(mut self) -> Self {
+            self.inner = self.inner.with_containers();
+            self
+        }
+
+        /// Fail every template disk import of `image_id`
+        pub fn with_import_failure(mut self, image_id: u64) -> Self {
+            self.import_fail_image = Some(image_id);
+            self
+        }
+
         pub fn create_failures_remaining(&self) -> u32 {
             self.create_vm_fail_count.load(Ordering::SeqCst)
         }
@@ -63,8 +80,12 @@ mod tests {
             self.inner.get_info().await
         }
 
-        async fn download_os_image(&self, image: &lnvps_db::VmOsImage) -> OpResult<()> {
-            self.inner.download_os_image(image).await
+        async fn download_os_image(
+            &self,
+            image: &lnvps_db::VmOsImage,
+            retry: RetryPolicy,
+        ) -> OpResult<()> {
+            self.inner.download_os_image(image, retry).await
         }
 
         async fn generate_mac(&self, vm: &Vm) -> OpResult<String> {
@@ -99,7 +120,27 @@ mod tests {
             self.inner.reset_vm(vm).await
         }
 
-        async fn create_vm(&self, req: &crate::host::FullVmInfo) -> OpResult<()> {
+        async fn reboot_vm(&self, vm: &Vm) -> OpResult<()> {
+            self.inner.reboot_vm(vm).await
+        }
+
+        async fn get_guest_info(&self, vm: &Vm) -> OpResult<lnvps_api_common::GuestInfo> {
+            self.inner.get_guest_info(vm).await
+        }
+
+        async fn reset_password(
+            &self,
+            cfg: &crate::host::FullVmInfo,
+            password: &str,
+        ) -> OpResult<crate::host::PasswordResetMethod> {
+            self.inner.reset_password(cfg, password).await
+        }
+
+        async fn create_vm(
+            &self,
+            req: &crate::host::FullVmInfo,
+            retry: RetryPolicy,
+        ) -> OpResult<()> {
             let fails = self.create_vm_fail_count.load(Ordering::SeqCst);
             if fails > 0 {
                 self.create_vm_fail_count.fetch_sub(1, Ordering::SeqCst);
@@ -108,7 +149,7 @@ mod tests {
                     fails - 1
                 )));
             }
-            self.inner.create_vm(req).await
+            self.inner.create_vm(req, retry).await
         }
 
         async fn delete_vm(&self, vm: &Vm) -> OpResult<()> {
@@ -128,6 +169,12 @@ mod tests {
         }
 
         async fn import_template_disk(&self, cfg: &crate::host::FullVmInfo) -> OpResult<()> {
+            if self.import_fail_image == Some(cfg.image.id) {
+                return Err(OpError::Fatal(anyhow::anyhow!(
+                    "Simulated import failure for image {}",
+                    cfg.image.id
+                )));
+            }
             self.inner.import_template_disk(cfg).await
         }
 
@@ -135,6 +182,14 @@ mod tests {
             self.inner.resize_disk(cfg).await
         }
 
+        async fn is_container(&self, vm: &Vm) -> OpResult<bool> {
+            self.inner.is_container(vm).await
+        }
+
+        async fn recreate_container(&self, cfg: &crate::host::FullVmInfo) -> OpResult<()> {
+            self.inner.recreate_container(cfg).await
+        }
+
         async fn get_vm_state(&self, vm: &Vm) -> OpResult<VmRunningState> {
             self.inner.get_vm_state(vm).await
         }
@@ -204,6 +259,8 @@ mod tests {
             fw_policy_in: None,
             fw_policy_out: None,
             admin_notes: None,
+            private_network: false,
+            suspended_at: None,
         };
 
         assert_eq!(failing_host.start_failures_remaining(), 2);
@@ -251,6 +308,8 @@ mod tests {
             fw_policy_in: None,
             fw_policy_out: None,
             admin_notes: None,
+            private_network: false,
+            suspended_at: None,
         };
 
         assert_eq!(failing_host.stop_failures_remaining(), 1);
@@ -293,6 +352,8 @@ mod tests {
             fw_policy_in: None,
             fw_policy_out: None,
             admin_notes: None,
+            private_network: false,
+            suspended_at: None,
         };
 
         assert_eq!(failing_host.delete_failures_remaining(), 1);
@@ -309,4 +370,146 @@ mod tests {
 
         Ok(())
     }
+
+    /// Provision a VM with image 1, create it on `host` and add image 2 to
+    /// reinstall with
+    async fn setup_reinstall(
+        db: &Arc<MockDb>,
+        host: &FailingVmHostClient,
+    ) -> Result<(VmProvisioner, FullVmInfo)> {
+        let mut image = db.get_os_image(1).await?;
+        image.id = 2;
+        image.version = "13".to_string();
+        db.os_images.lock().await.insert(2, image);
+
+        let prov = VmProvisioner::new(mock_settings(), db.clone());
+        let (user, ssh_key) = add_user(db).await?;
+        let vm = prov
+            .provision(user.id, 1, 1, ssh_key.id, None, false)
+            .await?;
+        let info = FullVmInfo::load(vm.id, db.clone()).await?;
+        host.create_vm(&info, RetryPolicy::default()).await?;
+        host.start_vm(&info.vm).await?;
+        Ok((prov, info))
+    }
+
+    #[tokio::test]
+    async fn test_reinstall_vm_new_image() -> Result<()> {
+        let db = Arc::new(MockDb::default());
+        let host = FailingVmHostClient::new(0, 0, 0, 0);
+        let (prov, info) = setup_reinstall(&db, &host).await?;
+        let vm_id = info.vm.id;
+        assert_eq!(host.inner.primary_disk_image(vm_id).await, Some(1));
+
+        prov.reinstall_vm_on(Arc::new(host.clone()), info, 2)
+            .await?;
+
+        assert_eq!(host.inner.primary_disk_image(vm_id).await, Some(2));
+        assert_eq!(db.get_vm(vm_id).await?.image_id, 2);
+        let vm = db.get_vm(vm_id).await?;
+        assert_eq!(
+            host.get_vm_state(&vm).await?.state,
+            VmRunningStates::Running
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_reinstall_vm_rolls_back_to_prior_image() -> Result<()> {
+        let db = Arc::new(MockDb::default());
+        let host = FailingVmHostClient::new(0, 0, 0, 0).with_import_failure(2);
+        let (prov, info) = setup_reinstall(&db, &host).await?;
+        let vm_id = info.vm.id;
+
+        let result = prov.reinstall_vm_on(Arc::new(host.clone()), info, 2).await;
+        assert!(result.is_err(), "import of image 2 must fail");
+
+        // disk restored from the prior image, image unchanged and VM running again
+        assert_eq!(host.inner.primary_disk_image(vm_id).await, Some(1));
+        let vm = db.get_vm(vm_id).await?;
+        assert_eq!(vm.image_id, 1);
+        assert_eq!(
+            host.get_vm_state(&vm).await?.state,
+            VmRunningStates::Running
+        );
+        Ok(())
+    }
+
+    /// Like [setup_reinstall] but with container template images and a data
+    /// volume attached in slot 0
+    async fn setup_container(
+        db: &Arc<MockDb>,
+        host: &FailingVmHostClient,
+    ) -> Result<(VmProvisioner, FullVmInfo)> {
+        let (prov, info) = setup_reinstall(db, host).await?;
+        for image in db.os_images.lock().await.values_mut() {
+            image.image_kind = OsImageKind::ContainerTemplate;
+        }
+        db.insert_vm_extra_disk(&VmExtraDisk {
+            vm_id: info.vm.id,
+            disk_id: 1,
+            size: 10 * lnvps_api_common::GB,
+            slot: 0,
+            ..Default::default()
+        })
+        .await?;
+        let info = FullVmInfo::load(info.vm.id, db.clone()).await?;
+        host.configure_vm(&info).await?;
+        Ok((prov, info))
+    }
+
+    #[tokio::test]
+    async fn test_swap_container_template_keeps_data() -> Result<()> {
+        let db = Arc::new(MockDb::default());
+        let host = FailingVmHostClient::new(0, 0, 0, 0)
+            .with_containers()
+            .with_import_failure(2);
+        let (prov, info) = setup_container(&db, &host).await?;
+        let vm_id = info.vm.id;
+        assert_eq!(host.inner.data_disk_slots(vm_id).await, vec![0]);
+
+        // a container image never touches the disk-import path
+        prov.reinstall_vm_on(Arc::new(host.clone()), info, 2)
+            .await?;
+
+        assert_eq!(host.inner.primary_disk_image(vm_id).await, Some(2));
+        assert_eq!(host.inner.data_disk_slots(vm_id).await, vec![0]);
+        let vm = db.get_vm(vm_id).await?;
+        assert_eq!(vm.image_id, 2);
+        assert_eq!(
+            host.get_vm_state(&vm).await?.state,
+            VmRunningStates::Running
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_container_template_rejected_on_vm_host() -> Result<()> {
+        let db = Arc::new(MockDb::default());
+        let host = FailingVmHostClient::new(0, 0, 0, 0);
+        let (prov, info) = setup_reinstall(&db, &host).await?;
+        let vm_id = info.vm.id;
+        db.os_images.lock().await.get_mut(&2).unwrap().image_kind = OsImageKind::ContainerTemplate;
+
+        let result = prov.reinstall_vm_on(Arc::new(host.clone()), info, 2).await;
+        assert!(matches!(result, Err(OpError::Fatal(_))));
+        assert_eq!(host.inner.primary_disk_image(vm_id).await, Some(1));
+        assert_eq!(db.get_vm(vm_id).await?.image_id, 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_vm_image_rejected_on_container_host() -> Result<()> {
+        let db = Arc::new(MockDb::default());
+        let host = FailingVmHostClient::new(0, 0, 0, 0).with_containers();
+        let (prov, info) = setup_container(&db, &host).await?;
+        let vm_id = info.vm.id;
+        db.os_images.lock().await.get_mut(&2).unwrap().image_kind = OsImageKind::CloudInit;
+
+        let result = prov.reinstall_vm_on(Arc::new(host.clone()), info, 2).await;
+        assert!(matches!(result, Err(OpError::Fatal(_))));
+        assert_eq!(host.inner.primary_disk_image(vm_id).await, Some(1));
+        assert_eq!(db.get_vm(vm_id).await?.image_id, 1);
+        Ok(())
+    }
 }
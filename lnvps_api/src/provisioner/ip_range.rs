@@ -16,6 +16,7 @@ use anyhow::{Context, Result, anyhow, bail};
 use ipnetwork::IpNetwork;
 use lnvps_api_common::{
     RegistryProvider, RoaDefinition, RouteObject, RpkiProvider, WorkCommander, WorkJob,
+    allocate_subnet,
 };
 use lnvps_db::{
     AvailableIpSpace, IpRangeSubscription, LNVpsDb, SubscriptionLineItem, SubscriptionPayment,
@@ -23,7 +24,6 @@ use lnvps_db::{
 use log::{info, warn};
 use serde::Deserialize;
 use serde_json::json;
-use std::net::{Ipv4Addr, Ipv6Addr};
 use std::sync::Arc;
 
 /// Immutable configuration persisted on the subscription line item at order
@@ -398,96 +398,6 @@ impl IpRangeProvisioner {
     }
 }
 
-// ===========================================================================
-// CIDR sub-allocation
-// ===========================================================================
-
-/// Inclusive numeric bounds `[start, end]` of a CIDR (both families widened to
-/// `u128`).
-fn net_bounds(n: &IpNetwork) -> (u128, u128) {
-    match n {
-        IpNetwork::V4(v4) => {
-            let base = u32::from(v4.network()) as u128;
-            let size = 1u128 << (32 - v4.prefix() as u32);
-            (base, base + size - 1)
-        }
-        IpNetwork::V6(v6) => {
-            let base = u128::from(v6.network());
-            let host_bits = 128 - v6.prefix() as u32;
-            if host_bits >= 128 {
-                (0, u128::MAX)
-            } else {
-                (base, base + (1u128 << host_bits) - 1)
-            }
-        }
-    }
-}
-
-/// Build a CIDR from a numeric network address, prefix, and family.
-fn make_net(start: u128, prefix: u8, v4: bool) -> Option<IpNetwork> {
-    if v4 {
-        IpNetwork::new(std::net::IpAddr::V4(Ipv4Addr::from(start as u32)), prefix).ok()
-    } else {
-        IpNetwork::new(std::net::IpAddr::V6(Ipv6Addr::from(start)), prefix).ok()
-    }
-}
-
-/// Find the first sub-prefix of length `target_len` inside `block` that does
-/// not overlap any CIDR in `taken`. Returns `None` if the parameters are
-/// invalid (wrong family / bad length) or the block is fully allocated.
-///
-/// Runs in time proportional to the number of `taken` allocations, not the size
-/// of the address space: it walks candidates in order and jumps past occupied
-/// ranges, so a mostly-empty block is served from its first free slot instantly.
-pub fn allocate_subnet(
-    block: &IpNetwork,
-    target_len: u8,
-    taken: &[IpNetwork],
-) -> Option<IpNetwork> {
-    let v4 = block.is_ipv4();
-    let max_bits: u32 = if v4 { 32 } else { 128 };
-    if (target_len as u32) < block.prefix() as u32 || target_len as u32 > max_bits {
-        return None;
-    }
-    let host_bits = max_bits - target_len as u32;
-    if host_bits >= 128 {
-        return None;
-    }
-    let size = 1u128 << host_bits;
-    let (block_start, block_end) = net_bounds(block);
-
-    // Only same-family allocations can overlap; sort by start for the walk.
-    let mut taken_bounds: Vec<(u128, u128)> = taken
-        .iter()
-        .filter(|t| t.is_ipv4() == v4)
-        .map(net_bounds)
-        .collect();
-    taken_bounds.sort_unstable();
-
-    let mut cur = block_start;
-    loop {
-        if cur > block_end {
-            return None;
-        }
-        let cand_end = cur.checked_add(size - 1)?;
-        if cand_end > block_end {
-            return None;
-        }
-        // Does the candidate overlap any taken range?
-        if let Some((_, te)) = taken_bounds
-            .iter()
-            .find(|(ts, te)| cur <= *te && *ts <= cand_end)
-        {
-            // Jump to the next size-aligned slot after the occupied range.
-            let after = te.checked_add(1)?;
-            let aligned = (after + size - 1) & !(size - 1);
-            cur = if aligned <= cur { cur + size } else { aligned };
-        } else {
-            return make_net(cur, target_len, v4);
-        }
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -695,6 +605,7 @@ mod tests {
             tax_treatment: None,
             tax_evidence: None,
             tax_breakdown: None,
+            invoice_number: None,
         }
     }
 
@@ -223,6 +223,8 @@ mod tests {
                 fw_policy_in: None,
                 fw_policy_out: None,
                 admin_notes: None,
+                private_network: false,
+                suspended_at: None,
             })
             .await?;
 
@@ -121,7 +121,7 @@ mod tests {
 
         let (user, ssh_key) = add_user(&db).await?;
         let vm = provisioner
-            .provision(user.id, 1, 1, ssh_key.id, None)
+            .provision(user.id, 1, 1, ssh_key.id, None, false)
             .await?;
 
         // Get initial ARP count
@@ -179,11 +179,22 @@ mod tests {
 
         let (user, ssh_key) = add_user(&db).await?;
         let vm = provisioner
-            .provision(user.id, 1, 1, ssh_key.id, None)
+            .provision(user.id, 1, 1, ssh_key.id, None, false)
             .await?;
 
         // Spawn the VM
         let pipeline = provisioner.spawn_vm_pipeline(vm.id).await?;
+        assert_eq!(
+            pipeline.plan(),
+            vec![
+                "ip_allocation (rollback)",
+                "host_spawn (rollback)",
+                "arp_setup (rollback)",
+                "dns_forward (rollback)",
+                "dns_reverse (rollback)",
+                "save_ips (rollback)",
+            ]
+        );
         pipeline.execute().await?;
 
         // Get the VM's MAC address
@@ -251,7 +262,7 @@ mod tests {
 
         let (user, ssh_key) = add_user(&db).await?;
         let vm = provisioner
-            .provision(user.id, 1, 1, ssh_key.id, None)
+            .provision(user.id, 1, 1, ssh_key.id, None, false)
             .await?;
 
         // Get initial DNS zone counts
@@ -332,7 +343,7 @@ mod tests {
 
         let (user, ssh_key) = add_user(&db).await?;
         let vm = provisioner
-            .provision(user.id, 1, 1, ssh_key.id, None)
+            .provision(user.id, 1, 1, ssh_key.id, None, false)
             .await?;
 
         // Before spawn - no IPs should exist
@@ -391,7 +402,7 @@ mod tests {
 
         let (user, ssh_key) = add_user(&db).await?;
         let vm = provisioner
-            .provision(user.id, 1, 1, ssh_key.id, None)
+            .provision(user.id, 1, 1, ssh_key.id, None, false)
             .await?;
 
         // Spawn VM once
@@ -431,7 +442,7 @@ mod tests {
 
         let (user, ssh_key) = add_user(&db).await?;
         let vm = provisioner
-            .provision(user.id, 1, 1, ssh_key.id, None)
+            .provision(user.id, 1, 1, ssh_key.id, None, false)
             .await?;
 
         // Spawn and get the MAC address
@@ -481,7 +492,7 @@ mod tests {
         let provisioner = VmProvisioner::new(settings, db.clone());
         let (user, ssh_key) = add_user(&db).await?;
         let vm = provisioner
-            .provision(user.id, 1, 1, ssh_key.id, None)
+            .provision(user.id, 1, 1, ssh_key.id, None, false)
             .await?;
 
         // Build the real spawn pipeline, then append a step that always fails so
@@ -522,6 +533,76 @@ mod tests {
         Ok(())
     }
 
+    /// When saving the IP assignments fails after the v4 row was written, the
+    /// saved row must be marked deleted and the ARP entry and DNS records
+    /// created by the earlier steps removed.
+    #[tokio::test]
+    async fn test_rollback_when_saving_ips_fails() -> Result<()> {
+        clear_mock_state().await;
+        let settings = mock_settings();
+        let db = Arc::new(MockDb::default());
+        let rates = Arc::new(MockExchangeRate::new());
+        let dns = MockDnsServer::new();
+        rates.set_rate(Ticker::btc_rate("EUR")?, 69_420.0).await;
+        setup_db_with_static_arp(&db).await?;
+
+        let provisioner = VmProvisioner::new(settings, db.clone());
+        let (user, ssh_key) = add_user(&db).await?;
+        let vm = provisioner
+            .provision(user.id, 1, 1, ssh_key.id, None, false)
+            .await?;
+
+        let zone_counts = || async {
+            let zones = dns.zones.lock().await;
+            let rev_count = zones.get("mock-rev-zone-id").map(|z| z.len()).unwrap_or(0);
+            let fwd_count = zones
+                .get("mock-forward-zone-id")
+                .map(|z| z.len())
+                .unwrap_or(0);
+            (rev_count, fwd_count)
+        };
+        let (initial_rev_count, initial_fwd_count) = zone_counts().await;
+
+        // the v4 assignment is saved, the v6 insert fails
+        *db.ip_assignment_insert_limit.lock().await = Some(1);
+        let res = provisioner.spawn_vm_pipeline(vm.id).await?.execute().await;
+        *db.ip_assignment_insert_limit.lock().await = None;
+        assert!(res.is_err(), "pipeline should fail when saving IPs fails");
+
+        assert!(db.list_vm_ip_assignments(vm.id).await?.is_empty());
+        {
+            let ips = db.ip_assignments.lock().await;
+            let saved: Vec<_> = ips.values().filter(|i| i.vm_id == vm.id).collect();
+            assert_eq!(saved.len(), 1, "only the v4 assignment was saved");
+            assert!(
+                saved[0].deleted,
+                "saved assignment should be marked deleted"
+            );
+        }
+
+        let vm_after = db.get_vm(vm.id).await?;
+        assert_eq!(vm_after.mac_address, "ff:ff:ff:ff:ff:ff");
+
+        let arp = MockRouter::new().list_arp_entry().await?;
+        assert!(
+            arp.is_empty(),
+            "router ARP entries should be cleaned up, got {:?}",
+            arp
+        );
+
+        let (rev_count, fwd_count) = zone_counts().await;
+        assert_eq!(
+            rev_count, initial_rev_count,
+            "Reverse DNS records should be removed"
+        );
+        assert_eq!(
+            fwd_count, initial_fwd_count,
+            "Forward DNS records should be removed"
+        );
+
+        Ok(())
+    }
+
     /// Regression: DNS is best-effort. A reverse (PTR) DNS failure — the classic
     /// OVH "forward not resolvable yet" 4xx — must NOT roll back / destroy the
     /// VM. The deploy should succeed with IPs + MAC intact and the reverse ref
@@ -542,7 +623,7 @@ mod tests {
         let provisioner = VmProvisioner::new(settings, db.clone());
         let (user, ssh_key) = add_user(&db).await?;
         let vm = provisioner
-            .provision(user.id, 1, 1, ssh_key.id, None)
+            .provision(user.id, 1, 1, ssh_key.id, None, false)
             .await?;
 
         let pipeline = provisioner.spawn_vm_pipeline(vm.id).await?;
@@ -598,7 +679,7 @@ mod tests {
 
         let (user, ssh_key) = add_user(&db).await?;
         let vm = provisioner
-            .provision(user.id, 1, 1, ssh_key.id, None)
+            .provision(user.id, 1, 1, ssh_key.id, None, false)
             .await?;
 
         // Spawn the VM
@@ -666,8 +747,11 @@ mod tests {
 
         setup_db_with_static_arp(&db).await?;
 
-        let network =
-            VmNetworkProvisioner::new(db.clone(), RetryPolicy::default().with_max_retries(0));
+        let network = VmNetworkProvisioner::new(
+            db.clone(),
+            RetryPolicy::default().with_max_retries(0),
+            RetryPolicy::default().with_max_retries(0),
+        );
 
         // Create a VM
         let (user, ssh_key) = add_user(&db).await?;
@@ -690,6 +774,8 @@ mod tests {
             fw_policy_in: None,
             fw_policy_out: None,
             admin_notes: None,
+            private_network: false,
+            suspended_at: None,
         };
         let vm_id = db.insert_vm(&vm).await?;
         vm.id = vm_id;
@@ -706,6 +792,8 @@ mod tests {
             dns_forward_ref: None,
             dns_reverse_ref: None,
             deleted: false,
+            prefix_length: None,
+            route_ref: None,
         };
 
         let range = db.get_ip_range(1).await?;
@@ -772,7 +860,7 @@ mod tests {
 
         let (user, ssh_key) = add_user(&db).await?;
         let vm = provisioner
-            .provision(user.id, 1, 1, ssh_key.id, None)
+            .provision(user.id, 1, 1, ssh_key.id, None, false)
             .await?;
         let vm_id = vm.id;
 
@@ -819,7 +907,7 @@ mod tests {
 
         let (user, ssh_key) = add_user(&db).await?;
         let vm = provisioner
-            .provision(user.id, 1, 1, ssh_key.id, None)
+            .provision(user.id, 1, 1, ssh_key.id, None, false)
             .await?;
         let vm_id = vm.id;
 
@@ -864,7 +952,7 @@ mod tests {
 
         let (user, ssh_key) = add_user(&db).await?;
         let vm = provisioner
-            .provision(user.id, 1, 1, ssh_key.id, None)
+            .provision(user.id, 1, 1, ssh_key.id, None, false)
             .await?;
 
         let pipeline = provisioner.spawn_vm_pipeline(vm.id).await?;
@@ -915,7 +1003,7 @@ mod tests {
 
         let (user, ssh_key) = add_user(&db).await?;
         let vm = provisioner
-            .provision(user.id, 1, 1, ssh_key.id, None)
+            .provision(user.id, 1, 1, ssh_key.id, None, false)
             .await?;
 
         // Spawn the VM first so it exists on the (mock) host.
@@ -923,7 +1011,7 @@ mod tests {
         pipeline.execute().await?;
 
         // Reinstall should complete without error.
-        provisioner.reinstall_vm(vm.id).await?;
+        provisioner.reinstall_vm(vm.id, vm.image_id).await?;
 
         Ok(())
     }
@@ -939,7 +1027,7 @@ mod tests {
         setup_db_with_static_arp(&db).await?;
 
         let provisioner = VmProvisioner::new(settings, db.clone());
-        let err = provisioner.reinstall_vm(1).await;
+        let err = provisioner.reinstall_vm(1, 1).await;
         assert!(err.is_err(), "reinstall in read-only mode must be rejected");
 
         Ok(())
@@ -1,7 +1,9 @@
-use crate::host::{FullVmInfo, VmHostClient, get_host_client};
+use crate::host::{
+    DataDisk, FullVmInfo, PasswordResetMethod, VmHostClient, create_vm_idempotent, get_host_client,
+};
 use crate::provisioner::VmNetworkProvisioner;
 use crate::router::{ArpEntry, Router, get_router};
-use crate::settings::{ProvisionerConfig, Settings};
+use crate::settings::{ProvisionerConfig, Settings, SharedRetryConfig};
 use anyhow::{Context, Result, anyhow, bail, ensure};
 use chrono::Utc;
 use ipnetwork::IpNetwork;
@@ -9,16 +11,24 @@ use isocountry::CountryCode;
 use lnvps_api_common::DnsServer;
 use lnvps_api_common::retry::{OpResult, Pipeline, RetryPolicy};
 use lnvps_api_common::{
-    AvailableIp, CostResult, HostCapacityService, NetworkProvisioner, NewPaymentInfo,
-    PricingEngine, UpgradeConfig, UpgradeCostQuote, VmStateCache, round_msat_to_sat,
+    AvailableIp, CostResult, CustomVmFloors, HostCapacityService, NetworkProvisioner,
+    NewPaymentInfo, Placement, PricingEngine, TemplateLimitError, UpgradeConfig, UpgradeCostQuote,
+    ValidationError, VmHistoryLogger, VmStateCache, round_msat_to_sat,
 };
 use lnvps_api_common::{ExchangeRateService, op_fatal};
 use lnvps_db::{
-    CpuArch, IntervalType, IpRange, IpRangeAllocationMode, LNVpsDb, PaymentMethod, PaymentType,
-    Subscription, SubscriptionLineItem, SubscriptionPayment, SubscriptionPaymentType,
-    SubscriptionType, Vm, VmCustomTemplate, VmIpAssignment, VmTemplate,
+    CapacityReservation, CpuArch, IntervalType, IpRange, IpRangeAllocationMode, LNVpsDb,
+    OsImageKind, PaymentMethod, PaymentType, Subscription, SubscriptionLineItem,
+    SubscriptionPayment, SubscriptionPaymentType, SubscriptionType, Vm, VmBackup, VmCustomTemplate,
+    VmExtraDisk, VmHostKind, VmIpAssignment, VmOsImage, VmTemplate,
 };
 
+/// Backups over the retention limit, oldest are pruned first
+pub fn backups_to_prune(mut backups: Vec<VmBackup>, retention: u16) -> Vec<VmBackup> {
+    backups.sort_by(|a, b| b.created.cmp(&a.created).then(b.id.cmp(&a.id)));
+    backups.into_iter().skip(retention as usize).collect()
+}
+
 /// Ensure an OS image's CPU architecture is compatible with the target
 /// template's architecture.
 ///
@@ -31,19 +41,121 @@ fn ensure_image_arch_compatible(image_arch: CpuArch, template_arch: CpuArch) ->
         return Ok(());
     }
     if image_arch != template_arch {
-        bail!(
+        bail!(ValidationError::new(format!(
             "OS image architecture ({}) is not compatible with the template architecture ({})",
-            image_arch,
-            template_arch
-        );
+            image_arch, template_arch
+        )));
+    }
+    Ok(())
+}
+
+/// Ensure an OS image can be used for automated provisioning.
+///
+/// Only cloud-init images boot unattended; ISO installers and container
+/// templates need a manual/admin flow.
+fn ensure_image_kind_provisionable(kind: OsImageKind) -> Result<()> {
+    if kind != OsImageKind::CloudInit {
+        bail!(ValidationError::new(format!(
+            "OS image of kind {} cannot be used for automated provisioning",
+            kind
+        )));
+    }
+    Ok(())
+}
+
+/// Ensure an image can be installed on a VM: container templates only on
+/// containers, disk images only on full VMs.
+fn ensure_image_matches_guest(is_container: bool, image: &VmOsImage) -> Result<()> {
+    let container_image = image.image_kind == OsImageKind::ContainerTemplate;
+    if container_image && !is_container {
+        bail!(ValidationError::new(
+            "Container templates can only be used on containers"
+        ));
+    }
+    if !container_image && is_container {
+        bail!(ValidationError::new(format!(
+            "OS image of kind {} cannot be used on a container",
+            image.image_kind
+        )));
+    }
+    Ok(())
+}
+
+/// Ensure an OS image is offered in a region.
+///
+/// Regions without an allowlist offer every image, otherwise the image must
+/// be on the region's list.
+pub async fn ensure_image_allowed_in_region(
+    db: &Arc<dyn LNVpsDb>,
+    region_id: u64,
+    image_id: u64,
+) -> Result<()> {
+    let allowed = db.list_region_os_images(region_id).await?;
+    if !allowed.is_empty() && !allowed.contains(&image_id) {
+        bail!(ValidationError::new(
+            "OS image is not available in this region"
+        ));
     }
     Ok(())
 }
-use log::{debug, info, warn};
+
+/// Minimum length of a VM login password
+const MIN_VM_PASSWORD_LEN: usize = 12;
+/// Maximum length of a VM login password
+const MAX_VM_PASSWORD_LEN: usize = 128;
+
+/// Ensure a VM login password is strong enough to expose on a public IP.
+///
+/// Requires at least 12 characters from at least 3 of the 4 classes
+/// (lowercase, uppercase, digits, symbols), without whitespace or control
+/// characters. Error messages never include the password itself.
+pub fn validate_vm_password(password: &str) -> Result<()> {
+    let len = password.chars().count();
+    ensure!(
+        len >= MIN_VM_PASSWORD_LEN,
+        "Password must be at least {} characters",
+        MIN_VM_PASSWORD_LEN
+    );
+    ensure!(
+        len <= MAX_VM_PASSWORD_LEN,
+        "Password must be at most {} characters",
+        MAX_VM_PASSWORD_LEN
+    );
+    ensure!(
+        !password
+            .chars()
+            .any(|c| c.is_whitespace() || c.is_control()),
+        "Password must not contain whitespace or control characters"
+    );
+    let classes = [
+        password.chars().any(|c| c.is_lowercase()),
+        password.chars().any(|c| c.is_uppercase()),
+        password.chars().any(|c| c.is_ascii_digit()),
+        password.chars().any(|c| !c.is_alphanumeric()),
+    ];
+    ensure!(
+        classes.iter().filter(|c| **c).count() >= 3,
+        "Password must contain at least 3 of: lowercase letters, uppercase letters, digits, symbols"
+    );
+    Ok(())
+}
+
+/// Generate a random VM login password which passes [validate_vm_password]
+pub fn generate_vm_password() -> String {
+    use rand::distr::{Alphanumeric, SampleString};
+    loop {
+        let password = Alphanumeric.sample_string(&mut rand::rng(), 20);
+        if validate_vm_password(&password).is_ok() {
+            return password;
+        }
+    }
+}
+use log::{debug, error, info, warn};
 use payments_rs::currency::{Currency, CurrencyAmount};
 use payments_rs::fiat::FiatPaymentService;
 use payments_rs::lightning::{AddInvoiceRequest, LightningNode};
 use std::collections::HashMap;
+use std::net::IpAddr;
 use std::ops::Add;
 use std::str::FromStr;
 use std::sync::Arc;
@@ -54,6 +166,26 @@ use std::time::Duration;
 /// leave a VM with a MAC but no IP.
 pub(crate) const UNASSIGNED_MAC: &str = "ff:ff:ff:ff:ff:ff";
 
+/// Maximum number of extra data disks a single VM can have
+const MAX_DATA_DISKS: u16 = 8;
+
+/// Longest a single VM create call on the host may take before the spawn is
+/// rolled back
+const HOST_SPAWN_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// Outcome of [VmProvisioner::hotplug_resize]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HotplugResize {
+    /// The new CPU/memory is applied to the running VM
+    Live,
+    /// Nothing was changed, the VM must be restarted to apply the new specs
+    RestartRequired,
+}
+
+/// How long a VM create request id stays claimed without a VM before the
+/// worker releases it
+pub const VM_CREATE_REQUEST_TIMEOUT: chrono::TimeDelta = chrono::TimeDelta::hours(1);
+
 /// Main provisioner class for LNVPS (VMs)
 #[derive(Clone)]
 pub struct VmProvisioner {
@@ -62,20 +194,22 @@ pub struct VmProvisioner {
     pub network: VmNetworkProvisioner,
     provisioner_config: ProvisionerConfig,
     pub delete_after: u16,
+    /// Retry policies, shared with the network provisioner and the worker
+    retry: SharedRetryConfig,
+    /// Smallest resources a custom VM may be ordered or upgraded to
+    custom_floors: CustomVmFloors,
 }
 
 impl VmProvisioner {
-    /// Create a retry policy for network operations (DNS, Router, Host)
-    fn retry_policy() -> RetryPolicy {
-        RetryPolicy::default()
-    }
-
     pub fn new(settings: Settings, db: Arc<dyn LNVpsDb>) -> Self {
+        let retry = SharedRetryConfig::new(settings.retry.clone());
         Self {
-            network: VmNetworkProvisioner::new(db.clone(), Self::retry_policy()),
+            network: VmNetworkProvisioner::new(db.clone(), retry.clone()),
+            retry,
             provisioner_config: settings.provisioner,
             read_only: settings.read_only,
             delete_after: settings.delete_after,
+            custom_floors: settings.custom_vm_floors,
             db,
         }
     }
@@ -84,6 +218,11 @@ impl VmProvisioner {
         &self.provisioner_config
     }
 
+    /// Retry policies used by the provisioner, a settings reload sets them here
+    pub fn retry_config(&self) -> SharedRetryConfig {
+        self.retry.clone()
+    }
+
     /// Do any necessary initialization
     pub async fn init(&self) -> Result<()> {
         Ok(())
@@ -99,6 +238,7 @@ impl VmProvisioner {
     /// Note:
     /// 1. Does not create a VM on the host machine
     /// 2. Does not assign any IP resources
+    /// 3. A `private_network` VM is only placed on hosts with a private bridge
     pub async fn provision(
         &self,
         user_id: u64,
@@ -106,115 +246,144 @@ impl VmProvisioner {
         image_id: u64,
         ssh_key_id: u64,
         ref_code: Option<String>,
+        private_network: bool,
     ) -> Result<Vm> {
         let user = self.db.get_user(user_id).await?;
         let template = self.db.get_vm_template(template_id).await?;
         let image = self.db.get_os_image(image_id).await?;
         let ssh_key = self.db.get_user_ssh_key(ssh_key_id).await?;
         if ssh_key.user_id != user.id {
-            bail!("SSH key does not belong to this user");
+            bail!(ValidationError::new("SSH key does not belong to this user"));
         }
 
         if !template.enabled {
-            bail!("Cant create VM from disabled template");
+            bail!(ValidationError::new(
+                "Cant create VM from disabled template"
+            ));
         }
         if let Some(exp) = template.expires.as_ref()
             && exp < &Utc::now()
         {
-            bail!("Cant create VM from expired template");
+            bail!(ValidationError::new("Cant create VM from expired template"));
         }
         if !image.enabled {
-            bail!("Cant create VM from disabled os image");
+            bail!(ValidationError::new(
+                "Cant create VM from disabled os image"
+            ));
         }
         ensure_image_arch_compatible(image.cpu_arch, template.cpu_arch)?;
+        ensure_image_kind_provisionable(image.image_kind)?;
+        ensure_image_allowed_in_region(&self.db, template.region_id, image.id).await?;
+        self.ensure_template_limit(user.id, &template).await?;
 
         // TODO: cache capacity somewhere
         let cap = HostCapacityService::new(self.db.clone());
-        let host = cap
-            .get_host_for_template(template.region_id, &template)
-            .await?;
-
-        let pick_disk = if let Some(hd) = host.disks.first() {
-            hd
-        } else {
-            bail!("No host disk found")
-        };
-
-        let region = self.db.get_host_region(template.region_id).await?;
-        let cost_plan = self.db.get_cost_plan(template.cost_plan_id).await?;
-
-        // Create subscription for this VM
-        let subscription = Subscription {
-            id: 0,
-            user_id: user.id,
-            company_id: region.company_id,
-            name: format!("{} subscription", template.name),
-            description: None,
-            created: Utc::now(),
-            expires: None,
-            is_active: false,
-            is_setup: false,
-            currency: cost_plan.currency.clone(),
-            interval_amount: cost_plan.interval_amount,
-            interval_type: cost_plan.interval_type,
-            setup_fee: 0,
-            // Default new VM subscriptions to auto-renew. Renewal only actually
-            // fires when the user has a saved payment method (the worker checks),
-            // so this is a no-op for users without one.
-            auto_renewal_enabled: true,
-            external_id: None,
-        };
-        let line_item = SubscriptionLineItem {
-            id: 0,
-            subscription_id: 0,
-            subscription_type: SubscriptionType::Vps,
-            name: template.name.clone(),
-            description: None,
-            amount: cost_plan.amount,
-            setup_amount: 0,
-            configuration: None,
-        };
-        let (subscription_id, line_item_ids) = self
-            .db
-            .insert_subscription_with_line_items(&subscription, vec![line_item])
-            .await?;
-        let subscription_line_item_id = line_item_ids[0];
-
-        let mut new_vm = Vm {
-            id: 0,
-            host_id: host.host.id,
-            user_id: user.id,
-            image_id: image.id,
-            template_id: Some(template.id),
-            custom_template_id: None,
-            subscription_line_item_id,
-            ssh_key_id: Some(ssh_key.id),
-            disk_id: pick_disk.disk.id,
-            mac_address: "ff:ff:ff:ff:ff:ff".to_string(),
-            deleted: false,
-            ref_code,
-            disabled: false,
-            fw_policy_in: None,
-            fw_policy_out: None,
-            admin_notes: None,
-        };
-
-        let new_id = self.db.insert_vm(&new_vm).await?;
-        new_vm.id = new_id;
-
-        // Update subscription and line item names now that the VM ID is known
-        let mut sub = self.db.get_subscription(subscription_id).await?;
-        sub.name = format!("VM{} subscription", new_vm.id);
-        self.db.update_subscription(&sub).await?;
-
-        let mut li = self
-            .db
-            .get_subscription_line_item(subscription_line_item_id)
+        let (host, reservation) = cap
+            .reserve_host_for_template(
+                template.region_id,
+                &template,
+                Placement::for_user(&user).with_private_network(private_network),
+            )
             .await?;
-        li.name = format!("VM{} - {}", new_vm.id, template.name);
-        self.db.update_subscription_line_item(&li).await?;
+        let res = async {
+            let region = self.db.get_host_region(template.region_id).await?;
+            let cost_plan = self.db.get_cost_plan(template.cost_plan_id).await?;
 
-        Ok(new_vm)
+            // Create subscription for this VM
+            let subscription = Subscription {
+                id: 0,
+                user_id: user.id,
+                company_id: region.company_id,
+                name: format!("{} subscription", template.name),
+                description: None,
+                created: Utc::now(),
+                expires: None,
+                is_active: false,
+                is_setup: false,
+                currency: cost_plan.currency.clone(),
+                interval_amount: cost_plan.interval_amount,
+                interval_type: cost_plan.interval_type,
+                setup_fee: 0,
+                // Default new VM subscriptions to auto-renew. Renewal only actually
+                // fires when the user has a saved payment method (the worker checks),
+                // so this is a no-op for users without one.
+                auto_renewal_enabled: true,
+                external_id: None,
+            };
+            let line_item = SubscriptionLineItem {
+                id: 0,
+                subscription_id: 0,
+                subscription_type: SubscriptionType::Vps,
+                name: template.name.clone(),
+                description: None,
+                amount: cost_plan.amount,
+                setup_amount: 0,
+                configuration: None,
+            };
+            let (subscription_id, line_item_ids) = self
+                .db
+                .insert_subscription_with_line_items(&subscription, vec![line_item])
+                .await?;
+            let subscription_line_item_id = line_item_ids[0];
+
+            let mut new_vm = Vm {
+                id: 0,
+                host_id: host.host.id,
+                user_id: user.id,
+                image_id: image.id,
+                template_id: Some(template.id),
+                custom_template_id: None,
+                subscription_line_item_id,
+                ssh_key_id: Some(ssh_key.id),
+                disk_id: reservation.disk_id,
+                mac_address: "ff:ff:ff:ff:ff:ff".to_string(),
+                deleted: false,
+                ref_code,
+                disabled: false,
+                fw_policy_in: None,
+                fw_policy_out: None,
+                admin_notes: None,
+                private_network,
+                suspended_at: None,
+            };
+
+            let new_id = match template.max_per_user {
+                Some(limit) => {
+                    // the check above is only a fast path, the insert enforces
+                    // the limit against concurrent orders
+                    let Some(id) = self
+                        .db
+                        .insert_vm_within_template_limit(&new_vm, limit)
+                        .await?
+                    else {
+                        self.db.delete_subscription(subscription_id).await?;
+                        bail!(TemplateLimitError {
+                            template_id: template.id,
+                            limit,
+                        });
+                    };
+                    id
+                }
+                None => self.db.insert_vm(&new_vm).await?,
+            };
+            new_vm.id = new_id;
+
+            // Update subscription and line item names now that the VM ID is known
+            let mut sub = self.db.get_subscription(subscription_id).await?;
+            sub.name = format!("VM{} subscription", new_vm.id);
+            self.db.update_subscription(&sub).await?;
+
+            let mut li = self
+                .db
+                .get_subscription_line_item(subscription_line_item_id)
+                .await?;
+            li.name = format!("VM{} - {}", new_vm.id, template.name);
+            self.db.update_subscription_line_item(&li).await?;
+
+            anyhow::Ok(new_vm)
+        }
+        .await;
+        self.finish_reservation(&cap, &reservation, res).await
     }
 
     /// Provision a new VM for a user on the database
@@ -222,6 +391,7 @@ impl VmProvisioner {
     /// Note:
     /// 1. Does not create a VM on the host machine
     /// 2. Does not assign any IP resources
+    /// 3. A `private_network` VM is only placed on hosts with a private bridge
     pub async fn provision_custom(
         &self,
         user_id: u64,
@@ -229,31 +399,40 @@ impl VmProvisioner {
         image_id: u64,
         ssh_key_id: u64,
         ref_code: Option<String>,
+        private_network: bool,
     ) -> Result<Vm> {
         let user = self.db.get_user(user_id).await?;
         let pricing = self.db.get_custom_pricing(template.pricing_id).await?;
         let image = self.db.get_os_image(image_id).await?;
         let ssh_key = self.db.get_user_ssh_key(ssh_key_id).await?;
         if ssh_key.user_id != user.id {
-            bail!("SSH key does not belong to this user");
+            bail!(ValidationError::new("SSH key does not belong to this user"));
         }
 
         if !pricing.enabled {
-            bail!("Cant create VM from disabled custom pricing");
+            bail!(ValidationError::new(
+                "Cant create VM from disabled custom pricing"
+            ));
         }
         if let Some(exp) = pricing.expires.as_ref()
             && exp < &Utc::now()
         {
-            bail!("Cant create VM from expired custom pricing");
+            bail!(ValidationError::new(
+                "Cant create VM from expired custom pricing"
+            ));
         }
         if !image.enabled {
-            bail!("Cant create VM from disabled os image");
+            bail!(ValidationError::new(
+                "Cant create VM from disabled os image"
+            ));
         }
         ensure_image_arch_compatible(image.cpu_arch, template.cpu_arch)?;
+        ensure_image_kind_provisionable(image.image_kind)?;
+        ensure_image_allowed_in_region(&self.db, pricing.region_id, image.id).await?;
 
         // Reject out-of-range specs at order time (pricing itself no longer
         // validates, so existing/grandfathered VMs can still be priced/renewed).
-        PricingEngine::validate_custom_vm_spec(&self.db, &template).await?;
+        PricingEngine::validate_custom_vm_spec(&self.db, &template, &self.custom_floors).await?;
 
         // Copy resource limits from the pricing plan into the template
         template.disk_iops_read = pricing.disk_iops_read;
@@ -264,112 +443,226 @@ impl VmProvisioner {
         template.cpu_limit = pricing.cpu_limit;
 
         let cap = HostCapacityService::new(self.db.clone());
-        let host = cap
-            .get_host_for_template(pricing.region_id, &template)
+        let (host, reservation) = cap
+            .reserve_host_for_template(
+                pricing.region_id,
+                &template,
+                Placement::for_user(&user).with_private_network(private_network),
+            )
             .await?;
+        let res = async {
+            // insert custom templates
+            let template_id = self.db.insert_custom_vm_template(&template).await?;
+            let region = self.db.get_host_region(pricing.region_id).await?;
 
-        let pick_disk = if let Some(hd) = host.disks.first() {
-            hd
-        } else {
-            bail!("No host disk found")
-        };
-
-        // insert custom templates
-        let template_id = self.db.insert_custom_vm_template(&template).await?;
-        let region = self.db.get_host_region(pricing.region_id).await?;
+            // Create subscription for this custom VM (1-month interval, amount computed at payment time)
+            let subscription = Subscription {
+                id: 0,
+                user_id: user.id,
+                company_id: region.company_id,
+                name: "Custom VM subscription".to_string(),
+                description: None,
+                created: Utc::now(),
+                expires: None,
+                is_active: false,
+                is_setup: false,
+                currency: pricing.currency.clone(),
+                interval_amount: 1,
+                interval_type: IntervalType::Month,
+                setup_fee: 0,
+                // Default new custom VM subscriptions to auto-renew (see note above).
+                auto_renewal_enabled: true,
+                external_id: None,
+            };
+            let line_item = SubscriptionLineItem {
+                id: 0,
+                subscription_id: 0,
+                subscription_type: SubscriptionType::Vps,
+                name: pricing.name.clone(),
+                description: None,
+                // Recorded base monthly amount; the exact charge is recomputed from
+                // the VM's specs (and IP assignments) at payment time. Filled in
+                // below once the VM id is known.
+                amount: 0,
+                setup_amount: 0,
+                configuration: None,
+            };
+            let (subscription_id, line_item_ids) = self
+                .db
+                .insert_subscription_with_line_items(&subscription, vec![line_item])
+                .await?;
+            let subscription_line_item_id = line_item_ids[0];
+
+            let mut new_vm = Vm {
+                id: 0,
+                host_id: host.host.id,
+                user_id: user.id,
+                image_id: image.id,
+                template_id: None,
+                custom_template_id: Some(template_id),
+                subscription_line_item_id,
+                ssh_key_id: Some(ssh_key.id),
+                disk_id: reservation.disk_id,
+                mac_address: "ff:ff:ff:ff:ff:ff".to_string(),
+                deleted: false,
+                ref_code,
+                disabled: false,
+                fw_policy_in: None,
+                fw_policy_out: None,
+                admin_notes: None,
+                private_network,
+                suspended_at: None,
+            };
+
+            let new_id = self.db.insert_vm(&new_vm).await?;
+            new_vm.id = new_id;
+
+            // Update subscription and line item names now that the VM ID is known
+            let mut sub = self.db.get_subscription(subscription_id).await?;
+            sub.name = format!("VM{} subscription", new_vm.id);
+            self.db.update_subscription(&sub).await?;
+
+            let mut li = self
+                .db
+                .get_subscription_line_item(subscription_line_item_id)
+                .await?;
+            li.name = format!("VM{} - {}", new_vm.id, pricing.name);
+            // Record the base monthly amount now that the VM id is known. With no IP
+            // assignments yet this prices the base config plus the minimum 1x IPv4/IPv6.
+            let price =
+                PricingEngine::get_custom_vm_cost_amount(&self.db, new_vm.id, &template).await?;
+            li.amount = price.total();
+            self.db.update_subscription_line_item(&li).await?;
+
+            anyhow::Ok(new_vm)
+        }
+        .await;
+        self.finish_reservation(&cap, &reservation, res).await
+    }
 
-        // Create subscription for this custom VM (1-month interval, amount computed at payment time)
-        let subscription = Subscription {
-            id: 0,
-            user_id: user.id,
-            company_id: region.company_id,
-            name: "Custom VM subscription".to_string(),
-            description: None,
-            created: Utc::now(),
-            expires: None,
-            is_active: false,
-            is_setup: false,
-            currency: pricing.currency.clone(),
-            interval_amount: 1,
-            interval_type: IntervalType::Month,
-            setup_fee: 0,
-            // Default new custom VM subscriptions to auto-renew (see note above).
-            auto_renewal_enabled: true,
-            external_id: None,
-        };
-        let line_item = SubscriptionLineItem {
-            id: 0,
-            subscription_id: 0,
-            subscription_type: SubscriptionType::Vps,
-            name: pricing.name.clone(),
-            description: None,
-            // Recorded base monthly amount; the exact charge is recomputed from
-            // the VM's specs (and IP assignments) at payment time. Filled in
-            // below once the VM id is known.
-            amount: 0,
-            setup_amount: 0,
-            configuration: None,
+    /// Reject a new VM when the user already has the template's
+    /// `max_per_user` active VMs of it, before any capacity is reserved.
+    /// The VM insert re-checks the limit atomically.
+    async fn ensure_template_limit(&self, user_id: u64, template: &VmTemplate) -> Result<()> {
+        let Some(limit) = template.max_per_user else {
+            return Ok(());
         };
-        let (subscription_id, line_item_ids) = self
+        let active = self
             .db
-            .insert_subscription_with_line_items(&subscription, vec![line_item])
-            .await?;
-        let subscription_line_item_id = line_item_ids[0];
-
-        let mut new_vm = Vm {
-            id: 0,
-            host_id: host.host.id,
-            user_id: user.id,
-            image_id: image.id,
-            template_id: None,
-            custom_template_id: Some(template_id),
-            subscription_line_item_id,
-            ssh_key_id: Some(ssh_key.id),
-            disk_id: pick_disk.disk.id,
-            mac_address: "ff:ff:ff:ff:ff:ff".to_string(),
-            deleted: false,
-            ref_code,
-            disabled: false,
-            fw_policy_in: None,
-            fw_policy_out: None,
-            admin_notes: None,
-        };
-
-        let new_id = self.db.insert_vm(&new_vm).await?;
-        new_vm.id = new_id;
+            .list_user_vms(user_id)
+            .await?
+            .iter()
+            .filter(|vm| vm.template_id == Some(template.id))
+            .count();
+        if active >= limit as usize {
+            bail!(TemplateLimitError {
+                template_id: template.id,
+                limit,
+            });
+        }
+        Ok(())
+    }
 
-        // Update subscription and line item names now that the VM ID is known
-        let mut sub = self.db.get_subscription(subscription_id).await?;
-        sub.name = format!("VM{} subscription", new_vm.id);
-        self.db.update_subscription(&sub).await?;
+    /// Complete a capacity reservation taken for a new order: bind it to the
+    /// created VM so it keeps holding capacity until the VM is paid for, or
+    /// release it if the order failed.
+    async fn finish_reservation(
+        &self,
+        cap: &HostCapacityService,
+        reservation: &CapacityReservation,
+        res: Result<Vm>,
+    ) -> Result<Vm> {
+        match res {
+            Ok(vm) => {
+                self.db
+                    .set_capacity_reservation_vm(reservation.id, vm.id)
+                    .await?;
+                Ok(vm)
+            }
+            Err(e) => {
+                if let Err(re) = cap.release_reservation(reservation).await {
+                    warn!(
+                        "Failed to release capacity reservation {}: {}",
+                        reservation.id, re
+                    );
+                }
+                Err(e)
+            }
+        }
+    }
 
-        let mut li = self
+    /// Run a VM create at most once per client request id of a user.
+    ///
+    /// The first call with a `request_id` runs `create`, later calls with the same
+    /// id return the VM it created. Returns the VM and whether it was created by
+    /// this call. Without a `request_id` `create` always runs. An id whose create
+    /// never finished is released by the worker after [VM_CREATE_REQUEST_TIMEOUT].
+    pub async fn provision_once<F>(
+        &self,
+        user_id: u64,
+        request_id: Option<&str>,
+        create: F,
+    ) -> Result<(Vm, bool)>
+    where
+        F: Future<Output = Result<Vm>>,
+    {
+        let Some(request_id) = request_id else {
+            return Ok((create.await?, true));
+        };
+        if !self
             .db
-            .get_subscription_line_item(subscription_line_item_id)
-            .await?;
-        li.name = format!("VM{} - {}", new_vm.id, pricing.name);
-        // Record the base monthly amount now that the VM id is known. With no IP
-        // assignments yet this prices the base config plus the minimum 1x IPv4/IPv6.
-        let price =
-            PricingEngine::get_custom_vm_cost_amount(&self.db, new_vm.id, &template).await?;
-        li.amount = price.total();
-        self.db.update_subscription_line_item(&li).await?;
-
-        Ok(new_vm)
+            .insert_vm_create_request(user_id, request_id)
+            .await?
+        {
+            let req = self.db.get_vm_create_request(user_id, request_id).await?;
+            let Some(vm_id) = req.vm_id else {
+                bail!(ValidationError::new(
+                    "A VM create request with this id is already in progress"
+                ));
+            };
+            return Ok((self.db.get_vm(vm_id).await?, false));
+        }
+        match create.await {
+            Ok(vm) => {
+                self.db
+                    .set_vm_create_request_vm(user_id, request_id, vm.id)
+                    .await?;
+                Ok((vm, true))
+            }
+            Err(e) => {
+                // release the id so the client can retry the failed create
+                if let Err(de) = self.db.delete_vm_create_request(user_id, request_id).await {
+                    warn!("Failed to release VM create request {}: {}", request_id, de);
+                }
+                Err(e)
+            }
+        }
     }
 
     /// Import a VM that already exists on a host but isn't tracked in the
     /// database (issue #166).
     ///
-    /// The VM is assigned to `user_id` and billed via the region's custom
-    /// pricing (which is required — import fails if the region has none). The
+    /// The VM is assigned to `user_id` and billed either from the standard
+    /// template `template_id` (which must be in the host's region and match the
+    /// VM's CPU, memory and disk size) or, when no
+    /// template is given, via the region's custom pricing (which is then
+    /// required — import fails if the region has none). In the latter case the
     /// VM's current specs (CPU/memory/disk) are captured into a custom template.
-    /// No changes are made on the host; this only creates the database records.
+    ///
+    /// IPs configured on the VM are recorded as IP assignments in the region's
+    /// matching IP ranges. No changes are made on the host or router; this only
+    /// creates the database records.
     ///
     /// The database VM id is fixed by the host's id mapping (e.g. Proxmox
     /// `vmid = db_id + 100`) so that all subsequent lifecycle operations target
     /// the correct host VM.
-    pub async fn import_vm(&self, host_id: u64, host_vm_id: i64, user_id: u64) -> Result<Vm> {
+    pub async fn import_vm(
+        &self,
+        host_id: u64,
+        host_vm_id: i64,
+        user_id: u64,
+        template_id: Option<u64>,
+    ) -> Result<Vm> {
         let user = self.db.get_user(user_id).await?;
         let host = self.db.get_host(host_id).await?;
 
@@ -397,19 +690,43 @@ impl VmProvisioner {
             bail!("VM {} is already tracked in the database", vm_id);
         }
 
-        // Region custom pricing is required for import
-        let pricing = self
-            .db
-            .list_custom_pricing(host.region_id)
-            .await?
-            .into_iter()
-            .find(|p| p.enabled && p.expires.map(|e| e > Utc::now()).unwrap_or(true))
-            .ok_or_else(|| {
-                anyhow!(
-                    "No enabled custom pricing for region {}, cannot import VM",
-                    host.region_id
-                )
-            })?;
+        // Resolve the VM's IPs before writing anything, so an IP which is
+        // already assigned elsewhere aborts the import cleanly
+        let ip_assignments = self
+            .import_ip_assignments(host.region_id, &spec.ip_addresses)
+            .await?;
+
+        let standard = if let Some(template_id) = template_id {
+            let template = self.db.get_vm_template(template_id).await?;
+            if template.region_id != host.region_id {
+                bail!(
+                    "Template {} is not in the region of host {}",
+                    template_id,
+                    host_id
+                );
+            }
+            // the template is what gets billed, so it must match what is running
+            if template.cpu != spec.cpu
+                || template.memory != spec.memory
+                || template.disk_size != spec.disk_size
+            {
+                bail!(
+                    "Template {} ({} CPU, {} memory, {} disk) does not match VM {} ({} CPU, {} memory, {} disk), import with custom pricing instead",
+                    template_id,
+                    template.cpu,
+                    template.memory,
+                    template.disk_size,
+                    host_vm_id,
+                    spec.cpu,
+                    spec.memory,
+                    spec.disk_size
+                );
+            }
+            let cost_plan = self.db.get_cost_plan(template.cost_plan_id).await?;
+            Some((template, cost_plan))
+        } else {
+            None
+        };
 
         // Pick the host disk backing this VM (match on the storage pool name,
         // else fall back to the first enabled/available disk).
@@ -427,77 +744,105 @@ impl VmProvisioner {
         // keeps its real disk; the image is only cosmetic / used on reinstall.
         let image = self
             .db
-            .list_os_image()
+            .list_os_image_by_kind(OsImageKind::CloudInit)
             .await?
             .into_iter()
             .find(|i| i.enabled)
             .ok_or_else(|| anyhow!("No OS image available to use as import placeholder"))?;
 
-        // Capture the current specs into a custom template
-        let template = VmCustomTemplate {
-            id: 0,
-            cpu: spec.cpu,
-            memory: spec.memory,
-            disk_size: spec.disk_size,
-            disk_type: disk.kind,
-            disk_interface: disk.interface,
-            pricing_id: pricing.id,
-            cpu_mfg: pricing.cpu_mfg,
-            cpu_arch: pricing.cpu_arch,
-            cpu_features: pricing.cpu_features.clone(),
-            disk_iops_read: pricing.disk_iops_read,
-            disk_iops_write: pricing.disk_iops_write,
-            disk_mbps_read: pricing.disk_mbps_read,
-            disk_mbps_write: pricing.disk_mbps_write,
-            network_mbps: pricing.network_mbps,
-            cpu_limit: pricing.cpu_limit,
-            firewall_rule_limit: None,
-        };
-        let template_id = self.db.insert_custom_vm_template(&template).await?;
         let region = self.db.get_host_region(host.region_id).await?;
-
-        // Create a subscription for this imported VM (mirrors provision_custom)
-        let subscription = Subscription {
+        let mut subscription = Subscription {
             id: 0,
             user_id: user.id,
             company_id: region.company_id,
-            name: "Imported VM subscription".to_string(),
+            name: format!("VM{} subscription", vm_id),
             description: None,
             created: Utc::now(),
             expires: None,
             is_active: false,
             is_setup: false,
-            currency: pricing.currency.clone(),
+            currency: String::new(),
             interval_amount: 1,
             interval_type: IntervalType::Month,
             setup_fee: 0,
             auto_renewal_enabled: true,
             external_id: None,
         };
-        let line_item = SubscriptionLineItem {
+        let mut line_item = SubscriptionLineItem {
             id: 0,
             subscription_id: 0,
             subscription_type: SubscriptionType::Vps,
-            name: pricing.name.clone(),
+            name: String::new(),
             description: None,
             amount: 0,
             setup_amount: 0,
             configuration: None,
         };
-        let (subscription_id, line_item_ids) = self
-            .db
-            .insert_subscription_with_line_items(&subscription, vec![line_item])
+
+        let custom_template = if let Some((template, cost_plan)) = &standard {
+            subscription.currency = cost_plan.currency.clone();
+            subscription.interval_amount = cost_plan.interval_amount;
+            subscription.interval_type = cost_plan.interval_type;
+            line_item.name = format!("VM{} - {}", vm_id, template.name);
+            line_item.amount = cost_plan.amount;
+            None
+        } else {
+            // Region custom pricing is required for import without a template
+            let pricing = self
+                .db
+                .list_custom_pricing(host.region_id)
+                .await?
+                .into_iter()
+                .find(|p| p.enabled && p.expires.map(|e| e > Utc::now()).unwrap_or(true))
+                .ok_or_else(|| {
+                    anyhow!(
+                        "No enabled custom pricing for region {}, cannot import VM",
+                        host.region_id
+                    )
+                })?;
+
+            // Capture the current specs into a custom template
+            let template = VmCustomTemplate {
+                id: 0,
+                cpu: spec.cpu,
+                memory: spec.memory,
+                disk_size: spec.disk_size,
+                disk_type: disk.kind,
+                disk_interface: disk.interface,
+                pricing_id: pricing.id,
+                cpu_mfg: pricing.cpu_mfg,
+                cpu_arch: pricing.cpu_arch,
+                cpu_features: pricing.cpu_features.clone(),
+                disk_iops_read: pricing.disk_iops_read,
+                disk_iops_write: pricing.disk_iops_write,
+                disk_mbps_read: pricing.disk_mbps_read,
+                disk_mbps_write: pricing.disk_mbps_write,
+                network_mbps: pricing.network_mbps,
+                cpu_limit: pricing.cpu_limit,
+                firewall_rule_limit: None,
+                backup_retention: 0,
+            };
+            let price = PricingEngine::get_custom_template_cost_amount(
+                &self.db,
+                &template,
+                &ip_assignments,
+                &[],
+            )
             .await?;
-        let subscription_line_item_id = line_item_ids[0];
+            subscription.currency = pricing.currency.clone();
+            line_item.name = format!("VM{} - {}", vm_id, pricing.name);
+            line_item.amount = price.total();
+            Some(template)
+        };
 
         let new_vm = Vm {
             id: vm_id,
             host_id: host.id,
             user_id: user.id,
             image_id: image.id,
-            template_id: None,
-            custom_template_id: Some(template_id),
-            subscription_line_item_id,
+            template_id: standard.as_ref().map(|(t, _)| t.id),
+            custom_template_id: None,
+            subscription_line_item_id: 0,
             ssh_key_id: None,
             disk_id: disk.id,
             mac_address: spec
@@ -510,27 +855,65 @@ impl VmProvisioner {
             fw_policy_in: None,
             fw_policy_out: None,
             admin_notes: None,
+            private_network: false,
+            suspended_at: None,
         };
 
-        // Insert with the explicit (mapped) id so lifecycle ops target the right host VM
-        self.db.insert_vm_with_id(&new_vm).await?;
-
-        // Name the subscription/line item and record the base monthly amount
-        let mut sub = self.db.get_subscription(subscription_id).await?;
-        sub.name = format!("VM{} subscription", new_vm.id);
-        self.db.update_subscription(&sub).await?;
-
-        let mut li = self
+        // Insert everything in one transaction, with the explicit (mapped) id
+        // so lifecycle ops target the right host VM
+        Ok(self
             .db
-            .get_subscription_line_item(subscription_line_item_id)
-            .await?;
-        li.name = format!("VM{} - {}", new_vm.id, pricing.name);
-        let price =
-            PricingEngine::get_custom_vm_cost_amount(&self.db, new_vm.id, &template).await?;
-        li.amount = price.total();
-        self.db.update_subscription_line_item(&li).await?;
+            .import_vm(
+                &new_vm,
+                &subscription,
+                &line_item,
+                custom_template.as_ref(),
+                &ip_assignments,
+            )
+            .await?)
+    }
 
-        Ok(new_vm)
+    /// Build the IP assignments for IPs found in an imported VM's config.
+    ///
+    /// Each IP is matched to an IP range in `region_id`, IPs outside every range
+    /// are skipped. Fails if an IP is already assigned to another VM.
+    async fn import_ip_assignments(
+        &self,
+        region_id: u64,
+        ips: &[String],
+    ) -> Result<Vec<VmIpAssignment>> {
+        let ranges = self.db.list_ip_range_in_region(region_id).await?;
+        let mut ret = Vec::new();
+        for ip in ips {
+            let addr: IpAddr = ip
+                .parse()
+                .with_context(|| format!("Invalid IP {} in host VM config", ip))?;
+            let Some(range) = ranges.iter().find(|r| {
+                r.cidr
+                    .parse::<IpNetwork>()
+                    .map(|n| n.contains(addr))
+                    .unwrap_or(false)
+            }) else {
+                warn!(
+                    "IP {} is not in any IP range of region {}, not importing it",
+                    ip, region_id
+                );
+                continue;
+            };
+            let assigned = self.db.list_vm_ip_assignments_in_range(range.id).await?;
+            if let Some(a) = assigned
+                .iter()
+                .find(|a| !a.deleted && a.ip.parse::<IpAddr>().ok() == Some(addr))
+            {
+                bail!("IP {} is already assigned to VM {}", ip, a.vm_id);
+            }
+            ret.push(VmIpAssignment {
+                ip_range_id: range.id,
+                ip: addr.to_string(),
+                ..Default::default()
+            });
+        }
+        Ok(ret)
     }
 
     /// Apply vm config to host
@@ -556,10 +939,12 @@ impl VmProvisioner {
             network: self.network.clone(),
             host_client: get_host_client(&info.host, &self.provisioner_config)?,
             generated_mac: None,
+            create_attempted: false,
+            retry: self.retry.host(),
             info,
         };
         Ok(Pipeline::new(ctx)
-            .with_retry_policy(Self::retry_policy())
+            .with_retry_policy(self.retry.host())
             .step_with_rollback(
                 "ip_allocation",
                 |ctx| {
@@ -577,9 +962,23 @@ impl VmProvisioner {
                     })
                 },
             )
-            .step_with_rollback(
+            .step_with_timeout(
                 "host_spawn",
-                |ctx| Box::pin(async move { ctx.host_client.create_vm(&ctx.info).await }),
+                HOST_SPAWN_TIMEOUT,
+                |ctx| {
+                    Box::pin(async move {
+                        // a previous attempt may have created the VM before failing
+                        let retrying = ctx.create_attempted;
+                        ctx.create_attempted = true;
+                        create_vm_idempotent(
+                            ctx.host_client.as_ref(),
+                            &ctx.info,
+                            retrying,
+                            ctx.retry.clone(),
+                        )
+                        .await
+                    })
+                },
                 |ctx| Box::pin(async move { ctx.host_client.delete_vm(&ctx.info.vm).await }),
             )
             .step_with_rollback(
@@ -589,6 +988,9 @@ impl VmProvisioner {
                         for ip in &mut ctx.info.ips {
                             let range = ctx.db.get_ip_range(ip.ip_range_id).await?;
                             ctx.network.validate_ip_assignment(ip, &range)?;
+                            if ip.is_prefix() {
+                                ctx.network.ensure_prefix_free(ip).await?;
+                            }
                             ctx.network
                                 .update_ip_assignment_access_policy(ip, &range)
                                 .await?;
@@ -599,7 +1001,7 @@ impl VmProvisioner {
                 |ctx| {
                     Box::pin(async move {
                         for ip in &mut ctx.info.ips {
-                            if ip.arp_ref.is_some() {
+                            if ip.arp_ref.is_some() || ip.route_ref.is_some() {
                                 let range = ctx.db.get_ip_range(ip.ip_range_id).await?;
                                 ctx.network
                                     .remove_ip_assignment_access_policy(ip, &range)
@@ -672,12 +1074,32 @@ impl VmProvisioner {
                 "save_ips",
                 |ctx| {
                     Box::pin(async move {
-                        for ip in &mut ctx.info.ips {
-                            if ip.id != 0 {
+                        let mut saved = Vec::new();
+                        for i in 0..ctx.info.ips.len() {
+                            if ctx.info.ips[i].id != 0 {
                                 // IP already inserted, skip
                                 continue;
                             }
-                            ctx.network.persist_ip_assignment(ip).await?;
+                            if let Err(e) =
+                                ctx.network.persist_ip_assignment(&mut ctx.info.ips[i]).await
+                            {
+                                // a step is not rolled back when it fails itself, mark
+                                // the assignments saved so far as deleted so they don't
+                                // outlive the ARP/DNS rollback of the earlier steps
+                                for j in saved {
+                                    let ip = &mut ctx.info.ips[j];
+                                    if let Err(de) = ctx.db.delete_vm_ip_assignment(ip.id).await {
+                                        error!(
+                                            "Failed to delete IP assignment {} ({}) after failed save: {}",
+                                            ip.id, ip.ip, de
+                                        );
+                                        continue;
+                                    }
+                                    ip.id = 0;
+                                }
+                                return Err(e);
+                            }
+                            saved.push(i);
                         }
                         Ok(())
                     })
@@ -699,6 +1121,82 @@ impl VmProvisioner {
             ))
     }
 
+    /// Transfer a VM to the user with `new_owner_pubkey`, creating the user if
+    /// they have no account yet. Returns the new owner's user id.
+    ///
+    /// The VM's subscription moves with it. The old owner's SSH key is
+    /// replaced by `new_ssh_key` (an OpenSSH public key added to the new
+    /// owner's account) or cleared when none is given; the caller must
+    /// re-configure the VM so the host drops the old key. VMs with unpaid
+    /// invoices can't be transferred.
+    pub async fn transfer_vm(
+        &self,
+        vm_id: u64,
+        new_owner_pubkey: &[u8; 32],
+        new_ssh_key: Option<&str>,
+    ) -> Result<u64> {
+        let vm = self.db.get_vm(vm_id).await?;
+        if vm.deleted {
+            bail!(ValidationError::new("Cannot transfer a deleted VM"));
+        }
+        if !self
+            .db
+            .list_pending_vm_subscription_payments(vm.id)
+            .await?
+            .is_empty()
+        {
+            bail!(ValidationError::new(
+                "VM has unpaid invoices, pay or let them expire before transferring it"
+            ));
+        }
+        // validate everything before the new owner's account is created
+        let old_owner = self.db.get_user(vm.user_id).await?;
+        if old_owner.pubkey.as_slice() == new_owner_pubkey.as_slice() {
+            bail!(ValidationError::new("VM already belongs to this user"));
+        }
+        let new_ssh_key = match new_ssh_key {
+            Some(k) => {
+                let pk: ssh_key::PublicKey = k
+                    .parse()
+                    .map_err(|_| ValidationError::new("Invalid SSH public key format"))?;
+                Some(pk)
+            }
+            None => None,
+        };
+
+        let new_user_id = self.db.upsert_user(new_owner_pubkey).await?;
+        self.db.transfer_vm(vm.id, new_user_id).await?;
+        if let Some(pk) = new_ssh_key {
+            let key_id = self
+                .db
+                .insert_user_ssh_key(&lnvps_db::UserSshKey {
+                    name: if pk.comment().is_empty() {
+                        format!("VM{} transfer", vm.id)
+                    } else {
+                        pk.comment().to_string()
+                    },
+                    user_id: new_user_id,
+                    key_data: pk.to_openssh()?.into(),
+                    ..Default::default()
+                })
+                .await?;
+            let mut vm = self.db.get_vm(vm.id).await?;
+            vm.ssh_key_id = Some(key_id);
+            self.db.update_vm(&vm).await?;
+        }
+        if let Err(e) = VmHistoryLogger::new(self.db.clone())
+            .log_vm_transferred(vm.id, Some(vm.user_id), vm.user_id, new_user_id, None)
+            .await
+        {
+            warn!("Failed to log VM {} transfer: {}", vm.id, e);
+        }
+        info!(
+            "VM {} transferred from user {} to user {}",
+            vm.id, vm.user_id, new_user_id
+        );
+        Ok(new_user_id)
+    }
+
     /// Delete a VM and its associated resources.
     ///
     /// When `purge` is set the VM is permanently removed from the database along
@@ -716,6 +1214,22 @@ impl VmProvisioner {
         let client = get_host_client(&host, &self.provisioner_config)?;
         let pipeline = Pipeline::new((self.db.clone(), client, self.network.clone()))
             .step("host_delete_vm", |ctx| Box::pin(ctx.1.delete_vm(&vm)))
+            .step("delete_backups", move |ctx| {
+                Box::pin(async move {
+                    // archives live on the backup storage, deleting the VM leaves them behind
+                    for backup in ctx.0.list_vm_backups(vm_id).await? {
+                        if let Err(e) = ctx.1.delete_backup(&backup.volume_id).await {
+                            warn!(
+                                "Failed to delete backup {} of VM {}: {}",
+                                backup.volume_id, vm_id, e
+                            );
+                            continue;
+                        }
+                        ctx.0.delete_vm_backup(backup.id).await?;
+                    }
+                    Ok(())
+                })
+            })
             .step("delete_ips", |ctx| {
                 Box::pin(ctx.2.delete_all_ip_assignments(vm_id))
             })
@@ -732,61 +1246,206 @@ impl VmProvisioner {
         Ok(())
     }
 
-    /// Re-install a VM: stop it, wipe & re-import the primary disk from the
-    /// current image template, then start it again. This is the pipeline that
+    /// Re-install a VM with `new_image_id` (which may be its current image):
+    /// stop it, wipe & re-import the primary disk from the image template,
+    /// re-apply cloud-init and start it again. This is the pipeline that
     /// previously ran inline in the web API request; it now lives here so it can
     /// be driven from the worker (serialised with spawn, avoiding races).
     ///
-    /// `FullVmInfo` is loaded fresh so any image change persisted by the caller
-    /// is reflected in the imported template.
-    pub async fn reinstall_vm(&self, vm_id: u64) -> OpResult<()> {
+    /// If any step fails the VM is rolled back to its prior image: the disk is
+    /// re-imported from the old template, the image change is reverted in the
+    /// database and the VM is started again.
+    pub async fn reinstall_vm(&self, vm_id: u64, new_image_id: u64) -> OpResult<()> {
         if self.read_only {
             op_fatal!("Cant re-install VM's in read-only mode");
         }
 
         let info = FullVmInfo::load(vm_id, self.db.clone()).await?;
-        let host_client = get_host_client(&info.host, &self.provisioner_config)?;
+        let client = get_host_client(&info.host, &self.provisioner_config)?;
+        self.reinstall_vm_on(client, info, new_image_id).await
+    }
 
-        struct ReinstallContext {
-            vm_id: u64,
-            client: Arc<dyn VmHostClient>,
-            info: FullVmInfo,
+    /// Run the re-install pipeline for `old` against a specific host client.
+    ///
+    /// Container template images go through [Self::swap_template] instead,
+    /// each kind of image is only accepted by its kind of guest.
+    pub(crate) async fn reinstall_vm_on(
+        &self,
+        client: Arc<dyn VmHostClient>,
+        old: FullVmInfo,
+        new_image_id: u64,
+    ) -> OpResult<()> {
+        let image = self.db.get_os_image(new_image_id).await?;
+        let is_container = client.is_container(&old.vm).await?;
+        if let Err(e) = ensure_image_matches_guest(is_container, &image) {
+            op_fatal!(e, anyhow::Error);
+        }
+        if is_container {
+            return self.swap_template(client, old, image).await;
+        }
+
+        let mut new = old.clone();
+        new.image = image;
+        new.vm.image_id = new_image_id;
+
+        struct ReinstallContext {
+            db: Arc<dyn LNVpsDb>,
+            client: Arc<dyn VmHostClient>,
+            old: FullVmInfo,
+            new: FullVmInfo,
         }
 
         let ctx = ReinstallContext {
-            vm_id,
-            client: host_client,
-            info,
+            db: self.db.clone(),
+            client,
+            old,
+            new,
         };
 
         Pipeline::new(ctx)
-            .with_retry_policy(Self::retry_policy())
-            .step("stop_vm", |ctx| {
-                Box::pin(async move {
-                    info!("Stopping VM {} for reinstall", ctx.vm_id);
-                    ctx.client.stop_vm(&ctx.info.vm).await
-                })
-            })
-            .step("unlink_disk", |ctx| {
-                Box::pin(async move {
-                    info!("Unlinking disk for VM {}", ctx.vm_id);
-                    ctx.client.unlink_primary_disk(&ctx.info.vm).await
-                })
-            })
+            .with_retry_policy(self.retry.host())
+            .step_with_rollback(
+                "stop_vm",
+                |ctx| {
+                    Box::pin(async move {
+                        info!("Stopping VM {} for reinstall", ctx.old.vm.id);
+                        ctx.client.stop_vm(&ctx.old.vm).await
+                    })
+                },
+                |ctx| Box::pin(async move { ctx.client.start_vm(&ctx.old.vm).await }),
+            )
+            .step_with_rollback(
+                "unlink_disk",
+                |ctx| {
+                    Box::pin(async move {
+                        info!("Unlinking disk for VM {}", ctx.old.vm.id);
+                        ctx.client.unlink_primary_disk(&ctx.old.vm).await
+                    })
+                },
+                |ctx| {
+                    Box::pin(async move {
+                        // the old disk is gone, restore a fresh disk from the
+                        // prior image after clearing whatever the new import left
+                        warn!(
+                            "Restoring VM {} disk from image {}",
+                            ctx.old.vm.id, ctx.old.image.id
+                        );
+                        ctx.client.unlink_primary_disk(&ctx.new.vm).await?;
+                        ctx.client.import_template_disk(&ctx.old).await
+                    })
+                },
+            )
             .step("import_template_disk", |ctx| {
                 Box::pin(async move {
                     // import_template_disk already imports AND resizes the primary
                     // disk to the template size; a separate resize step here would
                     // ask Proxmox to resize to the same size, which it rejects as a
                     // disallowed shrink and surfaces as a 500 (see issue #142).
-                    info!("Importing template disk for VM {}", ctx.vm_id);
-                    ctx.client.import_template_disk(&ctx.info).await
+                    info!(
+                        "Importing template disk for VM {} from image {}",
+                        ctx.new.vm.id, ctx.new.image.id
+                    );
+                    ctx.client.import_template_disk(&ctx.new).await
+                })
+            })
+            .step_with_rollback(
+                "update_image",
+                |ctx| {
+                    Box::pin(async move {
+                        if ctx.new.vm.image_id != ctx.old.vm.image_id {
+                            let mut vm = ctx.db.get_vm(ctx.new.vm.id).await?;
+                            vm.image_id = ctx.new.vm.image_id;
+                            ctx.db.update_vm(&vm).await?;
+                        }
+                        Ok(())
+                    })
+                },
+                |ctx| {
+                    Box::pin(async move {
+                        if ctx.new.vm.image_id != ctx.old.vm.image_id {
+                            let mut vm = ctx.db.get_vm(ctx.old.vm.id).await?;
+                            vm.image_id = ctx.old.vm.image_id;
+                            ctx.db.update_vm(&vm).await?;
+                        }
+                        Ok(())
+                    })
+                },
+            )
+            .step("configure_vm", |ctx| {
+                Box::pin(async move {
+                    info!("Re-applying cloud-init for VM {}", ctx.new.vm.id);
+                    ctx.client.configure_vm(&ctx.new).await
                 })
             })
             .step("start_vm", |ctx| {
                 Box::pin(async move {
-                    info!("Starting VM {} after reinstall", ctx.vm_id);
-                    ctx.client.start_vm(&ctx.info.vm).await
+                    info!("Starting VM {} after reinstall", ctx.new.vm.id);
+                    ctx.client.start_vm(&ctx.new.vm).await
+                })
+            })
+            .execute()
+            .await?;
+        Ok(())
+    }
+
+    /// Change the OS of a container to the container template `image` by
+    /// recreating its root filesystem on the host. Mounted data volumes are
+    /// kept, unlike a VM re-install there is no disk import.
+    async fn swap_template(
+        &self,
+        client: Arc<dyn VmHostClient>,
+        old: FullVmInfo,
+        image: VmOsImage,
+    ) -> OpResult<()> {
+        let mut new = old.clone();
+        new.vm.image_id = image.id;
+        new.image = image;
+
+        struct SwapContext {
+            db: Arc<dyn LNVpsDb>,
+            client: Arc<dyn VmHostClient>,
+            old: FullVmInfo,
+            new: FullVmInfo,
+        }
+
+        let ctx = SwapContext {
+            db: self.db.clone(),
+            client,
+            old,
+            new,
+        };
+
+        Pipeline::new(ctx)
+            .with_retry_policy(self.host_retry.clone())
+            .step_with_rollback(
+                "recreate_container",
+                |ctx| {
+                    Box::pin(async move {
+                        info!(
+                            "Recreating container {} from template {}",
+                            ctx.new.vm.id, ctx.new.image.id
+                        );
+                        ctx.client.recreate_container(&ctx.new).await
+                    })
+                },
+                |ctx| {
+                    Box::pin(async move {
+                        warn!(
+                            "Restoring container {} from template {}",
+                            ctx.old.vm.id, ctx.old.image.id
+                        );
+                        ctx.client.recreate_container(&ctx.old).await
+                    })
+                },
+            )
+            .step("update_image", |ctx| {
+                Box::pin(async move {
+                    if ctx.new.vm.image_id != ctx.old.vm.image_id {
+                        let mut vm = ctx.db.get_vm(ctx.new.vm.id).await?;
+                        vm.image_id = ctx.new.vm.image_id;
+                        ctx.db.update_vm(&vm).await?;
+                    }
+                    Ok(())
                 })
             })
             .execute()
@@ -814,6 +1473,175 @@ impl VmProvisioner {
         Ok(())
     }
 
+    /// Number of backups kept for a VM, from its (custom) template
+    pub async fn backup_retention(&self, vm: &Vm) -> Result<u16> {
+        Ok(if let Some(t) = vm.template_id {
+            self.db.get_vm_template(t).await?.backup_retention
+        } else if let Some(t) = vm.custom_template_id {
+            self.db.get_custom_vm_template(t).await?.backup_retention
+        } else {
+            0
+        })
+    }
+
+    /// Back up a VM to its host's backup storage, then prune backups over the
+    /// VM's retention
+    pub async fn backup_vm(&self, vm_id: u64) -> OpResult<VmBackup> {
+        if self.read_only {
+            op_fatal!("Cant backup VM's in read-only mode");
+        }
+        let vm = self.db.get_vm(vm_id).await?;
+        let retention = self.backup_retention(&vm).await?;
+        if retention == 0 {
+            op_fatal!("Backups are not included in the plan of VM {}", vm.id);
+        }
+        let host = self.db.get_host(vm.host_id).await?;
+        let client = get_host_client(&host, &self.provisioner_config)?;
+
+        info!("Backing up VM {}", vm.id);
+        let archive = client.backup_vm(&vm).await?;
+        let mut backup = VmBackup {
+            id: 0,
+            vm_id: vm.id,
+            volume_id: archive.volume_id,
+            size: archive.size,
+            created: Utc::now(),
+        };
+        backup.id = self.db.insert_vm_backup(&backup).await?;
+
+        let backups = self.db.list_vm_backups(vm.id).await?;
+        for old in backups_to_prune(backups, retention) {
+            info!("Pruning backup {} of VM {}", old.volume_id, vm.id);
+            client.delete_backup(&old.volume_id).await?;
+            self.db.delete_vm_backup(old.id).await?;
+        }
+        Ok(backup)
+    }
+
+    /// Replace a VM's disks with one of its backups, then re-apply the current
+    /// VM config (IPs, ssh key, firewall) and start it.
+    ///
+    /// The VM is backed up before the restore, if any later step fails the VM
+    /// is restored from that backup so a failed restore doesn't lose its disks.
+    pub async fn restore_vm_backup(&self, vm_id: u64, backup_id: u64) -> OpResult<()> {
+        if self.read_only {
+            op_fatal!("Cant restore VM's in read-only mode");
+        }
+        let backup = self.db.get_vm_backup(backup_id).await?;
+        if backup.vm_id != vm_id {
+            op_fatal!("Backup {} does not belong to VM {}", backup_id, vm_id);
+        }
+        let info = FullVmInfo::load(vm_id, self.db.clone()).await?;
+        let client = get_host_client(&info.host, &self.provisioner_config)?;
+
+        struct RestoreContext {
+            client: Arc<dyn VmHostClient>,
+            info: FullVmInfo,
+            volume_id: String,
+            /// Backup of the VM taken right before the restore
+            pre_restore: Option<String>,
+        }
+
+        Pipeline::new(RestoreContext {
+            client,
+            info,
+            volume_id: backup.volume_id,
+            pre_restore: None,
+        })
+        .with_retry_policy(self.retry.host())
+        .step_with_rollback(
+            "stop_vm",
+            |ctx| {
+                Box::pin(async move {
+                    info!("Stopping VM {} for restore", ctx.info.vm.id);
+                    ctx.client.stop_vm(&ctx.info.vm).await
+                })
+            },
+            |ctx| Box::pin(async move { ctx.client.start_vm(&ctx.info.vm).await }),
+        )
+        .step_with_rollback(
+            "backup_current",
+            |ctx| {
+                Box::pin(async move {
+                    info!("Backing up VM {} before restore", ctx.info.vm.id);
+                    let archive = ctx.client.backup_vm(&ctx.info.vm).await?;
+                    ctx.pre_restore = Some(archive.volume_id);
+                    Ok(())
+                })
+            },
+            |ctx| {
+                Box::pin(async move {
+                    let Some(pre_restore) = ctx.pre_restore.as_ref() else {
+                        return Ok(());
+                    };
+                    warn!(
+                        "Restoring VM {} to its state before the restore from {}",
+                        ctx.info.vm.id, pre_restore
+                    );
+                    ctx.client.restore_backup(&ctx.info, pre_restore).await?;
+                    if let Err(e) = ctx.client.delete_backup(pre_restore).await {
+                        warn!("Failed to delete backup {}: {}", pre_restore, e);
+                    }
+                    Ok(())
+                })
+            },
+        )
+        .step("restore_backup", |ctx| {
+            Box::pin(async move {
+                info!(
+                    "Restoring VM {} from backup {}",
+                    ctx.info.vm.id, ctx.volume_id
+                );
+                ctx.client.restore_backup(&ctx.info, &ctx.volume_id).await
+            })
+        })
+        .step("configure_vm", |ctx| {
+            Box::pin(async move { ctx.client.configure_vm(&ctx.info).await })
+        })
+        .step("patch_firewall", |ctx| {
+            Box::pin(async move { ctx.client.patch_firewall(&ctx.info).await })
+        })
+        .step("start_vm", |ctx| {
+            Box::pin(async move {
+                info!("Starting VM {} after restore", ctx.info.vm.id);
+                ctx.client.start_vm(&ctx.info.vm).await
+            })
+        })
+        .step("delete_pre_restore_backup", |ctx| {
+            Box::pin(async move {
+                if let Some(pre_restore) = ctx.pre_restore.as_ref()
+                    && let Err(e) = ctx.client.delete_backup(pre_restore).await
+                {
+                    warn!("Failed to delete backup {}: {}", pre_restore, e);
+                }
+                Ok(())
+            })
+        })
+        .execute()
+        .await?;
+        Ok(())
+    }
+
+    /// Reset the login password of a VM's default user, only returns once the
+    /// new password is applied inside the guest
+    pub async fn reset_vm_password(
+        &self,
+        vm_id: u64,
+        new_password: &str,
+    ) -> OpResult<PasswordResetMethod> {
+        if self.read_only {
+            op_fatal!("Cant reset VM passwords in read-only mode");
+        }
+        if let Err(e) = validate_vm_password(new_password) {
+            op_fatal!("{}", e);
+        }
+
+        let info = FullVmInfo::load(vm_id, self.db.clone()).await?;
+        let client = get_host_client(&info.host, &self.provisioner_config)?;
+        info!("Resetting password for VM {}", vm_id);
+        client.reset_password(&info, new_password).await
+    }
+
     /// Convert a VM from standard template to custom template
     pub async fn convert_to_custom_template(&self, vm_id: u64, cfg: &UpgradeConfig) -> Result<()> {
         let (mut vm, _, new_custom_template) = self.create_upgrade_template(vm_id, cfg).await?;
@@ -862,23 +1690,444 @@ impl VmProvisioner {
     /// template.  Called after the custom template's specs have been updated in the database so
     /// that `ApiSubscriptionLineItem.price` reflects the new cost.
     pub async fn update_line_item_cost_for_custom_vm(&self, vm_id: u64) -> Result<()> {
-        let vm = self.db.get_vm(vm_id).await?;
+        Self::reprice_custom_vm(&self.db, vm_id).await
+    }
+
+    async fn reprice_custom_vm(db: &Arc<dyn LNVpsDb>, vm_id: u64) -> Result<()> {
+        let vm = db.get_vm(vm_id).await?;
         let custom_template_id = vm
             .custom_template_id
             .ok_or_else(|| anyhow::anyhow!("VM does not have a custom template"))?;
+        let template = db.get_custom_vm_template(custom_template_id).await?;
+
+        let new_price = PricingEngine::get_custom_vm_cost_amount(db, vm_id, &template).await?;
+
+        let mut line_item = db
+            .get_subscription_line_item(vm.subscription_line_item_id)
+            .await?;
+        line_item.amount = new_price.total();
+        db.update_subscription_line_item(&line_item).await?;
+
+        Ok(())
+    }
+
+    /// Add an extra data disk of `size` bytes, allocated on host storage `disk_id`.
+    ///
+    /// Only custom template VMs are supported as the disk is billed at the
+    /// plan's disk rate for that storage. A VM that has not been spawned yet
+    /// only records the disk; it is attached when the VM is created on the host.
+    pub async fn add_data_disk(&self, vm_id: u64, size: u64, disk_id: u64) -> Result<VmExtraDisk> {
+        if self.read_only {
+            bail!("Cant add data disks in read-only mode")
+        }
+
+        let vm = self.db.get_vm(vm_id).await?;
+        let Some(custom_template_id) = vm.custom_template_id else {
+            bail!("Data disks are only supported on custom template VMs");
+        };
         let template = self.db.get_custom_vm_template(custom_template_id).await?;
+        let storage = self.db.get_host_disk(disk_id).await?;
+        if storage.host_id != vm.host_id || !storage.enabled {
+            bail!("Disk {} is not available on this VM's host", disk_id);
+        }
+        let pricing_disk = self
+            .db
+            .list_custom_pricing_disk(template.pricing_id)
+            .await?;
+        let Some(rate) = pricing_disk
+            .iter()
+            .find(|p| p.kind == storage.kind && p.interface == storage.interface)
+        else {
+            bail!("No disk price found for disk {}", disk_id);
+        };
+        if size < rate.min_disk_size || size > rate.max_disk_size {
+            bail!(
+                "Disk size must be between {} and {} bytes",
+                rate.min_disk_size,
+                rate.max_disk_size
+            );
+        }
 
-        let new_price =
-            PricingEngine::get_custom_vm_cost_amount(&self.db, vm_id, &template).await?;
+        let existing = self.db.list_vm_extra_disks(vm_id).await?;
+        let slot = (0..MAX_DATA_DISKS)
+            .find(|s| !existing.iter().any(|d| d.slot == *s))
+            .ok_or_else(|| anyhow!("VM already has {} data disks", MAX_DATA_DISKS))?;
+
+        let host = self.db.get_host(vm.host_id).await?;
+        // libvirt hosts can't attach data disks on create or later
+        if matches!(host.kind, VmHostKind::LibVirt) {
+            bail!("Data disks are not supported on this host type");
+        }
+
+        let cap = HostCapacityService::new(self.db.clone());
+        let free = cap
+            .get_host_capacity(&host, None, None)
+            .await?
+            .disks
+            .iter()
+            .find(|d| d.disk.id == disk_id)
+            .map(|d| d.available_capacity())
+            .unwrap_or(0);
+        if size > free {
+            bail!(
+                "Not enough space on disk {} for a {} byte data disk",
+                disk_id,
+                size
+            );
+        }
+
+        struct AddDataDiskContext {
+            db: Arc<dyn LNVpsDb>,
+            client: Arc<dyn VmHostClient>,
+            /// Whether the VM exists on the host yet
+            spawned: bool,
+            vm: Vm,
+            disk: DataDisk,
+        }
+
+        let ctx = AddDataDiskContext {
+            db: self.db.clone(),
+            client: get_host_client(&host, &self.provisioner_config)?,
+            spawned: vm.mac_address != UNASSIGNED_MAC,
+            vm,
+            disk: DataDisk {
+                disk: VmExtraDisk {
+                    id: 0,
+                    vm_id,
+                    disk_id,
+                    size,
+                    slot,
+                    created: Utc::now(),
+                },
+                storage,
+            },
+        };
+
+        let ctx = Pipeline::new(ctx)
+            .with_retry_policy(self.retry.host())
+            .step_with_rollback(
+                "save_disk",
+                |ctx| {
+                    Box::pin(async move {
+                        ctx.disk.disk.id = ctx.db.insert_vm_extra_disk(&ctx.disk.disk).await?;
+                        Ok(())
+                    })
+                },
+                |ctx| {
+                    Box::pin(async move {
+                        ctx.db.delete_vm_extra_disk(ctx.disk.disk.id).await?;
+                        Ok(())
+                    })
+                },
+            )
+            .step_with_rollback(
+                "host_attach",
+                |ctx| {
+                    Box::pin(async move {
+                        if ctx.spawned {
+                            ctx.client.attach_data_disk(&ctx.vm, &ctx.disk).await?;
+                        }
+                        Ok(())
+                    })
+                },
+                |ctx| {
+                    Box::pin(async move {
+                        // destroys the allocated volume
+                        if ctx.spawned {
+                            ctx.client.detach_data_disk(&ctx.vm, &ctx.disk).await?;
+                        }
+                        Ok(())
+                    })
+                },
+            )
+            .step("update_line_item", |ctx| {
+                Box::pin(async move { Ok(Self::reprice_custom_vm(&ctx.db, ctx.vm.id).await?) })
+            })
+            .execute()
+            .await?;
+
+        info!(
+            "Added {} byte data disk in slot {} to VM {}",
+            size, slot, vm_id
+        );
+        Ok(ctx.disk.disk)
+    }
+
+    /// Detach and destroy the extra data disk in slot `index` of a VM.
+    pub async fn remove_data_disk(&self, vm_id: u64, index: u16) -> Result<()> {
+        if self.read_only {
+            bail!("Cant remove data disks in read-only mode")
+        }
+
+        let vm = self.db.get_vm(vm_id).await?;
+        let disk = self
+            .db
+            .list_vm_extra_disks(vm_id)
+            .await?
+            .into_iter()
+            .find(|d| d.slot == index)
+            .ok_or_else(|| anyhow!("VM {} has no data disk {}", vm_id, index))?;
+        let disk = DataDisk::load(disk, &self.db).await?;
+
+        if vm.mac_address != UNASSIGNED_MAC {
+            let host = self.db.get_host(vm.host_id).await?;
+            let client = get_host_client(&host, &self.provisioner_config)?;
+            client.detach_data_disk(&vm, &disk).await?;
+        }
+        self.db.delete_vm_extra_disk(disk.disk.id).await?;
+        Self::reprice_custom_vm(&self.db, vm_id).await?;
+
+        info!("Removed data disk in slot {} from VM {}", index, vm_id);
+        Ok(())
+    }
+
+    /// Whether [Self::grow_disk] can grow the VM's disk without a stop cycle
+    pub async fn can_grow_disk_online(&self, vm_id: u64) -> Result<bool> {
+        let vm = self.db.get_vm(vm_id).await?;
+        let host = self.db.get_host(vm.host_id).await?;
+        // libvirt only grows the volume, the running guest doesn't see it
+        Ok(vm.mac_address == UNASSIGNED_MAC || !matches!(host.kind, VmHostKind::LibVirt))
+    }
+
+    /// Grow the primary disk of a VM to `new_size` bytes without a stop cycle.
+    ///
+    /// Applies a paid disk upgrade (`ProcessVmUpgrade`), the upgrade payment
+    /// covers the rest of the current period. The disk is resized on the host
+    /// while the VM keeps running (Proxmox resizes online). A standard template
+    /// is shared by many VMs, so those VMs are moved to a custom template first.
+    /// The subscription is repriced for the larger disk and the increase of the
+    /// renewal amount is returned.
+    pub(crate) async fn grow_disk(&self, vm_id: u64, new_size: u64) -> Result<u64> {
+        if self.read_only {
+            bail!("Cant resize disks in read-only mode")
+        }
+
+        let vm = self.db.get_vm(vm_id).await?;
+        let current = FullVmInfo::vm_resources(vm_id, self.db.clone())
+            .await?
+            .disk_size;
+        if new_size <= current {
+            bail!(ValidationError::new(format!(
+                "New disk size must be larger than the current {} bytes",
+                current
+            )));
+        }
+
+        if !self.can_grow_disk_online(vm_id).await? {
+            bail!("Online disk resize is not supported on this host type");
+        }
+        let host = self.db.get_host(vm.host_id).await?;
+        let spawned = vm.mac_address != UNASSIGNED_MAC;
+
+        let cfg = UpgradeConfig {
+            new_cpu: None,
+            new_memory: None,
+            new_disk: Some(new_size),
+        };
+        let old_template = match vm.custom_template_id {
+            Some(id) => Some(self.db.get_custom_vm_template(id).await?),
+            None => None,
+        };
+        let new_template = match &old_template {
+            Some(t) => VmCustomTemplate {
+                disk_size: new_size,
+                ..t.clone()
+            },
+            None => self.create_upgrade_template(vm_id, &cfg).await?.2,
+        };
+        PricingEngine::validate_custom_vm_spec(&self.db, &new_template, &self.custom_floors)
+            .await?;
+
+        let line_item = self
+            .db
+            .get_subscription_line_item(vm.subscription_line_item_id)
+            .await?;
+        let subscription = self.db.get_subscription(line_item.subscription_id).await?;
+
+        struct GrowDiskContext {
+            db: Arc<dyn LNVpsDb>,
+            provisioner: VmProvisioner,
+            client: Arc<dyn VmHostClient>,
+            spawned: bool,
+            cfg: UpgradeConfig,
+            /// State before the resize, restored on rollback
+            vm: Vm,
+            old_template: Option<VmCustomTemplate>,
+            new_template: VmCustomTemplate,
+            line_item: SubscriptionLineItem,
+            subscription: Subscription,
+        }
+
+        let old_amount = line_item.amount;
+        let ctx = GrowDiskContext {
+            db: self.db.clone(),
+            provisioner: self.clone(),
+            client: get_host_client(&host, &self.provisioner_config)?,
+            spawned,
+            cfg,
+            vm,
+            old_template,
+            new_template,
+            line_item,
+            subscription,
+        };
+
+        let ctx = Pipeline::new(ctx)
+            .with_retry_policy(self.retry.host())
+            .step_with_rollback(
+                "update_template",
+                |ctx| {
+                    Box::pin(async move {
+                        if ctx.old_template.is_some() {
+                            ctx.db.update_custom_vm_template(&ctx.new_template).await?;
+                            Self::reprice_custom_vm(&ctx.db, ctx.vm.id).await?;
+                        } else {
+                            ctx.provisioner
+                                .convert_to_custom_template(ctx.vm.id, &ctx.cfg)
+                                .await?;
+                        }
+                        Ok(())
+                    })
+                },
+                |ctx| {
+                    Box::pin(async move {
+                        ctx.db.update_vm(&ctx.vm).await?;
+                        if let Some(t) = &ctx.old_template {
+                            ctx.db.update_custom_vm_template(t).await?;
+                        }
+                        ctx.db.update_subscription_line_item(&ctx.line_item).await?;
+                        ctx.db.update_subscription(&ctx.subscription).await?;
+                        Ok(())
+                    })
+                },
+            )
+            .step("host_resize", |ctx| {
+                Box::pin(async move {
+                    if ctx.spawned {
+                        let info = FullVmInfo::load(ctx.vm.id, ctx.db.clone()).await?;
+                        ctx.client.resize_disk(&info).await?;
+                    }
+                    Ok(())
+                })
+            })
+            .execute()
+            .await?;
+
+        let vm_after = self.db.get_vm(vm_id).await?;
+        let new_amount = self
+            .db
+            .get_subscription_line_item(vm_after.subscription_line_item_id)
+            .await?
+            .amount;
+        let metadata = serde_json::json!({
+            "upgrade_type": "disk_grow",
+            "old_disk_size": current,
+            "new_disk_size": new_size,
+            "old_amount": old_amount,
+            "new_amount": new_amount
+        });
+        if let Err(e) = VmHistoryLogger::new(self.db.clone())
+            .log_vm_configuration_changed(vm_id, None, &ctx.vm, &vm_after, Some(metadata))
+            .await
+        {
+            warn!("Failed to log disk resize history for VM {}: {}", vm_id, e);
+        }
+
+        info!(
+            "Grew disk of VM {} from {} to {} bytes",
+            vm_id, current, new_size
+        );
+        Ok(new_amount.saturating_sub(old_amount))
+    }
+
+    /// Resize the CPU and memory of a running VM without restarting it.
+    ///
+    /// Only applied when the VM is running and its host and guest config
+    /// allow hot-plug, the VM's template is then updated to the new specs.
+    /// Otherwise nothing is changed and [HotplugResize::RestartRequired] is
+    /// returned, the resize has to go through a normal (restarting) upgrade.
+    pub async fn hotplug_resize(&self, vm_id: u64, cpu: u16, memory: u64) -> Result<HotplugResize> {
+        let vm = self.db.get_vm(vm_id).await?;
+        let host = self.db.get_host(vm.host_id).await?;
+        let client = get_host_client(&host, &self.provisioner_config)?;
+        self.hotplug_resize_on(client.as_ref(), vm, cpu, memory)
+            .await
+    }
+
+    async fn hotplug_resize_on(
+        &self,
+        client: &dyn VmHostClient,
+        vm: Vm,
+        cpu: u16,
+        memory: u64,
+    ) -> Result<HotplugResize> {
+        if self.read_only {
+            bail!("Cant resize VMs in read-only mode")
+        }
+
+        let current = FullVmInfo::vm_resources(vm.id, self.db.clone()).await?;
+        if cpu < current.cpu || memory < current.memory {
+            bail!(ValidationError::new("CPU and memory can only be increased"));
+        }
+        if cpu == current.cpu && memory == current.memory {
+            return Ok(HotplugResize::Live);
+        }
+
+        let cfg = UpgradeConfig {
+            new_cpu: Some(cpu),
+            new_memory: Some(memory),
+            new_disk: None,
+        };
+        let new_template = match vm.custom_template_id {
+            Some(id) => VmCustomTemplate {
+                cpu,
+                memory,
+                ..self.db.get_custom_vm_template(id).await?
+            },
+            None => self.create_upgrade_template(vm.id, &cfg).await?.2,
+        };
+        PricingEngine::validate_custom_vm_spec(&self.db, &new_template, &self.custom_floors)
+            .await?;
+
+        if !client.hotplug_resize(&vm, cpu, memory).await? {
+            info!("VM {} must be restarted to resize CPU/memory", vm.id);
+            return Ok(HotplugResize::RestartRequired);
+        }
+
+        if vm.custom_template_id.is_some() {
+            self.db.update_custom_vm_template(&new_template).await?;
+            Self::reprice_custom_vm(&self.db, vm.id).await?;
+        } else {
+            self.convert_to_custom_template(vm.id, &cfg).await?;
+        }
 
-        let mut line_item = self
-            .db
-            .get_subscription_line_item(vm.subscription_line_item_id)
-            .await?;
-        line_item.amount = new_price.total();
-        self.db.update_subscription_line_item(&line_item).await?;
+        let vm_after = self.db.get_vm(vm.id).await?;
+        let metadata = serde_json::json!({
+            "upgrade_type": "hotplug_resize",
+            "old_specs": {
+                "cpu": current.cpu,
+                "memory": current.memory
+            },
+            "new_specs": {
+                "cpu": cpu,
+                "memory": memory
+            }
+        });
+        if let Err(e) = VmHistoryLogger::new(self.db.clone())
+            .log_vm_configuration_changed(vm.id, None, &vm, &vm_after, Some(metadata))
+            .await
+        {
+            warn!(
+                "Failed to log hot-plug resize history for VM {}: {}",
+                vm.id, e
+            );
+        }
 
-        Ok(())
+        info!(
+            "Resized VM {} to {} vCPUs and {} bytes of memory without a restart",
+            vm.id, cpu, memory
+        );
+        Ok(HotplugResize::Live)
     }
 
     /// Create a new custom template using a vm's existing standard template
@@ -979,6 +2228,16 @@ impl VmProvisioner {
                 v6.ip =
                     IpNetwork::new(addr, v6.ip.prefix()).context("failed to parse IPv6 address")?;
             }
+            // the whole prefix is routed to the VM, store the network and its length
+            IpRangeAllocationMode::DelegatedPrefix => {
+                return Ok(VmIpAssignment {
+                    vm_id,
+                    ip_range_id: v6.range_id,
+                    ip: v6.ip.network().to_string(),
+                    prefix_length: Some(v6.ip.prefix()),
+                    ..Default::default()
+                });
+            }
             _ => {}
         }
         Ok(VmIpAssignment {
@@ -1002,6 +2261,10 @@ pub struct SpawnVmContext {
 
     /// Generated mac address, can be rolled back if the entry has an ID
     generated_mac: Option<ArpEntry>,
+    /// Set once `create_vm` has been called, so a retry checks the host first
+    create_attempted: bool,
+    /// Retry policy for the steps of the create on the host
+    retry: RetryPolicy,
 }
 
 impl SpawnVmContext {
@@ -1155,14 +2418,15 @@ mod tests {
         assert!(ensure_image_arch_compatible(CpuArch::ARM64, CpuArch::X86_64).is_err());
     }
 
+    use crate::host::dummy_host::DummyVmHost;
     use crate::mocks::{MockDnsServer, MockNode, MockOnChainProvider, MockRouter};
     use crate::settings::mock_settings;
     use crate::subscription::{
         SubscriptionHandler, SubscriptionLineItemHandler, VmLineItemHandler,
     };
     use lnvps_api_common::{
-        ChannelWorkCommander, GB, InMemoryRateCache, MockDb, MockExchangeRate, TB, Ticker,
-        WorkCommander, WorkJob,
+        CapacityError, ChannelWorkCommander, GB, InMemoryRateCache, MockDb, MockExchangeRate, TB,
+        Ticker, WorkCommander, WorkJob,
     };
     use lnvps_db::{
         AccessPolicy, DiskInterface, DiskType, IntervalType, LNVpsDbBase, NetworkAccessPolicy,
@@ -1257,7 +2521,14 @@ mod tests {
 
         let (user, ssh_key) = add_user(&db).await?;
         let vm = provisioner
-            .provision(user.id, 1, 1, ssh_key.id, Some("mock-ref".to_string()))
+            .provision(
+                user.id,
+                1,
+                1,
+                ssh_key.id,
+                Some("mock-ref".to_string()),
+                false,
+            )
             .await?;
         println!("{:?}", vm);
 
@@ -1412,7 +2683,9 @@ mod tests {
 
         let (user, ssh_key) = add_user(&db).await?;
 
-        let prov = prov.provision(user.id, id, 1, ssh_key.id, None).await;
+        let prov = prov
+            .provision(user.id, id, 1, ssh_key.id, None, false)
+            .await;
         assert!(prov.is_err());
         if let Err(e) = prov {
             println!("{}", e);
@@ -1447,7 +2720,9 @@ mod tests {
         let (_user_b, key_b) = add_user(&db).await?;
 
         // user_a tries to order a VM using user_b's SSH key
-        let res = prov.provision(user_a.id, id, 1, key_b.id, None).await;
+        let res = prov
+            .provision(user_a.id, id, 1, key_b.id, None, false)
+            .await;
         assert!(res.is_err());
         let msg = res.unwrap_err().to_string().to_lowercase();
         assert!(
@@ -1940,7 +3215,7 @@ mod tests {
 
         let prov = make_provisioner(db);
         let result = prov
-            .provision_custom(user.id, template, 1, ssh_key.id, None)
+            .provision_custom(user.id, template, 1, ssh_key.id, None, false)
             .await;
 
         assert!(
@@ -2037,7 +3312,7 @@ mod tests {
         }
         let (user, ssh_key) = add_user(&db).await?;
         let result = make_provisioner(db)
-            .provision(user.id, 1, 1, ssh_key.id, None)
+            .provision(user.id, 1, 1, ssh_key.id, None, false)
             .await;
         assert!(result.is_err(), "should fail with disabled template");
         assert!(
@@ -2051,6 +3326,24 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_provision_disabled_template_is_validation_error() -> Result<()> {
+        let db = Arc::new(MockDb::default());
+        {
+            let mut templates = db.templates.lock().await;
+            templates.get_mut(&1).unwrap().enabled = false;
+        }
+        let (user, ssh_key) = add_user(&db).await?;
+        let err = make_provisioner(db)
+            .provision(user.id, 1, 1, ssh_key.id, None, false)
+            .await
+            .unwrap_err();
+        let err = lnvps_api_common::ApiError::from(err);
+        assert_eq!(err.kind, lnvps_api_common::ApiErrorCode::ValidationFailed);
+        assert_eq!(err.error, "Cant create VM from disabled template");
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_provision_fails_with_expired_template() -> Result<()> {
         let db = Arc::new(MockDb::default());
@@ -2061,7 +3354,7 @@ mod tests {
         }
         let (user, ssh_key) = add_user(&db).await?;
         let result = make_provisioner(db)
-            .provision(user.id, 1, 1, ssh_key.id, None)
+            .provision(user.id, 1, 1, ssh_key.id, None, false)
             .await;
         assert!(result.is_err(), "should fail with expired template");
         assert!(
@@ -2075,6 +3368,57 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_provision_fails_with_iso_image() -> Result<()> {
+        let db = Arc::new(MockDb::default());
+        {
+            let mut images = db.os_images.lock().await;
+            images.get_mut(&1).unwrap().image_kind = OsImageKind::Iso;
+        }
+        let (user, ssh_key) = add_user(&db).await?;
+        let result = make_provisioner(db)
+            .provision(user.id, 1, 1, ssh_key.id, None, false)
+            .await;
+        assert!(result.is_err(), "should fail with an ISO image");
+        assert!(
+            result
+                .unwrap_err()
+                .to_string()
+                .contains("automated provisioning"),
+            "error should mention the image kind"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_provision_rejects_image_not_allowed_in_region() -> Result<()> {
+        let db = Arc::new(MockDb::default());
+        // template 1 is in region 1, only offer some other image there
+        db.set_region_os_images(1, &[2]).await?;
+        let (user, ssh_key) = add_user(&db).await?;
+        let result = make_provisioner(db.clone())
+            .provision(user.id, 1, 1, ssh_key.id, None, false)
+            .await;
+        assert!(
+            result.is_err(),
+            "should fail with an image outside the allowlist"
+        );
+        assert!(
+            result
+                .unwrap_err()
+                .to_string()
+                .contains("not available in this region"),
+            "error should mention the region"
+        );
+
+        // once the image is on the allowlist provisioning goes through
+        db.set_region_os_images(1, &[1, 2]).await?;
+        make_provisioner(db)
+            .provision(user.id, 1, 1, ssh_key.id, None, false)
+            .await?;
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_renew_vm_with_disabled_template_succeeds() -> Result<()> {
         let db = Arc::new(MockDb::default());
@@ -2166,7 +3510,7 @@ mod tests {
             ..Default::default()
         };
         let result = make_provisioner(db)
-            .provision_custom(user.id, template, 1, ssh_key.id, None)
+            .provision_custom(user.id, template, 1, ssh_key.id, None, false)
             .await;
         assert!(result.is_err(), "should fail with expired custom pricing");
         assert!(
@@ -2247,90 +3591,494 @@ mod tests {
         Ok(())
     }
 
-    // ── provision subscription creation tests ────────────────────────────────
-
-    /// provision() creates a subscription and line item linked to the VM.
+    // ── provision subscription creation tests ────────────────────────────────
+
+    /// A user at the template's `max_per_user` can't order another VM of it.
+    #[tokio::test]
+    async fn test_provision_rejected_at_template_limit() -> Result<()> {
+        let db = Arc::new(MockDb::default());
+        db.templates.lock().await.get_mut(&1).unwrap().max_per_user = Some(2);
+        let prov = make_provisioner(db.clone());
+        let (user, ssh_key) = add_user(&db).await?;
+
+        prov.provision(user.id, 1, 1, ssh_key.id, None, false)
+            .await?;
+        prov.provision(user.id, 1, 1, ssh_key.id, None, false)
+            .await?;
+        let err = prov
+            .provision(user.id, 1, 1, ssh_key.id, None, false)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<TemplateLimitError>(),
+            Some(&TemplateLimitError {
+                template_id: 1,
+                limit: 2
+            })
+        );
+        let err = lnvps_api_common::ApiError::from(err);
+        assert_eq!(
+            err.kind,
+            lnvps_api_common::ApiErrorCode::TemplateLimitReached
+        );
+
+        // deleted VMs don't count towards the limit
+        let vm = db.list_user_vms(user.id).await?.remove(0);
+        db.delete_vm(vm.id).await?;
+        prov.provision(user.id, 1, 1, ssh_key.id, None, false)
+            .await?;
+
+        // the limit is per user
+        let (other, other_key) = add_user(&db).await?;
+        prov.provision(other.id, 1, 1, other_key.id, None, false)
+            .await?;
+        Ok(())
+    }
+
+    /// Concurrent orders can't both take the last slot of a template.
+    #[tokio::test]
+    async fn test_provision_template_limit_concurrent() -> Result<()> {
+        let db = Arc::new(MockDb::default());
+        db.templates.lock().await.get_mut(&1).unwrap().max_per_user = Some(1);
+        let prov = make_provisioner(db.clone());
+        let (user, ssh_key) = add_user(&db).await?;
+
+        let (a, b) = tokio::join!(
+            prov.provision(user.id, 1, 1, ssh_key.id, None, false),
+            prov.provision(user.id, 1, 1, ssh_key.id, None, false)
+        );
+        assert_eq!([a.is_ok(), b.is_ok()].iter().filter(|ok| **ok).count(), 1);
+        let err = a.err().or(b.err()).unwrap();
+        assert!(err.downcast_ref::<TemplateLimitError>().is_some());
+        assert_eq!(db.list_user_vms(user.id).await?.len(), 1);
+        Ok(())
+    }
+
+    /// Two creates with the same client request id yield one VM.
+    #[tokio::test]
+    async fn test_provision_once_same_request_id() -> Result<()> {
+        let db = Arc::new(MockDb::default());
+        let prov = make_provisioner(db.clone());
+        let (user, ssh_key) = add_user(&db).await?;
+
+        let (vm, created) = prov
+            .provision_once(
+                user.id,
+                Some("req-1"),
+                prov.provision(user.id, 1, 1, ssh_key.id, None, false),
+            )
+            .await?;
+        assert!(created);
+        let (again, created) = prov
+            .provision_once(
+                user.id,
+                Some("req-1"),
+                prov.provision(user.id, 1, 1, ssh_key.id, None, false),
+            )
+            .await?;
+        assert!(!created);
+        assert_eq!(again.id, vm.id);
+        assert_eq!(db.list_user_vms(user.id).await?.len(), 1);
+
+        // ids are scoped to the user
+        let (other, other_key) = add_user(&db).await?;
+        let (other_vm, created) = prov
+            .provision_once(
+                other.id,
+                Some("req-1"),
+                prov.provision(other.id, 1, 1, other_key.id, None, false),
+            )
+            .await?;
+        assert!(created);
+        assert_ne!(other_vm.id, vm.id);
+        Ok(())
+    }
+
+    /// A failed create releases the request id so it can be retried.
+    #[tokio::test]
+    async fn test_provision_once_failed_create_can_retry() -> Result<()> {
+        let db = Arc::new(MockDb::default());
+        let prov = make_provisioner(db.clone());
+        let (user, ssh_key) = add_user(&db).await?;
+
+        let res = prov
+            .provision_once(
+                user.id,
+                Some("req-1"),
+                prov.provision(user.id, 1, 1, ssh_key.id + 100, None, false),
+            )
+            .await;
+        assert!(res.is_err());
+        let (_, created) = prov
+            .provision_once(
+                user.id,
+                Some("req-1"),
+                prov.provision(user.id, 1, 1, ssh_key.id, None, false),
+            )
+            .await?;
+        assert!(created);
+        Ok(())
+    }
+
+    /// provision() creates a subscription and line item linked to the VM.
+    #[tokio::test]
+    async fn test_provision_creates_subscription() -> Result<()> {
+        let db = Arc::new(MockDb::default());
+        let prov = make_provisioner(db.clone());
+        let (user, ssh_key) = add_user(&db).await?;
+
+        let vm = prov
+            .provision(user.id, 1, 1, ssh_key.id, None, false)
+            .await?;
+
+        assert!(
+            vm.subscription_line_item_id > 0,
+            "VM must have a subscription_line_item_id"
+        );
+
+        // Line item must exist
+        let line_item = db
+            .get_subscription_line_item(vm.subscription_line_item_id)
+            .await?;
+        assert_eq!(line_item.subscription_type, lnvps_db::SubscriptionType::Vps);
+
+        // Subscription must exist and have interval from cost_plan
+        let sub = db.get_subscription(line_item.subscription_id).await?;
+        assert_eq!(sub.user_id, user.id);
+        // Default cost_plan has interval_amount=1, interval_type=Month
+        assert_eq!(sub.interval_amount, 1);
+        assert!(
+            matches!(sub.interval_type, IntervalType::Month),
+            "expected Month interval"
+        );
+        assert!(!sub.is_active, "subscription should start inactive");
+        assert!(!sub.is_setup, "subscription should start un-setup");
+
+        Ok(())
+    }
+
+    /// provision_custom() creates a subscription with 1-Month interval.
+    #[tokio::test]
+    async fn test_provision_custom_creates_subscription() -> Result<()> {
+        let db = Arc::new(MockDb::default());
+        let prov = make_provisioner(db.clone());
+        let (user, ssh_key) = add_user(&db).await?;
+        let pricing_id = insert_custom_pricing(&*db, DiskType::SSD, DiskInterface::PCIe).await?;
+
+        let template = lnvps_db::VmCustomTemplate {
+            id: 0,
+            cpu: 2,
+            memory: 4 * GB,
+            disk_size: 50 * GB,
+            disk_type: DiskType::SSD,
+            disk_interface: DiskInterface::PCIe,
+            pricing_id,
+            ..Default::default()
+        };
+
+        let vm = prov
+            .provision_custom(user.id, template, 1, ssh_key.id, None, false)
+            .await?;
+
+        assert!(vm.subscription_line_item_id > 0);
+
+        let line_item = db
+            .get_subscription_line_item(vm.subscription_line_item_id)
+            .await?;
+        assert_eq!(line_item.subscription_type, lnvps_db::SubscriptionType::Vps);
+        // Regression: the recorded line item amount must reflect the base monthly
+        // cost of the custom template, not zero.
+        assert!(
+            line_item.amount > 0,
+            "custom VM line_item.amount must be the base cost, not zero"
+        );
+
+        let sub = db.get_subscription(line_item.subscription_id).await?;
+        assert_eq!(sub.user_id, user.id);
+        // Custom VMs always use 1-Month interval
+        assert_eq!(sub.interval_amount, 1);
+        assert!(
+            matches!(sub.interval_type, IntervalType::Month),
+            "expected Month interval"
+        );
+        assert!(!sub.is_active);
+        assert!(!sub.is_setup);
+
+        Ok(())
+    }
+
+    // ── data disk tests ──────────────────────────────────────────────────────
+
+    async fn provision_custom_vm(db: &Arc<MockDb>) -> Result<Vm> {
+        let (user, ssh_key) = add_user(db).await?;
+        let pricing_id = insert_custom_pricing(db, DiskType::SSD, DiskInterface::PCIe).await?;
+        let template = lnvps_db::VmCustomTemplate {
+            id: 0,
+            cpu: 2,
+            memory: 4 * GB,
+            disk_size: 50 * GB,
+            disk_type: DiskType::SSD,
+            disk_interface: DiskInterface::PCIe,
+            pricing_id,
+            ..Default::default()
+        };
+        make_provisioner(db.clone())
+            .provision_custom(user.id, template, 1, ssh_key.id, None, false)
+            .await
+    }
+
+    #[tokio::test]
+    async fn test_add_remove_data_disk() -> Result<()> {
+        let db = Arc::new(MockDb::default());
+        let vm = provision_custom_vm(&db).await?;
+        let prov = make_provisioner(db.clone());
+        let base_amount = db
+            .get_subscription_line_item(vm.subscription_line_item_id)
+            .await?
+            .amount;
+
+        let d0 = prov.add_data_disk(vm.id, 20 * GB, 1).await?;
+        let d1 = prov.add_data_disk(vm.id, 10 * GB, 1).await?;
+        assert_eq!((d0.slot, d1.slot), (0, 1));
+        assert_eq!(db.list_vm_extra_disks(vm.id).await?.len(), 2);
+
+        // Extra disks are billed at the plan's disk rate (5 per GB)
+        let li = db
+            .get_subscription_line_item(vm.subscription_line_item_id)
+            .await?;
+        assert_eq!(li.amount, base_amount + 30 * 5);
+
+        // Included in the FullVmInfo handed to the host on create
+        let info = FullVmInfo::load(vm.id, db.clone()).await?;
+        assert_eq!(info.data_disks.len(), 2);
+        assert_eq!(info.data_disks[0].storage.id, 1);
+
+        prov.remove_data_disk(vm.id, 0).await?;
+        let li = db
+            .get_subscription_line_item(vm.subscription_line_item_id)
+            .await?;
+        assert_eq!(li.amount, base_amount + 10 * 5);
+
+        // Freed slot is reused
+        let d2 = prov.add_data_disk(vm.id, 5 * GB, 1).await?;
+        assert_eq!(d2.slot, 0);
+
+        assert!(prov.remove_data_disk(vm.id, 5).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_add_data_disk_attach_failure_rolls_back() -> Result<()> {
+        let db = Arc::new(MockDb::default());
+        let mut vm = provision_custom_vm(&db).await?;
+        let base_amount = db
+            .get_subscription_line_item(vm.subscription_line_item_id)
+            .await?
+            .amount;
+
+        // Mark the VM as spawned; the (fresh) dummy host doesn't know about
+        // it so the attach fails and the pipeline must roll back.
+        vm.mac_address = "bc:24:11:00:00:01".to_string();
+        db.update_vm(&vm).await?;
+
+        let res = make_provisioner(db.clone())
+            .add_data_disk(vm.id, 20 * GB, 1)
+            .await;
+        assert!(res.is_err(), "attach should fail");
+        assert!(db.list_vm_extra_disks(vm.id).await?.is_empty());
+        let li = db
+            .get_subscription_line_item(vm.subscription_line_item_id)
+            .await?;
+        assert_eq!(li.amount, base_amount, "line item must not be repriced");
+        Ok(())
+    }
+
     #[tokio::test]
-    async fn test_provision_creates_subscription() -> Result<()> {
+    async fn test_add_data_disk_validation() -> Result<()> {
         let db = Arc::new(MockDb::default());
         let prov = make_provisioner(db.clone());
-        let (user, ssh_key) = add_user(&db).await?;
 
-        let vm = prov.provision(user.id, 1, 1, ssh_key.id, None).await?;
+        // Standard template VMs have no disk rate to bill against
+        insert_custom_pricing(&*db, DiskType::SSD, DiskInterface::PCIe).await?;
+        let std_vm = insert_standard_template_vm(&db).await?;
+        assert!(prov.add_data_disk(std_vm, 20 * GB, 1).await.is_err());
+
+        let vm = provision_custom_vm(&db).await?;
+        // Below the plan's minimum disk size
+        assert!(prov.add_data_disk(vm.id, GB / 2, 1).await.is_err());
+        // Unknown host storage
+        assert!(prov.add_data_disk(vm.id, 20 * GB, 99).await.is_err());
+        // Not enough free space on the host storage
+        db.host_disks.lock().await.get_mut(&1).unwrap().size = 10 * GB;
+        assert!(prov.add_data_disk(vm.id, 20 * GB, 1).await.is_err());
+        assert!(db.list_vm_extra_disks(vm.id).await?.is_empty());
+        Ok(())
+    }
 
-        assert!(
-            vm.subscription_line_item_id > 0,
-            "VM must have a subscription_line_item_id"
-        );
+    #[tokio::test]
+    async fn test_grow_disk() -> Result<()> {
+        let db = Arc::new(MockDb::default());
+        let vm = provision_custom_vm(&db).await?;
+        let prov = make_provisioner(db.clone());
+        let base_amount = db
+            .get_subscription_line_item(vm.subscription_line_item_id)
+            .await?
+            .amount;
 
-        // Line item must exist
-        let line_item = db
+        let delta = prov.grow_disk(vm.id, 80 * GB).await?;
+        let template = db
+            .get_custom_vm_template(vm.custom_template_id.unwrap())
+            .await?;
+        assert_eq!(template.disk_size, 80 * GB);
+        // priced at the plan's disk rate (5 per GB)
+        assert_eq!(delta, 30 * 5);
+        let li = db
             .get_subscription_line_item(vm.subscription_line_item_id)
             .await?;
-        assert_eq!(line_item.subscription_type, lnvps_db::SubscriptionType::Vps);
+        assert_eq!(li.amount, base_amount + delta);
+        assert!(db.vm_history.lock().await.values().any(|h| h.vm_id == vm.id
+            && matches!(
+                h.action_type,
+                lnvps_db::VmHistoryActionType::ConfigurationChanged
+            )));
+
+        // standard template VMs are moved to their own custom template
+        let std_vm = insert_standard_template_vm(&db).await?;
+        prov.grow_disk(std_vm, 100 * GB).await?;
+        let std_vm = db.get_vm(std_vm).await?;
+        assert_eq!(std_vm.template_id, None);
+        let template = db
+            .get_custom_vm_template(std_vm.custom_template_id.unwrap())
+            .await?;
+        assert_eq!(template.disk_size, 100 * GB);
+        assert_eq!(db.get_vm_template(1).await?.disk_size, 64 * GB);
+        Ok(())
+    }
 
-        // Subscription must exist and have interval from cost_plan
-        let sub = db.get_subscription(line_item.subscription_id).await?;
-        assert_eq!(sub.user_id, user.id);
-        // Default cost_plan has interval_amount=1, interval_type=Month
-        assert_eq!(sub.interval_amount, 1);
-        assert!(
-            matches!(sub.interval_type, IntervalType::Month),
-            "expected Month interval"
-        );
-        assert!(!sub.is_active, "subscription should start inactive");
-        assert!(!sub.is_setup, "subscription should start un-setup");
+    #[tokio::test]
+    async fn test_grow_disk_rejects_shrink() -> Result<()> {
+        let db = Arc::new(MockDb::default());
+        let vm = provision_custom_vm(&db).await?;
+        let prov = make_provisioner(db.clone());
 
+        for size in [50 * GB, 20 * GB] {
+            let err = prov.grow_disk(vm.id, size).await.unwrap_err();
+            assert!(err.downcast_ref::<ValidationError>().is_some());
+        }
+        let template = db
+            .get_custom_vm_template(vm.custom_template_id.unwrap())
+            .await?;
+        assert_eq!(template.disk_size, 50 * GB);
         Ok(())
     }
 
-    /// provision_custom() creates a subscription with 1-Month interval.
     #[tokio::test]
-    async fn test_provision_custom_creates_subscription() -> Result<()> {
+    async fn test_hotplug_resize_running_vm() -> Result<()> {
         let db = Arc::new(MockDb::default());
+        let vm = provision_custom_vm(&db).await?;
         let prov = make_provisioner(db.clone());
-        let (user, ssh_key) = add_user(&db).await?;
-        let pricing_id = insert_custom_pricing(&*db, DiskType::SSD, DiskInterface::PCIe).await?;
+        let base_amount = db
+            .get_subscription_line_item(vm.subscription_line_item_id)
+            .await?
+            .amount;
 
-        let template = lnvps_db::VmCustomTemplate {
-            id: 0,
-            cpu: 2,
-            memory: 4 * GB,
-            disk_size: 50 * GB,
-            disk_type: DiskType::SSD,
-            disk_interface: DiskInterface::PCIe,
-            pricing_id,
-            ..Default::default()
-        };
+        let client = DummyVmHost::new();
+        client
+            .create_vm(
+                &FullVmInfo::load(vm.id, db.clone()).await?,
+                RetryPolicy::default(),
+            )
+            .await?;
+        client.start_vm(&vm).await?;
 
-        let vm = prov
-            .provision_custom(user.id, template, 1, ssh_key.id, None)
+        let res = prov
+            .hotplug_resize_on(&client, vm.clone(), 4, 8 * GB)
             .await?;
+        assert_eq!(res, HotplugResize::Live);
+        let template = db
+            .get_custom_vm_template(vm.custom_template_id.unwrap())
+            .await?;
+        assert_eq!((template.cpu, template.memory), (4, 8 * GB));
+        let li = db
+            .get_subscription_line_item(vm.subscription_line_item_id)
+            .await?;
+        assert!(li.amount > base_amount);
 
-        assert!(vm.subscription_line_item_id > 0);
+        // can't shrink live
+        let err = prov
+            .hotplug_resize_on(&client, vm, 2, 8 * GB)
+            .await
+            .unwrap_err();
+        assert!(err.downcast_ref::<ValidationError>().is_some());
+        Ok(())
+    }
 
-        let line_item = db
+    #[tokio::test]
+    async fn test_hotplug_resize_stopped_vm_requires_restart() -> Result<()> {
+        let db = Arc::new(MockDb::default());
+        let vm = provision_custom_vm(&db).await?;
+        let prov = make_provisioner(db.clone());
+        let base_amount = db
             .get_subscription_line_item(vm.subscription_line_item_id)
+            .await?
+            .amount;
+
+        let client = DummyVmHost::new();
+        client
+            .create_vm(
+                &FullVmInfo::load(vm.id, db.clone()).await?,
+                RetryPolicy::default(),
+            )
             .await?;
-        assert_eq!(line_item.subscription_type, lnvps_db::SubscriptionType::Vps);
-        // Regression: the recorded line item amount must reflect the base monthly
-        // cost of the custom template, not zero.
-        assert!(
-            line_item.amount > 0,
-            "custom VM line_item.amount must be the base cost, not zero"
-        );
 
-        let sub = db.get_subscription(line_item.subscription_id).await?;
-        assert_eq!(sub.user_id, user.id);
-        // Custom VMs always use 1-Month interval
-        assert_eq!(sub.interval_amount, 1);
-        assert!(
-            matches!(sub.interval_type, IntervalType::Month),
-            "expected Month interval"
-        );
-        assert!(!sub.is_active);
-        assert!(!sub.is_setup);
+        let res = prov
+            .hotplug_resize_on(&client, vm.clone(), 4, 8 * GB)
+            .await?;
+        assert_eq!(res, HotplugResize::RestartRequired);
+        let template = db
+            .get_custom_vm_template(vm.custom_template_id.unwrap())
+            .await?;
+        assert_eq!((template.cpu, template.memory), (2, 4 * GB));
+        let li = db
+            .get_subscription_line_item(vm.subscription_line_item_id)
+            .await?;
+        assert_eq!(li.amount, base_amount);
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_vm_password() {
+        assert!(validate_vm_password("Tr0ub4dor&3-horse").is_ok());
+        // 3 of 4 classes is enough
+        assert!(validate_vm_password("correcthorse42!").is_ok());
+
+        assert!(validate_vm_password("").is_err());
+        assert!(validate_vm_password("Sh0rt!").is_err());
+        assert!(validate_vm_password("alllowercaseletters").is_err());
+        assert!(validate_vm_password("lowercase12345678").is_err());
+        assert!(validate_vm_password("With Spaces 123").is_err());
+        assert!(validate_vm_password(&"Aa1!".repeat(40)).is_err());
+
+        for _ in 0..50 {
+            assert!(validate_vm_password(&generate_vm_password()).is_ok());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reset_vm_password_rejects_weak_password() -> Result<()> {
+        let db = Arc::new(MockDb::default());
+        let vm = provision_custom_vm(&db).await?;
+        let prov = make_provisioner(db.clone());
 
+        let weak = "hunter2hunter2";
+        let err = prov
+            .reset_vm_password(vm.id, weak)
+            .await
+            .err()
+            .expect("weak password must be rejected");
+        let msg = err.to_string();
+        assert!(msg.contains("at least"), "unexpected error: {}", msg);
+        assert!(!msg.contains(weak), "error leaks the password: {}", msg);
         Ok(())
     }
 
@@ -2462,7 +4210,14 @@ mod tests {
         let (user, ssh_key) = add_user(&db).await?;
 
         let vm = prov
-            .provision(user.id, 1, 1, ssh_key.id, Some("TEST123".to_string()))
+            .provision(
+                user.id,
+                1,
+                1,
+                ssh_key.id,
+                Some("TEST123".to_string()),
+                false,
+            )
             .await?;
 
         assert_eq!(vm.ref_code, Some("TEST123".to_string()));
@@ -2484,7 +4239,7 @@ mod tests {
 
         // Provision a VM (subscription starts inactive, expires=None)
         let vm = provisioner
-            .provision(user.id, 1, 1, ssh_key.id, None)
+            .provision(user.id, 1, 1, ssh_key.id, None, false)
             .await?;
 
         let li = db
@@ -2552,7 +4307,7 @@ mod tests {
         let (user, ssh_key) = add_user(&db).await?;
 
         let vm = provisioner
-            .provision(user.id, 1, 1, ssh_key.id, None)
+            .provision(user.id, 1, 1, ssh_key.id, None, false)
             .await?;
         let li = db
             .get_subscription_line_item(vm.subscription_line_item_id)
@@ -2611,7 +4366,7 @@ mod tests {
         let (user, ssh_key) = add_user(&db).await?;
 
         let vm = provisioner
-            .provision(user.id, 1, 1, ssh_key.id, None)
+            .provision(user.id, 1, 1, ssh_key.id, None, false)
             .await?;
         let vm_id = vm.id;
 
@@ -2641,6 +4396,40 @@ mod tests {
         Ok(())
     }
 
+    /// Two concurrent orders for a host with room for one VM must not both be
+    /// placed: the second sees the first's capacity reservation.
+    #[tokio::test]
+    async fn test_concurrent_provision_reserves_capacity() -> Result<()> {
+        let db = Arc::new(MockDb::default());
+        {
+            let mut hosts = db.hosts.lock().await;
+            let host = hosts.get_mut(&1).unwrap();
+            host.cpu = 2;
+            host.load_cpu = 1.0;
+        }
+        let sub_handler = make_sub_handler(db.clone()).await?;
+        let provisioner = sub_handler.vm_provisioner();
+        let (user, ssh_key) = add_user(&db).await?;
+
+        let (a, b) = tokio::join!(
+            provisioner.provision(user.id, 1, 1, ssh_key.id, None, false),
+            provisioner.provision(user.id, 1, 1, ssh_key.id, None, false)
+        );
+        assert_eq!(
+            [&a, &b].iter().filter(|r| r.is_ok()).count(),
+            1,
+            "exactly one order must be placed"
+        );
+        let err = a.err().or(b.err()).unwrap();
+        assert!(err.downcast_ref::<CapacityError>().is_some(), "{err}");
+
+        // The placed (unpaid) VM keeps its reservation
+        let reservations = db.list_capacity_reservations(1).await?;
+        assert_eq!(reservations.len(), 1);
+        assert!(reservations[0].vm_id.is_some());
+        Ok(())
+    }
+
     /// When `on_grace_period_exceeded` is called the VM must be deleted
     /// (MockDb soft-deletes on `delete_vm`, setting `deleted = true`).
     #[tokio::test]
@@ -2652,7 +4441,7 @@ mod tests {
         let (user, ssh_key) = add_user(&db).await?;
 
         let vm = provisioner
-            .provision(user.id, 1, 1, ssh_key.id, None)
+            .provision(user.id, 1, 1, ssh_key.id, None, false)
             .await?;
         let vm_id = vm.id;
 
@@ -2696,7 +4485,7 @@ mod tests {
         let (user, ssh_key) = add_user(&db).await?;
 
         let vm = provisioner
-            .provision(user.id, 1, 1, ssh_key.id, None)
+            .provision(user.id, 1, 1, ssh_key.id, None, false)
             .await?;
         let li = db
             .get_subscription_line_item(vm.subscription_line_item_id)
@@ -2786,6 +4575,7 @@ mod tests {
                 disk_size: 20 * GB,
                 disk_storage: Some("mock-disk".to_string()),
                 mac_address: Some("bc:24:11:aa:bb:cc".to_string()),
+                ip_addresses: vec![],
                 running: true,
             }
         }
@@ -2797,7 +4587,7 @@ mod tests {
         crate::host::dummy_host::DummyVmHost::set_host_vms(vec![spec(200, 100)]).await;
 
         let provisioner = make_provisioner(db.clone());
-        let vm = provisioner.import_vm(1, 200, user.id).await?;
+        let vm = provisioner.import_vm(1, 200, user.id, None).await?;
 
         assert_eq!(vm.id, 100);
         assert_eq!(vm.user_id, user.id);
@@ -2824,7 +4614,7 @@ mod tests {
 
         // --- Scenario 2: re-importing an already-tracked VM fails
         let err = provisioner
-            .import_vm(1, 200, user.id)
+            .import_vm(1, 200, user.id, None)
             .await
             .expect_err("re-import must fail");
         assert!(
@@ -2835,7 +4625,7 @@ mod tests {
 
         // --- Scenario 3: VM not present on host
         let err = provisioner
-            .import_vm(1, 9999, user.id)
+            .import_vm(1, 9999, user.id, None)
             .await
             .expect_err("import must fail when VM not present on host");
         assert!(err.to_string().contains("not found"), "unexpected: {}", err);
@@ -2846,7 +4636,7 @@ mod tests {
         crate::host::dummy_host::DummyVmHost::set_host_vms(vec![spec(205, 105)]).await;
         let provisioner2 = make_provisioner(db2.clone());
         let err = provisioner2
-            .import_vm(1, 205, user2.id)
+            .import_vm(1, 205, user2.id, None)
             .await
             .expect_err("import must fail without custom pricing");
         assert!(
@@ -2855,6 +4645,266 @@ mod tests {
             err
         );
 
+        // --- Scenario 5: import with a standard template records the VM's IPs
+        let mut with_ips = spec(210, 110);
+        // sized like the mock template
+        with_ips.disk_size = 64 * GB;
+        with_ips.ip_addresses = vec![
+            "10.0.0.50".to_string(),
+            "fd00::50".to_string(),
+            // outside every range in the region, skipped
+            "192.168.1.50".to_string(),
+        ];
+        let mut conflicting = spec(211, 111);
+        conflicting.disk_size = 64 * GB;
+        conflicting.ip_addresses = vec!["10.0.0.50".to_string()];
+        // the mock template has a 64GB disk, this VM has 20GB
+        let smaller = spec(212, 112);
+        crate::host::dummy_host::DummyVmHost::set_host_vms(vec![with_ips, conflicting, smaller])
+            .await;
+        let vm = provisioner2.import_vm(1, 210, user2.id, Some(1)).await?;
+        assert_eq!(vm.id, 110);
+        assert_eq!(vm.template_id, Some(1));
+        assert!(vm.custom_template_id.is_none());
+        let li = db2
+            .get_subscription_line_item(vm.subscription_line_item_id)
+            .await?;
+        assert_eq!(li.amount, db2.get_cost_plan(1).await?.amount);
+        let mut ips: Vec<(u64, String)> = db2
+            .list_vm_ip_assignments(vm.id)
+            .await?
+            .into_iter()
+            .map(|a| (a.ip_range_id, a.ip))
+            .collect();
+        ips.sort();
+        assert_eq!(
+            ips,
+            vec![(1, "10.0.0.50".to_string()), (2, "fd00::50".to_string())]
+        );
+
+        // --- Scenario 6: an IP already assigned to another VM rejects the import
+        let err = provisioner2
+            .import_vm(1, 211, user2.id, Some(1))
+            .await
+            .expect_err("import must fail when an IP is taken");
+        assert!(
+            err.to_string().contains("already assigned"),
+            "unexpected error: {}",
+            err
+        );
+        assert!(db2.get_vm(111).await.is_err());
+
+        // --- Scenario 7: a template which doesn't match the VM's size is rejected
+        let subscriptions = db2.subscriptions.lock().await.len();
+        let err = provisioner2
+            .import_vm(1, 212, user2.id, Some(1))
+            .await
+            .expect_err("import must fail when the template doesn't match");
+        assert!(
+            err.to_string().contains("does not match"),
+            "unexpected error: {}",
+            err
+        );
+        assert!(db2.get_vm(112).await.is_err());
+        assert_eq!(db2.subscriptions.lock().await.len(), subscriptions);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_backups_to_prune_oldest_first() {
+        let now = Utc::now();
+        let backups: Vec<VmBackup> = (1..=4)
+            .map(|id| VmBackup {
+                id,
+                vm_id: 1,
+                created: now - chrono::Duration::days(id as i64),
+                ..Default::default()
+            })
+            .collect();
+        let pruned: Vec<u64> = backups_to_prune(backups.clone(), 2)
+            .iter()
+            .map(|b| b.id)
+            .collect();
+        assert_eq!(pruned, vec![3, 4]);
+        assert!(backups_to_prune(backups.clone(), 4).is_empty());
+        assert_eq!(backups_to_prune(backups, 0).len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_backup_vm_prunes_over_retention() -> Result<()> {
+        let db = Arc::new(MockDb::default());
+        db.templates
+            .lock()
+            .await
+            .get_mut(&1)
+            .unwrap()
+            .backup_retention = 2;
+        let (user, ssh_key) = add_user(&db).await?;
+        let prov = make_provisioner(db.clone());
+        let vm = prov
+            .provision(user.id, 1, 1, ssh_key.id, None, false)
+            .await?;
+
+        let first = prov.backup_vm(vm.id).await?;
+        let second = prov.backup_vm(vm.id).await?;
+        let third = prov.backup_vm(vm.id).await?;
+
+        let kept: Vec<u64> = db
+            .list_vm_backups(vm.id)
+            .await?
+            .iter()
+            .map(|b| b.id)
+            .collect();
+        assert_eq!(kept, vec![third.id, second.id]);
+        assert!(db.get_vm_backup(first.id).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_delete_vm_deletes_backups() -> Result<()> {
+        let db = Arc::new(MockDb::default());
+        db.templates
+            .lock()
+            .await
+            .get_mut(&1)
+            .unwrap()
+            .backup_retention = 2;
+        let (user, ssh_key) = add_user(&db).await?;
+        let prov = make_provisioner(db.clone());
+        let vm = prov
+            .provision(user.id, 1, 1, ssh_key.id, None, false)
+            .await?;
+        prov.backup_vm(vm.id).await?;
+        prov.backup_vm(vm.id).await?;
+
+        prov.delete_vm(vm.id, false).await?;
+        assert!(db.list_vm_backups(vm.id).await?.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_backup_vm_not_in_plan() -> Result<()> {
+        let db = Arc::new(MockDb::default());
+        let (user, ssh_key) = add_user(&db).await?;
+        let prov = make_provisioner(db.clone());
+        let vm = prov
+            .provision(user.id, 1, 1, ssh_key.id, None, false)
+            .await?;
+
+        assert!(prov.backup_vm(vm.id).await.is_err());
+        assert!(db.list_vm_backups(vm.id).await?.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_restore_rejects_other_vm_backup() -> Result<()> {
+        let db = Arc::new(MockDb::default());
+        let backup_id = db
+            .insert_vm_backup(&VmBackup {
+                vm_id: 99,
+                volume_id: "backup:backup/vzdump-qemu-99.vma.zst".to_string(),
+                ..Default::default()
+            })
+            .await?;
+        let err = make_provisioner(db)
+            .restore_vm_backup(1, backup_id)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("does not belong"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_transfer_vm_changes_owner() -> Result<()> {
+        let db = Arc::new(MockDb::default());
+        let (user, ssh_key) = add_user(&db).await?;
+        let prov = make_provisioner(db.clone());
+        let vm = prov
+            .provision(user.id, 1, 1, ssh_key.id, None, false)
+            .await?;
+
+        let new_owner: [u8; 32] = rand::random();
+        let new_uid = prov.transfer_vm(vm.id, &new_owner, None).await?;
+        assert_ne!(new_uid, user.id);
+        assert_eq!(db.upsert_user(&new_owner).await?, new_uid);
+
+        let vm = db.get_vm(vm.id).await?;
+        assert_eq!(vm.user_id, new_uid);
+        assert_eq!(vm.ssh_key_id, None, "old owner's key must be cleared");
+        let li = db
+            .get_subscription_line_item(vm.subscription_line_item_id)
+            .await?;
+        assert_eq!(
+            db.get_subscription(li.subscription_id).await?.user_id,
+            new_uid
+        );
+
+        let history = db.list_vm_history(vm.id).await?;
+        assert!(
+            history
+                .iter()
+                .any(|h| matches!(h.action_type, lnvps_db::VmHistoryActionType::Transferred))
+        );
+
+        // transferring to the current owner is rejected
+        assert!(prov.transfer_vm(vm.id, &new_owner, None).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_transfer_vm_rotates_ssh_key() -> Result<()> {
+        let db = Arc::new(MockDb::default());
+        let (user, ssh_key) = add_user(&db).await?;
+        let prov = make_provisioner(db.clone());
+        let vm = prov
+            .provision(user.id, 1, 1, ssh_key.id, None, false)
+            .await?;
+
+        // an invalid key is rejected before the new owner's account exists
+        let new_owner: [u8; 32] = rand::random();
+        let users = db.users.lock().await.len();
+        assert!(
+            prov.transfer_vm(vm.id, &new_owner, Some("not a key"))
+                .await
+                .is_err()
+        );
+        assert_eq!(db.users.lock().await.len(), users);
+        assert_eq!(db.get_vm(vm.id).await?.user_id, user.id);
+
+        let key = "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIGUSrwzZfbjqY81RRC7eg3zRvg0D53HOhjbG6h0SY3f3 new-owner";
+        let new_uid = prov.transfer_vm(vm.id, &new_owner, Some(key)).await?;
+        let vm = db.get_vm(vm.id).await?;
+        let new_key = db
+            .get_user_ssh_key(vm.ssh_key_id.expect("new owner's key"))
+            .await?;
+        assert_eq!(new_key.user_id, new_uid);
+        assert_eq!(new_key.name, "new-owner");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_transfer_vm_rejects_unpaid_invoice() -> Result<()> {
+        let db = Arc::new(MockDb::default());
+        let (user, ssh_key) = add_user(&db).await?;
+        let prov = make_provisioner(db.clone());
+        let vm = prov
+            .provision(user.id, 1, 1, ssh_key.id, None, false)
+            .await?;
+        let li = db
+            .get_subscription_line_item(vm.subscription_line_item_id)
+            .await?;
+        make_sub_handler(db.clone())
+            .await?
+            .renew_subscription(li.subscription_id, PaymentMethod::Lightning, 1)
+            .await?;
+
+        let err = prov
+            .transfer_vm(vm.id, &rand::random(), None)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("unpaid invoices"));
+        assert_eq!(db.get_vm(vm.id).await?.user_id, user.id);
         Ok(())
     }
 }
@@ -1,27 +1,62 @@
-use crate::router::{ArpEntry, get_router};
+use crate::router::{ArpEntry, PrefixRoute, Router, get_router};
+use crate::settings::SharedRetryConfig;
 use anyhow::{Context, anyhow};
 use ipnetwork::IpNetwork;
 use lnvps_api_common::op_fatal;
 use lnvps_api_common::retry::OpResult;
-use lnvps_api_common::{BasicRecord, DnsRef, get_dns_server};
-use lnvps_db::{AccessPolicy, IpRange, LNVpsDb, NetworkAccessPolicy, VmIpAssignment};
+use lnvps_api_common::{BasicRecord, DnsRef, DnsServer, DnsServerRegistry, NetworkProvisioner};
+use lnvps_db::{AccessPolicy, IpRange, LNVpsDb, NetworkAccessPolicy, RouterKind, VmIpAssignment};
 use log::warn;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
 use std::net::IpAddr;
 use std::str::FromStr;
 use std::sync::Arc;
-use try_procedure::{OpError, RetryPolicy, retry_async};
+use try_procedure::{OpError, retry_async};
 
 /// Network assignment tool for [super::VmProvisioner]
 #[derive(Clone)]
 pub struct VmNetworkProvisioner {
     db: Arc<dyn LNVpsDb>,
-    /// Retry policy to use when calling external services
-    retry_policy: RetryPolicy,
+    /// DNS server clients used by each ip range
+    pub dns: DnsServerRegistry,
+    /// Retry policies for router and DNS server calls
+    retry: SharedRetryConfig,
+}
+
+/// Outcome of [VmNetworkProvisioner::rebuild_router_arp]
+#[derive(Debug, Default)]
+pub struct ArpRebuildSummary {
+    pub router_id: u64,
+    /// Entries added back to (or corrected on) the router
+    pub restored: usize,
+    /// `ip: error` of each entry which could not be restored
+    pub failed: Vec<String>,
+}
+
+impl Display for ArpRebuildSummary {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Restored {} ARP entries on router {}, {} failed",
+            self.restored,
+            self.router_id,
+            self.failed.len()
+        )?;
+        for failure in &self.failed {
+            write!(f, "\n{}", failure)?;
+        }
+        Ok(())
+    }
 }
 
 impl VmNetworkProvisioner {
-    pub fn new(db: Arc<dyn LNVpsDb>, retry_policy: RetryPolicy) -> Self {
-        Self { db, retry_policy }
+    pub fn new(db: Arc<dyn LNVpsDb>, retry: SharedRetryConfig) -> Self {
+        Self {
+            dns: DnsServerRegistry::new(db.clone()),
+            db,
+            retry,
+        }
     }
 
     /// Create or Update access policy for a given ip assignment, does not save to database!
@@ -30,6 +65,9 @@ impl VmNetworkProvisioner {
         assignment: &mut VmIpAssignment,
         policy: &AccessPolicy,
     ) -> OpResult<()> {
+        if assignment.is_prefix() {
+            return self.update_prefix_route(assignment, policy).await;
+        }
         let ip = IpNetwork::from_str(&assignment.ip).map_err(|e| OpError::Fatal(anyhow!(e)))?;
         if matches!(policy.kind, NetworkAccessPolicy::StaticArp) && ip.is_ipv4() {
             let router = get_router(
@@ -58,12 +96,73 @@ impl VmNetworkProvisioner {
         Ok(())
     }
 
+    /// Route a delegated prefix to the VM on the access policy router, does not save to database!
+    pub async fn update_prefix_route(
+        &self,
+        assignment: &mut VmIpAssignment,
+        policy: &AccessPolicy,
+    ) -> OpResult<()> {
+        let router = get_router(
+            &self.db,
+            policy
+                .router_id
+                .context("Cannot route a delegated prefix with no router")?,
+        )
+        .await?;
+        let Some(routes) = router.prefix_routes() else {
+            op_fatal!("Router does not support prefix delegation")
+        };
+        let vm = self.db.get_vm(assignment.vm_id).await?;
+        let route = PrefixRoute::new(&vm, assignment, policy.interface.clone())?;
+        let route = routes.add_prefix_route(&route).await?;
+        if route.id.is_none() {
+            op_fatal!("Route id was empty")
+        }
+        assignment.route_ref = route.id;
+        Ok(())
+    }
+
+    /// Remove the route for a delegated prefix, does not save to database!
+    pub async fn remove_prefix_route(
+        &self,
+        assignment: &mut VmIpAssignment,
+        policy: &AccessPolicy,
+    ) -> OpResult<()> {
+        let Some(id) = assignment.route_ref.clone() else {
+            warn!("Route REF not found for {}, skipping", assignment);
+            return Ok(());
+        };
+        let router = get_router(
+            &self.db,
+            policy
+                .router_id
+                .context("Cannot route a delegated prefix with no router")?,
+        )
+        .await?;
+        if let Some(routes) = router.prefix_routes()
+            && let Err(e) = retry_async(self.retry.router(), || async {
+                routes.remove_prefix_route(&id).await
+            })
+            .await
+        {
+            warn!(
+                "Failed to remove prefix route after retries, skipping: {}",
+                e
+            );
+        }
+        assignment.route_ref = None;
+        Ok(())
+    }
+
     /// Remove an access policy for a given ip assignment, does not save to database!
     pub async fn remove_access_policy(
         &self,
         assignment: &mut VmIpAssignment,
         policy: &AccessPolicy,
     ) -> OpResult<()> {
+        if assignment.is_prefix() {
+            return self.remove_prefix_route(assignment, policy).await;
+        }
         let ip = IpNetwork::from_str(&assignment.ip).map_err(|e| OpError::Fatal(anyhow!(e)))?;
         if matches!(policy.kind, NetworkAccessPolicy::StaticArp) && ip.is_ipv4() {
             let router = get_router(
@@ -88,7 +187,7 @@ impl VmNetworkProvisioner {
             };
 
             if let Some(id) = id
-                && let Err(e) = retry_async(self.retry_policy.clone(), || async {
+                && let Err(e) = retry_async(self.retry.router(), || async {
                     router.remove_arp_entry(&id).await
                 })
                 .await
@@ -101,22 +200,138 @@ impl VmNetworkProvisioner {
         Ok(())
     }
 
+    /// Forward DNS suffix of the region `range` is in
+    async fn forward_dns_suffix(&self, range: &IpRange) -> OpResult<Option<String>> {
+        Ok(self
+            .db
+            .get_host_region(range.region_id)
+            .await?
+            .forward_dns_suffix)
+    }
+
+    /// Re-create the static ARP entries of every live IPv4 assignment in an
+    /// (enabled) range whose access policy uses `router_id`, e.g. after the
+    /// router was replaced and its ARP table is empty.
+    ///
+    /// Entries still on the router are updated in place. Each entry is retried
+    /// on its own and a failure is recorded in the summary instead of stopping
+    /// the rebuild. The new ARP refs are saved to the database.
+    pub async fn rebuild_router_arp(&self, router_id: u64) -> OpResult<ArpRebuildSummary> {
+        let router = get_router(&self.db, router_id).await?;
+        let existing: HashMap<String, ArpEntry> = retry_async(self.retry.router(), || async {
+            router.list_arp_entry().await
+        })
+        .await
+        .map_err(OpError::Fatal)?
+        .into_iter()
+        .map(|e| (e.address.clone(), e))
+        .collect();
+
+        let mut summary = ArpRebuildSummary {
+            router_id,
+            ..Default::default()
+        };
+        for range in self.db.list_ip_range().await? {
+            let Some(policy_id) = range.access_policy_id else {
+                continue;
+            };
+            let policy = self.db.get_access_policy(policy_id).await?;
+            if policy.router_id != Some(router_id)
+                || !matches!(policy.kind, NetworkAccessPolicy::StaticArp)
+            {
+                continue;
+            }
+            for mut assignment in self.db.list_vm_ip_assignments_in_range(range.id).await? {
+                let is_v4 = IpAddr::from_str(&assignment.ip).is_ok_and(|ip| ip.is_ipv4());
+                if assignment.is_prefix() || !is_v4 {
+                    continue;
+                }
+                let existing = existing.get(&assignment.ip);
+                match self
+                    .restore_arp_entry(router.as_ref(), &mut assignment, &policy, existing)
+                    .await
+                {
+                    Ok(()) => {
+                        self.db.update_vm_ip_assignment(&assignment).await?;
+                        summary.restored += 1;
+                    }
+                    Err(e) => {
+                        warn!(
+                            "Failed to restore ARP entry for {} on router {}: {}",
+                            assignment.ip, router_id, e
+                        );
+                        summary.failed.push(format!("{}: {}", assignment.ip, e));
+                    }
+                }
+            }
+        }
+        Ok(summary)
+    }
+
+    /// Add (or correct the `existing`) ARP entry of one assignment, does not
+    /// save to database!
+    async fn restore_arp_entry(
+        &self,
+        router: &dyn Router,
+        assignment: &mut VmIpAssignment,
+        policy: &AccessPolicy,
+        existing: Option<&ArpEntry>,
+    ) -> anyhow::Result<()> {
+        let vm = self.db.get_vm(assignment.vm_id).await?;
+        let mut entry = ArpEntry::new(&vm, assignment, policy.interface.clone())?;
+        // the stored ref may point at an entry the router no longer has
+        entry.id = existing.and_then(|e| e.id.clone());
+        let arp = retry_async(self.retry.router(), || async {
+            if entry.id.is_some() {
+                router.update_arp_entry(&entry).await
+            } else {
+                router.add_arp_entry(&entry).await
+            }
+        })
+        .await?;
+        assignment.arp_ref = Some(arp.id.context("ARP id was empty")?);
+        Ok(())
+    }
+
+    /// The DNS server and zone used for reverse records in `range`.
+    ///
+    /// Ranges without a reverse DNS server which are routed through an OVH
+    /// additional-IP router use OVH reverse DNS, keyed on the range's block.
+    pub async fn reverse_dns(
+        &self,
+        range: &IpRange,
+    ) -> OpResult<Option<(Arc<dyn DnsServer>, DnsRef)>> {
+        if let Some(dns_id) = range.reverse_dns_server_id {
+            let dns = self.dns.get(dns_id).await?;
+            return Ok(Some((dns, DnsRef::from_opt(range.reverse_zone_id.clone()))));
+        }
+        let Some(policy_id) = range.access_policy_id else {
+            return Ok(None);
+        };
+        let Some(router_id) = self.db.get_access_policy(policy_id).await?.router_id else {
+            return Ok(None);
+        };
+        let router = self.db.get_router(router_id).await?;
+        if !matches!(router.kind, RouterKind::OvhAdditionalIp) {
+            return Ok(None);
+        }
+        let dns = self.dns.ovh_router(&router).await?;
+        let block = range.reverse_zone_id.clone().unwrap_or(range.cidr.clone());
+        Ok(Some((dns, DnsRef::Id(block))))
+    }
+
     /// Delete DNS on the dns server, does not save to database!
     pub async fn remove_ip_dns(&self, assignment: &mut VmIpAssignment) -> OpResult<()> {
         let range = self.db.get_ip_range(assignment.ip_range_id).await?;
 
         // Delete reverse dns
-        if let (Some(dns_id), Some(_ref)) =
-            (range.reverse_dns_server_id, &assignment.dns_reverse_ref)
+        if assignment.dns_reverse_ref.is_some()
+            && let Some((dns, zone)) = self.reverse_dns(&range).await?
         {
-            let dns = get_dns_server(&self.db, dns_id).await?;
-            let rev =
-                BasicRecord::reverse(assignment, DnsRef::from_opt(range.reverse_zone_id.clone()))?;
+            let rev = BasicRecord::reverse(assignment, zone)?;
 
-            if let Err(e) = retry_async(self.retry_policy.clone(), || async {
-                dns.delete_record(&rev).await
-            })
-            .await
+            if let Err(e) =
+                retry_async(self.retry.dns(), || async { dns.delete_record(&rev).await }).await
             {
                 warn!("Failed to delete reverse record after retries: {}", e);
             }
@@ -128,14 +343,16 @@ impl VmNetworkProvisioner {
         if let (Some(dns_id), Some(_ref)) =
             (range.forward_dns_server_id, &assignment.dns_forward_ref)
         {
-            let dns = get_dns_server(&self.db, dns_id).await?;
-            let fwd =
-                BasicRecord::forward(assignment, DnsRef::from_opt(range.forward_zone_id.clone()))?;
-
-            if let Err(e) = retry_async(self.retry_policy.clone(), || async {
-                dns.delete_record(&fwd).await
-            })
-            .await
+            let dns = self.dns.get(dns_id).await?;
+            let suffix = self.forward_dns_suffix(&range).await?;
+            let fwd = BasicRecord::forward(
+                assignment,
+                DnsRef::from_opt(range.forward_zone_id.clone()),
+                suffix.as_deref(),
+            )?;
+
+            if let Err(e) =
+                retry_async(self.retry.dns(), || async { dns.delete_record(&fwd).await }).await
             {
                 warn!("Failed to delete forward record after retries: {}", e);
             }
@@ -147,12 +364,20 @@ impl VmNetworkProvisioner {
 
     /// Update DNS on the dns server, does not save to database!
     pub async fn update_forward_ip_dns(&self, assignment: &mut VmIpAssignment) -> OpResult<()> {
+        // a delegated prefix has no single address to name
+        if assignment.is_prefix() {
+            return Ok(());
+        }
         let range = self.db.get_ip_range(assignment.ip_range_id).await?;
         if let Some(dns_id) = range.forward_dns_server_id {
-            let dns = get_dns_server(&self.db, dns_id).await?;
-            let fwd =
-                BasicRecord::forward(assignment, DnsRef::from_opt(range.forward_zone_id.clone()))?;
-            let ret_fwd = retry_async(self.retry_policy.clone(), || async {
+            let dns = self.dns.get(dns_id).await?;
+            let suffix = self.forward_dns_suffix(&range).await?;
+            let fwd = BasicRecord::forward(
+                assignment,
+                DnsRef::from_opt(range.forward_zone_id.clone()),
+                suffix.as_deref(),
+            )?;
+            let ret_fwd = retry_async(self.retry.dns(), || async {
                 if fwd.id.is_some() {
                     dns.update_record(&fwd).await
                 } else {
@@ -161,6 +386,11 @@ impl VmNetworkProvisioner {
             })
             .await?;
 
+            // a PTR pointing at the old forward name follows it to the new one
+            if assignment.dns_reverse.is_some() && assignment.dns_reverse == assignment.dns_forward
+            {
+                assignment.dns_reverse = Some(ret_fwd.name.clone());
+            }
             assignment.dns_forward = Some(ret_fwd.name.clone());
             assignment.dns_forward_ref =
                 Some(ret_fwd.stored_ref().context("Record id is missing")?);
@@ -170,18 +400,19 @@ impl VmNetworkProvisioner {
 
     /// Update DNS on the dns server, does not save to database!
     pub async fn update_reverse_ip_dns(&self, assignment: &mut VmIpAssignment) -> OpResult<()> {
+        if assignment.is_prefix() {
+            return Ok(());
+        }
         let range = self.db.get_ip_range(assignment.ip_range_id).await?;
-        if let Some(dns_id) = range.reverse_dns_server_id {
-            let dns = get_dns_server(&self.db, dns_id).await?;
+        if let Some((dns, zone)) = self.reverse_dns(&range).await? {
             let has_ref = assignment.dns_reverse_ref.is_some();
-            let zone = DnsRef::from_opt(range.reverse_zone_id.clone());
             let rev_record = if has_ref {
                 BasicRecord::reverse(assignment, zone)?
             } else {
                 BasicRecord::reverse_to_fwd(assignment, zone)?
             };
 
-            let ret_rev = retry_async(self.retry_policy.clone(), || async {
+            let ret_rev = retry_async(self.retry.dns(), || async {
                 if has_ref {
                     dns.update_record(&rev_record).await
                 } else {
@@ -261,6 +492,43 @@ impl VmNetworkProvisioner {
             op_fatal!("IP address is not within the specified IP range");
         }
 
+        if let Some(prefix_length) = assignment.prefix_length {
+            let prefix =
+                IpNetwork::new(provided_ip, prefix_length).context("Invalid prefix length")?;
+            if prefix.is_ipv4() {
+                op_fatal!("Only IPv6 prefixes can be delegated");
+            }
+            if prefix_length < cidr.prefix() {
+                op_fatal!("Delegated prefix is larger than the specified IP range");
+            }
+            if prefix.network() != provided_ip {
+                op_fatal!("Delegated prefix must be the network address of the prefix");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Ensure a delegated prefix does not overlap any other live assignment in its range
+    pub async fn ensure_prefix_free(&self, assignment: &VmIpAssignment) -> OpResult<()> {
+        let prefix = NetworkProvisioner::assignment_network(assignment)
+            .context("Invalid delegated prefix")?;
+        let existing = self
+            .db
+            .list_vm_ip_assignments_in_range(assignment.ip_range_id)
+            .await?;
+        if let Some(other) = existing.iter().find(|o| {
+            o.id != assignment.id
+                && NetworkProvisioner::assignment_network(o)
+                    .is_some_and(|n| n.contains(prefix.network()) || prefix.contains(n.network()))
+        }) {
+            op_fatal!(
+                "Delegated prefix {} overlaps assignment {} of VM {}",
+                assignment,
+                other,
+                other.vm_id
+            );
+        }
         Ok(())
     }
 
@@ -284,6 +552,11 @@ impl VmNetworkProvisioner {
         if let Some(ap) = range.access_policy_id {
             let ap = self.db.get_access_policy(ap).await?;
             self.update_access_policy(assignment, &ap).await?;
+        } else if assignment.is_prefix() {
+            op_fatal!(
+                "IP range {} has no access policy router to route delegated prefixes",
+                range.id
+            );
         }
         Ok(())
     }
@@ -321,9 +594,374 @@ impl VmNetworkProvisioner {
         let range = self.db.get_ip_range(assignment.ip_range_id).await?;
 
         self.validate_ip_assignment(assignment, &range)?;
+        if assignment.is_prefix() {
+            self.ensure_prefix_free(assignment).await?;
+        }
         self.update_ip_assignment_policy(assignment, &range).await?;
         self.persist_ip_assignment(assignment).await?;
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lnvps_api_common::{MockDb, MockDnsServer};
+    use lnvps_db::{DnsServer, DnsServerKind, Region};
+    use try_procedure::RetryPolicy;
+
+    fn assignment(ip_range_id: u64, ip: &str) -> VmIpAssignment {
+        VmIpAssignment {
+            id: 1,
+            vm_id: 42,
+            ip_range_id,
+            ip: ip.to_string(),
+            ..Default::default()
+        }
+    }
+
+    async fn record_kinds(dns: &MockDnsServer, zone: &str) -> Vec<String> {
+        dns.zones
+            .lock()
+            .await
+            .get(zone)
+            .map(|z| z.values().map(|e| e.kind.clone()).collect())
+            .unwrap_or_default()
+    }
+
+    #[tokio::test]
+    async fn test_range_dns_routes_to_configured_server() -> anyhow::Result<()> {
+        let db = Arc::new(MockDb::default());
+        db.dns_servers.lock().await.insert(
+            2,
+            DnsServer {
+                id: 2,
+                name: "mock-dns-2".to_string(),
+                enabled: true,
+                kind: DnsServerKind::MockDns,
+                url: "https://localhost".to_string(),
+                token: "mock-token".into(),
+            },
+        );
+        {
+            let mut ranges = db.ip_range.lock().await;
+            let r1 = ranges.get_mut(&1).unwrap();
+            r1.forward_dns_server_id = Some(1);
+            r1.forward_zone_id = Some("fwd-1".to_string());
+            r1.reverse_dns_server_id = Some(2);
+            r1.reverse_zone_id = Some("rev-1".to_string());
+            let r2 = ranges.get_mut(&2).unwrap();
+            r2.forward_dns_server_id = Some(2);
+            r2.forward_zone_id = Some("fwd-2".to_string());
+        }
+
+        let network = VmNetworkProvisioner::new(db, retries(0, 0));
+        let dns_1 = Arc::new(MockDnsServer::isolated());
+        let dns_2 = Arc::new(MockDnsServer::isolated());
+        network.dns.register(1, dns_1.clone()).await;
+        network.dns.register(2, dns_2.clone()).await;
+
+        let mut v4 = assignment(1, "10.0.0.5");
+        network.update_forward_ip_dns(&mut v4).await?;
+        network.update_reverse_ip_dns(&mut v4).await?;
+        let mut v6 = assignment(2, "fd00::5");
+        network.update_forward_ip_dns(&mut v6).await?;
+        network.update_reverse_ip_dns(&mut v6).await?;
+
+        // range 1: A on server 1, PTR on server 2
+        assert_eq!(record_kinds(&dns_1, "fwd-1").await, vec!["A"]);
+        assert_eq!(record_kinds(&dns_2, "rev-1").await, vec!["PTR"]);
+        // range 2: AAAA on server 2, no reverse server configured
+        assert_eq!(record_kinds(&dns_2, "fwd-2").await, vec!["AAAA"]);
+        assert_eq!(dns_1.zones.lock().await.len(), 1);
+        assert_eq!(dns_2.zones.lock().await.len(), 2);
+        assert!(v6.dns_reverse_ref.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_forward_dns_suffix_per_region() -> anyhow::Result<()> {
+        let db = Arc::new(MockDb::default());
+        {
+            let mut regions = db.regions.lock().await;
+            let mut dub = regions.get(&1).cloned().unwrap();
+            dub.forward_dns_suffix = Some("dub".to_string());
+            let fra = Region {
+                id: 2,
+                name: "fra".to_string(),
+                forward_dns_suffix: Some("fra".to_string()),
+                ..dub.clone()
+            };
+            regions.insert(1, dub);
+            regions.insert(2, fra);
+        }
+        {
+            let mut ranges = db.ip_range.lock().await;
+            let r2 = ranges.get_mut(&2).unwrap();
+            r2.region_id = 2;
+            r2.reverse_dns_server_id = Some(1);
+            r2.reverse_zone_id = Some("rev-2".to_string());
+        }
+
+        let network = VmNetworkProvisioner::new(db.clone(), retries(0, 0));
+        network
+            .dns
+            .register(1, Arc::new(MockDnsServer::isolated()))
+            .await;
+
+        let mut dub = assignment(1, "10.0.0.5");
+        network.update_forward_ip_dns(&mut dub).await?;
+        let mut fra = assignment(2, "fd00::5");
+        network.update_forward_ip_dns(&mut fra).await?;
+        network.update_reverse_ip_dns(&mut fra).await?;
+
+        assert_eq!(dub.dns_forward.as_deref(), Some("vm-42.dub.lnvps.mock"));
+        assert_eq!(fra.dns_forward.as_deref(), Some("vm-42.fra.lnvps.mock"));
+        // the PTR points at the suffixed FQDN
+        assert_eq!(fra.dns_reverse.as_deref(), Some("vm-42.fra.lnvps.mock"));
+
+        // regions without a suffix name VMs directly in the zone
+        db.regions
+            .lock()
+            .await
+            .get_mut(&1)
+            .unwrap()
+            .forward_dns_suffix = None;
+        let mut plain = VmIpAssignment {
+            vm_id: 43,
+            ..assignment(1, "10.0.0.6")
+        };
+        network.update_forward_ip_dns(&mut plain).await?;
+        assert_eq!(plain.dns_forward.as_deref(), Some("vm-43.lnvps.mock"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_ovh_routed_range_uses_ovh_reverse_dns() -> anyhow::Result<()> {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/v1/auth/time"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(chrono::Utc::now().timestamp()))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/v1/ip/10.0.0.0%2F24/reverse"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "ipReverse": "10.0.0.5",
+                "reverse": "vm-42.lnvps.cloud."
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let db = Arc::new(MockDb::default());
+        db.router.lock().await.insert(
+            1,
+            lnvps_db::Router {
+                id: 1,
+                name: "ns1234".to_string(),
+                enabled: true,
+                kind: RouterKind::OvhAdditionalIp,
+                url: server.uri(),
+                token: "app-key:app-secret:consumer-key".into(),
+            },
+        );
+        db.access_policy.lock().await.insert(
+            1,
+            AccessPolicy {
+                id: 1,
+                name: "ovh".to_string(),
+                kind: NetworkAccessPolicy::StaticArp,
+                router_id: Some(1),
+                interface: None,
+            },
+        );
+        db.ip_range
+            .lock()
+            .await
+            .get_mut(&1)
+            .unwrap()
+            .access_policy_id = Some(1);
+
+        let network = VmNetworkProvisioner::new(db, retries(0, 0));
+        let mut v4 = VmIpAssignment {
+            dns_forward: Some("vm-42.lnvps.cloud".to_string()),
+            ..assignment(1, "10.0.0.5")
+        };
+        network.update_reverse_ip_dns(&mut v4).await?;
+        assert_eq!(v4.dns_reverse.as_deref(), Some("vm-42.lnvps.cloud."));
+        assert_eq!(v4.dns_reverse_ref.as_deref(), Some("10.0.0.5"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_rebuild_router_arp() -> anyhow::Result<()> {
+        use crate::mocks::MockRouter;
+        use lnvps_db::{LNVpsDbBase, Vm};
+
+        let db = Arc::new(MockDb::default());
+        db.router.lock().await.insert(
+            1,
+            lnvps_db::Router {
+                id: 1,
+                name: "r1".to_string(),
+                enabled: true,
+                kind: RouterKind::MockRouter,
+                url: "mock://".to_string(),
+                token: "".into(),
+            },
+        );
+        db.access_policy.lock().await.insert(
+            1,
+            AccessPolicy {
+                id: 1,
+                name: "arp".to_string(),
+                kind: NetworkAccessPolicy::StaticArp,
+                router_id: Some(1),
+                interface: Some("vlan100".to_string()),
+            },
+        );
+        db.ip_range
+            .lock()
+            .await
+            .get_mut(&1)
+            .unwrap()
+            .access_policy_id = Some(1);
+        // VMs 1-3 in the ARP range, 4 only has an IPv6 address
+        for (vm_id, range, ip) in [
+            (1, 1, "10.0.0.2"),
+            (2, 1, "10.0.0.3"),
+            (3, 1, "10.0.0.4"),
+            (4, 2, "fd00::4"),
+        ] {
+            db.vms.lock().await.insert(
+                vm_id,
+                Vm {
+                    id: vm_id,
+                    mac_address: format!("bc:24:11:00:00:0{}", vm_id),
+                    ..MockDb::mock_vm()
+                },
+            );
+            db.insert_vm_ip_assignment(&VmIpAssignment {
+                vm_id,
+                ip_range_id: range,
+                ip: ip.to_string(),
+                // refs from before the router lost its table
+                arp_ref: Some(format!("old-{}", vm_id)),
+                ..Default::default()
+            })
+            .await?;
+        }
+
+        let router = MockRouter::new();
+        router.clear().await;
+        // survived the reboot with a stale MAC
+        router
+            .add_arp_entry(&ArpEntry {
+                id: None,
+                address: "10.0.0.3".to_string(),
+                mac_address: "bc:24:11:ff:ff:ff".to_string(),
+                interface: None,
+                comment: None,
+            })
+            .await?;
+        router.fail_arp("10.0.0.4").await;
+
+        let network = VmNetworkProvisioner::new(db.clone(), retries(1, 0));
+        let summary = network.rebuild_router_arp(1).await?;
+        assert_eq!(summary.restored, 2);
+        assert_eq!(summary.failed.len(), 1);
+        assert!(summary.failed[0].starts_with("10.0.0.4: "));
+        assert!(
+            summary
+                .to_string()
+                .contains("Restored 2 ARP entries on router 1, 1 failed")
+        );
+
+        let mut entries: Vec<(String, String)> = router
+            .list_arp_entry()
+            .await?
+            .into_iter()
+            .map(|e| (e.address, e.mac_address))
+            .collect();
+        entries.sort();
+        assert_eq!(
+            entries,
+            vec![
+                ("10.0.0.2".to_string(), "bc:24:11:00:00:01".to_string()),
+                ("10.0.0.3".to_string(), "bc:24:11:00:00:02".to_string()),
+            ]
+        );
+
+        let refs: HashMap<String, Option<String>> = db
+            .ip_assignments
+            .lock()
+            .await
+            .values()
+            .map(|a| (a.ip.clone(), a.arp_ref.clone()))
+            .collect();
+        assert!(
+            refs["10.0.0.2"]
+                .as_deref()
+                .is_some_and(|r| !r.starts_with("old"))
+        );
+        assert!(
+            refs["10.0.0.3"]
+                .as_deref()
+                .is_some_and(|r| !r.starts_with("old"))
+        );
+        // a failed entry keeps its ref for the next rebuild to replace
+        assert_eq!(refs["10.0.0.4"].as_deref(), Some("old-3"));
+        assert_eq!(refs["fd00::4"].as_deref(), Some("old-4"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_configured_retry_policy_overrides_default() {
+        let mut settings = crate::settings::mock_settings();
+        settings.retry.router = Some(crate::settings::RetryPolicyConfig {
+            max_retries: Some(7),
+            ..Default::default()
+        });
+        let provisioner =
+            crate::provisioner::VmProvisioner::new(settings, Arc::new(MockDb::default()));
+        assert_eq!(provisioner.network.retry.router().max_retries, 7);
+        assert_eq!(
+            provisioner.network.retry.dns().max_retries,
+            RetryPolicy::default().max_retries
+        );
+
+        // a reload through the shared handle applies to the provisioner
+        let mut reloaded = crate::settings::RetryConfig::default();
+        reloaded.dns = Some(crate::settings::RetryPolicyConfig {
+            max_retries: Some(2),
+            ..Default::default()
+        });
+        provisioner.retry_config().set(reloaded);
+        assert_eq!(provisioner.network.retry.dns().max_retries, 2);
+        assert_eq!(
+            provisioner.network.retry.router().max_retries,
+            RetryPolicy::default().max_retries
+        );
+    }
+
+    /// Retry policies with `router`/`dns` retries and a 1ms delay
+    fn retries(router: u32, dns: u32) -> SharedRetryConfig {
+        let policy = |n| {
+            Some(crate::settings::RetryPolicyConfig {
+                min_delay_ms: Some(1),
+                max_retries: Some(n),
+                ..Default::default()
+            })
+        };
+        SharedRetryConfig::new(crate::settings::RetryConfig {
+            router: policy(router),
+            dns: policy(dns),
+            ..Default::default()
+        })
+    }
+}
@@ -0,0 +1,59 @@
+use async_trait::async_trait;
+use std::io::ErrorKind;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use tokio::net::TcpStream;
+
+/// Network reachability probe used by `CheckVm` to detect running VMs
+/// which are isolated from the network
+#[async_trait]
+pub trait ReachabilityProbe: Send + Sync {
+    /// Returns true if the address answered the probe
+    async fn is_reachable(&self, ip: IpAddr) -> bool;
+}
+
+/// Probe a VM by opening a TCP connection to a single port.
+///
+/// A refused connection still counts as reachable, the VM's network stack
+/// answered; only timeouts and unreachable errors count as failures.
+pub struct TcpReachabilityProbe {
+    port: u16,
+    timeout: Duration,
+}
+
+impl TcpReachabilityProbe {
+    pub fn new(port: u16, timeout: Duration) -> Self {
+        Self { port, timeout }
+    }
+}
+
+#[async_trait]
+impl ReachabilityProbe for TcpReachabilityProbe {
+    async fn is_reachable(&self, ip: IpAddr) -> bool {
+        let addr = SocketAddr::new(ip, self.port);
+        match tokio::time::timeout(self.timeout, TcpStream::connect(addr)).await {
+            Ok(Ok(_)) => true,
+            Ok(Err(e)) => e.kind() == ErrorKind::ConnectionRefused,
+            Err(_) => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn test_tcp_probe_open_and_refused() -> anyhow::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let port = listener.local_addr()?.port();
+        let probe = TcpReachabilityProbe::new(port, Duration::from_secs(1));
+        assert!(probe.is_reachable("127.0.0.1".parse()?).await);
+
+        // closed port answers with RST, which is still a live network stack
+        drop(listener);
+        assert!(probe.is_reachable("127.0.0.1".parse()?).await);
+        Ok(())
+    }
+}
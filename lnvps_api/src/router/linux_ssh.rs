@@ -7,8 +7,9 @@ use lnvps_api_common::retry::OpResult;
 use lnvps_api_common::{op_fatal, op_transient};
 
 use crate::router::{
-    ArpEntry, BgpPeer, BgpPeerDirection, BgpRoute, BgpRouter, BgpSession, GreConfig, Router,
-    Tunnel, TunnelConfig, TunnelRouter, TunnelTraffic, VxlanConfig, WireguardConfig, WireguardPeer,
+    ArpEntry, BgpPeer, BgpPeerDirection, BgpRoute, BgpRouter, BgpSession, GreConfig, PrefixRoute,
+    PrefixRouter, Router, Tunnel, TunnelConfig, TunnelRouter, TunnelTraffic, VxlanConfig,
+    WireguardConfig, WireguardPeer,
 };
 use crate::ssh_client::SshClient;
 
@@ -89,6 +90,23 @@ impl LinuxSshRouter {
         }
         Ok(out)
     }
+
+    /// Like [Self::exec_checked], but a non-zero exit whose output contains
+    /// `ignore` is treated as success
+    async fn exec_ignoring(&self, cmd: &str, ignore: &str) -> OpResult<()> {
+        let mut client = match self.connect().await {
+            Ok(c) => c,
+            Err(e) => op_transient!(e),
+        };
+        let (code, out) = match client.execute(cmd).await {
+            Ok(r) => r,
+            Err(e) => op_transient!(e),
+        };
+        if code != 0 && !out.contains(ignore) {
+            op_fatal!("command failed ({}): {} :: {}", code, cmd, out);
+        }
+        Ok(())
+    }
 }
 
 #[async_trait]
@@ -175,6 +193,72 @@ impl Router for LinuxSshRouter {
     fn bgp(&self) -> Option<&dyn BgpRouter> {
         Some(self)
     }
+
+    fn prefix_routes(&self) -> Option<&dyn PrefixRouter> {
+        Some(self)
+    }
+}
+
+/// Backend id of a delegated prefix route: the prefix, its next hop and the
+/// interface they were added on, all are needed to remove the route and its
+/// neighbour entry
+fn prefix_route_id(prefix: &str, next_hop: &str, dev: &str) -> String {
+    format!("{}={}@{}", prefix, next_hop, dev)
+}
+
+/// Split a [prefix_route_id] into `(prefix, next_hop, dev)`. Ids written before
+/// the interface was recorded carry no `@dev` and fall back to `default_dev`.
+fn parse_prefix_route_id<'a>(
+    id: &'a str,
+    default_dev: &'a str,
+) -> Option<(&'a str, &'a str, &'a str)> {
+    let (prefix, rest) = id.split_once('=')?;
+    let (next_hop, dev) = rest.split_once('@').unwrap_or((rest, default_dev));
+    if prefix.is_empty() || next_hop.is_empty() || dev.is_empty() {
+        return None;
+    }
+    Some((prefix, next_hop, dev))
+}
+
+#[async_trait]
+impl PrefixRouter for LinuxSshRouter {
+    async fn add_prefix_route(&self, route: &PrefixRoute) -> OpResult<PrefixRoute> {
+        let dev = route.interface.as_deref().unwrap_or(&self.interface);
+        // pin the next hop to the VM's MAC, then route the prefix via it
+        let cmd = format!(
+            "ip -6 neigh replace {nh} lladdr {mac} dev {dev} nud permanent && ip -6 route replace {prefix} via {nh} dev {dev}",
+            nh = route.next_hop,
+            mac = route.mac_address,
+            dev = dev,
+            prefix = route.prefix
+        );
+        self.exec_checked(&cmd).await?;
+        Ok(PrefixRoute {
+            id: Some(prefix_route_id(&route.prefix, &route.next_hop, dev)),
+            interface: Some(dev.to_string()),
+            ..route.clone()
+        })
+    }
+
+    async fn remove_prefix_route(&self, id: &str) -> OpResult<()> {
+        let Some((prefix, next_hop, dev)) = parse_prefix_route_id(id, &self.interface) else {
+            op_fatal!("Invalid prefix route id: {}", id);
+        };
+        // either may already be gone, removal is idempotent: iproute2 reports a
+        // missing route as ESRCH and a missing neighbour as ENOENT, anything
+        // else is a real failure
+        self.exec_ignoring(
+            &format!("ip -6 route del {} via {} dev {}", prefix, next_hop, dev),
+            "No such process",
+        )
+        .await?;
+        self.exec_ignoring(
+            &format!("ip -6 neigh del {} dev {}", next_hop, dev),
+            "No such file or directory",
+        )
+        .await?;
+        Ok(())
+    }
 }
 
 #[async_trait]
@@ -820,6 +904,21 @@ mod tests {
         assert!(LinuxSshRouter::new("ssh://10.0.0.1/", "K").is_err());
     }
 
+    #[test]
+    fn test_prefix_route_id() {
+        let id = prefix_route_id("2001:db8:1::/64", "fe80::1", "vmbr1");
+        assert_eq!(
+            parse_prefix_route_id(&id, "eth0"),
+            Some(("2001:db8:1::/64", "fe80::1", "vmbr1"))
+        );
+        // ids from before the interface was recorded use the router default
+        assert_eq!(
+            parse_prefix_route_id("2001:db8:1::/64=fe80::1", "eth0"),
+            Some(("2001:db8:1::/64", "fe80::1", "eth0"))
+        );
+        assert_eq!(parse_prefix_route_id("2001:db8:1::/64", "eth0"), None);
+    }
+
     #[test]
     fn test_parse_gre_key() {
         assert_eq!(parse_gre_key("10"), Some(10));
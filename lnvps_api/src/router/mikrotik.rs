@@ -1,33 +1,172 @@
 use crate::router::{
-    ArpEntry, BgpPeer, BgpPeerDirection, BgpRoute, BgpRouter, BgpSession, GreConfig, Router,
-    Tunnel, TunnelConfig, TunnelKind, TunnelRouter, TunnelTraffic, VxlanConfig, WireguardConfig,
-    WireguardPeer,
+    ArpEntry, BgpPeer, BgpPeerDirection, BgpRoute, BgpRouter, BgpSession, GreConfig, PrefixRoute,
+    PrefixRouter, Router, Tunnel, TunnelConfig, TunnelKind, TunnelRouter, TunnelTraffic,
+    VxlanConfig, WireguardConfig, WireguardPeer,
 };
 use anyhow::{Context, Result};
 use async_trait::async_trait;
 use base64::Engine;
 use base64::engine::general_purpose::STANDARD;
-use lnvps_api_common::JsonApi;
 use lnvps_api_common::op_fatal;
 use lnvps_api_common::retry::{OpError, OpResult};
-use reqwest::Method;
+use lnvps_api_common::{JsonApi, response_status};
+use log::warn;
+use reqwest::{Method, StatusCode};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+/// Wait before retrying a rate limited RouterOS call which has no `Retry-After`
+const MIKROTIK_RATE_LIMIT_DELAY: Duration = Duration::from_secs(5);
 
 pub struct MikrotikRouter {
+    url: String,
+    auth: String,
+    /// Current session, replaced when RouterOS stops accepting it
+    session: RwLock<Arc<MikrotikSession>>,
+    /// Number of sessions opened so far
+    sessions: AtomicU64,
+}
+
+/// A RouterOS REST session, the client keeps its connection alive between
+/// calls so bulk operations don't open a new connection per request
+struct MikrotikSession {
     api: JsonApi,
+    /// A call has succeeded with this session, so a later 401 means it
+    /// expired rather than that the credentials are wrong
+    verified: AtomicBool,
 }
 
 impl MikrotikRouter {
     pub fn new(url: &str, username: &str, password: &str) -> Self {
-        let auth = format!(
-            "Basic {}",
-            STANDARD.encode(format!("{}:{}", username, password))
-        );
+        let auth = basic_auth(username, password);
         Self {
-            api: JsonApi::token(url, &auth, true).unwrap(),
+            session: RwLock::new(Arc::new(Self::open_session(url, &auth))),
+            sessions: AtomicU64::new(1),
+            url: url.to_string(),
+            auth,
+        }
+    }
+
+    fn open_session(url: &str, auth: &str) -> MikrotikSession {
+        MikrotikSession {
+            api: JsonApi::token(url, auth, true)
+                .unwrap()
+                .with_rate_limit_parser(mikrotik_rate_limit),
+            verified: AtomicBool::new(false),
+        }
+    }
+
+    /// Replace `expired` with a new session, unless another task already did,
+    /// and return the current session
+    fn reconnect(&self, expired: &Arc<MikrotikSession>) -> Arc<MikrotikSession> {
+        let mut session = self.session.write().unwrap();
+        if Arc::ptr_eq(&session, expired) {
+            *session = Arc::new(Self::open_session(&self.url, &self.auth));
+            self.sessions.fetch_add(1, Ordering::Relaxed);
+        }
+        session.clone()
+    }
+
+    /// Call the REST API using the current session.
+    ///
+    /// A 401 on a session which worked before means it expired, the call is
+    /// repeated once on a new session. A 401 on a new session means the
+    /// credentials are wrong and is fatal, it is never retried.
+    async fn req<T: DeserializeOwned, R: Serialize>(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<R>,
+    ) -> OpResult<T> {
+        let session = self.session.read().unwrap().clone();
+        match session.api.req(method.clone(), path, body.as_ref()).await {
+            Ok(r) => {
+                session.verified.store(true, Ordering::Relaxed);
+                Ok(r)
+            }
+            Err(OpError::Fatal(e)) if self.session_expired(&session, &e) => {
+                let session = self.reconnect(&session);
+                let r = session.api.req(method, path, body).await?;
+                session.verified.store(true, Ordering::Relaxed);
+                Ok(r)
+            }
+            Err(e) => Err(e),
         }
     }
+
+    /// Like [Self::req] but only returns the status code
+    async fn req_status<R: Serialize>(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<R>,
+    ) -> OpResult<u16> {
+        let session = self.session.read().unwrap().clone();
+        match session
+            .api
+            .req_status(method.clone(), path, body.as_ref())
+            .await
+        {
+            Ok(r) => {
+                session.verified.store(true, Ordering::Relaxed);
+                Ok(r)
+            }
+            Err(OpError::Fatal(e)) if self.session_expired(&session, &e) => {
+                let session = self.reconnect(&session);
+                let r = session.api.req_status(method, path, body).await?;
+                session.verified.store(true, Ordering::Relaxed);
+                Ok(r)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// If `err` is a 401 on a session which worked before
+    fn session_expired(&self, session: &MikrotikSession, err: &anyhow::Error) -> bool {
+        let expired = is_unauthorized(err) && session.verified.load(Ordering::Relaxed);
+        if expired {
+            warn!("Mikrotik session for {} expired, reconnecting", self.url);
+        }
+        expired
+    }
+}
+
+fn basic_auth(username: &str, password: &str) -> String {
+    format!(
+        "Basic {}",
+        STANDARD.encode(format!("{}:{}", username, password))
+    )
+}
+
+/// Detect a 401 response in a [JsonApi] error
+fn is_unauthorized(err: &anyhow::Error) -> bool {
+    response_status(err) == Some(StatusCode::UNAUTHORIZED)
+}
+
+/// RouterOS REST error body
+#[derive(Deserialize)]
+struct MikrotikError {
+    error: u16,
+    #[serde(default)]
+    detail: Option<String>,
+}
+
+/// Detect RouterOS rate limit errors.
+///
+/// RouterOS reports the status in the `error` field of the body, and a
+/// `detail` of `too many ...` when the `www` service runs out of sessions.
+fn mikrotik_rate_limit(status: StatusCode, body: &str) -> Option<Duration> {
+    let limited = status == StatusCode::TOO_MANY_REQUESTS
+        || serde_json::from_str::<MikrotikError>(body).is_ok_and(|e| {
+            e.error == 429
+                || e.detail
+                    .is_some_and(|d| d.to_lowercase().starts_with("too many"))
+        });
+    limited.then_some(MIKROTIK_RATE_LIMIT_DELAY)
 }
 
 #[async_trait]
@@ -38,22 +177,19 @@ impl Router for MikrotikRouter {
     }
 
     async fn list_arp_entry(&self) -> OpResult<Vec<ArpEntry>> {
-        let rsp: Vec<MikrotikArpEntry> = self
-            .api
-            .req::<_, ()>(Method::GET, "/rest/ip/arp", None)
-            .await?;
+        let rsp: Vec<MikrotikArpEntry> =
+            self.req::<_, ()>(Method::GET, "/rest/ip/arp", None).await?;
         Ok(rsp.into_iter().filter_map(|e| e.try_into().ok()).collect())
     }
 
     async fn add_arp_entry(&self, entry: &ArpEntry) -> OpResult<ArpEntry> {
         let req: MikrotikArpEntry = entry.clone().into();
-        let rsp: MikrotikArpEntry = self.api.req(Method::PUT, "/rest/ip/arp", Some(req)).await?;
+        let rsp: MikrotikArpEntry = self.req(Method::PUT, "/rest/ip/arp", Some(req)).await?;
         rsp.try_into().map_err(OpError::Fatal)
     }
 
     async fn remove_arp_entry(&self, id: &str) -> OpResult<()> {
         let _rsp: MikrotikArpEntry = self
-            .api
             .req::<_, ()>(Method::DELETE, &format!("/rest/ip/arp/{}", id), None)
             .await?;
         Ok(())
@@ -65,7 +201,6 @@ impl Router for MikrotikRouter {
         }
         let req: MikrotikArpEntry = entry.clone().into();
         let rsp: MikrotikArpEntry = self
-            .api
             .req(
                 Method::PATCH,
                 &format!("/rest/ip/arp/{}", entry.id.as_ref().unwrap()),
@@ -82,17 +217,56 @@ impl Router for MikrotikRouter {
     fn bgp(&self) -> Option<&dyn BgpRouter> {
         Some(self)
     }
+
+    fn prefix_routes(&self) -> Option<&dyn PrefixRouter> {
+        Some(self)
+    }
+}
+
+#[async_trait]
+impl PrefixRouter for MikrotikRouter {
+    async fn add_prefix_route(&self, route: &PrefixRoute) -> OpResult<PrefixRoute> {
+        // RouterOS has no static IPv6 neighbours, the route alone is installed.
+        // A link-local gateway must be scoped to the interface it lives on.
+        let Some(interface) = route.interface.as_ref() else {
+            op_fatal!("Cannot route {} without an interface", route.prefix);
+        };
+        let body = json!({
+            "dst-address": route.prefix,
+            "gateway": format!("{}%{}", route.next_hop, interface),
+            "comment": route.comment,
+        });
+        let created: MtCreated = if let Some(id) = &route.id {
+            self.req(
+                Method::PATCH,
+                &format!("/rest/ipv6/route/{}", id),
+                Some(body),
+            )
+            .await?
+        } else {
+            self.req(Method::PUT, "/rest/ipv6/route", Some(body))
+                .await?
+        };
+        Ok(PrefixRoute {
+            id: created.id.or(route.id.clone()),
+            ..route.clone()
+        })
+    }
+
+    async fn remove_prefix_route(&self, id: &str) -> OpResult<()> {
+        self.req_status::<()>(Method::DELETE, &format!("/rest/ipv6/route/{}", id), None)
+            .await?;
+        Ok(())
+    }
 }
 
 #[async_trait]
 impl BgpRouter for MikrotikRouter {
     async fn list_sessions(&self) -> OpResult<Vec<BgpSession>> {
         let connections: Vec<MtBgpConnection> = self
-            .api
             .req::<_, ()>(Method::GET, "/rest/routing/bgp/connection", None)
             .await?;
         let sessions: Vec<MtBgpSession> = self
-            .api
             .req::<_, ()>(Method::GET, "/rest/routing/bgp/session", None)
             .await?;
         Ok(connections
@@ -140,7 +314,6 @@ impl BgpRouter for MikrotikRouter {
                 prefix
             );
             let routes: Vec<MtRoute> = self
-                .api
                 .req::<_, ()>(Method::GET, &path, None)
                 .await
                 .unwrap_or_default();
@@ -165,7 +338,7 @@ impl BgpRouter for MikrotikRouter {
                 "/rest/ip/route?dst-address={}&.proplist=dst-address,gateway",
                 dst
             );
-            let routes: Vec<MtRoute> = self.api.req::<_, ()>(Method::GET, &path, None).await?;
+            let routes: Vec<MtRoute> = self.req::<_, ()>(Method::GET, &path, None).await?;
             out.extend(routes.into_iter().map(|r| BgpRoute {
                 prefix: r.dst_address.unwrap_or_else(|| dst.to_string()),
                 next_hop: r.gateway,
@@ -196,7 +369,6 @@ impl BgpRouter for MikrotikRouter {
         };
         // Replace an existing static default for this family, else add a new one.
         let existing: Vec<MtRouteId> = self
-            .api
             .req::<_, ()>(
                 Method::GET,
                 &format!("/rest/{}/route?dst-address={}&.proplist=.id", menu, dst),
@@ -206,7 +378,6 @@ impl BgpRouter for MikrotikRouter {
             .unwrap_or_default();
         if let Some(id) = existing.into_iter().find_map(|r| r.id) {
             let _: serde_json::Value = self
-                .api
                 .req(
                     Method::PATCH,
                     &format!("/rest/{}/route/{}", menu, id),
@@ -215,7 +386,6 @@ impl BgpRouter for MikrotikRouter {
                 .await?;
         } else {
             let _: serde_json::Value = self
-                .api
                 .req(
                     Method::POST,
                     &format!("/rest/{}/route", menu),
@@ -229,7 +399,6 @@ impl BgpRouter for MikrotikRouter {
     async fn clear_default_route(&self) -> OpResult<()> {
         for (menu, dst) in [("ip", "0.0.0.0/0"), ("ipv6", "::/0")] {
             let existing: Vec<MtRouteId> = self
-                .api
                 .req::<_, ()>(
                     Method::GET,
                     &format!("/rest/{}/route?dst-address={}&.proplist=.id", menu, dst),
@@ -238,13 +407,12 @@ impl BgpRouter for MikrotikRouter {
                 .await
                 .unwrap_or_default();
             for id in existing.into_iter().filter_map(|r| r.id) {
-                self.api
-                    .req_status::<()>(
-                        Method::DELETE,
-                        &format!("/rest/{}/route/{}", menu, id),
-                        None,
-                    )
-                    .await?;
+                self.req_status::<()>(
+                    Method::DELETE,
+                    &format!("/rest/{}/route/{}", menu, id),
+                    None,
+                )
+                .await?;
             }
         }
         Ok(())
@@ -253,7 +421,6 @@ impl BgpRouter for MikrotikRouter {
     async fn set_session_enabled(&self, id: &str, enabled: bool) -> OpResult<()> {
         let body = json!({ "disabled": (!enabled).to_string() });
         let _: serde_json::Value = self
-            .api
             .req(
                 Method::PATCH,
                 &format!("/rest/routing/bgp/connection/{}", id),
@@ -324,7 +491,6 @@ impl TunnelRouter for MikrotikRouter {
         let mut out = Vec::new();
 
         let gres: Vec<MtGre> = self
-            .api
             .req::<_, ()>(Method::GET, "/rest/interface/gre", None)
             .await?;
         for g in gres {
@@ -339,7 +505,6 @@ impl TunnelRouter for MikrotikRouter {
         }
 
         let vxlans: Vec<MtVxlan> = self
-            .api
             .req::<_, ()>(Method::GET, "/rest/interface/vxlan", None)
             .await?;
         for v in vxlans {
@@ -357,12 +522,10 @@ impl TunnelRouter for MikrotikRouter {
         }
 
         let wgs: Vec<MtWireguard> = self
-            .api
             .req::<_, ()>(Method::GET, "/rest/interface/wireguard", None)
             .await?;
         if !wgs.is_empty() {
             let peers: Vec<MtWgPeer> = self
-                .api
                 .req::<_, ()>(Method::GET, "/rest/interface/wireguard/peers", None)
                 .await?;
             for w in wgs {
@@ -409,7 +572,7 @@ impl TunnelRouter for MikrotikRouter {
                     "local-address": tunnel.local_addr,
                     "remote-address": tunnel.remote_addr,
                 });
-                self.api.req(Method::PUT, &path, Some(body)).await?
+                self.req(Method::PUT, &path, Some(body)).await?
             }
             TunnelConfig::Vxlan(c) => {
                 let body = json!({
@@ -418,7 +581,7 @@ impl TunnelRouter for MikrotikRouter {
                     "port": c.dst_port.map(|p| p.to_string()),
                     "local-address": tunnel.local_addr,
                 });
-                self.api.req(Method::PUT, &path, Some(body)).await?
+                self.req(Method::PUT, &path, Some(body)).await?
             }
             TunnelConfig::Wireguard(c) => {
                 let body = json!({
@@ -426,7 +589,7 @@ impl TunnelRouter for MikrotikRouter {
                     "listen-port": c.listen_port.map(|p| p.to_string()),
                     "private-key": c.private_key,
                 });
-                let created: MtCreated = self.api.req(Method::PUT, &path, Some(body)).await?;
+                let created: MtCreated = self.req(Method::PUT, &path, Some(body)).await?;
                 for p in &c.peers {
                     let (ep_addr, ep_port) = split_endpoint(p.endpoint.as_deref());
                     let pbody = json!({
@@ -438,7 +601,6 @@ impl TunnelRouter for MikrotikRouter {
                         "persistent-keepalive": p.persistent_keepalive.map(|k| k.to_string()),
                     });
                     let _: MtCreated = self
-                        .api
                         .req(Method::PUT, "/rest/interface/wireguard/peers", Some(pbody))
                         .await?;
                 }
@@ -455,7 +617,6 @@ impl TunnelRouter for MikrotikRouter {
     async fn remove_tunnel(&self, id: &str) -> OpResult<()> {
         let (endpoint, ros_id) = split_tunnel_id(id)?;
         let _: serde_json::Value = self
-            .api
             .req::<_, ()>(
                 Method::DELETE,
                 &format!("/rest/interface/{}/{}", endpoint, ros_id),
@@ -494,7 +655,7 @@ impl TunnelRouter for MikrotikRouter {
                 "disabled": (!tunnel.enabled).to_string(),
             }),
         };
-        let _: serde_json::Value = self.api.req(Method::PATCH, &path, Some(body)).await?;
+        let _: serde_json::Value = self.req(Method::PATCH, &path, Some(body)).await?;
         Ok(tunnel.clone())
     }
 
@@ -502,7 +663,6 @@ impl TunnelRouter for MikrotikRouter {
         let (endpoint, ros_id) = split_tunnel_id(id)?;
         let body = json!({ "disabled": (!enabled).to_string() });
         let _: serde_json::Value = self
-            .api
             .req(
                 Method::PATCH,
                 &format!("/rest/interface/{}/{}", endpoint, ros_id),
@@ -514,7 +674,6 @@ impl TunnelRouter for MikrotikRouter {
 
     async fn tunnel_traffic(&self) -> OpResult<Vec<TunnelTraffic>> {
         let ifaces: Vec<MtInterface> = self
-            .api
             .req::<_, ()>(Method::GET, "/rest/interface", None)
             .await?;
         Ok(ifaces
@@ -747,3 +906,132 @@ mod tunnel_tests {
         assert_eq!(p2.endpoint(), None);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[test]
+    fn test_mikrotik_rate_limit() {
+        assert_eq!(
+            mikrotik_rate_limit(
+                StatusCode::TOO_MANY_REQUESTS,
+                r#"{"error":429,"message":"Too Many Requests"}"#
+            ),
+            Some(MIKROTIK_RATE_LIMIT_DELAY)
+        );
+        assert_eq!(
+            mikrotik_rate_limit(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                r#"{"detail":"too many sessions","error":500,"message":"Internal Server Error"}"#
+            ),
+            Some(MIKROTIK_RATE_LIMIT_DELAY)
+        );
+        assert_eq!(
+            mikrotik_rate_limit(
+                StatusCode::BAD_REQUEST,
+                r#"{"detail":"failure: already have such entry","error":400,"message":"Bad Request"}"#
+            ),
+            None
+        );
+        assert_eq!(
+            mikrotik_rate_limit(
+                StatusCode::NOT_FOUND,
+                r#"{"detail":"no such item","error":404,"message":"Not Found"}"#
+            ),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_session_reused_until_unauthorized() -> Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/rest/ip/arp"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!([])))
+            .mount(&server)
+            .await;
+
+        let router = MikrotikRouter::new(&server.uri(), "admin", "pass");
+        for _ in 0..5 {
+            router.list_arp_entry().await?;
+        }
+        assert_eq!(router.sessions.load(Ordering::Relaxed), 1);
+
+        // the router drops the session, the call is repeated on a new one
+        Mock::given(method("GET"))
+            .and(path("/rest/ip/arp"))
+            .respond_with(
+                ResponseTemplate::new(401)
+                    .set_body_json(json!({"error":401,"message":"Unauthorized"})),
+            )
+            .up_to_n_times(1)
+            .with_priority(1)
+            .mount(&server)
+            .await;
+        router.list_arp_entry().await?;
+        assert_eq!(router.sessions.load(Ordering::Relaxed), 2);
+
+        router.list_arp_entry().await?;
+        router.list_arp_entry().await?;
+        assert_eq!(router.sessions.load(Ordering::Relaxed), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_bad_credentials_are_fatal() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/rest/ip/arp"))
+            .respond_with(
+                ResponseTemplate::new(401)
+                    .set_body_json(json!({"error":401,"message":"Unauthorized"})),
+            )
+            .mount(&server)
+            .await;
+
+        let router = MikrotikRouter::new(&server.uri(), "admin", "wrong");
+        assert!(matches!(
+            router.list_arp_entry().await,
+            Err(OpError::Fatal(_))
+        ));
+        assert_eq!(router.sessions.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn test_revoked_credentials_are_fatal() -> Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/rest/ip/arp"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!([])))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/rest/ip/arp"))
+            .respond_with(
+                ResponseTemplate::new(401)
+                    .set_body_json(json!({"error":401,"message":"Unauthorized"})),
+            )
+            .mount(&server)
+            .await;
+
+        let router = MikrotikRouter::new(&server.uri(), "admin", "pass");
+        router.list_arp_entry().await?;
+
+        // the new session is refused too, so the 401 is not retried again
+        assert!(matches!(
+            router.list_arp_entry().await,
+            Err(OpError::Fatal(_))
+        ));
+        assert_eq!(router.sessions.load(Ordering::Relaxed), 2);
+        assert!(matches!(
+            router.list_arp_entry().await,
+            Err(OpError::Fatal(_))
+        ));
+        assert_eq!(router.sessions.load(Ordering::Relaxed), 2);
+        Ok(())
+    }
+}
@@ -1,8 +1,11 @@
 use anyhow::{Context, Result, ensure};
 use async_trait::async_trait;
+use lnvps_api_common::NetworkProvisioner;
 use lnvps_api_common::retry::OpResult;
 use lnvps_db::{LNVpsDb, RouterKind, Vm, VmIpAssignment};
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{Duration, Instant};
 
 /// Router defines a network device used to access the hosts
 ///
@@ -33,6 +36,26 @@ pub trait Router: Send + Sync {
     fn bgp(&self) -> Option<&dyn BgpRouter> {
         None
     }
+
+    /// Prefix delegation capability, if this router supports it.
+    ///
+    /// Returns `None` for routers that cannot route a prefix to a VM.
+    fn prefix_routes(&self) -> Option<&dyn PrefixRouter> {
+        None
+    }
+}
+
+/// Optional capability for routers that can route a delegated IPv6 prefix to a VM.
+///
+/// The prefix is routed via the VM's EUI-64 link-local address on the access
+/// interface, with a static neighbour entry where the router supports one so the
+/// next hop can't be claimed by another VM on the same link.
+#[async_trait]
+pub trait PrefixRouter: Send + Sync {
+    /// Install (or replace) the route and neighbour entry for a delegated prefix
+    async fn add_prefix_route(&self, route: &PrefixRoute) -> OpResult<PrefixRoute>;
+    /// Remove a delegated prefix route by its backend id, idempotent
+    async fn remove_prefix_route(&self, id: &str) -> OpResult<()>;
 }
 
 /// Optional capability for routers that run BGP (route servers / edge routers).
@@ -330,6 +353,42 @@ impl BgpRoute {
     }
 }
 
+/// A route for a prefix delegated to a VM
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrefixRoute {
+    /// Backend identifier (`prefix=next_hop@dev` on Linux, `.id` on Mikrotik)
+    pub id: Option<String>,
+    /// Delegated prefix (CIDR)
+    pub prefix: String,
+    /// Next hop, the VM's link-local address
+    pub next_hop: String,
+    /// VM MAC address used for the static neighbour entry of the next hop
+    pub mac_address: String,
+    /// Interface the VM is reachable on
+    pub interface: Option<String>,
+    pub comment: Option<String>,
+}
+
+impl PrefixRoute {
+    pub fn new(vm: &Vm, ip: &VmIpAssignment, interface: Option<String>) -> Result<Self> {
+        ensure!(
+            vm.mac_address != "ff:ff:ff:ff:ff:ff",
+            "MAC address is invalid because its blank"
+        );
+        let prefix_length = ip
+            .prefix_length
+            .context("IP assignment is not a delegated prefix")?;
+        Ok(Self {
+            id: ip.route_ref.clone(),
+            prefix: format!("{}/{}", ip.ip, prefix_length),
+            next_hop: NetworkProvisioner::link_local_next_hop(&vm.mac_address)?.to_string(),
+            mac_address: vm.mac_address.clone(),
+            interface,
+            comment: Some(format!("VM{}", vm.id)),
+        })
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct ArpEntry {
     pub id: Option<String>,
@@ -367,16 +426,77 @@ pub use linux_ssh::LinuxSshRouter;
 pub use mikrotik::MikrotikRouter;
 pub use ovh::OvhDedicatedServerVMacRouter;
 
+/// Split a decrypted router token into `username:password`.
+///
+/// Only the first `:` separates the two, so passwords may contain `:`.
+fn parse_router_credentials(token: &str) -> Result<(&str, &str)> {
+    token.split_once(':').context("Invalid username:password")
+}
+
+/// Url, username and password a router client was created for
+type RouterKey = (String, String, String);
+
+/// How long an unused router client stays cached
+const ROUTER_CACHE_TTL: Duration = Duration::from_secs(15 * 60);
+
+/// Most router clients cached at once, the least recently used one is dropped
+/// to make room for a new one
+const ROUTER_CACHE_SIZE: usize = 32;
+
+/// Router clients by url and credentials, so every caller and every router
+/// entry pointing at the same device with the same login shares its session.
+///
+/// Changed credentials get a new client, the old one expires after
+/// [ROUTER_CACHE_TTL] without use.
+struct RouterCache<T> {
+    entries: HashMap<RouterKey, (Arc<T>, Instant)>,
+}
+
+impl<T> RouterCache<T> {
+    fn new() -> Self {
+        Self {
+            entries: HashMap::new(),
+        }
+    }
+
+    /// The cached client for `key`, or a new one from `make`
+    fn get_or_insert(&mut self, key: RouterKey, now: Instant, make: impl FnOnce() -> T) -> Arc<T> {
+        self.entries
+            .retain(|_, (_, used)| now.duration_since(*used) < ROUTER_CACHE_TTL);
+        if let Some((r, used)) = self.entries.get_mut(&key) {
+            *used = now;
+            return r.clone();
+        }
+        if self.entries.len() >= ROUTER_CACHE_SIZE
+            && let Some(oldest) = self
+                .entries
+                .iter()
+                .min_by_key(|(_, (_, used))| *used)
+                .map(|(k, _)| k.clone())
+        {
+            self.entries.remove(&oldest);
+        }
+        let r = Arc::new(make());
+        self.entries.insert(key, (r.clone(), now));
+        r
+    }
+}
+
+static MIKROTIK_ROUTERS: LazyLock<Mutex<RouterCache<MikrotikRouter>>> =
+    LazyLock::new(|| Mutex::new(RouterCache::new()));
+
 pub async fn get_router(db: &Arc<dyn LNVpsDb>, router_id: u64) -> OpResult<Arc<dyn Router>> {
     let cfg = db.get_router(router_id).await?;
     match cfg.kind {
         RouterKind::Mikrotik => {
-            let mut t_split = cfg.token.as_str().split(":");
-            let (username, password) = (
-                t_split.next().context("Invalid username:password")?,
-                t_split.next().context("Invalid username:password")?,
-            );
-            Ok(Arc::new(MikrotikRouter::new(&cfg.url, username, password)))
+            let (username, password) = parse_router_credentials(cfg.token.as_str())?;
+            let key = (cfg.url.clone(), username.to_string(), password.to_string());
+            Ok(MIKROTIK_ROUTERS
+                .lock()
+                .unwrap()
+                .get_or_insert(key, Instant::now(), || {
+                    MikrotikRouter::new(&cfg.url, username, password)
+                }))
         }
         RouterKind::OvhAdditionalIp => Ok(Arc::new(
             OvhDedicatedServerVMacRouter::new(&cfg.url, &cfg.name, cfg.token.as_str()).await?,
@@ -415,6 +535,61 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_router_token_encryption_roundtrip() -> anyhow::Result<()> {
+        use lnvps_db::EncryptionContext;
+
+        EncryptionContext::try_init_from_hex(
+            "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef",
+        )?;
+        let ctx = EncryptionContext::get()?;
+
+        let token = "admin:s3cr:et";
+        let stored = ctx.encrypt(token)?;
+        assert!(EncryptionContext::is_encrypted(&stored));
+        assert!(!stored.contains("s3cr"));
+
+        let decrypted = ctx.decrypt(&stored)?;
+        assert_eq!(decrypted, token);
+        assert_eq!(parse_router_credentials(&decrypted)?, ("admin", "s3cr:et"));
+        assert!(parse_router_credentials("no-separator").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_router_cache() {
+        let key = |url: &str, pass: &str| (url.to_string(), "admin".to_string(), pass.to_string());
+        let mut cache = RouterCache::new();
+        let now = Instant::now();
+
+        let a = cache.get_or_insert(key("r1", "p1"), now, || 1);
+        let b = cache.get_or_insert(key("r1", "p1"), now, || 2);
+        assert!(Arc::ptr_eq(&a, &b));
+
+        // new credentials get a new client
+        let c = cache.get_or_insert(key("r1", "p2"), now, || 3);
+        assert_eq!(*c, 3);
+        assert_eq!(cache.entries.len(), 2);
+
+        // unused clients expire, used ones stay
+        let later = now + ROUTER_CACHE_TTL / 2;
+        cache.get_or_insert(key("r1", "p2"), later, || 4);
+        let expired = now + ROUTER_CACHE_TTL + Duration::from_secs(1);
+        let d = cache.get_or_insert(key("r1", "p1"), expired, || 5);
+        assert_eq!(*d, 5);
+        assert_eq!(cache.entries.len(), 2);
+
+        // when full the least recently used client is dropped
+        for i in 0..ROUTER_CACHE_SIZE {
+            let at = expired + Duration::from_secs(i as u64 + 1);
+            cache.get_or_insert(key(&format!("h{i}"), "p"), at, || 0);
+        }
+        assert_eq!(cache.entries.len(), ROUTER_CACHE_SIZE);
+        assert!(!cache.entries.contains_key(&key("r1", "p2")));
+        assert!(!cache.entries.contains_key(&key("r1", "p1")));
+        assert!(cache.entries.contains_key(&key("h0", "p")));
+    }
+
     #[tokio::test]
     async fn test_mock_tunnel_lifecycle() -> anyhow::Result<()> {
         let r = MockRouter::new();
@@ -498,6 +673,48 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_prefix_route_via_link_local() -> anyhow::Result<()> {
+        let vm = Vm {
+            id: 7,
+            mac_address: "bc:24:11:00:00:01".to_string(),
+            ..Default::default()
+        };
+        let mut ip = VmIpAssignment {
+            vm_id: 7,
+            ip: "2001:db8:100:7::".to_string(),
+            ..Default::default()
+        };
+        // a single address is not routed as a prefix
+        assert!(PrefixRoute::new(&vm, &ip, None).is_err());
+
+        ip.prefix_length = Some(64);
+        let route = PrefixRoute::new(&vm, &ip, Some("bridge1".to_string()))?;
+        assert_eq!(route.prefix, "2001:db8:100:7::/64");
+        assert_eq!(route.next_hop, "fe80::be24:11ff:fe00:1");
+        assert_eq!(route.comment.as_deref(), Some("VM7"));
+
+        let r = MockRouter::new();
+        r.clear().await;
+        let pr = r
+            .prefix_routes()
+            .expect("mock router supports prefix routes");
+        let added = pr.add_prefix_route(&route).await.unwrap();
+        assert!(added.id.is_some());
+        // the same prefix can't be routed to another VM
+        let other = PrefixRoute {
+            next_hop: "fe80::1".to_string(),
+            ..route.clone()
+        };
+        assert!(pr.add_prefix_route(&other).await.is_err());
+
+        pr.remove_prefix_route(added.id.as_deref().unwrap())
+            .await
+            .unwrap();
+        assert!(r.list_prefix_routes().await.is_empty());
+        Ok(())
+    }
+
     /// Regression: a "Down" BGP state is cached as administratively disabled,
     /// so disabling a session (which drives BIRD to state "Down") is reflected
     /// in the database on the next discovery refresh.
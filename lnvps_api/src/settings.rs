@@ -1,6 +1,11 @@
-use lnvps_api_common::RedisConfig;
+use lnvps_api_common::retry::RetryPolicy;
+use lnvps_api_common::{CorsSettings, CustomVmFloors, RedisConfig};
+use lnvps_db::PaymentMethod;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "kebab-case")]
@@ -8,12 +13,30 @@ pub struct Settings {
     /// Listen address for http server
     pub listen: Option<String>,
 
+    /// Listen address for the Prometheus metrics server, disabled when not set
+    pub metrics_listen: Option<String>,
+
     /// MYSQL connection string
     pub db: String,
 
-    /// Public URL mapping to this service
+    /// MYSQL read replica connection string, used for listings and reports
+    pub db_replica: Option<String>,
+
+    /// Public URL mapping to this service, including `base_path` when set
     pub public_url: String,
 
+    /// Path prefix all routes are mounted under (e.g. `/api`), for serving
+    /// behind a shared reverse proxy without rewriting
+    pub base_path: Option<String>,
+
+    /// Reverse proxies (CIDRs) whose `X-Forwarded-For` header is believed when
+    /// finding the client IP, headers are always believed when not set
+    pub trusted_proxies: Option<Vec<String>>,
+
+    /// Origins, methods and headers allowed for cross-origin requests
+    #[serde(default)]
+    pub cors: CorsSettings,
+
     /// Readonly mode, don't spawn any VM's
     pub read_only: bool,
 
@@ -53,6 +76,21 @@ pub struct Settings {
     /// Redis configuration for shared VM state cache
     pub redis: Option<RedisConfig>,
 
+    /// Queue worker jobs in the database when Redis is not configured, so
+    /// jobs survive restarts and can be shared by several workers
+    #[serde(default)]
+    pub db_work_queue: bool,
+
+    /// Seconds a worker may go without renewing its claim on a database
+    /// queue job before the job is handed to another worker (default 600)
+    #[serde(default)]
+    pub db_work_queue_lease_secs: Option<u64>,
+
+    /// Runs of a database queue job, failed or abandoned, before it is
+    /// dead-lettered (default 5)
+    #[serde(default)]
+    pub db_work_queue_max_attempts: Option<u32>,
+
     /// Database encryption configuration (fallback when the
     /// `LNVPS_ENCRYPTION_KEY` environment variable is not set)
     pub encryption: Option<EncryptionConfig>,
@@ -70,6 +108,10 @@ pub struct Settings {
     /// be paid manually by admins, but no automatic Lightning payouts are made.
     pub referral: Option<ReferralConfig>,
 
+    /// Periodically stop or delete VMs which were never paid for. Opt-in: when
+    /// omitted, unpaid VMs are only reclaimed through the admin API.
+    pub unpaid_vms: Option<UnpaidVmsConfig>,
+
     /// External OAuth/OIDC login support. When omitted, only Nostr (NIP-98)
     /// authentication is available.
     pub oauth: Option<OAuthConfig>,
@@ -82,6 +124,296 @@ pub struct Settings {
     /// configured — both issue the same stateless session JWTs. When omitted,
     /// `Bearer` session auth is disabled and only Nostr (NIP-98) auth works.
     pub session: Option<SessionConfig>,
+
+    /// VM reachability probe settings, used for regions with
+    /// `reachability_check` enabled. Defaults apply when omitted.
+    pub reachability: Option<ReachabilityConfig>,
+
+    /// How long new payments stay open, per payment method. Unpaid payments
+    /// are canceled once expired. Defaults apply when omitted.
+    #[serde(default)]
+    pub payment_expiry: PaymentExpiryConfig,
+
+    /// What to do when a Lightning invoice settles for a different amount
+    /// than requested. Defaults to crediting the user's account balance.
+    #[serde(default)]
+    pub underpayment_policy: UnderpaymentPolicy,
+
+    /// Whether suspended VMs are still charged for. Defaults to charged.
+    #[serde(default)]
+    pub suspended_billing: SuspendedBilling,
+
+    /// Longest suspension (days) that keeps a subscription from expiring and
+    /// is credited to its expiry when suspended time is not billed.
+    /// Defaults to 30.
+    #[serde(default = "default_max_suspended_days")]
+    pub max_suspended_days: u64,
+
+    /// Retry policies for calls to external services, per subsystem.
+    /// Unset subsystems (and fields) use the default policy.
+    #[serde(default)]
+    pub retry: RetryConfig,
+
+    /// How many worker jobs may run at once, per job type
+    #[serde(default)]
+    pub job_concurrency: JobConcurrencyConfig,
+
+    /// Interval in seconds between runs of scheduled worker jobs, keyed by job
+    /// name (e.g. `CheckVms`). Jobs not listed use their built-in interval.
+    #[serde(default)]
+    pub job_intervals: HashMap<String, u64>,
+
+    /// Largest change, in percent, host auto-discovery may make to a host's
+    /// CPU, memory or disk sizes. Bigger changes and drops to zero are
+    /// ignored as bad reads unless a host sync is forced. Defaults to 50.
+    #[serde(default = "default_host_discovery_max_change")]
+    pub host_discovery_max_change: u8,
+
+    /// Smallest CPU, memory and disk a custom VM may be ordered with, and the
+    /// lowest minimums a custom pricing plan may set
+    #[serde(default)]
+    pub custom_vm_floors: CustomVmFloors,
+}
+
+impl Settings {
+    /// Load settings from config files, layered in order so values in later
+    /// files override earlier ones
+    pub fn load(paths: &[PathBuf]) -> anyhow::Result<Self> {
+        let mut builder = config::Config::builder();
+        for path in paths {
+            builder = builder.add_source(config::File::from(path.clone()));
+        }
+        Ok(builder.build()?.try_deserialize()?)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct JobConcurrencyConfig {
+    /// Jobs allowed to run at once across all job types without their own
+    /// limit
+    pub default: usize,
+    /// Jobs allowed to run at once per job type, keyed by job name (e.g.
+    /// `CheckVm`). Each listed job type gets its own allowance.
+    pub limits: HashMap<String, usize>,
+}
+
+impl Default for JobConcurrencyConfig {
+    fn default() -> Self {
+        Self {
+            default: 1,
+            limits: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct RetryConfig {
+    /// Router calls (ARP entries, prefix routes)
+    pub router: Option<RetryPolicyConfig>,
+    /// DNS record updates
+    pub dns: Option<RetryPolicyConfig>,
+    /// VM host operations (spawn, re-install, upgrades, disks)
+    pub host: Option<RetryPolicyConfig>,
+    /// Lightning node calls
+    pub lightning: Option<RetryPolicyConfig>,
+}
+
+/// [RetryConfig] shared by every service using it and read at each use, so a
+/// settings reload applies to all of them
+#[derive(Debug, Clone, Default)]
+pub struct SharedRetryConfig(Arc<RwLock<RetryConfig>>);
+
+impl SharedRetryConfig {
+    pub fn new(config: RetryConfig) -> Self {
+        Self(Arc::new(RwLock::new(config)))
+    }
+
+    /// Replace the policies, later calls use the new ones
+    pub fn set(&self, config: RetryConfig) {
+        *self.0.write().unwrap() = config;
+    }
+
+    pub fn router(&self) -> RetryPolicy {
+        self.0.read().unwrap().router()
+    }
+
+    pub fn dns(&self) -> RetryPolicy {
+        self.0.read().unwrap().dns()
+    }
+
+    pub fn host(&self) -> RetryPolicy {
+        self.0.read().unwrap().host()
+    }
+
+    pub fn lightning(&self) -> RetryPolicy {
+        self.0.read().unwrap().lightning()
+    }
+}
+
+impl RetryConfig {
+    pub fn router(&self) -> RetryPolicy {
+        RetryPolicyConfig::policy(&self.router)
+    }
+
+    pub fn dns(&self) -> RetryPolicy {
+        RetryPolicyConfig::policy(&self.dns)
+    }
+
+    pub fn host(&self) -> RetryPolicy {
+        RetryPolicyConfig::policy(&self.host)
+    }
+
+    pub fn lightning(&self) -> RetryPolicy {
+        RetryPolicyConfig::policy(&self.lightning)
+    }
+}
+
+/// Overrides for [RetryPolicy::default]
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct RetryPolicyConfig {
+    /// Delay before the first retry in milliseconds
+    pub min_delay_ms: Option<u64>,
+    /// Cap on the backoff delay in milliseconds
+    pub max_delay_ms: Option<u64>,
+    /// Number of retries after the first attempt
+    pub max_retries: Option<u32>,
+    /// Backoff multiplier applied after each retry
+    pub factor: Option<f64>,
+}
+
+impl RetryPolicyConfig {
+    fn policy(cfg: &Option<Self>) -> RetryPolicy {
+        let mut policy = RetryPolicy::default();
+        let Some(cfg) = cfg else {
+            return policy;
+        };
+        if let Some(ms) = cfg.min_delay_ms {
+            policy = policy.with_min_delay(Duration::from_millis(ms));
+        }
+        if let Some(ms) = cfg.max_delay_ms {
+            policy = policy.with_max_delay(Duration::from_millis(ms));
+        }
+        if let Some(n) = cfg.max_retries {
+            policy = policy.with_max_retries(n);
+        }
+        if let Some(f) = cfg.factor {
+            policy = policy.with_factor(f);
+        }
+        policy
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum UnderpaymentPolicy {
+    /// Credit the amount received (or the excess, when overpaid) to the
+    /// user's account balance. An underpaid payment stays open.
+    #[default]
+    Credit,
+    /// Leave an underpaid payment open and ask admins to refund the amount
+    /// received (or the excess, when overpaid).
+    Refund,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum SuspendedBilling {
+    /// Suspended VMs are billed like running VMs
+    #[default]
+    Charged,
+    /// The time a VM spends suspended is added to its expiry when resumed
+    Paused,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PaymentExpiryConfig {
+    /// Lightning invoice expiry in seconds. Defaults to 600.
+    #[serde(default = "default_lightning_expiry")]
+    pub lightning: u64,
+    /// On-chain payment expiry in seconds. Defaults to 3600.
+    #[serde(default = "default_onchain_expiry")]
+    pub onchain: u64,
+    /// Fiat (Revolut, Stripe, PayPal) payment expiry in seconds. Defaults to 3600.
+    #[serde(default = "default_fiat_expiry")]
+    pub fiat: u64,
+}
+
+impl PaymentExpiryConfig {
+    /// Expiry for new payments made with `method`
+    pub fn expiry(&self, method: PaymentMethod) -> Duration {
+        Duration::from_secs(match method {
+            PaymentMethod::Lightning => self.lightning,
+            PaymentMethod::OnChain => self.onchain,
+            PaymentMethod::Revolut | PaymentMethod::Paypal | PaymentMethod::Stripe => self.fiat,
+        })
+    }
+}
+
+impl Default for PaymentExpiryConfig {
+    fn default() -> Self {
+        Self {
+            lightning: default_lightning_expiry(),
+            onchain: default_onchain_expiry(),
+            fiat: default_fiat_expiry(),
+        }
+    }
+}
+
+fn default_max_suspended_days() -> u64 {
+    30
+}
+
+fn default_lightning_expiry() -> u64 {
+    600
+}
+
+fn default_onchain_expiry() -> u64 {
+    3600
+}
+
+fn default_fiat_expiry() -> u64 {
+    3600
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ReachabilityConfig {
+    /// TCP port probed on the VM's primary IP. Defaults to 22.
+    #[serde(default = "default_reachability_port")]
+    pub port: u16,
+    /// Probe timeout in milliseconds. Defaults to 2000.
+    #[serde(default = "default_reachability_timeout_ms")]
+    pub timeout_ms: u64,
+    /// Consecutive failed probes before a previously-reachable VM is marked
+    /// unreachable and its owner notified. Defaults to 3.
+    #[serde(default = "default_reachability_failure_threshold")]
+    pub failure_threshold: u32,
+}
+
+impl Default for ReachabilityConfig {
+    fn default() -> Self {
+        Self {
+            port: default_reachability_port(),
+            timeout_ms: default_reachability_timeout_ms(),
+            failure_threshold: default_reachability_failure_threshold(),
+        }
+    }
+}
+
+fn default_reachability_port() -> u16 {
+    22
+}
+
+fn default_reachability_timeout_ms() -> u64 {
+    2000
+}
+
+fn default_reachability_failure_threshold() -> u32 {
+    3
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -362,6 +694,25 @@ fn default_mempool_url() -> String {
     "https://mempool.space".to_string()
 }
 
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct UnpaidVmsConfig {
+    /// Hours after creation before a never-paid VM is reclaimed. Defaults to 72.
+    #[serde(default = "default_unpaid_grace_hours")]
+    pub grace_hours: u64,
+    /// `stop` or `delete` the unpaid VMs. Defaults to `stop`.
+    #[serde(default = "default_unpaid_action")]
+    pub action: lnvps_api_common::UnpaidVmAction,
+}
+
+fn default_unpaid_grace_hours() -> u64 {
+    lnvps_api_common::DEFAULT_UNPAID_GRACE_HOURS
+}
+
+fn default_unpaid_action() -> lnvps_api_common::UnpaidVmAction {
+    lnvps_api_common::UnpaidVmAction::Stop
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct ReferralConfig {
@@ -524,6 +875,8 @@ pub struct ProxmoxConfig {
     pub ssh: Option<SshConfig>,
     /// MAC address prefix for NIC (eg. bc:24:11)
     pub mac_prefix: Option<String>,
+    /// Storage where VM backups are written, backups are disabled when unset
+    pub backup_storage: Option<String>,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -567,9 +920,17 @@ pub struct QemuConfig {
     pub balloon_min_pct: Option<u8>,
     /// Firewall configuration options
     pub firewall_config: Option<FirewallConfig>,
+    /// Seconds to wait for a guest to shut down on reboot before falling
+    /// back to a hard reset (default 120)
+    pub reboot_timeout: Option<u64>,
 }
 
 impl QemuConfig {
+    /// Seconds a graceful reboot may take before the VM is reset instead
+    pub fn reboot_timeout(&self) -> u64 {
+        self.reboot_timeout.unwrap_or(120)
+    }
+
     /// Compute the Proxmox `balloon` value (in MB) for a VM with the given
     /// sold memory, honouring [`QemuConfig::balloon_min_pct`].
     ///
@@ -627,13 +988,22 @@ pub fn default_max_prepay_days() -> u16 {
     365
 }
 
+pub fn default_host_discovery_max_change() -> u8 {
+    50
+}
+
 #[cfg(test)]
 pub fn mock_settings() -> Settings {
     Settings {
         listen: None,
+        metrics_listen: None,
         db: "".to_string(),
+        db_replica: None,
         encryption: None,
         public_url: "http://localhost:8000".to_string(),
+        base_path: None,
+        trusted_proxies: None,
+        cors: Default::default(),
         read_only: false,
         provisioner: ProvisionerConfig {
             proxmox: Some(ProxmoxConfig {
@@ -646,9 +1016,11 @@ pub fn mock_settings() -> Settings {
                     arch: "x86_64".to_string(),
                     balloon_min_pct: None,
                     firewall_config: None,
+                    reboot_timeout: None,
                 },
                 ssh: None,
                 mac_prefix: Some("ff:ff:ff".to_string()),
+                backup_storage: None,
             }),
             libvirt: None,
         },
@@ -664,12 +1036,26 @@ pub fn mock_settings() -> Settings {
         whatsapp: None,
         nostr_address_host: None,
         redis: None,
+        db_work_queue: false,
+        db_work_queue_lease_secs: None,
+        db_work_queue_max_attempts: None,
         captcha: None,
         geoip_database: None,
         referral: None,
+        unpaid_vms: None,
         oauth: None,
         webauthn: None,
         session: None,
+        reachability: None,
+        payment_expiry: Default::default(),
+        underpayment_policy: Default::default(),
+        suspended_billing: Default::default(),
+        max_suspended_days: default_max_suspended_days(),
+        retry: Default::default(),
+        job_concurrency: Default::default(),
+        job_intervals: Default::default(),
+        host_discovery_max_change: default_host_discovery_max_change(),
+        custom_vm_floors: Default::default(),
     }
 }
 
@@ -688,6 +1074,7 @@ mod tests {
             arch: "x86_64".to_string(),
             balloon_min_pct: pct,
             firewall_config: None,
+            reboot_timeout: None,
         }
     }
 
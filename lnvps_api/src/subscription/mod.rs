@@ -17,8 +17,8 @@ use anyhow::{Context, Result, bail, ensure};
 use async_trait::async_trait;
 use chrono::{Datelike, Utc};
 use lnvps_api_common::{
-    CostResult, ExchangeRateService, NewPaymentInfo, PricingEngine, UpgradeConfig, VatClient,
-    WorkCommander, round_msat_to_sat,
+    CostResult, ExchangeRateService, NewPaymentInfo, PricingEngine, TaxSummary, UpgradeConfig,
+    VatClient, WorkCommander, round_msat_to_sat,
 };
 use lnvps_db::{
     LNVpsDb, PaymentMethod, Subscription, SubscriptionLineItem, SubscriptionPayment,
@@ -38,10 +38,10 @@ mod ip_range;
 mod vm;
 
 use crate::provisioner::{IpRangeProvisioner, VmProvisioner};
-use crate::settings::Settings;
+use crate::settings::{PaymentExpiryConfig, Settings};
 pub use ip_range::IpRangeLineItemHandler;
 use lnvps_api_common::VmStateCache;
-pub use vm::VmLineItemHandler;
+pub use vm::{VmLineItemHandler, end_vm_suspension};
 
 // =========================================================================
 // Trait
@@ -74,6 +74,38 @@ pub struct CompletePaymentResult {
     pub expired_competing_upgrades: Vec<SubscriptionPayment>,
 }
 
+/// A priced subscription renewal, computed before any invoice or order exists.
+struct RenewalQuote {
+    subscription: Subscription,
+    user: User,
+    payment_type: SubscriptionPaymentType,
+    /// Net amount in `currency`
+    amount: u64,
+    currency: Currency,
+    rate: f32,
+    /// Seconds added to the subscription expiry
+    time_value: u64,
+    tax: u64,
+    processing_fee: u64,
+    tax_summary: TaxSummary,
+    tax_evidence: Option<serde_json::Value>,
+    tax_breakdown: Option<serde_json::Value>,
+}
+
+impl RenewalQuote {
+    /// Gross amount charged: net + tax + processing fee
+    fn total(&self) -> u64 {
+        self.amount + self.tax + self.processing_fee
+    }
+}
+
+/// Outcome of pricing a renewal
+enum RenewalCost {
+    /// An identical unpaid payment already exists
+    Existing(SubscriptionPayment),
+    New(RenewalQuote),
+}
+
 /// How a renewal/purchase payment is collected.
 #[derive(Debug, Clone)]
 pub enum RenewMode {
@@ -119,6 +151,8 @@ pub struct SubscriptionHandler {
     /// Global default cap (days) on how far in advance a subscription may be
     /// prepaid/renewed. Used when a company's own `max_prepay_days` is 0.
     max_prepay_days: u16,
+    /// Expiry of new payments per payment method
+    payment_expiry: PaymentExpiryConfig,
 }
 
 impl SubscriptionHandler {
@@ -135,9 +169,11 @@ impl SubscriptionHandler {
         vm_state_cache: VmStateCache,
     ) -> Result<Self> {
         let max_prepay_days = settings.max_prepay_days;
+        let payment_expiry = settings.payment_expiry.clone();
+        let custom_floors = settings.custom_vm_floors;
         Ok(Self {
             revolut,
-            pe: PricingEngine::new(db.clone(), rates, vat),
+            pe: PricingEngine::new(db.clone(), rates, vat, custom_floors),
             vm_provisioner: VmProvisioner::new(settings, db.clone()),
             ip_range_provisioner: IpRangeProvisioner::new(db.clone(), tx.clone()),
             db,
@@ -147,6 +183,7 @@ impl SubscriptionHandler {
             vm_state_cache,
             saved_payment_settle_timeout: SAVED_PAYMENT_SETTLE_TIMEOUT,
             max_prepay_days,
+            payment_expiry,
         })
     }
 
@@ -431,13 +468,14 @@ impl SubscriptionHandler {
     /// For [`RenewMode::Saved`] the saved payment method is collected on the spot
     /// (Revolut card charged, or NWC wallet pays the Lightning invoice) without
     /// customer interaction, then briefly polled for settlement.
-    async fn renew_subscription_inner(
+    /// Price a renewal/purchase of a subscription without creating any invoice
+    /// or order.
+    async fn quote_renewal(
         &self,
         subscription_id: u64,
         method: PaymentMethod,
         intervals: u32,
-        mode: RenewMode,
-    ) -> Result<SubscriptionPayment> {
+    ) -> Result<RenewalCost> {
         let intervals = intervals.max(1);
 
         // Get subscription and line items
@@ -493,7 +531,7 @@ impl SubscriptionHandler {
                     CostResult::New(p) => vm_payment_infos.push(p),
                     CostResult::Existing(p) => {
                         // An identical unpaid payment already exists — return it directly
-                        return Ok(p);
+                        return Ok(RenewalCost::Existing(p));
                     }
                 }
             } else {
@@ -633,7 +671,7 @@ impl SubscriptionHandler {
             .map(|p| p.currency)
             .unwrap_or(subscription_currency);
 
-        // Wrap the aggregated values so the invoice/order creation below can use them
+        // Wrap the aggregated values so the invoice/order creation can use them
         let converted_amount = total_amount;
         let converted_currency = payment_currency;
 
@@ -651,14 +689,69 @@ impl SubscriptionHandler {
             )
             .await?;
 
+        Ok(RenewalCost::New(RenewalQuote {
+            subscription,
+            user,
+            payment_type,
+            amount: converted_amount,
+            currency: converted_currency,
+            rate,
+            time_value,
+            tax,
+            processing_fee,
+            tax_summary,
+            tax_evidence,
+            tax_breakdown,
+        }))
+    }
+
+    async fn renew_subscription_inner(
+        &self,
+        subscription_id: u64,
+        method: PaymentMethod,
+        intervals: u32,
+        mode: RenewMode,
+    ) -> Result<SubscriptionPayment> {
+        match self
+            .quote_renewal(subscription_id, method, intervals)
+            .await?
+        {
+            RenewalCost::Existing(p) => Ok(p),
+            RenewalCost::New(quote) => self.create_renewal_payment(quote, method, mode).await,
+        }
+    }
+
+    /// Create the invoice / order for a priced renewal and save the payment.
+    async fn create_renewal_payment(
+        &self,
+        quote: RenewalQuote,
+        method: PaymentMethod,
+        mode: RenewMode,
+    ) -> Result<SubscriptionPayment> {
+        let RenewalQuote {
+            subscription,
+            user,
+            payment_type,
+            amount: converted_amount,
+            currency: converted_currency,
+            rate,
+            time_value,
+            tax,
+            processing_fee,
+            tax_summary,
+            tax_evidence,
+            tax_breakdown,
+        } = quote;
+        let subscription_id = subscription.id;
+
         // Generate payment based on method
+        let expiry = self.payment_expiry.expiry(method);
         let subscription_payment = match method {
             PaymentMethod::Lightning => {
                 ensure!(
                     converted_currency == Currency::BTC,
                     "Lightning payment must be in BTC"
                 );
-                const INVOICE_EXPIRE: u64 = 600;
                 // Round to nearest satoshi for wallet compatibility
                 let invoice_amount = round_msat_to_sat(converted_amount + tax);
                 let desc = match payment_type {
@@ -684,7 +777,7 @@ impl SubscriptionHandler {
                     .add_invoice(AddInvoiceRequest {
                         memo: Some(desc),
                         amount: invoice_amount,
-                        expire: Some(INVOICE_EXPIRE as u32),
+                        expire: Some(expiry.as_secs() as u32),
                     })
                     .await?;
 
@@ -693,7 +786,7 @@ impl SubscriptionHandler {
                     subscription_id,
                     user_id: subscription.user_id,
                     created: Utc::now(),
-                    expires: Utc::now().add(Duration::from_secs(INVOICE_EXPIRE)),
+                    expires: Utc::now().add(expiry),
                     amount: converted_amount,
                     currency: converted_currency.to_string(),
                     payment_method: method,
@@ -716,6 +809,7 @@ impl SubscriptionHandler {
                     tax_treatment: tax_summary.treatment.clone(),
                     tax_evidence: tax_evidence.clone(),
                     tax_breakdown: tax_breakdown.clone(),
+                    invoice_number: None,
                 }
             }
             PaymentMethod::Revolut => {
@@ -787,7 +881,7 @@ impl SubscriptionHandler {
                     subscription_id,
                     user_id: subscription.user_id,
                     created: Utc::now(),
-                    expires: Utc::now().add(Duration::from_secs(3600)),
+                    expires: Utc::now().add(expiry),
                     amount: converted_amount,
                     currency: converted_currency.to_string(),
                     payment_method: method,
@@ -810,6 +904,7 @@ impl SubscriptionHandler {
                     tax_treatment: tax_summary.treatment.clone(),
                     tax_evidence: tax_evidence.clone(),
                     tax_breakdown: tax_breakdown.clone(),
+                    invoice_number: None,
                 }
             }
             PaymentMethod::OnChain => {
@@ -817,9 +912,6 @@ impl SubscriptionHandler {
                     converted_currency == Currency::BTC,
                     "On-chain payment must be in BTC"
                 );
-                // On-chain payments need time for the tx to be broadcast and
-                // confirmed; hold the quoted rate for longer than Lightning.
-                const ONCHAIN_EXPIRE: u64 = 3600;
                 let invoice_amount = round_msat_to_sat(converted_amount + tax);
                 let desc = match payment_type {
                     SubscriptionPaymentType::Purchase => {
@@ -843,7 +935,7 @@ impl SubscriptionHandler {
                     subscription_id,
                     user_id: subscription.user_id,
                     created: Utc::now(),
-                    expires: Utc::now().add(Duration::from_secs(ONCHAIN_EXPIRE)),
+                    expires: Utc::now().add(expiry),
                     amount: converted_amount,
                     currency: converted_currency.to_string(),
                     payment_method: method,
@@ -868,6 +960,7 @@ impl SubscriptionHandler {
                     tax_treatment: tax_summary.treatment.clone(),
                     tax_evidence: tax_evidence.clone(),
                     tax_breakdown: tax_breakdown.clone(),
+                    invoice_number: None,
                 }
             }
             PaymentMethod::Paypal => bail!("PayPal not implemented"),
@@ -932,13 +1025,13 @@ impl SubscriptionHandler {
                 let tax_summary = lnvps_api_common::summarize_tax_lines(&tax_lines);
                 let tax_breakdown = serde_json::to_value(&tax_lines).ok();
                 let tax_evidence = Some(p.tax_details.evidence_json());
+                let expiry = self.payment_expiry.expiry(method);
                 let payment = match method {
                     PaymentMethod::Lightning => {
                         ensure!(
                             p.currency == Currency::BTC,
                             "Cannot create invoices for non-BTC currency"
                         );
-                        const INVOICE_EXPIRE: u64 = 600;
                         let total_amount = round_msat_to_sat(p.amount + p.tax);
                         info!(
                             "Creating invoice for vm {vm_id} for {} sats",
@@ -949,7 +1042,7 @@ impl SubscriptionHandler {
                             .add_invoice(AddInvoiceRequest {
                                 memo: Some(desc),
                                 amount: total_amount,
-                                expire: Some(INVOICE_EXPIRE as u32),
+                                expire: Some(expiry.as_secs() as u32),
                             })
                             .await?;
                         SubscriptionPayment {
@@ -957,7 +1050,7 @@ impl SubscriptionHandler {
                             subscription_id,
                             user_id: vm.user_id,
                             created: Utc::now(),
-                            expires: Utc::now().add(Duration::from_secs(INVOICE_EXPIRE)),
+                            expires: Utc::now().add(expiry),
                             amount: p.amount,
                             currency: p.currency.to_string(),
                             payment_method: method,
@@ -976,6 +1069,7 @@ impl SubscriptionHandler {
                             tax_treatment: tax_summary.treatment.clone(),
                             tax_evidence: tax_evidence.clone(),
                             tax_breakdown: tax_breakdown.clone(),
+                            invoice_number: None,
                         }
                     }
                     PaymentMethod::Revolut => {
@@ -1038,7 +1132,7 @@ impl SubscriptionHandler {
                             subscription_id,
                             user_id: vm.user_id,
                             created: Utc::now(),
-                            expires: Utc::now().add(Duration::from_secs(3600)),
+                            expires: Utc::now().add(expiry),
                             amount: p.amount,
                             currency: p.currency.to_string(),
                             payment_method: method,
@@ -1057,6 +1151,7 @@ impl SubscriptionHandler {
                             tax_treatment: tax_summary.treatment.clone(),
                             tax_evidence: tax_evidence.clone(),
                             tax_breakdown: tax_breakdown.clone(),
+                            invoice_number: None,
                         }
                     }
                     PaymentMethod::OnChain => {
@@ -1064,7 +1159,6 @@ impl SubscriptionHandler {
                             p.currency == Currency::BTC,
                             "Cannot create on-chain payments for non-BTC currency"
                         );
-                        const ONCHAIN_EXPIRE: u64 = 3600;
                         let total_amount = round_msat_to_sat(p.amount + p.tax);
                         info!(
                             "Creating on-chain address for vm {vm_id} for {} sats",
@@ -1079,7 +1173,7 @@ impl SubscriptionHandler {
                             subscription_id,
                             user_id: vm.user_id,
                             created: Utc::now(),
-                            expires: Utc::now().add(Duration::from_secs(ONCHAIN_EXPIRE)),
+                            expires: Utc::now().add(expiry),
                             amount: p.amount,
                             currency: p.currency.to_string(),
                             payment_method: method,
@@ -1100,6 +1194,7 @@ impl SubscriptionHandler {
                             tax_treatment: tax_summary.treatment.clone(),
                             tax_evidence: tax_evidence.clone(),
                             tax_breakdown: tax_breakdown.clone(),
+                            invoice_number: None,
                         }
                     }
                     PaymentMethod::Paypal => bail!("PayPal not implemented"),
@@ -1268,6 +1363,69 @@ impl SubscriptionHandler {
         Ok(payment)
     }
 
+    /// Attempt automatic renewal from the user's prepaid account credit.
+    ///
+    /// Credit is held in BTC, so the renewal is priced as a Lightning payment and
+    /// settled from credit if the balance covers its gross total. Returns
+    /// `Ok(None)` when the balance is insufficient, in which case no renewal
+    /// invoice is created (an already outstanding one is left unpaid). Once
+    /// credit settles the renewal its Lightning invoice is canceled so it can't
+    /// also be paid over Lightning.
+    pub async fn auto_renew_via_credit(&self, sub_id: u64) -> Result<Option<SubscriptionPayment>> {
+        let sub = self.db.get_subscription(sub_id).await?;
+        let balance = self
+            .db
+            .get_user_credit(sub.user_id, &Currency::BTC.to_string())
+            .await?;
+        if balance == 0 {
+            return Ok(None);
+        }
+        // Price the renewal first so no invoice is created unless the credit
+        // balance can settle it.
+        let payment = match self
+            .quote_renewal(sub_id, PaymentMethod::Lightning, 1)
+            .await?
+        {
+            RenewalCost::Existing(p) => p,
+            RenewalCost::New(quote) => {
+                if balance < quote.total() {
+                    debug!(
+                        "Insufficient credit to auto-renew sub {} ({} < {})",
+                        sub_id,
+                        balance,
+                        quote.total()
+                    );
+                    return Ok(None);
+                }
+                self.create_renewal_payment(quote, PaymentMethod::Lightning, RenewMode::default())
+                    .await?
+            }
+        };
+        if !self
+            .db
+            .pay_subscription_payment_with_credit(&payment)
+            .await?
+        {
+            debug!(
+                "Insufficient credit to auto-renew sub {} ({} < {})",
+                sub_id,
+                balance,
+                payment.amount + payment.tax + payment.processing_fee
+            );
+            return Ok(None);
+        }
+        if let Err(e) = self.node.cancel_invoice(&payment.id).await {
+            // the node expires the invoice by itself too, carry on
+            warn!(
+                "Failed to cancel invoice {} of credit-funded renewal: {}",
+                hex::encode(&payment.id),
+                e
+            );
+        }
+        info!("Auto-renewed sub {} from account credit", sub_id);
+        Ok(Some(self.db.get_subscription_payment(&payment.id).await?))
+    }
+
     /// Renew a VM using a specific amount
     pub async fn renew_amount(
         &self,
@@ -1572,7 +1730,8 @@ mod revolut_offline_tests {
 
     async fn setup(auto_renew: bool) -> (Arc<MockDb>, SubscriptionHandler, u64, u64) {
         let db = Arc::new(MockDb::default());
-        let node = Arc::new(MockNode::default());
+        // shared per test thread so tests can inspect the node's invoices
+        let node = Arc::new(MockNode::new());
         let user_id = db.upsert_user(&[9u8; 32]).await.unwrap();
         let (sub_id, _items) = db
             .insert_subscription_with_line_items(
@@ -1874,6 +2033,89 @@ mod revolut_offline_tests {
         assert_eq!(fiat.charged.lock().unwrap().len(), 1);
     }
 
+    #[tokio::test]
+    async fn test_auto_renew_via_credit_sufficient_balance() {
+        let (db, sub, user_id, sub_id) = setup(true).await;
+        db.add_user_credit(user_id, "BTC", 1_000_000_000_000)
+            .await
+            .unwrap();
+
+        let payment = sub
+            .auto_renew_via_credit(sub_id)
+            .await
+            .unwrap()
+            .expect("credit covers the renewal");
+        assert!(payment.is_paid);
+        assert_eq!(payment.payment_method, PaymentMethod::Lightning);
+        // The settled renewal's invoice can no longer be paid over Lightning
+        assert!(
+            !MockNode::new()
+                .invoices
+                .lock()
+                .await
+                .contains_key(&hex::encode(&payment.id))
+        );
+
+        let total = payment.amount + payment.tax + payment.processing_fee;
+        assert_eq!(
+            db.get_user_credit(user_id, "BTC").await.unwrap(),
+            1_000_000_000_000 - total
+        );
+        let renewed = db.get_subscription(sub_id).await.unwrap();
+        assert!(renewed.expires.unwrap() > Utc::now());
+
+        // Settling the same payment again must not debit twice
+        assert!(
+            !db.pay_subscription_payment_with_credit(&payment)
+                .await
+                .unwrap()
+        );
+        assert_eq!(
+            db.get_user_credit(user_id, "BTC").await.unwrap(),
+            1_000_000_000_000 - total
+        );
+    }
+
+    #[tokio::test]
+    async fn test_auto_renew_via_credit_insufficient_balance() {
+        let (db, sub, user_id, sub_id) = setup(true).await;
+        db.add_user_credit(user_id, "BTC", 1).await.unwrap();
+
+        assert!(sub.auto_renew_via_credit(sub_id).await.unwrap().is_none());
+        assert_eq!(db.get_user_credit(user_id, "BTC").await.unwrap(), 1);
+        assert!(db.get_subscription(sub_id).await.unwrap().expires.is_none());
+        // No invoice is created when the balance can't cover the renewal
+        assert!(
+            db.list_subscription_payments(sub_id)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_auto_renewal_claimed_once_per_period() {
+        let (db, _sub, _user_id, sub_id) = setup(true).await;
+        let expires = Utc::now() + chrono::TimeDelta::hours(1);
+        assert!(
+            db.claim_subscription_auto_renewal(sub_id, expires)
+                .await
+                .unwrap()
+        );
+        assert!(
+            !db.claim_subscription_auto_renewal(sub_id, expires)
+                .await
+                .unwrap()
+        );
+        // The next period can be claimed again
+        let next = expires + chrono::TimeDelta::days(30);
+        assert!(
+            db.claim_subscription_auto_renewal(sub_id, next)
+                .await
+                .unwrap()
+        );
+    }
+
     #[tokio::test]
     async fn test_default_payment_method_prefers_default_flag() {
         // NWC is the default even though a revolut method also exists.
@@ -1983,6 +2225,7 @@ mod revolut_offline_tests {
             tax_treatment: None,
             tax_evidence: None,
             tax_breakdown: None,
+            invoice_number: None,
         }
     }
 
@@ -1,8 +1,9 @@
 use crate::provisioner::VmProvisioner;
+use crate::settings::SuspendedBilling;
 use crate::subscription::SubscriptionLineItemHandler;
 use anyhow::Result;
 use async_trait::async_trait;
-use chrono::Utc;
+use chrono::{TimeDelta, Utc};
 use lnvps_api_common::{
     UpgradeConfig, VmHistoryLogger, VmRunningState, VmRunningStates, VmStateCache, WorkCommander,
     WorkJob,
@@ -272,3 +273,34 @@ impl SubscriptionLineItemHandler for VmLineItemHandler {
         Ok(())
     }
 }
+
+/// End the owner suspension of a VM. When suspended time is not billed the
+/// subscription expiry is pushed back by the time spent suspended, at most
+/// `max_suspended`. Returns the time credited.
+pub async fn end_vm_suspension(
+    db: &Arc<dyn LNVpsDb>,
+    vm: &Vm,
+    billing: SuspendedBilling,
+    max_suspended: TimeDelta,
+) -> Result<Option<TimeDelta>> {
+    if vm.suspended_at.is_none() {
+        return Ok(None);
+    }
+    // taking the stored time clears it, so a suspension is only credited once
+    let Some(suspended_at) = db.take_vm_suspended_at(vm.id).await? else {
+        return Ok(None);
+    };
+    if billing != SuspendedBilling::Paused {
+        return Ok(None);
+    }
+    let mut sub = db
+        .get_subscription_by_line_item_id(vm.subscription_line_item_id)
+        .await?;
+    let Some(expires) = sub.expires else {
+        return Ok(None);
+    };
+    let suspended_for = (Utc::now() - suspended_at).min(max_suspended);
+    sub.expires = Some(expires + suspended_for);
+    db.update_subscription(&sub).await?;
+    Ok(Some(suspended_for))
+}
@@ -1,33 +1,49 @@
-use crate::host::{FullVmInfo, VmHostClient, get_host_client};
-use crate::notifications::{Notification, NotificationChannel, build_channels, send_email};
-use crate::provisioner::VmProvisioner;
-use crate::settings::{ProvisionerConfig, Settings, SmtpConfig, TelegramConfig, WhatsAppConfig};
+use crate::host::{
+    FullVmInfo, TimeSeries, TimeSeriesData, VmHostClient, VmHostInfo, can_migrate, get_host_client,
+};
+use crate::job_limiter::JobLimiter;
+use crate::metrics::ApiMetrics;
+use crate::nostr_domain::NostrDomainVerifier;
+use crate::notifications::{
+    DeliveryStatus, Notification, NotificationService, build_channels, send_email,
+};
+use crate::provisioner::{HotplugResize, VmProvisioner};
+use crate::reachability::{ReachabilityProbe, TcpReachabilityProbe};
+use crate::settings::{
+    JobConcurrencyConfig, ProvisionerConfig, ReachabilityConfig, Settings, SharedRetryConfig,
+    SmtpConfig, SuspendedBilling, TelegramConfig, WhatsAppConfig,
+};
 use crate::ssh_client::SshClient;
-use crate::subscription::SubscriptionHandler;
+use crate::subscription::{SubscriptionHandler, end_vm_suspension};
 use anyhow::{Context, Result, anyhow, bail};
 use chrono::{DateTime, Days, TimeDelta, Utc};
-use hickory_resolver::TokioResolver;
+use ipnetwork::IpNetwork;
 use lnvps_api_common::{
-    BlackholeWorkFeedback, ChannelWorkCommander, InMemoryKeyValueStore, JobFeedback, KeyValueStore,
-    NetworkProvisioner, RedisConfig, RedisKeyValueStore, RedisWorkCommander, RedisWorkFeedback,
-    UpgradeConfig, VmHistoryLogger, VmRunningState, VmStateCache, WorkCommander, WorkFeedback,
-    WorkJob, WorkJobMessage, op_fatal,
-    retry::{OpError, Pipeline, RetryPolicy},
+    BlackholeWorkFeedback, ChannelWorkCommander, FEATURE_AUTO_RENEWAL, FEATURE_BACKUPS,
+    FEATURE_VM_MIGRATION, FeatureFlags, HostCapacityService, InMemoryKeyValueStore, JobFeedback,
+    KeyValueStore, NetworkProvisioner, RedisConfig, RedisKeyValueStore, RedisWorkCommander,
+    RedisWorkFeedback, UnpaidVmAction, UpgradeConfig, VmHistoryLogger, VmRunningState,
+    VmRunningStates, VmStateCache, WorkCommander, WorkFeedback, WorkJob, WorkJobMessage,
+    bulk_message_body, op_fatal,
+    retry::{OpError, Pipeline, RetryPolicy, retry_async},
 };
 use lnvps_db::{
-    CpuArch, CpuFeature, CpuMfg, IntervalType, LNVpsDb, PaymentMethod, RouterTunnelTraffic,
-    Subscription, SubscriptionLineItem, SubscriptionPayment, SubscriptionType, Vm,
-    VmHistoryActionType, VmHost, VmHostKind, VmIpAssignment, VmOsImage,
+    AdminAuditLog, BulkMessageDelivery, CapacityReservation, CpuArch, CpuFeature, CpuMfg,
+    IntervalType, IpRangeAllocationMode, LNVpsDb, PaymentMethod, RouterTunnelTraffic, Subscription,
+    SubscriptionLineItem, SubscriptionPayment, SubscriptionType, Vm, VmBandwidthUsage,
+    VmHistoryActionType, VmHost, VmHostDisk, VmHostKind, VmIpAssignment, VmOsImage, VmStateKind,
+    VmUsageAlert, VmUsageMetric,
 };
 use log::{debug, error, info, warn};
 use nostr_sdk::Client;
 use payments_rs::currency::{Currency, CurrencyAmount};
-use serde::Deserialize;
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
 use std::ops::{Add, Sub};
 use std::path::Path;
 use std::str::FromStr;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 use std::time::Duration;
 use tokio::task::JoinHandle;
 
@@ -37,6 +53,15 @@ const HOST_INFO_BINARY_NAME_X86_64: &str = "lnvps-host-info";
 const HOST_INFO_BINARY_NAME_ARM64: &str = "lnvps-host-info-arm64";
 /// Remote path where the binary will be uploaded and executed on hosts
 const HOST_INFO_REMOTE_PATH: &str = "/tmp/lnvps-host-info";
+/// Max number of expired payments canceled per `CancelExpiredPayments` run
+const EXPIRED_PAYMENTS_BATCH: u64 = 500;
+/// How often a running live migration is checked
+const MIGRATION_POLL_INTERVAL: Duration = Duration::from_secs(10);
+/// How often the progress of a running live migration is published
+const MIGRATION_PROGRESS_INTERVAL: Duration = Duration::from_secs(60);
+/// Give up on a live migration which hasn't finished after this long,
+/// copying local disks takes much longer than other host tasks
+const MIGRATION_TIMEOUT: Duration = Duration::from_secs(4 * 3600);
 
 /// Get the path to the host-info binary for x86_64 (in same directory as current executable)
 fn get_host_info_path() -> Option<std::path::PathBuf> {
@@ -69,6 +94,70 @@ fn payment_blocks_unpaid_vm_deletion(p: &SubscriptionPayment, now: DateTime<Utc>
             || (p.payment_method == PaymentMethod::OnChain && p.external_id.is_some()))
 }
 
+/// Why a value read by host auto-discovery looks like a bad read rather than
+/// a real change, `None` when the change is plausible.
+///
+/// A value dropping to zero is always rejected, otherwise the value may move
+/// by at most `max_change_pct` percent. Nothing is checked against an unset
+/// (zero) current value.
+fn implausible_change(current: u64, discovered: u64, max_change_pct: u8) -> Option<String> {
+    if current == 0 || current == discovered {
+        return None;
+    }
+    if discovered == 0 {
+        return Some(format!("dropped from {} to 0", current));
+    }
+    let change_pct = current.abs_diff(discovered) as f64 * 100.0 / current as f64;
+    if change_pct > max_change_pct as f64 {
+        Some(format!(
+            "changed from {} to {} ({:.0}%, limit {}%)",
+            current, discovered, change_pct, max_change_pct
+        ))
+    } else {
+        None
+    }
+}
+
+/// Copy the CPU and memory read by auto-discovery onto `host`, returns if
+/// anything changed.
+///
+/// Implausible values (see [implausible_change]) are logged and the prior
+/// value kept, unless `force` is set.
+fn apply_discovered_resources(
+    host: &mut VmHost,
+    info: &VmHostInfo,
+    max_change_pct: u8,
+    force: bool,
+) -> bool {
+    let mut changed = false;
+    if host.cpu != info.cpu {
+        match implausible_change(host.cpu as u64, info.cpu as u64, max_change_pct) {
+            Some(reason) if !force => {
+                warn!("Ignoring discovered cpu of host {}: {}", host.name, reason)
+            }
+            _ => {
+                host.cpu = info.cpu;
+                changed = true;
+            }
+        }
+    }
+    if host.memory != info.memory {
+        match implausible_change(host.memory, info.memory, max_change_pct) {
+            Some(reason) if !force => {
+                warn!(
+                    "Ignoring discovered memory of host {}: {}",
+                    host.name, reason
+                )
+            }
+            _ => {
+                host.memory = info.memory;
+                changed = true;
+            }
+        }
+    }
+    changed
+}
+
 /// Extract hostname/IP from a URL or return the input if it's already a plain host
 /// e.g. "https://192.168.1.1:8006/" -> "192.168.1.1"
 ///      "192.168.1.1" -> "192.168.1.1"
@@ -108,16 +197,167 @@ struct HostInfoOutput {
 #[derive(Clone)]
 pub struct Worker {
     settings: WorkerSettings,
+    /// Settings applied without a restart, see [Self::reload_settings]
+    live: Arc<RwLock<LiveSettings>>,
     db: Arc<dyn LNVpsDb>,
     subscription_handler: SubscriptionHandler,
-    notification_channels: Vec<Arc<dyn NotificationChannel>>,
     vm_history_logger: VmHistoryLogger,
     vm_state_cache: VmStateCache,
     work_commander: Arc<dyn WorkCommander>,
     feedback: Arc<dyn WorkFeedback>,
     kv: Arc<dyn KeyValueStore>,
     http_client: reqwest::Client,
+    domain_verifier: NostrDomainVerifier,
     referral_payouts: crate::referral::ReferralPayoutHandler,
+    reachability_probe: Arc<dyn ReachabilityProbe>,
+    node: Arc<dyn payments_rs::lightning::LightningNode>,
+    job_limiter: JobLimiter,
+    /// Retry policies, shared with the provisioner so a reload applies to both
+    retry: SharedRetryConfig,
+    nostr: Option<Client>,
+    features: FeatureFlags,
+}
+
+/// Top level settings [Worker::reload_settings] applies, changing any other
+/// setting needs a restart
+const RELOADABLE_SETTINGS: [&str; 6] = [
+    "retry",
+    "job-intervals",
+    "job-concurrency",
+    "smtp",
+    "telegram",
+    "whatsapp",
+];
+
+/// Worker settings which can change while running, retry policies are in the
+/// [SharedRetryConfig] of the provisioner
+#[derive(Clone)]
+struct LiveSettings {
+    job_intervals: HashMap<String, u64>,
+    smtp: Option<SmtpConfig>,
+    notifications: NotificationService,
+}
+
+/// Names of the top level settings which differ between `a` and `b`
+fn changed_settings(a: &Settings, b: &Settings) -> Result<Vec<String>> {
+    let (serde_json::Value::Object(a), serde_json::Value::Object(b)) =
+        (serde_json::to_value(a)?, serde_json::to_value(b)?)
+    else {
+        bail!("Settings are not an object");
+    };
+    let mut changed: Vec<String> = a
+        .keys()
+        .chain(b.keys())
+        .filter(|k| a.get(*k) != b.get(*k))
+        .cloned()
+        .collect();
+    changed.sort();
+    changed.dedup();
+    Ok(changed)
+}
+
+/// Reachability of a single VM as tracked between `CheckVm` runs
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct VmReachabilityState {
+    /// Last reported reachability, `None` until the VM answers a probe once
+    reachable: Option<bool>,
+    /// Number of consecutive failed probes
+    failures: u32,
+}
+
+/// Average value of an alert's metric over its window, `None` when no samples
+/// fall inside the window. CPU and memory are returned as a percentage.
+///
+/// Disk usage is not part of the time series, it comes from
+/// [lnvps_api_common::GuestInfo::max_disk_usage] instead.
+fn usage_alert_value(alert: &VmUsageAlert, series: &[TimeSeriesData], now: u64) -> Option<f64> {
+    let since = now.saturating_sub(alert.window_seconds as u64);
+    let values: Vec<f64> = series
+        .iter()
+        .filter(|d| d.timestamp >= since)
+        .filter_map(|d| match alert.metric {
+            VmUsageMetric::Cpu => Some(d.cpu as f64 * 100.0),
+            VmUsageMetric::Memory if d.memory_size > 0 => {
+                Some(d.memory as f64 / d.memory_size as f64 * 100.0)
+            }
+            VmUsageMetric::Memory => Some(0.0),
+            VmUsageMetric::NetIn => Some(d.net_in as f64),
+            VmUsageMetric::NetOut => Some(d.net_out as f64),
+            VmUsageMetric::DiskRead => Some(d.disk_read as f64),
+            VmUsageMetric::DiskWrite => Some(d.disk_write as f64),
+            VmUsageMetric::DiskUsage => None,
+        })
+        .collect();
+    if values.is_empty() {
+        None
+    } else {
+        Some(values.iter().sum::<f64>() / values.len() as f64)
+    }
+}
+
+/// Transfer in `series` after the sample at `after`, per calendar month.
+///
+/// A sample holds the average rate (bytes/s) since the previous sample, so its
+/// transfer is the rate times the gap to the previous sample, less any part of
+/// the gap before `after` which was already counted. The oldest sample only
+/// marks where the series starts.
+fn bandwidth_usage(
+    vm_id: u64,
+    series: &[TimeSeriesData],
+    after: Option<DateTime<Utc>>,
+) -> Vec<VmBandwidthUsage> {
+    let mut samples: Vec<&TimeSeriesData> = series.iter().collect();
+    samples.sort_by_key(|d| d.timestamp);
+    let after = after.map(|t| t.timestamp() as u64).unwrap_or(0);
+
+    let mut ret: Vec<VmBandwidthUsage> = Vec::new();
+    for pair in samples.windows(2) {
+        let (prev, cur) = (pair[0], pair[1]);
+        if cur.timestamp <= after {
+            continue;
+        }
+        let Some(time) = DateTime::from_timestamp(cur.timestamp as i64, 0) else {
+            continue;
+        };
+        let month = VmBandwidthUsage::month_of(time);
+        if ret.last().is_none_or(|u| u.month != month) {
+            ret.push(VmBandwidthUsage {
+                vm_id,
+                month,
+                ..Default::default()
+            });
+        }
+        let usage = ret.last_mut().unwrap();
+        let secs = (cur.timestamp - prev.timestamp.max(after)) as f64;
+        usage.bytes_in += (cur.net_in as f64 * secs) as u64;
+        usage.bytes_out += (cur.net_out as f64 * secs) as u64;
+        usage.last_sample = time;
+    }
+    ret
+}
+
+/// Add the transfer in `series` after `after` to the VM's monthly totals,
+/// returns how many months the transfer was added to
+async fn record_bandwidth_usage(
+    db: &dyn LNVpsDb,
+    vm_id: u64,
+    series: &[TimeSeriesData],
+    after: Option<DateTime<Utc>>,
+) -> Result<usize> {
+    let mut counted_after = after.unwrap_or(DateTime::UNIX_EPOCH);
+    let mut added = 0;
+    for usage in bandwidth_usage(vm_id, series, after) {
+        if db.add_vm_bandwidth_usage(&usage, counted_after).await? {
+            added += 1;
+        } else {
+            debug!(
+                "[bandwidth] vm {} {} already counted by another worker",
+                vm_id, usage.month
+            );
+        }
+        counted_after = usage.last_sample;
+    }
+    Ok(added)
 }
 
 #[derive(Clone)]
@@ -142,6 +382,18 @@ pub struct WorkerSettings {
     pub referral_max_onchain_fee_per_vbyte: u64,
     /// Source of the on-chain fee-rate estimate for the cap above.
     pub referral_fee_estimator: crate::settings::FeeEstimatorConfig,
+    /// Reachability probe settings for regions with `reachability_check` enabled
+    pub reachability: ReachabilityConfig,
+    /// Concurrent jobs allowed per job type
+    pub job_concurrency: JobConcurrencyConfig,
+    /// Interval overrides in seconds for scheduled jobs, by job name
+    pub job_intervals: HashMap<String, u64>,
+    /// Largest change (percent) host auto-discovery may make to a resource
+    pub host_discovery_max_change: u8,
+    /// Whether suspended VMs are still charged for
+    pub suspended_billing: SuspendedBilling,
+    /// Longest suspension (days) that defers expiry or is credited
+    pub max_suspended_days: u64,
 }
 
 impl From<&Settings> for WorkerSettings {
@@ -169,12 +421,23 @@ impl From<&Settings> for WorkerSettings {
                 .as_ref()
                 .map(|r| r.fee_estimator.clone())
                 .unwrap_or_default(),
+            reachability: val.reachability.clone().unwrap_or_default(),
+            job_concurrency: val.job_concurrency.clone(),
+            job_intervals: val.job_intervals.clone(),
+            host_discovery_max_change: val.host_discovery_max_change,
+            suspended_billing: val.suspended_billing,
+            max_suspended_days: val.max_suspended_days,
         }
     }
 }
 
 impl Worker {
     const CHECK_VMS_SECONDS: u64 = 30;
+    /// Minimum time between two notifications from the same usage alert
+    const USAGE_ALERT_COOLDOWN_SECONDS: i64 = 6 * 60 * 60;
+    /// How long a VM last seen migrating is left alone when it can't be found
+    /// on its host, a migration which hasn't settled by then has failed
+    const MIGRATING_GRACE_SECONDS: u64 = 10 * 60;
 
     pub async fn new(
         db: Arc<dyn LNVpsDb>,
@@ -192,7 +455,7 @@ impl Worker {
             crate::fee_estimate::build_fee_estimator(&settings.referral_fee_estimator);
         let referral_payouts = crate::referral::ReferralPayoutHandler::new(
             db.clone(),
-            node,
+            node.clone(),
             work_commander.clone(),
             settings.referral_min_payout_sats,
             onchain,
@@ -216,23 +479,111 @@ impl Worker {
             .timeout(Duration::from_secs(10))
             .build()?;
 
-        let notification_channels = build_channels(&settings, nostr.as_ref(), &http_client);
+        let notifications =
+            NotificationService::new(build_channels(&settings, nostr.as_ref(), &http_client));
+        let domain_verifier = NostrDomainVerifier::new(settings.nostr_hostname.clone())?;
+        let reachability_probe = Arc::new(TcpReachabilityProbe::new(
+            settings.reachability.port,
+            Duration::from_millis(settings.reachability.timeout_ms),
+        ));
+
+        let job_limiter = JobLimiter::new(&settings.job_concurrency);
+        let retry = subscription_handler.vm_provisioner().retry_config();
+        let live = Arc::new(RwLock::new(LiveSettings {
+            job_intervals: settings.job_intervals.clone(),
+            smtp: settings.smtp.clone(),
+            notifications,
+        }));
 
         Ok(Self {
+            features: FeatureFlags::new(db.clone()),
             db,
             subscription_handler,
             vm_state_cache,
-            notification_channels,
+            live,
             kv,
             feedback,
             vm_history_logger,
             settings,
             work_commander,
             http_client,
+            domain_verifier,
             referral_payouts,
+            reachability_probe,
+            node,
+            job_limiter,
+            retry,
+            nostr,
         })
     }
 
+    fn live(&self) -> LiveSettings {
+        self.live.read().unwrap().clone()
+    }
+
+    /// Hot-apply the safe-to-change subset of `new`: retry policies, job
+    /// intervals, job concurrency limits and notification channels.
+    ///
+    /// `applied` is the settings currently in effect, only what changed since
+    /// then is applied and copied into it. Changes to any other setting (e.g.
+    /// `db`) are logged and ignored until a restart, their names are returned.
+    pub fn reload_settings(&self, applied: &mut Settings, new: &Settings) -> Result<Vec<String>> {
+        let changed = changed_settings(applied, new)?;
+        let ignored: Vec<String> = changed
+            .iter()
+            .filter(|k| !RELOADABLE_SETTINGS.contains(&k.as_str()))
+            .cloned()
+            .collect();
+        for k in &ignored {
+            warn!("Setting '{}' changed, it is applied on restart only", k);
+        }
+        let is_changed = |k: &str| changed.iter().any(|c| c == k);
+
+        if is_changed("retry") {
+            self.retry.set(new.retry.clone());
+            applied.retry = new.retry.clone();
+        }
+        if is_changed("job-concurrency") {
+            self.job_limiter.reconfigure(&new.job_concurrency);
+            applied.job_concurrency = new.job_concurrency.clone();
+        }
+        if is_changed("job-intervals") {
+            self.live.write().unwrap().job_intervals = new.job_intervals.clone();
+            applied.job_intervals = new.job_intervals.clone();
+        }
+        if is_changed("smtp") || is_changed("telegram") || is_changed("whatsapp") {
+            let settings = WorkerSettings::from(new);
+            let notifications = NotificationService::new(build_channels(
+                &settings,
+                self.nostr.as_ref(),
+                &self.http_client,
+            ));
+            let mut live = self.live.write().unwrap();
+            live.smtp = settings.smtp;
+            live.notifications = notifications;
+            applied.smtp = new.smtp.clone();
+            applied.telegram = new.telegram.clone();
+            applied.whatsapp = new.whatsapp.clone();
+        }
+        info!(
+            "Reloaded settings, {} change(s) applied",
+            changed.len() - ignored.len()
+        );
+        Ok(ignored)
+    }
+
+    /// Interval between runs of a scheduled job, `default` unless overridden
+    /// in the `job-intervals` setting
+    pub fn job_interval(&self, job: &WorkJob, default: Duration) -> Duration {
+        self.live
+            .read()
+            .unwrap()
+            .job_intervals
+            .get(&job.to_string())
+            .map(|s| Duration::from_secs(*s))
+            .unwrap_or(default)
+    }
+
     pub fn commander(&self) -> Arc<dyn WorkCommander> {
         self.work_commander.clone()
     }
@@ -284,7 +635,8 @@ impl Worker {
 
     /// Handle subscription lifecycle state by dispatching to per-line-item handlers.
     /// 1. Expiring soon: attempt NWC auto-renewal; notify user; call on_expiring_soon per line item
-    /// 2. Expired: call on_expired per line item
+    /// 2. Expired: call on_expired per line item (stops VMs); remind the user one day
+    ///    before the grace period ends
     /// 3. Grace period exceeded: notify user; call on_grace_period_exceeded per line item
     async fn handle_subscription_state(
         &self,
@@ -292,11 +644,21 @@ impl Worker {
         last_check: DateTime<Utc>,
     ) -> Result<()> {
         const BEFORE_EXPIRE_NOTIFICATION_DAYS: u64 = 1;
+        const BEFORE_DELETE_NOTIFICATION_DAYS: u64 = 1;
         let Some(expires) = sub.expires else {
             return Ok(());
         };
 
         let line_items = self.db.list_subscription_line_items(sub.id).await?;
+        if self.has_paused_vm(&line_items).await? {
+            // the subscription doesn't run down while its VM is suspended, the
+            // expiry is pushed back when the VM is resumed
+            debug!(
+                "Subscription {} has a suspended VM, skipping expiry",
+                sub.id
+            );
+            return Ok(());
+        }
         let sub_notification_subject = self.sub_notification_subject(sub, &line_items).await;
         let sub_notification_descr = Self::sub_notification_message(sub, &line_items);
 
@@ -312,48 +674,44 @@ impl Worker {
             && expires < expiry_window
             && expires > last_check.add(Days::new(BEFORE_EXPIRE_NOTIFICATION_DAYS))
         {
-            // Attempt auto-renewal using the user's default saved payment method
-            // (NWC Lightning wallet or Revolut card), dispatched by provider.
+            // Attempt auto-renewal from account credit, then the user's default
+            // saved payment method (NWC Lightning wallet or Revolut card). Each
+            // billing period is claimed in the DB first, so a re-run of this window
+            // (stale `last_check`, restart, concurrent worker) can never charge twice.
             let mut auto_renewed = false;
 
-            if sub.auto_renewal_enabled {
-                let has_method = self
+            if sub.auto_renewal_enabled && self.auto_renewal_available(&line_items).await {
+                match self
                     .db
-                    .list_user_payment_methods(sub.user_id, None)
+                    .claim_subscription_auto_renewal(sub.id, expires)
                     .await
-                    .map(|m| m.iter().any(|pm| pm.enabled))
-                    .unwrap_or(false);
-                if has_method {
-                    info!("Attempting auto-renewal for subscription {}", sub.id);
-                    match self.subscription_handler.auto_renew(sub.id).await {
-                        Ok(_) => {
-                            info!("Successfully auto-renewed subscription {}", sub.id);
-                            self.queue_notification(
-                                sub.user_id,
-                                format!("Your subscription is being automatically renewed using your saved payment method.\n{}", sub_notification_descr),
-                                Some(format!("[{}] Auto-Renewed", sub_notification_subject)),
-                            ).await;
-                            auto_renewed = true;
-                        }
-                        Err(e) => {
-                            warn!("Auto-renewal error for subscription {}: {}", sub.id, e);
-                            self.queue_notification(
-                                sub.user_id,
-                                format!(
-                                    "Your subscription will expire soon.\nAutomatic renewal failed: '{}'\nPlease renew manually in the next {} day(s).\n{}",
-                                    e, BEFORE_EXPIRE_NOTIFICATION_DAYS, sub_notification_descr
-                                ),
-                                Some(format!("[{}] Expiring Soon", sub_notification_subject)),
-                            )
-                            .await;
-                            auto_renewed = true;
-                        }
+                {
+                    Ok(true) => {
+                        self.auto_renew_subscription(
+                            sub,
+                            &sub_notification_subject,
+                            &sub_notification_descr,
+                            BEFORE_EXPIRE_NOTIFICATION_DAYS,
+                        )
+                        .await;
+                        auto_renewed = true;
+                    }
+                    Ok(false) => {
+                        info!(
+                            "Auto-renewal for subscription {} already attempted for this period",
+                            sub.id
+                        );
+                        auto_renewed = true;
                     }
+                    Err(e) => warn!(
+                        "Failed to claim auto-renewal for subscription {}: {}",
+                        sub.id, e
+                    ),
                 }
             }
 
             // Send a plain expiry warning whenever auto-renewal was not attempted
-            // (auto_renewal off, or no saved payment method).
+            // (auto_renewal off, or the period could not be claimed).
             if !auto_renewed {
                 self.queue_notification(
                     sub.user_id,
@@ -425,6 +783,29 @@ impl Worker {
                     }
                 }
             }
+
+            // Final reminder one day before the grace period ends and the
+            // resources are deleted. Edge-triggered on `remind_at` crossing into
+            // (last_check, now] so it fires once. Skipped for 1-day grace windows
+            // where it would coincide with the "Expired" notification above.
+            let grace_days = self.grace_period_days(sub) as u64;
+            if grace_days > BEFORE_DELETE_NOTIFICATION_DAYS {
+                let delete_at = expires.add(Days::new(grace_days));
+                let remind_at = delete_at.sub(Days::new(BEFORE_DELETE_NOTIFICATION_DAYS));
+                if remind_at <= now && remind_at > last_check {
+                    self.queue_notification(
+                        sub.user_id,
+                        format!(
+                            "Your subscription expired on {} and will be permanently deleted on {} unless renewed.\n{}",
+                            expires.format("%Y-%m-%d %H:%M UTC"),
+                            delete_at.format("%Y-%m-%d %H:%M UTC"),
+                            sub_notification_descr
+                        ),
+                        Some(format!("[{}] Deletion Pending", sub_notification_subject)),
+                    )
+                    .await;
+                }
+            }
         }
 
         Ok(())
@@ -452,6 +833,81 @@ impl Worker {
         grace_period_days_for_sub(sub, Utc::now(), self.settings.delete_after)
     }
 
+    /// Auto-renew `sub`, preferring account credit and falling back to the
+    /// user's default saved payment method. Always notifies the user of the
+    /// outcome, including when neither source can pay.
+    async fn auto_renew_subscription(
+        &self,
+        sub: &Subscription,
+        subject: &str,
+        descr: &str,
+        days_left: u64,
+    ) {
+        match self
+            .subscription_handler
+            .auto_renew_via_credit(sub.id)
+            .await
+        {
+            Ok(Some(_)) => {
+                info!("Auto-renewed subscription {} from account credit", sub.id);
+                self.queue_notification(
+                    sub.user_id,
+                    format!(
+                        "Your subscription has been automatically renewed using your account credit.\n{}",
+                        descr
+                    ),
+                    Some(format!("[{}] Auto-Renewed", subject)),
+                )
+                .await;
+                return;
+            }
+            Ok(None) => debug!(
+                "Insufficient account credit to renew subscription {}",
+                sub.id
+            ),
+            Err(e) => warn!(
+                "Credit auto-renewal error for subscription {}: {}",
+                sub.id, e
+            ),
+        }
+
+        let has_method = self
+            .db
+            .list_user_payment_methods(sub.user_id, None)
+            .await
+            .map(|m| m.iter().any(|pm| pm.enabled))
+            .unwrap_or(false);
+        let error = if has_method {
+            info!("Attempting auto-renewal for subscription {}", sub.id);
+            match self.subscription_handler.auto_renew(sub.id).await {
+                Ok(_) => {
+                    info!("Successfully auto-renewed subscription {}", sub.id);
+                    self.queue_notification(
+                        sub.user_id,
+                        format!("Your subscription is being automatically renewed using your saved payment method.\n{}", descr),
+                        Some(format!("[{}] Auto-Renewed", subject)),
+                    ).await;
+                    return;
+                }
+                Err(e) => {
+                    warn!("Auto-renewal error for subscription {}: {}", sub.id, e);
+                    e.to_string()
+                }
+            }
+        } else {
+            "insufficient account credit and no saved payment method".to_string()
+        };
+        self.queue_notification(
+            sub.user_id,
+            format!(
+                "Your subscription will expire soon.\nAutomatic renewal failed: '{}'\nPlease renew manually in the next {} day(s).\n{}",
+                error, days_left, descr
+            ),
+            Some(format!("[{}] Expiring Soon", subject)),
+        )
+        .await;
+    }
+
     /// Whether the one-shot "expired" handling for `sub` has already run.
     ///
     /// VPS line items are authoritative: a VM-history `Expired` entry recorded at
@@ -494,6 +950,34 @@ impl Worker {
         }
     }
 
+    /// Longest suspension which defers expiry or is credited to it
+    fn max_suspended(&self) -> TimeDelta {
+        TimeDelta::days(self.settings.max_suspended_days as i64)
+    }
+
+    /// Whether any of the line items is a VM suspended by its owner, for less
+    /// than [Self::max_suspended], while suspended time is not billed
+    async fn has_paused_vm(&self, line_items: &[SubscriptionLineItem]) -> Result<bool> {
+        if self.settings.suspended_billing != SuspendedBilling::Paused {
+            return Ok(false);
+        }
+        let cutoff = Utc::now() - self.max_suspended();
+        for li in line_items
+            .iter()
+            .filter(|li| li.subscription_type == SubscriptionType::Vps)
+        {
+            match self.db.get_vm_by_line_item(li.id).await {
+                Ok(vm) if !vm.deleted && vm.suspended_at.is_some_and(|t| t > cutoff) => {
+                    return Ok(true);
+                }
+                Ok(_) => {}
+                Err(e) if e.is_row_not_found() => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(false)
+    }
+
     /// Get the subscription notification subject line
     async fn sub_notification_subject(
         &self,
@@ -824,13 +1308,51 @@ impl Worker {
         Ok(())
     }
 
-    async fn handle_vm_state(&self, state: Result<VmRunningState>, vm: &Vm) -> Result<()> {
+    /// Store the state read from the VM's host, or re-create the VM when it
+    /// is missing. Returns true when the stored state is unchanged, the caller
+    /// then only refreshes its `last_seen` (batched with
+    /// [LNVpsDb::touch_vm_states]).
+    async fn handle_vm_state(&self, state: Result<VmRunningState>, vm: &Vm) -> Result<bool> {
         match state {
             Ok(s) => {
+                // only write the stored state when it changes, the cache gets every sample
+                let kind = VmStateKind::from(&s.state);
+                let prev = self.vm_state_cache.get_state(vm.id).await;
+                let unchanged = prev.map(|p| VmStateKind::from(&p.state)) == Some(kind);
+                if !unchanged && let Err(e) = self.db.upsert_vm_state(vm.id, kind).await {
+                    warn!("Failed to save VM{} state: {}", vm.id, e);
+                }
+                // a suspended VM started or stopped some other way than by its
+                // owner (admin, host side, re-spawn) ends its suspension too
+                if matches!(s.state, VmRunningStates::Running | VmRunningStates::Stopped) {
+                    match end_vm_suspension(
+                        &self.db,
+                        vm,
+                        self.settings.suspended_billing,
+                        self.max_suspended(),
+                    )
+                    .await
+                    {
+                        Ok(Some(t)) => info!(
+                            "Extended VM {} expiry by {}s spent suspended",
+                            vm.id,
+                            t.num_seconds()
+                        ),
+                        Ok(None) => {}
+                        Err(e) => warn!("Failed to end suspension of VM {}: {}", vm.id, e),
+                    }
+                }
                 self.vm_state_cache.set_state(vm.id, s).await?;
+                return Ok(unchanged);
             }
             Err(e) => {
                 warn!("Failed to get VM{} state: {}", vm.id, e);
+                // a migrating VM can drop off its host before it shows up on
+                // the new one, re-creating it here would leave two copies
+                if self.recently_migrating(vm.id).await {
+                    info!("VM{} is migrating, not re-creating it", vm.id);
+                    return Ok(false);
+                }
                 if !vm.deleted
                     && self
                         .vm_expires(vm)
@@ -842,7 +1364,17 @@ impl Worker {
                 }
             }
         }
-        Ok(())
+        Ok(false)
+    }
+
+    /// Whether the VM was seen migrating within [Self::MIGRATING_GRACE_SECONDS],
+    /// an older cached migrating state is stale and not trusted
+    async fn recently_migrating(&self, vm_id: u64) -> bool {
+        let now = Utc::now().timestamp() as u64;
+        self.vm_state_cache.get_state(vm_id).await.is_some_and(|s| {
+            s.state == VmRunningStates::Migrating
+                && now.saturating_sub(s.timestamp) < Self::MIGRATING_GRACE_SECONDS
+        })
     }
 
     /// Resolve the authoritative expiry for a VM from its subscription.
@@ -884,95 +1416,992 @@ impl Worker {
     async fn check_vm(&self, vm: &Vm) -> Result<()> {
         debug!("Checking VM: {}", vm.id);
         let host = self.db.get_host(vm.host_id).await?;
+        let region = self.db.get_host_region(host.region_id).await?;
         let client = get_host_client(&host, &self.settings.provisioner_config)?;
-        self.handle_vm_state(
-            client
-                .get_vm_state(vm)
-                .await
-                .map_err(|e| anyhow!("VM state error {e}")),
-            &vm,
-        )
-        .await?;
-        self.reconcile_vm_dns(vm).await;
+        let state = client
+            .get_vm_state(vm)
+            .await
+            .map_err(|e| anyhow!("VM state error {e}"));
+        let running = matches!(&state, Ok(s) if s.state == VmRunningStates::Running);
+        let migrating = matches!(&state, Ok(s) if s.state == VmRunningStates::Migrating);
+        if self.handle_vm_state(state, &vm).await?
+            && let Err(e) = self.db.touch_vm_states(&[vm.id]).await
+        {
+            warn!("Failed to refresh VM{} state: {}", vm.id, e);
+        }
+        if running && region.reachability_check {
+            self.check_vm_reachability(vm).await;
+        }
+        // leave a migrating VM alone until it has settled on its new host
+        if !migrating {
+            self.reconcile_vm_dns(vm).await;
+        }
         Ok(())
     }
 
-    /// Best-effort reconciliation of missing DNS records for a VM's IPs.
+    /// Best-effort network reachability probe of a running VM's primary IP.
     ///
-    /// DNS is best-effort during spawn (a failed forward/reverse record must not
-    /// block or tear down a deploy — notably OVH rejects a PTR until the forward
-    /// name resolves). VMs whose records failed to create then self-heal here on
-    /// the periodic VM check. For healthy VMs (all refs present) this is a cheap
-    /// no-op that makes no provider API calls.
-    async fn reconcile_vm_dns(&self, vm: &Vm) {
-        let provisioner = self.subscription_handler.vm_provisioner();
-        let network = &provisioner.network;
-
-        let mut ips = match self.db.list_vm_ip_assignments(vm.id).await {
+    /// Only changes in reachability are written to the VM history. The owner is
+    /// notified once a VM which answered before fails `failure_threshold`
+    /// consecutive probes, and again when it recovers.
+    async fn check_vm_reachability(&self, vm: &Vm) {
+        let ips = match self.db.list_vm_ip_assignments(vm.id).await {
             Ok(i) => i,
             Err(e) => {
-                warn!("[dns-reconcile] failed to list ips for vm {}: {}", vm.id, e);
+                warn!("[reachability] failed to list ips for vm {}: {}", vm.id, e);
                 return;
             }
         };
-        for a in &mut ips {
-            let range = match self.db.get_ip_range(a.ip_range_id).await {
-                Ok(r) => r,
-                Err(_) => continue,
-            };
-            let want_fwd = range.forward_dns_server_id.is_some() && a.dns_forward_ref.is_none();
-            let want_rev = range.reverse_dns_server_id.is_some() && a.dns_reverse_ref.is_none();
-            if !want_fwd && !want_rev {
-                continue;
+        // Prefer the first IPv4 address, fall back to IPv6
+        let mut addrs: Vec<IpAddr> = ips.iter().filter_map(|i| i.ip.parse().ok()).collect();
+        addrs.sort_by_key(|a| !a.is_ipv4());
+        let Some(ip) = addrs.first().copied() else {
+            return;
+        };
+
+        let key = format!("worker-vm-reachability-{}", vm.id);
+        let mut state: VmReachabilityState = match self.kv.get(&key).await {
+            Ok(Some(v)) => serde_json::from_slice(&v).unwrap_or_default(),
+            Ok(None) => VmReachabilityState::default(),
+            Err(e) => {
+                warn!(
+                    "[reachability] failed to load state for vm {}: {}",
+                    vm.id, e
+                );
+                return;
             }
+        };
 
-            let mut changed = false;
-            if want_fwd {
-                match network.update_forward_ip_dns(a).await {
-                    Ok(_) => changed = true,
-                    Err(e) => warn!("[dns-reconcile] forward failed for {}: {}", a.ip, e),
+        let threshold = self.settings.reachability.failure_threshold.max(1);
+        if self.reachability_probe.is_reachable(ip).await {
+            if state.reachable != Some(true) {
+                if let Err(e) = self
+                    .vm_history_logger
+                    .log_vm_reachability_changed(vm.id, &ip.to_string(), true, state.failures)
+                    .await
+                {
+                    warn!("Failed to log VM {} reachability: {}", vm.id, e);
+                }
+                if state.reachable == Some(false) {
+                    self.queue_notification(
+                        vm.user_id,
+                        format!("Your VM #{} is reachable again on {}.", vm.id, ip),
+                        Some(format!("[VM{}] Reachable", vm.id)),
+                    )
+                    .await;
                 }
             }
-            if want_rev {
-                match network.update_reverse_ip_dns(a).await {
-                    Ok(_) => changed = true,
-                    Err(e) => warn!("[dns-reconcile] reverse failed for {}: {}", a.ip, e),
+            state.reachable = Some(true);
+            state.failures = 0;
+        } else {
+            state.failures += 1;
+            debug!(
+                "VM {} failed reachability probe on {} ({} consecutive)",
+                vm.id, ip, state.failures
+            );
+            if state.reachable == Some(true) && state.failures >= threshold {
+                if let Err(e) = self
+                    .vm_history_logger
+                    .log_vm_reachability_changed(vm.id, &ip.to_string(), false, state.failures)
+                    .await
+                {
+                    warn!("Failed to log VM {} reachability: {}", vm.id, e);
                 }
+                self.queue_notification(
+                    vm.user_id,
+                    format!(
+                        "Your VM #{} is running but has not been reachable on {} for the last {} checks.\n\nPlease check the network configuration inside your VM.",
+                        vm.id, ip, state.failures
+                    ),
+                    Some(format!("[VM{}] Unreachable", vm.id)),
+                )
+                .await;
+                state.reachable = Some(false);
             }
-            if changed && let Err(e) = self.db.update_vm_ip_assignment(a).await {
-                warn!(
-                    "[dns-reconcile] failed to persist dns refs for {}: {}",
-                    a.ip, e
-                );
+        }
+
+        match serde_json::to_vec(&state) {
+            Ok(v) => {
+                if let Err(e) = self.kv.store(&key, &v).await {
+                    warn!(
+                        "[reachability] failed to store state for vm {}: {}",
+                        vm.id, e
+                    );
+                }
             }
+            Err(e) => warn!(
+                "[reachability] failed to encode state for vm {}: {}",
+                vm.id, e
+            ),
         }
     }
 
-    /// Check multiple VMs on a single host using bulk API
-    async fn check_vms_on_host(&self, host_id: u64, vms: &[&Vm]) -> Result<()> {
-        debug!("Checking {} VMs on host {}", vms.len(), host_id);
-        let host = self.db.get_host(host_id).await?;
-        let client = get_host_client(&host, &self.settings.provisioner_config)?;
-
-        let states = client.get_all_vm_states().await?;
-        let state_map: HashMap<u64, VmRunningState> = states.into_iter().collect();
-
-        for vm in vms {
-            self.handle_vm_state(
-                state_map
-                    .get(&vm.id)
-                    .map(|s| s.clone())
-                    .context("VM not found in bulk response"),
-                &vm,
-            )
-            .await?;
-            // Self-heal any DNS records that failed to create during spawn.
-            self.reconcile_vm_dns(vm).await;
+    /// Evaluate user usage alerts against the last hour of VM time-series data,
+    /// notifying the owner once per cooldown while an alert is breached
+    async fn check_usage_alerts(&self) -> Result<()> {
+        let mut by_vm: HashMap<u64, Vec<VmUsageAlert>> = HashMap::new();
+        for alert in self.db.list_active_vm_usage_alerts().await? {
+            by_vm.entry(alert.vm_id).or_default().push(alert);
+        }
+        let now = Utc::now();
+        for (vm_id, alerts) in by_vm {
+            if let Err(e) = self.check_vm_usage_alerts(vm_id, &alerts, now).await {
+                warn!("[usage-alerts] failed to check vm {}: {}", vm_id, e);
+            }
         }
         Ok(())
     }
 
-    /// Spawn a VM and send notifications
+    /// Cancel unpaid payments which have expired, see [Self::cancel_expired_payment]
+    async fn cancel_expired_payments(&self) -> Result<()> {
+        // the rest is picked up by the next run
+        let payments = self
+            .db
+            .list_expired_subscription_payments(EXPIRED_PAYMENTS_BATCH)
+            .await?;
+        for payment in &payments {
+            if let Err(e) = self.cancel_expired_payment(payment).await {
+                error!(
+                    "Failed to cancel expired payment {}: {}",
+                    hex::encode(&payment.id),
+                    e
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Release the capacity reserved for the VM an expired purchase payment
+    /// was for.
+    ///
+    /// Renewals and upgrades never hold a reservation, and the reservation is
+    /// kept while the subscription has another open payment (the user asked
+    /// for a new invoice). Only subscriptions for a single VM are handled, for
+    /// anything else the payment doesn't say which VM it was for and the
+    /// reservations are left to lapse on their own.
+    async fn release_purchase_reservation(&self, payment: &SubscriptionPayment) -> Result<()> {
+        if payment.payment_type != lnvps_db::SubscriptionPaymentType::Purchase {
+            return Ok(());
+        }
+        let now = Utc::now();
+        if self
+            .db
+            .list_subscription_payments(payment.subscription_id)
+            .await?
+            .iter()
+            .any(|p| p.id != payment.id && !p.is_paid && p.expires > now)
+        {
+            return Ok(());
+        }
+        let line_items: Vec<_> = self
+            .db
+            .list_subscription_line_items(payment.subscription_id)
+            .await?
+            .into_iter()
+            .filter(|i| i.subscription_type == SubscriptionType::Vps)
+            .collect();
+        let [item] = line_items.as_slice() else {
+            return Ok(());
+        };
+        match self.db.get_vm_by_line_item(item.id).await {
+            Ok(vm) => self.db.delete_vm_capacity_reservations(vm.id).await?,
+            Err(e) => warn!(
+                "No VM for line item {} of expired payment {}: {}",
+                item.id,
+                hex::encode(&payment.id),
+                e
+            ),
+        }
+        Ok(())
+    }
+
+    /// Release VM create request ids whose create never recorded a VM, so a
+    /// client retrying with the same id isn't told it's in progress forever
+    async fn expire_vm_create_requests(&self) -> Result<()> {
+        let before = Utc::now() - crate::provisioner::VM_CREATE_REQUEST_TIMEOUT;
+        let n = self.db.delete_stale_vm_create_requests(before).await?;
+        if n > 0 {
+            info!("Released {} stale VM create requests", n);
+        }
+        Ok(())
+    }
+
+    /// Cancel the invoice at the lightning node so it can no longer be paid,
+    /// mark the payment canceled and free the capacity held for the VM it
+    /// would have purchased, see [Self::release_purchase_reservation]
+    async fn cancel_expired_payment(&self, payment: &SubscriptionPayment) -> Result<()> {
+        if payment.payment_method == PaymentMethod::Lightning
+            && let Err(e) = retry_async(self.retry.lightning(), || async {
+                self.node
+                    .cancel_invoice(&payment.id)
+                    .await
+                    .map_err(OpError::Transient)
+            })
+            .await
+        {
+            // the node expires the invoice by itself too, carry on
+            warn!(
+                "Failed to cancel invoice {}: {}",
+                hex::encode(&payment.id),
+                e
+            );
+        }
+        self.db.cancel_subscription_payment(&payment.id).await?;
+        self.release_purchase_reservation(payment).await?;
+        info!(
+            "Canceled expired payment {} for subscription {}",
+            hex::encode(&payment.id),
+            payment.subscription_id
+        );
+        Ok(())
+    }
+
+    /// Stop or delete VM's which were never paid for, see
+    /// [lnvps_db::LNVpsDbBase::list_unpaid_vms]. VM's with a payment still in flight are
+    /// skipped. Returns the number of VM's reclaimed.
+    async fn reclaim_unpaid_vms(&self, grace: TimeDelta, action: UnpaidVmAction) -> Result<u64> {
+        let provisioner = self.subscription_handler.vm_provisioner();
+        let now = Utc::now();
+        let mut reclaimed = 0;
+        for vm in self.db.list_unpaid_vms(grace).await? {
+            if self
+                .db
+                .list_vm_subscription_payments(vm.id)
+                .await?
+                .iter()
+                .any(|p| payment_blocks_unpaid_vm_deletion(p, now))
+            {
+                info!(
+                    "VM {} has pending or detected unpaid payments, not reclaiming",
+                    vm.id
+                );
+                continue;
+            }
+            let res = match action {
+                UnpaidVmAction::Stop => provisioner.stop_vm(vm.id).await,
+                UnpaidVmAction::Delete => provisioner.delete_vm(vm.id, true).await,
+            };
+            match res {
+                Ok(()) => {
+                    info!("Reclaimed unpaid VM {} ({})", vm.id, action);
+                    reclaimed += 1;
+                }
+                Err(e) => error!("Failed to {} unpaid VM {}: {}", action, vm.id, e),
+            }
+        }
+        Ok(reclaimed)
+    }
+
+    /// Auto-renewal can be switched off with the `auto_renewal` feature flag,
+    /// globally or in the region of any of the subscription's VMs. Expiring
+    /// subscriptions then only get the expiry warning
+    async fn auto_renewal_available(&self, line_items: &[SubscriptionLineItem]) -> bool {
+        match self.auto_renewal_enabled_for(line_items).await {
+            Ok(v) => v,
+            Err(e) => {
+                warn!("Failed to check auto-renewal feature flag: {}", e);
+                true
+            }
+        }
+    }
+
+    async fn auto_renewal_enabled_for(&self, line_items: &[SubscriptionLineItem]) -> Result<bool> {
+        let mut regions = HashSet::new();
+        for li in line_items
+            .iter()
+            .filter(|li| li.subscription_type == SubscriptionType::Vps)
+        {
+            match self.db.get_vm_by_line_item(li.id).await {
+                Ok(vm) if !vm.deleted => {
+                    regions.insert(self.db.get_host(vm.host_id).await?.region_id);
+                }
+                Ok(_) => {}
+                Err(e) if e.is_row_not_found() => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        if regions.is_empty() {
+            return self
+                .features
+                .is_feature_enabled(FEATURE_AUTO_RENEWAL, None)
+                .await;
+        }
+        for region in regions {
+            if !self
+                .features
+                .is_feature_enabled(FEATURE_AUTO_RENEWAL, Some(region))
+                .await?
+            {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Put a host into maintenance and queue a job for each of its VMs which
+    /// migrates it to `target_host_id`, or stops it when the hosts can't
+    /// live-migrate
+    async fn drain_host(
+        &self,
+        host_id: u64,
+        target_host_id: Option<u64>,
+        batch_id: Option<&str>,
+        admin_user_id: Option<u64>,
+    ) -> Result<String> {
+        let mut host = self.db.get_host(host_id).await?;
+        if !host.maintenance {
+            host.maintenance = true;
+            self.db.update_host(&host).await?;
+        }
+        let target = match target_host_id {
+            Some(id) => {
+                let target = self.db.get_host(id).await?;
+                if target.maintenance || !target.enabled {
+                    bail!("Target host {} is not accepting VMs", target.name);
+                }
+                if !self
+                    .features
+                    .is_feature_enabled(FEATURE_VM_MIGRATION, Some(host.region_id))
+                    .await?
+                {
+                    warn!(
+                        "VM migration is disabled in the region of host {}, stopping VMs instead",
+                        host.name
+                    );
+                    None
+                } else if can_migrate(&host, &target) {
+                    Some(target)
+                } else {
+                    warn!(
+                        "Host {} can't live-migrate VMs to {}, stopping them instead",
+                        host.name, target.name
+                    );
+                    None
+                }
+            }
+            None => None,
+        };
+
+        let (mut migrating, mut stopping) = (0, 0);
+        for vm in self.db.list_vms_on_host(host.id).await? {
+            if vm.deleted {
+                continue;
+            }
+            let (job, message) = if let Some(target) = &target {
+                migrating += 1;
+                (
+                    WorkJob::MigrateVm {
+                        vm_id: vm.id,
+                        target_host_id: target.id,
+                        batch_id: batch_id.map(str::to_string),
+                        admin_user_id,
+                    },
+                    format!("VM {} queued to migrate to host {}", vm.id, target.name),
+                )
+            } else {
+                stopping += 1;
+                (
+                    WorkJob::StopVm {
+                        vm_id: vm.id,
+                        admin_user_id,
+                    },
+                    format!("VM {} queued to stop", vm.id),
+                )
+            };
+            let job_id = self.work_commander.send(job).await?;
+            self.publish_drain_progress(batch_id, vm.id, 0, message, Some(job_id))
+                .await;
+        }
+        Ok(format!(
+            "Draining host {}: {} VM(s) migrating, {} VM(s) stopping",
+            host.name, migrating, stopping
+        ))
+    }
+
+    /// Publish the progress of a VM on the feedback channel of a host drain
+    async fn publish_drain_progress(
+        &self,
+        batch_id: Option<&str>,
+        vm_id: u64,
+        percent: u8,
+        message: String,
+        job_id: Option<String>,
+    ) {
+        let Some(batch_id) = batch_id else {
+            return;
+        };
+        let mut metadata = HashMap::from([("vm_id".to_string(), vm_id.to_string())]);
+        if let Some(job_id) = job_id {
+            metadata.insert("job_id".to_string(), job_id);
+        }
+        let feedback = JobFeedback::create_job_progress_feedback(
+            batch_id.to_string(),
+            "DrainHost".to_string(),
+            percent,
+            Some(message),
+        )
+        .with_metadata(metadata);
+        if let Err(e) = self.feedback.publish(feedback).await {
+            warn!("Failed to publish drain progress of VM {}: {}", vm_id, e);
+        }
+    }
+
+    /// Pick the disk on `target` a VM is migrated onto, checking `target`
+    /// has room for the VM's template and a private bridge if it needs one,
+    /// and reserve the VM's resources on it for as long as a migration can run
+    async fn migration_target_disk(
+        &self,
+        cap: &HostCapacityService,
+        vm: &Vm,
+        host: &VmHost,
+        target: &VmHost,
+    ) -> Result<(VmHostDisk, CapacityReservation)> {
+        if !can_migrate(host, target) {
+            bail!(
+                "Host {} can't live-migrate VMs to {}",
+                host.name,
+                target.name
+            );
+        }
+        if target.maintenance || !target.enabled {
+            bail!("Target host {} is not accepting VMs", target.name);
+        }
+        let ttl = TimeDelta::from_std(MIGRATION_TIMEOUT)? + TimeDelta::hours(1);
+        let disk = if let Some(id) = vm.template_id {
+            let template = self.db.get_vm_template(id).await?;
+            cap.reserve_disk_for_vm(target, &template, vm.private_network, ttl)
+                .await?
+        } else if let Some(id) = vm.custom_template_id {
+            let template = self.db.get_custom_vm_template(id).await?;
+            cap.reserve_disk_for_vm(target, &template, vm.private_network, ttl)
+                .await?
+        } else {
+            bail!("VM {} has no template", vm.id);
+        };
+        disk.ok_or_else(|| {
+            anyhow!(
+                "Host {} has no room for VM {}{}",
+                target.name,
+                vm.id,
+                if vm.private_network {
+                    " on the private network"
+                } else {
+                    ""
+                }
+            )
+        })
+    }
+
+    /// Start a live migration and poll it until it finishes.
+    ///
+    /// Copying local disks can take hours, the job's claim is renewed while
+    /// it runs (see [Self::renew_claim]) so no other worker picks it up.
+    async fn run_migration(
+        &self,
+        client: &dyn VmHostClient,
+        vm: &Vm,
+        target: &VmHost,
+        target_disk: &VmHostDisk,
+        batch_id: Option<&str>,
+    ) -> Result<()> {
+        let task = client.start_migrate_vm(vm, target, target_disk).await?;
+        let started = std::time::Instant::now();
+        let mut last_progress = started;
+        loop {
+            tokio::time::sleep(MIGRATION_POLL_INTERVAL).await;
+            match client.migration_finished(&task).await {
+                Ok(true) => return Ok(()),
+                Ok(false) => {}
+                Err(OpError::Transient(e)) => {
+                    warn!("Failed to check migration of VM {}: {}", vm.id, e)
+                }
+                Err(OpError::Fatal(e)) => return Err(e),
+            }
+            if started.elapsed() > MIGRATION_TIMEOUT {
+                bail!(
+                    "Migration of VM {} timed out after {} minutes",
+                    vm.id,
+                    MIGRATION_TIMEOUT.as_secs() / 60
+                );
+            }
+            if last_progress.elapsed() >= MIGRATION_PROGRESS_INTERVAL {
+                last_progress = std::time::Instant::now();
+                self.publish_drain_progress(
+                    batch_id,
+                    vm.id,
+                    50,
+                    format!(
+                        "VM {} migrating to host {} for {} minute(s)",
+                        vm.id,
+                        target.name,
+                        started.elapsed().as_secs() / 60
+                    ),
+                    None,
+                )
+                .await;
+            }
+        }
+    }
+
+    /// Live-migrate a VM onto `target_host_id`, queueing a stop of the VM
+    /// when the target can't take it or the migration fails
+    async fn migrate_vm(
+        &self,
+        vm_id: u64,
+        target_host_id: u64,
+        batch_id: Option<&str>,
+        admin_user_id: Option<u64>,
+    ) -> Result<String> {
+        let vm = self.db.get_vm(vm_id).await?;
+        if vm.deleted {
+            bail!("Cannot migrate deleted VM {}", vm_id);
+        }
+        let host = self.db.get_host(vm.host_id).await?;
+        self.features
+            .require(FEATURE_VM_MIGRATION, Some(host.region_id))
+            .await?;
+        let target = self.db.get_host(target_host_id).await?;
+
+        let cap = HostCapacityService::new(self.db.clone());
+        let mut reservation = None;
+        let res = match self.migration_target_disk(&cap, &vm, &host, &target).await {
+            Ok((target_disk, r)) => {
+                reservation = Some(r);
+                self.publish_drain_progress(
+                    batch_id,
+                    vm.id,
+                    0,
+                    format!("VM {} migrating to host {}", vm.id, target.name),
+                    None,
+                )
+                .await;
+                match get_host_client(&host, &self.settings.provisioner_config) {
+                    Ok(client) => self
+                        .run_migration(client.as_ref(), &vm, &target, &target_disk, batch_id)
+                        .await
+                        .map(|_| target_disk),
+                    Err(e) => Err(e),
+                }
+            }
+            Err(e) => Err(e),
+        };
+        let moved = match &res {
+            Ok(target_disk) => self
+                .db
+                .update_vm_host(vm.id, target.id, target_disk.id)
+                .await
+                .map_err(anyhow::Error::from),
+            Err(_) => Ok(()),
+        };
+        // a migrated VM counts on the target itself from here on
+        if let Some(r) = reservation
+            && let Err(e) = cap.release_reservation(&r).await
+        {
+            warn!("Failed to release capacity reservation {}: {}", r.id, e);
+        }
+        moved?;
+        match res {
+            Ok(target_disk) => {
+                let new_vm = Vm {
+                    host_id: target.id,
+                    disk_id: target_disk.id,
+                    ..vm.clone()
+                };
+                if let Err(e) = self
+                    .vm_history_logger
+                    .log_vm_configuration_changed(
+                        vm.id,
+                        admin_user_id,
+                        &vm,
+                        &new_vm,
+                        Some(serde_json::json!({
+                            "admin_action": true,
+                            "reason": "migrated",
+                        })),
+                    )
+                    .await
+                {
+                    warn!("Failed to log VM {} migration: {}", vm.id, e);
+                }
+                let msg = format!(
+                    "VM {} migrated from host {} to {}",
+                    vm.id, host.name, target.name
+                );
+                self.publish_drain_progress(batch_id, vm.id, 100, msg.clone(), None)
+                    .await;
+                Ok(msg)
+            }
+            Err(e) => {
+                warn!(
+                    "Failed to migrate VM {} to host {}, stopping it: {}",
+                    vm.id, target.name, e
+                );
+                let job_id = self
+                    .work_commander
+                    .send(WorkJob::StopVm {
+                        vm_id: vm.id,
+                        admin_user_id,
+                    })
+                    .await?;
+                let msg = format!(
+                    "VM {} migration to host {} failed, stopping it: {}",
+                    vm.id, target.name, e
+                );
+                self.publish_drain_progress(batch_id, vm.id, 100, msg.clone(), Some(job_id))
+                    .await;
+                Ok(msg)
+            }
+        }
+    }
+
+    /// Recompute the VM's expiry from the payments made since it was created
+    /// and extend it when it is short of what was paid for.
+    ///
+    /// An expiry later than the payments add up to (e.g. an admin extension)
+    /// is left as is, this never takes time away from a customer.
+    async fn recalculate_vm_expiry(&self, vm_id: u64) -> Result<String> {
+        let vm = self.db.get_vm(vm_id).await?;
+        if vm.deleted {
+            bail!("VM {} is deleted", vm_id);
+        }
+        let mut sub = self
+            .db
+            .get_subscription_by_line_item_id(vm.subscription_line_item_id)
+            .await?;
+        let payments = self.db.list_paid_vm_subscription_payments(vm_id).await?;
+        let Some(paid_until) = paid_expiry(&payments) else {
+            return Ok(format!("VM {} has no paid payments", vm_id));
+        };
+
+        let old_expires = match sub.expires {
+            Some(expires) if expires >= paid_until => {
+                return Ok(format!(
+                    "VM {} expiry {} covers the payments ({})",
+                    vm_id, expires, paid_until
+                ));
+            }
+            e => e,
+        };
+        sub.expires = Some(paid_until);
+        if paid_until > Utc::now() {
+            sub.is_active = true;
+        }
+        self.db.update_subscription(&sub).await?;
+        warn!(
+            "Corrected VM {} expiry from {:?} to {} ({} paid payments)",
+            vm_id,
+            old_expires,
+            paid_until,
+            payments.len()
+        );
+        if let Err(e) = self
+            .vm_history_logger
+            .log_vm_renewed(
+                vm_id,
+                None,
+                old_expires.unwrap_or(vm.created),
+                paid_until,
+                None,
+                None,
+                Some(serde_json::json!({ "reason": "expiry_recalculated" })),
+            )
+            .await
+        {
+            warn!("Failed to log VM {} expiry correction: {}", vm_id, e);
+        }
+        Ok(format!(
+            "VM {} expiry corrected from {:?} to {}",
+            vm_id, old_expires, paid_until
+        ))
+    }
+
+    /// Run every enabled backup schedule that is due
+    async fn check_backups(&self) -> Result<()> {
+        let now = Utc::now();
+        // region of each host, backups can be switched off per region
+        let mut host_regions: HashMap<u64, u64> = HashMap::new();
+        for schedule in self.db.list_active_vm_backup_schedules().await? {
+            if !schedule.is_due(now) {
+                continue;
+            }
+            // one broken schedule must not hold up the others
+            let vm = match self.db.get_vm(schedule.vm_id).await {
+                Ok(vm) => vm,
+                Err(e) => {
+                    warn!("Skipping backup schedule of VM {}: {}", schedule.vm_id, e);
+                    continue;
+                }
+            };
+            let region_id = match host_regions.get(&vm.host_id) {
+                Some(r) => *r,
+                None => match self.db.get_host(vm.host_id).await {
+                    Ok(h) => {
+                        host_regions.insert(vm.host_id, h.region_id);
+                        h.region_id
+                    }
+                    Err(e) => {
+                        warn!("Skipping backup schedule of VM {}: {}", schedule.vm_id, e);
+                        continue;
+                    }
+                },
+            };
+            if !self
+                .features
+                .is_feature_enabled(FEATURE_BACKUPS, Some(region_id))
+                .await?
+            {
+                // left due, so it runs as soon as backups are enabled again
+                debug!(
+                    "Backups are disabled in region {}, skipping VM {}",
+                    region_id, schedule.vm_id
+                );
+                continue;
+            }
+            // one job per VM so backups run as concurrently as the BackupVm
+            // job limit allows
+            self.work_commander
+                .send(WorkJob::BackupVm {
+                    vm_id: schedule.vm_id,
+                })
+                .await?;
+            // a failed run is not retried until the next interval
+            self.db
+                .set_vm_backup_schedule_last_run(schedule.vm_id, now)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Add the transfer of every active VM since its last counted sample to
+    /// its monthly bandwidth totals
+    async fn check_bandwidth_usage(&self) -> Result<()> {
+        let mut by_host: HashMap<u64, Vec<Vm>> = HashMap::new();
+        for vm in self.db.list_vms().await? {
+            if !vm.deleted {
+                by_host.entry(vm.host_id).or_default().push(vm);
+            }
+        }
+        for (host_id, vms) in by_host {
+            let host = self.db.get_host(host_id).await?;
+            let client = match get_host_client(&host, &self.settings.provisioner_config) {
+                Ok(c) => c,
+                Err(e) => {
+                    warn!(
+                        "[bandwidth] failed to get client for host {}: {}",
+                        host_id, e
+                    );
+                    continue;
+                }
+            };
+            for vm in vms {
+                if let Err(e) = self.check_vm_bandwidth_usage(client.as_ref(), &vm).await {
+                    warn!("[bandwidth] failed to check vm {}: {}", vm.id, e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn check_vm_bandwidth_usage(&self, client: &dyn VmHostClient, vm: &Vm) -> Result<()> {
+        let after = self.db.get_vm_bandwidth_last_sample(vm.id).await?;
+        // the hourly series only reaches back about an hour, after a longer
+        // outage use the first coarser series which covers the gap
+        let mut series = Vec::new();
+        for span in [
+            TimeSeries::Hourly,
+            TimeSeries::Daily,
+            TimeSeries::Weekly,
+            TimeSeries::Monthly,
+        ] {
+            series = client.get_time_series_data(vm, span).await?;
+            let oldest = series.iter().map(|d| d.timestamp).min();
+            match (after, oldest) {
+                (Some(after), Some(oldest)) if oldest > after.timestamp() as u64 => continue,
+                _ => break,
+            }
+        }
+        if let (Some(after), Some(oldest)) = (after, series.iter().map(|d| d.timestamp).min())
+            && oldest > after.timestamp() as u64
+        {
+            warn!(
+                "[bandwidth] vm {} has no samples between {} and {}, the transfer is not counted",
+                vm.id,
+                after,
+                DateTime::from_timestamp(oldest as i64, 0).unwrap_or_default()
+            );
+        }
+        record_bandwidth_usage(self.db.as_ref(), vm.id, &series, after).await?;
+        Ok(())
+    }
+
+    async fn check_vm_usage_alerts(
+        &self,
+        vm_id: u64,
+        alerts: &[VmUsageAlert],
+        now: DateTime<Utc>,
+    ) -> Result<()> {
+        let cooldown = TimeDelta::seconds(Self::USAGE_ALERT_COOLDOWN_SECONDS);
+        let due: Vec<&VmUsageAlert> = alerts
+            .iter()
+            .filter(|a| a.last_triggered.is_none_or(|t| now - t >= cooldown))
+            .collect();
+        if due.is_empty() {
+            return Ok(());
+        }
+
+        let vm = self.db.get_vm(vm_id).await?;
+        let host = self.db.get_host(vm.host_id).await?;
+        let client = get_host_client(&host, &self.settings.provisioner_config)?;
+        let series = client.get_time_series_data(&vm, TimeSeries::Hourly).await?;
+        // filesystem usage is only known inside the guest
+        let disk_usage = if due.iter().any(|a| a.metric == VmUsageMetric::DiskUsage) {
+            match client.get_guest_info(&vm).await {
+                Ok(info) => info.max_disk_usage(),
+                Err(e) => {
+                    warn!("Failed to get disk usage of VM {}: {}", vm.id, e);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        for alert in due {
+            let value = if alert.metric == VmUsageMetric::DiskUsage {
+                disk_usage
+            } else {
+                usage_alert_value(alert, &series, now.timestamp() as u64)
+            };
+            let Some(value) = value else {
+                continue;
+            };
+            if value < alert.threshold {
+                continue;
+            }
+            self.db.set_vm_usage_alert_triggered(alert.id, now).await?;
+            let (name, unit) = match alert.metric {
+                VmUsageMetric::Cpu => ("CPU", "%"),
+                VmUsageMetric::Memory => ("memory", "%"),
+                VmUsageMetric::NetIn => ("network receive", " B/s"),
+                VmUsageMetric::NetOut => ("network transmit", " B/s"),
+                VmUsageMetric::DiskRead => ("disk read", " B/s"),
+                VmUsageMetric::DiskWrite => ("disk write", " B/s"),
+                VmUsageMetric::DiskUsage => ("disk", "%"),
+            };
+            let message = if alert.metric == VmUsageMetric::DiskUsage {
+                format!(
+                    "Your VM #{} has a filesystem {:.1}% full, above your alert threshold of {}%.",
+                    vm.id, value, alert.threshold
+                )
+            } else {
+                format!(
+                    "Your VM #{} averaged {:.1}{} {} usage over the last {} minutes, above your alert threshold of {}{}.",
+                    vm.id,
+                    value,
+                    unit,
+                    name,
+                    alert.window_seconds / 60,
+                    alert.threshold,
+                    unit
+                )
+            };
+            self.queue_notification(
+                vm.user_id,
+                message,
+                Some(format!("[VM{}] High {} usage", vm.id, name)),
+            )
+            .await;
+        }
+        Ok(())
+    }
+
+    /// Best-effort reconciliation of missing DNS records for a VM's IPs.
+    ///
+    /// DNS is best-effort during spawn (a failed forward/reverse record must not
+    /// block or tear down a deploy — notably OVH rejects a PTR until the forward
+    /// name resolves). VMs whose records failed to create then self-heal here on
+    /// the periodic VM check. For healthy VMs (all refs present) this is a cheap
+    /// no-op that makes no provider API calls.
+    async fn reconcile_vm_dns(&self, vm: &Vm) {
+        let provisioner = self.subscription_handler.vm_provisioner();
+        let network = &provisioner.network;
+
+        let mut ips = match self.db.list_vm_ip_assignments(vm.id).await {
+            Ok(i) => i,
+            Err(e) => {
+                warn!("[dns-reconcile] failed to list ips for vm {}: {}", vm.id, e);
+                return;
+            }
+        };
+        for a in &mut ips {
+            let range = match self.db.get_ip_range(a.ip_range_id).await {
+                Ok(r) => r,
+                Err(_) => continue,
+            };
+            let want_fwd = range.forward_dns_server_id.is_some() && a.dns_forward_ref.is_none();
+            let want_rev = a.dns_reverse_ref.is_none()
+                && matches!(network.reverse_dns(&range).await, Ok(Some(_)));
+            if !want_fwd && !want_rev {
+                continue;
+            }
+
+            let mut changed = false;
+            if want_fwd {
+                match network.update_forward_ip_dns(a).await {
+                    Ok(_) => changed = true,
+                    Err(e) => warn!("[dns-reconcile] forward failed for {}: {}", a.ip, e),
+                }
+            }
+            if want_rev {
+                match network.update_reverse_ip_dns(a).await {
+                    Ok(_) => changed = true,
+                    Err(e) => warn!("[dns-reconcile] reverse failed for {}: {}", a.ip, e),
+                }
+            }
+            if changed && let Err(e) = self.db.update_vm_ip_assignment(a).await {
+                warn!(
+                    "[dns-reconcile] failed to persist dns refs for {}: {}",
+                    a.ip, e
+                );
+            }
+        }
+    }
+
+    /// Check multiple VMs on a single host using bulk API
+    async fn check_vms_on_host(&self, host_id: u64, vms: &[&Vm]) -> Result<()> {
+        debug!("Checking {} VMs on host {}", vms.len(), host_id);
+        let host = self.db.get_host(host_id).await?;
+        let region = self.db.get_host_region(host.region_id).await?;
+        let client = get_host_client(&host, &self.settings.provisioner_config)?;
+
+        let states = client.get_all_vm_states().await?;
+        let state_map: HashMap<u64, VmRunningState> = states.into_iter().collect();
+
+        let mut unchanged = Vec::new();
+        for vm in vms {
+            let state = state_map
+                .get(&vm.id)
+                .map(|s| s.clone())
+                .context("VM not found in bulk response");
+            let running = matches!(&state, Ok(s) if s.state == VmRunningStates::Running);
+            let migrating = matches!(&state, Ok(s) if s.state == VmRunningStates::Migrating);
+            if self.handle_vm_state(state, &vm).await? {
+                unchanged.push(vm.id);
+            }
+            if running && region.reachability_check {
+                self.check_vm_reachability(vm).await;
+            }
+            // Self-heal any DNS records that failed to create during spawn.
+            if !migrating {
+                self.reconcile_vm_dns(vm).await;
+            }
+        }
+        if let Err(e) = self.db.touch_vm_states(&unchanged).await {
+            warn!("Failed to refresh VM states on host {}: {}", host_id, e);
+        }
+        Ok(())
+    }
+
+    /// Spawn a VM and send notifications
     async fn spawn_vm_internal(&self, vm: &Vm) -> Result<()> {
         let provisioner = self.subscription_handler.vm_provisioner();
         let pipeline = provisioner.spawn_vm_pipeline(vm.id).await?;
@@ -1033,14 +2462,16 @@ impl Worker {
         Ok(())
     }
 
+    /// Queue `job` every `interval`, or the interval set for it in the
+    /// `job-intervals` setting
     pub fn spawn_job_interval(&self, job: WorkJob, interval: Duration) -> JoinHandle<()> {
-        let sender = self.work_commander.clone();
+        let this = self.clone();
         tokio::spawn(async move {
             loop {
-                if let Err(e) = sender.send(job.clone()).await {
+                if let Err(e) = this.work_commander.send(job.clone()).await {
                     error!("failed to send check vm: {}", e);
                 }
-                tokio::time::sleep(interval).await;
+                tokio::time::sleep(this.job_interval(&job, interval)).await;
             }
         })
     }
@@ -1180,26 +2611,13 @@ impl Worker {
         user_id: u64,
         message: String,
         title: Option<String>,
-    ) -> Result<()> {
+    ) -> Result<DeliveryStatus> {
         let user = self.db.get_user(user_id).await?;
         let notification = Notification::new(title, message);
-        for channel in &self.notification_channels {
-            if !channel.wants(&user) {
-                continue;
-            }
-            if let Err(e) = channel.send(&user, &notification).await {
-                match e {
-                    OpError::Fatal(e) => warn!(
-                        "Permanent {} notification error for user {}, skipping: {}",
-                        channel.name(),
-                        user_id,
-                        e
-                    ),
-                    OpError::Transient(e) => return Err(e),
-                }
-            }
-        }
-        Ok(())
+        self.live()
+            .notifications
+            .deliver(&user, &notification)
+            .await
     }
 
     async fn send_email_verification(
@@ -1215,7 +2633,7 @@ impl Worker {
         if user.email.is_empty() {
             return Ok(()); // No email, nothing to do
         }
-        let Some(smtp) = self.settings.smtp.as_ref() else {
+        let Some(smtp) = self.live().smtp else {
             return Ok(());
         };
         let plain_text = format!(
@@ -1227,7 +2645,7 @@ impl Worker {
             verify_url
         );
         send_email(
-            smtp,
+            &smtp,
             user.email.as_str(),
             "Verify your email address",
             &plain_text,
@@ -1255,6 +2673,7 @@ impl Worker {
         subject: String,
         message: String,
         admin_user_id: u64,
+        client_ip: Option<String>,
     ) -> Result<()> {
         info!("Processing bulk message: '{}'", subject);
 
@@ -1274,23 +2693,36 @@ impl Worker {
 
         let mut sent_count = 0;
         let mut failed_count = 0;
+        let mut deliveries = Vec::with_capacity(total_customers);
 
         for customer in active_customers {
-            // Personalize the message with customer name if available
-            let personalized_message = if let Some(ref name) = customer.billing_name {
-                format!("Dear {},\n\n{}", name, message)
-            } else {
-                format!("Dear Customer,\n\n{}", message)
-            };
+            let personalized_message = bulk_message_body(&customer, &message);
 
-            // Use the existing send_notification method which handles both email and NIP-17
+            let mut delivery = BulkMessageDelivery {
+                user_id: customer.id,
+                ..Default::default()
+            };
             match self
                 .send_notification(customer.id, personalized_message, Some(subject.clone()))
                 .await
             {
-                Ok(_) => {
+                Ok(DeliveryStatus::Delivered(channel)) => {
                     sent_count += 1;
                     info!("Bulk message sent to user ID: {}", customer.id);
+                    delivery.channel = Some(channel.to_string());
+                    delivery.status = "delivered".to_string();
+                }
+                Ok(status) => {
+                    failed_count += 1;
+                    warn!(
+                        "Bulk message not sent to user ID {}: {}",
+                        customer.id, status
+                    );
+                    delivery.status = match status {
+                        DeliveryStatus::NoChannel => "no_channel",
+                        _ => "failed",
+                    }
+                    .to_string();
                 }
                 Err(e) => {
                     failed_count += 1;
@@ -1298,8 +2730,11 @@ impl Worker {
                         "Failed to send bulk message to user ID {}: {}",
                         customer.id, e
                     );
+                    delivery.status = "failed".to_string();
+                    delivery.error = Some(e.to_string().chars().take(1024).collect());
                 }
             }
+            deliveries.push(delivery);
         }
 
         info!(
@@ -1307,6 +2742,34 @@ impl Worker {
             sent_count, failed_count, total_customers
         );
 
+        let detail = serde_json::json!({
+            "subject": subject,
+            "recipients": total_customers,
+            "sent": sent_count,
+            "failed": failed_count,
+            "ip": client_ip,
+        });
+        match self
+            .db
+            .insert_admin_audit_log(&AdminAuditLog {
+                admin_user_id,
+                action: "bulk_message".to_string(),
+                detail: Some(detail.to_string()),
+                ..Default::default()
+            })
+            .await
+        {
+            Ok(audit_log_id) => {
+                for d in deliveries.iter_mut() {
+                    d.audit_log_id = audit_log_id;
+                }
+                if let Err(e) = self.db.insert_bulk_message_deliveries(&deliveries).await {
+                    warn!("Failed to write bulk message deliveries: {}", e);
+                }
+            }
+            Err(e) => warn!("Failed to write bulk message audit log: {}", e),
+        }
+
         // Send completion notification to admin
         self.queue_notification(
             admin_user_id,
@@ -1331,7 +2794,9 @@ impl Worker {
         }
     }
 
-    async fn patch_host(&self, host: &mut VmHost) -> Result<()> {
+    /// Sync a host's resources from what the host reports. Implausible reads
+    /// are ignored unless `force` is set.
+    async fn patch_host(&self, host: &mut VmHost, force: bool) -> Result<()> {
         if host.kind == VmHostKind::Dummy {
             return Ok(());
         }
@@ -1340,10 +2805,8 @@ impl Worker {
             Err(e) => bail!("Failed to get host client: {} {}", host.name, e),
         };
         let info = client.get_info().await?;
-        let needs_update = info.cpu != host.cpu || info.memory != host.memory;
-        if needs_update {
-            host.cpu = info.cpu;
-            host.memory = info.memory;
+        let max_change = self.settings.host_discovery_max_change;
+        if apply_discovered_resources(host, &info, max_change, force) {
             self.db.update_host(host).await?;
             info!(
                 "Updated host {}: cpu={}, memory={}",
@@ -1355,6 +2818,15 @@ impl Worker {
         for disk in &info.disks {
             if let Some(hd) = host_disks.iter_mut().find(|d| d.name == disk.name) {
                 if hd.size != disk.size {
+                    if let Some(reason) = implausible_change(hd.size, disk.size, max_change)
+                        && !force
+                    {
+                        warn!(
+                            "Ignoring discovered size of host disk {}: {}",
+                            hd.name, reason
+                        );
+                        continue;
+                    }
                     hd.size = disk.size;
                     self.db.update_host_disk(hd).await?;
                     info!(
@@ -1528,168 +3000,31 @@ impl Worker {
             "x86_64" => CpuArch::X86_64,
             "arm64" => CpuArch::ARM64,
             _ => CpuArch::Unknown,
-        };
-
-        // Parse CPU features
-        let cpu_features: Vec<CpuFeature> = host_info
-            .cpu_features
-            .iter()
-            .filter_map(|f| f.parse().ok())
-            .collect();
-
-        let features_changed = host.cpu_mfg != cpu_mfg
-            || host.cpu_arch != cpu_arch
-            || host.cpu_features.0 != cpu_features;
-
-        if features_changed {
-            host.cpu_mfg = cpu_mfg;
-            host.cpu_arch = cpu_arch;
-            host.cpu_features = cpu_features.into();
-            self.db.update_host(host).await?;
-            info!(
-                "Updated host {} CPU info: mfg={:?}, arch={:?}, features={:?}",
-                host.name, host.cpu_mfg, host.cpu_arch, host.cpu_features
-            );
-        }
-
-        Ok(())
-    }
-
-    /// Check if a domain has a DNS record pointing to the configured nostr hostname or resolves to the same IP
-    async fn check_domain_dns(&self, domain: &str) -> Result<bool> {
-        let Some(expected_hostname) = &self.settings.nostr_hostname else {
-            warn!("No nostr hostname configured, skipping DNS record check");
-            return Ok(false);
-        };
-
-        // Create a resolver using system configuration
-        let resolver = TokioResolver::builder_tokio()?.build()?;
-
-        // Resolve both domain and expected hostname to IP addresses
-        // lookup_ip automatically follows DNS records to get final IPs
-        debug!(
-            "Checking IP resolution for {} vs {}",
-            domain, expected_hostname
-        );
-
-        // Resolve our expected hostname to IP addresses
-        let expected_ips = match resolver.lookup_ip(expected_hostname).await {
-            Ok(ips) => {
-                let ip_addrs: Vec<String> = ips.iter().map(|ip| ip.to_string()).collect();
-                debug!(
-                    "Expected hostname {} resolves to IPs: {:?}",
-                    expected_hostname, ip_addrs
-                );
-                ip_addrs
-            }
-            Err(e) => {
-                debug!(
-                    "Failed to resolve expected hostname {} to IP: {}",
-                    expected_hostname, e
-                );
-                return Ok(false);
-            }
-        };
-
-        // Resolve the domain to IP addresses (follows DNS records automatically)
-        match resolver.lookup_ip(domain).await {
-            Ok(domain_ips) => {
-                let domain_ip_addrs: Vec<String> =
-                    domain_ips.iter().map(|ip| ip.to_string()).collect();
-                debug!("Domain {} resolves to IPs: {:?}", domain, domain_ip_addrs);
-
-                // Check if any of the domain's IPs match any of our expected IPs
-                for domain_ip in &domain_ip_addrs {
-                    if expected_ips.contains(domain_ip) {
-                        debug!(
-                            "Domain {} IP check: {} matches expected hostname {} (matches: true)",
-                            domain, domain_ip, expected_hostname
-                        );
-                        return Ok(true);
-                    }
-                }
-
-                debug!(
-                    "Domain {} IP check: no IP overlap with expected hostname {} (matches: false)",
-                    domain, expected_hostname
-                );
-                Ok(false)
-            }
-            Err(e) => {
-                debug!("DNS IP lookup error for {}: {}", domain, e);
-                Ok(false)
-            }
-        }
-    }
-
-    /// Check if a domain can be activated via path-based activation
-    /// by fetching the activation URL and verifying the response is valid NIP-05 JSON
-    /// with an empty `names` map (indicating the hash was recognised by the server).
-    async fn check_path_activation(&self, domain: &lnvps_db::NostrDomain) -> Result<bool> {
-        let Some(activation_hash) = &domain.activation_hash else {
-            debug!("Domain {} has no activation hash", domain.name);
-            return Ok(false);
-        };
-
-        // Build the activation URL: http://<domain>/.well-known/nostr.json?name=<hash>
-        let activation_url = format!(
-            "http://{}/.well-known/nostr.json?name={}",
-            domain.name, activation_hash
-        );
-
-        debug!(
-            "Checking path activation for domain {} at {}",
-            domain.name, activation_url
-        );
-
-        match self.http_client.get(&activation_url).send().await {
-            Ok(response) => {
-                if !response.status().is_success() {
-                    debug!(
-                        "Path activation check failed for domain {} - got status {}",
-                        domain.name,
-                        response.status()
-                    );
-                    return Ok(false);
-                }
-                // Verify the body is valid NIP-05 JSON with an empty `names` map.
-                // The lnvps_nostr server returns `{"names":{},"relays":{}}` when
-                // the activation hash matches, rather than a real handle lookup.
-                match response.json::<serde_json::Value>().await {
-                    Ok(body) => {
-                        let names_empty = body
-                            .get("names")
-                            .and_then(|n| n.as_object())
-                            .map(|m| m.is_empty())
-                            .unwrap_or(false);
-                        if names_empty {
-                            debug!("Path activation check succeeded for domain {}", domain.name);
-                            Ok(true)
-                        } else {
-                            debug!(
-                                "Path activation check failed for domain {} - unexpected body",
-                                domain.name
-                            );
-                            Ok(false)
-                        }
-                    }
-                    Err(e) => {
-                        debug!(
-                            "Path activation check failed for domain {} - invalid JSON: {}",
-                            domain.name, e
-                        );
-                        Ok(false)
-                    }
-                }
-            }
-            Err(e) => {
-                debug!(
-                    "Path activation check failed for domain {} - error: {}",
-                    domain.name, e
-                );
-                Ok(false)
-            }
+        };
+
+        // Parse CPU features
+        let cpu_features: Vec<CpuFeature> = host_info
+            .cpu_features
+            .iter()
+            .filter_map(|f| f.parse().ok())
+            .collect();
+
+        let features_changed = host.cpu_mfg != cpu_mfg
+            || host.cpu_arch != cpu_arch
+            || host.cpu_features.0 != cpu_features;
+
+        if features_changed {
+            host.cpu_mfg = cpu_mfg;
+            host.cpu_arch = cpu_arch;
+            host.cpu_features = cpu_features.into();
+            self.db.update_host(host).await?;
+            info!(
+                "Updated host {} CPU info: mfg={:?}, arch={:?}, features={:?}",
+                host.name, host.cpu_mfg, host.cpu_arch, host.cpu_features
+            );
         }
+
+        Ok(())
     }
 
     /// Check all nostr domains for DNS records - enable disabled domains with DNS records, disable active domains without DNS records
@@ -1714,21 +3049,9 @@ impl Worker {
 
         for domain in &all_domains {
             // Check both DNS and path-based activation
-            let has_dns_record = match self.check_domain_dns(&domain.name).await {
-                Ok(result) => result,
-                Err(e) => {
-                    error!("DNS check error for {}: {}", domain.name, e);
-                    false
-                }
-            };
-
-            let has_path_activation = match self.check_path_activation(domain).await {
-                Ok(result) => result,
-                Err(e) => {
-                    error!("Path activation check error for {}: {}", domain.name, e);
-                    false
-                }
-            };
+            let check = self.domain_verifier.check(domain).await;
+            let has_dns_record = check.dns;
+            let has_path_activation = check.path;
 
             // If domain is disabled but has either DNS or path activation, enable it
             if !domain.enabled && (has_dns_record || has_path_activation) {
@@ -2044,9 +3367,14 @@ impl Worker {
                 let mut hosts = self.db.list_hosts().await?;
                 for host in &mut hosts {
                     info!("Patching host {}", host.name);
-                    self.patch_host(host).await?;
+                    self.patch_host(host, false).await?;
                 }
             }
+            WorkJob::SyncHost { host_id, force } => {
+                let mut host = self.db.get_host(*host_id).await?;
+                info!("Syncing host {} (force={})", host.name, force);
+                self.patch_host(&mut host, *force).await?;
+            }
             WorkJob::CheckVm { vm_id } => {
                 let vm = self.db.get_vm(*vm_id).await?;
                 self.check_vm(&vm).await?;
@@ -2067,8 +3395,10 @@ impl Worker {
                 message,
                 title,
             } => {
-                self.send_notification(*user_id, message.clone(), title.clone())
+                let status = self
+                    .send_notification(*user_id, message.clone(), title.clone())
                     .await?;
+                return Ok(Some(status.to_string()));
             }
             WorkJob::SendAdminNotification { message, title } => {
                 // Look up all admin users and queue individual notifications
@@ -2093,9 +3423,36 @@ impl Worker {
                 subject,
                 message,
                 admin_user_id,
+                client_ip,
             } => {
-                self.process_bulk_message(subject.clone(), message.clone(), *admin_user_id)
-                    .await?;
+                if let Err(e) = self
+                    .process_bulk_message(
+                        subject.clone(),
+                        message.clone(),
+                        *admin_user_id,
+                        client_ip.clone(),
+                    )
+                    .await
+                {
+                    let detail = serde_json::json!({
+                        "subject": subject,
+                        "error": e.to_string(),
+                        "ip": client_ip,
+                    });
+                    if let Err(e) = self
+                        .db
+                        .insert_admin_audit_log(&AdminAuditLog {
+                            admin_user_id: *admin_user_id,
+                            action: "bulk_message_failed".to_string(),
+                            detail: Some(detail.to_string()),
+                            ..Default::default()
+                        })
+                        .await
+                    {
+                        warn!("Failed to write bulk message audit log: {}", e);
+                    }
+                    return Err(e);
+                }
 
                 return Ok(Some(format!(
                     "Bulk message '{}' sent successfully",
@@ -2108,6 +3465,30 @@ impl Worker {
             WorkJob::CheckSubscriptions => {
                 self.check_subscriptions().await?;
             }
+            WorkJob::CancelExpiredPayments => {
+                self.cancel_expired_payments().await?;
+                self.expire_vm_create_requests().await?;
+            }
+            WorkJob::CheckUsageAlerts => {
+                self.check_usage_alerts().await?;
+            }
+            WorkJob::CheckBackups => {
+                self.check_backups().await?;
+            }
+            WorkJob::BackupVm { vm_id } => {
+                match self
+                    .subscription_handler
+                    .vm_provisioner()
+                    .backup_vm(*vm_id)
+                    .await
+                {
+                    Ok(b) => info!("[backups] created backup {} of vm {}", b.id, b.vm_id),
+                    Err(e) => warn!("[backups] failed to backup vm {}: {}", vm_id, e),
+                }
+            }
+            WorkJob::CheckBandwidthUsage => {
+                self.check_bandwidth_usage().await?;
+            }
             WorkJob::ProcessReferralPayouts => {
                 self.referral_payouts.process_payouts().await?;
             }
@@ -2131,6 +3512,19 @@ impl Worker {
             WorkJob::ClearRouterDefaultRoute { router_id } => {
                 self.clear_router_default_route(*router_id).await?;
             }
+            WorkJob::RebuildRouterArp { router_id } => {
+                let provisioner = self.subscription_handler.vm_provisioner();
+                let summary = provisioner.network.rebuild_router_arp(*router_id).await?;
+                info!("{}", summary);
+                if !summary.failed.is_empty() {
+                    self.queue_admin_notification(
+                        summary.to_string(),
+                        Some(format!("[Router{}] ARP rebuild incomplete", router_id)),
+                    )
+                    .await;
+                }
+                return Ok(Some(summary.to_string()));
+            }
             WorkJob::ToggleTunnel {
                 router_id,
                 name,
@@ -2163,7 +3557,9 @@ impl Worker {
 
                 // Delete the VM via provisioner
                 let provisioner = self.subscription_handler.vm_provisioner();
-                provisioner.delete_vm(*vm_id, hard_delete).await?;
+                let res = provisioner.delete_vm(*vm_id, hard_delete).await;
+                ApiMetrics::global().record_vm_operation("delete", res.is_ok());
+                res?;
 
                 // A hard delete removes the VM row (and its history), so logging
                 // a vm_history entry afterwards would fail the foreign key.
@@ -2325,6 +3721,24 @@ impl Worker {
 
                 return Ok(Some(format!("VM {} stopped successfully", vm_id)));
             }
+            WorkJob::RebootVm { vm_id, user_id } => {
+                let vm = self.db.get_vm(*vm_id).await?;
+                if vm.deleted {
+                    bail!("Cannot reboot deleted VM {}", vm_id);
+                }
+                let host = self.db.get_host(vm.host_id).await?;
+                let client = get_host_client(&host, &self.settings.provisioner_config)?;
+                let res = client.reboot_vm(&vm).await;
+                ApiMetrics::global().record_vm_operation("reboot", res.is_ok());
+                res?;
+                self.vm_history_logger
+                    .log_vm_rebooted(*vm_id, Some(*user_id), None)
+                    .await?;
+                self.work_commander
+                    .send(WorkJob::CheckVm { vm_id: *vm_id })
+                    .await?;
+                return Ok(Some(format!("VM {} rebooted", vm_id)));
+            }
             WorkJob::ProcessVmUpgrade { vm_id, config } => {
                 self.process_vm_upgrade(*vm_id, config).await?;
             }
@@ -2341,15 +3755,16 @@ impl Worker {
                 new_image_id,
                 reply_channel,
             } => {
-                // Run the reinstall pipeline (stop → wipe → import → start) on the
-                // provisioner and report the outcome on the reply channel so the
-                // waiting API request can respond to the user. Running here (rather
-                // than inline in the web request) serialises reinstall with spawn
-                // and other host operations, preventing the reinstall-vs-spawn race.
+                // Run the reinstall pipeline (stop → wipe → import → configure →
+                // start) on the provisioner and report the outcome on the reply
+                // channel so the waiting API request can respond to the user.
+                // Running here (rather than inline in the web request) serialises
+                // reinstall with spawn and other host operations, preventing the
+                // reinstall-vs-spawn race.
                 let result = self
                     .subscription_handler
                     .vm_provisioner()
-                    .reinstall_vm(*vm_id)
+                    .reinstall_vm(*vm_id, *new_image_id)
                     .await;
 
                 let feedback = match &result {
@@ -2401,6 +3816,87 @@ impl Worker {
             WorkJob::ApplyVmFirewall { vm_id } => {
                 self.apply_vm_firewall(*vm_id).await?;
             }
+            WorkJob::RestoreVmBackup { vm_id, backup_id } => {
+                let vm = self.db.get_vm(*vm_id).await?;
+                let result = self
+                    .subscription_handler
+                    .vm_provisioner()
+                    .restore_vm_backup(*vm_id, *backup_id)
+                    .await;
+                let message = match &result {
+                    Ok(()) => format!("Your VM #{} has been restored from a backup.", vm_id),
+                    Err(e) => format!("Restoring your VM #{} from a backup failed: {}", vm_id, e),
+                };
+                self.queue_notification(
+                    vm.user_id,
+                    message,
+                    Some(format!("[VM{}] Backup restore", vm_id)),
+                )
+                .await;
+                if let Err(e) = self
+                    .work_commander
+                    .send(WorkJob::CheckVm { vm_id: *vm_id })
+                    .await
+                {
+                    warn!(
+                        "Failed to queue CheckVm after restore of VM {}: {}",
+                        vm_id, e
+                    );
+                }
+                // the owner is told the outcome, don't retry a failed restore
+                return Ok(Some(match &result {
+                    Ok(()) => format!("VM {} restored from backup {}", vm_id, backup_id),
+                    Err(e) => format!("VM {} restore failed: {}", vm_id, e),
+                }));
+            }
+            WorkJob::RecalculateVmExpiry { vm_id } => {
+                return Ok(Some(self.recalculate_vm_expiry(*vm_id).await?));
+            }
+            WorkJob::ReclaimUnpaidVms {
+                grace_hours,
+                action,
+                admin_user_id,
+            } => {
+                info!(
+                    "Reclaiming unpaid VMs older than {}h ({}), requested by admin {:?}",
+                    grace_hours, action, admin_user_id
+                );
+                let n = self
+                    .reclaim_unpaid_vms(TimeDelta::hours(*grace_hours as i64), *action)
+                    .await?;
+                return Ok(Some(format!("Reclaimed {} unpaid VM(s) ({})", n, action)));
+            }
+            WorkJob::DrainHost {
+                host_id,
+                target_host_id,
+                batch_id,
+                admin_user_id,
+            } => {
+                info!(
+                    "Draining host {} (target {:?}), requested by admin {:?}",
+                    host_id, target_host_id, admin_user_id
+                );
+                return Ok(Some(
+                    self.drain_host(
+                        *host_id,
+                        *target_host_id,
+                        batch_id.as_deref(),
+                        *admin_user_id,
+                    )
+                    .await?,
+                ));
+            }
+            WorkJob::MigrateVm {
+                vm_id,
+                target_host_id,
+                batch_id,
+                admin_user_id,
+            } => {
+                return Ok(Some(
+                    self.migrate_vm(*vm_id, *target_host_id, batch_id.as_deref(), *admin_user_id)
+                        .await?,
+                ));
+            }
             WorkJob::CheckNostrDomains => {
                 self.check_nostr_domains().await?;
             }
@@ -2463,6 +3959,7 @@ impl Worker {
                         *image_id,
                         *ssh_key_id,
                         ref_code.clone(),
+                        false,
                     )
                     .await?;
 
@@ -2529,6 +4026,7 @@ impl Worker {
                 host_id,
                 host_vm_id,
                 user_id,
+                template_id,
                 admin_user_id,
                 reason,
             } => {
@@ -2538,7 +4036,7 @@ impl Worker {
                 );
                 let provisioner = self.subscription_handler.vm_provisioner();
                 let vm = provisioner
-                    .import_vm(*host_id, *host_vm_id, *user_id)
+                    .import_vm(*host_id, *host_vm_id, *user_id, *template_id)
                     .await?;
 
                 let metadata = Some(serde_json::json!({
@@ -2651,7 +4149,7 @@ impl Worker {
             )
             .collect();
 
-        download_images_on_hosts(clients, &images).await;
+        download_images_on_hosts(clients, &images, self.retry.host()).await;
         Ok(())
     }
 
@@ -2699,6 +4197,11 @@ impl Worker {
     async fn process_vm_upgrade(&self, vm_id: u64, cfg: &UpgradeConfig) -> Result<()> {
         info!("Processing VM {} upgrade with new specs", vm_id);
 
+        if self.process_vm_upgrade_online(vm_id, cfg).await? {
+            self.notify_vm_upgraded(vm_id).await?;
+            return Ok(());
+        }
+
         // Context struct for the pipeline
         struct UpgradeContext {
             vm_id: u64,
@@ -2719,7 +4222,7 @@ impl Worker {
         };
 
         Pipeline::new(ctx)
-            .with_retry_policy(RetryPolicy::default())
+            .with_retry_policy(self.retry.host())
             .step("update_template", |ctx| {
                 Box::pin(async move {
                     let vm_before = ctx.db.get_vm(ctx.vm_id).await?;
@@ -2908,6 +4411,46 @@ impl Worker {
             .execute()
             .await?;
 
+        self.notify_vm_upgraded(vm_id).await
+    }
+
+    /// Apply a paid upgrade without stopping the VM where that's possible.
+    ///
+    /// Disk-only upgrades grow the disk online, see [VmProvisioner::grow_disk].
+    /// CPU/memory-only upgrades are hot-plugged into the running VM, see
+    /// [VmProvisioner::hotplug_resize].
+    /// Returns `false` when the upgrade needs the stop/start cycle instead.
+    async fn process_vm_upgrade_online(&self, vm_id: u64, cfg: &UpgradeConfig) -> Result<bool> {
+        let provisioner = self.subscription_handler.vm_provisioner();
+        if cfg.new_disk.is_none() && (cfg.new_cpu.is_some() || cfg.new_memory.is_some()) {
+            let current = FullVmInfo::vm_resources(vm_id, self.db.clone()).await?;
+            let res = provisioner
+                .hotplug_resize(
+                    vm_id,
+                    cfg.new_cpu.unwrap_or(current.cpu),
+                    cfg.new_memory.unwrap_or(current.memory),
+                )
+                .await?;
+            return Ok(res == HotplugResize::Live);
+        }
+        if cfg.new_cpu.is_none()
+            && cfg.new_memory.is_none()
+            && let Some(new_disk) = cfg.new_disk
+            && provisioner.can_grow_disk_online(vm_id).await?
+        {
+            let current = FullVmInfo::vm_resources(vm_id, self.db.clone())
+                .await?
+                .disk_size;
+            // a retried job may find the disk already grown
+            if new_disk > current {
+                provisioner.grow_disk(vm_id, new_disk).await?;
+            }
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    async fn notify_vm_upgraded(&self, vm_id: u64) -> Result<()> {
         let upgraded_vm = self.db.get_vm(vm_id).await?;
         let new_resources = FullVmInfo::vm_resources(vm_id, self.db.clone()).await;
         let specs_line = match new_resources {
@@ -2948,6 +4491,8 @@ impl Worker {
         let client = get_host_client(&host, &self.settings.provisioner_config)?;
 
         client.configure_vm(&full_info).await?;
+        // reconcile firewall rules / IPsets with the database too
+        client.patch_firewall(&full_info).await?;
 
         info!(
             "Successfully re-configured VM {} using current database settings",
@@ -2993,17 +4538,42 @@ impl Worker {
             bail!("Cannot assign IP to a deleted VM");
         }
 
+        let range = self.db.get_ip_range(ip_range_id).await?;
+        let delegated = matches!(
+            range.allocation_mode,
+            IpRangeAllocationMode::DelegatedPrefix
+        );
+
         // Determine the IP to assign
-        let assigned_ip = if let Some(ip_str) = &ip {
-            ip_str.trim().to_string()
+        let (assigned_ip, prefix_length) = if let Some(ip_str) = &ip {
+            if delegated {
+                // an address without a length takes the range's delegated prefix length
+                let net: IpNetwork = ip_str.trim().parse().context("Invalid delegated prefix")?;
+                let len = if net.prefix() == 128 {
+                    range.delegated_prefix_length()
+                } else {
+                    net.prefix()
+                };
+                let net = IpNetwork::new(net.ip(), len)?;
+                (net.network().to_string(), Some(len))
+            } else {
+                (ip_str.trim().to_string(), None)
+            }
         } else {
             // Auto-assign IP from the range
             let network_provisioner = NetworkProvisioner::new(self.db.clone());
             let available_ip = network_provisioner
-                .pick_ip_from_range_id(ip_range_id)
+                .pick_ip_from_range(&range)
                 .await
                 .context("Failed to auto-assign IP from range")?;
-            available_ip.ip.ip().to_string()
+            if delegated {
+                (
+                    available_ip.ip.network().to_string(),
+                    Some(available_ip.ip.prefix()),
+                )
+            } else {
+                (available_ip.ip.ip().to_string(), None)
+            }
         };
 
         // Create the assignment (similar to admin API but without saving yet)
@@ -3018,6 +4588,8 @@ impl Worker {
             dns_forward_ref: None,
             dns_reverse: None,
             dns_reverse_ref: None,
+            prefix_length,
+            route_ref: None,
         };
 
         self.subscription_handler
@@ -3165,12 +4737,35 @@ impl Worker {
         Ok(())
     }
 
+    /// Receive jobs and run them, as many at once as [JobLimiter] allows.
+    ///
+    /// No more jobs are received until each received job may run, so a
+    /// worker never claims more jobs than it can run. The claim of a job
+    /// waiting to run is renewed like that of a running job.
     pub async fn handle(&self) -> Result<()> {
         loop {
             match self.work_commander.recv().await {
                 Ok(jobs) => {
                     for msg in jobs {
-                        self.handle_job(msg).await?;
+                        let Some(slot) = self.job_limiter.accept(&msg) else {
+                            debug!("Job {} is still running, skipping", msg.id);
+                            continue;
+                        };
+                        let renew = self.renew_claim(&msg.id);
+                        let permit = slot.acquire().await;
+                        let this = self.clone();
+                        tokio::spawn(async move {
+                            let _permit = permit;
+                            let _slot = slot;
+                            let id = msg.id.clone();
+                            let res = this.handle_job(msg).await;
+                            if let Some(renew) = renew {
+                                renew.abort();
+                            }
+                            if let Err(e) = res {
+                                error!("Failed to handle job {}: {}", id, e);
+                            }
+                        });
                     }
                 }
                 Err(e) => {
@@ -3183,6 +4778,27 @@ impl Worker {
         }
     }
 
+    /// Keep our claim on a job until the returned task is aborted, so a job
+    /// which waits or runs for long isn't handed to another worker. `None`
+    /// when the queue has no claim lease.
+    fn renew_claim(&self, id: &str) -> Option<tokio::task::JoinHandle<()>> {
+        self.work_commander.lease().map(|lease| {
+            let work_commander = self.work_commander.clone();
+            let id = id.to_string();
+            tokio::spawn(async move {
+                let mut interval =
+                    tokio::time::interval((lease / 3).max(Duration::from_millis(10)));
+                interval.tick().await;
+                loop {
+                    interval.tick().await;
+                    if let Err(e) = work_commander.renew(&id).await {
+                        warn!("Failed to renew claim on job {}: {}", id, e);
+                    }
+                }
+            })
+        })
+    }
+
     async fn handle_job(&self, msg: WorkJobMessage) -> Result<()> {
         let job = &msg.job;
         let stream_id = &msg.id;
@@ -3227,9 +4843,12 @@ impl Worker {
                     );
                 }
                 // if job can be skipped, just acknowledge job
-                if msg.job.can_skip()
-                    && let Err(e) = self.work_commander.ack(&msg.id).await
-                {
+                let res = if msg.job.can_skip() {
+                    self.work_commander.ack(&msg.id).await
+                } else {
+                    self.work_commander.fail(&msg.id, &e.to_string()).await
+                };
+                if let Err(e) = res {
                     error!("Failed to acknowledge job {}: {}", stream_id, e);
                 }
             }
@@ -3247,6 +4866,7 @@ impl Worker {
 pub(crate) async fn download_images_on_hosts(
     clients: Vec<(String, Arc<dyn VmHostClient>)>,
     images: &[VmOsImage],
+    retry: RetryPolicy,
 ) {
     // Spawn each host on its own task rather than combining them with
     // `join_all`. Host image downloads run over SSH (wget/decompress/checksum),
@@ -3260,10 +4880,11 @@ pub(crate) async fn download_images_on_hosts(
         .into_iter()
         .map(|(host_name, client)| {
             let images = images.to_vec();
+            let retry = retry.clone();
             tokio::spawn(async move {
                 for image in &images {
                     info!("Checking image {} on host {}", image.url, host_name);
-                    if let Err(e) = client.download_os_image(image).await {
+                    if let Err(e) = client.download_os_image(image, retry.clone()).await {
                         warn!(
                             "Failed to download image {} on host {}: {}",
                             image.url, host_name, e
@@ -3280,17 +4901,43 @@ pub(crate) async fn download_images_on_hosts(
     }
 }
 
+/// Expiry that `payments` (paid, oldest first) add up to, applying each the
+/// way it was when paid: from the later of the running expiry and the time of
+/// payment, by its `time_value`.
+///
+/// Payments which don't add time (upgrades, or no `time_value` recorded) are
+/// skipped, they neither extend nor move the expiry.
+fn paid_expiry(payments: &[SubscriptionPayment]) -> Option<DateTime<Utc>> {
+    payments
+        .iter()
+        .filter_map(|p| match p.time_value {
+            Some(secs)
+                if secs > 0 && p.payment_type != lnvps_db::SubscriptionPaymentType::Upgrade =>
+            {
+                Some((p, secs))
+            }
+            _ => None,
+        })
+        .try_fold(None, |expires: Option<DateTime<Utc>>, (p, secs)| {
+            let paid_at = p.paid_at.unwrap_or(p.created);
+            let base = expires.map_or(paid_at, |e| e.max(paid_at));
+            base.checked_add_signed(TimeDelta::seconds(secs as i64))
+                .map(Some)
+        })?
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::mocks::{MockNode, MockOnChainProvider};
+    use crate::mocks::{MockNode, MockOnChainProvider, MockReachabilityProbe};
     use crate::settings::mock_settings;
     use crate::subscription::SubscriptionHandler;
     use lnvps_api_common::{ChannelWorkCommander, MockDb, MockExchangeRate};
     use lnvps_db::{
-        LNVpsDbBase, Subscription, SubscriptionLineItem, SubscriptionPayment, SubscriptionType,
-        UserSshKey, Vm,
+        CapacityReservation, LNVpsDbBase, Subscription, SubscriptionLineItem, SubscriptionPayment,
+        SubscriptionType, UserSshKey, Vm,
     };
+    use payments_rs::lightning::{AddInvoiceRequest, LightningNode};
 
     mod download_parallel {
         use super::*;
@@ -3329,13 +4976,17 @@ mod tests {
                 self.inner.get_info().await
             }
 
-            async fn download_os_image(&self, image: &VmOsImage) -> OpResult<()> {
+            async fn download_os_image(
+                &self,
+                image: &VmOsImage,
+                retry: RetryPolicy,
+            ) -> OpResult<()> {
                 let now = self.active.fetch_add(1, Ordering::SeqCst) + 1;
                 self.max_active.fetch_max(now, Ordering::SeqCst);
                 tokio::time::sleep(Duration::from_millis(50)).await;
                 self.active.fetch_sub(1, Ordering::SeqCst);
                 self.downloads.fetch_add(1, Ordering::SeqCst);
-                self.inner.download_os_image(image).await
+                self.inner.download_os_image(image, retry).await
             }
 
             async fn generate_mac(&self, vm: &Vm) -> OpResult<String> {
@@ -3354,8 +5005,28 @@ mod tests {
                 self.inner.reset_vm(vm).await
             }
 
-            async fn create_vm(&self, req: &crate::host::FullVmInfo) -> OpResult<()> {
-                self.inner.create_vm(req).await
+            async fn reboot_vm(&self, vm: &Vm) -> OpResult<()> {
+                self.inner.reboot_vm(vm).await
+            }
+
+            async fn get_guest_info(&self, vm: &Vm) -> OpResult<lnvps_api_common::GuestInfo> {
+                self.inner.get_guest_info(vm).await
+            }
+
+            async fn reset_password(
+                &self,
+                cfg: &crate::host::FullVmInfo,
+                password: &str,
+            ) -> OpResult<crate::host::PasswordResetMethod> {
+                self.inner.reset_password(cfg, password).await
+            }
+
+            async fn create_vm(
+                &self,
+                req: &crate::host::FullVmInfo,
+                retry: RetryPolicy,
+            ) -> OpResult<()> {
+                self.inner.create_vm(req, retry).await
             }
 
             async fn delete_vm(&self, vm: &Vm) -> OpResult<()> {
@@ -3438,13 +5109,14 @@ mod tests {
                 default_username: None,
                 sha2: None,
                 sha2_url: None,
+                image_kind: lnvps_db::OsImageKind::CloudInit,
             };
             let images = vec![
                 img(1, "https://example.com/a.qcow2"),
                 img(2, "https://example.com/b.qcow2"),
             ];
 
-            download_images_on_hosts(clients, &images).await;
+            download_images_on_hosts(clients, &images, RetryPolicy::default()).await;
 
             // 3 hosts x 2 images
             assert_eq!(downloads.load(Ordering::SeqCst), 6);
@@ -3467,7 +5139,7 @@ mod tests {
     async fn setup_worker_with_delete_after(db: Arc<MockDb>, delete_after: u16) -> Result<Worker> {
         let mut settings = mock_settings();
         settings.delete_after = delete_after;
-        let node = Arc::new(MockNode::default());
+        let node = Arc::new(MockNode::new());
         let rates = Arc::new(MockExchangeRate::new());
         let work_commander = Arc::new(ChannelWorkCommander::new());
         let cache = VmStateCache::new();
@@ -3599,7 +5271,321 @@ mod tests {
             tax_treatment: None,
             tax_evidence: None,
             tax_breakdown: None,
+            invoice_number: None,
+        }
+    }
+
+    /// An expired unpaid invoice is canceled at the node and the capacity held
+    /// for its VM is released, while open payments are left alone.
+    #[tokio::test]
+    async fn test_cancel_expired_payment_releases_reservation() -> Result<()> {
+        let db = Arc::new(MockDb::default());
+        let (vm_id, subscription_id) = add_vm_with_subscription(&db, Utc::now(), false).await?;
+        let user_id = db.get_vm(vm_id).await?.user_id;
+        let reservation_id = db
+            .insert_capacity_reservation(&CapacityReservation {
+                id: 0,
+                host_id: 1,
+                disk_id: 1,
+                vm_id: Some(vm_id),
+                cpu: 1,
+                memory: 1024,
+                disk_size: 1024,
+                expires: Utc::now().add(TimeDelta::minutes(30)),
+            })
+            .await?;
+
+        let node = MockNode::new();
+        let invoice = node
+            .add_invoice(AddInvoiceRequest {
+                memo: None,
+                amount: 1000,
+                expire: Some(600),
+            })
+            .await?;
+        let mut expired = make_subscription_payment(
+            subscription_id,
+            user_id,
+            Utc::now().sub(TimeDelta::minutes(20)),
+            Utc::now().sub(TimeDelta::minutes(10)),
+            0,
+        );
+        expired.id = hex::decode(invoice.payment_hash())?;
+        expired.payment_type = lnvps_db::SubscriptionPaymentType::Purchase;
+        db.insert_subscription_payment(&expired).await?;
+        // an open payment of another VM's subscription
+        let (_, other_subscription_id) = add_vm_with_subscription(&db, Utc::now(), false).await?;
+        let open = make_subscription_payment(
+            other_subscription_id,
+            user_id,
+            Utc::now(),
+            Utc::now().add(TimeDelta::minutes(10)),
+            1,
+        );
+        db.insert_subscription_payment(&open).await?;
+
+        let worker = setup_worker(db.clone()).await?;
+        worker.cancel_expired_payments().await?;
+
+        let canceled = db.canceled_payments.lock().await.clone();
+        assert!(canceled.contains(&expired.id));
+        assert!(!canceled.contains(&open.id));
+        assert!(
+            !node
+                .invoices
+                .lock()
+                .await
+                .contains_key(&hex::encode(&expired.id))
+        );
+        assert!(
+            db.list_capacity_reservations(1)
+                .await?
+                .iter()
+                .all(|r| r.id != reservation_id)
+        );
+        assert!(db.list_expired_subscription_payments(10).await?.is_empty());
+        Ok(())
+    }
+
+    /// A CPU-only upgrade of a VM which can't be hot-plugged (it isn't
+    /// running) falls back to the stop/start cycle
+    #[tokio::test]
+    async fn test_process_vm_upgrade_hotplug_falls_back_to_restart() -> Result<()> {
+        let db = Arc::new(MockDb::default());
+        let (vm_id, _) = add_vm_with_subscription(&db, Utc::now(), true).await?;
+        let worker = setup_worker(db.clone()).await?;
+
+        let cfg = UpgradeConfig {
+            new_cpu: Some(4),
+            new_memory: None,
+            new_disk: None,
+        };
+        worker.process_vm_upgrade(vm_id, &cfg).await?;
+
+        let vm = db.get_vm(vm_id).await?;
+        let template = db
+            .get_custom_vm_template(vm.custom_template_id.unwrap())
+            .await?;
+        assert_eq!(template.cpu, 4);
+        let history: Vec<String> = db
+            .vm_history
+            .lock()
+            .await
+            .values()
+            .filter(|h| h.vm_id == vm_id)
+            .filter_map(|h| h.metadata.as_ref())
+            .map(|m| String::from_utf8_lossy(m).to_string())
+            .collect();
+        assert!(!history.iter().any(|m| m.contains("hotplug_resize")));
+        assert!(
+            history
+                .iter()
+                .any(|m| m.contains("standard_to_custom_conversion"))
+        );
+        Ok(())
+    }
+
+    /// Paid disk-only upgrades grow the disk online, a retried job is a no-op
+    #[tokio::test]
+    async fn test_process_vm_upgrade_grows_disk_online() -> Result<()> {
+        let db = Arc::new(MockDb::default());
+        let (vm_id, _) = add_vm_with_subscription(&db, Utc::now(), true).await?;
+        let worker = setup_worker(db.clone()).await?;
+
+        let cfg = UpgradeConfig {
+            new_cpu: None,
+            new_memory: None,
+            new_disk: Some(100 * crate::GB),
+        };
+        worker.process_vm_upgrade(vm_id, &cfg).await?;
+        worker.process_vm_upgrade(vm_id, &cfg).await?;
+
+        let vm = db.get_vm(vm_id).await?;
+        let template = db
+            .get_custom_vm_template(vm.custom_template_id.unwrap())
+            .await?;
+        assert_eq!(template.disk_size, 100 * crate::GB);
+        let grows = db
+            .vm_history
+            .lock()
+            .await
+            .values()
+            .filter(|h| {
+                h.vm_id == vm_id
+                    && h.metadata
+                        .as_ref()
+                        .is_some_and(|m| String::from_utf8_lossy(m).contains("disk_grow"))
+            })
+            .count();
+        assert_eq!(grows, 1);
+        Ok(())
+    }
+
+    /// Expired renewals, and purchases whose subscription has a newer open
+    /// invoice, keep the VM's reservation.
+    #[tokio::test]
+    async fn test_cancel_expired_payment_keeps_reservation() -> Result<()> {
+        let db = Arc::new(MockDb::default());
+        let (vm_id, subscription_id) = add_vm_with_subscription(&db, Utc::now(), false).await?;
+        let user_id = db.get_vm(vm_id).await?.user_id;
+        db.insert_capacity_reservation(&CapacityReservation {
+            id: 0,
+            host_id: 1,
+            disk_id: 1,
+            vm_id: Some(vm_id),
+            cpu: 1,
+            memory: 1024,
+            disk_size: 1024,
+            expires: Utc::now().add(TimeDelta::minutes(30)),
+        })
+        .await?;
+
+        let expired_renewal = make_subscription_payment(
+            subscription_id,
+            user_id,
+            Utc::now().sub(TimeDelta::minutes(20)),
+            Utc::now().sub(TimeDelta::minutes(10)),
+            0,
+        );
+        db.insert_subscription_payment(&expired_renewal).await?;
+        let mut expired_purchase = make_subscription_payment(
+            subscription_id,
+            user_id,
+            Utc::now().sub(TimeDelta::minutes(20)),
+            Utc::now().sub(TimeDelta::minutes(10)),
+            1,
+        );
+        expired_purchase.payment_type = lnvps_db::SubscriptionPaymentType::Purchase;
+        db.insert_subscription_payment(&expired_purchase).await?;
+        let mut open_purchase = make_subscription_payment(
+            subscription_id,
+            user_id,
+            Utc::now(),
+            Utc::now().add(TimeDelta::minutes(10)),
+            2,
+        );
+        open_purchase.payment_type = lnvps_db::SubscriptionPaymentType::Purchase;
+        db.insert_subscription_payment(&open_purchase).await?;
+
+        let worker = setup_worker(db.clone()).await?;
+        worker.cancel_expired_payments().await?;
+
+        let canceled = db.canceled_payments.lock().await.clone();
+        assert!(canceled.contains(&expired_renewal.id));
+        assert!(canceled.contains(&expired_purchase.id));
+        assert_eq!(
+            db.list_capacity_reservations(1)
+                .await?
+                .iter()
+                .filter(|r| r.vm_id == Some(vm_id))
+                .count(),
+            1
+        );
+        Ok(())
+    }
+
+    /// State changes seen on the host are stored so admins can filter VMs by state,
+    /// repeated samples of the same state only update the cache.
+    #[tokio::test]
+    async fn test_handle_vm_state_stores_state() -> Result<()> {
+        let db = Arc::new(MockDb::default());
+        let (vm_id, _) = add_vm_with_subscription(&db, Utc::now(), true).await?;
+        let vm = db.get_vm(vm_id).await?;
+        let worker = setup_worker(db.clone()).await?;
+
+        for (state, kind) in [
+            (VmRunningStates::Stopped, VmStateKind::Stopped),
+            (VmRunningStates::Running, VmStateKind::Running),
+        ] {
+            worker
+                .handle_vm_state(
+                    Ok(VmRunningState {
+                        state,
+                        ..Default::default()
+                    }),
+                    &vm,
+                )
+                .await?;
+            assert_eq!(
+                db.vm_states.lock().await.get(&vm_id).map(|s| s.state),
+                Some(kind)
+            );
         }
+
+        // an unchanged state is not written again, only marked as seen
+        let old_seen = Utc::now() - TimeDelta::hours(1);
+        db.vm_states.lock().await.get_mut(&vm_id).unwrap().last_seen = old_seen;
+        let unchanged = worker
+            .handle_vm_state(
+                Ok(VmRunningState {
+                    state: VmRunningStates::Running,
+                    ..Default::default()
+                }),
+                &vm,
+            )
+            .await?;
+        assert!(unchanged);
+        assert_eq!(db.vm_states.lock().await[&vm_id].last_seen, old_seen);
+        db.touch_vm_states(&[vm_id]).await?;
+        let stored = db.vm_states.lock().await[&vm_id].clone();
+        assert_eq!(stored.state, VmStateKind::Running);
+        assert!(stored.last_seen > old_seen);
+        Ok(())
+    }
+
+    /// A migrating VM is reported as such and is not re-created when it
+    /// briefly can't be found on its host.
+    #[tokio::test]
+    async fn test_migrating_vm_left_alone() -> Result<()> {
+        let db = Arc::new(MockDb::default());
+        let (vm_id, _) = add_vm_with_subscription(&db, Utc::now(), true).await?;
+        let vm = db.get_vm(vm_id).await?;
+        let worker = setup_worker(db.clone()).await?;
+
+        worker
+            .handle_vm_state(
+                Ok(VmRunningState {
+                    timestamp: Utc::now().timestamp() as u64,
+                    state: VmRunningStates::Migrating,
+                    ..Default::default()
+                }),
+                &vm,
+            )
+            .await?;
+        assert_eq!(
+            db.vm_states.lock().await.get(&vm_id).map(|s| s.state),
+            Some(VmStateKind::Migrating)
+        );
+
+        worker
+            .handle_vm_state(Err(anyhow!("VM not found in bulk response")), &vm)
+            .await?;
+        assert!(db.list_vm_history(vm_id).await?.is_empty());
+        assert!(db.list_vm_ip_assignments(vm_id).await?.is_empty());
+        assert_eq!(
+            worker
+                .vm_state_cache
+                .get_state(vm_id)
+                .await
+                .map(|s| s.state),
+            Some(VmRunningStates::Migrating)
+        );
+        assert!(worker.recently_migrating(vm_id).await);
+
+        // a migrating state which never settled is not trusted forever
+        worker
+            .vm_state_cache
+            .set_state(
+                vm_id,
+                VmRunningState {
+                    timestamp: Utc::now().timestamp() as u64 - Worker::MIGRATING_GRACE_SECONDS,
+                    state: VmRunningStates::Migrating,
+                    ..Default::default()
+                },
+            )
+            .await?;
+        assert!(!worker.recently_migrating(vm_id).await);
+        Ok(())
     }
 
     /// An unpaid VM (subscription not set up) older than 1 hour must be deleted by check_vms.
@@ -3651,6 +5637,324 @@ mod tests {
         Ok(())
     }
 
+    /// A reload applies new job intervals and ignores a changed database url
+    #[tokio::test]
+    async fn test_reload_settings_applies_interval() -> Result<()> {
+        let db = Arc::new(MockDb::default());
+        let worker = setup_worker(db).await?;
+        let default = Duration::from_secs(30);
+        assert_eq!(worker.job_interval(&WorkJob::CheckVms, default), default);
+
+        let mut applied = mock_settings();
+        let mut new = applied.clone();
+        new.job_intervals.insert("CheckVms".to_string(), 5);
+        new.db = "mysql://other-host/db".to_string();
+        let ignored = worker.reload_settings(&mut applied, &new)?;
+
+        assert_eq!(ignored, vec!["db".to_string()]);
+        assert_eq!(
+            worker.job_interval(&WorkJob::CheckVms, default),
+            Duration::from_secs(5)
+        );
+        assert_eq!(
+            worker.job_interval(&WorkJob::CheckSubscriptions, default),
+            default
+        );
+        // the applied settings track the reload, the ignored db change doesn't
+        assert_eq!(applied.job_intervals, new.job_intervals);
+        assert_ne!(applied.db, new.db);
+
+        // reloading the same file again only reports the still pending db change
+        let ignored = worker.reload_settings(&mut applied, &new)?;
+        assert_eq!(ignored, vec!["db".to_string()]);
+        Ok(())
+    }
+
+    /// A reload applies new retry policies to the provisioner and new job
+    /// concurrency limits to jobs accepted afterwards
+    #[tokio::test]
+    async fn test_reload_settings_applies_retry_and_concurrency() -> Result<()> {
+        let db = Arc::new(MockDb::default());
+        let worker = setup_worker(db).await?;
+        let provisioner = worker.subscription_handler.vm_provisioner();
+        assert_ne!(provisioner.retry_config().host().max_retries, 9);
+
+        let mut applied = mock_settings();
+        let mut new = applied.clone();
+        new.retry.host = Some(crate::settings::RetryPolicyConfig {
+            max_retries: Some(9),
+            ..Default::default()
+        });
+        new.job_concurrency.limits.insert("CheckVm".to_string(), 2);
+        let ignored = worker.reload_settings(&mut applied, &new)?;
+        assert!(ignored.is_empty());
+        assert_eq!(provisioner.retry_config().host().max_retries, 9);
+        assert_eq!(worker.retry.host().max_retries, 9);
+
+        // two CheckVm jobs may now run at once
+        let msg = |id: &str| lnvps_api_common::WorkJobMessage {
+            id: id.to_string(),
+            job: WorkJob::CheckVm { vm_id: 1 },
+            is_pending: false,
+        };
+        let a = worker.job_limiter.accept(&msg("a")).unwrap();
+        let b = worker.job_limiter.accept(&msg("b")).unwrap();
+        let _pa = a.acquire().await;
+        let _pb = tokio::time::timeout(Duration::from_millis(50), b.acquire()).await?;
+        Ok(())
+    }
+
+    /// A worker with no free job slot claims no more jobs from the database
+    /// queue, and keeps renewing the claim of the job waiting for a slot so it
+    /// isn't reclaimed and run twice
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_worker_claims_only_jobs_it_can_run() -> Result<()> {
+        let db = Arc::new(MockDb::default());
+        let mut worker = setup_worker(db.clone()).await?;
+        worker.work_commander = Arc::new(
+            lnvps_api_common::DbWorkCommander::new(db.clone(), "worker")
+                .with_lease(Duration::from_millis(60)),
+        );
+        worker
+            .job_limiter
+            .reconfigure(&crate::settings::JobConcurrencyConfig {
+                default: 1,
+                limits: HashMap::new(),
+            });
+
+        // another job holds the only slot
+        let blocker = worker
+            .job_limiter
+            .accept(&lnvps_api_common::WorkJobMessage {
+                id: "blocker".to_string(),
+                job: WorkJob::CheckVms,
+                is_pending: false,
+            })
+            .unwrap();
+        let permit = blocker.acquire().await;
+
+        worker.work_commander.send(WorkJob::CheckVms).await?;
+        worker.work_commander.send(WorkJob::CheckVms).await?;
+        let handle = {
+            let worker = worker.clone();
+            tokio::spawn(async move { worker.handle().await })
+        };
+
+        // several leases pass while the first job waits for the slot
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        {
+            let queue = db.work_queue.lock().await;
+            let mut items: Vec<_> = queue.values().collect();
+            items.sort_by_key(|j| j.id);
+            assert_eq!(items[0].status, lnvps_db::WorkQueueStatus::Claimed);
+            assert_eq!(items[0].attempts, 0, "waiting job was reclaimed");
+            assert_eq!(items[1].status, lnvps_db::WorkQueueStatus::Queued);
+        }
+
+        handle.abort();
+        drop(permit);
+        Ok(())
+    }
+
+    /// Only VM's without a paid payment and past the grace period are unpaid,
+    /// and reclaiming them purges just those
+    #[tokio::test]
+    async fn test_list_and_reclaim_unpaid_vms() -> Result<()> {
+        let db = Arc::new(MockDb::default());
+        let old = Utc::now().sub(TimeDelta::hours(2));
+        let (unpaid_id, _) = add_vm_with_subscription(&db, old, false).await?;
+        let (recent_id, _) = add_vm_with_subscription(&db, Utc::now(), false).await?;
+        let (paid_id, paid_sub) = add_vm_with_subscription(&db, old, true).await?;
+        let user_id = db.get_vm(paid_id).await?.user_id;
+        let mut payment = make_subscription_payment(paid_sub, user_id, old, old, 1);
+        payment.is_paid = true;
+        db.insert_subscription_payment(&payment).await?;
+
+        let unpaid: Vec<u64> = db
+            .list_unpaid_vms(TimeDelta::hours(1))
+            .await?
+            .into_iter()
+            .map(|v| v.id)
+            .collect();
+        assert_eq!(unpaid, vec![unpaid_id]);
+
+        let worker = setup_worker(db.clone()).await?;
+        let n = worker
+            .reclaim_unpaid_vms(TimeDelta::hours(1), UnpaidVmAction::Delete)
+            .await?;
+        assert_eq!(n, 1);
+        let vms = db.vms.lock().await;
+        assert!(!vms.contains_key(&unpaid_id));
+        assert!(vms.contains_key(&recent_id));
+        assert!(vms.contains_key(&paid_id));
+        Ok(())
+    }
+
+    /// Seed two paid 30 day payments, 40 and 10 days ago, which cover the VM
+    /// until 20 days from now
+    async fn add_vm_with_paid_history(db: &Arc<MockDb>) -> Result<(u64, u64, DateTime<Utc>)> {
+        let now = Utc::now();
+        let (vm_id, subscription_id) =
+            add_vm_with_subscription(db, now.sub(TimeDelta::days(40)), true).await?;
+        let user_id = {
+            let mut vms = db.vms.lock().await;
+            let vm = vms.get_mut(&vm_id).unwrap();
+            vm.created = now.sub(TimeDelta::days(40));
+            vm.user_id
+        };
+        for (i, days_ago) in [40, 10].into_iter().enumerate() {
+            let paid_at = now.sub(TimeDelta::days(days_ago));
+            let mut p = make_subscription_payment(
+                subscription_id,
+                user_id,
+                paid_at,
+                paid_at.add(TimeDelta::minutes(10)),
+                i as u8 + 1,
+            );
+            p.is_paid = true;
+            p.paid_at = Some(paid_at);
+            db.insert_subscription_payment(&p).await?;
+        }
+        Ok((vm_id, subscription_id, now.add(TimeDelta::days(20))))
+    }
+
+    #[test]
+    fn test_implausible_host_discovery() {
+        assert_eq!(implausible_change(0, 64, 50), None);
+        assert_eq!(implausible_change(64, 64, 50), None);
+        assert_eq!(implausible_change(64, 96, 50), None);
+        assert!(implausible_change(64, 0, 50).is_some());
+        assert!(implausible_change(64, 128, 50).is_some());
+        assert!(implausible_change(64, 16, 50).is_some());
+    }
+
+    #[test]
+    fn test_zero_memory_discovery_ignored() {
+        let mut host = VmHost {
+            name: "host-1".to_string(),
+            cpu: 32,
+            memory: 64 * crate::GB,
+            ..Default::default()
+        };
+        let bad_read = VmHostInfo {
+            cpu: 32,
+            memory: 0,
+            disks: vec![],
+        };
+        assert!(!apply_discovered_resources(&mut host, &bad_read, 50, false));
+        assert_eq!(host.memory, 64 * crate::GB);
+
+        // a plausible change to one value is applied while the other is kept
+        let partial = VmHostInfo {
+            cpu: 40,
+            memory: 0,
+            disks: vec![],
+        };
+        assert!(apply_discovered_resources(&mut host, &partial, 50, false));
+        assert_eq!((host.cpu, host.memory), (40, 64 * crate::GB));
+
+        // forced syncs take the read as-is
+        let upgrade = VmHostInfo {
+            cpu: 128,
+            memory: 512 * crate::GB,
+            disks: vec![],
+        };
+        assert!(!apply_discovered_resources(&mut host, &upgrade, 50, false));
+        assert!(apply_discovered_resources(&mut host, &upgrade, 50, true));
+        assert_eq!((host.cpu, host.memory), (128, 512 * crate::GB));
+    }
+
+    #[test]
+    fn test_paid_expiry_from_payments() {
+        let now = Utc::now();
+        let mut first = make_subscription_payment(1, 1, now, now, 1);
+        first.paid_at = Some(now);
+        // a late renewal starts from when it was paid, not the lapsed expiry
+        let mut late = make_subscription_payment(1, 1, now, now, 2);
+        late.paid_at = Some(now.add(TimeDelta::days(45)));
+        assert_eq!(
+            paid_expiry(&[first.clone(), late.clone()]),
+            Some(now.add(TimeDelta::days(75)))
+        );
+        // payments without a time value don't add time
+        let mut untimed = first.clone();
+        untimed.time_value = None;
+        assert_eq!(paid_expiry(&[untimed.clone()]), None);
+        assert_eq!(
+            paid_expiry(&[first.clone(), untimed]),
+            Some(now.add(TimeDelta::days(30)))
+        );
+        assert_eq!(paid_expiry(&[]), None);
+    }
+
+    /// An upgrade paid after the renewal lapsed neither extends nor moves the
+    /// expiry
+    #[test]
+    fn test_paid_expiry_skips_upgrades() {
+        let now = Utc::now();
+        let mut renewal = make_subscription_payment(1, 1, now, now, 1);
+        renewal.paid_at = Some(now);
+        let mut upgrade = make_subscription_payment(1, 1, now, now, 2);
+        upgrade.payment_type = lnvps_db::SubscriptionPaymentType::Upgrade;
+        upgrade.time_value = Some(0);
+        upgrade.paid_at = Some(now.add(TimeDelta::days(40)));
+        let mut legacy_upgrade = upgrade.clone();
+        legacy_upgrade.time_value = None;
+        assert_eq!(
+            paid_expiry(&[renewal, upgrade, legacy_upgrade]),
+            Some(now.add(TimeDelta::days(30)))
+        );
+    }
+
+    /// An expiry short of what was paid for is extended to match
+    #[tokio::test]
+    async fn test_recalculate_vm_expiry_extends_short_expiry() -> Result<()> {
+        let db = Arc::new(MockDb::default());
+        let (vm_id, subscription_id, paid_until) = add_vm_with_paid_history(&db).await?;
+        db.subscriptions
+            .lock()
+            .await
+            .get_mut(&subscription_id)
+            .unwrap()
+            .expires = Some(Utc::now().sub(TimeDelta::days(5)));
+
+        let worker = setup_worker(db.clone()).await?;
+        worker
+            .try_job(&WorkJob::RecalculateVmExpiry { vm_id })
+            .await?;
+
+        let sub = db.get_subscription(subscription_id).await?;
+        let expires = sub.expires.unwrap();
+        assert!((expires - paid_until).abs() < TimeDelta::seconds(5));
+        assert!(sub.is_active);
+        Ok(())
+    }
+
+    /// An expiry beyond what was paid for (e.g. an admin extension) is kept
+    #[tokio::test]
+    async fn test_recalculate_vm_expiry_never_shortens() -> Result<()> {
+        let db = Arc::new(MockDb::default());
+        let (vm_id, subscription_id, _) = add_vm_with_paid_history(&db).await?;
+        let extended = Utc::now().add(TimeDelta::days(90));
+        db.subscriptions
+            .lock()
+            .await
+            .get_mut(&subscription_id)
+            .unwrap()
+            .expires = Some(extended);
+
+        let worker = setup_worker(db.clone()).await?;
+        worker
+            .try_job(&WorkJob::RecalculateVmExpiry { vm_id })
+            .await?;
+
+        assert_eq!(
+            db.get_subscription(subscription_id).await?.expires,
+            Some(extended)
+        );
+        Ok(())
+    }
+
     /// An unpaid VM whose subscription was created less than 1 hour ago must NOT be deleted.
     #[tokio::test]
     async fn test_check_vms_skips_unpaid_vm_within_one_hour() -> Result<()> {
@@ -3803,41 +6107,407 @@ mod tests {
         let base = make_subscription_payment(
             1,
             1,
-            now.sub(TimeDelta::hours(2)),
-            now.sub(TimeDelta::hours(1)),
-            5,
+            now.sub(TimeDelta::hours(2)),
+            now.sub(TimeDelta::hours(1)),
+            5,
+        );
+
+        // Expired lightning payment: does not block.
+        assert!(!payment_blocks_unpaid_vm_deletion(&base, now));
+
+        // Unexpired payment: blocks.
+        let mut p = base.clone();
+        p.expires = now.add(TimeDelta::minutes(10));
+        assert!(payment_blocks_unpaid_vm_deletion(&p, now));
+
+        // Expired on-chain with detected deposit: blocks (#194).
+        let mut p = base.clone();
+        p.payment_method = lnvps_db::PaymentMethod::OnChain;
+        p.external_id = Some("txid:0".to_string());
+        assert!(payment_blocks_unpaid_vm_deletion(&p, now));
+
+        // Expired on-chain without detected deposit: does not block.
+        let mut p = base.clone();
+        p.payment_method = lnvps_db::PaymentMethod::OnChain;
+        assert!(!payment_blocks_unpaid_vm_deletion(&p, now));
+
+        // Expired lightning with external_id (not on-chain): does not block.
+        let mut p = base.clone();
+        p.external_id = Some("abc".to_string());
+        assert!(!payment_blocks_unpaid_vm_deletion(&p, now));
+
+        // Paid payment never blocks, even with detected deposit.
+        let mut p = base.clone();
+        p.payment_method = lnvps_db::PaymentMethod::OnChain;
+        p.external_id = Some("txid:0".to_string());
+        p.is_paid = true;
+        assert!(!payment_blocks_unpaid_vm_deletion(&p, now));
+    }
+
+    /// Drain all currently-queued work jobs without blocking
+    async fn queued_jobs(worker: &Worker) -> Vec<WorkJob> {
+        let mut ret = Vec::new();
+        while let Ok(Ok(jobs)) = tokio::time::timeout(
+            std::time::Duration::from_millis(50),
+            worker.work_commander.recv(),
+        )
+        .await
+        {
+            ret.extend(jobs.into_iter().map(|j| j.job));
+        }
+        ret
+    }
+
+    /// Draining a host stops its VMs, or migrates them when a target host
+    /// which can take them live is given
+    #[tokio::test]
+    async fn test_drain_host_queues_vm_jobs() -> Result<()> {
+        let db = Arc::new(MockDb::default());
+        let (vm1, _) = add_vm_with_subscription(&db, Utc::now(), true).await?;
+        let (vm2, _) = add_vm_with_subscription(&db, Utc::now(), true).await?;
+        let worker = setup_worker(db.clone()).await?;
+
+        worker.drain_host(1, None, None, Some(1)).await?;
+        assert!(db.get_host(1).await?.maintenance);
+        let mut stopped: Vec<u64> = queued_jobs(&worker)
+            .await
+            .into_iter()
+            .filter_map(|j| match j {
+                WorkJob::StopVm {
+                    vm_id,
+                    admin_user_id: Some(1),
+                } => Some(vm_id),
+                _ => None,
+            })
+            .collect();
+        stopped.sort();
+        assert_eq!(stopped, vec![vm1, vm2]);
+
+        {
+            let mut hosts = db.hosts.lock().await;
+            let host = hosts.get_mut(&1).unwrap();
+            host.kind = VmHostKind::Proxmox;
+            let mut target = host.clone();
+            target.id = 2;
+            target.name = "mock-host-2".to_string();
+            target.maintenance = false;
+            hosts.insert(2, target);
+        }
+        worker.drain_host(1, Some(2), None, Some(1)).await?;
+        let mut migrated: Vec<u64> = queued_jobs(&worker)
+            .await
+            .into_iter()
+            .filter_map(|j| match j {
+                WorkJob::MigrateVm {
+                    vm_id,
+                    target_host_id: 2,
+                    ..
+                } => Some(vm_id),
+                WorkJob::StopVm { .. } => panic!("VM stopped instead of migrated"),
+                _ => None,
+            })
+            .collect();
+        migrated.sort();
+        assert_eq!(migrated, vec![vm1, vm2]);
+        Ok(())
+    }
+
+    /// A private network VM is not migrated to a host without a private
+    /// bridge, it is stopped instead
+    #[tokio::test]
+    async fn test_migrate_private_network_vm_needs_private_bridge() -> Result<()> {
+        let db = Arc::new(MockDb::default());
+        let (vm1, _) = add_vm_with_subscription(&db, Utc::now(), true).await?;
+        db.vms.lock().await.get_mut(&vm1).unwrap().private_network = true;
+        {
+            let mut hosts = db.hosts.lock().await;
+            let host = hosts.get_mut(&1).unwrap();
+            host.kind = VmHostKind::Proxmox;
+            host.private_bridge = Some("vmbr9".to_string());
+            let mut target = host.clone();
+            target.id = 2;
+            target.name = "mock-host-2".to_string();
+            target.private_bridge = None;
+            hosts.insert(2, target);
+        }
+        let worker = setup_worker(db.clone()).await?;
+
+        let msg = worker.migrate_vm(vm1, 2, None, Some(1)).await?;
+        assert!(msg.contains("on the private network"), "{}", msg);
+        assert_eq!(db.get_vm(vm1).await?.host_id, 1);
+        let jobs = queued_jobs(&worker).await;
+        assert!(
+            jobs.iter()
+                .any(|j| matches!(j, WorkJob::StopVm { vm_id, .. } if *vm_id == vm1))
+        );
+        Ok(())
+    }
+
+    /// A VM is not migrated to a host without room for its template, it is
+    /// stopped instead
+    #[tokio::test]
+    async fn test_migrate_vm_needs_target_capacity() -> Result<()> {
+        let db = Arc::new(MockDb::default());
+        let (vm1, _) = add_vm_with_subscription(&db, Utc::now(), true).await?;
+        {
+            let mut hosts = db.hosts.lock().await;
+            let host = hosts.get_mut(&1).unwrap();
+            host.kind = VmHostKind::Proxmox;
+            let mut target = host.clone();
+            target.id = 2;
+            target.name = "mock-host-2".to_string();
+            target.maintenance = false;
+            hosts.insert(2, target);
+        }
+        let worker = setup_worker(db.clone()).await?;
+
+        // the target host has no disks
+        let msg = worker.migrate_vm(vm1, 2, None, Some(1)).await?;
+        assert!(msg.contains("has no room for VM"), "{}", msg);
+        let vm = db.get_vm(vm1).await?;
+        assert_eq!((vm.host_id, vm.disk_id), (1, 1));
+        let jobs = queued_jobs(&worker).await;
+        assert!(
+            jobs.iter()
+                .any(|j| matches!(j, WorkJob::StopVm { vm_id, .. } if *vm_id == vm1))
+        );
+        Ok(())
+    }
+
+    /// The target's resources are reserved for the migration and released
+    /// once it is over, here a failed one
+    #[tokio::test]
+    async fn test_migrate_vm_releases_reservation() -> Result<()> {
+        let db = Arc::new(MockDb::default());
+        let (vm1, _) = add_vm_with_subscription(&db, Utc::now(), true).await?;
+        {
+            let mut hosts = db.hosts.lock().await;
+            let host = hosts.get_mut(&1).unwrap();
+            host.kind = VmHostKind::Proxmox;
+            let mut target = host.clone();
+            target.id = 2;
+            target.name = "mock-host-2".to_string();
+            target.maintenance = false;
+            hosts.insert(2, target);
+            let mut disks = db.host_disks.lock().await;
+            let mut disk = disks.get(&1).unwrap().clone();
+            disk.id = 2;
+            disk.host_id = 2;
+            disks.insert(2, disk);
+        }
+        let worker = setup_worker(db.clone()).await?;
+
+        // the test settings have no Proxmox config, so the migration fails
+        // after the target disk was reserved
+        let msg = worker.migrate_vm(vm1, 2, None, Some(1)).await?;
+        assert!(msg.contains("failed"), "{}", msg);
+        assert!(db.capacity_reservations.lock().await.is_empty());
+        assert_eq!(db.get_vm(vm1).await?.host_id, 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_reboot_vm_job() -> Result<()> {
+        let db = Arc::new(MockDb::default());
+        let (vm_id, _) = add_vm_with_subscription(&db, Utc::now(), true).await?;
+        let worker = setup_worker(db.clone()).await?;
+
+        let res = worker
+            .try_job(&WorkJob::RebootVm { vm_id, user_id: 7 })
+            .await?;
+        assert_eq!(res, Some(format!("VM {} rebooted", vm_id)));
+        let history = db.list_vm_history(vm_id).await?;
+        assert!(history.iter().any(|h| {
+            matches!(h.action_type, VmHistoryActionType::Rebooted) && h.initiated_by_user == Some(7)
+        }));
+        // the new state is picked up straight away
+        assert!(
+            queued_jobs(&worker)
+                .await
+                .iter()
+                .any(|j| matches!(j, WorkJob::CheckVm { vm_id: id } if *id == vm_id))
+        );
+
+        // deleted VMs are not touched
+        db.vms.lock().await.get_mut(&vm_id).unwrap().deleted = true;
+        assert!(
+            worker
+                .try_job(&WorkJob::RebootVm { vm_id, user_id: 7 })
+                .await
+                .is_err()
+        );
+        Ok(())
+    }
+
+    /// A migration which finishes on the host completes the run
+    #[tokio::test(start_paused = true)]
+    async fn test_run_migration_finishes() -> Result<()> {
+        use crate::host::dummy_host::DummyVmHost;
+        use crate::host::tests::mock_full_vm;
+
+        let db = Arc::new(MockDb::default());
+        let worker = setup_worker(db.clone()).await?;
+        let client = DummyVmHost::new();
+        let cfg = mock_full_vm();
+        client.create_vm(&cfg, RetryPolicy::default()).await?;
+        let mut target = db.get_host(1).await?;
+        target.id = 2;
+        let mut disk = db.get_host_disk(1).await?;
+        disk.host_id = 2;
+
+        worker
+            .run_migration(&client, &cfg.vm, &target, &disk, None)
+            .await?;
+
+        // a VM the host doesn't have can't be migrated
+        let mut missing = cfg.vm.clone();
+        missing.id = 999;
+        assert!(
+            worker
+                .run_migration(&client, &missing, &target, &disk, None)
+                .await
+                .is_err()
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_migration_disabled_by_feature_flag() -> Result<()> {
+        let db = Arc::new(MockDb::default());
+        let (vm1, _) = add_vm_with_subscription(&db, Utc::now(), true).await?;
+        {
+            let mut hosts = db.hosts.lock().await;
+            let host = hosts.get_mut(&1).unwrap();
+            host.kind = VmHostKind::Proxmox;
+            let mut target = host.clone();
+            target.id = 2;
+            target.name = "mock-host-2".to_string();
+            hosts.insert(2, target);
+        }
+        db.feature_flags.lock().await.insert(
+            1,
+            lnvps_db::FeatureFlag {
+                id: 1,
+                key: FEATURE_VM_MIGRATION.to_string(),
+                region_id: None,
+                enabled: false,
+                ..Default::default()
+            },
         );
+        let worker = setup_worker(db.clone()).await?;
 
-        // Expired lightning payment: does not block.
-        assert!(!payment_blocks_unpaid_vm_deletion(&base, now));
+        let err = worker.migrate_vm(vm1, 2, None, Some(1)).await.unwrap_err();
+        assert_eq!(err.to_string(), "The vm_migration feature is not available");
+        assert_eq!(db.get_vm(vm1).await?.host_id, 1);
 
-        // Unexpired payment: blocks.
-        let mut p = base.clone();
-        p.expires = now.add(TimeDelta::minutes(10));
-        assert!(payment_blocks_unpaid_vm_deletion(&p, now));
+        // draining stops the VMs instead of migrating them
+        worker.drain_host(1, Some(2), None, Some(1)).await?;
+        let stopped: Vec<u64> = queued_jobs(&worker)
+            .await
+            .into_iter()
+            .filter_map(|j| match j {
+                WorkJob::StopVm { vm_id, .. } => Some(vm_id),
+                WorkJob::MigrateVm { .. } => panic!("VM migrated while migration is disabled"),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(stopped, vec![vm1]);
+        Ok(())
+    }
 
-        // Expired on-chain with detected deposit: blocks (#194).
-        let mut p = base.clone();
-        p.payment_method = lnvps_db::PaymentMethod::OnChain;
-        p.external_id = Some("txid:0".to_string());
-        assert!(payment_blocks_unpaid_vm_deletion(&p, now));
+    /// Backups and auto-renewal switched off in a VM's region are skipped,
+    /// flags of other regions don't matter
+    #[tokio::test]
+    async fn test_backups_and_auto_renewal_gated_per_region() -> Result<()> {
+        let db = Arc::new(MockDb::default());
+        let (vm1, sub_id) = add_vm_with_subscription(&db, Utc::now(), true).await?;
+        db.upsert_vm_backup_schedule(&lnvps_db::VmBackupSchedule {
+            vm_id: vm1,
+            interval_hours: 24,
+            enabled: true,
+            created: Utc::now() - TimeDelta::hours(1),
+            ..Default::default()
+        })
+        .await?;
+        {
+            let mut flags = db.feature_flags.lock().await;
+            for (id, key, region_id) in [
+                (1, FEATURE_BACKUPS, 1),
+                (2, FEATURE_AUTO_RENEWAL, 1),
+                (3, FEATURE_AUTO_RENEWAL, 2),
+            ] {
+                flags.insert(
+                    id,
+                    lnvps_db::FeatureFlag {
+                        id,
+                        key: key.to_string(),
+                        region_id: Some(region_id),
+                        enabled: region_id != 1,
+                        ..Default::default()
+                    },
+                );
+            }
+        }
+        let worker = setup_worker(db.clone()).await?;
+        let line_items = db.list_subscription_line_items(sub_id).await?;
 
-        // Expired on-chain without detected deposit: does not block.
-        let mut p = base.clone();
-        p.payment_method = lnvps_db::PaymentMethod::OnChain;
-        assert!(!payment_blocks_unpaid_vm_deletion(&p, now));
+        worker.check_backups().await?;
+        assert!(
+            !queued_jobs(&worker)
+                .await
+                .iter()
+                .any(|j| matches!(j, WorkJob::BackupVm { .. }))
+        );
+        assert!(
+            db.get_vm_backup_schedule(vm1)
+                .await?
+                .unwrap()
+                .last_run
+                .is_none()
+        );
+        assert!(!worker.auto_renewal_available(&line_items).await);
 
-        // Expired lightning with external_id (not on-chain): does not block.
-        let mut p = base.clone();
-        p.external_id = Some("abc".to_string());
-        assert!(!payment_blocks_unpaid_vm_deletion(&p, now));
+        db.feature_flags.lock().await.clear();
+        worker.features.invalidate().await;
+        worker.check_backups().await?;
+        assert!(
+            queued_jobs(&worker)
+                .await
+                .iter()
+                .any(|j| matches!(j, WorkJob::BackupVm { vm_id } if *vm_id == vm1))
+        );
+        assert!(worker.auto_renewal_available(&line_items).await);
+        Ok(())
+    }
 
-        // Paid payment never blocks, even with detected deposit.
-        let mut p = base.clone();
-        p.payment_method = lnvps_db::PaymentMethod::OnChain;
-        p.external_id = Some("txid:0".to_string());
-        p.is_paid = true;
-        assert!(!payment_blocks_unpaid_vm_deletion(&p, now));
+    /// A schedule which can't be resolved is skipped without stopping the others
+    #[tokio::test]
+    async fn test_check_backups_skips_broken_schedule() -> Result<()> {
+        let db = Arc::new(MockDb::default());
+        let (broken_id, _) = add_vm_with_subscription(&db, Utc::now(), true).await?;
+        let (vm_id, _) = add_vm_with_subscription(&db, Utc::now(), true).await?;
+        // host no longer exists
+        db.vms.lock().await.get_mut(&broken_id).unwrap().host_id = 999;
+        for id in [broken_id, vm_id] {
+            db.upsert_vm_backup_schedule(&lnvps_db::VmBackupSchedule {
+                vm_id: id,
+                interval_hours: 24,
+                enabled: true,
+                created: Utc::now() - TimeDelta::hours(1),
+                ..Default::default()
+            })
+            .await?;
+        }
+        let worker = setup_worker(db.clone()).await?;
+
+        worker.check_backups().await?;
+        assert!(
+            queued_jobs(&worker)
+                .await
+                .iter()
+                .any(|j| matches!(j, WorkJob::BackupVm { vm_id: id } if *id == vm_id))
+        );
+        Ok(())
     }
 
     /// Drain all currently-queued work jobs without blocking, returning the count of
@@ -3908,6 +6578,203 @@ mod tests {
         Ok(())
     }
 
+    /// Expired VMs are stopped first and only deleted once the grace period has
+    /// elapsed, with a reminder one day before deletion. Time is simulated by
+    /// moving the subscription's `expires` backwards between checks.
+    #[tokio::test]
+    async fn test_expired_vm_stop_then_delete_progression() -> Result<()> {
+        let db = Arc::new(MockDb::default());
+        // 40 day old subscription => 14 day grace tier
+        let created = Utc::now().sub(TimeDelta::days(40));
+        let (vm_id, subscription_id) = add_vm_with_subscription(&db, created, true).await?;
+        let worker = setup_worker_with_delete_after(db.clone(), 30).await?;
+        let grace = worker.grace_period_days(&db.get_subscription(subscription_id).await?) as i64;
+        assert_eq!(grace, 14);
+
+        let set_expires = |expires: DateTime<Utc>| {
+            let db = db.clone();
+            async move {
+                let mut sub = db.get_subscription(subscription_id).await?;
+                sub.expires = Some(expires);
+                db.update_subscription(&sub).await?;
+                anyhow::Ok(sub)
+            }
+        };
+
+        // Phase 1: just expired => stopped, history recorded, not deleted
+        let expires = Utc::now().sub(TimeDelta::hours(1));
+        let sub = set_expires(expires).await?;
+        worker
+            .handle_subscription_state(&sub, expires.sub(TimeDelta::minutes(30)))
+            .await?;
+        assert!(count_notifications(&worker, "Expired").await >= 1);
+        assert!(
+            db.list_vm_history(vm_id)
+                .await?
+                .iter()
+                .any(|h| matches!(h.action_type, VmHistoryActionType::Expired))
+        );
+        assert!(!db.get_vm(vm_id).await?.deleted);
+        assert!(
+            db.list_vms_expired_before(Utc::now())
+                .await?
+                .iter()
+                .any(|v| v.id == vm_id)
+        );
+        assert!(
+            db.list_vms_expired_before(Utc::now().sub(TimeDelta::days(grace)))
+                .await?
+                .is_empty(),
+            "VM must not be pending deletion yet"
+        );
+
+        // Phase 2: one day before deletion => reminder only
+        let expires = Utc::now()
+            .sub(TimeDelta::days(grace - 1))
+            .sub(TimeDelta::hours(1));
+        let sub = set_expires(expires).await?;
+        worker
+            .handle_subscription_state(&sub, Utc::now().sub(TimeDelta::hours(2)))
+            .await?;
+        assert_eq!(count_notifications(&worker, "Deletion Pending").await, 1);
+        assert!(!db.get_vm(vm_id).await?.deleted);
+
+        // Next cycle must not repeat the reminder
+        worker
+            .handle_subscription_state(&sub, Utc::now().sub(TimeDelta::minutes(1)))
+            .await?;
+        assert_eq!(count_notifications(&worker, "Deletion Pending").await, 0);
+
+        // Phase 3: grace elapsed => deleted
+        let expires = Utc::now().sub(TimeDelta::days(grace + 1));
+        let sub = set_expires(expires).await?;
+        assert!(
+            db.list_vms_expired_before(Utc::now().sub(TimeDelta::days(grace)))
+                .await?
+                .iter()
+                .any(|v| v.id == vm_id)
+        );
+        worker
+            .handle_subscription_state(&sub, Utc::now().sub(TimeDelta::minutes(1)))
+            .await?;
+        assert!(db.get_vm(vm_id).await?.deleted);
+        assert!(!db.get_subscription(subscription_id).await?.is_active);
+        assert!(db.list_vms_expired_before(Utc::now()).await?.is_empty());
+        Ok(())
+    }
+
+    /// A VM suspended while suspended time isn't billed is neither stopped
+    /// nor deleted when its subscription passes expiry
+    #[tokio::test]
+    async fn test_paused_suspended_vm_skips_expiry() -> Result<()> {
+        let db = Arc::new(MockDb::default());
+        let created = Utc::now().sub(TimeDelta::days(40));
+        let (vm_id, subscription_id) = add_vm_with_subscription(&db, created, true).await?;
+        db.set_vm_suspended_at(vm_id, Utc::now().sub(TimeDelta::days(20)))
+            .await?;
+        let mut worker = setup_worker_with_delete_after(db.clone(), 30).await?;
+        worker.settings.suspended_billing = SuspendedBilling::Paused;
+
+        let mut sub = db.get_subscription(subscription_id).await?;
+        sub.expires = Some(Utc::now().sub(TimeDelta::days(30)));
+        db.update_subscription(&sub).await?;
+        worker
+            .handle_subscription_state(&sub, Utc::now().sub(TimeDelta::days(31)))
+            .await?;
+        assert_eq!(count_notifications(&worker, "Expired").await, 0);
+        assert!(!db.get_vm(vm_id).await?.deleted);
+        assert!(db.get_subscription(subscription_id).await?.is_active);
+        assert!(
+            !db.list_vm_history(vm_id)
+                .await?
+                .iter()
+                .any(|h| matches!(h.action_type, VmHistoryActionType::Expired))
+        );
+
+        // a suspension longer than the cap no longer defers expiry
+        worker.settings.max_suspended_days = 10;
+        worker
+            .handle_subscription_state(&sub, Utc::now().sub(TimeDelta::days(31)))
+            .await?;
+        assert!(db.get_vm(vm_id).await?.deleted);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_paused_charged_suspended_vm_expires() -> Result<()> {
+        let db = Arc::new(MockDb::default());
+        let created = Utc::now().sub(TimeDelta::days(40));
+        let (vm_id, subscription_id) = add_vm_with_subscription(&db, created, true).await?;
+        db.set_vm_suspended_at(vm_id, Utc::now().sub(TimeDelta::days(20)))
+            .await?;
+        let worker = setup_worker_with_delete_after(db.clone(), 30).await?;
+
+        // charged suspended VMs expire as usual
+        let mut sub = db.get_subscription(subscription_id).await?;
+        sub.expires = Some(Utc::now().sub(TimeDelta::days(30)));
+        db.update_subscription(&sub).await?;
+        worker
+            .handle_subscription_state(&sub, Utc::now().sub(TimeDelta::days(31)))
+            .await?;
+        assert!(db.get_vm(vm_id).await?.deleted);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_running_vm_ends_suspension() -> Result<()> {
+        let db = Arc::new(MockDb::default());
+        let (vm_id, subscription_id) = add_vm_with_subscription(&db, Utc::now(), true).await?;
+        let expires = db.get_subscription(subscription_id).await?.expires.unwrap();
+        let mut worker = setup_worker(db.clone()).await?;
+        worker.settings.suspended_billing = SuspendedBilling::Paused;
+        worker.settings.max_suspended_days = 30;
+        let running = || -> Result<VmRunningState> {
+            Ok(VmRunningState {
+                state: VmRunningStates::Running,
+                ..Default::default()
+            })
+        };
+
+        // still suspended on the host, nothing changes
+        db.set_vm_suspended_at(vm_id, Utc::now().sub(TimeDelta::days(2)))
+            .await?;
+        let vm = db.get_vm(vm_id).await?;
+        worker
+            .handle_vm_state(
+                Ok(VmRunningState {
+                    state: VmRunningStates::Suspended,
+                    ..Default::default()
+                }),
+                &vm,
+            )
+            .await?;
+        assert!(db.get_vm(vm_id).await?.suspended_at.is_some());
+
+        // started by someone other than the owner, the suspension is credited
+        worker.handle_vm_state(running(), &vm).await?;
+        assert!(db.get_vm(vm_id).await?.suspended_at.is_none());
+        let credited = db.get_subscription(subscription_id).await?.expires.unwrap() - expires;
+        assert!((credited - TimeDelta::days(2)).num_seconds().abs() < 5);
+
+        // only once, even from a stale copy of the VM
+        worker.handle_vm_state(running(), &vm).await?;
+        assert_eq!(
+            db.get_subscription(subscription_id).await?.expires,
+            Some(expires + credited)
+        );
+
+        // and at most for the cap
+        db.set_vm_suspended_at(vm_id, Utc::now().sub(TimeDelta::days(90)))
+            .await?;
+        let vm = db.get_vm(vm_id).await?;
+        worker.handle_vm_state(running(), &vm).await?;
+        assert_eq!(
+            db.get_subscription(subscription_id).await?.expires,
+            Some(expires + credited + TimeDelta::days(30))
+        );
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_sync_router_state() -> Result<()> {
         use crate::mocks::MockRouter;
@@ -4133,4 +7000,379 @@ mod tests {
         mr.clear().await;
         Ok(())
     }
+
+    async fn add_vm_with_ip(db: &Arc<MockDb>, ip: &str) -> Result<Vm> {
+        let (vm_id, _) = add_vm_with_subscription(db, Utc::now(), true).await?;
+        db.insert_vm_ip_assignment(&VmIpAssignment {
+            vm_id,
+            ip_range_id: 1,
+            ip: ip.to_string(),
+            ..Default::default()
+        })
+        .await?;
+        Ok(db.get_vm(vm_id).await?)
+    }
+
+    async fn reachability_history(db: &Arc<MockDb>, vm_id: u64) -> Result<Vec<bool>> {
+        let mut history = db.list_vm_history(vm_id).await?;
+        history.sort_by_key(|h| h.id);
+        Ok(history
+            .into_iter()
+            .filter(|h| matches!(h.action_type, VmHistoryActionType::ReachabilityChanged))
+            .map(|h| {
+                let v: serde_json::Value =
+                    serde_json::from_slice(h.new_state.as_ref().unwrap()).unwrap();
+                v["reachable"].as_bool().unwrap()
+            })
+            .collect())
+    }
+
+    /// A previously-reachable VM is only reported unreachable after
+    /// `failure_threshold` consecutive failed probes, and reported again once
+    /// it answers.
+    #[tokio::test]
+    async fn test_reachability_notifies_after_consecutive_failures() -> Result<()> {
+        let db = Arc::new(MockDb::default());
+        let vm = add_vm_with_ip(&db, "10.0.0.2").await?;
+        let mut worker = setup_worker(db.clone()).await?;
+        worker.settings.reachability.failure_threshold = 3;
+        let probe = MockReachabilityProbe::default();
+        worker.reachability_probe = Arc::new(probe.clone());
+
+        probe.set_reachable(true);
+        worker.check_vm_reachability(&vm).await;
+        assert_eq!(reachability_history(&db, vm.id).await?, vec![true]);
+
+        probe.set_reachable(false);
+        for _ in 0..2 {
+            worker.check_vm_reachability(&vm).await;
+        }
+        assert_eq!(count_notifications(&worker, "Unreachable").await, 0);
+        assert_eq!(reachability_history(&db, vm.id).await?, vec![true]);
+
+        worker.check_vm_reachability(&vm).await;
+        assert_eq!(count_notifications(&worker, "Unreachable").await, 1);
+        assert_eq!(reachability_history(&db, vm.id).await?, vec![true, false]);
+
+        // still down, no repeat
+        worker.check_vm_reachability(&vm).await;
+        assert_eq!(count_notifications(&worker, "Unreachable").await, 0);
+
+        probe.set_reachable(true);
+        worker.check_vm_reachability(&vm).await;
+        assert_eq!(count_notifications(&worker, "Reachable").await, 1);
+        assert_eq!(
+            reachability_history(&db, vm.id).await?,
+            vec![true, false, true]
+        );
+        assert!(
+            probe
+                .probes
+                .lock()
+                .await
+                .iter()
+                .all(|ip| ip.to_string() == "10.0.0.2")
+        );
+        Ok(())
+    }
+
+    /// A VM which never answered a probe (e.g. firewalled from the start) is
+    /// never reported as having become unreachable.
+    #[tokio::test]
+    async fn test_reachability_ignores_never_reachable_vm() -> Result<()> {
+        let db = Arc::new(MockDb::default());
+        let vm = add_vm_with_ip(&db, "10.0.0.3").await?;
+        let mut worker = setup_worker(db.clone()).await?;
+        let probe = MockReachabilityProbe::default();
+        worker.reachability_probe = Arc::new(probe.clone());
+
+        for _ in 0..5 {
+            worker.check_vm_reachability(&vm).await;
+        }
+        assert_eq!(count_notifications(&worker, "Unreachable").await, 0);
+        assert!(reachability_history(&db, vm.id).await?.is_empty());
+        assert_eq!(probe.probes.lock().await.len(), 5);
+        Ok(())
+    }
+
+    async fn add_usage_alert(db: &Arc<MockDb>, vm_id: u64, threshold: f64) -> Result<u64> {
+        Ok(db
+            .insert_vm_usage_alert(&VmUsageAlert {
+                vm_id,
+                metric: VmUsageMetric::Cpu,
+                threshold,
+                window_seconds: 3600,
+                ..Default::default()
+            })
+            .await?)
+    }
+
+    #[test]
+    fn test_usage_alert_value_averages_window() {
+        let sample = |timestamp: u64, cpu: f32| TimeSeriesData {
+            timestamp,
+            cpu,
+            memory: 512.0,
+            memory_size: 1024,
+            net_in: 0.0,
+            net_out: 0.0,
+            disk_write: 0.0,
+            disk_read: 0.0,
+        };
+        let series = vec![sample(100, 1.0), sample(900, 0.5), sample(1000, 0.7)];
+        let mut alert = VmUsageAlert {
+            window_seconds: 200,
+            ..Default::default()
+        };
+        let cpu = usage_alert_value(&alert, &series, 1000).unwrap();
+        assert!((cpu - 60.0).abs() < 0.01);
+
+        alert.metric = VmUsageMetric::Memory;
+        assert_eq!(usage_alert_value(&alert, &series, 1000), Some(50.0));
+
+        // nothing inside the window
+        assert_eq!(usage_alert_value(&alert, &series, 5000), None);
+
+        // disk usage comes from the guest agent, not the series
+        alert.metric = VmUsageMetric::DiskUsage;
+        assert_eq!(usage_alert_value(&alert, &series, 1000), None);
+    }
+
+    /// Two runs over overlapping series only count each sample once.
+    #[tokio::test]
+    async fn test_bandwidth_usage_accumulates_deltas() -> Result<()> {
+        let sample = |timestamp: i64, net_in: f32, net_out: f32| TimeSeriesData {
+            timestamp: timestamp as u64,
+            cpu: 0.0,
+            memory: 0.0,
+            memory_size: 0,
+            net_in,
+            net_out,
+            disk_write: 0.0,
+            disk_read: 0.0,
+        };
+        let db = MockDb::default();
+        let base = DateTime::parse_from_rfc3339("2026-03-15T12:00:00Z")?.timestamp();
+        let month = VmBandwidthUsage::month_of(DateTime::from_timestamp(base, 0).unwrap());
+
+        // the first sample only marks the start of the series
+        let first = vec![
+            sample(base, 999.0, 999.0),
+            sample(base + 60, 100.0, 10.0),
+            sample(base + 120, 200.0, 20.0),
+        ];
+        let after = db.get_vm_bandwidth_last_sample(1).await?;
+        assert_eq!(record_bandwidth_usage(&db, 1, &first, after).await?, 1);
+        let usage = db.get_vm_bandwidth_usage(1, month).await?.unwrap();
+        assert_eq!(usage.bytes_in, 18_000);
+        assert_eq!(usage.bytes_out, 1_800);
+
+        let second = vec![
+            sample(base + 60, 100.0, 10.0),
+            sample(base + 120, 200.0, 20.0),
+            sample(base + 180, 50.0, 5.0),
+        ];
+        let after = db.get_vm_bandwidth_last_sample(1).await?;
+        record_bandwidth_usage(&db, 1, &second, after).await?;
+        let usage = db.get_vm_bandwidth_usage(1, month).await?.unwrap();
+        assert_eq!(usage.bytes_in, 21_000);
+        assert_eq!(usage.bytes_out, 2_100);
+        assert_eq!(usage.last_sample.timestamp(), base + 180);
+
+        // a second worker which read the same starting point adds nothing
+        assert_eq!(record_bandwidth_usage(&db, 1, &second, after).await?, 0);
+        let usage = db.get_vm_bandwidth_usage(1, month).await?.unwrap();
+        assert_eq!(usage.bytes_in, 21_000);
+
+        // a coarse sample spanning the last counted sample only counts the
+        // time after it
+        let coarse = vec![sample(base, 0.0, 0.0), sample(base + 1800, 10.0, 0.0)];
+        let after = db.get_vm_bandwidth_last_sample(1).await?;
+        record_bandwidth_usage(&db, 1, &coarse, after).await?;
+        let usage = db.get_vm_bandwidth_usage(1, month).await?.unwrap();
+        assert_eq!(usage.bytes_in, 21_000 + 10 * (1800 - 180));
+        assert_eq!(usage.last_sample.timestamp(), base + 1800);
+
+        // nothing new
+        assert!(bandwidth_usage(1, &second, Some(usage.last_sample)).is_empty());
+
+        // samples after midnight go to the next month
+        let end = DateTime::parse_from_rfc3339("2026-03-31T23:59:00Z")?.timestamp();
+        let split = bandwidth_usage(
+            1,
+            &[
+                sample(end - 60, 0.0, 0.0),
+                sample(end, 1.0, 0.0),
+                sample(end + 60, 2.0, 0.0),
+            ],
+            None,
+        );
+        assert_eq!(split.len(), 2);
+        assert_eq!(split[0].month, month);
+        assert_eq!(split[0].bytes_in, 60);
+        assert_eq!(split[1].month.to_rfc3339(), "2026-04-01T00:00:00+00:00");
+        assert_eq!(split[1].bytes_in, 120);
+        Ok(())
+    }
+
+    /// After an outage longer than the hourly series the gap is counted from
+    /// a coarser series instead of being dropped.
+    #[tokio::test]
+    async fn test_bandwidth_usage_after_outage() -> Result<()> {
+        use crate::host::dummy_host::DummyVmHost;
+
+        let db = Arc::new(MockDb::default());
+        let (vm_id, _) = add_vm_with_subscription(&db, Utc::now(), true).await?;
+        let vm = db.get_vm(vm_id).await?;
+        let worker = setup_worker(db.clone()).await?;
+        let host = DummyVmHost::new();
+
+        let after = Utc::now() - TimeDelta::hours(5);
+        db.bandwidth_usage.lock().await.insert(
+            (vm_id, VmBandwidthUsage::month_of(after)),
+            VmBandwidthUsage {
+                vm_id,
+                month: VmBandwidthUsage::month_of(after),
+                last_sample: after,
+                ..Default::default()
+            },
+        );
+        let hourly = host.get_time_series_data(&vm, TimeSeries::Hourly).await?;
+        let hourly_in: u64 = bandwidth_usage(vm_id, &hourly, Some(after))
+            .iter()
+            .map(|u| u.bytes_in)
+            .sum();
+
+        worker.check_vm_bandwidth_usage(&host, &vm).await?;
+        let counted_in: u64 = db
+            .bandwidth_usage
+            .lock()
+            .await
+            .values()
+            .filter(|u| u.vm_id == vm_id)
+            .map(|u| u.bytes_in)
+            .sum();
+        assert!(
+            counted_in > hourly_in * 2,
+            "outage not covered: {} vs hourly {}",
+            counted_in,
+            hourly_in
+        );
+        Ok(())
+    }
+
+    /// A breached usage alert notifies once, then stays quiet until the
+    /// cooldown has passed.
+    #[tokio::test]
+    async fn test_usage_alert_notifies_once_within_cooldown() -> Result<()> {
+        let db = Arc::new(MockDb::default());
+        let (vm_id, _) = add_vm_with_subscription(&db, Utc::now(), true).await?;
+        // dummy host reports ~24% average CPU over the last hour
+        let alert_id = add_usage_alert(&db, vm_id, 1.0).await?;
+        let worker = setup_worker(db.clone()).await?;
+
+        worker.check_usage_alerts().await?;
+        worker.check_usage_alerts().await?;
+        assert_eq!(count_notifications(&worker, "High CPU usage").await, 1);
+        let triggered = db.get_vm_usage_alert(alert_id).await?.last_triggered;
+        assert!(triggered.is_some());
+
+        // cooldown elapsed, still breached -> notify again
+        db.set_vm_usage_alert_triggered(
+            alert_id,
+            Utc::now() - TimeDelta::seconds(Worker::USAGE_ALERT_COOLDOWN_SECONDS + 1),
+        )
+        .await?;
+        worker.check_usage_alerts().await?;
+        assert_eq!(count_notifications(&worker, "High CPU usage").await, 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_usage_alert_below_threshold_is_quiet() -> Result<()> {
+        let db = Arc::new(MockDb::default());
+        let (vm_id, _) = add_vm_with_subscription(&db, Utc::now(), true).await?;
+        let alert_id = add_usage_alert(&db, vm_id, 99.0).await?;
+        let worker = setup_worker(db.clone()).await?;
+
+        worker.check_usage_alerts().await?;
+        assert_eq!(count_notifications(&worker, "High CPU usage").await, 0);
+        assert!(
+            db.get_vm_usage_alert(alert_id)
+                .await?
+                .last_triggered
+                .is_none()
+        );
+        Ok(())
+    }
+
+    /// Request ids left without a VM are released once stale, ids of created
+    /// VMs and fresh claims are kept
+    #[tokio::test]
+    async fn test_expire_vm_create_requests() -> Result<()> {
+        let db = Arc::new(MockDb::default());
+        let worker = setup_worker(db.clone()).await?;
+        assert!(db.insert_vm_create_request(1, "stale").await?);
+        assert!(db.insert_vm_create_request(1, "done").await?);
+        assert!(db.insert_vm_create_request(1, "fresh").await?);
+        db.set_vm_create_request_vm(1, "done", 1).await?;
+        for r in db.vm_create_requests.lock().await.iter_mut() {
+            if r.request_id != "fresh" {
+                r.created = Utc::now() - TimeDelta::hours(2);
+            }
+        }
+
+        worker.expire_vm_create_requests().await?;
+        let left: Vec<String> = db
+            .vm_create_requests
+            .lock()
+            .await
+            .iter()
+            .map(|r| r.request_id.clone())
+            .collect();
+        assert_eq!(left, vec!["done".to_string(), "fresh".to_string()]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_bulk_message_writes_audit_log() -> Result<()> {
+        let db = Arc::new(MockDb::default());
+        let (vm_id, _) = add_vm_with_subscription(&db, Utc::now(), true).await?;
+        let user_id = db.get_vm(vm_id).await?.user_id;
+        db.users
+            .lock()
+            .await
+            .get_mut(&user_id)
+            .unwrap()
+            .contact_nip17 = true;
+        let worker = setup_worker(db.clone()).await?;
+
+        worker
+            .process_bulk_message(
+                "Maintenance".to_string(),
+                "Hello".to_string(),
+                7,
+                Some("203.0.113.7".to_string()),
+            )
+            .await?;
+
+        let log = db.admin_audit_log.lock().await;
+        assert_eq!(log.len(), 1);
+        assert_eq!(log[0].admin_user_id, 7);
+        assert_eq!(log[0].action, "bulk_message");
+        let detail: serde_json::Value = serde_json::from_str(log[0].detail.as_ref().unwrap())?;
+        assert_eq!(detail["recipients"], 1);
+        // no notification channels are configured in tests
+        assert_eq!(detail["sent"], 0);
+        assert_eq!(detail["failed"], 1);
+        assert_eq!(detail["ip"], "203.0.113.7");
+
+        let deliveries = db.bulk_message_deliveries.lock().await;
+        assert_eq!(deliveries.len(), 1);
+        assert_eq!(deliveries[0].audit_log_id, log[0].id);
+        assert_eq!(deliveries[0].user_id, user_id);
+        assert_eq!(deliveries[0].status, "no_channel");
+        assert_eq!(deliveries[0].channel, None);
+        Ok(())
+    }
 }
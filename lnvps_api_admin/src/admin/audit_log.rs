@@ -0,0 +1,32 @@
+use crate::admin::RouterState;
+use crate::admin::auth::AdminAuth;
+use crate::admin::model::AdminAuditLogInfo;
+use axum::Router;
+use axum::extract::{Query, State};
+use axum::routing::get;
+use lnvps_api_common::{ApiPaginatedData, ApiPaginatedResult, PageQuery};
+use lnvps_db::{AdminAction, AdminResource};
+
+pub fn router() -> Router<RouterState> {
+    Router::new().route("/api/admin/v1/audit-log", get(admin_list_audit_log))
+}
+
+/// List recorded admin actions, newest first
+async fn admin_list_audit_log(
+    auth: AdminAuth,
+    State(this): State<RouterState>,
+    Query(page): Query<PageQuery>,
+) -> ApiPaginatedResult<AdminAuditLogInfo> {
+    auth.require_permission(AdminResource::Audit, AdminAction::View)?;
+
+    let limit = page.limit.unwrap_or(50).min(100);
+    let offset = page.offset.unwrap_or(0);
+
+    let (entries, total) = this.db.admin_list_audit_log(limit, offset).await?;
+    ApiPaginatedData::ok(
+        entries.into_iter().map(Into::into).collect(),
+        total,
+        limit,
+        offset,
+    )
+}
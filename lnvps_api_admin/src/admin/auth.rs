@@ -2,10 +2,7 @@ use crate::admin::RouterState;
 use crate::admin::model::Permission;
 use anyhow::Result;
 use axum::extract::FromRef;
-use axum::{
-    extract::FromRequestParts,
-    http::{StatusCode, request::Parts},
-};
+use axum::{extract::FromRequestParts, http::request::Parts};
 use lnvps_api_common::{ApiError, Nip98Auth};
 use lnvps_db::{AdminAction, AdminResource, LNVpsDb};
 use std::collections::HashSet;
@@ -117,7 +114,7 @@ where
     S: Send + Sync,
     RouterState: axum::extract::FromRef<S>,
 {
-    type Rejection = (StatusCode, String);
+    type Rejection = ApiError;
 
     fn from_request_parts(
         parts: &mut Parts,
@@ -131,7 +128,7 @@ where
             // Check admin privileges
             AdminAuth::from_nip98_auth(nip98_auth, &state.db)
                 .await
-                .map_err(|e| (StatusCode::FORBIDDEN, e.to_string()))
+                .map_err(|e| ApiError::forbidden(e.to_string()))
         })
     }
 }
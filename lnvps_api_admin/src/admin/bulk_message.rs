@@ -1,11 +1,17 @@
 use crate::admin::RouterState;
 use crate::admin::auth::AdminAuth;
-use crate::admin::model::{BulkMessageRequest, BulkMessageResponse};
+use crate::admin::model::{
+    BulkMessageChannelPreview, BulkMessagePreview, BulkMessageRequest, BulkMessageResponse,
+};
 use axum::extract::State;
 use axum::routing::post;
 use axum::{Json, Router};
-use lnvps_api_common::{ApiData, ApiResult, WorkJob};
-use log::{error, info};
+use lnvps_api_common::{
+    ApiData, ApiError, ApiResult, ClientIp, WorkJob, bulk_message_body, bulk_message_channels,
+    bulk_message_confirm_token,
+};
+use lnvps_db::{AdminAuditLog, User};
+use log::{error, info, warn};
 
 pub fn router() -> Router<RouterState> {
     Router::new().route("/api/admin/v1/users/bulk-message", post(admin_bulk_message))
@@ -14,6 +20,7 @@ pub fn router() -> Router<RouterState> {
 async fn admin_bulk_message(
     auth: AdminAuth,
     State(state): State<RouterState>,
+    client_ip: ClientIp,
     Json(req): Json<BulkMessageRequest>,
 ) -> ApiResult<BulkMessageResponse> {
     // Check permission - require admin access to users
@@ -30,28 +37,214 @@ async fn admin_bulk_message(
         return ApiData::err("Message body cannot be empty");
     }
 
+    let customers = state.db.get_active_customers_with_contact_prefs().await?;
+    if req.dry_run {
+        return ApiData::ok(BulkMessageResponse {
+            job_dispatched: false,
+            job_id: None,
+            preview: Some(preview(&req, &customers)),
+        });
+    }
+    let client_ip = client_ip.0.map(|ip| ip.to_string());
+    if let Err(e) = check_confirm_token(&req, customers.len() as u64) {
+        audit(
+            &state,
+            auth.user_id,
+            "bulk_message_rejected",
+            serde_json::json!({
+                "subject": req.subject,
+                "recipients": customers.len(),
+                "error": e.error,
+                "ip": client_ip,
+            }),
+        )
+        .await;
+        return Err(e);
+    }
+
     // Dispatch work job for async processing
     let job = WorkJob::BulkMessage {
         subject: req.subject.clone(),
         message: req.message.clone(),
         admin_user_id: auth.user_id,
+        client_ip: client_ip.clone(),
     };
 
     match state.work_commander.send(job).await {
         Ok(job_id) => {
             info!(
-                "Bulk message job dispatched with ID: {} for subject: '{}'",
+                "Bulk message job dispatched with ID: {} for subject: '{}' to {} recipients",
                 job_id,
-                req.subject.trim()
+                req.subject.trim(),
+                customers.len()
             );
+            audit(
+                &state,
+                auth.user_id,
+                "bulk_message_queued",
+                serde_json::json!({
+                    "subject": req.subject,
+                    "recipients": customers.len(),
+                    "job_id": job_id,
+                    "ip": client_ip,
+                }),
+            )
+            .await;
             ApiData::ok(BulkMessageResponse {
                 job_dispatched: true,
                 job_id: Some(job_id),
+                preview: None,
             })
         }
         Err(e) => {
             error!("Failed to dispatch bulk message job: {}", e);
+            audit(
+                &state,
+                auth.user_id,
+                "bulk_message_failed",
+                serde_json::json!({
+                    "subject": req.subject,
+                    "error": e.to_string(),
+                    "ip": client_ip,
+                }),
+            )
+            .await;
             ApiData::err("Failed to dispatch message job")
         }
     }
 }
+
+/// Record a bulk message attempt in the audit log, a failed write is only logged
+async fn audit(state: &RouterState, admin_user_id: u64, action: &str, detail: serde_json::Value) {
+    if let Err(e) = state
+        .db
+        .insert_admin_audit_log(&AdminAuditLog {
+            admin_user_id,
+            action: action.to_string(),
+            detail: Some(detail.to_string()),
+            ..Default::default()
+        })
+        .await
+    {
+        warn!("Failed to write {} audit log: {}", action, e);
+    }
+}
+
+/// Recipient counts and sample messages of a bulk message, without sending it.
+///
+/// Like delivery, each customer is counted once, on the first channel they
+/// have enabled.
+fn preview(req: &BulkMessageRequest, customers: &[User]) -> BulkMessagePreview {
+    let mut channels: Vec<BulkMessageChannelPreview> = Vec::new();
+    let mut unreachable = 0;
+    for customer in customers {
+        let Some(channel) = bulk_message_channels(customer).into_iter().next() else {
+            unreachable += 1;
+            continue;
+        };
+        if let Some(c) = channels.iter_mut().find(|c| c.channel == channel) {
+            c.recipients += 1;
+            continue;
+        }
+        channels.push(BulkMessageChannelPreview {
+            channel: channel.to_string(),
+            recipients: 1,
+            subject: (channel != "nip17").then(|| req.subject.clone()),
+            sample: bulk_message_body(customer, &req.message),
+        });
+    }
+    let recipients = customers.len() as u64;
+    BulkMessagePreview {
+        recipients,
+        unreachable,
+        channels,
+        confirm_token: bulk_message_confirm_token(&req.subject, &req.message, recipients),
+    }
+}
+
+/// Only send a bulk message which was previewed with a dry run
+fn check_confirm_token(req: &BulkMessageRequest, recipients: u64) -> Result<(), ApiError> {
+    let Some(token) = &req.confirm_token else {
+        return Err(ApiError::bad_request(
+            "confirm_token is required, run with dry_run first",
+        ));
+    };
+    if *token != bulk_message_confirm_token(&req.subject, &req.message, recipients) {
+        return Err(ApiError::bad_request(
+            "confirm_token does not match this message or the recipients changed, run with dry_run again",
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lnvps_api_common::MockDb;
+    use lnvps_db::{LNVpsDb, LNVpsDbBase, Vm};
+    use std::sync::Arc;
+
+    fn request(message: &str) -> BulkMessageRequest {
+        BulkMessageRequest {
+            subject: "Maintenance".to_string(),
+            message: message.to_string(),
+            dry_run: true,
+            confirm_token: None,
+        }
+    }
+
+    async fn add_customer(db: &MockDb, key: u8, f: impl FnOnce(&mut User)) {
+        let uid = db.upsert_user(&[key; 32]).await.unwrap();
+        f(db.users.lock().await.get_mut(&uid).unwrap());
+        db.insert_vm(&Vm {
+            user_id: uid,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_counts_recipients() {
+        let db = MockDb::default();
+        add_customer(&db, 1, |u| u.contact_nip17 = true).await;
+        add_customer(&db, 2, |u| {
+            u.contact_nip17 = true;
+            u.contact_email = true;
+            u.email = "a@example.com".into();
+            u.billing_name = Some("Alice".to_string());
+        })
+        .await;
+        // no contact method, not a recipient
+        add_customer(&db, 3, |_| {}).await;
+
+        let db: Arc<dyn LNVpsDb> = Arc::new(db);
+        let customers = db.get_active_customers_with_contact_prefs().await.unwrap();
+        let p = preview(&request("Hello"), &customers);
+        assert_eq!(p.recipients, 2);
+        assert_eq!(p.unreachable, 0);
+
+        // the customer with both channels is only sent the email
+        let nip17 = p.channels.iter().find(|c| c.channel == "nip17").unwrap();
+        assert_eq!(nip17.recipients, 1);
+        assert!(nip17.subject.is_none());
+        let email = p.channels.iter().find(|c| c.channel == "email").unwrap();
+        assert_eq!(email.recipients, 1);
+        assert_eq!(email.subject.as_deref(), Some("Maintenance"));
+        assert_eq!(email.sample, "Dear Alice,\n\nHello");
+    }
+
+    #[test]
+    fn test_send_requires_confirm_token() {
+        let mut req = request("Hello");
+        assert!(check_confirm_token(&req, 2).is_err());
+
+        req.confirm_token = Some(preview(&req, &[User::default(), User::default()]).confirm_token);
+        assert!(check_confirm_token(&req, 2).is_ok());
+        // recipients changed since the dry run
+        assert!(check_confirm_token(&req, 3).is_err());
+        // message edited after the dry run
+        req.message = "Hello!".to_string();
+        assert!(check_confirm_token(&req, 2).is_err());
+    }
+}
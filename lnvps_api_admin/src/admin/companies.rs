@@ -158,6 +158,7 @@ async fn admin_create_company(
         base_currency,
         referral_rate: req.referral_rate.unwrap_or(0.0).max(0.0),
         max_prepay_days: req.max_prepay_days.unwrap_or(0),
+        tax_display: req.tax_display.map(Into::into).unwrap_or_default(),
     };
 
     let company_id = this.db.admin_create_company(&company).await?;
@@ -277,6 +278,9 @@ async fn admin_update_company(
     if let Some(days) = req.max_prepay_days {
         company.max_prepay_days = days;
     }
+    if let Some(tax_display) = req.tax_display {
+        company.tax_display = tax_display.into();
+    }
 
     // Update company in database
     this.db.admin_update_company(&company).await?;
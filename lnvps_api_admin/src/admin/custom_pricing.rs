@@ -9,7 +9,8 @@ use axum::routing::{get, post};
 use axum::{Json, Router};
 use chrono::Utc;
 use lnvps_api_common::{
-    ApiData, ApiDiskInterface, ApiDiskType, ApiPaginatedData, ApiPaginatedResult, ApiResult,
+    ApiData, ApiDiskInterface, ApiDiskType, ApiError, ApiPaginatedData, ApiPaginatedResult,
+    ApiResult, PricingEngine,
 };
 use lnvps_db::{AdminAction, AdminResource, LNVpsDb, VmCustomPricing, VmCustomPricingDisk};
 use serde::Deserialize;
@@ -206,23 +207,27 @@ async fn admin_create_custom_pricing(
         cpu_limit: req.cpu_limit,
     };
 
-    let pricing_id = this.db.insert_custom_pricing(&pricing).await?;
-
-    // Insert disk pricing configurations
-    for disk_config in req.disk_pricing {
-        let disk_type = disk_config.kind;
-        let disk_interface = disk_config.interface;
-
-        let disk_pricing = VmCustomPricingDisk {
-            id: 0, // Will be set by database
-            pricing_id,
-            kind: disk_type.into(),
-            interface: disk_interface.into(),
+    let disks: Vec<VmCustomPricingDisk> = req
+        .disk_pricing
+        .into_iter()
+        .map(|disk_config| VmCustomPricingDisk {
+            id: 0,         // Will be set by database
+            pricing_id: 0, // Set once the pricing is inserted
+            kind: disk_config.kind.into(),
+            interface: disk_config.interface.into(),
             cost: disk_config.cost,
             min_disk_size: disk_config.min_disk_size,
             max_disk_size: disk_config.max_disk_size,
-        };
+        })
+        .collect();
+    PricingEngine::validate_custom_pricing_limits(&pricing, &disks, &this.custom_floors)
+        .map_err(ApiError::validation)?;
 
+    let pricing_id = this.db.insert_custom_pricing(&pricing).await?;
+
+    // Insert disk pricing configurations
+    for mut disk_pricing in disks {
+        disk_pricing.pricing_id = pricing_id;
         this.db.insert_custom_pricing_disk(&disk_pricing).await?;
     }
 
@@ -242,7 +247,8 @@ async fn admin_update_custom_pricing(
     auth.require_permission(AdminResource::VmCustomPricing, AdminAction::Update)?;
 
     // Get existing pricing
-    let mut pricing = this.db.get_custom_pricing(id).await?;
+    let existing = this.db.get_custom_pricing(id).await?;
+    let mut pricing = existing.clone();
 
     // Update fields if provided
     if let Some(name) = req.name {
@@ -319,28 +325,39 @@ async fn admin_update_custom_pricing(
         pricing.cpu_limit = v;
     }
 
+    let new_disks: Option<Vec<VmCustomPricingDisk>> = req.disk_pricing.map(|configs| {
+        configs
+            .into_iter()
+            .map(|disk_config| VmCustomPricingDisk {
+                id: 0, // Will be set by database
+                pricing_id: id,
+                kind: disk_config.kind.into(),
+                interface: disk_config.interface.into(),
+                cost: disk_config.cost,
+                min_disk_size: disk_config.min_disk_size,
+                max_disk_size: disk_config.max_disk_size,
+            })
+            .collect()
+    });
+    // only the limits being changed are checked, plans saved before the
+    // floors were enforced stay editable
+    PricingEngine::validate_custom_pricing_update(
+        &existing,
+        &pricing,
+        new_disks.as_deref(),
+        &this.custom_floors,
+    )
+    .map_err(ApiError::validation)?;
+
     this.db.update_custom_pricing(&pricing).await?;
 
     // Update disk pricing if provided
-    if let Some(disk_pricing_configs) = req.disk_pricing {
+    if let Some(new_disks) = new_disks {
         // Delete existing disk pricing configurations
         this.db.delete_custom_pricing_disks(id).await?;
 
         // Insert new configurations
-        for disk_config in disk_pricing_configs {
-            let disk_type = disk_config.kind;
-            let disk_interface = disk_config.interface;
-
-            let disk_pricing = VmCustomPricingDisk {
-                id: 0, // Will be set by database
-                pricing_id: id,
-                kind: disk_type.into(),
-                interface: disk_interface.into(),
-                cost: disk_config.cost,
-                min_disk_size: disk_config.min_disk_size,
-                max_disk_size: disk_config.max_disk_size,
-            };
-
+        for disk_pricing in new_disks {
             this.db.insert_custom_pricing_disk(&disk_pricing).await?;
         }
     }
@@ -0,0 +1,154 @@
+use crate::admin::RouterState;
+use crate::admin::auth::AdminAuth;
+use crate::admin::model::{
+    AdminFeatureFlagInfo, CreateFeatureFlagRequest, UpdateFeatureFlagRequest,
+};
+use axum::extract::{Path, State};
+use axum::routing::get;
+use axum::{Json, Router};
+use lnvps_api_common::{ApiData, ApiError, ApiResult, KNOWN_FEATURES};
+use lnvps_db::{AdminAction, AdminResource, FeatureFlag};
+
+pub fn router() -> Router<RouterState> {
+    Router::new()
+        .route(
+            "/api/admin/v1/feature_flags",
+            get(admin_list_feature_flags).post(admin_create_feature_flag),
+        )
+        .route(
+            "/api/admin/v1/feature_flags/{id}",
+            get(admin_get_feature_flag)
+                .patch(admin_update_feature_flag)
+                .delete(admin_delete_feature_flag),
+        )
+}
+
+/// Only keys checked by the code can be set, and only one flag per key and
+/// scope
+fn validate(flag: &FeatureFlag, existing: &[FeatureFlag]) -> Result<(), String> {
+    if !KNOWN_FEATURES.contains(&flag.key.as_str()) {
+        return Err(format!(
+            "Unknown feature '{}', expected one of: {}",
+            flag.key,
+            KNOWN_FEATURES.join(", ")
+        ));
+    }
+    if existing
+        .iter()
+        .any(|f| f.id != flag.id && f.key == flag.key && f.region_id == flag.region_id)
+    {
+        return Err("A flag for this feature and region already exists".to_string());
+    }
+    Ok(())
+}
+
+async fn check_flag(this: &RouterState, flag: &FeatureFlag) -> Result<(), ApiError> {
+    if let Some(region_id) = flag.region_id {
+        this.db.get_host_region(region_id).await?;
+    }
+    let existing = this.db.list_feature_flags().await?;
+    validate(flag, &existing).map_err(ApiError::validation)
+}
+
+async fn admin_list_feature_flags(
+    auth: AdminAuth,
+    State(this): State<RouterState>,
+) -> ApiResult<Vec<AdminFeatureFlagInfo>> {
+    auth.require_permission(AdminResource::System, AdminAction::View)?;
+
+    let flags = this.db.list_feature_flags().await?;
+    ApiData::ok(flags.into_iter().map(Into::into).collect())
+}
+
+async fn admin_get_feature_flag(
+    auth: AdminAuth,
+    State(this): State<RouterState>,
+    Path(id): Path<u64>,
+) -> ApiResult<AdminFeatureFlagInfo> {
+    auth.require_permission(AdminResource::System, AdminAction::View)?;
+
+    let flag = this.db.admin_get_feature_flag(id).await?;
+    ApiData::ok(flag.into())
+}
+
+async fn admin_create_feature_flag(
+    auth: AdminAuth,
+    State(this): State<RouterState>,
+    Json(request): Json<CreateFeatureFlagRequest>,
+) -> ApiResult<AdminFeatureFlagInfo> {
+    auth.require_permission(AdminResource::System, AdminAction::Create)?;
+
+    let flag = FeatureFlag {
+        key: request.key.trim().to_string(),
+        region_id: request.region_id,
+        enabled: request.enabled,
+        ..Default::default()
+    };
+    check_flag(&this, &flag).await?;
+    let id = this.db.admin_insert_feature_flag(&flag).await?;
+    this.features.invalidate().await;
+    let created = this.db.admin_get_feature_flag(id).await?;
+    ApiData::ok(created.into())
+}
+
+async fn admin_update_feature_flag(
+    auth: AdminAuth,
+    State(this): State<RouterState>,
+    Path(id): Path<u64>,
+    Json(request): Json<UpdateFeatureFlagRequest>,
+) -> ApiResult<AdminFeatureFlagInfo> {
+    auth.require_permission(AdminResource::System, AdminAction::Update)?;
+
+    let mut flag = this.db.admin_get_feature_flag(id).await?;
+    if let Some(region_id) = request.region_id {
+        flag.region_id = region_id;
+    }
+    if let Some(enabled) = request.enabled {
+        flag.enabled = enabled;
+    }
+    check_flag(&this, &flag).await?;
+
+    this.db.admin_update_feature_flag(&flag).await?;
+    this.features.invalidate().await;
+    let updated = this.db.admin_get_feature_flag(id).await?;
+    ApiData::ok(updated.into())
+}
+
+async fn admin_delete_feature_flag(
+    auth: AdminAuth,
+    State(this): State<RouterState>,
+    Path(id): Path<u64>,
+) -> ApiResult<()> {
+    auth.require_permission(AdminResource::System, AdminAction::Delete)?;
+
+    this.db.admin_delete_feature_flag(id).await?;
+    this.features.invalidate().await;
+    ApiData::ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lnvps_api_common::{FEATURE_BACKUPS, FEATURE_VM_MIGRATION};
+
+    fn flag(id: u64, key: &str, region_id: Option<u64>) -> FeatureFlag {
+        FeatureFlag {
+            id,
+            key: key.to_string(),
+            region_id,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_validate_feature_flag() {
+        let existing = vec![flag(1, FEATURE_BACKUPS, None)];
+
+        assert!(validate(&flag(0, "snapshots", None), &existing).is_err());
+        assert!(validate(&flag(0, FEATURE_BACKUPS, None), &existing).is_err());
+        assert!(validate(&flag(0, FEATURE_BACKUPS, Some(1)), &existing).is_ok());
+        assert!(validate(&flag(0, FEATURE_VM_MIGRATION, None), &existing).is_ok());
+        // updating the flag itself is not a duplicate
+        assert!(validate(&flag(1, FEATURE_BACKUPS, None), &existing).is_ok());
+    }
+}
@@ -1,8 +1,8 @@
 use crate::admin::RouterState;
 use crate::admin::auth::AdminAuth;
 use crate::admin::model::{
-    AdminHostDisk, AdminHostInfo, AdminImportVmRequest, AdminUnmanagedVmInfo, AdminVmHostKind,
-    JobResponse,
+    AdminDrainHostResponse, AdminHostDisk, AdminHostInfo, AdminImportVmRequest,
+    AdminUnmanagedVmInfo, AdminVmHostKind, JobResponse,
 };
 use axum::extract::{Path, Query, State};
 use axum::routing::{get, post};
@@ -11,7 +11,8 @@ use chrono::{DateTime, Utc};
 use futures::StreamExt;
 use lnvps_api_common::{
     ApiData, ApiDiskInterface, ApiDiskType, ApiError, ApiPaginatedData, ApiPaginatedResult,
-    ApiResult, JobFeedback, JobFeedbackStatus, PageQuery, WorkFeedback, WorkJob,
+    ApiResult, FEATURE_VM_MIGRATION, JobFeedback, JobFeedbackStatus, PageQuery, WorkFeedback,
+    WorkJob,
 };
 use lnvps_db::{AdminAction, AdminResource};
 use log::info;
@@ -28,6 +29,10 @@ pub fn router() -> Router<RouterState> {
             "/api/admin/v1/hosts/{id}",
             get(admin_get_host).patch(admin_update_host),
         )
+        .route(
+            "/api/admin/v1/hosts/{id}/api-token",
+            post(admin_rotate_host_api_token),
+        )
         // Host disk management
         .route(
             "/api/admin/v1/hosts/{id}/disks",
@@ -43,6 +48,8 @@ pub fn router() -> Router<RouterState> {
             get(admin_list_unmanaged_vms),
         )
         .route("/api/admin/v1/hosts/{id}/vms/import", post(admin_import_vm))
+        .route("/api/admin/v1/hosts/{id}/drain", post(admin_drain_host))
+        .route("/api/admin/v1/hosts/{id}/sync", post(admin_sync_host))
 }
 
 /// List all VM hosts with pagination
@@ -134,9 +141,21 @@ async fn admin_update_host(
     if let Some(mtu) = req.mtu {
         host.mtu = mtu;
     }
+    if let Some(private_bridge) = &req.private_bridge {
+        host.private_bridge = private_bridge.clone();
+    }
     if let Some(enabled) = req.enabled {
         host.enabled = enabled;
     }
+    if let Some(maintenance) = req.maintenance {
+        host.maintenance = maintenance;
+    }
+    if let Some(weight) = req.weight {
+        if weight == 0 {
+            return Err(ApiError::validation("Host weight must be at least 1"));
+        }
+        host.weight = weight;
+    }
     if let Some(cpu_mfg) = req.cpu_mfg {
         host.cpu_mfg = cpu_mfg.and_then(|s| s.parse().ok()).unwrap_or_default();
     }
@@ -199,6 +218,48 @@ async fn admin_update_host(
     ApiData::ok(host_info)
 }
 
+/// Replace the API token used to control a host
+///
+/// Host clients are built from the stored host on every use, so the new token
+/// takes effect on the next call to the host.
+async fn admin_rotate_host_api_token(
+    auth: AdminAuth,
+    State(this): State<RouterState>,
+    Path(id): Path<u64>,
+    Json(req): Json<AdminHostApiTokenRequest>,
+) -> ApiResult<AdminHostInfo> {
+    // Check permission
+    auth.require_permission(AdminResource::Hosts, AdminAction::Update)?;
+
+    let api_token = req.api_token.trim();
+    if api_token.is_empty() {
+        return ApiData::err("api_token is required");
+    }
+
+    let host = this.db.get_host(id).await?;
+    this.db
+        .update_host_api_token(host.id, &api_token.into())
+        .await?;
+    info!("Rotated API token for host {} ({})", host.id, host.name);
+
+    let updated_host = this.db.get_host(id).await?;
+    let region = this.db.get_host_region(updated_host.region_id).await?;
+    let disks = this.db.list_host_disks(id).await?;
+
+    // Create admin host manually
+    let admin_host = lnvps_db::AdminVmHost {
+        host: updated_host,
+        region_id: region.id,
+        region_name: region.name.clone(),
+        region_enabled: region.enabled,
+        region_company_id: region.company_id,
+        disks,
+        active_vm_count: this.db.count_active_vms_on_host(id).await.unwrap_or(0) as _,
+    };
+    let host_info = AdminHostInfo::from_admin_vm_host_with_capacity(&this.db, admin_host).await;
+    ApiData::ok(host_info)
+}
+
 /// Create a new host
 async fn admin_create_host(
     auth: AdminAuth,
@@ -210,6 +271,9 @@ async fn admin_create_host(
 
     // Validate region exists
     let _region = this.db.get_host_region(req.region_id).await?;
+    if req.weight == Some(0) {
+        return Err(ApiError::validation("Host weight must be at least 1"));
+    }
 
     // Create new host object
     let new_host = lnvps_db::VmHost {
@@ -249,9 +313,12 @@ async fn admin_create_host(
         load_disk: req.load_disk.unwrap_or(1.0),
         vlan_id: req.vlan_id,
         mtu: req.mtu,
+        private_bridge: req.private_bridge.clone(),
         ssh_user: req.ssh_user.clone(),
         ssh_key: req.ssh_key.clone().map(|k| k.into()),
         sunset_date: req.sunset_date,
+        maintenance: req.maintenance.unwrap_or(false),
+        weight: req.weight.unwrap_or(1),
     };
 
     // Create host in database
@@ -286,6 +353,12 @@ pub struct AdminHostUpdateRequest {
     pub vlan_id: Option<Option<u64>>,
     /// MTU setting for network configuration (use `Some(None)` to clear)
     pub mtu: Option<Option<u16>>,
+    /// Bridge with isolated ports for private network VMs (use `Some(None)` to clear)
+    #[serde(
+        default,
+        deserialize_with = "lnvps_api_common::deserialize_nullable_option"
+    )]
+    pub private_bridge: Option<Option<String>>,
     pub enabled: Option<bool>,
     /// CPU manufacturer (e.g. "intel", "amd", "apple")
     /// Use `Some(None)` or `null` to clear (reset to unknown)
@@ -324,6 +397,18 @@ pub struct AdminHostUpdateRequest {
         deserialize_with = "lnvps_api_common::deserialize_nullable_option"
     )]
     pub sunset_date: Option<Option<DateTime<Utc>>>,
+    /// Put the host into (or take it out of) maintenance mode. Existing VMs
+    /// remain manageable but no new VMs are placed on the host.
+    pub maintenance: Option<bool>,
+    /// Share of new VMs placed on the host relative to the other hosts in its
+    /// region, at least 1
+    pub weight: Option<u32>,
+}
+
+#[derive(Deserialize)]
+pub struct AdminHostApiTokenRequest {
+    /// New API token for controlling the host
+    pub api_token: String,
 }
 
 #[derive(Deserialize)]
@@ -336,6 +421,8 @@ pub struct AdminHostCreateRequest {
     pub vlan_id: Option<u64>,
     /// MTU setting for network configuration
     pub mtu: Option<u16>,
+    /// Bridge with isolated ports for private network VMs
+    pub private_bridge: Option<String>,
     pub cpu: u16,
     /// CPU manufacturer (e.g. "intel", "amd", "apple")
     pub cpu_mfg: Option<String>,
@@ -356,6 +443,11 @@ pub struct AdminHostCreateRequest {
     /// Sunset date for the host. While set, the host is effectively disabled for
     /// new provisioning and renewals are blocked once a VM's expiry reaches this date.
     pub sunset_date: Option<DateTime<Utc>>,
+    /// Create the host in maintenance mode (default: false)
+    pub maintenance: Option<bool>,
+    /// Share of new VMs placed on the host relative to the other hosts in its
+    /// region (default: 1)
+    pub weight: Option<u32>,
 }
 
 /// List host disks
@@ -562,7 +654,7 @@ async fn admin_list_unmanaged_vms(
 }
 
 /// Import an existing host VM into the database, assigning it to a user and
-/// billing via the region's custom pricing (issue #166).
+/// billing via a standard template or the region's custom pricing (issue #166).
 async fn admin_import_vm(
     auth: AdminAuth,
     State(this): State<RouterState>,
@@ -574,11 +666,15 @@ async fn admin_import_vm(
     // Validate host and user up front
     let _host = this.db.get_host(id).await?;
     let _user = this.db.get_user(req.user_id).await?;
+    if let Some(template_id) = req.template_id {
+        let _template = this.db.get_vm_template(template_id).await?;
+    }
 
     let job = WorkJob::ImportVm {
         host_id: id,
         host_vm_id: req.host_vm_id,
         user_id: req.user_id,
+        template_id: req.template_id,
         admin_user_id: auth.user_id,
         reason: req.reason,
     };
@@ -595,6 +691,91 @@ async fn admin_import_vm(
     }
 }
 
+#[derive(Deserialize)]
+struct AdminDrainHostRequest {
+    /// Host to live-migrate the VMs to, VMs are stopped when unset
+    target_host_id: Option<u64>,
+}
+
+/// Put a host into maintenance and queue a job which migrates or stops all
+/// VMs on it
+async fn admin_drain_host(
+    auth: AdminAuth,
+    State(this): State<RouterState>,
+    Path(id): Path<u64>,
+    Json(req): Json<AdminDrainHostRequest>,
+) -> ApiResult<AdminDrainHostResponse> {
+    auth.require_permission(AdminResource::Hosts, AdminAction::Update)?;
+
+    let mut host = this.db.get_host(id).await?;
+    if let Some(target_id) = req.target_host_id {
+        if target_id == id {
+            return Err(ApiError::validation("Cannot drain a host onto itself"));
+        }
+        let target = this.db.get_host(target_id).await?;
+        if target.maintenance || !target.enabled {
+            return Err(ApiError::validation(
+                "Target host is disabled or in maintenance",
+            ));
+        }
+        this.features
+            .require(FEATURE_VM_MIGRATION, Some(host.region_id))
+            .await?;
+    }
+
+    // stop new VMs landing on the host right away, the worker may be busy
+    if !host.maintenance {
+        host.maintenance = true;
+        this.db.update_host(&host).await?;
+    }
+
+    let batch_id = uuid::Uuid::new_v4().to_string();
+    let job = WorkJob::DrainHost {
+        host_id: id,
+        target_host_id: req.target_host_id,
+        batch_id: Some(batch_id.clone()),
+        admin_user_id: Some(auth.user_id),
+    };
+    match this.work_commander.send(job).await {
+        Ok(job_id) => {
+            info!("Host {} drain job queued with stream ID: {}", id, job_id);
+            ApiData::ok(AdminDrainHostResponse { job_id, batch_id })
+        }
+        Err(e) => ApiData::err(&format!("Failed to queue host drain job: {}", e)),
+    }
+}
+
+#[derive(Deserialize)]
+struct AdminSyncHostRequest {
+    /// Apply the values read from the host even when they look implausible
+    #[serde(default)]
+    force: bool,
+}
+
+/// Queue a job which re-reads a host's CPU, memory and disk sizes
+async fn admin_sync_host(
+    auth: AdminAuth,
+    State(this): State<RouterState>,
+    Path(id): Path<u64>,
+    Json(req): Json<AdminSyncHostRequest>,
+) -> ApiResult<JobResponse> {
+    auth.require_permission(AdminResource::Hosts, AdminAction::Update)?;
+
+    // 404 for unknown hosts before queueing
+    this.db.get_host(id).await?;
+    let job = WorkJob::SyncHost {
+        host_id: id,
+        force: req.force,
+    };
+    match this.work_commander.send(job).await {
+        Ok(job_id) => {
+            info!("Host {} sync job queued with stream ID: {}", id, job_id);
+            ApiData::ok(JobResponse { job_id })
+        }
+        Err(e) => ApiData::err(&format!("Failed to queue host sync job: {}", e)),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
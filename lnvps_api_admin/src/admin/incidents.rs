@@ -0,0 +1,355 @@
+use crate::admin::RouterState;
+use crate::admin::auth::AdminAuth;
+use crate::admin::model::{AdminIncidentInfo, CreateIncidentRequest, UpdateIncidentRequest};
+use axum::extract::{Path, Query, State};
+use axum::routing::get;
+use axum::{Json, Router};
+use lnvps_api_common::{
+    ApiData, ApiPaginatedData, ApiPaginatedResult, ApiResult, PageQuery, WorkCommander, WorkJob,
+};
+use lnvps_db::{AdminAction, AdminResource, Incident, LNVpsDb};
+use log::{info, warn};
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+pub fn router() -> Router<RouterState> {
+    Router::new()
+        .route(
+            "/api/admin/v1/incidents",
+            get(admin_list_incidents).post(admin_create_incident),
+        )
+        .route(
+            "/api/admin/v1/incidents/{id}",
+            get(admin_get_incident).patch(admin_update_incident),
+        )
+}
+
+/// Length of the `incident.message` column
+const MAX_MESSAGE_LEN: usize = 2000;
+
+fn validate(incident: &Incident) -> Result<(), &'static str> {
+    if incident.message.trim().is_empty() {
+        return Err("Message cannot be empty");
+    }
+    if incident.message.chars().count() > MAX_MESSAGE_LEN {
+        return Err("Message cannot be longer than 2000 characters");
+    }
+    if incident.region_id.is_some() == incident.ip_range_id.is_some() {
+        return Err("Incident must have either a region_id or an ip_range_id");
+    }
+    if incident.ended.is_some_and(|e| e <= incident.started) {
+        return Err("Incident must end after it starts");
+    }
+    Ok(())
+}
+
+/// Owners of the (not deleted) VMs in the incident's region or IP range
+async fn affected_users(
+    db: &Arc<dyn LNVpsDb>,
+    incident: &Incident,
+) -> anyhow::Result<BTreeSet<u64>> {
+    let mut users = BTreeSet::new();
+    if let Some(ip_range_id) = incident.ip_range_id {
+        for (vm, _) in db.list_vms_in_ip_range(ip_range_id).await? {
+            users.insert(vm.user_id);
+        }
+    }
+    if let Some(region_id) = incident.region_id {
+        for host in db.list_hosts().await? {
+            if host.region_id != region_id {
+                continue;
+            }
+            for vm in db.list_vms_on_host(host.id).await? {
+                users.insert(vm.user_id);
+            }
+        }
+    }
+    Ok(users)
+}
+
+/// Record the incident and notify each affected customer once
+async fn raise_incident(
+    db: &Arc<dyn LNVpsDb>,
+    work_commander: &Arc<dyn WorkCommander>,
+    incident: &Incident,
+) -> anyhow::Result<Incident> {
+    let title = if let Some(region_id) = incident.region_id {
+        format!("Incident in {}", db.get_host_region(region_id).await?.name)
+    } else if let Some(ip_range_id) = incident.ip_range_id {
+        format!(
+            "Incident in {}",
+            db.admin_get_ip_range(ip_range_id).await?.cidr
+        )
+    } else {
+        anyhow::bail!("Incident must have either a region_id or an ip_range_id");
+    };
+
+    let users = affected_users(db, incident).await?;
+    let id = db.admin_insert_incident(incident).await?;
+    let mut notified = 0;
+    for user_id in users {
+        let job = WorkJob::SendNotification {
+            user_id,
+            message: incident.message.clone(),
+            title: Some(title.clone()),
+        };
+        match work_commander.send(job).await {
+            Ok(_) => notified += 1,
+            Err(e) => warn!(
+                "Failed to queue incident {} notification for user {}: {}",
+                id, user_id, e
+            ),
+        }
+    }
+    info!("Incident {} raised, {} customers notified", id, notified);
+
+    let mut created = db.admin_get_incident(id).await?;
+    created.notified = notified;
+    db.admin_update_incident(&created).await?;
+    Ok(created)
+}
+
+async fn admin_list_incidents(
+    auth: AdminAuth,
+    State(this): State<RouterState>,
+    Query(params): Query<PageQuery>,
+) -> ApiPaginatedResult<AdminIncidentInfo> {
+    auth.require_permission(AdminResource::System, AdminAction::View)?;
+
+    let limit = params.limit.unwrap_or(50).min(100);
+    let offset = params.offset.unwrap_or(0);
+
+    let (incidents, total) = this.db.admin_list_incidents(limit, offset).await?;
+    ApiPaginatedData::ok(
+        incidents.into_iter().map(Into::into).collect(),
+        total,
+        limit,
+        offset,
+    )
+}
+
+async fn admin_get_incident(
+    auth: AdminAuth,
+    State(this): State<RouterState>,
+    Path(id): Path<u64>,
+) -> ApiResult<AdminIncidentInfo> {
+    auth.require_permission(AdminResource::System, AdminAction::View)?;
+
+    let incident = this.db.admin_get_incident(id).await?;
+    ApiData::ok(incident.into())
+}
+
+async fn admin_create_incident(
+    auth: AdminAuth,
+    State(this): State<RouterState>,
+    Json(request): Json<CreateIncidentRequest>,
+) -> ApiResult<AdminIncidentInfo> {
+    auth.require_permission(AdminResource::System, AdminAction::Create)?;
+    // customers are messaged like a bulk message
+    auth.require_permission(AdminResource::Users, AdminAction::Update)?;
+
+    let incident = request.to_incident(auth.user_id);
+    if let Err(e) = validate(&incident) {
+        return ApiData::err(e);
+    }
+    let created = raise_incident(&this.db, &this.work_commander, &incident).await?;
+    ApiData::ok(created.into())
+}
+
+/// Customers are only notified when the incident is raised, not on updates
+async fn admin_update_incident(
+    auth: AdminAuth,
+    State(this): State<RouterState>,
+    Path(id): Path<u64>,
+    Json(request): Json<UpdateIncidentRequest>,
+) -> ApiResult<AdminIncidentInfo> {
+    auth.require_permission(AdminResource::System, AdminAction::Update)?;
+
+    let mut incident = this.db.admin_get_incident(id).await?;
+    if let Some(message) = &request.message {
+        incident.message = message.trim().to_string();
+    }
+    if let Some(started) = request.started {
+        incident.started = started;
+    }
+    if let Some(ended) = request.ended {
+        incident.ended = ended;
+    }
+    if let Err(e) = validate(&incident) {
+        return ApiData::err(e);
+    }
+
+    this.db.admin_update_incident(&incident).await?;
+    let updated = this.db.admin_get_incident(id).await?;
+    ApiData::ok(updated.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, Utc};
+    use lnvps_api_common::{ChannelWorkCommander, MockDb};
+    use lnvps_db::{LNVpsDbBase, Region, Vm, VmHost, VmIpAssignment};
+
+    /// Add a VM on `host_id` owned by the user with pubkey `[key; 32]`
+    async fn add_vm(
+        db: &MockDb,
+        vm_id: u64,
+        key: u8,
+        host_id: u64,
+        ip: Option<(u64, &str)>,
+    ) -> u64 {
+        let uid = db.upsert_user(&[key; 32]).await.unwrap();
+        db.vms.lock().await.insert(
+            vm_id,
+            Vm {
+                id: vm_id,
+                user_id: uid,
+                host_id,
+                ..MockDb::mock_vm()
+            },
+        );
+        if let Some((range, ip)) = ip {
+            db.insert_vm_ip_assignment(&VmIpAssignment {
+                vm_id,
+                ip_range_id: range,
+                ip: ip.to_string(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        }
+        uid
+    }
+
+    /// A second region with its own host
+    async fn setup() -> MockDb {
+        let db = MockDb::default();
+        let region = db.regions.lock().await.get(&1).cloned().unwrap();
+        db.regions.lock().await.insert(
+            2,
+            Region {
+                id: 2,
+                name: "Other".to_string(),
+                ..region
+            },
+        );
+        let host = db.hosts.lock().await.get(&1).cloned().unwrap();
+        db.hosts.lock().await.insert(
+            2,
+            VmHost {
+                id: 2,
+                region_id: 2,
+                ..host
+            },
+        );
+        db
+    }
+
+    /// Users sent a notification, in the order they were queued
+    async fn notified_users(wc: &ChannelWorkCommander) -> Vec<u64> {
+        wc.list_pending()
+            .await
+            .unwrap()
+            .into_iter()
+            .filter_map(|j| match j.job {
+                WorkJob::SendNotification { user_id, .. } => Some(user_id),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn incident(region_id: Option<u64>, ip_range_id: Option<u64>) -> Incident {
+        Incident {
+            region_id,
+            ip_range_id,
+            message: "Network outage".to_string(),
+            started: Utc::now(),
+            admin_user_id: 1,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(validate(&incident(Some(1), None)).is_ok());
+        assert!(validate(&incident(None, Some(1))).is_ok());
+        assert!(validate(&incident(None, None)).is_err());
+        assert!(validate(&incident(Some(1), Some(1))).is_err());
+
+        let mut i = incident(Some(1), None);
+        i.message = " ".to_string();
+        assert!(validate(&i).is_err());
+        let mut i = incident(Some(1), None);
+        i.message = "é".repeat(MAX_MESSAGE_LEN);
+        assert!(validate(&i).is_ok());
+        i.message.push('x');
+        assert!(validate(&i).is_err());
+        let mut i = incident(Some(1), None);
+        i.ended = Some(i.started - Duration::minutes(5));
+        assert!(validate(&i).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_notify_ip_range_owners_once() {
+        let db = setup().await;
+        // two VMs in the range owned by the same user
+        let a = add_vm(&db, 1, 1, 1, Some((1, "10.0.0.2"))).await;
+        add_vm(&db, 2, 1, 1, Some((1, "10.0.0.3"))).await;
+        let b = add_vm(&db, 3, 2, 2, Some((1, "10.0.0.4"))).await;
+        // only has an IP in another range
+        add_vm(&db, 4, 3, 1, Some((2, "fd00::4"))).await;
+
+        let db: Arc<dyn LNVpsDb> = Arc::new(db);
+        let wc = Arc::new(ChannelWorkCommander::new());
+        let work_commander: Arc<dyn WorkCommander> = wc.clone();
+        let created = raise_incident(&db, &work_commander, &incident(None, Some(1)))
+            .await
+            .unwrap();
+
+        let mut users = notified_users(&wc).await;
+        users.sort();
+        assert_eq!(users, vec![a, b]);
+        assert_eq!(created.notified, 2);
+        assert_eq!(db.admin_get_incident(created.id).await.unwrap().notified, 2);
+    }
+
+    #[tokio::test]
+    async fn test_notify_region_owners_once() {
+        let db = setup().await;
+        let a = add_vm(&db, 1, 1, 1, None).await;
+        add_vm(&db, 2, 1, 1, None).await;
+        // same user also has a VM in the other region
+        add_vm(&db, 3, 1, 2, None).await;
+        // only in the other region
+        add_vm(&db, 4, 2, 2, None).await;
+        // deleted VMs don't count
+        add_vm(&db, 5, 3, 1, None).await;
+        db.vms.lock().await.get_mut(&5).unwrap().deleted = true;
+
+        let db: Arc<dyn LNVpsDb> = Arc::new(db);
+        let wc = Arc::new(ChannelWorkCommander::new());
+        let work_commander: Arc<dyn WorkCommander> = wc.clone();
+        let created = raise_incident(&db, &work_commander, &incident(Some(1), None))
+            .await
+            .unwrap();
+
+        assert_eq!(notified_users(&wc).await, vec![a]);
+        assert_eq!(created.notified, 1);
+        assert_eq!(created.region_id, Some(1));
+    }
+
+    #[tokio::test]
+    async fn test_unknown_scope_is_not_recorded() {
+        let db: Arc<dyn LNVpsDb> = Arc::new(setup().await);
+        let wc = Arc::new(ChannelWorkCommander::new());
+        let work_commander: Arc<dyn WorkCommander> = wc.clone();
+        assert!(
+            raise_incident(&db, &work_commander, &incident(Some(99), None))
+                .await
+                .is_err()
+        );
+        assert_eq!(db.admin_list_incidents(10, 0).await.unwrap().1, 0);
+        assert!(notified_users(&wc).await.is_empty());
+    }
+}
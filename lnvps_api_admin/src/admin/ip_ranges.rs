@@ -1,8 +1,8 @@
 use crate::admin::RouterState;
 use crate::admin::auth::AdminAuth;
 use crate::admin::model::{
-    AdminIpRangeAllocationMode, AdminIpRangeInfo, AdminIpRangeRouter, CreateIpRangeRequest,
-    JobResponse, UpdateIpRangeRequest,
+    AdminIpRangeAllocationMode, AdminIpRangeInfo, AdminIpRangeRouter, AdminIpRangeVmInfo,
+    CreateIpRangeRequest, JobResponse, UpdateIpRangeRequest,
 };
 use axum::extract::{Path, Query, State};
 use axum::routing::{get, post};
@@ -11,8 +11,9 @@ use lnvps_api_common::{
     ApiData, ApiPaginatedData, ApiPaginatedResult, ApiResult, NetworkProvisioner, WorkJob,
     parse_gateway,
 };
-use lnvps_db::{AdminAction, AdminResource, IpRangeAllocationMode};
+use lnvps_db::{AdminAction, AdminResource, IpRange, IpRangeAllocationMode, LNVpsDb};
 use serde::Deserialize;
+use std::sync::Arc;
 
 pub fn router() -> Router<RouterState> {
     Router::new()
@@ -30,6 +31,10 @@ pub fn router() -> Router<RouterState> {
             "/api/admin/v1/ip_ranges/{id}/free_ips",
             get(admin_list_free_ips),
         )
+        .route(
+            "/api/admin/v1/ip_ranges/{id}/vms",
+            get(admin_list_ip_range_vms),
+        )
         .route(
             "/api/admin/v1/ip_ranges/{id}/patch_dns",
             post(admin_patch_ip_range_dns),
@@ -58,6 +63,33 @@ async fn admin_patch_ip_range_dns(
     ApiData::ok(JobResponse { job_id })
 }
 
+/// List the VMs with an IP in a range and their owners, e.g. to see which
+/// customers are affected when the range's router has an outage
+async fn admin_list_ip_range_vms(
+    auth: AdminAuth,
+    State(this): State<RouterState>,
+    Path(id): Path<u64>,
+) -> ApiResult<Vec<AdminIpRangeVmInfo>> {
+    auth.require_permission(AdminResource::IpRange, AdminAction::View)?;
+    auth.require_permission(AdminResource::Users, AdminAction::View)?;
+
+    ApiData::ok(list_ip_range_vms(&this.db, id).await?)
+}
+
+async fn list_ip_range_vms(
+    db: &Arc<dyn LNVpsDb>,
+    ip_range_id: u64,
+) -> anyhow::Result<Vec<AdminIpRangeVmInfo>> {
+    // 404 for unknown ranges rather than an empty list
+    db.admin_get_ip_range(ip_range_id).await?;
+    Ok(db
+        .list_vms_in_ip_range(ip_range_id)
+        .await?
+        .into_iter()
+        .map(Into::into)
+        .collect())
+}
+
 /// Resolve the routers that route a given IP range via its access policy.
 /// Returns an empty list when the range has no access policy or the policy has
 /// no router configured.
@@ -92,6 +124,27 @@ fn validate_gateway(gateway: &str) -> Result<String, &'static str> {
         .map_err(|_| "Invalid gateway format. Must be an IP address or CIDR notation (e.g., '10.0.0.1' or '10.0.0.1/24')")
 }
 
+/// Validate the delegated prefix settings of a range: delegation is IPv6 only and
+/// the delegated prefix must fit inside the range.
+fn validate_delegated_prefix(ip_range: &IpRange) -> Result<(), &'static str> {
+    let Ok(cidr) = ip_range.cidr.parse::<ipnetwork::IpNetwork>() else {
+        return Err("Invalid CIDR format");
+    };
+    if matches!(
+        ip_range.allocation_mode,
+        IpRangeAllocationMode::DelegatedPrefix
+    ) && cidr.is_ipv4()
+    {
+        return Err("Prefix delegation is only supported for IPv6 ranges");
+    }
+    if let Some(len) = ip_range.delegated_prefix
+        && (len > 128 || len < cidr.prefix())
+    {
+        return Err("Delegated prefix must be between the range prefix and /128");
+    }
+    Ok(())
+}
+
 #[derive(Deserialize, Default)]
 #[serde(default)]
 struct IpRangeQuery {
@@ -260,6 +313,9 @@ async fn admin_create_ip_range(
     // Create IP range object with normalized gateway
     let mut ip_range = req.to_ip_range()?;
     ip_range.gateway = normalized_gateway;
+    if let Err(err) = validate_delegated_prefix(&ip_range) {
+        return ApiData::err(err);
+    }
 
     let ip_range_id = this.db.admin_create_ip_range(&ip_range).await?;
 
@@ -365,6 +421,7 @@ async fn admin_update_ip_range(
             AdminIpRangeAllocationMode::Random => IpRangeAllocationMode::Random,
             AdminIpRangeAllocationMode::Sequential => IpRangeAllocationMode::Sequential,
             AdminIpRangeAllocationMode::SlaacEui64 => IpRangeAllocationMode::SlaacEui64,
+            AdminIpRangeAllocationMode::DelegatedPrefix => IpRangeAllocationMode::DelegatedPrefix,
         };
         ip_range.allocation_mode = db_allocation_mode;
     }
@@ -398,6 +455,13 @@ async fn admin_update_ip_range(
             .filter(|s| !s.is_empty());
     }
 
+    if let Some(delegated_prefix) = req.delegated_prefix {
+        ip_range.delegated_prefix = delegated_prefix;
+    }
+    if let Err(err) = validate_delegated_prefix(&ip_range) {
+        return ApiData::err(err);
+    }
+
     // Update IP range in database
     this.db.admin_update_ip_range(&ip_range).await?;
 
@@ -471,3 +535,70 @@ async fn admin_list_free_ips(
 
     ApiData::ok(free_ip_strings)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lnvps_api_common::MockDb;
+    use lnvps_db::{LNVpsDbBase, Vm, VmIpAssignment};
+
+    async fn add_vm(db: &MockDb, key: u8, ips: &[(u64, &str)]) -> u64 {
+        let uid = db.upsert_user(&[key; 32]).await.unwrap();
+        let vm_id = key as u64;
+        db.vms.lock().await.insert(
+            vm_id,
+            Vm {
+                id: vm_id,
+                user_id: uid,
+                ..MockDb::mock_vm()
+            },
+        );
+        for (range, ip) in ips {
+            db.insert_vm_ip_assignment(&VmIpAssignment {
+                vm_id,
+                ip_range_id: *range,
+                ip: ip.to_string(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        }
+        vm_id
+    }
+
+    #[tokio::test]
+    async fn test_list_ip_range_vms() {
+        let db = MockDb::default();
+        let a = add_vm(&db, 1, &[(1, "10.0.0.2"), (2, "fd00::2")]).await;
+        let b = add_vm(&db, 2, &[(1, "10.0.0.3")]).await;
+        let c = add_vm(&db, 3, &[(2, "fd00::3")]).await;
+        // a second IP in the same range doesn't list the VM twice
+        db.insert_vm_ip_assignment(&VmIpAssignment {
+            vm_id: b,
+            ip_range_id: 1,
+            ip: "10.0.0.4".to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        // deleted VMs are not affected
+        let d = add_vm(&db, 4, &[(1, "10.0.0.5")]).await;
+        db.vms.lock().await.get_mut(&d).unwrap().deleted = true;
+
+        let db: Arc<dyn LNVpsDb> = Arc::new(db);
+        let range1 = list_ip_range_vms(&db, 1).await.unwrap();
+        assert_eq!(
+            range1.iter().map(|v| v.vm_id).collect::<Vec<_>>(),
+            vec![a, b]
+        );
+        assert_eq!(range1[1].pubkey, hex::encode([2; 32]));
+
+        let range2 = list_ip_range_vms(&db, 2).await.unwrap();
+        assert_eq!(
+            range2.iter().map(|v| v.vm_id).collect::<Vec<_>>(),
+            vec![a, c]
+        );
+
+        assert!(list_ip_range_vms(&db, 99).await.is_err());
+    }
+}
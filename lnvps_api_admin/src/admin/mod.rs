@@ -1,11 +1,15 @@
 use axum::Router;
 use axum::extract::FromRef;
-use lnvps_api_common::{ExchangeRateService, RedisWorkFeedback, VmStateCache, WorkCommander};
+use lnvps_api_common::{
+    CustomVmFloors, ExchangeRateService, FeatureFlags, RedisWorkFeedback, VmStateCache,
+    WorkCommander,
+};
 use lnvps_db::LNVpsDb;
 use std::sync::Arc;
 
 mod access_policies;
 mod apps;
+mod audit_log;
 mod auth;
 mod bulk_message;
 mod companies;
@@ -14,7 +18,9 @@ mod costs;
 mod custom_pricing;
 mod dns_servers;
 mod docs;
+mod feature_flags;
 mod hosts;
+mod incidents;
 mod ip_ranges;
 mod ip_space;
 mod model;
@@ -25,7 +31,9 @@ mod regions;
 mod reports;
 mod roles;
 mod routers;
+mod search;
 mod subscriptions;
+mod system_notices;
 mod user_payment_methods;
 mod users;
 mod vm_ip_assignments;
@@ -33,6 +41,7 @@ mod vm_os_images;
 mod vm_templates;
 mod vms;
 mod websocket;
+mod work_jobs;
 
 #[derive(Clone, FromRef)]
 pub(crate) struct RouterState {
@@ -41,6 +50,10 @@ pub(crate) struct RouterState {
     pub feedback: Option<RedisWorkFeedback>,
     pub vm_state_cache: VmStateCache,
     pub exchange: Arc<dyn ExchangeRateService>,
+    /// Invalidated whenever a feature flag is changed through the admin API
+    pub features: FeatureFlags,
+    /// Configured `custom-vm-floors`, for custom pricing validation and pricing engines
+    pub custom_floors: CustomVmFloors,
 }
 
 pub fn admin_router(
@@ -49,12 +62,15 @@ pub fn admin_router(
     vm_state_cache: VmStateCache,
     exchange: Arc<dyn ExchangeRateService>,
     feedback: Option<RedisWorkFeedback>,
+    custom_floors: CustomVmFloors,
 ) -> Router {
     Router::new()
         .merge(docs::router())
         .merge(users::router())
+        .merge(search::router())
         .merge(passkeys::router())
         .merge(bulk_message::router())
+        .merge(audit_log::router())
         .merge(vms::router())
         .merge(hosts::router())
         .merge(regions::router())
@@ -72,17 +88,23 @@ pub fn admin_router(
         .merge(dns_servers::router())
         .merge(vm_ip_assignments::router())
         .merge(subscriptions::router())
+        .merge(system_notices::router())
+        .merge(feature_flags::router())
+        .merge(incidents::router())
         .merge(referrals::router())
         .merge(apps::router())
         .merge(reports::router())
         .merge(websocket::router())
+        .merge(work_jobs::router())
         .merge(payment_methods::router())
         .merge(user_payment_methods::router())
         .with_state(RouterState {
+            features: FeatureFlags::new(db.clone()),
             db,
             work_commander,
             vm_state_cache,
             feedback,
             exchange,
+            custom_floors,
         })
 }
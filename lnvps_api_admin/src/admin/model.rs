@@ -6,13 +6,13 @@ use std::str::FromStr;
 use std::sync::Arc;
 
 use lnvps_api_common::{
-    ApiDiskInterface, ApiDiskType, ApiIntervalType, ApiOsDistribution,
-    ApiSubscriptionLineItemResource, VmRunningState,
+    ApiDiskInterface, ApiDiskType, ApiIntervalType, ApiNoticeSeverity, ApiOsDistribution,
+    ApiSubscriptionLineItemResource, ApiTaxDisplay, VmRunningState, WorkJob, WorkJobMessage,
 };
 use lnvps_db::{
     AdminAction, AdminResource, AdminRole, IpRangeAllocationMode, NetworkAccessPolicy,
-    OsDistribution, PaymentMethod, RouterKind, SubscriptionPayment, SubscriptionType, VmHistory,
-    VmHistoryActionType, VmHostKind,
+    OsDistribution, OsImageKind, PaymentMethod, RouterKind, SubscriptionPayment, SubscriptionType,
+    VmHistory, VmHistoryActionType, VmHostKind,
 };
 
 // Admin API Enums - Using enums from common crate where available, creating new ones only where needed
@@ -45,12 +45,42 @@ impl From<AdminVmHostKind> for VmHostKind {
     }
 }
 
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum AdminOsImageKind {
+    #[default]
+    CloudInit,
+    Iso,
+    ContainerTemplate,
+}
+
+impl From<OsImageKind> for AdminOsImageKind {
+    fn from(kind: OsImageKind) -> Self {
+        match kind {
+            OsImageKind::CloudInit => AdminOsImageKind::CloudInit,
+            OsImageKind::Iso => AdminOsImageKind::Iso,
+            OsImageKind::ContainerTemplate => AdminOsImageKind::ContainerTemplate,
+        }
+    }
+}
+
+impl From<AdminOsImageKind> for OsImageKind {
+    fn from(kind: AdminOsImageKind) -> Self {
+        match kind {
+            AdminOsImageKind::CloudInit => OsImageKind::CloudInit,
+            AdminOsImageKind::Iso => OsImageKind::Iso,
+            AdminOsImageKind::ContainerTemplate => OsImageKind::ContainerTemplate,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
 #[serde(rename_all = "snake_case")]
 pub enum AdminIpRangeAllocationMode {
     Random,
     Sequential,
     SlaacEui64,
+    DelegatedPrefix,
 }
 
 impl From<IpRangeAllocationMode> for AdminIpRangeAllocationMode {
@@ -59,6 +89,7 @@ impl From<IpRangeAllocationMode> for AdminIpRangeAllocationMode {
             IpRangeAllocationMode::Random => AdminIpRangeAllocationMode::Random,
             IpRangeAllocationMode::Sequential => AdminIpRangeAllocationMode::Sequential,
             IpRangeAllocationMode::SlaacEui64 => AdminIpRangeAllocationMode::SlaacEui64,
+            IpRangeAllocationMode::DelegatedPrefix => AdminIpRangeAllocationMode::DelegatedPrefix,
         }
     }
 }
@@ -69,6 +100,7 @@ impl From<AdminIpRangeAllocationMode> for IpRangeAllocationMode {
             AdminIpRangeAllocationMode::Random => IpRangeAllocationMode::Random,
             AdminIpRangeAllocationMode::Sequential => IpRangeAllocationMode::Sequential,
             AdminIpRangeAllocationMode::SlaacEui64 => IpRangeAllocationMode::SlaacEui64,
+            AdminIpRangeAllocationMode::DelegatedPrefix => IpRangeAllocationMode::DelegatedPrefix,
         }
     }
 }
@@ -400,6 +432,14 @@ pub struct JobResponse {
     pub job_id: String,
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AdminDrainHostResponse {
+    /// The `DrainHost` job
+    pub job_id: String,
+    /// Feedback channel the progress of each VM is published on
+    pub batch_id: String,
+}
+
 #[derive(Serialize)]
 pub struct PaginatedResponse<T> {
     pub data: Vec<T>,
@@ -425,6 +465,8 @@ pub struct AdminUserInfo {
     pub billing_state: Option<String>,
     pub billing_postcode: Option<String>,
     pub billing_tax_id: Option<String>,
+    /// Whether `billing_tax_id` was confirmed by VIES when the user saved it
+    pub billing_tax_id_validated: bool,
     /// Country (ISO 3166-1 alpha-3) resolved from the client's IP address.
     /// Independent place-of-supply evidence, captured automatically and stored
     /// separately from the self-declared `country_code`.
@@ -541,6 +583,7 @@ impl From<lnvps_db::User> for AdminUserInfo {
             billing_state: user.billing_state,
             billing_postcode: user.billing_postcode,
             billing_tax_id: user.billing_tax_id,
+            billing_tax_id_validated: user.billing_tax_id_validated,
             geo_country_code: user.geo_country_code,
             geo_ip: user.geo_ip,
             geo_updated: user.geo_updated,
@@ -579,6 +622,7 @@ impl From<lnvps_db::AdminUserInfo> for AdminUserInfo {
             billing_state: user.user_info.billing_state,
             billing_postcode: user.user_info.billing_postcode,
             billing_tax_id: user.user_info.billing_tax_id,
+            billing_tax_id_validated: user.user_info.billing_tax_id_validated,
             geo_country_code: user.user_info.geo_country_code,
             geo_ip: user.user_info.geo_ip,
             geo_updated: user.user_info.geo_updated,
@@ -689,6 +733,8 @@ pub struct AdminVmInfo {
     /// Free-form admin-only notes about this VM (not exposed to the customer)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub admin_notes: Option<String>,
+    /// VM is isolated at L2 from other VMs on its host
+    pub private_network: bool,
     /// Subscription linked to this VM (includes line items and payment count)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub subscription: Option<AdminSubscriptionInfo>,
@@ -864,6 +910,7 @@ impl AdminVmInfo {
             ref_code,
             disabled: vm.disabled,
             admin_notes: vm.admin_notes.clone(),
+            private_network: vm.private_network,
             subscription,
         })
     }
@@ -937,6 +984,8 @@ pub struct AdminHostInfo {
     pub vlan_id: Option<u64>,
     /// MTU setting for network configuration
     pub mtu: Option<u16>,
+    /// Bridge with isolated ports for private network VMs
+    pub private_bridge: Option<String>,
     pub disks: Vec<AdminHostDisk>,
     // Calculated load metrics
     pub calculated_load: CalculatedHostLoad,
@@ -948,6 +997,11 @@ pub struct AdminHostInfo {
     /// renewals are capped at this date.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub sunset_date: Option<chrono::DateTime<chrono::Utc>>,
+    /// Host is under maintenance and takes no new VMs
+    pub maintenance: bool,
+    /// Share of new VMs placed on this host relative to the other hosts in
+    /// its region
+    pub weight: u32,
 }
 
 #[derive(Serialize)]
@@ -1004,6 +1058,12 @@ pub struct AdminRegionInfo {
     pub name: String,
     pub enabled: bool,
     pub company_id: u64,
+    /// Region is under maintenance and no new VMs are placed in it
+    pub maintenance: bool,
+    /// Running VMs in this region are probed for network reachability
+    pub reachability_check: bool,
+    /// Suffix of the forward DNS names of VMs in this region
+    pub forward_dns_suffix: Option<String>,
     pub host_count: u64,
     pub total_vms: u64,
     pub total_cpu_cores: u64,
@@ -1016,6 +1076,8 @@ pub struct CreateRegionRequest {
     pub name: String,
     pub enabled: bool,
     pub company_id: u64,
+    /// Forward DNS suffix for VMs in this region
+    pub forward_dns_suffix: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -1023,6 +1085,20 @@ pub struct UpdateRegionRequest {
     pub name: Option<String>,
     pub enabled: Option<bool>,
     pub company_id: Option<u64>,
+    /// Put the region into (or take it out of) maintenance mode
+    pub maintenance: Option<bool>,
+    /// Enable or disable the VM reachability probe for this region
+    pub reachability_check: Option<bool>,
+    /// Forward DNS suffix for VMs in this region, `null` names them directly
+    /// in the forward zone
+    #[serde(default, deserialize_with = "crate::admin::model::double_option")]
+    pub forward_dns_suffix: Option<Option<String>>,
+}
+
+/// Replace the OS image allowlist of a region, an empty list offers every image
+#[derive(Deserialize)]
+pub struct RegionOsImagesRequest {
+    pub image_ids: Vec<u64>,
 }
 
 impl AdminHostInfo {
@@ -1057,6 +1133,7 @@ impl AdminHostInfo {
             load_disk: host.load_disk,
             vlan_id: host.vlan_id,
             mtu: host.mtu,
+            private_bridge: host.private_bridge.clone(),
             disks: Vec::new(), // Empty disks - should be populated separately
             calculated_load: CalculatedHostLoad {
                 overall_load: 0.0,
@@ -1070,6 +1147,8 @@ impl AdminHostInfo {
             ssh_user: host.ssh_user,
             ssh_key_configured,
             sunset_date: host.sunset_date,
+            maintenance: host.maintenance,
+            weight: host.weight,
         }
     }
 
@@ -1110,6 +1189,7 @@ impl AdminHostInfo {
             load_disk: host.load_disk,
             vlan_id: host.vlan_id,
             mtu: host.mtu,
+            private_bridge: host.private_bridge.clone(),
             disks: admin_disks,
             calculated_load: CalculatedHostLoad {
                 overall_load: 0.0,
@@ -1123,6 +1203,8 @@ impl AdminHostInfo {
             ssh_user: host.ssh_user,
             ssh_key_configured,
             sunset_date: host.sunset_date,
+            maintenance: host.maintenance,
+            weight: host.weight,
         }
     }
 
@@ -1169,6 +1251,7 @@ impl AdminHostInfo {
             load_disk: capacity.host.load_disk,
             vlan_id: capacity.host.vlan_id,
             mtu: capacity.host.mtu,
+            private_bridge: capacity.host.private_bridge.clone(),
             disks: admin_disks,
             calculated_load: CalculatedHostLoad {
                 overall_load: capacity.load(),
@@ -1182,6 +1265,8 @@ impl AdminHostInfo {
             ssh_user: capacity.host.ssh_user.clone(),
             ssh_key_configured,
             sunset_date: capacity.host.sunset_date,
+            maintenance: capacity.host.maintenance,
+            weight: capacity.host.weight,
         }
     }
 
@@ -1228,6 +1313,7 @@ impl AdminHostInfo {
             load_disk: admin_host.host.load_disk,
             vlan_id: admin_host.host.vlan_id,
             mtu: admin_host.host.mtu,
+            private_bridge: admin_host.host.private_bridge.clone(),
             disks: admin_disks,
             calculated_load: CalculatedHostLoad {
                 overall_load: 0.0,
@@ -1241,6 +1327,8 @@ impl AdminHostInfo {
             ssh_user: admin_host.host.ssh_user,
             ssh_key_configured,
             sunset_date: admin_host.host.sunset_date,
+            maintenance: admin_host.host.maintenance,
+            weight: admin_host.host.weight,
         }
     }
 
@@ -1297,6 +1385,7 @@ impl AdminHostInfo {
                     load_disk: capacity.host.load_disk,
                     vlan_id: capacity.host.vlan_id,
                     mtu: capacity.host.mtu,
+                    private_bridge: capacity.host.private_bridge.clone(),
                     disks: admin_disks,
                     calculated_load: CalculatedHostLoad {
                         overall_load: capacity.load(),
@@ -1310,6 +1399,8 @@ impl AdminHostInfo {
                     ssh_user: capacity.host.ssh_user.clone(),
                     ssh_key_configured,
                     sunset_date: capacity.host.sunset_date,
+                    maintenance: capacity.host.maintenance,
+                    weight: capacity.host.weight,
                 }
             }
             Err(_) => {
@@ -1338,6 +1429,8 @@ pub struct AdminVmOsImageInfo {
     pub active_vm_count: i64, // Number of active (non-deleted) VMs using this image
     pub sha2: Option<String>,
     pub sha2_url: Option<String>,
+    /// Kind of image; only `cloud_init` images are offered for automated provisioning
+    pub image_kind: AdminOsImageKind,
 }
 
 #[derive(Deserialize)]
@@ -1353,6 +1446,9 @@ pub struct CreateVmOsImageRequest {
     pub default_username: Option<String>,
     pub sha2: Option<String>,
     pub sha2_url: Option<String>,
+    /// Kind of image. Defaults to `cloud_init` when omitted.
+    #[serde(default)]
+    pub image_kind: AdminOsImageKind,
 }
 
 #[derive(Deserialize)]
@@ -1380,6 +1476,7 @@ pub struct UpdateVmOsImageRequest {
         deserialize_with = "lnvps_api_common::deserialize_nullable_option"
     )]
     pub sha2_url: Option<Option<String>>,
+    pub image_kind: Option<AdminOsImageKind>,
 }
 
 impl AdminVmOsImageInfo {
@@ -1410,6 +1507,7 @@ impl AdminVmOsImageInfo {
             default_username: image.default_username,
             sha2: image.sha2,
             sha2_url: image.sha2_url,
+            image_kind: image.image_kind.into(),
             active_vm_count,
         })
     }
@@ -1433,6 +1531,7 @@ impl From<lnvps_db::VmOsImage> for AdminVmOsImageInfo {
             default_username: image.default_username,
             sha2: image.sha2,
             sha2_url: image.sha2_url,
+            image_kind: image.image_kind.into(),
             active_vm_count: 0, // Default when not using the async method
         }
     }
@@ -1481,6 +1580,7 @@ impl CreateVmOsImageRequest {
             default_username: self.default_username.clone(),
             sha2: self.sha2.clone(),
             sha2_url: self.sha2_url.clone(),
+            image_kind: self.image_kind.into(),
         })
     }
 }
@@ -1509,6 +1609,9 @@ pub struct AdminVmTemplateInfo {
     pub region_name: Option<String>,
     pub cost_plan_name: Option<String>,
     pub active_vm_count: i64, // Number of active (non-deleted) VMs using this template
+    /// Whether a host in the template's region can currently fit it (None for disabled templates)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub available: Option<bool>,
     /// Maximum disk read IOPS (None = uncapped)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub disk_iops_read: Option<u32>,
@@ -1527,6 +1630,14 @@ pub struct AdminVmTemplateInfo {
     /// Maximum CPU usage as a fraction of allocated cores (None = uncapped)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub cpu_limit: Option<f32>,
+    /// Maximum number of active VMs of this template per user (None = unlimited)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_per_user: Option<u16>,
+    /// Bytes of transfer (in + out) included per month (None = unmetered)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bandwidth_quota: Option<u64>,
+    /// Number of scheduled backups kept per VM (0 = backups not offered)
+    pub backup_retention: u16,
 }
 
 #[derive(Deserialize)]
@@ -1567,6 +1678,12 @@ pub struct AdminCreateVmTemplateRequest {
     pub network_mbps: Option<u32>,
     /// Maximum CPU usage as a fraction of allocated cores, e.g. 0.5 = 50% (None = uncapped)
     pub cpu_limit: Option<f32>,
+    /// Maximum number of active VMs of this template per user (None = unlimited)
+    pub max_per_user: Option<u16>,
+    /// Bytes of transfer (in + out) included per month (None = unmetered)
+    pub bandwidth_quota: Option<u64>,
+    /// Number of scheduled backups kept per VM (0 = backups not offered, the default)
+    pub backup_retention: Option<u16>,
 }
 
 #[derive(Deserialize)]
@@ -1649,6 +1766,20 @@ pub struct AdminUpdateVmTemplateRequest {
         deserialize_with = "lnvps_api_common::deserialize_nullable_option"
     )]
     pub cpu_limit: Option<Option<f32>>,
+    /// Maximum number of active VMs of this template per user — use `null` to clear
+    #[serde(
+        default,
+        deserialize_with = "lnvps_api_common::deserialize_nullable_option"
+    )]
+    pub max_per_user: Option<Option<u16>>,
+    /// Bytes of transfer (in + out) included per month — use `null` for unmetered
+    #[serde(
+        default,
+        deserialize_with = "lnvps_api_common::deserialize_nullable_option"
+    )]
+    pub bandwidth_quota: Option<Option<u64>>,
+    /// Number of scheduled backups kept per VM, 0 stops offering backups
+    pub backup_retention: Option<u16>,
 }
 
 // Common response structures
@@ -1893,6 +2024,8 @@ pub struct AdminCompanyInfo {
     /// Maximum number of days a subscription may be prepaid/renewed in advance
     /// (0 = inherit the global default).
     pub max_prepay_days: u16,
+    /// Whether prices are shown to customers including or excluding tax
+    pub tax_display: ApiTaxDisplay,
     pub region_count: u64, // Number of regions assigned to this company
 }
 
@@ -1913,6 +2046,8 @@ pub struct CreateCompanyRequest {
     pub referral_rate: Option<f32>,
     /// Maximum prepay/renewal window in days (0 = inherit the global default).
     pub max_prepay_days: Option<u16>,
+    /// Show prices including or excluding tax (default: exclusive).
+    pub tax_display: Option<ApiTaxDisplay>,
 }
 
 #[derive(Deserialize)]
@@ -1932,6 +2067,8 @@ pub struct UpdateCompanyRequest {
     pub referral_rate: Option<f32>,
     /// Maximum prepay/renewal window in days (0 = inherit the global default).
     pub max_prepay_days: Option<u16>,
+    /// Show prices including or excluding tax (default: exclusive).
+    pub tax_display: Option<ApiTaxDisplay>,
 }
 
 impl From<lnvps_db::Company> for AdminCompanyInfo {
@@ -1952,6 +2089,7 @@ impl From<lnvps_db::Company> for AdminCompanyInfo {
             base_currency: company.base_currency,
             referral_rate: company.referral_rate,
             max_prepay_days: company.max_prepay_days,
+            tax_display: company.tax_display.into(),
             region_count: 0, // Will be filled by handler
         }
     }
@@ -2123,7 +2261,8 @@ pub struct AdminIpRangeInfo {
     pub forward_dns_server_id: Option<u64>,
     pub reverse_dns_server_id: Option<u64>,
     pub forward_zone_id: Option<String>,
-    pub assignment_count: u64, // Number of active IP assignments in this range
+    pub delegated_prefix: Option<u8>, // Prefix length per VM for delegated_prefix ranges
+    pub assignment_count: u64,        // Number of active IP assignments in this range
     #[serde(skip_serializing_if = "Option::is_none")]
     pub available_ips: Option<u64>, // Number of available IPs (only for IPv4 ranges)
     /// Routers that route this range, resolved via the range's access policy.
@@ -2138,6 +2277,33 @@ pub struct AdminIpRangeRouter {
     pub name: String,
 }
 
+/// A VM with an IP in an IP range, and its owner
+#[derive(Serialize)]
+pub struct AdminIpRangeVmInfo {
+    pub vm_id: u64,
+    pub host_id: u64,
+    pub user_id: u64,
+    /// Owner pubkey (hex encoded)
+    pub pubkey: String,
+    pub email: Option<String>,
+}
+
+impl From<(lnvps_db::Vm, lnvps_db::User)> for AdminIpRangeVmInfo {
+    fn from((vm, user): (lnvps_db::Vm, lnvps_db::User)) -> Self {
+        Self {
+            vm_id: vm.id,
+            host_id: vm.host_id,
+            user_id: user.id,
+            pubkey: hex::encode(&user.pubkey),
+            email: if user.email.is_empty() {
+                None
+            } else {
+                Some(user.email.into())
+            },
+        }
+    }
+}
+
 #[derive(Deserialize)]
 pub struct CreateIpRangeRequest {
     pub cidr: String,
@@ -2151,6 +2317,7 @@ pub struct CreateIpRangeRequest {
     pub forward_dns_server_id: Option<u64>,
     pub reverse_dns_server_id: Option<u64>,
     pub forward_zone_id: Option<String>,
+    pub delegated_prefix: Option<u8>, // default: 64 for delegated_prefix ranges
 }
 
 #[derive(Deserialize)]
@@ -2186,6 +2353,11 @@ pub struct UpdateIpRangeRequest {
         deserialize_with = "lnvps_api_common::deserialize_nullable_option"
     )]
     pub forward_zone_id: Option<Option<String>>,
+    #[serde(
+        default,
+        deserialize_with = "lnvps_api_common::deserialize_nullable_option"
+    )]
+    pub delegated_prefix: Option<Option<u8>>,
 }
 
 // Access Policy Models for IP range management
@@ -2215,6 +2387,7 @@ impl From<lnvps_db::IpRange> for AdminIpRangeInfo {
             forward_dns_server_id: ip_range.forward_dns_server_id,
             reverse_dns_server_id: ip_range.reverse_dns_server_id,
             forward_zone_id: ip_range.forward_zone_id,
+            delegated_prefix: ip_range.delegated_prefix,
             assignment_count: 0, // Will be filled by handler
             available_ips: None, // Will be filled by handler for IPv4 ranges
             routers: Vec::new(), // Will be filled by handler
@@ -2262,6 +2435,7 @@ impl CreateIpRangeRequest {
                 .as_ref()
                 .map(|s| s.trim().to_string())
                 .filter(|s| !s.is_empty()),
+            delegated_prefix: self.delegated_prefix,
         })
     }
 }
@@ -2681,6 +2855,10 @@ pub enum AdminVmHistoryActionType {
     PaymentReceived,
     ConfigurationChanged,
     Transferred,
+    ReachabilityChanged,
+    Rebooted,
+    Suspended,
+    Resumed,
 }
 
 impl From<VmHistoryActionType> for AdminVmHistoryActionType {
@@ -2700,6 +2878,12 @@ impl From<VmHistoryActionType> for AdminVmHistoryActionType {
                 AdminVmHistoryActionType::ConfigurationChanged
             }
             VmHistoryActionType::Transferred => AdminVmHistoryActionType::Transferred,
+            VmHistoryActionType::ReachabilityChanged => {
+                AdminVmHistoryActionType::ReachabilityChanged
+            }
+            VmHistoryActionType::Rebooted => AdminVmHistoryActionType::Rebooted,
+            VmHistoryActionType::Suspended => AdminVmHistoryActionType::Suspended,
+            VmHistoryActionType::Resumed => AdminVmHistoryActionType::Resumed,
         }
     }
 }
@@ -2805,6 +2989,8 @@ pub struct AdminVmPaymentInfo {
     pub is_paid: bool,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub paid_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub invoice_number: Option<String>,
     pub rate: f32, // Exchange rate to base currency (EUR)
                    // Note: external_data is omitted as it may contain sensitive payment provider data
 }
@@ -2829,6 +3015,7 @@ impl AdminVmPaymentInfo {
             external_id: payment.external_id.clone(),
             is_paid: payment.is_paid,
             paid_at: payment.paid_at,
+            invoice_number: payment.invoice_number.clone(),
             rate: payment.rate,
         }
     }
@@ -2849,6 +3036,8 @@ pub struct AdminVmIpAssignmentInfo {
     pub dns_forward_ref: Option<String>,
     pub dns_reverse: Option<String>,
     pub dns_reverse_ref: Option<String>,
+    pub prefix_length: Option<u8>, // Set when this is a delegated prefix
+    pub route_ref: Option<String>,
     pub ip_range_cidr: Option<String>,
     pub region_name: Option<String>,
 }
@@ -2870,6 +3059,8 @@ impl AdminVmIpAssignmentInfo {
             dns_forward_ref: assignment.dns_forward_ref.clone(),
             dns_reverse: assignment.dns_reverse.clone(),
             dns_reverse_ref: assignment.dns_reverse_ref.clone(),
+            prefix_length: assignment.prefix_length,
+            route_ref: assignment.route_ref.clone(),
             user_id: 0,
             ip_range_cidr: None,
             region_name: None,
@@ -2930,12 +3121,344 @@ pub struct UpdateVmIpAssignmentRequest {
 pub struct BulkMessageRequest {
     pub subject: String,
     pub message: String,
+    /// Only count recipients and render samples, nothing is sent
+    #[serde(default)]
+    pub dry_run: bool,
+    /// `confirm_token` from the dry run of this exact message, required to send
+    pub confirm_token: Option<String>,
 }
 
 #[derive(Serialize)]
 pub struct BulkMessageResponse {
     pub job_dispatched: bool,
     pub job_id: Option<String>,
+    /// Set for a dry run
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preview: Option<BulkMessagePreview>,
+}
+
+#[derive(Serialize)]
+pub struct BulkMessagePreview {
+    /// Number of customers the message would be sent to
+    pub recipients: u64,
+    /// Recipients with no usable contact channel, they will not get the message
+    pub unreachable: u64,
+    /// Recipients and a sample message per contact channel, each recipient is
+    /// counted on the one channel the message is delivered on
+    pub channels: Vec<BulkMessageChannelPreview>,
+    /// Send this back with the same subject and message to dispatch it
+    pub confirm_token: String,
+}
+
+#[derive(Serialize)]
+pub struct BulkMessageChannelPreview {
+    /// `email`, `nip17`, `telegram` or `whatsapp`
+    pub channel: String,
+    pub recipients: u64,
+    /// Subject line, NIP-17 messages have none
+    pub subject: Option<String>,
+    /// Message as rendered for the first recipient on this channel
+    pub sample: String,
+}
+
+#[derive(Serialize)]
+pub struct AdminAuditLogInfo {
+    pub id: u64,
+    pub admin_user_id: u64,
+    /// Action name, e.g. `bulk_message`
+    pub action: String,
+    /// Details of the action
+    pub detail: Option<serde_json::Value>,
+    pub created: DateTime<Utc>,
+}
+
+impl From<lnvps_db::AdminAuditLog> for AdminAuditLogInfo {
+    fn from(log: lnvps_db::AdminAuditLog) -> Self {
+        Self {
+            id: log.id,
+            admin_user_id: log.admin_user_id,
+            action: log.action,
+            detail: log
+                .detail
+                .map(|d| serde_json::from_str(&d).unwrap_or(serde_json::Value::String(d))),
+            created: log.created,
+        }
+    }
+}
+
+/// An object matched by the admin search
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum AdminSearchResult {
+    User {
+        id: u64,
+        pubkey: String,
+        email: Option<String>,
+        created: DateTime<Utc>,
+    },
+    Vm {
+        id: u64,
+        user_id: u64,
+        host_id: u64,
+        deleted: bool,
+        disabled: bool,
+    },
+    IpAssignment {
+        id: u64,
+        vm_id: u64,
+        ip_range_id: u64,
+        ip: String,
+    },
+    Payment {
+        /// Hex encoded
+        id: String,
+        subscription_id: u64,
+        user_id: u64,
+        created: DateTime<Utc>,
+        amount: u64,
+        currency: String,
+        is_paid: bool,
+    },
+}
+
+impl From<lnvps_db::User> for AdminSearchResult {
+    fn from(user: lnvps_db::User) -> Self {
+        Self::User {
+            id: user.id,
+            pubkey: hex::encode(&user.pubkey),
+            email: if user.email.is_empty() {
+                None
+            } else {
+                Some(user.email.into())
+            },
+            created: user.created,
+        }
+    }
+}
+
+impl From<lnvps_db::Vm> for AdminSearchResult {
+    fn from(vm: lnvps_db::Vm) -> Self {
+        Self::Vm {
+            id: vm.id,
+            user_id: vm.user_id,
+            host_id: vm.host_id,
+            deleted: vm.deleted,
+            disabled: vm.disabled,
+        }
+    }
+}
+
+impl From<lnvps_db::VmIpAssignment> for AdminSearchResult {
+    fn from(assignment: lnvps_db::VmIpAssignment) -> Self {
+        Self::IpAssignment {
+            id: assignment.id,
+            vm_id: assignment.vm_id,
+            ip_range_id: assignment.ip_range_id,
+            ip: assignment.ip,
+        }
+    }
+}
+
+impl From<SubscriptionPayment> for AdminSearchResult {
+    fn from(payment: SubscriptionPayment) -> Self {
+        Self::Payment {
+            id: hex::encode(&payment.id),
+            subscription_id: payment.subscription_id,
+            user_id: payment.user_id,
+            created: payment.created,
+            amount: payment.amount,
+            currency: payment.currency,
+            is_paid: payment.is_paid,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct AdminSystemNoticeInfo {
+    pub id: u64,
+    pub created: DateTime<Utc>,
+    pub message: String,
+    pub severity: ApiNoticeSeverity,
+    pub starts: DateTime<Utc>,
+    pub ends: Option<DateTime<Utc>>,
+    /// Regions affected, empty for all regions
+    pub regions: Vec<u64>,
+}
+
+impl From<lnvps_db::SystemNotice> for AdminSystemNoticeInfo {
+    fn from(notice: lnvps_db::SystemNotice) -> Self {
+        Self {
+            id: notice.id,
+            created: notice.created,
+            message: notice.message,
+            severity: notice.severity.into(),
+            starts: notice.starts,
+            ends: notice.ends,
+            regions: notice.regions.into_inner(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CreateSystemNoticeRequest {
+    pub message: String,
+    pub severity: Option<ApiNoticeSeverity>, // Default: info
+    /// Default: now
+    pub starts: Option<DateTime<Utc>>,
+    pub ends: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub regions: Vec<u64>,
+}
+
+impl CreateSystemNoticeRequest {
+    pub fn to_system_notice(&self) -> lnvps_db::SystemNotice {
+        lnvps_db::SystemNotice {
+            id: 0,
+            created: Utc::now(),
+            message: self.message.trim().to_string(),
+            severity: self.severity.map(Into::into).unwrap_or_default(),
+            starts: self.starts.unwrap_or_else(Utc::now),
+            ends: self.ends,
+            regions: self.regions.clone().into(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct UpdateSystemNoticeRequest {
+    pub message: Option<String>,
+    pub severity: Option<ApiNoticeSeverity>,
+    pub starts: Option<DateTime<Utc>>,
+    /// `null` makes the notice open ended
+    #[serde(default, deserialize_with = "crate::admin::model::double_option")]
+    pub ends: Option<Option<DateTime<Utc>>>,
+    pub regions: Option<Vec<u64>>,
+}
+
+#[derive(Serialize)]
+pub struct AdminFeatureFlagInfo {
+    pub id: u64,
+    pub key: String,
+    /// Region the flag applies to, `null` for every region
+    pub region_id: Option<u64>,
+    pub enabled: bool,
+    pub updated: DateTime<Utc>,
+}
+
+impl From<lnvps_db::FeatureFlag> for AdminFeatureFlagInfo {
+    fn from(flag: lnvps_db::FeatureFlag) -> Self {
+        Self {
+            id: flag.id,
+            key: flag.key,
+            region_id: flag.region_id,
+            enabled: flag.enabled,
+            updated: flag.updated,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CreateFeatureFlagRequest {
+    pub key: String,
+    pub region_id: Option<u64>,
+    pub enabled: bool,
+}
+
+#[derive(Deserialize)]
+pub struct UpdateFeatureFlagRequest {
+    /// `null` makes the flag apply to every region
+    #[serde(default, deserialize_with = "crate::admin::model::double_option")]
+    pub region_id: Option<Option<u64>>,
+    pub enabled: Option<bool>,
+}
+
+#[derive(Serialize)]
+pub struct AdminIncidentInfo {
+    pub id: u64,
+    pub created: DateTime<Utc>,
+    pub region_id: Option<u64>,
+    pub ip_range_id: Option<u64>,
+    pub message: String,
+    pub started: DateTime<Utc>,
+    pub ended: Option<DateTime<Utc>>,
+    pub admin_user_id: u64,
+    /// Number of customers notified
+    pub notified: u64,
+}
+
+impl From<lnvps_db::Incident> for AdminIncidentInfo {
+    fn from(incident: lnvps_db::Incident) -> Self {
+        Self {
+            id: incident.id,
+            created: incident.created,
+            region_id: incident.region_id,
+            ip_range_id: incident.ip_range_id,
+            message: incident.message,
+            started: incident.started,
+            ended: incident.ended,
+            admin_user_id: incident.admin_user_id,
+            notified: incident.notified,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CreateIncidentRequest {
+    /// Either `region_id` or `ip_range_id` is required
+    pub region_id: Option<u64>,
+    pub ip_range_id: Option<u64>,
+    pub message: String,
+    /// Default: now
+    pub started: Option<DateTime<Utc>>,
+    pub ended: Option<DateTime<Utc>>,
+}
+
+impl CreateIncidentRequest {
+    pub fn to_incident(&self, admin_user_id: u64) -> lnvps_db::Incident {
+        lnvps_db::Incident {
+            id: 0,
+            created: Utc::now(),
+            region_id: self.region_id,
+            ip_range_id: self.ip_range_id,
+            message: self.message.trim().to_string(),
+            started: self.started.unwrap_or_else(Utc::now),
+            ended: self.ended,
+            admin_user_id,
+            notified: 0,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct UpdateIncidentRequest {
+    pub message: Option<String>,
+    pub started: Option<DateTime<Utc>>,
+    /// `null` re-opens the incident
+    #[serde(default, deserialize_with = "crate::admin::model::double_option")]
+    pub ended: Option<Option<DateTime<Utc>>>,
+}
+
+#[derive(Serialize)]
+pub struct AdminWorkJobInfo {
+    /// Work stream id of the job
+    pub id: String,
+    /// Job name, e.g. `CheckVm`
+    pub job_type: String,
+    /// Full job payload
+    pub job: WorkJob,
+    /// Job has been picked up by a worker but not finished yet
+    pub in_progress: bool,
+}
+
+impl From<WorkJobMessage> for AdminWorkJobInfo {
+    fn from(value: WorkJobMessage) -> Self {
+        Self {
+            id: value.id,
+            job_type: value.job.to_string(),
+            job: value.job,
+            in_progress: value.is_pending,
+        }
+    }
 }
 
 #[derive(Deserialize)]
@@ -2955,6 +3478,8 @@ pub struct AdminImportVmRequest {
     pub host_vm_id: i64,
     /// User the imported VM is assigned to
     pub user_id: u64,
+    /// Standard template to bill the VM with, region custom pricing if unset
+    pub template_id: Option<u64>,
     pub reason: Option<String>,
 }
 
@@ -2971,6 +3496,7 @@ pub struct AdminUnmanagedVmInfo {
     pub disk_size: u64,
     pub disk_storage: Option<String>,
     pub mac_address: Option<String>,
+    pub ip_addresses: Vec<String>,
     pub running: bool,
 }
 
@@ -2985,6 +3511,7 @@ impl From<lnvps_api_common::HostVmSpec> for AdminUnmanagedVmInfo {
             disk_size: s.disk_size,
             disk_storage: s.disk_storage,
             mac_address: s.mac_address,
+            ip_addresses: s.ip_addresses,
             running: s.running,
         }
     }
@@ -3218,6 +3745,8 @@ pub struct AdminSubscriptionPaymentInfo {
     pub is_paid: bool,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub paid_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub invoice_number: Option<String>,
     pub rate: f32,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub time_value: Option<u64>,
@@ -3270,6 +3799,7 @@ impl AdminSubscriptionPaymentInfo {
             external_id: payment.external_id,
             is_paid: payment.is_paid,
             paid_at: payment.paid_at,
+            invoice_number: payment.invoice_number,
             rate: payment.rate,
             time_value: payment.time_value,
             metadata: payment.metadata,
@@ -3293,6 +3823,7 @@ impl AdminSubscriptionPaymentInfo {
             external_id: payment.external_id,
             is_paid: payment.is_paid,
             paid_at: payment.paid_at,
+            invoice_number: payment.invoice_number,
             rate: payment.rate,
             time_value: payment.time_value,
             metadata: payment.metadata,
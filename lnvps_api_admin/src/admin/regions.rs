@@ -1,11 +1,17 @@
 use crate::admin::RouterState;
 use crate::admin::auth::AdminAuth;
-use crate::admin::model::{AdminRegionInfo, CreateRegionRequest, UpdateRegionRequest};
+use crate::admin::model::{
+    AdminRegionInfo, CreateRegionRequest, RegionOsImagesRequest, UpdateRegionRequest,
+};
 use axum::extract::{Path, Query, State};
 use axum::routing::get;
 use axum::{Json, Router};
-use lnvps_api_common::{ApiData, ApiPaginatedData, ApiPaginatedResult, ApiResult, PageQuery};
+use lnvps_api_common::{
+    ApiData, ApiError, ApiPaginatedData, ApiPaginatedResult, ApiResult, PageQuery, WorkJob,
+    is_valid_forward_suffix,
+};
 use lnvps_db::{AdminAction, AdminResource};
+use log::warn;
 use serde::Serialize;
 
 pub fn router() -> Router<RouterState> {
@@ -20,6 +26,10 @@ pub fn router() -> Router<RouterState> {
                 .patch(admin_update_region)
                 .delete(admin_delete_region),
         )
+        .route(
+            "/api/admin/v1/regions/{id}/os_images",
+            get(admin_get_region_os_images).put(admin_set_region_os_images),
+        )
 }
 
 /// List all regions with pagination
@@ -46,6 +56,9 @@ async fn admin_list_regions(
             name: region.name,
             enabled: region.enabled,
             company_id: region.company_id,
+            maintenance: region.maintenance,
+            reachability_check: region.reachability_check,
+            forward_dns_suffix: region.forward_dns_suffix,
             host_count: stats.host_count,
             total_vms: stats.total_vms,
             total_cpu_cores: stats.total_cpu_cores,
@@ -74,6 +87,9 @@ async fn admin_get_region(
         name: region.name,
         enabled: region.enabled,
         company_id: region.company_id,
+        maintenance: region.maintenance,
+        reachability_check: region.reachability_check,
+        forward_dns_suffix: region.forward_dns_suffix,
         host_count: stats.host_count,
         total_vms: stats.total_vms,
         total_cpu_cores: stats.total_cpu_cores,
@@ -92,6 +108,7 @@ async fn admin_create_region(
 ) -> ApiResult<AdminRegionInfo> {
     // Check permission
     auth.require_permission(AdminResource::Hosts, AdminAction::Create)?;
+    let forward_dns_suffix = parse_forward_suffix(req.forward_dns_suffix.as_deref())?;
 
     let region_id = this
         .db
@@ -99,12 +116,19 @@ async fn admin_create_region(
         .await?;
 
     // Get the created region
-    let region = this.db.get_host_region(region_id).await?;
+    let mut region = this.db.get_host_region(region_id).await?;
+    if forward_dns_suffix.is_some() {
+        region.forward_dns_suffix = forward_dns_suffix;
+        this.db.admin_update_region(&region).await?;
+    }
     let region_info = AdminRegionInfo {
         id: region.id,
         name: region.name,
         enabled: region.enabled,
         company_id: region.company_id,
+        maintenance: region.maintenance,
+        reachability_check: region.reachability_check,
+        forward_dns_suffix: region.forward_dns_suffix,
         host_count: 0, // New region has no hosts
         total_vms: 0,
         total_cpu_cores: 0,
@@ -138,10 +162,41 @@ async fn admin_update_region(
     if let Some(company_id) = req.company_id {
         region.company_id = company_id;
     }
+    if let Some(maintenance) = req.maintenance {
+        region.maintenance = maintenance;
+    }
+    if let Some(reachability_check) = req.reachability_check {
+        region.reachability_check = reachability_check;
+    }
+    let mut suffix_changed = false;
+    if let Some(suffix) = &req.forward_dns_suffix {
+        let suffix = parse_forward_suffix(suffix.as_deref())?;
+        suffix_changed = suffix != region.forward_dns_suffix;
+        region.forward_dns_suffix = suffix;
+    }
 
     // Save changes
     this.db.admin_update_region(&region).await?;
 
+    // Rename the forward records (and PTRs pointing at them) of existing VMs
+    if suffix_changed {
+        for range in this.db.list_ip_range_in_region(id).await? {
+            if let Err(e) = this
+                .work_commander
+                .send(WorkJob::PatchIpRangeDns {
+                    ip_range_id: range.id,
+                    admin_user_id: Some(auth.user_id),
+                })
+                .await
+            {
+                warn!(
+                    "Failed to queue DNS update for IP range {}: {}",
+                    range.id, e
+                );
+            }
+        }
+    }
+
     // Return updated region
     let stats = this.db.admin_get_region_stats(id).await?;
     let region_info = AdminRegionInfo {
@@ -149,6 +204,9 @@ async fn admin_update_region(
         name: region.name,
         enabled: region.enabled,
         company_id: region.company_id,
+        maintenance: region.maintenance,
+        reachability_check: region.reachability_check,
+        forward_dns_suffix: region.forward_dns_suffix,
         host_count: stats.host_count,
         total_vms: stats.total_vms,
         total_cpu_cores: stats.total_cpu_cores,
@@ -159,6 +217,19 @@ async fn admin_update_region(
     ApiData::ok(region_info)
 }
 
+/// Normalize a forward DNS suffix, an empty suffix is the same as none
+fn parse_forward_suffix(suffix: Option<&str>) -> Result<Option<String>, ApiError> {
+    let suffix = suffix
+        .map(|s| s.trim().trim_matches('.').to_lowercase())
+        .filter(|s| !s.is_empty());
+    if let Some(suffix) = &suffix
+        && !is_valid_forward_suffix(suffix)
+    {
+        return Err(ApiError::bad_request("Invalid forward DNS suffix"));
+    }
+    Ok(suffix)
+}
+
 /// Delete/disable region
 async fn admin_delete_region(
     auth: AdminAuth,
@@ -176,6 +247,35 @@ async fn admin_delete_region(
     })
 }
 
+/// Get the OS image allowlist of a region, empty when every image is offered
+async fn admin_get_region_os_images(
+    auth: AdminAuth,
+    State(this): State<RouterState>,
+    Path(id): Path<u64>,
+) -> ApiResult<Vec<u64>> {
+    auth.require_permission(AdminResource::Hosts, AdminAction::View)?;
+
+    this.db.get_host_region(id).await?;
+    ApiData::ok(this.db.list_region_os_images(id).await?)
+}
+
+/// Replace the OS image allowlist of a region
+async fn admin_set_region_os_images(
+    auth: AdminAuth,
+    State(this): State<RouterState>,
+    Path(id): Path<u64>,
+    Json(req): Json<RegionOsImagesRequest>,
+) -> ApiResult<Vec<u64>> {
+    auth.require_permission(AdminResource::Hosts, AdminAction::Update)?;
+
+    this.db.get_host_region(id).await?;
+    for image_id in &req.image_ids {
+        this.db.get_os_image(*image_id).await?;
+    }
+    this.db.set_region_os_images(id, &req.image_ids).await?;
+    ApiData::ok(this.db.list_region_os_images(id).await?)
+}
+
 #[derive(Serialize)]
 struct RegionDeleteResponse {
     success: bool,
@@ -56,6 +56,10 @@ pub fn router() -> Router<RouterState> {
             "/api/admin/v1/routers/{id}/routes/default",
             post(admin_set_default_route).delete(admin_clear_default_route),
         )
+        .route(
+            "/api/admin/v1/routers/{id}/arp/rebuild",
+            post(admin_rebuild_router_arp),
+        )
 }
 
 /// Time-range filter for traffic history (defaults to the last 24 hours)
@@ -194,6 +198,30 @@ async fn admin_clear_default_route(
     }
 }
 
+async fn admin_rebuild_router_arp(
+    auth: AdminAuth,
+    State(this): State<RouterState>,
+    Path(router_id): Path<u64>,
+) -> ApiResult<JobResponse> {
+    auth.require_permission(AdminResource::Router, AdminAction::Update)?;
+    // fail early on an unknown router
+    this.db.get_router(router_id).await?;
+    let job = WorkJob::RebuildRouterArp { router_id };
+    match this.work_commander.send(job).await {
+        Ok(stream_id) => {
+            info!(
+                "Rebuild router ARP job queued with stream ID: {}",
+                stream_id
+            );
+            ApiData::ok(JobResponse { job_id: stream_id })
+        }
+        Err(e) => {
+            error!("Failed to queue rebuild router ARP job: {}", e);
+            ApiData::err("Failed to queue rebuild router ARP job")
+        }
+    }
+}
+
 async fn admin_toggle_bgp_session(
     auth: AdminAuth,
     State(this): State<RouterState>,
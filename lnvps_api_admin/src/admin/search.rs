@@ -0,0 +1,334 @@
+use crate::admin::RouterState;
+use crate::admin::auth::AdminAuth;
+use crate::admin::model::{AdminSearchResult, Permission};
+use axum::Router;
+use axum::extract::{Query, State};
+use axum::routing::get;
+use lnvps_api_common::{ApiError, ApiPaginatedData, ApiPaginatedResult, PageQuery};
+use lnvps_db::{AdminAction, AdminResource, DbResult, LNVpsDb};
+use serde::Deserialize;
+use std::net::IpAddr;
+use std::sync::Arc;
+
+pub fn router() -> Router<RouterState> {
+    Router::new().route("/api/admin/v1/search", get(admin_search))
+}
+
+#[derive(Deserialize)]
+struct SearchQuery {
+    #[serde(flatten)]
+    page: PageQuery,
+    /// Pubkey or payment id (64 hex chars), user/VM id or IP address
+    q: String,
+}
+
+/// What a search term was recognised as
+#[derive(Debug, PartialEq)]
+enum SearchTerm {
+    /// 32 bytes of hex, a user pubkey or a payment id
+    Hash(Vec<u8>),
+    /// A user or VM id
+    Id(u64),
+    Ip(IpAddr),
+}
+
+impl SearchTerm {
+    fn parse(q: &str) -> Result<Self, ApiError> {
+        let q = q.trim();
+        if let Ok(id) = q.parse() {
+            return Ok(Self::Id(id));
+        }
+        if let Ok(ip) = q.parse() {
+            return Ok(Self::Ip(ip));
+        }
+        if q.len() == 64
+            && let Ok(hash) = hex::decode(q)
+        {
+            return Ok(Self::Hash(hash));
+        }
+        Err(ApiError::bad_request(
+            "Search for a pubkey, payment id, user/VM id or IP address",
+        ))
+    }
+}
+
+/// Kinds of objects the admin is allowed to see in the results
+struct SearchScope {
+    users: bool,
+    /// VMs and their IP assignments
+    vms: bool,
+    payments: bool,
+}
+
+/// A lookup which found nothing is `None`, other errors are returned
+fn found<T>(res: DbResult<T>) -> DbResult<Option<T>> {
+    match res {
+        Ok(v) => Ok(Some(v)),
+        Err(e) if e.is_row_not_found() => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Find everything matching `term`
+async fn search(
+    db: &Arc<dyn LNVpsDb>,
+    term: &SearchTerm,
+    scope: &SearchScope,
+) -> DbResult<Vec<AdminSearchResult>> {
+    let mut results = Vec::new();
+    match term {
+        SearchTerm::Hash(hash) => {
+            if scope.users
+                && let Some(user) = found(db.get_user_by_pubkey(hash).await)?
+            {
+                results.push(user.into());
+            }
+            if scope.payments
+                && let Some(payment) = found(db.get_subscription_payment(hash).await)?
+            {
+                results.push(payment.into());
+            }
+        }
+        SearchTerm::Id(id) => {
+            if scope.users
+                && let Some(user) = found(db.get_user(*id).await)?
+            {
+                results.push(user.into());
+            }
+            if scope.vms
+                && let Some(vm) = found(db.get_vm(*id).await)?
+            {
+                results.push(vm.into());
+            }
+        }
+        SearchTerm::Ip(ip) => {
+            // the assignment along with the VM and customer it belongs to
+            if scope.vms
+                && let Some(assignment) =
+                    found(db.get_vm_ip_assignment_by_ip(&ip.to_string()).await)?
+            {
+                if let Some(vm) = found(db.get_vm(assignment.vm_id).await)? {
+                    if scope.users
+                        && let Some(user) = found(db.get_user(vm.user_id).await)?
+                    {
+                        results.push(user.into());
+                    }
+                    results.push(vm.into());
+                }
+                results.push(assignment.into());
+            }
+        }
+    }
+    Ok(results)
+}
+
+/// Look up a user, VM, IP assignment or payment from a single search term
+async fn admin_search(
+    auth: AdminAuth,
+    State(this): State<RouterState>,
+    Query(query): Query<SearchQuery>,
+) -> ApiPaginatedResult<AdminSearchResult> {
+    auth.require_any_permission(&[
+        Permission {
+            resource: AdminResource::Users,
+            action: AdminAction::View,
+        },
+        Permission {
+            resource: AdminResource::VirtualMachines,
+            action: AdminAction::View,
+        },
+        Permission {
+            resource: AdminResource::SubscriptionPayments,
+            action: AdminAction::View,
+        },
+    ])?;
+
+    let limit = query.page.limit.unwrap_or(50).min(100);
+    let offset = query.page.offset.unwrap_or(0);
+
+    let term = SearchTerm::parse(&query.q)?;
+    let scope = SearchScope {
+        users: auth.has_permission(AdminResource::Users, AdminAction::View),
+        vms: auth.has_permission(AdminResource::VirtualMachines, AdminAction::View),
+        payments: auth.has_permission(AdminResource::SubscriptionPayments, AdminAction::View),
+    };
+    let results = search(&this.db, &term, &scope).await?;
+    let total = results.len() as u64;
+    ApiPaginatedData::ok(
+        results
+            .into_iter()
+            .skip(offset as usize)
+            .take(limit as usize)
+            .collect(),
+        total,
+        limit,
+        offset,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use lnvps_api_common::MockDb;
+    use lnvps_db::{
+        LNVpsDbBase, PaymentMethod, SubscriptionPayment, SubscriptionPaymentType, Vm,
+        VmIpAssignment,
+    };
+
+    const ALL: SearchScope = SearchScope {
+        users: true,
+        vms: true,
+        payments: true,
+    };
+
+    /// A customer with a VM, an IP on it and a payment
+    async fn setup() -> (Arc<dyn LNVpsDb>, u64, u64) {
+        let db = MockDb::default();
+        let uid = db.upsert_user(&[1; 32]).await.unwrap();
+        let vm_id = 5;
+        db.vms.lock().await.insert(
+            vm_id,
+            Vm {
+                id: vm_id,
+                user_id: uid,
+                ..MockDb::mock_vm()
+            },
+        );
+        db.insert_vm_ip_assignment(&VmIpAssignment {
+            vm_id,
+            ip_range_id: 1,
+            ip: "10.0.0.5".to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        db.subscription_payments
+            .lock()
+            .await
+            .push(SubscriptionPayment {
+                id: vec![2; 32],
+                subscription_id: 1,
+                user_id: uid,
+                created: Utc::now(),
+                expires: Utc::now(),
+                amount: 500,
+                currency: "EUR".to_string(),
+                payment_method: PaymentMethod::Lightning,
+                payment_type: SubscriptionPaymentType::Purchase,
+                external_data: "{}".to_string().into(),
+                external_id: None,
+                is_paid: true,
+                rate: 1.0,
+                time_value: None,
+                metadata: None,
+                tax: 0,
+                processing_fee: 0,
+                paid_at: None,
+                tax_rate: None,
+                tax_country_code: None,
+                tax_treatment: None,
+                tax_evidence: None,
+                tax_breakdown: None,
+                invoice_number: None,
+            });
+        (Arc::new(db), uid, vm_id)
+    }
+
+    #[test]
+    fn test_parse_search_term() {
+        assert_eq!(SearchTerm::parse("42").unwrap(), SearchTerm::Id(42));
+        assert_eq!(
+            SearchTerm::parse(" 10.0.0.5 ").unwrap(),
+            SearchTerm::Ip("10.0.0.5".parse().unwrap())
+        );
+        assert_eq!(
+            SearchTerm::parse("2001:db8::1").unwrap(),
+            SearchTerm::Ip("2001:db8::1".parse().unwrap())
+        );
+        assert_eq!(
+            SearchTerm::parse(&hex::encode([0xab; 32])).unwrap(),
+            SearchTerm::Hash(vec![0xab; 32])
+        );
+        assert!(SearchTerm::parse("abcd").is_err());
+        assert!(SearchTerm::parse(&"zz".repeat(32)).is_err());
+        assert!(SearchTerm::parse("").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_search_pubkey() {
+        let (db, uid, _) = setup().await;
+        let term = SearchTerm::parse(&hex::encode([1; 32])).unwrap();
+        let results = search(&db, &term, &ALL).await.unwrap();
+        assert_eq!(results.len(), 1);
+        assert!(matches!(results[0], AdminSearchResult::User { id, .. } if id == uid));
+    }
+
+    #[tokio::test]
+    async fn test_search_payment_id() {
+        let (db, uid, _) = setup().await;
+        let term = SearchTerm::parse(&hex::encode([2; 32])).unwrap();
+        let results = search(&db, &term, &ALL).await.unwrap();
+        assert_eq!(results.len(), 1);
+        assert!(matches!(
+            &results[0],
+            AdminSearchResult::Payment { id, user_id, .. } if *id == hex::encode([2; 32]) && *user_id == uid
+        ));
+
+        // hidden without the payments permission
+        let scope = SearchScope {
+            payments: false,
+            ..ALL
+        };
+        assert!(search(&db, &term, &scope).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_search_id() {
+        let (db, uid, vm_id) = setup().await;
+        let results = search(&db, &SearchTerm::Id(vm_id), &ALL).await.unwrap();
+        assert!(
+            results
+                .iter()
+                .any(|r| matches!(r, AdminSearchResult::Vm { id, user_id, .. } if *id == vm_id && *user_id == uid))
+        );
+
+        let results = search(&db, &SearchTerm::Id(uid), &ALL).await.unwrap();
+        assert!(
+            results
+                .iter()
+                .any(|r| matches!(r, AdminSearchResult::User { id, .. } if *id == uid))
+        );
+
+        assert!(
+            search(&db, &SearchTerm::Id(999), &ALL)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_search_ip() {
+        let (db, uid, vm_id) = setup().await;
+        let term = SearchTerm::parse("10.0.0.5").unwrap();
+        let results = search(&db, &term, &ALL).await.unwrap();
+        assert_eq!(results.len(), 3);
+        assert!(matches!(results[0], AdminSearchResult::User { id, .. } if id == uid));
+        assert!(matches!(results[1], AdminSearchResult::Vm { id, .. } if id == vm_id));
+        assert!(matches!(
+            &results[2],
+            AdminSearchResult::IpAssignment { vm_id: v, ip, .. } if *v == vm_id && ip == "10.0.0.5"
+        ));
+
+        // the owner is only shown with the users permission
+        let scope = SearchScope {
+            users: false,
+            ..ALL
+        };
+        assert_eq!(search(&db, &term, &scope).await.unwrap().len(), 2);
+
+        let term = SearchTerm::parse("10.0.0.6").unwrap();
+        assert!(search(&db, &term, &ALL).await.unwrap().is_empty());
+    }
+}
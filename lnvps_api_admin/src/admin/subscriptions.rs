@@ -6,10 +6,12 @@ use crate::admin::model::{
     AdminUpdateSubscriptionLineItemRequest, AdminUpdateSubscriptionRequest,
 };
 use axum::extract::{Path, Query, State};
+use axum::http::header;
+use axum::response::IntoResponse;
 use axum::routing::{get, post};
 use axum::{Json, Router};
 use lnvps_api_common::{
-    ApiData, ApiError, ApiPaginatedData, ApiPaginatedResult, ApiResult, PageQuery, WorkJob,
+    ApiData, ApiError, ApiPaginatedData, ApiPaginatedResult, ApiResult, Invoice, PageQuery, WorkJob,
 };
 use lnvps_db::{AdminAction, AdminResource, LNVpsDb};
 use serde::Deserialize;
@@ -49,6 +51,10 @@ pub fn router() -> Router<RouterState> {
             "/api/admin/v1/subscription_payments/{id}",
             get(admin_get_subscription_payment),
         )
+        .route(
+            "/api/admin/v1/subscription_payments/{id}/invoice",
+            get(admin_get_subscription_payment_invoice),
+        )
         .route(
             "/api/admin/v1/subscription_payments/{id}/complete",
             post(admin_complete_subscription_payment),
@@ -473,6 +479,30 @@ async fn admin_get_subscription_payment(
     ApiData::ok(AdminSubscriptionPaymentInfo::from_with_company(payment))
 }
 
+/// Download the PDF invoice for a paid subscription payment
+async fn admin_get_subscription_payment_invoice(
+    auth: AdminAuth,
+    State(this): State<RouterState>,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    auth.require_permission(AdminResource::SubscriptionPayments, AdminAction::View)?;
+
+    let payment_id = hex::decode(&id).map_err(|_| anyhow::anyhow!("Invalid payment ID format"))?;
+
+    let payment = this.db.get_subscription_payment(&payment_id).await?;
+    let invoice = Invoice::for_payment(this.db.as_ref(), &payment).await?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/pdf".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("inline; filename=\"invoice-{}.pdf\"", id),
+            ),
+        ],
+        invoice.to_pdf(),
+    ))
+}
+
 /// Manually mark a subscription payment as paid (admin override).
 ///
 /// This calls `subscription_payment_paid` which sets `is_paid=true`,
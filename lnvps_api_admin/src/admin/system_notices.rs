@@ -0,0 +1,124 @@
+use crate::admin::RouterState;
+use crate::admin::auth::AdminAuth;
+use crate::admin::model::{
+    AdminSystemNoticeInfo, CreateSystemNoticeRequest, UpdateSystemNoticeRequest,
+};
+use axum::extract::{Path, Query, State};
+use axum::routing::get;
+use axum::{Json, Router};
+use lnvps_api_common::{ApiData, ApiPaginatedData, ApiPaginatedResult, ApiResult, PageQuery};
+use lnvps_db::{AdminAction, AdminResource, SystemNotice};
+
+pub fn router() -> Router<RouterState> {
+    Router::new()
+        .route(
+            "/api/admin/v1/system_notices",
+            get(admin_list_system_notices).post(admin_create_system_notice),
+        )
+        .route(
+            "/api/admin/v1/system_notices/{id}",
+            get(admin_get_system_notice)
+                .patch(admin_update_system_notice)
+                .delete(admin_delete_system_notice),
+        )
+}
+
+fn validate(notice: &SystemNotice) -> Result<(), &'static str> {
+    if notice.message.trim().is_empty() {
+        return Err("Message cannot be empty");
+    }
+    if notice.ends.is_some_and(|e| e <= notice.starts) {
+        return Err("Notice must end after it starts");
+    }
+    Ok(())
+}
+
+async fn admin_list_system_notices(
+    auth: AdminAuth,
+    State(this): State<RouterState>,
+    Query(params): Query<PageQuery>,
+) -> ApiPaginatedResult<AdminSystemNoticeInfo> {
+    auth.require_permission(AdminResource::System, AdminAction::View)?;
+
+    let limit = params.limit.unwrap_or(50).min(100);
+    let offset = params.offset.unwrap_or(0);
+
+    let (notices, total) = this.db.admin_list_system_notices(limit, offset).await?;
+    ApiPaginatedData::ok(
+        notices.into_iter().map(Into::into).collect(),
+        total,
+        limit,
+        offset,
+    )
+}
+
+async fn admin_get_system_notice(
+    auth: AdminAuth,
+    State(this): State<RouterState>,
+    Path(id): Path<u64>,
+) -> ApiResult<AdminSystemNoticeInfo> {
+    auth.require_permission(AdminResource::System, AdminAction::View)?;
+
+    let notice = this.db.admin_get_system_notice(id).await?;
+    ApiData::ok(notice.into())
+}
+
+async fn admin_create_system_notice(
+    auth: AdminAuth,
+    State(this): State<RouterState>,
+    Json(request): Json<CreateSystemNoticeRequest>,
+) -> ApiResult<AdminSystemNoticeInfo> {
+    auth.require_permission(AdminResource::System, AdminAction::Create)?;
+
+    let notice = request.to_system_notice();
+    if let Err(e) = validate(&notice) {
+        return ApiData::err(e);
+    }
+    let id = this.db.admin_insert_system_notice(&notice).await?;
+    let created = this.db.admin_get_system_notice(id).await?;
+    ApiData::ok(created.into())
+}
+
+async fn admin_update_system_notice(
+    auth: AdminAuth,
+    State(this): State<RouterState>,
+    Path(id): Path<u64>,
+    Json(request): Json<UpdateSystemNoticeRequest>,
+) -> ApiResult<AdminSystemNoticeInfo> {
+    auth.require_permission(AdminResource::System, AdminAction::Update)?;
+
+    let mut notice = this.db.admin_get_system_notice(id).await?;
+    if let Some(message) = &request.message {
+        notice.message = message.trim().to_string();
+    }
+    if let Some(severity) = request.severity {
+        notice.severity = severity.into();
+    }
+    if let Some(starts) = request.starts {
+        notice.starts = starts;
+    }
+    if let Some(ends) = request.ends {
+        notice.ends = ends;
+    }
+    if let Some(regions) = request.regions {
+        notice.regions = regions.into();
+    }
+    if let Err(e) = validate(&notice) {
+        return ApiData::err(e);
+    }
+
+    this.db.admin_update_system_notice(&notice).await?;
+    let updated = this.db.admin_get_system_notice(id).await?;
+    ApiData::ok(updated.into())
+}
+
+async fn admin_delete_system_notice(
+    auth: AdminAuth,
+    State(this): State<RouterState>,
+    Path(id): Path<u64>,
+) -> ApiResult<()> {
+    auth.require_permission(AdminResource::System, AdminAction::Delete)?;
+
+    this.db.admin_delete_system_notice(id).await?;
+    ApiData::ok(())
+}
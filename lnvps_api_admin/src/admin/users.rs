@@ -197,6 +197,11 @@ async fn admin_update_user(
         user.billing_postcode = Some(billing_postcode.clone());
     }
     if let Some(billing_tax_id) = &req.billing_tax_id {
+        if user.billing_tax_id.as_ref() != Some(billing_tax_id) {
+            // only the user's own save validates against VIES
+            user.billing_tax_id_validated = false;
+            user.billing_tax_id_name = None;
+        }
         user.billing_tax_id = Some(billing_tax_id.clone());
     }
     // IP-resolved geolocation evidence. Editing either field bumps geo_updated
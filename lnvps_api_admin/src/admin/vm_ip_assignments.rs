@@ -199,6 +199,8 @@ async fn admin_create_vm_ip_assignment(
         dns_forward_ref: None,
         dns_reverse: None,
         dns_reverse_ref: None,
+        prefix_length: None,
+        route_ref: None,
         ip_range_cidr: None,
         region_name: None,
     })
@@ -174,6 +174,10 @@ async fn admin_update_vm_os_image(
         image.default_username = Some(default_username.clone());
     }
 
+    if let Some(image_kind) = request.image_kind {
+        image.image_kind = image_kind.into();
+    }
+
     if let Some(sha2) = &request.sha2 {
         image.sha2 = sha2.clone();
     }
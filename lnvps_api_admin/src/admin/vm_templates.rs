@@ -8,9 +8,11 @@ use axum::routing::get;
 use axum::{Json, Router};
 use chrono::Utc;
 use lnvps_api_common::{
-    ApiData, ApiError, ApiPaginatedData, ApiPaginatedResult, ApiResult, PageQuery,
+    ApiData, ApiError, ApiPaginatedData, ApiPaginatedResult, ApiResult, HostCapacityService,
+    PageQuery,
 };
 use lnvps_db::{AdminAction, AdminResource, LNVpsDb, VmTemplate};
+use std::collections::HashMap;
 use std::sync::Arc;
 
 pub fn router() -> Router<RouterState> {
@@ -73,12 +75,16 @@ impl AdminVmTemplateInfo {
             region_name: region.map(|r| r.name),
             cost_plan_name: cost_plan.map(|cp| cp.name),
             active_vm_count,
+            available: None,
             disk_iops_read: template.disk_iops_read,
             disk_iops_write: template.disk_iops_write,
             disk_mbps_read: template.disk_mbps_read,
             disk_mbps_write: template.disk_mbps_write,
             network_mbps: template.network_mbps,
             cpu_limit: template.cpu_limit,
+            max_per_user: template.max_per_user,
+            bandwidth_quota: template.bandwidth_quota,
+            backup_retention: template.backup_retention,
         })
     }
 }
@@ -99,10 +105,20 @@ async fn admin_list_vm_templates(
         .db
         .list_vm_templates_paginated(limit as i64, offset as i64)
         .await?;
+    // one capacity lookup for the whole page
+    let available: HashMap<u64, bool> = HostCapacityService::new(this.db.clone())
+        .list_available_templates(None)
+        .await?
+        .into_iter()
+        .map(|t| (t.template.id, t.available))
+        .collect();
     let mut template_infos = Vec::new();
     for template in templates {
         match AdminVmTemplateInfo::from_vm_template(&this.db, &template).await {
-            Ok(info) => template_infos.push(info),
+            Ok(mut info) => {
+                info.available = available.get(&template.id).copied();
+                template_infos.push(info)
+            }
             Err(_) => continue,
         }
     }
@@ -120,7 +136,13 @@ async fn admin_get_vm_template(
     auth.require_permission(AdminResource::VmTemplate, AdminAction::View)?;
 
     let template = this.db.get_vm_template(id).await?;
-    let info = AdminVmTemplateInfo::from_vm_template(&this.db, &template).await?;
+    let mut info = AdminVmTemplateInfo::from_vm_template(&this.db, &template).await?;
+    info.available = HostCapacityService::new(this.db.clone())
+        .list_available_templates(Some(template.region_id))
+        .await?
+        .into_iter()
+        .find(|t| t.template.id == template.id)
+        .map(|t| t.available);
     ApiData::ok(info)
 }
 
@@ -206,6 +228,9 @@ async fn admin_create_vm_template(
         network_mbps: req.network_mbps,
         cpu_limit: req.cpu_limit,
         firewall_rule_limit: None,
+        backup_retention: req.backup_retention.unwrap_or(0),
+        max_per_user: req.max_per_user,
+        bandwidth_quota: req.bandwidth_quota,
     };
 
     let template_id = this.db.insert_vm_template(&template).await?;
@@ -337,6 +362,15 @@ async fn admin_update_vm_template(
     if let Some(v) = req.cpu_limit {
         template.cpu_limit = v;
     }
+    if let Some(v) = req.max_per_user {
+        template.max_per_user = v;
+    }
+    if let Some(v) = req.bandwidth_quota {
+        template.bandwidth_quota = v;
+    }
+    if let Some(v) = req.backup_retention {
+        template.backup_retention = v;
+    }
 
     this.db.update_vm_template(&template).await?;
     let info = AdminVmTemplateInfo::from_vm_template(&this.db, &template).await?;
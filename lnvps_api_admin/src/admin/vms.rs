@@ -9,10 +9,11 @@ use axum::routing::{get, post, put};
 use axum::{Json, Router};
 use chrono::{DateTime, Days, Utc};
 use lnvps_api_common::{
-    ApiData, ApiError, ApiPaginatedData, ApiPaginatedResult, ApiResult, PageQuery, PricingEngine,
-    UpgradeConfig, VatClient, VmHistoryLogger, VmRunningState, VmStateCache, WorkJob,
+    ApiData, ApiError, ApiPaginatedData, ApiPaginatedResult, ApiResult, DEFAULT_UNPAID_GRACE_HOURS,
+    PageQuery, PricingEngine, UnpaidVmAction, UpgradeConfig, VatClient, VmHistoryLogger,
+    VmRunningState, VmRunningStates, VmStateCache, WorkJob,
 };
-use lnvps_db::{AdminAction, AdminResource, SubscriptionPaymentType};
+use lnvps_db::{AdminAction, AdminResource, SubscriptionPaymentType, VmStateKind};
 use log::{error, info};
 use serde::Deserialize;
 
@@ -23,6 +24,11 @@ pub fn router() -> Router<RouterState> {
             get(admin_list_vms).post(admin_create_vm),
         )
         .route("/api/admin/v1/vms/extend-all", post(admin_extend_all_vms))
+        .route("/api/admin/v1/vms/unpaid", get(admin_list_unpaid_vms))
+        .route(
+            "/api/admin/v1/vms/unpaid/reclaim",
+            post(admin_reclaim_unpaid_vms),
+        )
         .route(
             "/api/admin/v1/vms/{id}",
             get(admin_get_vm)
@@ -91,6 +97,8 @@ struct ListVmsQuery {
     pubkey: Option<String>,
     #[serde(deserialize_with = "lnvps_api_common::deserialize_from_str_optional")]
     region_id: Option<u64>,
+    /// Last running state seen by the worker
+    state: Option<VmRunningStates>,
     include_deleted: Option<bool>,
 }
 
@@ -116,6 +124,7 @@ async fn admin_list_vms(
             query.host_id,
             query.pubkey.as_deref(), // Convert Option<String> to Option<&str>
             query.region_id,
+            query.state.as_ref().map(VmStateKind::from),
             query.include_deleted,
         )
         .await?;
@@ -266,6 +275,8 @@ struct AdminPatchVmRequest {
         deserialize_with = "lnvps_api_common::deserialize_nullable_option"
     )]
     admin_notes: Option<Option<String>>,
+    /// Move the VM onto (or off) its host's isolated private network bridge
+    private_network: Option<bool>,
 }
 
 /// Patch (update) a VM
@@ -296,6 +307,24 @@ async fn admin_patch_vm(
         }
     }
 
+    if let Some(private_network) = req.private_network
+        && vm.private_network != private_network
+    {
+        if private_network
+            && !this
+                .db
+                .get_host(vm.host_id)
+                .await?
+                .supports_private_network()
+        {
+            return Err(ApiError::validation(
+                "VM host does not support private networks, set a private bridge on the host",
+            ));
+        }
+        vm.private_network = private_network;
+        needs_reconfigure = true;
+    }
+
     // Handle admin notes change (DB-only metadata, no host reconfigure needed)
     if let Some(admin_notes) = &req.admin_notes
         && vm.admin_notes != *admin_notes
@@ -316,8 +345,8 @@ async fn admin_patch_vm(
     if needs_reconfigure {
         this.db.update_vm(&vm).await?;
         info!(
-            "Admin {} updated VM {}: disabled={}",
-            auth.user_id, id, vm.disabled
+            "Admin {} updated VM {}: disabled={}, private_network={}",
+            auth.user_id, id, vm.disabled, vm.private_network
         );
 
         // Send work job to reconfigure the VM on the host
@@ -719,6 +748,83 @@ async fn admin_extend_all_vms(
     ApiData::ok(AdminExtendAllVmsResult { extended, failed })
 }
 
+#[derive(Deserialize)]
+struct UnpaidVmsQuery {
+    grace_hours: Option<u64>,
+}
+
+#[derive(serde::Serialize)]
+struct AdminUnpaidVmInfo {
+    id: u64,
+    user_id: u64,
+    host_id: u64,
+    subscription_id: u64,
+    /// When the VM was ordered (subscription created)
+    created: DateTime<Utc>,
+}
+
+#[derive(Deserialize)]
+struct AdminReclaimUnpaidVmsRequest {
+    grace_hours: Option<u64>,
+    action: UnpaidVmAction,
+}
+
+/// List VMs which were never paid for and are older than the grace period
+async fn admin_list_unpaid_vms(
+    auth: AdminAuth,
+    State(this): State<RouterState>,
+    Query(query): Query<UnpaidVmsQuery>,
+) -> ApiResult<Vec<AdminUnpaidVmInfo>> {
+    auth.require_permission(AdminResource::VirtualMachines, AdminAction::View)?;
+
+    let grace_hours = query.grace_hours.unwrap_or(DEFAULT_UNPAID_GRACE_HOURS);
+    let vms = this
+        .db
+        .list_unpaid_vms(chrono::TimeDelta::hours(grace_hours as i64))
+        .await?;
+    let mut ret = Vec::with_capacity(vms.len());
+    for vm in vms {
+        let sub = this
+            .db
+            .get_subscription_by_line_item_id(vm.subscription_line_item_id)
+            .await?;
+        ret.push(AdminUnpaidVmInfo {
+            id: vm.id,
+            user_id: vm.user_id,
+            host_id: vm.host_id,
+            subscription_id: sub.id,
+            created: sub.created,
+        });
+    }
+    ApiData::ok(ret)
+}
+
+/// Queue a job to stop or delete all VMs which were never paid for
+async fn admin_reclaim_unpaid_vms(
+    auth: AdminAuth,
+    State(this): State<RouterState>,
+    Json(req): Json<AdminReclaimUnpaidVmsRequest>,
+) -> ApiResult<JobResponse> {
+    // Fleet-wide mutation, same permission as extend-all
+    auth.require_permission(AdminResource::VirtualMachines, AdminAction::BulkUpdate)?;
+
+    let job = WorkJob::ReclaimUnpaidVms {
+        grace_hours: req.grace_hours.unwrap_or(DEFAULT_UNPAID_GRACE_HOURS),
+        action: req.action,
+        admin_user_id: Some(auth.user_id),
+    };
+    match this.work_commander.send(job).await {
+        Ok(job_id) => {
+            info!("Unpaid VM reclaim job queued with stream ID: {}", job_id);
+            ApiData::ok(JobResponse { job_id })
+        }
+        Err(e) => {
+            error!("Failed to queue unpaid VM reclaim job: {}", e);
+            ApiData::err("Failed to queue unpaid VM reclaim job")
+        }
+    }
+}
+
 /// List VM history with pagination
 async fn admin_list_vm_history(
     auth: AdminAuth,
@@ -893,8 +999,12 @@ async fn admin_calculate_vm_refund(
 
     // Create pricing engine instance with real exchange rates. Refunds are
     // computed from stored payment amounts, so an empty VAT client is fine here.
-    let pricing_engine =
-        PricingEngine::new(this.db.clone(), this.exchange.clone(), VatClient::new());
+    let pricing_engine = PricingEngine::new(
+        this.db.clone(),
+        this.exchange.clone(),
+        VatClient::new(),
+        this.custom_floors,
+    );
 
     // Calculate the refund amount from the specified date
     let refund_result = pricing_engine
@@ -0,0 +1,47 @@
+use crate::admin::RouterState;
+use crate::admin::auth::AdminAuth;
+use crate::admin::model::AdminWorkJobInfo;
+use axum::Router;
+use axum::extract::{Path, State};
+use axum::routing::{delete, get};
+use lnvps_api_common::{ApiData, ApiError, ApiResult};
+use lnvps_db::{AdminAction, AdminResource};
+use log::info;
+
+pub fn router() -> Router<RouterState> {
+    Router::new()
+        .route("/api/admin/v1/work/jobs", get(admin_list_work_jobs))
+        .route(
+            "/api/admin/v1/work/jobs/{id}",
+            delete(admin_cancel_work_job),
+        )
+}
+
+/// List work jobs which are queued or in progress
+async fn admin_list_work_jobs(
+    auth: AdminAuth,
+    State(this): State<RouterState>,
+) -> ApiResult<Vec<AdminWorkJobInfo>> {
+    auth.require_permission(AdminResource::System, AdminAction::View)?;
+
+    let jobs = this.work_commander.list_pending().await?;
+    ApiData::ok(jobs.into_iter().map(AdminWorkJobInfo::from).collect())
+}
+
+/// Remove a job from the work queue
+async fn admin_cancel_work_job(
+    auth: AdminAuth,
+    State(this): State<RouterState>,
+    Path(id): Path<String>,
+) -> ApiResult<()> {
+    auth.require_permission(AdminResource::System, AdminAction::Delete)?;
+
+    if !this.work_commander.cancel(&id).await? {
+        return Err(ApiError::not_found(format!(
+            "Work job {} is not queued",
+            id
+        )));
+    }
+    info!("Admin {} cancelled work job {}", auth.user_id, id);
+    ApiData::ok(())
+}
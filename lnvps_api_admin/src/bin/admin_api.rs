@@ -1,12 +1,13 @@
 use anyhow::{Error, bail};
 use async_trait::async_trait;
+use axum::Extension;
 use clap::Parser;
 use config::{Config, File};
 use lnvps_api_admin::admin::admin_router;
 use lnvps_api_admin::settings::Settings;
 use lnvps_api_common::{
-    RedisWorkCommander, RedisWorkFeedback, VmStateCache, WorkCommander, WorkJob, WorkJobMessage,
-    make_exchange_service,
+    DbWorkCommander, RedisWorkCommander, RedisWorkFeedback, TrustedProxies, VmStateCache,
+    WorkCommander, WorkJob, WorkJobMessage, make_exchange_service, with_base_path,
 };
 use lnvps_db::{EncryptionContext, LNVpsDb, LNVpsDbBase, LNVpsDbMysql};
 use log::info;
@@ -15,23 +16,6 @@ use std::path::PathBuf;
 use std::sync::Arc;
 use tokio::net::TcpListener;
 use tokio::net::TcpSocket;
-use tower_http::cors::{AllowHeaders, Any, CorsLayer};
-
-/// CORS layer for the admin API.
-///
-/// Auth is carried in the `Authorization` header (NIP-98 / JWT), never cookies,
-/// so credentials are NOT needed. Returning `Access-Control-Allow-Origin: *`
-/// (rather than reflecting the origin like `very_permissive`) keeps Tor/Brave
-/// working, which send `Origin: null` on cross-site requests — a `null`
-/// allow-origin plus allow-credentials is rejected by browsers. Headers are
-/// mirrored because a literal `*` does not cover `Authorization`.
-fn cors_layer() -> CorsLayer {
-    CorsLayer::new()
-        .allow_origin(Any)
-        .allow_methods(Any)
-        .allow_headers(AllowHeaders::mirror_request())
-        .expose_headers(Any)
-}
 
 #[derive(Parser)]
 #[clap(about, version, author)]
@@ -71,7 +55,10 @@ async fn main() -> Result<(), Error> {
     }
 
     // Connect database and migrate
-    let db = LNVpsDbMysql::new(&settings.db).await?;
+    let db = match &settings.db_replica {
+        Some(replica) => LNVpsDbMysql::new_with_replica(&settings.db, replica).await?,
+        None => LNVpsDbMysql::new(&settings.db).await?,
+    };
     db.migrate().await?;
     let db: Arc<dyn LNVpsDb> = Arc::new(db);
 
@@ -85,6 +72,8 @@ async fn main() -> Result<(), Error> {
     // Initialize WorkCommander for job distribution (publisher mode)
     let work_commander: Arc<dyn WorkCommander> = if let Some(redis_config) = &settings.redis {
         Arc::new(RedisWorkCommander::new_publisher(&redis_config.url).await?)
+    } else if settings.db_work_queue {
+        Arc::new(DbWorkCommander::new(db.clone(), ""))
     } else {
         Arc::new(NeverWorkCommander)
     };
@@ -109,8 +98,21 @@ async fn main() -> Result<(), Error> {
         vm_state_cache,
         exchange,
         feedback,
+        settings.custom_vm_floors,
     );
-    axum::serve(listener, router.layer(cors_layer())).await?;
+    let router = with_base_path(router, settings.base_path.as_deref());
+    let router = match &settings.trusted_proxies {
+        Some(p) => router.layer(Extension(TrustedProxies::parse(p)?)),
+        None => router,
+    };
+    let cors = settings.cors.layer()?;
+    axum::serve(
+        listener,
+        router
+            .layer(cors)
+            .into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await?;
 
     Ok(())
 }
@@ -137,4 +139,16 @@ impl WorkCommander for NeverWorkCommander {
     async fn ack(&self, _id: &str) -> anyhow::Result<()> {
         Ok(())
     }
+
+    async fn list_pending(&self) -> anyhow::Result<Vec<WorkJobMessage>> {
+        bail!("Work commander not configured, not possible to list work jobs")
+    }
+
+    async fn cancel(&self, _id: &str) -> anyhow::Result<bool> {
+        bail!("Work commander not configured, not possible to cancel work jobs")
+    }
+
+    async fn worker_idle(&self) -> anyhow::Result<Option<std::time::Duration>> {
+        bail!("Work commander not configured, not possible to check the worker")
+    }
 }
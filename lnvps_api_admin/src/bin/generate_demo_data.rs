@@ -159,6 +159,7 @@ async fn create_companies(db: &LNVpsDbMysql) -> Result<Vec<Company>> {
             base_currency: "USD".to_string(),
             referral_rate: 0.0,
             max_prepay_days: 0,
+            tax_display: Default::default(),
             address_2: None,
         },
         Company {
@@ -176,6 +177,7 @@ async fn create_companies(db: &LNVpsDbMysql) -> Result<Vec<Company>> {
             base_currency: "GBP".to_string(),
             referral_rate: 0.0,
             max_prepay_days: 0,
+            tax_display: Default::default(),
             address_2: None,
         },
         Company {
@@ -193,6 +195,7 @@ async fn create_companies(db: &LNVpsDbMysql) -> Result<Vec<Company>> {
             base_currency: "EUR".to_string(),
             referral_rate: 0.0,
             max_prepay_days: 0,
+            tax_display: Default::default(),
             address_2: None,
         },
     ];
@@ -228,6 +231,9 @@ async fn create_regions(db: &LNVpsDbMysql, companies: &[Company]) -> Result<Vec<
             name: name.to_string(),
             enabled: true,
             company_id,
+            maintenance: false,
+            reachability_check: false,
+            forward_dns_suffix: None,
         };
 
         let id = db
@@ -269,6 +275,7 @@ async fn create_ip_ranges(db: &LNVpsDbMysql, regions: &[Region]) -> Result<Vec<I
             forward_dns_server_id: None,
             reverse_dns_server_id: None,
             forward_zone_id: None,
+            delegated_prefix: None,
         };
 
         let id = db.admin_create_ip_range(&ip_range).await?;
@@ -377,9 +384,12 @@ async fn create_hosts(db: &LNVpsDbMysql, regions: &[Region]) -> Result<Vec<VmHos
             load_disk: 1.0,
             vlan_id: None,
             mtu: None,
+            private_bridge: None,
             ssh_user: None,
             ssh_key: None,
             sunset_date: None,
+            maintenance: false,
+            weight: 1,
         };
 
         let id = db.create_host(&host).await?;
@@ -503,6 +513,7 @@ async fn create_os_images(db: &LNVpsDbMysql) -> Result<Vec<VmOsImage>> {
             default_username: Some("ubuntu".to_string()),
             sha2: None,
             sha2_url: None,
+            image_kind: lnvps_db::OsImageKind::CloudInit,
         };
 
         let id = db.admin_create_vm_os_image(&os_image).await?;
@@ -1109,6 +1120,8 @@ async fn create_users(db: &LNVpsDbMysql) -> Result<Vec<User>> {
             billing_state: None,
             billing_postcode: None,
             billing_tax_id: None,
+            billing_tax_id_validated: false,
+            billing_tax_id_name: None,
             email_hash: None,
         };
 
@@ -1211,6 +1224,8 @@ async fn create_vms(
             fw_policy_in: None,
             fw_policy_out: None,
             admin_notes: None,
+            private_network: false,
+            suspended_at: None,
         };
 
         let id = db.insert_vm(&vm).await?;
@@ -1258,6 +1273,8 @@ async fn create_vms(
             fw_policy_in: None,
             fw_policy_out: None,
             admin_notes: None,
+            private_network: false,
+            suspended_at: None,
         };
 
         let id = db.insert_vm(&vm).await?;
@@ -1286,6 +1303,8 @@ async fn create_ip_assignments(db: &LNVpsDbMysql, vms: &[Vm], ip_ranges: &[IpRan
             dns_forward_ref: None,
             dns_reverse: None,
             dns_reverse_ref: None,
+            prefix_length: None,
+            route_ref: None,
         };
 
         db.insert_vm_ip_assignment(&assignment).await?;
@@ -1361,6 +1380,7 @@ async fn create_payments(db: &LNVpsDbMysql, vms: &[Vm]) -> Result<()> {
             tax_treatment: None,
             tax_evidence: None,
             tax_breakdown: None,
+            invoice_number: None,
         };
 
         db.insert_subscription_payment(&payment).await?;
@@ -1,4 +1,4 @@
-use lnvps_api_common::RedisConfig;
+use lnvps_api_common::{CorsSettings, CustomVmFloors, RedisConfig};
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 
@@ -8,15 +8,40 @@ pub struct Settings {
     /// Listen address for http server
     pub listen: Option<String>,
 
+    /// Path prefix all routes are mounted under (e.g. `/api`), for serving
+    /// behind a shared reverse proxy without rewriting
+    pub base_path: Option<String>,
+
+    /// Reverse proxies (CIDRs) whose `X-Forwarded-For` header is believed when
+    /// finding the client IP, headers are always believed when not set
+    pub trusted_proxies: Option<Vec<String>>,
+
+    /// Origins, methods and headers allowed for cross-origin requests
+    #[serde(default)]
+    pub cors: CorsSettings,
+
     /// MYSQL connection string
     pub db: String,
 
+    /// MYSQL read replica connection string, used for listings and reports
+    pub db_replica: Option<String>,
+
     /// Redis configuration for shared VM state cache
     pub redis: Option<RedisConfig>,
 
+    /// Send worker jobs through the database work queue when Redis is not
+    /// configured, must match the API worker setting
+    #[serde(default)]
+    pub db_work_queue: bool,
+
     /// Database encryption configuration (fallback when the
     /// `LNVPS_ENCRYPTION_KEY` environment variable is not set)
     pub encryption: Option<EncryptionConfig>,
+
+    /// Lowest minimums a custom pricing plan may be saved with, should match
+    /// the API setting
+    #[serde(default)]
+    pub custom_vm_floors: CustomVmFloors,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -1,15 +1,18 @@
 use crate::Template;
-use crate::network::parse_gateway;
+use crate::network::NetworkProvisioner;
 use anyhow::Result;
-use chrono::Utc;
+use chrono::{TimeDelta, Utc};
 use futures::future::join_all;
 use ipnetwork::{IpNetwork, NetworkSize};
 use lnvps_db::{
-    App, AppCluster, CpuArch, CpuMfg, DbResult, DiskInterface, DiskType, IpRange, LNVpsDb,
-    VmCustomTemplate, VmHost, VmHostDisk, VmIpAssignment, VmTemplate,
+    App, AppCluster, CapacityReservation, CpuArch, CpuMfg, DbResult, DiskInterface, DiskType,
+    IpRange, LNVpsDb, PlacementPolicy, User, VmCustomTemplate, VmExtraDisk, VmHost, VmHostDisk,
+    VmIpAssignment, VmTemplate,
 };
+use rand::Rng;
 use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
+use std::time::Duration;
 
 /// Errors related to host capacity that should be surfaced to the user rather
 /// than logged as an opaque internal server error.
@@ -32,6 +35,79 @@ impl std::fmt::Display for CapacityError {
 
 impl std::error::Error for CapacityError {}
 
+/// How long a capacity reservation holds resources on a host. Matches the
+/// window an unpaid order is kept for before the worker deletes it.
+pub const CAPACITY_RESERVATION_TTL: TimeDelta = TimeDelta::hours(1);
+
+/// How long to wait for the placement lock held by a concurrent order
+const PLACEMENT_LOCK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Chance of a host being picked: its weight (0 counts as 1) scaled by its
+/// remaining headroom, so a nearly full host takes fewer new VMs than an
+/// empty one of the same weight
+fn placement_weight(host: &HostCapacity) -> u64 {
+    let load = host.load();
+    let headroom = if load.is_finite() {
+        (1.0 - load).clamp(0.01, 1.0)
+    } else {
+        1.0
+    };
+    ((host.host.weight.max(1) as f32 * headroom * 100.0).round() as u64).max(1)
+}
+
+/// Pick one of `hosts` at random, each with a chance proportional to its
+/// [placement_weight]
+fn pick_weighted(hosts: Vec<HostCapacity>, rng: &mut impl Rng) -> Option<HostCapacity> {
+    let total: u64 = hosts.iter().map(placement_weight).sum();
+    if total == 0 {
+        return None;
+    }
+    let mut n = rng.random_range(0..total);
+    for h in hosts {
+        let weight = placement_weight(&h);
+        if n < weight {
+            return Some(h);
+        }
+        n -= weight;
+    }
+    None
+}
+
+/// Placement of a new VM relative to the other VMs of its owner
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Placement {
+    pub user_id: u64,
+    pub policy: PlacementPolicy,
+    /// Only place on hosts with a private bridge, see
+    /// [VmHost::supports_private_network]
+    pub private_network: bool,
+}
+
+impl Placement {
+    /// Place by the user's own placement policy
+    pub fn for_user(user: &User) -> Self {
+        Self {
+            user_id: user.id,
+            policy: user.placement_policy,
+            private_network: false,
+        }
+    }
+
+    /// Place a VM on the private network
+    pub fn with_private_network(mut self, private_network: bool) -> Self {
+        self.private_network = private_network;
+        self
+    }
+}
+
+/// A template together with whether any host can currently fit it
+#[derive(Clone, Debug)]
+pub struct TemplateAvailability {
+    pub template: VmTemplate,
+    /// At least one host in the template's region has room for it
+    pub available: bool,
+}
+
 /// Simple capacity management
 #[derive(Clone)]
 pub struct HostCapacityService {
@@ -44,58 +120,258 @@ impl HostCapacityService {
         Self { db }
     }
 
-    /// List templates which can be sold, based on available capacity
-    pub async fn list_available_vm_templates(&self) -> Result<Vec<VmTemplate>> {
-        let templates = self.db.list_vm_templates().await?;
+    /// List hosts which can take new VMs, excluding hosts (or hosts in regions)
+    /// which are under maintenance
+    async fn list_placement_hosts(&self) -> Result<Vec<VmHost>> {
+        let maintenance_regions: HashSet<u64> = self
+            .db
+            .list_host_region()
+            .await?
+            .into_iter()
+            .filter(|r| r.maintenance)
+            .map(|r| r.id)
+            .collect();
+        Ok(self
+            .db
+            .list_hosts()
+            .await?
+            .into_iter()
+            .filter(|h| !h.maintenance && !maintenance_regions.contains(&h.region_id))
+            .collect())
+    }
+
+    /// List the enabled templates of a region (or of every region), flagging
+    /// whether each one can currently be placed on a host in its region.
+    ///
+    /// Admin listings should use [`LNVpsDb::list_vm_templates`] directly, this
+    /// is for deciding what can be offered to customers.
+    pub async fn list_available_templates(
+        &self,
+        region_id: Option<u64>,
+    ) -> Result<Vec<TemplateAvailability>> {
+        let in_region = |id: u64| region_id.is_none_or(|r| r == id);
+        let templates: Vec<VmTemplate> = self
+            .db
+            .list_vm_templates()
+            .await?
+            .into_iter()
+            .filter(|t| in_region(t.region_id))
+            .collect();
+        if templates.is_empty() {
+            return Ok(vec![]);
+        }
 
-        // TODO: list hosts in regions where templates are active?
-        // use all hosts since we dont expect there to be many
-        let hosts = self.db.list_hosts().await?;
-        let caps: Vec<Result<HostCapacity>> =
-            join_all(hosts.iter().map(|h| self.get_host_capacity(h, None, None))).await;
+        let hosts = self.list_placement_hosts().await?;
+        let caps: Vec<Result<HostCapacity>> = join_all(
+            hosts
+                .iter()
+                .filter(|h| in_region(h.region_id))
+                .map(|h| self.get_host_capacity(h, None, None)),
+        )
+        .await;
         let caps: Vec<HostCapacity> = caps.into_iter().filter_map(Result::ok).collect();
 
         Ok(templates
             .into_iter()
-            .filter(|t| {
-                caps.iter()
+            .map(|t| {
+                let available = caps
+                    .iter()
                     .filter(|c| c.host.region_id == t.region_id)
-                    .any(|c| c.can_accommodate(t))
+                    .any(|c| c.can_accommodate(&t));
+                TemplateAvailability {
+                    template: t,
+                    available,
+                }
             })
             .collect())
     }
 
-    /// Pick a host for the purposes of provisioning a new VM
+    /// Pick a host for the purposes of provisioning a new VM, hosts which can
+    /// fit the template are narrowed down by the [Placement] policy and then
+    /// picked in proportion to their weight and remaining headroom
     pub async fn get_host_for_template(
         &self,
         region_id: u64,
         template: &impl Template,
+        placement: Placement,
     ) -> Result<HostCapacity> {
-        let hosts = self.db.list_hosts().await?;
-        let caps: Vec<Result<HostCapacity>> =
-            join_all(hosts.iter().filter(|h| h.region_id == region_id).map(|h| {
-                self.get_host_capacity(
-                    h,
-                    Some(template.disk_type()),
-                    Some(template.disk_interface()),
-                )
-            }))
+        let hosts = self.list_placement_hosts().await?;
+        let host_cap = self
+            .hosts_fitting(
+                hosts.iter().filter(|h| h.region_id == region_id),
+                template,
+                placement.private_network,
+            )
             .await;
-        let mut host_cap: Vec<HostCapacity> = caps
-            .into_iter()
-            .filter_map(|v| v.ok())
-            .filter(|v| v.can_accommodate(template))
-            .collect();
-
-        host_cap.sort_by(|a, b| a.load().partial_cmp(&b.load()).unwrap());
+        let host_cap = self.apply_placement(host_cap, placement).await?;
 
-        if let Some(f) = host_cap.into_iter().next() {
+        if let Some(f) = pick_weighted(host_cap, &mut rand::rng()) {
             Ok(f)
         } else {
             Err(CapacityError::NoAvailableHosts.into())
         }
     }
 
+    /// Capacity of the `hosts` which can fit `template`, and have a private
+    /// bridge when `private_network` is set
+    async fn hosts_fitting<'a>(
+        &self,
+        hosts: impl Iterator<Item = &'a VmHost>,
+        template: &impl Template,
+        private_network: bool,
+    ) -> Vec<HostCapacity> {
+        let caps: Vec<Result<HostCapacity>> = join_all(hosts.map(|h| {
+            self.get_host_capacity(
+                h,
+                Some(template.disk_type()),
+                Some(template.disk_interface()),
+            )
+        }))
+        .await;
+        caps.into_iter()
+            .filter_map(|v| v.ok())
+            .filter(|v| v.can_accommodate(template))
+            .filter(|v| !private_network || v.host.supports_private_network())
+            .collect()
+    }
+
+    /// Keep the hosts preferred by the placement policy: the hosts running
+    /// the fewest of the user's VMs for [PlacementPolicy::Spread] and hosts
+    /// with any of them for [PlacementPolicy::Pack]. All hosts are kept when
+    /// none is preferred.
+    async fn apply_placement(
+        &self,
+        hosts: Vec<HostCapacity>,
+        placement: Placement,
+    ) -> Result<Vec<HostCapacity>> {
+        if placement.policy == PlacementPolicy::None || hosts.len() < 2 {
+            return Ok(hosts);
+        }
+        let mut user_vms: HashMap<u64, usize> = HashMap::new();
+        for vm in self.db.list_user_vms(placement.user_id).await? {
+            *user_vms.entry(vm.host_id).or_default() += 1;
+        }
+        let count = |h: &HostCapacity| user_vms.get(&h.host.id).copied().unwrap_or(0);
+        Ok(match placement.policy {
+            PlacementPolicy::Pack => {
+                let (with_user, without_user): (Vec<HostCapacity>, Vec<HostCapacity>) =
+                    hosts.into_iter().partition(|h| count(h) > 0);
+                if with_user.is_empty() {
+                    without_user
+                } else {
+                    with_user
+                }
+            }
+            _ => {
+                let fewest = hosts.iter().map(count).min().unwrap_or(0);
+                hosts.into_iter().filter(|h| count(h) == fewest).collect()
+            }
+        })
+    }
+
+    /// Pick a host for a new VM and hold the template's resources on it.
+    ///
+    /// The reservation counts as used capacity until it expires, is released
+    /// with [`Self::release_reservation`], or the VM bound to it (see
+    /// `set_capacity_reservation_vm`) is paid for and counts itself.
+    pub async fn reserve_host_for_template(
+        &self,
+        region_id: u64,
+        template: &impl Template,
+        placement: Placement,
+    ) -> Result<(HostCapacity, CapacityReservation)> {
+        // Serialise host selection and reservation across every API instance, so
+        // two concurrent orders can't both claim the last slot on a host
+        let _guard = self.db.lock_placement(PLACEMENT_LOCK_TIMEOUT).await?;
+        let host = self
+            .get_host_for_template(region_id, template, placement)
+            .await?;
+        let Some(disk) = host.find_disk(template) else {
+            return Err(CapacityError::NoAvailableHosts.into());
+        };
+        let mut reservation = CapacityReservation {
+            id: 0,
+            host_id: host.host.id,
+            disk_id: disk.disk.id,
+            vm_id: None,
+            cpu: template.cpu(),
+            memory: template.memory(),
+            disk_size: template.disk_size(),
+            expires: Utc::now() + CAPACITY_RESERVATION_TTL,
+        };
+        reservation.id = self.db.insert_capacity_reservation(&reservation).await?;
+        Ok((host, reservation))
+    }
+
+    /// Pick the disk on `host` an existing VM of `template` can be moved onto,
+    /// `None` when the host can't fit the VM or has no private bridge for a
+    /// VM on the private network
+    pub async fn find_disk_for_vm(
+        &self,
+        host: &VmHost,
+        template: &impl Template,
+        private_network: bool,
+    ) -> Result<Option<VmHostDisk>> {
+        if private_network && !host.supports_private_network() {
+            return Ok(None);
+        }
+        let cap = self
+            .get_host_capacity(
+                host,
+                Some(template.disk_type()),
+                Some(template.disk_interface()),
+            )
+            .await?;
+        if !cap.can_accommodate(template) {
+            return Ok(None);
+        }
+        Ok(cap.find_disk(template).map(|d| d.disk.clone()))
+    }
+
+    /// Pick the disk on `host` an existing VM of `template` is moved onto, like
+    /// [`Self::find_disk_for_vm`], and hold the template's resources on it for
+    /// `ttl` while the VM is moved.
+    ///
+    /// The reservation counts as used capacity until it expires or is
+    /// released with [`Self::release_reservation`]. Returns `None` when the
+    /// host can't fit the VM.
+    pub async fn reserve_disk_for_vm(
+        &self,
+        host: &VmHost,
+        template: &impl Template,
+        private_network: bool,
+        ttl: TimeDelta,
+    ) -> Result<Option<(VmHostDisk, CapacityReservation)>> {
+        // Serialise with orders and other moves, so two of them can't both
+        // claim the last slot on the host
+        let _guard = self.db.lock_placement(PLACEMENT_LOCK_TIMEOUT).await?;
+        let Some(disk) = self
+            .find_disk_for_vm(host, template, private_network)
+            .await?
+        else {
+            return Ok(None);
+        };
+        let mut reservation = CapacityReservation {
+            id: 0,
+            host_id: host.id,
+            disk_id: disk.id,
+            vm_id: None,
+            cpu: template.cpu(),
+            memory: template.memory(),
+            disk_size: template.disk_size(),
+            expires: Utc::now() + ttl,
+        };
+        reservation.id = self.db.insert_capacity_reservation(&reservation).await?;
+        Ok(Some((disk, reservation)))
+    }
+
+    /// Release a reservation taken by [`Self::reserve_host_for_template`] or
+    /// [`Self::reserve_disk_for_vm`]
+    pub async fn release_reservation(&self, reservation: &CapacityReservation) -> Result<()> {
+        self.db.delete_capacity_reservation(reservation.id).await?;
+        Ok(())
+    }
+
     /// Calculate and apply host capacity limits to custom pricing templates
     /// Processes all regions efficiently and modifies the vector in place
     pub async fn apply_host_capacity_limits(
@@ -112,7 +388,7 @@ impl HostCapacityService {
         // Get distinct region IDs from the templates
         let region_ids: HashSet<u64> = templates.iter().map(|t| t.region.id).collect();
 
-        let hosts = self.db.list_hosts().await?;
+        let hosts = self.list_placement_hosts().await?;
         let caps: Vec<Result<HostCapacity>> = join_all(
             hosts
                 .iter()
@@ -243,6 +519,7 @@ impl HostCapacityService {
         let all_vms = self.db.list_vms_on_host(host.id).await?;
         // Only count VMs that have been paid for (subscription is_setup = true)
         let mut vms = Vec::new();
+        let mut unpaid_vms = HashSet::new();
         for vm in all_vms {
             if vm.deleted {
                 continue;
@@ -255,9 +532,22 @@ impl HostCapacityService {
                 .unwrap_or(false);
             if is_paid {
                 vms.push(vm);
+            } else {
+                unpaid_vms.insert(vm.id);
             }
         }
 
+        // Reservations hold capacity for orders in flight: either not yet
+        // created, or created but unpaid. Once the VM is paid (or deleted) its
+        // reservation no longer counts.
+        let reservations: Vec<CapacityReservation> = self
+            .db
+            .list_capacity_reservations(host.id)
+            .await?
+            .into_iter()
+            .filter(|r| r.vm_id.map(|id| unpaid_vms.contains(&id)).unwrap_or(true))
+            .collect();
+
         // load ip ranges
         let ip_ranges = self.db.list_ip_range_in_region(host.region_id).await?;
         // TODO: handle very large number of assignments, maybe just count assignments
@@ -275,6 +565,21 @@ impl HostCapacityService {
         // TODO: filter disks from DB? Should be very few disks anyway
         let storage = self.db.list_host_disks(host.id).await?;
 
+        // extra data disks take space on their own storage, paid or not (the
+        // order reservation only covers the template disk)
+        let extra_disks: Vec<VmExtraDisk> = join_all(
+            vms.iter()
+                .map(|v| v.id)
+                .chain(unpaid_vms.iter().copied())
+                .map(|id| self.db.list_vm_extra_disks(id)),
+        )
+        .await
+        .into_iter()
+        .collect::<DbResult<Vec<_>>>()?
+        .into_iter()
+        .flatten()
+        .collect();
+
         // load templates
         let templates = self.db.list_vm_templates().await?;
         let custom_templates: Vec<DbResult<VmCustomTemplate>> = join_all(
@@ -339,7 +644,15 @@ impl HostCapacityService {
                 let usage = vm_resources
                     .iter()
                     .filter(|(_k, v)| s.id == v.disk_id)
-                    .fold(0, |acc, (_k, v)| acc + v.disk);
+                    .fold(0, |acc, (_k, v)| acc + v.disk)
+                    + reservations
+                        .iter()
+                        .filter(|r| r.disk_id == s.id)
+                        .fold(0, |acc, r| acc + r.disk_size)
+                    + extra_disks
+                        .iter()
+                        .filter(|d| d.disk_id == s.id)
+                        .fold(0, |acc, d| acc + d.size);
                 DiskCapacity {
                     load_factor: host.load_disk,
                     disk: s.clone(),
@@ -350,8 +663,10 @@ impl HostCapacityService {
 
         storage_disks.sort_by(|a, b| a.load_factor.partial_cmp(&b.load_factor).unwrap());
 
-        let cpu_consumed = vm_resources.values().fold(0, |acc, vm| acc + vm.cpu);
-        let memory_consumed = vm_resources.values().fold(0, |acc, vm| acc + vm.memory);
+        let cpu_consumed = vm_resources.values().fold(0, |acc, vm| acc + vm.cpu)
+            + reservations.iter().fold(0, |acc, r| acc + r.cpu);
+        let memory_consumed = vm_resources.values().fold(0, |acc, vm| acc + vm.memory)
+            + reservations.iter().fold(0, |acc, r| acc + r.memory);
 
         Ok(HostCapacity {
             load_factor: LoadFactors {
@@ -434,6 +749,15 @@ impl HostCapacity {
         self.disks.iter().fold(0.0, |acc, disk| acc + disk.load()) / self.disks.len() as f32
     }
 
+    /// Pick a disk of the template's type and interface with room for its disk
+    pub fn find_disk(&self, template: &impl Template) -> Option<&DiskCapacity> {
+        self.disks.iter().find(|d| {
+            d.disk.kind == template.disk_type()
+                && d.disk.interface == template.disk_interface()
+                && d.available_capacity() >= template.disk_size()
+        })
+    }
+
     /// Can this host and its available capacity accommodate the given template
     pub fn can_accommodate(&self, template: &impl Template) -> bool {
         // Check cpu manufacturer match (Unknown means any)
@@ -454,10 +778,7 @@ impl HostCapacity {
             && features_ok
             && self.available_cpu() >= template.cpu()
             && self.available_memory() >= template.memory()
-            && self
-                .disks
-                .iter()
-                .any(|d| d.available_capacity() >= template.disk_size())
+            && self.find_disk(template).is_some()
             && self
                 .ranges
                 .iter()
@@ -506,22 +827,14 @@ impl IPRangeCapacity {
             NetworkSize::V6(s) => s,
         };
 
-        // Only count the gateway as reserved if it actually falls within the CIDR.
-        // Gateways may be outside the range (e.g. a shared upstream gateway), in
-        // which case they do not consume a slot in this range.
-        let gw_reserved: u128 = if let Ok(gw) = parse_gateway(&self.range.gateway) {
-            if net.contains(gw.ip()) { 1 } else { 0 }
-        } else {
-            0
-        };
-
-        // If not using the full range, network and broadcast addresses are reserved.
-        let boundary_reserved: u128 = if self.range.use_full_range { 0 } else { 2 };
+        // Same reservations as the allocator: the gateway only if it falls within
+        // the CIDR, network and broadcast unless using the full range, each
+        // address counted once.
+        let reserved = NetworkProvisioner::reserved_ips(&self.range, &net)
+            .map(|r| r.len() as u128)
+            .unwrap_or(if self.range.use_full_range { 0 } else { 2 });
 
-        total
-            .saturating_sub(self.usage)
-            .saturating_sub(gw_reserved)
-            .saturating_sub(boundary_reserved)
+        total.saturating_sub(self.usage).saturating_sub(reserved)
     }
 
     /// Returns true if this range is an IPv4 range
@@ -680,7 +993,7 @@ mod tests {
     use super::*;
     use crate::GB;
     use crate::mock::MockDb;
-    use lnvps_db::{CpuFeature, DiskInterface, DiskType, LNVpsDbBase};
+    use lnvps_db::{CpuFeature, DiskInterface, DiskType, LNVpsDbBase, Vm, VmHostKind};
 
     #[test]
     fn loads() {
@@ -755,13 +1068,14 @@ mod tests {
 
         let template = db.get_vm_template(1).await?;
         let host = hc
-            .get_host_for_template(template.region_id, &template)
+            .get_host_for_template(template.region_id, &template, Placement::default())
             .await?;
         assert_eq!(host.host.id, 1);
 
         // all templates should be available
-        let templates = hc.list_available_vm_templates().await?;
+        let templates = hc.list_available_templates(None).await?;
         assert_eq!(templates.len(), db.list_vm_templates().await?.len());
+        assert!(templates.iter().all(|t| t.available));
 
         Ok(())
     }
@@ -788,6 +1102,508 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn extra_disks_count() -> Result<()> {
+        let db = MockDb::default();
+        {
+            let mut v = db.vms.lock().await;
+            v.insert(1, MockDb::mock_vm());
+        }
+        db.insert_vm_extra_disk(&VmExtraDisk {
+            id: 0,
+            vm_id: 1,
+            disk_id: 1,
+            size: 50 * GB,
+            slot: 0,
+            created: Utc::now(),
+        })
+        .await?;
+
+        let db: Arc<dyn LNVpsDb> = Arc::new(db);
+        let hc = HostCapacityService::new(db.clone());
+        let host = db.get_host(1).await?;
+        let cap = hc.get_host_capacity(&host, None, None).await?;
+        // the VM itself is unpaid, its data disk still takes space
+        assert_eq!(cap.cpu, 0);
+        let disk = cap.disks.iter().find(|d| d.disk.id == 1).unwrap();
+        assert_eq!(disk.usage, 50 * GB);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reservation_counts_until_released() -> Result<()> {
+        let db = MockDb::default();
+        {
+            // unpaid VM, doesn't count on its own
+            let mut v = db.vms.lock().await;
+            v.insert(1, MockDb::mock_vm());
+        }
+
+        let db: Arc<dyn LNVpsDb> = Arc::new(db);
+        let hc = HostCapacityService::new(db.clone());
+        let host = db.get_host(1).await?;
+        let template = db.get_vm_template(1).await?;
+        let (placed, reservation) = hc
+            .reserve_host_for_template(1, &template, Placement::default())
+            .await?;
+        assert_eq!(placed.host.id, 1);
+
+        let cap = hc.get_host_capacity(&host, None, None).await?;
+        assert_eq!(cap.cpu, template.cpu);
+        assert_eq!(cap.memory, template.memory);
+        assert_eq!(
+            cap.disks
+                .iter()
+                .find(|d| d.disk.id == reservation.disk_id)
+                .unwrap()
+                .usage,
+            template.disk_size
+        );
+
+        // Bound to an unpaid VM it still holds capacity
+        db.set_capacity_reservation_vm(reservation.id, 1).await?;
+        let cap = hc.get_host_capacity(&host, None, None).await?;
+        assert_eq!(cap.cpu, template.cpu);
+
+        hc.release_reservation(&reservation).await?;
+        let cap = hc.get_host_capacity(&host, None, None).await?;
+        assert_eq!(cap.cpu, 0);
+        assert_eq!(cap.memory, 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn vm_move_reservation_counts_until_released() -> Result<()> {
+        let db: Arc<dyn LNVpsDb> = Arc::new(MockDb::default());
+        let hc = HostCapacityService::new(db.clone());
+        let host = db.get_host(1).await?;
+        let template = db.get_vm_template(1).await?;
+
+        let (disk, reservation) = hc
+            .reserve_disk_for_vm(&host, &template, false, TimeDelta::hours(4))
+            .await?
+            .expect("the host fits the VM");
+        assert_eq!(disk.host_id, 1);
+        assert!(reservation.expires > Utc::now() + TimeDelta::hours(3));
+        let cap = hc.get_host_capacity(&host, None, None).await?;
+        assert_eq!(cap.cpu, template.cpu);
+        assert_eq!(cap.memory, template.memory);
+
+        // no private bridge, nothing is reserved
+        assert!(
+            hc.reserve_disk_for_vm(&host, &template, true, TimeDelta::hours(4))
+                .await?
+                .is_none()
+        );
+
+        hc.release_reservation(&reservation).await?;
+        let cap = hc.get_host_capacity(&host, None, None).await?;
+        assert_eq!(cap.cpu, 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn placement_skips_maintenance_hosts() -> Result<()> {
+        let db = MockDb::default();
+        {
+            let mut hosts = db.hosts.lock().await;
+            let mut host2 = hosts.get(&1).unwrap().clone();
+            host2.id = 2;
+            host2.name = "mock-host-2".to_string();
+            hosts.insert(2, host2);
+            hosts.get_mut(&1).unwrap().maintenance = true;
+
+            let mut disks = db.host_disks.lock().await;
+            let mut disk2 = disks.get(&1).unwrap().clone();
+            disk2.id = 2;
+            disk2.host_id = 2;
+            disks.insert(2, disk2);
+        }
+        let db = Arc::new(db);
+        let hc = HostCapacityService::new(db.clone() as Arc<dyn LNVpsDb>);
+        let template = db.get_vm_template(1).await?;
+
+        let host = hc
+            .get_host_for_template(1, &template, Placement::default())
+            .await?;
+        assert_eq!(host.host.id, 2, "maintenance host must not be picked");
+
+        // host still listed so existing VMs remain manageable
+        assert!(db.list_hosts().await?.iter().any(|h| h.id == 1));
+
+        db.hosts.lock().await.get_mut(&2).unwrap().maintenance = true;
+        let err = hc
+            .get_host_for_template(1, &template, Placement::default())
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<CapacityError>(),
+            Some(&CapacityError::NoAvailableHosts)
+        );
+        assert!(
+            hc.list_available_templates(None)
+                .await?
+                .iter()
+                .all(|t| !t.available)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn placement_follows_host_weights() {
+        use rand::SeedableRng;
+
+        let hosts: Vec<HostCapacity> = [(1, 1), (2, 3), (3, 6)]
+            .into_iter()
+            .map(|(id, weight)| {
+                let mut cap = make_host_capacity(CpuMfg::Unknown, CpuArch::Unknown, vec![]);
+                cap.host.id = id;
+                cap.host.weight = weight;
+                cap
+            })
+            .collect();
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+        let rounds = 10_000;
+        let mut picks: HashMap<u64, u32> = HashMap::new();
+        for _ in 0..rounds {
+            let host = pick_weighted(hosts.clone(), &mut rng).unwrap();
+            *picks.entry(host.host.id).or_default() += 1;
+        }
+        for (id, share) in [(1, 0.1), (2, 0.3), (3, 0.6)] {
+            let got = picks[&id] as f64 / rounds as f64;
+            assert!(
+                (got - share).abs() < 0.02,
+                "host {} got {:.3} of placements, expected {}",
+                id,
+                got,
+                share
+            );
+        }
+
+        assert!(pick_weighted(vec![], &mut rng).is_none());
+    }
+
+    #[tokio::test]
+    async fn placement_favours_host_headroom() -> Result<()> {
+        let db = two_hosts_with_user_vm().await;
+        {
+            // host 1 is about half full, host 2 is empty
+            db.hosts.lock().await.get_mut(&1).unwrap().load_disk = 1.0;
+            db.host_disks.lock().await.get_mut(&1).unwrap().size = 200 * GB;
+            let mut vms = db.vms.lock().await;
+            for id in [1, 2] {
+                vms.insert(
+                    id,
+                    Vm {
+                        id,
+                        user_id: 1,
+                        host_id: 1,
+                        disk_id: 1,
+                        template_id: Some(1),
+                        ..Default::default()
+                    },
+                );
+            }
+        }
+        let hc = HostCapacityService::new(db.clone() as Arc<dyn LNVpsDb>);
+        let template = db.get_vm_template(1).await?;
+
+        let mut picks: HashMap<u64, u32> = HashMap::new();
+        for _ in 0..500 {
+            let host = hc
+                .get_host_for_template(1, &template, Placement::default())
+                .await?;
+            *picks.entry(host.host.id).or_default() += 1;
+        }
+        let (full, empty) = (picks[&1], picks[&2]);
+        assert!(
+            empty > full + 60,
+            "empty host got {} placements, half full host {}",
+            empty,
+            full
+        );
+        Ok(())
+    }
+
+    /// Mock DB with a second host and a VM of user 1 on host 1
+    async fn two_hosts_with_user_vm() -> Arc<MockDb> {
+        let db = MockDb::default();
+        {
+            let mut hosts = db.hosts.lock().await;
+            let mut host2 = hosts.get(&1).unwrap().clone();
+            host2.id = 2;
+            host2.name = "mock-host-2".to_string();
+            hosts.insert(2, host2);
+
+            let mut disks = db.host_disks.lock().await;
+            let mut disk2 = disks.get(&1).unwrap().clone();
+            disk2.id = 2;
+            disk2.host_id = 2;
+            disks.insert(2, disk2);
+
+            db.vms.lock().await.insert(
+                1,
+                Vm {
+                    id: 1,
+                    user_id: 1,
+                    host_id: 1,
+                    ..Default::default()
+                },
+            );
+        }
+        Arc::new(db)
+    }
+
+    #[tokio::test]
+    async fn placement_spread_avoids_user_hosts() -> Result<()> {
+        let db = two_hosts_with_user_vm().await;
+        let hc = HostCapacityService::new(db.clone() as Arc<dyn LNVpsDb>);
+        let template = db.get_vm_template(1).await?;
+
+        let spread = Placement {
+            user_id: 1,
+            policy: PlacementPolicy::Spread,
+            private_network: false,
+        };
+        for _ in 0..20 {
+            let host = hc.get_host_for_template(1, &template, spread).await?;
+            assert_eq!(host.host.id, 2, "host 1 already runs the user's VM");
+        }
+
+        // with no alternative the user's host is still used
+        db.hosts.lock().await.get_mut(&2).unwrap().maintenance = true;
+        let host = hc.get_host_for_template(1, &template, spread).await?;
+        assert_eq!(host.host.id, 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn placement_spread_prefers_fewest_user_vms() -> Result<()> {
+        let db = two_hosts_with_user_vm().await;
+        {
+            let mut hosts = db.hosts.lock().await;
+            let mut host3 = hosts.get(&1).unwrap().clone();
+            host3.id = 3;
+            host3.name = "mock-host-3".to_string();
+            hosts.insert(3, host3);
+
+            let mut disks = db.host_disks.lock().await;
+            let mut disk3 = disks.get(&1).unwrap().clone();
+            disk3.id = 3;
+            disk3.host_id = 3;
+            disks.insert(3, disk3);
+
+            // user 1 runs 2 VMs on host 1 and 1 VM on host 2
+            let mut vms = db.vms.lock().await;
+            for (id, host_id) in [(2, 1), (3, 2)] {
+                vms.insert(
+                    id,
+                    Vm {
+                        id,
+                        user_id: 1,
+                        host_id,
+                        ..Default::default()
+                    },
+                );
+            }
+        }
+        let hc = HostCapacityService::new(db.clone() as Arc<dyn LNVpsDb>);
+        let template = db.get_vm_template(1).await?;
+
+        let spread = Placement {
+            user_id: 1,
+            policy: PlacementPolicy::Spread,
+            private_network: false,
+        };
+        for _ in 0..20 {
+            let host = hc.get_host_for_template(1, &template, spread).await?;
+            assert_eq!(host.host.id, 3, "host 3 runs none of the user's VMs");
+        }
+
+        db.hosts.lock().await.get_mut(&3).unwrap().maintenance = true;
+        for _ in 0..20 {
+            let host = hc.get_host_for_template(1, &template, spread).await?;
+            assert_eq!(host.host.id, 2, "host 2 runs fewer of the user's VMs");
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn placement_private_network_needs_private_bridge() -> Result<()> {
+        let db = two_hosts_with_user_vm().await;
+        let hc = HostCapacityService::new(db.clone() as Arc<dyn LNVpsDb>);
+        let template = db.get_vm_template(1).await?;
+        {
+            let mut hosts = db.hosts.lock().await;
+            let host = hosts.get_mut(&2).unwrap();
+            host.kind = VmHostKind::Proxmox;
+            host.private_bridge = Some("vmbr9".to_string());
+        }
+
+        let private = Placement {
+            user_id: 2,
+            policy: PlacementPolicy::None,
+            private_network: true,
+        };
+        for _ in 0..20 {
+            let host = hc.get_host_for_template(1, &template, private).await?;
+            assert_eq!(host.host.id, 2, "only host 2 has a private bridge");
+        }
+
+        db.hosts.lock().await.get_mut(&2).unwrap().maintenance = true;
+        assert!(
+            hc.get_host_for_template(1, &template, private)
+                .await
+                .is_err()
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn placement_pack_prefers_user_hosts() -> Result<()> {
+        let db = two_hosts_with_user_vm().await;
+        let hc = HostCapacityService::new(db.clone() as Arc<dyn LNVpsDb>);
+        let template = db.get_vm_template(1).await?;
+
+        let pack = Placement {
+            user_id: 1,
+            policy: PlacementPolicy::Pack,
+            private_network: false,
+        };
+        for _ in 0..20 {
+            let host = hc.get_host_for_template(1, &template, pack).await?;
+            assert_eq!(host.host.id, 1);
+        }
+
+        // other users are unaffected by user 1's VMs
+        let other = Placement {
+            user_id: 2,
+            policy: PlacementPolicy::Pack,
+            private_network: false,
+        };
+        let mut picked = HashSet::new();
+        for _ in 0..50 {
+            picked.insert(hc.get_host_for_template(1, &template, other).await?.host.id);
+        }
+        assert_eq!(picked.len(), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn placement_skips_maintenance_region() -> Result<()> {
+        let db = Arc::new(MockDb::default());
+        db.regions.lock().await.get_mut(&1).unwrap().maintenance = true;
+        let hc = HostCapacityService::new(db.clone() as Arc<dyn LNVpsDb>);
+        let template = db.get_vm_template(1).await?;
+
+        let err = hc
+            .get_host_for_template(1, &template, Placement::default())
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<CapacityError>(),
+            Some(&CapacityError::NoAvailableHosts)
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn placement_matches_disk_type_and_interface() -> Result<()> {
+        let db = Arc::new(MockDb::default());
+        // the mock template wants an SSD on PCIe (NVMe)
+        db.host_disks.lock().await.get_mut(&1).unwrap().interface = DiskInterface::SATA;
+        let hc = HostCapacityService::new(db.clone() as Arc<dyn LNVpsDb>);
+        let template = db.get_vm_template(1).await?;
+
+        let err = hc
+            .get_host_for_template(1, &template, Placement::default())
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<CapacityError>(),
+            Some(&CapacityError::NoAvailableHosts)
+        );
+        assert!(
+            !hc.list_available_templates(None)
+                .await?
+                .iter()
+                .any(|t| t.available && t.template.id == template.id)
+        );
+
+        db.host_disks.lock().await.get_mut(&1).unwrap().interface = DiskInterface::PCIe;
+        let host = hc
+            .get_host_for_template(1, &template, Placement::default())
+            .await?;
+        assert_eq!(host.host.id, 1);
+        assert!(
+            hc.list_available_templates(None)
+                .await?
+                .iter()
+                .any(|t| t.available && t.template.id == template.id)
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn available_templates_flag_oversized() -> Result<()> {
+        let db = Arc::new(MockDb::default());
+        {
+            let mut templates = db.templates.lock().await;
+            let mut big = templates.get(&1).unwrap().clone();
+            big.id = 2;
+            big.name = "too-big".to_string();
+            big.cpu = 1024;
+            big.memory = 1024 * 1024 * GB;
+            templates.insert(2, big);
+
+            let mut other_region = templates.get(&1).unwrap().clone();
+            other_region.id = 3;
+            other_region.region_id = 2;
+            templates.insert(3, other_region);
+        }
+        let hc = HostCapacityService::new(db.clone() as Arc<dyn LNVpsDb>);
+
+        let templates = hc.list_available_templates(Some(1)).await?;
+        assert_eq!(
+            templates.len(),
+            2,
+            "only templates in the region are listed"
+        );
+        let small = templates.iter().find(|t| t.template.id == 1).unwrap();
+        assert!(small.available);
+        let big = templates.iter().find(|t| t.template.id == 2).unwrap();
+        assert!(!big.available, "no host can fit the oversized template");
+
+        // raw list still has everything
+        assert_eq!(db.list_vm_templates().await?.len(), 3);
+
+        // nothing can be placed while the only host is in maintenance
+        db.hosts.lock().await.get_mut(&1).unwrap().maintenance = true;
+        assert!(
+            hc.list_available_templates(Some(1))
+                .await?
+                .iter()
+                .all(|t| !t.available)
+        );
+        Ok(())
+    }
+
+    /// A host's unfiltered capacity must not offer an SATA disk to a PCIe template
+    #[test]
+    fn can_accommodate_requires_matching_disk() {
+        let mut cap = make_host_capacity(CpuMfg::Intel, CpuArch::X86_64, vec![]);
+        let template = make_template(CpuMfg::Unknown, CpuArch::Unknown, vec![]);
+        cap.disks[0].disk.interface = DiskInterface::SATA;
+        assert!(!cap.can_accommodate(&template));
+        assert!(cap.find_disk(&template).is_none());
+
+        cap.disks[0].disk.interface = DiskInterface::PCIe;
+        assert!(cap.can_accommodate(&template));
+        cap.disks[0].disk.kind = DiskType::HDD;
+        assert!(!cap.can_accommodate(&template));
+    }
+
     // ── CPU filtering tests ──────────────────────────────────────────────────
 
     /// Helper to create a minimal VmTemplate for testing CPU filtering
@@ -1125,6 +1941,7 @@ mod tests {
                 id: 1,
                 name: "test-region".to_string(),
                 company_id: 1,
+                tax_display: Default::default(),
             },
             cpu_features: vec![],
             cpu_mfg: None,
@@ -1234,9 +2051,12 @@ mod tests {
                     load_disk: 1.0,
                     vlan_id: Some(100),
                     mtu: None,
+                    private_bridge: None,
                     ssh_user: None,
                     ssh_key: None,
                     sunset_date: None,
+                    maintenance: false,
+                    weight: 1,
                 },
             );
             let mut disks = db.host_disks.lock().await;
@@ -1,7 +1,10 @@
-use axum::extract::FromRequestParts;
+use anyhow::{Context, Result};
+use axum::extract::{ConnectInfo, FromRequestParts};
+use axum::http::HeaderMap;
 use axum::http::request::Parts;
+use ipnetwork::IpNetwork;
 use std::convert::Infallible;
-use std::net::IpAddr;
+use std::net::{IpAddr, SocketAddr};
 
 /// Extractor for the originating client IP address.
 ///
@@ -12,6 +15,10 @@ use std::net::IpAddr;
 /// 1. `X-Forwarded-For` — the left-most (original client) entry, or
 /// 2. `X-Real-IP` — a single address.
 ///
+/// When [TrustedProxies] is added to the router as an extension (and the
+/// server provides [ConnectInfo]), forwarding headers are only believed when
+/// the peer is one of those proxies, see [TrustedProxies::client_ip].
+///
 /// This is best-effort: the value is only used as *one* non-contradictory piece
 /// of place-of-supply evidence for EU VAT and is never trusted for
 /// authentication. Extraction never fails; the address is `None` when no usable
@@ -21,7 +28,7 @@ pub struct ClientIp(pub Option<IpAddr>);
 
 impl ClientIp {
     /// Parse the client IP out of a set of request headers.
-    pub fn from_headers(headers: &axum::http::HeaderMap) -> Self {
+    pub fn from_headers(headers: &HeaderMap) -> Self {
         // X-Forwarded-For: client, proxy1, proxy2 -> take the left-most entry.
         if let Some(xff) = headers.get("x-forwarded-for").and_then(|v| v.to_str().ok()) {
             for part in xff.split(',') {
@@ -65,6 +72,72 @@ fn parse_ip(s: &str) -> Option<IpAddr> {
     None
 }
 
+/// Reverse proxies allowed to set `X-Forwarded-For` / `X-Real-IP`
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxies(pub Vec<IpNetwork>);
+
+impl TrustedProxies {
+    /// Parse a list of CIDRs (or plain addresses) from the config
+    pub fn parse<S: AsRef<str>>(cidrs: &[S]) -> Result<Self> {
+        cidrs
+            .iter()
+            .map(|c| {
+                c.as_ref()
+                    .trim()
+                    .parse::<IpNetwork>()
+                    .with_context(|| format!("Invalid trusted proxy {}", c.as_ref()))
+            })
+            .collect::<Result<Vec<_>>>()
+            .map(Self)
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        // compare IPv4-mapped IPv6 peers (dual stack sockets) as IPv4
+        let ip = ip.to_canonical();
+        self.0.iter().any(|n| n.contains(ip))
+    }
+
+    /// The real client IP of a request received from `peer`.
+    ///
+    /// A peer which isn't a trusted proxy is the client. Otherwise
+    /// `X-Forwarded-For` is walked from the right (the hop closest to us),
+    /// skipping trusted proxies, and the first untrusted address is the
+    /// client. Entries left of it could be set by the client itself so are
+    /// ignored.
+    pub fn client_ip(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        if !self.contains(peer) {
+            return peer;
+        }
+        let xff: Vec<&str> = headers
+            .get_all("x-forwarded-for")
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .collect();
+        if xff.is_empty() {
+            return headers
+                .get("x-real-ip")
+                .and_then(|v| v.to_str().ok())
+                .and_then(parse_ip)
+                .unwrap_or(peer);
+        }
+        let mut client = peer;
+        for part in xff.iter().rev() {
+            match parse_ip(part) {
+                Some(ip) => {
+                    client = ip;
+                    if !self.contains(ip) {
+                        break;
+                    }
+                }
+                // a garbage hop can't be walked past
+                None => break,
+            }
+        }
+        client
+    }
+}
+
 impl<S> FromRequestParts<S> for ClientIp
 where
     S: Send + Sync,
@@ -72,7 +145,14 @@ where
     type Rejection = Infallible;
 
     async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
-        Ok(ClientIp::from_headers(&parts.headers))
+        let peer = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|c| c.0.ip());
+        Ok(match (parts.extensions.get::<TrustedProxies>(), peer) {
+            (Some(trusted), Some(peer)) => ClientIp(Some(trusted.client_ip(peer, &parts.headers))),
+            _ => ClientIp::from_headers(&parts.headers),
+        })
     }
 }
 
@@ -150,4 +230,56 @@ mod tests {
         let ip = ClientIp::from_request_parts(&mut parts, &()).await.unwrap();
         assert_eq!(ip.0, Some("198.51.100.9".parse().unwrap()));
     }
+
+    fn trusted() -> TrustedProxies {
+        TrustedProxies::parse(&["10.0.0.0/8", "2001:db8:ffff::/48"]).unwrap()
+    }
+
+    #[test]
+    fn trusted_peer_walks_forwarded_for() {
+        let h = headers(&[(
+            "x-forwarded-for",
+            "1.1.1.1, 203.0.113.7, 10.0.0.2, 2001:db8:ffff::1",
+        )]);
+        // 1.1.1.1 was added by the client, 203.0.113.7 is the last untrusted hop
+        let ip = trusted().client_ip("10.0.0.1".parse().unwrap(), &h);
+        assert_eq!(ip, "203.0.113.7".parse::<IpAddr>().unwrap());
+
+        // only X-Real-IP from the proxy
+        let h = headers(&[("x-real-ip", "198.51.100.9")]);
+        let ip = trusted().client_ip("10.0.0.1".parse().unwrap(), &h);
+        assert_eq!(ip, "198.51.100.9".parse::<IpAddr>().unwrap());
+
+        // no headers at all, the proxy is the client
+        let ip = trusted().client_ip("10.0.0.1".parse().unwrap(), &HeaderMap::new());
+        assert_eq!(ip, "10.0.0.1".parse::<IpAddr>().unwrap());
+    }
+
+    #[test]
+    fn untrusted_peer_uses_socket_address() {
+        let h = headers(&[
+            ("x-forwarded-for", "198.51.100.9"),
+            ("x-real-ip", "198.51.100.9"),
+        ]);
+        let ip = trusted().client_ip("203.0.113.7".parse().unwrap(), &h);
+        assert_eq!(ip, "203.0.113.7".parse::<IpAddr>().unwrap());
+        // IPv4-mapped peer on a dual stack socket still matches the IPv4 range
+        let ip = trusted().client_ip("::ffff:10.0.0.1".parse().unwrap(), &h);
+        assert_eq!(ip, "198.51.100.9".parse::<IpAddr>().unwrap());
+        assert!(TrustedProxies::parse(&["not-a-cidr"]).is_err());
+    }
+
+    #[tokio::test]
+    async fn extractor_uses_trusted_proxies() {
+        use axum::extract::FromRequestParts;
+        let req = axum::http::Request::builder()
+            .header("x-forwarded-for", "198.51.100.9")
+            .extension(trusted())
+            .extension(ConnectInfo(SocketAddr::from(([203, 0, 113, 7], 443))))
+            .body(())
+            .unwrap();
+        let (mut parts, _) = req.into_parts();
+        let ip = ClientIp::from_request_parts(&mut parts, &()).await.unwrap();
+        assert_eq!(ip.0, Some("203.0.113.7".parse().unwrap()));
+    }
 }
@@ -0,0 +1,205 @@
+use anyhow::{Context, Result};
+use axum::http::{HeaderName, HeaderValue, Method};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use tower_http::cors::{AllowHeaders, AllowMethods, AllowOrigin, Any, CorsLayer};
+
+/// Cross-origin access to the API.
+///
+/// Auth is carried in the `Authorization` header (NIP-98 / JWT), never cookies,
+/// so credentials are never allowed. An allowed origin is echoed back in
+/// `Access-Control-Allow-Origin`, other origins get no CORS headers. Tor/Brave
+/// send `Origin: null` on cross-site requests, add `null` to `origins` to allow
+/// them.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct CorsSettings {
+    /// Origins allowed to call the API (e.g. `https://lnvps.net`), `*` allows
+    /// any origin. Default: none, only same-origin requests work
+    pub origins: Vec<String>,
+    /// Allowed methods, `*` allows any method
+    pub methods: Vec<String>,
+    /// Allowed request headers, `*` allows whatever the preflight asks for (a
+    /// literal `*` in `Access-Control-Allow-Headers` does not cover
+    /// `Authorization`)
+    pub headers: Vec<String>,
+}
+
+impl Default for CorsSettings {
+    fn default() -> Self {
+        Self {
+            origins: vec![],
+            methods: ["GET", "POST", "PUT", "PATCH", "DELETE"]
+                .map(String::from)
+                .to_vec(),
+            headers: ["authorization", "content-type"].map(String::from).to_vec(),
+        }
+    }
+}
+
+fn is_wildcard(values: &[String]) -> bool {
+    values.iter().any(|v| v == "*")
+}
+
+impl CorsSettings {
+    /// Build the CORS layer, failing on values which are not valid in headers
+    pub fn layer(&self) -> Result<CorsLayer> {
+        if self.origins.is_empty() {
+            warn!(
+                "No cors.origins configured, browsers will block the API for every page not served from the API's own origin. Add the frontend origin(s) to cors.origins (or \"*\" for local development)"
+            );
+        }
+        let origins = if is_wildcard(&self.origins) {
+            AllowOrigin::any()
+        } else {
+            // browsers never send a trailing slash in `Origin`
+            let origins = self
+                .origins
+                .iter()
+                .map(|o| {
+                    HeaderValue::from_str(o.trim_end_matches('/'))
+                        .with_context(|| format!("Invalid CORS origin: {}", o))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            AllowOrigin::list(origins)
+        };
+        let methods = if is_wildcard(&self.methods) {
+            AllowMethods::any()
+        } else {
+            let methods = self
+                .methods
+                .iter()
+                .map(|m| {
+                    Method::from_bytes(m.to_uppercase().as_bytes())
+                        .with_context(|| format!("Invalid CORS method: {}", m))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            AllowMethods::list(methods)
+        };
+        let headers = if is_wildcard(&self.headers) {
+            AllowHeaders::mirror_request()
+        } else {
+            let headers = self
+                .headers
+                .iter()
+                .map(|h| {
+                    HeaderName::from_bytes(h.to_lowercase().as_bytes())
+                        .with_context(|| format!("Invalid CORS header: {}", h))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            AllowHeaders::list(headers)
+        };
+        Ok(CorsLayer::new()
+            .allow_origin(origins)
+            .allow_methods(methods)
+            .allow_headers(headers)
+            .expose_headers(Any))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::{ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN};
+
+    /// Serve a ping route behind the CORS layer, returning the base url
+    async fn serve(cors: CorsSettings) -> Result<String> {
+        let router = axum::Router::new()
+            .route("/api/v1/ping", axum::routing::get(async || "pong"))
+            .layer(cors.layer()?);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let base = format!("http://{}", listener.local_addr()?);
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        Ok(base)
+    }
+
+    async fn allow_origin(base: &str, origin: &str) -> Result<Option<String>> {
+        let rsp = reqwest::Client::new()
+            .get(format!("{}/api/v1/ping", base))
+            .header("origin", origin)
+            .send()
+            .await?;
+        Ok(rsp
+            .headers()
+            .get(ACCESS_CONTROL_ALLOW_ORIGIN)
+            .map(|v| v.to_str().unwrap().to_string()))
+    }
+
+    #[tokio::test]
+    async fn test_allowed_origin_is_echoed() -> Result<()> {
+        let base = serve(CorsSettings {
+            origins: vec!["https://lnvps.net/".to_string(), "null".to_string()],
+            ..Default::default()
+        })
+        .await?;
+
+        assert_eq!(
+            allow_origin(&base, "https://lnvps.net").await?.as_deref(),
+            Some("https://lnvps.net")
+        );
+        assert_eq!(allow_origin(&base, "null").await?.as_deref(), Some("null"));
+        assert_eq!(allow_origin(&base, "https://evil.example").await?, None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_default_allows_no_origin() -> Result<()> {
+        let base = serve(CorsSettings::default()).await?;
+        assert_eq!(allow_origin(&base, "https://lnvps.net").await?, None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_wildcard_origin() -> Result<()> {
+        let base = serve(CorsSettings {
+            origins: vec!["*".to_string()],
+            ..Default::default()
+        })
+        .await?;
+        assert_eq!(
+            allow_origin(&base, "http://localhost:5173")
+                .await?
+                .as_deref(),
+            Some("*")
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_preflight_methods() -> Result<()> {
+        let base = serve(CorsSettings {
+            origins: vec!["https://lnvps.net".to_string()],
+            methods: vec!["get".to_string(), "POST".to_string()],
+            ..Default::default()
+        })
+        .await?;
+        let rsp = reqwest::Client::new()
+            .request(reqwest::Method::OPTIONS, format!("{}/api/v1/ping", base))
+            .header("origin", "https://lnvps.net")
+            .header("access-control-request-method", "POST")
+            .header("access-control-request-headers", "authorization")
+            .send()
+            .await?;
+        let methods = rsp
+            .headers()
+            .get(ACCESS_CONTROL_ALLOW_METHODS)
+            .unwrap()
+            .to_str()?;
+        assert_eq!(methods, "GET,POST");
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_values_rejected() {
+        let cors = CorsSettings {
+            methods: vec!["NOT A METHOD".to_string()],
+            ..Default::default()
+        };
+        assert!(cors.layer().is_err());
+        let cors = CorsSettings {
+            headers: vec!["bad header".to_string()],
+            ..Default::default()
+        };
+        assert!(cors.layer().is_err());
+    }
+}
@@ -5,10 +5,12 @@ use async_trait::async_trait;
 use lnvps_db::VmIpAssignment;
 use lnvps_db::{DnsServerKind, LNVpsDb};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fmt::{Display, Formatter};
 use std::net::IpAddr;
 use std::str::FromStr;
 use std::sync::Arc;
+use tokio::sync::RwLock;
 
 mod cloudflare;
 mod ovh;
@@ -58,6 +60,108 @@ pub async fn get_dns_server(
     }
 }
 
+/// DNS server clients by `dns_server` id, each IP range picks its forward and
+/// reverse backend by id.
+///
+/// Clients are built from the `dns_server` table on first use and reused until
+/// the row's provider, url or token changes.
+#[derive(Clone)]
+pub struct DnsServerRegistry {
+    db: Arc<dyn LNVpsDb>,
+    servers: Arc<RwLock<HashMap<u64, RegistryEntry>>>,
+    /// OVH reverse DNS clients by OVH additional-IP `router` id
+    ovh_routers: Arc<RwLock<HashMap<u64, RegistryEntry>>>,
+}
+
+#[derive(Clone)]
+enum RegistryEntry {
+    /// Built from a `dns_server` row
+    Configured {
+        kind: DnsServerKind,
+        url: String,
+        token: String,
+        server: Arc<dyn DnsServer>,
+    },
+    /// Registered in code, never rebuilt
+    Fixed(Arc<dyn DnsServer>),
+}
+
+impl DnsServerRegistry {
+    pub fn new(db: Arc<dyn LNVpsDb>) -> Self {
+        Self {
+            db,
+            servers: Default::default(),
+            ovh_routers: Default::default(),
+        }
+    }
+
+    /// Use `server` for `dns_server_id` instead of building it from the database
+    pub async fn register(&self, dns_server_id: u64, server: Arc<dyn DnsServer>) {
+        self.servers
+            .write()
+            .await
+            .insert(dns_server_id, RegistryEntry::Fixed(server));
+    }
+
+    /// Get the client for a `dns_server` id
+    pub async fn get(&self, dns_server_id: u64) -> OpResult<Arc<dyn DnsServer>> {
+        if let Some(RegistryEntry::Fixed(s)) = self.servers.read().await.get(&dns_server_id) {
+            return Ok(s.clone());
+        }
+        let cfg = self.db.get_dns_server(dns_server_id).await?;
+        if let Some(RegistryEntry::Configured {
+            kind,
+            url,
+            token,
+            server,
+        }) = self.servers.read().await.get(&dns_server_id)
+            && *kind == cfg.kind
+            && *url == cfg.url
+            && token == cfg.token.as_str()
+        {
+            return Ok(server.clone());
+        }
+        let server = get_dns_server(&self.db, dns_server_id).await?;
+        self.servers.write().await.insert(
+            dns_server_id,
+            RegistryEntry::Configured {
+                kind: cfg.kind,
+                url: cfg.url,
+                token: cfg.token.as_str().to_string(),
+                server: server.clone(),
+            },
+        );
+        Ok(server)
+    }
+
+    /// Get the OVH reverse DNS client for an OVH additional-IP router.
+    ///
+    /// Used for ranges routed through OVH which have no reverse DNS server of
+    /// their own, OVH only accepts reverse records for its IPs through its API.
+    pub async fn ovh_router(&self, router: &lnvps_db::Router) -> OpResult<Arc<dyn DnsServer>> {
+        if let Some(RegistryEntry::Configured {
+            url, token, server, ..
+        }) = self.ovh_routers.read().await.get(&router.id)
+            && *url == router.url
+            && token == router.token.as_str()
+        {
+            return Ok(server.clone());
+        }
+        let server: Arc<dyn DnsServer> =
+            Arc::new(ovh::OvhDns::new(&router.url, router.token.as_str()).await?);
+        self.ovh_routers.write().await.insert(
+            router.id,
+            RegistryEntry::Configured {
+                kind: DnsServerKind::Ovh,
+                url: router.url.clone(),
+                token: router.token.as_str().to_string(),
+                server: server.clone(),
+            },
+        );
+        Ok(server)
+    }
+}
+
 #[derive(Clone, Debug)]
 pub enum RecordType {
     A,
@@ -133,11 +237,26 @@ impl Display for RecordType {
     }
 }
 
+/// Name of a VM's forward record relative to the forward zone, `vm-{id}` or
+/// `vm-{id}.{suffix}` with the region's forward DNS suffix
+pub fn forward_name(vm_id: u64, suffix: Option<&str>) -> String {
+    match suffix {
+        Some(suffix) => format!("vm-{}.{}", vm_id, suffix),
+        None => format!("vm-{}", vm_id),
+    }
+}
+
+/// A forward DNS suffix is one or more DNS labels (e.g. `dub` or `dub.eu`)
+pub fn is_valid_forward_suffix(suffix: &str) -> bool {
+    !suffix.ends_with('.') && is_valid_fqdn(&forward_name(0, Some(suffix)))
+}
+
 impl BasicRecord {
-    pub fn forward(ip: &VmIpAssignment, zone: DnsRef) -> Result<Self> {
+    /// The forward record of a VM IP, see [`forward_name`]
+    pub fn forward(ip: &VmIpAssignment, zone: DnsRef, suffix: Option<&str>) -> Result<Self> {
         let addr = IpAddr::from_str(&ip.ip)?;
         Ok(Self {
-            name: format!("vm-{}", &ip.vm_id),
+            name: forward_name(ip.vm_id, suffix),
             value: addr.to_string(),
             id: ip.dns_forward_ref.clone().map(DnsRef::Id),
             kind: match addr {
@@ -279,7 +398,7 @@ mod tests {
 
     #[test]
     fn test_stored_ref_implicit_uses_ip() {
-        let mut rec = BasicRecord::forward(&v4_assignment(), DnsRef::Implicit).unwrap();
+        let mut rec = BasicRecord::forward(&v4_assignment(), DnsRef::Implicit, None).unwrap();
         rec.id = Some(DnsRef::Implicit);
         assert_eq!(rec.stored_ref().as_deref(), Some("10.0.0.5"));
         rec.id = Some(DnsRef::Id("cf-123".into()));
@@ -291,7 +410,7 @@ mod tests {
     #[test]
     fn test_forward_record_sets_ip_and_zone() -> anyhow::Result<()> {
         let ip = v4_assignment();
-        let rec = BasicRecord::forward(&ip, DnsRef::Id("zone-1".to_string()))?;
+        let rec = BasicRecord::forward(&ip, DnsRef::Id("zone-1".to_string()), None)?;
         assert_eq!(rec.ip, "10.0.0.5");
         assert_eq!(rec.zone, DnsRef::Id("zone-1".to_string()));
         assert!(matches!(rec.kind, RecordType::A));
@@ -299,6 +418,24 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_forward_record_region_suffix() -> anyhow::Result<()> {
+        let ip = v4_assignment();
+        let zone = DnsRef::Id("zone-1".to_string());
+        let dub = BasicRecord::forward(&ip, zone.clone(), Some("dub"))?;
+        let fra = BasicRecord::forward(&ip, zone.clone(), Some("fra"))?;
+        assert_eq!(dub.name, "vm-42.dub");
+        assert_eq!(fra.name, "vm-42.fra");
+        assert_eq!(BasicRecord::forward(&ip, zone, None)?.name, "vm-42");
+
+        assert!(is_valid_forward_suffix("dub"));
+        assert!(is_valid_forward_suffix("dub.eu"));
+        assert!(!is_valid_forward_suffix("dub."));
+        assert!(!is_valid_forward_suffix("-dub"));
+        assert!(!is_valid_forward_suffix("d ub"));
+        Ok(())
+    }
+
     #[test]
     fn test_reverse_records() -> anyhow::Result<()> {
         let ip = v4_assignment();
@@ -324,10 +461,39 @@ mod tests {
         let db: Arc<dyn LNVpsDb> = Arc::new(MockDb::default());
         let dns = get_dns_server(&db, 1).await?;
 
-        let rec = BasicRecord::forward(&v4_assignment(), DnsRef::Id("zone-x".to_string()))?;
+        let rec = BasicRecord::forward(&v4_assignment(), DnsRef::Id("zone-x".to_string()), None)?;
         let added = dns.add_record(&rec).await?;
         assert!(added.id.is_some());
         dns.delete_record(&added).await?;
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_registry_reuses_client_until_config_changes() -> anyhow::Result<()> {
+        let mock = Arc::new(MockDb::default());
+        let db: Arc<dyn LNVpsDb> = mock.clone();
+        let registry = DnsServerRegistry::new(db);
+
+        let a = registry.get(1).await?;
+        let b = registry.get(1).await?;
+        assert!(Arc::ptr_eq(&a, &b));
+
+        mock.dns_servers.lock().await.get_mut(&1).unwrap().token = "new-token".into();
+        let c = registry.get(1).await?;
+        assert!(!Arc::ptr_eq(&a, &c));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_registry_registered_server() -> anyhow::Result<()> {
+        let db: Arc<dyn LNVpsDb> = Arc::new(MockDb::default());
+        let registry = DnsServerRegistry::new(db);
+
+        let fixed: Arc<dyn DnsServer> = Arc::new(crate::MockDnsServer::isolated());
+        registry.register(1, fixed.clone()).await;
+        assert!(Arc::ptr_eq(&registry.get(1).await?, &fixed));
+        // unknown ids still fail
+        assert!(registry.get(99).await.is_err());
+        Ok(())
+    }
 }
@@ -6,12 +6,13 @@
 //! by another provider (e.g. Cloudflare).
 
 use crate::dns::{BasicRecord, DnsRef, DnsServer, DnsZone, RecordType};
-use crate::json_api::JsonApi;
+use crate::json_api::{JsonApi, response_status};
 use crate::op_fatal;
 use crate::ovh::ovh_json_api;
 use crate::retry::OpResult;
 use async_trait::async_trait;
 use log::info;
+use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
 
 pub struct OvhDns {
@@ -31,9 +32,13 @@ impl OvhDns {
     /// record's `zone` (from the range's `reverse_zone_id`); we fall back to the
     /// bare IP for standalone services with no configured block.
     fn block_for(record: &BasicRecord) -> String {
-        match record.zone.as_id() {
+        Self::block_path(&record.zone, &record.ip)
+    }
+
+    fn block_path(zone: &DnsRef, ip: &str) -> String {
+        match zone.as_id() {
             Some(block) => block.replace('/', "%2F"),
-            None => record.ip.clone(),
+            None => ip.to_string(),
         }
     }
 
@@ -47,6 +52,17 @@ impl OvhDns {
         }
     }
 
+    /// Get the reverse name currently set for `ip` in `block`, `None` if OVH
+    /// has no reverse record for it.
+    pub async fn get_reverse(&self, block: &DnsRef, ip: &str) -> OpResult<Option<String>> {
+        let block = Self::block_path(block, ip);
+        let rsp: Option<OvhReverse> = self
+            .api
+            .get_opt(&format!("v1/ip/{}/reverse/{}", block, ip))
+            .await?;
+        Ok(rsp.map(|r| r.reverse))
+    }
+
     /// Set (create or overwrite) the reverse record for an IP.
     async fn set_reverse(&self, record: &BasicRecord) -> OpResult<BasicRecord> {
         if !matches!(record.kind, RecordType::PTR) {
@@ -96,14 +112,20 @@ impl DnsServer for OvhDns {
         }
         info!("[OVH] Deleting reverse: {}", record.ip);
         let block = Self::block_for(record);
-        self.api
-            .req::<(), ()>(
+        match self
+            .api
+            .req_status::<()>(
                 reqwest::Method::DELETE,
                 &format!("v1/ip/{}/reverse/{}", block, record.ip),
                 None,
             )
-            .await?;
-        Ok(())
+            .await
+        {
+            Ok(_) => Ok(()),
+            // nothing to delete, OVH answers a DELETE of a missing reverse with a 404
+            Err(e) if response_status(e.inner()) == Some(StatusCode::NOT_FOUND) => Ok(()),
+            Err(e) => Err(e),
+        }
     }
 
     /// OVH reverse DNS is keyed per-IP block and exposes no listable zones.
@@ -124,13 +146,32 @@ struct OvhReverseRequest {
 struct OvhReverse {
     #[allow(dead_code)]
     ip_reverse: String,
-    #[allow(dead_code)]
     reverse: String,
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::retry::OpError;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    /// An OVH API mock answering the clock sync done when the client is built
+    async fn ovh_server() -> MockServer {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/v1/auth/time"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(chrono::Utc::now().timestamp()))
+            .mount(&server)
+            .await;
+        server
+    }
+
+    async fn ovh_dns(server: &MockServer) -> OvhDns {
+        OvhDns::new(&server.uri(), "app-key:app-secret:consumer-key")
+            .await
+            .unwrap()
+    }
 
     fn ptr_record(ip: &str, zone: DnsRef) -> BasicRecord {
         BasicRecord {
@@ -165,4 +206,127 @@ mod tests {
             "host.example.com."
         );
     }
+
+    #[tokio::test]
+    async fn test_set_and_get_reverse() -> anyhow::Result<()> {
+        let server = ovh_server().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/ip/15.235.3.224%2F28/reverse"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "ipReverse": "15.235.3.229",
+                "reverse": "vm-1.lnvps.cloud."
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/v1/ip/15.235.3.224%2F28/reverse/15.235.3.229"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "ipReverse": "15.235.3.229",
+                "reverse": "vm-1.lnvps.cloud."
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/v1/ip/15.235.3.224%2F28/reverse/15.235.3.230"))
+            .respond_with(ResponseTemplate::new(404).set_body_json(serde_json::json!({
+                "class": "Client::NotFound",
+                "message": "The requested object (ipReverse = 15.235.3.230) does not exist"
+            })))
+            .mount(&server)
+            .await;
+
+        let dns = ovh_dns(&server).await;
+        let zone = DnsRef::Id("15.235.3.224/28".to_string());
+        let rec = dns
+            .add_record(&ptr_record("15.235.3.229", zone.clone()))
+            .await?;
+        assert_eq!(rec.value, "vm-1.lnvps.cloud.");
+        assert_eq!(rec.stored_ref().as_deref(), Some("15.235.3.229"));
+
+        assert_eq!(
+            dns.get_reverse(&zone, "15.235.3.229").await?.as_deref(),
+            Some("vm-1.lnvps.cloud.")
+        );
+        assert_eq!(dns.get_reverse(&zone, "15.235.3.230").await?, None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_delete_missing_reverse() -> anyhow::Result<()> {
+        let server = ovh_server().await;
+        Mock::given(method("DELETE"))
+            .and(path("/v1/ip/15.235.3.224%2F28/reverse/15.235.3.229"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::Value::Null))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("DELETE"))
+            .and(path("/v1/ip/15.235.3.224%2F28/reverse/15.235.3.230"))
+            .respond_with(ResponseTemplate::new(404).set_body_json(serde_json::json!({
+                "class": "Client::NotFound",
+                "message": "The requested object (ipReverse = 15.235.3.230) does not exist"
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        // the reverse is never looked up before deleting it
+        Mock::given(method("GET"))
+            .and(path("/v1/ip/15.235.3.224%2F28/reverse/15.235.3.229"))
+            .respond_with(ResponseTemplate::new(500))
+            .expect(0)
+            .mount(&server)
+            .await;
+
+        let dns = ovh_dns(&server).await;
+        let zone = DnsRef::Id("15.235.3.224/28".to_string());
+        dns.delete_record(&ptr_record("15.235.3.229", zone.clone()))
+            .await?;
+        // a reverse which was never set is already deleted
+        dns.delete_record(&ptr_record("15.235.3.230", zone)).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_and_server_errors_are_transient() {
+        let server = ovh_server().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/ip/15.235.3.229/reverse"))
+            .respond_with(ResponseTemplate::new(429).set_body_json(serde_json::json!({
+                "class": "Client::TooManyRequests",
+                "message": "Too many requests"
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/v1/ip/15.235.3.230/reverse"))
+            .respond_with(ResponseTemplate::new(503).set_body_json(serde_json::json!({
+                "class": "Server::ServiceUnavailable",
+                "message": "Service temporarily unavailable"
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/v1/ip/15.235.3.231/reverse"))
+            .respond_with(ResponseTemplate::new(400).set_body_json(serde_json::json!({
+                "class": "Client::BadRequest",
+                "message": "Cannot check if vm-1.lnvps.cloud. resolves to 15.235.3.231"
+            })))
+            .mount(&server)
+            .await;
+
+        let dns = ovh_dns(&server).await;
+        for ip in ["15.235.3.229", "15.235.3.230"] {
+            let e = dns
+                .add_record(&ptr_record(ip, DnsRef::Implicit))
+                .await
+                .unwrap_err();
+            assert!(e.is_transient(), "{} should be transient", ip);
+        }
+        assert!(matches!(
+            dns.add_record(&ptr_record("15.235.3.231", DnsRef::Implicit))
+                .await,
+            Err(OpError::Fatal(_))
+        ));
+    }
 }
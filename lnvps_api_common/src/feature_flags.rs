@@ -0,0 +1,201 @@
+use anyhow::Result;
+use lnvps_db::{FeatureFlag, LNVpsDb};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::RwLock;
+
+/// Customers can schedule and restore VM backups
+pub const FEATURE_BACKUPS: &str = "backups";
+/// VMs can be live migrated between hosts (admin migrations and host drains)
+pub const FEATURE_VM_MIGRATION: &str = "vm_migration";
+/// Expiring subscriptions are renewed from credit or a saved payment method
+pub const FEATURE_AUTO_RENEWAL: &str = "auto_renewal";
+
+/// Flags checked by the code, used to validate admin input
+pub const KNOWN_FEATURES: [&str; 3] = [FEATURE_BACKUPS, FEATURE_VM_MIGRATION, FEATURE_AUTO_RENEWAL];
+
+/// How long flags are cached before being reloaded from the database. Other
+/// processes pick up a change within this window.
+pub const FEATURE_FLAG_TTL: Duration = Duration::from_secs(30);
+
+/// A gated feature is switched off, answered with `403 feature_unavailable`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FeatureDisabledError {
+    pub key: String,
+}
+
+impl std::fmt::Display for FeatureDisabledError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "The {} feature is not available", self.key)
+    }
+}
+
+impl std::error::Error for FeatureDisabledError {}
+
+/// Cached view of the `feature_flag` table.
+///
+/// A feature without any flag is enabled, so a flag only has to exist to
+/// switch something off. A region flag wins over the global flag.
+#[derive(Clone)]
+pub struct FeatureFlags {
+    db: Arc<dyn LNVpsDb>,
+    ttl: Duration,
+    cache: Arc<RwLock<Option<(Instant, Vec<FeatureFlag>)>>>,
+}
+
+impl FeatureFlags {
+    pub fn new(db: Arc<dyn LNVpsDb>) -> Self {
+        Self::with_ttl(db, FEATURE_FLAG_TTL)
+    }
+
+    pub fn with_ttl(db: Arc<dyn LNVpsDb>, ttl: Duration) -> Self {
+        Self {
+            db,
+            ttl,
+            cache: Arc::new(RwLock::new(None)),
+        }
+    }
+
+    /// Drop the cached flags so the next check reads the database.
+    ///
+    /// This only clears the cache of this process, other processes (API,
+    /// worker) keep their copy until [FEATURE_FLAG_TTL] has passed.
+    pub async fn invalidate(&self) {
+        self.cache.write().await.take();
+    }
+
+    async fn flags(&self) -> Result<Vec<FeatureFlag>> {
+        if let Some((loaded, flags)) = self.cache.read().await.as_ref()
+            && loaded.elapsed() < self.ttl
+        {
+            return Ok(flags.clone());
+        }
+        let flags = self.db.list_feature_flags().await?;
+        *self.cache.write().await = Some((Instant::now(), flags.clone()));
+        Ok(flags)
+    }
+
+    /// Is `key` enabled, for `region` when given
+    pub async fn is_feature_enabled(&self, key: &str, region: Option<u64>) -> Result<bool> {
+        let flags = self.flags().await?;
+        let mut global = None;
+        for f in flags.iter().filter(|f| f.key == key) {
+            match f.region_id {
+                Some(r) if Some(r) == region => return Ok(f.enabled),
+                None => global = Some(f.enabled),
+                _ => {}
+            }
+        }
+        Ok(global.unwrap_or(true))
+    }
+
+    /// Fail with [`FeatureDisabledError`] unless `key` is enabled
+    pub async fn require(&self, key: &str, region: Option<u64>) -> Result<()> {
+        if self.is_feature_enabled(key, region).await? {
+            Ok(())
+        } else {
+            Err(FeatureDisabledError {
+                key: key.to_string(),
+            }
+            .into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MockDb;
+
+    fn flag(key: &str, region_id: Option<u64>, enabled: bool) -> FeatureFlag {
+        FeatureFlag {
+            key: key.to_string(),
+            region_id,
+            enabled,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn region_flag_overrides_global() -> Result<()> {
+        let db = Arc::new(MockDb::default());
+        let features = FeatureFlags::new(db.clone());
+        assert!(features.is_feature_enabled(FEATURE_BACKUPS, None).await?);
+
+        {
+            let mut flags = db.feature_flags.lock().await;
+            flags.insert(1, flag(FEATURE_BACKUPS, None, false));
+            flags.insert(2, flag(FEATURE_BACKUPS, Some(2), true));
+        }
+        features.invalidate().await;
+        assert!(!features.is_feature_enabled(FEATURE_BACKUPS, None).await?);
+        assert!(
+            !features
+                .is_feature_enabled(FEATURE_BACKUPS, Some(1))
+                .await?
+        );
+        assert!(
+            features
+                .is_feature_enabled(FEATURE_BACKUPS, Some(2))
+                .await?
+        );
+        assert!(
+            features
+                .is_feature_enabled(FEATURE_VM_MIGRATION, Some(1))
+                .await?
+        );
+
+        let err = features
+            .require(FEATURE_BACKUPS, Some(1))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<FeatureDisabledError>().unwrap().key,
+            FEATURE_BACKUPS
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn cached_until_invalidated() -> Result<()> {
+        let db = Arc::new(MockDb::default());
+        let features = FeatureFlags::new(db.clone());
+        assert!(
+            features
+                .is_feature_enabled(FEATURE_AUTO_RENEWAL, None)
+                .await?
+        );
+
+        db.feature_flags
+            .lock()
+            .await
+            .insert(1, flag(FEATURE_AUTO_RENEWAL, None, false));
+        assert!(
+            features
+                .is_feature_enabled(FEATURE_AUTO_RENEWAL, None)
+                .await?,
+            "served from the cache"
+        );
+        features.invalidate().await;
+        assert!(
+            !features
+                .is_feature_enabled(FEATURE_AUTO_RENEWAL, None)
+                .await?
+        );
+
+        // an expired cache reloads by itself
+        let features = FeatureFlags::with_ttl(db.clone(), Duration::ZERO);
+        assert!(
+            !features
+                .is_feature_enabled(FEATURE_AUTO_RENEWAL, None)
+                .await?
+        );
+        db.feature_flags.lock().await.get_mut(&1).unwrap().enabled = true;
+        assert!(
+            features
+                .is_feature_enabled(FEATURE_AUTO_RENEWAL, None)
+                .await?
+        );
+        Ok(())
+    }
+}
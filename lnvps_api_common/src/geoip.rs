@@ -25,9 +25,10 @@ impl<T: CountryResolver + ?Sized> CountryResolver for Arc<T> {
     }
 }
 
-/// Returns `true` for addresses that can never yield useful geolocation
-/// (loopback, private, link-local, unspecified, unique-local IPv6, ...).
-fn is_non_routable(ip: &IpAddr) -> bool {
+/// Returns `true` for addresses that are not reachable on the public
+/// internet (loopback, private, link-local, unspecified, unique-local IPv6,
+/// ...), they can never yield useful geolocation.
+pub fn is_non_routable(ip: &IpAddr) -> bool {
     match ip {
         IpAddr::V4(v4) => {
             v4.is_private()
@@ -36,9 +37,16 @@ fn is_non_routable(ip: &IpAddr) -> bool {
                 || v4.is_unspecified()
                 || v4.is_broadcast()
                 || v4.is_documentation()
+                || v4.is_multicast()
+                // shared address space (100.64.0.0/10)
+                || (v4.octets()[0] == 100 && (v4.octets()[1] & 0xc0) == 64)
         }
         IpAddr::V6(v6) => {
+            if let Some(v4) = v6.to_ipv4_mapped() {
+                return is_non_routable(&IpAddr::V4(v4));
+            }
             v6.is_loopback()
+                || v6.is_multicast()
                 || v6.is_unspecified()
                 // unique local (fc00::/7)
                 || (v6.segments()[0] & 0xfe00) == 0xfc00
@@ -119,6 +127,8 @@ mod tests {
         assert!(is_non_routable(&"::1".parse().unwrap()));
         assert!(is_non_routable(&"fd00::1".parse().unwrap()));
         assert!(is_non_routable(&"fe80::1".parse().unwrap()));
+        assert!(is_non_routable(&"100.64.0.1".parse().unwrap()));
+        assert!(is_non_routable(&"::ffff:127.0.0.1".parse().unwrap()));
         assert!(!is_non_routable(&"8.8.8.8".parse().unwrap()));
         assert!(!is_non_routable(&"1.1.1.1".parse().unwrap()));
     }
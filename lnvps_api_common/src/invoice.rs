@@ -0,0 +1,540 @@
+//! Invoices for paid payments, rendered as PDF.
+//!
+//! The PDF is written directly as A4 pages using the standard Helvetica
+//! fonts, so no font files or external renderer are needed.
+
+use crate::TaxLine;
+use anyhow::{Result, anyhow, ensure};
+use chrono::{DateTime, Utc};
+use lnvps_db::{Company, LNVpsDb, SubscriptionPayment, SubscriptionPaymentType, User};
+use payments_rs::currency::{Currency, CurrencyAmount};
+use std::fmt::Write;
+
+/// A4 page size in points
+const PAGE_WIDTH: f32 = 595.0;
+const PAGE_HEIGHT: f32 = 842.0;
+const MARGIN: f32 = 50.0;
+/// X position of the amount columns
+const NET_COLUMN: f32 = 380.0;
+const TAX_COLUMN: f32 = 470.0;
+
+/// One line of an invoice
+#[derive(Debug, Clone)]
+pub struct InvoiceLine {
+    pub description: String,
+    /// Net amount in smallest currency units
+    pub amount: u64,
+    /// Tax on `amount` in smallest currency units
+    pub tax: u64,
+}
+
+/// A paid payment laid out as an invoice
+#[derive(Debug, Clone)]
+pub struct Invoice {
+    /// Sequential invoice number, or the hex encoded payment id for payments
+    /// paid before invoice numbers were assigned
+    pub number: String,
+    /// When the payment was made
+    pub date: DateTime<Utc>,
+    /// Seller name, address and tax id
+    pub seller: Vec<String>,
+    /// Customer billing name, address and tax id
+    pub customer: Vec<String>,
+    pub lines: Vec<InvoiceLine>,
+    pub currency: Currency,
+    /// Label for the tax total, e.g. `VAT 23% (IRL)`
+    pub tax_label: String,
+    /// Legal note printed under the totals (e.g. reverse charge)
+    pub note: Option<String>,
+    pub processing_fee: u64,
+}
+
+impl Invoice {
+    /// Build the invoice for a paid payment. VM payments are invoiced by the
+    /// company of the VM's region, other subscriptions by their own company.
+    pub async fn for_payment(db: &dyn LNVpsDb, payment: &SubscriptionPayment) -> Result<Self> {
+        ensure!(
+            payment.is_paid,
+            "Payment is not paid, can't generate invoice"
+        );
+        let subscription = db.get_subscription(payment.subscription_id).await?;
+        let company_id = match db.get_vm_by_subscription(subscription.id).await {
+            Ok(vm) => db.get_vm_company_id(vm.id).await?,
+            Err(e) if e.is_row_not_found() => subscription.company_id,
+            Err(e) => return Err(e.into()),
+        };
+        let company = db.get_company(company_id).await?;
+        let user = db.get_user(payment.user_id).await?;
+        let currency: Currency = payment
+            .currency
+            .parse()
+            .map_err(|_| anyhow!("Invalid currency {}", payment.currency))?;
+
+        let kind = match payment.payment_type {
+            SubscriptionPaymentType::Purchase => "Purchase",
+            SubscriptionPaymentType::Renewal => "Renewal",
+            SubscriptionPaymentType::Upgrade => "Upgrade",
+        };
+        // one line per line item when the stored tax breakdown lines up with them
+        let items = db.list_subscription_line_items(subscription.id).await?;
+        let breakdown: Vec<TaxLine> = payment
+            .tax_breakdown
+            .as_ref()
+            .and_then(|b| serde_json::from_value(b.clone()).ok())
+            .unwrap_or_default();
+        let lines = if !items.is_empty() && items.len() == breakdown.len() {
+            items
+                .iter()
+                .zip(breakdown)
+                .map(|(i, l)| InvoiceLine {
+                    description: format!("{} - {}", kind, i.name),
+                    amount: l.net,
+                    tax: l.tax,
+                })
+                .collect()
+        } else {
+            vec![InvoiceLine {
+                description: format!("{} - {}", kind, subscription.name),
+                amount: payment.amount,
+                tax: payment.tax,
+            }]
+        };
+
+        let (tax_label, note) = match payment.tax_treatment.as_deref() {
+            Some("reverse_charge") => (
+                "VAT reverse charged".to_string(),
+                Some(
+                    "VAT reverse charged - the recipient is liable to account for VAT \
+                     (Article 196, Council Directive 2006/112/EC)."
+                        .to_string(),
+                ),
+            ),
+            _ => match (payment.tax_rate, payment.tax_country_code.as_deref()) {
+                (Some(r), Some(cc)) => (format!("VAT {:.0}% ({})", r, cc), None),
+                (Some(r), None) => (format!("VAT {:.0}%", r), None),
+                _ => ("VAT".to_string(), None),
+            },
+        };
+
+        Ok(Self {
+            number: payment
+                .invoice_number
+                .clone()
+                .unwrap_or_else(|| hex::encode(&payment.id)),
+            date: payment.paid_at.unwrap_or(payment.created),
+            seller: company_lines(&company),
+            customer: user_lines(&user),
+            lines,
+            currency,
+            tax_label,
+            note,
+            processing_fee: payment.processing_fee,
+        })
+    }
+
+    /// Total of the net line amounts
+    pub fn net(&self) -> u64 {
+        self.lines.iter().map(|l| l.amount).sum()
+    }
+
+    /// Total tax of all lines
+    pub fn tax(&self) -> u64 {
+        self.lines.iter().map(|l| l.tax).sum()
+    }
+
+    /// Amount paid, net + tax + processing fee
+    pub fn total(&self) -> u64 {
+        self.net() + self.tax() + self.processing_fee
+    }
+
+    fn format(&self, amount: u64) -> String {
+        CurrencyAmount::from_u64(self.currency, amount).to_string()
+    }
+
+    /// Render the invoice as a PDF, continuing onto more pages when the lines
+    /// don't fit on one
+    pub fn to_pdf(&self) -> Vec<u8> {
+        let mut doc = PdfDocument::default();
+        doc.down(10.0);
+        doc.text(MARGIN, 20.0, true, "INVOICE");
+        doc.down(24.0);
+        doc.text(MARGIN, 10.0, false, &format!("Invoice #{}", self.number));
+        doc.down(14.0);
+        doc.text(
+            MARGIN,
+            10.0,
+            false,
+            &format!("Date: {}", self.date.format("%Y-%m-%d")),
+        );
+        doc.down(30.0);
+
+        doc.text(MARGIN, 11.0, true, "From");
+        doc.text(PAGE_WIDTH / 2.0, 11.0, true, "Bill to");
+        let rows = self.seller.len().max(self.customer.len());
+        for i in 0..rows {
+            doc.down(14.0);
+            if let Some(l) = self.seller.get(i) {
+                doc.text(MARGIN, 10.0, false, l);
+            }
+            if let Some(l) = self.customer.get(i) {
+                doc.text(PAGE_WIDTH / 2.0, 10.0, false, l);
+            }
+        }
+        doc.down(36.0);
+
+        self.line_header(&mut doc);
+        for l in &self.lines {
+            if doc.down(16.0) {
+                // repeat the column headings at the top of each new page
+                self.line_header(&mut doc);
+                doc.down(16.0);
+            }
+            doc.text(MARGIN, 10.0, false, &l.description);
+            doc.text(NET_COLUMN, 10.0, false, &self.format(l.amount));
+            doc.text(TAX_COLUMN, 10.0, false, &self.format(l.tax));
+        }
+        doc.down(8.0);
+        doc.rule();
+
+        let mut totals = vec![
+            ("Subtotal".to_string(), self.net()),
+            (self.tax_label.clone(), self.tax()),
+        ];
+        if self.processing_fee > 0 {
+            totals.push(("Processing fee".to_string(), self.processing_fee));
+        }
+        for (label, amount) in totals {
+            doc.down(16.0);
+            doc.text(NET_COLUMN - 100.0, 10.0, false, &label);
+            doc.text(TAX_COLUMN, 10.0, false, &self.format(amount));
+        }
+        doc.down(20.0);
+        doc.text(NET_COLUMN - 100.0, 12.0, true, "Total");
+        doc.text(TAX_COLUMN, 12.0, true, &self.format(self.total()));
+
+        if let Some(note) = &self.note {
+            doc.down(36.0);
+            doc.text(MARGIN, 8.0, false, note);
+        }
+        doc.finish()
+    }
+
+    fn line_header(&self, doc: &mut PdfDocument) {
+        doc.text(MARGIN, 10.0, true, "Description");
+        doc.text(NET_COLUMN, 10.0, true, "Amount");
+        doc.text(TAX_COLUMN, 10.0, true, "Tax");
+        doc.down(6.0);
+        doc.rule();
+    }
+}
+
+fn company_lines(company: &Company) -> Vec<String> {
+    let mut lines = vec![company.name.clone()];
+    lines.extend(
+        [
+            &company.address_1,
+            &company.address_2,
+            &company.city,
+            &company.state,
+            &company.postcode,
+            &company.country_code,
+        ]
+        .into_iter()
+        .flatten()
+        .filter(|s| !s.trim().is_empty())
+        .cloned(),
+    );
+    if let Some(tax_id) = company.tax_id.as_ref().filter(|s| !s.trim().is_empty()) {
+        lines.push(format!("Tax ID: {}", tax_id));
+    }
+    lines
+}
+
+fn user_lines(user: &User) -> Vec<String> {
+    let mut lines: Vec<String> = [
+        &user.billing_name,
+        &user.billing_address_1,
+        &user.billing_address_2,
+        &user.billing_city,
+        &user.billing_state,
+        &user.billing_postcode,
+        &user.country_code,
+    ]
+    .into_iter()
+    .flatten()
+    .filter(|s| !s.trim().is_empty())
+    .cloned()
+    .collect();
+    if let Some(tax_id) = user
+        .billing_tax_id
+        .as_ref()
+        .filter(|s| !s.trim().is_empty())
+    {
+        lines.push(format!("Tax ID: {}", tax_id));
+    }
+    lines
+}
+
+/// Content streams of a PDF, one per page, with a cursor on the current page
+struct PdfDocument {
+    pages: Vec<String>,
+    /// Baseline of the current line on the last page
+    y: f32,
+}
+
+impl Default for PdfDocument {
+    fn default() -> Self {
+        Self {
+            pages: vec![String::new()],
+            y: PAGE_HEIGHT - MARGIN,
+        }
+    }
+}
+
+impl PdfDocument {
+    /// Move the cursor down by `dy`, starting a new page when it would pass the
+    /// bottom margin. Returns true when a new page was started.
+    fn down(&mut self, dy: f32) -> bool {
+        self.y -= dy;
+        if self.y < MARGIN {
+            self.pages.push(String::new());
+            self.y = PAGE_HEIGHT - MARGIN;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn content(&mut self) -> &mut String {
+        self.pages.last_mut().expect("document has a page")
+    }
+
+    fn text(&mut self, x: f32, size: f32, bold: bool, text: &str) {
+        let font = if bold { "F2" } else { "F1" };
+        let y = self.y;
+        let _ = writeln!(
+            self.content(),
+            "BT /{} {} Tf {:.1} {:.1} Td ({}) Tj ET",
+            font,
+            size,
+            x,
+            y,
+            pdf_string(text)
+        );
+    }
+
+    /// Horizontal rule across the page at the cursor
+    fn rule(&mut self) {
+        let y = self.y;
+        let _ = writeln!(
+            self.content(),
+            "0.5 w {:.1} {:.1} m {:.1} {:.1} l S",
+            MARGIN,
+            y,
+            PAGE_WIDTH - MARGIN,
+            y
+        );
+    }
+
+    /// Write out the document: catalog, page tree, fonts, then each page and
+    /// its content stream
+    fn finish(self) -> Vec<u8> {
+        // objects 1-4 are fixed, pages start at 5 with the content after each page
+        let page_id = |i: usize| 5 + i * 2;
+        let kids = (0..self.pages.len())
+            .map(|i| format!("{} 0 R", page_id(i)))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let mut objects = vec![
+            "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+            format!(
+                "<< /Type /Pages /Kids [{}] /Count {} >>",
+                kids,
+                self.pages.len()
+            ),
+            "<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica /Encoding /WinAnsiEncoding >>"
+                .to_string(),
+            "<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica-Bold /Encoding /WinAnsiEncoding >>"
+                .to_string(),
+        ];
+        for (i, content) in self.pages.iter().enumerate() {
+            objects.push(format!(
+                "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {} {}] \
+                 /Resources << /Font << /F1 3 0 R /F2 4 0 R >> >> /Contents {} 0 R >>",
+                PAGE_WIDTH,
+                PAGE_HEIGHT,
+                page_id(i) + 1
+            ));
+            objects.push(format!(
+                "<< /Length {} >>\nstream\n{}endstream",
+                content.len(),
+                content
+            ));
+        }
+
+        let mut out = String::from("%PDF-1.4\n");
+        let mut offsets = Vec::with_capacity(objects.len());
+        for (i, obj) in objects.iter().enumerate() {
+            offsets.push(out.len());
+            let _ = write!(out, "{} 0 obj\n{}\nendobj\n", i + 1, obj);
+        }
+        let xref = out.len();
+        let _ = write!(out, "xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1);
+        for o in offsets {
+            let _ = writeln!(out, "{:010} 00000 n ", o);
+        }
+        let _ = write!(
+            out,
+            "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n",
+            objects.len() + 1,
+            xref
+        );
+        out.into_bytes()
+    }
+}
+
+/// Escape text for a PDF string literal in WinAnsi encoding, characters
+/// outside it are replaced with `?`
+fn pdf_string(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '(' | ')' | '\\' => {
+                out.push('\\');
+                out.push(c);
+            }
+            ' '..='~' => out.push(c),
+            '€' => out.push_str("\\200"),
+            '\u{a0}'..='\u{ff}' => {
+                let _ = write!(out, "\\{:03o}", c as u32);
+            }
+            _ => out.push('?'),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MockDb;
+    use lnvps_db::{LNVpsDbBase, PaymentMethod};
+
+    fn sample_invoice() -> Invoice {
+        Invoice {
+            number: "0a1b2c3d".to_string(),
+            date: Utc::now(),
+            seller: vec!["LNVPS Ltd".to_string(), "Tax ID: IE1234567X".to_string()],
+            customer: vec!["Jane (ACME)".to_string(), "Dublin".to_string()],
+            lines: vec![InvoiceLine {
+                description: "Renewal - VM #1".to_string(),
+                amount: 1000,
+                tax: 230,
+            }],
+            currency: Currency::EUR,
+            tax_label: "VAT 23% (IRL)".to_string(),
+            note: None,
+            processing_fee: 48,
+        }
+    }
+
+    #[test]
+    fn test_invoice_pdf() {
+        let invoice = sample_invoice();
+        assert_eq!(invoice.total(), 1278);
+        let pdf = invoice.to_pdf();
+        assert!(pdf.starts_with(b"%PDF-1.4"));
+        assert!(pdf.ends_with(b"%%EOF\n"));
+        let text = String::from_utf8_lossy(&pdf);
+        assert!(text.contains("Invoice #0a1b2c3d"));
+        assert!(text.contains("Jane \\(ACME\\)"));
+
+        // the xref offsets point at each object
+        let xref = text.rsplit("startxref\n").next().unwrap();
+        let xref: usize = xref.lines().next().unwrap().parse().unwrap();
+        assert!(text[xref..].starts_with("xref"));
+        let first = text[xref..].lines().nth(3).unwrap();
+        let offset: usize = first[..10].parse().unwrap();
+        assert!(text[offset..].starts_with("1 0 obj"));
+    }
+
+    #[test]
+    fn test_invoice_pdf_pages() {
+        let mut invoice = sample_invoice();
+        assert_eq!(pdf_page_count(&invoice.to_pdf()), 1);
+
+        invoice.lines = (0..100)
+            .map(|i| InvoiceLine {
+                description: format!("Renewal - VM #{}", i),
+                amount: 100,
+                tax: 23,
+            })
+            .collect();
+        let pdf = invoice.to_pdf();
+        assert!(pdf_page_count(&pdf) > 1);
+        let text = String::from_utf8_lossy(&pdf);
+        // every line is rendered and the last page still has the total
+        assert!(text.contains("(Renewal - VM #99)"));
+        assert!(text.contains("(Total)"));
+    }
+
+    fn pdf_page_count(pdf: &[u8]) -> usize {
+        String::from_utf8_lossy(pdf).matches("/Type /Page ").count()
+    }
+
+    #[test]
+    fn test_pdf_string_escapes() {
+        assert_eq!(pdf_string("a (b) \\c"), "a \\(b\\) \\\\c");
+        assert_eq!(pdf_string("€5 Café"), "\\2005 Caf\\351");
+        assert_eq!(pdf_string("日本"), "??");
+    }
+
+    #[tokio::test]
+    async fn test_invoice_for_payment() -> Result<()> {
+        let db = MockDb::default();
+        let user_id = db.upsert_user(&[7; 32]).await?;
+        let mut user = db.get_user(user_id).await?;
+        user.billing_name = Some("Jane".to_string());
+        db.update_user(&user).await?;
+        let mut payment = SubscriptionPayment {
+            id: vec![0xab; 32],
+            subscription_id: 1,
+            user_id,
+            created: Utc::now(),
+            expires: Utc::now(),
+            amount: 1000,
+            currency: "EUR".to_string(),
+            payment_method: PaymentMethod::Revolut,
+            payment_type: SubscriptionPaymentType::Renewal,
+            external_data: "".to_string().into(),
+            external_id: None,
+            is_paid: false,
+            rate: 1.0,
+            time_value: None,
+            metadata: None,
+            tax: 230,
+            processing_fee: 0,
+            paid_at: None,
+            tax_rate: Some(23.0),
+            tax_country_code: Some("IRL".to_string()),
+            tax_treatment: Some("domestic".to_string()),
+            tax_evidence: None,
+            tax_breakdown: None,
+            invoice_number: None,
+        };
+        assert!(Invoice::for_payment(&db, &payment).await.is_err());
+
+        payment.is_paid = true;
+        let invoice = Invoice::for_payment(&db, &payment).await?;
+        assert_eq!(invoice.number, hex::encode([0xab; 32]));
+        assert_eq!(invoice.seller[0], "Default Company");
+        assert_eq!(invoice.customer, vec!["Jane".to_string()]);
+        assert_eq!(invoice.tax_label, "VAT 23% (IRL)");
+        assert_eq!(invoice.total(), 1230);
+
+        payment.invoice_number = Some("LNVPS-1-2026-000001".to_string());
+        let invoice = Invoice::for_payment(&db, &payment).await?;
+        assert_eq!(invoice.number, "LNVPS-1-2026-000001");
+        Ok(())
+    }
+}
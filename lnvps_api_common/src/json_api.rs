@@ -1,9 +1,10 @@
-use crate::retry::{OpError, OpResult};
+use crate::retry::{OpError, OpResult, RetryAfter};
 use crate::{op_fatal, op_transient};
 use anyhow::{Result, anyhow};
+use chrono::{DateTime, Utc};
 use log::debug;
-use reqwest::header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE, HeaderMap, USER_AGENT};
-use reqwest::{Client, Method, Request, RequestBuilder, Url};
+use reqwest::header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE, HeaderMap, RETRY_AFTER, USER_AGENT};
+use reqwest::{Client, Method, Request, RequestBuilder, StatusCode, Url};
 use serde::Serialize;
 use serde::de::DeserializeOwned;
 use std::error::Error;
@@ -54,12 +55,20 @@ pub trait TokenGen: Send + Sync {
     ) -> Result<RequestBuilder>;
 }
 
+/// Vendor specific detection of rate limit responses.
+///
+/// Returns the delay to wait before retrying when the response body is a rate
+/// limit error. A `Retry-After` header on the response takes precedence.
+pub type RateLimitParser = fn(StatusCode, &str) -> Option<Duration>;
+
 #[derive(Clone)]
 pub struct JsonApi {
     client: Client,
     base: Url,
     /// Custom token generator per request
     token_gen: Option<Arc<dyn TokenGen>>,
+    /// Vendor rate limit detection
+    rate_limit: Option<RateLimitParser>,
 }
 
 impl JsonApi {
@@ -78,6 +87,7 @@ impl JsonApi {
             client,
             base: base.parse()?,
             token_gen: None,
+            rate_limit: None,
         })
     }
 
@@ -97,6 +107,7 @@ impl JsonApi {
             client,
             base: base.parse()?,
             token_gen: None,
+            rate_limit: None,
         })
     }
 
@@ -119,9 +130,17 @@ impl JsonApi {
             client,
             base: base.parse()?,
             token_gen: Some(Arc::new(tg)),
+            rate_limit: None,
         })
     }
 
+    /// Detect vendor specific rate limit responses, they are retried after the
+    /// delay returned by `parser`
+    pub fn with_rate_limit_parser(mut self, parser: RateLimitParser) -> Self {
+        self.rate_limit = Some(parser);
+        self
+    }
+
     pub fn base(&self) -> &Url {
         &self.base
     }
@@ -142,11 +161,31 @@ impl JsonApi {
         self.req(Method::PUT, path, Some(body)).await
     }
 
+    /// POST a body containing secrets (passwords etc.), the body is never
+    /// written to the debug log
+    pub async fn post_sensitive<T: DeserializeOwned, R: Serialize>(
+        &self,
+        path: &str,
+        body: R,
+    ) -> OpResult<T> {
+        self.send(Method::POST, path, Some(body), true).await
+    }
+
     pub fn build_req(
         &self,
         method: Method,
         path: &str,
         body: Option<impl Serialize>,
+    ) -> Result<Request> {
+        self.build_req_inner(method, path, body, false)
+    }
+
+    fn build_req_inner(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<impl Serialize>,
+        sensitive: bool,
     ) -> Result<Request> {
         let url = self.base.join(path)?;
         let mut req = self.client.request(method.clone(), url.clone());
@@ -155,7 +194,11 @@ impl JsonApi {
             if let Some(token_gen) = self.token_gen.as_ref() {
                 req = token_gen.generate_token(method.clone(), &url, Some(&body), req)?;
             }
-            debug!(">> {} {}: {}", method.clone(), path, &body);
+            if sensitive {
+                debug!(">> {} {}: <redacted>", method.clone(), path);
+            } else {
+                debug!(">> {} {}: {}", method.clone(), path, &body);
+            }
             req.header(CONTENT_TYPE, "application/json; charset=utf-8")
                 .body(body)
                 .build()?
@@ -174,12 +217,22 @@ impl JsonApi {
         method: Method,
         path: &str,
         body: Option<R>,
+    ) -> OpResult<T> {
+        self.send(method, path, body, false).await
+    }
+
+    async fn send<T: DeserializeOwned, R: Serialize>(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<R>,
+        sensitive: bool,
     ) -> OpResult<T> {
         // Serialize the body once so we can rebuild the request on each retry.
         let body = body.as_ref();
         let mut attempt = 0u32;
         let rsp = loop {
-            let req = self.build_req(method.clone(), path, body)?;
+            let req = self.build_req_inner(method.clone(), path, body, sensitive)?;
             match self.client.execute(req).await {
                 Ok(rsp) => break rsp,
                 Err(e) if is_stale_connection_error(&e) && attempt < STALE_CONNECTION_RETRIES => {
@@ -219,6 +272,7 @@ impl JsonApi {
         };
 
         let status = rsp.status();
+        let retry_after = retry_after_header(rsp.headers());
         let text = rsp.text().await.map_err(|e| OpError::Fatal(anyhow!(e)))?;
         #[cfg(debug_assertions)]
         debug!("<< {}", text);
@@ -229,14 +283,36 @@ impl JsonApi {
                     op_fatal!("Failed to parse JSON from {}: {} {}", path, text, e);
                 }
             }
-        } else if is_retryable_status(status) && !is_missing_resource_body(&text) {
-            op_transient!("{} {}: {}: {}", method, path, status, &text);
         } else {
-            // Definitive client errors (404/401/403/400 ...) must not be retried:
-            // e.g. a 404 means the resource really is gone, not a transient outage.
-            // Proxmox reports a missing config as a 5xx with a "does not exist"
-            // body — that is definitive too, so treat it as fatal (not-found).
-            op_fatal!("{} {}: {}: {}", method, path, status, &text);
+            Err(self.error_response(&method, path, status, retry_after, &text))
+        }
+    }
+
+    /// Classify a failed response as transient or fatal.
+    ///
+    /// Definitive client errors (404/401/403/400 ...) must not be retried:
+    /// e.g. a 404 means the resource really is gone, not a transient outage.
+    /// Proxmox reports a missing config as a 5xx with a "does not exist" body —
+    /// that is definitive too, so treat it as fatal (not-found). Rate limit
+    /// responses are transient and carry the delay to wait before retrying.
+    fn error_response(
+        &self,
+        method: &Method,
+        path: &str,
+        status: StatusCode,
+        retry_after: Option<Duration>,
+        text: &str,
+    ) -> OpError<anyhow::Error> {
+        let err = anyhow::Error::new(HttpStatusError {
+            status,
+            message: format!("{} {}: {}: {}", method, path, status, text),
+        });
+        if let Some(delay) = rate_limit_delay(self.rate_limit, status, retry_after, text) {
+            OpError::Transient(anyhow::Error::new(RetryAfter::new(delay, err)))
+        } else if is_retryable_status(status) && !is_missing_resource_body(text) {
+            OpError::Transient(err)
+        } else {
+            OpError::Fatal(err)
         }
     }
 
@@ -280,6 +356,7 @@ impl JsonApi {
         };
 
         let status = rsp.status();
+        let retry_after = retry_after_header(rsp.headers());
         let text = rsp
             .text()
             .await
@@ -288,14 +365,73 @@ impl JsonApi {
         debug!("<< {}", text);
         if status.is_success() {
             Ok(status.as_u16())
-        } else if is_retryable_status(status) && !is_missing_resource_body(&text) {
-            op_transient!("{} {}: {}: {}", method, path, status, &text);
         } else {
-            op_fatal!("{} {}: {}: {}", method, path, status, &text);
+            Err(self.error_response(&method, path, status, retry_after, &text))
         }
     }
 }
 
+/// A non-success HTTP response, read the status back with [response_status]
+#[derive(Debug)]
+pub struct HttpStatusError {
+    pub status: StatusCode,
+    message: String,
+}
+
+impl std::fmt::Display for HttpStatusError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl Error for HttpStatusError {}
+
+/// The HTTP status of a failed [JsonApi] response, `None` if the request
+/// failed before a response was received
+pub fn response_status(err: &anyhow::Error) -> Option<StatusCode> {
+    err.chain()
+        .find_map(|e| e.downcast_ref::<HttpStatusError>())
+        .map(|e| e.status)
+}
+
+/// The delay before retrying a rate limited response, `None` if the response
+/// is not a rate limit.
+///
+/// A `Retry-After` header is honoured on 429/503 responses, otherwise the
+/// vendor parser decides.
+fn rate_limit_delay(
+    parser: Option<RateLimitParser>,
+    status: StatusCode,
+    retry_after: Option<Duration>,
+    text: &str,
+) -> Option<Duration> {
+    let vendor = parser.and_then(|p| p(status, text));
+    let is_limit_status = matches!(
+        status,
+        StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE
+    );
+    if vendor.is_some() || is_limit_status {
+        retry_after.or(vendor)
+    } else {
+        None
+    }
+}
+
+fn retry_after_header(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?;
+    parse_retry_after(value, Utc::now())
+}
+
+/// Parse a `Retry-After` value, either delay seconds or an HTTP date
+fn parse_retry_after(value: &str, now: DateTime<Utc>) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let at = DateTime::parse_from_rfc2822(value).ok()?;
+    Some((at.with_timezone(&Utc) - now).to_std().unwrap_or_default())
+}
+
 /// HTTP status codes that are worth retrying: server errors (5xx) plus the two
 /// retryable client errors (408 Request Timeout, 429 Too Many Requests). All
 /// other 4xx codes are definitive and should be treated as fatal.
@@ -323,10 +459,13 @@ fn is_missing_resource_body(text: &str) -> bool {
 #[cfg(test)]
 mod tests {
     use super::{
-        is_missing_resource_body, is_not_found_error, is_retryable_status,
-        is_stale_connection_message,
+        JsonApi, is_missing_resource_body, is_not_found_error, is_retryable_status,
+        is_stale_connection_message, parse_retry_after, response_status,
     };
-    use reqwest::StatusCode;
+    use crate::retry::{OpError, RetryHint};
+    use chrono::{TimeZone, Utc};
+    use reqwest::{Method, StatusCode};
+    use std::time::Duration;
 
     #[test]
     fn only_5xx_408_429_are_retryable() {
@@ -393,4 +532,91 @@ mod tests {
         assert!(!is_stale_connection_message("invalid status code: 500"));
         assert!(!is_stale_connection_message(""));
     }
+
+    #[test]
+    fn parses_retry_after_seconds_and_date() {
+        let now = Utc.with_ymd_and_hms(2015, 10, 21, 7, 28, 0).unwrap();
+        assert_eq!(
+            parse_retry_after("120", now),
+            Some(Duration::from_secs(120))
+        );
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:30 GMT", now),
+            Some(Duration::from_secs(30))
+        );
+        // a date in the past means retry now
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:00:00 GMT", now),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(parse_retry_after("soon", now), None);
+    }
+
+    #[test]
+    fn rate_limits_are_transient_with_delay() {
+        fn vendor(status: StatusCode, body: &str) -> Option<Duration> {
+            (status == StatusCode::FORBIDDEN && body.contains("rate"))
+                .then_some(Duration::from_secs(7))
+        }
+        let api = JsonApi::new("http://localhost")
+            .unwrap()
+            .with_rate_limit_parser(vendor);
+
+        // Retry-After wins over the vendor delay
+        let e = api.error_response(
+            &Method::GET,
+            "/x",
+            StatusCode::TOO_MANY_REQUESTS,
+            Some(Duration::from_secs(3)),
+            "",
+        );
+        assert!(e.is_transient());
+        assert_eq!(e.inner().retry_after(), Some(Duration::from_secs(3)));
+
+        // a vendor rate limit on a status which is otherwise fatal
+        let e = api.error_response(&Method::GET, "/x", StatusCode::FORBIDDEN, None, "rate");
+        assert!(e.is_transient());
+        assert_eq!(e.inner().retry_after(), Some(Duration::from_secs(7)));
+
+        // plain 5xx keeps the policy backoff
+        let e = api.error_response(&Method::GET, "/x", StatusCode::BAD_GATEWAY, None, "");
+        assert!(e.is_transient());
+        assert_eq!(e.inner().retry_after(), None);
+
+        // Retry-After on a non rate limit status is ignored
+        let e = api.error_response(
+            &Method::GET,
+            "/x",
+            StatusCode::NOT_FOUND,
+            Some(Duration::from_secs(3)),
+            "",
+        );
+        assert!(matches!(e, OpError::Fatal(_)));
+        assert!(is_not_found_error(e.inner()));
+    }
+
+    #[test]
+    fn test_response_status() {
+        let api = JsonApi::new("http://localhost").unwrap();
+        let e = api.error_response(&Method::GET, "/x", StatusCode::UNAUTHORIZED, None, "");
+        assert_eq!(response_status(e.inner()), Some(StatusCode::UNAUTHORIZED));
+
+        // the status survives the rate limit wrapper
+        let e = api.error_response(&Method::GET, "/x", StatusCode::TOO_MANY_REQUESTS, None, "");
+        assert_eq!(
+            response_status(e.inner()),
+            Some(StatusCode::TOO_MANY_REQUESTS)
+        );
+
+        // a body mentioning 401 is not a 401
+        let e = api.error_response(
+            &Method::GET,
+            "/x",
+            StatusCode::BAD_REQUEST,
+            None,
+            "401 Unauthorized",
+        );
+        assert_eq!(response_status(e.inner()), Some(StatusCode::BAD_REQUEST));
+        assert_eq!(response_status(&anyhow::anyhow!("401 Unauthorized")), None);
+    }
 }
@@ -1,8 +1,11 @@
 mod capacity;
 mod client_ip;
+mod cors;
 mod dns;
 mod exchange;
+mod feature_flags;
 mod geoip;
+mod invoice;
 mod json_api;
 mod kv;
 mod mock;
@@ -21,11 +24,17 @@ mod vat;
 mod vm_history;
 mod work;
 
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
 pub use capacity::*;
+use chrono::{DateTime, Utc};
 pub use client_ip::*;
+pub use cors::*;
 pub use dns::*;
 pub use exchange::*;
+pub use feature_flags::*;
 pub use geoip::*;
+pub use invoice::*;
 pub use json_api::*;
 pub use kv::*;
 pub use mock::*;
@@ -58,6 +67,62 @@ pub struct PageQuery {
     pub limit: Option<u64>,
     #[serde(deserialize_with = "deserialize_from_str_optional")]
     pub offset: Option<u64>,
+    /// Opaque [PageCursor] returned as `next` by the previous page
+    pub after: Option<String>,
+}
+
+/// Position of the last row of a page, ordered by (timestamp, id) descending.
+///
+/// Sent to clients as an opaque string, clients must not rely on its format.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PageCursor {
+    pub timestamp: DateTime<Utc>,
+    pub id: Vec<u8>,
+}
+
+impl PageCursor {
+    pub fn new(timestamp: DateTime<Utc>, id: Vec<u8>) -> Self {
+        Self { timestamp, id }
+    }
+
+    pub fn from_u64(timestamp: DateTime<Utc>, id: u64) -> Self {
+        Self::new(timestamp, id.to_be_bytes().to_vec())
+    }
+
+    /// Read the id back as a numeric row id
+    pub fn id_u64(&self) -> anyhow::Result<u64> {
+        let bytes: [u8; 8] = self
+            .id
+            .as_slice()
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("Invalid cursor"))?;
+        Ok(u64::from_be_bytes(bytes))
+    }
+
+    pub fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(format!(
+            "{}:{}",
+            self.timestamp.timestamp_micros(),
+            hex::encode(&self.id)
+        ))
+    }
+
+    pub fn decode(cursor: &str) -> anyhow::Result<Self> {
+        let raw = URL_SAFE_NO_PAD
+            .decode(cursor)
+            .map_err(|_| anyhow::anyhow!("Invalid cursor"))?;
+        let raw = String::from_utf8(raw).map_err(|_| anyhow::anyhow!("Invalid cursor"))?;
+        let (ts, id) = raw
+            .split_once(':')
+            .ok_or_else(|| anyhow::anyhow!("Invalid cursor"))?;
+        let timestamp = ts
+            .parse::<i64>()
+            .ok()
+            .and_then(DateTime::from_timestamp_micros)
+            .ok_or_else(|| anyhow::anyhow!("Invalid cursor"))?;
+        let id = hex::decode(id).map_err(|_| anyhow::anyhow!("Invalid cursor"))?;
+        Ok(Self { timestamp, id })
+    }
 }
 
 /// Deserialize an optional value from either a string or the actual type
@@ -1,25 +1,28 @@
 use crate::{ExchangeRateService, Ticker, TickerRate};
 use anyhow::{Context, anyhow};
-use chrono::{Days, Months, TimeDelta, Utc};
+use chrono::{DateTime, Datelike, Days, Months, TimeDelta, Utc};
 use lnvps_db::nostr::LNVPSNostrDb;
 use lnvps_db::{
-    AccessPolicy, App, AppCluster, AppDeployment, AppDeploymentDesiredState, AppDeploymentStatus,
-    AsnSubscription, AsnSubscriptionStatus, AvailableIpSpace, Company, CpuArch, CpuMfg, DbError,
-    DbResult, DiskInterface, DiskType, DnsServer, DnsServerKind, IntervalType, IpRange,
-    IpRangeAllocationMode, IpRangeSubscription, IpSpacePricing, LNVpsDbBase, NostrDomain,
-    NostrDomainHandle, OsDistribution, PaymentMethod, PaymentMethodConfig, Referral,
-    ReferralCostUsage, ReferralPayout, Region, Router, RouterBgpRoute, RouterBgpSession,
-    RouterTunnel, RouterTunnelTraffic, Subscription, SubscriptionLineItem, SubscriptionPayment,
-    SubscriptionPaymentWithCompany, User, UserPaymentMethod, UserSshKey, Vm, VmCostPlan,
-    VmCustomPricing, VmCustomPricingDisk, VmCustomTemplate, VmFirewallPolicy, VmFirewallRule,
-    VmHistory, VmHost, VmHostDisk, VmHostKind, VmIpAssignment, VmOsImage, VmTemplate,
-    WebauthnCredential,
+    AccessPolicy, AdminAuditLog, App, AppCluster, AppDeployment, AppDeploymentDesiredState,
+    AppDeploymentStatus, AsnSubscription, AsnSubscriptionStatus, AvailableIpSpace,
+    BulkMessageDelivery, CapacityReservation, Company, CpuArch, CpuMfg, DbError, DbResult,
+    DiskInterface, DiskType, DnsServer, DnsServerKind, EncryptedString, FeatureFlag, Incident,
+    IntervalType, IpRange, IpRangeAllocationMode, IpRangeSubscription, IpSpacePricing, LNVpsDbBase,
+    NostrDomain, NostrDomainHandle, OsDistribution, OsImageKind, PaymentMethod,
+    PaymentMethodConfig, Referral, ReferralCostUsage, ReferralPayout, Region, Router,
+    RouterBgpRoute, RouterBgpSession, RouterTunnel, RouterTunnelTraffic, Subscription,
+    SubscriptionLineItem, SubscriptionPayment, SubscriptionPaymentWithCompany, SystemNotice, User,
+    UserPaymentMethod, UserSshKey, Vm, VmBackup, VmBackupSchedule, VmBandwidthUsage, VmCostPlan,
+    VmCreateRequest, VmCustomPricing, VmCustomPricingDisk, VmCustomTemplate, VmExtraDisk,
+    VmFirewallPolicy, VmFirewallRule, VmHistory, VmHost, VmHostDisk, VmHostKind, VmIpAssignment,
+    VmOsImage, VmState, VmStateKind, VmTemplate, VmUsageAlert, WebauthnCredential, WorkQueueItem,
+    WorkQueueStatus,
 };
 
 use async_trait::async_trait;
 #[cfg(feature = "admin")]
 use lnvps_db::{AdminRole, AdminRoleAssignment, AdminUserInfo, AdminVmHost, RegionStats};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::ops::Add;
 use std::sync::Arc;
 use tokio::sync::Mutex;
@@ -34,8 +37,12 @@ pub struct MockDb {
     pub user_payment_methods: Arc<Mutex<HashMap<u64, UserPaymentMethod>>>,
     pub cost_plans: Arc<Mutex<HashMap<u64, VmCostPlan>>>,
     pub os_images: Arc<Mutex<HashMap<u64, VmOsImage>>>,
+    /// OS image allowlist keyed by region id
+    pub region_os_images: Arc<Mutex<HashMap<u64, Vec<u64>>>>,
     pub templates: Arc<Mutex<HashMap<u64, VmTemplate>>>,
     pub vms: Arc<Mutex<HashMap<u64, Vm>>>,
+    /// Last running state of each VM, keyed by VM id
+    pub vm_states: Arc<Mutex<HashMap<u64, VmState>>>,
     pub ip_range: Arc<Mutex<HashMap<u64, IpRange>>>,
     pub ip_assignments: Arc<Mutex<HashMap<u64, VmIpAssignment>>>,
     pub custom_pricing: Arc<Mutex<HashMap<u64, VmCustomPricing>>>,
@@ -49,6 +56,10 @@ pub struct MockDb {
     pub subscriptions: Arc<Mutex<HashMap<u64, Subscription>>>,
     pub subscription_line_items: Arc<Mutex<HashMap<u64, SubscriptionLineItem>>>,
     pub subscription_payments: Arc<Mutex<Vec<SubscriptionPayment>>>,
+    /// Last invoice number per (company, year)
+    pub invoice_counters: Arc<Mutex<HashMap<(u64, u64), u64>>>,
+    /// Ids of payments canceled by `cancel_subscription_payment`
+    pub canceled_payments: Arc<Mutex<HashSet<Vec<u8>>>>,
     pub ip_range_subscriptions: Arc<Mutex<HashMap<u64, IpRangeSubscription>>>,
     pub available_ip_space: Arc<Mutex<HashMap<u64, AvailableIpSpace>>>,
     pub asn_subscriptions: Arc<Mutex<HashMap<u64, AsnSubscription>>>,
@@ -60,10 +71,42 @@ pub struct MockDb {
     pub router_bgp_sessions: Arc<Mutex<HashMap<u64, RouterBgpSession>>>,
     pub router_bgp_routes: Arc<Mutex<HashMap<u64, RouterBgpRoute>>>,
     pub firewall_rules: Arc<Mutex<HashMap<u64, VmFirewallRule>>>,
+    pub usage_alerts: Arc<Mutex<HashMap<u64, VmUsageAlert>>>,
+    /// Backup schedules keyed by vm id
+    pub backup_schedules: Arc<Mutex<HashMap<u64, VmBackupSchedule>>>,
+    pub backups: Arc<Mutex<HashMap<u64, VmBackup>>>,
+    /// Monthly transfer keyed by (vm_id, month)
+    pub bandwidth_usage: Arc<Mutex<HashMap<(u64, DateTime<Utc>), VmBandwidthUsage>>>,
+    pub nostr_domains: Arc<Mutex<HashMap<u64, NostrDomain>>>,
+    pub nostr_handles: Arc<Mutex<HashMap<u64, NostrDomainHandle>>>,
+    pub extra_disks: Arc<Mutex<HashMap<u64, VmExtraDisk>>>,
+    pub capacity_reservations: Arc<Mutex<HashMap<u64, CapacityReservation>>>,
+    /// Stands in for the database placement lock
+    pub placement_lock: Arc<Mutex<()>>,
+    /// Account credit keyed by (user_id, currency)
+    pub user_credit: Arc<Mutex<HashMap<(u64, String), u64>>>,
+    /// Claimed auto-renewal periods as (subscription_id, period_expires)
+    pub auto_renewals: Arc<Mutex<Vec<(u64, DateTime<Utc>)>>>,
     pub webauthn_credentials: Arc<Mutex<HashMap<u64, WebauthnCredential>>>,
     pub apps: Arc<Mutex<HashMap<u64, App>>>,
     pub app_clusters: Arc<Mutex<HashMap<u64, AppCluster>>>,
     pub app_deployments: Arc<Mutex<HashMap<u64, AppDeployment>>>,
+    pub admin_audit_log: Arc<Mutex<Vec<AdminAuditLog>>>,
+    pub bulk_message_deliveries: Arc<Mutex<Vec<BulkMessageDelivery>>>,
+    pub system_notices: Arc<Mutex<HashMap<u64, SystemNotice>>>,
+    pub feature_flags: Arc<Mutex<HashMap<u64, FeatureFlag>>>,
+    pub incidents: Arc<Mutex<HashMap<u64, Incident>>>,
+    pub work_queue: Arc<Mutex<HashMap<u64, WorkQueueItem>>>,
+    /// Last poll of the work queue by consumer
+    pub work_queue_polls: Arc<Mutex<HashMap<String, DateTime<Utc>>>>,
+    pub vm_create_requests: Arc<Mutex<Vec<VmCreateRequest>>>,
+    /// Recorded startup data migration state by name
+    pub data_migration_state: Arc<Mutex<HashMap<String, String>>>,
+    /// Simulate the database being unreachable, makes [`LNVpsDbBase::ping`] fail
+    pub offline: Arc<Mutex<bool>>,
+    /// Simulate a failing insert, [`LNVpsDbBase::insert_vm_ip_assignment`]
+    /// fails once this many live assignments exist
+    pub ip_assignment_insert_limit: Arc<Mutex<Option<usize>>>,
 }
 
 impl MockDb {
@@ -73,6 +116,35 @@ impl MockDb {
         }
     }
 
+    /// Insert a VM while the caller holds the `vms` lock
+    async fn insert_vm_locked(&self, vms: &mut HashMap<u64, Vm>, vm: &Vm) -> DbResult<u64> {
+        let max_id = *vms.keys().max().unwrap_or(&0);
+
+        // lazy test FK
+        self.get_host(vm.host_id).await?;
+        self.get_user(vm.user_id).await?;
+        self.get_os_image(vm.image_id).await?;
+        if let Some(t) = vm.template_id {
+            self.get_vm_template(t).await?;
+        }
+        if let Some(t) = vm.custom_template_id {
+            self.get_custom_vm_template(t).await?;
+        }
+        if let Some(k) = vm.ssh_key_id {
+            self.get_user_ssh_key(k).await?;
+        }
+        self.get_host_disk(vm.disk_id).await?;
+
+        vms.insert(
+            max_id + 1,
+            Vm {
+                id: max_id + 1,
+                ..vm.clone()
+            },
+        );
+        Ok(max_id + 1)
+    }
+
     pub fn mock_cost_plan() -> VmCostPlan {
         VmCostPlan {
             id: 1,
@@ -109,6 +181,9 @@ impl MockDb {
             network_mbps: None,
             cpu_limit: None,
             firewall_rule_limit: None,
+            backup_retention: 0,
+            max_per_user: None,
+            bandwidth_quota: None,
         }
     }
 
@@ -131,6 +206,8 @@ impl MockDb {
             fw_policy_in: None,
             fw_policy_out: None,
             admin_notes: None,
+            private_network: false,
+            suspended_at: None,
         }
     }
 }
@@ -145,6 +222,9 @@ impl Default for MockDb {
                 name: "Mock".to_string(),
                 enabled: true,
                 company_id: 1, // Link to default company
+                maintenance: false,
+                reachability_check: false,
+                forward_dns_suffix: None,
             },
         );
         // Default mock DNS server (forward records via the shared MockDnsServer).
@@ -210,9 +290,12 @@ impl Default for MockDb {
                 load_disk: 3.0,
                 vlan_id: Some(100),
                 mtu: None,
+                private_bridge: None,
                 ssh_user: None,
                 ssh_key: None,
                 sunset_date: None,
+                maintenance: false,
+                weight: 1,
             },
         );
         let mut host_disks = HashMap::new();
@@ -247,6 +330,7 @@ impl Default for MockDb {
                 default_username: None,
                 sha2: None,
                 sha2_url: None,
+                image_kind: OsImageKind::CloudInit,
             },
         );
         Self {
@@ -257,8 +341,10 @@ impl Default for MockDb {
             cost_plans: Arc::new(Mutex::new(cost_plans)),
             templates: Arc::new(Mutex::new(templates)),
             os_images: Arc::new(Mutex::new(os_images)),
+            region_os_images: Arc::new(Default::default()),
             users: Arc::new(Default::default()),
             vms: Arc::new(Default::default()),
+            vm_states: Arc::new(Default::default()),
             ip_assignments: Arc::new(Default::default()),
             custom_pricing: Arc::new(Default::default()),
             custom_pricing_disk: Arc::new(Default::default()),
@@ -288,6 +374,7 @@ impl Default for MockDb {
                         base_currency: "EUR".to_string(),
                         referral_rate: 0.0,
                         max_prepay_days: 0,
+                        tax_display: Default::default(),
                     },
                 );
                 companies
@@ -335,6 +422,8 @@ impl Default for MockDb {
                 m
             })),
             subscription_payments: Arc::new(Default::default()),
+            invoice_counters: Arc::new(Default::default()),
+            canceled_payments: Arc::new(Default::default()),
             ip_range_subscriptions: Arc::new(Default::default()),
             available_ip_space: Arc::new(Default::default()),
             asn_subscriptions: Arc::new(Default::default()),
@@ -346,10 +435,32 @@ impl Default for MockDb {
             router_bgp_sessions: Arc::new(Default::default()),
             router_bgp_routes: Arc::new(Default::default()),
             firewall_rules: Arc::new(Default::default()),
+            usage_alerts: Arc::new(Default::default()),
+            backup_schedules: Arc::new(Default::default()),
+            backups: Arc::new(Default::default()),
+            bandwidth_usage: Arc::new(Default::default()),
+            nostr_domains: Arc::new(Default::default()),
+            nostr_handles: Arc::new(Default::default()),
+            extra_disks: Arc::new(Default::default()),
+            capacity_reservations: Arc::new(Default::default()),
+            placement_lock: Arc::new(Default::default()),
+            user_credit: Arc::new(Default::default()),
+            auto_renewals: Arc::new(Default::default()),
             webauthn_credentials: Arc::new(Default::default()),
             apps: Arc::new(Default::default()),
             app_clusters: Arc::new(Default::default()),
             app_deployments: Arc::new(Default::default()),
+            admin_audit_log: Arc::new(Default::default()),
+            bulk_message_deliveries: Arc::new(Default::default()),
+            system_notices: Arc::new(Default::default()),
+            feature_flags: Arc::new(Default::default()),
+            incidents: Arc::new(Default::default()),
+            work_queue: Arc::new(Default::default()),
+            work_queue_polls: Arc::new(Default::default()),
+            vm_create_requests: Arc::new(Default::default()),
+            data_migration_state: Arc::new(Default::default()),
+            offline: Arc::new(Default::default()),
+            ip_assignment_insert_limit: Arc::new(Default::default()),
         }
     }
 }
@@ -360,6 +471,13 @@ impl LNVpsDbBase for MockDb {
         Ok(())
     }
 
+    async fn ping(&self) -> DbResult<()> {
+        if *self.offline.lock().await {
+            return Err(DbError::Other(anyhow!("database is offline")));
+        }
+        Ok(())
+    }
+
     async fn upsert_user(&self, pubkey: &[u8; 32]) -> DbResult<u64> {
         let mut users = self.users.lock().await;
         if let Some(e) = users.iter().find(|(_k, u)| u.pubkey == *pubkey) {
@@ -468,7 +586,7 @@ impl LNVpsDbBase for MockDb {
 
     async fn get_user(&self, id: u64) -> DbResult<User> {
         let users = self.users.lock().await;
-        Ok(users.get(&id).ok_or(anyhow!("no user"))?.clone())
+        users.get(&id).cloned().ok_or_else(DbError::row_not_found)
     }
 
     async fn update_user(&self, user: &User) -> DbResult<()> {
@@ -495,9 +613,12 @@ impl LNVpsDbBase for MockDb {
             u.billing_state = user.billing_state.clone();
             u.billing_postcode = user.billing_postcode.clone();
             u.billing_tax_id = user.billing_tax_id.clone();
+            u.billing_tax_id_validated = user.billing_tax_id_validated;
+            u.billing_tax_id_name = user.billing_tax_id_name.clone();
             u.geo_country_code = user.geo_country_code.clone();
             u.geo_ip = user.geo_ip.clone();
             u.geo_updated = user.geo_updated;
+            u.placement_policy = user.placement_policy;
         }
         Ok(())
     }
@@ -551,6 +672,18 @@ impl LNVpsDbBase for MockDb {
             .lock()
             .await
             .retain(|_, r| !user_vm_ids.contains(&r.vm_id));
+        self.usage_alerts
+            .lock()
+            .await
+            .retain(|_, a| !user_vm_ids.contains(&a.vm_id));
+        self.backup_schedules
+            .lock()
+            .await
+            .retain(|vm_id, _| !user_vm_ids.contains(vm_id));
+        self.backups
+            .lock()
+            .await
+            .retain(|_, b| !user_vm_ids.contains(&b.vm_id));
         self.vm_history
             .lock()
             .await
@@ -839,6 +972,17 @@ impl LNVpsDbBase for MockDb {
         Ok(())
     }
 
+    async fn update_host_api_token(
+        &self,
+        host_id: u64,
+        api_token: &EncryptedString,
+    ) -> DbResult<()> {
+        let mut hosts = self.hosts.lock().await;
+        let host = hosts.get_mut(&host_id).ok_or(anyhow!("no host"))?;
+        host.api_token = api_token.clone();
+        Ok(())
+    }
+
     async fn create_host(&self, host: &VmHost) -> DbResult<u64> {
         let mut hosts = self.hosts.lock().await;
         let id = (hosts.len() as u64) + 1;
@@ -894,6 +1038,15 @@ impl LNVpsDbBase for MockDb {
         Ok(os_images.values().filter(|i| i.enabled).cloned().collect())
     }
 
+    async fn list_os_image_by_kind(&self, kind: OsImageKind) -> DbResult<Vec<VmOsImage>> {
+        let os_images = self.os_images.lock().await;
+        Ok(os_images
+            .values()
+            .filter(|i| i.enabled && i.image_kind == kind)
+            .cloned()
+            .collect())
+    }
+
     async fn count_vms_by_os_image(&self) -> DbResult<Vec<(u64, u64)>> {
         let vms = self.vms.lock().await;
         let mut counts: HashMap<u64, u64> = HashMap::new();
@@ -903,6 +1056,24 @@ impl LNVpsDbBase for MockDb {
         Ok(counts.into_iter().collect())
     }
 
+    async fn list_region_os_images(&self, region_id: u64) -> DbResult<Vec<u64>> {
+        let allowed = self.region_os_images.lock().await;
+        Ok(allowed.get(&region_id).cloned().unwrap_or_default())
+    }
+
+    async fn set_region_os_images(&self, region_id: u64, image_ids: &[u64]) -> DbResult<()> {
+        let mut allowed = self.region_os_images.lock().await;
+        if image_ids.is_empty() {
+            allowed.remove(&region_id);
+        } else {
+            let mut ids = image_ids.to_vec();
+            ids.sort();
+            ids.dedup();
+            allowed.insert(region_id, ids);
+        }
+        Ok(())
+    }
+
     async fn update_os_image(&self, image: &VmOsImage) -> DbResult<()> {
         let mut os_images = self.os_images.lock().await;
         os_images.insert(image.id, image.clone());
@@ -1054,6 +1225,25 @@ impl LNVpsDbBase for MockDb {
         Ok(expired)
     }
 
+    async fn list_vms_expired_before(&self, before: DateTime<Utc>) -> DbResult<Vec<Vm>> {
+        let vms = self.vms.lock().await;
+        let line_items = self.subscription_line_items.lock().await;
+        let subs = self.subscriptions.lock().await;
+        Ok(vms
+            .values()
+            .filter(|v| !v.deleted)
+            .filter(|v| {
+                line_items
+                    .get(&v.subscription_line_item_id)
+                    .and_then(|li| subs.get(&li.subscription_id))
+                    .and_then(|s| s.expires)
+                    .map(|e| e < before)
+                    .unwrap_or(false)
+            })
+            .cloned()
+            .collect())
+    }
+
     async fn list_active_vms(&self) -> DbResult<Vec<Vm>> {
         // Active VMs: non-deleted whose subscription has been set up (paid at
         // least once), regardless of current expiry (expired VMs included).
@@ -1081,6 +1271,32 @@ impl LNVpsDbBase for MockDb {
         Ok(active)
     }
 
+    async fn list_unpaid_vms(&self, grace: TimeDelta) -> DbResult<Vec<Vm>> {
+        let cutoff = Utc::now() - grace;
+        let vms = self.vms.lock().await;
+        let line_items = self.subscription_line_items.lock().await;
+        let subs = self.subscriptions.lock().await;
+        let payments = self.subscription_payments.lock().await;
+        Ok(vms
+            .values()
+            .filter(|v| !v.deleted)
+            .filter(|v| {
+                let Some(sub) = line_items
+                    .get(&v.subscription_line_item_id)
+                    .and_then(|li| subs.get(&li.subscription_id))
+                else {
+                    return false;
+                };
+                !sub.is_setup
+                    && sub.created < cutoff
+                    && !payments
+                        .iter()
+                        .any(|p| p.subscription_id == sub.id && p.is_paid)
+            })
+            .cloned()
+            .collect())
+    }
+
     async fn list_user_vms(&self, id: u64) -> DbResult<Vec<Vm>> {
         let vms = self.vms.lock().await;
         Ok(vms
@@ -1092,36 +1308,24 @@ impl LNVpsDbBase for MockDb {
 
     async fn get_vm(&self, vm_id: u64) -> DbResult<Vm> {
         let vms = self.vms.lock().await;
-        Ok(vms.get(&vm_id).ok_or(anyhow!("no vm"))?.clone())
+        vms.get(&vm_id).cloned().ok_or_else(DbError::row_not_found)
     }
 
     async fn insert_vm(&self, vm: &Vm) -> DbResult<u64> {
         let mut vms = self.vms.lock().await;
-        let max_id = *vms.keys().max().unwrap_or(&0);
+        self.insert_vm_locked(&mut vms, vm).await
+    }
 
-        // lazy test FK
-        self.get_host(vm.host_id).await?;
-        self.get_user(vm.user_id).await?;
-        self.get_os_image(vm.image_id).await?;
-        if let Some(t) = vm.template_id {
-            self.get_vm_template(t).await?;
-        }
-        if let Some(t) = vm.custom_template_id {
-            self.get_custom_vm_template(t).await?;
-        }
-        if let Some(k) = vm.ssh_key_id {
-            self.get_user_ssh_key(k).await?;
+    async fn insert_vm_within_template_limit(&self, vm: &Vm, limit: u16) -> DbResult<Option<u64>> {
+        let mut vms = self.vms.lock().await;
+        let active = vms
+            .values()
+            .filter(|v| !v.deleted && v.user_id == vm.user_id && v.template_id == vm.template_id)
+            .count();
+        if active >= limit as usize {
+            return Ok(None);
         }
-        self.get_host_disk(vm.disk_id).await?;
-
-        vms.insert(
-            max_id + 1,
-            Vm {
-                id: max_id + 1,
-                ..vm.clone()
-            },
-        );
-        Ok(max_id + 1)
+        Ok(Some(self.insert_vm_locked(&mut vms, vm).await?))
     }
 
     async fn insert_vm_with_id(&self, vm: &Vm) -> DbResult<u64> {
@@ -1154,6 +1358,35 @@ impl LNVpsDbBase for MockDb {
         Ok(vm.id)
     }
 
+    async fn import_vm(
+        &self,
+        vm: &Vm,
+        subscription: &Subscription,
+        line_item: &SubscriptionLineItem,
+        custom_template: Option<&VmCustomTemplate>,
+        ip_assignments: &[VmIpAssignment],
+    ) -> DbResult<Vm> {
+        // check the id up front, the inserts below aren't rolled back
+        if self.vms.lock().await.contains_key(&vm.id) {
+            return Err(DbError::from(anyhow!("VM id {} already exists", vm.id)));
+        }
+        let mut vm = vm.clone();
+        if let Some(template) = custom_template {
+            vm.custom_template_id = Some(self.insert_custom_vm_template(template).await?);
+        }
+        let (_, line_item_ids) = self
+            .insert_subscription_with_line_items(subscription, vec![line_item.clone()])
+            .await?;
+        vm.subscription_line_item_id = line_item_ids[0];
+        self.insert_vm_with_id(&vm).await?;
+        for ip in ip_assignments {
+            let mut ip = ip.clone();
+            ip.vm_id = vm.id;
+            self.insert_vm_ip_assignment(&ip).await?;
+        }
+        Ok(vm)
+    }
+
     async fn delete_vm(&self, vm_id: u64) -> DbResult<()> {
         let mut vms = self.vms.lock().await;
         if let Some(vm) = vms.get_mut(&vm_id) {
@@ -1179,6 +1412,20 @@ impl LNVpsDbBase for MockDb {
             .lock()
             .await
             .retain(|_, r| r.vm_id != vm_id);
+        self.usage_alerts
+            .lock()
+            .await
+            .retain(|_, a| a.vm_id != vm_id);
+        self.backup_schedules.lock().await.remove(&vm_id);
+        self.backups.lock().await.retain(|_, b| b.vm_id != vm_id);
+        self.vm_create_requests
+            .lock()
+            .await
+            .retain(|r| r.vm_id != Some(vm_id));
+        self.extra_disks
+            .lock()
+            .await
+            .retain(|_, d| d.vm_id != vm_id);
         self.ip_assignments
             .lock()
             .await
@@ -1216,6 +1463,28 @@ impl LNVpsDbBase for MockDb {
             .collect())
     }
 
+    async fn transfer_vm(&self, vm_id: u64, new_user_id: u64) -> DbResult<()> {
+        let line_item_id = {
+            let mut vms = self.vms.lock().await;
+            let vm = vms.get_mut(&vm_id).ok_or(anyhow!("no vm"))?;
+            vm.user_id = new_user_id;
+            vm.ssh_key_id = None;
+            vm.subscription_line_item_id
+        };
+        let sub_id = self
+            .subscription_line_items
+            .lock()
+            .await
+            .get(&line_item_id)
+            .map(|l| l.subscription_id);
+        if let Some(sub) = sub_id
+            && let Some(s) = self.subscriptions.lock().await.get_mut(&sub)
+        {
+            s.user_id = new_user_id;
+        }
+        Ok(())
+    }
+
     async fn update_vm(&self, vm: &Vm) -> DbResult<()> {
         let mut vms = self.vms.lock().await;
         if let Some(v) = vms.get_mut(&vm.id) {
@@ -1227,16 +1496,71 @@ impl LNVpsDbBase for MockDb {
             v.disk_id = vm.disk_id;
             v.mac_address = vm.mac_address.clone();
             v.disabled = vm.disabled;
+            v.private_network = vm.private_network;
+        }
+        Ok(())
+    }
+
+    async fn update_vm_host(&self, vm_id: u64, host_id: u64, disk_id: u64) -> DbResult<()> {
+        if let Some(v) = self.vms.lock().await.get_mut(&vm_id) {
+            v.host_id = host_id;
+            v.disk_id = disk_id;
+        }
+        Ok(())
+    }
+
+    async fn set_vm_suspended_at(&self, vm_id: u64, suspended_at: DateTime<Utc>) -> DbResult<()> {
+        if let Some(v) = self.vms.lock().await.get_mut(&vm_id) {
+            v.suspended_at = Some(suspended_at);
+        }
+        Ok(())
+    }
+
+    async fn take_vm_suspended_at(&self, vm_id: u64) -> DbResult<Option<DateTime<Utc>>> {
+        Ok(self
+            .vms
+            .lock()
+            .await
+            .get_mut(&vm_id)
+            .and_then(|v| v.suspended_at.take()))
+    }
+
+    async fn upsert_vm_state(&self, vm_id: u64, state: VmStateKind) -> DbResult<()> {
+        self.vm_states.lock().await.insert(
+            vm_id,
+            VmState {
+                vm_id,
+                state,
+                last_seen: Utc::now(),
+            },
+        );
+        Ok(())
+    }
+
+    async fn touch_vm_states(&self, vm_ids: &[u64]) -> DbResult<()> {
+        let mut states = self.vm_states.lock().await;
+        for id in vm_ids {
+            if let Some(s) = states.get_mut(id) {
+                s.last_seen = Utc::now();
+            }
         }
         Ok(())
     }
 
+    async fn list_vm_states(&self, vm_ids: &[u64]) -> DbResult<Vec<VmState>> {
+        let states = self.vm_states.lock().await;
+        Ok(vm_ids
+            .iter()
+            .filter_map(|id| states.get(id).cloned())
+            .collect())
+    }
+
     async fn get_vm_by_line_item(&self, line_item_id: u64) -> DbResult<Vm> {
         let vms = self.vms.lock().await;
         vms.values()
             .find(|v| v.subscription_line_item_id == line_item_id && !v.deleted)
             .cloned()
-            .ok_or_else(|| anyhow!("VM not found for line item {}", line_item_id).into())
+            .ok_or_else(DbError::row_not_found)
     }
 
     async fn get_vm_by_subscription(&self, subscription_id: u64) -> DbResult<Vm> {
@@ -1249,12 +1573,7 @@ impl LNVpsDbBase for MockDb {
                     && matches!(li.subscription_type, SubscriptionType::Vps)
             })
             .map(|li| li.id)
-            .ok_or_else(|| {
-                DbError::Other(anyhow!(
-                    "No VM line item for subscription {}",
-                    subscription_id
-                ))
-            })?;
+            .ok_or_else(DbError::row_not_found)?;
         drop(items);
         // Mirror the MySQL impl: unlike get_vm_by_line_item, this does NOT
         // filter deleted VMs (callers such as the on-chain watcher need to
@@ -1263,7 +1582,7 @@ impl LNVpsDbBase for MockDb {
         vms.values()
             .find(|v| v.subscription_line_item_id == line_item_id)
             .cloned()
-            .ok_or_else(|| anyhow!("VM not found for line item {}", line_item_id).into())
+            .ok_or_else(DbError::row_not_found)
     }
 
     async fn list_vm_subscription_payments(
@@ -1307,6 +1626,21 @@ impl LNVpsDbBase for MockDb {
             .collect())
     }
 
+    async fn list_paid_vm_subscription_payments(
+        &self,
+        vm_id: u64,
+    ) -> DbResult<Vec<SubscriptionPayment>> {
+        let created = self.get_vm(vm_id).await?.created;
+        let mut paid: Vec<_> = self
+            .list_vm_subscription_payments(vm_id)
+            .await?
+            .into_iter()
+            .filter(|p| p.is_paid && p.created >= created)
+            .collect();
+        paid.sort_by_key(|p| (p.paid_at.unwrap_or(p.created), p.created));
+        Ok(paid)
+    }
+
     async fn list_vm_subscription_payments_paginated(
         &self,
         vm_id: u64,
@@ -1321,6 +1655,24 @@ impl LNVpsDbBase for MockDb {
             .collect())
     }
 
+    async fn list_vm_subscription_payments_after(
+        &self,
+        vm_id: u64,
+        after: Option<(DateTime<Utc>, Vec<u8>)>,
+        limit: u64,
+    ) -> DbResult<Vec<SubscriptionPayment>> {
+        let mut all = self.list_vm_subscription_payments(vm_id).await?;
+        all.sort_by(|a, b| b.created.cmp(&a.created).then_with(|| b.id.cmp(&a.id)));
+        Ok(all
+            .into_iter()
+            .filter(|p| match &after {
+                Some((created, id)) => (&p.created, &p.id) < (created, id),
+                None => true,
+            })
+            .take(limit as usize)
+            .collect())
+    }
+
     async fn count_vm_subscription_payments(&self, vm_id: u64) -> DbResult<u64> {
         let all = self.list_vm_subscription_payments(vm_id).await?;
         Ok(all.len() as u64)
@@ -1328,6 +1680,11 @@ impl LNVpsDbBase for MockDb {
 
     async fn insert_vm_ip_assignment(&self, ip_assignment: &VmIpAssignment) -> DbResult<u64> {
         let mut ip_assignments = self.ip_assignments.lock().await;
+        if let Some(limit) = *self.ip_assignment_insert_limit.lock().await
+            && ip_assignments.values().filter(|a| !a.deleted).count() >= limit
+        {
+            return Err(DbError::Other(anyhow!("ip assignment insert failed")));
+        }
         let max = *ip_assignments.keys().max().unwrap_or(&0);
         ip_assignments.insert(
             max + 1,
@@ -1347,6 +1704,7 @@ impl LNVpsDbBase for MockDb {
             i.dns_reverse = ip_assignment.dns_reverse.clone();
             i.dns_reverse_ref = ip_assignment.dns_reverse_ref.clone();
             i.dns_forward_ref = ip_assignment.dns_forward_ref.clone();
+            i.route_ref = ip_assignment.route_ref.clone();
         }
         Ok(())
     }
@@ -1360,6 +1718,40 @@ impl LNVpsDbBase for MockDb {
             .collect())
     }
 
+    async fn list_user_ip_assignments(
+        &self,
+        user_id: u64,
+    ) -> DbResult<Vec<(Vm, VmIpAssignment, IpRange)>> {
+        let mut vms: Vec<Vm> = self
+            .vms
+            .lock()
+            .await
+            .values()
+            .filter(|v| v.user_id == user_id && !v.deleted)
+            .cloned()
+            .collect();
+        vms.sort_by_key(|v| v.id);
+        let mut ips: Vec<VmIpAssignment> = self
+            .ip_assignments
+            .lock()
+            .await
+            .values()
+            .filter(|a| !a.deleted)
+            .cloned()
+            .collect();
+        ips.sort_by_key(|a| a.id);
+        let ranges = self.ip_range.lock().await;
+        let mut ret = Vec::new();
+        for vm in vms {
+            for ip in ips.iter().filter(|a| a.vm_id == vm.id) {
+                if let Some(range) = ranges.get(&ip.ip_range_id) {
+                    ret.push((vm.clone(), ip.clone(), range.clone()));
+                }
+            }
+        }
+        Ok(ret)
+    }
+
     async fn list_vm_ip_assignments_in_range(
         &self,
         range_id: u64,
@@ -1398,6 +1790,39 @@ impl LNVpsDbBase for MockDb {
         Ok(())
     }
 
+    async fn find_duplicate_live_ips(&self) -> DbResult<Vec<(String, Vec<u64>)>> {
+        let vms = self.vms.lock().await;
+        let ip_assignments = self.ip_assignments.lock().await;
+        let mut by_ip: HashMap<String, Vec<u64>> = HashMap::new();
+        for a in ip_assignments.values().filter(|a| !a.deleted) {
+            if vms.get(&a.vm_id).map(|v| !v.deleted).unwrap_or(false) {
+                by_ip.entry(a.ip.clone()).or_default().push(a.vm_id);
+            }
+        }
+        let mut ret: Vec<(String, Vec<u64>)> = by_ip
+            .into_iter()
+            .filter_map(|(ip, mut vm_ids)| {
+                vm_ids.sort();
+                vm_ids.dedup();
+                (vm_ids.len() > 1).then_some((ip, vm_ids))
+            })
+            .collect();
+        ret.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(ret)
+    }
+
+    async fn get_data_migration_state(&self, name: &str) -> DbResult<Option<String>> {
+        Ok(self.data_migration_state.lock().await.get(name).cloned())
+    }
+
+    async fn set_data_migration_state(&self, name: &str, value: &str) -> DbResult<()> {
+        self.data_migration_state
+            .lock()
+            .await
+            .insert(name.to_string(), value.to_string());
+        Ok(())
+    }
+
     async fn insert_vm_firewall_rule(&self, rule: &VmFirewallRule) -> DbResult<u64> {
         let mut rules = self.firewall_rules.lock().await;
         let max = *rules.keys().max().unwrap_or(&0);
@@ -1446,36 +1871,403 @@ impl LNVpsDbBase for MockDb {
         Ok(())
     }
 
-    async fn update_vm_firewall_policy(
-        &self,
-        vm_id: u64,
-        policy_in: Option<VmFirewallPolicy>,
-        policy_out: Option<VmFirewallPolicy>,
-    ) -> DbResult<()> {
-        let mut vms = self.vms.lock().await;
-        if let Some(vm) = vms.get_mut(&vm_id) {
-            vm.fw_policy_in = policy_in;
-            vm.fw_policy_out = policy_out;
-        }
-        Ok(())
+    async fn insert_vm_usage_alert(&self, alert: &VmUsageAlert) -> DbResult<u64> {
+        let mut alerts = self.usage_alerts.lock().await;
+        let max = *alerts.keys().max().unwrap_or(&0);
+        let id = max + 1;
+        alerts.insert(
+            id,
+            VmUsageAlert {
+                id,
+                created: Utc::now(),
+                ..alert.clone()
+            },
+        );
+        Ok(id)
     }
 
-    async fn list_custom_pricing(&self, _tb: u64) -> DbResult<Vec<VmCustomPricing>> {
-        let p = self.custom_pricing.lock().await;
-        Ok(p.values().cloned().collect())
+    async fn get_vm_usage_alert(&self, alert_id: u64) -> DbResult<VmUsageAlert> {
+        let alerts = self.usage_alerts.lock().await;
+        alerts
+            .get(&alert_id)
+            .cloned()
+            .ok_or_else(|| DbError::Other(anyhow!("Usage alert not found")))
     }
 
-    async fn list_custom_pricing_paginated(
-        &self,
-        region_id: Option<u64>,
-        enabled: Option<bool>,
-        limit: u64,
-        offset: u64,
-    ) -> DbResult<(Vec<VmCustomPricing>, u64)> {
-        let p = self.custom_pricing.lock().await;
-        let mut all: Vec<_> = p
+    async fn list_vm_usage_alerts(&self, vm_id: u64) -> DbResult<Vec<VmUsageAlert>> {
+        let alerts = self.usage_alerts.lock().await;
+        let mut out: Vec<VmUsageAlert> = alerts
             .values()
-            .filter(|v| region_id.map_or(true, |r| v.region_id == r))
+            .filter(|a| a.vm_id == vm_id)
+            .cloned()
+            .collect();
+        out.sort_by_key(|a| a.id);
+        Ok(out)
+    }
+
+    async fn list_active_vm_usage_alerts(&self) -> DbResult<Vec<VmUsageAlert>> {
+        let vms = self.vms.lock().await;
+        let alerts = self.usage_alerts.lock().await;
+        let mut out: Vec<VmUsageAlert> = alerts
+            .values()
+            .filter(|a| vms.get(&a.vm_id).is_some_and(|v| !v.deleted))
+            .cloned()
+            .collect();
+        out.sort_by_key(|a| (a.vm_id, a.id));
+        Ok(out)
+    }
+
+    async fn update_vm_usage_alert(&self, alert: &VmUsageAlert) -> DbResult<()> {
+        let mut alerts = self.usage_alerts.lock().await;
+        if let Some(a) = alerts.get_mut(&alert.id) {
+            a.metric = alert.metric;
+            a.threshold = alert.threshold;
+            a.window_seconds = alert.window_seconds;
+        }
+        Ok(())
+    }
+
+    async fn set_vm_usage_alert_triggered(
+        &self,
+        alert_id: u64,
+        triggered: DateTime<Utc>,
+    ) -> DbResult<()> {
+        let mut alerts = self.usage_alerts.lock().await;
+        if let Some(a) = alerts.get_mut(&alert_id) {
+            a.last_triggered = Some(triggered);
+        }
+        Ok(())
+    }
+
+    async fn delete_vm_usage_alert(&self, alert_id: u64) -> DbResult<()> {
+        let mut alerts = self.usage_alerts.lock().await;
+        alerts.remove(&alert_id);
+        Ok(())
+    }
+
+    async fn get_vm_backup_schedule(&self, vm_id: u64) -> DbResult<Option<VmBackupSchedule>> {
+        Ok(self.backup_schedules.lock().await.get(&vm_id).cloned())
+    }
+
+    async fn upsert_vm_backup_schedule(&self, schedule: &VmBackupSchedule) -> DbResult<()> {
+        let mut schedules = self.backup_schedules.lock().await;
+        if let Some(s) = schedules.get_mut(&schedule.vm_id) {
+            s.interval_hours = schedule.interval_hours;
+            s.enabled = schedule.enabled;
+        } else {
+            let id = schedules.values().map(|s| s.id).max().unwrap_or(0) + 1;
+            schedules.insert(
+                schedule.vm_id,
+                VmBackupSchedule {
+                    id,
+                    last_run: None,
+                    created: Utc::now(),
+                    ..schedule.clone()
+                },
+            );
+        }
+        Ok(())
+    }
+
+    async fn delete_vm_backup_schedule(&self, vm_id: u64) -> DbResult<()> {
+        self.backup_schedules.lock().await.remove(&vm_id);
+        Ok(())
+    }
+
+    async fn list_active_vm_backup_schedules(&self) -> DbResult<Vec<VmBackupSchedule>> {
+        let vms = self.vms.lock().await;
+        let schedules = self.backup_schedules.lock().await;
+        let mut out: Vec<VmBackupSchedule> = schedules
+            .values()
+            .filter(|s| s.enabled && vms.get(&s.vm_id).is_some_and(|v| !v.deleted))
+            .cloned()
+            .collect();
+        out.sort_by_key(|s| s.vm_id);
+        Ok(out)
+    }
+
+    async fn set_vm_backup_schedule_last_run(
+        &self,
+        vm_id: u64,
+        last_run: DateTime<Utc>,
+    ) -> DbResult<()> {
+        if let Some(s) = self.backup_schedules.lock().await.get_mut(&vm_id) {
+            s.last_run = Some(last_run);
+        }
+        Ok(())
+    }
+
+    async fn insert_vm_backup(&self, backup: &VmBackup) -> DbResult<u64> {
+        let mut backups = self.backups.lock().await;
+        let id = *backups.keys().max().unwrap_or(&0) + 1;
+        backups.insert(
+            id,
+            VmBackup {
+                id,
+                created: Utc::now(),
+                ..backup.clone()
+            },
+        );
+        Ok(id)
+    }
+
+    async fn get_vm_backup(&self, backup_id: u64) -> DbResult<VmBackup> {
+        let backups = self.backups.lock().await;
+        backups
+            .get(&backup_id)
+            .cloned()
+            .ok_or_else(|| DbError::Other(anyhow!("Backup not found")))
+    }
+
+    async fn list_vm_backups(&self, vm_id: u64) -> DbResult<Vec<VmBackup>> {
+        let backups = self.backups.lock().await;
+        let mut out: Vec<VmBackup> = backups
+            .values()
+            .filter(|b| b.vm_id == vm_id)
+            .cloned()
+            .collect();
+        out.sort_by(|a, b| b.created.cmp(&a.created).then(b.id.cmp(&a.id)));
+        Ok(out)
+    }
+
+    async fn delete_vm_backup(&self, backup_id: u64) -> DbResult<()> {
+        self.backups.lock().await.remove(&backup_id);
+        Ok(())
+    }
+
+    async fn get_vm_bandwidth_usage(
+        &self,
+        vm_id: u64,
+        month: DateTime<Utc>,
+    ) -> DbResult<Option<VmBandwidthUsage>> {
+        Ok(self
+            .bandwidth_usage
+            .lock()
+            .await
+            .get(&(vm_id, month))
+            .cloned())
+    }
+
+    async fn get_vm_bandwidth_last_sample(&self, vm_id: u64) -> DbResult<Option<DateTime<Utc>>> {
+        Ok(self
+            .bandwidth_usage
+            .lock()
+            .await
+            .values()
+            .filter(|u| u.vm_id == vm_id)
+            .map(|u| u.last_sample)
+            .max())
+    }
+
+    async fn add_vm_bandwidth_usage(
+        &self,
+        usage: &VmBandwidthUsage,
+        counted_after: DateTime<Utc>,
+    ) -> DbResult<bool> {
+        let mut usages = self.bandwidth_usage.lock().await;
+        match usages.get_mut(&(usage.vm_id, usage.month)) {
+            Some(u) if u.last_sample <= counted_after => {
+                u.bytes_in += usage.bytes_in;
+                u.bytes_out += usage.bytes_out;
+                u.last_sample = usage.last_sample;
+                Ok(true)
+            }
+            Some(_) => Ok(false),
+            None => {
+                usages.insert((usage.vm_id, usage.month), usage.clone());
+                Ok(true)
+            }
+        }
+    }
+
+    async fn insert_vm_create_request(&self, user_id: u64, request_id: &str) -> DbResult<bool> {
+        let mut requests = self.vm_create_requests.lock().await;
+        if requests
+            .iter()
+            .any(|r| r.user_id == user_id && r.request_id == request_id)
+        {
+            return Ok(false);
+        }
+        requests.push(VmCreateRequest {
+            id: requests.len() as u64 + 1,
+            user_id,
+            request_id: request_id.to_string(),
+            vm_id: None,
+            created: Utc::now(),
+        });
+        Ok(true)
+    }
+
+    async fn get_vm_create_request(
+        &self,
+        user_id: u64,
+        request_id: &str,
+    ) -> DbResult<VmCreateRequest> {
+        self.vm_create_requests
+            .lock()
+            .await
+            .iter()
+            .find(|r| r.user_id == user_id && r.request_id == request_id)
+            .cloned()
+            .ok_or_else(|| DbError::Other(anyhow!("no vm create request")))
+    }
+
+    async fn set_vm_create_request_vm(
+        &self,
+        user_id: u64,
+        request_id: &str,
+        vm_id: u64,
+    ) -> DbResult<()> {
+        if let Some(r) = self
+            .vm_create_requests
+            .lock()
+            .await
+            .iter_mut()
+            .find(|r| r.user_id == user_id && r.request_id == request_id)
+        {
+            r.vm_id = Some(vm_id);
+        }
+        Ok(())
+    }
+
+    async fn delete_vm_create_request(&self, user_id: u64, request_id: &str) -> DbResult<()> {
+        self.vm_create_requests
+            .lock()
+            .await
+            .retain(|r| !(r.user_id == user_id && r.request_id == request_id));
+        Ok(())
+    }
+
+    async fn delete_stale_vm_create_requests(&self, before: DateTime<Utc>) -> DbResult<u64> {
+        let mut requests = self.vm_create_requests.lock().await;
+        let len = requests.len();
+        requests.retain(|r| r.vm_id.is_some() || r.created >= before);
+        Ok((len - requests.len()) as u64)
+    }
+
+    async fn insert_vm_extra_disk(&self, disk: &VmExtraDisk) -> DbResult<u64> {
+        let mut disks = self.extra_disks.lock().await;
+        if disks
+            .values()
+            .any(|d| d.vm_id == disk.vm_id && d.slot == disk.slot)
+        {
+            return Err(DbError::Other(anyhow!("Duplicate extra disk slot")));
+        }
+        let max = *disks.keys().max().unwrap_or(&0);
+        let id = max + 1;
+        disks.insert(
+            id,
+            VmExtraDisk {
+                id,
+                created: Utc::now(),
+                ..disk.clone()
+            },
+        );
+        Ok(id)
+    }
+
+    async fn list_vm_extra_disks(&self, vm_id: u64) -> DbResult<Vec<VmExtraDisk>> {
+        let disks = self.extra_disks.lock().await;
+        let mut out: Vec<VmExtraDisk> = disks
+            .values()
+            .filter(|d| d.vm_id == vm_id)
+            .cloned()
+            .collect();
+        out.sort_by_key(|d| d.slot);
+        Ok(out)
+    }
+
+    async fn delete_vm_extra_disk(&self, disk_id: u64) -> DbResult<()> {
+        self.extra_disks.lock().await.remove(&disk_id);
+        Ok(())
+    }
+
+    async fn lock_placement(&self, timeout: std::time::Duration) -> DbResult<lnvps_db::DbLock> {
+        let guard = tokio::time::timeout(timeout, self.placement_lock.clone().lock_owned())
+            .await
+            .map_err(|_| DbError::Other(anyhow!("Timed out waiting for the placement lock")))?;
+        Ok(lnvps_db::DbLock::new(guard))
+    }
+
+    async fn insert_capacity_reservation(
+        &self,
+        reservation: &CapacityReservation,
+    ) -> DbResult<u64> {
+        let mut reservations = self.capacity_reservations.lock().await;
+        let now = Utc::now();
+        reservations.retain(|_, r| r.expires >= now);
+        let id = reservations.keys().max().unwrap_or(&0) + 1;
+        reservations.insert(
+            id,
+            CapacityReservation {
+                id,
+                ..reservation.clone()
+            },
+        );
+        Ok(id)
+    }
+
+    async fn list_capacity_reservations(&self, host_id: u64) -> DbResult<Vec<CapacityReservation>> {
+        let reservations = self.capacity_reservations.lock().await;
+        let now = Utc::now();
+        Ok(reservations
+            .values()
+            .filter(|r| r.host_id == host_id && r.expires > now)
+            .cloned()
+            .collect())
+    }
+
+    async fn set_capacity_reservation_vm(&self, id: u64, vm_id: u64) -> DbResult<()> {
+        let mut reservations = self.capacity_reservations.lock().await;
+        if let Some(r) = reservations.get_mut(&id) {
+            r.vm_id = Some(vm_id);
+        }
+        Ok(())
+    }
+
+    async fn delete_capacity_reservation(&self, id: u64) -> DbResult<()> {
+        self.capacity_reservations.lock().await.remove(&id);
+        Ok(())
+    }
+
+    async fn delete_vm_capacity_reservations(&self, vm_id: u64) -> DbResult<()> {
+        self.capacity_reservations
+            .lock()
+            .await
+            .retain(|_, r| r.vm_id != Some(vm_id));
+        Ok(())
+    }
+
+    async fn update_vm_firewall_policy(
+        &self,
+        vm_id: u64,
+        policy_in: Option<VmFirewallPolicy>,
+        policy_out: Option<VmFirewallPolicy>,
+    ) -> DbResult<()> {
+        let mut vms = self.vms.lock().await;
+        if let Some(vm) = vms.get_mut(&vm_id) {
+            vm.fw_policy_in = policy_in;
+            vm.fw_policy_out = policy_out;
+        }
+        Ok(())
+    }
+
+    async fn list_custom_pricing(&self, _tb: u64) -> DbResult<Vec<VmCustomPricing>> {
+        let p = self.custom_pricing.lock().await;
+        Ok(p.values().cloned().collect())
+    }
+
+    async fn list_custom_pricing_paginated(
+        &self,
+        region_id: Option<u64>,
+        enabled: Option<bool>,
+        limit: u64,
+        offset: u64,
+    ) -> DbResult<(Vec<VmCustomPricing>, u64)> {
+        let p = self.custom_pricing.lock().await;
+        let mut all: Vec<_> = p
+            .values()
+            .filter(|v| region_id.map_or(true, |r| v.region_id == r))
             .filter(|v| enabled.map_or(true, |e| v.enabled == e))
             .cloned()
             .collect();
@@ -1789,11 +2581,11 @@ impl LNVpsDbBase for MockDb {
 
     async fn get_vm_ip_assignment_by_ip(&self, ip: &str) -> DbResult<VmIpAssignment> {
         let assignments = self.ip_assignments.lock().await;
-        Ok(assignments
+        assignments
             .values()
             .find(|a| a.ip == ip)
             .cloned()
-            .ok_or_else(|| anyhow!("IP assignment not found for {}", ip))?)
+            .ok_or_else(DbError::row_not_found)
     }
 
     async fn get_access_policy(&self, access_policy_id: u64) -> DbResult<AccessPolicy> {
@@ -1895,6 +2687,24 @@ impl LNVpsDbBase for MockDb {
         }
     }
 
+    async fn list_vm_history_after(
+        &self,
+        vm_id: u64,
+        after: Option<(DateTime<Utc>, u64)>,
+        limit: u64,
+    ) -> DbResult<Vec<VmHistory>> {
+        let mut history = self.list_vm_history(vm_id).await?;
+        history.sort_by(|a, b| b.timestamp.cmp(&a.timestamp).then(b.id.cmp(&a.id)));
+        Ok(history
+            .into_iter()
+            .filter(|h| match after {
+                Some((timestamp, id)) => (h.timestamp, h.id) < (timestamp, id),
+                None => true,
+            })
+            .take(limit as usize)
+            .collect())
+    }
+
     async fn get_vm_history(&self, id: u64) -> DbResult<VmHistory> {
         let vm_history_map = self.vm_history.lock().await;
         Ok(vm_history_map
@@ -1949,6 +2759,173 @@ impl LNVpsDbBase for MockDb {
         Ok(vec![])
     }
 
+    async fn insert_admin_audit_log(&self, entry: &AdminAuditLog) -> DbResult<u64> {
+        let mut log = self.admin_audit_log.lock().await;
+        let id = log.len() as u64 + 1;
+        log.push(AdminAuditLog {
+            id,
+            created: Utc::now(),
+            ..entry.clone()
+        });
+        Ok(id)
+    }
+
+    async fn insert_bulk_message_deliveries(
+        &self,
+        deliveries: &[BulkMessageDelivery],
+    ) -> DbResult<()> {
+        let mut all = self.bulk_message_deliveries.lock().await;
+        for d in deliveries {
+            let id = all.len() as u64 + 1;
+            all.push(BulkMessageDelivery {
+                id,
+                created: Utc::now(),
+                ..d.clone()
+            });
+        }
+        Ok(())
+    }
+
+    async fn list_active_system_notices(&self) -> DbResult<Vec<SystemNotice>> {
+        let now = Utc::now();
+        let mut notices: Vec<SystemNotice> = self
+            .system_notices
+            .lock()
+            .await
+            .values()
+            .filter(|n| n.is_active(now))
+            .cloned()
+            .collect();
+        notices.sort_by(|a, b| (b.severity as u16, b.starts).cmp(&(a.severity as u16, a.starts)));
+        Ok(notices)
+    }
+
+    async fn list_feature_flags(&self) -> DbResult<Vec<FeatureFlag>> {
+        let mut flags: Vec<FeatureFlag> =
+            self.feature_flags.lock().await.values().cloned().collect();
+        flags.sort_by(|a, b| (&a.key, a.region_id).cmp(&(&b.key, b.region_id)));
+        Ok(flags)
+    }
+
+    async fn enqueue_job(&self, job: &str) -> DbResult<u64> {
+        let mut queue = self.work_queue.lock().await;
+        let id = queue.keys().max().unwrap_or(&0) + 1;
+        let now = Utc::now();
+        queue.insert(
+            id,
+            WorkQueueItem {
+                id,
+                created: now,
+                job: job.to_string(),
+                due: now,
+                ..Default::default()
+            },
+        );
+        Ok(id)
+    }
+
+    async fn claim_next_job(
+        &self,
+        consumer: &str,
+        reclaim_after: TimeDelta,
+        max_attempts: u32,
+    ) -> DbResult<Option<WorkQueueItem>> {
+        let now = Utc::now();
+        self.work_queue_polls
+            .lock()
+            .await
+            .insert(consumer.to_string(), now);
+        // the lock is held from select to update, like the row lock in mysql
+        let mut queue = self.work_queue.lock().await;
+        let abandoned = |j: &WorkQueueItem| {
+            j.status == WorkQueueStatus::Claimed
+                && j.claimed_at.is_some_and(|c| c < now - reclaim_after)
+        };
+        for j in queue.values_mut() {
+            if abandoned(j) && j.attempts + 1 >= max_attempts {
+                j.status = WorkQueueStatus::Dead;
+                j.attempts += 1;
+                j.last_error = Some("Abandoned by worker".to_string());
+            }
+        }
+        let Some(item) = queue
+            .values_mut()
+            .filter(|j| match j.status {
+                WorkQueueStatus::Queued => j.due <= now,
+                WorkQueueStatus::Claimed => abandoned(j),
+                WorkQueueStatus::Dead => false,
+            })
+            .min_by_key(|j| j.id)
+        else {
+            return Ok(None);
+        };
+        if item.status == WorkQueueStatus::Claimed {
+            item.attempts += 1;
+        }
+        item.status = WorkQueueStatus::Claimed;
+        item.claimed_by = Some(consumer.to_string());
+        item.claimed_at = Some(now);
+        Ok(Some(item.clone()))
+    }
+
+    async fn complete_job(&self, id: u64) -> DbResult<()> {
+        self.work_queue.lock().await.remove(&id);
+        Ok(())
+    }
+
+    async fn renew_job_claim(&self, id: u64) -> DbResult<()> {
+        if let Some(j) = self.work_queue.lock().await.get_mut(&id)
+            && j.status == WorkQueueStatus::Claimed
+        {
+            j.claimed_at = Some(Utc::now());
+        }
+        Ok(())
+    }
+
+    async fn fail_job(
+        &self,
+        id: u64,
+        error: &str,
+        retry_at: DateTime<Utc>,
+        max_attempts: u32,
+    ) -> DbResult<()> {
+        if let Some(j) = self.work_queue.lock().await.get_mut(&id) {
+            j.attempts += 1;
+            j.status = if j.attempts >= max_attempts {
+                WorkQueueStatus::Dead
+            } else {
+                WorkQueueStatus::Queued
+            };
+            j.claimed_by = None;
+            j.claimed_at = None;
+            j.last_error = Some(error.to_string());
+            j.due = retry_at;
+        }
+        Ok(())
+    }
+
+    async fn list_work_queue(&self, limit: u64) -> DbResult<Vec<WorkQueueItem>> {
+        let mut jobs: Vec<WorkQueueItem> = self
+            .work_queue
+            .lock()
+            .await
+            .values()
+            .filter(|j| j.status != WorkQueueStatus::Dead)
+            .cloned()
+            .collect();
+        jobs.sort_by_key(|j| j.id);
+        jobs.truncate(limit as usize);
+        Ok(jobs)
+    }
+
+    async fn delete_job(&self, id: u64) -> DbResult<bool> {
+        Ok(self.work_queue.lock().await.remove(&id).is_some())
+    }
+
+    async fn work_queue_last_poll(&self) -> DbResult<Option<DateTime<Utc>>> {
+        Ok(self.work_queue_polls.lock().await.values().max().cloned())
+    }
+
     // Subscription methods
     async fn list_subscriptions(&self) -> DbResult<Vec<Subscription>> {
         let subscriptions = self.subscriptions.lock().await;
@@ -2095,10 +3072,10 @@ impl LNVpsDbBase for MockDb {
 
     async fn get_subscription(&self, id: u64) -> DbResult<Subscription> {
         let subscriptions = self.subscriptions.lock().await;
-        Ok(subscriptions
+        subscriptions
             .get(&id)
             .cloned()
-            .ok_or_else(|| anyhow!("Subscription not found: {}", id))?)
+            .ok_or_else(DbError::row_not_found)
     }
 
     async fn get_subscription_by_ext_id(&self, ext_id: &str) -> DbResult<Subscription> {
@@ -2286,11 +3263,11 @@ impl LNVpsDbBase for MockDb {
 
     async fn get_subscription_payment(&self, id: &Vec<u8>) -> DbResult<SubscriptionPayment> {
         let payments = self.subscription_payments.lock().await;
-        Ok(payments
+        payments
             .iter()
             .find(|p| &p.id == id)
             .cloned()
-            .context("Subscription payment not found")?)
+            .ok_or_else(DbError::row_not_found)
     }
 
     async fn get_subscription_payment_by_ext_id(
@@ -2353,6 +3330,7 @@ impl LNVpsDbBase for MockDb {
             tax_treatment: payment.tax_treatment.clone(),
             tax_evidence: payment.tax_evidence.clone(),
             tax_breakdown: payment.tax_breakdown.clone(),
+            invoice_number: payment.invoice_number.clone(),
             company_id: 0,
             company_name: String::new(),
             company_base_currency: "EUR".to_string(),
@@ -2397,14 +3375,35 @@ impl LNVpsDbBase for MockDb {
     }
 
     async fn subscription_payment_paid(&self, payment: &SubscriptionPayment) -> DbResult<()> {
+        // VM payments are invoiced by the company of the VM's region
+        let company_id = match self.get_vm_by_subscription(payment.subscription_id).await {
+            Ok(vm) => self.get_vm_company_id(vm.id).await.ok(),
+            Err(_) => None,
+        };
+        let company_id = match company_id {
+            Some(id) => id,
+            None => self
+                .get_subscription(payment.subscription_id)
+                .await
+                .map(|s| s.company_id)
+                .unwrap_or_default(),
+        };
         // Mark payment as paid with timestamp. Idempotent: if the payment is already
         // paid (or unknown), do nothing and skip the expiry extension below.
         let mut payments = self.subscription_payments.lock().await;
         match payments.iter_mut().find(|p| p.id == payment.id) {
             Some(p) if !p.is_paid => {
+                let now = Utc::now();
                 p.is_paid = true;
-                p.paid_at = Some(Utc::now());
+                p.paid_at = Some(now);
                 p.external_data = payment.external_data.clone();
+                let year = now.year() as u64;
+                let mut counters = self.invoice_counters.lock().await;
+                let seq = counters.entry((company_id, year)).or_default();
+                *seq += 1;
+                p.invoice_number = Some(SubscriptionPayment::format_invoice_number(
+                    company_id, year, *seq,
+                ));
             }
             _ => {
                 drop(payments);
@@ -2456,18 +3455,93 @@ impl LNVpsDbBase for MockDb {
                 vm.deleted = false;
             }
         }
-        drop(vms);
+        drop(vms);
+
+        Ok(())
+    }
+
+    async fn last_paid_subscription_invoice(&self) -> DbResult<Option<SubscriptionPayment>> {
+        let payments = self.subscription_payments.lock().await;
+        Ok(payments
+            .iter()
+            .filter(|p| p.is_paid)
+            .max_by(|a, b| a.created.cmp(&b.created))
+            .cloned())
+    }
+
+    async fn list_expired_subscription_payments(
+        &self,
+        limit: u64,
+    ) -> DbResult<Vec<SubscriptionPayment>> {
+        let payments = self.subscription_payments.lock().await;
+        let canceled = self.canceled_payments.lock().await;
+        let now = Utc::now();
+        let mut expired: Vec<_> = payments
+            .iter()
+            .filter(|p| !p.is_paid && p.expires < now && !canceled.contains(&p.id))
+            .cloned()
+            .collect();
+        expired.sort_by_key(|p| p.expires);
+        expired.truncate(limit as usize);
+        Ok(expired)
+    }
+
+    async fn cancel_subscription_payment(&self, id: &Vec<u8>) -> DbResult<()> {
+        let payments = self.subscription_payments.lock().await;
+        if payments.iter().any(|p| &p.id == id && !p.is_paid) {
+            self.canceled_payments.lock().await.insert(id.clone());
+        }
+        Ok(())
+    }
+
+    async fn pay_subscription_payment_with_credit(
+        &self,
+        payment: &SubscriptionPayment,
+    ) -> DbResult<bool> {
+        // Hold the credit lock across the settle so concurrent debits serialise.
+        let mut credit = self.user_credit.lock().await;
+        let unpaid = self
+            .subscription_payments
+            .lock()
+            .await
+            .iter()
+            .any(|p| p.id == payment.id && !p.is_paid);
+        let total = payment.amount + payment.tax + payment.processing_fee;
+        let key = (payment.user_id, payment.currency.clone());
+        let balance = credit.get(&key).copied().unwrap_or(0);
+        if !unpaid || balance < total {
+            return Ok(false);
+        }
+        credit.insert(key, balance - total);
+        self.subscription_payment_paid(payment).await?;
+        Ok(true)
+    }
+
+    async fn claim_subscription_auto_renewal(
+        &self,
+        subscription_id: u64,
+        period_expires: DateTime<Utc>,
+    ) -> DbResult<bool> {
+        let mut renewals = self.auto_renewals.lock().await;
+        if renewals.contains(&(subscription_id, period_expires)) {
+            return Ok(false);
+        }
+        renewals.push((subscription_id, period_expires));
+        Ok(true)
+    }
 
-        Ok(())
+    async fn get_user_credit(&self, user_id: u64, currency: &str) -> DbResult<u64> {
+        let credit = self.user_credit.lock().await;
+        Ok(credit
+            .get(&(user_id, currency.to_string()))
+            .copied()
+            .unwrap_or(0))
     }
 
-    async fn last_paid_subscription_invoice(&self) -> DbResult<Option<SubscriptionPayment>> {
-        let payments = self.subscription_payments.lock().await;
-        Ok(payments
-            .iter()
-            .filter(|p| p.is_paid)
-            .max_by(|a, b| a.created.cmp(&b.created))
-            .cloned())
+    async fn add_user_credit(&self, user_id: u64, currency: &str, amount: u64) -> DbResult<()> {
+        let mut credit = self.user_credit.lock().await;
+        *credit.entry((user_id, currency.to_string())).or_insert(0) += amount;
+        Ok(())
     }
 
     async fn list_available_ip_space(&self) -> DbResult<Vec<AvailableIpSpace>> {
@@ -3502,7 +4576,11 @@ impl lnvps_db::AdminDb for MockDb {
     ) -> DbResult<u64> {
         Ok(1)
     }
-    async fn admin_update_region(&self, _region: &Region) -> DbResult<()> {
+    async fn admin_update_region(&self, region: &Region) -> DbResult<()> {
+        let mut regions = self.regions.lock().await;
+        if let Some(r) = regions.get_mut(&region.id) {
+            *r = region.clone();
+        }
         Ok(())
     }
     async fn admin_delete_region(&self, _region_id: u64) -> DbResult<()> {
@@ -3515,11 +4593,7 @@ impl lnvps_db::AdminDb for MockDb {
         todo!()
     }
     async fn admin_transfer_vm(&self, vm_id: u64, new_user_id: u64) -> DbResult<()> {
-        let mut vms = self.vms.lock().await;
-        let vm = vms.get_mut(&vm_id).ok_or(anyhow!("no vm"))?;
-        vm.user_id = new_user_id;
-        vm.ssh_key_id = None;
-        Ok(())
+        self.transfer_vm(vm_id, new_user_id).await
     }
 
     async fn admin_list_vm_os_images(
@@ -3538,7 +4612,28 @@ impl lnvps_db::AdminDb for MockDb {
     async fn admin_update_vm_os_image(&self, _image: &VmOsImage) -> DbResult<()> {
         Ok(())
     }
-    async fn admin_delete_vm_os_image(&self, _image_id: u64) -> DbResult<()> {
+    async fn admin_delete_vm_os_image(&self, image_id: u64) -> DbResult<()> {
+        // hold both locks for the whole check-and-delete, like the transaction
+        let vms = self.vms.lock().await;
+        let mut allowed = self.region_os_images.lock().await;
+        let in_use = vms.values().filter(|v| v.image_id == image_id).count();
+        if in_use > 0 {
+            return Err(anyhow!(
+                "Cannot delete VM OS image: {} VMs are using this image",
+                in_use
+            )
+            .into());
+        }
+        if allowed.values().any(|ids| ids.as_slice() == [image_id]) {
+            return Err(anyhow!(
+                "Cannot delete VM OS image: it is the only image allowed in a region"
+            )
+            .into());
+        }
+        for ids in allowed.values_mut() {
+            ids.retain(|i| *i != image_id);
+        }
+        self.os_images.lock().await.remove(&image_id);
         Ok(())
     }
     async fn list_vm_templates_paginated(
@@ -3813,6 +4908,7 @@ impl lnvps_db::AdminDb for MockDb {
                     tax_treatment: payment.tax_treatment.clone(),
                     tax_evidence: payment.tax_evidence.clone(),
                     tax_breakdown: payment.tax_breakdown.clone(),
+                    invoice_number: payment.invoice_number.clone(),
                     company_id: cid,
                     company_name: company.name.clone(),
                     company_base_currency: company.base_currency.clone(),
@@ -3895,6 +4991,24 @@ impl lnvps_db::AdminDb for MockDb {
     async fn admin_count_ip_range_assignments(&self, _ip_range_id: u64) -> DbResult<u64> {
         Ok(0)
     }
+    async fn list_vms_in_ip_range(&self, ip_range_id: u64) -> DbResult<Vec<(Vm, User)>> {
+        let vm_ids: std::collections::BTreeSet<u64> = self
+            .ip_assignments
+            .lock()
+            .await
+            .values()
+            .filter(|a| a.ip_range_id == ip_range_id && !a.deleted)
+            .map(|a| a.vm_id)
+            .collect();
+        let vms = self.vms.lock().await;
+        let users = self.users.lock().await;
+        Ok(vm_ids
+            .iter()
+            .filter_map(|id| vms.get(id))
+            .filter(|vm| !vm.deleted)
+            .filter_map(|vm| users.get(&vm.user_id).map(|u| (vm.clone(), u.clone())))
+            .collect())
+    }
     async fn admin_list_access_policies(&self) -> DbResult<Vec<AccessPolicy>> {
         Ok(vec![])
     }
@@ -3966,10 +5080,12 @@ impl lnvps_db::AdminDb for MockDb {
         host_id: Option<u64>,
         pubkey: Option<&str>,
         region_id: Option<u64>,
+        state: Option<VmStateKind>,
         include_deleted: Option<bool>,
     ) -> DbResult<(Vec<Vm>, u64)> {
         let vms = self.vms.lock().await;
         let hosts = self.hosts.lock().await;
+        let vm_states = self.vm_states.lock().await;
 
         // Resolve user_id from pubkey if provided
         let resolved_user_id = if let Some(pk) = pubkey {
@@ -4012,6 +5128,13 @@ impl lnvps_db::AdminDb for MockDb {
                     }
                 }
 
+                // Filter by last known state, VMs without one are unknown
+                if let Some(state) = state
+                    && vm_states.get(&vm.id).map(|s| s.state).unwrap_or_default() != state
+                {
+                    return false;
+                }
+
                 // Filter by deleted status
                 match include_deleted {
                     Some(false) | None => {
@@ -4044,11 +5167,11 @@ impl lnvps_db::AdminDb for MockDb {
 
     async fn get_user_by_pubkey(&self, pubkey: &[u8]) -> DbResult<User> {
         let users = self.users.lock().await;
-        Ok(users
+        users
             .values()
             .find(|user| user.pubkey == pubkey)
             .cloned()
-            .ok_or_else(|| anyhow!("User not found with provided pubkey"))?)
+            .ok_or_else(DbError::row_not_found)
     }
 
     async fn admin_list_vm_ip_assignments(
@@ -4134,84 +5257,334 @@ impl lnvps_db::AdminDb for MockDb {
     ) -> DbResult<Vec<lnvps_db::ResourceCost>> {
         Ok(vec![])
     }
+
+    async fn admin_list_audit_log(
+        &self,
+        limit: u64,
+        offset: u64,
+    ) -> DbResult<(Vec<AdminAuditLog>, u64)> {
+        let log = self.admin_audit_log.lock().await;
+        let page = log
+            .iter()
+            .rev()
+            .skip(offset as usize)
+            .take(limit as usize)
+            .cloned()
+            .collect();
+        Ok((page, log.len() as u64))
+    }
+
+    async fn admin_list_system_notices(
+        &self,
+        limit: u64,
+        offset: u64,
+    ) -> DbResult<(Vec<SystemNotice>, u64)> {
+        let notices = self.system_notices.lock().await;
+        let mut all: Vec<SystemNotice> = notices.values().cloned().collect();
+        all.sort_by(|a, b| (b.starts, b.id).cmp(&(a.starts, a.id)));
+        let page = all
+            .into_iter()
+            .skip(offset as usize)
+            .take(limit as usize)
+            .collect();
+        Ok((page, notices.len() as u64))
+    }
+
+    async fn admin_get_system_notice(&self, id: u64) -> DbResult<SystemNotice> {
+        self.system_notices
+            .lock()
+            .await
+            .get(&id)
+            .cloned()
+            .ok_or_else(|| anyhow!("System notice not found").into())
+    }
+
+    async fn admin_insert_system_notice(&self, notice: &SystemNotice) -> DbResult<u64> {
+        let mut notices = self.system_notices.lock().await;
+        let id = notices.keys().max().copied().unwrap_or(0) + 1;
+        notices.insert(
+            id,
+            SystemNotice {
+                id,
+                created: Utc::now(),
+                ..notice.clone()
+            },
+        );
+        Ok(id)
+    }
+
+    async fn admin_update_system_notice(&self, notice: &SystemNotice) -> DbResult<()> {
+        let mut notices = self.system_notices.lock().await;
+        match notices.get_mut(&notice.id) {
+            Some(n) => {
+                *n = notice.clone();
+                Ok(())
+            }
+            None => Err(anyhow!("System notice not found").into()),
+        }
+    }
+
+    async fn admin_delete_system_notice(&self, id: u64) -> DbResult<()> {
+        self.system_notices.lock().await.remove(&id);
+        Ok(())
+    }
+
+    async fn admin_get_feature_flag(&self, id: u64) -> DbResult<FeatureFlag> {
+        self.feature_flags
+            .lock()
+            .await
+            .get(&id)
+            .cloned()
+            .ok_or_else(DbError::row_not_found)
+    }
+
+    async fn admin_insert_feature_flag(&self, flag: &FeatureFlag) -> DbResult<u64> {
+        let mut flags = self.feature_flags.lock().await;
+        if flags
+            .values()
+            .any(|f| f.key == flag.key && f.region_id == flag.region_id)
+        {
+            return Err(anyhow!("Duplicate feature flag").into());
+        }
+        let id = flags.keys().max().copied().unwrap_or(0) + 1;
+        flags.insert(
+            id,
+            FeatureFlag {
+                id,
+                updated: Utc::now(),
+                ..flag.clone()
+            },
+        );
+        Ok(id)
+    }
+
+    async fn admin_update_feature_flag(&self, flag: &FeatureFlag) -> DbResult<()> {
+        let mut flags = self.feature_flags.lock().await;
+        match flags.get_mut(&flag.id) {
+            Some(f) => {
+                *f = FeatureFlag {
+                    updated: Utc::now(),
+                    ..flag.clone()
+                };
+                Ok(())
+            }
+            None => Err(DbError::row_not_found()),
+        }
+    }
+
+    async fn admin_delete_feature_flag(&self, id: u64) -> DbResult<()> {
+        self.feature_flags.lock().await.remove(&id);
+        Ok(())
+    }
+
+    async fn admin_list_incidents(
+        &self,
+        limit: u64,
+        offset: u64,
+    ) -> DbResult<(Vec<Incident>, u64)> {
+        let incidents = self.incidents.lock().await;
+        let mut all: Vec<Incident> = incidents.values().cloned().collect();
+        all.sort_by(|a, b| (b.started, b.id).cmp(&(a.started, a.id)));
+        let page = all
+            .into_iter()
+            .skip(offset as usize)
+            .take(limit as usize)
+            .collect();
+        Ok((page, incidents.len() as u64))
+    }
+
+    async fn admin_get_incident(&self, id: u64) -> DbResult<Incident> {
+        self.incidents
+            .lock()
+            .await
+            .get(&id)
+            .cloned()
+            .ok_or_else(DbError::row_not_found)
+    }
+
+    async fn admin_insert_incident(&self, incident: &Incident) -> DbResult<u64> {
+        let mut incidents = self.incidents.lock().await;
+        let id = incidents.keys().max().copied().unwrap_or(0) + 1;
+        incidents.insert(
+            id,
+            Incident {
+                id,
+                created: Utc::now(),
+                ..incident.clone()
+            },
+        );
+        Ok(id)
+    }
+
+    async fn admin_update_incident(&self, incident: &Incident) -> DbResult<()> {
+        let mut incidents = self.incidents.lock().await;
+        match incidents.get_mut(&incident.id) {
+            Some(i) => {
+                i.message = incident.message.clone();
+                i.started = incident.started;
+                i.ended = incident.ended;
+                i.notified = incident.notified;
+                Ok(())
+            }
+            None => Err(DbError::row_not_found()),
+        }
+    }
 }
 
-// Nostr trait implementation with stub methods
+// Nostr trait implementation, handles are stubbed
 #[async_trait]
 impl LNVPSNostrDb for MockDb {
-    async fn get_handle(&self, _handle_id: u64) -> DbResult<NostrDomainHandle> {
-        todo!()
+    async fn get_handle(&self, handle_id: u64) -> DbResult<NostrDomainHandle> {
+        let h = self.nostr_handles.lock().await;
+        h.get(&handle_id)
+            .cloned()
+            .ok_or_else(DbError::row_not_found)
     }
 
     async fn get_handle_by_name(
         &self,
-        _domain_id: u64,
-        _handle: &str,
+        domain_id: u64,
+        handle: &str,
     ) -> DbResult<NostrDomainHandle> {
-        todo!()
+        let h = self.nostr_handles.lock().await;
+        h.values()
+            .find(|h| h.domain_id == domain_id && h.handle == handle)
+            .cloned()
+            .ok_or_else(DbError::row_not_found)
     }
 
-    async fn insert_handle(&self, _handle: &NostrDomainHandle) -> DbResult<u64> {
-        Ok(1)
+    async fn resolve_handle(&self, domain_id: u64, name: &str) -> DbResult<NostrDomainHandle> {
+        let h = self.nostr_handles.lock().await;
+        let find = |name: &str| {
+            h.values()
+                .find(|h| h.domain_id == domain_id && h.handle == name)
+                .cloned()
+        };
+        find(name)
+            .or_else(|| find(NostrDomainHandle::DEFAULT))
+            .ok_or_else(DbError::row_not_found)
+    }
+
+    async fn insert_handle(&self, handle: &NostrDomainHandle) -> DbResult<u64> {
+        let mut h = self.nostr_handles.lock().await;
+        let id = h.keys().max().unwrap_or(&0) + 1;
+        h.insert(
+            id,
+            NostrDomainHandle {
+                id,
+                ..handle.clone()
+            },
+        );
+        Ok(id)
     }
 
-    async fn update_handle(&self, _handle: &NostrDomainHandle) -> DbResult<()> {
+    async fn update_handle(&self, handle: &NostrDomainHandle) -> DbResult<()> {
+        let mut h = self.nostr_handles.lock().await;
+        if let Some(h) = h.get_mut(&handle.id) {
+            h.handle = handle.handle.clone();
+            h.pubkey = handle.pubkey.clone();
+            h.relays = handle.relays.clone();
+        }
         Ok(())
     }
 
-    async fn delete_handle(&self, _handle_id: u64) -> DbResult<()> {
+    async fn delete_handle(&self, handle_id: u64) -> DbResult<()> {
+        self.nostr_handles.lock().await.remove(&handle_id);
         Ok(())
     }
 
-    async fn list_handles(&self, _domain_id: u64) -> DbResult<Vec<NostrDomainHandle>> {
-        Ok(vec![])
+    async fn list_handles(&self, domain_id: u64) -> DbResult<Vec<NostrDomainHandle>> {
+        let h = self.nostr_handles.lock().await;
+        Ok(h.values()
+            .filter(|h| h.domain_id == domain_id)
+            .cloned()
+            .collect())
     }
 
-    async fn get_domain(&self, _id: u64) -> DbResult<NostrDomain> {
-        todo!()
+    async fn get_domain(&self, id: u64) -> DbResult<NostrDomain> {
+        let d = self.nostr_domains.lock().await;
+        Ok(d.get(&id).cloned().context("no domain")?)
     }
 
-    async fn get_domain_by_name(&self, _name: &str) -> DbResult<NostrDomain> {
-        todo!()
+    async fn get_domain_by_name(&self, name: &str) -> DbResult<NostrDomain> {
+        let d = self.nostr_domains.lock().await;
+        Ok(d.values()
+            .find(|d| d.name == name)
+            .cloned()
+            .context("no domain")?)
     }
 
-    async fn get_domain_by_activation_hash(&self, _hash: &str) -> DbResult<NostrDomain> {
-        todo!()
+    async fn get_domain_by_activation_hash(&self, hash: &str) -> DbResult<NostrDomain> {
+        let d = self.nostr_domains.lock().await;
+        Ok(d.values()
+            .find(|d| d.activation_hash.as_deref() == Some(hash))
+            .cloned()
+            .context("no domain")?)
     }
 
-    async fn list_domains(&self, _owner_id: u64) -> DbResult<Vec<NostrDomain>> {
-        Ok(vec![])
+    async fn list_domains(&self, owner_id: u64) -> DbResult<Vec<NostrDomain>> {
+        let d = self.nostr_domains.lock().await;
+        Ok(d.values()
+            .filter(|d| d.owner_id == owner_id)
+            .cloned()
+            .collect())
     }
 
-    async fn insert_domain(&self, _domain: &NostrDomain) -> DbResult<u64> {
-        Ok(1)
+    async fn insert_domain(&self, domain: &NostrDomain) -> DbResult<u64> {
+        let mut d = self.nostr_domains.lock().await;
+        let id = d.keys().max().unwrap_or(&0) + 1;
+        d.insert(
+            id,
+            NostrDomain {
+                id,
+                ..domain.clone()
+            },
+        );
+        Ok(id)
     }
 
-    async fn delete_domain(&self, _domain_id: u64) -> DbResult<()> {
+    async fn delete_domain(&self, domain_id: u64) -> DbResult<()> {
+        self.nostr_domains.lock().await.remove(&domain_id);
         Ok(())
     }
 
     async fn list_all_domains(&self) -> DbResult<Vec<NostrDomain>> {
-        Ok(vec![])
+        Ok(self.nostr_domains.lock().await.values().cloned().collect())
     }
 
     async fn list_active_domains(&self) -> DbResult<Vec<NostrDomain>> {
-        Ok(vec![])
+        let d = self.nostr_domains.lock().await;
+        Ok(d.values().filter(|d| d.enabled).cloned().collect())
     }
 
     async fn list_disabled_domains(&self) -> DbResult<Vec<NostrDomain>> {
-        Ok(vec![])
+        let d = self.nostr_domains.lock().await;
+        Ok(d.values().filter(|d| !d.enabled).cloned().collect())
     }
 
-    async fn enable_domain_with_https(&self, _domain_id: u64) -> DbResult<()> {
+    async fn enable_domain_with_https(&self, domain_id: u64) -> DbResult<()> {
+        let mut d = self.nostr_domains.lock().await;
+        let d = d.get_mut(&domain_id).context("no domain")?;
+        d.enabled = true;
+        d.http_only = false;
+        d.last_status_change = Utc::now();
         Ok(())
     }
 
-    async fn enable_domain_http_only(&self, _domain_id: u64) -> DbResult<()> {
+    async fn enable_domain_http_only(&self, domain_id: u64) -> DbResult<()> {
+        let mut d = self.nostr_domains.lock().await;
+        let d = d.get_mut(&domain_id).context("no domain")?;
+        d.enabled = true;
+        d.http_only = true;
+        d.last_status_change = Utc::now();
         Ok(())
     }
 
-    async fn disable_domain(&self, _domain_id: u64) -> DbResult<()> {
+    async fn disable_domain(&self, domain_id: u64) -> DbResult<()> {
+        let mut d = self.nostr_domains.lock().await;
+        let d = d.get_mut(&domain_id).context("no domain")?;
+        d.enabled = false;
+        d.last_status_change = Utc::now();
         Ok(())
     }
 }
@@ -4271,6 +5644,113 @@ mod tests {
         assert_eq!(counts.get(&2), Some(&1)); // deleted VM excluded
     }
 
+    #[tokio::test]
+    async fn test_upsert_vm_state_updates_row() {
+        let db = MockDb::default();
+        db.upsert_vm_state(1, VmStateKind::Running).await.unwrap();
+        let first = db.list_vm_states(&[1, 2]).await.unwrap();
+        assert_eq!(first.len(), 1, "VM 2 was never seen");
+        assert_eq!(first[0].state, VmStateKind::Running);
+
+        db.upsert_vm_state(1, VmStateKind::Stopped).await.unwrap();
+        let second = db.list_vm_states(&[1]).await.unwrap();
+        assert_eq!(second.len(), 1);
+        assert_eq!(second[0].state, VmStateKind::Stopped);
+        assert!(second[0].last_seen >= first[0].last_seen);
+    }
+
+    #[cfg(feature = "admin")]
+    #[tokio::test]
+    async fn test_admin_list_vms_filtered_by_state() {
+        use lnvps_db::AdminDb;
+
+        let db = MockDb::default();
+        {
+            let mut vms = db.vms.lock().await;
+            for id in 1..=4 {
+                vms.insert(
+                    id,
+                    Vm {
+                        id,
+                        ..MockDb::mock_vm()
+                    },
+                );
+            }
+        }
+        db.upsert_vm_state(1, VmStateKind::Stopped).await.unwrap();
+        db.upsert_vm_state(2, VmStateKind::Running).await.unwrap();
+        db.upsert_vm_state(3, VmStateKind::Stopped).await.unwrap();
+        // VM 4 was never checked
+
+        let (vms, total) = db
+            .admin_list_vms_filtered(
+                50,
+                0,
+                None,
+                None,
+                None,
+                None,
+                Some(VmStateKind::Stopped),
+                None,
+            )
+            .await
+            .unwrap();
+        let mut ids: Vec<u64> = vms.iter().map(|v| v.id).collect();
+        ids.sort();
+        assert_eq!(ids, vec![1, 3]);
+        assert_eq!(total, 2);
+
+        let (vms, _) = db
+            .admin_list_vms_filtered(
+                50,
+                0,
+                None,
+                None,
+                None,
+                None,
+                Some(VmStateKind::Unknown),
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(vms.iter().map(|v| v.id).collect::<Vec<_>>(), vec![4]);
+
+        let (_, total) = db
+            .admin_list_vms_filtered(50, 0, None, None, None, None, None, None)
+            .await
+            .unwrap();
+        assert_eq!(total, 4);
+    }
+
+    #[cfg(feature = "admin")]
+    #[tokio::test]
+    async fn test_admin_delete_vm_os_image() {
+        use lnvps_db::AdminDb;
+
+        let db = MockDb::default();
+        {
+            let mut images = db.os_images.lock().await;
+            for id in [2, 3] {
+                let mut other = images.get(&1).unwrap().clone();
+                other.id = id;
+                images.insert(id, other);
+            }
+        }
+        db.set_region_os_images(1, &[2]).await.unwrap();
+        db.set_region_os_images(2, &[2, 3]).await.unwrap();
+
+        // in use by a VM
+        db.vms.lock().await.insert(1, MockDb::mock_vm());
+        assert!(db.admin_delete_vm_os_image(1).await.is_err());
+        // the only image allowed in region 1, deleting it would lift the allowlist
+        assert!(db.admin_delete_vm_os_image(2).await.is_err());
+        assert_eq!(db.list_region_os_images(1).await.unwrap(), vec![2]);
+
+        db.admin_delete_vm_os_image(3).await.unwrap();
+        assert_eq!(db.list_region_os_images(2).await.unwrap(), vec![2]);
+        assert!(!db.os_images.lock().await.contains_key(&3));
+    }
+
     #[cfg(feature = "admin")]
     #[tokio::test]
     async fn test_admin_transfer_vm() {
@@ -4557,6 +6037,7 @@ mod tests {
             tax_treatment: None,
             tax_evidence: None,
             tax_breakdown: None,
+            invoice_number: None,
         }
     }
 
@@ -4822,6 +6303,51 @@ mod tests {
         assert!(p.paid_at.is_some());
     }
 
+    /// Concurrent settlements each get their own invoice number, with no gaps,
+    /// and settling a payment twice doesn't use up a number.
+    ///
+    /// This only covers the MockDb counter (a mutex), the MySQL row locking is
+    /// tested by the ignored `test_invoice_numbers_concurrent` in `lnvps_db`.
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_subscription_payment_paid_invoice_numbers_sequential() {
+        let db = MockDb::default();
+        let mut payments = Vec::new();
+        for i in 0..20u8 {
+            let mut payment = make_payment(1, Some(86400));
+            payment.id = vec![i; 16];
+            db.insert_subscription_payment(&payment).await.unwrap();
+            payments.push(payment);
+        }
+
+        let tasks: Vec<_> = payments
+            .iter()
+            .cloned()
+            .map(|p| {
+                let db = db.clone();
+                tokio::spawn(async move { db.subscription_payment_paid(&p).await })
+            })
+            .collect();
+        for t in tasks {
+            t.await.unwrap().unwrap();
+        }
+        db.subscription_payment_paid(&payments[0]).await.unwrap();
+
+        let year = Utc::now().year() as u64;
+        let mut numbers: Vec<String> = db
+            .subscription_payments
+            .lock()
+            .await
+            .iter()
+            .map(|p| p.invoice_number.clone().unwrap())
+            .collect();
+        numbers.sort();
+        let expected: Vec<String> = (1..=20)
+            .map(|seq| SubscriptionPayment::format_invoice_number(1, year, seq))
+            .collect();
+        assert_eq!(numbers, expected);
+        assert_eq!(expected[0], format!("LNVPS-1-{}-000001", year));
+    }
+
     /// VM path: time_value is set — subscription expires extended by that many seconds.
     #[tokio::test]
     async fn test_subscription_payment_paid_vm_extends_by_time_value() {
@@ -5192,6 +6718,44 @@ mod tests {
         assert_ne!(page1[0].id, page2[0].id);
     }
 
+    /// list_vm_subscription_payments_after continues from the cursor row even
+    /// when a newer payment is inserted between page fetches.
+    #[tokio::test]
+    async fn test_list_vm_subscription_payments_after_stable_on_insert() {
+        let db = MockDb::default();
+        {
+            let mut vms = db.vms.lock().await;
+            vms.insert(1, MockDb::mock_vm());
+        }
+        let now = Utc::now();
+        for i in 0u8..5 {
+            let mut p = make_payment(1, Some(86400));
+            p.id = vec![i; 16];
+            p.created = now + chrono::Duration::seconds(i as i64);
+            db.insert_subscription_payment(&p).await.unwrap();
+        }
+
+        let page0 = db
+            .list_vm_subscription_payments_after(1, None, 2)
+            .await
+            .unwrap();
+        assert_eq!(page0.len(), 2);
+
+        // New payment arrives, with offset paging this would repeat page0's last row
+        let mut p = make_payment(1, Some(86400));
+        p.id = vec![9; 16];
+        p.created = now + chrono::Duration::seconds(10);
+        db.insert_subscription_payment(&p).await.unwrap();
+
+        let last = page0.last().unwrap();
+        let page1 = db
+            .list_vm_subscription_payments_after(1, Some((last.created, last.id.clone())), 10)
+            .await
+            .unwrap();
+        let ids: Vec<u8> = page0.iter().chain(page1.iter()).map(|p| p.id[0]).collect();
+        assert_eq!(ids, vec![4, 3, 2, 1, 0]);
+    }
+
     // =========================================================================
     // Subscription lifecycle DB tests (Increment 15)
     // =========================================================================
@@ -6103,6 +7667,14 @@ impl MockDnsServer {
         }
     }
 
+    /// A server with its own zones, not shared with [`MockDnsServer::new`].
+    pub fn isolated() -> Self {
+        Self {
+            zones: Default::default(),
+            fail_kind: Default::default(),
+        }
+    }
+
     /// Make `add_record` fail for records of the given kind ("A", "AAAA",
     /// "PTR") or "*" for all kinds.
     pub async fn fail_on_kind(kind: &str) {
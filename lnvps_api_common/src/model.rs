@@ -1,13 +1,13 @@
-use crate::VmRunningState;
 use crate::pricing::PricingEngine;
+use crate::{GuestInfo, VmRunningState};
 use anyhow::{Result, anyhow, bail};
 use chrono::{DateTime, Days, Utc};
 use futures::future::join_all;
 use ipnetwork::IpNetwork;
 use lnvps_db::{
     CpuArch, CpuFeature, CpuMfg, IpRange, LNVpsDb, LNVpsDbBase, Region, Subscription,
-    SubscriptionLineItem, SubscriptionType, Vm, VmCostPlan, VmCustomPricing, VmCustomPricingDisk,
-    VmCustomTemplate, VmHost, VmTemplate,
+    SubscriptionLineItem, SubscriptionType, Vm, VmBandwidthUsage, VmCostPlan, VmCustomPricing,
+    VmCustomPricingDisk, VmCustomTemplate, VmHost, VmTemplate,
 };
 use payments_rs::currency::{Currency, CurrencyAmount};
 use serde::{Deserialize, Serialize};
@@ -102,13 +102,15 @@ impl ApiVmTemplate {
         let template = db.get_vm_template(template_id).await?;
         let cost_plan = db.get_cost_plan(template.cost_plan_id).await?;
         let region = db.get_host_region(template.region_id).await?;
-        Self::from_standard_data(&template, &cost_plan, &region)
+        let company = db.get_company(region.company_id).await?;
+        Self::from_standard_data(&template, &cost_plan, &region, company.tax_display)
     }
 
     pub async fn from_custom(db: &Arc<dyn LNVpsDb>, vm_id: u64, template_id: u64) -> Result<Self> {
         let template = db.get_custom_vm_template(template_id).await?;
         let pricing = db.get_custom_pricing(template.pricing_id).await?;
         let region = db.get_host_region(pricing.region_id).await?;
+        let company = db.get_company(region.company_id).await?;
         let price = PricingEngine::get_custom_vm_cost_amount(db, vm_id, &template).await?;
         Ok(Self {
             id: template.id,
@@ -144,11 +146,7 @@ impl ApiVmTemplate {
                 interval_amount: 1,
                 interval_type: ApiIntervalType::Month,
             },
-            region: ApiVmHostRegion {
-                id: region.id,
-                name: region.name,
-                company_id: region.company_id,
-            },
+            region: ApiVmHostRegion::new(&region, company.tax_display),
         })
     }
 
@@ -166,6 +164,7 @@ impl ApiVmTemplate {
         template: &VmTemplate,
         cost_plan: &VmCostPlan,
         region: &Region,
+        tax_display: lnvps_db::TaxDisplay,
     ) -> Result<Self> {
         Ok(Self {
             id: template.id,
@@ -203,11 +202,7 @@ impl ApiVmTemplate {
                 interval_amount: cost_plan.interval_amount,
                 interval_type: cost_plan.interval_type.into(),
             },
-            region: ApiVmHostRegion {
-                id: region.id,
-                name: region.name.clone(),
-                company_id: region.company_id,
-            },
+            region: ApiVmHostRegion::new(region, tax_display),
         })
     }
 }
@@ -260,6 +255,46 @@ pub struct ApiVmStatus {
     /// for a reinstall. `None`/omitted when the host arch is unknown.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub cpu_arch: Option<String>,
+    /// Addresses and uptime reported by the guest agent, only filled on the
+    /// single VM endpoint while the VM is running and its agent answers
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub guest_info: Option<GuestInfo>,
+    /// Network transfer this month, only filled on the single VM endpoint
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bandwidth_usage: Option<ApiVmBandwidthUsage>,
+}
+
+/// Network transfer of a VM in the current calendar month (UTC)
+#[derive(Serialize, Debug, PartialEq)]
+pub struct ApiVmBandwidthUsage {
+    /// Start of the month the totals are for
+    pub month: DateTime<Utc>,
+    /// Bytes received
+    pub bytes_in: u64,
+    /// Bytes sent
+    pub bytes_out: u64,
+    /// Bytes of transfer (in + out) included per month by the VM's template,
+    /// omitted when unmetered
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quota: Option<u64>,
+}
+
+impl ApiVmBandwidthUsage {
+    /// Transfer of `vm` in the month `now` falls in
+    pub async fn load(db: &Arc<dyn LNVpsDb>, vm: &Vm, now: DateTime<Utc>) -> Result<Self> {
+        let month = VmBandwidthUsage::month_of(now);
+        let usage = db.get_vm_bandwidth_usage(vm.id, month).await?;
+        let quota = match vm.template_id {
+            Some(t) => db.get_vm_template(t).await?.bandwidth_quota,
+            None => None,
+        };
+        Ok(Self {
+            month,
+            bytes_in: usage.as_ref().map(|u| u.bytes_in).unwrap_or(0),
+            bytes_out: usage.as_ref().map(|u| u.bytes_out).unwrap_or(0),
+            quota,
+        })
+    }
 }
 
 /// Grace period (days) for a subscription, tiered by how long the subscription
@@ -386,6 +421,8 @@ pub async fn vm_to_status(
             arch => Some(arch.to_string()),
         }),
         max_prepay_days,
+        guest_info: None,
+        bandwidth_usage: None,
     })
 }
 
@@ -402,9 +439,11 @@ impl ApiVmIpAssignment {
     pub fn from(ip: &lnvps_db::VmIpAssignment, range: &lnvps_db::IpRange) -> Self {
         ApiVmIpAssignment {
             id: ip.id,
+            // a delegated prefix is shown with its own length rather than the range's
             ip: IpNetwork::new(
                 IpNetwork::from_str(&ip.ip).unwrap().ip(),
-                IpNetwork::from_str(&range.cidr).unwrap().prefix(),
+                ip.prefix_length
+                    .unwrap_or(IpNetwork::from_str(&range.cidr).unwrap().prefix()),
             )
             .unwrap()
             .to_string(),
@@ -468,6 +507,37 @@ impl From<ApiDiskInterface> for lnvps_db::DiskInterface {
     }
 }
 
+/// Where new VMs are placed relative to the user's other VMs
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ApiPlacementPolicy {
+    None,
+    /// Prefer the hosts running the fewest of the user's VMs
+    Spread,
+    /// Prefer hosts already running the user's VMs
+    Pack,
+}
+
+impl From<lnvps_db::PlacementPolicy> for ApiPlacementPolicy {
+    fn from(value: lnvps_db::PlacementPolicy) -> Self {
+        match value {
+            lnvps_db::PlacementPolicy::None => Self::None,
+            lnvps_db::PlacementPolicy::Spread => Self::Spread,
+            lnvps_db::PlacementPolicy::Pack => Self::Pack,
+        }
+    }
+}
+
+impl From<ApiPlacementPolicy> for lnvps_db::PlacementPolicy {
+    fn from(value: ApiPlacementPolicy) -> Self {
+        match value {
+            ApiPlacementPolicy::None => Self::None,
+            ApiPlacementPolicy::Spread => Self::Spread,
+            ApiPlacementPolicy::Pack => Self::Pack,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct ApiVmTemplate {
     pub id: u64,
@@ -518,6 +588,87 @@ impl From<ApiIntervalType> for lnvps_db::IntervalType {
     }
 }
 
+/// Whether prices are shown including (`inclusive`) or excluding (`exclusive`) tax
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ApiTaxDisplay {
+    #[default]
+    Exclusive,
+    Inclusive,
+}
+
+impl From<lnvps_db::TaxDisplay> for ApiTaxDisplay {
+    fn from(value: lnvps_db::TaxDisplay) -> Self {
+        match value {
+            lnvps_db::TaxDisplay::Exclusive => Self::Exclusive,
+            lnvps_db::TaxDisplay::Inclusive => Self::Inclusive,
+        }
+    }
+}
+
+impl From<ApiTaxDisplay> for lnvps_db::TaxDisplay {
+    fn from(value: ApiTaxDisplay) -> Self {
+        match value {
+            ApiTaxDisplay::Exclusive => Self::Exclusive,
+            ApiTaxDisplay::Inclusive => Self::Inclusive,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ApiNoticeSeverity {
+    Info,
+    Warning,
+    Critical,
+}
+
+impl From<lnvps_db::NoticeSeverity> for ApiNoticeSeverity {
+    fn from(value: lnvps_db::NoticeSeverity) -> Self {
+        match value {
+            lnvps_db::NoticeSeverity::Info => Self::Info,
+            lnvps_db::NoticeSeverity::Warning => Self::Warning,
+            lnvps_db::NoticeSeverity::Critical => Self::Critical,
+        }
+    }
+}
+
+impl From<ApiNoticeSeverity> for lnvps_db::NoticeSeverity {
+    fn from(value: ApiNoticeSeverity) -> Self {
+        match value {
+            ApiNoticeSeverity::Info => Self::Info,
+            ApiNoticeSeverity::Warning => Self::Warning,
+            ApiNoticeSeverity::Critical => Self::Critical,
+        }
+    }
+}
+
+/// A banner to show customers, e.g. planned maintenance
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ApiSystemNotice {
+    pub id: u64,
+    pub message: String,
+    pub severity: ApiNoticeSeverity,
+    pub starts: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ends: Option<DateTime<Utc>>,
+    /// Regions affected, empty for all regions
+    pub regions: Vec<u64>,
+}
+
+impl From<lnvps_db::SystemNotice> for ApiSystemNotice {
+    fn from(value: lnvps_db::SystemNotice) -> Self {
+        Self {
+            id: value.id,
+            message: value.message,
+            severity: value.severity.into(),
+            starts: value.starts,
+            ends: value.ends,
+            regions: value.regions.into_inner(),
+        }
+    }
+}
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ApiCurrency {
     EUR,
@@ -579,6 +730,20 @@ pub struct ApiVmHostRegion {
     /// Seller company id for this region; use with the account `tax` info to
     /// determine the VAT rate that applies to payments for VMs in this region.
     pub company_id: u64,
+    /// Whether the seller shows prices including or excluding tax. Prices in
+    /// this region are always net, add the VAT rate for `inclusive`.
+    pub tax_display: ApiTaxDisplay,
+}
+
+impl ApiVmHostRegion {
+    pub fn new(region: &Region, tax_display: lnvps_db::TaxDisplay) -> Self {
+        Self {
+            id: region.id,
+            name: region.name.clone(),
+            company_id: region.company_id,
+            tax_display: tax_display.into(),
+        }
+    }
 }
 
 // Shared models used by ApiVmStatus
@@ -760,15 +925,12 @@ impl ApiCustomTemplateParams {
         pricing: &VmCustomPricing,
         disks: &Vec<VmCustomPricingDisk>,
         region: &Region,
+        tax_display: lnvps_db::TaxDisplay,
     ) -> Self {
         ApiCustomTemplateParams {
             id: pricing.id,
             name: pricing.name.clone(),
-            region: ApiVmHostRegion {
-                id: region.id,
-                name: region.name.clone(),
-                company_id: region.company_id,
-            },
+            region: ApiVmHostRegion::new(region, tax_display),
             cpu_features: pricing
                 .cpu_features
                 .iter()
@@ -954,6 +1116,9 @@ pub struct HostVmSpec {
     pub disk_storage: Option<String>,
     /// Primary NIC MAC address
     pub mac_address: Option<String>,
+    /// Static IPs configured on the VM (without prefix length)
+    #[serde(default)]
+    pub ip_addresses: Vec<String>,
     /// Whether the VM is currently running
     pub running: bool,
 }
@@ -1,11 +1,11 @@
 use anyhow::{Context, Result, bail};
 use ipnetwork::{IpNetwork, NetworkSize};
-use lnvps_db::{IpRange, IpRangeAllocationMode, LNVpsDb};
+use lnvps_db::{IpRange, IpRangeAllocationMode, LNVpsDb, VmIpAssignment};
 use log::warn;
 use rand::Rng;
 use rand::prelude::SliceRandom;
 use std::collections::HashSet;
-use std::net::{IpAddr, Ipv6Addr};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::sync::Arc;
 
 /// Parse gateway string as IpNetwork, with backward compatibility for plain IP addresses.
@@ -134,9 +134,12 @@ impl NetworkProvisioner {
 
     pub async fn pick_ip_from_range(&self, range: &IpRange) -> Result<AvailableIp> {
         let range_cidr: IpNetwork = range.cidr.parse()?;
-        let ips = self.db.list_vm_ip_assignments_in_range(range.id).await?;
+        let assignments = self.db.list_vm_ip_assignments_in_range(range.id).await?;
         // Parse stored IPs (stored as plain IP addresses)
-        let mut ips: HashSet<IpAddr> = ips.iter().filter_map(|i| i.ip.parse().ok()).collect();
+        let mut ips: HashSet<IpAddr> = assignments
+            .iter()
+            .filter_map(|i| i.ip.parse().ok())
+            .collect();
 
         let gateway: IpNetwork = parse_gateway(&range.gateway)?;
 
@@ -145,17 +148,8 @@ impl NetworkProvisioner {
         // This allows VMs to reach gateways outside their allocation range.
         let max_net = range_cidr.prefix().min(gateway.prefix());
 
-        // mark some IPS as always used
-        // Namely:
-        //  .0 & .255 of /24 (first and last)
-        //  gateway ip of the range (only if it falls within the CIDR)
-        if !range.use_full_range && range_cidr.is_ipv4() {
-            ips.insert(range_cidr.iter().next().unwrap());
-            ips.insert(range_cidr.iter().last().unwrap());
-        }
-        if range_cidr.contains(gateway.ip()) {
-            ips.insert(gateway.ip());
-        }
+        // mark the gateway and (unless use_full_range) network/broadcast as used
+        ips.extend(Self::reserved_ips(range, &range_cidr)?);
 
         // Early exit if the range is already full
         if let NetworkSize::V4(size) = range_cidr.size() {
@@ -167,9 +161,7 @@ impl NetworkProvisioner {
         // pick an IP from the range
         let ip_pick = {
             match &range.allocation_mode {
-                IpRangeAllocationMode::Sequential => range_cidr
-                    .iter()
-                    .find(|i| !ips.contains(i))
+                IpRangeAllocationMode::Sequential => Self::next_free_ip(&range_cidr, &ips)
                     .and_then(|i| IpNetwork::new(i, max_net).ok()),
                 IpRangeAllocationMode::Random => {
                     let mut rng = rand::rng();
@@ -202,6 +194,13 @@ impl NetworkProvisioner {
                         Some(range_cidr)
                     }
                 }
+                IpRangeAllocationMode::DelegatedPrefix => {
+                    if range_cidr.is_ipv4() {
+                        bail!("Cannot delegate a prefix from an IPv4 range")
+                    }
+                    let taken = Self::taken_prefixes(&assignments, &ips);
+                    allocate_subnet(&range_cidr, range.delegated_prefix_length(), &taken)
+                }
             }
         }
         .context("No ips available in range")?;
@@ -215,6 +214,62 @@ impl NetworkProvisioner {
         })
     }
 
+    /// IPs in a range which are never assigned to a VM: the gateway (only if it
+    /// falls within the CIDR) and, for IPv4 ranges without `use_full_range`,
+    /// the network and broadcast addresses.
+    pub fn reserved_ips(range: &IpRange, network: &IpNetwork) -> Result<HashSet<IpAddr>> {
+        let mut reserved = HashSet::new();
+        if !range.use_full_range && network.is_ipv4() {
+            reserved.insert(network.network());
+            if let IpNetwork::V4(v4) = network {
+                reserved.insert(IpAddr::V4(v4.broadcast()));
+            }
+        }
+        let gateway = parse_gateway(&range.gateway)?;
+        if network.contains(gateway.ip()) {
+            reserved.insert(gateway.ip());
+        }
+        Ok(reserved)
+    }
+
+    /// Networks in a range which a new delegated prefix must not overlap: every
+    /// live assignment (a delegated prefix or a single address) plus `reserved`.
+    pub fn taken_prefixes(
+        assignments: &[VmIpAssignment],
+        reserved: &HashSet<IpAddr>,
+    ) -> Vec<IpNetwork> {
+        assignments
+            .iter()
+            .filter_map(Self::assignment_network)
+            .chain(
+                reserved
+                    .iter()
+                    .filter_map(|ip| IpNetwork::new(*ip, max_prefix(ip)).ok()),
+            )
+            .collect()
+    }
+
+    /// The network covered by an assignment, a single address is a host route
+    pub fn assignment_network(assignment: &VmIpAssignment) -> Option<IpNetwork> {
+        let ip: IpAddr = assignment.ip.trim().parse().ok()?;
+        IpNetwork::new(ip, assignment.prefix_length.unwrap_or(max_prefix(&ip))).ok()
+    }
+
+    /// The link-local address a delegated prefix is routed to, the EUI-64
+    /// address the VM derives from its MAC on the shared link
+    pub fn link_local_next_hop(mac: &str) -> Result<IpAddr> {
+        let mac = Self::parse_mac(mac)?;
+        Self::calculate_eui64(&mac, &IpNetwork::V6("fe80::/64".parse()?))
+    }
+
+    /// The lowest IP in `network` which is not in `used`.
+    ///
+    /// `used` must already contain [NetworkProvisioner::reserved_ips] for the
+    /// range, returns `None` when the range is full.
+    pub fn next_free_ip(network: &IpNetwork, used: &HashSet<IpAddr>) -> Option<IpAddr> {
+        network.iter().find(|i| !used.contains(i))
+    }
+
     pub fn calculate_eui64(mac: &[u8; 6], prefix: &IpNetwork) -> Result<IpAddr> {
         if prefix.is_ipv4() {
             bail!("Prefix must be IPv6".to_string())
@@ -271,19 +326,11 @@ impl NetworkProvisioner {
 
         // Count reserved IPs that actually fall within the CIDR.
         // The gateway may be outside the range (e.g. a shared upstream gateway),
-        // in which case it does not consume a slot in this range.
-        let mut reserved: u64 = 0;
-
-        if let Ok(gw) = parse_gateway(&range.gateway) {
-            if network.contains(gw.ip()) {
-                reserved += 1;
-            }
-        }
-
-        // If not using the full range, network and broadcast addresses are also reserved.
-        if !range.use_full_range {
-            reserved += 2;
-        }
+        // in which case it does not consume a slot in this range. A gateway on
+        // the network/broadcast address is only counted once.
+        let reserved = Self::reserved_ips(range, &network)
+            .map(|r| r.len() as u64)
+            .unwrap_or(if range.use_full_range { 0 } else { 2 });
 
         let available = total_ips
             .saturating_sub(reserved)
@@ -330,22 +377,7 @@ impl NetworkProvisioner {
             .filter_map(|a| a.ip.parse().ok())
             .collect();
 
-        // Parse gateway to get reserved IPs
-        let gateway = parse_gateway(&range.gateway)?;
-
-        // Build set of reserved IPs
-        let mut reserved_ips: HashSet<IpAddr> = HashSet::new();
-        reserved_ips.insert(gateway.ip());
-
-        // If not using full range, reserve first and last IPs (network + broadcast)
-        if !range.use_full_range {
-            if let Some(first) = network.iter().next() {
-                reserved_ips.insert(first);
-            }
-            if let Some(last) = network.iter().last() {
-                reserved_ips.insert(last);
-            }
-        }
+        let reserved_ips = Self::reserved_ips(range, &network)?;
 
         // Collect free IPs
         let free_ips: Vec<IpAddr> = network
@@ -357,6 +389,101 @@ impl NetworkProvisioner {
     }
 }
 
+// ===========================================================================
+// CIDR sub-allocation
+// ===========================================================================
+
+/// Host prefix length for an address (/32 or /128)
+fn max_prefix(ip: &IpAddr) -> u8 {
+    if ip.is_ipv4() { 32 } else { 128 }
+}
+
+/// Inclusive numeric bounds `[start, end]` of a CIDR (both families widened to
+/// `u128`).
+fn net_bounds(n: &IpNetwork) -> (u128, u128) {
+    match n {
+        IpNetwork::V4(v4) => {
+            let base = u32::from(v4.network()) as u128;
+            let size = 1u128 << (32 - v4.prefix() as u32);
+            (base, base + size - 1)
+        }
+        IpNetwork::V6(v6) => {
+            let base = u128::from(v6.network());
+            let host_bits = 128 - v6.prefix() as u32;
+            if host_bits >= 128 {
+                (0, u128::MAX)
+            } else {
+                (base, base + (1u128 << host_bits) - 1)
+            }
+        }
+    }
+}
+
+/// Build a CIDR from a numeric network address, prefix, and family.
+fn make_net(start: u128, prefix: u8, v4: bool) -> Option<IpNetwork> {
+    if v4 {
+        IpNetwork::new(IpAddr::V4(Ipv4Addr::from(start as u32)), prefix).ok()
+    } else {
+        IpNetwork::new(IpAddr::V6(Ipv6Addr::from(start)), prefix).ok()
+    }
+}
+
+/// Find the first sub-prefix of length `target_len` inside `block` that does
+/// not overlap any CIDR in `taken`. Returns `None` if the parameters are
+/// invalid (wrong family / bad length) or the block is fully allocated.
+///
+/// Runs in time proportional to the number of `taken` allocations, not the size
+/// of the address space: it walks candidates in order and jumps past occupied
+/// ranges, so a mostly-empty block is served from its first free slot instantly.
+pub fn allocate_subnet(
+    block: &IpNetwork,
+    target_len: u8,
+    taken: &[IpNetwork],
+) -> Option<IpNetwork> {
+    let v4 = block.is_ipv4();
+    let max_bits: u32 = if v4 { 32 } else { 128 };
+    if (target_len as u32) < block.prefix() as u32 || target_len as u32 > max_bits {
+        return None;
+    }
+    let host_bits = max_bits - target_len as u32;
+    if host_bits >= 128 {
+        return None;
+    }
+    let size = 1u128 << host_bits;
+    let (block_start, block_end) = net_bounds(block);
+
+    // Only same-family allocations can overlap; sort by start for the walk.
+    let mut taken_bounds: Vec<(u128, u128)> = taken
+        .iter()
+        .filter(|t| t.is_ipv4() == v4)
+        .map(net_bounds)
+        .collect();
+    taken_bounds.sort_unstable();
+
+    let mut cur = block_start;
+    loop {
+        if cur > block_end {
+            return None;
+        }
+        let cand_end = cur.checked_add(size - 1)?;
+        if cand_end > block_end {
+            return None;
+        }
+        // Does the candidate overlap any taken range?
+        if let Some((_, te)) = taken_bounds
+            .iter()
+            .find(|(ts, te)| cur <= *te && *ts <= cand_end)
+        {
+            // Jump to the next size-aligned slot after the occupied range.
+            let after = te.checked_add(1)?;
+            let aligned = (after + size - 1) & !(size - 1);
+            cur = if aligned <= cur { cur + size } else { aligned };
+        } else {
+            return make_net(cur, target_len, v4);
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -680,6 +807,99 @@ mod tests {
         assert!(free_ip_strs.contains(&"192.168.1.3".to_string()));
     }
 
+    /// A /30 with the gateway outside the range
+    fn slash30_range(id: u64, use_full_range: bool) -> IpRange {
+        IpRange {
+            id,
+            cidr: "10.0.0.0/30".to_string(),
+            gateway: "192.168.1.1".to_string(),
+            enabled: true,
+            region_id: 1,
+            allocation_mode: IpRangeAllocationMode::Sequential,
+            use_full_range,
+            ..Default::default()
+        }
+    }
+
+    fn ips(ips: &[&str]) -> Vec<IpAddr> {
+        ips.iter().map(|i| i.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn test_next_free_ip_slash30_boundaries() {
+        for (use_full_range, expected) in [
+            (false, ips(&["10.0.0.1", "10.0.0.2"])),
+            (true, ips(&["10.0.0.0", "10.0.0.1", "10.0.0.2", "10.0.0.3"])),
+        ] {
+            let range = slash30_range(1, use_full_range);
+            let net: IpNetwork = range.cidr.parse().unwrap();
+            let mut used = NetworkProvisioner::reserved_ips(&range, &net).unwrap();
+            assert_eq!(used.len(), 4 - expected.len());
+
+            let mut picked = vec![];
+            while let Some(ip) = NetworkProvisioner::next_free_ip(&net, &used) {
+                assert_eq!(
+                    NetworkProvisioner::count_available_ips(&range, picked.len() as u64),
+                    Some((expected.len() - picked.len()) as u64)
+                );
+                picked.push(ip);
+                used.insert(ip);
+            }
+            assert_eq!(picked, expected, "use_full_range={}", use_full_range);
+            assert_eq!(
+                NetworkProvisioner::count_available_ips(&range, picked.len() as u64),
+                Some(0)
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pick_ip_slash30_exhausts_at_boundary() {
+        for (use_full_range, expected) in [
+            (false, ips(&["10.0.0.1", "10.0.0.2"])),
+            (true, ips(&["10.0.0.0", "10.0.0.1", "10.0.0.2", "10.0.0.3"])),
+        ] {
+            let db = MockDb::default();
+            let range = slash30_range(104, use_full_range);
+            db.ip_range.lock().await.insert(range.id, range.clone());
+            let db: Arc<dyn LNVpsDb> = Arc::new(db);
+            let mgr = NetworkProvisioner::new(db.clone());
+
+            let mut picked = vec![];
+            while let Ok(ip) = mgr.pick_ip_from_range(&range).await {
+                assert!(picked.len() < 4, "picked more IPs than the /30 holds");
+                db.insert_vm_ip_assignment(&VmIpAssignment {
+                    vm_id: picked.len() as u64,
+                    ip_range_id: range.id,
+                    ip: ip.ip.ip().to_string(),
+                    ..Default::default()
+                })
+                .await
+                .unwrap();
+                picked.push(ip.ip.ip());
+            }
+            assert_eq!(picked, expected, "use_full_range={}", use_full_range);
+            assert!(
+                mgr.list_free_ips_in_range(range.id)
+                    .await
+                    .unwrap()
+                    .is_empty()
+            );
+        }
+    }
+
+    /// A gateway on the network/broadcast address is already reserved, it must
+    /// not be counted a second time
+    #[test]
+    fn test_count_available_ips_gateway_on_boundary() {
+        let mut range = slash30_range(1, false);
+        range.gateway = "10.0.0.3".to_string();
+        assert_eq!(NetworkProvisioner::count_available_ips(&range, 0), Some(2));
+
+        range.use_full_range = true;
+        assert_eq!(NetworkProvisioner::count_available_ips(&range, 0), Some(3));
+    }
+
     /// Reproduces the bug where available_ips showed 0 but free_ips returned 1 entry
     /// because the gateway was outside the CIDR and was incorrectly counted as reserved.
     #[test]
@@ -861,4 +1081,124 @@ mod tests {
         assert!(result.is_err());
         assert!(result.unwrap_err().to_string().contains("IPv4 ranges"));
     }
+
+    fn delegated_range(id: u64, delegated_prefix: Option<u8>) -> IpRange {
+        IpRange {
+            id,
+            cidr: "2001:db8:100::/48".to_string(),
+            gateway: "fe80::1".to_string(),
+            enabled: true,
+            region_id: 1,
+            allocation_mode: IpRangeAllocationMode::DelegatedPrefix,
+            delegated_prefix,
+            ..Default::default()
+        }
+    }
+
+    /// Pick `n` prefixes from a range, recording each as an assignment
+    async fn pick_prefixes(db: &Arc<dyn LNVpsDb>, range: &IpRange, n: usize) -> Vec<IpNetwork> {
+        let mgr = NetworkProvisioner::new(db.clone());
+        let mut picked = vec![];
+        for i in 0..n {
+            let p = mgr.pick_ip_from_range(range).await.unwrap();
+            db.insert_vm_ip_assignment(&VmIpAssignment {
+                vm_id: i as u64,
+                ip_range_id: range.id,
+                ip: p.ip.network().to_string(),
+                prefix_length: Some(p.ip.prefix()),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+            picked.push(p.ip);
+        }
+        picked
+    }
+
+    #[tokio::test]
+    async fn test_pick_delegated_prefix_non_overlapping_slash64s() {
+        let db = MockDb::default();
+        let range = delegated_range(110, None);
+        db.ip_range.lock().await.insert(range.id, range.clone());
+        let db: Arc<dyn LNVpsDb> = Arc::new(db);
+
+        let picked = pick_prefixes(&db, &range, 4).await;
+        let block: IpNetwork = range.cidr.parse().unwrap();
+        for (i, p) in picked.iter().enumerate() {
+            assert_eq!(p.prefix(), 64);
+            assert_eq!(p.ip(), p.network(), "prefix must be its network address");
+            assert!(block.contains(p.network()));
+            for q in &picked[i + 1..] {
+                assert!(!p.contains(q.network()) && !q.contains(p.network()));
+            }
+        }
+        assert_eq!(
+            picked,
+            [
+                "2001:db8:100::/64",
+                "2001:db8:100:1::/64",
+                "2001:db8:100:2::/64",
+                "2001:db8:100:3::/64"
+            ]
+            .map(|s| s.parse::<IpNetwork>().unwrap())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_pick_delegated_prefix_skips_existing_assignments() {
+        let db = MockDb::default();
+        let range = delegated_range(111, None);
+        db.ip_range.lock().await.insert(range.id, range.clone());
+        // a wider delegation covers the first 256 /64s, a single address takes one more
+        for (ip, prefix_length) in [("2001:db8:100::", Some(56)), ("2001:db8:100:100::5", None)] {
+            db.insert_vm_ip_assignment(&VmIpAssignment {
+                vm_id: 99,
+                ip_range_id: range.id,
+                ip: ip.to_string(),
+                prefix_length,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        }
+        let db: Arc<dyn LNVpsDb> = Arc::new(db);
+
+        let picked = pick_prefixes(&db, &range, 1).await;
+        assert_eq!(picked[0], "2001:db8:100:101::/64".parse().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_pick_delegated_prefix_configured_length() {
+        let db = MockDb::default();
+        let range = delegated_range(112, Some(56));
+        db.ip_range.lock().await.insert(range.id, range.clone());
+        let db: Arc<dyn LNVpsDb> = Arc::new(db);
+
+        let picked = pick_prefixes(&db, &range, 2).await;
+        assert_eq!(
+            picked,
+            ["2001:db8:100::/56", "2001:db8:100:100::/56"].map(|s| s.parse::<IpNetwork>().unwrap())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_pick_delegated_prefix_ipv4_rejected() {
+        let db: Arc<dyn LNVpsDb> = Arc::new(MockDb::default());
+        let mgr = NetworkProvisioner::new(db);
+        let range = IpRange {
+            cidr: "10.0.0.0/24".to_string(),
+            gateway: "10.0.0.1".to_string(),
+            allocation_mode: IpRangeAllocationMode::DelegatedPrefix,
+            ..Default::default()
+        };
+        assert!(mgr.pick_ip_from_range(&range).await.is_err());
+    }
+
+    #[test]
+    fn test_link_local_next_hop() {
+        assert_eq!(
+            NetworkProvisioner::link_local_next_hop("bc:24:11:00:00:01").unwrap(),
+            "fe80::be24:11ff:fe00:1".parse::<IpAddr>().unwrap()
+        );
+    }
 }
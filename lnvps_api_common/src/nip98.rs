@@ -1,7 +1,7 @@
 use anyhow::bail;
 use axum::{
     extract::FromRequestParts,
-    http::{StatusCode, Uri, request::Parts},
+    http::{Uri, request::Parts},
 };
 use base64::Engine;
 use base64::prelude::BASE64_STANDARD;
@@ -9,8 +9,10 @@ use log::debug;
 use nostr::{Event, JsonUtil, Kind, PublicKey, Timestamp};
 
 use crate::session::{SessionClaims, verify_session_token};
+use crate::{ApiError, ApiErrorCode};
 
 /// How a request authenticated.
+#[derive(Clone)]
 pub enum AuthKind {
     /// NIP-98 signed Nostr HTTP-auth event. `pubkey` is a real Nostr key.
     Nostr(Event),
@@ -27,6 +29,11 @@ pub enum AuthKind {
 ///
 /// Handlers should use [`Nip98Auth::pubkey`] to get the 32-byte identity
 /// (works for both schemes) rather than reaching for the underlying event.
+///
+/// An already-verified auth can be placed in the request extensions for
+/// internal sub-requests (e.g. `POST /api/v1/batch`), the extractor then uses
+/// it as-is instead of reading the `Authorization` header.
+#[derive(Clone)]
 pub struct Nip98Auth {
     /// The concrete auth scheme and its payload.
     pub kind: AuthKind,
@@ -204,53 +211,78 @@ mod tests {
         ]);
         assert!(auth.check("/api/v1/account", "GET").is_ok());
     }
+
+    /// Missing or bad auth is rejected with the `unauthorized` error code.
+    #[tokio::test]
+    async fn rejections_use_unauthorized_code() {
+        let req = axum::http::Request::builder().body(()).unwrap();
+        let (mut parts, _) = req.into_parts();
+        let Err(err) = Nip98Auth::from_request_parts(&mut parts, &()).await else {
+            panic!("missing auth header accepted");
+        };
+        assert_eq!(err.code, axum::http::StatusCode::UNAUTHORIZED);
+        assert_eq!(err.kind, ApiErrorCode::Unauthorized);
+
+        let req = axum::http::Request::builder()
+            .uri("/api/v1/account")
+            .header("authorization", "Nostr not-base64")
+            .body(())
+            .unwrap();
+        let (mut parts, _) = req.into_parts();
+        let Err(err) = Nip98Auth::from_request_parts(&mut parts, &()).await else {
+            panic!("invalid auth accepted");
+        };
+        assert_eq!(err.code, axum::http::StatusCode::UNAUTHORIZED);
+        assert_eq!(err.kind, ApiErrorCode::Unauthorized);
+    }
 }
 
 impl<S> FromRequestParts<S> for Nip98Auth
 where
     S: Send + Sync,
 {
-    type Rejection = (StatusCode, String);
+    type Rejection = ApiError;
 
     fn from_request_parts(
         parts: &mut Parts,
         _state: &S,
     ) -> impl Future<Output = Result<Self, Self::Rejection>> + Send {
         Box::pin(async {
+            // Sub-request dispatched internally on behalf of an authenticated request.
+            // Extensions can't be set by clients, only by our own handlers.
+            if let Some(auth) = parts.extensions.get::<Nip98Auth>() {
+                return Ok(auth.clone());
+            }
+
             let auth_header = parts
                 .headers
                 .get("authorization")
                 .and_then(|v| v.to_str().ok())
-                .ok_or((StatusCode::FORBIDDEN, "Auth header not found".to_string()))?;
+                .ok_or_else(|| ApiError::unauthorized("Auth header not found"))?;
 
             // Session (bearer) scheme: JWT issued after an external OAuth login.
             if let Some(token) = auth_header.strip_prefix("Bearer ") {
                 let claims = verify_session_token(token.trim())
-                    .map_err(|e| (StatusCode::UNAUTHORIZED, format!("Invalid session: {}", e)))?;
+                    .map_err(|e| ApiError::unauthorized(format!("Invalid session: {}", e)))?;
                 return Nip98Auth::from_session_claims(claims)
-                    .map_err(|e| (StatusCode::UNAUTHORIZED, format!("Invalid session: {}", e)));
+                    .map_err(|e| ApiError::unauthorized(format!("Invalid session: {}", e)));
             }
 
             // Nostr (NIP-98) scheme.
             if !auth_header.starts_with("Nostr ") {
-                return Err((
-                    StatusCode::FORBIDDEN,
-                    "Auth scheme must be Nostr or Bearer".to_string(),
+                return Err(ApiError::unauthorized(
+                    "Auth scheme must be Nostr or Bearer",
                 ));
             }
 
             let auth = Nip98Auth::from_base64(&auth_header[6..])
-                .map_err(|e| (StatusCode::UNAUTHORIZED, format!("Invalid auth: {}", e)))?;
+                .map_err(|e| ApiError::unauthorized(format!("Invalid auth: {}", e)))?;
 
             let path = parts.uri.path();
             let method = parts.method.as_str();
 
-            auth.check(path, method).map_err(|e| {
-                (
-                    StatusCode::UNAUTHORIZED,
-                    format!("Auth check failed: {}", e),
-                )
-            })?;
+            auth.check(path, method)
+                .map_err(|e| ApiError::unauthorized(format!("Auth check failed: {}", e)))?;
 
             Ok(auth)
         })
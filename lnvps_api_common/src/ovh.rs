@@ -12,9 +12,14 @@ use crate::json_api::{JsonApi, TokenGen};
 use crate::retry::{OpError, OpResult};
 use anyhow::{Context, Result};
 use chrono::Utc;
-use reqwest::{Method, RequestBuilder, Url};
+use reqwest::{Method, RequestBuilder, StatusCode, Url};
+use serde::Deserialize;
 use sha1::{Digest, Sha1};
 use std::ops::Sub;
+use std::time::Duration;
+
+/// Wait before retrying a rate limited OVH call which has no `Retry-After`
+const OVH_RATE_LIMIT_DELAY: Duration = Duration::from_secs(10);
 
 /// Generates signed OVH API request headers from an `app_key:app_secret:consumer_key` token.
 #[derive(Clone)]
@@ -124,5 +129,62 @@ pub async fn ovh_json_api(url: &str, token: &str) -> OpResult<JsonApi> {
         false,
         OvhTokenGen::new(delta, token).map_err(OpError::Fatal)?,
     )
+    .map(|api| api.with_rate_limit_parser(ovh_rate_limit))
     .map_err(OpError::Fatal)
 }
+
+/// OVH API error body
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct OvhError {
+    class: Option<String>,
+    http_code: Option<String>,
+}
+
+/// Detect OVH rate limit errors.
+///
+/// OVH reports them as `Client::TooManyRequests` errors, or with an
+/// `httpCode` of `429` on errors raised behind its API gateway.
+pub fn ovh_rate_limit(status: StatusCode, body: &str) -> Option<Duration> {
+    let limited = status == StatusCode::TOO_MANY_REQUESTS
+        || serde_json::from_str::<OvhError>(body).is_ok_and(|e| {
+            e.class.is_some_and(|c| c.ends_with("TooManyRequests"))
+                || e.http_code.is_some_and(|c| c.starts_with("429"))
+        });
+    limited.then_some(OVH_RATE_LIMIT_DELAY)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ovh_rate_limit() {
+        assert_eq!(
+            ovh_rate_limit(
+                StatusCode::TOO_MANY_REQUESTS,
+                r#"{"class":"Client::TooManyRequests","message":"Too many requests"}"#
+            ),
+            Some(OVH_RATE_LIMIT_DELAY)
+        );
+        // gateway errors keep the real status in the body
+        assert_eq!(
+            ovh_rate_limit(
+                StatusCode::FORBIDDEN,
+                r#"{"errorCode":"QUERY_RATE_LIMITED","httpCode":"429 Too Many Requests","message":"Rate limit exceeded"}"#
+            ),
+            Some(OVH_RATE_LIMIT_DELAY)
+        );
+        assert_eq!(
+            ovh_rate_limit(
+                StatusCode::NOT_FOUND,
+                r#"{"class":"Client::NotFound","message":"The requested object (virtualMac) does not exist"}"#
+            ),
+            None
+        );
+        assert_eq!(
+            ovh_rate_limit(StatusCode::BAD_GATEWAY, "<html>bad gateway</html>"),
+            None
+        );
+    }
+}
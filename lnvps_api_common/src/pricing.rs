@@ -1,5 +1,6 @@
 use crate::{
-    ConvertedCurrencyAmount, ExchangeRateService, Ticker, TickerRate, UpgradeConfig, VatClient,
+    ConvertedCurrencyAmount, ExchangeRateService, Ticker, TickerRate, UpgradeConfig,
+    ValidationError, VatClient,
 };
 use anyhow::{Result, anyhow, bail, ensure};
 use chrono::{DateTime, Days, Months, TimeDelta, Utc};
@@ -7,8 +8,8 @@ use ipnetwork::IpNetwork;
 use isocountry::CountryCode;
 use lnvps_db::{
     CpuArch, CpuFeature, CpuMfg, DiskInterface, DiskType, IntervalType, LNVpsDb, PaymentMethod,
-    SubscriptionPayment, SubscriptionPaymentType, Vm, VmCostPlan, VmCustomPricing,
-    VmCustomTemplate,
+    SubscriptionPayment, SubscriptionPaymentType, TaxDisplay, Vm, VmCostPlan, VmCustomPricing,
+    VmCustomPricingDisk, VmCustomTemplate,
 };
 use payments_rs::currency::{Currency, CurrencyAmount};
 #[cfg(test)]
@@ -17,6 +18,38 @@ use std::ops::{Add, Sub};
 use std::str::FromStr;
 use std::sync::Arc;
 
+/// Smallest resources any custom VM may be ordered with, whatever its plan
+/// says. Custom pricing plans cannot be saved with minimums below these.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct CustomVmFloors {
+    /// Default: 1
+    pub min_cpu: u16,
+    /// Bytes, default: 1GB
+    pub min_memory: u64,
+    /// Bytes, default: 1GB
+    pub min_disk: u64,
+}
+
+impl Default for CustomVmFloors {
+    fn default() -> Self {
+        Self {
+            min_cpu: 1,
+            min_memory: crate::GB,
+            min_disk: crate::GB,
+        }
+    }
+}
+
+/// Format a byte count in whole GB where possible, for error messages
+fn format_bytes(bytes: u64) -> String {
+    if bytes % crate::GB == 0 {
+        format!("{}GB", bytes / crate::GB)
+    } else {
+        format!("{}MB", bytes / crate::MB)
+    }
+}
+
 /// Round milli-satoshi amount up to the nearest satoshi.
 ///
 /// Some Lightning wallets don't handle milli-sats correctly, so we round
@@ -44,6 +77,53 @@ pub struct UpgradeCostQuote {
     /// Payment processing fee (in the upgrade currency's minor units), grossed up
     /// on the upgrade amount + tax. Zero for Lightning.
     pub processing_fee: u64,
+    /// How the seller shows prices to the customer
+    pub tax_display: TaxDisplay,
+}
+
+impl UpgradeCostQuote {
+    /// The upgrade amount as net and gross, to show per [Self::tax_display]
+    pub fn price(&self) -> DisplayPrice {
+        DisplayPrice::new(
+            self.upgrade.amount.value(),
+            self.tax.amount,
+            self.tax_display,
+        )
+    }
+}
+
+/// A net price and its tax, shown to the customer including or excluding tax
+/// depending on the seller's [TaxDisplay].
+///
+/// Only for display, amounts are always charged and stored as net + tax.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DisplayPrice {
+    /// Price before tax
+    pub net: u64,
+    /// Tax charged on `net`
+    pub tax: u64,
+    /// Price including tax (`net + tax`)
+    pub gross: u64,
+    pub tax_display: TaxDisplay,
+}
+
+impl DisplayPrice {
+    pub fn new(net: u64, tax: u64, tax_display: TaxDisplay) -> Self {
+        Self {
+            net,
+            tax,
+            gross: net + tax,
+            tax_display,
+        }
+    }
+
+    /// The amount to show as the price
+    pub fn shown(&self) -> u64 {
+        match self.tax_display {
+            TaxDisplay::Exclusive => self.net,
+            TaxDisplay::Inclusive => self.gross,
+        }
+    }
 }
 
 /// Information about remaining time and costs for a VM
@@ -275,11 +355,24 @@ pub struct PricingEngine {
     db: Arc<dyn LNVpsDb>,
     rates: Arc<dyn ExchangeRateService>,
     vat: VatClient,
+    custom_floors: CustomVmFloors,
 }
 
 impl PricingEngine {
-    pub fn new(db: Arc<dyn LNVpsDb>, rates: Arc<dyn ExchangeRateService>, vat: VatClient) -> Self {
-        Self { db, rates, vat }
+    /// `custom_floors` are enforced when quoting custom VM upgrades, pass the
+    /// configured `custom-vm-floors`
+    pub fn new(
+        db: Arc<dyn LNVpsDb>,
+        rates: Arc<dyn ExchangeRateService>,
+        vat: VatClient,
+        custom_floors: CustomVmFloors,
+    ) -> Self {
+        Self {
+            db,
+            rates,
+            vat,
+            custom_floors,
+        }
     }
 
     /// The shared VAT client backing this engine (for rate refreshes).
@@ -749,10 +842,22 @@ impl PricingEngine {
         db: &Arc<dyn LNVpsDb>,
         vm_id: u64,
         template: &VmCustomTemplate,
+    ) -> Result<PricingData> {
+        let ips = db.list_vm_ip_assignments(vm_id).await?;
+        let extra_disks = db.list_vm_extra_disks(vm_id).await?;
+        Self::get_custom_template_cost_amount(db, template, &ips, &extra_disks).await
+    }
+
+    /// Cost of a custom template with the given IPs and extra disks, for VMs
+    /// which aren't in the database yet
+    pub async fn get_custom_template_cost_amount(
+        db: &Arc<dyn LNVpsDb>,
+        template: &VmCustomTemplate,
+        ips: &[lnvps_db::VmIpAssignment],
+        extra_disks: &[lnvps_db::VmExtraDisk],
     ) -> Result<PricingData> {
         let pricing = db.get_custom_pricing(template.pricing_id).await?;
         let pricing_disk = db.list_custom_pricing_disk(pricing.id).await?;
-        let ips = db.list_vm_ip_assignments(vm_id).await?;
         let v4s = ips
             .iter()
             .filter(|i| {
@@ -794,7 +899,22 @@ impl PricingEngine {
         let disk_size_gb = template.disk_size.div_ceil(crate::GB);
         let memory_gb = template.memory.div_ceil(crate::GB);
 
-        let disk_cost = disk_size_gb * disk_pricing.cost;
+        let mut disk_cost = disk_size_gb * disk_pricing.cost;
+
+        // Extra data disks are billed at the plan's rate for the storage they
+        // are allocated on.
+        for extra in extra_disks {
+            let storage = db.get_host_disk(extra.disk_id).await?;
+            let rate = if let Some(p) = pricing_disk
+                .iter()
+                .find(|p| p.kind == storage.kind && p.interface == storage.interface)
+            {
+                p
+            } else {
+                bail!("No disk price found for extra disk {}", extra.id)
+            };
+            disk_cost += extra.size.div_ceil(crate::GB) * rate.cost;
+        }
         let cpu_cost = pricing.cpu_cost * template.cpu as u64;
         let memory_cost = pricing.memory_cost * memory_gb;
         let ip4_cost = pricing.ip4_cost * v4s as u64;
@@ -823,42 +943,140 @@ impl PricingEngine {
     /// must always succeed for existing VMs (renewals, subscription backfill),
     /// including grandfathered VMs whose specs predate the current plan limits.
     /// Only genuine order/upgrade entry points call this.
+    ///
+    /// Violations are returned as a [`ValidationError`] so the API answers 400
+    /// with the reason. The plan minimums never go below `floors`.
     pub async fn validate_custom_vm_spec(
         db: &Arc<dyn LNVpsDb>,
         template: &VmCustomTemplate,
+        floors: &CustomVmFloors,
     ) -> Result<()> {
         let pricing = db.get_custom_pricing(template.pricing_id).await?;
         let pricing_disk = db.list_custom_pricing_disk(pricing.id).await?;
         let disk_pricing = pricing_disk
             .iter()
             .find(|p| p.kind == template.disk_type && p.interface == template.disk_interface)
-            .ok_or_else(|| anyhow!("No disk price found"))?;
-
-        if template.cpu < pricing.min_cpu || template.cpu > pricing.max_cpu {
-            bail!(
+            .ok_or_else(|| {
+                ValidationError::new(format!(
+                    "{} {} disks are not offered on this plan",
+                    template.disk_type, template.disk_interface
+                ))
+            })?;
+
+        let min_cpu = pricing.min_cpu.max(floors.min_cpu);
+        if template.cpu < min_cpu || template.cpu > pricing.max_cpu {
+            bail!(ValidationError::new(format!(
                 "CPU count {} out of range ({}-{})",
-                template.cpu,
-                pricing.min_cpu,
-                pricing.max_cpu
-            );
+                template.cpu, min_cpu, pricing.max_cpu
+            )));
         }
-        if template.memory < pricing.min_memory || template.memory > pricing.max_memory {
-            bail!(
+        let min_memory = pricing.min_memory.max(floors.min_memory);
+        if template.memory < min_memory || template.memory > pricing.max_memory {
+            bail!(ValidationError::new(format!(
                 "Memory {} out of range ({}-{})",
-                template.memory,
-                pricing.min_memory,
-                pricing.max_memory
-            );
+                format_bytes(template.memory),
+                format_bytes(min_memory),
+                format_bytes(pricing.max_memory)
+            )));
         }
-        if template.disk_size < disk_pricing.min_disk_size
-            || template.disk_size > disk_pricing.max_disk_size
-        {
-            bail!(
+        let min_disk = disk_pricing.min_disk_size.max(floors.min_disk);
+        if template.disk_size < min_disk || template.disk_size > disk_pricing.max_disk_size {
+            bail!(ValidationError::new(format!(
                 "Disk size {} out of range ({}-{})",
-                template.disk_size,
-                disk_pricing.min_disk_size,
-                disk_pricing.max_disk_size
-            );
+                format_bytes(template.disk_size),
+                format_bytes(min_disk),
+                format_bytes(disk_pricing.max_disk_size)
+            )));
+        }
+        Ok(())
+    }
+
+    /// Check a custom pricing plan's own limits before it is saved, so a plan
+    /// cannot offer zero-sized resources or a range no spec can satisfy.
+    pub fn validate_custom_pricing_limits(
+        pricing: &VmCustomPricing,
+        disks: &[VmCustomPricingDisk],
+        floors: &CustomVmFloors,
+    ) -> Result<(), ValidationError> {
+        Self::validate_custom_cpu_limits(pricing, floors)?;
+        Self::validate_custom_memory_limits(pricing, floors)?;
+        Self::validate_custom_disk_limits(disks, floors)
+    }
+
+    /// Like [`Self::validate_custom_pricing_limits`] but only checks the
+    /// limits an update changes, so plans saved before the floors existed can
+    /// still be edited. `disks` is `None` when the disk pricing is kept.
+    pub fn validate_custom_pricing_update(
+        old: &VmCustomPricing,
+        new: &VmCustomPricing,
+        disks: Option<&[VmCustomPricingDisk]>,
+        floors: &CustomVmFloors,
+    ) -> Result<(), ValidationError> {
+        if old.min_cpu != new.min_cpu || old.max_cpu != new.max_cpu {
+            Self::validate_custom_cpu_limits(new, floors)?;
+        }
+        if old.min_memory != new.min_memory || old.max_memory != new.max_memory {
+            Self::validate_custom_memory_limits(new, floors)?;
+        }
+        if let Some(disks) = disks {
+            Self::validate_custom_disk_limits(disks, floors)?;
+        }
+        Ok(())
+    }
+
+    fn validate_custom_cpu_limits(
+        pricing: &VmCustomPricing,
+        floors: &CustomVmFloors,
+    ) -> Result<(), ValidationError> {
+        if pricing.min_cpu < floors.min_cpu {
+            return Err(ValidationError::new(format!(
+                "min_cpu must be at least {}",
+                floors.min_cpu
+            )));
+        }
+        if pricing.min_cpu > pricing.max_cpu {
+            return Err(ValidationError::new("min_cpu must not exceed max_cpu"));
+        }
+        Ok(())
+    }
+
+    fn validate_custom_memory_limits(
+        pricing: &VmCustomPricing,
+        floors: &CustomVmFloors,
+    ) -> Result<(), ValidationError> {
+        if pricing.min_memory < floors.min_memory {
+            return Err(ValidationError::new(format!(
+                "min_memory must be at least {}",
+                format_bytes(floors.min_memory)
+            )));
+        }
+        if pricing.min_memory > pricing.max_memory {
+            return Err(ValidationError::new(
+                "min_memory must not exceed max_memory",
+            ));
+        }
+        Ok(())
+    }
+
+    fn validate_custom_disk_limits(
+        disks: &[VmCustomPricingDisk],
+        floors: &CustomVmFloors,
+    ) -> Result<(), ValidationError> {
+        for d in disks {
+            if d.min_disk_size < floors.min_disk {
+                return Err(ValidationError::new(format!(
+                    "min_disk_size for {} {} must be at least {}",
+                    d.kind,
+                    d.interface,
+                    format_bytes(floors.min_disk)
+                )));
+            }
+            if d.min_disk_size > d.max_disk_size {
+                return Err(ValidationError::new(format!(
+                    "min_disk_size for {} {} must not exceed max_disk_size",
+                    d.kind, d.interface
+                )));
+            }
         }
         Ok(())
     }
@@ -1312,6 +1530,7 @@ impl PricingEngine {
             network_mbps: pricing.network_mbps,
             cpu_limit: pricing.cpu_limit,
             firewall_rule_limit: None,
+            backup_retention: 0,
         };
         Ok(new_custom_template)
     }
@@ -1425,7 +1644,7 @@ impl PricingEngine {
 
         // Upgrades are a spec change chosen by the user, so enforce the plan's
         // min/max limits (unlike plain renewals of existing specs).
-        Self::validate_custom_vm_spec(&self.db, &new_custom_template).await?;
+        Self::validate_custom_vm_spec(&self.db, &new_custom_template, &self.custom_floors).await?;
 
         // Get the cost of renewal
         let new_price =
@@ -1469,12 +1688,15 @@ impl PricingEngine {
             )
             .await;
 
+        let tax_display = self.db.get_company(company_id).await?.tax_display;
+
         Ok(UpgradeCostQuote {
             upgrade,
             renewal: new_renewal_currency,
             discount: discount_currency,
             tax,
             processing_fee,
+            tax_display,
         })
     }
 
@@ -1706,7 +1928,7 @@ mod tests {
 
         let db: Arc<dyn LNVpsDb> = Arc::new(db);
         let rates = Arc::new(MockExchangeRate::new());
-        let pe = PricingEngine::new(db, rates, VatClient::new());
+        let pe = PricingEngine::new(db, rates, VatClient::new(), CustomVmFloors::default());
 
         // Test a range of amounts to ensure gross-up always holds
         for base in [100u64, 345, 1000, 9999, 50000] {
@@ -1775,7 +1997,7 @@ mod tests {
 
         let db: Arc<dyn LNVpsDb> = Arc::new(db);
         let rates = Arc::new(MockExchangeRate::new());
-        let pe = PricingEngine::new(db, rates, VatClient::new());
+        let pe = PricingEngine::new(db, rates, VatClient::new(), CustomVmFloors::default());
 
         let amount = 990u64; // €9.90 in cents
         let fee = pe
@@ -1859,7 +2081,9 @@ mod tests {
         }
         let db: Arc<dyn LNVpsDb> = Arc::new(db);
         let template = db.get_custom_vm_template(1).await?;
-        let res = PricingEngine::validate_custom_vm_spec(&db, &template).await;
+        let res =
+            PricingEngine::validate_custom_vm_spec(&db, &template, &CustomVmFloors::default())
+                .await;
         assert!(res.is_err(), "cpu=0 must be rejected (below min_cpu)");
         // But pricing itself must still succeed — grandfathered/out-of-range VMs
         // must remain renewable and migratable.
@@ -1871,6 +2095,187 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn custom_spec_floors_and_ceilings() -> Result<()> {
+        let db = MockDb::default();
+        add_custom_pricing(&db).await;
+        // a plan without its own floor still gets the global one
+        db.custom_pricing
+            .lock()
+            .await
+            .get_mut(&1)
+            .unwrap()
+            .min_memory = 0;
+        let db = Arc::new(db);
+        let dyn_db: Arc<dyn LNVpsDb> = db.clone();
+        let base = dyn_db.get_custom_vm_template(1).await?;
+        PricingEngine::validate_custom_vm_spec(&dyn_db, &base, &CustomVmFloors::default()).await?;
+
+        let cases = [
+            (
+                VmCustomTemplate {
+                    disk_size: 2 * crate::GB,
+                    ..base.clone()
+                },
+                "Disk size 2GB out of range (5GB-1024GB)",
+            ),
+            (
+                VmCustomTemplate {
+                    disk_size: 2 * crate::TB,
+                    ..base.clone()
+                },
+                "Disk size 2048GB out of range (5GB-1024GB)",
+            ),
+            (
+                VmCustomTemplate {
+                    memory: 512 * crate::MB,
+                    ..base.clone()
+                },
+                "Memory 512MB out of range (1GB-64GB)",
+            ),
+            (
+                VmCustomTemplate {
+                    memory: 128 * crate::GB,
+                    ..base.clone()
+                },
+                "Memory 128GB out of range (1GB-64GB)",
+            ),
+            (
+                VmCustomTemplate {
+                    cpu: 32,
+                    ..base.clone()
+                },
+                "CPU count 32 out of range (1-16)",
+            ),
+        ];
+        for (template, msg) in cases {
+            let err = PricingEngine::validate_custom_vm_spec(
+                &dyn_db,
+                &template,
+                &CustomVmFloors::default(),
+            )
+            .await
+            .unwrap_err();
+            let err = err
+                .downcast_ref::<ValidationError>()
+                .expect("out of range spec must be a validation error");
+            assert_eq!(err.to_string(), msg);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn custom_pricing_limits_validated() {
+        let floors = CustomVmFloors::default();
+        let pricing = VmCustomPricing {
+            min_cpu: 1,
+            max_cpu: 8,
+            min_memory: crate::GB,
+            max_memory: 16 * crate::GB,
+            ..Default::default()
+        };
+        let disk = VmCustomPricingDisk {
+            min_disk_size: 10 * crate::GB,
+            max_disk_size: crate::TB,
+            ..Default::default()
+        };
+        assert!(
+            PricingEngine::validate_custom_pricing_limits(&pricing, &[disk.clone()], &floors)
+                .is_ok()
+        );
+
+        let bad_pricing = [
+            VmCustomPricing {
+                min_cpu: 0,
+                ..pricing.clone()
+            },
+            VmCustomPricing {
+                min_cpu: 16,
+                ..pricing.clone()
+            },
+            VmCustomPricing {
+                min_memory: 0,
+                ..pricing.clone()
+            },
+            VmCustomPricing {
+                max_memory: crate::GB / 2,
+                ..pricing.clone()
+            },
+        ];
+        for p in bad_pricing {
+            assert!(
+                PricingEngine::validate_custom_pricing_limits(&p, &[disk.clone()], &floors)
+                    .is_err()
+            );
+        }
+
+        let bad_disks = [
+            VmCustomPricingDisk {
+                min_disk_size: 0,
+                ..disk.clone()
+            },
+            VmCustomPricingDisk {
+                max_disk_size: crate::GB,
+                ..disk.clone()
+            },
+        ];
+        for d in bad_disks {
+            assert!(
+                PricingEngine::validate_custom_pricing_limits(&pricing, &[d], &floors).is_err()
+            );
+        }
+
+        // configured floors replace the defaults
+        let floors = CustomVmFloors {
+            min_cpu: 2,
+            ..floors
+        };
+        assert!(
+            PricingEngine::validate_custom_pricing_limits(&pricing, &[disk.clone()], &floors)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn custom_pricing_update_checks_changed_limits() {
+        let floors = CustomVmFloors::default();
+        // a plan saved before floors were enforced
+        let legacy = VmCustomPricing {
+            min_cpu: 1,
+            max_cpu: 8,
+            min_memory: 0,
+            max_memory: 16 * crate::GB,
+            ..Default::default()
+        };
+        let renamed = VmCustomPricing {
+            name: "renamed".to_string(),
+            max_cpu: 16,
+            ..legacy.clone()
+        };
+        assert!(
+            PricingEngine::validate_custom_pricing_update(&legacy, &renamed, None, &floors).is_ok()
+        );
+
+        // touching the memory range has to satisfy the floor
+        let memory = VmCustomPricing {
+            max_memory: 32 * crate::GB,
+            ..legacy.clone()
+        };
+        assert!(
+            PricingEngine::validate_custom_pricing_update(&legacy, &memory, None, &floors).is_err()
+        );
+
+        let disk = VmCustomPricingDisk {
+            min_disk_size: 0,
+            max_disk_size: crate::TB,
+            ..Default::default()
+        };
+        assert!(
+            PricingEngine::validate_custom_pricing_update(&legacy, &legacy, Some(&[disk]), &floors)
+                .is_err()
+        );
+    }
+
     /// Regression: disk pricing must match the requested interface, not just the
     /// disk kind. A request for an interface with no pricing row must be rejected
     /// rather than silently billed at another interface's rate.
@@ -1937,7 +2342,12 @@ mod tests {
             .await?;
 
         let db: Arc<dyn LNVpsDb> = Arc::new(db);
-        let pe = PricingEngine::new(db.clone(), rates, VatClient::new());
+        let pe = PricingEngine::new(
+            db.clone(),
+            rates,
+            VatClient::new(),
+            CustomVmFloors::default(),
+        );
 
         // Full month price in msats: €10 / 100000 EUR/BTC * 1e11 = 10_000_000 msat
         let mo_price = (10.0f64 / MOCK_RATE as f64 * 1.0e11) as u64;
@@ -2018,7 +2428,7 @@ mod tests {
 
         let db: Arc<dyn LNVpsDb> = Arc::new(db);
         let taxes = VatClient::with_rates(HashMap::from([(CountryCode::IRL, 23.0)]));
-        let pe = PricingEngine::new(db.clone(), rates, taxes);
+        let pe = PricingEngine::new(db.clone(), rates, taxes, CustomVmFloors::default());
 
         // `input` is the GROSS the customer paid. Lightning has no fee, so the
         // net + tax must sum back to exactly the paid amount (the tax is taken
@@ -2062,6 +2472,7 @@ mod tests {
             db.clone(),
             Arc::new(MockExchangeRate::new()),
             VatClient::new(),
+            CustomVmFloors::default(),
         );
 
         // Lightning has no fee: net = gross / (1 + tax).
@@ -2150,7 +2561,7 @@ mod tests {
 
         let taxes = VatClient::with_rates(HashMap::from([(CountryCode::IRL, 23.0)]));
 
-        let pe = PricingEngine::new(db.clone(), rates, taxes);
+        let pe = PricingEngine::new(db.clone(), rates, taxes, CustomVmFloors::default());
         let plan = MockDb::mock_cost_plan();
 
         let price = pe.get_vm_cost(1, PaymentMethod::Lightning).await?;
@@ -2329,7 +2740,7 @@ mod tests {
 
         let db_arc: Arc<dyn LNVpsDb> = Arc::new(db);
         let taxes = VatClient::new();
-        let pe = PricingEngine::new(db_arc.clone(), rates, taxes);
+        let pe = PricingEngine::new(db_arc.clone(), rates, taxes, CustomVmFloors::default());
 
         // Test upgrade configuration - increase CPU from 1 to 2
         let upgrade_config = UpgradeConfig {
@@ -2402,7 +2813,7 @@ mod tests {
 
         let db_arc: Arc<dyn LNVpsDb> = Arc::new(db);
         let taxes = VatClient::with_rates(HashMap::from([(CountryCode::IRL, 23.0)]));
-        let pe = PricingEngine::new(db_arc.clone(), rates, taxes);
+        let pe = PricingEngine::new(db_arc.clone(), rates, taxes, CustomVmFloors::default());
 
         let cfg = UpgradeConfig {
             new_cpu: Some(2),
@@ -2437,6 +2848,89 @@ mod tests {
         Ok(())
     }
 
+    /// At 21% VAT a 10.00 net price is 12.10 gross, shown as one or the other
+    /// by the seller's tax display, while the charged amounts are the same
+    #[test]
+    fn test_display_price_inclusive_vs_exclusive() {
+        let tax = TaxDetermination::taxed(
+            1000,
+            21.0,
+            Some("NLD".to_string()),
+            TaxTreatment::Domestic,
+            None,
+        );
+        let exclusive = DisplayPrice::new(1000, tax.amount, TaxDisplay::Exclusive);
+        let inclusive = DisplayPrice::new(1000, tax.amount, TaxDisplay::Inclusive);
+        assert_eq!(
+            (exclusive.net, exclusive.tax, exclusive.gross),
+            (1000, 210, 1210)
+        );
+        assert_eq!(
+            (inclusive.net, inclusive.tax, inclusive.gross),
+            (1000, 210, 1210)
+        );
+        assert_eq!(exclusive.shown(), 1000);
+        assert_eq!(inclusive.shown(), 1210);
+    }
+
+    #[tokio::test]
+    async fn test_upgrade_quote_respects_company_tax_display() -> Result<()> {
+        let db = MockDb::default();
+        let rates = Arc::new(MockExchangeRate::new());
+        rates.set_rate(Ticker::btc_rate("EUR")?, MOCK_RATE).await;
+        setup_upgrade_test_data(&db).await?;
+        {
+            let mut subs = db.subscriptions.lock().await;
+            let s = subs.get_mut(&1).unwrap();
+            s.expires = Some(Utc::now() + chrono::Duration::days(15));
+            s.is_setup = true;
+        }
+        db.users.lock().await.insert(
+            1,
+            User {
+                id: 1,
+                pubkey: vec![],
+                country_code: Some("IRL".to_string()),
+                ..Default::default()
+            },
+        );
+        {
+            let mut companies = db.companies.lock().await;
+            let c = companies.get_mut(&1).unwrap();
+            c.country_code = Some("IRL".to_string());
+            c.tax_display = TaxDisplay::Inclusive;
+        }
+        let taxes = VatClient::with_rates(HashMap::from([(CountryCode::IRL, 23.0)]));
+        let pe = PricingEngine::new(
+            Arc::new(db.clone()),
+            rates,
+            taxes,
+            CustomVmFloors::default(),
+        );
+        let cfg = UpgradeConfig {
+            new_cpu: Some(2),
+            new_memory: None,
+            new_disk: None,
+        };
+        let quote = pe
+            .calculate_vm_upgrade_cost(1, &cfg, PaymentMethod::Lightning)
+            .await?;
+        let price = quote.price();
+        assert_eq!(price.tax_display, TaxDisplay::Inclusive);
+        assert!(price.tax > 0);
+        // the quoted net amount and tax are unchanged by the display setting
+        assert_eq!(price.net, quote.upgrade.amount.value());
+        assert_eq!(price.tax, quote.tax.amount);
+        assert_eq!(price.shown(), price.net + price.tax);
+
+        db.companies.lock().await.get_mut(&1).unwrap().tax_display = TaxDisplay::Exclusive;
+        let quote = pe
+            .calculate_vm_upgrade_cost(1, &cfg, PaymentMethod::Lightning)
+            .await?;
+        assert_eq!(quote.price().shown(), quote.upgrade.amount.value());
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_calculate_upgrade_cost_expired_vm() -> Result<()> {
         let db = MockDb::default();
@@ -2470,7 +2964,7 @@ mod tests {
 
         let db_arc: Arc<dyn LNVpsDb> = Arc::new(db);
         let taxes = VatClient::new();
-        let pe = PricingEngine::new(db_arc.clone(), rates, taxes);
+        let pe = PricingEngine::new(db_arc.clone(), rates, taxes, CustomVmFloors::default());
 
         let upgrade_config = UpgradeConfig {
             new_cpu: Some(2),
@@ -2520,7 +3014,7 @@ mod tests {
 
         let db_arc: Arc<dyn LNVpsDb> = Arc::new(db);
         let taxes = VatClient::new();
-        let pe = PricingEngine::new(db_arc.clone(), rates, taxes);
+        let pe = PricingEngine::new(db_arc.clone(), rates, taxes, CustomVmFloors::default());
 
         let upgrade_config = UpgradeConfig {
             new_cpu: Some(2),
@@ -2571,7 +3065,7 @@ mod tests {
 
         let db_arc: Arc<dyn LNVpsDb> = Arc::new(db);
         let taxes = VatClient::new();
-        let pe = PricingEngine::new(db_arc.clone(), rates, taxes);
+        let pe = PricingEngine::new(db_arc.clone(), rates, taxes, CustomVmFloors::default());
 
         let upgrade_config = UpgradeConfig {
             new_cpu: Some(4), // Upgrade from 2 to 4 CPUs
@@ -2632,7 +3126,7 @@ mod tests {
 
         let db_arc: Arc<dyn LNVpsDb> = Arc::new(db);
         let taxes = VatClient::new();
-        let pe = PricingEngine::new(db_arc.clone(), rates, taxes);
+        let pe = PricingEngine::new(db_arc.clone(), rates, taxes, CustomVmFloors::default());
 
         // Test upgrade - increase CPU from 2 to 4 (double the CPU)
         let upgrade_config = UpgradeConfig {
@@ -2803,7 +3297,7 @@ mod tests {
 
         let db_arc: Arc<dyn LNVpsDb> = Arc::new(db);
         let taxes = VatClient::new();
-        let pe = PricingEngine::new(db_arc.clone(), rates, taxes);
+        let pe = PricingEngine::new(db_arc.clone(), rates, taxes, CustomVmFloors::default());
 
         // Test large upgrade - significantly increase all resources
         let upgrade_config = UpgradeConfig {
@@ -2960,7 +3454,7 @@ mod tests {
 
         let db: Arc<dyn LNVpsDb> = Arc::new(db);
         let taxes = VatClient::new();
-        let pe = PricingEngine::new(db.clone(), rates, taxes.clone());
+        let pe = PricingEngine::new(db.clone(), rates, taxes.clone(), CustomVmFloors::default());
 
         // Test Lightning payment (no processing fee)
         let price_lightning = pe.get_vm_cost(1, PaymentMethod::Lightning).await?;
@@ -3032,7 +3526,7 @@ mod tests {
 
         let db: Arc<dyn LNVpsDb> = Arc::new(db);
         let taxes = VatClient::with_rates(HashMap::from([(CountryCode::IRL, 23.0)]));
-        let pe = PricingEngine::new(db.clone(), rates, taxes);
+        let pe = PricingEngine::new(db.clone(), rates, taxes, CustomVmFloors::default());
 
         let payment_info = match pe.get_vm_cost(1, PaymentMethod::Revolut).await? {
             CostResult::New(p) => p,
@@ -3197,7 +3691,7 @@ mod tests {
 
         let db: Arc<dyn LNVpsDb> = Arc::new(db);
         let rates = Arc::new(MockExchangeRate::new());
-        let pe = PricingEngine::new(db, rates, VatClient::new());
+        let pe = PricingEngine::new(db, rates, VatClient::new(), CustomVmFloors::default());
 
         let cfg = crate::UpgradeConfig {
             new_cpu: Some(4),
@@ -3220,7 +3714,7 @@ mod tests {
 
         let db: Arc<dyn LNVpsDb> = Arc::new(db);
         let rates = Arc::new(MockExchangeRate::new());
-        let pe = PricingEngine::new(db, rates, VatClient::new());
+        let pe = PricingEngine::new(db, rates, VatClient::new(), CustomVmFloors::default());
 
         let cfg = crate::UpgradeConfig {
             new_cpu: Some(4),
@@ -3247,7 +3741,7 @@ mod tests {
 
         let db: Arc<dyn LNVpsDb> = Arc::new(db);
         let rates = Arc::new(MockExchangeRate::new());
-        let pe = PricingEngine::new(db, rates, VatClient::new());
+        let pe = PricingEngine::new(db, rates, VatClient::new(), CustomVmFloors::default());
 
         let cfg = crate::UpgradeConfig {
             new_cpu: Some(4),
@@ -3275,7 +3769,7 @@ mod tests {
 
         let db: Arc<dyn LNVpsDb> = Arc::new(db);
         let rates = Arc::new(MockExchangeRate::new());
-        let pe = PricingEngine::new(db, rates, VatClient::new());
+        let pe = PricingEngine::new(db, rates, VatClient::new(), CustomVmFloors::default());
 
         let cfg = crate::UpgradeConfig {
             new_cpu: Some(4),
@@ -3299,7 +3793,7 @@ mod tests {
 
         let db: Arc<dyn LNVpsDb> = Arc::new(db);
         let rates = Arc::new(MockExchangeRate::new());
-        let pe = PricingEngine::new(db, rates, VatClient::new());
+        let pe = PricingEngine::new(db, rates, VatClient::new(), CustomVmFloors::default());
 
         let cfg = crate::UpgradeConfig {
             new_cpu: Some(4),
@@ -3338,7 +3832,7 @@ mod tests {
 
         let db: Arc<dyn LNVpsDb> = Arc::new(db);
         let rates = Arc::new(MockExchangeRate::new());
-        let pe = PricingEngine::new(db, rates, VatClient::new());
+        let pe = PricingEngine::new(db, rates, VatClient::new(), CustomVmFloors::default());
 
         let cfg = crate::UpgradeConfig {
             new_cpu: Some(4),
@@ -3359,7 +3853,12 @@ mod tests {
         rates
             .set_rate(Ticker::btc_rate("EUR").unwrap(), MOCK_RATE)
             .await;
-        PricingEngine::new(db, rates as Arc<dyn ExchangeRateService>, VatClient::new())
+        PricingEngine::new(
+            db,
+            rates as Arc<dyn ExchangeRateService>,
+            VatClient::new(),
+            CustomVmFloors::default(),
+        )
     }
 
     /// get_vm_cost_for_intervals returns CostResult::Existing when a valid (non-expired)
@@ -3416,6 +3915,7 @@ mod tests {
             tax_treatment: None,
             tax_evidence: None,
             tax_breakdown: None,
+            invoice_number: None,
         };
         db.insert_subscription_payment(&existing).await?;
 
@@ -3486,6 +3986,7 @@ mod tests {
             tax_treatment: None,
             tax_evidence: None,
             tax_breakdown: None,
+            invoice_number: None,
         };
         db.insert_subscription_payment(&existing).await?;
 
@@ -3542,6 +4043,7 @@ mod tests {
             tax_treatment: None,
             tax_evidence: None,
             tax_breakdown: None,
+            invoice_number: None,
         };
         db.insert_subscription_payment(&expired).await?;
 
@@ -3623,6 +4125,7 @@ mod tests {
             db,
             Arc::new(MockExchangeRate::new()),
             VatClient::with_rates(eu_tax_rates()),
+            CustomVmFloors::default(),
         )
     }
 
@@ -3989,7 +4492,7 @@ mod tests {
         let rates = Arc::new(MockExchangeRate::new());
         rates.set_rate(Ticker::btc_rate("EUR")?, 100_000.0).await; // 100k EUR/BTC
         rates.set_rate(Ticker::btc_rate("USD")?, 120_000.0).await; // 120k USD/BTC
-        let pe = PricingEngine::new(db, rates, VatClient::new());
+        let pe = PricingEngine::new(db, rates, VatClient::new(), CustomVmFloors::default());
 
         // $120.00 -> BTC (0.001) -> EUR (€100.00 = 10000 cents)
         let out = pe
@@ -4011,7 +4514,7 @@ mod tests {
         rates
             .set_rate(Ticker(Currency::EUR, Currency::USD), 1.10)
             .await;
-        let pe = PricingEngine::new(db, rates, VatClient::new());
+        let pe = PricingEngine::new(db, rates, VatClient::new(), CustomVmFloors::default());
 
         // €10.00 -> $11.00
         let out = pe
@@ -5,7 +5,10 @@
 //! specify the error type parameter.
 
 // Re-export the core crate
-pub use try_procedure::{OpError, Pipeline, RetryOp, RetryPolicy, Retryable, retry_async};
+pub use try_procedure::{
+    OpError, ParallelStep, Pipeline, PipelineEvent, RetryAfter, RetryHint, RetryOp, RetryPolicy,
+    Retryable, StepTimeout, retry_async, retry_async_with,
+};
 
 /// Convenience type alias that defaults the error to [`anyhow::Error`].
 ///
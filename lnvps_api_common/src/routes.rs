@@ -4,8 +4,24 @@ use axum::response::{IntoResponse, Response};
 use log::error;
 use serde::{Deserialize, Serialize};
 
+/// Mount `router` under `base_path` (e.g. `/api`), or leave it at the root
+/// when no base path is configured
+pub fn with_base_path<S>(router: axum::Router<S>, base_path: Option<&str>) -> axum::Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    match base_path
+        .map(|p| p.trim_matches('/'))
+        .filter(|p| !p.is_empty())
+    {
+        Some(p) => axum::Router::new().nest(&format!("/{}", p), router),
+        None => router,
+    }
+}
+
 pub type ApiResult<T> = Result<Json<ApiData<T>>, ApiError>;
 pub type ApiPaginatedResult<T> = Result<Json<ApiPaginatedData<T>>, ApiError>;
+pub type ApiCursorResult<T> = Result<Json<ApiCursorData<T>>, ApiError>;
 
 #[derive(Serialize, Deserialize)]
 pub struct ApiData<T: Serialize> {
@@ -45,11 +61,118 @@ impl<T: Serialize> ApiPaginatedData<T> {
     }
 }
 
+/// Page of results with an opaque cursor for fetching the next page
+#[derive(Serialize, Deserialize)]
+pub struct ApiCursorData<T: Serialize> {
+    pub data: Vec<T>,
+    /// Pass as `after` to fetch the next page, absent on the last page
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next: Option<String>,
+}
+
+impl<T: Serialize> ApiCursorData<T> {
+    pub fn ok(data: Vec<T>, next: Option<String>) -> ApiCursorResult<T> {
+        Ok(Json(ApiCursorData { data, next }))
+    }
+}
+
+/// Machine-readable error kind, returned as `code` next to the human message.
+///
+/// These strings are part of the API, clients branch on them, so never rename
+/// a variant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ApiErrorCode {
+    /// Generic client error
+    BadRequest,
+    /// The request was well-formed but its values were rejected
+    ValidationFailed,
+    /// Missing or invalid authentication
+    Unauthorized,
+    /// Authenticated but not allowed to access the resource
+    Forbidden,
+    /// The resource does not exist
+    NotFound,
+    /// The resource must be paid for (e.g. an expired VM)
+    PaymentRequired,
+    /// The request conflicts with the current state
+    Conflict,
+    /// No host capacity is available for the requested resources
+    InsufficientCapacity,
+    /// The user already has the maximum number of VMs allowed for a template
+    TemplateLimitReached,
+    /// The feature is switched off by a feature flag
+    FeatureUnavailable,
+    /// The operation is not supported
+    NotImplemented,
+    /// Unexpected server-side failure
+    InternalError,
+}
+
+impl ApiErrorCode {
+    /// Default error kind for an HTTP status code
+    pub fn from_status(code: StatusCode) -> Self {
+        match code {
+            StatusCode::UNAUTHORIZED => Self::Unauthorized,
+            StatusCode::FORBIDDEN => Self::Forbidden,
+            StatusCode::NOT_FOUND => Self::NotFound,
+            StatusCode::PAYMENT_REQUIRED => Self::PaymentRequired,
+            StatusCode::CONFLICT => Self::Conflict,
+            StatusCode::NOT_IMPLEMENTED => Self::NotImplemented,
+            c if c.is_server_error() => Self::InternalError,
+            _ => Self::BadRequest,
+        }
+    }
+}
+
+/// A request was rejected because of invalid values, e.g. ordering from a
+/// disabled template. Returned from service code so the API can answer with a
+/// `400 validation_failed` instead of an internal error.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationError(pub String);
+
+impl ValidationError {
+    pub fn new(message: impl ToString) -> Self {
+        Self(message.to_string())
+    }
+}
+
+impl std::fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+/// A user already has `limit` active VMs of a template with a per-user
+/// maximum. Answered with `409 template_limit_reached`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TemplateLimitError {
+    pub template_id: u64,
+    pub limit: u16,
+}
+
+impl std::fmt::Display for TemplateLimitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "You already have the maximum of {} VMs allowed for this template",
+            self.limit
+        )
+    }
+}
+
+impl std::error::Error for TemplateLimitError {}
+
 #[derive(Serialize)]
 pub struct ApiError {
     pub error: String,
+    /// Machine-readable error kind
+    #[serde(rename = "code")]
+    pub kind: ApiErrorCode,
     /// HTTP status code to return for this error. Skipped during serialization
-    /// (the response body only carries the `error` message).
+    /// (the response body only carries the `error` message and its kind).
     #[serde(skip)]
     pub code: StatusCode,
 }
@@ -62,25 +185,35 @@ impl ApiError {
     /// [`ApiError::internal`]; for other client errors use the dedicated
     /// [`ApiError::not_found`], [`ApiError::forbidden`], etc. helpers.
     pub fn new(message: impl ToString) -> Self {
-        Self {
-            error: message.to_string(),
-            code: StatusCode::BAD_REQUEST,
-        }
+        Self::with_status(StatusCode::BAD_REQUEST, message)
     }
 
-    /// Create an API error with a specific HTTP status code
+    /// Create an API error with a specific HTTP status code, the error kind
+    /// is derived from the status
     pub fn with_status(code: StatusCode, message: impl ToString) -> Self {
         Self {
             error: message.to_string(),
+            kind: ApiErrorCode::from_status(code),
             code,
         }
     }
 
+    /// Override the error kind, keeping the status code
+    pub fn with_kind(mut self, kind: ApiErrorCode) -> Self {
+        self.kind = kind;
+        self
+    }
+
     /// Create a 400 Bad Request error (explicit alias for [`ApiError::new`])
     pub fn bad_request(message: impl ToString) -> Self {
         Self::with_status(StatusCode::BAD_REQUEST, message)
     }
 
+    /// Create a 400 Bad Request error for rejected request values
+    pub fn validation(message: impl ToString) -> Self {
+        Self::bad_request(message).with_kind(ApiErrorCode::ValidationFailed)
+    }
+
     /// Create a 401 Unauthorized error
     pub fn unauthorized(message: impl ToString) -> Self {
         Self::with_status(StatusCode::UNAUTHORIZED, message)
@@ -116,20 +249,17 @@ impl ApiError {
     #[cfg(not(feature = "admin"))]
     pub fn internal(err: impl std::fmt::Display) -> Self {
         error!("Internal error: {}", err);
-        Self {
-            error: "An internal error occurred".to_string(),
-            code: StatusCode::INTERNAL_SERVER_ERROR,
-        }
+        Self::with_status(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "An internal error occurred",
+        )
     }
 
     /// In admin mode, show the full error message for debugging
     #[cfg(feature = "admin")]
     pub fn internal(err: impl std::fmt::Display) -> Self {
         error!("Internal error: {}", err);
-        Self {
-            error: err.to_string(),
-            code: StatusCode::INTERNAL_SERVER_ERROR,
-        }
+        Self::with_status(StatusCode::INTERNAL_SERVER_ERROR, err)
     }
 }
 
@@ -138,7 +268,21 @@ impl From<anyhow::Error> for ApiError {
         // Surface known capacity errors as a user-facing 409 instead of a
         // generic internal server error.
         if let Some(cap) = value.downcast_ref::<crate::CapacityError>() {
-            return Self::conflict(cap);
+            return Self::conflict(cap).with_kind(ApiErrorCode::InsufficientCapacity);
+        }
+        if let Some(v) = value.downcast_ref::<ValidationError>() {
+            return Self::validation(v);
+        }
+        if let Some(l) = value.downcast_ref::<TemplateLimitError>() {
+            return Self::conflict(l).with_kind(ApiErrorCode::TemplateLimitReached);
+        }
+        if let Some(f) = value.downcast_ref::<crate::FeatureDisabledError>() {
+            return Self::forbidden(f).with_kind(ApiErrorCode::FeatureUnavailable);
+        }
+        if let Some(db) = value.downcast_ref::<lnvps_db::DbError>()
+            && db.is_row_not_found()
+        {
+            return Self::not_found("Resource not found");
         }
         Self::internal(value)
     }
@@ -188,11 +332,93 @@ impl IntoResponse for ApiError {
 mod tests {
     use super::*;
 
+    #[tokio::test]
+    async fn test_with_base_path_routes_prefixed_requests() -> anyhow::Result<()> {
+        let router = axum::Router::new().route("/api/v1/ping", axum::routing::get(async || "pong"));
+        let router = with_base_path(router, Some("/lnvps/"));
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let base = format!("http://{}", listener.local_addr()?);
+        tokio::spawn(async move { axum::serve(listener, router).await });
+
+        let rsp = reqwest::get(format!("{}/lnvps/api/v1/ping", base)).await?;
+        assert_eq!(rsp.status(), reqwest::StatusCode::OK);
+        assert_eq!(rsp.text().await?, "pong");
+
+        let rsp = reqwest::get(format!("{}/api/v1/ping", base)).await?;
+        assert_eq!(rsp.status(), reqwest::StatusCode::NOT_FOUND);
+        Ok(())
+    }
+
     #[test]
     fn test_api_error_json_format() {
         let error = ApiError::new("Something went wrong");
         let json = serde_json::to_string(&error).unwrap();
-        assert_eq!(json, r#"{"error":"Something went wrong"}"#);
+        assert_eq!(
+            json,
+            r#"{"error":"Something went wrong","code":"bad_request"}"#
+        );
+    }
+
+    #[test]
+    fn test_api_error_codes() {
+        assert_eq!(ApiError::new("x").kind, ApiErrorCode::BadRequest);
+        assert_eq!(
+            ApiError::validation("x").kind,
+            ApiErrorCode::ValidationFailed
+        );
+        assert_eq!(ApiError::validation("x").code, StatusCode::BAD_REQUEST);
+        assert_eq!(ApiError::unauthorized("x").kind, ApiErrorCode::Unauthorized);
+        assert_eq!(ApiError::forbidden("x").kind, ApiErrorCode::Forbidden);
+        assert_eq!(ApiError::not_found("x").kind, ApiErrorCode::NotFound);
+        assert_eq!(
+            ApiError::payment_required("x").kind,
+            ApiErrorCode::PaymentRequired
+        );
+        assert_eq!(ApiError::internal("x").kind, ApiErrorCode::InternalError);
+
+        let json = serde_json::to_value(ApiError::payment_required("Renew first")).unwrap();
+        assert_eq!(json["code"], "payment_required");
+        assert_eq!(json["error"], "Renew first");
+    }
+
+    #[test]
+    fn test_anyhow_errors_map_to_codes() {
+        let err: ApiError = anyhow::Error::from(crate::CapacityError::NoAvailableHosts).into();
+        assert_eq!(err.code, StatusCode::CONFLICT);
+        assert_eq!(err.kind, ApiErrorCode::InsufficientCapacity);
+
+        let err: ApiError = anyhow::Error::from(ValidationError::new("Invalid template")).into();
+        assert_eq!(err.code, StatusCode::BAD_REQUEST);
+        assert_eq!(err.kind, ApiErrorCode::ValidationFailed);
+        assert_eq!(err.error, "Invalid template");
+
+        let err: ApiError = anyhow::Error::from(TemplateLimitError {
+            template_id: 1,
+            limit: 2,
+        })
+        .into();
+        assert_eq!(err.code, StatusCode::CONFLICT);
+        assert_eq!(err.kind, ApiErrorCode::TemplateLimitReached);
+        assert_eq!(
+            serde_json::to_value(&err).unwrap()["code"],
+            "template_limit_reached"
+        );
+
+        let err: ApiError = anyhow::Error::from(crate::FeatureDisabledError {
+            key: "backups".to_string(),
+        })
+        .into();
+        assert_eq!(err.code, StatusCode::FORBIDDEN);
+        assert_eq!(err.kind, ApiErrorCode::FeatureUnavailable);
+        assert_eq!(err.error, "The backups feature is not available");
+
+        let err: ApiError =
+            anyhow::Error::from(lnvps_db::DbError::SqlxError(sqlx::Error::RowNotFound)).into();
+        assert_eq!(err.kind, ApiErrorCode::NotFound);
+
+        let err: ApiError = anyhow::anyhow!("boom").into();
+        assert_eq!(err.kind, ApiErrorCode::InternalError);
     }
 
     #[test]
@@ -1,4 +1,5 @@
 use anyhow::Result;
+use lnvps_db::VmStateKind;
 use redis::AsyncCommands;
 use redis::aio::MultiplexedConnection;
 use serde::Deserialize;
@@ -18,6 +19,36 @@ pub enum VmRunningStates {
     Stopped,
     /// Payment received; VM is being provisioned on the host for the first time.
     Creating,
+    /// Paused on the host with its memory kept, resumes where it left off.
+    Suspended,
+    /// Being live migrated to another host, it runs on both hosts for a while.
+    Migrating,
+}
+
+impl From<&VmRunningStates> for VmStateKind {
+    fn from(value: &VmRunningStates) -> Self {
+        match value {
+            VmRunningStates::Unknown => VmStateKind::Unknown,
+            VmRunningStates::Running => VmStateKind::Running,
+            VmRunningStates::Stopped => VmStateKind::Stopped,
+            VmRunningStates::Creating => VmStateKind::Creating,
+            VmRunningStates::Suspended => VmStateKind::Suspended,
+            VmRunningStates::Migrating => VmStateKind::Migrating,
+        }
+    }
+}
+
+impl From<VmStateKind> for VmRunningStates {
+    fn from(value: VmStateKind) -> Self {
+        match value {
+            VmStateKind::Unknown => VmRunningStates::Unknown,
+            VmStateKind::Running => VmRunningStates::Running,
+            VmStateKind::Stopped => VmRunningStates::Stopped,
+            VmStateKind::Creating => VmRunningStates::Creating,
+            VmStateKind::Suspended => VmRunningStates::Suspended,
+            VmStateKind::Migrating => VmRunningStates::Migrating,
+        }
+    }
 }
 
 #[derive(Clone, Serialize, Deserialize, Default)]
@@ -33,6 +64,48 @@ pub struct VmRunningState {
     pub disk_read: u64,
 }
 
+/// Information reported from inside a VM by the QEMU guest agent
+#[derive(Clone, Serialize, Deserialize, Default, PartialEq, Debug)]
+pub struct GuestInfo {
+    /// Network interfaces as seen by the guest OS
+    pub interfaces: Vec<GuestInterface>,
+    /// Seconds since the guest booted, when the host can report it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uptime: Option<u64>,
+    /// Mounted writable filesystems, when the host can report them
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub filesystems: Vec<GuestFilesystem>,
+}
+
+impl GuestInfo {
+    /// Percent used of the fullest filesystem, `None` when none are reported
+    pub fn max_disk_usage(&self) -> Option<f64> {
+        self.filesystems
+            .iter()
+            .filter(|f| f.total_bytes > 0)
+            .map(|f| f.used_bytes as f64 / f.total_bytes as f64 * 100.0)
+            .max_by(|a, b| a.total_cmp(b))
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, Default, PartialEq, Debug)]
+pub struct GuestFilesystem {
+    /// Where the filesystem is mounted in the guest
+    pub mountpoint: String,
+    pub used_bytes: u64,
+    pub total_bytes: u64,
+}
+
+#[derive(Clone, Serialize, Deserialize, Default, PartialEq, Debug)]
+pub struct GuestInterface {
+    /// Interface name inside the guest (eth0, ens18 etc.)
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mac_address: Option<String>,
+    /// Configured addresses in CIDR notation
+    pub addresses: Vec<String>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RedisConfig {
     pub url: String,
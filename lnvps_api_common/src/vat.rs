@@ -1,9 +1,25 @@
-use anyhow::{Result, bail};
+use crate::retry::{OpError, OpResult, RetryPolicy, retry_async};
+use anyhow::{Result, anyhow, bail};
 use isocountry::CountryCode;
 use log::trace;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+/// Max time to wait for a single VIES response
+const VIES_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// VIES `userError` codes for a busy or unavailable service / member state,
+/// worth retrying
+const VIES_TRANSIENT_ERRORS: [&str; 6] = [
+    "SERVICE_UNAVAILABLE",
+    "MS_UNAVAILABLE",
+    "TIMEOUT",
+    "GLOBAL_MAX_CONCURRENT_REQ",
+    "GLOBAL_MAX_CONCURRENT_REQ_TIME",
+    "MS_MAX_CONCURRENT_REQ",
+];
 
 /// Convert a VAT-territory 2-letter code to an [`isocountry::CountryCode`].
 ///
@@ -194,6 +210,10 @@ pub struct VatClient {
     validation_url: String,
     /// Cached standard VAT rates keyed by country, shared across clones.
     cache: Arc<RwLock<HashMap<CountryCode, f32>>>,
+    /// Timeout for each VAT validation request
+    timeout: Duration,
+    /// Backoff for transient VAT validation failures (timeouts, VIES busy)
+    retry: RetryPolicy,
 }
 
 impl Default for VatClient {
@@ -205,12 +225,10 @@ impl Default for VatClient {
 impl VatClient {
     /// Create a new client with default API URLs and an empty rate cache.
     pub fn new() -> Self {
-        Self {
-            rates_url: "https://euvatrates.com/rates.json".to_string(),
-            validation_url: "https://ec.europa.eu/taxation_customs/vies/rest-api/check-vat-number"
-                .to_string(),
-            cache: Arc::new(RwLock::new(HashMap::new())),
-        }
+        Self::with_urls(
+            "https://euvatrates.com/rates.json",
+            "https://ec.europa.eu/taxation_customs/vies/rest-api/check-vat-number",
+        )
     }
 
     /// Create a client with custom URLs (useful for testing)
@@ -219,9 +237,22 @@ impl VatClient {
             rates_url: rates_url.into(),
             validation_url: validation_url.into(),
             cache: Arc::new(RwLock::new(HashMap::new())),
+            timeout: VIES_TIMEOUT,
+            // VAT numbers are validated while the user waits, so keep retries short
+            retry: RetryPolicy::default()
+                .with_min_delay(Duration::from_millis(500))
+                .with_max_delay(Duration::from_secs(2))
+                .with_max_retries(2),
         }
     }
 
+    /// Set the timeout and retry policy used for VAT number validation
+    pub fn with_validation_retry(mut self, timeout: Duration, retry: RetryPolicy) -> Self {
+        self.timeout = timeout;
+        self.retry = retry;
+        self
+    }
+
     /// Create a client with a pre-populated rate cache (useful for testing /
     /// offline operation). No network access is performed.
     pub fn with_rates(rates: HashMap<CountryCode, f32>) -> Self {
@@ -324,6 +355,10 @@ impl VatClient {
     /// The returned [`VatValidationResult`] carries per-field match indicators
     /// (`name_match`, `street_match`, ...). Note that many member states do not
     /// support approximate matching and will report `NOT_PROCESSED`.
+    ///
+    /// Timeouts, connection errors and VIES reporting itself (or the member
+    /// state) as busy/unavailable are retried; an error is returned once the
+    /// retries are used up.
     pub async fn validate_vat_number_with_trader(
         &self,
         vat_number: &str,
@@ -376,20 +411,9 @@ impl VatClient {
             add("traderCompanyType", &t.company_type);
         }
 
-        let client = reqwest::Client::new();
-        let response = client
-            .post(&self.validation_url)
-            .json(&body)
-            .send()
-            .await?
-            .text()
-            .await?;
-
-        let vat_response: EuVatValidationResponse = serde_json::from_str(&response)?;
-
-        if let Some(error) = vat_response.user_error {
-            bail!("VAT validation error: {}", error);
-        }
+        let client = reqwest::Client::builder().timeout(self.timeout).build()?;
+        let vat_response =
+            retry_async(self.retry.clone(), || self.check_vat_number(&client, &body)).await?;
 
         Ok(VatValidationResult {
             valid: vat_response.valid,
@@ -405,11 +429,56 @@ impl VatClient {
             company_type_match: vat_response.trader_company_type_match,
         })
     }
+
+    /// Make a single VIES request
+    async fn check_vat_number(
+        &self,
+        client: &reqwest::Client,
+        body: &serde_json::Value,
+    ) -> OpResult<EuVatValidationResponse> {
+        let rsp = client
+            .post(&self.validation_url)
+            .json(body)
+            .send()
+            .await
+            .map_err(|e| {
+                if e.is_timeout() || e.is_connect() {
+                    OpError::Transient(anyhow!("VAT validation service unavailable: {}", e))
+                } else {
+                    OpError::Fatal(anyhow!(e))
+                }
+            })?;
+        let status = rsp.status();
+        let text = rsp
+            .text()
+            .await
+            .map_err(|e| OpError::Transient(anyhow!(e)))?;
+        if status.is_server_error() {
+            return Err(OpError::Transient(anyhow!(
+                "VAT validation service unavailable: HTTP {}",
+                status
+            )));
+        }
+
+        let vat_response: EuVatValidationResponse =
+            serde_json::from_str(&text).map_err(|e| OpError::Fatal(anyhow!(e)))?;
+        match vat_response.user_error.as_deref() {
+            // VIES echoes the outcome here on a successful check
+            None | Some("VALID") | Some("INVALID") => Ok(vat_response),
+            Some(e) if VIES_TRANSIENT_ERRORS.contains(&e) => Err(OpError::Transient(anyhow!(
+                "VAT validation service unavailable: {}",
+                e
+            ))),
+            Some(e) => Err(OpError::Fatal(anyhow!("VAT validation error: {}", e))),
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use wiremock::matchers::{body_partial_json, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
 
     #[test]
     fn test_vat_code_to_isocountry() {
@@ -584,4 +653,105 @@ mod tests {
         };
         assert_eq!(result.mismatched_fields(), vec!["name", "city"]);
     }
+
+    /// Client pointed at a mock VIES responder, retrying quickly
+    async fn mock_vies() -> (MockServer, VatClient) {
+        let server = MockServer::start().await;
+        let client = VatClient::with_urls("", format!("{}/check-vat-number", server.uri()))
+            .with_validation_retry(
+                Duration::from_millis(200),
+                RetryPolicy::default()
+                    .with_min_delay(Duration::from_millis(1))
+                    .with_max_retries(2),
+            );
+        (server, client)
+    }
+
+    fn vies_response(valid: bool, user_error: &str) -> ResponseTemplate {
+        ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "countryCode": "DE",
+            "vatNumber": "123456789",
+            "valid": valid,
+            "userError": user_error,
+            "name": if valid { "ACME GMBH" } else { "---" },
+            "address": if valid { "1 MAIN ST" } else { "---" },
+            "requestIdentifier": ""
+        }))
+    }
+
+    #[tokio::test]
+    async fn test_vies_valid_vat_number() -> anyhow::Result<()> {
+        let (server, client) = mock_vies().await;
+        Mock::given(method("POST"))
+            .and(path("/check-vat-number"))
+            .and(body_partial_json(
+                serde_json::json!({ "countryCode": "DE", "vatNumber": "123456789" }),
+            ))
+            .respond_with(vies_response(true, "VALID"))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let result = client.validate_vat_number("DE 123 456 789", None).await?;
+        assert!(result.valid);
+        assert_eq!(result.name.as_deref(), Some("ACME GMBH"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_vies_invalid_vat_number() -> anyhow::Result<()> {
+        let (server, client) = mock_vies().await;
+        Mock::given(method("POST"))
+            .respond_with(vies_response(false, "INVALID"))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let result = client.validate_vat_number("DE123456789", None).await?;
+        assert!(!result.valid);
+        assert_eq!(result.name, None);
+        Ok(())
+    }
+
+    /// A timed out request and a busy member state are retried
+    #[tokio::test]
+    async fn test_vies_timeout_is_retried() -> anyhow::Result<()> {
+        let (server, client) = mock_vies().await;
+        Mock::given(method("POST"))
+            .respond_with(vies_response(true, "VALID").set_delay(Duration::from_secs(1)))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .respond_with(vies_response(false, "MS_UNAVAILABLE"))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .respond_with(vies_response(true, "VALID"))
+            .mount(&server)
+            .await;
+
+        let result = client.validate_vat_number("DE123456789", None).await?;
+        assert!(result.valid);
+        assert_eq!(server.received_requests().await.unwrap().len(), 3);
+        Ok(())
+    }
+
+    /// A request VIES rejects as malformed is not retried
+    #[tokio::test]
+    async fn test_vies_input_error_not_retried() {
+        let (server, client) = mock_vies().await;
+        Mock::given(method("POST"))
+            .respond_with(vies_response(false, "INVALID_INPUT"))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let err = client
+            .validate_vat_number("DE123456789", None)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("INVALID_INPUT"));
+    }
 }
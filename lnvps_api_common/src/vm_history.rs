@@ -1,3 +1,4 @@
+use crate::PageCursor;
 use anyhow::Result;
 use chrono::Utc;
 use lnvps_db::{LNVpsDb, Vm, VmHistory, VmHistoryActionType};
@@ -120,6 +121,72 @@ impl VmHistoryLogger {
         Ok(())
     }
 
+    pub async fn log_vm_rebooted(
+        &self,
+        vm_id: u64,
+        initiated_by_user: Option<u64>,
+        metadata: Option<Value>,
+    ) -> Result<()> {
+        let history = VmHistory {
+            id: 0,
+            vm_id,
+            action_type: VmHistoryActionType::Rebooted,
+            timestamp: Utc::now(),
+            initiated_by_user,
+            previous_state: None,
+            new_state: None,
+            metadata: serialize_json_to_bytes(metadata),
+            description: Some(format!("VM {} was rebooted", vm_id)),
+        };
+
+        self.db.insert_vm_history(&history).await?;
+        Ok(())
+    }
+
+    pub async fn log_vm_suspended(
+        &self,
+        vm_id: u64,
+        initiated_by_user: Option<u64>,
+        metadata: Option<Value>,
+    ) -> Result<()> {
+        let history = VmHistory {
+            id: 0,
+            vm_id,
+            action_type: VmHistoryActionType::Suspended,
+            timestamp: Utc::now(),
+            initiated_by_user,
+            previous_state: None,
+            new_state: None,
+            metadata: serialize_json_to_bytes(metadata),
+            description: Some(format!("VM {} was suspended", vm_id)),
+        };
+
+        self.db.insert_vm_history(&history).await?;
+        Ok(())
+    }
+
+    pub async fn log_vm_resumed(
+        &self,
+        vm_id: u64,
+        initiated_by_user: Option<u64>,
+        metadata: Option<Value>,
+    ) -> Result<()> {
+        let history = VmHistory {
+            id: 0,
+            vm_id,
+            action_type: VmHistoryActionType::Resumed,
+            timestamp: Utc::now(),
+            initiated_by_user,
+            previous_state: None,
+            new_state: None,
+            metadata: serialize_json_to_bytes(metadata),
+            description: Some(format!("VM {} was resumed", vm_id)),
+        };
+
+        self.db.insert_vm_history(&history).await?;
+        Ok(())
+    }
+
     pub async fn log_vm_deleted(
         &self,
         vm_id: u64,
@@ -329,6 +396,39 @@ impl VmHistoryLogger {
         Ok(())
     }
 
+    pub async fn log_vm_reachability_changed(
+        &self,
+        vm_id: u64,
+        ip: &str,
+        reachable: bool,
+        consecutive_failures: u32,
+    ) -> Result<()> {
+        let history = VmHistory {
+            id: 0,
+            vm_id,
+            action_type: VmHistoryActionType::ReachabilityChanged,
+            timestamp: Utc::now(),
+            initiated_by_user: None, // System action
+            previous_state: serialize_json_to_bytes(Some(json!({"reachable": !reachable}))),
+            new_state: serialize_json_to_bytes(Some(json!({"reachable": reachable}))),
+            metadata: serialize_json_to_bytes(Some(json!({
+                "ip": ip,
+                "consecutive_failures": consecutive_failures
+            }))),
+            description: Some(if reachable {
+                format!("VM {} is reachable on {}", vm_id, ip)
+            } else {
+                format!(
+                    "VM {} became unreachable on {} after {} failed checks",
+                    vm_id, ip, consecutive_failures
+                )
+            }),
+        };
+
+        self.db.insert_vm_history(&history).await?;
+        Ok(())
+    }
+
     pub async fn log_vm_payment_received(
         &self,
         vm_id: u64,
@@ -364,6 +464,44 @@ impl VmHistoryLogger {
         Ok(())
     }
 
+    /// Record a payment which settled for a different amount than requested,
+    /// `handling` describes what was done with the difference
+    pub async fn log_vm_payment_discrepancy(
+        &self,
+        vm_id: u64,
+        expected_amount: u64,
+        received_amount: u64,
+        payment_currency: &str,
+        handling: &str,
+    ) -> Result<()> {
+        let currency = Currency::from_str(payment_currency).unwrap_or(Currency::BTC);
+        let expected = CurrencyAmount::from_u64(currency, expected_amount);
+        let received = CurrencyAmount::from_u64(currency, received_amount);
+
+        let history = VmHistory {
+            id: 0,
+            vm_id,
+            action_type: VmHistoryActionType::PaymentReceived,
+            timestamp: Utc::now(),
+            initiated_by_user: None,
+            previous_state: None,
+            new_state: None,
+            metadata: serialize_json_to_bytes(Some(json!({
+                "expected_amount": expected_amount,
+                "received_amount": received_amount,
+                "payment_currency": payment_currency,
+                "handling": handling
+            }))),
+            description: Some(format!(
+                "VM {} received payment of {} but {} was requested ({})",
+                vm_id, received, expected, handling
+            )),
+        };
+
+        self.db.insert_vm_history(&history).await?;
+        Ok(())
+    }
+
     pub async fn log_vm_configuration_changed(
         &self,
         vm_id: u64,
@@ -423,6 +561,29 @@ impl VmHistoryLogger {
             .list_vm_history_paginated(vm_id, limit, offset)
             .await?)
     }
+
+    /// Get the page of history following `after`, along with the cursor for the
+    /// page after it (if this page is full)
+    pub async fn get_vm_history_after(
+        &self,
+        vm_id: u64,
+        after: Option<&PageCursor>,
+        limit: u64,
+    ) -> Result<(Vec<VmHistory>, Option<PageCursor>)> {
+        let after = match after {
+            Some(c) => Some((c.timestamp, c.id_u64()?)),
+            None => None,
+        };
+        let history = self.db.list_vm_history_after(vm_id, after, limit).await?;
+        let next = if history.len() as u64 == limit {
+            history
+                .last()
+                .map(|h| PageCursor::from_u64(h.timestamp, h.id))
+        } else {
+            None
+        };
+        Ok((history, next))
+    }
 }
 
 #[cfg(test)]
@@ -501,6 +662,8 @@ mod tests {
             fw_policy_in: None,
             fw_policy_out: None,
             admin_notes: None,
+            private_network: false,
+            suspended_at: None,
         };
         logger.log_vm_created(&vm, Some(1), None).await.unwrap();
         let history = logger.db.list_vm_history(42).await.unwrap();
@@ -540,6 +703,15 @@ mod tests {
         assert_eq!(history[0].action_type.to_string(), "restarted");
     }
 
+    #[tokio::test]
+    async fn test_log_vm_rebooted() {
+        let logger = make_logger();
+        logger.log_vm_rebooted(10, Some(1), None).await.unwrap();
+        let history = logger.db.list_vm_history(10).await.unwrap();
+        assert_eq!(history[0].action_type.to_string(), "rebooted");
+        assert_eq!(history[0].initiated_by_user, Some(1));
+    }
+
     #[tokio::test]
     async fn test_log_vm_deleted_with_reason() {
         let logger = make_logger();
@@ -673,6 +845,24 @@ mod tests {
         assert_eq!(next["user_id"], 7);
     }
 
+    #[tokio::test]
+    async fn test_log_vm_reachability_changed() {
+        let logger = make_logger();
+        logger
+            .log_vm_reachability_changed(18, "10.0.0.2", false, 3)
+            .await
+            .unwrap();
+        let history = logger.db.list_vm_history(18).await.unwrap();
+        assert_eq!(history[0].action_type.to_string(), "reachability_changed");
+        let next: serde_json::Value =
+            serde_json::from_slice(history[0].new_state.as_ref().unwrap()).unwrap();
+        assert_eq!(next["reachable"], false);
+        let meta: serde_json::Value =
+            serde_json::from_slice(history[0].metadata.as_ref().unwrap()).unwrap();
+        assert_eq!(meta["ip"], "10.0.0.2");
+        assert_eq!(meta["consecutive_failures"], 3);
+    }
+
     #[tokio::test]
     async fn test_log_vm_state_changed() {
         let logger = make_logger();
@@ -706,6 +896,8 @@ mod tests {
             fw_policy_in: None,
             fw_policy_out: None,
             admin_notes: None,
+            private_network: false,
+            suspended_at: None,
         };
         let mut new_vm = old_vm.clone();
         new_vm.image_id = 2;
@@ -739,4 +931,37 @@ mod tests {
         let page3 = logger.get_vm_history_paginated(21, 2, 4).await.unwrap();
         assert_eq!(page3.len(), 1);
     }
+
+    #[tokio::test]
+    async fn test_get_vm_history_after_is_stable_across_inserts() {
+        let logger = make_logger();
+        for _ in 0..5 {
+            logger.log_vm_started(22, None, None).await.unwrap();
+        }
+        let (page1, next) = logger.get_vm_history_after(22, None, 2).await.unwrap();
+        assert_eq!(page1.len(), 2);
+        let next = next.expect("full page has a cursor");
+
+        // a new row arriving between fetches must not shift the next page
+        logger.log_vm_stopped(22, None, None).await.unwrap();
+        let cursor = PageCursor::decode(&next.encode()).unwrap();
+        let (page2, next) = logger
+            .get_vm_history_after(22, Some(&cursor), 2)
+            .await
+            .unwrap();
+        let (page3, last) = logger
+            .get_vm_history_after(22, next.as_ref(), 2)
+            .await
+            .unwrap();
+        assert_eq!(page3.len(), 1);
+        assert!(last.is_none());
+
+        let ids: Vec<u64> = page1
+            .iter()
+            .chain(page2.iter())
+            .chain(page3.iter())
+            .map(|h| h.id)
+            .collect();
+        assert_eq!(ids, vec![5, 4, 3, 2, 1]);
+    }
 }
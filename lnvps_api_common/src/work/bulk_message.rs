@@ -0,0 +1,96 @@
+use lnvps_db::{AccountType, User};
+use sha2::{Digest, Sha256};
+
+/// Body of a [super::WorkJob::BulkMessage] as sent to one customer
+pub fn bulk_message_body(user: &User, message: &str) -> String {
+    if let Some(ref name) = user.billing_name {
+        format!("Dear {},\n\n{}", name, message)
+    } else {
+        format!("Dear Customer,\n\n{}", message)
+    }
+}
+
+/// Contact channels enabled for a customer, matching the `wants` checks of the
+/// worker's notification channels and in the order the worker tries them.
+///
+/// A bulk message is delivered once, on the first of these channels that
+/// succeeds, the later ones are only fallbacks.
+pub fn bulk_message_channels(user: &User) -> Vec<&'static str> {
+    let mut ret = Vec::new();
+    if user.contact_email && !user.email.is_empty() {
+        ret.push("email");
+    }
+    if user.contact_nip17 && user.account_type == AccountType::Nostr {
+        ret.push("nip17");
+    }
+    if user.contact_telegram && user.telegram_chat_id.is_some() {
+        ret.push("telegram");
+    }
+    if user.contact_whatsapp && user.whatsapp_verified && user.whatsapp_number.is_some() {
+        ret.push("whatsapp");
+    }
+    ret
+}
+
+/// Token which must be sent back to dispatch a bulk message after a dry run.
+///
+/// Not a secret, it ties the confirmation to the previewed subject, message
+/// and recipient count so any change needs a new dry run.
+pub fn bulk_message_confirm_token(subject: &str, message: &str, recipients: u64) -> String {
+    let mut hash = Sha256::new();
+    hash.update(subject.trim().as_bytes());
+    hash.update([0]);
+    hash.update(message.trim().as_bytes());
+    hash.update([0]);
+    hash.update(recipients.to_be_bytes());
+    hex::encode(&hash.finalize()[..16])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bulk_message_body() {
+        let mut user = User::default();
+        assert_eq!(bulk_message_body(&user, "hi"), "Dear Customer,\n\nhi");
+        user.billing_name = Some("Alice".to_string());
+        assert_eq!(bulk_message_body(&user, "hi"), "Dear Alice,\n\nhi");
+    }
+
+    #[test]
+    fn test_bulk_message_channels() {
+        let mut user = User {
+            contact_nip17: true,
+            account_type: AccountType::Nostr,
+            ..Default::default()
+        };
+        assert_eq!(bulk_message_channels(&user), vec!["nip17"]);
+
+        // email is only used with an address
+        user.contact_email = true;
+        assert_eq!(bulk_message_channels(&user), vec!["nip17"]);
+        user.email = "a@example.com".into();
+        // email is tried first, as in the worker
+        assert_eq!(bulk_message_channels(&user), vec!["email", "nip17"]);
+
+        // oauth accounts have no key to DM
+        user.account_type = AccountType::OAuth;
+        assert_eq!(bulk_message_channels(&user), vec!["email"]);
+
+        // telegram needs a linked chat
+        user.contact_telegram = true;
+        assert_eq!(bulk_message_channels(&user), vec!["email"]);
+        user.telegram_chat_id = Some(1);
+        assert_eq!(bulk_message_channels(&user), vec!["email", "telegram"]);
+    }
+
+    #[test]
+    fn test_confirm_token_binds_message_and_count() {
+        let token = bulk_message_confirm_token("Subject", "Body", 10);
+        assert_eq!(token, bulk_message_confirm_token(" Subject ", "Body\n", 10));
+        assert_ne!(token, bulk_message_confirm_token("Subject", "Body!", 10));
+        assert_ne!(token, bulk_message_confirm_token("Other", "Body", 10));
+        assert_ne!(token, bulk_message_confirm_token("Subject", "Body", 11));
+    }
+}
@@ -0,0 +1,286 @@
+use crate::{WorkCommander, WorkJob, WorkJobMessage};
+use anyhow::{Result, bail};
+use async_trait::async_trait;
+use chrono::{TimeDelta, Utc};
+use lnvps_db::{LNVpsDb, WorkQueueStatus};
+use log::warn;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Max number of queued jobs returned when listing the queue
+const MAX_LIST_PENDING: u64 = 1000;
+
+/// How long to wait before polling again when the queue is empty
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Default claim lease, see [DbWorkCommander::with_lease]
+pub const DEFAULT_WORK_QUEUE_LEASE: Duration = Duration::from_secs(600);
+
+/// Default number of runs before a job is dead-lettered
+pub const DEFAULT_WORK_QUEUE_MAX_ATTEMPTS: u32 = 5;
+
+/// Work queue stored in the `work_queue` table, for setups without Redis.
+///
+/// Any number of workers can read from the same queue, a job is only ever
+/// claimed by one of them. A job which fails (or whose worker stops) too many
+/// times is moved to the dead letter state and no longer run.
+#[derive(Clone)]
+pub struct DbWorkCommander {
+    db: Arc<dyn LNVpsDb>,
+    consumer_name: String,
+    /// Claimed jobs whose claim isn't renewed within this time are handed out
+    /// again
+    reclaim_after: TimeDelta,
+    /// Delay before a failed job is retried
+    retry_after: TimeDelta,
+    /// Runs of a job before it is dead-lettered
+    max_attempts: u32,
+}
+
+impl DbWorkCommander {
+    pub fn new(db: Arc<dyn LNVpsDb>, consumer_name: &str) -> Self {
+        Self {
+            db,
+            consumer_name: consumer_name.to_string(),
+            reclaim_after: TimeDelta::from_std(DEFAULT_WORK_QUEUE_LEASE).unwrap(),
+            retry_after: TimeDelta::seconds(10),
+            max_attempts: DEFAULT_WORK_QUEUE_MAX_ATTEMPTS,
+        }
+    }
+
+    /// How long a claimed job may go without its claim being renewed before
+    /// it is handed to another worker. Workers renew the claim of running
+    /// jobs, so this bounds how long a stopped worker holds a job, not how
+    /// long a job may run.
+    pub fn with_lease(mut self, lease: Duration) -> Self {
+        self.reclaim_after = TimeDelta::from_std(lease).unwrap_or(TimeDelta::MAX);
+        self
+    }
+
+    /// Runs of a job, failed or abandoned, before it is dead-lettered
+    pub fn with_max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    fn parse_id(id: &str) -> Result<u64> {
+        match id.parse() {
+            Ok(id) => Ok(id),
+            Err(_) => bail!("Invalid work queue id: {}", id),
+        }
+    }
+}
+
+#[async_trait]
+impl WorkCommander for DbWorkCommander {
+    async fn send(&self, job: WorkJob) -> Result<String> {
+        let id = self.db.enqueue_job(&serde_json::to_string(&job)?).await?;
+        Ok(id.to_string())
+    }
+
+    async fn recv(&self) -> Result<Vec<WorkJobMessage>> {
+        loop {
+            let Some(item) = self
+                .db
+                .claim_next_job(&self.consumer_name, self.reclaim_after, self.max_attempts)
+                .await?
+            else {
+                tokio::time::sleep(POLL_INTERVAL).await;
+                continue;
+            };
+            match serde_json::from_str(&item.job) {
+                Ok(job) => {
+                    return Ok(vec![WorkJobMessage {
+                        id: item.id.to_string(),
+                        job,
+                        // retry of a failed run
+                        is_pending: item.attempts > 0,
+                    }]);
+                }
+                Err(e) => {
+                    warn!("Dropping invalid work queue job {}: {}", item.id, e);
+                    self.db.delete_job(item.id).await?;
+                }
+            }
+        }
+    }
+
+    async fn ack(&self, id: &str) -> Result<()> {
+        self.db.complete_job(Self::parse_id(id)?).await?;
+        Ok(())
+    }
+
+    async fn fail(&self, id: &str, error: &str) -> Result<()> {
+        self.db
+            .fail_job(
+                Self::parse_id(id)?,
+                error,
+                Utc::now() + self.retry_after,
+                self.max_attempts,
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn renew(&self, id: &str) -> Result<()> {
+        self.db.renew_job_claim(Self::parse_id(id)?).await?;
+        Ok(())
+    }
+
+    fn lease(&self) -> Option<Duration> {
+        self.reclaim_after.to_std().ok()
+    }
+
+    async fn list_pending(&self) -> Result<Vec<WorkJobMessage>> {
+        Ok(self
+            .db
+            .list_work_queue(MAX_LIST_PENDING)
+            .await?
+            .into_iter()
+            .filter_map(|j| {
+                Some(WorkJobMessage {
+                    id: j.id.to_string(),
+                    job: serde_json::from_str(&j.job).ok()?,
+                    is_pending: j.status == WorkQueueStatus::Claimed,
+                })
+            })
+            .collect())
+    }
+
+    async fn cancel(&self, id: &str) -> Result<bool> {
+        Ok(self.db.delete_job(Self::parse_id(id)?).await?)
+    }
+
+    async fn worker_idle(&self) -> Result<Option<Duration>> {
+        Ok(self
+            .db
+            .work_queue_last_poll()
+            .await?
+            .map(|t| (Utc::now() - t).to_std().unwrap_or(Duration::ZERO)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MockDb;
+    use std::collections::HashSet;
+
+    /// Only covers the MockDb claim (a mutex), the `for update skip locked`
+    /// claim in MySQL is not exercised here
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_two_workers_never_claim_same_job() -> Result<()> {
+        let db: Arc<dyn LNVpsDb> = Arc::new(MockDb::default());
+        let publisher = DbWorkCommander::new(db.clone(), "");
+        for i in 0..50 {
+            publisher.send(WorkJob::CheckVm { vm_id: i }).await?;
+        }
+
+        let workers = ["worker-a", "worker-b"].map(|name| {
+            let work = DbWorkCommander::new(db.clone(), name);
+            tokio::spawn(async move {
+                let mut claimed = Vec::new();
+                while let Ok(Ok(jobs)) =
+                    tokio::time::timeout(Duration::from_secs(2), work.recv()).await
+                {
+                    for j in jobs {
+                        claimed.push(j.id.clone());
+                        work.ack(&j.id).await.unwrap();
+                    }
+                }
+                claimed
+            })
+        });
+
+        let mut seen = HashSet::new();
+        for w in workers {
+            for id in w.await? {
+                assert!(seen.insert(id.clone()), "job {} claimed twice", id);
+            }
+        }
+        assert_eq!(seen.len(), 50);
+        assert!(publisher.list_pending().await?.is_empty());
+        assert!(publisher.worker_idle().await?.is_some());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_failed_job_is_retried() -> Result<()> {
+        let db: Arc<dyn LNVpsDb> = Arc::new(MockDb::default());
+        let work = DbWorkCommander {
+            retry_after: TimeDelta::zero(),
+            ..DbWorkCommander::new(db.clone(), "worker")
+        };
+        let id = work.send(WorkJob::CheckVms).await?;
+
+        let first = work.recv().await?;
+        assert_eq!(first[0].id, id);
+        assert!(!first[0].is_pending);
+        assert!(work.list_pending().await?[0].is_pending);
+
+        work.fail(&id, "host offline").await?;
+        let retry = work.recv().await?;
+        assert_eq!(retry[0].id, id);
+        assert!(retry[0].is_pending);
+
+        work.ack(&id).await?;
+        assert!(!work.cancel(&id).await?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_job_dead_lettered_after_max_attempts() -> Result<()> {
+        let db = Arc::new(MockDb::default());
+        let work = DbWorkCommander {
+            retry_after: TimeDelta::zero(),
+            ..DbWorkCommander::new(db.clone(), "worker").with_max_attempts(2)
+        };
+        let id = work.send(WorkJob::CheckVms).await?;
+
+        work.recv().await?;
+        work.fail(&id, "first").await?;
+        work.recv().await?;
+        work.fail(&id, "second").await?;
+
+        // no longer handed out or listed, but kept with its last error
+        assert!(
+            tokio::time::timeout(Duration::from_millis(100), work.recv())
+                .await
+                .is_err()
+        );
+        assert!(work.list_pending().await?.is_empty());
+        let item = db.work_queue.lock().await[&DbWorkCommander::parse_id(&id)?].clone();
+        assert_eq!(item.status, WorkQueueStatus::Dead);
+        assert_eq!(item.attempts, 2);
+        assert_eq!(item.last_error.as_deref(), Some("second"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_renewed_claim_is_not_reclaimed() -> Result<()> {
+        let db = Arc::new(MockDb::default());
+        let work = DbWorkCommander::new(db.clone(), "worker").with_lease(Duration::from_millis(50));
+        assert_eq!(work.lease(), Some(Duration::from_millis(50)));
+        let id = work.send(WorkJob::CheckVms).await?;
+        work.recv().await?;
+
+        // renewed while running, nobody else gets it
+        for _ in 0..3 {
+            tokio::time::sleep(Duration::from_millis(30)).await;
+            work.renew(&id).await?;
+        }
+        let other = DbWorkCommander::new(db.clone(), "other").with_lease(Duration::from_millis(50));
+        assert!(
+            tokio::time::timeout(Duration::from_millis(20), other.recv())
+                .await
+                .is_err()
+        );
+
+        // once the claim lapses it is reclaimed, counting as an attempt
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        let reclaimed = other.recv().await?;
+        assert_eq!(reclaimed[0].id, id);
+        assert!(reclaimed[0].is_pending);
+        Ok(())
+    }
+}
@@ -3,10 +3,15 @@ use anyhow::Result;
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
 use std::fmt;
+use std::time::Duration;
 
+mod bulk_message;
+mod db;
 mod feedback;
 mod sender;
 
+pub use bulk_message::*;
+pub use db::*;
 pub use feedback::*;
 pub use sender::*;
 
@@ -23,12 +28,41 @@ pub trait WorkCommander: Send + Sync {
     async fn send(&self, job: WorkJob) -> Result<String>;
     async fn recv(&self) -> Result<Vec<WorkJobMessage>>;
     async fn ack(&self, id: &str) -> Result<()>;
+    /// Record a failed run of a job which should be retried, queues without
+    /// retry tracking leave the job to be re-claimed once idle
+    async fn fail(&self, _id: &str, _error: &str) -> Result<()> {
+        Ok(())
+    }
+    /// Renew the claim on a running job so it isn't handed to another worker,
+    /// queues without claim leases do nothing
+    async fn renew(&self, _id: &str) -> Result<()> {
+        Ok(())
+    }
+    /// How long a claim lasts without [Self::renew], `None` when the queue
+    /// has no claim lease
+    fn lease(&self) -> Option<Duration> {
+        None
+    }
+    /// List jobs which are queued or picked up but not yet acked,
+    /// in-progress jobs have [WorkJobMessage::is_pending] set
+    async fn list_pending(&self) -> Result<Vec<WorkJobMessage>>;
+    /// Remove a job from the queue, returns false when no such job is queued.
+    ///
+    /// A job already being processed is not interrupted but will not be
+    /// retried.
+    async fn cancel(&self, id: &str) -> Result<bool>;
+    /// Time since a worker last read from the queue, `None` when no worker
+    /// has read from it yet
+    async fn worker_idle(&self) -> Result<Option<Duration>>;
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum WorkJob {
     /// Sync resources from hosts to database
     PatchHosts,
+    /// Sync one host's resources to the database, `force` applies values
+    /// the auto-discovery sanity checks would ignore
+    SyncHost { host_id: u64, force: bool },
     /// Check all running VMS
     CheckVms,
     /// Check the VM status matches database state
@@ -57,6 +91,9 @@ pub enum WorkJob {
         subject: String,
         message: String,
         admin_user_id: u64,
+        /// IP address the admin sent the request from, for the audit log
+        #[serde(default)]
+        client_ip: Option<String>,
     },
     /// Delete a VM at admin request
     DeleteVm {
@@ -80,6 +117,9 @@ pub enum WorkJob {
         vm_id: u64,
         admin_user_id: Option<u64>,
     },
+    /// Gracefully reboot a VM for its owner, the host falls back to a reset
+    /// when the guest doesn't shut down in time
+    RebootVm { vm_id: u64, user_id: u64 },
     /// Check all nostr domains DNS records - enable disabled domains with DNS records, disable active domains without DNS records
     CheckNostrDomains,
     /// Process VM upgrade after payment confirmation
@@ -126,17 +166,21 @@ pub enum WorkJob {
         reply_channel: String,
     },
     /// Import a VM that exists on a host but isn't tracked in the database,
-    /// assigning it to a user and billing it via the region's custom pricing.
+    /// assigning it to a user and billing it via a standard template or the
+    /// region's custom pricing.
     ImportVm {
         host_id: u64,
         /// Raw host VM id (e.g. Proxmox vmid)
         host_vm_id: i64,
         user_id: u64,
+        /// Standard template to bill the VM with, region custom pricing if unset
+        #[serde(default)]
+        template_id: Option<u64>,
         admin_user_id: u64,
         reason: Option<String>,
     },
-    /// Re-install a VM: stop it, wipe & re-import the primary disk from its
-    /// current image template, then start it again. Runs on the worker so it is
+    /// Re-install a VM: stop it, wipe & re-import the primary disk from the
+    /// `new_image_id` template, then start it again. Runs on the worker so it is
     /// serialised with spawn (avoiding a reinstall racing an in-flight spawn).
     /// The worker publishes the outcome on `reply_channel` so the API can wait
     /// for the result before responding to the user.
@@ -146,7 +190,8 @@ pub enum WorkJob {
         user_id: Option<u64>,
         /// Image id already in effect on the VM (recorded in history).
         old_image_id: u64,
-        /// Image the VM was reinstalled with (recorded in history).
+        /// Image to reinstall the VM with, persisted on the VM once the new
+        /// disk is imported.
         new_image_id: u64,
         /// Temporary channel id the worker replies on (via job feedback).
         reply_channel: String,
@@ -168,6 +213,11 @@ pub enum WorkJob {
     DownloadOsImages { image_id: Option<u64> },
     /// Check all active subscriptions for expiry, auto-renewal, and deactivation.
     CheckSubscriptions,
+    /// Cancel expired unpaid payments and release the capacity they held, also
+    /// releases stale VM create request ids.
+    CancelExpiredPayments,
+    /// Evaluate user-configured VM usage alerts against recent time-series data.
+    CheckUsageAlerts,
     /// Process automated referral commission payouts (BTC, over Lightning).
     ProcessReferralPayouts,
     /// Poll routers to refresh cached tunnel/BGP session/route state and record
@@ -185,6 +235,9 @@ pub enum WorkJob {
     SetRouterDefaultRoute { router_id: u64, next_hop: String },
     /// Remove the static default route(s) from a router (admin action).
     ClearRouterDefaultRoute { router_id: u64 },
+    /// Re-add the ARP entries of every IP whose range uses a router, e.g. after
+    /// it was rebooted or replaced and lost its ARP table (admin action).
+    RebuildRouterArp { router_id: u64 },
     /// Enable or disable a tunnel on a router (admin action).
     ToggleTunnel {
         router_id: u64,
@@ -200,6 +253,69 @@ pub enum WorkJob {
         ip_range_id: u64,
         admin_user_id: Option<u64>,
     },
+    /// Queue a [WorkJob::BackupVm] for every VM whose backup schedule is due
+    CheckBackups,
+    /// Take a scheduled backup of a VM and prune backups over its retention
+    BackupVm { vm_id: u64 },
+    /// Add the network transfer of VMs since the last run to their monthly
+    /// bandwidth totals
+    CheckBandwidthUsage,
+    /// Restore a VM from one of its backups
+    RestoreVmBackup { vm_id: u64, backup_id: u64 },
+    /// Recompute a VM's expiry from its paid payments and extend it when it
+    /// falls short, never shortens the expiry
+    RecalculateVmExpiry { vm_id: u64 },
+    /// Stop or delete VM's which were never paid for and are older than
+    /// `grace_hours`, reclaiming the capacity held by abandoned orders
+    ReclaimUnpaidVms {
+        grace_hours: u64,
+        action: UnpaidVmAction,
+        admin_user_id: Option<u64>,
+    },
+    /// Put a host into maintenance and move every VM off it, each VM is
+    /// migrated to `target_host_id` when the hosts support live migration and
+    /// stopped otherwise
+    DrainHost {
+        host_id: u64,
+        target_host_id: Option<u64>,
+        /// Channel id the progress of each VM is published on (via job
+        /// feedback)
+        #[serde(default)]
+        batch_id: Option<String>,
+        admin_user_id: Option<u64>,
+    },
+    /// Live-migrate a VM to another host, the VM is stopped instead when the
+    /// migration fails
+    MigrateVm {
+        vm_id: u64,
+        target_host_id: u64,
+        /// Channel id of the [WorkJob::DrainHost] this migration is part of
+        #[serde(default)]
+        batch_id: Option<String>,
+        admin_user_id: Option<u64>,
+    },
+}
+
+/// Hours after creation before a never-paid VM counts as abandoned, unless
+/// configured or requested otherwise
+pub const DEFAULT_UNPAID_GRACE_HOURS: u64 = 72;
+
+/// What [WorkJob::ReclaimUnpaidVms] does with each unpaid VM
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum UnpaidVmAction {
+    Stop,
+    /// Purge the VM, never-paid VM's carry no customer data
+    Delete,
+}
+
+impl fmt::Display for UnpaidVmAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UnpaidVmAction::Stop => write!(f, "stop"),
+            UnpaidVmAction::Delete => write!(f, "delete"),
+        }
+    }
 }
 
 impl WorkJob {
@@ -209,15 +325,25 @@ impl WorkJob {
             Self::CheckNostrDomains { .. } => true,
             Self::StopVm { .. } => true,
             Self::StartVm { .. } => true,
+            // A reboot is a one-shot user action, don't repeat it later
+            Self::RebootVm { .. } => true,
             Self::CheckVm { .. } => true,
             Self::CheckVms => true,
             Self::CheckSubscriptions => true,
+            Self::CancelExpiredPayments => true,
+            Self::CheckUsageAlerts => true,
+            Self::CheckBackups => true,
+            // A failed scheduled backup waits for the next interval
+            Self::BackupVm { .. } => true,
+            Self::CheckBandwidthUsage => true,
             // A discovery request is a one-shot read tied to a waiting admin
             // request; never retry it if it fails.
             Self::ListUnmanagedVms { .. } => true,
             // A reinstall is a one-shot action tied to a waiting user request;
             // don't let the worker silently retry it later.
             Self::ReinstallVm { .. } => true,
+            // A failed migration already fell back to stopping the VM
+            Self::MigrateVm { .. } => true,
             _ => false,
         }
     }
@@ -227,6 +353,7 @@ impl fmt::Display for WorkJob {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             WorkJob::PatchHosts => write!(f, "PatchHosts"),
+            WorkJob::SyncHost { .. } => write!(f, "SyncHost"),
             WorkJob::CheckVms => write!(f, "CheckVms"),
             WorkJob::CheckVm { .. } => write!(f, "CheckVm"),
             WorkJob::SendNotification { .. } => write!(f, "SendNotification"),
@@ -235,6 +362,7 @@ impl fmt::Display for WorkJob {
             WorkJob::DeleteVm { .. } => write!(f, "DeleteVm"),
             WorkJob::StartVm { .. } => write!(f, "StartVm"),
             WorkJob::StopVm { .. } => write!(f, "StopVm"),
+            WorkJob::RebootVm { .. } => write!(f, "RebootVm"),
             WorkJob::CheckNostrDomains => write!(f, "CheckNostrDomains"),
             WorkJob::ProcessVmUpgrade { .. } => write!(f, "ProcessVmUpgrade"),
             WorkJob::ConfigureVm { .. } => write!(f, "ConfigureVm"),
@@ -250,14 +378,25 @@ impl fmt::Display for WorkJob {
             WorkJob::SendEmailVerification { .. } => write!(f, "SendEmailVerification"),
             WorkJob::DownloadOsImages { .. } => write!(f, "DownloadOsImages"),
             WorkJob::CheckSubscriptions => write!(f, "CheckSubscriptions"),
+            WorkJob::CancelExpiredPayments => write!(f, "CancelExpiredPayments"),
+            WorkJob::CheckUsageAlerts => write!(f, "CheckUsageAlerts"),
             WorkJob::ProcessReferralPayouts => write!(f, "ProcessReferralPayouts"),
             WorkJob::SpawnVm { .. } => write!(f, "SpawnVm"),
             WorkJob::SyncRouterState => write!(f, "SyncRouterState"),
             WorkJob::ToggleBgpSession { .. } => write!(f, "ToggleBgpSession"),
             WorkJob::SetRouterDefaultRoute { .. } => write!(f, "SetRouterDefaultRoute"),
             WorkJob::ClearRouterDefaultRoute { .. } => write!(f, "ClearRouterDefaultRoute"),
+            WorkJob::RebuildRouterArp { .. } => write!(f, "RebuildRouterArp"),
             WorkJob::ToggleTunnel { .. } => write!(f, "ToggleTunnel"),
             WorkJob::PatchIpRangeDns { .. } => write!(f, "PatchIpRangeDns"),
+            WorkJob::CheckBackups => write!(f, "CheckBackups"),
+            WorkJob::BackupVm { .. } => write!(f, "BackupVm"),
+            WorkJob::CheckBandwidthUsage => write!(f, "CheckBandwidthUsage"),
+            WorkJob::RestoreVmBackup { .. } => write!(f, "RestoreVmBackup"),
+            WorkJob::RecalculateVmExpiry { .. } => write!(f, "RecalculateVmExpiry"),
+            WorkJob::ReclaimUnpaidVms { .. } => write!(f, "ReclaimUnpaidVms"),
+            WorkJob::DrainHost { .. } => write!(f, "DrainHost"),
+            WorkJob::MigrateVm { .. } => write!(f, "MigrateVm"),
         }
     }
 }
@@ -280,6 +419,10 @@ mod tests {
             WorkJob::ClearRouterDefaultRoute { router_id: 1 }.to_string(),
             "ClearRouterDefaultRoute"
         );
+        assert_eq!(
+            WorkJob::RebuildRouterArp { router_id: 1 }.to_string(),
+            "RebuildRouterArp"
+        );
         assert_eq!(
             WorkJob::ToggleTunnel {
                 router_id: 1,
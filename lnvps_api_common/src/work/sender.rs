@@ -5,12 +5,21 @@ use chrono::Utc;
 use log::info;
 use redis::aio::MultiplexedConnection;
 use redis::streams::{
-    StreamAddOptions, StreamAutoClaimOptions, StreamAutoClaimReply, StreamId, StreamReadOptions,
-    StreamReadReply, StreamTrimStrategy, StreamTrimmingMode,
+    StreamAddOptions, StreamAutoClaimOptions, StreamAutoClaimReply, StreamId,
+    StreamInfoConsumersReply, StreamInfoGroupsReply, StreamPendingCountReply, StreamRangeReply,
+    StreamReadOptions, StreamReadReply, StreamTrimStrategy, StreamTrimmingMode,
 };
 use redis::{AsyncCommands, FromRedisValue};
-use tokio::sync::Mutex;
-use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::sync::{Mutex, Notify};
+
+/// Consumer group used by the API worker to read the work stream
+pub const WORKER_GROUP: &str = "workers";
+
+/// Number of stream entries read per request when listing queued jobs
+const LIST_PENDING_PAGE: usize = 500;
 
 #[derive(Clone)]
 pub struct RedisWorkCommander {
@@ -42,7 +51,7 @@ impl RedisWorkCommander {
         Ok(Self {
             conn,
             redis,
-            group_name: String::new(),
+            group_name: WORKER_GROUP.to_string(),
             consumer_name: String::new(),
         })
     }
@@ -109,6 +118,16 @@ impl RedisWorkCommander {
             .collect())
     }
 
+    /// Range start which excludes the entry `id`, or the whole stream when no
+    /// entry was read yet
+    fn range_after(id: &str) -> String {
+        if id.is_empty() || id == "0-0" {
+            "-".to_string()
+        } else {
+            format!("({}", id)
+        }
+    }
+
     fn map_work_job(stream_id: &StreamId) -> Option<WorkJobMessage> {
         if let Some(job_value) = stream_id.map.get("job")
             && let Ok(job_str) = String::from_redis_value(job_value.clone())
@@ -153,11 +172,102 @@ impl WorkCommander for RedisWorkCommander {
         let _: u64 = conn.xack("worker", &self.group_name, &[id]).await?;
         Ok(())
     }
+
+    async fn list_pending(&self) -> Result<Vec<WorkJobMessage>> {
+        let mut conn = self.conn.clone();
+        let exists: bool = conn.exists("worker").await?;
+        if !exists {
+            return Ok(vec![]);
+        }
+        let groups: StreamInfoGroupsReply = conn.xinfo_groups("worker").await?;
+        // entries added before the group exists are never read by the worker
+        let Some(group) = groups.groups.iter().find(|g| g.name == self.group_name) else {
+            return Ok(vec![]);
+        };
+        // jobs claimed by a worker but not acked yet, paged by entry id
+        let mut in_progress = Vec::new();
+        let mut start = "-".to_string();
+        loop {
+            let page: StreamPendingCountReply = conn
+                .xpending_count("worker", &self.group_name, &start, "+", LIST_PENDING_PAGE)
+                .await?;
+            let Some(last) = page.ids.last() else {
+                break;
+            };
+            start = Self::range_after(&last.id);
+            let done = page.ids.len() < LIST_PENDING_PAGE;
+            in_progress.extend(page.ids.into_iter().map(|p| p.id));
+            if done {
+                break;
+            }
+        }
+
+        let mut jobs = Vec::new();
+        for id in &in_progress {
+            let entry: StreamRangeReply = conn.xrange_count("worker", id, id, 1).await?;
+            jobs.extend(entry.ids.iter().filter_map(|e| {
+                Self::map_work_job(e).map(|mut j| {
+                    j.is_pending = true;
+                    j
+                })
+            }));
+        }
+
+        // entries after the group's last delivered id haven't been read yet
+        let mut start = Self::range_after(&group.last_delivered_id);
+        loop {
+            let page: StreamRangeReply = conn
+                .xrange_count("worker", &start, "+", LIST_PENDING_PAGE)
+                .await?;
+            let Some(last) = page.ids.last() else {
+                break;
+            };
+            start = Self::range_after(&last.id);
+            jobs.extend(page.ids.iter().filter_map(Self::map_work_job));
+            if page.ids.len() < LIST_PENDING_PAGE {
+                break;
+            }
+        }
+        Ok(jobs)
+    }
+
+    async fn cancel(&self, id: &str) -> Result<bool> {
+        if !self.list_pending().await?.iter().any(|j| j.id == id) {
+            return Ok(false);
+        }
+        let mut conn = self.conn.clone();
+        // ack first so an in-progress job isn't re-claimed after it's deleted
+        let _: u64 = conn.xack("worker", &self.group_name, &[id]).await?;
+        let deleted: u64 = conn.xdel("worker", &[id]).await?;
+        Ok(deleted > 0)
+    }
+
+    async fn worker_idle(&self) -> Result<Option<Duration>> {
+        let mut conn = self.conn.clone();
+        let exists: bool = conn.exists("worker").await?;
+        if !exists {
+            return Ok(None);
+        }
+        let groups: StreamInfoGroupsReply = conn.xinfo_groups("worker").await?;
+        if !groups.groups.iter().any(|g| g.name == self.group_name) {
+            return Ok(None);
+        }
+        // workers poll the stream in a loop, the most recent one is the live worker
+        let consumers: StreamInfoConsumersReply =
+            conn.xinfo_consumers("worker", &self.group_name).await?;
+        Ok(consumers
+            .consumers
+            .iter()
+            .map(|c| Duration::from_millis(c.idle as u64))
+            .min())
+    }
 }
 
+/// In-memory work queue for tests and single process setups
 pub struct ChannelWorkCommander {
-    sender: UnboundedSender<WorkJobMessage>,
-    receiver: Mutex<UnboundedReceiver<WorkJobMessage>>,
+    queue: Mutex<VecDeque<WorkJobMessage>>,
+    notify: Notify,
+    next_seq: AtomicU64,
 }
 
 impl Default for ChannelWorkCommander {
@@ -168,10 +278,10 @@ impl Default for ChannelWorkCommander {
 
 impl ChannelWorkCommander {
     pub fn new() -> Self {
-        let (tx, rx) = unbounded_channel();
         Self {
-            sender: tx,
-            receiver: Mutex::new(rx),
+            queue: Mutex::new(VecDeque::new()),
+            notify: Notify::new(),
+            next_seq: AtomicU64::new(0),
         }
     }
 }
@@ -179,24 +289,87 @@ impl ChannelWorkCommander {
 #[async_trait]
 impl WorkCommander for ChannelWorkCommander {
     async fn send(&self, job: WorkJob) -> Result<String> {
-        let id = Utc::now().timestamp_millis().to_string();
+        // same shape as a redis stream id, unique even within the same millisecond
+        let id = format!(
+            "{}-{}",
+            Utc::now().timestamp_millis(),
+            self.next_seq.fetch_add(1, Ordering::Relaxed)
+        );
         let msg = WorkJobMessage {
             id: id.clone(),
             job,
             is_pending: false,
         };
-        self.sender.send(msg)?;
+        self.queue.lock().await.push_back(msg);
+        self.notify.notify_one();
         Ok(id)
     }
 
     async fn recv(&self) -> Result<Vec<WorkJobMessage>> {
-        let Some(next) = self.receiver.lock().await.recv().await else {
-            return Ok(vec![]);
-        };
-        Ok(vec![next])
+        loop {
+            if let Some(next) = self.queue.lock().await.pop_front() {
+                return Ok(vec![next]);
+            }
+            self.notify.notified().await;
+        }
     }
 
     async fn ack(&self, _id: &str) -> Result<()> {
         Ok(())
     }
+
+    async fn list_pending(&self) -> Result<Vec<WorkJobMessage>> {
+        Ok(self.queue.lock().await.iter().cloned().collect())
+    }
+
+    async fn cancel(&self, id: &str) -> Result<bool> {
+        let mut queue = self.queue.lock().await;
+        let len = queue.len();
+        queue.retain(|j| j.id != id);
+        Ok(queue.len() != len)
+    }
+
+    async fn worker_idle(&self) -> Result<Option<Duration>> {
+        // the worker runs in this process, it is live as long as we are
+        Ok(Some(Duration::ZERO))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_range_after() {
+        assert_eq!(
+            RedisWorkCommander::range_after("1700000000000-3"),
+            "(1700000000000-3"
+        );
+        assert_eq!(RedisWorkCommander::range_after("0-0"), "-");
+        assert_eq!(RedisWorkCommander::range_after(""), "-");
+    }
+
+    #[tokio::test]
+    async fn test_channel_list_and_cancel() -> Result<()> {
+        let work = ChannelWorkCommander::new();
+        let first = work.send(WorkJob::CheckVms).await?;
+        let second = work.send(WorkJob::CheckVm { vm_id: 1 }).await?;
+        assert_ne!(first, second);
+
+        let pending = work.list_pending().await?;
+        assert_eq!(
+            pending.iter().map(|j| j.id.as_str()).collect::<Vec<_>>(),
+            vec![first.as_str(), second.as_str()]
+        );
+
+        assert!(work.cancel(&first).await?);
+        assert!(!work.cancel(&first).await?);
+        assert_eq!(work.list_pending().await?.len(), 1);
+
+        // the cancelled job is never picked up
+        let next = work.recv().await?;
+        assert_eq!(next[0].id, second);
+        assert!(work.list_pending().await?.is_empty());
+        Ok(())
+    }
 }
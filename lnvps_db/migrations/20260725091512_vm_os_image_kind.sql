@@ -0,0 +1,2 @@
+ALTER TABLE vm_os_image
+    ADD COLUMN image_kind SMALLINT UNSIGNED NOT NULL DEFAULT 0;
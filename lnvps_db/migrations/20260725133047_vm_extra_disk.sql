@@ -0,0 +1,19 @@
+-- Additional data volumes attached to a VM alongside its primary disk.
+-- Each volume lives on a host storage (vm_host_disk) and occupies a stable
+-- attachment slot on the VM.
+
+create table vm_extra_disk
+(
+    id      integer unsigned  not null auto_increment primary key,
+    vm_id   integer unsigned  not null,
+    -- Host storage the volume is allocated on
+    disk_id integer unsigned  not null,
+    -- Size in bytes
+    size    bigint unsigned   not null,
+    -- Attachment slot on the VM (0-based, unique per VM)
+    slot    smallint unsigned not null,
+    created timestamp         not null default current_timestamp,
+    constraint fk_vm_extra_disk_vm foreign key (vm_id) references vm (id),
+    constraint fk_vm_extra_disk_disk foreign key (disk_id) references vm_host_disk (id),
+    constraint ux_vm_extra_disk_slot unique (vm_id, slot)
+);
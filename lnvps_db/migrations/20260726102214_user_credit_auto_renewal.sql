@@ -0,0 +1,24 @@
+-- Prepaid account credit, per user and currency. Amounts are in the smallest
+-- unit of the currency (millisats for BTC, cents for fiat).
+create table user_credit
+(
+    user_id  integer unsigned not null,
+    currency varchar(5)       not null,
+    amount   bigint unsigned  not null default 0,
+    updated  timestamp        not null default current_timestamp on update current_timestamp,
+    primary key (user_id, currency),
+    constraint fk_user_credit_user foreign key (user_id) references users (id)
+);
+
+-- One row per automatic renewal attempt. The unique key on the billing period
+-- (subscription + the expiry being renewed) makes the worker's auto-renewal
+-- idempotent: a period can only ever be charged once.
+create table subscription_auto_renewal
+(
+    id              integer unsigned not null auto_increment primary key,
+    subscription_id integer unsigned not null,
+    period_expires  timestamp        not null,
+    created         timestamp        not null default current_timestamp,
+    constraint fk_subscription_auto_renewal_sub foreign key (subscription_id) references subscription (id) on delete cascade,
+    constraint ux_subscription_auto_renewal_period unique (subscription_id, period_expires)
+);
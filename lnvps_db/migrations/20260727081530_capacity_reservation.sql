@@ -0,0 +1,19 @@
+-- Short-lived capacity holds taken while a VM is being placed on a host.
+-- Unpaid VMs don't count towards host usage, so a reservation keeps the
+-- resources held until the VM is paid for or the reservation expires.
+create table capacity_reservation
+(
+    id        integer unsigned  not null auto_increment primary key,
+    host_id   integer unsigned  not null,
+    disk_id   integer unsigned  not null,
+    -- VM created against this reservation, once known
+    vm_id     integer unsigned,
+    cpu       smallint unsigned not null,
+    -- Memory in bytes
+    memory    bigint unsigned   not null,
+    -- Disk size in bytes
+    disk_size bigint unsigned   not null,
+    expires   timestamp         not null,
+    constraint fk_capacity_reservation_host foreign key (host_id) references vm_host (id),
+    index ix_capacity_reservation_host (host_id, expires)
+);
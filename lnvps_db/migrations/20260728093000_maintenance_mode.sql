@@ -0,0 +1,4 @@
+alter table vm_host
+    add column maintenance bit(1) not null default 0;
+alter table region
+    add column maintenance bit(1) not null default 0;
@@ -0,0 +1,2 @@
+alter table region
+    add column reachability_check bit(1) not null default 0;
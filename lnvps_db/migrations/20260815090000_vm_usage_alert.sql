@@ -0,0 +1,18 @@
+-- User-configurable resource usage alerts evaluated against the VM time-series data
+create table vm_usage_alert
+(
+    id             integer unsigned not null auto_increment primary key,
+    vm_id          integer unsigned not null,
+    -- 0 = cpu, 1 = memory, 2 = net_in, 3 = net_out, 4 = disk_read, 5 = disk_write
+    metric         smallint unsigned not null,
+    -- Percent for cpu/memory, bytes per second for network/disk
+    threshold      double           not null,
+    -- Averaging window in seconds
+    window_seconds integer unsigned not null default 300,
+    -- When the alert last sent a notification (cooldown)
+    last_triggered timestamp        null     default null,
+    created        timestamp        not null default current_timestamp,
+    constraint fk_vm_usage_alert_vm foreign key (vm_id) references vm (id)
+);
+
+create index ix_vm_usage_alert_vm on vm_usage_alert (vm_id);
@@ -0,0 +1,9 @@
+-- Per-region allowlist of OS images, a region without any rows offers every enabled image
+create table region_os_image
+(
+    region_id integer unsigned not null,
+    image_id  integer unsigned not null,
+    primary key (region_id, image_id),
+    constraint fk_region_os_image_region foreign key (region_id) references region (id),
+    constraint fk_region_os_image_image foreign key (image_id) references vm_os_image (id)
+);
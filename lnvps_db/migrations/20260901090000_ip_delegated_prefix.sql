@@ -0,0 +1,8 @@
+-- Prefix length handed out per VM by ranges using the delegated prefix allocation mode
+alter table ip_range
+    add column delegated_prefix tinyint unsigned;
+-- Delegated prefixes store the network address in `ip` alongside its length,
+-- plus the id of the route which points the prefix at the VM
+alter table vm_ip_assignment
+    add column prefix_length tinyint unsigned,
+    add column route_ref     varchar(255);
@@ -0,0 +1,8 @@
+-- Last running state of each VM seen by the worker, so VMs can be filtered by state
+create table vm_state
+(
+    vm_id   integer unsigned not null primary key,
+    state   smallint unsigned not null,
+    updated timestamp        not null default current_timestamp on update current_timestamp,
+    constraint fk_vm_state_vm foreign key (vm_id) references vm (id) on delete cascade
+);
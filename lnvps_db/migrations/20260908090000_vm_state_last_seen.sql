@@ -0,0 +1,3 @@
+-- vm_state is written on every check, last_seen tells how fresh the stored state is
+alter table vm_state
+    change column updated last_seen timestamp not null default current_timestamp;
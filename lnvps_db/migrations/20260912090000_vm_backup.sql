@@ -0,0 +1,35 @@
+-- Number of backups kept per VM, 0 = scheduled backups not offered
+alter table vm_template
+    add column backup_retention smallint unsigned not null default 0;
+alter table vm_custom_template
+    add column backup_retention smallint unsigned not null default 0;
+
+-- User-configured automatic backup schedule, one per VM
+create table vm_backup_schedule
+(
+    id             integer unsigned  not null auto_increment primary key,
+    vm_id          integer unsigned  not null,
+    -- Hours between backups
+    interval_hours smallint unsigned not null,
+    enabled        bit(1)            not null default 1,
+    -- When the last backup was taken
+    last_run       timestamp         null     default null,
+    created        timestamp         not null default current_timestamp,
+    constraint fk_vm_backup_schedule_vm foreign key (vm_id) references vm (id)
+);
+
+create unique index ix_vm_backup_schedule_vm on vm_backup_schedule (vm_id);
+
+-- Backup archives stored on the VM host's backup storage
+create table vm_backup
+(
+    id        integer unsigned not null auto_increment primary key,
+    vm_id     integer unsigned not null,
+    -- Host volume id of the archive, e.g. backup:backup/vzdump-qemu-101-2026_01_01-00_00_00.vma.zst
+    volume_id varchar(255)     not null,
+    size      bigint unsigned  not null,
+    created   timestamp        not null default current_timestamp,
+    constraint fk_vm_backup_vm foreign key (vm_id) references vm (id)
+);
+
+create index ix_vm_backup_vm on vm_backup (vm_id);
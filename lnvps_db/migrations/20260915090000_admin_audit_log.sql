@@ -0,0 +1,14 @@
+-- Record of sensitive admin actions, e.g. bulk messages
+create table admin_audit_log
+(
+    id            integer unsigned not null auto_increment primary key,
+    admin_user_id integer unsigned not null,
+    -- Action name, e.g. bulk_message
+    action        varchar(64)      not null,
+    -- JSON details of the action
+    detail        text             null,
+    created       timestamp        not null default current_timestamp,
+    constraint fk_admin_audit_log_user foreign key (admin_user_id) references users (id)
+);
+
+create index ix_admin_audit_log_created on admin_audit_log (created);
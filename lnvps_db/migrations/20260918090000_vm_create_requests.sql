@@ -0,0 +1,15 @@
+-- Client supplied ids of VM create requests, a repeated id returns the VM
+-- created by the first request instead of creating another one
+create table vm_create_requests
+(
+    id         integer unsigned not null auto_increment primary key,
+    user_id    integer unsigned not null,
+    request_id varchar(64)      not null,
+    -- VM created by this request, null while it is being created
+    vm_id      integer unsigned null,
+    created    timestamp        not null default current_timestamp,
+    constraint fk_vm_create_requests_user foreign key (user_id) references users (id) on delete cascade,
+    constraint fk_vm_create_requests_vm foreign key (vm_id) references vm (id) on delete cascade
+);
+
+create unique index ix_vm_create_requests_user_request on vm_create_requests (user_id, request_id);
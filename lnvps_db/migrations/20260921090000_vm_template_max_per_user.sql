@@ -0,0 +1,3 @@
+-- Maximum number of active VMs of a template per user, null = unlimited
+alter table vm_template
+    add column max_per_user smallint unsigned null default null;
@@ -0,0 +1,3 @@
+-- Expired unpaid payments canceled by the worker (invoice canceled at the node)
+alter table subscription_payment
+    add column is_canceled bit(1) not null default 0;
@@ -0,0 +1,5 @@
+-- Whether prices are shown to customers including or excluding tax.
+-- 0 = exclusive (net price, tax added on top), 1 = inclusive (gross price).
+-- Only affects display: payments always store the net amount and tax separately.
+alter table company
+    add column tax_display smallint unsigned not null default 0;
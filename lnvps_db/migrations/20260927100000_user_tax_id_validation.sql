@@ -0,0 +1,5 @@
+-- Result of validating the user's billing tax id (EU VAT number) against VIES
+-- when it was saved, with the business name VIES has registered for it.
+alter table users
+    add column billing_tax_id_validated bit(1) not null default 0,
+    add column billing_tax_id_name varchar(255) null;
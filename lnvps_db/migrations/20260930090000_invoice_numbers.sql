@@ -0,0 +1,14 @@
+-- Sequential invoice numbers per company and year, assigned when a payment is paid
+create table company_invoice_counter
+(
+    company_id integer unsigned not null,
+    year       smallint unsigned not null,
+    seq        integer unsigned not null default 0,
+    primary key (company_id, year),
+    constraint fk_company_invoice_counter_company
+        foreign key (company_id) references company (id)
+);
+alter table subscription_payment
+    add column invoice_number varchar(64);
+create unique index ix_subscription_payment_invoice_number
+    on subscription_payment (invoice_number);
@@ -0,0 +1,13 @@
+-- Maintenance / incident banners shown by the frontend
+create table system_notice
+(
+    id       integer unsigned not null auto_increment primary key,
+    created  timestamp          not null default current_timestamp,
+    message  varchar(2000)      not null,
+    severity smallint unsigned  not null default 0,
+    starts   timestamp          not null default current_timestamp,
+    ends     timestamp          null,
+    -- comma separated region ids, empty for all regions
+    regions  varchar(255)       not null default ''
+);
+create index ix_system_notice_window on system_notice (starts, ends);
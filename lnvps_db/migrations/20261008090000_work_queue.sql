@@ -0,0 +1,22 @@
+-- MySQL backed work queue, for installs without Redis
+create table work_queue
+(
+    id         integer unsigned  not null auto_increment primary key,
+    created    timestamp         not null default current_timestamp,
+    -- serialized job
+    job        text              not null,
+    status     smallint unsigned not null default 0,
+    -- the job is not claimed before this time
+    due        timestamp         not null default current_timestamp,
+    claimed_by varchar(100),
+    claimed_at timestamp         null,
+    attempts   integer unsigned  not null default 0,
+    last_error text
+);
+create index ix_work_queue_due on work_queue (status, due);
+-- Last time each worker polled the queue
+create table work_queue_consumer
+(
+    name      varchar(100) not null primary key,
+    last_poll timestamp    not null
+);
@@ -0,0 +1,5 @@
+-- Place a VM on the host's isolated bridge instead of the shared one
+alter table vm add column private_network bit(1) not null default 0;
+
+-- Bridge with isolated ports used for private network VMs, unset = not supported
+alter table vm_host add column private_bridge varchar(50) null after mtu;
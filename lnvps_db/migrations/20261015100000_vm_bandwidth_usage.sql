@@ -0,0 +1,18 @@
+-- Network transfer per VM per calendar month (UTC), accumulated by the worker
+-- from the host time-series data
+create table vm_bandwidth_usage
+(
+    vm_id       integer unsigned not null,
+    -- Start of the month
+    month       timestamp        not null,
+    bytes_in    bigint unsigned  not null default 0,
+    bytes_out   bigint unsigned  not null default 0,
+    -- Timestamp of the newest sample counted
+    last_sample timestamp        not null,
+    primary key (vm_id, month),
+    constraint fk_vm_bandwidth_usage_vm foreign key (vm_id) references vm (id) on delete cascade
+);
+
+-- Bytes of transfer (in + out) included per month, null = unmetered
+alter table vm_template
+    add column bandwidth_quota bigint unsigned null default null;
@@ -0,0 +1,15 @@
+-- Outages affecting a region or IP range, the owners of affected VMs are notified
+create table incident
+(
+    id            integer unsigned not null auto_increment primary key,
+    created       timestamp        not null default current_timestamp,
+    region_id     integer unsigned null,
+    ip_range_id   integer unsigned null,
+    message       varchar(2000)    not null,
+    started       timestamp        not null default current_timestamp,
+    ended         timestamp        null,
+    admin_user_id integer unsigned not null,
+    -- number of customers notified when the incident was raised
+    notified      integer unsigned not null default 0
+);
+create index ix_incident_started on incident (started);
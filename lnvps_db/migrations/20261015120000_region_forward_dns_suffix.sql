@@ -0,0 +1,2 @@
+alter table region
+    add column forward_dns_suffix varchar(253);
@@ -0,0 +1,12 @@
+-- Switches gating behaviours (backups, migration, auto-renewal) without a redeploy
+create table feature_flag
+(
+    id        integer unsigned not null auto_increment primary key,
+    `key`     varchar(100)     not null,
+    -- region the flag applies to, null for every region
+    region_id integer unsigned null,
+    enabled   bit(1)           not null,
+    updated   timestamp        not null default current_timestamp on update current_timestamp,
+    constraint fk_feature_flag_region foreign key (region_id) references region (id)
+);
+create unique index ix_feature_flag_key_region on feature_flag (`key`, region_id);
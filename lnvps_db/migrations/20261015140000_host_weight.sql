@@ -0,0 +1,2 @@
+alter table vm_host
+    add column weight int unsigned not null default 1;
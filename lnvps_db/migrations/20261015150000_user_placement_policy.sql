@@ -0,0 +1,2 @@
+alter table users
+    add column placement_policy smallint unsigned not null default 0;
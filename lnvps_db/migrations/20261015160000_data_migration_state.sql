@@ -0,0 +1,7 @@
+-- Persist state between boots for startup data migrations
+create table data_migration_state
+(
+    name    varchar(100) not null primary key,
+    value   text         not null,
+    updated timestamp    not null default current_timestamp on update current_timestamp
+);
@@ -0,0 +1,2 @@
+-- When the VM was suspended by its owner, unset while it isn't suspended
+alter table vm add column suspended_at timestamp null;
@@ -0,0 +1,7 @@
+-- A unique index treats every null region_id as distinct, so several global
+-- flags with the same key could be inserted. Index a non-null scope instead,
+-- 0 standing in for "every region".
+alter table feature_flag
+    add column region_scope integer unsigned as (coalesce(region_id, 0)) stored;
+drop index ix_feature_flag_key_region on feature_flag;
+create unique index ix_feature_flag_key_region on feature_flag (`key`, region_scope);
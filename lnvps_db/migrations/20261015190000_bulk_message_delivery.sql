@@ -0,0 +1,18 @@
+-- Per-recipient outcome of a bulk message
+create table bulk_message_delivery
+(
+    id           integer unsigned not null auto_increment primary key,
+    -- The bulk_message audit log entry of the send
+    audit_log_id integer unsigned not null,
+    user_id      integer unsigned not null,
+    -- Channel the message was delivered on
+    channel      varchar(16)      null,
+    -- delivered, no_channel or failed
+    status       varchar(16)      not null,
+    error        varchar(1024)    null,
+    created      timestamp        not null default current_timestamp,
+    constraint fk_bulk_message_delivery_audit_log foreign key (audit_log_id) references admin_audit_log (id),
+    constraint fk_bulk_message_delivery_user foreign key (user_id) references users (id)
+);
+
+create index ix_bulk_message_delivery_audit_log on bulk_message_delivery (audit_log_id);
@@ -299,6 +299,13 @@ pub trait AdminDb: Send + Sync {
     /// Count IP assignments in an IP range
     async fn admin_count_ip_range_assignments(&self, ip_range_id: u64) -> DbResult<u64>;
 
+    /// List the non-deleted VMs with an active IP assignment in an IP range,
+    /// along with their owners
+    async fn list_vms_in_ip_range(
+        &self,
+        ip_range_id: u64,
+    ) -> DbResult<Vec<(crate::Vm, crate::User)>>;
+
     /// List access policies
     async fn admin_list_access_policies(&self) -> DbResult<Vec<crate::AccessPolicy>>;
 
@@ -358,8 +365,10 @@ pub trait AdminDb: Send + Sync {
 
     // VM management methods with advanced filtering
     /// List VMs with advanced filtering for admin interface
-    /// Supports filtering by user_id, host_id, pubkey (hex string), region_id, and deleted status
+    /// Supports filtering by user_id, host_id, pubkey (hex string), region_id, last known
+    /// running state and deleted status
     /// Returns (vms, total_count_before_pagination)
+    #[allow(clippy::too_many_arguments)]
     async fn admin_list_vms_filtered(
         &self,
         limit: u64,
@@ -368,6 +377,7 @@ pub trait AdminDb: Send + Sync {
         host_id: Option<u64>,
         pubkey: Option<&str>,
         region_id: Option<u64>,
+        state: Option<crate::VmStateKind>,
         include_deleted: Option<bool>,
     ) -> DbResult<(Vec<crate::Vm>, u64)>;
 
@@ -445,4 +455,58 @@ pub trait AdminDb: Send + Sync {
         start: chrono::DateTime<chrono::Utc>,
         end: chrono::DateTime<chrono::Utc>,
     ) -> DbResult<Vec<crate::ResourceCost>>;
+
+    /// List the admin audit log, newest first
+    async fn admin_list_audit_log(
+        &self,
+        limit: u64,
+        offset: u64,
+    ) -> DbResult<(Vec<crate::AdminAuditLog>, u64)>;
+
+    /// List all system notices, latest start first
+    async fn admin_list_system_notices(
+        &self,
+        limit: u64,
+        offset: u64,
+    ) -> DbResult<(Vec<crate::SystemNotice>, u64)>;
+
+    /// Get a system notice
+    async fn admin_get_system_notice(&self, id: u64) -> DbResult<crate::SystemNotice>;
+
+    /// Insert a system notice, returning its id
+    async fn admin_insert_system_notice(&self, notice: &crate::SystemNotice) -> DbResult<u64>;
+
+    /// Update a system notice
+    async fn admin_update_system_notice(&self, notice: &crate::SystemNotice) -> DbResult<()>;
+
+    /// Delete a system notice
+    async fn admin_delete_system_notice(&self, id: u64) -> DbResult<()>;
+
+    /// Get a feature flag
+    async fn admin_get_feature_flag(&self, id: u64) -> DbResult<crate::FeatureFlag>;
+
+    /// Insert a feature flag, returning its id
+    async fn admin_insert_feature_flag(&self, flag: &crate::FeatureFlag) -> DbResult<u64>;
+
+    /// Update a feature flag
+    async fn admin_update_feature_flag(&self, flag: &crate::FeatureFlag) -> DbResult<()>;
+
+    /// Delete a feature flag
+    async fn admin_delete_feature_flag(&self, id: u64) -> DbResult<()>;
+
+    /// List incidents, latest first
+    async fn admin_list_incidents(
+        &self,
+        limit: u64,
+        offset: u64,
+    ) -> DbResult<(Vec<crate::Incident>, u64)>;
+
+    /// Get an incident
+    async fn admin_get_incident(&self, id: u64) -> DbResult<crate::Incident>;
+
+    /// Insert an incident, returning its id
+    async fn admin_insert_incident(&self, incident: &crate::Incident) -> DbResult<u64>;
+
+    /// Update an incident
+    async fn admin_update_incident(&self, incident: &crate::Incident) -> DbResult<()>;
 }
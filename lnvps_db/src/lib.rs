@@ -1,6 +1,6 @@
 use anyhow::{Error, Result, anyhow};
 use async_trait::async_trait;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, TimeDelta, Utc};
 use sqlx::migrate::MigrateError;
 use thiserror::Error;
 
@@ -99,6 +99,21 @@ impl DbError {
     pub fn is_row_not_found(&self) -> bool {
         matches!(self, DbError::SqlxError(sqlx::Error::RowNotFound))
     }
+
+    /// The "row not found" error, for wrappers answering from a cache
+    pub fn row_not_found() -> Self {
+        DbError::SqlxError(sqlx::Error::RowNotFound)
+    }
+}
+
+/// A database-wide lock, held until this guard is dropped
+pub struct DbLock(#[allow(dead_code)] Box<dyn Send>);
+
+impl DbLock {
+    /// Wrap a value which releases the lock when dropped
+    pub fn new(inner: impl Send + 'static) -> Self {
+        Self(Box::new(inner))
+    }
 }
 
 impl From<DbError> for OpError<anyhow::Error> {
@@ -139,6 +154,9 @@ pub trait LNVpsDbBase: Send + Sync {
     /// Migrate database
     async fn migrate(&self) -> DbResult<()>;
 
+    /// Check the database is reachable
+    async fn ping(&self) -> DbResult<()>;
+
     /// Insert/Fetch user by pubkey
     async fn upsert_user(&self, pubkey: &[u8; 32]) -> DbResult<u64>;
 
@@ -285,6 +303,13 @@ pub trait LNVpsDbBase: Send + Sync {
     /// Create a new host
     async fn create_host(&self, host: &VmHost) -> DbResult<u64>;
 
+    /// Replace the API token used to control a host, stored encrypted
+    async fn update_host_api_token(
+        &self,
+        host_id: u64,
+        api_token: &EncryptedString,
+    ) -> DbResult<()>;
+
     /// List enabled storage disks on the host
     async fn list_host_disks(&self, host_id: u64) -> DbResult<Vec<VmHostDisk>>;
 
@@ -303,9 +328,19 @@ pub trait LNVpsDbBase: Send + Sync {
     /// List available OS images
     async fn list_os_image(&self) -> DbResult<Vec<VmOsImage>>;
 
+    /// List enabled OS images of a single [OsImageKind]
+    async fn list_os_image_by_kind(&self, kind: OsImageKind) -> DbResult<Vec<VmOsImage>>;
+
     /// Count active (non-deleted) VMs grouped by OS image id
     async fn count_vms_by_os_image(&self) -> DbResult<Vec<(u64, u64)>>;
 
+    /// List the OS image ids allowed in a region, empty when the region has no
+    /// allowlist and offers every image
+    async fn list_region_os_images(&self, region_id: u64) -> DbResult<Vec<u64>>;
+
+    /// Replace the OS image allowlist of a region, an empty list removes the restriction
+    async fn set_region_os_images(&self, region_id: u64, image_ids: &[u64]) -> DbResult<()>;
+
     /// Update an OS image (sha2, sha2_url, and other fields)
     async fn update_os_image(&self, image: &VmOsImage) -> DbResult<()>;
 
@@ -361,12 +396,24 @@ pub trait LNVpsDbBase: Send + Sync {
     /// List expired VM's
     async fn list_expired_vms(&self) -> DbResult<Vec<Vm>>;
 
+    /// List non-deleted VM's whose subscription expired before `before`.
+    ///
+    /// Passing `now - grace` selects VM's past their grace period (pending
+    /// deletion); the difference with [`Self::list_expired_vms`] is the set of
+    /// VM's currently stopped within their grace window.
+    async fn list_vms_expired_before(&self, before: DateTime<Utc>) -> DbResult<Vec<Vm>>;
+
     /// List active VM's — non-deleted VM's whose subscription has been set up
     /// (paid at least once). Includes VM's that are currently **expired** (a
     /// real customer VM that has lapsed but not yet been purged); excludes
     /// never-paid pending orders (`is_setup = 0`) and deleted VM's.
     async fn list_active_vms(&self) -> DbResult<Vec<Vm>>;
 
+    /// List unpaid VM's — non-deleted VM's whose subscription was created more
+    /// than `grace` ago, was never set up and has no paid payment. Admin
+    /// extended VM's are set up without a payment and are not included.
+    async fn list_unpaid_vms(&self, grace: TimeDelta) -> DbResult<Vec<Vm>>;
+
     /// List VM's owned by a specific user
     async fn list_user_vms(&self, id: u64) -> DbResult<Vec<Vm>>;
 
@@ -376,6 +423,11 @@ pub trait LNVpsDbBase: Send + Sync {
     /// Insert a new VM record
     async fn insert_vm(&self, vm: &Vm) -> DbResult<u64>;
 
+    /// Insert a new VM record unless its user already has `limit` active VMs
+    /// of the VM's template, checked in the same statement as the insert.
+    /// Returns `None` when the limit is reached.
+    async fn insert_vm_within_template_limit(&self, vm: &Vm, limit: u16) -> DbResult<Option<u64>>;
+
     /// Insert a VM record with an explicit id.
     ///
     /// Used when importing a pre-existing host VM whose id is fixed by the
@@ -383,6 +435,21 @@ pub trait LNVpsDbBase: Send + Sync {
     /// id is already taken.
     async fn insert_vm_with_id(&self, vm: &Vm) -> DbResult<u64>;
 
+    /// Insert an imported host VM (with its explicit id) together with its
+    /// subscription, line item, custom template and IP assignments in one
+    /// transaction, so a failed import leaves nothing behind.
+    ///
+    /// The VM's `subscription_line_item_id` and `custom_template_id` are taken
+    /// from the inserted rows. Returns the inserted VM.
+    async fn import_vm(
+        &self,
+        vm: &Vm,
+        subscription: &Subscription,
+        line_item: &SubscriptionLineItem,
+        custom_template: Option<&VmCustomTemplate>,
+        ip_assignments: &[VmIpAssignment],
+    ) -> DbResult<Vm>;
+
     /// Delete a VM by id
     async fn delete_vm(&self, vm_id: u64) -> DbResult<()>;
 
@@ -390,7 +457,8 @@ pub trait LNVpsDbBase: Send + Sync {
     ///
     /// Unlike [`delete_vm`](Self::delete_vm) (which soft-deletes by setting
     /// `deleted = 1`), this removes the VM row entirely along with every entity
-    /// that references it: `vm_history`, `vm_firewall_rule`, `vm_ip_assignment`,
+    /// that references it: `vm_history`, `vm_firewall_rule`, `vm_usage_alert`, `vm_backup`,
+    /// `vm_backup_schedule`, `vm_ip_assignment`,
     /// and the VM's own `subscription` (its `subscription_line_item` rows and
     /// `subscription_payment` history). Intended for purging never-paid (new)
     /// VMs and for super-admin forced deletions of test VMs. This is
@@ -409,6 +477,32 @@ pub trait LNVpsDbBase: Send + Sync {
     /// Update a VM
     async fn update_vm(&self, vm: &Vm) -> DbResult<()>;
 
+    /// Move a VM to another host and its primary disk to `disk_id` on it
+    async fn update_vm_host(&self, vm_id: u64, host_id: u64, disk_id: u64) -> DbResult<()>;
+
+    /// Record that a VM was suspended by its owner at `suspended_at`
+    async fn set_vm_suspended_at(&self, vm_id: u64, suspended_at: DateTime<Utc>) -> DbResult<()>;
+
+    /// Clear the suspend time of a VM, returning it when the VM was suspended.
+    /// Only one caller gets the time back, so a suspension is credited once.
+    async fn take_vm_suspended_at(&self, vm_id: u64) -> DbResult<Option<DateTime<Utc>>>;
+
+    /// Move a VM and its subscription to another user.
+    ///
+    /// Clears the VM's `ssh_key_id` because the old owner's key does not
+    /// belong to the new account.
+    async fn transfer_vm(&self, vm_id: u64, new_user_id: u64) -> DbResult<()>;
+
+    /// Record the running state of a VM just read from its host
+    async fn upsert_vm_state(&self, vm_id: u64, state: VmStateKind) -> DbResult<()>;
+
+    /// Mark the stored states of VMs as just read from their hosts, without
+    /// changing them
+    async fn touch_vm_states(&self, vm_ids: &[u64]) -> DbResult<()>;
+
+    /// Get the stored running states of a list of VMs, VMs never seen are omitted
+    async fn list_vm_states(&self, vm_ids: &[u64]) -> DbResult<Vec<VmState>>;
+
     /// Get a VM by its subscription line item ID
     async fn get_vm_by_line_item(&self, line_item_id: u64) -> DbResult<Vm>;
 
@@ -425,6 +519,13 @@ pub trait LNVpsDbBase: Send + Sync {
         vm_id: u64,
     ) -> DbResult<Vec<SubscriptionPayment>>;
 
+    /// List paid subscription payments for a VM made since the VM was created,
+    /// in the order they were paid
+    async fn list_paid_vm_subscription_payments(
+        &self,
+        vm_id: u64,
+    ) -> DbResult<Vec<SubscriptionPayment>>;
+
     /// List subscription payments for a VM with pagination
     async fn list_vm_subscription_payments_paginated(
         &self,
@@ -433,6 +534,15 @@ pub trait LNVpsDbBase: Send + Sync {
         offset: u64,
     ) -> DbResult<Vec<SubscriptionPayment>>;
 
+    /// List subscription payments for a VM older than `after` (`created`, `id`),
+    /// newest first. Keyset pagination, rows inserted between pages don't shift results.
+    async fn list_vm_subscription_payments_after(
+        &self,
+        vm_id: u64,
+        after: Option<(DateTime<Utc>, Vec<u8>)>,
+        limit: u64,
+    ) -> DbResult<Vec<SubscriptionPayment>>;
+
     /// Count total subscription payments for a VM (for pagination metadata)
     async fn count_vm_subscription_payments(&self, vm_id: u64) -> DbResult<u64>;
 
@@ -445,6 +555,13 @@ pub trait LNVpsDbBase: Send + Sync {
     /// List VM ip assignments
     async fn list_vm_ip_assignments(&self, vm_id: u64) -> DbResult<Vec<VmIpAssignment>>;
 
+    /// List the ip assignments of all (not deleted) VMs owned by a user, with
+    /// the VM and the range of each assignment, ordered by VM
+    async fn list_user_ip_assignments(
+        &self,
+        user_id: u64,
+    ) -> DbResult<Vec<(Vm, VmIpAssignment, IpRange)>>;
+
     /// List VM ip assignments by IP range
     async fn list_vm_ip_assignments_in_range(&self, range_id: u64)
     -> DbResult<Vec<VmIpAssignment>>;
@@ -458,6 +575,18 @@ pub trait LNVpsDbBase: Send + Sync {
     /// Delete assigned VM ip
     async fn delete_vm_ip_assignment(&self, assignment_id: u64) -> DbResult<()>;
 
+    /// Find IPs assigned to more than one live (non-deleted) VM.
+    ///
+    /// Returns one entry per conflicting IP with the ids of every live VM it is
+    /// assigned to (ascending), ordered by IP.
+    async fn find_duplicate_live_ips(&self) -> DbResult<Vec<(String, Vec<u64>)>>;
+
+    /// Get the stored state of a startup data migration, if it has recorded one
+    async fn get_data_migration_state(&self, name: &str) -> DbResult<Option<String>>;
+
+    /// Record the state of a startup data migration, replacing any previous value
+    async fn set_data_migration_state(&self, name: &str, value: &str) -> DbResult<()>;
+
     /// Create a firewall rule, returns the new rule id
     async fn insert_vm_firewall_rule(&self, rule: &VmFirewallRule) -> DbResult<u64>;
 
@@ -473,6 +602,139 @@ pub trait LNVpsDbBase: Send + Sync {
     /// Delete a firewall rule by id
     async fn delete_vm_firewall_rule(&self, rule_id: u64) -> DbResult<()>;
 
+    /// Create a usage alert, returns the new alert id
+    async fn insert_vm_usage_alert(&self, alert: &VmUsageAlert) -> DbResult<u64>;
+
+    /// Get a single usage alert by id
+    async fn get_vm_usage_alert(&self, alert_id: u64) -> DbResult<VmUsageAlert>;
+
+    /// List usage alerts for a VM
+    async fn list_vm_usage_alerts(&self, vm_id: u64) -> DbResult<Vec<VmUsageAlert>>;
+
+    /// List usage alerts on all non-deleted VMs
+    async fn list_active_vm_usage_alerts(&self) -> DbResult<Vec<VmUsageAlert>>;
+
+    /// Update the metric, threshold and window of a usage alert
+    async fn update_vm_usage_alert(&self, alert: &VmUsageAlert) -> DbResult<()>;
+
+    /// Record when a usage alert last sent a notification
+    async fn set_vm_usage_alert_triggered(
+        &self,
+        alert_id: u64,
+        triggered: DateTime<Utc>,
+    ) -> DbResult<()>;
+
+    /// Delete a usage alert by id
+    async fn delete_vm_usage_alert(&self, alert_id: u64) -> DbResult<()>;
+
+    /// Get the backup schedule of a VM
+    async fn get_vm_backup_schedule(&self, vm_id: u64) -> DbResult<Option<VmBackupSchedule>>;
+
+    /// Create or replace the backup schedule of a VM
+    async fn upsert_vm_backup_schedule(&self, schedule: &VmBackupSchedule) -> DbResult<()>;
+
+    /// Delete the backup schedule of a VM
+    async fn delete_vm_backup_schedule(&self, vm_id: u64) -> DbResult<()>;
+
+    /// List enabled backup schedules on all non-deleted VMs
+    async fn list_active_vm_backup_schedules(&self) -> DbResult<Vec<VmBackupSchedule>>;
+
+    /// Record when the scheduled backup of a VM last ran
+    async fn set_vm_backup_schedule_last_run(
+        &self,
+        vm_id: u64,
+        last_run: DateTime<Utc>,
+    ) -> DbResult<()>;
+
+    /// Record a new backup, returns the new backup id
+    async fn insert_vm_backup(&self, backup: &VmBackup) -> DbResult<u64>;
+
+    /// Get a single backup by id
+    async fn get_vm_backup(&self, backup_id: u64) -> DbResult<VmBackup>;
+
+    /// List backups of a VM, newest first
+    async fn list_vm_backups(&self, vm_id: u64) -> DbResult<Vec<VmBackup>>;
+
+    /// Delete a backup record by id
+    async fn delete_vm_backup(&self, backup_id: u64) -> DbResult<()>;
+
+    /// Get the network transfer of a VM in the month starting at `month`
+    async fn get_vm_bandwidth_usage(
+        &self,
+        vm_id: u64,
+        month: DateTime<Utc>,
+    ) -> DbResult<Option<VmBandwidthUsage>>;
+
+    /// Timestamp of the newest time-series sample counted for a VM
+    async fn get_vm_bandwidth_last_sample(&self, vm_id: u64) -> DbResult<Option<DateTime<Utc>>>;
+
+    /// Add transfer to a VM's monthly totals, creating the month when missing,
+    /// and move its `last_sample` forward.
+    ///
+    /// The transfer is only added when the month has not counted samples
+    /// after `counted_after`, so two workers counting the same samples add
+    /// them once. Returns whether the transfer was added.
+    async fn add_vm_bandwidth_usage(
+        &self,
+        usage: &VmBandwidthUsage,
+        counted_after: DateTime<Utc>,
+    ) -> DbResult<bool>;
+
+    /// Claim a client request id for a VM create, returns false when the user
+    /// already used this id
+    async fn insert_vm_create_request(&self, user_id: u64, request_id: &str) -> DbResult<bool>;
+
+    /// Get a VM create request by user and client request id
+    async fn get_vm_create_request(
+        &self,
+        user_id: u64,
+        request_id: &str,
+    ) -> DbResult<VmCreateRequest>;
+
+    /// Record the VM created by a VM create request
+    async fn set_vm_create_request_vm(
+        &self,
+        user_id: u64,
+        request_id: &str,
+        vm_id: u64,
+    ) -> DbResult<()>;
+
+    /// Release a client request id, e.g. after the create failed
+    async fn delete_vm_create_request(&self, user_id: u64, request_id: &str) -> DbResult<()>;
+
+    /// Delete VM create requests claimed before `before` which never got a VM,
+    /// e.g. when the API stopped mid-create. Returns the number deleted
+    async fn delete_stale_vm_create_requests(&self, before: DateTime<Utc>) -> DbResult<u64>;
+
+    /// Insert a new extra data disk for a VM
+    async fn insert_vm_extra_disk(&self, disk: &VmExtraDisk) -> DbResult<u64>;
+
+    /// List the extra data disks attached to a VM, ordered by slot
+    async fn list_vm_extra_disks(&self, vm_id: u64) -> DbResult<Vec<VmExtraDisk>>;
+
+    /// Delete an extra data disk by id
+    async fn delete_vm_extra_disk(&self, disk_id: u64) -> DbResult<()>;
+
+    /// Take the lock serialising host selection and capacity reservation across
+    /// every API instance sharing this database, waiting up to `timeout`
+    async fn lock_placement(&self, timeout: std::time::Duration) -> DbResult<DbLock>;
+
+    /// Insert a host capacity reservation, purging any that have expired
+    async fn insert_capacity_reservation(&self, reservation: &CapacityReservation)
+    -> DbResult<u64>;
+
+    /// List the unexpired capacity reservations on a host
+    async fn list_capacity_reservations(&self, host_id: u64) -> DbResult<Vec<CapacityReservation>>;
+
+    /// Bind a capacity reservation to the VM created against it
+    async fn set_capacity_reservation_vm(&self, id: u64, vm_id: u64) -> DbResult<()>;
+
+    /// Release a capacity reservation
+    async fn delete_capacity_reservation(&self, id: u64) -> DbResult<()>;
+
+    /// Release all capacity reservations bound to a VM
+    async fn delete_vm_capacity_reservations(&self, vm_id: u64) -> DbResult<()>;
+
     /// Update the per-VM default firewall policy (None = inherit host default)
     async fn update_vm_firewall_policy(
         &self,
@@ -639,6 +901,15 @@ pub trait LNVpsDbBase: Send + Sync {
         offset: u64,
     ) -> DbResult<Vec<VmHistory>>;
 
+    /// List VM history for a given VM older than `after` (`timestamp`, `id`),
+    /// newest first. Keyset pagination, rows inserted between pages don't shift results.
+    async fn list_vm_history_after(
+        &self,
+        vm_id: u64,
+        after: Option<(DateTime<Utc>, u64)>,
+        limit: u64,
+    ) -> DbResult<Vec<VmHistory>>;
+
     /// Get VM history entry by id
     async fn get_vm_history(&self, id: u64) -> DbResult<VmHistory>;
 
@@ -662,6 +933,63 @@ pub trait LNVpsDbBase: Send + Sync {
     /// Get all user IDs that have admin privileges (active role assignments)
     async fn list_admin_user_ids(&self) -> DbResult<Vec<u64>>;
 
+    /// Record an admin action in the audit log
+    async fn insert_admin_audit_log(&self, entry: &crate::AdminAuditLog) -> DbResult<u64>;
+
+    /// Record the per-recipient outcome of a bulk message
+    async fn insert_bulk_message_deliveries(
+        &self,
+        deliveries: &[crate::BulkMessageDelivery],
+    ) -> DbResult<()>;
+
+    /// List system notices shown right now, for all regions
+    async fn list_active_system_notices(&self) -> DbResult<Vec<crate::SystemNotice>>;
+
+    /// List all feature flags, global and region scoped
+    async fn list_feature_flags(&self) -> DbResult<Vec<crate::FeatureFlag>>;
+
+    /// Add a job to the work queue, due now
+    async fn enqueue_job(&self, job: &str) -> DbResult<u64>;
+
+    /// Atomically claim the oldest due job for `consumer`, so no two workers
+    /// claim the same job. Jobs whose claim wasn't renewed for `reclaim_after`
+    /// are assumed abandoned (e.g. the worker died) and can be claimed again,
+    /// which counts as a failed attempt; abandoned jobs out of attempts are
+    /// moved to the dead letter state instead. Also records that `consumer`
+    /// polled the queue.
+    async fn claim_next_job(
+        &self,
+        consumer: &str,
+        reclaim_after: TimeDelta,
+        max_attempts: u32,
+    ) -> DbResult<Option<crate::WorkQueueItem>>;
+
+    /// Renew the claim on a running job so it isn't reclaimed
+    async fn renew_job_claim(&self, id: u64) -> DbResult<()>;
+
+    /// Remove a finished job from the work queue
+    async fn complete_job(&self, id: u64) -> DbResult<()>;
+
+    /// Release a failed job back to the queue, to be claimed again at
+    /// `retry_at`. A job which has failed `max_attempts` times is moved to the
+    /// dead letter state and kept for inspection instead.
+    async fn fail_job(
+        &self,
+        id: u64,
+        error: &str,
+        retry_at: DateTime<Utc>,
+        max_attempts: u32,
+    ) -> DbResult<()>;
+
+    /// List live jobs in the work queue (queued and claimed), oldest first
+    async fn list_work_queue(&self, limit: u64) -> DbResult<Vec<crate::WorkQueueItem>>;
+
+    /// Remove a job from the work queue, returns false when there was no such job
+    async fn delete_job(&self, id: u64) -> DbResult<bool>;
+
+    /// Last time any worker polled the work queue
+    async fn work_queue_last_poll(&self) -> DbResult<Option<DateTime<Utc>>>;
+
     // ========================================================================
     // Subscription Billing System Methods
     // ========================================================================
@@ -781,6 +1109,44 @@ pub trait LNVpsDbBase: Send + Sync {
     async fn subscription_payment_paid(&self, payment: &SubscriptionPayment) -> DbResult<()>;
     async fn last_paid_subscription_invoice(&self) -> DbResult<Option<SubscriptionPayment>>;
 
+    /// List up to `limit` unpaid payments past their expiry which haven't been
+    /// canceled yet, oldest first
+    async fn list_expired_subscription_payments(
+        &self,
+        limit: u64,
+    ) -> DbResult<Vec<SubscriptionPayment>>;
+
+    /// Mark an unpaid payment as canceled, so it is no longer picked up by
+    /// [`Self::list_expired_subscription_payments`]
+    async fn cancel_subscription_payment(&self, id: &Vec<u8>) -> DbResult<()>;
+
+    /// Settle an unpaid payment from the user's account credit.
+    ///
+    /// Atomically debits `amount + tax + processing_fee` (in the payment's
+    /// currency) and marks the payment paid, extending the subscription exactly
+    /// like [`Self::subscription_payment_paid`]. Returns `false` without changing
+    /// anything if the credit balance is insufficient or the payment is already paid.
+    async fn pay_subscription_payment_with_credit(
+        &self,
+        payment: &SubscriptionPayment,
+    ) -> DbResult<bool>;
+
+    /// Claim the automatic renewal of `subscription_id` for the billing period
+    /// ending at `period_expires`. Returns `false` if that period was already
+    /// claimed, so each period is charged at most once.
+    async fn claim_subscription_auto_renewal(
+        &self,
+        subscription_id: u64,
+        period_expires: DateTime<Utc>,
+    ) -> DbResult<bool>;
+
+    // User Credit
+    /// Get a user's account credit balance in `currency` (0 if none)
+    async fn get_user_credit(&self, user_id: u64, currency: &str) -> DbResult<u64>;
+
+    /// Add `amount` to a user's account credit balance in `currency`
+    async fn add_user_credit(&self, user_id: u64, currency: &str, amount: u64) -> DbResult<()>;
+
     // Available IP Space
     async fn list_available_ip_space(&self) -> DbResult<Vec<AvailableIpSpace>>;
 
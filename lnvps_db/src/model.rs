@@ -1,7 +1,7 @@
 use crate::comma_separated::CommaSeparated;
 use crate::encrypted_string::EncryptedString;
 use anyhow::{Result, anyhow, bail};
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Datelike, NaiveTime, TimeDelta, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::{FromRow, Type};
 use std::fmt::{Display, Formatter};
@@ -97,6 +97,12 @@ pub struct User {
     pub billing_postcode: Option<String>,
     /// Billing tax id
     pub billing_tax_id: Option<String>,
+    /// Whether `billing_tax_id` was confirmed valid by VIES when it was saved
+    #[sqlx(default)]
+    pub billing_tax_id_validated: bool,
+    /// Business name VIES has registered for `billing_tax_id`, if validated
+    #[sqlx(default)]
+    pub billing_tax_id_name: Option<String>,
     /// Country (ISO 3166-1 alpha-3) resolved from the client's IP address.
     ///
     /// This is an *independent* place-of-supply evidence signal for EU VAT,
@@ -110,6 +116,46 @@ pub struct User {
     /// When the geolocation was last resolved.
     #[sqlx(default)]
     pub geo_updated: Option<DateTime<Utc>>,
+    /// Where new VMs are placed relative to the user's other VMs
+    #[sqlx(default)]
+    pub placement_policy: PlacementPolicy,
+}
+
+/// Host choice for a new VM relative to the hosts already running the
+/// owner's VMs
+#[derive(Clone, Copy, Debug, sqlx::Type, Default, PartialEq, Eq)]
+#[repr(u16)]
+pub enum PlacementPolicy {
+    /// Hosts are picked without regard to the owner's other VMs
+    #[default]
+    None = 0,
+    /// Prefer the hosts running the fewest of the owner's VMs
+    Spread = 1,
+    /// Prefer hosts already running the owner's VMs
+    Pack = 2,
+}
+
+impl FromStr for PlacementPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "none" => Ok(PlacementPolicy::None),
+            "spread" => Ok(PlacementPolicy::Spread),
+            "pack" => Ok(PlacementPolicy::Pack),
+            _ => Err(anyhow!("unknown placement policy {}", s)),
+        }
+    }
+}
+
+impl Display for PlacementPolicy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PlacementPolicy::None => write!(f, "none"),
+            PlacementPolicy::Spread => write!(f, "spread"),
+            PlacementPolicy::Pack => write!(f, "pack"),
+        }
+    }
 }
 
 /// A saved payment method for off-session (merchant-initiated) automatic
@@ -556,6 +602,13 @@ pub struct Region {
     pub name: String,
     pub enabled: bool,
     pub company_id: u64,
+    /// Region is under maintenance, no new VMs are placed on any of its hosts
+    pub maintenance: bool,
+    /// Probe running VMs in this region for network reachability during CheckVm
+    pub reachability_check: bool,
+    /// Suffix for forward DNS records of VMs in this region (e.g. `dub` names
+    /// them `vm-{id}.dub` in the forward zone), `None` uses the zone directly
+    pub forward_dns_suffix: Option<String>,
 }
 
 #[derive(FromRow, Clone, Debug, Default)]
@@ -592,6 +645,9 @@ pub struct VmHost {
     pub vlan_id: Option<u64>,
     /// MTU setting for network configuration
     pub mtu: Option<u16>,
+    /// Bridge with isolated ports for VMs on a private network, the host
+    /// can't run private network VMs when unset
+    pub private_bridge: Option<String>,
     /// SSH username for running host utilities (default: root)
     pub ssh_user: Option<String>,
     /// SSH private key for running host utilities (encrypted, PEM format)
@@ -600,6 +656,20 @@ pub struct VmHost {
     /// host `enabled = false` (so it takes no new VMs), and renewals are blocked
     /// once a VM's expiry reaches this date.
     pub sunset_date: Option<DateTime<Utc>>,
+    /// Host is under maintenance, existing VMs remain manageable but no new
+    /// VMs are placed on it
+    pub maintenance: bool,
+    /// Share of new VMs placed on this host relative to the other hosts in
+    /// its region which can fit them
+    pub weight: u32,
+}
+
+impl VmHost {
+    /// Can VMs on this host be placed on a private network, only Proxmox
+    /// hosts with a [VmHost::private_bridge] support it
+    pub fn supports_private_network(&self) -> bool {
+        self.kind == VmHostKind::Proxmox && self.private_bridge.is_some()
+    }
 }
 
 #[derive(FromRow, Clone, Debug, Default)]
@@ -768,6 +838,42 @@ impl Display for OsDistribution {
     }
 }
 
+/// What kind of artifact an OS image URL points to
+#[derive(Clone, Copy, Debug, sqlx::Type, Default, PartialEq, Eq)]
+#[repr(u16)]
+pub enum OsImageKind {
+    /// Cloud-init ready disk image, usable for automated provisioning
+    #[default]
+    CloudInit = 0,
+    /// Installer ISO, requires a manual install (admin/manual flows only)
+    Iso = 1,
+    /// Container (LXC) template
+    ContainerTemplate = 2,
+}
+
+impl FromStr for OsImageKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "cloud_init" => Ok(OsImageKind::CloudInit),
+            "iso" => Ok(OsImageKind::Iso),
+            "container_template" => Ok(OsImageKind::ContainerTemplate),
+            _ => Err(anyhow!("unknown image kind {}", s)),
+        }
+    }
+}
+
+impl Display for OsImageKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OsImageKind::CloudInit => write!(f, "cloud_init"),
+            OsImageKind::Iso => write!(f, "iso"),
+            OsImageKind::ContainerTemplate => write!(f, "container_template"),
+        }
+    }
+}
+
 /// OS Images are templates which are used as a basis for
 /// provisioning new vms
 #[derive(FromRow, Clone, Debug)]
@@ -787,6 +893,9 @@ pub struct VmOsImage {
     pub sha2: Option<String>,
     /// URL to the SHA-2 checksums file (e.g., SHA512SUMS)
     pub sha2_url: Option<String>,
+    /// What kind of image this is; only [OsImageKind::CloudInit] images are
+    /// used for automated VM creation
+    pub image_kind: OsImageKind,
 }
 
 /// Compression extensions recognised on OS image URLs. Files ending with one
@@ -990,6 +1099,19 @@ pub struct IpRange {
     pub reverse_dns_server_id: Option<u64>,
     /// Forward zone id (provider specific, e.g. Cloudflare zone id) for forward records
     pub forward_zone_id: Option<String>,
+    /// Prefix length delegated to each VM when using [IpRangeAllocationMode::DelegatedPrefix]
+    /// (defaults to [DEFAULT_DELEGATED_PREFIX])
+    pub delegated_prefix: Option<u8>,
+}
+
+/// Default prefix length delegated to a VM from a [IpRangeAllocationMode::DelegatedPrefix] range
+pub const DEFAULT_DELEGATED_PREFIX: u8 = 64;
+
+impl IpRange {
+    /// Prefix length delegated to each VM from this range
+    pub fn delegated_prefix_length(&self) -> u8 {
+        self.delegated_prefix.unwrap_or(DEFAULT_DELEGATED_PREFIX)
+    }
 }
 
 #[derive(Debug, Clone, Copy, sqlx::Type, Default)]
@@ -1003,6 +1125,34 @@ pub enum IpRangeAllocationMode {
     Sequential = 1,
     /// IP(v6) assignment uses SLAAC EUI-64
     SlaacEui64 = 2,
+    /// Each VM is delegated a routed IPv6 prefix carved out of the range
+    DelegatedPrefix = 3,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type, Default)]
+#[repr(u16)]
+/// Last running state of a VM seen by the worker
+pub enum VmStateKind {
+    /// Never checked (or the host didn't report the VM)
+    #[default]
+    Unknown = 0,
+    Running = 1,
+    Stopped = 2,
+    /// Being provisioned on the host for the first time
+    Creating = 3,
+    /// Paused with its memory kept on the host
+    Suspended = 4,
+    /// Being live migrated between hosts
+    Migrating = 5,
+}
+
+#[derive(FromRow, Clone, Debug)]
+/// Last running state of a VM as stored by the worker
+pub struct VmState {
+    pub vm_id: u64,
+    pub state: VmStateKind,
+    /// When the state was last read from the host
+    pub last_seen: DateTime<Utc>,
 }
 
 #[derive(FromRow, Clone, Debug)]
@@ -1187,6 +1337,12 @@ pub struct VmTemplate {
     pub cpu_limit: Option<f32>,
     /// Maximum number of user firewall rules per VM (None = use global default)
     pub firewall_rule_limit: Option<u16>,
+    /// Number of scheduled backups kept per VM (0 = backups not offered)
+    pub backup_retention: u16,
+    /// Maximum number of active VMs of this template per user (None = unlimited)
+    pub max_per_user: Option<u16>,
+    /// Bytes of transfer (in + out) included per month (None = unmetered)
+    pub bandwidth_quota: Option<u64>,
 }
 
 /// A custom pricing template, used for billing calculation of a specific VM
@@ -1217,6 +1373,8 @@ pub struct VmCustomTemplate {
     pub cpu_limit: Option<f32>,
     /// Maximum number of user firewall rules per VM (None = use global default)
     pub firewall_rule_limit: Option<u16>,
+    /// Number of scheduled backups kept per VM (0 = backups not offered)
+    pub backup_retention: u16,
 }
 
 /// Custom pricing template, usually 1 per region
@@ -1311,6 +1469,10 @@ pub struct Vm {
     pub fw_policy_out: Option<VmFirewallPolicy>,
     /// Free-form admin-only notes about this VM (not exposed to the customer)
     pub admin_notes: Option<String>,
+    /// Isolate the VM at L2 from other VMs on the host
+    pub private_network: bool,
+    /// When the owner suspended the VM, `None` unless it is suspended
+    pub suspended_at: Option<DateTime<Utc>>,
 }
 
 #[derive(FromRow, Clone, Debug, Default)]
@@ -1335,11 +1497,25 @@ pub struct VmIpAssignment {
     pub dns_reverse: Option<String>,
     /// External ID pointing to the reverse DNS entry for this IP
     pub dns_reverse_ref: Option<String>,
+    /// Prefix length when this assignment is a delegated prefix, `ip` is then the network address
+    pub prefix_length: Option<u8>,
+    /// External ID pointing to the route for a delegated prefix on the router
+    pub route_ref: Option<String>,
+}
+
+impl VmIpAssignment {
+    /// Is this assignment a delegated prefix rather than a single address
+    pub fn is_prefix(&self) -> bool {
+        self.prefix_length.is_some()
+    }
 }
 
 impl Display for VmIpAssignment {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.ip)
+        match self.prefix_length {
+            Some(p) => write!(f, "{}/{}", self.ip, p),
+            None => write!(f, "{}", self.ip),
+        }
     }
 }
 
@@ -1392,6 +1568,137 @@ pub enum VmFirewallPolicy {
     Reject = 2,
 }
 
+/// VM time-series metric a usage alert watches
+#[derive(Debug, Clone, Copy, sqlx::Type, PartialEq, Eq, Default)]
+#[repr(u16)]
+pub enum VmUsageMetric {
+    /// CPU usage, percent
+    #[default]
+    Cpu = 0,
+    /// Memory usage, percent of the VM's memory
+    Memory = 1,
+    /// Network receive, bytes/s
+    NetIn = 2,
+    /// Network transmit, bytes/s
+    NetOut = 3,
+    /// Disk read, bytes/s
+    DiskRead = 4,
+    /// Disk write, bytes/s
+    DiskWrite = 5,
+    /// Space used on the fullest guest filesystem, percent. Read from the
+    /// guest agent when the alert is checked, the window does not apply.
+    DiskUsage = 6,
+}
+
+/// A user-configurable resource usage alert on a VM
+#[derive(FromRow, Clone, Debug, Default)]
+pub struct VmUsageAlert {
+    /// Unique id of this alert
+    pub id: u64,
+    /// VM this alert watches
+    pub vm_id: u64,
+    /// Metric compared against the threshold
+    pub metric: VmUsageMetric,
+    /// Alert when the average over the window is at or above this value
+    pub threshold: f64,
+    /// Averaging window in seconds
+    pub window_seconds: u32,
+    /// When this alert last sent a notification
+    pub last_triggered: Option<DateTime<Utc>>,
+    /// When this alert was created
+    pub created: DateTime<Utc>,
+}
+
+/// Automatic backup schedule of a VM
+#[derive(FromRow, Clone, Debug, Default)]
+pub struct VmBackupSchedule {
+    /// Unique id of this schedule
+    pub id: u64,
+    /// VM which is backed up
+    pub vm_id: u64,
+    /// Hours between backups
+    pub interval_hours: u16,
+    /// Schedule is active
+    pub enabled: bool,
+    /// When the last backup was taken
+    pub last_run: Option<DateTime<Utc>>,
+    /// When this schedule was created
+    pub created: DateTime<Utc>,
+}
+
+impl VmBackupSchedule {
+    /// When the next backup is due, the first backup is due right away.
+    /// `None` when the schedule is disabled
+    pub fn next_run(&self) -> Option<DateTime<Utc>> {
+        if !self.enabled {
+            return None;
+        }
+        Some(match self.last_run {
+            Some(last) => last + TimeDelta::hours(self.interval_hours as i64),
+            None => self.created,
+        })
+    }
+
+    /// If a backup should be taken at `now`
+    pub fn is_due(&self, now: DateTime<Utc>) -> bool {
+        self.next_run().is_some_and(|t| t <= now)
+    }
+}
+
+/// Network transfer of a VM in one calendar month (UTC)
+#[derive(FromRow, Clone, Debug, Default, PartialEq)]
+pub struct VmBandwidthUsage {
+    pub vm_id: u64,
+    /// Start of the month
+    pub month: DateTime<Utc>,
+    /// Bytes received
+    pub bytes_in: u64,
+    /// Bytes sent
+    pub bytes_out: u64,
+    /// Timestamp of the newest time-series sample counted
+    pub last_sample: DateTime<Utc>,
+}
+
+impl VmBandwidthUsage {
+    /// Start of the calendar month `time` falls in
+    pub fn month_of(time: DateTime<Utc>) -> DateTime<Utc> {
+        time.date_naive()
+            .with_day(1)
+            .expect("every month has a first day")
+            .and_time(NaiveTime::MIN)
+            .and_utc()
+    }
+}
+
+/// Client supplied id of a VM create request, used to return the same VM when
+/// a create is retried
+#[derive(FromRow, Clone, Debug, Default)]
+pub struct VmCreateRequest {
+    pub id: u64,
+    /// User who sent the request, ids are unique per user
+    pub user_id: u64,
+    /// Id supplied by the client
+    pub request_id: String,
+    /// VM created by this request, `None` while it is being created
+    pub vm_id: Option<u64>,
+    pub created: DateTime<Utc>,
+}
+
+/// A backup archive of a VM on its host's backup storage
+#[derive(FromRow, Clone, Debug, Default)]
+pub struct VmBackup {
+    /// Unique id of this backup
+    pub id: u64,
+    /// VM this backup was taken from
+    pub vm_id: u64,
+    /// Host volume id of the archive
+    pub volume_id: String,
+    /// Size of the archive in bytes
+    pub size: u64,
+    /// When the backup was taken
+    pub created: DateTime<Utc>,
+}
+
 /// A user-configurable per-VM firewall rule (#36)
 #[derive(FromRow, Clone, Debug, Default)]
 pub struct VmFirewallRule {
@@ -1421,6 +1728,44 @@ pub struct VmFirewallRule {
     pub updated: DateTime<Utc>,
 }
 
+/// An additional data volume attached to a VM alongside its primary disk
+#[derive(FromRow, Clone, Debug, Default)]
+pub struct VmExtraDisk {
+    /// Unique id of this disk
+    pub id: u64,
+    /// VM this disk is attached to
+    pub vm_id: u64,
+    /// Host storage ([VmHostDisk]) the volume is allocated on
+    pub disk_id: u64,
+    /// Size of the volume in bytes
+    pub size: u64,
+    /// Attachment slot on the VM (0-based, unique per VM)
+    pub slot: u16,
+    /// When this disk was added
+    pub created: DateTime<Utc>,
+}
+
+/// Host capacity held for a VM being placed, so concurrent orders can't
+/// oversell a host before the VM itself counts towards usage
+#[derive(FromRow, Clone, Debug, Default)]
+pub struct CapacityReservation {
+    pub id: u64,
+    /// Host the capacity is held on
+    pub host_id: u64,
+    /// Host storage ([VmHostDisk]) the disk space is held on
+    pub disk_id: u64,
+    /// VM created against this reservation, once known
+    pub vm_id: Option<u64>,
+    /// Number of CPU cores held
+    pub cpu: u16,
+    /// Memory held in bytes
+    pub memory: u64,
+    /// Disk space held in bytes
+    pub disk_size: u64,
+    /// When the reservation lapses
+    pub expires: DateTime<Utc>,
+}
+
 #[derive(FromRow, Clone, Debug, Default)]
 pub struct Referral {
     /// Unique id of this referral entry
@@ -1649,6 +1994,26 @@ pub struct NostrDomainHandle {
     pub relays: Option<String>,
 }
 
+impl NostrDomainHandle {
+    /// Catch-all handle, names without a handle of their own resolve to it
+    pub const DEFAULT: &'static str = "_";
+
+    pub fn is_default(&self) -> bool {
+        self.handle == Self::DEFAULT
+    }
+}
+
+/// How a company shows prices to its customers
+#[derive(Clone, Copy, Debug, sqlx::Type, Default, PartialEq, Eq)]
+#[repr(u16)]
+pub enum TaxDisplay {
+    /// Prices are shown before tax, tax is added on top
+    #[default]
+    Exclusive = 0,
+    /// Prices are shown including tax
+    Inclusive = 1,
+}
+
 #[derive(FromRow, Clone, Debug, Default)]
 pub struct Company {
     pub id: u64,
@@ -1674,6 +2039,10 @@ pub struct Company {
     /// `now + max_prepay_days`. `0` means "inherit the global default".
     #[sqlx(default)]
     pub max_prepay_days: u16,
+    /// Whether prices are shown including or excluding tax. Display only,
+    /// payments always store the net `amount` and `tax` separately.
+    #[sqlx(default)]
+    pub tax_display: TaxDisplay,
 }
 
 #[derive(Clone, Debug, Default)]
@@ -1700,6 +2069,10 @@ pub enum VmHistoryActionType {
     PaymentReceived = 9,
     ConfigurationChanged = 10,
     Transferred = 11,
+    ReachabilityChanged = 12,
+    Rebooted = 13,
+    Suspended = 14,
+    Resumed = 15,
 }
 
 impl Display for VmHistoryActionType {
@@ -1717,6 +2090,10 @@ impl Display for VmHistoryActionType {
             VmHistoryActionType::PaymentReceived => write!(f, "payment_received"),
             VmHistoryActionType::ConfigurationChanged => write!(f, "configuration_changed"),
             VmHistoryActionType::Transferred => write!(f, "transferred"),
+            VmHistoryActionType::ReachabilityChanged => write!(f, "reachability_changed"),
+            VmHistoryActionType::Rebooted => write!(f, "rebooted"),
+            VmHistoryActionType::Suspended => write!(f, "suspended"),
+            VmHistoryActionType::Resumed => write!(f, "resumed"),
         }
     }
 }
@@ -1738,6 +2115,10 @@ impl FromStr for VmHistoryActionType {
             "payment_received" => Ok(VmHistoryActionType::PaymentReceived),
             "configuration_changed" => Ok(VmHistoryActionType::ConfigurationChanged),
             "transferred" => Ok(VmHistoryActionType::Transferred),
+            "reachability_changed" => Ok(VmHistoryActionType::ReachabilityChanged),
+            "rebooted" => Ok(VmHistoryActionType::Rebooted),
+            "suspended" => Ok(VmHistoryActionType::Suspended),
+            "resumed" => Ok(VmHistoryActionType::Resumed),
             _ => Err(anyhow!("unknown VM history action type: {}", s)),
         }
     }
@@ -1790,6 +2171,35 @@ pub struct AdminRoleAssignment {
     pub expires_at: Option<DateTime<Utc>>,
 }
 
+/// An admin action recorded for audit
+#[derive(FromRow, Clone, Debug, Default)]
+pub struct AdminAuditLog {
+    pub id: u64,
+    /// Admin user who performed the action
+    pub admin_user_id: u64,
+    /// Action name, e.g. `bulk_message`
+    pub action: String,
+    /// JSON details of the action
+    pub detail: Option<String>,
+    pub created: DateTime<Utc>,
+}
+
+/// Outcome of a bulk message for one recipient
+#[derive(FromRow, Clone, Debug, Default)]
+pub struct BulkMessageDelivery {
+    pub id: u64,
+    /// The `bulk_message` [AdminAuditLog] entry of the send
+    pub audit_log_id: u64,
+    pub user_id: u64,
+    /// Channel the message was delivered on
+    pub channel: Option<String>,
+    /// `delivered`, `no_channel` or `failed`
+    pub status: String,
+    /// Why delivery failed
+    pub error: Option<String>,
+    pub created: DateTime<Utc>,
+}
+
 /// Administrative resources that can be managed
 #[derive(Clone, Copy, Debug, sqlx::Type, PartialEq, Eq, Hash, Serialize, Deserialize)]
 #[repr(u16)]
@@ -2141,6 +2551,8 @@ pub struct SubscriptionPayment {
     pub user_id: u64,
     pub created: DateTime<Utc>,
     pub expires: DateTime<Utc>,
+    /// Net amount (before tax) in smallest currency units, regardless of the
+    /// company's [TaxDisplay]
     pub amount: u64,
     pub currency: String,
     pub payment_method: PaymentMethod,
@@ -2175,6 +2587,17 @@ pub struct SubscriptionPayment {
     /// time. Losslessly records payments that mix rates/treatments.
     #[sqlx(default)]
     pub tax_breakdown: Option<serde_json::Value>,
+    /// Sequential invoice number (`LNVPS-{company}-{year}-{seq}`), assigned
+    /// when the payment is marked paid
+    #[sqlx(default)]
+    pub invoice_number: Option<String>,
+}
+
+impl SubscriptionPayment {
+    /// Invoice number for the `seq`th invoice of a company in a year
+    pub fn format_invoice_number(company_id: u64, year: u64, seq: u64) -> String {
+        format!("LNVPS-{}-{}-{:06}", company_id, year, seq)
+    }
 }
 
 /// Subscription payment with company info (for admin views and time-series reporting)
@@ -2216,6 +2639,9 @@ pub struct SubscriptionPaymentWithCompany {
     /// Authoritative per-line-item VAT breakdown (JSON array), frozen at sale time.
     #[sqlx(default)]
     pub tax_breakdown: Option<serde_json::Value>,
+    /// Sequential invoice number, set once paid.
+    #[sqlx(default)]
+    pub invoice_number: Option<String>,
     // Company information
     pub company_id: u64,
     pub company_name: String,
@@ -2279,10 +2705,139 @@ impl InternetRegistry {
     }
 }
 
+/// A switch gating a behaviour, for every region or for one region. A
+/// region flag overrides the global flag with the same key.
+#[derive(FromRow, Clone, Debug, Default)]
+pub struct FeatureFlag {
+    pub id: u64,
+    pub key: String,
+    /// Region the flag applies to, `None` for every region
+    pub region_id: Option<u64>,
+    pub enabled: bool,
+    pub updated: DateTime<Utc>,
+}
+
+/// Importance of a [SystemNotice], drives how the banner is shown
+#[derive(Clone, Copy, Debug, sqlx::Type, Default, PartialEq, Eq)]
+#[repr(u16)]
+pub enum NoticeSeverity {
+    #[default]
+    Info = 0,
+    Warning = 1,
+    Critical = 2,
+}
+
+/// A banner (e.g. planned maintenance) shown to customers between `starts`
+/// and `ends`
+#[derive(FromRow, Clone, Debug)]
+pub struct SystemNotice {
+    pub id: u64,
+    pub created: DateTime<Utc>,
+    pub message: String,
+    pub severity: NoticeSeverity,
+    pub starts: DateTime<Utc>,
+    /// Open ended when `None`
+    pub ends: Option<DateTime<Utc>>,
+    /// Regions affected, empty for all regions
+    pub regions: CommaSeparated<u64>,
+}
+
+impl SystemNotice {
+    /// Notice is shown at `now`
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        self.starts <= now && self.ends.is_none_or(|e| e > now)
+    }
+
+    /// Notice is for everyone or for `region_id`. Region scoped notices are
+    /// only returned when a region is given.
+    pub fn applies_to(&self, region_id: Option<u64>) -> bool {
+        self.regions.is_empty() || region_id.is_some_and(|r| self.regions.contains(&r))
+    }
+}
+
+/// An outage affecting either a region or an IP range
+#[derive(FromRow, Clone, Debug, Default)]
+pub struct Incident {
+    pub id: u64,
+    pub created: DateTime<Utc>,
+    pub region_id: Option<u64>,
+    pub ip_range_id: Option<u64>,
+    pub message: String,
+    pub started: DateTime<Utc>,
+    /// Ongoing when `None`
+    pub ended: Option<DateTime<Utc>>,
+    /// Admin who raised the incident
+    pub admin_user_id: u64,
+    /// Number of customers notified
+    pub notified: u64,
+}
+
+#[derive(Clone, Copy, Debug, sqlx::Type, Default, PartialEq, Eq)]
+#[repr(u16)]
+pub enum WorkQueueStatus {
+    /// Waiting to be claimed once due
+    #[default]
+    Queued = 0,
+    /// Claimed by a worker which is running it
+    Claimed = 1,
+    /// Failed too many times, kept for inspection and never claimed
+    Dead = 2,
+}
+
+/// A job in the MySQL backed work queue, completed jobs are deleted
+#[derive(FromRow, Clone, Debug, Default)]
+pub struct WorkQueueItem {
+    pub id: u64,
+    pub created: DateTime<Utc>,
+    /// Serialized job
+    pub job: String,
+    pub status: WorkQueueStatus,
+    /// The job is not claimed before this time
+    pub due: DateTime<Utc>,
+    /// Worker which claimed the job
+    pub claimed_by: Option<String>,
+    pub claimed_at: Option<DateTime<Utc>>,
+    /// Number of failed or abandoned runs
+    pub attempts: u32,
+    /// Error of the last failed run
+    pub last_error: Option<String>,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_system_notice_active_window() {
+        let now = Utc::now();
+        let notice = |starts: TimeDelta, ends: Option<TimeDelta>, regions: Vec<u64>| SystemNotice {
+            id: 1,
+            created: now,
+            message: "Maintenance".to_string(),
+            severity: NoticeSeverity::Warning,
+            starts: now + starts,
+            ends: ends.map(|e| now + e),
+            regions: regions.into(),
+        };
+        let hour = TimeDelta::hours(1);
+
+        assert!(notice(-hour, Some(hour), vec![]).is_active(now));
+        assert!(notice(-hour, None, vec![]).is_active(now));
+        // not started yet / already over
+        assert!(!notice(hour, Some(hour * 2), vec![]).is_active(now));
+        assert!(!notice(-hour * 2, Some(-hour), vec![]).is_active(now));
+        // ends is exclusive
+        assert!(!notice(-hour, Some(TimeDelta::zero()), vec![]).is_active(now));
+
+        let global = notice(-hour, None, vec![]);
+        assert!(global.applies_to(None));
+        assert!(global.applies_to(Some(3)));
+        let scoped = notice(-hour, None, vec![1, 2]);
+        assert!(scoped.applies_to(Some(2)));
+        assert!(!scoped.applies_to(Some(3)));
+        assert!(!scoped.applies_to(None));
+    }
+
     #[test]
     fn test_os_distribution_from_str_and_display() {
         let all = [
@@ -2329,6 +2884,7 @@ mod tests {
             default_username: None,
             sha2: None,
             sha2_url: None,
+            image_kind: OsImageKind::CloudInit,
         }
     }
 
@@ -2685,6 +3241,30 @@ mod tests {
         assert_eq!(oc.account, None);
         assert_eq!(oc.min_confirmations, 1);
     }
+
+    #[test]
+    fn test_backup_schedule_is_due() {
+        let created = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let mut schedule = VmBackupSchedule {
+            id: 1,
+            vm_id: 1,
+            interval_hours: 24,
+            enabled: true,
+            last_run: None,
+            created,
+        };
+        // never run, due right away
+        assert_eq!(schedule.next_run(), Some(created));
+        assert!(schedule.is_due(created));
+
+        schedule.last_run = Some(created);
+        assert!(!schedule.is_due(created + TimeDelta::hours(23)));
+        assert!(schedule.is_due(created + TimeDelta::hours(24)));
+
+        schedule.enabled = false;
+        assert_eq!(schedule.next_run(), None);
+        assert!(!schedule.is_due(created + TimeDelta::days(30)));
+    }
 }
 
 /// Available IP Space - Inventory of IP ranges available for sale
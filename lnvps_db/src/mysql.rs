@@ -1,13 +1,16 @@
 use crate::{
-    AccessPolicy, App, AppCluster, AppDeployment, AsnSubscription, AsnSubscriptionStatus,
-    AvailableIpSpace, Company, DbError, DbResult, DnsServer, IntervalType, IpRange,
-    IpRangeSubscription, IpSpacePricing, LNVpsDbBase, PaymentMethod, PaymentMethodConfig,
-    PaymentType, Referral, ReferralCostUsage, ReferralPayout, Region, RegionStats, Router,
-    RouterBgpRoute, RouterBgpSession, RouterTunnel, RouterTunnelTraffic, Subscription,
-    SubscriptionLineItem, SubscriptionPayment, SubscriptionPaymentWithCompany, User,
-    UserPaymentMethod, UserSshKey, Vm, VmCostPlan, VmCustomPricing, VmCustomPricingDisk,
-    VmCustomTemplate, VmFirewallPolicy, VmFirewallRule, VmHistory, VmHost, VmHostDisk,
-    VmIpAssignment, VmOsImage, VmTemplate, WebauthnCredential,
+    AccessPolicy, AdminAuditLog, App, AppCluster, AppDeployment, AsnSubscription,
+    AsnSubscriptionStatus, AvailableIpSpace, BulkMessageDelivery, CapacityReservation, Company,
+    DbError, DbResult, DnsServer, EncryptedString, FeatureFlag, Incident, IntervalType, IpRange,
+    IpRangeSubscription, IpSpacePricing, LNVpsDbBase, OsImageKind, PaymentMethod,
+    PaymentMethodConfig, PaymentType, Referral, ReferralCostUsage, ReferralPayout, Region,
+    RegionStats, Router, RouterBgpRoute, RouterBgpSession, RouterTunnel, RouterTunnelTraffic,
+    Subscription, SubscriptionLineItem, SubscriptionPayment, SubscriptionPaymentWithCompany,
+    SystemNotice, User, UserPaymentMethod, UserSshKey, Vm, VmBackup, VmBackupSchedule,
+    VmBandwidthUsage, VmCostPlan, VmCreateRequest, VmCustomPricing, VmCustomPricingDisk,
+    VmCustomTemplate, VmExtraDisk, VmFirewallPolicy, VmFirewallRule, VmHistory, VmHost, VmHostDisk,
+    VmIpAssignment, VmOsImage, VmState, VmStateKind, VmTemplate, VmUsageAlert, WebauthnCredential,
+    WorkQueueItem, WorkQueueStatus,
 };
 #[cfg(feature = "admin")]
 use crate::{AdminDb, AdminRole, AdminRoleAssignment, AdminVmHost};
@@ -15,18 +18,41 @@ use crate::{AdminDb, AdminRole, AdminRoleAssignment, AdminVmHost};
 use crate::{LNVPSNostrDb, NostrDomain, NostrDomainHandle};
 use anyhow::{Result, anyhow};
 use async_trait::async_trait;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, TimeDelta, Utc};
 use sqlx::{Executor, MySqlPool, QueryBuilder, Row};
 
 #[derive(Clone)]
 pub struct LNVpsDbMysql {
     db: MySqlPool,
+    /// Pool for heavy read queries (listings, counts, reports), same as `db`
+    /// when no read replica is configured
+    read: MySqlPool,
 }
 
 impl LNVpsDbMysql {
     pub async fn new(conn: &str) -> DbResult<Self> {
         let db = MySqlPool::connect(conn).await?;
-        Ok(Self { db })
+        Ok(Self {
+            read: db.clone(),
+            db,
+        })
+    }
+
+    /// Connect to a primary and a read replica, listings, counts and reports
+    /// are read from the replica while everything else uses the primary
+    pub async fn new_with_replica(primary: &str, replica: &str) -> DbResult<Self> {
+        let db = MySqlPool::connect(primary).await?;
+        let read = MySqlPool::connect(replica).await?;
+        Ok(Self { db, read })
+    }
+
+    /// A handle which reads everything from the primary, for reads which must
+    /// see a write made just before (replicas can lag behind)
+    pub fn primary(&self) -> Self {
+        Self {
+            db: self.db.clone(),
+            read: self.db.clone(),
+        }
     }
 
     pub async fn execute(&self, sql: &str) -> DbResult<()> {
@@ -38,6 +64,133 @@ impl LNVpsDbMysql {
     pub fn pool(&self) -> &MySqlPool {
         &self.db
     }
+
+    /// Pool used for listings, counts and reports
+    pub fn read_pool(&self) -> &MySqlPool {
+        &self.read
+    }
+
+    /// Give a just paid payment the next invoice number of its company for the
+    /// year it was paid in.
+    ///
+    /// The counter row is locked by the upsert until the transaction ends, so
+    /// concurrent settlements for the same company queue up behind each other
+    /// and a rolled back settlement gives its number back (no gaps).
+    async fn assign_invoice_number(
+        tx: &mut sqlx::Transaction<'_, sqlx::MySql>,
+        payment_id: &Vec<u8>,
+    ) -> DbResult<()> {
+        // VM payments are invoiced by the company of the VM's region
+        let (company_id, year): (u64, u64) = sqlx::query_as(
+            "SELECT CAST(COALESCE(
+                (SELECT r.company_id FROM subscription_line_item li
+                 JOIN vm v ON v.subscription_line_item_id = li.id
+                 JOIN vm_host vh ON v.host_id = vh.id
+                 JOIN region r ON vh.region_id = r.id
+                 WHERE li.subscription_id = s.id LIMIT 1),
+                s.company_id) AS UNSIGNED),
+             CAST(YEAR(p.paid_at) AS UNSIGNED)
+             FROM subscription_payment p
+             JOIN subscription s ON p.subscription_id = s.id
+             WHERE p.id = ?",
+        )
+        .bind(payment_id)
+        .fetch_one(&mut **tx)
+        .await?;
+
+        sqlx::query(
+            "INSERT INTO company_invoice_counter (company_id, year, seq) VALUES (?, ?, 1) \
+             ON DUPLICATE KEY UPDATE seq = seq + 1",
+        )
+        .bind(company_id)
+        .bind(year)
+        .execute(&mut **tx)
+        .await?;
+        let seq: u64 = sqlx::query_scalar(
+            "SELECT seq FROM company_invoice_counter WHERE company_id = ? AND year = ? FOR UPDATE",
+        )
+        .bind(company_id)
+        .bind(year)
+        .fetch_one(&mut **tx)
+        .await?;
+
+        sqlx::query("UPDATE subscription_payment SET invoice_number = ? WHERE id = ?")
+            .bind(SubscriptionPayment::format_invoice_number(
+                company_id, year, seq,
+            ))
+            .bind(payment_id)
+            .execute(&mut **tx)
+            .await?;
+        Ok(())
+    }
+
+    /// Mark `payment` paid and extend its subscription within `tx`.
+    ///
+    /// Returns `false` (no changes made) if the payment is already paid or unknown.
+    async fn mark_payment_paid(
+        tx: &mut sqlx::Transaction<'_, sqlx::MySql>,
+        payment: &SubscriptionPayment,
+    ) -> DbResult<bool> {
+        // Mark payment as paid. The `AND is_paid = 0` guard makes this idempotent:
+        // duplicate webhook deliveries / replayed settle events affect 0 rows and
+        // are skipped below, so the subscription expiry is never extended twice.
+        let paid = sqlx::query(
+            "UPDATE subscription_payment SET is_paid = 1, external_data = ?, paid_at = NOW() WHERE id = ? AND is_paid = 0",
+        )
+        .bind(&payment.external_data)
+        .bind(&payment.id)
+        .execute(&mut **tx)
+        .await?;
+
+        if paid.rows_affected() == 0 {
+            // Already paid (or unknown id) — nothing to extend.
+            return Ok(false);
+        }
+
+        Self::assign_invoice_number(tx, &payment.id).await?;
+
+        // Un-delete any VM linked to this subscription (e.g. auto-cleaned up before
+        // payment arrived). This handles payment methods with longer timeouts.
+        sqlx::query(
+            "UPDATE vm SET deleted = 0 WHERE subscription_line_item_id IN (SELECT id FROM subscription_line_item WHERE subscription_id = ?)",
+        )
+        .bind(payment.subscription_id)
+        .execute(&mut **tx)
+        .await?;
+
+        if let Some(time_value) = payment.time_value {
+            // Extend subscription.expires by explicit time_value seconds
+            sqlx::query(
+                "UPDATE subscription SET expires = DATE_ADD(GREATEST(COALESCE(expires, NOW()), NOW()), INTERVAL ? SECOND), is_active = 1, is_setup = 1 WHERE id = ?",
+            )
+            .bind(time_value)
+            .bind(payment.subscription_id)
+            .execute(&mut **tx)
+            .await?;
+        } else {
+            // Regular subscription path: read interval from the subscription itself
+            let sub: Subscription = sqlx::query_as("SELECT * FROM subscription WHERE id = ?")
+                .bind(payment.subscription_id)
+                .fetch_one(&mut **tx)
+                .await?;
+            let interval_sql = match sub.interval_type {
+                IntervalType::Day => "DAY",
+                IntervalType::Month => "MONTH",
+                IntervalType::Year => "YEAR",
+            };
+            let sql = format!(
+                "UPDATE subscription SET expires = DATE_ADD(GREATEST(COALESCE(expires, NOW()), NOW()), INTERVAL ? {}), is_active = 1, is_setup = 1 WHERE id = ?",
+                interval_sql
+            );
+            sqlx::query(&sql)
+                .bind(sub.interval_amount)
+                .bind(payment.subscription_id)
+                .execute(&mut **tx)
+                .await?;
+        }
+
+        Ok(true)
+    }
 }
 
 #[async_trait]
@@ -48,6 +201,11 @@ impl LNVpsDbBase for LNVpsDbMysql {
         Ok(())
     }
 
+    async fn ping(&self) -> DbResult<()> {
+        sqlx::query("select 1").execute(&self.db).await?;
+        Ok(())
+    }
+
     async fn upsert_user(&self, pubkey: &[u8; 32]) -> DbResult<u64> {
         let res =
             sqlx::query("insert ignore into users(pubkey,contact_nip17) values(?,1) returning id")
@@ -167,7 +325,7 @@ impl LNVpsDbBase for LNVpsDbMysql {
             Some(crate::email_hash(user.email.as_str()).to_vec())
         };
         sqlx::query(
-            "update users set email=?, email_hash=?, email_verified=?, email_verify_token=?, contact_nip17=?, contact_email=?, contact_telegram=?, telegram_chat_id=?, telegram_link_token=?, contact_whatsapp=?, whatsapp_number=?, whatsapp_verified=?, whatsapp_verify_code=?, country_code=?, billing_name=?, billing_address_1=?, billing_address_2=?, billing_city=?, billing_state=?, billing_postcode=?, billing_tax_id=?, geo_country_code=?, geo_ip=?, geo_updated=? where id = ?",
+            "update users set email=?, email_hash=?, email_verified=?, email_verify_token=?, contact_nip17=?, contact_email=?, contact_telegram=?, telegram_chat_id=?, telegram_link_token=?, contact_whatsapp=?, whatsapp_number=?, whatsapp_verified=?, whatsapp_verify_code=?, country_code=?, billing_name=?, billing_address_1=?, billing_address_2=?, billing_city=?, billing_state=?, billing_postcode=?, billing_tax_id=?, billing_tax_id_validated=?, billing_tax_id_name=?, geo_country_code=?, geo_ip=?, geo_updated=?, placement_policy=? where id = ?",
         )
             .bind(&user.email)
             .bind(hash)
@@ -190,9 +348,12 @@ impl LNVpsDbBase for LNVpsDbMysql {
             .bind(&user.billing_state)
             .bind(&user.billing_postcode)
             .bind(&user.billing_tax_id)
+            .bind(user.billing_tax_id_validated)
+            .bind(&user.billing_tax_id_name)
             .bind(&user.geo_country_code)
             .bind(&user.geo_ip)
             .bind(user.geo_updated)
+            .bind(user.placement_policy)
             .bind(user.id)
             .execute(&self.db)
             .await?;
@@ -242,6 +403,9 @@ impl LNVpsDbBase for LNVpsDbMysql {
         for child in [
             "delete from vm_ip_assignment where vm_id in (select id from vm where user_id = ?)",
             "delete from vm_firewall_rule where vm_id in (select id from vm where user_id = ?)",
+            "delete from vm_usage_alert where vm_id in (select id from vm where user_id = ?)",
+            "delete from vm_backup where vm_id in (select id from vm where user_id = ?)",
+            "delete from vm_backup_schedule where vm_id in (select id from vm where user_id = ?)",
             "delete from vm_history where vm_id in (select id from vm where user_id = ?)",
         ] {
             sqlx::query(child).bind(id).execute(&mut *tx).await?;
@@ -363,14 +527,14 @@ impl LNVpsDbBase for LNVpsDbMysql {
             sqlx::query_as("select * from users order by id limit ? offset ?")
                 .bind(limit)
                 .bind(offset)
-                .fetch_all(&self.db)
+                .fetch_all(&self.read)
                 .await?,
         )
     }
 
     async fn count_users(&self) -> DbResult<u64> {
         Ok(sqlx::query("select count(*) as count from users")
-            .fetch_one(&self.db)
+            .fetch_one(&self.read)
             .await?
             .try_get(0)?)
     }
@@ -437,7 +601,7 @@ impl LNVpsDbBase for LNVpsDbMysql {
             let total: i64 =
                 sqlx::query_scalar("select count(*) from user_payment_method where user_id=?")
                     .bind(user_id)
-                    .fetch_one(&self.db)
+                    .fetch_one(&self.read)
                     .await?;
             let rows = sqlx::query_as(
                 "select * from user_payment_method where user_id=? order by id desc limit ? offset ?",
@@ -445,19 +609,19 @@ impl LNVpsDbBase for LNVpsDbMysql {
             .bind(user_id)
             .bind(limit)
             .bind(offset)
-            .fetch_all(&self.db)
+            .fetch_all(&self.read)
             .await?;
             Ok((rows, total as u64))
         } else {
             let total: i64 = sqlx::query_scalar("select count(*) from user_payment_method")
-                .fetch_one(&self.db)
+                .fetch_one(&self.read)
                 .await?;
             let rows = sqlx::query_as(
                 "select * from user_payment_method order by id desc limit ? offset ?",
             )
             .bind(limit)
             .bind(offset)
-            .fetch_all(&self.db)
+            .fetch_all(&self.read)
             .await?;
             Ok((rows, total as u64))
         }
@@ -555,7 +719,7 @@ impl LNVpsDbBase for LNVpsDbMysql {
         let total: i64 = sqlx::query_scalar(
             "SELECT COUNT(*) FROM vm_host h, region hr WHERE h.enabled = 1 AND h.region_id = hr.id AND hr.enabled = 1"
         )
-        .fetch_one(&self.db)
+        .fetch_one(&self.read)
         .await?;
 
         // Get paginated results
@@ -564,7 +728,7 @@ impl LNVpsDbBase for LNVpsDbMysql {
         )
         .bind(limit)
         .bind(offset)
-        .fetch_all(&self.db)
+        .fetch_all(&self.read)
         .await?;
 
         Ok((hosts, total as u64))
@@ -579,19 +743,19 @@ impl LNVpsDbBase for LNVpsDbMysql {
         let total: i64 = sqlx::query_scalar(
             "SELECT COUNT(*) FROM vm_host h, region hr WHERE h.enabled = 1 AND h.region_id = hr.id AND hr.enabled = 1"
         )
-        .fetch_one(&self.db)
+        .fetch_one(&self.read)
         .await?;
 
         // Get paginated results with region info
         let rows = sqlx::query(
-            "SELECT h.*, hr.id as region_id, hr.name as region_name, hr.enabled as region_enabled, hr.company_id as region_company_id 
+            "SELECT h.*, hr.id as region_id, hr.name as region_name, hr.enabled as region_enabled, hr.company_id as region_company_id, hr.maintenance as region_maintenance, hr.reachability_check as region_reachability_check, hr.forward_dns_suffix as region_forward_dns_suffix 
              FROM vm_host h, region hr 
              WHERE h.enabled = 1 AND h.region_id = hr.id AND hr.enabled = 1 
              ORDER BY h.name LIMIT ? OFFSET ?"
         )
         .bind(limit)
         .bind(offset)
-        .fetch_all(&self.db)
+        .fetch_all(&self.read)
         .await?;
 
         let mut results = Vec::new();
@@ -614,9 +778,12 @@ impl LNVpsDbBase for LNVpsDbMysql {
                 load_disk: row.get("load_disk"),
                 vlan_id: row.get("vlan_id"),
                 mtu: row.get("mtu"),
+                private_bridge: row.get("private_bridge"),
                 ssh_user: row.get("ssh_user"),
                 ssh_key: row.get("ssh_key"),
                 sunset_date: row.get("sunset_date"),
+                maintenance: row.get("maintenance"),
+                weight: row.get("weight"),
             };
 
             let region = Region {
@@ -624,6 +791,9 @@ impl LNVpsDbBase for LNVpsDbMysql {
                 name: row.get("region_name"),
                 enabled: row.get("region_enabled"),
                 company_id: row.get("region_company_id"),
+                maintenance: row.get("region_maintenance"),
+                reachability_check: row.get("region_reachability_check"),
+                forward_dns_suffix: row.get("region_forward_dns_suffix"),
             };
 
             results.push((host, region));
@@ -644,7 +814,8 @@ impl LNVpsDbBase for LNVpsDbMysql {
             "UPDATE vm_host SET kind = ?, region_id = ?, name = ?, ip = ?, cpu = ?, \
              cpu_mfg = ?, cpu_arch = ?, cpu_features = ?, memory = ?, enabled = ?, \
              api_token = ?, load_cpu = ?, load_memory = ?, load_disk = ?, vlan_id = ?, \
-             mtu = ?, ssh_user = ?, ssh_key = ?, sunset_date = ? WHERE id = ?",
+             mtu = ?, private_bridge = ?, ssh_user = ?, ssh_key = ?, sunset_date = ?, \
+             maintenance = ?, weight = ? WHERE id = ?",
         )
         .bind(&host.kind)
         .bind(host.region_id)
@@ -662,20 +833,37 @@ impl LNVpsDbBase for LNVpsDbMysql {
         .bind(host.load_disk)
         .bind(host.vlan_id)
         .bind(host.mtu)
+        .bind(&host.private_bridge)
         .bind(&host.ssh_user)
         .bind(&host.ssh_key)
         .bind(host.sunset_date)
+        .bind(host.maintenance)
+        .bind(host.weight)
         .bind(host.id)
         .execute(&self.db)
         .await?;
         Ok(())
     }
 
+    async fn update_host_api_token(
+        &self,
+        host_id: u64,
+        api_token: &EncryptedString,
+    ) -> DbResult<()> {
+        sqlx::query("UPDATE vm_host SET api_token = ? WHERE id = ?")
+            .bind(api_token)
+            .bind(host_id)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
     async fn create_host(&self, host: &VmHost) -> DbResult<u64> {
         let result = sqlx::query(
             "INSERT INTO vm_host (kind, region_id, name, ip, cpu, cpu_mfg, cpu_arch, \
              cpu_features, memory, enabled, api_token, load_cpu, load_memory, load_disk, \
-             vlan_id, mtu, ssh_user, ssh_key, sunset_date) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+             vlan_id, mtu, private_bridge, ssh_user, ssh_key, sunset_date, maintenance, weight) \
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
         )
         .bind(&host.kind)
         .bind(host.region_id)
@@ -693,9 +881,12 @@ impl LNVpsDbBase for LNVpsDbMysql {
         .bind(host.load_disk)
         .bind(host.vlan_id)
         .bind(host.mtu)
+        .bind(&host.private_bridge)
         .bind(&host.ssh_user)
         .bind(&host.ssh_key)
         .bind(host.sunset_date)
+        .bind(host.maintenance)
+        .bind(host.weight)
         .execute(&self.db)
         .await?;
         Ok(result.last_insert_id())
@@ -758,17 +949,52 @@ impl LNVpsDbBase for LNVpsDbMysql {
             .await?)
     }
 
+    async fn list_os_image_by_kind(&self, kind: OsImageKind) -> DbResult<Vec<VmOsImage>> {
+        Ok(
+            sqlx::query_as("select * from vm_os_image where image_kind=? and enabled = 1")
+                .bind(kind as u16)
+                .fetch_all(&self.db)
+                .await?,
+        )
+    }
+
     async fn count_vms_by_os_image(&self) -> DbResult<Vec<(u64, u64)>> {
         Ok(sqlx::query_as(
             "SELECT image_id, CAST(COUNT(*) AS UNSIGNED) FROM vm WHERE deleted = 0 GROUP BY image_id",
         )
-        .fetch_all(&self.db)
+        .fetch_all(&self.read)
         .await?)
     }
 
+    async fn list_region_os_images(&self, region_id: u64) -> DbResult<Vec<u64>> {
+        Ok(
+            sqlx::query_scalar("select image_id from region_os_image where region_id=?")
+                .bind(region_id)
+                .fetch_all(&self.db)
+                .await?,
+        )
+    }
+
+    async fn set_region_os_images(&self, region_id: u64, image_ids: &[u64]) -> DbResult<()> {
+        let mut tx = self.db.begin().await?;
+        sqlx::query("delete from region_os_image where region_id=?")
+            .bind(region_id)
+            .execute(&mut *tx)
+            .await?;
+        for image_id in image_ids {
+            sqlx::query("insert ignore into region_os_image(region_id,image_id) values(?,?)")
+                .bind(region_id)
+                .bind(image_id)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
     async fn update_os_image(&self, image: &VmOsImage) -> DbResult<()> {
         sqlx::query(
-            "UPDATE vm_os_image SET distribution=?, flavour=?, version=?, enabled=?, release_date=?, url=?, cpu_arch=?, default_username=?, sha2=?, sha2_url=? WHERE id=?"
+            "UPDATE vm_os_image SET distribution=?, flavour=?, version=?, enabled=?, release_date=?, url=?, cpu_arch=?, default_username=?, sha2=?, sha2_url=?, image_kind=? WHERE id=?"
         )
         .bind(image.distribution as u16)
         .bind(&image.flavour)
@@ -780,6 +1006,7 @@ impl LNVpsDbBase for LNVpsDbMysql {
         .bind(&image.default_username)
         .bind(&image.sha2)
         .bind(&image.sha2_url)
+        .bind(image.image_kind as u16)
         .bind(image.id)
         .execute(&self.db)
         .await?;
@@ -829,13 +1056,13 @@ impl LNVpsDbBase for LNVpsDbMysql {
         offset: u64,
     ) -> DbResult<(Vec<VmCostPlan>, u64)> {
         let total: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM vm_cost_plan")
-            .fetch_one(&self.db)
+            .fetch_one(&self.read)
             .await?;
         let rows =
             sqlx::query_as("SELECT * FROM vm_cost_plan ORDER BY created DESC LIMIT ? OFFSET ?")
                 .bind(limit)
                 .bind(offset)
-                .fetch_all(&self.db)
+                .fetch_all(&self.read)
                 .await?;
         Ok((rows, total as u64))
     }
@@ -890,7 +1117,7 @@ impl LNVpsDbBase for LNVpsDbMysql {
     }
 
     async fn insert_vm_template(&self, template: &VmTemplate) -> DbResult<u64> {
-        Ok(sqlx::query("insert into vm_template(name,enabled,created,expires,cpu,cpu_mfg,cpu_arch,cpu_features,memory,disk_size,disk_type,disk_interface,cost_plan_id,region_id,disk_iops_read,disk_iops_write,disk_mbps_read,disk_mbps_write,network_mbps,cpu_limit) values(?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?) returning id")
+        Ok(sqlx::query("insert into vm_template(name,enabled,created,expires,cpu,cpu_mfg,cpu_arch,cpu_features,memory,disk_size,disk_type,disk_interface,cost_plan_id,region_id,disk_iops_read,disk_iops_write,disk_mbps_read,disk_mbps_write,network_mbps,cpu_limit,max_per_user,bandwidth_quota,backup_retention) values(?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?) returning id")
             .bind(&template.name)
             .bind(template.enabled)
             .bind(template.created)
@@ -911,6 +1138,9 @@ impl LNVpsDbBase for LNVpsDbMysql {
             .bind(template.disk_mbps_write)
             .bind(template.network_mbps)
             .bind(template.cpu_limit)
+            .bind(template.max_per_user)
+            .bind(template.bandwidth_quota)
+            .bind(template.backup_retention)
             .fetch_one(&self.db)
             .await?
             .try_get(0)?)
@@ -952,6 +1182,18 @@ impl LNVpsDbBase for LNVpsDbMysql {
         .await?)
     }
 
+    async fn list_vms_expired_before(&self, before: DateTime<Utc>) -> DbResult<Vec<Vm>> {
+        Ok(sqlx::query_as(
+            "SELECT v.* FROM vm v \
+             INNER JOIN subscription_line_item sli ON sli.id = v.subscription_line_item_id \
+             INNER JOIN subscription s ON s.id = sli.subscription_id \
+             WHERE v.deleted = 0 AND s.expires < ?",
+        )
+        .bind(before)
+        .fetch_all(&self.db)
+        .await?)
+    }
+
     async fn list_active_vms(&self) -> DbResult<Vec<Vm>> {
         // Active VMs are non-deleted VMs whose subscription has been set up
         // (paid at least once). Currently-expired VMs are included (a lapsed
@@ -967,6 +1209,20 @@ impl LNVpsDbBase for LNVpsDbMysql {
         .await?)
     }
 
+    async fn list_unpaid_vms(&self, grace: TimeDelta) -> DbResult<Vec<Vm>> {
+        Ok(sqlx::query_as(
+            "SELECT v.* FROM vm v \
+             INNER JOIN subscription_line_item sli ON sli.id = v.subscription_line_item_id \
+             INNER JOIN subscription s ON s.id = sli.subscription_id \
+             WHERE v.deleted = 0 AND s.is_setup = 0 AND s.created < ? \
+             AND NOT EXISTS (SELECT 1 FROM subscription_payment p \
+             WHERE p.subscription_id = s.id AND p.is_paid = 1)",
+        )
+        .bind(Utc::now() - grace)
+        .fetch_all(&self.db)
+        .await?)
+    }
+
     async fn list_user_vms(&self, id: u64) -> DbResult<Vec<Vm>> {
         Ok(
             sqlx::query_as("select * from vm where user_id = ? and deleted = 0")
@@ -984,7 +1240,7 @@ impl LNVpsDbBase for LNVpsDbMysql {
     }
 
     async fn insert_vm(&self, vm: &Vm) -> DbResult<u64> {
-        Ok(sqlx::query("insert into vm(host_id,user_id,image_id,template_id,custom_template_id,subscription_line_item_id,ssh_key_id,disk_id,mac_address,ref_code) values(?, ?, ?, ?, ?, ?, ?, ?, ?, ?) returning id")
+        Ok(sqlx::query("insert into vm(host_id,user_id,image_id,template_id,custom_template_id,subscription_line_item_id,ssh_key_id,disk_id,mac_address,ref_code,private_network) values(?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?) returning id")
             .bind(vm.host_id)
             .bind(vm.user_id)
             .bind(vm.image_id)
@@ -995,13 +1251,40 @@ impl LNVpsDbBase for LNVpsDbMysql {
             .bind(vm.disk_id)
             .bind(&vm.mac_address)
             .bind(&vm.ref_code)
+            .bind(vm.private_network)
             .fetch_one(&self.db)
             .await?
             .try_get(0)?)
     }
 
+    async fn insert_vm_within_template_limit(&self, vm: &Vm, limit: u16) -> DbResult<Option<u64>> {
+        // insert .. select takes shared locks on the counted rows so two
+        // concurrent orders can't both see a free slot
+        let row = sqlx::query("insert into vm(host_id,user_id,image_id,template_id,custom_template_id,subscription_line_item_id,ssh_key_id,disk_id,mac_address,ref_code,private_network) select ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ? from dual where (select count(*) from vm where user_id = ? and template_id = ? and deleted = 0) < ? returning id")
+            .bind(vm.host_id)
+            .bind(vm.user_id)
+            .bind(vm.image_id)
+            .bind(vm.template_id)
+            .bind(vm.custom_template_id)
+            .bind(vm.subscription_line_item_id)
+            .bind(vm.ssh_key_id)
+            .bind(vm.disk_id)
+            .bind(&vm.mac_address)
+            .bind(&vm.ref_code)
+            .bind(vm.private_network)
+            .bind(vm.user_id)
+            .bind(vm.template_id)
+            .bind(limit)
+            .fetch_optional(&self.db)
+            .await?;
+        Ok(match row {
+            Some(r) => Some(r.try_get(0)?),
+            None => None,
+        })
+    }
+
     async fn insert_vm_with_id(&self, vm: &Vm) -> DbResult<u64> {
-        sqlx::query("insert into vm(id,host_id,user_id,image_id,template_id,custom_template_id,subscription_line_item_id,ssh_key_id,disk_id,mac_address,ref_code) values(?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)")
+        sqlx::query("insert into vm(id,host_id,user_id,image_id,template_id,custom_template_id,subscription_line_item_id,ssh_key_id,disk_id,mac_address,ref_code,private_network) values(?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)")
             .bind(vm.id)
             .bind(vm.host_id)
             .bind(vm.user_id)
@@ -1013,11 +1296,119 @@ impl LNVpsDbBase for LNVpsDbMysql {
             .bind(vm.disk_id)
             .bind(&vm.mac_address)
             .bind(&vm.ref_code)
+            .bind(vm.private_network)
             .execute(&self.db)
             .await?;
         Ok(vm.id)
     }
 
+    async fn import_vm(
+        &self,
+        vm: &Vm,
+        subscription: &Subscription,
+        line_item: &SubscriptionLineItem,
+        custom_template: Option<&VmCustomTemplate>,
+        ip_assignments: &[VmIpAssignment],
+    ) -> DbResult<Vm> {
+        let mut tx = self.db.begin().await?;
+        let mut vm = vm.clone();
+
+        if let Some(template) = custom_template {
+            vm.custom_template_id = Some(sqlx::query("insert into vm_custom_template(cpu,memory,disk_size,disk_type,disk_interface,pricing_id,cpu_mfg,cpu_arch,cpu_features,disk_iops_read,disk_iops_write,disk_mbps_read,disk_mbps_write,network_mbps,cpu_limit,backup_retention) values(?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?) returning id")
+                .bind(template.cpu)
+                .bind(template.memory)
+                .bind(template.disk_size)
+                .bind(template.disk_type)
+                .bind(template.disk_interface)
+                .bind(template.pricing_id)
+                .bind(&template.cpu_mfg)
+                .bind(&template.cpu_arch)
+                .bind(&template.cpu_features)
+                .bind(template.disk_iops_read)
+                .bind(template.disk_iops_write)
+                .bind(template.disk_mbps_read)
+                .bind(template.disk_mbps_write)
+                .bind(template.network_mbps)
+                .bind(template.cpu_limit)
+                .bind(template.backup_retention)
+                .fetch_one(&mut *tx)
+                .await?
+                .try_get(0)?);
+        }
+
+        let subscription_id = sqlx::query(
+            "INSERT INTO subscription (user_id, company_id, name, description, created, expires, is_active, is_setup, currency, interval_amount, interval_type, setup_fee, auto_renewal_enabled, external_id) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"
+        )
+        .bind(subscription.user_id)
+        .bind(subscription.company_id)
+        .bind(&subscription.name)
+        .bind(&subscription.description)
+        .bind(subscription.created)
+        .bind(subscription.expires)
+        .bind(subscription.is_active)
+        .bind(subscription.is_setup)
+        .bind(&subscription.currency)
+        .bind(subscription.interval_amount)
+        .bind(subscription.interval_type)
+        .bind(subscription.setup_fee)
+        .bind(subscription.auto_renewal_enabled)
+        .bind(&subscription.external_id)
+        .execute(&mut *tx)
+        .await?
+        .last_insert_id();
+
+        vm.subscription_line_item_id = sqlx::query(
+            "INSERT INTO subscription_line_item (subscription_id, subscription_type, name, description, amount, setup_amount, configuration) VALUES (?, ?, ?, ?, ?, ?, ?)"
+        )
+        .bind(subscription_id)
+        .bind(line_item.subscription_type)
+        .bind(&line_item.name)
+        .bind(&line_item.description)
+        .bind(line_item.amount)
+        .bind(line_item.setup_amount)
+        .bind(&line_item.configuration)
+        .execute(&mut *tx)
+        .await?
+        .last_insert_id();
+
+        sqlx::query("insert into vm(id,host_id,user_id,image_id,template_id,custom_template_id,subscription_line_item_id,ssh_key_id,disk_id,mac_address,ref_code,private_network) values(?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)")
+            .bind(vm.id)
+            .bind(vm.host_id)
+            .bind(vm.user_id)
+            .bind(vm.image_id)
+            .bind(vm.template_id)
+            .bind(vm.custom_template_id)
+            .bind(vm.subscription_line_item_id)
+            .bind(vm.ssh_key_id)
+            .bind(vm.disk_id)
+            .bind(&vm.mac_address)
+            .bind(&vm.ref_code)
+            .bind(vm.private_network)
+            .execute(&mut *tx)
+            .await?;
+
+        for ip in ip_assignments {
+            sqlx::query(
+                "insert into vm_ip_assignment(vm_id,ip_range_id,ip,arp_ref,dns_forward,dns_forward_ref,dns_reverse,dns_reverse_ref,prefix_length,route_ref) values(?,?,?,?,?,?,?,?,?,?)",
+            )
+            .bind(vm.id)
+            .bind(ip.ip_range_id)
+            .bind(&ip.ip)
+            .bind(&ip.arp_ref)
+            .bind(&ip.dns_forward)
+            .bind(&ip.dns_forward_ref)
+            .bind(&ip.dns_reverse)
+            .bind(&ip.dns_reverse_ref)
+            .bind(ip.prefix_length)
+            .bind(&ip.route_ref)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+        Ok(vm)
+    }
+
     async fn delete_vm(&self, vm_id: u64) -> DbResult<()> {
         sqlx::query("update vm set deleted = 1, ssh_key_id = null where id = ?")
             .bind(vm_id)
@@ -1049,6 +1440,22 @@ impl LNVpsDbBase for LNVpsDbMysql {
             .bind(vm_id)
             .execute(&mut *tx)
             .await?;
+        sqlx::query("delete from vm_usage_alert where vm_id = ?")
+            .bind(vm_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("delete from vm_backup where vm_id = ?")
+            .bind(vm_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("delete from vm_backup_schedule where vm_id = ?")
+            .bind(vm_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("delete from vm_extra_disk where vm_id = ?")
+            .bind(vm_id)
+            .execute(&mut *tx)
+            .await?;
         sqlx::query("delete from vm_ip_assignment where vm_id = ?")
             .bind(vm_id)
             .execute(&mut *tx)
@@ -1089,30 +1496,138 @@ impl LNVpsDbBase for LNVpsDbMysql {
         .await?)
     }
 
-    async fn update_vm(&self, vm: &Vm) -> DbResult<()> {
-        sqlx::query(
-            "update vm set image_id=?,template_id=?,custom_template_id=?,subscription_line_item_id=?,ssh_key_id=?,disk_id=?,mac_address=?,disabled=?,fw_policy_in=?,fw_policy_out=?,admin_notes=? where id=?",
-        )
-            .bind(vm.image_id)
-            .bind(vm.template_id)
-            .bind(vm.custom_template_id)
-            .bind(vm.subscription_line_item_id)
-            .bind(vm.ssh_key_id)
-            .bind(vm.disk_id)
-            .bind(&vm.mac_address)
-            .bind(vm.disabled)
-            .bind(vm.fw_policy_in)
-            .bind(vm.fw_policy_out)
-            .bind(&vm.admin_notes)
-            .bind(vm.id)
-            .execute(&self.db)
-            .await?;
-        Ok(())
-    }
+    async fn transfer_vm(&self, vm_id: u64, new_user_id: u64) -> DbResult<()> {
+        let mut tx = self.db.begin().await?;
 
-    async fn get_vm_by_line_item(&self, line_item_id: u64) -> DbResult<Vm> {
-        Ok(
-            sqlx::query_as("SELECT * FROM vm WHERE subscription_line_item_id = ? AND deleted = 0")
+        // Resolve the subscription linked to this VM so we can move billing too
+        let sub_id: (u64,) = sqlx::query_as(
+            "SELECT s.id FROM subscription s \
+             JOIN subscription_line_item sli ON sli.subscription_id = s.id \
+             JOIN vm v ON v.subscription_line_item_id = sli.id \
+             WHERE v.id = ?",
+        )
+        .bind(vm_id)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        // Move VM ownership and drop the old owner's ssh key reference
+        sqlx::query("UPDATE vm SET user_id = ?, ssh_key_id = NULL WHERE id = ?")
+            .bind(new_user_id)
+            .bind(vm_id)
+            .execute(&mut *tx)
+            .await?;
+
+        // Move subscription ownership so renewals bill the new account
+        sqlx::query("UPDATE subscription SET user_id = ? WHERE id = ?")
+            .bind(new_user_id)
+            .bind(sub_id.0)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn update_vm(&self, vm: &Vm) -> DbResult<()> {
+        sqlx::query(
+            "update vm set image_id=?,template_id=?,custom_template_id=?,subscription_line_item_id=?,ssh_key_id=?,disk_id=?,mac_address=?,disabled=?,fw_policy_in=?,fw_policy_out=?,admin_notes=?,private_network=? where id=?",
+        )
+            .bind(vm.image_id)
+            .bind(vm.template_id)
+            .bind(vm.custom_template_id)
+            .bind(vm.subscription_line_item_id)
+            .bind(vm.ssh_key_id)
+            .bind(vm.disk_id)
+            .bind(&vm.mac_address)
+            .bind(vm.disabled)
+            .bind(vm.fw_policy_in)
+            .bind(vm.fw_policy_out)
+            .bind(&vm.admin_notes)
+            .bind(vm.private_network)
+            .bind(vm.id)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    async fn update_vm_host(&self, vm_id: u64, host_id: u64, disk_id: u64) -> DbResult<()> {
+        sqlx::query("update vm set host_id=?,disk_id=? where id=?")
+            .bind(host_id)
+            .bind(disk_id)
+            .bind(vm_id)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    async fn set_vm_suspended_at(&self, vm_id: u64, suspended_at: DateTime<Utc>) -> DbResult<()> {
+        sqlx::query("update vm set suspended_at=? where id=?")
+            .bind(suspended_at)
+            .bind(vm_id)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    async fn take_vm_suspended_at(&self, vm_id: u64) -> DbResult<Option<DateTime<Utc>>> {
+        let mut tx = self.db.begin().await?;
+        let suspended_at: Option<DateTime<Utc>> =
+            sqlx::query_scalar("select suspended_at from vm where id=? for update")
+                .bind(vm_id)
+                .fetch_one(&mut *tx)
+                .await?;
+        if suspended_at.is_some() {
+            sqlx::query("update vm set suspended_at=null where id=?")
+                .bind(vm_id)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+        Ok(suspended_at)
+    }
+
+    async fn upsert_vm_state(&self, vm_id: u64, state: VmStateKind) -> DbResult<()> {
+        sqlx::query(
+            "insert into vm_state(vm_id,state,last_seen) values(?,?,current_timestamp) on duplicate key update state=values(state),last_seen=current_timestamp",
+        )
+        .bind(vm_id)
+        .bind(state)
+        .execute(&self.db)
+        .await?;
+        Ok(())
+    }
+
+    async fn touch_vm_states(&self, vm_ids: &[u64]) -> DbResult<()> {
+        if vm_ids.is_empty() {
+            return Ok(());
+        }
+        let mut q =
+            QueryBuilder::new("update vm_state set last_seen=current_timestamp where vm_id in (");
+        let mut ids = q.separated(",");
+        for id in vm_ids {
+            ids.push_bind(*id);
+        }
+        q.push(")");
+        q.build().execute(&self.db).await?;
+        Ok(())
+    }
+
+    async fn list_vm_states(&self, vm_ids: &[u64]) -> DbResult<Vec<VmState>> {
+        if vm_ids.is_empty() {
+            return Ok(vec![]);
+        }
+        let mut q = QueryBuilder::new("select * from vm_state where vm_id in (");
+        let mut ids = q.separated(",");
+        for id in vm_ids {
+            ids.push_bind(*id);
+        }
+        q.push(")");
+        Ok(q.build_query_as().fetch_all(&self.db).await?)
+    }
+
+    async fn get_vm_by_line_item(&self, line_item_id: u64) -> DbResult<Vm> {
+        Ok(
+            sqlx::query_as("SELECT * FROM vm WHERE subscription_line_item_id = ? AND deleted = 0")
                 .bind(line_item_id)
                 .fetch_one(&self.db)
                 .await?,
@@ -1164,6 +1679,22 @@ impl LNVpsDbBase for LNVpsDbMysql {
         .await?)
     }
 
+    async fn list_paid_vm_subscription_payments(
+        &self,
+        vm_id: u64,
+    ) -> DbResult<Vec<SubscriptionPayment>> {
+        Ok(sqlx::query_as(
+            "SELECT sp.* FROM subscription_payment sp \
+             INNER JOIN subscription_line_item sli ON sli.subscription_id = sp.subscription_id \
+             INNER JOIN vm v ON v.subscription_line_item_id = sli.id \
+             WHERE v.id = ? AND sp.is_paid = 1 AND sp.created >= v.created \
+             ORDER BY COALESCE(sp.paid_at, sp.created), sp.created",
+        )
+        .bind(vm_id)
+        .fetch_all(&self.db)
+        .await?)
+    }
+
     async fn list_vm_subscription_payments_paginated(
         &self,
         vm_id: u64,
@@ -1181,6 +1712,35 @@ impl LNVpsDbBase for LNVpsDbMysql {
         .bind(vm_id)
         .bind(limit)
         .bind(offset)
+        .fetch_all(&self.read)
+        .await?)
+    }
+
+    async fn list_vm_subscription_payments_after(
+        &self,
+        vm_id: u64,
+        after: Option<(DateTime<Utc>, Vec<u8>)>,
+        limit: u64,
+    ) -> DbResult<Vec<SubscriptionPayment>> {
+        let (created, id) = match after {
+            Some((created, id)) => (Some(created), Some(id)),
+            None => (None, None),
+        };
+        Ok(sqlx::query_as(
+            "SELECT sp.* FROM subscription_payment sp \
+             INNER JOIN subscription_line_item sli ON sli.subscription_id = sp.subscription_id \
+             INNER JOIN vm v ON v.subscription_line_item_id = sli.id \
+             WHERE v.id = ? \
+             AND (? IS NULL OR sp.created < ? OR (sp.created = ? AND sp.id < ?)) \
+             ORDER BY sp.created DESC, sp.id DESC \
+             LIMIT ?",
+        )
+        .bind(vm_id)
+        .bind(created)
+        .bind(created)
+        .bind(created)
+        .bind(id)
+        .bind(limit)
         .fetch_all(&self.db)
         .await?)
     }
@@ -1193,14 +1753,14 @@ impl LNVpsDbBase for LNVpsDbMysql {
              WHERE v.id = ?",
         )
         .bind(vm_id)
-        .fetch_one(&self.db)
+        .fetch_one(&self.read)
         .await?;
         Ok(row.0 as u64)
     }
 
     async fn insert_vm_ip_assignment(&self, ip_assignment: &VmIpAssignment) -> DbResult<u64> {
         Ok(sqlx::query(
-            "insert into vm_ip_assignment(vm_id,ip_range_id,ip,arp_ref,dns_forward,dns_forward_ref,dns_reverse,dns_reverse_ref) values(?,?,?,?,?,?,?,?) returning id",
+            "insert into vm_ip_assignment(vm_id,ip_range_id,ip,arp_ref,dns_forward,dns_forward_ref,dns_reverse,dns_reverse_ref,prefix_length,route_ref) values(?,?,?,?,?,?,?,?,?,?) returning id",
         )
             .bind(ip_assignment.vm_id)
             .bind(ip_assignment.ip_range_id)
@@ -1210,6 +1770,8 @@ impl LNVpsDbBase for LNVpsDbMysql {
             .bind(&ip_assignment.dns_forward_ref)
             .bind(&ip_assignment.dns_reverse)
             .bind(&ip_assignment.dns_reverse_ref)
+            .bind(ip_assignment.prefix_length)
+            .bind(&ip_assignment.route_ref)
             .fetch_one(&self.db)
             .await?
             .try_get(0)?)
@@ -1217,13 +1779,14 @@ impl LNVpsDbBase for LNVpsDbMysql {
 
     async fn update_vm_ip_assignment(&self, ip_assignment: &VmIpAssignment) -> DbResult<()> {
         sqlx::query(
-            "update vm_ip_assignment set arp_ref = ?, dns_forward = ?, dns_forward_ref = ?, dns_reverse = ?, dns_reverse_ref = ? where id = ?",
+            "update vm_ip_assignment set arp_ref = ?, dns_forward = ?, dns_forward_ref = ?, dns_reverse = ?, dns_reverse_ref = ?, route_ref = ? where id = ?",
         )
             .bind(&ip_assignment.arp_ref)
             .bind(&ip_assignment.dns_forward)
             .bind(&ip_assignment.dns_forward_ref)
             .bind(&ip_assignment.dns_reverse)
             .bind(&ip_assignment.dns_reverse_ref)
+            .bind(&ip_assignment.route_ref)
             .bind(ip_assignment.id)
             .execute(&self.db)
             .await?;
@@ -1239,6 +1802,46 @@ impl LNVpsDbBase for LNVpsDbMysql {
         )
     }
 
+    async fn list_user_ip_assignments(
+        &self,
+        user_id: u64,
+    ) -> DbResult<Vec<(Vm, VmIpAssignment, IpRange)>> {
+        let vms: Vec<Vm> =
+            sqlx::query_as("SELECT * FROM vm WHERE user_id = ? AND deleted = false ORDER BY id")
+                .bind(user_id)
+                .fetch_all(&self.read)
+                .await?;
+        let ips: Vec<VmIpAssignment> = sqlx::query_as(
+            "SELECT a.* FROM vm_ip_assignment a \
+             JOIN vm v ON a.vm_id = v.id \
+             WHERE v.user_id = ? AND v.deleted = false AND a.deleted = false \
+             ORDER BY a.id",
+        )
+        .bind(user_id)
+        .fetch_all(&self.read)
+        .await?;
+        let ranges: Vec<IpRange> = sqlx::query_as(
+            "SELECT DISTINCT r.* FROM ip_range r \
+             JOIN vm_ip_assignment a ON a.ip_range_id = r.id \
+             JOIN vm v ON a.vm_id = v.id \
+             WHERE v.user_id = ? AND v.deleted = false AND a.deleted = false",
+        )
+        .bind(user_id)
+        .fetch_all(&self.read)
+        .await?;
+        let ranges: std::collections::HashMap<u64, IpRange> =
+            ranges.into_iter().map(|r| (r.id, r)).collect();
+        let mut ret = Vec::new();
+        for vm in vms {
+            for ip in ips.iter().filter(|a| a.vm_id == vm.id) {
+                if let Some(range) = ranges.get(&ip.ip_range_id) {
+                    ret.push((vm.clone(), ip.clone(), range.clone()));
+                }
+            }
+        }
+        Ok(ret)
+    }
+
     async fn list_vm_ip_assignments_in_range(
         &self,
         range_id: u64,
@@ -1275,6 +1878,50 @@ impl LNVpsDbBase for LNVpsDbMysql {
         Ok(())
     }
 
+    async fn find_duplicate_live_ips(&self) -> DbResult<Vec<(String, Vec<u64>)>> {
+        let rows: Vec<(String, u64)> = sqlx::query_as(
+            "select distinct a.ip, a.vm_id from vm_ip_assignment a \
+             join vm v on v.id = a.vm_id \
+             where a.deleted = 0 and v.deleted = 0 and a.ip in ( \
+                select a2.ip from vm_ip_assignment a2 \
+                join vm v2 on v2.id = a2.vm_id \
+                where a2.deleted = 0 and v2.deleted = 0 \
+                group by a2.ip having count(distinct a2.vm_id) > 1) \
+             order by a.ip, a.vm_id",
+        )
+        .fetch_all(&self.db)
+        .await?;
+        let mut ret: Vec<(String, Vec<u64>)> = Vec::new();
+        for (ip, vm_id) in rows {
+            match ret.last_mut() {
+                Some((last_ip, vms)) if *last_ip == ip => vms.push(vm_id),
+                _ => ret.push((ip, vec![vm_id])),
+            }
+        }
+        Ok(ret)
+    }
+
+    async fn get_data_migration_state(&self, name: &str) -> DbResult<Option<String>> {
+        Ok(
+            sqlx::query_scalar("select value from data_migration_state where name = ?")
+                .bind(name)
+                .fetch_optional(&self.db)
+                .await?,
+        )
+    }
+
+    async fn set_data_migration_state(&self, name: &str, value: &str) -> DbResult<()> {
+        sqlx::query(
+            "insert into data_migration_state(name, value) values(?, ?) \
+             on duplicate key update value = values(value)",
+        )
+        .bind(name)
+        .bind(value)
+        .execute(&self.db)
+        .await?;
+        Ok(())
+    }
+
     async fn insert_vm_firewall_rule(&self, rule: &VmFirewallRule) -> DbResult<u64> {
         Ok(sqlx::query(
             "insert into vm_firewall_rule(vm_id,priority,direction,protocol,action,src_cidr,dst_port_start,dst_port_end,enabled) values(?,?,?,?,?,?,?,?,?) returning id",
@@ -1293,45 +1940,427 @@ impl LNVpsDbBase for LNVpsDbMysql {
             .try_get(0)?)
     }
 
-    async fn get_vm_firewall_rule(&self, rule_id: u64) -> DbResult<VmFirewallRule> {
+    async fn get_vm_firewall_rule(&self, rule_id: u64) -> DbResult<VmFirewallRule> {
+        Ok(
+            sqlx::query_as("select * from vm_firewall_rule where id = ?")
+                .bind(rule_id)
+                .fetch_one(&self.db)
+                .await?,
+        )
+    }
+
+    async fn list_vm_firewall_rules(&self, vm_id: u64) -> DbResult<Vec<VmFirewallRule>> {
+        Ok(sqlx::query_as(
+            "select * from vm_firewall_rule where vm_id = ? order by priority asc, id asc",
+        )
+        .bind(vm_id)
+        .fetch_all(&self.db)
+        .await?)
+    }
+
+    async fn update_vm_firewall_rule(&self, rule: &VmFirewallRule) -> DbResult<()> {
+        sqlx::query(
+            "update vm_firewall_rule set priority=?, direction=?, protocol=?, action=?, src_cidr=?, dst_port_start=?, dst_port_end=?, enabled=? where id=?",
+        )
+            .bind(rule.priority)
+            .bind(rule.direction)
+            .bind(rule.protocol)
+            .bind(rule.action)
+            .bind(&rule.src_cidr)
+            .bind(rule.dst_port_start)
+            .bind(rule.dst_port_end)
+            .bind(rule.enabled)
+            .bind(rule.id)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    async fn delete_vm_firewall_rule(&self, rule_id: u64) -> DbResult<()> {
+        sqlx::query("delete from vm_firewall_rule where id = ?")
+            .bind(rule_id)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    async fn insert_vm_usage_alert(&self, alert: &VmUsageAlert) -> DbResult<u64> {
+        Ok(sqlx::query(
+            "insert into vm_usage_alert(vm_id,metric,threshold,window_seconds) values(?,?,?,?) returning id",
+        )
+        .bind(alert.vm_id)
+        .bind(alert.metric)
+        .bind(alert.threshold)
+        .bind(alert.window_seconds)
+        .fetch_one(&self.db)
+        .await?
+        .try_get(0)?)
+    }
+
+    async fn get_vm_usage_alert(&self, alert_id: u64) -> DbResult<VmUsageAlert> {
+        Ok(sqlx::query_as("select * from vm_usage_alert where id = ?")
+            .bind(alert_id)
+            .fetch_one(&self.db)
+            .await?)
+    }
+
+    async fn list_vm_usage_alerts(&self, vm_id: u64) -> DbResult<Vec<VmUsageAlert>> {
+        Ok(
+            sqlx::query_as("select * from vm_usage_alert where vm_id = ? order by id asc")
+                .bind(vm_id)
+                .fetch_all(&self.db)
+                .await?,
+        )
+    }
+
+    async fn list_active_vm_usage_alerts(&self) -> DbResult<Vec<VmUsageAlert>> {
+        Ok(sqlx::query_as(
+            "select a.* from vm_usage_alert a join vm v on v.id = a.vm_id where v.deleted = 0 order by a.vm_id, a.id",
+        )
+        .fetch_all(&self.db)
+        .await?)
+    }
+
+    async fn update_vm_usage_alert(&self, alert: &VmUsageAlert) -> DbResult<()> {
+        sqlx::query("update vm_usage_alert set metric=?, threshold=?, window_seconds=? where id=?")
+            .bind(alert.metric)
+            .bind(alert.threshold)
+            .bind(alert.window_seconds)
+            .bind(alert.id)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    async fn set_vm_usage_alert_triggered(
+        &self,
+        alert_id: u64,
+        triggered: DateTime<Utc>,
+    ) -> DbResult<()> {
+        sqlx::query("update vm_usage_alert set last_triggered=? where id=?")
+            .bind(triggered)
+            .bind(alert_id)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    async fn delete_vm_usage_alert(&self, alert_id: u64) -> DbResult<()> {
+        sqlx::query("delete from vm_usage_alert where id = ?")
+            .bind(alert_id)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_vm_backup_schedule(&self, vm_id: u64) -> DbResult<Option<VmBackupSchedule>> {
+        Ok(
+            sqlx::query_as("select * from vm_backup_schedule where vm_id = ?")
+                .bind(vm_id)
+                .fetch_optional(&self.db)
+                .await?,
+        )
+    }
+
+    async fn upsert_vm_backup_schedule(&self, schedule: &VmBackupSchedule) -> DbResult<()> {
+        sqlx::query(
+            "insert into vm_backup_schedule(vm_id,interval_hours,enabled) values(?,?,?) \
+            on duplicate key update interval_hours=values(interval_hours), enabled=values(enabled)",
+        )
+        .bind(schedule.vm_id)
+        .bind(schedule.interval_hours)
+        .bind(schedule.enabled)
+        .execute(&self.db)
+        .await?;
+        Ok(())
+    }
+
+    async fn delete_vm_backup_schedule(&self, vm_id: u64) -> DbResult<()> {
+        sqlx::query("delete from vm_backup_schedule where vm_id = ?")
+            .bind(vm_id)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    async fn list_active_vm_backup_schedules(&self) -> DbResult<Vec<VmBackupSchedule>> {
+        Ok(sqlx::query_as(
+            "select s.* from vm_backup_schedule s join vm v on v.id = s.vm_id where v.deleted = 0 and s.enabled = 1 order by s.vm_id",
+        )
+        .fetch_all(&self.db)
+        .await?)
+    }
+
+    async fn set_vm_backup_schedule_last_run(
+        &self,
+        vm_id: u64,
+        last_run: DateTime<Utc>,
+    ) -> DbResult<()> {
+        sqlx::query("update vm_backup_schedule set last_run=? where vm_id=?")
+            .bind(last_run)
+            .bind(vm_id)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    async fn insert_vm_backup(&self, backup: &VmBackup) -> DbResult<u64> {
+        Ok(
+            sqlx::query("insert into vm_backup(vm_id,volume_id,size) values(?,?,?) returning id")
+                .bind(backup.vm_id)
+                .bind(&backup.volume_id)
+                .bind(backup.size)
+                .fetch_one(&self.db)
+                .await?
+                .try_get(0)?,
+        )
+    }
+
+    async fn get_vm_backup(&self, backup_id: u64) -> DbResult<VmBackup> {
+        Ok(sqlx::query_as("select * from vm_backup where id = ?")
+            .bind(backup_id)
+            .fetch_one(&self.db)
+            .await?)
+    }
+
+    async fn list_vm_backups(&self, vm_id: u64) -> DbResult<Vec<VmBackup>> {
+        Ok(
+            sqlx::query_as(
+                "select * from vm_backup where vm_id = ? order by created desc, id desc",
+            )
+            .bind(vm_id)
+            .fetch_all(&self.db)
+            .await?,
+        )
+    }
+
+    async fn delete_vm_backup(&self, backup_id: u64) -> DbResult<()> {
+        sqlx::query("delete from vm_backup where id = ?")
+            .bind(backup_id)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_vm_bandwidth_usage(
+        &self,
+        vm_id: u64,
+        month: DateTime<Utc>,
+    ) -> DbResult<Option<VmBandwidthUsage>> {
+        Ok(
+            sqlx::query_as("select * from vm_bandwidth_usage where vm_id = ? and month = ?")
+                .bind(vm_id)
+                .bind(month)
+                .fetch_optional(&self.db)
+                .await?,
+        )
+    }
+
+    async fn get_vm_bandwidth_last_sample(&self, vm_id: u64) -> DbResult<Option<DateTime<Utc>>> {
+        Ok(
+            sqlx::query_scalar("select max(last_sample) from vm_bandwidth_usage where vm_id = ?")
+                .bind(vm_id)
+                .fetch_one(&self.db)
+                .await?,
+        )
+    }
+
+    async fn add_vm_bandwidth_usage(
+        &self,
+        usage: &VmBandwidthUsage,
+        counted_after: DateTime<Utc>,
+    ) -> DbResult<bool> {
+        let res = sqlx::query(
+            "update vm_bandwidth_usage set bytes_in = bytes_in + ?, bytes_out = bytes_out + ?, last_sample = ? \
+             where vm_id = ? and month = ? and last_sample <= ?",
+        )
+        .bind(usage.bytes_in)
+        .bind(usage.bytes_out)
+        .bind(usage.last_sample)
+        .bind(usage.vm_id)
+        .bind(usage.month)
+        .bind(counted_after)
+        .execute(&self.db)
+        .await?;
+        if res.rows_affected() == 1 {
+            return Ok(true);
+        }
+        // no row for the month yet, or another worker already counted these
+        // samples and the insert is ignored
+        let res = sqlx::query(
+            "insert ignore into vm_bandwidth_usage(vm_id,month,bytes_in,bytes_out,last_sample) values(?,?,?,?,?)",
+        )
+        .bind(usage.vm_id)
+        .bind(usage.month)
+        .bind(usage.bytes_in)
+        .bind(usage.bytes_out)
+        .bind(usage.last_sample)
+        .execute(&self.db)
+        .await?;
+        Ok(res.rows_affected() == 1)
+    }
+
+    async fn insert_vm_create_request(&self, user_id: u64, request_id: &str) -> DbResult<bool> {
+        // only a duplicate (user_id, request_id) means the id is taken, any
+        // other failure is returned as an error
+        match sqlx::query("insert into vm_create_requests(user_id,request_id) values(?,?)")
+            .bind(user_id)
+            .bind(request_id)
+            .execute(&self.db)
+            .await
+        {
+            Ok(_) => Ok(true),
+            Err(sqlx::Error::Database(e)) if e.is_unique_violation() => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn get_vm_create_request(
+        &self,
+        user_id: u64,
+        request_id: &str,
+    ) -> DbResult<VmCreateRequest> {
+        Ok(
+            sqlx::query_as("select * from vm_create_requests where user_id = ? and request_id = ?")
+                .bind(user_id)
+                .bind(request_id)
+                .fetch_one(&self.db)
+                .await?,
+        )
+    }
+
+    async fn set_vm_create_request_vm(
+        &self,
+        user_id: u64,
+        request_id: &str,
+        vm_id: u64,
+    ) -> DbResult<()> {
+        sqlx::query("update vm_create_requests set vm_id = ? where user_id = ? and request_id = ?")
+            .bind(vm_id)
+            .bind(user_id)
+            .bind(request_id)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    async fn delete_vm_create_request(&self, user_id: u64, request_id: &str) -> DbResult<()> {
+        sqlx::query("delete from vm_create_requests where user_id = ? and request_id = ?")
+            .bind(user_id)
+            .bind(request_id)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    async fn delete_stale_vm_create_requests(&self, before: DateTime<Utc>) -> DbResult<u64> {
+        Ok(
+            sqlx::query("delete from vm_create_requests where vm_id is null and created < ?")
+                .bind(before)
+                .execute(&self.db)
+                .await?
+                .rows_affected(),
+        )
+    }
+
+    async fn insert_vm_extra_disk(&self, disk: &VmExtraDisk) -> DbResult<u64> {
+        Ok(sqlx::query(
+            "insert into vm_extra_disk(vm_id,disk_id,size,slot) values(?,?,?,?) returning id",
+        )
+        .bind(disk.vm_id)
+        .bind(disk.disk_id)
+        .bind(disk.size)
+        .bind(disk.slot)
+        .fetch_one(&self.db)
+        .await?
+        .try_get(0)?)
+    }
+
+    async fn list_vm_extra_disks(&self, vm_id: u64) -> DbResult<Vec<VmExtraDisk>> {
         Ok(
-            sqlx::query_as("select * from vm_firewall_rule where id = ?")
-                .bind(rule_id)
-                .fetch_one(&self.db)
+            sqlx::query_as("select * from vm_extra_disk where vm_id = ? order by slot asc")
+                .bind(vm_id)
+                .fetch_all(&self.db)
                 .await?,
         )
     }
 
-    async fn list_vm_firewall_rules(&self, vm_id: u64) -> DbResult<Vec<VmFirewallRule>> {
+    async fn delete_vm_extra_disk(&self, disk_id: u64) -> DbResult<()> {
+        sqlx::query("delete from vm_extra_disk where id = ?")
+            .bind(disk_id)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    async fn lock_placement(&self, timeout: std::time::Duration) -> DbResult<crate::DbLock> {
+        // GET_LOCK is bound to the session, so hold the connection for the
+        // lifetime of the guard and close it on drop which releases the lock
+        let mut conn = self.db.acquire().await?;
+        let locked: Option<i64> = sqlx::query_scalar("select get_lock('lnvps_placement', ?)")
+            .bind(timeout.as_secs())
+            .fetch_one(conn.as_mut())
+            .await?;
+        if locked != Some(1) {
+            return Err(DbError::Other(anyhow!(
+                "Timed out waiting for the placement lock"
+            )));
+        }
+        conn.close_on_drop();
+        Ok(crate::DbLock::new(conn))
+    }
+
+    async fn insert_capacity_reservation(
+        &self,
+        reservation: &CapacityReservation,
+    ) -> DbResult<u64> {
+        sqlx::query("delete from capacity_reservation where expires < NOW()")
+            .execute(&self.db)
+            .await?;
+        Ok(sqlx::query(
+            "insert into capacity_reservation(host_id,disk_id,vm_id,cpu,memory,disk_size,expires) values(?,?,?,?,?,?,?) returning id",
+        )
+        .bind(reservation.host_id)
+        .bind(reservation.disk_id)
+        .bind(reservation.vm_id)
+        .bind(reservation.cpu)
+        .bind(reservation.memory)
+        .bind(reservation.disk_size)
+        .bind(reservation.expires)
+        .fetch_one(&self.db)
+        .await?
+        .try_get(0)?)
+    }
+
+    async fn list_capacity_reservations(&self, host_id: u64) -> DbResult<Vec<CapacityReservation>> {
         Ok(sqlx::query_as(
-            "select * from vm_firewall_rule where vm_id = ? order by priority asc, id asc",
+            "select * from capacity_reservation where host_id = ? and expires > NOW()",
         )
-        .bind(vm_id)
+        .bind(host_id)
         .fetch_all(&self.db)
         .await?)
     }
 
-    async fn update_vm_firewall_rule(&self, rule: &VmFirewallRule) -> DbResult<()> {
-        sqlx::query(
-            "update vm_firewall_rule set priority=?, direction=?, protocol=?, action=?, src_cidr=?, dst_port_start=?, dst_port_end=?, enabled=? where id=?",
-        )
-            .bind(rule.priority)
-            .bind(rule.direction)
-            .bind(rule.protocol)
-            .bind(rule.action)
-            .bind(&rule.src_cidr)
-            .bind(rule.dst_port_start)
-            .bind(rule.dst_port_end)
-            .bind(rule.enabled)
-            .bind(rule.id)
+    async fn set_capacity_reservation_vm(&self, id: u64, vm_id: u64) -> DbResult<()> {
+        sqlx::query("update capacity_reservation set vm_id = ? where id = ?")
+            .bind(vm_id)
+            .bind(id)
             .execute(&self.db)
             .await?;
         Ok(())
     }
 
-    async fn delete_vm_firewall_rule(&self, rule_id: u64) -> DbResult<()> {
-        sqlx::query("delete from vm_firewall_rule where id = ?")
-            .bind(rule_id)
+    async fn delete_capacity_reservation(&self, id: u64) -> DbResult<()> {
+        sqlx::query("delete from capacity_reservation where id = ?")
+            .bind(id)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    async fn delete_vm_capacity_reservations(&self, vm_id: u64) -> DbResult<()> {
+        sqlx::query("delete from capacity_reservation where vm_id = ?")
+            .bind(vm_id)
             .execute(&self.db)
             .await?;
         Ok(())
@@ -1396,7 +2425,7 @@ impl LNVpsDbBase for LNVpsDbMysql {
         if let Some(e) = enabled {
             count_q = count_q.bind(e);
         }
-        let total: i64 = count_q.fetch_one(&self.db).await?;
+        let total: i64 = count_q.fetch_one(&self.read).await?;
 
         // Build and execute data query
         let mut data_q = sqlx::query_as(&data_sql);
@@ -1407,7 +2436,7 @@ impl LNVpsDbBase for LNVpsDbMysql {
             data_q = data_q.bind(e);
         }
         data_q = data_q.bind(limit).bind(offset);
-        let rows = data_q.fetch_all(&self.db).await?;
+        let rows = data_q.fetch_all(&self.read).await?;
 
         Ok((rows, total as u64))
     }
@@ -1429,7 +2458,7 @@ impl LNVpsDbBase for LNVpsDbMysql {
     }
 
     async fn insert_custom_vm_template(&self, template: &VmCustomTemplate) -> DbResult<u64> {
-        Ok(sqlx::query("insert into vm_custom_template(cpu,memory,disk_size,disk_type,disk_interface,pricing_id,cpu_mfg,cpu_arch,cpu_features,disk_iops_read,disk_iops_write,disk_mbps_read,disk_mbps_write,network_mbps,cpu_limit) values(?,?,?,?,?,?,?,?,?,?,?,?,?,?,?) returning id")
+        Ok(sqlx::query("insert into vm_custom_template(cpu,memory,disk_size,disk_type,disk_interface,pricing_id,cpu_mfg,cpu_arch,cpu_features,disk_iops_read,disk_iops_write,disk_mbps_read,disk_mbps_write,network_mbps,cpu_limit,backup_retention) values(?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?) returning id")
             .bind(template.cpu)
             .bind(template.memory)
             .bind(template.disk_size)
@@ -1445,13 +2474,14 @@ impl LNVpsDbBase for LNVpsDbMysql {
             .bind(template.disk_mbps_write)
             .bind(template.network_mbps)
             .bind(template.cpu_limit)
+            .bind(template.backup_retention)
             .fetch_one(&self.db)
             .await?
             .try_get(0)?)
     }
 
     async fn update_custom_vm_template(&self, template: &VmCustomTemplate) -> DbResult<()> {
-        sqlx::query("update vm_custom_template set cpu=?, memory=?, disk_size=?, disk_type=?, disk_interface=?, pricing_id=?, cpu_mfg=?, cpu_arch=?, cpu_features=?, disk_iops_read=?, disk_iops_write=?, disk_mbps_read=?, disk_mbps_write=?, network_mbps=?, cpu_limit=? where id=?")
+        sqlx::query("update vm_custom_template set cpu=?, memory=?, disk_size=?, disk_type=?, disk_interface=?, pricing_id=?, cpu_mfg=?, cpu_arch=?, cpu_features=?, disk_iops_read=?, disk_iops_write=?, disk_mbps_read=?, disk_mbps_write=?, network_mbps=?, cpu_limit=?, backup_retention=? where id=?")
             .bind(template.cpu)
             .bind(template.memory)
             .bind(template.disk_size)
@@ -1467,6 +2497,7 @@ impl LNVpsDbBase for LNVpsDbMysql {
             .bind(template.disk_mbps_write)
             .bind(template.network_mbps)
             .bind(template.cpu_limit)
+            .bind(template.backup_retention)
             .bind(template.id)
             .execute(&self.db)
             .await?;
@@ -1533,11 +2564,11 @@ impl LNVpsDbBase for LNVpsDbMysql {
         )
         .bind(limit)
         .bind(offset)
-        .fetch_all(&self.db)
+        .fetch_all(&self.read)
         .await?;
 
         let total: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM dns_server")
-            .fetch_one(&self.db)
+            .fetch_one(&self.read)
             .await?;
 
         Ok((servers, total.0 as u64))
@@ -1895,6 +2926,31 @@ impl LNVpsDbBase for LNVpsDbMysql {
         .bind(vm_id)
         .bind(limit)
         .bind(offset)
+        .fetch_all(&self.read)
+        .await?)
+    }
+
+    async fn list_vm_history_after(
+        &self,
+        vm_id: u64,
+        after: Option<(DateTime<Utc>, u64)>,
+        limit: u64,
+    ) -> DbResult<Vec<VmHistory>> {
+        let (timestamp, id) = match after {
+            Some((timestamp, id)) => (Some(timestamp), Some(id)),
+            None => (None, None),
+        };
+        Ok(sqlx::query_as(
+            "select * from vm_history where vm_id = ? \
+             and (? is null or timestamp < ? or (timestamp = ? and id < ?)) \
+             order by timestamp desc, id desc limit ?",
+        )
+        .bind(vm_id)
+        .bind(timestamp)
+        .bind(timestamp)
+        .bind(timestamp)
+        .bind(id)
+        .bind(limit)
         .fetch_all(&self.db)
         .await?)
     }
@@ -1963,6 +3019,8 @@ impl LNVpsDbBase for LNVpsDbMysql {
                 u.billing_state,
                 u.billing_postcode,
                 u.billing_tax_id,
+                u.billing_tax_id_validated,
+                u.billing_tax_id_name,
                 u.geo_country_code,
                 u.geo_ip,
                 u.geo_updated
@@ -1997,6 +3055,188 @@ impl LNVpsDbBase for LNVpsDbMysql {
         Ok(user_ids)
     }
 
+    async fn insert_admin_audit_log(&self, entry: &AdminAuditLog) -> DbResult<u64> {
+        Ok(sqlx::query(
+            "insert into admin_audit_log(admin_user_id,action,detail) values(?,?,?) returning id",
+        )
+        .bind(entry.admin_user_id)
+        .bind(&entry.action)
+        .bind(&entry.detail)
+        .fetch_one(&self.db)
+        .await?
+        .try_get(0)?)
+    }
+
+    async fn insert_bulk_message_deliveries(
+        &self,
+        deliveries: &[BulkMessageDelivery],
+    ) -> DbResult<()> {
+        let mut tx = self.db.begin().await?;
+        for d in deliveries {
+            sqlx::query(
+                "insert into bulk_message_delivery(audit_log_id,user_id,channel,status,error) values(?,?,?,?,?)",
+            )
+            .bind(d.audit_log_id)
+            .bind(d.user_id)
+            .bind(&d.channel)
+            .bind(&d.status)
+            .bind(&d.error)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn list_active_system_notices(&self) -> DbResult<Vec<SystemNotice>> {
+        Ok(sqlx::query_as(
+            "SELECT * FROM system_notice WHERE starts <= NOW() AND (ends IS NULL OR ends > NOW()) \
+             ORDER BY severity DESC, starts DESC",
+        )
+        .fetch_all(&self.db)
+        .await?)
+    }
+
+    async fn list_feature_flags(&self) -> DbResult<Vec<FeatureFlag>> {
+        Ok(
+            sqlx::query_as("SELECT * FROM feature_flag ORDER BY `key`, region_id")
+                .fetch_all(&self.read)
+                .await?,
+        )
+    }
+
+    async fn enqueue_job(&self, job: &str) -> DbResult<u64> {
+        Ok(
+            sqlx::query("insert into work_queue(job) values(?) returning id")
+                .bind(job)
+                .fetch_one(&self.db)
+                .await?
+                .try_get(0)?,
+        )
+    }
+
+    async fn claim_next_job(
+        &self,
+        consumer: &str,
+        reclaim_after: TimeDelta,
+        max_attempts: u32,
+    ) -> DbResult<Option<WorkQueueItem>> {
+        let mut tx = self.db.begin().await?;
+        sqlx::query(
+            "insert into work_queue_consumer(name, last_poll) values(?, now()) \
+             on duplicate key update last_poll = now()",
+        )
+        .bind(consumer)
+        .execute(&mut *tx)
+        .await?;
+        // abandoned jobs out of attempts go to the dead letter state
+        sqlx::query(
+            "update work_queue set status = 2, attempts = attempts + 1, \
+             last_error = 'Abandoned by worker' \
+             where status = 1 and claimed_at < ? and attempts + 1 >= ?",
+        )
+        .bind(Utc::now() - reclaim_after)
+        .bind(max_attempts)
+        .execute(&mut *tx)
+        .await?;
+        // SKIP LOCKED so concurrent workers each get a different row instead
+        // of waiting on (and then double claiming) the same one
+        let item: Option<WorkQueueItem> = sqlx::query_as(
+            "select * from work_queue \
+             where (status = 0 and due <= now()) or (status = 1 and claimed_at < ?) \
+             order by id limit 1 for update skip locked",
+        )
+        .bind(Utc::now() - reclaim_after)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some(mut item) = item else {
+            tx.commit().await?;
+            return Ok(None);
+        };
+        let now = Utc::now();
+        // reclaiming an abandoned job counts as a failed attempt
+        if item.status == WorkQueueStatus::Claimed {
+            item.attempts += 1;
+        }
+        sqlx::query(
+            "update work_queue set status = 1, claimed_by = ?, claimed_at = ?, attempts = ? \
+             where id = ?",
+        )
+        .bind(consumer)
+        .bind(now)
+        .bind(item.attempts)
+        .bind(item.id)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        item.status = WorkQueueStatus::Claimed;
+        item.claimed_by = Some(consumer.to_string());
+        item.claimed_at = Some(now);
+        Ok(Some(item))
+    }
+
+    async fn complete_job(&self, id: u64) -> DbResult<()> {
+        sqlx::query("delete from work_queue where id = ?")
+            .bind(id)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    async fn renew_job_claim(&self, id: u64) -> DbResult<()> {
+        sqlx::query("update work_queue set claimed_at = ? where id = ? and status = 1")
+            .bind(Utc::now())
+            .bind(id)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    async fn fail_job(
+        &self,
+        id: u64,
+        error: &str,
+        retry_at: DateTime<Utc>,
+        max_attempts: u32,
+    ) -> DbResult<()> {
+        sqlx::query(
+            "update work_queue set status = if(attempts + 1 >= ?, 2, 0), claimed_by = null, \
+             claimed_at = null, attempts = attempts + 1, last_error = ?, due = ? where id = ?",
+        )
+        .bind(max_attempts)
+        .bind(error)
+        .bind(retry_at)
+        .bind(id)
+        .execute(&self.db)
+        .await?;
+        Ok(())
+    }
+
+    async fn list_work_queue(&self, limit: u64) -> DbResult<Vec<WorkQueueItem>> {
+        Ok(
+            sqlx::query_as("select * from work_queue where status != 2 order by id limit ?")
+                .bind(limit)
+                .fetch_all(&self.db)
+                .await?,
+        )
+    }
+
+    async fn delete_job(&self, id: u64) -> DbResult<bool> {
+        let res = sqlx::query("delete from work_queue where id = ?")
+            .bind(id)
+            .execute(&self.db)
+            .await?;
+        Ok(res.rows_affected() > 0)
+    }
+
+    async fn work_queue_last_poll(&self) -> DbResult<Option<DateTime<Utc>>> {
+        Ok(
+            sqlx::query_scalar("select max(last_poll) from work_queue_consumer")
+                .fetch_one(&self.db)
+                .await?,
+        )
+    }
+
     // ========================================================================
     // Subscription Billing System Implementations
     // ========================================================================
@@ -2029,7 +3269,7 @@ impl LNVpsDbBase for LNVpsDbMysql {
             let total: i64 =
                 sqlx::query_scalar("SELECT COUNT(*) FROM subscription WHERE user_id = ?")
                     .bind(uid)
-                    .fetch_one(&self.db)
+                    .fetch_one(&self.read)
                     .await?;
             let rows = sqlx::query_as(
                 "SELECT * FROM subscription WHERE user_id = ? ORDER BY id DESC LIMIT ? OFFSET ?",
@@ -2037,18 +3277,18 @@ impl LNVpsDbBase for LNVpsDbMysql {
             .bind(uid)
             .bind(limit)
             .bind(offset)
-            .fetch_all(&self.db)
+            .fetch_all(&self.read)
             .await?;
             (total, rows)
         } else {
             let total: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM subscription")
-                .fetch_one(&self.db)
+                .fetch_one(&self.read)
                 .await?;
             let rows =
                 sqlx::query_as("SELECT * FROM subscription ORDER BY id DESC LIMIT ? OFFSET ?")
                     .bind(limit)
                     .bind(offset)
-                    .fetch_all(&self.db)
+                    .fetch_all(&self.read)
                     .await?;
             (total, rows)
         };
@@ -2467,7 +3707,7 @@ impl LNVpsDbBase for LNVpsDbMysql {
             "SELECT COUNT(*) FROM subscription_payment WHERE subscription_id = ?",
         )
         .bind(subscription_id)
-        .fetch_one(&self.db)
+        .fetch_one(&self.read)
         .await?;
         let rows = sqlx::query_as(
             "SELECT * FROM subscription_payment WHERE subscription_id = ? ORDER BY created DESC LIMIT ? OFFSET ?",
@@ -2475,7 +3715,7 @@ impl LNVpsDbBase for LNVpsDbMysql {
         .bind(subscription_id)
         .bind(limit)
         .bind(offset)
-        .fetch_all(&self.db)
+        .fetch_all(&self.read)
         .await?;
         Ok((rows, total as u64))
     }
@@ -2614,74 +3854,105 @@ impl LNVpsDbBase for LNVpsDbMysql {
 
     async fn subscription_payment_paid(&self, payment: &SubscriptionPayment) -> DbResult<()> {
         let mut tx = self.db.begin().await?;
+        // Already paid (or unknown id) marks nothing; commit the no-op either way.
+        Self::mark_payment_paid(&mut tx, payment).await?;
+        tx.commit().await?;
+        Ok(())
+    }
 
-        // Mark payment as paid. The `AND is_paid = 0` guard makes this idempotent:
-        // duplicate webhook deliveries / replayed settle events affect 0 rows and
-        // are skipped below, so the subscription expiry is never extended twice.
-        let paid = sqlx::query(
-            "UPDATE subscription_payment SET is_paid = 1, external_data = ?, paid_at = NOW() WHERE id = ? AND is_paid = 0",
+    async fn last_paid_subscription_invoice(&self) -> DbResult<Option<SubscriptionPayment>> {
+        Ok(sqlx::query_as(
+            "SELECT * FROM subscription_payment WHERE is_paid = 1 ORDER BY created DESC LIMIT 1",
         )
-        .bind(&payment.external_data)
-        .bind(&payment.id)
-        .execute(tx.as_mut())
-        .await?;
+        .fetch_optional(&self.db)
+        .await?)
+    }
 
-        if paid.rows_affected() == 0 {
-            // Already paid (or unknown id) — nothing to extend, commit no-op.
-            tx.commit().await?;
-            return Ok(());
-        }
+    async fn list_expired_subscription_payments(
+        &self,
+        limit: u64,
+    ) -> DbResult<Vec<SubscriptionPayment>> {
+        Ok(sqlx::query_as(
+            "SELECT * FROM subscription_payment WHERE is_paid = 0 AND is_canceled = 0 AND expires < NOW() ORDER BY expires LIMIT ?",
+        )
+        .bind(limit)
+        .fetch_all(&self.db)
+        .await?)
+    }
 
-        // Un-delete any VM linked to this subscription (e.g. auto-cleaned up before
-        // payment arrived). This handles payment methods with longer timeouts.
-        sqlx::query(
-            "UPDATE vm SET deleted = 0 WHERE subscription_line_item_id IN (SELECT id FROM subscription_line_item WHERE subscription_id = ?)",
+    async fn cancel_subscription_payment(&self, id: &Vec<u8>) -> DbResult<()> {
+        sqlx::query("UPDATE subscription_payment SET is_canceled = 1 WHERE id = ? AND is_paid = 0")
+            .bind(id)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    async fn pay_subscription_payment_with_credit(
+        &self,
+        payment: &SubscriptionPayment,
+    ) -> DbResult<bool> {
+        let mut tx = self.db.begin().await?;
+        if !Self::mark_payment_paid(&mut tx, payment).await? {
+            tx.rollback().await?;
+            return Ok(false);
+        }
+        // Conditional debit: affects 0 rows when the balance is too low, which
+        // also rolls back the payment above.
+        let total = payment.amount + payment.tax + payment.processing_fee;
+        let debit = sqlx::query(
+            "UPDATE user_credit SET amount = amount - ? WHERE user_id = ? AND currency = ? AND amount >= ?",
         )
-        .bind(payment.subscription_id)
+        .bind(total)
+        .bind(payment.user_id)
+        .bind(&payment.currency)
+        .bind(total)
         .execute(tx.as_mut())
         .await?;
-
-        if let Some(time_value) = payment.time_value {
-            // Extend subscription.expires by explicit time_value seconds
-            sqlx::query(
-                "UPDATE subscription SET expires = DATE_ADD(GREATEST(COALESCE(expires, NOW()), NOW()), INTERVAL ? SECOND), is_active = 1, is_setup = 1 WHERE id = ?",
-            )
-            .bind(time_value)
-            .bind(payment.subscription_id)
-            .execute(tx.as_mut())
-            .await?;
-        } else {
-            // Regular subscription path: read interval from the subscription itself
-            let sub: Subscription = sqlx::query_as("SELECT * FROM subscription WHERE id = ?")
-                .bind(payment.subscription_id)
-                .fetch_one(tx.as_mut())
-                .await?;
-            let interval_sql = match sub.interval_type {
-                IntervalType::Day => "DAY",
-                IntervalType::Month => "MONTH",
-                IntervalType::Year => "YEAR",
-            };
-            let sql = format!(
-                "UPDATE subscription SET expires = DATE_ADD(GREATEST(COALESCE(expires, NOW()), NOW()), INTERVAL ? {}), is_active = 1, is_setup = 1 WHERE id = ?",
-                interval_sql
-            );
-            sqlx::query(&sql)
-                .bind(sub.interval_amount)
-                .bind(payment.subscription_id)
-                .execute(tx.as_mut())
-                .await?;
+        if debit.rows_affected() == 0 {
+            tx.rollback().await?;
+            return Ok(false);
         }
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    async fn claim_subscription_auto_renewal(
+        &self,
+        subscription_id: u64,
+        period_expires: DateTime<Utc>,
+    ) -> DbResult<bool> {
+        let res = sqlx::query(
+            "INSERT IGNORE INTO subscription_auto_renewal (subscription_id, period_expires) VALUES (?, ?)",
+        )
+        .bind(subscription_id)
+        .bind(period_expires)
+        .execute(&self.db)
+        .await?;
+        Ok(res.rows_affected() == 1)
+    }
 
-        tx.commit().await?;
-        Ok(())
+    async fn get_user_credit(&self, user_id: u64, currency: &str) -> DbResult<u64> {
+        let amount: Option<(u64,)> =
+            sqlx::query_as("SELECT amount FROM user_credit WHERE user_id = ? AND currency = ?")
+                .bind(user_id)
+                .bind(currency)
+                .fetch_optional(&self.db)
+                .await?;
+        Ok(amount.map(|a| a.0).unwrap_or(0))
     }
 
-    async fn last_paid_subscription_invoice(&self) -> DbResult<Option<SubscriptionPayment>> {
-        Ok(sqlx::query_as(
-            "SELECT * FROM subscription_payment WHERE is_paid = 1 ORDER BY created DESC LIMIT 1",
+    async fn add_user_credit(&self, user_id: u64, currency: &str, amount: u64) -> DbResult<()> {
+        sqlx::query(
+            "INSERT INTO user_credit (user_id, currency, amount) VALUES (?, ?, ?) \
+             ON DUPLICATE KEY UPDATE amount = amount + VALUES(amount)",
         )
-        .fetch_optional(&self.db)
-        .await?)
+        .bind(user_id)
+        .bind(currency)
+        .bind(amount)
+        .execute(&self.db)
+        .await?;
+        Ok(())
     }
 
     // Available IP Space
@@ -2733,7 +4004,7 @@ impl LNVpsDbBase for LNVpsDbMysql {
         if let Some(v) = registry {
             count_q = count_q.bind(v);
         }
-        let total: i64 = count_q.fetch_one(&self.db).await?;
+        let total: i64 = count_q.fetch_one(&self.read).await?;
 
         let mut data_q = sqlx::query_as(&data_sql);
         if let Some(v) = is_available {
@@ -2746,7 +4017,7 @@ impl LNVpsDbBase for LNVpsDbMysql {
             data_q = data_q.bind(v);
         }
         data_q = data_q.bind(limit).bind(offset);
-        let rows = data_q.fetch_all(&self.db).await?;
+        let rows = data_q.fetch_all(&self.read).await?;
 
         Ok((rows, total as u64))
     }
@@ -2840,7 +4111,7 @@ impl LNVpsDbBase for LNVpsDbMysql {
             "SELECT COUNT(*) FROM ip_space_pricing WHERE available_ip_space_id = ?",
         )
         .bind(available_ip_space_id)
-        .fetch_one(&self.db)
+        .fetch_one(&self.read)
         .await?;
         let rows = sqlx::query_as(
             "SELECT * FROM ip_space_pricing WHERE available_ip_space_id = ? ORDER BY id DESC LIMIT ? OFFSET ?",
@@ -2848,7 +4119,7 @@ impl LNVpsDbBase for LNVpsDbMysql {
         .bind(available_ip_space_id)
         .bind(limit)
         .bind(offset)
-        .fetch_all(&self.db)
+        .fetch_all(&self.read)
         .await?;
         Ok((rows, total as u64))
     }
@@ -2995,7 +4266,7 @@ impl LNVpsDbBase for LNVpsDbMysql {
         if let Some(a) = is_active {
             count_q = count_q.bind(a);
         }
-        let total: i64 = count_q.fetch_one(&self.db).await?;
+        let total: i64 = count_q.fetch_one(&self.read).await?;
 
         let mut data_q = sqlx::query_as(&data_sql).bind(available_ip_space_id);
         if let Some(u) = user_id {
@@ -3005,7 +4276,7 @@ impl LNVpsDbBase for LNVpsDbMysql {
             data_q = data_q.bind(a);
         }
         data_q = data_q.bind(limit).bind(offset);
-        let rows = data_q.fetch_all(&self.db).await?;
+        let rows = data_q.fetch_all(&self.read).await?;
 
         Ok((rows, total as u64))
     }
@@ -3143,14 +4414,14 @@ impl LNVpsDbBase for LNVpsDbMysql {
         if let Some(s) = status {
             count_q = count_q.bind(s);
         }
-        let total: i64 = count_q.fetch_one(&self.db).await?;
+        let total: i64 = count_q.fetch_one(&self.read).await?;
 
         let mut data_q = sqlx::query_as(&data_sql);
         if let Some(s) = status {
             data_q = data_q.bind(s);
         }
         data_q = data_q.bind(limit).bind(offset);
-        let rows = data_q.fetch_all(&self.db).await?;
+        let rows = data_q.fetch_all(&self.read).await?;
 
         Ok((rows, total as u64))
     }
@@ -3239,14 +4510,14 @@ impl LNVpsDbBase for LNVpsDbMysql {
         offset: u64,
     ) -> DbResult<(Vec<PaymentMethodConfig>, u64)> {
         let total: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM payment_method_config")
-            .fetch_one(&self.db)
+            .fetch_one(&self.read)
             .await?;
         let rows = sqlx::query_as(
             "SELECT * FROM payment_method_config ORDER BY company_id, payment_method, name LIMIT ? OFFSET ?",
         )
         .bind(limit)
         .bind(offset)
-        .fetch_all(&self.db)
+        .fetch_all(&self.read)
         .await?;
         Ok((rows, total as u64))
     }
@@ -3560,7 +4831,7 @@ impl LNVpsDbBase for LNVpsDbMysql {
         .bind(code)
         .bind(limit)
         .bind(offset)
-        .fetch_all(&self.db)
+        .fetch_all(&self.read)
         .await?;
 
         // Total row count mirrors the same joins/filters as the page query.
@@ -3582,7 +4853,7 @@ impl LNVpsDbBase for LNVpsDbMysql {
              WHERE v.ref_code = ?",
         )
         .bind(code)
-        .fetch_one(&self.db)
+        .fetch_one(&self.read)
         .await?;
 
         Ok((rows, total as u64))
@@ -3601,7 +4872,7 @@ impl LNVpsDbBase for LNVpsDbMysql {
                )",
         )
         .bind(code)
-        .fetch_one(&self.db)
+        .fetch_one(&self.read)
         .await?;
         Ok(count as u64)
     }
@@ -3868,6 +5139,20 @@ impl LNVPSNostrDb for LNVpsDbMysql {
         )
     }
 
+    async fn resolve_handle(&self, domain_id: u64, name: &str) -> DbResult<NostrDomainHandle> {
+        // the exact name sorts before the default
+        Ok(sqlx::query_as(
+            "select * from nostr_domain_handle where domain_id=? and handle in (?, ?) \
+             order by handle = ? limit 1",
+        )
+        .bind(domain_id)
+        .bind(name)
+        .bind(NostrDomainHandle::DEFAULT)
+        .bind(NostrDomainHandle::DEFAULT)
+        .fetch_one(&self.db)
+        .await?)
+    }
+
     async fn insert_handle(&self, handle: &NostrDomainHandle) -> DbResult<u64> {
         Ok(
             sqlx::query(
@@ -4167,14 +5452,14 @@ impl AdminDb for LNVpsDbMysql {
         offset: u64,
     ) -> DbResult<(Vec<AdminRole>, u64)> {
         let total: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM admin_roles")
-            .fetch_one(&self.db)
+            .fetch_one(&self.read)
             .await?;
         let rows = sqlx::query_as(
             "SELECT * FROM admin_roles ORDER BY is_system_role DESC, name ASC LIMIT ? OFFSET ?",
         )
         .bind(limit)
         .bind(offset)
-        .fetch_all(&self.db)
+        .fetch_all(&self.read)
         .await?;
         Ok((rows, total as u64))
     }
@@ -4407,6 +5692,8 @@ impl AdminDb for LNVpsDbMysql {
                 u.billing_state,
                 u.billing_postcode,
                 u.billing_tax_id,
+                u.billing_tax_id_validated,
+                u.billing_tax_id_name,
                 u.geo_country_code,
                 u.geo_ip,
                 u.geo_updated,
@@ -4451,7 +5738,7 @@ impl AdminDb for LNVpsDbMysql {
         let users = query_builder
             .bind(limit)
             .bind(offset)
-            .fetch_all(&self.db)
+            .fetch_all(&self.read)
             .await?;
 
         // Get total count
@@ -4468,7 +5755,7 @@ impl AdminDb for LNVpsDbMysql {
             count_query_builder = count_query_builder.bind(role.clone());
         }
 
-        let total = count_query_builder.fetch_one(&self.db).await? as u64;
+        let total = count_query_builder.fetch_one(&self.read).await? as u64;
 
         Ok((users, total))
     }
@@ -4504,6 +5791,8 @@ impl AdminDb for LNVpsDbMysql {
                 u.billing_state,
                 u.billing_postcode,
                 u.billing_tax_id,
+                u.billing_tax_id_validated,
+                u.billing_tax_id_name,
                 u.geo_country_code,
                 u.geo_ip,
                 u.geo_updated,
@@ -4539,7 +5828,7 @@ impl AdminDb for LNVpsDbMysql {
     async fn admin_list_regions(&self, limit: u64, offset: u64) -> DbResult<(Vec<Region>, u64)> {
         // Get total count
         let total: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM region")
-            .fetch_one(&self.db)
+            .fetch_one(&self.read)
             .await?;
 
         // Get paginated results
@@ -4547,7 +5836,7 @@ impl AdminDb for LNVpsDbMysql {
             sqlx::query_as::<_, Region>("SELECT * FROM region ORDER BY name LIMIT ? OFFSET ?")
                 .bind(limit)
                 .bind(offset)
-                .fetch_all(&self.db)
+                .fetch_all(&self.read)
                 .await?;
 
         Ok((regions, total as u64))
@@ -4572,13 +5861,18 @@ impl AdminDb for LNVpsDbMysql {
     }
 
     async fn admin_update_region(&self, region: &Region) -> DbResult<()> {
-        sqlx::query("UPDATE region SET name = ?, enabled = ?, company_id = ? WHERE id = ?")
-            .bind(&region.name)
-            .bind(region.enabled)
-            .bind(region.company_id)
-            .bind(region.id)
-            .execute(&self.db)
-            .await?;
+        sqlx::query(
+            "UPDATE region SET name = ?, enabled = ?, company_id = ?, maintenance = ?, reachability_check = ?, forward_dns_suffix = ? WHERE id = ?",
+        )
+        .bind(&region.name)
+        .bind(region.enabled)
+        .bind(region.company_id)
+        .bind(region.maintenance)
+        .bind(region.reachability_check)
+        .bind(&region.forward_dns_suffix)
+        .bind(region.id)
+        .execute(&self.db)
+        .await?;
 
         Ok(())
     }
@@ -4645,7 +5939,7 @@ impl AdminDb for LNVpsDbMysql {
         .bind(region_id)
         .bind(region_id)
         .bind(region_id)
-        .fetch_one(&self.db)
+        .fetch_one(&self.read)
         .await?;
 
         Ok(RegionStats {
@@ -4658,35 +5952,7 @@ impl AdminDb for LNVpsDbMysql {
     }
 
     async fn admin_transfer_vm(&self, vm_id: u64, new_user_id: u64) -> DbResult<()> {
-        let mut tx = self.db.begin().await?;
-
-        // Resolve the subscription linked to this VM so we can move billing too
-        let sub_id: (u64,) = sqlx::query_as(
-            "SELECT s.id FROM subscription s \
-             JOIN subscription_line_item sli ON sli.subscription_id = s.id \
-             JOIN vm v ON v.subscription_line_item_id = sli.id \
-             WHERE v.id = ?",
-        )
-        .bind(vm_id)
-        .fetch_one(&mut *tx)
-        .await?;
-
-        // Move VM ownership and drop the old owner's ssh key reference
-        sqlx::query("UPDATE vm SET user_id = ?, ssh_key_id = NULL WHERE id = ?")
-            .bind(new_user_id)
-            .bind(vm_id)
-            .execute(&mut *tx)
-            .await?;
-
-        // Move subscription ownership so renewals bill the new account
-        sqlx::query("UPDATE subscription SET user_id = ? WHERE id = ?")
-            .bind(new_user_id)
-            .bind(sub_id.0)
-            .execute(&mut *tx)
-            .await?;
-
-        tx.commit().await?;
-        Ok(())
+        self.transfer_vm(vm_id, new_user_id).await
     }
 
     async fn admin_list_vm_os_images(
@@ -4700,12 +5966,12 @@ impl AdminDb for LNVpsDbMysql {
         )
         .bind(limit)
         .bind(offset)
-        .fetch_all(&self.db)
+        .fetch_all(&self.read)
         .await?;
 
         // Get total count
         let total_count: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM vm_os_image")
-            .fetch_one(&self.db)
+            .fetch_one(&self.read)
             .await?;
 
         Ok((images, total_count.0 as u64))
@@ -4721,8 +5987,8 @@ impl AdminDb for LNVpsDbMysql {
     async fn admin_create_vm_os_image(&self, image: &VmOsImage) -> DbResult<u64> {
         let result = sqlx::query(
             r#"
-            INSERT INTO vm_os_image (distribution, flavour, version, enabled, release_date, url, cpu_arch, default_username)
-            VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+            INSERT INTO vm_os_image (distribution, flavour, version, enabled, release_date, url, cpu_arch, default_username, image_kind)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
             "#
         )
         .bind(image.distribution as u16)
@@ -4733,6 +5999,7 @@ impl AdminDb for LNVpsDbMysql {
         .bind(&image.url)
         .bind(image.cpu_arch as u16)
         .bind(&image.default_username)
+        .bind(image.image_kind as u16)
         .execute(&self.db)
         .await?;
 
@@ -4743,7 +6010,7 @@ impl AdminDb for LNVpsDbMysql {
         sqlx::query(
             r#"
             UPDATE vm_os_image 
-            SET distribution = ?, flavour = ?, version = ?, enabled = ?, release_date = ?, url = ?, cpu_arch = ?, default_username = ?, sha2 = ?, sha2_url = ?
+            SET distribution = ?, flavour = ?, version = ?, enabled = ?, release_date = ?, url = ?, cpu_arch = ?, default_username = ?, sha2 = ?, sha2_url = ?, image_kind = ?
             WHERE id = ?
             "#
         )
@@ -4757,6 +6024,7 @@ impl AdminDb for LNVpsDbMysql {
         .bind(&image.default_username)
         .bind(&image.sha2)
         .bind(&image.sha2_url)
+        .bind(image.image_kind as u16)
         .bind(image.id)
         .execute(&self.db)
         .await?;
@@ -4765,10 +6033,12 @@ impl AdminDb for LNVpsDbMysql {
     }
 
     async fn admin_delete_vm_os_image(&self, image_id: u64) -> DbResult<()> {
+        let mut tx = self.db.begin().await?;
+
         // Check if the image is referenced by any VMs
         let vm_count: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM vm WHERE image_id = ?")
             .bind(image_id)
-            .fetch_one(&self.db)
+            .fetch_one(&mut *tx)
             .await?;
 
         if vm_count.0 > 0 {
@@ -4781,11 +6051,37 @@ impl AdminDb for LNVpsDbMysql {
             ));
         }
 
+        // An empty allowlist offers every image, so removing a region's only
+        // allowed image would silently open the region up to all of them
+        let sole_regions: Vec<u64> = sqlx::query_scalar(
+            "SELECT r.region_id FROM region_os_image r \
+             WHERE r.image_id = ? AND \
+             (SELECT COUNT(*) FROM region_os_image o WHERE o.region_id = r.region_id) = 1 \
+             FOR UPDATE",
+        )
+        .bind(image_id)
+        .fetch_all(&mut *tx)
+        .await?;
+        if !sole_regions.is_empty() {
+            return Err(DbError::Source(
+                anyhow!(
+                    "Cannot delete VM OS image: it is the only image allowed in region(s) {:?}",
+                    sole_regions
+                )
+                .into_boxed_dyn_error(),
+            ));
+        }
+
+        sqlx::query("DELETE FROM region_os_image WHERE image_id = ?")
+            .bind(image_id)
+            .execute(&mut *tx)
+            .await?;
         sqlx::query("DELETE FROM vm_os_image WHERE id = ?")
             .bind(image_id)
-            .execute(&self.db)
+            .execute(&mut *tx)
             .await?;
 
+        tx.commit().await?;
         Ok(())
     }
 
@@ -4800,12 +6096,12 @@ impl AdminDb for LNVpsDbMysql {
         )
         .bind(limit)
         .bind(offset)
-        .fetch_all(&self.db)
+        .fetch_all(&self.read)
         .await?;
 
         // Get total count
         let total: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM vm_template")
-            .fetch_one(&self.db)
+            .fetch_one(&self.read)
             .await?;
 
         Ok((templates, total.0))
@@ -4818,7 +6114,8 @@ impl AdminDb for LNVpsDbMysql {
                disk_size = ?, disk_type = ?, disk_interface = ?, 
                cost_plan_id = ?, region_id = ?,
                disk_iops_read = ?, disk_iops_write = ?, disk_mbps_read = ?, disk_mbps_write = ?,
-               network_mbps = ?, cpu_limit = ?
+               network_mbps = ?, cpu_limit = ?, max_per_user = ?, bandwidth_quota = ?,
+               backup_retention = ?
                WHERE id = ?"#,
         )
         .bind(&template.name)
@@ -4840,6 +6137,9 @@ impl AdminDb for LNVpsDbMysql {
         .bind(template.disk_mbps_write)
         .bind(template.network_mbps)
         .bind(template.cpu_limit)
+        .bind(template.max_per_user)
+        .bind(template.bandwidth_quota)
+        .bind(template.backup_retention)
         .bind(template.id)
         .execute(&self.db)
         .await?;
@@ -4871,7 +6171,7 @@ impl AdminDb for LNVpsDbMysql {
         let total: i64 = sqlx::query_scalar(
             "SELECT COUNT(*) FROM vm_host h JOIN region hr ON h.region_id = hr.id",
         )
-        .fetch_one(&self.db)
+        .fetch_one(&self.read)
         .await?;
 
         // Get paginated results with region info and active VM count (including disabled hosts)
@@ -4895,7 +6195,7 @@ impl AdminDb for LNVpsDbMysql {
         )
         .bind(limit)
         .bind(offset)
-        .fetch_all(&self.db)
+        .fetch_all(&self.read)
         .await?;
 
         // Fetch disk information for each host
@@ -4903,7 +6203,7 @@ impl AdminDb for LNVpsDbMysql {
             let disks: Vec<VmHostDisk> =
                 sqlx::query_as("SELECT * FROM vm_host_disk WHERE host_id = ? ORDER BY name")
                     .bind(host.host.id)
-                    .fetch_all(&self.db)
+                    .fetch_all(&self.read)
                     .await?;
 
             host.disks = disks;
@@ -5057,7 +6357,7 @@ impl AdminDb for LNVpsDbMysql {
             "SELECT COUNT(*) FROM vm_custom_template WHERE pricing_id = ?",
         )
         .bind(pricing_id)
-        .fetch_one(&self.db)
+        .fetch_one(&self.read)
         .await?;
 
         let templates = sqlx::query_as::<_, VmCustomTemplate>(
@@ -5066,7 +6366,7 @@ impl AdminDb for LNVpsDbMysql {
         .bind(pricing_id)
         .bind(limit)
         .bind(offset)
-        .fetch_all(&self.db)
+        .fetch_all(&self.read)
         .await?;
 
         Ok((templates, total as u64))
@@ -5163,11 +6463,11 @@ impl AdminDb for LNVpsDbMysql {
         )
         .bind(limit)
         .bind(offset)
-        .fetch_all(&self.db)
+        .fetch_all(&self.read)
         .await?;
 
         let total = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM company")
-            .fetch_one(&self.db)
+            .fetch_one(&self.read)
             .await?;
 
         Ok((companies, total as u64))
@@ -5184,8 +6484,8 @@ impl AdminDb for LNVpsDbMysql {
 
     async fn admin_create_company(&self, company: &Company) -> DbResult<u64> {
         let result = sqlx::query(
-            r#"INSERT INTO company (name, address_1, address_2, city, state, country_code, tax_id, postcode, phone, email, created, base_currency, referral_rate, max_prepay_days)
-               VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, NOW(), ?, ?, ?)"#,
+            r#"INSERT INTO company (name, address_1, address_2, city, state, country_code, tax_id, postcode, phone, email, created, base_currency, referral_rate, max_prepay_days, tax_display)
+               VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, NOW(), ?, ?, ?, ?)"#,
         )
         .bind(&company.name)
         .bind(&company.address_1)
@@ -5200,6 +6500,7 @@ impl AdminDb for LNVpsDbMysql {
             .bind(&company.base_currency)
         .bind(company.referral_rate)
         .bind(company.max_prepay_days)
+        .bind(company.tax_display)
         .execute(&self.db)
         .await?;
 
@@ -5210,7 +6511,7 @@ impl AdminDb for LNVpsDbMysql {
         sqlx::query(
             r#"UPDATE company SET 
                name = ?, address_1 = ?, address_2 = ?, city = ?, state = ?, 
-               country_code = ?, tax_id = ?, postcode = ?, phone = ?, email = ?, base_currency = ?, referral_rate = ?, max_prepay_days = ?
+               country_code = ?, tax_id = ?, postcode = ?, phone = ?, email = ?, base_currency = ?, referral_rate = ?, max_prepay_days = ?, tax_display = ?
                WHERE id = ?"#,
         )
         .bind(&company.name)
@@ -5226,6 +6527,7 @@ impl AdminDb for LNVpsDbMysql {
         .bind(&company.base_currency)
         .bind(company.referral_rate)
         .bind(company.max_prepay_days)
+        .bind(company.tax_display)
         .bind(company.id)
         .execute(&self.db)
         .await?;
@@ -5304,7 +6606,7 @@ impl AdminDb for LNVpsDbMysql {
 
         Ok(query
             .build_query_as::<SubscriptionPaymentWithCompany>()
-            .fetch_all(&self.db)
+            .fetch_all(&self.read)
             .await?)
     }
 
@@ -5357,7 +6659,7 @@ impl AdminDb for LNVpsDbMysql {
             db_query = db_query.bind(code);
         }
 
-        Ok(db_query.fetch_all(&self.db).await?)
+        Ok(db_query.fetch_all(&self.read).await?)
     }
 
     async fn admin_list_referrals(
@@ -5400,8 +6702,12 @@ impl AdminDb for LNVpsDbMysql {
             list_q = list_q.bind(like.clone());
             count_q = count_q.bind(like.clone());
         }
-        let rows = list_q.bind(limit).bind(offset).fetch_all(&self.db).await?;
-        let total = count_q.fetch_one(&self.db).await? as u64;
+        let rows = list_q
+            .bind(limit)
+            .bind(offset)
+            .fetch_all(&self.read)
+            .await?;
+        let total = count_q.fetch_one(&self.read).await? as u64;
         Ok((rows, total))
     }
 
@@ -5426,13 +6732,13 @@ impl AdminDb for LNVpsDbMysql {
             .bind(region_id)
             .bind(limit)
             .bind(offset)
-            .fetch_all(&self.db)
+            .fetch_all(&self.read)
             .await?;
 
             let total =
                 sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM ip_range WHERE region_id = ?")
                     .bind(region_id)
-                    .fetch_one(&self.db)
+                    .fetch_one(&self.read)
                     .await?;
 
             (ip_ranges, total)
@@ -5443,11 +6749,11 @@ impl AdminDb for LNVpsDbMysql {
             )
             .bind(limit)
             .bind(offset)
-            .fetch_all(&self.db)
+            .fetch_all(&self.read)
             .await?;
 
             let total = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM ip_range")
-                .fetch_one(&self.db)
+                .fetch_one(&self.read)
                 .await?;
 
             (ip_ranges, total)
@@ -5467,8 +6773,8 @@ impl AdminDb for LNVpsDbMysql {
 
     async fn admin_create_ip_range(&self, ip_range: &IpRange) -> DbResult<u64> {
         let result = sqlx::query(
-            r#"INSERT INTO ip_range (cidr, gateway, enabled, region_id, reverse_zone_id, access_policy_id, allocation_mode, use_full_range, forward_dns_server_id, reverse_dns_server_id, forward_zone_id)
-               VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"#,
+            r#"INSERT INTO ip_range (cidr, gateway, enabled, region_id, reverse_zone_id, access_policy_id, allocation_mode, use_full_range, forward_dns_server_id, reverse_dns_server_id, forward_zone_id, delegated_prefix)
+               VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"#,
         )
         .bind(&ip_range.cidr)
         .bind(&ip_range.gateway)
@@ -5481,6 +6787,7 @@ impl AdminDb for LNVpsDbMysql {
         .bind(ip_range.forward_dns_server_id)
         .bind(ip_range.reverse_dns_server_id)
         .bind(&ip_range.forward_zone_id)
+        .bind(ip_range.delegated_prefix)
         .execute(&self.db)
         .await?;
 
@@ -5492,7 +6799,8 @@ impl AdminDb for LNVpsDbMysql {
             r#"UPDATE ip_range SET 
                cidr = ?, gateway = ?, enabled = ?, region_id = ?, 
                reverse_zone_id = ?, access_policy_id = ?, allocation_mode = ?, use_full_range = ?,
-               forward_dns_server_id = ?, reverse_dns_server_id = ?, forward_zone_id = ?
+               forward_dns_server_id = ?, reverse_dns_server_id = ?, forward_zone_id = ?,
+               delegated_prefix = ?
                WHERE id = ?"#,
         )
         .bind(&ip_range.cidr)
@@ -5506,6 +6814,7 @@ impl AdminDb for LNVpsDbMysql {
         .bind(ip_range.forward_dns_server_id)
         .bind(ip_range.reverse_dns_server_id)
         .bind(&ip_range.forward_zone_id)
+        .bind(ip_range.delegated_prefix)
         .bind(ip_range.id)
         .execute(&self.db)
         .await?;
@@ -5545,10 +6854,37 @@ impl AdminDb for LNVpsDbMysql {
         Ok(count as u64)
     }
 
+    async fn list_vms_in_ip_range(&self, ip_range_id: u64) -> DbResult<Vec<(Vm, User)>> {
+        let vms: Vec<Vm> = sqlx::query_as(
+            "SELECT DISTINCT v.* FROM vm v \
+             JOIN vm_ip_assignment a ON a.vm_id = v.id \
+             WHERE a.ip_range_id = ? AND a.deleted = false AND v.deleted = false \
+             ORDER BY v.id",
+        )
+        .bind(ip_range_id)
+        .fetch_all(&self.read)
+        .await?;
+        let users: Vec<User> = sqlx::query_as(
+            "SELECT DISTINCT u.* FROM users u \
+             JOIN vm v ON v.user_id = u.id \
+             JOIN vm_ip_assignment a ON a.vm_id = v.id \
+             WHERE a.ip_range_id = ? AND a.deleted = false AND v.deleted = false",
+        )
+        .bind(ip_range_id)
+        .fetch_all(&self.read)
+        .await?;
+        let users: std::collections::HashMap<u64, User> =
+            users.into_iter().map(|u| (u.id, u)).collect();
+        Ok(vms
+            .into_iter()
+            .filter_map(|vm| users.get(&vm.user_id).map(|u| (vm, u.clone())))
+            .collect())
+    }
+
     async fn admin_list_access_policies(&self) -> DbResult<Vec<AccessPolicy>> {
         Ok(
             sqlx::query_as::<_, AccessPolicy>("SELECT * FROM access_policy ORDER BY name")
-                .fetch_all(&self.db)
+                .fetch_all(&self.read)
                 .await?,
         )
     }
@@ -5563,11 +6899,11 @@ impl AdminDb for LNVpsDbMysql {
         )
         .bind(limit)
         .bind(offset)
-        .fetch_all(&self.db)
+        .fetch_all(&self.read)
         .await?;
 
         let total = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM access_policy")
-            .fetch_one(&self.db)
+            .fetch_one(&self.read)
             .await?;
 
         Ok((access_policies, total as u64))
@@ -5651,7 +6987,7 @@ impl AdminDb for LNVpsDbMysql {
     async fn admin_list_routers(&self) -> DbResult<Vec<Router>> {
         Ok(
             sqlx::query_as::<_, Router>("SELECT * FROM router ORDER BY name")
-                .fetch_all(&self.db)
+                .fetch_all(&self.read)
                 .await?,
         )
     }
@@ -5665,11 +7001,11 @@ impl AdminDb for LNVpsDbMysql {
             sqlx::query_as::<_, Router>("SELECT * FROM router ORDER BY name LIMIT ? OFFSET ?")
                 .bind(limit)
                 .bind(offset)
-                .fetch_all(&self.db)
+                .fetch_all(&self.read)
                 .await?;
 
         let total: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM router")
-            .fetch_one(&self.db)
+            .fetch_one(&self.read)
             .await?;
 
         Ok((routers, total.0 as u64))
@@ -5759,6 +7095,7 @@ impl AdminDb for LNVpsDbMysql {
         host_id: Option<u64>,
         pubkey: Option<&str>,
         region_id: Option<u64>,
+        state: Option<VmStateKind>,
         include_deleted: Option<bool>,
     ) -> DbResult<(Vec<crate::Vm>, u64)> {
         // Resolve user_id from pubkey if provided
@@ -5767,7 +7104,7 @@ impl AdminDb for LNVpsDbMysql {
             let user_result: Result<(u64,), _> =
                 sqlx::query_as("SELECT id FROM users WHERE pubkey = UNHEX(?)")
                     .bind(pk)
-                    .fetch_one(&self.db)
+                    .fetch_one(&self.read)
                     .await;
 
             match user_result {
@@ -5779,7 +7116,8 @@ impl AdminDb for LNVpsDbMysql {
         };
 
         // Build queries using query builder
-        let base_from = "vm v LEFT JOIN vm_host h ON v.host_id = h.id";
+        let base_from =
+            "vm v LEFT JOIN vm_host h ON v.host_id = h.id LEFT JOIN vm_state s ON s.vm_id = v.id";
 
         // Start with the base query
         let mut count_query = sqlx::QueryBuilder::new("SELECT COUNT(*) FROM ");
@@ -5830,6 +7168,20 @@ impl AdminDb for LNVpsDbMysql {
             data_query.push("h.region_id = ").push_bind(rid);
         }
 
+        if let Some(state) = state {
+            if !has_conditions {
+                count_query.push(" WHERE ");
+                data_query.push(" WHERE ");
+                has_conditions = true;
+            } else {
+                count_query.push(" AND ");
+                data_query.push(" AND ");
+            }
+            // VMs never seen by the worker have no row and count as unknown
+            count_query.push("COALESCE(s.state, 0) = ").push_bind(state);
+            data_query.push("COALESCE(s.state, 0) = ").push_bind(state);
+        }
+
         // Handle deleted filter
         match include_deleted {
             Some(false) | None => {
@@ -5850,7 +7202,10 @@ impl AdminDb for LNVpsDbMysql {
         }
 
         // Execute count query
-        let total: i64 = count_query.build_query_scalar().fetch_one(&self.db).await?;
+        let total: i64 = count_query
+            .build_query_scalar()
+            .fetch_one(&self.read)
+            .await?;
 
         // Add ordering and pagination to data query
         data_query
@@ -5860,7 +7215,7 @@ impl AdminDb for LNVpsDbMysql {
             .push_bind(offset);
 
         // Execute data query
-        let vms: Vec<Vm> = data_query.build_query_as().fetch_all(&self.db).await?;
+        let vms: Vec<Vm> = data_query.build_query_as().fetch_all(&self.read).await?;
 
         Ok((vms, total as u64))
     }
@@ -5986,7 +7341,7 @@ impl AdminDb for LNVpsDbMysql {
         }
 
         let result = sqlx::query(
-            "INSERT INTO vm_ip_assignment (vm_id, ip_range_id, ip, deleted, arp_ref, dns_forward, dns_forward_ref, dns_reverse, dns_reverse_ref) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)"
+            "INSERT INTO vm_ip_assignment (vm_id, ip_range_id, ip, deleted, arp_ref, dns_forward, dns_forward_ref, dns_reverse, dns_reverse_ref, prefix_length, route_ref) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"
         )
         .bind(assignment.vm_id)
         .bind(assignment.ip_range_id)
@@ -5997,6 +7352,8 @@ impl AdminDb for LNVpsDbMysql {
         .bind(&assignment.dns_forward_ref)
         .bind(&assignment.dns_reverse)
         .bind(&assignment.dns_reverse_ref)
+        .bind(assignment.prefix_length)
+        .bind(&assignment.route_ref)
         .execute(&self.db)
         .await?;
 
@@ -6024,7 +7381,7 @@ impl AdminDb for LNVpsDbMysql {
         }
 
         sqlx::query(
-            "UPDATE vm_ip_assignment SET vm_id = ?, ip_range_id = ?, ip = ?, arp_ref = ?, dns_forward = ?, dns_forward_ref = ?, dns_reverse = ?, dns_reverse_ref = ? WHERE id = ?"
+            "UPDATE vm_ip_assignment SET vm_id = ?, ip_range_id = ?, ip = ?, arp_ref = ?, dns_forward = ?, dns_forward_ref = ?, dns_reverse = ?, dns_reverse_ref = ?, prefix_length = ?, route_ref = ? WHERE id = ?"
         )
         .bind(assignment.vm_id)
         .bind(assignment.ip_range_id)
@@ -6034,6 +7391,8 @@ impl AdminDb for LNVpsDbMysql {
         .bind(&assignment.dns_forward_ref)
         .bind(&assignment.dns_reverse)
         .bind(&assignment.dns_reverse_ref)
+        .bind(assignment.prefix_length)
+        .bind(&assignment.route_ref)
         .bind(assignment.id)
         .execute(&self.db)
         .await?;
@@ -6069,7 +7428,7 @@ impl AdminDb for LNVpsDbMysql {
         .bind(resource_id)
         .bind(limit)
         .bind(offset)
-        .fetch_all(&self.db)
+        .fetch_all(&self.read)
         .await?;
 
         let total: (i64,) = sqlx::query_as(
@@ -6081,7 +7440,7 @@ impl AdminDb for LNVpsDbMysql {
         .bind(resource_type)
         .bind(resource_id)
         .bind(resource_id)
-        .fetch_one(&self.db)
+        .fetch_one(&self.read)
         .await?;
 
         Ok((rows, total.0 as u64))
@@ -6097,7 +7456,7 @@ impl AdminDb for LNVpsDbMysql {
         )
         .bind(resource_type)
         .bind(resource_id)
-        .fetch_all(&self.db)
+        .fetch_all(&self.read)
         .await?)
     }
 
@@ -6184,7 +7543,321 @@ impl AdminDb for LNVpsDbMysql {
         .bind(start)
         .bind(start)
         .bind(end)
-        .fetch_all(&self.db)
+        .fetch_all(&self.read)
         .await?)
     }
+
+    async fn admin_list_audit_log(
+        &self,
+        limit: u64,
+        offset: u64,
+    ) -> DbResult<(Vec<AdminAuditLog>, u64)> {
+        let total: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM admin_audit_log")
+            .fetch_one(&self.read)
+            .await?;
+        let rows = sqlx::query_as(
+            "SELECT * FROM admin_audit_log ORDER BY created DESC, id DESC LIMIT ? OFFSET ?",
+        )
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&self.read)
+        .await?;
+        Ok((rows, total as u64))
+    }
+
+    async fn admin_list_system_notices(
+        &self,
+        limit: u64,
+        offset: u64,
+    ) -> DbResult<(Vec<SystemNotice>, u64)> {
+        let total: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM system_notice")
+            .fetch_one(&self.read)
+            .await?;
+        let rows = sqlx::query_as(
+            "SELECT * FROM system_notice ORDER BY starts DESC, id DESC LIMIT ? OFFSET ?",
+        )
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&self.read)
+        .await?;
+        Ok((rows, total as u64))
+    }
+
+    async fn admin_get_system_notice(&self, id: u64) -> DbResult<SystemNotice> {
+        Ok(sqlx::query_as("SELECT * FROM system_notice WHERE id = ?")
+            .bind(id)
+            .fetch_one(&self.db)
+            .await?)
+    }
+
+    async fn admin_insert_system_notice(&self, notice: &SystemNotice) -> DbResult<u64> {
+        let result = sqlx::query(
+            "INSERT INTO system_notice (message, severity, starts, ends, regions) VALUES (?, ?, ?, ?, ?)",
+        )
+        .bind(&notice.message)
+        .bind(notice.severity)
+        .bind(notice.starts)
+        .bind(notice.ends)
+        .bind(&notice.regions)
+        .execute(&self.db)
+        .await?;
+        Ok(result.last_insert_id())
+    }
+
+    async fn admin_update_system_notice(&self, notice: &SystemNotice) -> DbResult<()> {
+        sqlx::query(
+            "UPDATE system_notice SET message = ?, severity = ?, starts = ?, ends = ?, regions = ? WHERE id = ?",
+        )
+        .bind(&notice.message)
+        .bind(notice.severity)
+        .bind(notice.starts)
+        .bind(notice.ends)
+        .bind(&notice.regions)
+        .bind(notice.id)
+        .execute(&self.db)
+        .await?;
+        Ok(())
+    }
+
+    async fn admin_delete_system_notice(&self, id: u64) -> DbResult<()> {
+        sqlx::query("DELETE FROM system_notice WHERE id = ?")
+            .bind(id)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    async fn admin_get_feature_flag(&self, id: u64) -> DbResult<FeatureFlag> {
+        Ok(sqlx::query_as("SELECT * FROM feature_flag WHERE id = ?")
+            .bind(id)
+            .fetch_one(&self.db)
+            .await?)
+    }
+
+    async fn admin_insert_feature_flag(&self, flag: &FeatureFlag) -> DbResult<u64> {
+        let result =
+            sqlx::query("INSERT INTO feature_flag (`key`, region_id, enabled) VALUES (?, ?, ?)")
+                .bind(&flag.key)
+                .bind(flag.region_id)
+                .bind(flag.enabled)
+                .execute(&self.db)
+                .await?;
+        Ok(result.last_insert_id())
+    }
+
+    async fn admin_update_feature_flag(&self, flag: &FeatureFlag) -> DbResult<()> {
+        sqlx::query("UPDATE feature_flag SET `key` = ?, region_id = ?, enabled = ? WHERE id = ?")
+            .bind(&flag.key)
+            .bind(flag.region_id)
+            .bind(flag.enabled)
+            .bind(flag.id)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    async fn admin_delete_feature_flag(&self, id: u64) -> DbResult<()> {
+        sqlx::query("DELETE FROM feature_flag WHERE id = ?")
+            .bind(id)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    async fn admin_list_incidents(
+        &self,
+        limit: u64,
+        offset: u64,
+    ) -> DbResult<(Vec<Incident>, u64)> {
+        let total: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM incident")
+            .fetch_one(&self.read)
+            .await?;
+        let rows = sqlx::query_as(
+            "SELECT * FROM incident ORDER BY started DESC, id DESC LIMIT ? OFFSET ?",
+        )
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&self.read)
+        .await?;
+        Ok((rows, total as u64))
+    }
+
+    async fn admin_get_incident(&self, id: u64) -> DbResult<Incident> {
+        Ok(sqlx::query_as("SELECT * FROM incident WHERE id = ?")
+            .bind(id)
+            .fetch_one(&self.db)
+            .await?)
+    }
+
+    async fn admin_insert_incident(&self, incident: &Incident) -> DbResult<u64> {
+        let result = sqlx::query(
+            "INSERT INTO incident (region_id, ip_range_id, message, started, ended, admin_user_id, notified) VALUES (?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(incident.region_id)
+        .bind(incident.ip_range_id)
+        .bind(&incident.message)
+        .bind(incident.started)
+        .bind(incident.ended)
+        .bind(incident.admin_user_id)
+        .bind(incident.notified)
+        .execute(&self.db)
+        .await?;
+        Ok(result.last_insert_id())
+    }
+
+    async fn admin_update_incident(&self, incident: &Incident) -> DbResult<()> {
+        sqlx::query(
+            "UPDATE incident SET message = ?, started = ?, ended = ?, notified = ? WHERE id = ?",
+        )
+        .bind(&incident.message)
+        .bind(incident.started)
+        .bind(incident.ended)
+        .bind(incident.notified)
+        .bind(incident.id)
+        .execute(&self.db)
+        .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::mysql::MySqlPoolOptions;
+    use std::time::Duration;
+
+    fn is_pool_closed<T>(r: &DbResult<T>) -> bool {
+        matches!(r, Err(DbError::SqlxError(sqlx::Error::PoolClosed)))
+    }
+
+    #[tokio::test]
+    async fn test_read_methods_use_replica() -> anyhow::Result<()> {
+        // the primary is closed so anything routed to it fails with PoolClosed,
+        // the replica is unreachable so it fails with a different error
+        let primary = MySqlPool::connect_lazy("mysql://root@127.0.0.1:1/primary")?;
+        primary.close().await;
+        let replica = MySqlPoolOptions::new()
+            .acquire_timeout(Duration::from_millis(200))
+            .connect_lazy("mysql://root@127.0.0.1:1/replica")?;
+        let db = LNVpsDbMysql {
+            db: primary,
+            read: replica,
+        };
+
+        assert!(!is_pool_closed(&db.count_users().await));
+        assert!(!is_pool_closed(&db.list_users_paginated(10, 0).await));
+        assert!(is_pool_closed(&db.upsert_user(&[0; 32]).await));
+        assert!(is_pool_closed(&db.get_user(1).await));
+
+        // forced to primary
+        assert!(is_pool_closed(&db.primary().count_users().await));
+        Ok(())
+    }
+
+    /// Concurrent settlements through the real counter table get unique, gap
+    /// free invoice numbers. Needs a migrated MySQL database in `LNVPS_TEST_DB`,
+    /// run with `cargo test -p lnvps_db -- --ignored`.
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    #[ignore = "requires a MySQL database (LNVPS_TEST_DB)"]
+    async fn test_invoice_numbers_concurrent() -> anyhow::Result<()> {
+        let db = LNVpsDbMysql::new(&std::env::var("LNVPS_TEST_DB")?).await?;
+        db.migrate().await?;
+
+        // a fresh company so the counter starts at 1
+        let company_id = sqlx::query("insert into company (name, email) values (?, ?)")
+            .bind("invoice-test")
+            .bind("invoice-test@example.com")
+            .execute(&db.db)
+            .await?
+            .last_insert_id();
+        let random_id = || {
+            let mut id = [0u8; 32];
+            id[..16].copy_from_slice(uuid::Uuid::new_v4().as_bytes());
+            id[16..].copy_from_slice(uuid::Uuid::new_v4().as_bytes());
+            id
+        };
+        let user_id = db.upsert_user(&random_id()).await?;
+        let subscription_id = db
+            .insert_subscription(&Subscription {
+                id: 0,
+                user_id,
+                company_id,
+                name: "invoice-test".to_string(),
+                description: None,
+                created: Utc::now(),
+                expires: None,
+                is_active: true,
+                is_setup: true,
+                currency: "EUR".to_string(),
+                interval_amount: 1,
+                interval_type: IntervalType::Month,
+                setup_fee: 0,
+                auto_renewal_enabled: false,
+                external_id: None,
+            })
+            .await?;
+
+        let mut payments = Vec::new();
+        for _ in 0..20 {
+            let payment = SubscriptionPayment {
+                id: random_id().to_vec(),
+                subscription_id,
+                user_id,
+                created: Utc::now(),
+                expires: Utc::now() + TimeDelta::hours(1),
+                amount: 1000,
+                currency: "EUR".to_string(),
+                payment_method: PaymentMethod::Revolut,
+                payment_type: crate::SubscriptionPaymentType::Renewal,
+                external_data: "".to_string().into(),
+                external_id: None,
+                is_paid: false,
+                rate: 1.0,
+                time_value: Some(86400),
+                metadata: None,
+                tax: 0,
+                processing_fee: 0,
+                paid_at: None,
+                tax_rate: None,
+                tax_country_code: None,
+                tax_treatment: None,
+                tax_evidence: None,
+                tax_breakdown: None,
+                invoice_number: None,
+            };
+            db.insert_subscription_payment(&payment).await?;
+            payments.push(payment);
+        }
+
+        let db = std::sync::Arc::new(db);
+        let tasks: Vec<_> = payments
+            .iter()
+            .cloned()
+            .map(|p| {
+                let db = db.clone();
+                tokio::spawn(async move { db.subscription_payment_paid(&p).await })
+            })
+            .collect();
+        for t in tasks {
+            t.await??;
+        }
+        // settling again doesn't use up a number
+        db.subscription_payment_paid(&payments[0]).await?;
+
+        let mut numbers = Vec::new();
+        for p in &payments {
+            numbers.push(db.get_subscription_payment(&p.id).await?.invoice_number);
+        }
+        numbers.sort();
+        let year = chrono::Datelike::year(&Utc::now()) as u64;
+        let expected: Vec<Option<String>> = (1..=20)
+            .map(|seq| {
+                Some(SubscriptionPayment::format_invoice_number(
+                    company_id, year, seq,
+                ))
+            })
+            .collect();
+        assert_eq!(numbers, expected);
+        Ok(())
+    }
 }
@@ -10,6 +10,10 @@ pub trait LNVPSNostrDb: Sync + Send {
     async fn get_handle_by_name(&self, domain_id: u64, handle: &str)
     -> DbResult<NostrDomainHandle>;
 
+    /// Get the handle `name`, or the domain's default handle when `name` has
+    /// no handle of its own, in one lookup
+    async fn resolve_handle(&self, domain_id: u64, name: &str) -> DbResult<NostrDomainHandle>;
+
     /// Insert a new handle
     async fn insert_handle(&self, handle: &NostrDomainHandle) -> DbResult<u64>;
 
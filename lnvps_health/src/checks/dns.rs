@@ -9,6 +9,7 @@ use std::net::IpAddr;
 use std::time::{Duration, Instant};
 
 use super::{CheckResult, HealthCheck};
+use crate::schedule::CheckSchedule;
 
 /// Configuration for a DNS check
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -28,6 +29,9 @@ pub struct DnsCheckConfig {
     /// Query timeout in seconds
     #[serde(default = "default_timeout")]
     pub timeout_secs: u64,
+    /// Interval overrides for this check
+    #[serde(flatten)]
+    pub schedule: CheckSchedule,
 }
 
 fn default_timeout() -> u64 {
@@ -210,6 +214,10 @@ impl HealthCheck for DnsCheck {
             self.family_suffix()
         )
     }
+
+    fn schedule(&self) -> CheckSchedule {
+        self.config.schedule
+    }
 }
 
 #[cfg(test)]
@@ -225,6 +233,7 @@ mod tests {
             query: "google.com".to_string(),
             expected_ips: vec![],
             timeout_secs: 5,
+            schedule: Default::default(),
         };
 
         let check = DnsCheck::new(config, DnsAddrFamily::V4);
@@ -248,6 +257,7 @@ mod tests {
             query: "google.com".to_string(),
             expected_ips: vec![],
             timeout_secs: 5,
+            schedule: Default::default(),
         };
 
         let check = DnsCheck::new(config, DnsAddrFamily::V6);
@@ -272,6 +282,7 @@ mod tests {
             query: "one.one.one.one".to_string(),
             expected_ips: vec!["1.1.1.1".to_string()],
             timeout_secs: 5,
+            schedule: Default::default(),
         };
 
         let checks = DnsCheck::from_config(config);
@@ -1,8 +1,10 @@
+use crate::schedule::CheckSchedule;
 use anyhow::Result;
 use async_trait::async_trait;
 
 pub mod dns;
 pub mod mss;
+pub mod tcp;
 
 /// Result of a health check
 #[derive(Debug, Clone)]
@@ -68,4 +70,9 @@ pub trait HealthCheck: Send + Sync {
 
     /// Get a unique identifier for this check (used for alert cooldown tracking)
     fn id(&self) -> String;
+
+    /// When this check runs, the global interval is used by default
+    fn schedule(&self) -> CheckSchedule {
+        CheckSchedule::default()
+    }
 }
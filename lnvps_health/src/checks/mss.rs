@@ -8,6 +8,7 @@ use std::os::unix::io::AsRawFd;
 use std::time::Duration;
 
 use super::{CheckResult, HealthCheck};
+use crate::schedule::CheckSchedule;
 
 /// Configuration for an MSS probe target
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -25,6 +26,9 @@ pub struct MssCheckConfig {
     /// Expected minimum MSS for IPv6 (default: 1440, 20 bytes less than IPv4 due to larger header)
     #[serde(default = "default_expected_mss_v6")]
     pub expected_mss_v6: Option<u16>,
+    /// Interval overrides for this check
+    #[serde(flatten)]
+    pub schedule: CheckSchedule,
 }
 
 fn default_expected_mss() -> u16 {
@@ -220,6 +224,10 @@ impl HealthCheck for MssCheck {
             self.family_suffix()
         )
     }
+
+    fn schedule(&self) -> CheckSchedule {
+        self.config.schedule
+    }
 }
 
 /// Result of an MSS probe
@@ -364,6 +372,7 @@ mod tests {
             port: 443,
             expected_mss: 1000,
             expected_mss_v6: None,
+            schedule: Default::default(),
         };
 
         let check = MssCheck::new(config, AddrFamily::V4);
@@ -386,6 +395,7 @@ mod tests {
             port: 443,
             expected_mss: 1000,
             expected_mss_v6: Some(980),
+            schedule: Default::default(),
         };
 
         let check = MssCheck::new(config, AddrFamily::V6);
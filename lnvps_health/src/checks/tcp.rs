@@ -0,0 +1,279 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
+
+use super::mss::AddrFamily;
+use super::{CheckResult, HealthCheck};
+use crate::schedule::CheckSchedule;
+
+/// Address families to probe
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TcpFamilies {
+    /// Probe both, a family without an address is skipped
+    #[default]
+    Both,
+    /// Only probe IPv4, the check fails when the host has no IPv4 address
+    V4,
+    /// Only probe IPv6, the check fails when the host has no IPv6 address
+    V6,
+}
+
+/// Configuration for a TCP connect check
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct TcpConnectCheckConfig {
+    /// Human-readable name for this target
+    pub name: String,
+    /// Target address (IP or hostname)
+    pub host: String,
+    /// Target port
+    pub port: u16,
+    /// Connect timeout in seconds
+    #[serde(default = "default_timeout")]
+    pub timeout_secs: u64,
+    /// Address families to probe (default: both)
+    #[serde(default)]
+    pub family: TcpFamilies,
+    /// Interval overrides for this check
+    #[serde(flatten)]
+    pub schedule: CheckSchedule,
+}
+
+fn default_timeout() -> u64 {
+    5
+}
+
+/// TCP connect health check - verifies a TCP connection can be opened and
+/// measures how long the handshake takes
+pub struct TcpConnectCheck {
+    config: TcpConnectCheckConfig,
+    addr_family: AddrFamily,
+}
+
+impl TcpConnectCheck {
+    pub fn new(config: TcpConnectCheckConfig, addr_family: AddrFamily) -> Self {
+        Self {
+            config,
+            addr_family,
+        }
+    }
+
+    /// Create a check for each address family selected in the config
+    pub fn from_config(config: TcpConnectCheckConfig) -> Vec<Box<dyn HealthCheck>> {
+        let mut checks: Vec<Box<dyn HealthCheck>> = Vec::new();
+
+        if config.family != TcpFamilies::V6 {
+            checks.push(Box::new(TcpConnectCheck::new(
+                config.clone(),
+                AddrFamily::V4,
+            )));
+        }
+        if config.family != TcpFamilies::V4 {
+            checks.push(Box::new(TcpConnectCheck::new(config, AddrFamily::V6)));
+        }
+
+        checks
+    }
+
+    async fn resolve_target(&self) -> Result<Option<SocketAddr>> {
+        // IPv6 literals may be written with or without brackets
+        let host = self
+            .config
+            .host
+            .trim_start_matches('[')
+            .trim_end_matches(']');
+        let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, self.config.port))
+            .await
+            .context("DNS lookup failed")?
+            .collect();
+
+        let addr = match self.addr_family {
+            AddrFamily::V4 => addrs.iter().find(|a| a.is_ipv4()).copied(),
+            AddrFamily::V6 => addrs.iter().find(|a| a.is_ipv6()).copied(),
+        };
+
+        Ok(addr)
+    }
+
+    fn family_suffix(&self) -> &'static str {
+        match self.addr_family {
+            AddrFamily::V4 => "v4",
+            AddrFamily::V6 => "v6",
+        }
+    }
+}
+
+#[async_trait]
+impl HealthCheck for TcpConnectCheck {
+    async fn check(&self) -> Result<CheckResult> {
+        let name = format!("{} ({})", self.config.name, self.family_suffix());
+
+        let addr = match self.resolve_target().await? {
+            Some(addr) => addr,
+            None if self.config.family != TcpFamilies::Both => {
+                // the family was asked for explicitly, so it must be reachable
+                return Ok(CheckResult::fail(
+                    &name,
+                    format!(
+                        "No {} address found for {}",
+                        self.family_suffix(),
+                        self.config.host
+                    ),
+                ));
+            }
+            None => {
+                // No address of this family - skip check (not a failure)
+                debug!(
+                    "No {} address found for {}, skipping",
+                    self.family_suffix(),
+                    self.config.host
+                );
+                return Ok(CheckResult::ok(
+                    &name,
+                    format!("Skipped: no {} address", self.family_suffix()),
+                ));
+            }
+        };
+
+        let timeout = Duration::from_secs(self.config.timeout_secs);
+        let details = format!(
+            "Target: {}:{} ({})",
+            self.config.host, self.config.port, addr
+        );
+
+        let start = Instant::now();
+        match tokio::time::timeout(timeout, TcpStream::connect(addr)).await {
+            Ok(Ok(_stream)) => {
+                let latency = start.elapsed();
+                Ok(CheckResult::ok(
+                    &name,
+                    format!(
+                        "Connected to {} ({:.1}ms)",
+                        addr,
+                        latency.as_secs_f64() * 1000.0
+                    ),
+                )
+                .with_metric(latency.as_secs_f64()))
+            }
+            Ok(Err(e)) => Ok(CheckResult::fail(
+                &name,
+                format!("Connect to {} failed: {}", addr, e),
+            )
+            .with_details(format!("{}\nError: {}", details, e))),
+            Err(_) => Ok(CheckResult::fail(
+                &name,
+                format!("Connect to {} timed out after {}s", addr, timeout.as_secs()),
+            )
+            .with_details(details)),
+        }
+    }
+
+    fn id(&self) -> String {
+        format!(
+            "tcp:{}:{}:{}",
+            self.config.host,
+            self.config.port,
+            self.family_suffix()
+        )
+    }
+
+    fn schedule(&self) -> CheckSchedule {
+        self.config.schedule
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    fn config(port: u16, family: TcpFamilies) -> TcpConnectCheckConfig {
+        TcpConnectCheckConfig {
+            name: "Local".to_string(),
+            host: "127.0.0.1".to_string(),
+            port,
+            timeout_secs: 5,
+            family,
+            schedule: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_from_config_families() {
+        assert_eq!(
+            TcpConnectCheck::from_config(config(80, TcpFamilies::Both)).len(),
+            2
+        );
+        let checks = TcpConnectCheck::from_config(config(80, TcpFamilies::V4));
+        assert_eq!(checks.len(), 1);
+        assert_eq!(checks[0].id(), "tcp:127.0.0.1:80:v4");
+        let checks = TcpConnectCheck::from_config(config(80, TcpFamilies::V6));
+        assert_eq!(checks.len(), 1);
+        assert_eq!(checks[0].id(), "tcp:127.0.0.1:80:v6");
+    }
+
+    #[tokio::test]
+    async fn test_connect_local_listener() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let port = listener.local_addr()?.port();
+
+        let check = TcpConnectCheck::new(config(port, TcpFamilies::V4), AddrFamily::V4);
+        let result = check.check().await?;
+        assert!(result.passed, "{}", result.message);
+        assert!(result.metric_value.is_some_and(|l| l >= 0.0));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_connect_closed_port() -> Result<()> {
+        // grab a free port and close it again
+        let port = TcpListener::bind("127.0.0.1:0").await?.local_addr()?.port();
+
+        let check = TcpConnectCheck::new(config(port, TcpFamilies::V4), AddrFamily::V4);
+        let result = check.check().await?;
+        assert!(!result.passed);
+        assert!(result.metric_value.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_no_address_for_family_is_skipped() -> Result<()> {
+        let check = TcpConnectCheck::new(config(80, TcpFamilies::Both), AddrFamily::V6);
+        let result = check.check().await?;
+        assert!(result.passed);
+        assert!(result.message.starts_with("Skipped"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_no_address_for_explicit_family_fails() -> Result<()> {
+        let check = TcpConnectCheck::new(config(80, TcpFamilies::V6), AddrFamily::V6);
+        let result = check.check().await?;
+        assert!(!result.passed);
+        assert!(result.message.starts_with("No v6 address"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_resolve_ipv6_literal() -> Result<()> {
+        for host in ["::1", "[::1]"] {
+            let check = TcpConnectCheck::new(
+                TcpConnectCheckConfig {
+                    host: host.to_string(),
+                    ..config(80, TcpFamilies::V6)
+                },
+                AddrFamily::V6,
+            );
+            assert_eq!(
+                check.resolve_target().await?,
+                Some("[::1]:80".parse().unwrap())
+            );
+        }
+        Ok(())
+    }
+}
@@ -1,4 +1,4 @@
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, bail};
 use axum::Router;
 use axum::routing::get;
 use clap::Parser;
@@ -13,17 +13,20 @@ use std::time::Duration;
 use tokio::net::{TcpListener, TcpSocket};
 use tokio::signal;
 use tokio::sync::Mutex;
-use tokio::time::interval;
+use tokio::time::sleep_until;
 
 mod checks;
 mod metrics;
 mod notify;
+mod schedule;
 
 use checks::HealthCheck;
 use checks::dns::{DnsCheck, DnsCheckConfig};
 use checks::mss::{MssCheck, MssCheckConfig};
+use checks::tcp::{TcpConnectCheck, TcpConnectCheckConfig};
 use metrics::{HealthMetrics, metrics_handler};
 use notify::{EmailNotifier, NoopNotifier, Notifier, SmtpConfig};
+use schedule::Scheduler;
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "kebab-case")]
@@ -57,7 +60,8 @@ pub struct Settings {
     /// Prometheus metrics configuration
     #[serde(default)]
     pub metrics: MetricsConfig,
-    /// Check interval in seconds (default: 600 = 10 minutes)
+    /// Check interval in seconds (default: 600 = 10 minutes), checks can
+    /// override it with their own `interval-secs`
     #[serde(default = "default_interval")]
     pub interval_secs: u64,
     /// Alert cooldown in seconds - don't re-alert for same check within this period
@@ -69,6 +73,9 @@ pub struct Settings {
     /// DNS check configurations
     #[serde(default)]
     pub dns_checks: Vec<DnsCheckConfig>,
+    /// TCP connect check configurations
+    #[serde(default)]
+    pub tcp_checks: Vec<TcpConnectCheckConfig>,
 }
 
 fn default_interval() -> u64 {
@@ -136,6 +143,9 @@ async fn main() -> Result<()> {
         .context("Failed to build configuration")?
         .try_deserialize()
         .context("Failed to parse configuration")?;
+    if settings.interval_secs == 0 {
+        bail!("interval-secs must be at least 1");
+    }
 
     // Build all health checks from config
     let mut health_checks: Vec<Box<dyn HealthCheck>> = Vec::new();
@@ -150,11 +160,24 @@ async fn main() -> Result<()> {
         health_checks.extend(DnsCheck::from_config(config.clone()));
     }
 
+    for config in &settings.tcp_checks {
+        // Creates checks for the selected address families
+        health_checks.extend(TcpConnectCheck::from_config(config.clone()));
+    }
+
+    for check in &health_checks {
+        check
+            .schedule()
+            .validate()
+            .with_context(|| format!("Invalid schedule for check {}", check.id()))?;
+    }
+
     info!(
-        "Health checker starting with {} checks ({} MSS, {} DNS configs), interval {}s",
+        "Health checker starting with {} checks ({} MSS, {} DNS, {} TCP configs), interval {}s",
         health_checks.len(),
         settings.mss_checks.len(),
         settings.dns_checks.len(),
+        settings.tcp_checks.len(),
         settings.interval_secs
     );
 
@@ -220,26 +243,33 @@ async fn main() -> Result<()> {
         return Ok(());
     }
 
-    let check_interval = Duration::from_secs(settings.interval_secs);
-    let mut interval_timer = interval(check_interval);
-
-    // Run initial check immediately
-    interval_timer.tick().await;
+    let mut scheduler = Scheduler::new(
+        Duration::from_secs(settings.interval_secs),
+        health_checks.iter().map(|c| c.schedule()),
+        std::time::Instant::now(),
+    );
+    let cooldown = Duration::from_secs(settings.alert_cooldown_secs);
 
     let check_loop = async {
         loop {
-            if let Err(e) = run_checks(
-                &health_checks,
-                &settings,
-                &notifier,
-                &alert_state,
-                &health_metrics,
-            )
-            .await
-            {
-                error!("Check cycle failed: {}", e);
+            let due = scheduler.due(std::time::Instant::now());
+            if !due.is_empty() {
+                info!("Running {} health checks", due.len());
+            }
+            for idx in due {
+                let passed = run_check(
+                    health_checks[idx].as_ref(),
+                    cooldown,
+                    &notifier,
+                    &alert_state,
+                    &health_metrics,
+                )
+                .await;
+                scheduler.record(idx, passed, std::time::Instant::now());
+            }
+            if let Some(next) = scheduler.next_run() {
+                sleep_until(next.into()).await;
             }
-            interval_timer.tick().await;
         }
     };
 
@@ -266,60 +296,73 @@ async fn run_checks(
     let cooldown = Duration::from_secs(settings.alert_cooldown_secs);
 
     for check in checks {
-        let check_id = check.id();
-
-        match check.check().await {
-            Ok(result) => {
-                // Record metrics
-                metrics.record_status(&check_id, &result.name, result.passed);
-                record_check_metric(metrics, &check_id, &result);
-
-                if result.passed {
-                    info!("[PASS] {}: {}", result.name, result.message);
-                    // Clear alert state if issue resolved
-                    let mut state = alert_state.lock().await;
-                    state.clear_alert(&check_id);
-                } else {
-                    let mut state = alert_state.lock().await;
-                    if state.should_alert(&check_id, cooldown) {
-                        let mut message =
-                            format!("Health check FAILED: {}\n\n{}", result.name, result.message);
-                        if let Some(details) = &result.details {
-                            message.push_str("\n\nDetails:\n");
-                            message.push_str(details);
-                        }
-
-                        warn!("[FAIL] {}: {}", result.name, result.message);
-                        let title = format!("[Health Alert] {}", result.name);
-                        if let Err(e) = notifier.send(&title, &message).await {
-                            error!("Failed to send notification: {}", e);
-                        }
-                    } else {
-                        info!(
-                            "[FAIL] {}: {} (alert on cooldown)",
-                            result.name, result.message
-                        );
-                    }
-                }
-            }
-            Err(e) => {
-                // Record error status
-                metrics.record_status(&check_id, &check_id, false);
+        run_check(check.as_ref(), cooldown, notifier, alert_state, metrics).await;
+    }
+
+    Ok(())
+}
 
+/// Run a single check, alerting on failure. Returns whether the check passed.
+async fn run_check(
+    check: &dyn HealthCheck,
+    cooldown: Duration,
+    notifier: &Arc<dyn Notifier>,
+    alert_state: &Arc<Mutex<AlertState>>,
+    metrics: &Arc<HealthMetrics>,
+) -> bool {
+    let check_id = check.id();
+
+    match check.check().await {
+        Ok(result) => {
+            // Record metrics
+            metrics.record_status(&check_id, &result.name, result.passed);
+            record_check_metric(metrics, &check_id, &result);
+
+            if result.passed {
+                info!("[PASS] {}: {}", result.name, result.message);
+                // Clear alert state if issue resolved
+                let mut state = alert_state.lock().await;
+                state.clear_alert(&check_id);
+            } else {
                 let mut state = alert_state.lock().await;
                 if state.should_alert(&check_id, cooldown) {
-                    let title = format!("[Health Alert] {}", check_id);
-                    let message = format!("Health check ERROR: {}\n\nError: {}", check_id, e);
-                    error!("[ERROR] {}: {}", check_id, e);
+                    let mut message =
+                        format!("Health check FAILED: {}\n\n{}", result.name, result.message);
+                    if let Some(details) = &result.details {
+                        message.push_str("\n\nDetails:\n");
+                        message.push_str(details);
+                    }
+
+                    warn!("[FAIL] {}: {}", result.name, result.message);
+                    let title = format!("[Health Alert] {}", result.name);
                     if let Err(e) = notifier.send(&title, &message).await {
                         error!("Failed to send notification: {}", e);
                     }
+                } else {
+                    info!(
+                        "[FAIL] {}: {} (alert on cooldown)",
+                        result.name, result.message
+                    );
+                }
+            }
+            result.passed
+        }
+        Err(e) => {
+            // Record error status
+            metrics.record_status(&check_id, &check_id, false);
+
+            let mut state = alert_state.lock().await;
+            if state.should_alert(&check_id, cooldown) {
+                let title = format!("[Health Alert] {}", check_id);
+                let message = format!("Health check ERROR: {}\n\nError: {}", check_id, e);
+                error!("[ERROR] {}: {}", check_id, e);
+                if let Err(e) = notifier.send(&title, &message).await {
+                    error!("Failed to send notification: {}", e);
                 }
             }
+            false
         }
     }
-
-    Ok(())
 }
 
 /// Split a `type:middle:field2:field1` check id into
@@ -371,6 +414,16 @@ fn record_check_metric(metrics: &HealthMetrics, check_id: &str, result: &checks:
                     .set(value);
             }
         }
+        "tcp" => {
+            // tcp:host:port:family (host may be an IPv6 literal with colons)
+            let (host, port) = (middle, field2);
+            if let Some(value) = result.metric_value {
+                metrics
+                    .tcp_connect_gauge
+                    .with_label_values(&[host, port, family])
+                    .set(value);
+            }
+        }
         _ => {}
     }
 }
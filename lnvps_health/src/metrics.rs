@@ -11,6 +11,8 @@ pub struct HealthMetrics {
     pub pmtu_gauge: GaugeVec,
     /// DNS check latency (server, query, address_family)
     pub dns_latency_gauge: GaugeVec,
+    /// TCP connect latency (host, port, address_family)
+    pub tcp_connect_gauge: GaugeVec,
     /// Check status (1 = pass, 0 = fail) by check_id
     pub check_status: GaugeVec,
 }
@@ -37,6 +39,15 @@ impl HealthMetrics {
         )
         .expect("Failed to create dns_latency_gauge");
 
+        let tcp_connect_gauge = GaugeVec::new(
+            Opts::new(
+                "health_tcp_connect_seconds",
+                "TCP connect latency in seconds",
+            ),
+            &["host", "port", "family"],
+        )
+        .expect("Failed to create tcp_connect_gauge");
+
         let check_status = GaugeVec::new(
             Opts::new(
                 "health_check_status",
@@ -55,6 +66,9 @@ impl HealthMetrics {
         registry
             .register(Box::new(dns_latency_gauge.clone()))
             .expect("Failed to register dns_latency_gauge");
+        registry
+            .register(Box::new(tcp_connect_gauge.clone()))
+            .expect("Failed to register tcp_connect_gauge");
         registry
             .register(Box::new(check_status.clone()))
             .expect("Failed to register check_status");
@@ -64,6 +78,7 @@ impl HealthMetrics {
             mss_gauge,
             pmtu_gauge,
             dns_latency_gauge,
+            tcp_connect_gauge,
             check_status,
         }
     }
@@ -1,40 +1,123 @@
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, bail};
+use lettre::message::Mailbox;
 use lettre::message::header::ContentType;
 use lettre::transport::smtp::authentication::Credentials;
+use lettre::transport::smtp::client::{Tls, TlsParameters};
 use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
 use log::{debug, error, info};
 use serde::{Deserialize, Serialize};
 
+/// How the connection to the SMTP server is secured
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TlsMode {
+    /// Plain text, only for relays on a trusted network
+    None,
+    /// Upgrade a plain connection with STARTTLS, usually port 587
+    StartTls,
+    /// TLS from the start (SMTPS), usually port 465
+    Implicit,
+}
+
+impl TlsMode {
+    fn default_port(&self) -> u16 {
+        match self {
+            TlsMode::None => 25,
+            TlsMode::StartTls => 587,
+            TlsMode::Implicit => 465,
+        }
+    }
+}
+
 /// SMTP configuration for sending alert emails
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct SmtpConfig {
     /// SMTP server hostname
     pub host: String,
-    /// SMTP server port (default: 587 for STARTTLS)
-    #[serde(default = "default_smtp_port")]
-    pub port: u16,
-    /// SMTP username
-    pub username: String,
-    /// SMTP password
-    pub password: String,
+    /// SMTP server port (default: 587 for STARTTLS, 465 for implicit TLS, 25 without TLS)
+    pub port: Option<u16>,
+    /// SMTP username, the relay is used without auth when unset
+    pub username: Option<String>,
+    /// SMTP password, required with `username`
+    pub password: Option<String>,
     /// Sender email address
     pub from: String,
+    /// Reply-To address, replies go to `from` when unset
+    pub reply_to: Option<String>,
     /// Recipient email addresses for alerts
     pub to: Vec<String>,
-    /// Use STARTTLS (default: true)
+    /// Connection security (default: from `starttls`)
+    pub tls_mode: Option<TlsMode>,
+    /// Use STARTTLS, implicit TLS when false. Superseded by `tls-mode`.
     #[serde(default = "default_starttls")]
     pub starttls: bool,
 }
 
-fn default_smtp_port() -> u16 {
-    587
-}
-
 fn default_starttls() -> bool {
     true
 }
 
+impl SmtpConfig {
+    pub fn tls_mode(&self) -> TlsMode {
+        self.tls_mode.unwrap_or(if self.starttls {
+            TlsMode::StartTls
+        } else {
+            TlsMode::Implicit
+        })
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port.unwrap_or_else(|| self.tls_mode().default_port())
+    }
+
+    /// Check the config can be used to send mail
+    pub fn validate(&self) -> Result<()> {
+        if self.host.trim().is_empty() {
+            bail!("SMTP host is required");
+        }
+        if self.username.is_some() != self.password.is_some() {
+            bail!("SMTP username and password must be set together");
+        }
+        if self.to.is_empty() {
+            bail!("At least one SMTP recipient is required");
+        }
+        self.from
+            .parse::<Mailbox>()
+            .with_context(|| format!("Invalid from address: {}", self.from))?;
+        if let Some(reply_to) = &self.reply_to {
+            reply_to
+                .parse::<Mailbox>()
+                .with_context(|| format!("Invalid reply-to address: {}", reply_to))?;
+        }
+        for to in &self.to {
+            to.parse::<Mailbox>()
+                .with_context(|| format!("Invalid recipient address: {}", to))?;
+        }
+        Ok(())
+    }
+
+    /// TLS settings of the transport for the configured mode
+    fn tls(&self) -> Result<Tls> {
+        let params = || TlsParameters::new(self.host.clone()).context("Invalid TLS parameters");
+        Ok(match self.tls_mode() {
+            TlsMode::None => Tls::None,
+            TlsMode::StartTls => Tls::Required(params()?),
+            TlsMode::Implicit => Tls::Wrapper(params()?),
+        })
+    }
+
+    fn transport(&self) -> Result<AsyncSmtpTransport<Tokio1Executor>> {
+        let mut builder = AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&self.host)
+            .port(self.port())
+            .tls(self.tls()?);
+        if let (Some(username), Some(password)) = (&self.username, &self.password) {
+            builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+        }
+        Ok(builder.build())
+    }
+}
+
 /// Email notifier for sending health check alerts
 pub struct EmailNotifier {
     config: SmtpConfig,
@@ -43,21 +126,10 @@ pub struct EmailNotifier {
 
 impl EmailNotifier {
     pub fn new(config: SmtpConfig) -> Result<Self> {
-        let creds = Credentials::new(config.username.clone(), config.password.clone());
-
-        let mailer = if config.starttls {
-            AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.host)
-                .context("Failed to create SMTP transport")?
-                .port(config.port)
-                .credentials(creds)
-                .build()
-        } else {
-            AsyncSmtpTransport::<Tokio1Executor>::relay(&config.host)
-                .context("Failed to create SMTP transport")?
-                .port(config.port)
-                .credentials(creds)
-                .build()
-        };
+        config.validate().context("Invalid SMTP config")?;
+        let mailer = config
+            .transport()
+            .context("Failed to create SMTP transport")?;
 
         Ok(Self { config, mailer })
     }
@@ -65,9 +137,13 @@ impl EmailNotifier {
     /// Send an alert email
     pub async fn send_alert(&self, subject: &str, body: &str) -> Result<()> {
         for recipient in &self.config.to {
-            let email = Message::builder()
+            let mut builder = Message::builder()
                 .from(self.config.from.parse().context("Invalid from address")?)
-                .to(recipient.parse().context("Invalid recipient address")?)
+                .to(recipient.parse().context("Invalid recipient address")?);
+            if let Some(reply_to) = &self.config.reply_to {
+                builder = builder.reply_to(reply_to.parse().context("Invalid reply-to address")?);
+            }
+            let email = builder
                 .subject(subject)
                 .header(ContentType::TEXT_PLAIN)
                 .body(body.to_string())
@@ -111,3 +187,122 @@ impl Notifier for NoopNotifier {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> SmtpConfig {
+        SmtpConfig {
+            host: "smtp.example.com".to_string(),
+            port: None,
+            username: Some("alerts@example.com".to_string()),
+            password: Some("secret".to_string()),
+            from: "LNVPS Health <alerts@example.com>".to_string(),
+            reply_to: None,
+            to: vec!["admin@example.com".to_string()],
+            tls_mode: None,
+            starttls: true,
+        }
+    }
+
+    #[test]
+    fn test_tls_mode_selects_transport() {
+        let mut c = config();
+        assert_eq!(c.tls_mode(), TlsMode::StartTls);
+        assert_eq!(c.port(), 587);
+        assert!(matches!(c.tls().unwrap(), Tls::Required(_)));
+
+        c.tls_mode = Some(TlsMode::Implicit);
+        assert_eq!(c.port(), 465);
+        assert!(matches!(c.tls().unwrap(), Tls::Wrapper(_)));
+
+        c.tls_mode = Some(TlsMode::None);
+        assert_eq!(c.port(), 25);
+        assert!(matches!(c.tls().unwrap(), Tls::None));
+
+        // an explicit port wins over the mode default
+        c.port = Some(2525);
+        assert_eq!(c.port(), 2525);
+
+        // legacy starttls: false meant implicit TLS
+        let mut c = config();
+        c.starttls = false;
+        assert_eq!(c.tls_mode(), TlsMode::Implicit);
+        // tls-mode takes precedence over starttls
+        c.tls_mode = Some(TlsMode::StartTls);
+        assert_eq!(c.tls_mode(), TlsMode::StartTls);
+
+        for mode in [TlsMode::None, TlsMode::StartTls, TlsMode::Implicit] {
+            let c = SmtpConfig {
+                tls_mode: Some(mode),
+                ..config()
+            };
+            assert!(EmailNotifier::new(c).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_parse_tls_mode() {
+        for (value, mode) in [
+            ("none", TlsMode::None),
+            ("starttls", TlsMode::StartTls),
+            ("implicit", TlsMode::Implicit),
+        ] {
+            let yaml = format!(
+                "host: smtp.example.com\nfrom: alerts@example.com\nto: [admin@example.com]\ntls-mode: {}\n",
+                value
+            );
+            let parsed: SmtpConfig = config::Config::builder()
+                .add_source(config::File::from_str(&yaml, config::FileFormat::Yaml))
+                .build()
+                .unwrap()
+                .try_deserialize()
+                .unwrap();
+            assert_eq!(parsed.tls_mode(), mode);
+            assert!(parsed.username.is_none());
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(config().validate().is_ok());
+
+        // no auth
+        let c = SmtpConfig {
+            username: None,
+            password: None,
+            ..config()
+        };
+        assert!(c.validate().is_ok());
+
+        let c = SmtpConfig {
+            password: None,
+            ..config()
+        };
+        assert!(c.validate().is_err());
+
+        let c = SmtpConfig {
+            from: "not an address".to_string(),
+            ..config()
+        };
+        assert!(c.validate().is_err());
+
+        let c = SmtpConfig {
+            reply_to: Some("oncall@example.com".to_string()),
+            ..config()
+        };
+        assert!(c.validate().is_ok());
+        let c = SmtpConfig {
+            reply_to: Some("oncall".to_string()),
+            ..config()
+        };
+        assert!(c.validate().is_err());
+
+        let c = SmtpConfig {
+            to: vec![],
+            ..config()
+        };
+        assert!(c.validate().is_err());
+    }
+}
@@ -0,0 +1,240 @@
+use anyhow::{Result, bail};
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+/// Per-check overrides of when a check runs
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub struct CheckSchedule {
+    /// Seconds between runs of this check, defaults to the global `interval-secs`
+    pub interval_secs: Option<u64>,
+    /// Fastest re-probe interval while the check is failing. Each consecutive
+    /// failure halves the interval until it reaches this value, the check goes
+    /// back to its normal interval once it passes. Failing checks keep their
+    /// normal interval when unset.
+    pub failure_interval_secs: Option<u64>,
+}
+
+impl CheckSchedule {
+    /// Check the intervals are at least a second, a check with a 0 interval
+    /// would run in a busy loop
+    pub fn validate(&self) -> Result<()> {
+        if self.interval_secs == Some(0) {
+            bail!("interval-secs must be at least 1");
+        }
+        if self.failure_interval_secs == Some(0) {
+            bail!("failure-interval-secs must be at least 1");
+        }
+        Ok(())
+    }
+
+    /// Delay before the next run after `failures` consecutive failures
+    pub fn next_interval(&self, base: Duration, failures: u32) -> Duration {
+        let interval = self.interval_secs.map(Duration::from_secs).unwrap_or(base);
+        let Some(floor) = self.failure_interval_secs.map(Duration::from_secs) else {
+            return interval;
+        };
+        if failures == 0 || floor >= interval {
+            return interval;
+        }
+        // halve once per failure, capped so the shift can't overflow
+        (interval / 2u32.pow(failures.min(31))).max(floor)
+    }
+}
+
+struct CheckState {
+    schedule: CheckSchedule,
+    next_run: Instant,
+    failures: u32,
+}
+
+/// Tracks when each check is next due
+pub struct Scheduler {
+    base: Duration,
+    checks: Vec<CheckState>,
+}
+
+impl Scheduler {
+    /// All checks are due at `now`
+    pub fn new(
+        base: Duration,
+        schedules: impl IntoIterator<Item = CheckSchedule>,
+        now: Instant,
+    ) -> Self {
+        Self {
+            base,
+            checks: schedules
+                .into_iter()
+                .map(|schedule| CheckState {
+                    schedule,
+                    next_run: now,
+                    failures: 0,
+                })
+                .collect(),
+        }
+    }
+
+    /// Indexes of the checks due at `now`
+    pub fn due(&self, now: Instant) -> Vec<usize> {
+        self.checks
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| c.next_run <= now)
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// Record the outcome of a run of check `idx` which finished at `now`
+    pub fn record(&mut self, idx: usize, passed: bool, now: Instant) {
+        let Some(check) = self.checks.get_mut(idx) else {
+            return;
+        };
+        check.failures = if passed {
+            0
+        } else {
+            check.failures.saturating_add(1)
+        };
+        check.next_run = now + check.schedule.next_interval(self.base, check.failures);
+    }
+
+    /// When the next check is due
+    pub fn next_run(&self) -> Option<Instant> {
+        self.checks.iter().map(|c| c.next_run).min()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: Duration = Duration::from_secs(600);
+
+    #[test]
+    fn test_next_interval() {
+        let default = CheckSchedule::default();
+        assert_eq!(default.next_interval(BASE, 0), BASE);
+        assert_eq!(default.next_interval(BASE, 3), BASE);
+
+        let fast = CheckSchedule {
+            interval_secs: Some(120),
+            failure_interval_secs: Some(20),
+        };
+        assert_eq!(fast.next_interval(BASE, 0), Duration::from_secs(120));
+        assert_eq!(fast.next_interval(BASE, 1), Duration::from_secs(60));
+        assert_eq!(fast.next_interval(BASE, 2), Duration::from_secs(30));
+        assert_eq!(fast.next_interval(BASE, 3), Duration::from_secs(20));
+        assert_eq!(fast.next_interval(BASE, 100), Duration::from_secs(20));
+
+        // a floor above the interval never slows the check down
+        let slow = CheckSchedule {
+            interval_secs: None,
+            failure_interval_secs: Some(900),
+        };
+        assert_eq!(slow.next_interval(BASE, 2), BASE);
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(CheckSchedule::default().validate().is_ok());
+        let ok = CheckSchedule {
+            interval_secs: Some(1),
+            failure_interval_secs: Some(1),
+        };
+        assert!(ok.validate().is_ok());
+        let zero = CheckSchedule {
+            interval_secs: Some(0),
+            failure_interval_secs: None,
+        };
+        assert!(zero.validate().is_err());
+        let zero_failure = CheckSchedule {
+            interval_secs: None,
+            failure_interval_secs: Some(0),
+        };
+        assert!(zero_failure.validate().is_err());
+    }
+
+    /// Runs the due checks every second for `secs`, returning the seconds at
+    /// which each check ran
+    fn simulate(
+        schedules: &[CheckSchedule],
+        secs: u64,
+        passes: impl Fn(usize, u64) -> bool,
+    ) -> Vec<Vec<u64>> {
+        let start = Instant::now();
+        let mut scheduler = Scheduler::new(BASE, schedules.iter().copied(), start);
+        let mut runs = vec![Vec::new(); schedules.len()];
+        for t in 0..=secs {
+            let now = start + Duration::from_secs(t);
+            for idx in scheduler.due(now) {
+                runs[idx].push(t);
+                scheduler.record(idx, passes(idx, t), now);
+            }
+        }
+        runs
+    }
+
+    #[test]
+    fn test_per_check_interval() {
+        let schedules = [
+            CheckSchedule::default(),
+            CheckSchedule {
+                interval_secs: Some(300),
+                ..Default::default()
+            },
+        ];
+        let runs = simulate(&schedules, 1200, |_, _| true);
+        assert_eq!(runs[0], vec![0, 600, 1200]);
+        assert_eq!(runs[1], vec![0, 300, 600, 900, 1200]);
+    }
+
+    #[test]
+    fn test_failing_check_probes_faster_until_recovered() {
+        let schedules = [
+            // fails between t=300 and t=500
+            CheckSchedule {
+                interval_secs: Some(300),
+                failure_interval_secs: Some(30),
+            },
+            // same outage without a failure interval
+            CheckSchedule {
+                interval_secs: Some(300),
+                ..Default::default()
+            },
+        ];
+        let runs = simulate(&schedules, 1200, |_, t| !(300..500).contains(&t));
+        // 300 fails -> +150, 450 fails -> +75, 525 passes -> back to +300
+        assert_eq!(runs[0], vec![0, 300, 450, 525, 825, 1125]);
+        // recovery is only seen at the next regular run
+        assert_eq!(runs[1], vec![0, 300, 600, 900, 1200]);
+    }
+
+    #[test]
+    fn test_backoff_reaches_floor() {
+        let schedules = [CheckSchedule {
+            interval_secs: Some(240),
+            failure_interval_secs: Some(50),
+        }];
+        let runs = simulate(&schedules, 600, |_, t| !(200..500).contains(&t));
+        // 240 -> +120, 360 -> +60, 420 -> +50 (floor), 470 -> +50, 520 passes
+        assert_eq!(runs[0], vec![0, 240, 360, 420, 470, 520]);
+    }
+
+    #[test]
+    fn test_next_run() {
+        let start = Instant::now();
+        let schedules = [
+            CheckSchedule::default(),
+            CheckSchedule {
+                interval_secs: Some(60),
+                ..Default::default()
+            },
+        ];
+        let mut scheduler = Scheduler::new(BASE, schedules, start);
+        assert_eq!(scheduler.next_run(), Some(start));
+        scheduler.record(0, true, start);
+        scheduler.record(1, true, start);
+        assert_eq!(scheduler.next_run(), Some(start + Duration::from_secs(60)));
+        assert!(scheduler.due(start + Duration::from_secs(59)).is_empty());
+        assert_eq!(scheduler.due(start + Duration::from_secs(60)), vec![1]);
+    }
+}
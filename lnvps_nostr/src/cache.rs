@@ -0,0 +1,324 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use lnvps_db::nostr::LNVPSNostrDb;
+use lnvps_db::{DbError, DbResult, NostrDomain, NostrDomainHandle};
+
+/// Caches the lookups made for NIP-05 requests for a short time.
+///
+/// Entries are only dropped when they expire, writes go straight to the
+/// database. The API, which makes all the changes, runs in another process
+/// so it can't drop entries here: a change shows up within `ttl`.
+/// Missing domains and handles are cached too, so unknown names can't be used
+/// to hammer the database, each cache holds at most `capacity` entries so they
+/// can't be used to fill our memory either.
+pub struct CachedNostrDb {
+    db: Arc<dyn LNVPSNostrDb>,
+    /// Domains by name, `None` when the domain doesn't exist
+    domains: TtlCache<String, Option<NostrDomain>>,
+    /// Resolved handles by (domain id, name), `None` when neither the name nor
+    /// the default handle exist
+    handles: TtlCache<(u64, String), Option<NostrDomainHandle>>,
+}
+
+/// Entries which expire after `ttl`, when full the least recently used entry
+/// is dropped
+struct TtlCache<K, V> {
+    ttl: Duration,
+    capacity: usize,
+    entries: Mutex<HashMap<K, Cached<V>>>,
+}
+
+struct Cached<V> {
+    value: V,
+    expires: Instant,
+    used: Instant,
+}
+
+impl<K: Eq + Hash + Clone, V: Clone> TtlCache<K, V> {
+    fn new(ttl: Duration, capacity: usize) -> Self {
+        Self {
+            ttl,
+            capacity,
+            entries: Default::default(),
+        }
+    }
+
+    fn get(&self, key: &K) -> Option<V> {
+        let mut entries = self.entries.lock().unwrap();
+        let now = Instant::now();
+        match entries.get_mut(key) {
+            Some(c) if c.expires > now => {
+                c.used = now;
+                Some(c.value.clone())
+            }
+            Some(_) => {
+                entries.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn put(&self, key: K, value: V) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        let now = Instant::now();
+        if entries.len() >= self.capacity && !entries.contains_key(&key) {
+            entries.retain(|_, c| c.expires > now);
+        }
+        // a linear scan, the caches are small and only full under abuse
+        while entries.len() >= self.capacity && !entries.contains_key(&key) {
+            let Some(oldest) = entries
+                .iter()
+                .min_by_key(|(_, c)| c.used)
+                .map(|(k, _)| k.clone())
+            else {
+                break;
+            };
+            entries.remove(&oldest);
+        }
+        entries.insert(
+            key,
+            Cached {
+                value,
+                expires: now + self.ttl,
+                used: now,
+            },
+        );
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+}
+
+impl CachedNostrDb {
+    pub fn new(db: Arc<dyn LNVPSNostrDb>, ttl: Duration, capacity: usize) -> Self {
+        Self {
+            db,
+            domains: TtlCache::new(ttl, capacity),
+            handles: TtlCache::new(ttl, capacity),
+        }
+    }
+}
+
+/// Turn a "not found" error into `None`, so it can be cached
+fn found<T>(r: DbResult<T>) -> DbResult<Option<T>> {
+    match r {
+        Ok(v) => Ok(Some(v)),
+        Err(e) if e.is_row_not_found() => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// The "not found" error for a cached `None`
+fn not_found<T>(v: Option<T>) -> DbResult<T> {
+    v.ok_or_else(DbError::row_not_found)
+}
+
+#[async_trait]
+impl LNVPSNostrDb for CachedNostrDb {
+    async fn get_handle(&self, handle_id: u64) -> DbResult<NostrDomainHandle> {
+        self.db.get_handle(handle_id).await
+    }
+
+    async fn get_handle_by_name(
+        &self,
+        domain_id: u64,
+        handle: &str,
+    ) -> DbResult<NostrDomainHandle> {
+        self.db.get_handle_by_name(domain_id, handle).await
+    }
+
+    async fn resolve_handle(&self, domain_id: u64, name: &str) -> DbResult<NostrDomainHandle> {
+        let key = (domain_id, name.to_string());
+        if let Some(h) = self.handles.get(&key) {
+            return not_found(h);
+        }
+        let h = found(self.db.resolve_handle(domain_id, name).await)?;
+        self.handles.put(key, h.clone());
+        not_found(h)
+    }
+
+    async fn insert_handle(&self, handle: &NostrDomainHandle) -> DbResult<u64> {
+        self.db.insert_handle(handle).await
+    }
+
+    async fn update_handle(&self, handle: &NostrDomainHandle) -> DbResult<()> {
+        self.db.update_handle(handle).await
+    }
+
+    async fn delete_handle(&self, handle_id: u64) -> DbResult<()> {
+        self.db.delete_handle(handle_id).await
+    }
+
+    async fn list_handles(&self, domain_id: u64) -> DbResult<Vec<NostrDomainHandle>> {
+        self.db.list_handles(domain_id).await
+    }
+
+    async fn get_domain(&self, id: u64) -> DbResult<NostrDomain> {
+        self.db.get_domain(id).await
+    }
+
+    async fn get_domain_by_name(&self, name: &str) -> DbResult<NostrDomain> {
+        if let Some(d) = self.domains.get(&name.to_string()) {
+            return not_found(d);
+        }
+        let d = found(self.db.get_domain_by_name(name).await)?;
+        self.domains.put(name.to_string(), d.clone());
+        not_found(d)
+    }
+
+    async fn get_domain_by_activation_hash(&self, hash: &str) -> DbResult<NostrDomain> {
+        self.db.get_domain_by_activation_hash(hash).await
+    }
+
+    async fn list_domains(&self, owner_id: u64) -> DbResult<Vec<NostrDomain>> {
+        self.db.list_domains(owner_id).await
+    }
+
+    async fn insert_domain(&self, domain: &NostrDomain) -> DbResult<u64> {
+        self.db.insert_domain(domain).await
+    }
+
+    async fn delete_domain(&self, domain_id: u64) -> DbResult<()> {
+        self.db.delete_domain(domain_id).await
+    }
+
+    async fn list_all_domains(&self) -> DbResult<Vec<NostrDomain>> {
+        self.db.list_all_domains().await
+    }
+
+    async fn list_active_domains(&self) -> DbResult<Vec<NostrDomain>> {
+        self.db.list_active_domains().await
+    }
+
+    async fn list_disabled_domains(&self) -> DbResult<Vec<NostrDomain>> {
+        self.db.list_disabled_domains().await
+    }
+
+    async fn enable_domain_with_https(&self, domain_id: u64) -> DbResult<()> {
+        self.db.enable_domain_with_https(domain_id).await
+    }
+
+    async fn enable_domain_http_only(&self, domain_id: u64) -> DbResult<()> {
+        self.db.enable_domain_http_only(domain_id).await
+    }
+
+    async fn disable_domain(&self, domain_id: u64) -> DbResult<()> {
+        self.db.disable_domain(domain_id).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mocks::MockNostrDb;
+    use std::sync::atomic::Ordering;
+
+    fn alice(pubkey: u8) -> NostrDomainHandle {
+        NostrDomainHandle {
+            id: 1,
+            domain_id: 1,
+            handle: "alice".to_string(),
+            pubkey: vec![pubkey; 32],
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_lookup_within_ttl_hits_cache() -> anyhow::Result<()> {
+        let db = Arc::new(MockNostrDb::default());
+        db.insert_handle(&alice(1)).await?;
+        let cache = CachedNostrDb::new(db.clone(), Duration::from_secs(60), 100);
+
+        for _ in 0..3 {
+            assert_eq!(cache.resolve_handle(1, "alice").await?.pubkey, vec![1; 32]);
+            assert!(cache.resolve_handle(1, "bob").await.is_err());
+        }
+        // one lookup each for alice and the missing bob
+        assert_eq!(db.handle_lookups.load(Ordering::Relaxed), 2);
+
+        // expired entries are looked up again
+        let cache = CachedNostrDb::new(db.clone(), Duration::ZERO, 100);
+        cache.resolve_handle(1, "alice").await?;
+        cache.resolve_handle(1, "alice").await?;
+        assert_eq!(db.handle_lookups.load(Ordering::Relaxed), 4);
+        Ok(())
+    }
+
+    #[test]
+    fn test_full_cache_drops_least_recently_used() {
+        let cache = TtlCache::new(Duration::from_secs(60), 2);
+        cache.put("a", 1);
+        cache.put("b", 2);
+        // a is used after b, so b goes when c is added
+        assert_eq!(cache.get(&"a"), Some(1));
+        cache.put("c", 3);
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get(&"a"), Some(1));
+        assert_eq!(cache.get(&"b"), None);
+        assert_eq!(cache.get(&"c"), Some(3));
+
+        // replacing an entry doesn't drop another
+        cache.put("c", 4);
+        assert_eq!(cache.get(&"a"), Some(1));
+        assert_eq!(cache.get(&"c"), Some(4));
+    }
+
+    #[tokio::test]
+    async fn test_changes_show_up_after_ttl() -> anyhow::Result<()> {
+        let db = Arc::new(MockNostrDb::default());
+        db.insert_handle(&alice(1)).await?;
+        let id = db
+            .insert_domain(&NostrDomain {
+                name: "example.com".to_string(),
+                ..Default::default()
+            })
+            .await?;
+        db.enable_domain_with_https(id).await?;
+
+        // changes made by the API, which has its own connection
+        let cache = CachedNostrDb::new(db.clone(), Duration::from_secs(60), 100);
+        assert_eq!(cache.resolve_handle(1, "alice").await?.pubkey, vec![1; 32]);
+        assert!(cache.get_domain_by_name("example.com").await?.enabled);
+        db.update_handle(&alice(2)).await?;
+        db.disable_domain(id).await?;
+        assert_eq!(cache.resolve_handle(1, "alice").await?.pubkey, vec![1; 32]);
+        assert!(cache.get_domain_by_name("example.com").await?.enabled);
+
+        let cache = CachedNostrDb::new(db.clone(), Duration::ZERO, 100);
+        assert_eq!(cache.resolve_handle(1, "alice").await?.pubkey, vec![2; 32]);
+        assert!(!cache.get_domain_by_name("example.com").await?.enabled);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_unknown_name_falls_back_to_default() -> anyhow::Result<()> {
+        let db = Arc::new(MockNostrDb::default());
+        db.insert_handle(&alice(1)).await?;
+        db.insert_handle(&NostrDomainHandle {
+            id: 2,
+            handle: NostrDomainHandle::DEFAULT.to_string(),
+            ..alice(2)
+        })
+        .await?;
+        let cache = CachedNostrDb::new(db.clone(), Duration::from_secs(60), 100);
+        let h = cache.resolve_handle(1, "bob").await?;
+        assert!(h.is_default());
+        assert_eq!(h.pubkey, vec![2; 32]);
+        // an explicit handle wins over the default
+        assert_eq!(cache.resolve_handle(1, "alice").await?.id, 1);
+        assert_eq!(cache.resolve_handle(1, "_").await?.id, 2);
+        // one lookup per name, the fallback is part of it
+        assert_eq!(db.handle_lookups.load(Ordering::Relaxed), 3);
+        Ok(())
+    }
+}
@@ -1,22 +1,56 @@
+use crate::cache::CachedNostrDb;
+use crate::rate_limit::RateLimiter;
 use anyhow::Result;
+use axum::Extension;
 use config::{Config, File};
+use lnvps_api_common::TrustedProxies;
 use lnvps_db::{LNVpsDbMysql, nostr::LNVPSNostrDb};
 use log::info;
 use serde::Deserialize;
 use std::net::{IpAddr, SocketAddr};
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::net::{TcpListener, TcpSocket};
 use tower_http::cors::CorsLayer;
 
+mod cache;
+#[cfg(test)]
+mod mocks;
+mod rate_limit;
 mod routes;
 
 #[derive(Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
 struct Settings {
     /// Database connection string
     db: String,
     /// Listen address for http server
     listen: Option<String>,
+    /// Seconds domain and handle lookups are cached for
+    #[serde(default = "default_cache_ttl")]
+    cache_ttl: u64,
+    /// Requests allowed per client IP per minute
+    #[serde(default = "default_rate_limit")]
+    rate_limit: u32,
+    /// Most domains, and most handles, kept in the lookup cache
+    #[serde(default = "default_cache_size")]
+    cache_size: usize,
+    /// Proxies allowed to set `X-Real-IP` / `X-Forwarded-For`, the headers of
+    /// other peers are ignored
+    trusted_proxies: Option<Vec<String>>,
+}
+
+fn default_cache_ttl() -> u64 {
+    30
+}
+
+fn default_rate_limit() -> u32 {
+    120
+}
+
+fn default_cache_size() -> usize {
+    10_000
 }
 
 #[tokio::main]
@@ -30,7 +64,15 @@ async fn main() -> Result<()> {
 
     // Connect database
     let db = LNVpsDbMysql::new(&settings.db).await?;
-    let db: Arc<dyn LNVPSNostrDb> = Arc::new(db);
+    let db: Arc<dyn LNVPSNostrDb> = Arc::new(CachedNostrDb::new(
+        Arc::new(db),
+        Duration::from_secs(settings.cache_ttl),
+        settings.cache_size,
+    ));
+    let limiter = Arc::new(RateLimiter::new(
+        settings.rate_limit,
+        Duration::from_secs(60),
+    ));
 
     let ip: SocketAddr = match &settings.listen {
         Some(i) => i.parse()?,
@@ -38,8 +80,18 @@ async fn main() -> Result<()> {
     };
     let listener = bind_address(ip).await?;
     info!("Listening on {}", ip);
-    let router = routes::routes(db);
-    axum::serve(listener, router.layer(CorsLayer::permissive())).await?;
+    let router = routes::routes(db, limiter);
+    let router = match &settings.trusted_proxies {
+        Some(p) => router.layer(Extension(TrustedProxies::parse(p)?)),
+        None => router,
+    };
+    axum::serve(
+        listener,
+        router
+            .layer(CorsLayer::permissive())
+            .into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await?;
 
     Ok(())
 }
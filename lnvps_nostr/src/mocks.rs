@@ -0,0 +1,183 @@
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use async_trait::async_trait;
+use lnvps_db::nostr::LNVPSNostrDb;
+use lnvps_db::{DbError, DbResult, NostrDomain, NostrDomainHandle};
+
+/// Domains and their handles, counting handle lookups
+#[derive(Default)]
+pub struct MockNostrDb {
+    pub domains: Mutex<Vec<NostrDomain>>,
+    pub handles: Mutex<Vec<NostrDomainHandle>>,
+    pub handle_lookups: AtomicUsize,
+}
+
+impl MockNostrDb {
+    /// Update the domain `domain_id` with `f`
+    fn update_domain(&self, domain_id: u64, f: impl FnOnce(&mut NostrDomain)) -> DbResult<()> {
+        let mut domains = self.domains.lock().unwrap();
+        let d = domains
+            .iter_mut()
+            .find(|d| d.id == domain_id)
+            .ok_or_else(DbError::row_not_found)?;
+        f(d);
+        Ok(())
+    }
+
+    fn find_domain(&self, f: impl Fn(&NostrDomain) -> bool) -> DbResult<NostrDomain> {
+        self.domains
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|d| f(d))
+            .cloned()
+            .ok_or_else(DbError::row_not_found)
+    }
+
+    fn filter_domains(&self, f: impl Fn(&NostrDomain) -> bool) -> Vec<NostrDomain> {
+        self.domains
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|d| f(d))
+            .cloned()
+            .collect()
+    }
+}
+
+#[async_trait]
+impl LNVPSNostrDb for MockNostrDb {
+    async fn get_handle(&self, handle_id: u64) -> DbResult<NostrDomainHandle> {
+        self.handles
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|h| h.id == handle_id)
+            .cloned()
+            .ok_or_else(DbError::row_not_found)
+    }
+
+    async fn get_handle_by_name(
+        &self,
+        domain_id: u64,
+        handle: &str,
+    ) -> DbResult<NostrDomainHandle> {
+        self.handle_lookups.fetch_add(1, Ordering::Relaxed);
+        self.handles
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|h| h.domain_id == domain_id && h.handle == handle)
+            .cloned()
+            .ok_or_else(DbError::row_not_found)
+    }
+
+    async fn resolve_handle(&self, domain_id: u64, name: &str) -> DbResult<NostrDomainHandle> {
+        self.handle_lookups.fetch_add(1, Ordering::Relaxed);
+        let handles = self.handles.lock().unwrap();
+        let find = |name: &str| {
+            handles
+                .iter()
+                .find(|h| h.domain_id == domain_id && h.handle == name)
+                .cloned()
+        };
+        find(name)
+            .or_else(|| find(NostrDomainHandle::DEFAULT))
+            .ok_or_else(DbError::row_not_found)
+    }
+
+    async fn insert_handle(&self, handle: &NostrDomainHandle) -> DbResult<u64> {
+        self.handles.lock().unwrap().push(handle.clone());
+        Ok(handle.id)
+    }
+
+    async fn update_handle(&self, handle: &NostrDomainHandle) -> DbResult<()> {
+        let mut handles = self.handles.lock().unwrap();
+        if let Some(h) = handles.iter_mut().find(|h| h.id == handle.id) {
+            *h = handle.clone();
+        }
+        Ok(())
+    }
+
+    async fn delete_handle(&self, handle_id: u64) -> DbResult<()> {
+        self.handles.lock().unwrap().retain(|h| h.id != handle_id);
+        Ok(())
+    }
+
+    async fn list_handles(&self, domain_id: u64) -> DbResult<Vec<NostrDomainHandle>> {
+        Ok(self
+            .handles
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|h| h.domain_id == domain_id)
+            .cloned()
+            .collect())
+    }
+
+    async fn get_domain(&self, id: u64) -> DbResult<NostrDomain> {
+        self.find_domain(|d| d.id == id)
+    }
+
+    async fn get_domain_by_name(&self, name: &str) -> DbResult<NostrDomain> {
+        self.find_domain(|d| d.name == name)
+    }
+
+    async fn get_domain_by_activation_hash(&self, hash: &str) -> DbResult<NostrDomain> {
+        self.find_domain(|d| d.activation_hash.as_deref() == Some(hash))
+    }
+
+    async fn list_domains(&self, owner_id: u64) -> DbResult<Vec<NostrDomain>> {
+        Ok(self.filter_domains(|d| d.owner_id == owner_id))
+    }
+
+    async fn insert_domain(&self, domain: &NostrDomain) -> DbResult<u64> {
+        let mut domains = self.domains.lock().unwrap();
+        let id = domains.iter().map(|d| d.id).max().unwrap_or(0) + 1;
+        domains.push(NostrDomain {
+            id,
+            ..domain.clone()
+        });
+        Ok(id)
+    }
+
+    async fn delete_domain(&self, domain_id: u64) -> DbResult<()> {
+        self.domains.lock().unwrap().retain(|d| d.id != domain_id);
+        self.handles
+            .lock()
+            .unwrap()
+            .retain(|h| h.domain_id != domain_id);
+        Ok(())
+    }
+
+    async fn list_all_domains(&self) -> DbResult<Vec<NostrDomain>> {
+        Ok(self.filter_domains(|_| true))
+    }
+
+    async fn list_active_domains(&self) -> DbResult<Vec<NostrDomain>> {
+        Ok(self.filter_domains(|d| d.enabled))
+    }
+
+    async fn list_disabled_domains(&self) -> DbResult<Vec<NostrDomain>> {
+        Ok(self.filter_domains(|d| !d.enabled))
+    }
+
+    async fn enable_domain_with_https(&self, domain_id: u64) -> DbResult<()> {
+        self.update_domain(domain_id, |d| {
+            d.enabled = true;
+            d.http_only = false;
+        })
+    }
+
+    async fn enable_domain_http_only(&self, domain_id: u64) -> DbResult<()> {
+        self.update_domain(domain_id, |d| {
+            d.enabled = true;
+            d.http_only = true;
+        })
+    }
+
+    async fn disable_domain(&self, domain_id: u64) -> DbResult<()> {
+        self.update_domain(domain_id, |d| d.enabled = false)
+    }
+}
@@ -0,0 +1,63 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Limits how many requests each client IP can make per window
+pub struct RateLimiter {
+    limit: u32,
+    window: Duration,
+    /// Start of the current window and requests made in it, by client IP
+    clients: Mutex<HashMap<IpAddr, (Instant, u32)>>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: u32, window: Duration) -> Self {
+        Self {
+            limit,
+            window,
+            clients: Default::default(),
+        }
+    }
+
+    /// Count a request from `ip`, false when it is over the limit
+    pub fn check(&self, ip: IpAddr) -> bool {
+        self.check_at(ip, Instant::now())
+    }
+
+    fn check_at(&self, ip: IpAddr, now: Instant) -> bool {
+        let mut clients = self.clients.lock().unwrap();
+        // forget clients whose window has ended so the map can't grow forever
+        if clients.len() > 10_000 {
+            clients.retain(|_, (start, _)| now.duration_since(*start) < self.window);
+        }
+        let (start, count) = clients.entry(ip).or_insert((now, 0));
+        if now.duration_since(*start) >= self.window {
+            *start = now;
+            *count = 0;
+        }
+        *count += 1;
+        *count <= self.limit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limit_per_ip_and_window() {
+        let limiter = RateLimiter::new(2, Duration::from_secs(60));
+        let a: IpAddr = "10.0.0.1".parse().unwrap();
+        let b: IpAddr = "10.0.0.2".parse().unwrap();
+        let now = Instant::now();
+
+        assert!(limiter.check_at(a, now));
+        assert!(limiter.check_at(a, now));
+        assert!(!limiter.check_at(a, now));
+        // other clients have their own allowance
+        assert!(limiter.check_at(b, now));
+        // a new window resets the count
+        assert!(limiter.check_at(a, now + Duration::from_secs(60)));
+    }
+}
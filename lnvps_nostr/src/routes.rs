@@ -1,26 +1,42 @@
 use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
 
-use axum::extract::{Query, State};
-use axum::http::HeaderMap;
-use axum::response::Html;
+use axum::extract::{ConnectInfo, Query, State};
+use axum::http::{Extensions, HeaderMap, StatusCode};
+use axum::response::{Html, IntoResponse, Response};
 use axum::routing::get;
 use axum::{Json, Router};
-use log::info;
+use log::{info, warn};
 use serde::{Deserialize, Serialize};
 
+use crate::rate_limit::RateLimiter;
+use lnvps_api_common::TrustedProxies;
 use lnvps_db::nostr::LNVPSNostrDb;
 
 #[derive(Clone)]
 struct RouterState {
     db: Arc<dyn LNVPSNostrDb>,
+    limiter: Arc<RateLimiter>,
 }
 
-pub fn routes(db: Arc<dyn LNVPSNostrDb>) -> Router {
+pub fn routes(db: Arc<dyn LNVPSNostrDb>, limiter: Arc<RateLimiter>) -> Router {
     Router::new()
         .route("/", get(async || Html(include_str!("../index.html"))))
         .route("/.well-known/nostr.json", get(nostr_address))
-        .with_state(RouterState { db })
+        .with_state(RouterState { db, limiter })
+}
+
+/// The client address, forwarding headers are only believed when the peer
+/// is one of the `trusted-proxies`
+fn client_ip(headers: &HeaderMap, extensions: &Extensions) -> Option<IpAddr> {
+    let peer = extensions
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|c| c.0.ip())?;
+    Some(match extensions.get::<TrustedProxies>() {
+        Some(trusted) => trusted.client_ip(peer, headers),
+        None => peer,
+    })
 }
 
 #[derive(Serialize)]
@@ -37,8 +53,15 @@ struct NostrAddressQuery {
 async fn nostr_address(
     State(this): State<RouterState>,
     headers: HeaderMap,
+    extensions: Extensions,
     Query(q): Query<NostrAddressQuery>,
-) -> Result<Json<NostrJson>, &'static str> {
+) -> Result<Json<NostrJson>, Response> {
+    if let Some(ip) = client_ip(&headers, &extensions)
+        && !this.limiter.check(ip)
+    {
+        warn!("Rate limited NIP-05 request from {}", ip);
+        return Err((StatusCode::TOO_MANY_REQUESTS, "Too many requests").into_response());
+    }
     let name = q.name.clone().unwrap_or("_".to_string());
     let host = headers
         .get("host")
@@ -50,7 +73,7 @@ async fn nostr_address(
         .db
         .get_domain_by_name(host)
         .await
-        .map_err(|_| "Domain not found")?;
+        .map_err(|_| "Domain not found".into_response())?;
 
     // If the name parameter matches the activation hash, return a simple success response
     // This allows the activation check to verify the path is reachable
@@ -62,11 +85,16 @@ async fn nostr_address(
         }));
     }
 
+    // only the activation check is answered until the domain is enabled
+    if !domain.enabled {
+        return Err("Domain not found".into_response());
+    }
+
     let handle = this
         .db
-        .get_handle_by_name(domain.id, &name)
+        .resolve_handle(domain.id, &name)
         .await
-        .map_err(|_| "Handle not found")?;
+        .map_err(|_| "Handle not found".into_response())?;
 
     let pubkey_hex = hex::encode(handle.pubkey);
     let relays = if let Some(r) = handle.relays {
@@ -81,3 +109,34 @@ async fn nostr_address(
         relays: HashMap::from([(pubkey_hex, relays)]),
     }))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request_from(peer: &str, real_ip: &str) -> (HeaderMap, Extensions) {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-real-ip", real_ip.parse().unwrap());
+        let mut extensions = Extensions::new();
+        extensions.insert(ConnectInfo::<SocketAddr>(peer.parse().unwrap()));
+        (headers, extensions)
+    }
+
+    #[test]
+    fn test_real_ip_only_from_trusted_proxies() {
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+
+        // without trusted proxies the header is ignored
+        let (headers, extensions) = request_from("203.0.113.1:1234", "198.51.100.9");
+        assert_eq!(client_ip(&headers, &extensions), Some(ip("203.0.113.1")));
+
+        let (headers, mut extensions) = request_from("10.0.0.1:1234", "198.51.100.9");
+        extensions.insert(TrustedProxies::parse(&["10.0.0.0/8"]).unwrap());
+        assert_eq!(client_ip(&headers, &extensions), Some(ip("198.51.100.9")));
+
+        // a peer which isn't a trusted proxy can't pick its address
+        let (headers, mut extensions) = request_from("203.0.113.1:1234", "198.51.100.9");
+        extensions.insert(TrustedProxies::parse(&["10.0.0.0/8"]).unwrap());
+        assert_eq!(client_ip(&headers, &extensions), Some(ip("203.0.113.1")));
+    }
+}
@@ -26,6 +26,10 @@
 //! }
 //! ```
 //!
+//! A transient error can suggest how long to wait before the next attempt, e.g.
+//! from an HTTP `Retry-After` header, by wrapping it in [`RetryAfter`]. The
+//! suggested delay is used instead of the backoff when it is longer.
+//!
 //! # Retry
 //!
 //! ```ignore
@@ -42,6 +46,14 @@
 //! fatal errors. With a [`RetryPolicy`], transient step failures are retried
 //! automatically. Rollbacks run on fatal errors or after retries are exhausted.
 //!
+//! Set [`Pipeline::with_event_sink`] to receive a [`PipelineEvent`] as each step
+//! and rollback starts, succeeds or fails, e.g. for per step history or metrics.
+//!
+//! Independent steps can run concurrently with [`Pipeline::parallel_steps`].
+//!
+//! Steps added with [`Pipeline::step_with_timeout`] fail (and roll back) when
+//! their action doesn't finish in time, so a hung call can't stall the pipeline.
+//!
 //! Both [`retry_async`] and [`Pipeline`] use lifetime parameters so closures
 //! can borrow from the caller's scope without `Arc` or `'static` bounds.
 //!
@@ -71,11 +83,15 @@
 //!     .await?;
 //! ```
 
-use log::warn;
+use log::{debug, warn};
+use std::error::Error;
 use std::fmt;
 use std::future::{Future, IntoFuture};
 use std::pin::Pin;
-use std::time::Duration;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::task::Poll;
+use std::time::{Duration, Instant};
 use tokio::time::sleep;
 
 // ─── Error Classification ──────────────────────────────────────────────────────
@@ -143,6 +159,85 @@ impl<E> From<E> for OpError<E> {
     }
 }
 
+/// A transient error with a suggested delay before the next attempt.
+///
+/// Rate limited APIs usually say how long to back off (`Retry-After`), wrap the
+/// error in this so the retry loop waits that long instead of burning attempts
+/// on the regular backoff. The delay is capped at
+/// [`RetryPolicy::max_retry_after`].
+#[derive(Debug)]
+pub struct RetryAfter {
+    pub delay: Duration,
+    pub error: Box<dyn Error + Send + Sync>,
+}
+
+impl RetryAfter {
+    pub fn new(delay: Duration, error: impl Into<Box<dyn Error + Send + Sync>>) -> Self {
+        Self {
+            delay,
+            error: error.into(),
+        }
+    }
+}
+
+impl fmt::Display for RetryAfter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (retry after {:?})", self.error, self.delay)
+    }
+}
+
+impl Error for RetryAfter {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(self.error.as_ref())
+    }
+}
+
+/// Errors which may carry a [`RetryAfter`] delay anywhere in their source chain.
+///
+/// Implemented for every error viewable as `dyn Error`, which covers
+/// `anyhow::Error` and `Box<dyn Error + Send + Sync>`.
+pub trait RetryHint {
+    /// The suggested delay before retrying, if any
+    fn retry_after(&self) -> Option<Duration>;
+}
+
+impl<E> RetryHint for E
+where
+    E: AsRef<dyn Error + Send + Sync + 'static>,
+{
+    fn retry_after(&self) -> Option<Duration> {
+        let mut source: Option<&(dyn Error + 'static)> = Some(self.as_ref());
+        while let Some(err) = source {
+            if let Some(r) = err.downcast_ref::<RetryAfter>() {
+                return Some(r.delay);
+            }
+            source = err.source();
+        }
+        None
+    }
+}
+
+/// A pipeline step which did not finish within its timeout.
+///
+/// See [`Pipeline::step_with_timeout`].
+#[derive(Debug)]
+pub struct StepTimeout {
+    pub step: String,
+    pub timeout: Duration,
+}
+
+impl fmt::Display for StepTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Pipeline step '{}' timed out after {:?}",
+            self.step, self.timeout
+        )
+    }
+}
+
+impl Error for StepTimeout {}
+
 /// Convenience type alias for operations that return retryable results.
 ///
 /// The default error type is `Box<dyn std::error::Error + Send + Sync>`.
@@ -161,6 +256,8 @@ pub struct RetryPolicy {
     pub max_retries: u32,
     /// Multiplier for exponential backoff (delay *= factor each attempt)
     pub factor: f64,
+    /// Maximum delay honoured from a [`RetryAfter`] hint
+    pub max_retry_after: Duration,
 }
 
 impl Default for RetryPolicy {
@@ -170,6 +267,7 @@ impl Default for RetryPolicy {
             max_delay: Duration::from_secs(30),
             max_retries: 3,
             factor: 2.0,
+            max_retry_after: Duration::from_secs(120),
         }
     }
 }
@@ -195,12 +293,27 @@ impl RetryPolicy {
         self
     }
 
+    pub fn with_max_retry_after(mut self, delay: Duration) -> Self {
+        self.max_retry_after = delay;
+        self
+    }
+
     /// Calculate the delay for a given attempt number (0-indexed)
     fn delay_for_attempt(&self, attempt: u32) -> Duration {
         let delay = self.min_delay.as_secs_f64() * self.factor.powi(attempt as i32);
         let clamped = delay.min(self.max_delay.as_secs_f64());
         Duration::from_secs_f64(clamped)
     }
+
+    /// Delay before retrying after `err`, the longer of the backoff and the
+    /// error's [`RetryAfter`] hint
+    fn delay_for_error<E: RetryHint>(&self, attempt: u32, err: &E) -> Duration {
+        let backoff = self.delay_for_attempt(attempt);
+        match err.retry_after() {
+            Some(hint) => hint.min(self.max_retry_after).max(backoff),
+            None => backoff,
+        }
+    }
 }
 
 // ─── Retry Operation ───────────────────────────────────────────────────────────
@@ -226,7 +339,7 @@ pub struct RetryOp<F, Fut, T, E>
 where
     F: FnMut() -> Fut,
     Fut: Future<Output = Result<T, OpError<E>>>,
-    E: fmt::Display,
+    E: fmt::Display + RetryHint,
 {
     factory: F,
     policy: RetryPolicy,
@@ -237,7 +350,7 @@ impl<F, Fut, T, E> RetryOp<F, Fut, T, E>
 where
     F: FnMut() -> Fut,
     Fut: Future<Output = Result<T, OpError<E>>>,
-    E: fmt::Display,
+    E: fmt::Display + RetryHint,
 {
     pub fn new(policy: RetryPolicy, factory: F) -> Self {
         Self {
@@ -270,7 +383,7 @@ where
                     if attempt >= self.policy.max_retries {
                         return Err(e);
                     }
-                    let delay = self.policy.delay_for_attempt(attempt);
+                    let delay = self.policy.delay_for_error(attempt, &e);
                     if let Some(ref cb) = self.on_retry {
                         cb(&e, attempt + 1, delay);
                     }
@@ -287,7 +400,7 @@ where
     F: FnMut() -> Fut + Send + 'static,
     Fut: Future<Output = Result<T, OpError<E>>> + Send + 'static,
     T: Send + 'static,
-    E: fmt::Display + Send + 'static,
+    E: fmt::Display + RetryHint + Send + 'static,
 {
     type Output = Result<T, E>;
     type IntoFuture = Pin<Box<dyn Future<Output = Self::Output> + Send>>;
@@ -318,7 +431,7 @@ pub trait Retryable<F, Fut, T, E>
 where
     F: FnMut() -> Fut,
     Fut: Future<Output = Result<T, OpError<E>>>,
-    E: fmt::Display,
+    E: fmt::Display + RetryHint,
 {
     fn with_retry(self, policy: RetryPolicy) -> RetryOp<F, Fut, T, E>;
 }
@@ -327,7 +440,7 @@ impl<F, Fut, T, E> Retryable<F, Fut, T, E> for F
 where
     F: FnMut() -> Fut,
     Fut: Future<Output = Result<T, OpError<E>>>,
-    E: fmt::Display,
+    E: fmt::Display + RetryHint,
 {
     fn with_retry(self, policy: RetryPolicy) -> RetryOp<F, Fut, T, E> {
         RetryOp::new(policy, self)
@@ -348,11 +461,50 @@ where
 ///     router.update_arp_entry(&entry).await
 /// }).await?;
 /// ```
-pub async fn retry_async<F, Fut, T, E>(policy: RetryPolicy, mut f: F) -> Result<T, E>
+pub async fn retry_async<F, Fut, T, E>(policy: RetryPolicy, f: F) -> Result<T, E>
 where
     F: FnMut() -> Fut,
     Fut: Future<Output = Result<T, OpError<E>>>,
-    E: fmt::Display,
+    E: fmt::Display + RetryHint,
+{
+    let max_retries = policy.max_retries;
+    retry_async_with(
+        policy,
+        |e: &E, attempt, delay| {
+            warn!(
+                "Transient error (attempt {}/{}), retrying in {:?}: {}",
+                attempt, max_retries, delay, e
+            )
+        },
+        f,
+    )
+    .await
+}
+
+/// Like [`retry_async`], but calls `on_retry` before each retry instead of logging.
+///
+/// The callback receives the error, attempt number (1-indexed), and delay before
+/// the next attempt, the same as [`RetryOp::on_retry`], so callers can record
+/// metrics or tracing spans.
+///
+/// # Example
+/// ```ignore
+/// let result = retry_async_with(
+///     RetryPolicy::default(),
+///     |e, attempt, delay| metrics.record_retry("router", attempt),
+///     || async { router.update_arp_entry(&entry).await },
+/// ).await?;
+/// ```
+pub async fn retry_async_with<F, Fut, T, E, C>(
+    policy: RetryPolicy,
+    mut on_retry: C,
+    mut f: F,
+) -> Result<T, E>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, OpError<E>>>,
+    E: fmt::Display + RetryHint,
+    C: FnMut(&E, u32, Duration),
 {
     let mut attempt = 0u32;
 
@@ -364,14 +516,8 @@ where
                 if attempt >= policy.max_retries {
                     return Err(e);
                 }
-                let delay = policy.delay_for_attempt(attempt);
-                warn!(
-                    "Transient error (attempt {}/{}), retrying in {:?}: {}",
-                    attempt + 1,
-                    policy.max_retries,
-                    delay,
-                    e
-                );
+                let delay = policy.delay_for_error(attempt, &e);
+                on_retry(&e, attempt + 1, delay);
                 sleep(delay).await;
                 attempt += 1;
             }
@@ -394,11 +540,161 @@ type StepFn<'a, Ctx, E> =
 type RollbackFn<'a, Ctx, E> =
     Box<dyn FnMut(&mut Ctx) -> BoxFuture<'_, Result<(), OpError<E>>> + Send + 'a>;
 
+/// Predicate deciding whether a step added with [`Pipeline::step_if`] runs.
+type ConditionFn<'a, Ctx> = Box<dyn Fn(&Ctx) -> bool + Send + 'a>;
+
+/// Progress of a [`Pipeline`] run, passed to the sink set with
+/// [`Pipeline::with_event_sink`].
+///
+/// `elapsed` covers all attempts, including retry delays.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PipelineEvent {
+    StepStarted {
+        step: String,
+    },
+    StepSucceeded {
+        step: String,
+        elapsed: Duration,
+    },
+    /// The step failed for good (fatal, or out of retries), rollback follows
+    StepFailed {
+        step: String,
+        elapsed: Duration,
+        error: String,
+    },
+    RollbackStarted {
+        step: String,
+    },
+    RollbackFailed {
+        step: String,
+        elapsed: Duration,
+        error: String,
+    },
+}
+
+/// Callback receiving [`PipelineEvent`]s
+type EventSink<'a> = Box<dyn Fn(PipelineEvent) + Send + Sync + 'a>;
+
+/// Pass an event to the sink, the event is only built when a sink is set
+fn emit(sink: &Option<EventSink<'_>>, event: impl FnOnce() -> PipelineEvent) {
+    if let Some(sink) = sink {
+        sink(event());
+    }
+}
+
+/// Type-erased action or rollback of a [`ParallelStep`], which only gets shared
+/// access to the context so it can run alongside its siblings.
+type SharedStepFn<'a, Ctx, E> =
+    Box<dyn FnMut(&Ctx) -> BoxFuture<'_, Result<(), OpError<E>>> + Send + 'a>;
+
+/// One of a group of independent steps added with [`Pipeline::parallel_steps`].
+pub struct ParallelStep<'a, Ctx, E> {
+    name: String,
+    action: SharedStepFn<'a, Ctx, E>,
+    rollback: SharedStepFn<'a, Ctx, E>,
+}
+
+impl<'a, Ctx, E> ParallelStep<'a, Ctx, E> {
+    pub fn new(
+        name: impl Into<String>,
+        action: impl FnMut(&Ctx) -> BoxFuture<'_, Result<(), OpError<E>>> + Send + 'a,
+        rollback: impl FnMut(&Ctx) -> BoxFuture<'_, Result<(), OpError<E>>> + Send + 'a,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            action: Box::new(action),
+            rollback: Box::new(rollback),
+        }
+    }
+}
+
+/// Poll all futures concurrently, returning their outputs in order
+async fn join_all<F: Future + Unpin>(futures: Vec<F>) -> Vec<F::Output> {
+    let mut futures: Vec<Option<F>> = futures.into_iter().map(Some).collect();
+    let mut outputs: Vec<Option<F::Output>> = futures.iter().map(|_| None).collect();
+    std::future::poll_fn(|cx| {
+        let mut pending = false;
+        for (f, out) in futures.iter_mut().zip(outputs.iter_mut()) {
+            if let Some(fut) = f {
+                match Pin::new(fut).poll(cx) {
+                    Poll::Ready(v) => {
+                        *out = Some(v);
+                        *f = None;
+                    }
+                    Poll::Pending => pending = true,
+                }
+            }
+        }
+        if pending {
+            Poll::Pending
+        } else {
+            Poll::Ready(())
+        }
+    })
+    .await;
+    outputs
+        .into_iter()
+        .map(|o| o.expect("all futures completed"))
+        .collect()
+}
+
+/// Combine the results of concurrently run actions, marking `done` the ones
+/// which succeeded. A fatal error wins over a transient one.
+fn collect_parallel<E: fmt::Display>(
+    results: Vec<(usize, Result<(), OpError<E>>)>,
+    names: &[String],
+    done: &[AtomicBool],
+    mark: bool,
+) -> Result<(), OpError<E>> {
+    let mut error: Option<OpError<E>> = None;
+    for (i, r) in results {
+        match r {
+            Ok(()) => done[i].store(mark, Ordering::SeqCst),
+            Err(e) => {
+                warn!("Parallel step '{}' failed: {}", names[i], e);
+                if error.as_ref().is_none_or(|err| err.is_transient()) {
+                    error = Some(e);
+                }
+            }
+        }
+    }
+    match error {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
 /// A step in the pipeline: an action and an optional rollback.
 struct PipelineStep<'a, Ctx, E> {
     name: String,
     action: StepFn<'a, Ctx, E>,
     rollback: Option<RollbackFn<'a, Ctx, E>>,
+    /// Deadline for each attempt of the action, and how to turn a timeout into `E`
+    timeout: Option<(Duration, fn(StepTimeout) -> E)>,
+    /// Run this step's own rollback when its action fails, for steps which
+    /// can partly complete (see [`Pipeline::parallel_steps`])
+    rollback_on_failure: bool,
+    /// Only run the step when this returns `true` for the context at that point
+    condition: Option<ConditionFn<'a, Ctx>>,
+}
+
+impl<Ctx, E> PipelineStep<'_, Ctx, E> {
+    /// Run the action once, bounded by the step timeout.
+    ///
+    /// Returns `Err` when the action timed out.
+    async fn run(&mut self, ctx: &mut Ctx) -> Result<Result<(), OpError<E>>, E> {
+        let Some((timeout, to_error)) = self.timeout else {
+            return Ok((self.action)(ctx).await);
+        };
+        tokio::time::timeout(timeout, (self.action)(ctx))
+            .await
+            .map_err(|_| {
+                to_error(StepTimeout {
+                    step: self.name.clone(),
+                    timeout,
+                })
+            })
+    }
 }
 
 /// A pipeline of steps that execute in order with automatic rollback on failure.
@@ -471,21 +767,30 @@ pub struct Pipeline<'a, Ctx, E = Box<dyn std::error::Error + Send + Sync>> {
     ctx: Ctx,
     steps: Vec<PipelineStep<'a, Ctx, E>>,
     retry_policy: Option<RetryPolicy>,
+    event_sink: Option<EventSink<'a>>,
 }
 
 impl<'a, Ctx, E> Pipeline<'a, Ctx, E>
 where
     Ctx: Send + 'a,
-    E: fmt::Display + fmt::Debug + Send + 'a,
+    E: fmt::Display + fmt::Debug + RetryHint + Send + 'a,
 {
     pub fn new(ctx: Ctx) -> Self {
         Self {
             ctx,
             steps: Vec::new(),
             retry_policy: None,
+            event_sink: None,
         }
     }
 
+    /// Call `sink` with a [`PipelineEvent`] as each step and rollback runs,
+    /// e.g. to record history or metrics per step.
+    pub fn with_event_sink(mut self, sink: impl Fn(PipelineEvent) + Send + Sync + 'a) -> Self {
+        self.event_sink = Some(Box::new(sink));
+        self
+    }
+
     /// Set the retry policy for transient step and rollback failures.
     ///
     /// When set, steps and rollbacks that return [`OpError::Transient`] are retried
@@ -512,6 +817,9 @@ where
             name: name.into(),
             action: Box::new(action),
             rollback: None,
+            timeout: None,
+            rollback_on_failure: false,
+            condition: None,
         });
         self
     }
@@ -532,10 +840,140 @@ where
             name: name.into(),
             action: Box::new(action),
             rollback: Some(Box::new(rollback)),
+            timeout: None,
+            rollback_on_failure: false,
+            condition: None,
+        });
+        self
+    }
+
+    /// Add a step with an action, a rollback and a timeout for the action.
+    ///
+    /// Each attempt of the action is cancelled if it doesn't finish within
+    /// `timeout`. A timeout is a fatal step failure: it is not retried and
+    /// completed steps are rolled back. The action may have partly completed
+    /// before it was cancelled, so this step's own rollback runs first.
+    pub fn step_with_timeout(
+        mut self,
+        name: impl Into<String>,
+        timeout: Duration,
+        action: impl FnMut(&mut Ctx) -> BoxFuture<'_, Result<(), OpError<E>>> + Send + 'a,
+        rollback: impl FnMut(&mut Ctx) -> BoxFuture<'_, Result<(), OpError<E>>> + Send + 'a,
+    ) -> Self
+    where
+        E: From<StepTimeout>,
+    {
+        self.steps.push(PipelineStep {
+            name: name.into(),
+            action: Box::new(action),
+            rollback: Some(Box::new(rollback)),
+            timeout: Some((timeout, E::from)),
+            rollback_on_failure: false,
+            condition: None,
         });
         self
     }
 
+    /// Add a step with only an action, which is skipped unless `condition`
+    /// returns `true` for the context when the step is reached.
+    pub fn step_if(
+        mut self,
+        name: impl Into<String>,
+        condition: impl Fn(&Ctx) -> bool + Send + 'a,
+        action: impl FnMut(&mut Ctx) -> BoxFuture<'_, Result<(), OpError<E>>> + Send + 'a,
+    ) -> Self {
+        self.steps.push(PipelineStep {
+            name: name.into(),
+            action: Box::new(action),
+            rollback: None,
+            timeout: None,
+            rollback_on_failure: false,
+            condition: Some(Box::new(condition)),
+        });
+        self
+    }
+
+    /// The steps which would run, in order, without running anything.
+    ///
+    /// Steps with a rollback are suffixed with ` (rollback)`. Conditions of
+    /// [`step_if`](Pipeline::step_if) steps are evaluated against the current
+    /// context, a step whose condition depends on earlier steps may run
+    /// differently.
+    pub fn plan(&self) -> Vec<String> {
+        self.steps
+            .iter()
+            .filter(|s| s.condition.as_ref().is_none_or(|c| c(&self.ctx)))
+            .map(|s| match s.rollback {
+                Some(_) => format!("{} (rollback)", s.name),
+                None => s.name.clone(),
+            })
+            .collect()
+    }
+
+    /// Add a group of independent steps whose actions run concurrently.
+    ///
+    /// Actions and rollbacks only get `&Ctx`, anything they produce must be
+    /// kept with interior mutability or found again by a later step. The
+    /// group is a single step of the pipeline, named after its steps joined
+    /// with `+`: when any action fails, the
+    /// siblings which succeeded are rolled back along with the earlier steps.
+    /// On a transient failure only the failed actions are retried, and if a
+    /// later step fails all of the group's rollbacks run concurrently.
+    pub fn parallel_steps(self, steps: impl IntoIterator<Item = ParallelStep<'a, Ctx, E>>) -> Self {
+        let mut names = Vec::new();
+        let mut actions = Vec::new();
+        let mut rollbacks = Vec::new();
+        for s in steps {
+            names.push(s.name);
+            actions.push(s.action);
+            rollbacks.push(s.rollback);
+        }
+        let name = names.join("+");
+        let names: Arc<[String]> = names.into();
+        let done: Arc<[AtomicBool]> = names.iter().map(|_| AtomicBool::new(false)).collect();
+        let (action_names, action_done) = (names.clone(), done.clone());
+
+        let mut pipeline = self.step_with_rollback(
+            name,
+            move |ctx| {
+                let ctx: &Ctx = ctx;
+                // only (re-)run the actions which haven't succeeded yet
+                let pending: Vec<_> = actions
+                    .iter_mut()
+                    .enumerate()
+                    .filter(|(i, _)| !action_done[*i].load(Ordering::SeqCst))
+                    .map(|(i, action)| (i, action(ctx)))
+                    .collect();
+                let (names, done) = (action_names.clone(), action_done.clone());
+                Box::pin(async move {
+                    let (idx, futures): (Vec<_>, Vec<_>) = pending.into_iter().unzip();
+                    let results = idx.into_iter().zip(join_all(futures).await).collect();
+                    collect_parallel(results, &names, &done, true)
+                })
+            },
+            move |ctx| {
+                let ctx: &Ctx = ctx;
+                let pending: Vec<_> = rollbacks
+                    .iter_mut()
+                    .enumerate()
+                    .filter(|(i, _)| done[*i].load(Ordering::SeqCst))
+                    .map(|(i, rollback)| (i, rollback(ctx)))
+                    .collect();
+                let (names, done) = (names.clone(), done.clone());
+                Box::pin(async move {
+                    let (idx, futures): (Vec<_>, Vec<_>) = pending.into_iter().unzip();
+                    let results = idx.into_iter().zip(join_all(futures).await).collect();
+                    // rolled back steps are no longer done, so a retry skips them
+                    collect_parallel(results, &names, &done, false)
+                })
+            },
+        );
+        if let Some(step) = pipeline.steps.last_mut() {
+            step.rollback_on_failure = true;
+        }
+        pipeline
+    }
+
     /// Execute all steps in order with retry on transient errors.
     ///
     /// On fatal failure (or transient failure after exhausting retries),
@@ -543,24 +981,39 @@ where
     ///
     /// Returns the context on success so the caller can extract results from it.
     pub async fn execute(mut self) -> Result<Ctx, E> {
-        let mut completed_rollbacks: Vec<RollbackFn<'a, Ctx, E>> = Vec::new();
+        let mut completed_rollbacks: Vec<(String, RollbackFn<'a, Ctx, E>)> = Vec::new();
 
         // Drain steps so we can take ownership of each one
         let steps: Vec<PipelineStep<'a, Ctx, E>> = self.steps.drain(..).collect();
 
         for mut step in steps {
+            if let Some(condition) = &step.condition
+                && !condition(&self.ctx)
+            {
+                debug!("Pipeline step '{}' skipped", step.name);
+                continue;
+            }
+            emit(&self.event_sink, || PipelineEvent::StepStarted {
+                step: step.name.clone(),
+            });
+            let started = Instant::now();
+            let mut timed_out = false;
             let result = match &self.retry_policy {
                 Some(policy) => {
                     let mut attempt = 0u32;
                     loop {
-                        match (step.action)(&mut self.ctx).await {
-                            Ok(()) => break Ok(()),
-                            Err(OpError::Fatal(e)) => break Err(e),
-                            Err(OpError::Transient(e)) => {
+                        match step.run(&mut self.ctx).await {
+                            Err(e) => {
+                                timed_out = true;
+                                break Err(e);
+                            }
+                            Ok(Ok(())) => break Ok(()),
+                            Ok(Err(OpError::Fatal(e))) => break Err(e),
+                            Ok(Err(OpError::Transient(e))) => {
                                 if attempt >= policy.max_retries {
                                     break Err(e);
                                 }
-                                let delay = policy.delay_for_attempt(attempt);
+                                let delay = policy.delay_for_error(attempt, &e);
                                 warn!(
                                     "Pipeline step '{}' transient error (attempt {}/{}), \
                                      retrying in {:?}: {}",
@@ -578,20 +1031,40 @@ where
                 }
                 None => {
                     // No retry policy: any error is terminal
-                    match (step.action)(&mut self.ctx).await {
-                        Ok(()) => Ok(()),
-                        Err(op_err) => Err(op_err.into_inner()),
+                    match step.run(&mut self.ctx).await {
+                        Err(e) => {
+                            timed_out = true;
+                            Err(e)
+                        }
+                        Ok(Ok(())) => Ok(()),
+                        Ok(Err(op_err)) => Err(op_err.into_inner()),
                     }
                 }
             };
 
             match result {
                 Ok(()) => {
+                    emit(&self.event_sink, || PipelineEvent::StepSucceeded {
+                        step: step.name.clone(),
+                        elapsed: started.elapsed(),
+                    });
                     if let Some(rollback) = step.rollback {
-                        completed_rollbacks.push(rollback);
+                        completed_rollbacks.push((step.name, rollback));
                     }
                 }
                 Err(e) => {
+                    emit(&self.event_sink, || PipelineEvent::StepFailed {
+                        step: step.name.clone(),
+                        elapsed: started.elapsed(),
+                        error: e.to_string(),
+                    });
+                    // a timed out action or parallel group may have partly
+                    // completed, roll it back first
+                    if (timed_out || step.rollback_on_failure)
+                        && let Some(rollback) = step.rollback
+                    {
+                        completed_rollbacks.push((step.name.clone(), rollback));
+                    }
                     warn!(
                         "Pipeline step '{}' failed: {}, rolling back {} steps",
                         step.name,
@@ -600,7 +1073,18 @@ where
                     );
 
                     // Rollback in reverse order
-                    for mut rollback in completed_rollbacks.into_iter().rev() {
+                    for (name, mut rollback) in completed_rollbacks.into_iter().rev() {
+                        emit(&self.event_sink, || PipelineEvent::RollbackStarted {
+                            step: name.clone(),
+                        });
+                        let started = Instant::now();
+                        let rollback_failed = |e: &E| {
+                            emit(&self.event_sink, || PipelineEvent::RollbackFailed {
+                                step: name.clone(),
+                                elapsed: started.elapsed(),
+                                error: e.to_string(),
+                            })
+                        };
                         match &self.retry_policy {
                             Some(policy) => {
                                 let mut attempt = 0u32;
@@ -609,6 +1093,7 @@ where
                                         Ok(()) => break,
                                         Err(OpError::Fatal(e)) => {
                                             warn!("Rollback failed (fatal): {}", e);
+                                            rollback_failed(&e);
                                             break;
                                         }
                                         Err(OpError::Transient(e)) => {
@@ -617,9 +1102,10 @@ where
                                                     "Rollback failed after {} retries: {}",
                                                     policy.max_retries, e
                                                 );
+                                                rollback_failed(&e);
                                                 break;
                                             }
-                                            let delay = policy.delay_for_attempt(attempt);
+                                            let delay = policy.delay_for_error(attempt, &e);
                                             warn!(
                                                 "Rollback transient error (attempt {}/{}), \
                                                  retrying in {:?}: {}",
@@ -637,6 +1123,7 @@ where
                             None => {
                                 if let Err(rb_err) = (rollback)(&mut self.ctx).await {
                                     warn!("Rollback failed: {}", rb_err);
+                                    rollback_failed(rb_err.inner());
                                 }
                             }
                         }
@@ -654,7 +1141,7 @@ where
 impl<Ctx, E> IntoFuture for Pipeline<'static, Ctx, E>
 where
     Ctx: Send + 'static,
-    E: fmt::Display + fmt::Debug + Send + 'static,
+    E: fmt::Display + fmt::Debug + RetryHint + Send + 'static,
 {
     type Output = Result<Ctx, E>;
     type IntoFuture = Pin<Box<dyn Future<Output = Self::Output> + Send>>;
@@ -783,6 +1270,60 @@ mod tests {
         assert_eq!(counter.load(Ordering::SeqCst), 2);
     }
 
+    #[test]
+    fn retry_after_hint_overrides_backoff() {
+        let policy = RetryPolicy::default()
+            .with_min_delay(Duration::from_millis(10))
+            .with_max_retry_after(Duration::from_secs(60));
+        let plain = anyhow::anyhow!("503 Service Unavailable");
+        assert_eq!(policy.delay_for_error(0, &plain), Duration::from_millis(10));
+
+        let hinted = anyhow::Error::new(RetryAfter::new(Duration::from_secs(5), "429"));
+        assert_eq!(policy.delay_for_error(0, &hinted), Duration::from_secs(5));
+
+        // found below added context
+        let wrapped = hinted.context("add arp entry");
+        assert_eq!(wrapped.retry_after(), Some(Duration::from_secs(5)));
+
+        // capped at max_retry_after
+        let long = anyhow::Error::new(RetryAfter::new(Duration::from_secs(3600), "429"));
+        assert_eq!(policy.delay_for_error(0, &long), Duration::from_secs(60));
+
+        // never shorter than the backoff
+        let short = anyhow::Error::new(RetryAfter::new(Duration::from_millis(1), "429"));
+        assert_eq!(policy.delay_for_error(2, &short), Duration::from_millis(40));
+    }
+
+    #[tokio::test]
+    async fn retry_op_waits_retry_after_hint() {
+        let delays = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let d = delays.clone();
+        let counter = Arc::new(AtomicU32::new(0));
+        let c = counter.clone();
+
+        let result: Result<(), anyhow::Error> = (move || {
+            let c = c.clone();
+            async move {
+                if c.fetch_add(1, Ordering::SeqCst) == 0 {
+                    Err(OpError::Transient(anyhow::Error::new(RetryAfter::new(
+                        Duration::from_millis(20),
+                        "rate limited",
+                    ))))
+                } else {
+                    Ok(())
+                }
+            }
+        })
+        .with_retry(RetryPolicy::default().with_min_delay(Duration::from_millis(1)))
+        .on_retry(move |_err, _attempt, delay| {
+            d.lock().unwrap().push(delay);
+        })
+        .await;
+
+        assert!(result.is_ok());
+        assert_eq!(*delays.lock().unwrap(), vec![Duration::from_millis(20)]);
+    }
+
     #[tokio::test]
     async fn retry_op_on_retry_callback() {
         let retry_count = Arc::new(AtomicU32::new(0));
@@ -859,6 +1400,35 @@ mod tests {
         assert_eq!(counter.load(Ordering::SeqCst), 1);
     }
 
+    #[tokio::test]
+    async fn retry_async_with_calls_on_retry() {
+        let counter = AtomicU32::new(0);
+        let mut retries = Vec::new();
+
+        let result: Result<(), anyhow::Error> = retry_async_with(
+            RetryPolicy::default()
+                .with_min_delay(Duration::from_millis(1))
+                .with_max_retries(3),
+            |e, attempt, delay| retries.push((e.to_string(), attempt, delay)),
+            || {
+                counter.fetch_add(1, Ordering::SeqCst);
+                async { Err(OpError::Transient(anyhow::anyhow!("always fails"))) }
+            },
+        )
+        .await;
+
+        assert!(result.is_err());
+        assert_eq!(counter.load(Ordering::SeqCst), 4); // 1 initial + 3 retries
+        // no callback after the last attempt, nothing is retried
+        assert_eq!(
+            retries.iter().map(|r| r.1).collect::<Vec<_>>(),
+            vec![1, 2, 3]
+        );
+        assert!(retries.iter().all(|r| r.0 == "always fails"));
+        // exponential backoff delays are passed through
+        assert!(retries[1].2 > retries[0].2);
+    }
+
     // ─── Pipeline tests ──────────────────────────────────────────────────
 
     #[tokio::test]
@@ -1362,4 +1932,357 @@ mod tests {
         // 1 initial attempt + 2 retries = 3 total
         assert_eq!(rollback_attempts.load(Ordering::SeqCst), 3);
     }
+
+    /// Step action or rollback which records `$name` on `$log` and returns `$result`
+    macro_rules! logged {
+        ($name:expr, $log:expr, $result:expr) => {{
+            let log = $log.clone();
+            move |_ctx| {
+                let log = log.clone();
+                Box::pin(async move {
+                    log.lock().await.push($name.to_string());
+                    $result
+                })
+            }
+        }};
+    }
+
+    #[tokio::test]
+    async fn pipeline_parallel_steps_all_succeed() {
+        let executed = Arc::new(tokio::sync::Mutex::new(Vec::<String>::new()));
+        let rolled_back = Arc::new(tokio::sync::Mutex::new(Vec::<String>::new()));
+        // both actions wait for each other, so this only finishes if they run concurrently
+        let barrier = Arc::new(tokio::sync::Barrier::new(2));
+        let (b1, b2) = (barrier.clone(), barrier.clone());
+
+        let result = tokio::time::timeout(
+            Duration::from_secs(5),
+            Pipeline::<(), anyhow::Error>::new(())
+                .parallel_steps(vec![
+                    ParallelStep::new(
+                        "a",
+                        move |_ctx| {
+                            let b = b1.clone();
+                            Box::pin(async move {
+                                b.wait().await;
+                                Ok(())
+                            })
+                        },
+                        logged!("a", rolled_back, Ok(())),
+                    ),
+                    ParallelStep::new(
+                        "b",
+                        move |_ctx| {
+                            let b = b2.clone();
+                            Box::pin(async move {
+                                b.wait().await;
+                                Ok(())
+                            })
+                        },
+                        logged!("b", rolled_back, Ok(())),
+                    ),
+                ])
+                .step("after", logged!("after", executed, Ok(())))
+                .execute(),
+        )
+        .await
+        .expect("parallel steps ran serially");
+
+        assert!(result.is_ok());
+        assert_eq!(*executed.lock().await, vec!["after"]);
+        assert!(rolled_back.lock().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn pipeline_parallel_step_failure_rolls_back_siblings() {
+        let executed = Arc::new(tokio::sync::Mutex::new(Vec::<String>::new()));
+        let rolled_back = Arc::new(tokio::sync::Mutex::new(Vec::<String>::new()));
+
+        let result = Pipeline::<(), anyhow::Error>::new(())
+            .step_with_rollback(
+                "first",
+                logged!("first", executed, Ok(())),
+                logged!("first", rolled_back, Ok(())),
+            )
+            .parallel_steps(vec![
+                ParallelStep::new(
+                    "a",
+                    logged!("a", executed, Ok(())),
+                    logged!("a", rolled_back, Ok(())),
+                ),
+                ParallelStep::new(
+                    "b",
+                    logged!(
+                        "b",
+                        executed,
+                        Err(OpError::Fatal(anyhow::anyhow!("b failed")))
+                    ),
+                    logged!("b", rolled_back, Ok(())),
+                ),
+                ParallelStep::new(
+                    "c",
+                    logged!("c", executed, Ok(())),
+                    logged!("c", rolled_back, Ok(())),
+                ),
+            ])
+            .step("after", logged!("after", executed, Ok(())))
+            .execute()
+            .await;
+
+        assert_eq!(result.unwrap_err().to_string(), "b failed");
+        let mut ran = executed.lock().await.clone();
+        ran.sort();
+        assert_eq!(ran, vec!["a", "b", "c", "first"]);
+        // the siblings which succeeded are rolled back before the earlier steps,
+        // the failed one isn't
+        let rb = rolled_back.lock().await;
+        let mut siblings = rb[..2].to_vec();
+        siblings.sort();
+        assert_eq!(siblings, vec!["a", "c"]);
+        assert_eq!(rb[2..], ["first"]);
+    }
+
+    #[tokio::test]
+    async fn pipeline_parallel_steps_retry_only_failed() {
+        let attempts_a = Arc::new(AtomicU32::new(0));
+        let attempts_b = Arc::new(AtomicU32::new(0));
+        let (a, b) = (attempts_a.clone(), attempts_b.clone());
+
+        let result = Pipeline::<(), anyhow::Error>::new(())
+            .with_retry_policy(RetryPolicy::default().with_min_delay(Duration::from_millis(1)))
+            .parallel_steps(vec![
+                ParallelStep::new(
+                    "a",
+                    move |_ctx| {
+                        a.fetch_add(1, Ordering::SeqCst);
+                        Box::pin(async { Ok(()) })
+                    },
+                    |_ctx| Box::pin(async { Ok(()) }),
+                ),
+                ParallelStep::new(
+                    "b",
+                    move |_ctx| {
+                        let attempt = b.fetch_add(1, Ordering::SeqCst);
+                        Box::pin(async move {
+                            if attempt == 0 {
+                                Err(OpError::Transient(anyhow::anyhow!("try again")))
+                            } else {
+                                Ok(())
+                            }
+                        })
+                    },
+                    |_ctx| Box::pin(async { Ok(()) }),
+                ),
+            ])
+            .execute()
+            .await;
+
+        assert!(result.is_ok());
+        assert_eq!(attempts_a.load(Ordering::SeqCst), 1);
+        assert_eq!(attempts_b.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn pipeline_plan_lists_steps() {
+        let executed = Arc::new(tokio::sync::Mutex::new(Vec::<String>::new()));
+        let pipeline = Pipeline::<u32, anyhow::Error>::new(1)
+            .step_with_rollback("allocate", logged!("allocate", executed, Ok(())), |_ctx| {
+                Box::pin(async { Ok(()) })
+            })
+            .step_if(
+                "only_if_one",
+                |ctx| *ctx == 1,
+                |_ctx| Box::pin(async { Ok(()) }),
+            )
+            .step_if(
+                "only_if_two",
+                |ctx| *ctx == 2,
+                |_ctx| Box::pin(async { Ok(()) }),
+            )
+            .parallel_steps(vec![
+                ParallelStep::new(
+                    "a",
+                    |_ctx| Box::pin(async { Ok(()) }),
+                    |_ctx| Box::pin(async { Ok(()) }),
+                ),
+                ParallelStep::new(
+                    "b",
+                    |_ctx| Box::pin(async { Ok(()) }),
+                    |_ctx| Box::pin(async { Ok(()) }),
+                ),
+            ])
+            .step("save", |_ctx| Box::pin(async { Ok(()) }));
+
+        assert_eq!(
+            pipeline.plan(),
+            vec![
+                "allocate (rollback)",
+                "only_if_one",
+                "a+b (rollback)",
+                "save"
+            ]
+        );
+        // nothing ran
+        assert!(executed.lock().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn pipeline_step_if_skips_step() {
+        let executed = Arc::new(tokio::sync::Mutex::new(Vec::<String>::new()));
+        let ctx = Pipeline::<u32, anyhow::Error>::new(0)
+            .step("increment", |ctx| {
+                Box::pin(async move {
+                    *ctx += 1;
+                    Ok(())
+                })
+            })
+            // conditions see the context as left by earlier steps
+            .step_if(
+                "if_zero",
+                |ctx| *ctx == 0,
+                logged!("if_zero", executed, Ok(())),
+            )
+            .step_if(
+                "if_one",
+                |ctx| *ctx == 1,
+                logged!("if_one", executed, Ok(())),
+            )
+            .execute()
+            .await
+            .unwrap();
+
+        assert_eq!(ctx, 1);
+        assert_eq!(*executed.lock().await, vec!["if_one"]);
+    }
+
+    #[tokio::test]
+    async fn pipeline_events_for_failing_pipeline() {
+        let events = Arc::new(std::sync::Mutex::new(Vec::<PipelineEvent>::new()));
+        let ev = events.clone();
+
+        let result = Pipeline::<(), anyhow::Error>::new(())
+            .with_event_sink(move |e| ev.lock().unwrap().push(e))
+            .step_with_rollback(
+                "step1",
+                |_ctx| Box::pin(async { Ok(()) }),
+                |_ctx| {
+                    Box::pin(async { Err(OpError::Fatal(anyhow::anyhow!("rollback 1 failed"))) })
+                },
+            )
+            .step_with_rollback(
+                "step2",
+                |_ctx| Box::pin(async { Ok(()) }),
+                |_ctx| Box::pin(async { Ok(()) }),
+            )
+            .step("step3", |_ctx| {
+                Box::pin(async { Err(OpError::Fatal(anyhow::anyhow!("step 3 failed"))) })
+            })
+            .execute()
+            .await;
+        assert!(result.is_err());
+
+        // timings vary, compare without them
+        let events: Vec<_> = events
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|e| match e {
+                PipelineEvent::StepStarted { step } => format!("started {step}"),
+                PipelineEvent::StepSucceeded { step, .. } => format!("succeeded {step}"),
+                PipelineEvent::StepFailed { step, error, .. } => {
+                    format!("failed {step}: {error}")
+                }
+                PipelineEvent::RollbackStarted { step } => format!("rollback {step}"),
+                PipelineEvent::RollbackFailed { step, error, .. } => {
+                    format!("rollback failed {step}: {error}")
+                }
+            })
+            .collect();
+        assert_eq!(
+            events,
+            vec![
+                "started step1",
+                "succeeded step1",
+                "started step2",
+                "succeeded step2",
+                "started step3",
+                "failed step3: step 3 failed",
+                "rollback step2",
+                "rollback step1",
+                "rollback failed step1: rollback 1 failed",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn pipeline_step_timeout_rolls_back() {
+        let rolled_back = Arc::new(tokio::sync::Mutex::new(Vec::<String>::new()));
+        let r1 = rolled_back.clone();
+        let r2 = rolled_back.clone();
+        let attempts = Arc::new(AtomicU32::new(0));
+        let a = attempts.clone();
+
+        let result = Pipeline::<(), anyhow::Error>::new(())
+            .with_retry_policy(RetryPolicy::default().with_min_delay(Duration::from_millis(1)))
+            .step_with_rollback(
+                "step1",
+                |_ctx| Box::pin(async { Ok(()) }),
+                move |_ctx| {
+                    let r = r1.clone();
+                    Box::pin(async move {
+                        r.lock().await.push("step1".into());
+                        Ok(())
+                    })
+                },
+            )
+            .step_with_timeout(
+                "step2_hangs",
+                Duration::from_millis(10),
+                move |_ctx| {
+                    let a = a.clone();
+                    Box::pin(async move {
+                        a.fetch_add(1, Ordering::SeqCst);
+                        sleep(Duration::from_secs(5)).await;
+                        Ok(())
+                    })
+                },
+                move |_ctx| {
+                    let r = r2.clone();
+                    Box::pin(async move {
+                        r.lock().await.push("step2_hangs".into());
+                        Ok(())
+                    })
+                },
+            )
+            .execute()
+            .await;
+
+        let err = result.unwrap_err();
+        assert!(err.downcast_ref::<StepTimeout>().is_some());
+        // a timeout is not retried
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+        // the timed out step is rolled back too, before the completed steps
+        let rb = rolled_back.lock().await;
+        assert_eq!(*rb, vec!["step2_hangs", "step1"]);
+    }
+
+    #[tokio::test]
+    async fn pipeline_step_within_timeout_succeeds() {
+        let result = Pipeline::<u32, anyhow::Error>::new(0)
+            .step_with_timeout(
+                "fast",
+                Duration::from_secs(5),
+                |ctx| {
+                    Box::pin(async move {
+                        *ctx += 1;
+                        Ok(())
+                    })
+                },
+                |_ctx| Box::pin(async { Err(OpError::Fatal(anyhow::anyhow!("not rolled back"))) }),
+            )
+            .execute()
+            .await;
+
+        assert_eq!(result.unwrap(), 1);
+    }
 }